@@ -0,0 +1,91 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! ARM Data Watchpoint and Trace Unit
+//!
+//! Only the cycle counter is supported. It is not implemented on Cortex-M0
+//! and Cortex-M0+ cores.
+//!
+//! <https://developer.arm.com/documentation/ddi0403/d/Debug-Architecture/ARMv7-M-Debug/The-Data-Watchpoint-and-Trace-unit>
+
+use kernel::hil::hw_debug::CycleCounter;
+use kernel::utilities::registers::interfaces::{ReadWriteable, Readable, Writeable};
+use kernel::utilities::registers::{register_bitfields, register_structs, ReadWrite};
+use kernel::utilities::StaticRef;
+
+register_structs! {
+    DwtRegisters {
+        /// Control Register
+        (0x00 => ctrl: ReadWrite<u32, Control::Register>),
+
+        /// Cycle Count Register
+        (0x04 => cyccnt: ReadWrite<u32>),
+
+        (0x08 => @END),
+    }
+}
+
+register_structs! {
+    DcbRegisters {
+        (0x00 => _reserved0),
+
+        /// Debug Exception and Monitor Control Register
+        (0x0c => demcr: ReadWrite<u32, DebugExceptionAndMonitorControl::Register>),
+
+        (0x10 => @END),
+    }
+}
+
+register_bitfields![u32,
+    Control [
+        /// Cycle counter not supported
+        NOCYCCNT OFFSET(25) NUMBITS(1),
+        /// Enable the cycle counter
+        CYCCNTENA OFFSET(0) NUMBITS(1)
+    ],
+
+    DebugExceptionAndMonitorControl [
+        /// Enable the DWT and ITM units
+        TRCENA OFFSET(24) NUMBITS(1)
+    ]
+];
+
+const DWT: StaticRef<DwtRegisters> = unsafe { StaticRef::new(0xE0001000 as *const DwtRegisters) };
+
+const DCB: StaticRef<DcbRegisters> = unsafe { StaticRef::new(0xE000EDF0 as *const DcbRegisters) };
+
+pub struct Dwt(());
+
+impl Dwt {
+    pub const unsafe fn new() -> Dwt {
+        Dwt(())
+    }
+
+    /// Whether the core implements the cycle counter.
+    pub fn is_cycle_counter_present(&self) -> bool {
+        DCB.demcr
+            .modify(DebugExceptionAndMonitorControl::TRCENA::SET);
+        !DWT.ctrl.is_set(Control::NOCYCCNT)
+    }
+}
+
+impl CycleCounter for Dwt {
+    fn start(&self) {
+        DCB.demcr
+            .modify(DebugExceptionAndMonitorControl::TRCENA::SET);
+        DWT.ctrl.modify(Control::CYCCNTENA::SET);
+    }
+
+    fn stop(&self) {
+        DWT.ctrl.modify(Control::CYCCNTENA::CLEAR);
+    }
+
+    fn count(&self) -> u64 {
+        DWT.cyccnt.get() as u64
+    }
+
+    fn reset(&self) {
+        DWT.cyccnt.set(0);
+    }
+}
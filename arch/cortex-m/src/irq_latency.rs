@@ -0,0 +1,39 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Interrupt timestamps for `kernel::irq_latency`.
+//!
+//! If the kernel is built with the `irq_latency` feature, the generic
+//! interrupt handler stores the DWT cycle counter in `ENTRY_CYCLES` when an
+//! interrupt fires. The chip calls `record()` before it services the
+//! interrupt, which passes the elapsed cycles to the kernel. The cycle
+//! counter must be started, e.g. with `dwt::Dwt::start()`.
+
+use core::ptr::addr_of;
+
+/// Number of interrupts that are timestamped.
+pub const NUM_IRQS: usize = 128;
+
+/// Cycle counter at the last top half of each interrupt. Written by
+/// `generic_isr_arm_v7m_timestamped`.
+pub(crate) static mut ENTRY_CYCLES: [u32; NUM_IRQS] = [0; NUM_IRQS];
+
+/// Address of the DWT cycle counter register.
+const DWT_CYCCNT: *const u32 = 0xE000_1004 as *const u32;
+
+/// Record the latency of interrupt `index`, whose bottom half is about to
+/// run.
+pub fn record(index: usize) {
+    if kernel::irq_latency::ENABLED && index < NUM_IRQS {
+        // SAFETY: the top half only writes the entry of a disabled
+        // interrupt, which is not re-enabled before its bottom half ran.
+        let (entry, now) = unsafe {
+            (
+                (*addr_of!(ENTRY_CYCLES))[index],
+                core::ptr::read_volatile(DWT_CYCCNT),
+            )
+        };
+        kernel::irq_latency::record(index, now.wrapping_sub(entry));
+    }
+}
@@ -11,6 +11,8 @@
 
 use core::fmt::Write;
 
+pub mod dwt;
+pub mod irq_latency;
 pub mod mpu;
 pub mod nvic;
 pub mod scb;
@@ -317,6 +319,48 @@ pub unsafe extern "C" fn generic_isr_arm_v7m() {
     );
 }
 
+/// Generic interrupt handler for ARMv7-M instruction sets that timestamps the
+/// interrupt for `kernel::irq_latency`.
+///
+/// Stores the DWT cycle counter in `irq_latency::ENTRY_CYCLES` and continues
+/// with [`generic_isr_arm_v7m`]. Only `r0` to `r3` are used, which the
+/// hardware saves on exception entry.
+#[cfg(all(
+    target_arch = "arm",
+    target_feature = "v7",
+    target_feature = "thumb-mode",
+    target_os = "none"
+))]
+#[naked]
+pub unsafe extern "C" fn generic_isr_arm_v7m_timestamped() {
+    use core::arch::asm;
+    asm!(
+        "
+    // r0 = zero-indexed interrupt number, see `generic_isr_arm_v7m`.
+    mrs r0, IPSR                      // r0 = Interrupt Program Status Register (IPSR)
+    and r0, #0xff                     // r0 = r0 & 0xFF; Get lowest 8 bits
+    sub r0, #16                       // r0 = r0 - 16;   ISRs start at 16, so subtract 16 to get zero-indexed.
+
+    // Only the first `NUM_IRQS` interrupts are timestamped.
+    cmp r0, #{num_irqs}
+    bhs 100f
+
+    // ENTRY_CYCLES[r0] = DWT.CYCCNT
+    ldr r1, =0xe0001004               // r1 = &DWT.CYCCNT
+    ldr r1, [r1]                      // r1 = DWT.CYCCNT
+    ldr r2, ={entry_cycles}           // r2 = &ENTRY_CYCLES
+    str r1, [r2, r0, lsl #2]          // *(r2 + r0 * 4) = r1
+
+100:
+    b {generic_isr}
+    ",
+        num_irqs = const irq_latency::NUM_IRQS,
+        entry_cycles = sym irq_latency::ENTRY_CYCLES,
+        generic_isr = sym generic_isr_arm_v7m,
+        options(noreturn)
+    );
+}
+
 #[cfg(all(target_arch = "arm", target_os = "none"))]
 pub unsafe extern "C" fn unhandled_interrupt() {
     use core::arch::asm;
@@ -962,6 +1006,11 @@ pub unsafe extern "C" fn generic_isr_arm_v7m() {
     unimplemented!()
 }
 
+#[cfg(not(any(target_arch = "arm", target_os = "none")))]
+pub unsafe extern "C" fn generic_isr_arm_v7m_timestamped() {
+    unimplemented!()
+}
+
 #[cfg(not(any(target_arch = "arm", target_os = "none")))]
 pub unsafe extern "C" fn unhandled_interrupt() {
     unimplemented!()
@@ -117,6 +117,14 @@ register_bitfields![u32,
             ReadOnly = 0b110,               // R-          R-
             ReadOnlyAlias = 0b111           // R-          R-
         ],
+        /// Memory type, together with C and B
+        TEX OFFSET(19) NUMBITS(3) [],
+        /// Shareable
+        S OFFSET(18) NUMBITS(1) [],
+        /// Cacheable
+        C OFFSET(17) NUMBITS(1) [],
+        /// Bufferable
+        B OFFSET(16) NUMBITS(1) [],
         /// Subregion disable bits
         SRD OFFSET(8) NUMBITS(8) [],
         /// Specifies the region size, being 2^(SIZE+1) (minimum 3)
@@ -309,11 +317,23 @@ impl CortexMRegion {
 
         let size_value = math::log_base_two(region_size as u32) - 1;
 
+        // Code regions are normal, write-through cacheable memory. Other
+        // regions keep the strongly-ordered memory type. Code may be executed
+        // in place from external flash, which is slow unless the chip can
+        // cache the fetches.
+        let memory_type = match permissions {
+            mpu::Permissions::ReadExecuteOnly | mpu::Permissions::ExecuteOnly => {
+                RegionAttributes::TEX.val(0b000) + RegionAttributes::C::SET
+            }
+            _ => RegionAttributes::TEX.val(0b000) + RegionAttributes::C::CLEAR,
+        };
+
         // Attributes register
         let mut attributes = RegionAttributes::ENABLE::SET
             + RegionAttributes::SIZE.val(size_value)
             + access
-            + execute;
+            + execute
+            + memory_type;
 
         // If using subregions, add a subregion mask. The mask is a 8-bit
         // bitfield where `0` indicates that the corresponding subregion is enabled.
@@ -14,6 +14,7 @@
 //! without a home, so we include it in the NVIC files as it's conceptually here.
 //! <https://developer.arm.com/docs/ddi0337/latest/nested-vectored-interrupt-controller/nvic-programmers-model/interrupt-controller-type-register-ictr>
 
+use kernel::platform::userspace_driver::InterruptMask;
 use kernel::utilities::registers::interfaces::{Readable, Writeable};
 use kernel::utilities::registers::{register_bitfields, register_structs, ReadOnly, ReadWrite};
 use kernel::utilities::StaticRef;
@@ -115,6 +116,31 @@ register_bitfields![u32,
 const NVIC: StaticRef<NvicRegisters> =
     unsafe { StaticRef::new(0xe000e000 as *const NvicRegisters) };
 
+/// Interrupts held masked by `NvicInterruptMask`, one bit per interrupt.
+/// `Nvic::enable()` leaves these disabled, so chips can keep re-enabling
+/// every interrupt they serviced.
+static mut HELD: [u32; 16] = [0; 16];
+
+fn is_held(idx: u32) -> bool {
+    let block = idx as usize / 32;
+    // Safety: `HELD` is only accessed from the kernel main loop.
+    block < 16 && unsafe { HELD[block] } & (1 << (idx & 31)) != 0
+}
+
+fn set_held(idx: u32, held: bool) {
+    let block = idx as usize / 32;
+    if block < 16 {
+        // Safety: `HELD` is only accessed from the kernel main loop.
+        unsafe {
+            if held {
+                HELD[block] |= 1 << (idx & 31);
+            } else {
+                HELD[block] &= !(1 << (idx & 31));
+            }
+        }
+    }
+}
+
 /// Number of valid NVIC_XXXX registers. Note this is a ceiling on the number
 /// of available interrupts (as this is the number of banks of 32), but the
 /// actual number may be less. See NVIC and ICTR documentation for more detail.
@@ -232,8 +258,12 @@ impl Nvic {
         Nvic(idx)
     }
 
-    /// Enable the interrupt
+    /// Enable the interrupt, unless it is held masked by an
+    /// `NvicInterruptMask`.
     pub fn enable(&self) {
+        if is_held(self.0) {
+            return;
+        }
         let idx = self.0 as usize;
 
         NVIC.iser[idx / 32].set(1 << (self.0 & 31));
@@ -253,3 +283,29 @@ impl Nvic {
         NVIC.icpr[idx / 32].set(1 << (self.0 & 31));
     }
 }
+
+/// Masks NVIC interrupts for `InterruptForwarding`.
+///
+/// A masked interrupt is disabled and stays disabled when the chip
+/// re-enables it through `Nvic::enable()` after servicing it.
+pub struct NvicInterruptMask(());
+
+impl NvicInterruptMask {
+    /// Marked unsafe because only platform configuration code should be able
+    /// to create these.
+    pub const unsafe fn new() -> NvicInterruptMask {
+        NvicInterruptMask(())
+    }
+}
+
+impl InterruptMask for NvicInterruptMask {
+    fn mask(&self, irq: u32) {
+        set_held(irq, true);
+        Nvic(irq).disable();
+    }
+
+    fn unmask(&self, irq: u32) {
+        set_held(irq, false);
+        Nvic(irq).enable();
+    }
+}
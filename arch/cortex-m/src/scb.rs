@@ -6,9 +6,11 @@
 //!
 //! <http://infocenter.arm.com/help/index.jsp?topic=/com.arm.doc.dui0553a/CIHFDJCA.html>
 
+use kernel::hil::reset::{Reset, ResetMode};
 use kernel::utilities::registers::interfaces::{ReadWriteable, Readable, Writeable};
 use kernel::utilities::registers::{register_bitfields, register_structs, ReadOnly, ReadWrite};
 use kernel::utilities::StaticRef;
+use kernel::ErrorCode;
 
 register_structs! {
     /// In an ARMv7-M processor, a System Control Block (SCB) in the SCS
@@ -294,6 +296,30 @@ pub unsafe fn reset() {
     );
 }
 
+/// `hil::reset::Reset` through the System Control Block. Only supports
+/// `ResetMode::Normal`, chips with a bootloader provide their own
+/// implementation.
+pub struct ScbReset;
+
+impl Reset for ScbReset {
+    fn supports(&self, mode: ResetMode) -> bool {
+        mode == ResetMode::Normal
+    }
+
+    fn reset(&self, mode: ResetMode) -> ErrorCode {
+        if mode != ResetMode::Normal {
+            return ErrorCode::NOSUPPORT;
+        }
+        unsafe {
+            reset();
+        }
+        // The reset request takes a few cycles to take effect.
+        loop {
+            crate::support::nop();
+        }
+    }
+}
+
 /// relocate interrupt vector table
 pub unsafe fn set_vector_table_offset(offset: *const ()) {
     SCB.vtor.set(offset as u32);
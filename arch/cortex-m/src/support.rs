@@ -22,6 +22,14 @@ pub unsafe fn wfi() {
     asm!("wfi", options(nomem, preserves_flags));
 }
 
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+#[inline(always)]
+/// SEV instruction, wakes up other cores waiting in WFE
+pub unsafe fn sev() {
+    use core::arch::asm;
+    asm!("sev", options(nomem, nostack, preserves_flags));
+}
+
 #[cfg(all(target_arch = "arm", target_os = "none"))]
 pub unsafe fn atomic<F, R>(f: F) -> R
 where
@@ -38,6 +46,24 @@ where
     return res;
 }
 
+/// Start the image whose vector table is at `vector_table` as if the chip
+/// had booted it: load its initial stack pointer into MSP and branch to its
+/// reset handler. This is used to enter ROM bootloaders, interrupts should
+/// be disabled and peripherals reset before.
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+pub unsafe fn jump_to_image(vector_table: usize) -> ! {
+    use core::arch::asm;
+    let stack_pointer = core::ptr::read_volatile(vector_table as *const u32);
+    let reset_handler = core::ptr::read_volatile((vector_table + 4) as *const u32);
+    asm!(
+        "msr msp, {sp}",
+        "bx {pc}",
+        sp = in(reg) stack_pointer,
+        pc = in(reg) reset_handler,
+        options(noreturn)
+    );
+}
+
 // Mock implementations for tests on Travis-CI.
 #[cfg(not(any(target_arch = "arm", target_os = "none")))]
 /// NOP instruction (mock)
@@ -51,6 +77,18 @@ pub unsafe fn wfi() {
     unimplemented!()
 }
 
+#[cfg(not(any(target_arch = "arm", target_os = "none")))]
+/// SEV instruction (mock)
+pub unsafe fn sev() {
+    unimplemented!()
+}
+
+#[cfg(not(any(target_arch = "arm", target_os = "none")))]
+/// Start another image (mock)
+pub unsafe fn jump_to_image(_vector_table: usize) -> ! {
+    unimplemented!()
+}
+
 #[cfg(not(any(target_arch = "arm", target_os = "none")))]
 pub unsafe fn atomic<F, R>(_f: F) -> R
 where
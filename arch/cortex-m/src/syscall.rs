@@ -316,6 +316,23 @@ impl<A: CortexMVariant> kernel::syscall::UserspaceKernelBoundary for SysCall<A>
         (switch_reason, Some(new_stack_pointer as *const u8))
     }
 
+    unsafe fn get_pc_and_sp(
+        &self,
+        accessible_memory_start: *const u8,
+        app_brk: *const u8,
+        state: &CortexMStoredState,
+    ) -> Option<(usize, usize)> {
+        // The PC is in the exception frame on the process stack, so it can
+        // only be read if the stored stack pointer is valid.
+        if state.psp < accessible_memory_start as usize
+            || state.psp.saturating_add(SVC_FRAME_SIZE) > app_brk as usize
+        {
+            return None;
+        }
+        let pc = ptr::read((state.psp as *const usize).offset(6));
+        Some((pc, state.psp))
+    }
+
     unsafe fn print_context(
         &self,
         accessible_memory_start: *const u8,
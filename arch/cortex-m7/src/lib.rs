@@ -14,7 +14,9 @@ pub mod mpu {
     pub type MPU = cortexm::mpu::MPU<16, 32>; // Cortex-M7 MPU has 16 regions
 }
 
+pub use cortexm::dwt;
 pub use cortexm::initialize_ram_jump_to_main;
+pub use cortexm::irq_latency;
 pub use cortexm::nvic;
 pub use cortexm::scb;
 pub use cortexm::support;
@@ -28,7 +30,11 @@ pub use cortexm::CortexMVariant;
 pub enum CortexM7 {}
 
 impl cortexm::CortexMVariant for CortexM7 {
-    const GENERIC_ISR: unsafe extern "C" fn() = cortexm::generic_isr_arm_v7m;
+    const GENERIC_ISR: unsafe extern "C" fn() = if kernel::irq_latency::ENABLED {
+        cortexm::generic_isr_arm_v7m_timestamped
+    } else {
+        cortexm::generic_isr_arm_v7m
+    };
     const SYSTICK_HANDLER: unsafe extern "C" fn() = cortexm::systick_handler_arm_v7m;
     const SVC_HANDLER: unsafe extern "C" fn() = cortexm::svc_handler_arm_v7m;
     const HARD_FAULT_HANDLER: unsafe extern "C" fn() = cortexm::hard_fault_handler_arm_v7m;
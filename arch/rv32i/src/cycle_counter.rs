@@ -0,0 +1,34 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Cycle counter backed by the `mcycle` CSR.
+
+use kernel::hil::hw_debug::CycleCounter;
+
+use crate::csr::CSR;
+
+/// The `mcycle` counter runs all the time, so `start()` and `stop()` have
+/// no effect.
+#[derive(Default)]
+pub struct McycleCounter(());
+
+impl McycleCounter {
+    pub const fn new() -> McycleCounter {
+        McycleCounter(())
+    }
+}
+
+impl CycleCounter for McycleCounter {
+    fn start(&self) {}
+
+    fn stop(&self) {}
+
+    fn count(&self) -> u64 {
+        CSR.read_cycle_counter()
+    }
+
+    fn reset(&self) {
+        CSR.reset_cycle_counter();
+    }
+}
@@ -14,6 +14,7 @@ use core::fmt::Write;
 use kernel::utilities::registers::interfaces::{Readable, Writeable};
 
 pub mod clic;
+pub mod cycle_counter;
 pub mod epmp;
 pub mod machine_timer;
 pub mod pmp;
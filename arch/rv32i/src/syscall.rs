@@ -449,6 +449,15 @@ impl kernel::syscall::UserspaceKernelBoundary for SysCall {
         (ret, Some(new_stack_pointer as *const u8))
     }
 
+    unsafe fn get_pc_and_sp(
+        &self,
+        _accessible_memory_start: *const u8,
+        _app_brk: *const u8,
+        state: &Riscv32iStoredState,
+    ) -> Option<(usize, usize)> {
+        Some((state.pc as usize, state.regs[R_SP] as usize))
+    }
+
     unsafe fn print_context(
         &self,
         _accessible_memory_start: *const u8,
@@ -31,14 +31,42 @@
 //! `FloatingState::PullUp` whereas `ActivationMode::ActiveHigh` will be paired
 //! with `FloatingState::PullDown`. `FloatingState::None` will be used when the
 //! board provides external pull-up/pull-down resistors.
+//!
+//! Boards with bouncy switches can use `ButtonDebounceComponent` instead,
+//! which debounces every button with the given interval in milliseconds.
+//! Apps can change the interval of a button afterwards.
+//!
+//! ```rust
+//! let button = components::button::ButtonDebounceComponent::new(
+//!     board_kernel,
+//!     capsules_core::button::DRIVER_NUM,
+//!     components::button_component_helper!(
+//!         sam4l::gpio::GPIOPin,
+//!         (
+//!             &sam4l::gpio::PC[24],
+//!             kernel::hil::gpio::ActivationMode::ActiveLow,
+//!             kernel::hil::gpio::FloatingState::PullUp
+//!         )
+//!     ),
+//!     mux_alarm,
+//!     20,
+//! )
+//! .finalize(components::button_debounce_component_static!(
+//!     sam4l::gpio::GPIOPin,
+//!     sam4l::ast::Ast,
+//!     1
+//! ));
+//! ```
 
-use capsules_core::button::Button;
+use capsules_core::button::{Button, Debounce};
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
 use core::mem::MaybeUninit;
 use kernel::capabilities;
 use kernel::component::Component;
 use kernel::create_capability;
 use kernel::hil::gpio;
 use kernel::hil::gpio::InterruptWithValue;
+use kernel::hil::time::{self, Alarm};
 
 #[macro_export]
 macro_rules! button_component_helper_owned {
@@ -84,6 +112,27 @@ macro_rules! button_component_static {
     };};
 }
 
+#[macro_export]
+macro_rules! button_debounce_component_static {
+    ($Pin:ty, $A:ty, $N:expr $(,)?) => {{
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let debounce = kernel::static_buf!(
+            [capsules_core::button::Debounce<<$A as kernel::hil::time::Time>::Ticks>; $N]
+        );
+        let button = kernel::static_buf!(
+            capsules_core::button::Button<
+                'static,
+                $Pin,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+
+        (alarm, debounce, button)
+    };};
+}
+
 pub struct ButtonComponent<IP: 'static + gpio::InterruptPin<'static>> {
     board_kernel: &'static kernel::Kernel,
     driver_num: usize,
@@ -129,3 +178,84 @@ impl<IP: 'static + gpio::InterruptPin<'static>> Component for ButtonComponent<IP
         button
     }
 }
+
+pub struct ButtonDebounceComponent<
+    IP: 'static + gpio::InterruptPin<'static>,
+    A: 'static + time::Alarm<'static>,
+    const NUM_BUTTONS: usize,
+> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    button_pins: &'static [(
+        &'static gpio::InterruptValueWrapper<'static, IP>,
+        gpio::ActivationMode,
+        gpio::FloatingState,
+    )],
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    debounce_ms: u32,
+}
+
+impl<
+        IP: 'static + gpio::InterruptPin<'static>,
+        A: 'static + time::Alarm<'static>,
+        const NUM_BUTTONS: usize,
+    > ButtonDebounceComponent<IP, A, NUM_BUTTONS>
+{
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        button_pins: &'static [(
+            &'static gpio::InterruptValueWrapper<'static, IP>,
+            gpio::ActivationMode,
+            gpio::FloatingState,
+        )],
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        debounce_ms: u32,
+    ) -> Self {
+        Self {
+            board_kernel,
+            driver_num,
+            button_pins,
+            alarm_mux,
+            debounce_ms,
+        }
+    }
+}
+
+impl<
+        IP: 'static + gpio::InterruptPin<'static>,
+        A: 'static + time::Alarm<'static>,
+        const NUM_BUTTONS: usize,
+    > Component for ButtonDebounceComponent<IP, A, NUM_BUTTONS>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<[Debounce<A::Ticks>; NUM_BUTTONS]>,
+        &'static mut MaybeUninit<Button<'static, IP, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = &'static Button<'static, IP, VirtualMuxAlarm<'static, A>>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let alarm = static_buffer.0.write(VirtualMuxAlarm::new(self.alarm_mux));
+        alarm.setup();
+
+        let debounce = static_buffer
+            .1
+            .write(core::array::from_fn(|_| Debounce::new(self.debounce_ms)));
+
+        let button = static_buffer.2.write(Button::new_with_debounce(
+            self.button_pins,
+            alarm,
+            debounce,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+        ));
+        for (pin, _, _) in self.button_pins.iter() {
+            pin.set_client(button);
+        }
+        alarm.set_alarm_client(button);
+
+        button
+    }
+}
@@ -23,13 +23,32 @@
 //!     STRINGS)
 //! .finalize(components::cdc_acm_component_static!(nrf52::usbd::Usbd));
 //! ```
+//!
+//! `CdcFallbackComponent` provides a UART that uses the CDC-ACM device once a
+//! USB host enumerates it and a hardware UART otherwise. Creating the UART mux
+//! for the console and debug writer on it gives a console over USB that falls
+//! back to the hardware UART:
+//!
+//! ```rust
+//! let cdc_fallback = components::cdc::CdcFallbackComponent::new(cdc_acm, &nrf52840::uart::UARTE0)
+//!     .finalize(components::cdc_fallback_component_static!(
+//!         nrf52::usbd::Usbd,
+//!         nrf52::rtc::Rtc,
+//!         nrf52840::uart::Uarte
+//!     ));
+//! let uart_mux = components::console::UartMuxComponent::new(cdc_fallback, 115200)
+//!     .finalize(components::uart_mux_component_static!());
+//! ```
 
 use core::mem::MaybeUninit;
 
 use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::usb::cdc::CdcAcm;
+use capsules_extra::usb::cdc_fallback::CdcFallback;
 use kernel::component::Component;
 use kernel::hil;
 use kernel::hil::time::Alarm;
+use kernel::hil::uart;
 
 // Setup static space for the objects.
 #[macro_export]
@@ -119,3 +138,62 @@ impl<U: 'static + hil::usb::UsbController<'static>, A: 'static + Alarm<'static>>
         cdc
     }
 }
+
+#[macro_export]
+macro_rules! cdc_fallback_component_static {
+    ($U:ty, $A:ty, $H:ty $(,)?) => {{
+        kernel::static_buf!(
+            capsules_extra::usb::cdc_fallback::CdcFallback<
+                'static,
+                $U,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+                $H,
+            >
+        )
+    };};
+}
+
+pub struct CdcFallbackComponent<
+    U: 'static + hil::usb::UsbController<'static>,
+    A: 'static + Alarm<'static>,
+    H: 'static + uart::Uart<'static>,
+> {
+    cdc: &'static CdcAcm<'static, U, VirtualMuxAlarm<'static, A>>,
+    uart: &'static H,
+}
+
+impl<
+        U: 'static + hil::usb::UsbController<'static>,
+        A: 'static + Alarm<'static>,
+        H: 'static + uart::Uart<'static>,
+    > CdcFallbackComponent<U, A, H>
+{
+    pub fn new(
+        cdc: &'static CdcAcm<'static, U, VirtualMuxAlarm<'static, A>>,
+        uart: &'static H,
+    ) -> Self {
+        Self { cdc, uart }
+    }
+}
+
+impl<
+        U: 'static + hil::usb::UsbController<'static>,
+        A: 'static + Alarm<'static>,
+        H: 'static + uart::Uart<'static>,
+    > Component for CdcFallbackComponent<U, A, H>
+{
+    type StaticInput =
+        &'static mut MaybeUninit<CdcFallback<'static, U, VirtualMuxAlarm<'static, A>, H>>;
+    type Output = &'static CdcFallback<'static, U, VirtualMuxAlarm<'static, A>, H>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let cdc_fallback = s.write(CdcFallback::new(self.cdc, self.uart));
+        uart::Transmit::set_transmit_client(self.cdc, cdc_fallback);
+        uart::Receive::set_receive_client(self.cdc, cdc_fallback);
+        self.cdc.set_enumeration_client(cdc_fallback);
+        self.uart.set_transmit_client(cdc_fallback);
+        self.uart.set_receive_client(cdc_fallback);
+
+        cdc_fallback
+    }
+}
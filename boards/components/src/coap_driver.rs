@@ -0,0 +1,193 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Component to initialize the userland CoAP driver.
+//!
+//! This provides one Component, CoAPDriverComponent. This component initializes
+//! a userspace CoAP driver on top of the UDP/6LoWPAN stack, and binds it to a
+//! UDP port in the kernel's port table. The port table only accepts bindings
+//! once the userland UDP driver is set up, so this component must be
+//! finalized after `UDPDriverComponent`.
+//!
+//! Usage
+//! -----
+//! ```rust
+//!    let coap_driver = CoAPDriverComponent::new(
+//!        board_kernel,
+//!        capsules_extra::net::coap::DRIVER_NUM,
+//!        udp_send_mux,
+//!        udp_recv_mux,
+//!        udp_port_table,
+//!        capsules_extra::net::coap::COAP_PORT,
+//!        mux_alarm,
+//!     )
+//!     .finalize(components::coap_driver_component_static!(sam4l::ast::Ast));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::net::coap::driver::{Resource, MAX_NUM_RESOURCES};
+use capsules_extra::net::coap::CoAPDriver;
+use capsules_extra::net::ipv6::ipv6_send::IP6SendStruct;
+use capsules_extra::net::network_capabilities::{
+    AddrRange, NetworkCapability, PortRange, UdpVisibilityCapability,
+};
+use capsules_extra::net::udp::udp_port_table::UdpPortManager;
+use capsules_extra::net::udp::udp_recv::{MuxUdpReceiver, UDPReceiver};
+use capsules_extra::net::udp::udp_send::{MuxUdpSender, UDPSendStruct, UDPSender};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::capabilities::NetworkCapabilityCreationCapability;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::time::Alarm;
+use kernel::utilities::leasable_buffer::LeasableMutableBuffer;
+
+const MAX_PAYLOAD_LEN: usize = super::udp_mux::MAX_PAYLOAD_LEN;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! coap_driver_component_static {
+    ($A:ty $(,)?) => {{
+        use components::udp_mux::MAX_PAYLOAD_LEN;
+
+        let coap_send = kernel::static_buf!(
+            capsules_extra::net::udp::udp_send::UDPSendStruct<
+                'static,
+                capsules_extra::net::ipv6::ipv6_send::IP6SendStruct<
+                    'static,
+                    capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+                >,
+            >
+        );
+        let coap_recv =
+            kernel::static_buf!(capsules_extra::net::udp::udp_recv::UDPReceiver<'static>);
+        let udp_vis_cap =
+            kernel::static_buf!(capsules_extra::net::network_capabilities::UdpVisibilityCapability);
+        let net_cap =
+            kernel::static_buf!(capsules_extra::net::network_capabilities::NetworkCapability);
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let resources = kernel::static_buf!(
+            [capsules_extra::net::coap::driver::Resource;
+                capsules_extra::net::coap::driver::MAX_NUM_RESOURCES]
+        );
+        let coap_driver = kernel::static_buf!(
+            capsules_extra::net::coap::CoAPDriver<
+                'static,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+        let buffer = kernel::static_buf!([u8; MAX_PAYLOAD_LEN]);
+
+        (
+            coap_send,
+            coap_recv,
+            udp_vis_cap,
+            net_cap,
+            alarm,
+            resources,
+            coap_driver,
+            buffer,
+        )
+    };};
+}
+
+pub struct CoAPDriverComponent<A: Alarm<'static> + 'static> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    udp_send_mux:
+        &'static MuxUdpSender<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+    udp_recv_mux: &'static MuxUdpReceiver<'static>,
+    port_table: &'static UdpPortManager,
+    port: u16,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+}
+
+impl<A: Alarm<'static>> CoAPDriverComponent<A> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        udp_send_mux: &'static MuxUdpSender<
+            'static,
+            IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
+        >,
+        udp_recv_mux: &'static MuxUdpReceiver<'static>,
+        port_table: &'static UdpPortManager,
+        port: u16,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+    ) -> Self {
+        Self {
+            board_kernel,
+            driver_num,
+            udp_send_mux,
+            udp_recv_mux,
+            port_table,
+            port,
+            alarm_mux,
+        }
+    }
+}
+
+impl<A: Alarm<'static>> Component for CoAPDriverComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<
+            UDPSendStruct<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+        >,
+        &'static mut MaybeUninit<UDPReceiver<'static>>,
+        &'static mut MaybeUninit<UdpVisibilityCapability>,
+        &'static mut MaybeUninit<NetworkCapability>,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<[Resource; MAX_NUM_RESOURCES]>,
+        &'static mut MaybeUninit<CoAPDriver<'static, VirtualMuxAlarm<'static, A>>>,
+        &'static mut MaybeUninit<[u8; MAX_PAYLOAD_LEN]>,
+    );
+    type Output = &'static CoAPDriver<'static, VirtualMuxAlarm<'static, A>>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+        let create_cap = create_capability!(NetworkCapabilityCreationCapability);
+        let udp_vis = s.2.write(UdpVisibilityCapability::new(&create_cap));
+        let coap_send = s.0.write(UDPSendStruct::new(self.udp_send_mux, udp_vis));
+        let coap_recv = s.1.write(UDPReceiver::new());
+        self.udp_recv_mux.add_client(coap_recv);
+
+        let net_cap = s.3.write(NetworkCapability::new(
+            AddrRange::Any,
+            PortRange::Any,
+            PortRange::Any,
+            &create_cap,
+        ));
+
+        let socket = self
+            .port_table
+            .create_socket()
+            .expect("CoAP: no free UDP socket");
+        let (send_binding, recv_binding) = self
+            .port_table
+            .bind(socket, self.port, net_cap)
+            .expect("CoAP: UDP port unavailable");
+        coap_send.set_binding(send_binding);
+        coap_recv.set_binding(recv_binding);
+
+        let alarm = s.4.write(VirtualMuxAlarm::new(self.alarm_mux));
+        alarm.setup();
+
+        let resources = s.5.write(core::array::from_fn(|_| Resource::new()));
+        let buffer = s.7.write([0; MAX_PAYLOAD_LEN]);
+
+        let coap_driver = s.6.write(CoAPDriver::new(
+            coap_send,
+            alarm,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+            resources,
+            LeasableMutableBuffer::new(buffer),
+            net_cap,
+        ));
+        coap_send.set_client(coap_driver);
+        coap_recv.set_client(coap_driver);
+        alarm.set_alarm_client(coap_driver);
+        coap_driver
+    }
+}
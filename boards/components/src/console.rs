@@ -153,6 +153,7 @@ impl Component for ConsoleComponent {
 
         let console_uart = s.2.write(UartDevice::new(self.uart_mux, true));
         console_uart.setup();
+        console_uart.set_name("console");
 
         let console = s.3.write(console::Console::new(
             console_uart,
@@ -229,6 +230,7 @@ impl<A: 'static + time::Alarm<'static>> Component for ConsoleOrderedComponent<A>
 
         let console_uart = static_buffer.2.write(UartDevice::new(self.uart_mux, true));
         console_uart.setup();
+        console_uart.set_name("console");
 
         let console = static_buffer.3.write(ConsoleOrdered::new(
             console_uart,
@@ -0,0 +1,75 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for keeping the kernel crash report in a flash page.
+//!
+//! Registers the page as the kernel's crash report storage, and reads the
+//! report of a previous boot from it.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let crash_report = components::crash_report::FlashCrashReportComponent::new(
+//!     virtual_crash_flash,
+//!     0x3f,
+//! )
+//! .finalize(components::flash_crash_report_component_static!(
+//!     capsules_core::virtualizers::virtual_flash::FlashUser<'static, nrf52833::nvmc::Nvmc>
+//! ));
+//! ```
+
+use capsules_extra::flash_crash_report::FlashCrashReport;
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::flash::{Flash, HasClient};
+
+#[macro_export]
+macro_rules! flash_crash_report_component_static {
+    ($F:ty $(,)?) => {{
+        let report =
+            kernel::static_buf!(capsules_extra::flash_crash_report::FlashCrashReport<'static, $F>);
+        let page = kernel::static_buf!(<$F as kernel::hil::flash::Flash>::Page);
+
+        (report, page)
+    };};
+}
+
+pub struct FlashCrashReportComponent<
+    F: 'static + Flash + HasClient<'static, FlashCrashReport<'static, F>>,
+> {
+    flash: &'static F,
+    page_number: usize,
+}
+
+impl<F: 'static + Flash + HasClient<'static, FlashCrashReport<'static, F>>>
+    FlashCrashReportComponent<F>
+{
+    /// `page_number` is the flash page reserved for the report.
+    pub fn new(flash: &'static F, page_number: usize) -> Self {
+        Self { flash, page_number }
+    }
+}
+
+impl<F: 'static + Flash + HasClient<'static, FlashCrashReport<'static, F>>> Component
+    for FlashCrashReportComponent<F>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<FlashCrashReport<'static, F>>,
+        &'static mut MaybeUninit<F::Page>,
+    );
+    type Output = &'static FlashCrashReport<'static, F>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let page = s.1.write(F::Page::default());
+        let crash_report =
+            s.0.write(FlashCrashReport::new(self.flash, self.page_number, page));
+        self.flash.set_client(crash_report);
+        unsafe {
+            kernel::crash_report::set_storage(crash_report);
+        }
+        let _ = crash_report.load();
+
+        crash_report
+    }
+}
@@ -114,6 +114,7 @@ impl<const BUF_SIZE_BYTES: usize> Component for DebugWriterComponent<BUF_SIZE_BY
         // Create virtual device for kernel debug.
         let debugger_uart = s.0.write(UartDevice::new(self.uart_mux, false));
         debugger_uart.setup();
+        debugger_uart.set_name("debug");
         let ring_buffer = s.1.write(RingBuffer::new(internal_buf));
         let debugger = s.3.write(kernel::debug::DebugWriter::new(
             debugger_uart,
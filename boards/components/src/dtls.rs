@@ -0,0 +1,276 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Component to initialize a kernel DTLS client session.
+//!
+//! This provides one Component, DtlsComponent. This component creates a DTLS
+//! session on top of the UDP/6LoWPAN stack, bound to a local UDP port in the
+//! kernel's port table. Records are protected with the AES-CCM hardware
+//! through its CCM virtualizer, and the handshake is computed with a software
+//! SHA-256 owned by the session. The port table only accepts bindings once
+//! the userland UDP driver is set up, so this component must be finalized
+//! after `UDPDriverComponent`.
+//!
+//! The random number generator seeds the handshake randoms, so it should be
+//! a cryptographically secure one such as the one `CsprngComponent` returns.
+//!
+//! Usage
+//! -----
+//! ```rust
+//!    let dtls_session = DtlsComponent::new(
+//!        udp_send_mux,
+//!        udp_recv_mux,
+//!        udp_port_table,
+//!        5684,
+//!        mux_alarm,
+//!        aes_mux,
+//!        csprng,
+//!        b"imix",
+//!        &PSK,
+//!     )
+//!     .finalize(components::dtls_component_static!(
+//!         sam4l::ast::Ast,
+//!         sam4l::aes::Aes
+//!     ));
+//!    dtls_session.set_client(telemetry);
+//! ```
+
+use capsules_core::virtualizers::virtual_aes_ccm::{MuxAES128CCM, VirtualAES128CCM};
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::net::dtls::session::{
+    CCM_BUF_LEN, DIGEST_LEN, HASH_BUF_LEN, TRANSCRIPT_BUF_LEN, TX_BUF_LEN,
+};
+use capsules_extra::net::dtls::DtlsSession;
+use capsules_extra::net::ipv6::ipv6_send::IP6SendStruct;
+use capsules_extra::net::network_capabilities::{
+    AddrRange, NetworkCapability, PortRange, UdpVisibilityCapability,
+};
+use capsules_extra::net::udp::udp_port_table::UdpPortManager;
+use capsules_extra::net::udp::udp_recv::{MuxUdpReceiver, UDPReceiver};
+use capsules_extra::net::udp::udp_send::{MuxUdpSender, UDPSendStruct, UDPSender};
+use capsules_extra::sha256::Sha256Software;
+use core::mem::MaybeUninit;
+use kernel::capabilities::NetworkCapabilityCreationCapability;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::deferred_call::DeferredCallClient;
+use kernel::hil::digest::Digest;
+use kernel::hil::rng::Random;
+use kernel::hil::symmetric_encryption::{
+    AES128Ctr, AES128, AES128CBC, AES128CCM, AES128ECB, AES128_BLOCK_SIZE,
+};
+use kernel::hil::time::Alarm;
+
+/// The size of the buffer the CCM virtualizer needs for the records of a
+/// session.
+pub const CRYPT_SIZE: usize = 3 * AES128_BLOCK_SIZE + CCM_BUF_LEN;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! dtls_component_static {
+    ($A:ty, $AES:ty $(,)?) => {{
+        use capsules_extra::net::dtls::session::{
+            CCM_BUF_LEN, DIGEST_LEN, HASH_BUF_LEN, TRANSCRIPT_BUF_LEN, TX_BUF_LEN,
+        };
+
+        let udp_send = kernel::static_buf!(
+            capsules_extra::net::udp::udp_send::UDPSendStruct<
+                'static,
+                capsules_extra::net::ipv6::ipv6_send::IP6SendStruct<
+                    'static,
+                    capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+                >,
+            >
+        );
+        let udp_recv =
+            kernel::static_buf!(capsules_extra::net::udp::udp_recv::UDPReceiver<'static>);
+        let udp_vis_cap =
+            kernel::static_buf!(capsules_extra::net::network_capabilities::UdpVisibilityCapability);
+        let net_cap =
+            kernel::static_buf!(capsules_extra::net::network_capabilities::NetworkCapability);
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let aes_ccm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_aes_ccm::VirtualAES128CCM<'static, $AES>
+        );
+        let crypt_buf = kernel::static_buf!([u8; components::dtls::CRYPT_SIZE]);
+        let sha = kernel::static_buf!(capsules_extra::sha256::Sha256Software<'static>);
+        let session = kernel::static_buf!(
+            capsules_extra::net::dtls::DtlsSession<
+                'static,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+                capsules_core::virtualizers::virtual_aes_ccm::VirtualAES128CCM<'static, $AES>,
+                capsules_extra::sha256::Sha256Software<'static>,
+            >
+        );
+        let tx_buffer = kernel::static_buf!([u8; TX_BUF_LEN]);
+        let ccm_buffer = kernel::static_buf!([u8; CCM_BUF_LEN]);
+        let transcript = kernel::static_buf!([u8; TRANSCRIPT_BUF_LEN]);
+        let hash_buffer = kernel::static_buf!([u8; HASH_BUF_LEN]);
+        let digest = kernel::static_buf!([u8; DIGEST_LEN]);
+
+        (
+            udp_send,
+            udp_recv,
+            udp_vis_cap,
+            net_cap,
+            alarm,
+            aes_ccm,
+            crypt_buf,
+            sha,
+            session,
+            tx_buffer,
+            ccm_buffer,
+            transcript,
+            hash_buffer,
+            digest,
+        )
+    };};
+}
+
+pub struct DtlsComponent<
+    A: Alarm<'static> + 'static,
+    AES: AES128<'static> + AES128Ctr + AES128CBC + AES128ECB + 'static,
+> {
+    udp_send_mux:
+        &'static MuxUdpSender<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+    udp_recv_mux: &'static MuxUdpReceiver<'static>,
+    port_table: &'static UdpPortManager,
+    port: u16,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    aes_mux: &'static MuxAES128CCM<'static, AES>,
+    rng: &'static dyn Random<'static>,
+    psk_identity: &'static [u8],
+    psk: &'static [u8],
+}
+
+impl<
+        A: Alarm<'static> + 'static,
+        AES: AES128<'static> + AES128Ctr + AES128CBC + AES128ECB + 'static,
+    > DtlsComponent<A, AES>
+{
+    pub fn new(
+        udp_send_mux: &'static MuxUdpSender<
+            'static,
+            IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
+        >,
+        udp_recv_mux: &'static MuxUdpReceiver<'static>,
+        port_table: &'static UdpPortManager,
+        port: u16,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        aes_mux: &'static MuxAES128CCM<'static, AES>,
+        rng: &'static dyn Random<'static>,
+        psk_identity: &'static [u8],
+        psk: &'static [u8],
+    ) -> Self {
+        Self {
+            udp_send_mux,
+            udp_recv_mux,
+            port_table,
+            port,
+            alarm_mux,
+            aes_mux,
+            rng,
+            psk_identity,
+            psk,
+        }
+    }
+}
+
+impl<
+        A: Alarm<'static> + 'static,
+        AES: AES128<'static> + AES128Ctr + AES128CBC + AES128ECB + 'static,
+    > Component for DtlsComponent<A, AES>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<
+            UDPSendStruct<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+        >,
+        &'static mut MaybeUninit<UDPReceiver<'static>>,
+        &'static mut MaybeUninit<UdpVisibilityCapability>,
+        &'static mut MaybeUninit<NetworkCapability>,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<VirtualAES128CCM<'static, AES>>,
+        &'static mut MaybeUninit<[u8; CRYPT_SIZE]>,
+        &'static mut MaybeUninit<Sha256Software<'static>>,
+        &'static mut MaybeUninit<
+            DtlsSession<
+                'static,
+                VirtualMuxAlarm<'static, A>,
+                VirtualAES128CCM<'static, AES>,
+                Sha256Software<'static>,
+            >,
+        >,
+        &'static mut MaybeUninit<[u8; TX_BUF_LEN]>,
+        &'static mut MaybeUninit<[u8; CCM_BUF_LEN]>,
+        &'static mut MaybeUninit<[u8; TRANSCRIPT_BUF_LEN]>,
+        &'static mut MaybeUninit<[u8; HASH_BUF_LEN]>,
+        &'static mut MaybeUninit<[u8; DIGEST_LEN]>,
+    );
+    type Output = &'static DtlsSession<
+        'static,
+        VirtualMuxAlarm<'static, A>,
+        VirtualAES128CCM<'static, AES>,
+        Sha256Software<'static>,
+    >;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let create_cap = create_capability!(NetworkCapabilityCreationCapability);
+        let udp_vis = s.2.write(UdpVisibilityCapability::new(&create_cap));
+        let udp_send = s.0.write(UDPSendStruct::new(self.udp_send_mux, udp_vis));
+        let udp_recv = s.1.write(UDPReceiver::new());
+        self.udp_recv_mux.add_client(udp_recv);
+
+        let net_cap = s.3.write(NetworkCapability::new(
+            AddrRange::Any,
+            PortRange::Any,
+            PortRange::Any,
+            &create_cap,
+        ));
+
+        let socket = self
+            .port_table
+            .create_socket()
+            .expect("DTLS: no free UDP socket");
+        let (send_binding, recv_binding) = self
+            .port_table
+            .bind(socket, self.port, net_cap)
+            .expect("DTLS: UDP port unavailable");
+        udp_send.set_binding(send_binding);
+        udp_recv.set_binding(recv_binding);
+
+        let alarm = s.4.write(VirtualMuxAlarm::new(self.alarm_mux));
+        alarm.setup();
+
+        let crypt_buf = s.6.write([0; CRYPT_SIZE]);
+        let aes_ccm = s.5.write(VirtualAES128CCM::new(self.aes_mux, crypt_buf));
+        aes_ccm.setup();
+
+        let sha = s.7.write(Sha256Software::new());
+        sha.register();
+
+        let session = s.8.write(DtlsSession::new(
+            udp_send,
+            alarm,
+            aes_ccm,
+            sha,
+            self.rng,
+            net_cap,
+            self.psk_identity,
+            self.psk,
+            s.9.write([0; TX_BUF_LEN]),
+            s.10.write([0; CCM_BUF_LEN]),
+            s.11.write([0; TRANSCRIPT_BUF_LEN]),
+            s.12.write([0; HASH_BUF_LEN]),
+            s.13.write([0; DIGEST_LEN]),
+        ));
+        udp_send.set_client(session);
+        udp_recv.set_client(session);
+        alarm.set_alarm_client(session);
+        AES128CCM::set_client(aes_ccm, session);
+        sha.set_client(session);
+        session
+    }
+}
@@ -0,0 +1,128 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Component for the ENC28J60 SPI Ethernet controller.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let enc28j60 = components::enc28j60::Enc28j60Component::new(
+//!     mux_spi,
+//!     chip_select,
+//!     &gpio_port[ENC28J60_INT_PIN],
+//!     mux_alarm,
+//!     [0x02, 0x00, 0x00, 0x00, 0x00, 0x01],
+//! )
+//! .finalize(components::enc28j60_component_static!(
+//!     nrf52::spi::SPIM,
+//!     nrf52::rtc::Rtc
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_core::virtualizers::virtual_spi::{MuxSpiMaster, VirtualSpiMasterDevice};
+use capsules_extra::enc28j60::Enc28j60;
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil;
+use kernel::hil::ethernet::MacAddress;
+use kernel::hil::gpio;
+use kernel::hil::spi::SpiMasterDevice;
+use kernel::hil::time::Alarm;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! enc28j60_component_static {
+    ($S:ty, $A:ty $(,)?) => {{
+        let spi_device = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_spi::VirtualSpiMasterDevice<'static, $S>
+        );
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let enc28j60 = kernel::static_buf!(
+            capsules_extra::enc28j60::Enc28j60<
+                'static,
+                capsules_core::virtualizers::virtual_spi::VirtualSpiMasterDevice<'static, $S>,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+
+        let tx_buf = kernel::static_buf!([u8; capsules_extra::enc28j60::BUF_LEN]);
+        let rx_buf = kernel::static_buf!([u8; capsules_extra::enc28j60::BUF_LEN]);
+
+        (spi_device, alarm, enc28j60, tx_buf, rx_buf)
+    };};
+}
+
+pub struct Enc28j60Component<
+    S: 'static + hil::spi::SpiMaster,
+    A: 'static + hil::time::Alarm<'static>,
+> {
+    mux_spi: &'static MuxSpiMaster<'static, S>,
+    chip_select: S::ChipSelect,
+    interrupt_pin: &'static dyn gpio::InterruptPin<'static>,
+    mux_alarm: &'static MuxAlarm<'static, A>,
+    address: MacAddress,
+}
+
+impl<S: 'static + hil::spi::SpiMaster, A: 'static + hil::time::Alarm<'static>>
+    Enc28j60Component<S, A>
+{
+    pub fn new(
+        mux_spi: &'static MuxSpiMaster<'static, S>,
+        chip_select: S::ChipSelect,
+        interrupt_pin: &'static dyn gpio::InterruptPin<'static>,
+        mux_alarm: &'static MuxAlarm<'static, A>,
+        address: MacAddress,
+    ) -> Enc28j60Component<S, A> {
+        Enc28j60Component {
+            mux_spi,
+            chip_select,
+            interrupt_pin,
+            mux_alarm,
+            address,
+        }
+    }
+}
+
+impl<S: 'static + hil::spi::SpiMaster, A: 'static + hil::time::Alarm<'static>> Component
+    for Enc28j60Component<S, A>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualSpiMasterDevice<'static, S>>,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<
+            Enc28j60<'static, VirtualSpiMasterDevice<'static, S>, VirtualMuxAlarm<'static, A>>,
+        >,
+        &'static mut MaybeUninit<[u8; capsules_extra::enc28j60::BUF_LEN]>,
+        &'static mut MaybeUninit<[u8; capsules_extra::enc28j60::BUF_LEN]>,
+    );
+    type Output =
+        &'static Enc28j60<'static, VirtualSpiMasterDevice<'static, S>, VirtualMuxAlarm<'static, A>>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let enc28j60_spi =
+            s.0.write(VirtualSpiMasterDevice::new(self.mux_spi, self.chip_select));
+        let enc28j60_alarm = s.1.write(VirtualMuxAlarm::new(self.mux_alarm));
+        enc28j60_alarm.setup();
+
+        let tx_buf = s.3.write([0; capsules_extra::enc28j60::BUF_LEN]);
+        let rx_buf = s.4.write([0; capsules_extra::enc28j60::BUF_LEN]);
+
+        let enc28j60 = s.2.write(Enc28j60::new(
+            enc28j60_spi,
+            self.interrupt_pin,
+            enc28j60_alarm,
+            tx_buf,
+            rx_buf,
+            self.address,
+        ));
+        enc28j60_spi.setup();
+        enc28j60_spi.set_client(enc28j60);
+        enc28j60_alarm.set_alarm_client(enc28j60);
+        self.interrupt_pin.set_client(enc28j60);
+        enc28j60
+    }
+}
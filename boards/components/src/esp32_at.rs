@@ -0,0 +1,122 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for Wi-Fi with an ESP32 module running the ESP-AT firmware.
+//!
+//! `uart_mux` should be a mux of the UART the module is attached to, set up
+//! with the baud rate of the module.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let esp32_uart_mux = components::console::UartMuxComponent::new(
+//!     &peripherals.uarte1,
+//!     115200,
+//! )
+//! .finalize(components::uart_mux_component_static!());
+//! let wifi = components::esp32_at::Esp32AtComponent::new(
+//!     board_kernel,
+//!     capsules_extra::wifi::DRIVER_NUM,
+//!     esp32_uart_mux,
+//! )
+//! .finalize(components::esp32_at_component_static!());
+//! ```
+
+use capsules_core::virtualizers::virtual_uart::{MuxUart, UartDevice};
+use capsules_extra::esp32_at::{Esp32At, LINE_BUF_LEN, RX_BUF_LEN, TX_BUF_LEN};
+use capsules_extra::wifi::{WifiDriver, BUF_LEN};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil;
+use kernel::hil::wifi::Station;
+
+#[macro_export]
+macro_rules! esp32_at_component_static {
+    () => {{
+        let uart =
+            kernel::static_buf!(capsules_core::virtualizers::virtual_uart::UartDevice<'static>);
+        let esp32 = kernel::static_buf!(capsules_extra::esp32_at::Esp32At<'static>);
+        let wifi = kernel::static_buf!(
+            capsules_extra::wifi::WifiDriver<'static, capsules_extra::esp32_at::Esp32At<'static>>
+        );
+        let tx_buffer = kernel::static_buf!([u8; capsules_extra::esp32_at::TX_BUF_LEN]);
+        let rx_buffer = kernel::static_buf!([u8; capsules_extra::esp32_at::RX_BUF_LEN]);
+        let line_buffer = kernel::static_buf!([u8; capsules_extra::esp32_at::LINE_BUF_LEN]);
+        let send_buffer = kernel::static_buf!([u8; capsules_extra::wifi::BUF_LEN]);
+
+        (
+            uart,
+            esp32,
+            wifi,
+            tx_buffer,
+            rx_buffer,
+            line_buffer,
+            send_buffer,
+        )
+    };};
+}
+
+pub struct Esp32AtComponent {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    uart_mux: &'static MuxUart<'static>,
+}
+
+impl Esp32AtComponent {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        uart_mux: &'static MuxUart<'static>,
+    ) -> Esp32AtComponent {
+        Esp32AtComponent {
+            board_kernel,
+            driver_num,
+            uart_mux,
+        }
+    }
+}
+
+impl Component for Esp32AtComponent {
+    type StaticInput = (
+        &'static mut MaybeUninit<UartDevice<'static>>,
+        &'static mut MaybeUninit<Esp32At<'static>>,
+        &'static mut MaybeUninit<WifiDriver<'static, Esp32At<'static>>>,
+        &'static mut MaybeUninit<[u8; TX_BUF_LEN]>,
+        &'static mut MaybeUninit<[u8; RX_BUF_LEN]>,
+        &'static mut MaybeUninit<[u8; LINE_BUF_LEN]>,
+        &'static mut MaybeUninit<[u8; BUF_LEN]>,
+    );
+    type Output = &'static WifiDriver<'static, Esp32At<'static>>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let uart = s.0.write(UartDevice::new(self.uart_mux, true));
+        uart.setup();
+        uart.set_name("esp32");
+
+        let esp32 = s.1.write(Esp32At::new(
+            uart,
+            s.3.write([0; TX_BUF_LEN]),
+            s.4.write([0; RX_BUF_LEN]),
+            s.5.write([0; LINE_BUF_LEN]),
+        ));
+        hil::uart::Transmit::set_transmit_client(uart, esp32);
+        hil::uart::Receive::set_receive_client(uart, esp32);
+
+        let wifi = s.2.write(WifiDriver::new(
+            esp32,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+            s.6.write([0; BUF_LEN]),
+        ));
+        esp32.set_scan_client(wifi);
+        esp32.set_station_client(wifi);
+        esp32.set_socket_client(wifi);
+        esp32.initialize();
+
+        wifi
+    }
+}
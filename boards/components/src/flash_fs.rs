@@ -0,0 +1,116 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for a file system in a region of flash pages, and its syscall
+//! driver.
+//!
+//! The file system is mounted when the component is finalized, which formats
+//! the region if it holds no file system. The second argument of the static
+//! macro is the number of files and directories it holds.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let flash_fs = components::flash_fs::FlashFsComponent::new(
+//!     virtual_fs_flash,
+//!     board_kernel,
+//!     capsules_extra::flash_fs_driver::DRIVER_NUM,
+//!     0xe0,
+//!     32,
+//! )
+//! .finalize(components::flash_fs_component_static!(
+//!     capsules_core::virtualizers::virtual_flash::FlashUser<'static, nrf52840::nvmc::Nvmc>,
+//!     16
+//! ));
+//! ```
+
+use capsules_extra::flash_fs::{Entry, FlashFs};
+use capsules_extra::flash_fs_driver::{FlashFsDriver, BUF_LEN};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::flash::{Flash, HasClient};
+
+#[macro_export]
+macro_rules! flash_fs_component_static {
+    ($F:ty, $N:expr $(,)?) => {{
+        let fs = kernel::static_buf!(capsules_extra::flash_fs::FlashFs<'static, $F>);
+        let driver =
+            kernel::static_buf!(capsules_extra::flash_fs_driver::FlashFsDriver<'static, $F>);
+        let page = kernel::static_buf!(<$F as kernel::hil::flash::Flash>::Page);
+        let entries = kernel::static_buf!([capsules_extra::flash_fs::Entry; $N]);
+        let buffer = kernel::static_buf!([u8; capsules_extra::flash_fs_driver::BUF_LEN]);
+
+        (fs, driver, page, entries, buffer)
+    };};
+}
+
+pub struct FlashFsComponent<
+    F: 'static + Flash + HasClient<'static, FlashFs<'static, F>>,
+    const N: usize,
+> {
+    flash: &'static F,
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    first_page: usize,
+    num_pages: usize,
+}
+
+impl<F: 'static + Flash + HasClient<'static, FlashFs<'static, F>>, const N: usize>
+    FlashFsComponent<F, N>
+{
+    /// The file system takes `num_pages` flash pages from `first_page`.
+    pub fn new(
+        flash: &'static F,
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        first_page: usize,
+        num_pages: usize,
+    ) -> Self {
+        Self {
+            flash,
+            board_kernel,
+            driver_num,
+            first_page,
+            num_pages,
+        }
+    }
+}
+
+impl<F: 'static + Flash + HasClient<'static, FlashFs<'static, F>>, const N: usize> Component
+    for FlashFsComponent<F, N>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<FlashFs<'static, F>>,
+        &'static mut MaybeUninit<FlashFsDriver<'static, F>>,
+        &'static mut MaybeUninit<F::Page>,
+        &'static mut MaybeUninit<[Entry; N]>,
+        &'static mut MaybeUninit<[u8; BUF_LEN]>,
+    );
+    type Output = &'static FlashFsDriver<'static, F>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let fs = s.0.write(FlashFs::new(
+            self.flash,
+            self.first_page,
+            self.num_pages,
+            s.2.write(F::Page::default()),
+            s.3.write([Entry::default(); N]),
+        ));
+        self.flash.set_client(fs);
+
+        let driver = s.1.write(FlashFsDriver::new(
+            fs,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+            s.4.write([0; BUF_LEN]),
+        ));
+        fs.set_client(driver);
+        let _ = fs.mount();
+
+        driver
+    }
+}
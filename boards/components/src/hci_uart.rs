@@ -0,0 +1,96 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Component for the HCI UART (H:4) transport to an external Bluetooth
+//! controller.
+//!
+//! `uart_mux` should be a mux of the UART the controller is attached to, set
+//! up with the baud rate of the controller.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let controller_uart_mux = components::console::UartMuxComponent::new(
+//!     &peripherals.usart3,
+//!     1_000_000,
+//! )
+//! .finalize(components::uart_mux_component_static!());
+//! let hci = components::hci_uart::HciUartComponent::new(
+//!     board_kernel,
+//!     capsules_extra::hci_uart::DRIVER_NUM,
+//!     controller_uart_mux,
+//! )
+//! .finalize(components::hci_uart_component_static!());
+//! ```
+
+use capsules_core::virtualizers::virtual_uart::{MuxUart, UartDevice};
+use capsules_extra::hci_uart::{HciUart, BUF_LEN};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil;
+
+#[macro_export]
+macro_rules! hci_uart_component_static {
+    () => {{
+        let uart =
+            kernel::static_buf!(capsules_core::virtualizers::virtual_uart::UartDevice<'static>);
+        let hci = kernel::static_buf!(capsules_extra::hci_uart::HciUart<'static>);
+        let tx_buffer = kernel::static_buf!([u8; capsules_extra::hci_uart::BUF_LEN]);
+        let rx_buffer = kernel::static_buf!([u8; capsules_extra::hci_uart::BUF_LEN]);
+
+        (uart, hci, tx_buffer, rx_buffer)
+    };};
+}
+
+pub struct HciUartComponent {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    uart_mux: &'static MuxUart<'static>,
+}
+
+impl HciUartComponent {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        uart_mux: &'static MuxUart<'static>,
+    ) -> HciUartComponent {
+        HciUartComponent {
+            board_kernel,
+            driver_num,
+            uart_mux,
+        }
+    }
+}
+
+impl Component for HciUartComponent {
+    type StaticInput = (
+        &'static mut MaybeUninit<UartDevice<'static>>,
+        &'static mut MaybeUninit<HciUart<'static>>,
+        &'static mut MaybeUninit<[u8; BUF_LEN]>,
+        &'static mut MaybeUninit<[u8; BUF_LEN]>,
+    );
+    type Output = &'static HciUart<'static>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let uart = s.0.write(UartDevice::new(self.uart_mux, true));
+        uart.setup();
+        uart.set_name("hci");
+
+        let hci = s.1.write(HciUart::new(
+            uart,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+            s.2.write([0; BUF_LEN]),
+            s.3.write([0; BUF_LEN]),
+        ));
+        hil::uart::Transmit::set_transmit_client(uart, hci);
+        hil::uart::Receive::set_receive_client(uart, hci);
+        hci.initialize();
+
+        hci
+    }
+}
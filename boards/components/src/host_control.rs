@@ -0,0 +1,96 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for HostControl, the binary control channel for host tools.
+//!
+//! The channel should have a UART to itself, so pass a `MuxUart` that the
+//! console and debug output do not use.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let host_control = HostControlComponent::new(board_kernel, control_uart_mux)
+//!     .finalize(components::host_control_component_static!());
+//! let _ = host_control.start();
+//! ```
+
+use capsules_core::virtualizers::virtual_uart::{MuxUart, UartDevice};
+use capsules_extra::host_control::{self, HostControl};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::hil;
+
+#[macro_export]
+macro_rules! host_control_component_static {
+    () => {{
+        let uart =
+            kernel::static_buf!(capsules_core::virtualizers::virtual_uart::UartDevice<'static>);
+        let rx_buffer = kernel::static_buf!([u8; capsules_extra::host_control::READ_BUF_LEN]);
+        let frame = kernel::static_buf!([u8; capsules_extra::host_control::BUF_LEN]);
+        let tx_buffer = kernel::static_buf!([u8; capsules_extra::host_control::BUF_LEN]);
+        let host_control = kernel::static_buf!(
+            capsules_extra::host_control::HostControl<
+                'static,
+                components::host_control::Capability,
+            >
+        );
+
+        (uart, rx_buffer, frame, tx_buffer, host_control)
+    };};
+}
+
+pub struct HostControlComponent {
+    board_kernel: &'static kernel::Kernel,
+    uart_mux: &'static MuxUart<'static>,
+}
+
+impl HostControlComponent {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        uart_mux: &'static MuxUart,
+    ) -> HostControlComponent {
+        HostControlComponent {
+            board_kernel,
+            uart_mux,
+        }
+    }
+}
+
+pub struct Capability;
+unsafe impl capabilities::ProcessManagementCapability for Capability {}
+
+impl Component for HostControlComponent {
+    type StaticInput = (
+        &'static mut MaybeUninit<UartDevice<'static>>,
+        &'static mut MaybeUninit<[u8; host_control::READ_BUF_LEN]>,
+        &'static mut MaybeUninit<[u8; host_control::BUF_LEN]>,
+        &'static mut MaybeUninit<[u8; host_control::BUF_LEN]>,
+        &'static mut MaybeUninit<HostControl<'static, Capability>>,
+    );
+    type Output = &'static HostControl<'static, Capability>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let uart = s.0.write(UartDevice::new(self.uart_mux, true));
+        uart.setup();
+        uart.set_name("host_control");
+
+        let rx_buffer = s.1.write([0; host_control::READ_BUF_LEN]);
+        let frame = s.2.write([0; host_control::BUF_LEN]);
+        let tx_buffer = s.3.write([0; host_control::BUF_LEN]);
+
+        let host_control = s.4.write(HostControl::new(
+            uart,
+            self.board_kernel,
+            rx_buffer,
+            frame,
+            tx_buffer,
+            Capability,
+        ));
+        hil::uart::Transmit::set_transmit_client(uart, host_control);
+        hil::uart::Receive::set_receive_client(uart, host_control);
+
+        host_control
+    }
+}
@@ -7,6 +7,8 @@
 //! This provides one Component, `Ieee802154Component`, which implements a
 //! userspace syscall interface to a full 802.15.4 stack with a always-on MAC
 //! implementation, as well as multiplexed access to that MAC implementation.
+//! The syscall interface can set the channel of the radio and scan the energy
+//! on its channels.
 //!
 //! Usage
 //! -----
@@ -32,16 +34,38 @@
 //!     nrf52::aes::AesECB<'static>
 //! ));
 //! ```
+//!
+//! On boards with a key-value store, `Ieee802154FrameCounterComponent`
+//! persists the outgoing frame counter of the radio driver in it:
+//!
+//! ```rust
+//! components::ieee802154::Ieee802154FrameCounterComponent::new(
+//!     kv_store,
+//!     radio,
+//!     FRAME_COUNTER_STORAGE_ID,
+//! )
+//! .finalize(components::ieee802154_frame_counter_component_static!(
+//!     capsules_extra::tickv::TicKVStore<
+//!         capsules_core::virtualizers::virtual_flash::FlashUser<lowrisc::flash_ctrl::FlashCtrl>,
+//!     >,
+//!     capsules_extra::tickv::TicKVKeyType,
+//! ));
+//! ```
 
 use capsules_core::virtualizers::virtual_aes_ccm::MuxAES128CCM;
 use capsules_extra::ieee802154::device::MacDevice;
+use capsules_extra::ieee802154::frame_counter::{FrameCounterStore, KEY, KEY_LEN, VALUE_BUF_LEN};
 use capsules_extra::ieee802154::mac::{AwakeMac, Mac};
+use capsules_extra::ieee802154::RadioDriver;
+use capsules_extra::kv_store::KVStore;
 use core::mem::MaybeUninit;
 use kernel::capabilities;
 use kernel::component::Component;
 use kernel::create_capability;
+use kernel::hil::kv_system::{KVSystem, KeyType};
 use kernel::hil::radio;
 use kernel::hil::symmetric_encryption::{self, AES128Ctr, AES128, AES128CBC, AES128CCM, AES128ECB};
+use kernel::storage_permissions::StoragePermissions;
 
 // This buffer is used as an intermediate buffer for AES CCM encryption. An
 // upper bound on the required size is `3 * BLOCK_SIZE + radio::MAX_BUF_SIZE`.
@@ -218,6 +242,7 @@ impl<
             ));
         mac_device.set_transmit_client(mux_mac);
         mac_device.set_receive_client(mux_mac);
+        awake_mac.set_capture_client(mux_mac);
 
         let userspace_mac =
             static_buffer
@@ -239,11 +264,85 @@ impl<
 
         mac_device.set_key_procedure(radio_driver);
         mac_device.set_device_procedure(radio_driver);
+        mac_device.set_frame_counter_procedure(radio_driver);
         userspace_mac.set_transmit_client(radio_driver);
         userspace_mac.set_receive_client(radio_driver);
         userspace_mac.set_pan(self.pan_id);
         userspace_mac.set_address(self.short_addr);
+        radio_driver.set_radio_config(self.radio);
+        self.radio.set_energy_detect_client(radio_driver);
 
         (radio_driver, mux_mac)
     }
 }
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! ieee802154_frame_counter_component_static {
+    ($K:ty, $T:ty $(,)?) => {{
+        use capsules_extra::ieee802154::frame_counter::{KEY_LEN, VALUE_BUF_LEN};
+
+        let store = kernel::static_buf!(
+            capsules_extra::ieee802154::frame_counter::FrameCounterStore<'static, $K, $T>
+        );
+        let key = kernel::static_buf!([u8; KEY_LEN]);
+        let value = kernel::static_buf!([u8; VALUE_BUF_LEN]);
+
+        (store, key, value)
+    };};
+}
+
+pub struct Ieee802154FrameCounterComponent<
+    K: 'static + KVSystem<'static, K = T>,
+    T: 'static + KeyType,
+> {
+    kv_store: &'static KVStore<'static, K, T>,
+    radio_driver: &'static RadioDriver<'static>,
+    storage_id: u32,
+}
+
+impl<K: 'static + KVSystem<'static, K = T>, T: 'static + KeyType>
+    Ieee802154FrameCounterComponent<K, T>
+{
+    /// The frame counter is stored with the kernel storage permissions of
+    /// `storage_id`, which must not be given to processes.
+    pub fn new(
+        kv_store: &'static KVStore<'static, K, T>,
+        radio_driver: &'static RadioDriver<'static>,
+        storage_id: u32,
+    ) -> Self {
+        Self {
+            kv_store,
+            radio_driver,
+            storage_id,
+        }
+    }
+}
+
+impl<K: 'static + KVSystem<'static, K = T>, T: 'static + KeyType> Component
+    for Ieee802154FrameCounterComponent<K, T>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<FrameCounterStore<'static, K, T>>,
+        &'static mut MaybeUninit<[u8; KEY_LEN]>,
+        &'static mut MaybeUninit<[u8; VALUE_BUF_LEN]>,
+    );
+    type Output = &'static FrameCounterStore<'static, K, T>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let storage_cap = create_capability!(capabilities::KernelStorageCapability);
+        let perms = StoragePermissions::new_kernel(self.storage_id, &storage_cap);
+
+        let key = static_buffer.1.write([0; KEY_LEN]);
+        key.copy_from_slice(KEY);
+        let value = static_buffer.2.write([0; VALUE_BUF_LEN]);
+
+        let store = static_buffer
+            .0
+            .write(FrameCounterStore::new(self.kv_store, perms, key, value));
+        self.kv_store.set_client(store);
+        self.radio_driver.set_frame_counter_store(store);
+        let _ = store.load();
+        store
+    }
+}
@@ -0,0 +1,95 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Component for an IEEE 802.15.4 sniffer.
+//!
+//! This provides one Component, `Ieee802154SnifferComponent`, which creates a
+//! `Sniffer` on a device of the UART mux and enables the capture mode of the
+//! 802.15.4 MAC mux, so that every received frame is streamed over the UART
+//! in the pcap format. The console should not share the UART mux with the
+//! sniffer, as its output would corrupt the pcap stream.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let sniffer = components::ieee802154_sniffer::Ieee802154SnifferComponent::new(
+//!     mux_mac,
+//!     uart_mux,
+//!     rtc,
+//! )
+//! .finalize(components::ieee802154_sniffer_component_static!(nrf52::rtc::Rtc));
+//! ```
+
+use capsules_core::virtualizers::virtual_uart::{MuxUart, UartDevice};
+use capsules_extra::ieee802154::sniffer::{Sniffer, BUF_LEN};
+use capsules_extra::ieee802154::virtual_mac::MuxMac;
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil;
+use kernel::hil::time::Time;
+
+#[macro_export]
+macro_rules! ieee802154_sniffer_component_static {
+    ($T:ty $(,)?) => {{
+        let uart =
+            kernel::static_buf!(capsules_core::virtualizers::virtual_uart::UartDevice<'static>);
+        let sniffer = kernel::static_buf!(
+            capsules_extra::ieee802154::sniffer::Sniffer<
+                'static,
+                capsules_core::virtualizers::virtual_uart::UartDevice<'static>,
+                $T,
+            >
+        );
+        let buffer = kernel::static_buf!([u8; capsules_extra::ieee802154::sniffer::BUF_LEN]);
+
+        (uart, sniffer, buffer)
+    };};
+}
+
+pub struct Ieee802154SnifferComponent<T: 'static + Time> {
+    mux_mac: &'static MuxMac<'static>,
+    uart_mux: &'static MuxUart<'static>,
+    time: &'static T,
+}
+
+impl<T: 'static + Time> Ieee802154SnifferComponent<T> {
+    pub fn new(
+        mux_mac: &'static MuxMac<'static>,
+        uart_mux: &'static MuxUart<'static>,
+        time: &'static T,
+    ) -> Self {
+        Self {
+            mux_mac,
+            uart_mux,
+            time,
+        }
+    }
+}
+
+impl<T: 'static + Time> Component for Ieee802154SnifferComponent<T> {
+    type StaticInput = (
+        &'static mut MaybeUninit<UartDevice<'static>>,
+        &'static mut MaybeUninit<Sniffer<'static, UartDevice<'static>, T>>,
+        &'static mut MaybeUninit<[u8; BUF_LEN]>,
+    );
+    type Output = &'static Sniffer<'static, UartDevice<'static>, T>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let capture_cap = create_capability!(capabilities::Ieee802154CaptureCapability);
+
+        let uart = s.0.write(UartDevice::new(self.uart_mux, false));
+        uart.setup();
+        uart.set_name("ieee802154-sniffer");
+
+        let buffer = s.2.write([0; BUF_LEN]);
+        let sniffer = s.1.write(Sniffer::new(uart, self.time, buffer));
+        hil::uart::Transmit::set_transmit_client(uart, sniffer);
+
+        self.mux_mac.enable_capture(sniffer, &capture_cap);
+        let _ = sniffer.start();
+        sniffer
+    }
+}
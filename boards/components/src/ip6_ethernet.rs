@@ -0,0 +1,107 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Component to initialize the IPv6 layer of an Ethernet interface.
+//!
+//! This provides one Component, Ip6EthernetComponent, which sends and
+//! receives uncompressed IPv6 packets over an `EthernetAdapter`. It exposes
+//! the IPv6 sender, which a `MuxUdpSender` can be put on top of, and the IPv6
+//! receiver, whose clients are set like those of the 6LoWPAN interface of
+//! `UDPMuxComponent`. A board with both interfaces routes between them by
+//! forwarding the packets each receiver does not accept locally through the
+//! sender of the other.
+//!
+//! Usage
+//! -----
+//! ```rust
+//!    let (eth_ip_send, eth_ip_receive) = components::ip6_ethernet::Ip6EthernetComponent::new(
+//!        enc28j60,
+//!        GATEWAY_MAC_ADDR,
+//!        local_ip_ifaces,
+//!    )
+//!    .finalize(components::ip6_ethernet_component_static!());
+//!    enc28j60.enable();
+//! ```
+
+use capsules_extra::net::ipv6::ipv6_recv::IP6RecvStruct;
+use capsules_extra::net::ipv6::ipv6_send::{IP6EthSendStruct, IP6Sender};
+use capsules_extra::net::ipv6::slaac::InterfaceList;
+use capsules_extra::net::network_capabilities::IpVisibilityCapability;
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::ethernet::{self, EthernetAdapter};
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! ip6_ethernet_component_static {
+    () => {{
+        let ip6_send =
+            kernel::static_buf!(capsules_extra::net::ipv6::ipv6_send::IP6EthSendStruct<'static>);
+        let ip6_receive =
+            kernel::static_buf!(capsules_extra::net::ipv6::ipv6_recv::IP6RecvStruct<'static>);
+        let frame = kernel::static_buf!([u8; kernel::hil::ethernet::MAX_FRAME_LEN]);
+        let ip_vis_cap =
+            kernel::static_buf!(capsules_extra::net::network_capabilities::IpVisibilityCapability);
+
+        (ip6_send, ip6_receive, frame, ip_vis_cap)
+    };};
+}
+
+pub struct Ip6EthernetComponent {
+    ethernet: &'static dyn EthernetAdapter<'static>,
+    gateway: ethernet::MacAddress,
+    interface_list: &'static InterfaceList,
+}
+
+impl Ip6EthernetComponent {
+    pub fn new(
+        ethernet: &'static dyn EthernetAdapter<'static>,
+        gateway: ethernet::MacAddress,
+        interface_list: &'static InterfaceList,
+    ) -> Self {
+        Self {
+            ethernet,
+            gateway,
+            interface_list,
+        }
+    }
+}
+
+impl Component for Ip6EthernetComponent {
+    type StaticInput = (
+        &'static mut MaybeUninit<IP6EthSendStruct<'static>>,
+        &'static mut MaybeUninit<IP6RecvStruct<'static>>,
+        &'static mut MaybeUninit<[u8; ethernet::MAX_FRAME_LEN]>,
+        &'static mut MaybeUninit<IpVisibilityCapability>,
+    );
+    type Output = (
+        &'static IP6EthSendStruct<'static>,
+        &'static IP6RecvStruct<'static>,
+    );
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let create_cap = create_capability!(capabilities::NetworkCapabilityCreationCapability);
+        let ip_vis = s.3.write(IpVisibilityCapability::new(&create_cap));
+
+        let frame = s.2.write([0; ethernet::MAX_FRAME_LEN]);
+
+        // Unicast packets are sent to the gateway unless a neighbor cache
+        // resolves their next hop, as on the 6LoWPAN interface.
+        let ip_send = s.0.write(IP6EthSendStruct::new(
+            frame,
+            self.ethernet,
+            self.gateway,
+            ip_vis,
+        ));
+        ip_send.set_interface_list(self.interface_list);
+        self.ethernet.set_transmit_client(ip_send);
+
+        let ip_receive = s.1.write(IP6RecvStruct::new());
+        self.ethernet.set_receive_client(ip_receive);
+
+        (ip_send, ip_receive)
+    }
+}
@@ -19,48 +19,66 @@ pub mod button;
 pub mod can;
 pub mod ccs811;
 pub mod cdc;
+pub mod coap_driver;
 pub mod console;
+pub mod crash_report;
 pub mod crc;
 pub mod ctap;
 pub mod dac;
 pub mod debug_queue;
 pub mod debug_writer;
 pub mod digest;
+pub mod dtls;
+pub mod enc28j60;
+pub mod esp32_at;
 pub mod flash;
+pub mod flash_fs;
 pub mod fm25cl;
 pub mod ft6x06;
 pub mod fxos8700;
 pub mod gpio;
+pub mod hci_uart;
 pub mod hd44780;
 pub mod hmac;
+pub mod host_control;
 pub mod hts221;
 pub mod humidity;
 pub mod i2c;
 pub mod ieee802154;
+pub mod ieee802154_sniffer;
+pub mod ip6_ethernet;
 pub mod isl29035;
 pub mod kv_system;
 pub mod l3gd20;
 pub mod led;
 pub mod led_matrix;
 pub mod lldb;
+pub mod log_driver;
+pub mod lora;
+pub mod lorawan;
 pub mod lpm013m126;
 pub mod lps25hb;
 pub mod lsm303agr;
 pub mod lsm303dlhc;
 pub mod lsm6dsox;
 pub mod ltc294x;
+pub mod mdns;
+pub mod mld;
 pub mod mlx90614;
 pub mod mx25r6435f;
+pub mod neighbor_discovery;
 pub mod ninedof;
 pub mod nonvolatile_storage;
 pub mod nrf51822;
 pub mod panic_button;
+pub mod periodic_timer;
 pub mod process_console;
 pub mod process_printer;
 pub mod proximity;
 pub mod pwm;
 pub mod rf233;
 pub mod rng;
+pub mod rpl;
 pub mod sched;
 pub mod screen;
 pub mod segger_rtt;
@@ -70,11 +88,14 @@ pub mod si7021;
 pub mod sound_pressure;
 pub mod spi;
 pub mod st77xx;
+pub mod sx127x;
+pub mod tcp_driver;
 pub mod temperature;
 pub mod temperature_rp2040;
 pub mod temperature_stm;
 pub mod test;
 pub mod text_screen;
+pub mod thread;
 pub mod tickv;
 pub mod touch;
 pub mod udp_driver;
@@ -78,6 +78,7 @@ impl Component for LowLevelDebugComponent {
 
         let lldb_uart = s.0.write(UartDevice::new(self.uart_mux, true));
         lldb_uart.setup();
+        lldb_uart.set_name("lldb");
 
         let buffer = s.1.write([0; capsules_core::low_level_debug::BUF_LEN]);
 
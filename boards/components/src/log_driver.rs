@@ -0,0 +1,80 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for the syscall driver of a persistent log.
+//!
+//! The driver becomes the log's read and append client, so the log can't be
+//! used by other capsules as well. It starts reading the log to recover the
+//! newest sequence number when the component is finalized.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let log_driver = components::log_driver::LogDriverComponent::new(
+//!     log,
+//!     board_kernel,
+//!     capsules_extra::log_driver::DRIVER_NUM,
+//! )
+//! .finalize(components::log_driver_component_static!(
+//!     capsules_extra::log::Log<'static, nrf52840::nvmc::Nvmc>
+//! ));
+//! ```
+
+use capsules_extra::log_driver::{LogDriver, BUF_LEN};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::log::{LogRead, LogWrite};
+
+#[macro_export]
+macro_rules! log_driver_component_static {
+    ($L:ty $(,)?) => {{
+        let driver = kernel::static_buf!(capsules_extra::log_driver::LogDriver<'static, $L>);
+        let buffer = kernel::static_buf!([u8; capsules_extra::log_driver::BUF_LEN]);
+
+        (driver, buffer)
+    };};
+}
+
+pub struct LogDriverComponent<L: 'static + LogRead<'static, EntryID = usize> + LogWrite<'static>> {
+    log: &'static L,
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+}
+
+impl<L: 'static + LogRead<'static, EntryID = usize> + LogWrite<'static>> LogDriverComponent<L> {
+    pub fn new(log: &'static L, board_kernel: &'static kernel::Kernel, driver_num: usize) -> Self {
+        Self {
+            log,
+            board_kernel,
+            driver_num,
+        }
+    }
+}
+
+impl<L: 'static + LogRead<'static, EntryID = usize> + LogWrite<'static>> Component
+    for LogDriverComponent<L>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<LogDriver<'static, L>>,
+        &'static mut MaybeUninit<[u8; BUF_LEN]>,
+    );
+    type Output = &'static LogDriver<'static, L>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let driver = s.0.write(LogDriver::new(
+            self.log,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+            s.1.write([0; BUF_LEN]),
+        ));
+        self.log.set_read_client(driver);
+        self.log.set_append_client(driver);
+        let _ = driver.recover();
+
+        driver
+    }
+}
@@ -0,0 +1,84 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Component for the raw LoRa syscall interface.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let lora = components::lora::LoRaComponent::new(
+//!     board_kernel,
+//!     capsules_extra::lora::DRIVER_NUM,
+//!     sx127x,
+//! )
+//! .finalize(components::lora_component_static!(
+//!     capsules_extra::sx127x::Sx127x<
+//!         'static,
+//!         VirtualSpiMasterDevice<'static, nrf52840::spi::SPIM>,
+//!         VirtualMuxAlarm<'static, nrf52840::rtc::Rtc>,
+//!     >
+//! ));
+//! ```
+
+use capsules_extra::lora::LoRaDriver;
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::lora::{self, LoRaRadio};
+use kernel::{capabilities, create_capability};
+
+#[macro_export]
+macro_rules! lora_component_static {
+    ($R:ty $(,)?) => {{
+        let tx_buf = kernel::static_buf!([u8; kernel::hil::lora::MAX_PAYLOAD_LEN]);
+        let rx_buf = kernel::static_buf!([u8; kernel::hil::lora::MAX_PAYLOAD_LEN]);
+        let lora = kernel::static_buf!(capsules_extra::lora::LoRaDriver<'static, $R>);
+        (lora, tx_buf, rx_buf)
+    };};
+}
+
+pub struct LoRaComponent<R: 'static + LoRaRadio<'static>> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    radio: &'static R,
+}
+
+impl<R: 'static + LoRaRadio<'static>> LoRaComponent<R> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        radio: &'static R,
+    ) -> LoRaComponent<R> {
+        LoRaComponent {
+            board_kernel,
+            driver_num,
+            radio,
+        }
+    }
+}
+
+impl<R: 'static + LoRaRadio<'static>> Component for LoRaComponent<R> {
+    type StaticInput = (
+        &'static mut MaybeUninit<LoRaDriver<'static, R>>,
+        &'static mut MaybeUninit<[u8; lora::MAX_PAYLOAD_LEN]>,
+        &'static mut MaybeUninit<[u8; lora::MAX_PAYLOAD_LEN]>,
+    );
+    type Output = &'static LoRaDriver<'static, R>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+        let grant_lora = self.board_kernel.create_grant(self.driver_num, &grant_cap);
+
+        let lora = s.0.write(LoRaDriver::new(
+            self.radio,
+            grant_lora,
+            s.1.write([0; lora::MAX_PAYLOAD_LEN]),
+        ));
+        self.radio.set_transmit_client(lora);
+        self.radio.set_receive_client(lora);
+        self.radio
+            .set_receive_buffer(s.2.write([0; lora::MAX_PAYLOAD_LEN]));
+
+        lora
+    }
+}
@@ -0,0 +1,213 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Component for the LoRaWAN MAC and its syscall interface.
+//!
+//! The MAC keeps its DevNonce, session and frame counters in `kv_store` with
+//! the kernel storage permissions of `storage_id`, which must not be given to
+//! processes. It uses the raw AES interface of `aes`, such as a
+//! `VirtualAES128CCM`, and must be its only raw client.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let lorawan = components::lorawan::LoRaWanComponent::new(
+//!     board_kernel,
+//!     capsules_extra::lorawan::DRIVER_NUM,
+//!     sx127x,
+//!     mux_alarm,
+//!     aes,
+//!     kv_store,
+//!     0xfff0_0001,
+//!     capsules_extra::lorawan::mac::Credentials {
+//!         dev_eui: DEV_EUI,
+//!         join_eui: JOIN_EUI,
+//!         app_key: APP_KEY,
+//!     },
+//! )
+//! .finalize(components::lorawan_component_static!(
+//!     Sx127xRadio,
+//!     nrf52840::rtc::Rtc,
+//!     VirtualAES128CCM<'static, nrf52840::aes::AesECB<'static>>,
+//!     capsules_extra::tickv::TicKVStore<'static, ...>,
+//!     capsules_extra::tickv::TicKVKeyType,
+//! ));
+//! sx127x.enable();
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::kv_store::KVStore;
+use capsules_extra::lorawan::mac::{
+    Credentials, LoRaWan, LoRaWanMac, CRYPT_BUF_LEN, KEY, KEY_LEN, MAX_PAYLOAD_LEN, VALUE_BUF_LEN,
+};
+use capsules_extra::lorawan::LoRaWanDriver;
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::kv_system::{KVSystem, KeyType};
+use kernel::hil::lora::{self, LoRaRadio};
+use kernel::hil::symmetric_encryption::{AES128, AES128CBC, AES128ECB};
+use kernel::hil::time::Alarm;
+use kernel::storage_permissions::StoragePermissions;
+use kernel::{capabilities, create_capability};
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! lorawan_component_static {
+    ($R:ty, $A:ty, $E:ty, $K:ty, $T:ty $(,)?) => {{
+        use capsules_extra::lorawan::mac::{
+            CRYPT_BUF_LEN, KEY_LEN, MAX_PAYLOAD_LEN, VALUE_BUF_LEN,
+        };
+
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let mac = kernel::static_buf!(
+            capsules_extra::lorawan::mac::LoRaWanMac<
+                'static,
+                $R,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+                $E,
+                $K,
+                $T,
+            >
+        );
+        let driver = kernel::static_buf!(
+            capsules_extra::lorawan::LoRaWanDriver<
+                'static,
+                capsules_extra::lorawan::mac::LoRaWanMac<
+                    'static,
+                    $R,
+                    capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+                    $E,
+                    $K,
+                    $T,
+                >,
+            >
+        );
+        let tx_buf = kernel::static_buf!([u8; kernel::hil::lora::MAX_PAYLOAD_LEN]);
+        let rx_buf = kernel::static_buf!([u8; kernel::hil::lora::MAX_PAYLOAD_LEN]);
+        let crypt_buf = kernel::static_buf!([u8; CRYPT_BUF_LEN]);
+        let key = kernel::static_buf!([u8; KEY_LEN]);
+        let value = kernel::static_buf!([u8; VALUE_BUF_LEN]);
+        let kernel_tx = kernel::static_buf!([u8; MAX_PAYLOAD_LEN]);
+
+        (
+            alarm, mac, driver, tx_buf, rx_buf, crypt_buf, key, value, kernel_tx,
+        )
+    };};
+}
+
+type Mac<R, A, E, K, T> = LoRaWanMac<'static, R, VirtualMuxAlarm<'static, A>, E, K, T>;
+
+pub struct LoRaWanComponent<
+    R: 'static + LoRaRadio<'static>,
+    A: 'static + Alarm<'static>,
+    E: 'static + AES128<'static> + AES128ECB + AES128CBC,
+    K: 'static + KVSystem<'static, K = T>,
+    T: 'static + KeyType,
+> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    radio: &'static R,
+    mux_alarm: &'static MuxAlarm<'static, A>,
+    aes: &'static E,
+    kv_store: &'static KVStore<'static, K, T>,
+    storage_id: u32,
+    credentials: Credentials,
+}
+
+impl<
+        R: 'static + LoRaRadio<'static>,
+        A: 'static + Alarm<'static>,
+        E: 'static + AES128<'static> + AES128ECB + AES128CBC,
+        K: 'static + KVSystem<'static, K = T>,
+        T: 'static + KeyType,
+    > LoRaWanComponent<R, A, E, K, T>
+{
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        radio: &'static R,
+        mux_alarm: &'static MuxAlarm<'static, A>,
+        aes: &'static E,
+        kv_store: &'static KVStore<'static, K, T>,
+        storage_id: u32,
+        credentials: Credentials,
+    ) -> Self {
+        Self {
+            board_kernel,
+            driver_num,
+            radio,
+            mux_alarm,
+            aes,
+            kv_store,
+            storage_id,
+            credentials,
+        }
+    }
+}
+
+impl<
+        R: 'static + LoRaRadio<'static>,
+        A: 'static + Alarm<'static>,
+        E: 'static + AES128<'static> + AES128ECB + AES128CBC,
+        K: 'static + KVSystem<'static, K = T>,
+        T: 'static + KeyType,
+    > Component for LoRaWanComponent<R, A, E, K, T>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<Mac<R, A, E, K, T>>,
+        &'static mut MaybeUninit<LoRaWanDriver<'static, Mac<R, A, E, K, T>>>,
+        &'static mut MaybeUninit<[u8; lora::MAX_PAYLOAD_LEN]>,
+        &'static mut MaybeUninit<[u8; lora::MAX_PAYLOAD_LEN]>,
+        &'static mut MaybeUninit<[u8; CRYPT_BUF_LEN]>,
+        &'static mut MaybeUninit<[u8; KEY_LEN]>,
+        &'static mut MaybeUninit<[u8; VALUE_BUF_LEN]>,
+        &'static mut MaybeUninit<[u8; MAX_PAYLOAD_LEN]>,
+    );
+    type Output = &'static LoRaWanDriver<'static, Mac<R, A, E, K, T>>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+        let storage_cap = create_capability!(capabilities::KernelStorageCapability);
+        let perms = StoragePermissions::new_kernel(self.storage_id, &storage_cap);
+
+        let alarm = s.0.write(VirtualMuxAlarm::new(self.mux_alarm));
+        alarm.setup();
+
+        let key = s.6.write([0; KEY_LEN]);
+        key.copy_from_slice(KEY);
+
+        let mac = s.1.write(LoRaWanMac::new(
+            self.radio,
+            alarm,
+            self.aes,
+            self.kv_store,
+            perms,
+            self.credentials,
+            s.3.write([0; lora::MAX_PAYLOAD_LEN]),
+            s.5.write([0; CRYPT_BUF_LEN]),
+            key,
+            s.7.write([0; VALUE_BUF_LEN]),
+        ));
+        alarm.set_alarm_client(mac);
+        self.radio.set_transmit_client(mac);
+        self.radio.set_receive_client(mac);
+        self.radio
+            .set_receive_buffer(s.4.write([0; lora::MAX_PAYLOAD_LEN]));
+        self.aes.set_client(mac);
+        self.kv_store.set_client(mac);
+
+        let driver = s.2.write(LoRaWanDriver::new(
+            mac,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+            s.8.write([0; MAX_PAYLOAD_LEN]),
+        ));
+        mac.set_client(driver);
+        let _ = mac.load();
+
+        driver
+    }
+}
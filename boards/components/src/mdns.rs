@@ -0,0 +1,192 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Component to initialize a multicast DNS responder.
+//!
+//! This provides one Component, MdnsComponent. This component creates an
+//! mDNS responder on top of the UDP/6LoWPAN stack, bound to the mDNS port in
+//! the kernel's port table, which answers for `<hostname>.local` with the
+//! addresses of the interface list and advertises the given DNS-SD services.
+//! The port table only accepts bindings once the userland UDP driver is set
+//! up, so this component must be finalized after `UDPDriverComponent`, and
+//! after `MulticastListenerComponent` if the board reports its multicast
+//! groups, as the responder joins the mDNS group.
+//!
+//! The responder announces its records once it is created.
+//!
+//! Usage
+//! -----
+//! ```rust
+//!    let mdns = MdnsComponent::new(
+//!        udp_send_mux,
+//!        udp_recv_mux,
+//!        udp_port_table,
+//!        mux_alarm,
+//!        local_ip_ifaces,
+//!        "imix",
+//!        &MDNS_SERVICES,
+//!     )
+//!     .finalize(components::mdns_component_static!(sam4l::ast::Ast));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::net::ipv6::ipv6_send::IP6SendStruct;
+use capsules_extra::net::ipv6::slaac::InterfaceList;
+use capsules_extra::net::mdns::responder::TX_BUF_LEN;
+use capsules_extra::net::mdns::{MdnsResponder, Service, MDNS_ADDR, MDNS_PORT};
+use capsules_extra::net::network_capabilities::{
+    AddrRange, NetworkCapability, PortRange, UdpVisibilityCapability,
+};
+use capsules_extra::net::udp::udp_port_table::UdpPortManager;
+use capsules_extra::net::udp::udp_recv::{MuxUdpReceiver, UDPReceiver};
+use capsules_extra::net::udp::udp_send::{MuxUdpSender, UDPSendStruct, UDPSender};
+use core::mem::MaybeUninit;
+use kernel::capabilities::NetworkCapabilityCreationCapability;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::time::Alarm;
+use kernel::utilities::leasable_buffer::LeasableMutableBuffer;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! mdns_component_static {
+    ($A:ty $(,)?) => {{
+        use capsules_extra::net::mdns::responder::TX_BUF_LEN;
+
+        let udp_send = kernel::static_buf!(
+            capsules_extra::net::udp::udp_send::UDPSendStruct<
+                'static,
+                capsules_extra::net::ipv6::ipv6_send::IP6SendStruct<
+                    'static,
+                    capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+                >,
+            >
+        );
+        let udp_recv =
+            kernel::static_buf!(capsules_extra::net::udp::udp_recv::UDPReceiver<'static>);
+        let udp_vis_cap =
+            kernel::static_buf!(capsules_extra::net::network_capabilities::UdpVisibilityCapability);
+        let net_cap =
+            kernel::static_buf!(capsules_extra::net::network_capabilities::NetworkCapability);
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let responder = kernel::static_buf!(
+            capsules_extra::net::mdns::MdnsResponder<
+                'static,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+        let buffer = kernel::static_buf!([u8; TX_BUF_LEN]);
+
+        (
+            udp_send,
+            udp_recv,
+            udp_vis_cap,
+            net_cap,
+            alarm,
+            responder,
+            buffer,
+        )
+    };};
+}
+
+pub struct MdnsComponent<A: Alarm<'static> + 'static> {
+    udp_send_mux:
+        &'static MuxUdpSender<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+    udp_recv_mux: &'static MuxUdpReceiver<'static>,
+    port_table: &'static UdpPortManager,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    interface_list: &'static InterfaceList,
+    hostname: &'static str,
+    services: &'static [Service],
+}
+
+impl<A: Alarm<'static>> MdnsComponent<A> {
+    pub fn new(
+        udp_send_mux: &'static MuxUdpSender<
+            'static,
+            IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
+        >,
+        udp_recv_mux: &'static MuxUdpReceiver<'static>,
+        port_table: &'static UdpPortManager,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        interface_list: &'static InterfaceList,
+        hostname: &'static str,
+        services: &'static [Service],
+    ) -> Self {
+        Self {
+            udp_send_mux,
+            udp_recv_mux,
+            port_table,
+            alarm_mux,
+            interface_list,
+            hostname,
+            services,
+        }
+    }
+}
+
+impl<A: Alarm<'static>> Component for MdnsComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<
+            UDPSendStruct<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+        >,
+        &'static mut MaybeUninit<UDPReceiver<'static>>,
+        &'static mut MaybeUninit<UdpVisibilityCapability>,
+        &'static mut MaybeUninit<NetworkCapability>,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<MdnsResponder<'static, VirtualMuxAlarm<'static, A>>>,
+        &'static mut MaybeUninit<[u8; TX_BUF_LEN]>,
+    );
+    type Output = &'static MdnsResponder<'static, VirtualMuxAlarm<'static, A>>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let create_cap = create_capability!(NetworkCapabilityCreationCapability);
+        let udp_vis = s.2.write(UdpVisibilityCapability::new(&create_cap));
+        let udp_send = s.0.write(UDPSendStruct::new(self.udp_send_mux, udp_vis));
+        let udp_recv = s.1.write(UDPReceiver::new());
+        self.udp_recv_mux.add_client(udp_recv);
+
+        let net_cap = s.3.write(NetworkCapability::new(
+            AddrRange::Any,
+            PortRange::Any,
+            PortRange::Any,
+            &create_cap,
+        ));
+
+        let socket = self
+            .port_table
+            .create_socket()
+            .expect("mDNS: no free UDP socket");
+        let (send_binding, recv_binding) = self
+            .port_table
+            .bind(socket, MDNS_PORT, net_cap)
+            .expect("mDNS: UDP port unavailable");
+        self.port_table
+            .join_group(&recv_binding, MDNS_ADDR)
+            .expect("mDNS: cannot join the mDNS group");
+        udp_send.set_binding(send_binding);
+        udp_recv.set_binding(recv_binding);
+
+        let alarm = s.4.write(VirtualMuxAlarm::new(self.alarm_mux));
+        alarm.setup();
+
+        let buffer = s.6.write([0; TX_BUF_LEN]);
+        let responder = s.5.write(MdnsResponder::new(
+            udp_send,
+            alarm,
+            net_cap,
+            self.interface_list,
+            self.hostname,
+            self.services,
+            LeasableMutableBuffer::new(buffer),
+        ));
+        udp_send.set_client(responder);
+        udp_recv.set_client(responder);
+        alarm.set_alarm_client(responder);
+        responder.announce();
+        responder
+    }
+}
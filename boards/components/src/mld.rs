@@ -0,0 +1,167 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Component to initialize Multicast Listener Discovery.
+//!
+//! This provides one Component, MulticastListenerComponent. This component
+//! creates a multicast listener on top of the UDP/6LoWPAN stack, passes it
+//! the MLD messages received by the IPv6 receiver returned by
+//! `UDPMuxComponent`, and makes it report the multicast groups joined
+//! through the UDP port table, by capsules and by apps through the UDP
+//! driver. Groups joined before this component is finalized are not
+//! reported, so it should be finalized before the capsules that join groups.
+//!
+//! Usage
+//! -----
+//! ```rust
+//!    let mld = MulticastListenerComponent::new(
+//!        udp_send_mux,
+//!        ip_receive,
+//!        udp_port_table,
+//!        mux_alarm,
+//!        local_ip_ifaces,
+//!     )
+//!     .finalize(components::mld_component_static!(sam4l::ast::Ast));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::net::ipv6::ipv6_recv::{IP6Receiver, IP6RecvStruct};
+use capsules_extra::net::ipv6::ipv6_send::IP6SendStruct;
+use capsules_extra::net::ipv6::mld::{MulticastGroup, MulticastListener, MAX_GROUPS, TX_BUF_LEN};
+use capsules_extra::net::ipv6::slaac::InterfaceList;
+use capsules_extra::net::network_capabilities::{
+    AddrRange, NetworkCapability, PortRange, UdpVisibilityCapability,
+};
+use capsules_extra::net::udp::udp_port_table::UdpPortManager;
+use capsules_extra::net::udp::udp_send::{MuxUdpSender, UDPSendStruct, UDPSender};
+use core::mem::MaybeUninit;
+use kernel::capabilities::NetworkCapabilityCreationCapability;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::deferred_call::DeferredCallClient;
+use kernel::hil::time::Alarm;
+use kernel::utilities::leasable_buffer::LeasableMutableBuffer;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! mld_component_static {
+    ($A:ty $(,)?) => {{
+        use capsules_extra::net::ipv6::mld::{MAX_GROUPS, TX_BUF_LEN};
+
+        let mld_send = kernel::static_buf!(
+            capsules_extra::net::udp::udp_send::UDPSendStruct<
+                'static,
+                capsules_extra::net::ipv6::ipv6_send::IP6SendStruct<
+                    'static,
+                    capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+                >,
+            >
+        );
+        let udp_vis_cap =
+            kernel::static_buf!(capsules_extra::net::network_capabilities::UdpVisibilityCapability);
+        let net_cap =
+            kernel::static_buf!(capsules_extra::net::network_capabilities::NetworkCapability);
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let groups =
+            kernel::static_buf!([capsules_extra::net::ipv6::mld::MulticastGroup; MAX_GROUPS]);
+        let listener = kernel::static_buf!(
+            capsules_extra::net::ipv6::mld::MulticastListener<
+                'static,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+        let buffer = kernel::static_buf!([u8; TX_BUF_LEN]);
+
+        (
+            mld_send,
+            udp_vis_cap,
+            net_cap,
+            alarm,
+            groups,
+            listener,
+            buffer,
+        )
+    };};
+}
+
+pub struct MulticastListenerComponent<A: Alarm<'static> + 'static> {
+    udp_send_mux:
+        &'static MuxUdpSender<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+    ip_receive: &'static IP6RecvStruct<'static>,
+    port_table: &'static UdpPortManager,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    interface_list: &'static InterfaceList,
+}
+
+impl<A: Alarm<'static>> MulticastListenerComponent<A> {
+    pub fn new(
+        udp_send_mux: &'static MuxUdpSender<
+            'static,
+            IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
+        >,
+        ip_receive: &'static IP6RecvStruct<'static>,
+        port_table: &'static UdpPortManager,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        interface_list: &'static InterfaceList,
+    ) -> Self {
+        Self {
+            udp_send_mux,
+            ip_receive,
+            port_table,
+            alarm_mux,
+            interface_list,
+        }
+    }
+}
+
+impl<A: Alarm<'static>> Component for MulticastListenerComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<
+            UDPSendStruct<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+        >,
+        &'static mut MaybeUninit<UdpVisibilityCapability>,
+        &'static mut MaybeUninit<NetworkCapability>,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<[MulticastGroup; MAX_GROUPS]>,
+        &'static mut MaybeUninit<MulticastListener<'static, VirtualMuxAlarm<'static, A>>>,
+        &'static mut MaybeUninit<[u8; TX_BUF_LEN]>,
+    );
+    type Output = &'static MulticastListener<'static, VirtualMuxAlarm<'static, A>>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let create_cap = create_capability!(NetworkCapabilityCreationCapability);
+        let udp_vis = s.1.write(UdpVisibilityCapability::new(&create_cap));
+        let mld_send = s.0.write(UDPSendStruct::new(self.udp_send_mux, udp_vis));
+
+        let net_cap = s.2.write(NetworkCapability::new(
+            AddrRange::Any,
+            PortRange::Any,
+            PortRange::Any,
+            &create_cap,
+        ));
+
+        let alarm = s.3.write(VirtualMuxAlarm::new(self.alarm_mux));
+        alarm.setup();
+
+        let groups = s.4.write(core::array::from_fn(|_| MulticastGroup::new()));
+        let buffer = s.6.write([0; TX_BUF_LEN]);
+
+        let listener = s.5.write(MulticastListener::new(
+            mld_send,
+            alarm,
+            net_cap,
+            self.interface_list,
+            groups,
+            LeasableMutableBuffer::new(buffer),
+        ));
+        mld_send.set_client(listener);
+        self.ip_receive.set_mld_client(listener);
+        self.port_table.set_group_membership(listener);
+        alarm.set_alarm_client(listener);
+        listener.register();
+        listener
+    }
+}
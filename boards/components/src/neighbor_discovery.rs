@@ -0,0 +1,172 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Component to initialize IPv6 Neighbor Discovery.
+//!
+//! This provides one Component, NeighborDiscoveryComponent. This component
+//! creates a neighbor cache on top of the UDP/6LoWPAN stack and makes it the
+//! resolver of the IPv6 sender returned by `UDPMuxComponent`, so that packets
+//! are sent to the MAC address of their next hop. The destination MAC address
+//! passed to `UDPMuxComponent` remains the fallback for next hops that cannot
+//! be resolved. Received ICMPv6 messages are passed to the neighbor cache, and
+//! it starts soliciting routers once the kernel loop runs. Addresses are
+//! autoconfigured from the prefixes routers advertise, and added to the
+//! interface list shared with the rest of the stack.
+//!
+//! Usage
+//! -----
+//! ```rust
+//!    let neighbor_discovery = NeighborDiscoveryComponent::new(
+//!        udp_send_mux,
+//!        ip_receive,
+//!        ip_send,
+//!        mux_alarm,
+//!        local_ip_ifaces,
+//!     )
+//!     .finalize(components::neighbor_discovery_component_static!(sam4l::ast::Ast));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::net::ipv6::ipv6_nd::{
+    Neighbor, NeighborDiscovery, NEIGHBOR_CACHE_SIZE, TX_BUF_LEN,
+};
+use capsules_extra::net::ipv6::ipv6_recv::{IP6Receiver, IP6RecvStruct};
+use capsules_extra::net::ipv6::ipv6_send::{IP6SendStruct, IP6Sender};
+use capsules_extra::net::ipv6::slaac::InterfaceList;
+use capsules_extra::net::network_capabilities::{
+    AddrRange, NetworkCapability, PortRange, UdpVisibilityCapability,
+};
+use capsules_extra::net::udp::udp_send::{MuxUdpSender, UDPSendStruct, UDPSender};
+use core::mem::MaybeUninit;
+use kernel::capabilities::NetworkCapabilityCreationCapability;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::deferred_call::DeferredCallClient;
+use kernel::hil::time::Alarm;
+use kernel::utilities::leasable_buffer::LeasableMutableBuffer;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! neighbor_discovery_component_static {
+    ($A:ty $(,)?) => {{
+        use capsules_extra::net::ipv6::ipv6_nd::{NEIGHBOR_CACHE_SIZE, TX_BUF_LEN};
+
+        let nd_send = kernel::static_buf!(
+            capsules_extra::net::udp::udp_send::UDPSendStruct<
+                'static,
+                capsules_extra::net::ipv6::ipv6_send::IP6SendStruct<
+                    'static,
+                    capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+                >,
+            >
+        );
+        let udp_vis_cap =
+            kernel::static_buf!(capsules_extra::net::network_capabilities::UdpVisibilityCapability);
+        let net_cap =
+            kernel::static_buf!(capsules_extra::net::network_capabilities::NetworkCapability);
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let neighbors = kernel::static_buf!(
+            [capsules_extra::net::ipv6::ipv6_nd::Neighbor; NEIGHBOR_CACHE_SIZE]
+        );
+        let neighbor_discovery = kernel::static_buf!(
+            capsules_extra::net::ipv6::ipv6_nd::NeighborDiscovery<
+                'static,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+        let buffer = kernel::static_buf!([u8; TX_BUF_LEN]);
+
+        (
+            nd_send,
+            udp_vis_cap,
+            net_cap,
+            alarm,
+            neighbors,
+            neighbor_discovery,
+            buffer,
+        )
+    };};
+}
+
+pub struct NeighborDiscoveryComponent<A: Alarm<'static> + 'static> {
+    udp_send_mux:
+        &'static MuxUdpSender<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+    ip_receive: &'static IP6RecvStruct<'static>,
+    ip_send: &'static IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    interface_list: &'static InterfaceList,
+}
+
+impl<A: Alarm<'static>> NeighborDiscoveryComponent<A> {
+    pub fn new(
+        udp_send_mux: &'static MuxUdpSender<
+            'static,
+            IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
+        >,
+        ip_receive: &'static IP6RecvStruct<'static>,
+        ip_send: &'static IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        interface_list: &'static InterfaceList,
+    ) -> Self {
+        Self {
+            udp_send_mux,
+            ip_receive,
+            ip_send,
+            alarm_mux,
+            interface_list,
+        }
+    }
+}
+
+impl<A: Alarm<'static>> Component for NeighborDiscoveryComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<
+            UDPSendStruct<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+        >,
+        &'static mut MaybeUninit<UdpVisibilityCapability>,
+        &'static mut MaybeUninit<NetworkCapability>,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<[Neighbor; NEIGHBOR_CACHE_SIZE]>,
+        &'static mut MaybeUninit<NeighborDiscovery<'static, VirtualMuxAlarm<'static, A>>>,
+        &'static mut MaybeUninit<[u8; TX_BUF_LEN]>,
+    );
+    type Output = &'static NeighborDiscovery<'static, VirtualMuxAlarm<'static, A>>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let create_cap = create_capability!(NetworkCapabilityCreationCapability);
+        let udp_vis = s.1.write(UdpVisibilityCapability::new(&create_cap));
+        let nd_send = s.0.write(UDPSendStruct::new(self.udp_send_mux, udp_vis));
+
+        let net_cap = s.2.write(NetworkCapability::new(
+            AddrRange::Any,
+            PortRange::Any,
+            PortRange::Any,
+            &create_cap,
+        ));
+
+        let alarm = s.3.write(VirtualMuxAlarm::new(self.alarm_mux));
+        alarm.setup();
+
+        let neighbors = s.4.write(core::array::from_fn(|_| Neighbor::new()));
+        let buffer = s.6.write([0; TX_BUF_LEN]);
+
+        let neighbor_discovery = s.5.write(NeighborDiscovery::new(
+            nd_send,
+            alarm,
+            net_cap,
+            self.interface_list,
+            neighbors,
+            LeasableMutableBuffer::new(buffer),
+        ));
+        nd_send.set_client(neighbor_discovery);
+        self.ip_receive.set_icmp_client(neighbor_discovery);
+        self.ip_send.set_resolver(neighbor_discovery);
+        alarm.set_alarm_client(neighbor_discovery);
+        neighbor_discovery.register();
+        neighbor_discovery.start();
+        neighbor_discovery
+    }
+}
@@ -4,8 +4,13 @@
 
 //! Component for non-volatile storage Drivers.
 //!
-//! This provides one component, NonvolatileStorageComponent, which provides
-//! a system call interface to non-volatile storage.
+//! This provides two components, which provide a system call interface to
+//! non-volatile storage:
+//!
+//! - `NonvolatileStorageComponent` over flash.
+//! - `NonvolatileStorageBlockComponent` over block storage like an SD card.
+//!   The second argument of its static macro is the size of the storage's
+//!   blocks.
 //!
 //! Usage
 //! -----
@@ -17,13 +22,30 @@
 //!     0x20000,
 //!     &_sstorage as *const u8 as usize,
 //!     &_estorage as *const u8 as usize,
+//!     &[], // All apps share the userspace region.
 //! )
 //! .finalize(components::nonvolatile_storage_component_static!(
 //!     sam4l::flashcalw::FLASHCALW
 //! ));
+//!
+//! let nonvolatile_storage = components::nonvolatile_storage::NonvolatileStorageBlockComponent::new(
+//!     board_kernel,
+//!     capsules_extra::nonvolatile_storage_driver::DRIVER_NUM,
+//!     sdcard,
+//!     0x0,
+//!     0x100000,
+//!     0x100000,
+//!     0x10000,
+//!     &[],
+//! )
+//! .finalize(components::nonvolatile_storage_block_component_static!(
+//!     capsules_extra::sdcard::SDCard<'static, VirtualMuxAlarm<'static, nrf52840::rtc::Rtc>>,
+//!     512
+//! ));
 //! ```
 
-use capsules_extra::nonvolatile_storage_driver::NonvolatileStorage;
+use capsules_extra::nonvolatile_storage_driver::{NonvolatileStorage, StorageRegion};
+use capsules_extra::nonvolatile_to_blocks::NonvolatileToBlocks;
 use capsules_extra::nonvolatile_to_pages::NonvolatileToPages;
 use core::mem::MaybeUninit;
 use kernel::capabilities;
@@ -48,6 +70,22 @@ macro_rules! nonvolatile_storage_component_static {
     };};
 }
 
+#[macro_export]
+macro_rules! nonvolatile_storage_block_component_static {
+    ($B:ty, $BLOCK_SIZE:expr $(,)?) => {{
+        let block = kernel::static_buf!([u8; $BLOCK_SIZE]);
+        let ntb = kernel::static_buf!(
+            capsules_extra::nonvolatile_to_blocks::NonvolatileToBlocks<'static, $B>
+        );
+        let ns = kernel::static_buf!(
+            capsules_extra::nonvolatile_storage_driver::NonvolatileStorage<'static>
+        );
+        let buffer = kernel::static_buf!([u8; capsules_extra::nonvolatile_storage_driver::BUF_LEN]);
+
+        (block, ntb, ns, buffer)
+    };};
+}
+
 pub struct NonvolatileStorageComponent<
     F: 'static + hil::flash::Flash + hil::flash::HasClient<'static, NonvolatileToPages<'static, F>>,
 > {
@@ -58,6 +96,7 @@ pub struct NonvolatileStorageComponent<
     userspace_length: usize,
     kernel_start: usize,
     kernel_length: usize,
+    regions: &'static [StorageRegion],
 }
 
 impl<
@@ -74,6 +113,7 @@ impl<
         userspace_length: usize,
         kernel_start: usize,
         kernel_length: usize,
+        regions: &'static [StorageRegion],
     ) -> Self {
         Self {
             board_kernel,
@@ -83,6 +123,7 @@ impl<
             userspace_length,
             kernel_start,
             kernel_length,
+            regions,
         }
     }
 }
@@ -124,9 +165,90 @@ impl<
             self.userspace_length, // Length of userspace accessible region
             self.kernel_start,    // Start address of kernel region
             self.kernel_length,   // Length of kernel region
+            self.regions,         // Per-app regions of the userspace region
             buffer,
         ));
         hil::nonvolatile_storage::NonvolatileStorage::set_client(nv_to_page, nonvolatile_storage);
         nonvolatile_storage
     }
 }
+
+pub struct NonvolatileStorageBlockComponent<
+    B: 'static + hil::block_storage::BlockStorage<'static>,
+    const BLOCK_SIZE: usize,
+> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    storage: &'static B,
+    userspace_start: usize,
+    userspace_length: usize,
+    kernel_start: usize,
+    kernel_length: usize,
+    regions: &'static [StorageRegion],
+}
+
+impl<B: 'static + hil::block_storage::BlockStorage<'static>, const BLOCK_SIZE: usize>
+    NonvolatileStorageBlockComponent<B, BLOCK_SIZE>
+{
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        storage: &'static B,
+        userspace_start: usize,
+        userspace_length: usize,
+        kernel_start: usize,
+        kernel_length: usize,
+        regions: &'static [StorageRegion],
+    ) -> Self {
+        Self {
+            board_kernel,
+            driver_num,
+            storage,
+            userspace_start,
+            userspace_length,
+            kernel_start,
+            kernel_length,
+            regions,
+        }
+    }
+}
+
+impl<B: 'static + hil::block_storage::BlockStorage<'static>, const BLOCK_SIZE: usize> Component
+    for NonvolatileStorageBlockComponent<B, BLOCK_SIZE>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<[u8; BLOCK_SIZE]>,
+        &'static mut MaybeUninit<NonvolatileToBlocks<'static, B>>,
+        &'static mut MaybeUninit<NonvolatileStorage<'static>>,
+        &'static mut MaybeUninit<[u8; capsules_extra::nonvolatile_storage_driver::BUF_LEN]>,
+    );
+    type Output = &'static NonvolatileStorage<'static>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let buffer = static_buffer
+            .3
+            .write([0; capsules_extra::nonvolatile_storage_driver::BUF_LEN]);
+
+        let blockbuffer = static_buffer.0.write([0; BLOCK_SIZE]);
+
+        let nv_to_blocks = static_buffer
+            .1
+            .write(NonvolatileToBlocks::new(self.storage, blockbuffer));
+        hil::block_storage::BlockStorage::set_client(self.storage, nv_to_blocks);
+
+        let nonvolatile_storage = static_buffer.2.write(NonvolatileStorage::new(
+            nv_to_blocks,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+            self.userspace_start, // Start address for userspace accessible region
+            self.userspace_length, // Length of userspace accessible region
+            self.kernel_start,    // Start address of kernel region
+            self.kernel_length,   // Length of kernel region
+            self.regions,         // Per-app regions of the userspace region
+            buffer,
+        ));
+        hil::nonvolatile_storage::NonvolatileStorage::set_client(nv_to_blocks, nonvolatile_storage);
+        nonvolatile_storage
+    }
+}
@@ -0,0 +1,86 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for the periodic timer system call driver.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let periodic_timer = components::periodic_timer::PeriodicTimerComponent::new(
+//!     board_kernel,
+//!     capsules_extra::periodic_timer::DRIVER_NUM,
+//!     mux_alarm,
+//! )
+//! .finalize(components::periodic_timer_component_static!(sam4l::ast::Ast));
+//! ```
+
+use core::mem::MaybeUninit;
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::periodic_timer::PeriodicTimer;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::time::{self, Alarm};
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! periodic_timer_component_static {
+    ($A:ty $(,)?) => {{
+        let mux_alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let periodic_timer = kernel::static_buf!(
+            capsules_extra::periodic_timer::PeriodicTimer<
+                'static,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+
+        (mux_alarm, periodic_timer)
+    };};
+}
+
+pub struct PeriodicTimerComponent<A: 'static + time::Alarm<'static>> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+}
+
+impl<A: 'static + time::Alarm<'static>> PeriodicTimerComponent<A> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+    ) -> PeriodicTimerComponent<A> {
+        PeriodicTimerComponent {
+            board_kernel,
+            driver_num,
+            alarm_mux,
+        }
+    }
+}
+
+impl<A: 'static + time::Alarm<'static>> Component for PeriodicTimerComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<PeriodicTimer<'static, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = &'static PeriodicTimer<'static, VirtualMuxAlarm<'static, A>>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let virtual_alarm = static_buffer.0.write(VirtualMuxAlarm::new(self.alarm_mux));
+        virtual_alarm.setup();
+
+        let periodic_timer = static_buffer.1.write(PeriodicTimer::new(
+            virtual_alarm,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+        ));
+
+        virtual_alarm.set_alarm_client(periodic_timer);
+        periodic_timer
+    }
+}
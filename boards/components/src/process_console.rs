@@ -136,6 +136,7 @@ impl<const COMMAND_HISTORY_LEN: usize, A: 'static + Alarm<'static>> Component
         // Create virtual device for console.
         let console_uart = static_buffer.1.write(UartDevice::new(self.uart_mux, true));
         console_uart.setup();
+        console_uart.set_name("process_console");
 
         // Get addresses of where the kernel is placed to enable additional
         // debugging in process console.
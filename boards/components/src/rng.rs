@@ -2,28 +2,44 @@
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 // Copyright Tock Contributors 2022.
 
-//! Component for random number generator using `Entropy32ToRandom`.
+//! Components for random number generators.
 //!
-//! This provides one Component, RngComponent, which implements a userspace
-//! syscall interface to the RNG peripheral (TRNG).
+//! This provides two Components. RngComponent implements a userspace syscall
+//! interface directly on the RNG peripheral (TRNG), using
+//! `Entropy32ToRandom`. CsprngComponent instead serves the syscall interface
+//! from a `Csprng` seeded by the TRNG, so that slow TRNGs do not hold up
+//! userspace reads. It also returns the `Csprng` for in-kernel consumers.
 //!
 //! Usage
 //! -----
 //! ```rust
 //! let rng = components::rng::RngComponent::new(board_kernel, &sam4l::trng::TRNG)
 //!     .finalize(rng_component_static!());
+//!
+//! let (rng, csprng) = components::rng::CsprngComponent::new(
+//!     board_kernel,
+//!     capsules_core::rng::DRIVER_NUM,
+//!     &peripherals.trng,
+//!     mux_alarm,
+//!     60_000,
+//! )
+//! .finalize(components::csprng_component_static!(sam4l::ast::Ast));
 //! ```
 
 // Author: Hudson Ayers <hayers@cs.stanford.edu>
 // Last modified: 07/12/2019
 
 use capsules_core::rng;
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::csprng::Csprng;
 use core::mem::MaybeUninit;
 use kernel::capabilities;
 use kernel::component::Component;
 use kernel::create_capability;
+use kernel::deferred_call::DeferredCallClient;
 use kernel::hil::entropy::Entropy32;
 use kernel::hil::rng::Rng;
+use kernel::hil::time::{self, Alarm};
 
 #[macro_export]
 macro_rules! rng_component_static {
@@ -78,3 +94,81 @@ impl Component for RngComponent {
         rng
     }
 }
+
+#[macro_export]
+macro_rules! csprng_component_static {
+    ($A:ty $(,)?) => {{
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let csprng = kernel::static_buf!(
+            capsules_extra::csprng::Csprng<
+                'static,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+        let rng = kernel::static_buf!(capsules_core::rng::RngDriver<'static>);
+
+        (alarm, csprng, rng)
+    };};
+}
+
+pub struct CsprngComponent<A: 'static + time::Alarm<'static>> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    trng: &'static dyn Entropy32<'static>,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    reseed_interval_ms: u32,
+}
+
+impl<A: 'static + time::Alarm<'static>> CsprngComponent<A> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        trng: &'static dyn Entropy32<'static>,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        reseed_interval_ms: u32,
+    ) -> Self {
+        Self {
+            board_kernel,
+            driver_num,
+            trng,
+            alarm_mux,
+            reseed_interval_ms,
+        }
+    }
+}
+
+impl<A: 'static + time::Alarm<'static>> Component for CsprngComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<Csprng<'static, VirtualMuxAlarm<'static, A>>>,
+        &'static mut MaybeUninit<capsules_core::rng::RngDriver<'static>>,
+    );
+    type Output = (
+        &'static rng::RngDriver<'static>,
+        &'static Csprng<'static, VirtualMuxAlarm<'static, A>>,
+    );
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let alarm = static_buffer.0.write(VirtualMuxAlarm::new(self.alarm_mux));
+        alarm.setup();
+
+        let csprng = static_buffer
+            .1
+            .write(Csprng::new(self.trng, alarm, self.reseed_interval_ms));
+        let rng = static_buffer.2.write(rng::RngDriver::new(
+            csprng,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+        ));
+        self.trng.set_client(csprng);
+        alarm.set_alarm_client(csprng);
+        csprng.register();
+        csprng.set_client(rng);
+        csprng.start();
+
+        (rng, csprng)
+    }
+}
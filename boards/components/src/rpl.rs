@@ -0,0 +1,215 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Component to initialize RPL routing.
+//!
+//! This provides one Component, RplComponent. This component creates an RPL
+//! router on top of the UDP/6LoWPAN stack and makes it the router of the IPv6
+//! sender returned by `UDPMuxComponent`, so that packets follow the routes of
+//! the multi-hop network. It also creates an `IP6Forwarder`, which forwards
+//! the packets of other nodes along the same routes. Next hops are resolved
+//! by the neighbor cache, so this component must be used together with
+//! `NeighborDiscoveryComponent`.
+//!
+//! With a `root` address, the node starts a DODAG as its root, and the other
+//! nodes autoconfigure their addresses from the prefix of that address.
+//! Otherwise, the node joins the DODAG of a root it hears of.
+//!
+//! Usage
+//! -----
+//! ```rust
+//!    let rpl = RplComponent::new(
+//!        udp_send_mux,
+//!        ip_receive,
+//!        ip_send,
+//!        mux_alarm,
+//!        local_ip_ifaces,
+//!        None,
+//!     )
+//!     .finalize(components::rpl_component_static!(sam4l::ast::Ast));
+//! ```
+
+use crate::udp_mux::MAX_PAYLOAD_LEN;
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::net::ipv6::ip_utils::IPAddr;
+use capsules_extra::net::ipv6::ipv6_forward::IP6Forwarder;
+use capsules_extra::net::ipv6::ipv6_recv::{IP6Receiver, IP6RecvStruct};
+use capsules_extra::net::ipv6::ipv6_send::{IP6SendStruct, IP6Sender};
+use capsules_extra::net::ipv6::rpl::{Route, Rpl, ROUTE_TABLE_SIZE, TX_BUF_LEN};
+use capsules_extra::net::ipv6::slaac::InterfaceList;
+use capsules_extra::net::network_capabilities::{
+    AddrRange, NetworkCapability, PortRange, UdpVisibilityCapability,
+};
+use capsules_extra::net::udp::udp_send::{MuxUdpSender, UDPSendStruct, UDPSender};
+use core::mem::MaybeUninit;
+use kernel::capabilities::NetworkCapabilityCreationCapability;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::deferred_call::DeferredCallClient;
+use kernel::hil::time::Alarm;
+use kernel::utilities::leasable_buffer::LeasableMutableBuffer;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! rpl_component_static {
+    ($A:ty $(,)?) => {{
+        use capsules_extra::net::ipv6::rpl::{ROUTE_TABLE_SIZE, TX_BUF_LEN};
+        use components::udp_mux::MAX_PAYLOAD_LEN;
+
+        let rpl_send = kernel::static_buf!(
+            capsules_extra::net::udp::udp_send::UDPSendStruct<
+                'static,
+                capsules_extra::net::ipv6::ipv6_send::IP6SendStruct<
+                    'static,
+                    capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+                >,
+            >
+        );
+        let forward_send = kernel::static_buf!(
+            capsules_extra::net::udp::udp_send::UDPSendStruct<
+                'static,
+                capsules_extra::net::ipv6::ipv6_send::IP6SendStruct<
+                    'static,
+                    capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+                >,
+            >
+        );
+        let udp_vis_cap =
+            kernel::static_buf!(capsules_extra::net::network_capabilities::UdpVisibilityCapability);
+        let net_cap =
+            kernel::static_buf!(capsules_extra::net::network_capabilities::NetworkCapability);
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let routes = kernel::static_buf!([capsules_extra::net::ipv6::rpl::Route; ROUTE_TABLE_SIZE]);
+        let rpl = kernel::static_buf!(
+            capsules_extra::net::ipv6::rpl::Rpl<
+                'static,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+        let forwarder =
+            kernel::static_buf!(capsules_extra::net::ipv6::ipv6_forward::IP6Forwarder<'static>);
+        let buffer = kernel::static_buf!([u8; TX_BUF_LEN]);
+        let forward_buffer = kernel::static_buf!([u8; MAX_PAYLOAD_LEN]);
+
+        (
+            rpl_send,
+            forward_send,
+            udp_vis_cap,
+            net_cap,
+            alarm,
+            routes,
+            rpl,
+            forwarder,
+            buffer,
+            forward_buffer,
+        )
+    };};
+}
+
+pub struct RplComponent<A: Alarm<'static> + 'static> {
+    udp_send_mux:
+        &'static MuxUdpSender<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+    ip_receive: &'static IP6RecvStruct<'static>,
+    ip_send: &'static IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    interface_list: &'static InterfaceList,
+    root: Option<IPAddr>,
+}
+
+impl<A: Alarm<'static>> RplComponent<A> {
+    pub fn new(
+        udp_send_mux: &'static MuxUdpSender<
+            'static,
+            IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
+        >,
+        ip_receive: &'static IP6RecvStruct<'static>,
+        ip_send: &'static IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        interface_list: &'static InterfaceList,
+        root: Option<IPAddr>,
+    ) -> Self {
+        Self {
+            udp_send_mux,
+            ip_receive,
+            ip_send,
+            alarm_mux,
+            interface_list,
+            root,
+        }
+    }
+}
+
+impl<A: Alarm<'static>> Component for RplComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<
+            UDPSendStruct<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+        >,
+        &'static mut MaybeUninit<
+            UDPSendStruct<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+        >,
+        &'static mut MaybeUninit<UdpVisibilityCapability>,
+        &'static mut MaybeUninit<NetworkCapability>,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<[Route; ROUTE_TABLE_SIZE]>,
+        &'static mut MaybeUninit<Rpl<'static, VirtualMuxAlarm<'static, A>>>,
+        &'static mut MaybeUninit<IP6Forwarder<'static>>,
+        &'static mut MaybeUninit<[u8; TX_BUF_LEN]>,
+        &'static mut MaybeUninit<[u8; MAX_PAYLOAD_LEN]>,
+    );
+    type Output = &'static Rpl<'static, VirtualMuxAlarm<'static, A>>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let create_cap = create_capability!(NetworkCapabilityCreationCapability);
+        let udp_vis = s.2.write(UdpVisibilityCapability::new(&create_cap));
+        let rpl_send = s.0.write(UDPSendStruct::new(self.udp_send_mux, udp_vis));
+        let forward_send = s.1.write(UDPSendStruct::new(self.udp_send_mux, udp_vis));
+
+        let net_cap = s.3.write(NetworkCapability::new(
+            AddrRange::Any,
+            PortRange::Any,
+            PortRange::Any,
+            &create_cap,
+        ));
+
+        let alarm = s.4.write(VirtualMuxAlarm::new(self.alarm_mux));
+        alarm.setup();
+
+        let routes = s.5.write(core::array::from_fn(|_| Route::new()));
+        let buffer = s.8.write([0; TX_BUF_LEN]);
+
+        let rpl = s.6.write(Rpl::new(
+            rpl_send,
+            alarm,
+            net_cap,
+            self.interface_list,
+            routes,
+            LeasableMutableBuffer::new(buffer),
+        ));
+        rpl_send.set_client(rpl);
+        self.ip_receive.set_rpl_client(rpl);
+        self.ip_send.set_router(rpl);
+        alarm.set_alarm_client(rpl);
+        rpl.register();
+
+        let forward_buffer = s.9.write([0; MAX_PAYLOAD_LEN]);
+        let forwarder = s.7.write(IP6Forwarder::new(
+            forward_send,
+            net_cap,
+            LeasableMutableBuffer::new(forward_buffer),
+        ));
+        forward_send.set_client(forwarder);
+        self.ip_receive.set_interface_list(self.interface_list);
+        self.ip_receive.set_forward_client(forwarder);
+
+        match self.root {
+            Some(dodag_id) => rpl
+                .start_root(dodag_id)
+                .expect("RPL: no room for the root address"),
+            None => rpl.start(),
+        }
+        rpl
+    }
+}
@@ -0,0 +1,80 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for an earliest deadline first scheduler.
+//!
+//! This provides one Component, EDFComponent. Processes declare their
+//! deadlines through `capsules_extra::deadline::DeadlineDriver`, which takes
+//! the scheduler returned by this component.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let scheduler = components::sched::edf::EDFComponent::new(board_kernel, mux_alarm)
+//!     .finalize(components::edf_component_static!(nrf52840::rtc::Rtc, NUM_PROCS));
+//! let deadline = static_init!(
+//!     capsules_extra::deadline::DeadlineDriver,
+//!     capsules_extra::deadline::DeadlineDriver::new(scheduler)
+//! );
+//! ```
+
+use core::mem::MaybeUninit;
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel::component::Component;
+use kernel::hil::time;
+use kernel::scheduler::edf::EDFSched;
+
+#[macro_export]
+macro_rules! edf_component_static {
+    ($A:ty, $N:expr $(,)?) => {{
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let edf_sched = kernel::static_buf!(
+            kernel::scheduler::edf::EDFSched<
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+                $N,
+            >
+        );
+
+        (alarm, edf_sched)
+    };};
+}
+
+pub struct EDFComponent<A: 'static + time::Alarm<'static>, const NUM_PROCS: usize> {
+    board_kernel: &'static kernel::Kernel,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+}
+
+impl<A: 'static + time::Alarm<'static>, const NUM_PROCS: usize> EDFComponent<A, NUM_PROCS> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+    ) -> EDFComponent<A, NUM_PROCS> {
+        EDFComponent {
+            board_kernel,
+            alarm_mux,
+        }
+    }
+}
+
+impl<A: 'static + time::Alarm<'static>, const NUM_PROCS: usize> Component
+    for EDFComponent<A, NUM_PROCS>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<EDFSched<VirtualMuxAlarm<'static, A>, NUM_PROCS>>,
+    );
+    type Output = &'static EDFSched<VirtualMuxAlarm<'static, A>, NUM_PROCS>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let scheduler_alarm = static_buffer.0.write(VirtualMuxAlarm::new(self.alarm_mux));
+        scheduler_alarm.setup();
+
+        static_buffer
+            .1
+            .write(EDFSched::new(self.board_kernel, scheduler_alarm))
+    }
+}
@@ -3,6 +3,7 @@
 // Copyright Tock Contributors 2022.
 
 pub mod cooperative;
+pub mod edf;
 pub mod mlfq;
 pub mod priority;
 pub mod round_robin;
@@ -4,15 +4,19 @@
 
 //! Components for SPI.
 //!
-//! This provides four components.
+//! This provides five components.
 //!
 //! 1. `SpiMuxComponent` provides a virtualization layer for a SPI controller.
 //! 2. `SpiSyscallComponent` provides a controller system call interface to SPI.
-//! 3. `SpiPSyscallComponent` provides a peripheral system call interface to SPI.
+//! 3. `SpiSyscallPComponent` provides a peripheral system call interface to SPI.
 //! 4. `SpiComponent` provides a virtualized client to the SPI bus.
+//! 5. `SpiPeripheralComponent` provides a peripheral system call interface to
+//!    SPI hardware that implements `SpiSlaveDevice` itself.
 //!
 //! `SpiSyscallComponent` is used for processes, while `SpiComponent` is used
-//! for kernel capsules that need access to the SPI bus.
+//! for kernel capsules that need access to the SPI bus. The peripheral
+//! components initialize the SPI hardware in peripheral ("SPI device") mode,
+//! so it cannot also be used as a controller.
 //!
 //! Usage
 //! -----
@@ -24,6 +28,17 @@
 //! let rf233_spi = SpiComponent::new(mux_spi, 3).finalize(
 //!     components::spi_component_static!(sam4l::spi::SpiHw));
 //! ```
+//!
+//! On a board that is a SPI peripheral instead:
+//!
+//! ```rust
+//! let spi_peripheral = SpiSyscallPComponent::new(
+//!     board_kernel,
+//!     &sam4l::spi::SPI,
+//!     capsules_core::spi_peripheral::DRIVER_NUM,
+//! )
+//! .finalize(components::spi_syscallp_component_static!(sam4l::spi::SpiHw));
+//! ```
 
 // Author: Philip Levis <pal@cs.stanford.edu>
 // Last modified: 6/20/2018
@@ -31,6 +46,7 @@
 use core::mem::MaybeUninit;
 
 use capsules_core::spi_controller::{Spi, DEFAULT_READ_BUF_LENGTH, DEFAULT_WRITE_BUF_LENGTH};
+use capsules_core::spi_peripheral;
 use capsules_core::spi_peripheral::SpiPeripheral;
 use capsules_core::virtualizers::virtual_spi;
 use capsules_core::virtualizers::virtual_spi::{MuxSpiMaster, VirtualSpiMasterDevice};
@@ -84,9 +100,9 @@ macro_rules! spi_syscallp_component_static {
         );
 
         let spi_read_buf =
-            kernel::static_buf!([u8; capsules_core::spi_controller::DEFAULT_READ_BUF_LENGTH]);
+            kernel::static_buf!([u8; capsules_core::spi_peripheral::DEFAULT_READ_BUF_LENGTH]);
         let spi_write_buf =
-            kernel::static_buf!([u8; capsules_core::spi_controller::DEFAULT_WRITE_BUF_LENGTH]);
+            kernel::static_buf!([u8; capsules_core::spi_peripheral::DEFAULT_WRITE_BUF_LENGTH]);
 
         (spi_slave, spi_peripheral, spi_read_buf, spi_write_buf)
     };};
@@ -104,7 +120,15 @@ macro_rules! spi_component_static {
 #[macro_export]
 macro_rules! spi_peripheral_component_static {
     ($S:ty $(,)?) => {{
-        kernel::static_buf!(capsules_core::spi_peripheral::SpiPeripheral<'static, $S>)
+        let spi_peripheral =
+            kernel::static_buf!(capsules_core::spi_peripheral::SpiPeripheral<'static, $S>);
+
+        let spi_read_buf =
+            kernel::static_buf!([u8; capsules_core::spi_peripheral::DEFAULT_READ_BUF_LENGTH]);
+        let spi_write_buf =
+            kernel::static_buf!([u8; capsules_core::spi_peripheral::DEFAULT_WRITE_BUF_LENGTH]);
+
+        (spi_peripheral, spi_read_buf, spi_write_buf)
     };};
 }
 
@@ -219,8 +243,8 @@ impl<S: 'static + spi::SpiSlave> Component for SpiSyscallPComponent<S> {
     type StaticInput = (
         &'static mut MaybeUninit<virtual_spi::SpiSlaveDevice<'static, S>>,
         &'static mut MaybeUninit<SpiPeripheral<'static, virtual_spi::SpiSlaveDevice<'static, S>>>,
-        &'static mut MaybeUninit<[u8; DEFAULT_READ_BUF_LENGTH]>,
-        &'static mut MaybeUninit<[u8; DEFAULT_WRITE_BUF_LENGTH]>,
+        &'static mut MaybeUninit<[u8; spi_peripheral::DEFAULT_READ_BUF_LENGTH]>,
+        &'static mut MaybeUninit<[u8; spi_peripheral::DEFAULT_WRITE_BUF_LENGTH]>,
     );
     type Output = &'static SpiPeripheral<'static, virtual_spi::SpiSlaveDevice<'static, S>>;
 
@@ -236,11 +260,20 @@ impl<S: 'static + spi::SpiSlave> Component for SpiSyscallPComponent<S> {
             self.board_kernel.create_grant(self.driver_num, &grant_cap),
         ));
 
-        let spi_read_buf = static_buffer.2.write([0; DEFAULT_READ_BUF_LENGTH]);
-        let spi_write_buf = static_buffer.3.write([0; DEFAULT_WRITE_BUF_LENGTH]);
+        let spi_read_buf = static_buffer
+            .2
+            .write([0; spi_peripheral::DEFAULT_READ_BUF_LENGTH]);
+        let spi_write_buf = static_buffer
+            .3
+            .write([0; spi_peripheral::DEFAULT_WRITE_BUF_LENGTH]);
 
         spi_syscallsp.config_buffers(spi_read_buf, spi_write_buf);
         syscallp_spi_device.set_client(spi_syscallsp);
+        self.spi_slave.set_client(Some(syscallp_spi_device));
+
+        if let Err(error) = self.spi_slave.init() {
+            panic!("SPI peripheral init failed ({:?})", error);
+        }
 
         spi_syscallsp
     }
@@ -290,17 +323,35 @@ impl<S: 'static + spi::SpiSlave> SpiPeripheralComponent<S> {
 impl<S: 'static + spi::SpiSlave + kernel::hil::spi::SpiSlaveDevice> Component
     for SpiPeripheralComponent<S>
 {
-    type StaticInput = &'static mut MaybeUninit<SpiPeripheral<'static, S>>;
+    type StaticInput = (
+        &'static mut MaybeUninit<SpiPeripheral<'static, S>>,
+        &'static mut MaybeUninit<[u8; spi_peripheral::DEFAULT_READ_BUF_LENGTH]>,
+        &'static mut MaybeUninit<[u8; spi_peripheral::DEFAULT_WRITE_BUF_LENGTH]>,
+    );
     type Output = &'static SpiPeripheral<'static, S>;
 
     fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
         let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
 
-        let spi_device = static_buffer.write(SpiPeripheral::new(
+        let spi_device = static_buffer.0.write(SpiPeripheral::new(
             self.device,
             self.board_kernel.create_grant(self.driver_num, &grant_cap),
         ));
 
+        let spi_read_buf = static_buffer
+            .1
+            .write([0; spi_peripheral::DEFAULT_READ_BUF_LENGTH]);
+        let spi_write_buf = static_buffer
+            .2
+            .write([0; spi_peripheral::DEFAULT_WRITE_BUF_LENGTH]);
+
+        spi_device.config_buffers(spi_read_buf, spi_write_buf);
+        SpiSlaveDevice::set_client(self.device, spi_device);
+
+        if let Err(error) = spi::SpiSlave::init(self.device) {
+            panic!("SPI peripheral init failed ({:?})", error);
+        }
+
         spi_device
     }
 }
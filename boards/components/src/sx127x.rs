@@ -0,0 +1,125 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Component for the SX127x LoRa radios.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let sx127x = components::sx127x::Sx127xComponent::new(
+//!     mux_spi,
+//!     chip_select,
+//!     &gpio_port[SX127X_DIO0_PIN],
+//!     Some(&gpio_port[SX127X_RESET_PIN]),
+//!     mux_alarm,
+//! )
+//! .finalize(components::sx127x_component_static!(
+//!     nrf52::spi::SPIM,
+//!     nrf52::rtc::Rtc
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_core::virtualizers::virtual_spi::{MuxSpiMaster, VirtualSpiMasterDevice};
+use capsules_extra::sx127x::Sx127x;
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil;
+use kernel::hil::gpio;
+use kernel::hil::spi::SpiMasterDevice;
+use kernel::hil::time::Alarm;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! sx127x_component_static {
+    ($S:ty, $A:ty $(,)?) => {{
+        let spi_device = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_spi::VirtualSpiMasterDevice<'static, $S>
+        );
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let sx127x = kernel::static_buf!(
+            capsules_extra::sx127x::Sx127x<
+                'static,
+                capsules_core::virtualizers::virtual_spi::VirtualSpiMasterDevice<'static, $S>,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+
+        let tx_buf = kernel::static_buf!([u8; capsules_extra::sx127x::BUF_LEN]);
+        let rx_buf = kernel::static_buf!([u8; capsules_extra::sx127x::BUF_LEN]);
+
+        (spi_device, alarm, sx127x, tx_buf, rx_buf)
+    };};
+}
+
+pub struct Sx127xComponent<S: 'static + hil::spi::SpiMaster, A: 'static + hil::time::Alarm<'static>>
+{
+    mux_spi: &'static MuxSpiMaster<'static, S>,
+    chip_select: S::ChipSelect,
+    dio0: &'static dyn gpio::InterruptPin<'static>,
+    reset: Option<&'static dyn gpio::Pin>,
+    mux_alarm: &'static MuxAlarm<'static, A>,
+}
+
+impl<S: 'static + hil::spi::SpiMaster, A: 'static + hil::time::Alarm<'static>>
+    Sx127xComponent<S, A>
+{
+    pub fn new(
+        mux_spi: &'static MuxSpiMaster<'static, S>,
+        chip_select: S::ChipSelect,
+        dio0: &'static dyn gpio::InterruptPin<'static>,
+        reset: Option<&'static dyn gpio::Pin>,
+        mux_alarm: &'static MuxAlarm<'static, A>,
+    ) -> Sx127xComponent<S, A> {
+        Sx127xComponent {
+            mux_spi,
+            chip_select,
+            dio0,
+            reset,
+            mux_alarm,
+        }
+    }
+}
+
+impl<S: 'static + hil::spi::SpiMaster, A: 'static + hil::time::Alarm<'static>> Component
+    for Sx127xComponent<S, A>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualSpiMasterDevice<'static, S>>,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<
+            Sx127x<'static, VirtualSpiMasterDevice<'static, S>, VirtualMuxAlarm<'static, A>>,
+        >,
+        &'static mut MaybeUninit<[u8; capsules_extra::sx127x::BUF_LEN]>,
+        &'static mut MaybeUninit<[u8; capsules_extra::sx127x::BUF_LEN]>,
+    );
+    type Output =
+        &'static Sx127x<'static, VirtualSpiMasterDevice<'static, S>, VirtualMuxAlarm<'static, A>>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let sx127x_spi =
+            s.0.write(VirtualSpiMasterDevice::new(self.mux_spi, self.chip_select));
+        let sx127x_alarm = s.1.write(VirtualMuxAlarm::new(self.mux_alarm));
+        sx127x_alarm.setup();
+
+        let tx_buf = s.3.write([0; capsules_extra::sx127x::BUF_LEN]);
+        let rx_buf = s.4.write([0; capsules_extra::sx127x::BUF_LEN]);
+
+        let sx127x = s.2.write(Sx127x::new(
+            sx127x_spi,
+            self.dio0,
+            self.reset,
+            sx127x_alarm,
+            tx_buf,
+            rx_buf,
+        ));
+        sx127x_spi.setup();
+        sx127x_spi.set_client(sx127x);
+        sx127x_alarm.set_alarm_client(sx127x);
+        self.dio0.set_client(sx127x);
+        sx127x
+    }
+}
@@ -0,0 +1,174 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component to initialize the userland TCP driver.
+//!
+//! This provides one Component, TCPDriverComponent. This component initializes
+//! a userspace TCP driver on top of the UDP/6LoWPAN stack: segments are sent
+//! through the UDP send mux, and received from the IPv6 receiver returned by
+//! `UDPMuxComponent`. Initial sequence numbers are drawn from `rng`, which
+//! should be a CSPRNG such as the one `CsprngComponent` returns.
+//!
+//! Usage
+//! -----
+//! ```rust
+//!    let tcp_driver = TCPDriverComponent::new(
+//!        board_kernel,
+//!        capsules_extra::net::tcp::DRIVER_NUM,
+//!        udp_send_mux,
+//!        ip_receive,
+//!        mux_alarm,
+//!        csprng,
+//!     )
+//!     .finalize(components::tcp_driver_component_static!(sam4l::ast::Ast));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::net::ipv6::ipv6_recv::{IP6Receiver, IP6RecvStruct};
+use capsules_extra::net::ipv6::ipv6_send::IP6SendStruct;
+use capsules_extra::net::network_capabilities::{
+    AddrRange, NetworkCapability, PortRange, UdpVisibilityCapability,
+};
+use capsules_extra::net::tcp::driver::{Connection, MAX_NUM_CONNECTIONS};
+use capsules_extra::net::tcp::TCPDriver;
+use capsules_extra::net::udp::udp_send::{MuxUdpSender, UDPSendStruct, UDPSender};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::capabilities::NetworkCapabilityCreationCapability;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::rng::Random;
+use kernel::hil::time::Alarm;
+use kernel::utilities::leasable_buffer::LeasableMutableBuffer;
+
+const MAX_PAYLOAD_LEN: usize = super::udp_mux::MAX_PAYLOAD_LEN;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! tcp_driver_component_static {
+    ($A:ty $(,)?) => {{
+        use components::udp_mux::MAX_PAYLOAD_LEN;
+
+        let tcp_send = kernel::static_buf!(
+            capsules_extra::net::udp::udp_send::UDPSendStruct<
+                'static,
+                capsules_extra::net::ipv6::ipv6_send::IP6SendStruct<
+                    'static,
+                    capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+                >,
+            >
+        );
+        let udp_vis_cap =
+            kernel::static_buf!(capsules_extra::net::network_capabilities::UdpVisibilityCapability);
+        let net_cap =
+            kernel::static_buf!(capsules_extra::net::network_capabilities::NetworkCapability);
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let connections = kernel::static_buf!(
+            [capsules_extra::net::tcp::driver::Connection;
+                capsules_extra::net::tcp::driver::MAX_NUM_CONNECTIONS]
+        );
+        let tcp_driver = kernel::static_buf!(
+            capsules_extra::net::tcp::TCPDriver<
+                'static,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+        let buffer = kernel::static_buf!([u8; MAX_PAYLOAD_LEN]);
+
+        (
+            tcp_send,
+            udp_vis_cap,
+            net_cap,
+            alarm,
+            connections,
+            tcp_driver,
+            buffer,
+        )
+    };};
+}
+
+pub struct TCPDriverComponent<A: Alarm<'static> + 'static> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    udp_send_mux:
+        &'static MuxUdpSender<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+    ip_receive: &'static IP6RecvStruct<'static>,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    rng: &'static dyn Random<'static>,
+}
+
+impl<A: Alarm<'static>> TCPDriverComponent<A> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        udp_send_mux: &'static MuxUdpSender<
+            'static,
+            IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
+        >,
+        ip_receive: &'static IP6RecvStruct<'static>,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        rng: &'static dyn Random<'static>,
+    ) -> Self {
+        Self {
+            board_kernel,
+            driver_num,
+            udp_send_mux,
+            ip_receive,
+            alarm_mux,
+            rng,
+        }
+    }
+}
+
+impl<A: Alarm<'static>> Component for TCPDriverComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<
+            UDPSendStruct<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+        >,
+        &'static mut MaybeUninit<UdpVisibilityCapability>,
+        &'static mut MaybeUninit<NetworkCapability>,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<[Connection; MAX_NUM_CONNECTIONS]>,
+        &'static mut MaybeUninit<TCPDriver<'static, VirtualMuxAlarm<'static, A>>>,
+        &'static mut MaybeUninit<[u8; MAX_PAYLOAD_LEN]>,
+    );
+    type Output = &'static TCPDriver<'static, VirtualMuxAlarm<'static, A>>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+        let create_cap = create_capability!(NetworkCapabilityCreationCapability);
+        let udp_vis = s.1.write(UdpVisibilityCapability::new(&create_cap));
+        let tcp_send = s.0.write(UDPSendStruct::new(self.udp_send_mux, udp_vis));
+
+        let net_cap = s.2.write(NetworkCapability::new(
+            AddrRange::Any,
+            PortRange::Any,
+            PortRange::Any,
+            &create_cap,
+        ));
+
+        let alarm = s.3.write(VirtualMuxAlarm::new(self.alarm_mux));
+        alarm.setup();
+
+        let connections = s.4.write(core::array::from_fn(|_| Connection::new()));
+        let buffer = s.6.write([0; MAX_PAYLOAD_LEN]);
+
+        let tcp_driver = s.5.write(TCPDriver::new(
+            tcp_send,
+            alarm,
+            self.rng,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+            connections,
+            MAX_PAYLOAD_LEN,
+            LeasableMutableBuffer::new(buffer),
+            net_cap,
+        ));
+        tcp_send.set_client(tcp_driver);
+        self.ip_receive.set_tcp_client(tcp_driver);
+        alarm.set_alarm_client(tcp_driver);
+        tcp_driver
+    }
+}
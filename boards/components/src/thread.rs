@@ -0,0 +1,307 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Component for Thread's MLE and its syscall interface.
+//!
+//! This provides one Component, ThreadComponent. This component creates an
+//! MLE instance on top of the UDP/6LoWPAN stack, bound to the MLE port in the
+//! kernel's port table, which attaches the node to a Thread network as a
+//! Minimal End Device. MLE messages are protected with the AES-CCM hardware
+//! through its CCM virtualizer, and keys are derived with a software
+//! HMAC-SHA256 owned by MLE. The port table only accepts bindings once the
+//! userland UDP driver is set up, so this component must be finalized after
+//! `UDPDriverComponent`.
+//!
+//! MLE replaces the neighbor resolver of `ip_send`, so this component should
+//! be finalized after `NeighborDiscoveryComponent` if the board has one, and
+//! provides the link-layer keys and the parent to `radio_driver`.
+//!
+//! Thread identifies nodes by their extended addresses, so the interface list
+//! and the 6LoWPAN stack must use the long address of the radio, and context
+//! 0 of 6LoWPAN must be the mesh-local prefix of the network.
+//!
+//! Usage
+//! -----
+//! ```rust
+//!    let thread_driver = ThreadComponent::new(
+//!        board_kernel,
+//!        capsules_extra::net::thread::DRIVER_NUM,
+//!        udp_send_mux,
+//!        udp_recv_mux,
+//!        udp_port_table,
+//!        ip_send,
+//!        radio_driver,
+//!        &nrf52840_peripherals.nrf52.ieee802154_radio,
+//!        mux_alarm,
+//!        aes_mux,
+//!        rng,
+//!        local_ip_ifaces,
+//!     )
+//!     .finalize(components::thread_component_static!(
+//!         nrf52840::rtc::Rtc,
+//!         nrf52840::aes::AesECB<'static>
+//!     ));
+//! ```
+
+use capsules_core::virtualizers::virtual_aes_ccm::{MuxAES128CCM, VirtualAES128CCM};
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::ieee802154::RadioDriver;
+use capsules_extra::net::ipv6::ipv6_send::{IP6SendStruct, IP6Sender};
+use capsules_extra::net::ipv6::slaac::InterfaceList;
+use capsules_extra::net::network_capabilities::{
+    AddrRange, NetworkCapability, PortRange, UdpVisibilityCapability,
+};
+use capsules_extra::net::thread::mle::{
+    Mle, ThreadNetwork, CCM_BUF_LEN, DIGEST_LEN, KDF_BUF_LEN, MLE_PORT, TX_BUF_LEN,
+};
+use capsules_extra::net::thread::ThreadDriver;
+use capsules_extra::net::udp::udp_port_table::UdpPortManager;
+use capsules_extra::net::udp::udp_recv::{MuxUdpReceiver, UDPReceiver};
+use capsules_extra::net::udp::udp_send::{MuxUdpSender, UDPSendStruct, UDPSender};
+use capsules_extra::sha256::Sha256Software;
+use core::mem::MaybeUninit;
+use kernel::capabilities::{self, NetworkCapabilityCreationCapability};
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::deferred_call::DeferredCallClient;
+use kernel::hil::digest::Digest;
+use kernel::hil::radio::RadioConfig;
+use kernel::hil::rng::Random;
+use kernel::hil::symmetric_encryption::{
+    AES128Ctr, AES128, AES128CBC, AES128CCM, AES128ECB, AES128_BLOCK_SIZE,
+};
+use kernel::hil::time::Alarm;
+
+/// The size of the buffer the CCM virtualizer needs for MLE messages.
+pub const CRYPT_SIZE: usize = 3 * AES128_BLOCK_SIZE + CCM_BUF_LEN;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! thread_component_static {
+    ($A:ty, $AES:ty $(,)?) => {{
+        use capsules_extra::net::thread::mle::{CCM_BUF_LEN, DIGEST_LEN, KDF_BUF_LEN, TX_BUF_LEN};
+
+        let udp_send = kernel::static_buf!(
+            capsules_extra::net::udp::udp_send::UDPSendStruct<
+                'static,
+                capsules_extra::net::ipv6::ipv6_send::IP6SendStruct<
+                    'static,
+                    capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+                >,
+            >
+        );
+        let udp_recv =
+            kernel::static_buf!(capsules_extra::net::udp::udp_recv::UDPReceiver<'static>);
+        let udp_vis_cap =
+            kernel::static_buf!(capsules_extra::net::network_capabilities::UdpVisibilityCapability);
+        let net_cap =
+            kernel::static_buf!(capsules_extra::net::network_capabilities::NetworkCapability);
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let aes_ccm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_aes_ccm::VirtualAES128CCM<'static, $AES>
+        );
+        let crypt_buf = kernel::static_buf!([u8; components::thread::CRYPT_SIZE]);
+        let sha = kernel::static_buf!(capsules_extra::sha256::Sha256Software<'static>);
+        let mle = kernel::static_buf!(
+            capsules_extra::net::thread::mle::Mle<
+                'static,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+                capsules_core::virtualizers::virtual_aes_ccm::VirtualAES128CCM<'static, $AES>,
+                capsules_extra::sha256::Sha256Software<'static>,
+            >
+        );
+        let driver = kernel::static_buf!(
+            capsules_extra::net::thread::ThreadDriver<
+                'static,
+                capsules_extra::net::thread::mle::Mle<
+                    'static,
+                    capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+                    capsules_core::virtualizers::virtual_aes_ccm::VirtualAES128CCM<'static, $AES>,
+                    capsules_extra::sha256::Sha256Software<'static>,
+                >,
+            >
+        );
+        let tx_buffer = kernel::static_buf!([u8; TX_BUF_LEN]);
+        let ccm_buffer = kernel::static_buf!([u8; CCM_BUF_LEN]);
+        let kdf_buffer = kernel::static_buf!([u8; KDF_BUF_LEN]);
+        let digest = kernel::static_buf!([u8; DIGEST_LEN]);
+
+        (
+            udp_send,
+            udp_recv,
+            udp_vis_cap,
+            net_cap,
+            alarm,
+            aes_ccm,
+            crypt_buf,
+            sha,
+            mle,
+            driver,
+            tx_buffer,
+            ccm_buffer,
+            kdf_buffer,
+            digest,
+        )
+    };};
+}
+
+type ThreadMle<A, AES> = Mle<
+    'static,
+    VirtualMuxAlarm<'static, A>,
+    VirtualAES128CCM<'static, AES>,
+    Sha256Software<'static>,
+>;
+
+pub struct ThreadComponent<
+    A: Alarm<'static> + 'static,
+    AES: AES128<'static> + AES128Ctr + AES128CBC + AES128ECB + 'static,
+> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    udp_send_mux:
+        &'static MuxUdpSender<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+    udp_recv_mux: &'static MuxUdpReceiver<'static>,
+    port_table: &'static UdpPortManager,
+    ip_send: &'static IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
+    radio_driver: &'static RadioDriver<'static>,
+    radio: &'static dyn RadioConfig<'static>,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    aes_mux: &'static MuxAES128CCM<'static, AES>,
+    rng: &'static dyn Random<'static>,
+    interface_list: &'static InterfaceList,
+}
+
+impl<
+        A: Alarm<'static> + 'static,
+        AES: AES128<'static> + AES128Ctr + AES128CBC + AES128ECB + 'static,
+    > ThreadComponent<A, AES>
+{
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        udp_send_mux: &'static MuxUdpSender<
+            'static,
+            IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
+        >,
+        udp_recv_mux: &'static MuxUdpReceiver<'static>,
+        port_table: &'static UdpPortManager,
+        ip_send: &'static IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
+        radio_driver: &'static RadioDriver<'static>,
+        radio: &'static dyn RadioConfig<'static>,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        aes_mux: &'static MuxAES128CCM<'static, AES>,
+        rng: &'static dyn Random<'static>,
+        interface_list: &'static InterfaceList,
+    ) -> Self {
+        Self {
+            board_kernel,
+            driver_num,
+            udp_send_mux,
+            udp_recv_mux,
+            port_table,
+            ip_send,
+            radio_driver,
+            radio,
+            alarm_mux,
+            aes_mux,
+            rng,
+            interface_list,
+        }
+    }
+}
+
+impl<
+        A: Alarm<'static> + 'static,
+        AES: AES128<'static> + AES128Ctr + AES128CBC + AES128ECB + 'static,
+    > Component for ThreadComponent<A, AES>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<
+            UDPSendStruct<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+        >,
+        &'static mut MaybeUninit<UDPReceiver<'static>>,
+        &'static mut MaybeUninit<UdpVisibilityCapability>,
+        &'static mut MaybeUninit<NetworkCapability>,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<VirtualAES128CCM<'static, AES>>,
+        &'static mut MaybeUninit<[u8; CRYPT_SIZE]>,
+        &'static mut MaybeUninit<Sha256Software<'static>>,
+        &'static mut MaybeUninit<ThreadMle<A, AES>>,
+        &'static mut MaybeUninit<ThreadDriver<'static, ThreadMle<A, AES>>>,
+        &'static mut MaybeUninit<[u8; TX_BUF_LEN]>,
+        &'static mut MaybeUninit<[u8; CCM_BUF_LEN]>,
+        &'static mut MaybeUninit<[u8; KDF_BUF_LEN]>,
+        &'static mut MaybeUninit<[u8; DIGEST_LEN]>,
+    );
+    type Output = &'static ThreadDriver<'static, ThreadMle<A, AES>>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+        let create_cap = create_capability!(NetworkCapabilityCreationCapability);
+        let udp_vis = s.2.write(UdpVisibilityCapability::new(&create_cap));
+        let udp_send = s.0.write(UDPSendStruct::new(self.udp_send_mux, udp_vis));
+        let udp_recv = s.1.write(UDPReceiver::new());
+        self.udp_recv_mux.add_client(udp_recv);
+
+        let net_cap = s.3.write(NetworkCapability::new(
+            AddrRange::Any,
+            PortRange::Any,
+            PortRange::Any,
+            &create_cap,
+        ));
+
+        let socket = self
+            .port_table
+            .create_socket()
+            .expect("Thread: no free UDP socket");
+        let (send_binding, recv_binding) = self
+            .port_table
+            .bind(socket, MLE_PORT, net_cap)
+            .expect("Thread: MLE port unavailable");
+        udp_send.set_binding(send_binding);
+        udp_recv.set_binding(recv_binding);
+
+        let alarm = s.4.write(VirtualMuxAlarm::new(self.alarm_mux));
+        alarm.setup();
+
+        let crypt_buf = s.6.write([0; CRYPT_SIZE]);
+        let aes_ccm = s.5.write(VirtualAES128CCM::new(self.aes_mux, crypt_buf));
+        aes_ccm.setup();
+
+        let sha = s.7.write(Sha256Software::new());
+        sha.register();
+
+        let mle = s.8.write(Mle::new(
+            udp_send,
+            alarm,
+            aes_ccm,
+            sha,
+            self.rng,
+            self.radio,
+            self.interface_list,
+            net_cap,
+            s.10.write([0; TX_BUF_LEN]),
+            s.11.write([0; CCM_BUF_LEN]),
+            s.12.write([0; KDF_BUF_LEN]),
+            s.13.write([0; DIGEST_LEN]),
+        ));
+        udp_send.set_client(mle);
+        udp_recv.set_client(mle);
+        alarm.set_alarm_client(mle);
+        AES128CCM::set_client(aes_ccm, mle);
+        sha.set_client(mle);
+        self.radio_driver.set_kernel_procedures(mle, mle);
+        self.ip_send.set_resolver(mle);
+        self.ip_send.set_link_security(mle);
+
+        let driver = s.9.write(ThreadDriver::new(
+            mle,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+        ));
+        mle.set_client(driver);
+        driver
+    }
+}
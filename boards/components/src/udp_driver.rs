@@ -5,7 +5,8 @@
 //! Component to initialize the userland UDP driver.
 //!
 //! This provides one Component, UDPDriverComponent. This component initializes
-//! a userspace UDP driver that allows apps to use the UDP stack.
+//! a userspace UDP driver that allows apps to use the UDP stack, with a pool of
+//! `TX_POOL_LEN` kernel buffers that apps queue their datagrams in.
 //!
 //! Usage
 //! -----
@@ -23,11 +24,12 @@
 
 use capsules_core;
 use capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm;
-use capsules_extra::net::ipv6::ip_utils::IPAddr;
 use capsules_extra::net::ipv6::ipv6_send::IP6SendStruct;
+use capsules_extra::net::ipv6::slaac::InterfaceList;
 use capsules_extra::net::network_capabilities::{
     AddrRange, NetworkCapability, PortRange, UdpVisibilityCapability,
 };
+use capsules_extra::net::udp::driver::{TxSlot, TX_POOL_LEN};
 use capsules_extra::net::udp::udp_port_table::UdpPortManager;
 use capsules_extra::net::udp::udp_recv::MuxUdpReceiver;
 use capsules_extra::net::udp::udp_recv::UDPReceiver;
@@ -46,6 +48,7 @@ const MAX_PAYLOAD_LEN: usize = super::udp_mux::MAX_PAYLOAD_LEN;
 #[macro_export]
 macro_rules! udp_driver_component_static {
     ($A:ty $(,)?) => {{
+        use capsules_extra::net::udp::driver::TX_POOL_LEN;
         use components::udp_mux::MAX_PAYLOAD_LEN;
 
         let udp_send = kernel::static_buf!(
@@ -62,11 +65,20 @@ macro_rules! udp_driver_component_static {
         let net_cap =
             kernel::static_buf!(capsules_extra::net::network_capabilities::NetworkCapability);
         let udp_driver = kernel::static_buf!(capsules_extra::net::udp::UDPDriver<'static>);
-        let buffer = kernel::static_buf!([u8; MAX_PAYLOAD_LEN]);
+        let buffers = kernel::static_buf!([[u8; MAX_PAYLOAD_LEN]; TX_POOL_LEN]);
         let udp_recv =
             kernel::static_buf!(capsules_extra::net::udp::udp_recv::UDPReceiver<'static>);
+        let tx_pool = kernel::static_buf!([capsules_extra::net::udp::driver::TxSlot; TX_POOL_LEN]);
 
-        (udp_send, udp_vis_cap, net_cap, udp_driver, buffer, udp_recv)
+        (
+            udp_send,
+            udp_vis_cap,
+            net_cap,
+            udp_driver,
+            buffers,
+            udp_recv,
+            tx_pool,
+        )
     };};
 }
 
@@ -77,7 +89,7 @@ pub struct UDPDriverComponent<A: Alarm<'static> + 'static> {
         &'static MuxUdpSender<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
     udp_recv_mux: &'static MuxUdpReceiver<'static>,
     port_table: &'static UdpPortManager,
-    interface_list: &'static [IPAddr],
+    interface_list: &'static InterfaceList,
 }
 
 impl<A: Alarm<'static>> UDPDriverComponent<A> {
@@ -90,7 +102,7 @@ impl<A: Alarm<'static>> UDPDriverComponent<A> {
         >,
         udp_recv_mux: &'static MuxUdpReceiver<'static>,
         port_table: &'static UdpPortManager,
-        interface_list: &'static [IPAddr],
+        interface_list: &'static InterfaceList,
     ) -> Self {
         Self {
             board_kernel,
@@ -119,8 +131,9 @@ impl<A: Alarm<'static>> Component for UDPDriverComponent<A> {
         >,
         &'static mut MaybeUninit<capsules_extra::net::network_capabilities::NetworkCapability>,
         &'static mut MaybeUninit<capsules_extra::net::udp::UDPDriver<'static>>,
-        &'static mut MaybeUninit<[u8; MAX_PAYLOAD_LEN]>,
+        &'static mut MaybeUninit<[[u8; MAX_PAYLOAD_LEN]; TX_POOL_LEN]>,
         &'static mut MaybeUninit<UDPReceiver<'static>>,
+        &'static mut MaybeUninit<[TxSlot; TX_POOL_LEN]>,
     );
     type Output = &'static capsules_extra::net::udp::UDPDriver<'static>;
 
@@ -144,7 +157,10 @@ impl<A: Alarm<'static>> Component for UDPDriverComponent<A> {
             &create_cap,
         ));
 
-        let buffer = s.4.write([0; MAX_PAYLOAD_LEN]);
+        let mut buffers = s.4.write([[0; MAX_PAYLOAD_LEN]; TX_POOL_LEN]).iter_mut();
+        let tx_pool = s.6.write(core::array::from_fn(|_| {
+            TxSlot::new(buffers.next().unwrap())
+        }));
 
         let udp_driver = s.3.write(capsules_extra::net::udp::UDPDriver::new(
             udp_send,
@@ -152,7 +168,7 @@ impl<A: Alarm<'static>> Component for UDPDriverComponent<A> {
             self.interface_list,
             MAX_PAYLOAD_LEN,
             self.port_table,
-            kernel::utilities::leasable_buffer::LeasableMutableBuffer::new(buffer),
+            tx_pool,
             &DRIVER_CAP,
             net_cap,
         ));
@@ -6,12 +6,14 @@
 //!
 //! This provides one Component, UDPMuxComponent. This component
 //! exposes a MuxUdpSender that other components can implement
-//! UDPSenders on top of to use the UDP/6Lowpan stack.
+//! UDPSenders on top of to use the UDP/6Lowpan stack. It also exposes
+//! the IPv6 receiver, so that a TCP driver can be attached to it, and the IPv6
+//! sender, so that a neighbor cache can resolve next hops for it.
 //!
 //! Usage
 //! -----
 //! ```rust
-//!    let (udp_mux, udp_recv) = UDPMuxComponent::new(
+//!    let (udp_mux, udp_recv, udp_port_table, ip_receive, ip_send) = UDPMuxComponent::new(
 //!        mux_mac,
 //!        DEFAULT_CTX_PREFIX_LEN,
 //!        DEFAULT_CTX_PREFIX,
@@ -31,12 +33,11 @@ use capsules_core;
 use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
 use capsules_extra::ieee802154::device::MacDevice;
 use capsules_extra::net::ieee802154::MacAddress;
-use capsules_extra::net::ipv6::ip_utils::IPAddr;
 use capsules_extra::net::ipv6::ipv6_recv::IP6Receiver;
 use capsules_extra::net::ipv6::ipv6_recv::IP6RecvStruct;
 use capsules_extra::net::ipv6::ipv6_send::IP6SendStruct;
 use capsules_extra::net::ipv6::ipv6_send::IP6Sender;
-use capsules_extra::net::ipv6::{IP6Packet, IPPayload, TransportHeader};
+use capsules_extra::net::ipv6::slaac::InterfaceList;
 use capsules_extra::net::network_capabilities::{IpVisibilityCapability, UdpVisibilityCapability};
 use capsules_extra::net::sixlowpan::{sixlowpan_compression, sixlowpan_state};
 use capsules_extra::net::udp::udp_port_table::{
@@ -44,7 +45,6 @@ use capsules_extra::net::udp::udp_port_table::{
 };
 use capsules_extra::net::udp::udp_recv::MuxUdpReceiver;
 use capsules_extra::net::udp::udp_send::MuxUdpSender;
-use capsules_extra::net::udp::UDPHeader;
 use core::mem::MaybeUninit;
 use kernel;
 use kernel::capabilities;
@@ -57,10 +57,10 @@ use kernel::hil::time::Alarm;
 //
 //   1. RADIO_BUF: buffer the IP6_Sender uses to pass frames to the radio after fragmentation
 //   2. SIXLOWPAN_RX_BUF: Buffer to hold full IP packets after they are decompressed by 6LoWPAN
-//   3. UDP_DGRAM: The payload of the IP6_Packet, which holds full IP Packets before they are tx'd.
 //
 //   Additionally, every capsule using the stack needs an additional buffer to craft packets for
-//   tx which can then be passed to the MuxUdpSender for tx.
+//   tx which can then be passed to the MuxUdpSender for tx. The IP6_Sender compresses and
+//   fragments the payload straight out of that buffer, so it needs none of its own.
 
 pub const MAX_PAYLOAD_LEN: usize = 200; //The max size UDP message that can be sent by userspace apps or capsules
 
@@ -72,7 +72,6 @@ macro_rules! udp_mux_component_static {
         use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
         use capsules_extra::net::sixlowpan::{sixlowpan_compression, sixlowpan_state};
         use capsules_extra::net::udp::udp_send::MuxUdpSender;
-        use components::udp_mux::MAX_PAYLOAD_LEN;
         use core::mem::MaybeUninit;
 
         let alarm = kernel::static_buf!(VirtualMuxAlarm<'static, $A>);
@@ -106,7 +105,6 @@ macro_rules! udp_mux_component_static {
         let udp_port_manager =
             kernel::static_buf!(capsules_extra::net::udp::udp_port_table::UdpPortManager);
 
-        let ip6_packet = kernel::static_buf!(capsules_extra::net::ipv6::IP6Packet<'static>);
         let ip6_receive =
             kernel::static_buf!(capsules_extra::net::ipv6::ipv6_recv::IP6RecvStruct<'static>);
 
@@ -128,7 +126,6 @@ macro_rules! udp_mux_component_static {
 
         let radio_buf = kernel::static_buf!([u8; kernel::hil::radio::MAX_BUF_SIZE]);
         let sixlowpan_rx = kernel::static_buf!([u8; 1280]);
-        let udp_dgram = kernel::static_buf!([u8; MAX_PAYLOAD_LEN]);
 
         let udp_vis_cap =
             kernel::static_buf!(capsules_extra::net::network_capabilities::UdpVisibilityCapability);
@@ -144,12 +141,10 @@ macro_rules! udp_mux_component_static {
             mux_udp_send,
             mux_udp_recv,
             udp_port_manager,
-            ip6_packet,
             ip6_receive,
             used_ports,
             radio_buf,
             sixlowpan_rx,
-            udp_dgram,
             udp_vis_cap,
             ip_vis_cap,
         )
@@ -162,7 +157,7 @@ pub struct UDPMuxComponent<A: Alarm<'static> + 'static> {
     ctx_pfix: [u8; 16],
     dst_mac_addr: MacAddress,
     src_mac_addr: MacAddress,
-    interface_list: &'static [IPAddr],
+    interface_list: &'static InterfaceList,
     alarm_mux: &'static MuxAlarm<'static, A>,
 }
 
@@ -173,7 +168,7 @@ impl<A: Alarm<'static> + 'static> UDPMuxComponent<A> {
         ctx_pfix: [u8; 16],
         dst_mac_addr: MacAddress,
         src_mac_addr: MacAddress,
-        interface_list: &'static [IPAddr],
+        interface_list: &'static InterfaceList,
         alarm_mux: &'static MuxAlarm<'static, A>,
     ) -> Self {
         Self {
@@ -217,12 +212,10 @@ impl<A: Alarm<'static> + 'static> Component for UDPMuxComponent<A> {
         >,
         &'static mut MaybeUninit<MuxUdpReceiver<'static>>,
         &'static mut MaybeUninit<UdpPortManager>,
-        &'static mut MaybeUninit<IP6Packet<'static>>,
         &'static mut MaybeUninit<IP6RecvStruct<'static>>,
         &'static mut MaybeUninit<[Option<SocketBindingEntry>; MAX_NUM_BOUND_PORTS]>,
         &'static mut MaybeUninit<[u8; radio::MAX_BUF_SIZE]>,
         &'static mut MaybeUninit<[u8; 1280]>,
-        &'static mut MaybeUninit<[u8; MAX_PAYLOAD_LEN]>,
         &'static mut MaybeUninit<UdpVisibilityCapability>,
         &'static mut MaybeUninit<IpVisibilityCapability>,
     );
@@ -230,6 +223,8 @@ impl<A: Alarm<'static> + 'static> Component for UDPMuxComponent<A> {
         &'static MuxUdpSender<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
         &'static MuxUdpReceiver<'static>,
         &'static UdpPortManager,
+        &'static IP6RecvStruct<'static>,
+        &'static IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
     );
 
     fn finalize(self, s: Self::StaticInput) -> Self::Output {
@@ -242,8 +237,8 @@ impl<A: Alarm<'static> + 'static> Component for UDPMuxComponent<A> {
             ));
         self.mux_mac.add_user(udp_mac);
         let create_cap = create_capability!(capabilities::NetworkCapabilityCreationCapability);
-        let udp_vis = s.14.write(UdpVisibilityCapability::new(&create_cap));
-        let ip_vis = s.15.write(IpVisibilityCapability::new(&create_cap));
+        let udp_vis = s.12.write(UdpVisibilityCapability::new(&create_cap));
+        let ip_vis = s.13.write(IpVisibilityCapability::new(&create_cap));
 
         let sixlowpan = s.2.write(sixlowpan_state::Sixlowpan::new(
             sixlowpan_compression::Context {
@@ -255,7 +250,7 @@ impl<A: Alarm<'static> + 'static> Component for UDPMuxComponent<A> {
             ipsender_virtual_alarm, // OK to reuse bc only used to get time, not set alarms
         ));
 
-        let sixlowpan_rx_buffer = s.12.write([0; 1280]);
+        let sixlowpan_rx_buffer = s.11.write([0; 1280]);
         let sixlowpan_state = sixlowpan as &dyn sixlowpan_state::SixlowpanState;
         let sixlowpan_tx = sixlowpan_state::TxState::new(sixlowpan_state);
         let default_rx_state =
@@ -263,26 +258,16 @@ impl<A: Alarm<'static> + 'static> Component for UDPMuxComponent<A> {
         sixlowpan_state.add_rx_state(default_rx_state);
         udp_mac.set_receive_client(sixlowpan);
 
-        let udp_dgram_buffer = s.13.write([0; MAX_PAYLOAD_LEN]);
-        let tr_hdr = TransportHeader::UDP(UDPHeader::new());
-        let ip_pyld: IPPayload = IPPayload {
-            header: tr_hdr,
-            payload: udp_dgram_buffer,
-        };
-        let ip6_dg = s.8.write(IP6Packet::new(ip_pyld));
+        let radio_buf = s.10.write([0; radio::MAX_BUF_SIZE]);
 
-        let radio_buf = s.11.write([0; radio::MAX_BUF_SIZE]);
-
-        // In current design, all udp senders share same IP sender, and the IP
-        // sender holds the destination mac address. This means all UDP senders
-        // must send to the same mac address...this works fine under the
-        // assumption of all packets being routed via a single gateway router,
-        // but doesn't work if multiple senders want to send to different
-        // addresses on a local network. This will be fixed once we have an
-        // ipv6_nd cache mapping IP addresses to dst macs
+        // All udp senders share the same IP sender. Without a neighbor cache,
+        // the IP sender sends every packet to the destination mac address
+        // given here, which works under the assumption of all packets being
+        // routed via a single gateway router. `NeighborDiscoveryComponent`
+        // adds a cache mapping IP addresses to dst macs, and this address
+        // remains the fallback for destinations it cannot resolve.
         let ip_send =
             s.4.write(capsules_extra::net::ipv6::ipv6_send::IP6SendStruct::new(
-                ip6_dg,
                 ipsender_virtual_alarm,
                 radio_buf,
                 sixlowpan_tx,
@@ -293,15 +278,15 @@ impl<A: Alarm<'static> + 'static> Component for UDPMuxComponent<A> {
             ));
         ipsender_virtual_alarm.set_alarm_client(ip_send);
 
-        // Initially, set src IP of the sender to be the first IP in the
-        // Interface list. Userland apps can change this if they so choose.
-        // Notably, the src addr is the same regardless of if messages are sent
-        // from userland or capsules.
-        ip_send.set_addr(self.interface_list[0]);
+        // The sender selects the src IP of each packet from the interface
+        // list, which autoconfiguration adds global addresses to. Notably,
+        // the src addr is the same regardless of if messages are sent from
+        // userland or capsules.
+        ip_send.set_interface_list(self.interface_list);
         udp_mac.set_transmit_client(ip_send);
 
         let ip_receive =
-            s.9.write(capsules_extra::net::ipv6::ipv6_recv::IP6RecvStruct::new());
+            s.8.write(capsules_extra::net::ipv6::ipv6_recv::IP6RecvStruct::new());
         sixlowpan_state.set_rx_client(ip_receive);
         let udp_recv_mux = s.6.write(MuxUdpReceiver::new());
         ip_receive.set_client(udp_recv_mux);
@@ -309,14 +294,21 @@ impl<A: Alarm<'static> + 'static> Component for UDPMuxComponent<A> {
         let udp_send_mux = s.5.write(MuxUdpSender::new(ip_send));
         ip_send.set_client(udp_send_mux);
 
-        let kernel_ports = s.10.write([None; MAX_NUM_BOUND_PORTS]);
+        let kernel_ports = s.9.write([None; MAX_NUM_BOUND_PORTS]);
         let create_table_cap = create_capability!(capabilities::CreatePortTableCapability);
         let udp_port_table = s.7.write(UdpPortManager::new(
             &create_table_cap,
             kernel_ports,
             udp_vis,
         ));
+        udp_recv_mux.set_port_table(udp_port_table);
 
-        (udp_send_mux, udp_recv_mux, udp_port_table)
+        (
+            udp_send_mux,
+            udp_recv_mux,
+            udp_port_table,
+            ip_receive,
+            ip_send,
+        )
     }
 }
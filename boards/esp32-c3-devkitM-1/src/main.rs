@@ -264,8 +264,13 @@ unsafe fn setup() -> (
         static _eappmem: u8;
     }
 
-    let scheduler = components::sched::priority::PriorityComponent::new(board_kernel)
-        .finalize(components::priority_component_static!());
+    let scheduler: &'static PrioritySched =
+        components::sched::priority::PriorityComponent::new(board_kernel)
+            .finalize(components::priority_component_static!());
+    scheduler.set_priority_levels(static_init!(
+        [core::cell::Cell<u8>; NUM_PROCS],
+        core::array::from_fn(|_| core::cell::Cell::new(0))
+    ));
 
     let process_printer = components::process_printer::ProcessPrinterTextComponent::new()
         .finalize(components::process_printer_text_component_static!());
@@ -282,6 +287,7 @@ unsafe fn setup() -> (
     .finalize(components::process_console_component_static!(
         esp32_c3::timg::TimG
     ));
+    process_console.set_priority_control(scheduler);
     let _ = process_console.start();
 
     let esp32_c3_board = static_init!(
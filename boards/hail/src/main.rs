@@ -440,13 +440,16 @@ pub unsafe fn main() {
     )
     .finalize(components::adc_dedicated_component_static!(sam4l::adc::Adc));
 
-    // Setup RNG
-    let rng = components::rng::RngComponent::new(
+    // Setup RNG. The TRNG only seeds a CSPRNG that serves userspace, as it
+    // is too slow to serve large requests directly.
+    let (rng, _csprng) = components::rng::CsprngComponent::new(
         board_kernel,
         capsules_core::rng::DRIVER_NUM,
         &peripherals.trng,
+        mux_alarm,
+        60_000,
     )
-    .finalize(components::rng_component_static!());
+    .finalize(components::csprng_component_static!(sam4l::ast::Ast));
 
     // set GPIO driver controlling remaining GPIO pins
     let gpio = components::gpio::GpioComponent::new(
@@ -536,6 +539,7 @@ pub unsafe fn main() {
     // Uncomment to measure overheads for TakeCell and MapCell:
     // test_take_map_cell::test_take_map_cell();
 
+    kernel::reboot_reason::report(Some(pm));
     debug!("Initialization complete. Entering main loop.");
 
     // These symbols are defined in the linker script.
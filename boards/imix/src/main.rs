@@ -21,7 +21,10 @@ use capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm;
 use capsules_core::virtualizers::virtual_i2c::MuxI2C;
 use capsules_core::virtualizers::virtual_spi::VirtualSpiMasterDevice;
 use capsules_extra::net::ieee802154::MacAddress;
-use capsules_extra::net::ipv6::ip_utils::IPAddr;
+use capsules_extra::net::ipv6::slaac::InterfaceList;
+#[cfg(feature = "ipv6_stacks")]
+use capsules_extra::net::mdns::Service;
+use capsules_extra::nonvolatile_storage_driver::StorageRegion;
 use kernel::capabilities;
 use kernel::component::Component;
 use kernel::deferred_call::DeferredCallClient;
@@ -31,6 +34,8 @@ use kernel::hil::radio;
 #[allow(unused_imports)]
 use kernel::hil::radio::{RadioConfig, RadioData};
 use kernel::hil::symmetric_encryption::AES128;
+#[cfg(feature = "tickless")]
+use kernel::platform::scheduler_timer::TicklessSchedulerTimer;
 use kernel::platform::{KernelResources, SyscallDriverLookup};
 use kernel::process_checker::basic::AppCheckerSha256;
 use kernel::scheduler::round_robin::RoundRobinSched;
@@ -55,7 +60,7 @@ use components::isl29035::Isl29035Component;
 use components::led::LedsComponent;
 use components::nrf51822::Nrf51822Component;
 use components::process_console::ProcessConsoleComponent;
-use components::rng::RngComponent;
+use components::rng::CsprngComponent;
 use components::si7021::SI7021Component;
 use components::spi::{SpiComponent, SpiSyscallComponent};
 
@@ -82,19 +87,31 @@ mod multi_timer_test;
 const NUM_PROCS: usize = 4;
 
 // Constants related to the configuration of the 15.4 network stack
-// TODO: Notably, the radio MAC addresses can be configured from userland at the moment
-// We probably want to change this from a security perspective (multiple apps being
-// able to change the MAC address seems problematic), but it is very convenient for
-// development to be able to just flash two corresponding apps onto two devices and
-// have those devices talk to each other without having to modify the kernel flashed
-// onto each device. This makes MAC address configuration a good target for capabilities -
-// only allow one app per board to have control of MAC address configuration?
+// Notably, any app can configure the radio MAC addresses from userland. This is
+// very convenient for development, to be able to just flash two corresponding
+// apps onto two devices and have those devices talk to each other without
+// having to modify the kernel flashed onto each device. Deployments should
+// only allow the app that manages the network to change the addresses, with
+// `RadioDriver::restrict_address_config()`.
+//
+// RADIO_CHANNEL is the channel the radio starts on; processes can scan the
+// energy on the channels and move to a quieter one at runtime.
 const RADIO_CHANNEL: u8 = 26;
 const DST_MAC_ADDR: MacAddress = MacAddress::Short(49138);
 const DEFAULT_CTX_PREFIX_LEN: u8 = 8; //Length of context for 6LoWPAN compression
 const DEFAULT_CTX_PREFIX: [u8; 16] = [0x0 as u8; 16]; //Context for 6LoWPAN Compression
 const PAN_ID: u16 = 0xABCD;
 
+// The CoAP server of the CoAP driver, advertised with DNS-SD.
+#[cfg(feature = "ipv6_stacks")]
+static MDNS_SERVICES: [Service; 1] = [Service {
+    instance: "imix",
+    service: "_coap",
+    protocol: "_udp",
+    port: capsules_extra::net::coap::COAP_PORT,
+    txt: &[],
+}];
+
 // how should the kernel respond when a process faults
 const FAULT_RESPONSE: kernel::process::StopFaultPolicy = kernel::process::StopFaultPolicy {};
 
@@ -104,21 +121,16 @@ static mut PROCESSES: [Option<&'static dyn kernel::process::Process>; NUM_PROCS]
 static mut CHIP: Option<&'static sam4l::chip::Sam4l<Sam4lDefaultPeripherals>> = None;
 static mut PROCESS_PRINTER: Option<&'static kernel::process::ProcessPrinterText> = None;
 
+#[cfg(not(feature = "tickless"))]
+type ImixSchedulerTimer = cortexm4::systick::SysTick;
+#[cfg(feature = "tickless")]
+type ImixSchedulerTimer = TicklessSchedulerTimer<cortexm4::systick::SysTick>;
+
 /// Dummy buffer that causes the linker to reserve enough space for the stack.
 #[no_mangle]
 #[link_section = ".stack_buffer"]
 pub static mut STACK_MEMORY: [u8; 0x2000] = [0; 0x2000];
 
-// Function for the process console to use to reboot the board
-fn reset() -> ! {
-    unsafe {
-        cortexm4::scb::reset();
-    }
-    loop {
-        cortexm4::support::nop();
-    }
-}
-
 struct Imix {
     pconsole: &'static capsules_core::process_console::ProcessConsole<
         'static,
@@ -157,6 +169,16 @@ struct Imix {
     ipc: kernel::ipc::IPC<{ NUM_PROCS as u8 }>,
     ninedof: &'static capsules_extra::ninedof::NineDof<'static>,
     udp_driver: &'static capsules_extra::net::udp::UDPDriver<'static>,
+    #[cfg(feature = "ipv6_stacks")]
+    tcp_driver: &'static capsules_extra::net::tcp::TCPDriver<
+        'static,
+        VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>,
+    >,
+    #[cfg(feature = "ipv6_stacks")]
+    coap_driver: &'static capsules_extra::net::coap::CoAPDriver<
+        'static,
+        VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>,
+    >,
     crc: &'static capsules_extra::crc::CrcDriver<'static, sam4l::crccu::Crccu<'static>>,
     usb_driver: &'static capsules_extra::usb::usb_user::UsbSyscallDriver<
         'static,
@@ -166,7 +188,7 @@ struct Imix {
     nonvolatile_storage:
         &'static capsules_extra::nonvolatile_storage_driver::NonvolatileStorage<'static>,
     scheduler: &'static RoundRobinSched<'static>,
-    systick: cortexm4::systick::SysTick,
+    scheduler_timer: ImixSchedulerTimer,
     credentials_checking_policy: &'static (),
     //credentials_checking_policy: &'static AppCheckerSha256,
 }
@@ -206,6 +228,10 @@ impl SyscallDriverLookup for Imix {
             capsules_extra::crc::DRIVER_NUM => f(Some(self.crc)),
             capsules_extra::usb::usb_user::DRIVER_NUM => f(Some(self.usb_driver)),
             capsules_extra::net::udp::DRIVER_NUM => f(Some(self.udp_driver)),
+            #[cfg(feature = "ipv6_stacks")]
+            capsules_extra::net::tcp::DRIVER_NUM => f(Some(self.tcp_driver)),
+            #[cfg(feature = "ipv6_stacks")]
+            capsules_extra::net::coap::DRIVER_NUM => f(Some(self.coap_driver)),
             capsules_extra::nrf51822_serialization::DRIVER_NUM => f(Some(self.nrf51822)),
             capsules_extra::nonvolatile_storage_driver::DRIVER_NUM => {
                 f(Some(self.nonvolatile_storage))
@@ -224,7 +250,7 @@ impl KernelResources<sam4l::chip::Sam4l<Sam4lDefaultPeripherals>> for Imix {
     type CredentialsCheckingPolicy = ();
     //type CredentialsCheckingPolicy = AppCheckerSha256;
     type Scheduler = RoundRobinSched<'static>;
-    type SchedulerTimer = cortexm4::systick::SysTick;
+    type SchedulerTimer = ImixSchedulerTimer;
     type WatchDog = ();
     type ContextSwitchCallback = ();
 
@@ -244,7 +270,7 @@ impl KernelResources<sam4l::chip::Sam4l<Sam4lDefaultPeripherals>> for Imix {
         self.scheduler
     }
     fn scheduler_timer(&self) -> &Self::SchedulerTimer {
-        &self.systick
+        &self.scheduler_timer
     }
     fn watchdog(&self) -> &Self::WatchDog {
         &()
@@ -401,26 +427,38 @@ pub unsafe fn main() {
     peripherals.usart3.set_mode(sam4l::usart::UsartMode::Uart);
     let uart_mux = UartMuxComponent::new(&peripherals.usart3, 115200)
         .finalize(components::uart_mux_component_static!());
+    // Combine queued console and debug output into single DMA transfers.
+    uart_mux.set_transmit_advanced(&peripherals.usart3, kernel::static_dma_buf!(512, 4));
 
     // # TIMER
     let mux_alarm = AlarmMuxComponent::new(&peripherals.ast)
         .finalize(components::alarm_mux_component_static!(sam4l::ast::Ast));
     peripherals.ast.configure(mux_alarm);
 
+    // Only enter deep sleep when it can wake up in time for the next alarm.
+    #[cfg(feature = "idle_arbiter")]
+    {
+        let idle_arbiter = static_init!(
+            kernel::platform::idle::IdleArbiter<'static>,
+            kernel::platform::idle::IdleArbiter::new(static_init!(
+                [&'static dyn kernel::platform::idle::IdleSource; 1],
+                [mux_alarm]
+            ))
+        );
+        chip.set_idle_arbiter(idle_arbiter);
+    }
+
     let alarm =
         AlarmDriverComponent::new(board_kernel, capsules_core::alarm::DRIVER_NUM, mux_alarm)
             .finalize(components::alarm_component_static!(sam4l::ast::Ast));
 
-    let pconsole = ProcessConsoleComponent::new(
-        board_kernel,
-        uart_mux,
-        mux_alarm,
-        process_printer,
-        Some(reset),
-    )
-    .finalize(components::process_console_component_static!(
-        sam4l::ast::Ast
-    ));
+    let pconsole =
+        ProcessConsoleComponent::new(board_kernel, uart_mux, mux_alarm, process_printer, None)
+            .finalize(components::process_console_component_static!(
+                sam4l::ast::Ast
+            ));
+    pconsole.set_reset(&cortexm4::scb::ScbReset);
+    pconsole.set_clock_introspection(pm);
 
     let console = ConsoleOrderedComponent::new(
         board_kernel,
@@ -605,12 +643,15 @@ pub unsafe fn main() {
     .finalize(components::analog_comparator_component_static!(
         sam4l::acifc::Acifc
     ));
-    let rng = RngComponent::new(
+    // The CSPRNG also provides the TCP driver's initial sequence numbers.
+    let (rng, csprng) = CsprngComponent::new(
         board_kernel,
         capsules_core::rng::DRIVER_NUM,
         &peripherals.trng,
+        mux_alarm,
+        60_000,
     )
-    .finalize(components::rng_component_static!());
+    .finalize(components::csprng_component_static!(sam4l::ast::Ast));
 
     // For now, assign the 802.15.4 MAC address on the device as
     // simply a 16-bit short address which represents the last 16 bits
@@ -666,37 +707,52 @@ pub unsafe fn main() {
         0x20000,                          // Length of userspace accessible region
         &_sstorage as *const u8 as usize, //start address of kernel region
         &_estorage as *const u8 as usize - &_sstorage as *const u8 as usize, // length of kernel region
+        // Split the userspace region between the apps with storage IDs 1 to 4
+        // (the `write_id` in their TBF headers).
+        &[
+            StorageRegion {
+                storage_id: 1,
+                offset: 0x0,
+                length: 0x8000,
+            },
+            StorageRegion {
+                storage_id: 2,
+                offset: 0x8000,
+                length: 0x8000,
+            },
+            StorageRegion {
+                storage_id: 3,
+                offset: 0x10000,
+                length: 0x8000,
+            },
+            StorageRegion {
+                storage_id: 4,
+                offset: 0x18000,
+                length: 0x8000,
+            },
+        ],
     )
     .finalize(components::nonvolatile_storage_component_static!(
         sam4l::flashcalw::FLASHCALW
     ));
 
-    let local_ip_ifaces = static_init!(
-        [IPAddr; 3],
-        [
-            IPAddr([
-                0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d,
-                0x0e, 0x0f,
-            ]),
-            IPAddr([
-                0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b, 0x1c, 0x1d,
-                0x1e, 0x1f,
-            ]),
-            IPAddr::generate_from_mac(src_mac_from_serial_num),
-        ]
-    );
-
-    let (udp_send_mux, udp_recv_mux, udp_port_table) = components::udp_mux::UDPMuxComponent::new(
-        mux_mac,
-        DEFAULT_CTX_PREFIX_LEN,
-        DEFAULT_CTX_PREFIX,
-        DST_MAC_ADDR,
-        src_mac_from_serial_num, //comment out for dual rx test only
-        //MacAddress::Short(49138), //comment in for dual rx test only
-        local_ip_ifaces,
-        mux_alarm,
-    )
-    .finalize(components::udp_mux_component_static!(sam4l::ast::Ast));
+    // The link-local address is formed from the MAC address, and global
+    // addresses are autoconfigured from the prefixes routers advertise (with
+    // the `ipv6_stacks` feature).
+    let local_ip_ifaces = static_init!(InterfaceList, InterfaceList::new(src_mac_from_serial_num));
+
+    let (udp_send_mux, udp_recv_mux, udp_port_table, ip_receive, ip_send) =
+        components::udp_mux::UDPMuxComponent::new(
+            mux_mac,
+            DEFAULT_CTX_PREFIX_LEN,
+            DEFAULT_CTX_PREFIX,
+            DST_MAC_ADDR,
+            src_mac_from_serial_num, //comment out for dual rx test only
+            //MacAddress::Short(49138), //comment in for dual rx test only
+            local_ip_ifaces,
+            mux_alarm,
+        )
+        .finalize(components::udp_mux_component_static!(sam4l::ast::Ast));
 
     // UDP driver initialization happens here
     let udp_driver = components::udp_driver::UDPDriverComponent::new(
@@ -709,6 +765,90 @@ pub unsafe fn main() {
     )
     .finalize(components::udp_driver_component_static!(sam4l::ast::Ast));
 
+    // The other IPv6 stacks are only built with the `ipv6_stacks` feature.
+    #[cfg(not(feature = "ipv6_stacks"))]
+    let _ = (ip_receive, ip_send, csprng);
+    #[cfg(feature = "ipv6_stacks")]
+    let (tcp_driver, coap_driver) = {
+        // Neighbor Discovery, so that packets are sent to the MAC address of
+        // their next hop. DST_MAC_ADDR remains the fallback for next hops that
+        // cannot be resolved.
+        components::neighbor_discovery::NeighborDiscoveryComponent::new(
+            udp_send_mux,
+            ip_receive,
+            ip_send,
+            mux_alarm,
+            local_ip_ifaces,
+        )
+        .finalize(components::neighbor_discovery_component_static!(
+            sam4l::ast::Ast
+        ));
+
+        // Multicast Listener Discovery, so that the multicast groups joined by
+        // capsules and apps are reported to the routers of the link. It must be
+        // set up before the capsules that join groups.
+        components::mld::MulticastListenerComponent::new(
+            udp_send_mux,
+            ip_receive,
+            udp_port_table,
+            mux_alarm,
+            local_ip_ifaces,
+        )
+        .finalize(components::mld_component_static!(sam4l::ast::Ast));
+
+        // RPL routing, so that imix nodes form a multi-hop mesh and forward the
+        // packets of their neighbors. This node joins the DODAG of a root it
+        // hears of; pass the global address of this node to start a DODAG instead.
+        components::rpl::RplComponent::new(
+            udp_send_mux,
+            ip_receive,
+            ip_send,
+            mux_alarm,
+            local_ip_ifaces,
+            None,
+        )
+        .finalize(components::rpl_component_static!(sam4l::ast::Ast));
+
+        // TCP driver initialization, sharing the UDP/6LoWPAN stack
+        let tcp_driver = components::tcp_driver::TCPDriverComponent::new(
+            board_kernel,
+            capsules_extra::net::tcp::DRIVER_NUM,
+            udp_send_mux,
+            ip_receive,
+            mux_alarm,
+            csprng,
+        )
+        .finalize(components::tcp_driver_component_static!(sam4l::ast::Ast));
+
+        // CoAP driver initialization. It binds its UDP port through the port
+        // table, which requires the UDP driver to be set up first.
+        let coap_driver = components::coap_driver::CoAPDriverComponent::new(
+            board_kernel,
+            capsules_extra::net::coap::DRIVER_NUM,
+            udp_send_mux,
+            udp_recv_mux,
+            udp_port_table,
+            capsules_extra::net::coap::COAP_PORT,
+            mux_alarm,
+        )
+        .finalize(components::coap_driver_component_static!(sam4l::ast::Ast));
+
+        // mDNS responder, so that hosts on the link find this node as imix.local
+        // and discover its CoAP server.
+        components::mdns::MdnsComponent::new(
+            udp_send_mux,
+            udp_recv_mux,
+            udp_port_table,
+            mux_alarm,
+            local_ip_ifaces,
+            "imix",
+            &MDNS_SERVICES,
+        )
+        .finalize(components::mdns_component_static!(sam4l::ast::Ast));
+
+        (tcp_driver, coap_driver)
+    };
+
     let scheduler = components::sched::round_robin::RoundRobinComponent::new(&PROCESSES)
         .finalize(components::round_robin_component_static!(NUM_PROCS));
 
@@ -730,11 +870,23 @@ pub unsafe fn main() {
         ipc: kernel::ipc::IPC::new(board_kernel, kernel::ipc::DRIVER_NUM, &grant_cap),
         ninedof,
         udp_driver,
+        #[cfg(feature = "ipv6_stacks")]
+        tcp_driver,
+        #[cfg(feature = "ipv6_stacks")]
+        coap_driver,
         usb_driver,
         nrf51822: nrf_serialization,
         nonvolatile_storage,
         scheduler,
-        systick: cortexm4::systick::SysTick::new(),
+        #[cfg(not(feature = "tickless"))]
+        scheduler_timer: cortexm4::systick::SysTick::new(),
+        // Only interrupt processes when another one is ready to run, to
+        // save wakeups when a single process runs on battery.
+        #[cfg(feature = "tickless")]
+        scheduler_timer: TicklessSchedulerTimer::new(
+            board_kernel,
+            cortexm4::systick::SysTick::new(),
+        ),
         //credentials_checking_policy: checker,
         credentials_checking_policy: &(),
     };
@@ -798,6 +950,7 @@ pub unsafe fn main() {
     .finalize(components::multi_alarm_test_component_buf!(sam4l::ast::Ast))
     .run();*/
 
+    kernel::reboot_reason::report(Some(pm));
     debug!("Initialization complete. Entering main loop");
 
     // These symbols are defined in the linker script.
@@ -20,7 +20,6 @@ use capsules_extra::net::icmpv6::{ICMP6Header, ICMP6Type};
 use capsules_extra::net::ieee802154::MacAddress;
 use capsules_extra::net::ipv6::ip_utils::IPAddr;
 use capsules_extra::net::ipv6::ipv6_send::{IP6SendStruct, IP6Sender};
-use capsules_extra::net::ipv6::{IP6Packet, IPPayload, TransportHeader};
 use capsules_extra::net::network_capabilities::{
     AddrRange, IpVisibilityCapability, NetworkCapability, PortRange,
 };
@@ -111,19 +110,9 @@ pub unsafe fn run(
     let sixlowpan_state = sixlowpan as &dyn SixlowpanState;
     let sixlowpan_tx = TxState::new(sixlowpan_state);
 
-    let icmp_hdr = ICMP6Header::new(ICMP6Type::Type128); // Echo Request
-
-    let ip_pyld: IPPayload = IPPayload {
-        header: TransportHeader::ICMP(icmp_hdr),
-        payload: &mut ICMP_PAYLOAD,
-    };
-
-    let ip6_dg = static_init!(IP6Packet<'static>, IP6Packet::new(ip_pyld));
-
     let ip6_sender = static_init!(
         IP6SendStruct<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
         IP6SendStruct::new(
-            ip6_dg,
             ipsender_virtual_alarm,
             &mut RF233_BUF,
             sixlowpan_tx,
@@ -586,6 +586,21 @@ pub unsafe fn main() {
         512
     ));
 
+    // Crash report, kept in the page right before the apps.
+
+    let virtual_crash_flash = components::flash::FlashUserComponent::new(mux_flash).finalize(
+        components::flash_user_component_static!(nrf52833::nvmc::Nvmc),
+    );
+
+    let crash_report =
+        components::crash_report::FlashCrashReportComponent::new(virtual_crash_flash, 0x3f)
+            .finalize(components::flash_crash_report_component_static!(
+                capsules_core::virtualizers::virtual_flash::FlashUser<
+                    'static,
+                    nrf52833::nvmc::Nvmc,
+                >
+            ));
+
     //--------------------------------------------------------------------------
     // WIRELESS
     //--------------------------------------------------------------------------
@@ -689,7 +704,7 @@ pub unsafe fn main() {
         .finalize(components::process_printer_text_component_static!());
     PROCESS_PRINTER = Some(process_printer);
 
-    let _process_console = components::process_console::ProcessConsoleComponent::new(
+    let process_console = components::process_console::ProcessConsoleComponent::new(
         board_kernel,
         uart_mux,
         mux_alarm,
@@ -699,7 +714,8 @@ pub unsafe fn main() {
     .finalize(components::process_console_component_static!(
         nrf52833::rtc::Rtc
     ));
-    let _ = _process_console.start();
+    process_console.set_crash_report(crash_report);
+    let _ = process_console.start();
 
     //--------------------------------------------------------------------------
     // FINAL SETUP AND BOARD BOOT
@@ -554,35 +554,23 @@ pub unsafe fn main() {
         nrf52840::ieee802154_radio::Radio,
         nrf52840::aes::AesECB<'static>
     ));
-    use capsules_extra::net::ipv6::ip_utils::IPAddr;
-
-    let local_ip_ifaces = static_init!(
-        [IPAddr; 3],
-        [
-            IPAddr([
-                0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d,
-                0x0e, 0x0f,
-            ]),
-            IPAddr([
-                0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b, 0x1c, 0x1d,
-                0x1e, 0x1f,
-            ]),
-            IPAddr::generate_from_mac(capsules_extra::net::ieee802154::MacAddress::Short(
-                serial_num_bottom_16
-            )),
-        ]
-    );
-
-    let (udp_send_mux, udp_recv_mux, udp_port_table) = components::udp_mux::UDPMuxComponent::new(
-        mux_mac,
-        DEFAULT_CTX_PREFIX_LEN,
-        DEFAULT_CTX_PREFIX,
-        DST_MAC_ADDR,
-        src_mac_from_serial_num,
-        local_ip_ifaces,
-        mux_alarm,
-    )
-    .finalize(components::udp_mux_component_static!(nrf52840::rtc::Rtc));
+    use capsules_extra::net::ipv6::slaac::InterfaceList;
+
+    // The link-local address is formed from the MAC address, and global
+    // addresses are autoconfigured from the prefixes routers advertise.
+    let local_ip_ifaces = static_init!(InterfaceList, InterfaceList::new(src_mac_from_serial_num));
+
+    let (udp_send_mux, udp_recv_mux, udp_port_table, ip_receive, ip_send) =
+        components::udp_mux::UDPMuxComponent::new(
+            mux_mac,
+            DEFAULT_CTX_PREFIX_LEN,
+            DEFAULT_CTX_PREFIX,
+            DST_MAC_ADDR,
+            src_mac_from_serial_num,
+            local_ip_ifaces,
+            mux_alarm,
+        )
+        .finalize(components::udp_mux_component_static!(nrf52840::rtc::Rtc));
 
     // UDP driver initialization happens here
     let udp_driver = components::udp_driver::UDPDriverComponent::new(
@@ -595,6 +583,18 @@ pub unsafe fn main() {
     )
     .finalize(components::udp_driver_component_static!(nrf52840::rtc::Rtc));
 
+    // Neighbor Discovery, which also autoconfigures the global addresses
+    components::neighbor_discovery::NeighborDiscoveryComponent::new(
+        udp_send_mux,
+        ip_receive,
+        ip_send,
+        mux_alarm,
+        local_ip_ifaces,
+    )
+    .finalize(components::neighbor_discovery_component_static!(
+        nrf52840::rtc::Rtc
+    ));
+
     //--------------------------------------------------------------------------
     // FINAL SETUP AND BOARD BOOT
     //--------------------------------------------------------------------------
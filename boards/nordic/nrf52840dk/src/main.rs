@@ -77,13 +77,13 @@ use capsules_core::i2c_master_slave_driver::I2CMasterSlaveDriver;
 use capsules_core::virtualizers::virtual_aes_ccm::MuxAES128CCM;
 use capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm;
 use capsules_extra::net::ieee802154::MacAddress;
-use capsules_extra::net::ipv6::ip_utils::IPAddr;
+use capsules_extra::net::ipv6::slaac::InterfaceList;
 use kernel::component::Component;
 use kernel::deferred_call::DeferredCallClient;
 use kernel::hil::i2c::{I2CMaster, I2CSlave};
 use kernel::hil::led::LedLow;
 use kernel::hil::symmetric_encryption::AES128;
-use kernel::hil::time::Counter;
+use kernel::hil::time::{Alarm, Counter};
 #[allow(unused_imports)]
 use kernel::hil::usb::Client;
 use kernel::platform::{KernelResources, SyscallDriverLookup};
@@ -210,6 +210,7 @@ pub struct Platform {
     i2c_master_slave: &'static capsules_core::i2c_master_slave_driver::I2CMasterSlaveDriver<
         'static,
         nrf52840::i2c::TWI,
+        VirtualMuxAlarm<'static, nrf52840::rtc::Rtc<'static>>,
     >,
     spi_controller: &'static capsules_core::spi_controller::Spi<
         'static,
@@ -466,6 +467,7 @@ pub unsafe fn main() {
         uart_mux,
     )
     .finalize(components::console_component_static!());
+    pconsole.set_input_focus(console);
     // Create the debugger object that handles calls to `debug!()`.
     components::debug_writer::DebugWriterComponent::new(uart_mux)
         .finalize(components::debug_writer_component_static!());
@@ -504,33 +506,21 @@ pub unsafe fn main() {
         nrf52840::aes::AesECB<'static>
     ));
 
-    let local_ip_ifaces = static_init!(
-        [IPAddr; 3],
-        [
-            IPAddr([
-                0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d,
-                0x0e, 0x0f,
-            ]),
-            IPAddr([
-                0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b, 0x1c, 0x1d,
-                0x1e, 0x1f,
-            ]),
-            IPAddr::generate_from_mac(capsules_extra::net::ieee802154::MacAddress::Short(
-                serial_num_bottom_16
-            )),
-        ]
-    );
-
-    let (udp_send_mux, udp_recv_mux, udp_port_table) = components::udp_mux::UDPMuxComponent::new(
-        mux_mac,
-        DEFAULT_CTX_PREFIX_LEN,
-        DEFAULT_CTX_PREFIX,
-        DST_MAC_ADDR,
-        src_mac_from_serial_num,
-        local_ip_ifaces,
-        mux_alarm,
-    )
-    .finalize(components::udp_mux_component_static!(nrf52840::rtc::Rtc));
+    // The link-local address is formed from the MAC address, and global
+    // addresses are autoconfigured from the prefixes routers advertise.
+    let local_ip_ifaces = static_init!(InterfaceList, InterfaceList::new(src_mac_from_serial_num));
+
+    let (udp_send_mux, udp_recv_mux, udp_port_table, ip_receive, ip_send) =
+        components::udp_mux::UDPMuxComponent::new(
+            mux_mac,
+            DEFAULT_CTX_PREFIX_LEN,
+            DEFAULT_CTX_PREFIX,
+            DST_MAC_ADDR,
+            src_mac_from_serial_num,
+            local_ip_ifaces,
+            mux_alarm,
+        )
+        .finalize(components::udp_mux_component_static!(nrf52840::rtc::Rtc));
 
     // UDP driver initialization happens here
     let udp_driver = components::udp_driver::UDPDriverComponent::new(
@@ -543,6 +533,18 @@ pub unsafe fn main() {
     )
     .finalize(components::udp_driver_component_static!(nrf52840::rtc::Rtc));
 
+    // Neighbor Discovery, which also autoconfigures the global addresses
+    components::neighbor_discovery::NeighborDiscoveryComponent::new(
+        udp_send_mux,
+        ip_receive,
+        ip_send,
+        mux_alarm,
+        local_ip_ifaces,
+    )
+    .finalize(components::neighbor_discovery_component_static!(
+        nrf52840::rtc::Rtc
+    ));
+
     let temp = components::temperature::TemperatureComponent::new(
         board_kernel,
         capsules_extra::temperature::DRIVER_NUM,
@@ -598,6 +600,7 @@ pub unsafe fn main() {
         0x3FA0000, // Length of userspace accessible region
         0,         // Start address of kernel region
         0x60000,   // Length of kernel region
+        &[],       // All apps share the userspace accessible region
     )
     .finalize(components::nonvolatile_storage_component_static!(
         capsules_extra::mx25r6435f::MX25R6435F<
@@ -611,14 +614,32 @@ pub unsafe fn main() {
         >
     ));
 
+    // Keep the process console command history in the kernel region of the
+    // external flash, so it survives resets.
+    const HISTORY_STORAGE_LEN: usize = capsules_core::process_console::history_storage_len(
+        capsules_core::process_console::DEFAULT_COMMAND_HISTORY_LEN,
+    );
+    let _ = pconsole.set_history_storage(
+        nonvolatile_storage,
+        0,
+        static_init!([u8; HISTORY_STORAGE_LEN], [0; HISTORY_STORAGE_LEN]),
+    );
+
     let i2c_master_buffer = static_init!([u8; 32], [0; 32]);
     let i2c_slave_buffer1 = static_init!([u8; 32], [0; 32]);
     let i2c_slave_buffer2 = static_init!([u8; 32], [0; 32]);
 
+    let i2c_master_slave_alarm = static_init!(
+        VirtualMuxAlarm<'static, nrf52840::rtc::Rtc>,
+        VirtualMuxAlarm::new(mux_alarm)
+    );
+    i2c_master_slave_alarm.setup();
+
     let i2c_master_slave = static_init!(
-        I2CMasterSlaveDriver<nrf52840::i2c::TWI>,
+        I2CMasterSlaveDriver<nrf52840::i2c::TWI, VirtualMuxAlarm<'static, nrf52840::rtc::Rtc>>,
         I2CMasterSlaveDriver::new(
             &base_peripherals.twi1,
+            i2c_master_slave_alarm,
             i2c_master_buffer,
             i2c_slave_buffer1,
             i2c_slave_buffer2,
@@ -634,6 +655,7 @@ pub unsafe fn main() {
     );
     base_peripherals.twi1.set_master_client(i2c_master_slave);
     base_peripherals.twi1.set_slave_client(i2c_master_slave);
+    i2c_master_slave_alarm.set_alarm_client(i2c_master_slave);
     base_peripherals.twi1.set_speed(nrf52840::i2c::Speed::K400);
 
     // Initialize AC using AIN5 (P0.29) as VIN+ and VIN- as AIN0 (P0.02)
@@ -634,6 +634,7 @@ pub unsafe fn main() {
     // // See comment in `boards/imix/src/main.rs`
     // virtual_uart_rx_test::run_virtual_uart_receive(mux_uart);
 
+    kernel::reboot_reason::report(Some(&stm32f429zi::rcc::Rcc::new()));
     debug!("Initialization complete. Entering main loop");
 
     // These symbols are defined in the linker script.
@@ -586,6 +586,17 @@ unsafe fn setup() -> (
         capsules_extra::tickv::TicKVKeyType,
     ));
 
+    // Keep each storage ID to an eighth of the TicKV region, so that one app
+    // can't fill it
+    let kv_usage = static_init!(
+        [capsules_extra::kv_store::WriteIdUsage; 8],
+        [Default::default(); 8]
+    );
+    let _ = kv_store.set_quota(
+        lowrisc::flash_ctrl::FLASH_PAGES_PER_BANK * lowrisc::flash_ctrl::PAGE_SIZE / 8,
+        kv_usage,
+    );
+
     let mux_otbn = crate::otbn::AccelMuxComponent::new(&peripherals.otbn)
         .finalize(otbn_mux_component_static!());
 
@@ -803,6 +814,8 @@ unsafe fn setup() -> (
             &mut mpu_config,
         )
         .unwrap();
+    // Buffers declared with static_dma_buf!()
+    chip.pmp.allocate_dma_regions(&mut mpu_config).unwrap();
 
     chip.pmp.enable_kernel_mpu(&mut mpu_config);
 
@@ -21,7 +21,7 @@ use kernel::deferred_call::DeferredCallClient;
 use kernel::hil::i2c::{I2CMaster, I2CSlave};
 use kernel::hil::led::LedLow;
 use kernel::hil::symmetric_encryption::AES128;
-use kernel::hil::time::Counter;
+use kernel::hil::time::{Alarm, Counter};
 use kernel::hil::usb::Client;
 use kernel::platform::{KernelResources, SyscallDriverLookup};
 use kernel::scheduler::round_robin::RoundRobinSched;
@@ -134,6 +134,7 @@ pub struct Platform {
     i2c_master_slave: &'static capsules_core::i2c_master_slave_driver::I2CMasterSlaveDriver<
         'static,
         nrf52840::i2c::TWI,
+        VirtualMuxAlarm<'static, nrf52840::rtc::Rtc<'static>>,
     >,
     alarm: &'static capsules_core::alarm::AlarmDriver<
         'static,
@@ -551,10 +552,17 @@ pub unsafe fn main() {
     let i2c_slave_buffer1 = static_init!([u8; 32], [0; 32]);
     let i2c_slave_buffer2 = static_init!([u8; 32], [0; 32]);
 
+    let i2c_master_slave_alarm = static_init!(
+        VirtualMuxAlarm<'static, nrf52840::rtc::Rtc>,
+        VirtualMuxAlarm::new(mux_alarm)
+    );
+    i2c_master_slave_alarm.setup();
+
     let i2c_master_slave = static_init!(
-        I2CMasterSlaveDriver<nrf52840::i2c::TWI>,
+        I2CMasterSlaveDriver<nrf52840::i2c::TWI, VirtualMuxAlarm<'static, nrf52840::rtc::Rtc>>,
         I2CMasterSlaveDriver::new(
             &base_peripherals.twi1,
+            i2c_master_slave_alarm,
             i2c_master_buffer,
             i2c_slave_buffer1,
             i2c_slave_buffer2,
@@ -570,6 +578,7 @@ pub unsafe fn main() {
     );
     base_peripherals.twi1.set_master_client(i2c_master_slave);
     base_peripherals.twi1.set_slave_client(i2c_master_slave);
+    i2c_master_slave_alarm.set_alarm_client(i2c_master_slave);
     base_peripherals.twi1.set_speed(nrf52840::i2c::Speed::K400);
 
     //--------------------------------------------------------------------------
@@ -436,6 +436,7 @@ pub unsafe fn main() {
         uart_mux,
     )
     .finalize(components::low_level_debug_component_static!());
+    lldb.set_clock(hardware_timer);
 
     let scheduler = components::sched::cooperative::CooperativeComponent::new(&PROCESSES)
         .finalize(components::cooperative_component_static!(NUM_PROCS));
@@ -82,6 +82,13 @@ static mut PROCESSES: [Option<&'static dyn kernel::process::Process>; NUM_PROCS]
 static mut CHIP: Option<&'static Rp2040<Rp2040DefaultPeripherals>> = None;
 static mut PROCESS_PRINTER: Option<&'static kernel::process::ProcessPrinterText> = None;
 
+/// Stack of processor 1.
+#[cfg(feature = "multi_core")]
+static mut PROCESSOR1_STACK: [u64; 0x200] = [0; 0x200];
+
+#[cfg(feature = "multi_core")]
+static mut PROCESSOR1: Option<&'static Processor1> = None;
+
 /// Supported drivers by the platform
 pub struct RaspberryPiPico {
     ipc: kernel::ipc::IPC<{ NUM_PROCS as u8 }>,
@@ -119,6 +126,70 @@ impl SyscallDriverLookup for RaspberryPiPico {
     }
 }
 
+/// Kernel resources of processor 1, which runs the processes in the upper
+/// half of the process slots with its own scheduler and SysTick. System
+/// calls use the drivers of the board.
+#[cfg(feature = "multi_core")]
+struct Processor1 {
+    board_kernel: &'static Kernel,
+    board: &'static RaspberryPiPico,
+    scheduler: &'static RoundRobinSched<'static>,
+    systick: cortexm0p::systick::SysTick,
+}
+
+#[cfg(feature = "multi_core")]
+impl KernelResources<Rp2040<'static, Rp2040DefaultPeripherals<'static>>> for Processor1 {
+    type SyscallDriverLookup = RaspberryPiPico;
+    type SyscallFilter = ();
+    type ProcessFault = ();
+    type CredentialsCheckingPolicy = ();
+    type Scheduler = RoundRobinSched<'static>;
+    type SchedulerTimer = cortexm0p::systick::SysTick;
+    type WatchDog = ();
+    type ContextSwitchCallback = ();
+
+    fn syscall_driver_lookup(&self) -> &Self::SyscallDriverLookup {
+        self.board
+    }
+    fn syscall_filter(&self) -> &Self::SyscallFilter {
+        &()
+    }
+    fn process_fault(&self) -> &Self::ProcessFault {
+        &()
+    }
+    fn credentials_checking_policy(&self) -> &'static Self::CredentialsCheckingPolicy {
+        &()
+    }
+    fn scheduler(&self) -> &Self::Scheduler {
+        self.scheduler
+    }
+    fn scheduler_timer(&self) -> &Self::SchedulerTimer {
+        &self.systick
+    }
+    fn watchdog(&self) -> &Self::WatchDog {
+        &()
+    }
+    fn context_switch_callback(&self) -> &Self::ContextSwitchCallback {
+        &()
+    }
+}
+
+/// Entry point of processor 1, started by `Rp2040::launch_processor1()`.
+#[cfg(feature = "multi_core")]
+unsafe extern "C" fn processor1_main() -> ! {
+    // Processor 1 only handles the interrupt of its FIFO, which wakes it up.
+    cortexm0p::nvic::Nvic::new(rp2040::interrupts::SIO_IRQ_PROC1).enable();
+
+    let main_loop_capability = create_capability!(capabilities::MainLoopCapability);
+    let processor1 = PROCESSOR1.unwrap();
+    processor1.board_kernel.kernel_loop(
+        processor1,
+        CHIP.unwrap(),
+        Some(&processor1.board.ipc),
+        &main_loop_capability,
+    );
+}
+
 impl KernelResources<Rp2040<'static, Rp2040DefaultPeripherals<'static>>> for RaspberryPiPico {
     type SyscallDriverLookup = Self;
     type SyscallFilter = ();
@@ -430,6 +501,11 @@ pub unsafe fn main() {
         ),
     )
     .finalize(components::gpio_component_static!(RPGpioPin<'static>));
+    // Let apps drive the GPIO pins above at once through the SIO.
+    gpio.set_ports(static_init!(
+        [(&'static dyn kernel::hil::gpio::Port, u32); 1],
+        [(&peripherals.pins, 0x01ff_ffcc)]
+    ));
 
     let led = LedsComponent::new().finalize(components::led_component_static!(
         LedHigh<'static, RPGpioPin<'static>>,
@@ -494,6 +570,7 @@ pub unsafe fn main() {
         Some(reset_function),
     )
     .finalize(components::process_console_component_static!(RPTimer));
+    process_console.set_clock_introspection(&peripherals.clocks);
     let _ = process_console.start();
 
     let sda_pin = peripherals.pins.get_pin(RPGpio::GPIO4);
@@ -520,26 +597,35 @@ pub unsafe fn main() {
     i2c0.init(10 * 1000);
     i2c0.set_master_client(i2c);
 
+    #[cfg(not(feature = "multi_core"))]
     let scheduler = components::sched::round_robin::RoundRobinComponent::new(&PROCESSES)
         .finalize(components::round_robin_component_static!(NUM_PROCS));
-
-    let raspberry_pi_pico = RaspberryPiPico {
-        ipc: kernel::ipc::IPC::new(
-            board_kernel,
-            kernel::ipc::DRIVER_NUM,
-            &memory_allocation_capability,
-        ),
-        alarm,
-        gpio,
-        led,
-        console,
-        adc: adc_syscall,
-        temperature: temp,
-        i2c,
-
-        scheduler,
-        systick: cortexm0p::systick::SysTick::new_with_calibration(125_000_000),
-    };
+    // Processor 0 runs the processes in the lower half of the process slots.
+    #[cfg(feature = "multi_core")]
+    let scheduler =
+        components::sched::round_robin::RoundRobinComponent::new(&PROCESSES[..NUM_PROCS / 2])
+            .finalize(components::round_robin_component_static!(NUM_PROCS / 2));
+
+    let raspberry_pi_pico = static_init!(
+        RaspberryPiPico,
+        RaspberryPiPico {
+            ipc: kernel::ipc::IPC::new(
+                board_kernel,
+                kernel::ipc::DRIVER_NUM,
+                &memory_allocation_capability,
+            ),
+            alarm,
+            gpio,
+            led,
+            console,
+            adc: adc_syscall,
+            temperature: temp,
+            i2c,
+
+            scheduler,
+            systick: cortexm0p::systick::SysTick::new_with_calibration(125_000_000),
+        }
+    );
 
     let platform_type = match peripherals.sysinfo.get_platform() {
         sysinfo::Platform::Asic => "ASIC",
@@ -552,6 +638,7 @@ pub unsafe fn main() {
         platform_type
     );
 
+    kernel::reboot_reason::report(Some(&peripherals.watchdog));
     debug!("Initialization complete. Enter main loop");
 
     // These symbols are defined in the linker script.
@@ -586,8 +673,32 @@ pub unsafe fn main() {
         debug!("{:?}", err);
     });
 
+    #[cfg(feature = "multi_core")]
+    {
+        let processor1 = static_init!(
+            Processor1,
+            Processor1 {
+                board_kernel,
+                board: raspberry_pi_pico,
+                scheduler: components::sched::round_robin::RoundRobinComponent::new(
+                    &PROCESSES[NUM_PROCS / 2..]
+                )
+                .finalize(components::round_robin_component_static!(
+                    NUM_PROCS - NUM_PROCS / 2
+                )),
+                systick: cortexm0p::systick::SysTick::new_with_calibration(125_000_000),
+            }
+        );
+        PROCESSOR1 = Some(processor1);
+        chip.launch_processor1(
+            &rp2040::BASE_VECTORS as *const _ as u32,
+            core::ptr::addr_of_mut!(PROCESSOR1_STACK).add(1) as u32,
+            processor1_main as usize as u32,
+        );
+    }
+
     board_kernel.kernel_loop(
-        &raspberry_pi_pico,
+        raspberry_pi_pico,
         chip,
         Some(&raspberry_pi_pico.ipc),
         &main_loop_capability,
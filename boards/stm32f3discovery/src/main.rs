@@ -777,6 +777,7 @@ pub unsafe fn main() {
         0x8000,     // Length of userspace accesible region (16 pages)
         &_sstorage as *const u8 as usize,
         &_estorage as *const u8 as usize - &_sstorage as *const u8 as usize,
+        &[], // All apps share the userspace accessible region
     )
     .finalize(components::nonvolatile_storage_component_static!(
         stm32f303xc::flash::Flash
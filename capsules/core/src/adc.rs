@@ -110,6 +110,8 @@ pub(crate) enum AdcMode {
     ContinuousSample = 1,
     SingleBuffer = 2,
     ContinuousBuffer = 3,
+    /// Not a state, only used to tell applications about dropped samples.
+    BufferOverrun = 4,
 }
 
 // Datas passed by the application to us
@@ -124,7 +126,6 @@ pub struct App {
     app_buf_offset: Cell<usize>,
     samples_remaining: Cell<usize>,
     samples_outstanding: Cell<usize>,
-    next_samples_outstanding: Cell<usize>,
     using_app_buf0: Cell<bool>,
 }
 
@@ -134,7 +135,6 @@ impl Default for App {
             app_buf_offset: Cell::new(0),
             samples_remaining: Cell::new(0),
             samples_outstanding: Cell::new(0),
-            next_samples_outstanding: Cell::new(0),
             using_app_buf0: Cell::new(true),
         }
     }
@@ -436,9 +436,10 @@ impl<'a, A: hil::adc::Adc<'a> + hil::adc::AdcHighSpeed<'a>> AdcDedicated<'a, A>
 
     /// Collect analog samples continuously.
     ///
-    /// Fills one "allowed" application buffer at a time and then swaps to
-    /// filling the second buffer. Upcalls occur when the in use "allowed"
-    /// buffer fills.
+    /// Fills the two "allowed" application buffers in turn, swapping to the
+    /// other buffer when one is full. Upcalls occur when an "allowed" buffer
+    /// fills, and when the ADC dropped samples because it had no buffer to
+    /// sample into.
     ///
     /// - `channel` - index into `channels` array, which channel to sample
     /// - `frequency` - number of samples per second to collect
@@ -454,21 +455,20 @@ impl<'a, A: hil::adc::Adc<'a> + hil::adc::AdcHighSpeed<'a>> AdcDedicated<'a, A>
         }
         let chan = &self.channels[channel];
 
-        // cannot continuously sample without two buffers
-        let mut app_buf_length = 0;
-        let mut next_app_buf_length = 0;
+        // cannot continuously sample without two buffers that each hold at
+        // least one sample
         let exists = self.processid.map_or(false, |id| {
             self.apps
                 .enter(*id, |_, kernel_data| {
-                    app_buf_length = kernel_data
+                    let app_buf_length = kernel_data
                         .get_readwrite_processbuffer(0)
                         .map(|b| b.len())
                         .unwrap_or(0);
-                    next_app_buf_length = kernel_data
+                    let next_app_buf_length = kernel_data
                         .get_readwrite_processbuffer(1)
                         .map(|b| b.len())
                         .unwrap_or(0);
-                    app_buf_length > 0 && next_app_buf_length > 0
+                    app_buf_length >= 2 && next_app_buf_length >= 2
                 })
                 .map_err(|err| {
                     if err == kernel::process::Error::NoSuchApp
@@ -491,45 +491,17 @@ impl<'a, A: hil::adc::Adc<'a> + hil::adc::AdcHighSpeed<'a>> AdcDedicated<'a, A>
             self.apps
                 .enter(*id, |app, _| {
                     app.app_buf_offset.set(0);
+                    app.using_app_buf0.set(true);
                     self.channel.set(channel);
-                    // start a continuous sample
+                    // Start a continuous sample. The ADC always fills whole
+                    // internal buffers, which are copied to the app buffers
+                    // as they come in and handed straight back to the ADC.
                     self.adc_buf1.take().map_or(Err(ErrorCode::BUSY), |buf1| {
                         self.adc_buf2
                             .take()
                             .map_or(Err(ErrorCode::BUSY), move |buf2| {
-                                // determine request lengths
-                                let samples_needed = app_buf_length / 2;
-                                let next_samples_needed = next_app_buf_length / 2;
-
-                                // determine request lengths
-                                let len1;
-                                let len2;
-                                if samples_needed <= buf1.len() {
-                                    // we can fit the entire app_buffer request in the first
-                                    // buffer. The second buffer will be used for the next
-                                    // app_buffer
-                                    len1 = samples_needed;
-                                    len2 = cmp::min(next_samples_needed, buf2.len());
-                                    app.samples_remaining.set(0);
-                                    app.samples_outstanding.set(len1);
-                                } else if samples_needed <= (buf1.len() + buf2.len()) {
-                                    // we can fit the entire app_buffer request between the two
-                                    // buffers
-                                    len1 = buf1.len();
-                                    len2 = samples_needed - buf1.len();
-                                    app.samples_remaining.set(0);
-                                    app.samples_outstanding.set(len1 + len2);
-                                } else {
-                                    // the app_buffer is larger than both buffers, so just
-                                    // request max lengths
-                                    len1 = buf1.len();
-                                    len2 = buf2.len();
-                                    app.samples_remaining.set(samples_needed - len1 - len2);
-                                    app.samples_outstanding.set(len1 + len2);
-                                }
-
-                                // begin sampling
-                                app.using_app_buf0.set(true);
+                                let len1 = buf1.len();
+                                let len2 = buf2.len();
                                 self.adc
                                     .sample_highspeed(&chan, frequency, buf1, len1, buf2, len2)
                                     .map_or_else(
@@ -557,24 +529,103 @@ impl<'a, A: hil::adc::Adc<'a> + hil::adc::AdcHighSpeed<'a>> AdcDedicated<'a, A>
             // failure, clear state
             self.active.set(false);
             self.mode.set(AdcMode::NoMode);
-            self.processid.map(|id| {
-                self.apps
-                    .enter(*id, |app, _| {
-                        app.samples_remaining.set(0);
-                        app.samples_outstanding.set(0);
-                    })
-                    .map_err(|err| {
-                        if err == kernel::process::Error::NoSuchApp
-                            || err == kernel::process::Error::InactiveApp
-                        {
-                            self.processid.clear();
-                        }
-                    })
-            });
         }
         ret
     }
 
+    /// Copy samples from a continuous sampling operation to the application
+    /// buffers and hand the internal buffer back to the ADC.
+    ///
+    /// Returns `false` if the application can no longer receive samples.
+    ///
+    /// - `buffer_with_samples` - internal buffer filled with analog samples
+    /// - `length` - number of valid samples in the buffer
+    fn continuous_samples_ready(
+        &self,
+        buffer_with_samples: &TakeCell<'static, [u16]>,
+        length: usize,
+    ) -> bool {
+        self.processid.map_or(false, |id| {
+            self.apps
+                .enter(*id, |app, kernel_data| {
+                    let mut copied = 0;
+                    while copied < length {
+                        let use0 = app.using_app_buf0.get();
+                        let app_buf =
+                            match kernel_data.get_readwrite_processbuffer(if use0 { 0 } else { 1 })
+                            {
+                                Ok(buf) => buf,
+                                Err(_) => return false,
+                            };
+
+                        // the app may have swapped or revoked the buffer
+                        let capacity = app_buf.len() / 2;
+                        let offset = app.app_buf_offset.get() / 2;
+                        if offset >= capacity {
+                            return false;
+                        }
+                        let count = cmp::min(capacity - offset, length - copied);
+
+                        let _ = app_buf.mut_enter(|app_buf| {
+                            buffer_with_samples.map(|adc_buf| {
+                                for (chunk, &sample) in app_buf
+                                    .chunks(2)
+                                    .skip(offset)
+                                    .zip(adc_buf[copied..copied + count].iter())
+                                {
+                                    let mut val = sample;
+                                    for byte in chunk.iter() {
+                                        byte.set((val & 0xFF) as u8);
+                                        val >>= 8;
+                                    }
+                                }
+                            });
+                        });
+                        copied += count;
+                        app.app_buf_offset.set((offset + count) * 2);
+
+                        if offset + count == capacity {
+                            // the app buffer is full, notify the app and
+                            // continue with the other one
+                            let len_chan = (capacity << 8) | (self.channel.get() & 0xFF);
+                            kernel_data
+                                .schedule_upcall(
+                                    0,
+                                    (
+                                        AdcMode::ContinuousBuffer as usize,
+                                        len_chan,
+                                        app_buf.ptr() as usize,
+                                    ),
+                                )
+                                .ok();
+                            app.using_app_buf0.set(!use0);
+                            app.app_buf_offset.set(0);
+                        }
+                    }
+
+                    // keep the ADC sampling into a full internal buffer
+                    self.take_and_map_buffer(|adc_buf| {
+                        let request_len = adc_buf.len();
+                        let _ =
+                            self.adc
+                                .provide_buffer(adc_buf, request_len)
+                                .map_err(|(_, buf)| {
+                                    self.replace_buffer(buf);
+                                });
+                    });
+                    true
+                })
+                .map_err(|err| {
+                    if err == kernel::process::Error::NoSuchApp
+                        || err == kernel::process::Error::InactiveApp
+                    {
+                        self.processid.clear();
+                    }
+                })
+                .unwrap_or(false)
+        })
+    }
+
     /// Stops sampling the ADC.
     ///
     /// Any active operation by the ADC is canceled. No additional callbacks
@@ -818,9 +869,9 @@ impl<'a, A: hil::adc::Adc<'a> + hil::adc::AdcHighSpeed<'a>> hil::adc::HighSpeedC
     /// Internal buffer has filled from a buffered sampling operation.
     /// Copies data over to application buffer, determines if more data is
     /// needed, and performs a callback to the application if ready. If
-    /// continuously sampling, also swaps application buffers and continues
-    /// sampling when necessary. If only filling a single buffer, stops
-    /// sampling operation when the application buffer is full.
+    /// continuously sampling, also swaps application buffers when one is
+    /// full. If only filling a single buffer, stops sampling operation when
+    /// the application buffer is full.
     ///
     /// - `buf` - internal buffer filled with analog samples
     /// - `length` - number of valid samples in the buffer, guaranteed to be
@@ -834,10 +885,9 @@ impl<'a, A: hil::adc::Adc<'a> + hil::adc::AdcHighSpeed<'a>> hil::adc::HighSpeedC
         let buffer_with_samples = self.replace_buffer(buf);
 
         // do we expect a buffer?
-        if self.active.get()
-            && (self.mode.get() == AdcMode::SingleBuffer
-                || self.mode.get() == AdcMode::ContinuousBuffer)
-        {
+        if self.active.get() && self.mode.get() == AdcMode::ContinuousBuffer {
+            unexpected_state = !self.continuous_samples_ready(buffer_with_samples, length);
+        } else if self.active.get() && self.mode.get() == AdcMode::SingleBuffer {
             // we did expect a buffer. Determine the current application state
             self.processid.map(|id| {
                 self.apps
@@ -852,18 +902,8 @@ impl<'a, A: hil::adc::Adc<'a> + hil::adc::AdcHighSpeed<'a>> hil::adc::HighSpeedC
                             Ok(buf) => buf,
                             Err(_) => return,
                         };
-                        // determine which app buffer to copy data into and which is
-                        // next up if we're in continuous mode
+                        // determine which app buffer to copy data into
                         let use0 = app.using_app_buf0.get();
-                        let next_app_buf;
-                        let app_buf_ref;
-                        if use0 {
-                            app_buf_ref = &app_buf0;
-                            next_app_buf = &app_buf1;
-                        } else {
-                            app_buf_ref = &app_buf1;
-                            next_app_buf = &app_buf0;
-                        }
 
                         // update count of outstanding sample requests
                         app.samples_outstanding
@@ -871,121 +911,13 @@ impl<'a, A: hil::adc::Adc<'a> + hil::adc::AdcHighSpeed<'a>> hil::adc::HighSpeedC
 
                         // provide a new buffer and length request to the ADC if
                         // necessary. If we haven't received enough samples for the
-                        // current app_buffer, we may need to place more requests. If we
-                        // have received enough, but are in continuous mode, we should
-                        // place a request for the next app_buffer. This is all
-                        // unfortunately made more complicated by the fact that there is
-                        // always one outstanding request to the ADC.
+                        // app_buffer, we may need to place more requests.
                         let perform_callback;
                         if app.samples_remaining.get() == 0 {
                             // we have already placed outstanding requests for all the
-                            // samples needed to fill the current app_buffer
-
-                            if app.samples_outstanding.get() == 0 {
-                                // and the samples we just received are the last ones
-                                // we need
-                                perform_callback = true;
-
-                                if self.mode.get() == AdcMode::ContinuousBuffer {
-                                    // it's time to switch to the next app_buffer, but
-                                    // there's already an outstanding request to the ADC
-                                    // for the next app_buffer that was placed last
-                                    // time, so we need to account for that
-                                    let samples_needed =
-                                        next_app_buf.enter(|buf| buf.len() / 2).unwrap_or(0);
-                                    app.samples_remaining
-                                        .set(samples_needed - app.next_samples_outstanding.get());
-                                    app.samples_outstanding
-                                        .set(app.next_samples_outstanding.get());
-                                    app.using_app_buf0.set(!app.using_app_buf0.get());
-
-                                    // we also need to place our next request, however
-                                    // the outstanding request already placed for the
-                                    // next app_buffer might have completed it! So we
-                                    // have to account for that case
-                                    if app.samples_remaining.get() == 0 {
-                                        // oh boy. We actually need to place a request
-                                        // for the next next app_buffer (which is
-                                        // actually the current app_buf, but try not to
-                                        // think about that...). In practice, this
-                                        // should be a pretty uncommon case to hit, only
-                                        // occurring if the length of the app buffers
-                                        // are smaller than the length of the adc
-                                        // buffers, which is unsustainable at high
-                                        // sampling frequencies
-                                        let next_next_app_buf = &app_buf_ref;
-
-                                        // provide a new buffer. However, we cannot
-                                        // currently update state since the next
-                                        // app_buffer still has a request outstanding.
-                                        // We'll just make a request and handle the
-                                        // state updating on next callback
-                                        self.take_and_map_buffer(|adc_buf| {
-                                            let samples_needed = next_next_app_buf
-                                                .enter(|buf| buf.len() / 2)
-                                                .unwrap_or(0);
-                                            let request_len =
-                                                cmp::min(samples_needed, adc_buf.len());
-                                            app.next_samples_outstanding.set(request_len);
-                                            let _ = self
-                                                .adc
-                                                .provide_buffer(adc_buf, request_len)
-                                                .map_err(|(_, buf)| {
-                                                    self.replace_buffer(buf);
-                                                });
-                                        });
-                                    } else {
-                                        // okay, we still need more samples for the next
-                                        // app_buffer
-
-                                        // provide a new buffer and update state
-                                        self.take_and_map_buffer(|adc_buf| {
-                                            let request_len = cmp::min(
-                                                app.samples_remaining.get(),
-                                                adc_buf.len(),
-                                            );
-                                            app.samples_remaining
-                                                .set(app.samples_remaining.get() - request_len);
-                                            app.samples_outstanding
-                                                .set(app.samples_outstanding.get() + request_len);
-                                            let _ = self
-                                                .adc
-                                                .provide_buffer(adc_buf, request_len)
-                                                .map_err(|(_, buf)| {
-                                                    self.replace_buffer(buf);
-                                                });
-                                        });
-                                    }
-                                }
-                            } else {
-                                // but there are still outstanding samples for the
-                                // current app_buffer (actually exactly one request, the
-                                // one the ADC is currently acting on)
-                                perform_callback = false;
-
-                                if self.mode.get() == AdcMode::ContinuousBuffer {
-                                    // we're in continuous mode, so we need to start the
-                                    // first request for the next app_buffer
-
-                                    // provide a new buffer. However, we cannot
-                                    // currently update state since the current
-                                    // app_buffer still has a request outstanding. We'll
-                                    // just make a request and handle the state updating
-                                    // on next callback
-                                    self.take_and_map_buffer(|adc_buf| {
-                                        let samples_needed =
-                                            next_app_buf.enter(|buf| buf.len() / 2).unwrap_or(0);
-                                        let request_len = cmp::min(samples_needed, adc_buf.len());
-                                        app.next_samples_outstanding.set(request_len);
-                                        let _ = self
-                                            .adc
-                                            .provide_buffer(adc_buf, request_len)
-                                            .map_err(|(_, buf)| {
-                                                self.replace_buffer(buf);
-                                            });
-                                    });
-                                }
-                            }
+                            // samples needed to fill the app_buffer, so we are done
+                            // once the samples we just received are the last ones
+                            perform_callback = app.samples_outstanding.get() == 0;
                         } else {
                             // we need to get more samples for the current app_buffer
                             perform_callback = false;
@@ -1070,29 +1002,22 @@ impl<'a, A: hil::adc::Adc<'a> + hil::adc::AdcHighSpeed<'a>> hil::adc::HighSpeedC
                                 )
                                 .ok();
 
-                            // if the mode is SingleBuffer, the operation is
-                            // complete. Clean up state
-                            if self.mode.get() == AdcMode::SingleBuffer {
-                                self.active.set(false);
-                                self.mode.set(AdcMode::NoMode);
-                                app.app_buf_offset.set(0);
+                            // the operation is complete. Clean up state
+                            self.active.set(false);
+                            self.mode.set(AdcMode::NoMode);
+                            app.app_buf_offset.set(0);
 
-                                // need to actually stop sampling
-                                let _ = self.adc.stop_sampling();
+                            // need to actually stop sampling
+                            let _ = self.adc.stop_sampling();
 
-                                // reclaim buffers and store them
-                                if let Ok((buf1, buf2)) = self.adc.retrieve_buffers() {
-                                    buf1.map(|buf| {
-                                        self.replace_buffer(buf);
-                                    });
-                                    buf2.map(|buf| {
-                                        self.replace_buffer(buf);
-                                    });
+                            // reclaim buffers and store them
+                            if let Ok((buf1, buf2)) = self.adc.retrieve_buffers() {
+                                if let Some(buf) = buf1 {
+                                    self.replace_buffer(buf);
+                                }
+                                if let Some(buf) = buf2 {
+                                    self.replace_buffer(buf);
                                 }
-                            } else {
-                                // if the mode is ContinuousBuffer, we've just
-                                // switched app buffers. Reset our offset to zero
-                                app.app_buf_offset.set(0);
                             }
                         }
                     })
@@ -1143,6 +1068,25 @@ impl<'a, A: hil::adc::Adc<'a> + hil::adc::AdcHighSpeed<'a>> hil::adc::HighSpeedC
             }
         }
     }
+
+    /// The ADC dropped samples because it had no buffer to sample into.
+    ///
+    /// When continuously sampling, tells the application that the samples in
+    /// the application buffer are not contiguous.
+    fn overrun(&self) {
+        if self.active.get() && self.mode.get() == AdcMode::ContinuousBuffer {
+            self.processid.map(|id| {
+                let _ = self.apps.enter(*id, |_, kernel_data| {
+                    kernel_data
+                        .schedule_upcall(
+                            0,
+                            (AdcMode::BufferOverrun as usize, self.channel.get(), 0),
+                        )
+                        .ok();
+                });
+            });
+        }
+    }
 }
 
 /// Implementations of application syscalls
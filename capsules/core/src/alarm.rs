@@ -4,11 +4,19 @@
 
 //! Tock syscall driver capsule for Alarms, which issue callbacks when
 //! a point in time has been reached.
+//!
+//! The driver extends the hardware counter to 64 bits in software by
+//! counting its wraparounds, so that processes can also set alarms further
+//! in the future than the counter wraps (commands 7 to 9). Wraparounds are
+//! only seen while the underlying alarm is armed: the driver never arms it
+//! further than half the counter range ahead, and while a process that used
+//! the 64-bit clock is alive it keeps the alarm armed so the clock stays
+//! correct.
 
 use core::cell::Cell;
 
 use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
-use kernel::hil::time::{self, Alarm, Frequency, Ticks, Ticks32};
+use kernel::hil::time::{self, Alarm, Frequency, Ticks};
 use kernel::syscall::{CommandReturn, SyscallDriver};
 use kernel::{ErrorCode, ProcessId};
 
@@ -16,15 +24,19 @@ use kernel::{ErrorCode, ProcessId};
 use crate::driver;
 pub const DRIVER_NUM: usize = driver::NUM::Alarm as usize;
 
+/// Expiration of an alarm, in ticks of the 64-bit extended clock.
 #[derive(Copy, Clone, Debug)]
 enum Expiration {
     Disabled,
-    Enabled { reference: u32, dt: u32 },
+    Enabled { reference: u64, dt: u64 },
 }
 
 #[derive(Copy, Clone)]
 pub struct AlarmData {
     expiration: Expiration,
+    /// Whether the process used the 64-bit clock, so wraparounds must be
+    /// counted even without armed alarms.
+    extended: bool,
 }
 
 const ALARM_CALLBACK_NUM: usize = 0;
@@ -34,15 +46,16 @@ impl Default for AlarmData {
     fn default() -> AlarmData {
         AlarmData {
             expiration: Expiration::Disabled,
+            extended: false,
         }
     }
 }
 
 pub struct AlarmDriver<'a, A: Alarm<'a>> {
     alarm: &'a A,
-    num_armed: Cell<usize>,
     app_alarms: Grant<AlarmData, UpcallCount<NUM_UPCALLS>, AllowRoCount<0>, AllowRwCount<0>>,
-    next_alarm: Cell<Expiration>,
+    /// The 64-bit extended clock when the counter was last read.
+    last_now: Cell<u64>,
 }
 
 impl<'a, A: Alarm<'a>> AlarmDriver<'a, A> {
@@ -52,105 +65,65 @@ impl<'a, A: Alarm<'a>> AlarmDriver<'a, A> {
     ) -> AlarmDriver<'a, A> {
         AlarmDriver {
             alarm: alarm,
-            num_armed: Cell::new(0),
             app_alarms: grant,
-            next_alarm: Cell::new(Expiration::Disabled),
+            last_now: Cell::new(0),
         }
     }
 
-    // This logic is tricky because it needs to handle the case when the
-    // underlying alarm is wider than 32 bits.
-    fn reset_active_alarm(&self) {
-        let mut earliest_alarm = Expiration::Disabled;
-        let mut earliest_end: A::Ticks = A::Ticks::from(0);
-        // Scale now down to a u32 since that is the width of the alarm;
-        // otherwise for larger widths (e.g., u64) now can be outside of
-        // the range of what an alarm can be set to.
-        let now = self.alarm.now();
-        let now_lower_bits = A::Ticks::from(now.into_u32());
-        // Find the first alarm to fire and store it in earliest_alarm,
-        // its counter value at earliest_end. In the case that there
-        // are multiple alarms in the past, just store one of them
-        // and resolve ordering later, when we fire.
-        for alarm in self.app_alarms.iter() {
-            alarm.enter(|alarm, _upcalls| match alarm.expiration {
-                Expiration::Enabled { reference, dt } => {
-                    // Do this because `reference` shadowed below
-                    let current_reference = reference;
-                    let current_reference_ticks = A::Ticks::from(current_reference);
-                    let current_dt = dt;
-                    let current_dt_ticks = A::Ticks::from(current_dt);
-                    let current_end_ticks = current_reference_ticks.wrapping_add(current_dt_ticks);
+    /// Mask of the counter values processes see with the 32-bit commands:
+    /// the width of the counter, but at most 32 bits.
+    fn user_mask() -> u64 {
+        A::Ticks::max_value().into_u64().min(u32::MAX as u64)
+    }
 
-                    earliest_alarm = match earliest_alarm {
-                        Expiration::Disabled => {
-                            earliest_end = current_end_ticks;
-                            alarm.expiration
-                        }
-                        Expiration::Enabled { reference, dt } => {
-                            // There are two cases when current might be
-                            // an earlier alarm.  The first is if it
-                            // fires inside the interval (reference,
-                            // reference+dt) of the existing earliest.
-                            // The second is if now is not within the
-                            // interval: this means that it has
-                            // passed. It could be the earliest has passed
-                            // too, but at this point we don't need to track
-                            // which is earlier: the key point is that
-                            // the alarm must fire immediately, and then when
-                            // we handle the alarm callback the userspace
-                            // callbacks will all be pushed onto processes.
-                            // Because there is at most a single callback per
-                            // process and they must go through the scheduler
-                            // we don't care about the order in which we push
-                            // their callbacks, as their order of execution is
-                            // determined by the scheduler not push order. -pal
-                            let temp_earliest_reference = A::Ticks::from(reference);
-                            let temp_earliest_dt = A::Ticks::from(dt);
-                            let temp_earliest_end =
-                                temp_earliest_reference.wrapping_add(temp_earliest_dt);
+    /// Read the counter and extend it to 64 bits, assuming it wrapped at
+    /// most once since it was last read.
+    fn now(&self) -> u64 {
+        let mask = A::Ticks::max_value().into_u64();
+        let last = self.last_now.get();
+        let elapsed = self.alarm.now().into_u64().wrapping_sub(last) & mask;
+        let now = last.wrapping_add(elapsed);
+        self.last_now.set(now);
+        now
+    }
 
-                            if current_end_ticks
-                                .within_range(temp_earliest_reference, temp_earliest_end)
-                            {
-                                earliest_end = current_end_ticks;
-                                alarm.expiration
-                            } else if !now_lower_bits
-                                .within_range(temp_earliest_reference, temp_earliest_end)
-                            {
-                                earliest_end = temp_earliest_end;
-                                alarm.expiration
-                            } else {
-                                earliest_alarm
-                            }
-                        }
-                    }
+    /// Extend a counter value a process passed, which must be in the past,
+    /// to the 64-bit clock.
+    fn extend_past(&self, now: u64, value: u32) -> u64 {
+        let mask = Self::user_mask();
+        now.saturating_sub(now.wrapping_sub(value as u64) & mask)
+    }
+
+    /// Arm the underlying alarm for the earliest expiration, but no further
+    /// than half the counter range ahead so that wraparounds are seen.
+    /// Disarm it if no alarm is set and no process uses the 64-bit clock.
+    fn reset_active_alarm(&self) {
+        let now = self.now();
+        let mut earliest_end: Option<u64> = None;
+        let mut extended = false;
+        for alarm in self.app_alarms.iter() {
+            alarm.enter(|alarm, _upcalls| {
+                extended |= alarm.extended;
+                if let Expiration::Enabled { reference, dt } = alarm.expiration {
+                    let end = reference.saturating_add(dt);
+                    earliest_end = Some(earliest_end.map_or(end, |earliest| earliest.min(end)));
                 }
-                Expiration::Disabled => {}
             });
         }
-        self.next_alarm.set(earliest_alarm);
-        match earliest_alarm {
-            Expiration::Disabled => {
+
+        let half_range = A::Ticks::half_max_value().into_u64();
+        let dt = match earliest_end {
+            Some(end) => end.saturating_sub(now).min(half_range),
+            None if extended => half_range,
+            None => {
                 let _ = self.alarm.disarm();
+                return;
             }
-            Expiration::Enabled { reference, dt } => {
-                // This logic handles when the underlying Alarm is wider than
-                // 32 bits; it sets the reference to include the high bits of now
-                let mut high_bits = now.wrapping_sub(now_lower_bits);
-                // If lower bits have wrapped around from reference, this means the
-                // reference's high bits are actually one less; if we don't subtract
-                // one then the alarm will incorrectly be set 1<<32 higher than it should.
-                // This uses the invariant that reference <= now.
-                if now_lower_bits.into_u32() < reference {
-                    // Build 1<<32 in a way that just overflows to 0 if we are 32 bits
-                    let bit33 = A::Ticks::from(0xffffffff).wrapping_add(A::Ticks::from(0x1));
-                    high_bits = high_bits.wrapping_sub(bit33);
-                }
-                let real_reference = high_bits.wrapping_add(A::Ticks::from(reference));
-                self.alarm.set_alarm(real_reference, A::Ticks::from(dt));
-            }
-        }
+        };
+        self.alarm.set_alarm(
+            A::Ticks::from_or_max(now & A::Ticks::max_value().into_u64()),
+            A::Ticks::from_or_max(dt),
+        );
     }
 }
 
@@ -165,6 +138,13 @@ impl<'a, A: Alarm<'a>> SyscallDriver for AlarmDriver<'a, A> {
     /// - `3`: Stop the alarm if it is outstanding
     /// - `4`: Set an alarm to fire at a given clock value `time`.
     /// - `5`: Set an alarm to fire at a given clock value `time` relative to `now` (EXPERIMENTAL).
+    /// - `6`: Set an alarm to fire `data2` ticks after the reference clock
+    ///   value `data`.
+    /// - `7`: Read the current value of the 64-bit clock.
+    /// - `8`: Set an alarm to fire after the 64-bit number of ticks in
+    ///   `data` (lower 32 bits) and `data2` (upper 32 bits).
+    /// - `9`: Set an alarm to fire at the 64-bit clock value in `data`
+    ///   (lower 32 bits) and `data2` (upper 32 bits).
     fn command(
         &self,
         cmd_type: usize,
@@ -172,28 +152,19 @@ impl<'a, A: Alarm<'a>> SyscallDriver for AlarmDriver<'a, A> {
         data2: usize,
         caller_id: ProcessId,
     ) -> CommandReturn {
+        let data64 = (data as u32 as u64) | ((data2 as u32 as u64) << 32);
         // Returns the error code to return to the user and whether we need to
-        // reset which is the next active alarm. We _don't_ reset if
-        //   - we're disabling the underlying alarm anyway,
-        //   - the underlying alarm is currently disabled and we're enabling the first alarm, or
-        //   - on an error (i.e. no change to the alarms).
+        // reset which is the next active alarm. We _don't_ reset on an error
+        // (i.e. no change to the alarms).
         self.app_alarms
             .enter(caller_id, |td, _upcalls| {
                 // helper function to rearm alarm
-                let mut rearm = |reference: usize, dt: usize| {
-                    if let Expiration::Disabled = td.expiration {
-                        self.num_armed.set(self.num_armed.get() + 1);
-                    }
-                    td.expiration = Expiration::Enabled {
-                        reference: reference as u32,
-                        dt: dt as u32,
-                    };
-                    (
-                        CommandReturn::success_u32(reference.wrapping_add(dt) as u32),
-                        true,
-                    )
+                let mut rearm = |reference: u64, dt: u64| {
+                    td.expiration = Expiration::Enabled { reference, dt };
+                    reference.saturating_add(dt)
                 };
-                let now = self.alarm.now();
+                let now = self.now();
+                let user_mask = Self::user_mask();
                 match cmd_type {
                     0 /* check if present */ => (CommandReturn::success(), false),
                     1 /* Get clock frequency */ => {
@@ -201,7 +172,7 @@ impl<'a, A: Alarm<'a>> SyscallDriver for AlarmDriver<'a, A> {
                         (CommandReturn::success_u32(freq), false)
                     },
                     2 /* capture time */ => {
-                        (CommandReturn::success_u32(now.into_u32()), false)
+                        (CommandReturn::success_u32((now & user_mask) as u32), false)
                     },
                     3 /* Stop */ => {
                         match td.expiration {
@@ -211,8 +182,6 @@ impl<'a, A: Alarm<'a>> SyscallDriver for AlarmDriver<'a, A> {
                             },
                             _ => {
                                 td.expiration = Expiration::Disabled;
-                                let new_num_armed = self.num_armed.get() - 1;
-                                self.num_armed.set(new_num_armed);
                                 (CommandReturn::success(), true)
                             }
                         }
@@ -221,15 +190,27 @@ impl<'a, A: Alarm<'a>> SyscallDriver for AlarmDriver<'a, A> {
                         (CommandReturn::failure(ErrorCode::NOSUPPORT), false)
                     },
                     5 /* Set relative expiration */ => {
-                        let reference = now.into_u32() as usize;
-                        let dt = data;
-                        // if previously unarmed, but now will become armed
-                        rearm(reference, dt)
+                        let end = rearm(now, data as u32 as u64);
+                        (CommandReturn::success_u32((end & user_mask) as u32), true)
                     },
                     6 /* Set absolute expiration with reference point */ => {
-                        let reference = data;
-                        let dt = data2;
-                        rearm(reference, dt)
+                        let reference = self.extend_past(now, data as u32);
+                        let end = rearm(reference, data2 as u32 as u64);
+                        (CommandReturn::success_u32((end & user_mask) as u32), true)
+                    }
+                    7 /* capture 64-bit time */ => {
+                        td.extended = true;
+                        (CommandReturn::success_u64(now), true)
+                    }
+                    8 /* Set 64-bit relative expiration */ => {
+                        let end = rearm(now, data64);
+                        td.extended = true;
+                        (CommandReturn::success_u64(end), true)
+                    }
+                    9 /* Set 64-bit absolute expiration */ => {
+                        rearm(now, data64.saturating_sub(now));
+                        td.extended = true;
+                        (CommandReturn::success_u64(data64), true)
                     }
                     _ => (CommandReturn::failure(ErrorCode::NOSUPPORT), false)
                 }
@@ -252,38 +233,25 @@ impl<'a, A: Alarm<'a>> SyscallDriver for AlarmDriver<'a, A> {
 
 impl<'a, A: Alarm<'a>> time::AlarmClient for AlarmDriver<'a, A> {
     fn alarm(&self) {
-        let now: Ticks32 = Ticks32::from(self.alarm.now().into_u32());
+        let now = self.now();
+        let user_mask = Self::user_mask();
         self.app_alarms.each(|_processid, alarm, upcalls| {
             if let Expiration::Enabled { reference, dt } = alarm.expiration {
-                // Now is not within reference, reference + ticks; this timer
-                // as passed (since reference must be in the past)
-                if !now.within_range(
-                    Ticks32::from(reference),
-                    Ticks32::from(reference.wrapping_add(dt)),
-                ) {
+                let end = reference.saturating_add(dt);
+                if now >= end {
                     alarm.expiration = Expiration::Disabled;
-                    self.num_armed.set(self.num_armed.get() - 1);
                     upcalls
                         .schedule_upcall(
                             ALARM_CALLBACK_NUM,
-                            (
-                                now.into_u32() as usize,
-                                reference.wrapping_add(dt) as usize,
-                                0,
-                            ),
+                            ((now & user_mask) as usize, (end & user_mask) as usize, 0),
                         )
                         .ok();
                 }
             }
         });
 
-        // If there are no armed alarms left, skip checking and just disable.
-        // Otherwise, check all the alarms and find the next one, rescheduling
-        // the underlying alarm.
-        if self.num_armed.get() == 0 {
-            let _ = self.alarm.disarm();
-        } else {
-            self.reset_active_alarm();
-        }
+        // Rearm the underlying alarm for the next expiration, or for the
+        // next check of the 64-bit clock, or disable it.
+        self.reset_active_alarm();
     }
 }
@@ -0,0 +1,304 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Bus transaction tracing for the I2C and SPI virtualizers.
+//!
+//! `BusTraceBuffer` acts as a software logic analyzer: when attached to a
+//! `MuxI2C` or `MuxSpiMaster`, it records every transaction the mux issues
+//! (bus, address, direction, the first `TRACE_DATA_LEN` bytes written and
+//! read, completion status and duration) into a ring buffer. When the ring
+//! is full, the oldest transaction is overwritten.
+//!
+//! The trace is dumped from the process console with the `bustrace`
+//! command, see `ProcessConsole::set_bus_trace()`.
+//!
+//! For I2C and SMBus, the address is the 7-bit device address. SPI devices
+//! have no address, so the index of the virtual device on the mux is
+//! recorded instead.
+//!
+//! Only one transaction per bus kind is tracked in flight, so a single
+//! buffer should be attached to at most one mux of each kind.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let trace = static_init!(
+//!     capsules_core::bus_trace::BusTraceBuffer<'static, VirtualMuxAlarm<'static, Rtc>>,
+//!     capsules_core::bus_trace::BusTraceBuffer::new(
+//!         alarm,
+//!         static_init!([capsules_core::bus_trace::Transaction; 32], [Default::default(); 32])
+//!     )
+//! );
+//! mux_i2c.set_tracer(trace);
+//! mux_spi.set_tracer(trace);
+//! process_console.set_bus_trace(trace);
+//! ```
+
+use core::cell::Cell;
+
+use kernel::hil::i2c;
+use kernel::hil::time::{ConvertTicks, Ticks, Time};
+use kernel::utilities::cells::TakeCell;
+use kernel::ErrorCode;
+
+/// Number of bytes of each direction stored per transaction.
+pub const TRACE_DATA_LEN: usize = 8;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Bus {
+    I2C,
+    SMBus,
+    Spi,
+}
+
+impl Bus {
+    fn index(&self) -> usize {
+        match self {
+            Bus::I2C => 0,
+            Bus::SMBus => 1,
+            Bus::Spi => 2,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Write,
+    Read,
+    WriteRead,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Status {
+    /// The transaction has not completed yet.
+    Pending,
+    Ok,
+    I2C(i2c::Error),
+    Error(ErrorCode),
+}
+
+impl From<Result<(), i2c::Error>> for Status {
+    fn from(result: Result<(), i2c::Error>) -> Status {
+        match result {
+            Ok(()) => Status::Ok,
+            Err(e) => Status::I2C(e),
+        }
+    }
+}
+
+impl From<Result<(), ErrorCode>> for Status {
+    fn from(result: Result<(), ErrorCode>) -> Status {
+        match result {
+            Ok(()) => Status::Ok,
+            Err(e) => Status::Error(e),
+        }
+    }
+}
+
+/// A single recorded bus transaction.
+#[derive(Copy, Clone, Debug)]
+pub struct Transaction {
+    /// Sequence number, incremented for every transaction started.
+    pub seq: u32,
+    pub bus: Bus,
+    pub address: u8,
+    pub direction: Direction,
+    pub write_len: usize,
+    pub read_len: usize,
+    /// First bytes written, valid up to `min(write_len, TRACE_DATA_LEN)`.
+    pub write_data: [u8; TRACE_DATA_LEN],
+    /// First bytes read, valid up to `min(read_len, TRACE_DATA_LEN)`.
+    pub read_data: [u8; TRACE_DATA_LEN],
+    pub status: Status,
+    /// Time from issuing the transaction to its completion.
+    pub duration_us: u32,
+    start: u32,
+}
+
+impl Default for Transaction {
+    fn default() -> Transaction {
+        Transaction {
+            seq: 0,
+            bus: Bus::I2C,
+            address: 0,
+            direction: Direction::Write,
+            write_len: 0,
+            read_len: 0,
+            write_data: [0; TRACE_DATA_LEN],
+            read_data: [0; TRACE_DATA_LEN],
+            status: Status::Pending,
+            duration_us: 0,
+            start: 0,
+        }
+    }
+}
+
+/// Interface used by the bus virtualizers to report transactions.
+pub trait BusTracer {
+    /// A transaction was issued to the hardware. `write` holds the data
+    /// written (for SPI, the whole write buffer).
+    fn start(
+        &self,
+        bus: Bus,
+        address: u8,
+        direction: Direction,
+        write: &[u8],
+        write_len: usize,
+        read_len: usize,
+    );
+
+    /// The transaction in flight on `bus` completed. `read` holds the data
+    /// read, if any. Ignored if no transaction is in flight.
+    fn complete(&self, bus: Bus, read: Option<&[u8]>, status: Status);
+}
+
+/// Interface used by the process console to inspect the trace.
+pub trait BusTraceLog {
+    /// Number of recorded transactions.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Recorded transaction `index`, oldest first.
+    fn entry(&self, index: usize) -> Option<Transaction>;
+
+    /// Drop all recorded transactions.
+    fn clear(&self);
+
+    /// Start or stop recording new transactions.
+    fn set_enabled(&self, enabled: bool);
+
+    fn is_enabled(&self) -> bool;
+}
+
+pub struct BusTraceBuffer<'a, T: Time> {
+    time: &'a T,
+    ring: TakeCell<'a, [Transaction]>,
+    /// Index of the oldest recorded transaction.
+    head: Cell<usize>,
+    len: Cell<usize>,
+    in_flight: [Cell<Option<Transaction>>; 3],
+    seq: Cell<u32>,
+    enabled: Cell<bool>,
+}
+
+impl<'a, T: Time> BusTraceBuffer<'a, T> {
+    pub fn new(time: &'a T, ring: &'a mut [Transaction]) -> BusTraceBuffer<'a, T> {
+        BusTraceBuffer {
+            time,
+            ring: TakeCell::new(ring),
+            head: Cell::new(0),
+            len: Cell::new(0),
+            in_flight: [Cell::new(None), Cell::new(None), Cell::new(None)],
+            seq: Cell::new(0),
+            enabled: Cell::new(true),
+        }
+    }
+
+    fn record(&self, transaction: Transaction) {
+        self.ring.map(|ring| {
+            if ring.is_empty() {
+                return;
+            }
+            let capacity = ring.len();
+            let len = self.len.get();
+            if len < capacity {
+                ring[(self.head.get() + len) % capacity] = transaction;
+                self.len.set(len + 1);
+            } else {
+                // Full, overwrite the oldest transaction.
+                ring[self.head.get()] = transaction;
+                self.head.set((self.head.get() + 1) % capacity);
+            }
+        });
+    }
+}
+
+impl<'a, T: Time> BusTracer for BusTraceBuffer<'a, T> {
+    fn start(
+        &self,
+        bus: Bus,
+        address: u8,
+        direction: Direction,
+        write: &[u8],
+        write_len: usize,
+        read_len: usize,
+    ) {
+        if !self.enabled.get() {
+            return;
+        }
+        let mut transaction = Transaction {
+            seq: self.seq.get(),
+            bus,
+            address,
+            direction,
+            write_len,
+            read_len,
+            start: self.time.now().into_u32(),
+            ..Default::default()
+        };
+        self.seq.set(self.seq.get().wrapping_add(1));
+        if direction != Direction::Read {
+            let count = core::cmp::min(core::cmp::min(write_len, write.len()), TRACE_DATA_LEN);
+            transaction.write_data[..count].copy_from_slice(&write[..count]);
+        }
+        self.in_flight[bus.index()].set(Some(transaction));
+    }
+
+    fn complete(&self, bus: Bus, read: Option<&[u8]>, status: Status) {
+        if let Some(mut transaction) = self.in_flight[bus.index()].take() {
+            let elapsed = self
+                .time
+                .now()
+                .wrapping_sub(T::Ticks::from(transaction.start));
+            transaction.duration_us = self.time.ticks_to_us(elapsed);
+            transaction.status = status;
+            if transaction.direction != Direction::Write {
+                if let Some(read) = read {
+                    let count = core::cmp::min(
+                        core::cmp::min(transaction.read_len, read.len()),
+                        TRACE_DATA_LEN,
+                    );
+                    transaction.read_data[..count].copy_from_slice(&read[..count]);
+                }
+            }
+            self.record(transaction);
+        }
+    }
+}
+
+impl<'a, T: Time> BusTraceLog for BusTraceBuffer<'a, T> {
+    fn len(&self) -> usize {
+        self.len.get()
+    }
+
+    fn entry(&self, index: usize) -> Option<Transaction> {
+        if index >= self.len.get() {
+            return None;
+        }
+        self.ring
+            .map(|ring| ring[(self.head.get() + index) % ring.len()])
+    }
+
+    fn clear(&self) {
+        self.head.set(0);
+        self.len.set(0);
+    }
+
+    fn set_enabled(&self, enabled: bool) {
+        self.enabled.set(enabled);
+        if !enabled {
+            for in_flight in self.in_flight.iter() {
+                in_flight.set(None);
+            }
+        }
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.enabled.get()
+    }
+}
@@ -25,6 +25,17 @@
 //! }
 //! ```
 //!
+//! Debouncing
+//! ----------
+//!
+//! Mechanical switches bounce, producing a burst of edges for a single
+//! press. A button created with `Button::new_with_debounce()` can filter
+//! these in the kernel: each button has a debounce interval, and an edge is
+//! only reported once the pin has been stable for that long. If the button
+//! bounced back to the state that was last reported, nothing is reported.
+//! Buttons with an interval of zero report every edge immediately, which is
+//! also what a button created with `Button::new()` does.
+//!
 //! Syscall Interface
 //! -----------------
 //!
@@ -42,6 +53,9 @@
 //! - `2`: Disable interrupts for a button. No affect or reliance on
 //!   registered callback.
 //! - `3`: Read the current state of the button.
+//! - `4`: Set the debounce interval of a button in milliseconds. Zero
+//!   disables debouncing for the button. Returns `NOSUPPORT` if the board
+//!   did not provide an alarm for debouncing.
 //!
 //! ### Subscribe
 //!
@@ -60,7 +74,9 @@ use core::cell::Cell;
 use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
 use kernel::hil::gpio;
 use kernel::hil::gpio::{Configure, Input, InterruptWithValue};
+use kernel::hil::time::{self, Alarm, ConvertTicks, Ticks};
 use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::OptionalCell;
 use kernel::{ErrorCode, ProcessId};
 
 /// Syscall driver number.
@@ -80,14 +96,69 @@ pub struct App {
     subscribe_map: u32,
 }
 
+/// Debouncing state of a single button.
+pub struct Debounce<T: Ticks> {
+    /// How long the pin must be stable before an edge is reported, in ms.
+    interval_ms: Cell<u32>,
+    /// The reference and duration of the running debounce interval.
+    expiration: OptionalCell<(T, T)>,
+    /// The state that was last reported to apps.
+    reported: Cell<gpio::ActivationState>,
+}
+
+impl<T: Ticks> Debounce<T> {
+    pub fn new(interval_ms: u32) -> Self {
+        Self {
+            interval_ms: Cell::new(interval_ms),
+            expiration: OptionalCell::empty(),
+            reported: Cell::new(gpio::ActivationState::Inactive),
+        }
+    }
+}
+
+/// Placeholder alarm for buttons that are not debounced. It never fires.
+pub struct NoAlarm;
+
+impl time::Time for NoAlarm {
+    type Frequency = time::Freq1KHz;
+    type Ticks = time::Ticks32;
+
+    fn now(&self) -> Self::Ticks {
+        0.into()
+    }
+}
+
+impl<'a> Alarm<'a> for NoAlarm {
+    fn set_alarm_client(&self, _client: &'a dyn time::AlarmClient) {}
+    fn set_alarm(&self, _reference: Self::Ticks, _dt: Self::Ticks) {}
+    fn get_alarm(&self) -> Self::Ticks {
+        0.into()
+    }
+    fn disarm(&self) -> Result<(), ErrorCode> {
+        Ok(())
+    }
+    fn is_armed(&self) -> bool {
+        false
+    }
+    fn minimum_dt(&self) -> Self::Ticks {
+        0.into()
+    }
+}
+
 /// Manages the list of GPIO pins that are connected to buttons and which apps
 /// are listening for interrupts from which buttons.
-pub struct Button<'a, P: gpio::InterruptPin<'a>> {
+///
+/// `NoAlarm` is a placeholder for `A` on boards that do not debounce their
+/// buttons.
+pub struct Button<'a, P: gpio::InterruptPin<'a>, A: Alarm<'a> = NoAlarm> {
     pins: &'a [(
         &'a gpio::InterruptValueWrapper<'a, P>,
         gpio::ActivationMode,
         gpio::FloatingState,
     )],
+    alarm: Option<&'a A>,
+    /// Debouncing state for each pin, empty if there is no alarm.
+    debounce: &'a [Debounce<A::Ticks>],
     apps: Grant<App, UpcallCount<1>, AllowRoCount<0>, AllowRwCount<0>>,
 }
 
@@ -100,22 +171,108 @@ impl<'a, P: gpio::InterruptPin<'a>> Button<'a, P> {
         )],
         grant: Grant<App, UpcallCount<1>, AllowRoCount<0>, AllowRwCount<0>>,
     ) -> Self {
-        for (i, &(pin, _, floating_state)) in pins.iter().enumerate() {
-            pin.make_input();
-            pin.set_value(i as u32);
-            pin.set_floating_state(floating_state);
+        Self::setup_pins(pins);
+
+        Self {
+            pins,
+            alarm: None,
+            debounce: &[],
+            apps: grant,
         }
+    }
+}
+
+impl<'a, P: gpio::InterruptPin<'a>, A: Alarm<'a>> Button<'a, P, A> {
+    /// Create a button driver that debounces its pins with `alarm`.
+    /// `debounce` holds the state of each pin and must be as long as `pins`.
+    pub fn new_with_debounce(
+        pins: &'a [(
+            &'a gpio::InterruptValueWrapper<'a, P>,
+            gpio::ActivationMode,
+            gpio::FloatingState,
+        )],
+        alarm: &'a A,
+        debounce: &'a [Debounce<A::Ticks>],
+        grant: Grant<App, UpcallCount<1>, AllowRoCount<0>, AllowRwCount<0>>,
+    ) -> Self {
+        assert!(debounce.len() >= pins.len());
+        Self::setup_pins(pins);
 
         Self {
-            pins: pins,
+            pins,
+            alarm: Some(alarm),
+            debounce,
             apps: grant,
         }
     }
 
+    fn setup_pins(
+        pins: &'a [(
+            &'a gpio::InterruptValueWrapper<'a, P>,
+            gpio::ActivationMode,
+            gpio::FloatingState,
+        )],
+    ) {
+        for (i, &(pin, _, floating_state)) in pins.iter().enumerate() {
+            pin.make_input();
+            pin.set_value(i as u32);
+            pin.set_floating_state(floating_state);
+        }
+    }
+
     fn get_button_state(&self, pin_num: u32) -> gpio::ActivationState {
         let pin = &self.pins[pin_num as usize];
         pin.0.read_activation(pin.1)
     }
+
+    /// Arm the alarm for the debounce interval that ends first, if any.
+    fn arm_debounce_alarm(&self, alarm: &A) {
+        let now = alarm.now();
+        let next = self
+            .debounce
+            .iter()
+            .filter_map(|debounce| debounce.expiration.extract())
+            .map(|(reference, dt)| {
+                let end = reference.wrapping_add(dt);
+                if now.within_range(reference, end) {
+                    end.wrapping_sub(now)
+                } else {
+                    0.into()
+                }
+            })
+            .min();
+        match next {
+            Some(dt) => alarm.set_alarm(now, dt),
+            None => {
+                let _ = alarm.disarm();
+            }
+        }
+    }
+
+    /// Report the state of a button to every app listening to it.
+    fn report(&self, pin_num: u32, button_state: gpio::ActivationState) {
+        let interrupt_count = Cell::new(0);
+        if let Some(debounce) = self.debounce.get(pin_num as usize) {
+            debounce.reported.set(button_state);
+        }
+
+        // schedule callback with the pin number and value
+        self.apps.each(|_, cntr, upcalls| {
+            if cntr.subscribe_map & (1 << pin_num) != 0 {
+                interrupt_count.set(interrupt_count.get() + 1);
+                upcalls
+                    .schedule_upcall(UPCALL_NUM, (pin_num as usize, button_state as usize, 0))
+                    .ok();
+            }
+        });
+
+        // It's possible we got an interrupt for a process that has since died
+        // (and didn't unregister the interrupt). Lazily disable interrupts for
+        // this button if so.
+        if interrupt_count.get() == 0 {
+            self.pins[pin_num as usize].0.disable_interrupts();
+        }
+    }
 }
 
 /// ### `subscribe_num`
@@ -127,7 +284,7 @@ impl<'a, P: gpio::InterruptPin<'a>> Button<'a, P> {
 ///   button.
 const UPCALL_NUM: usize = 0;
 
-impl<'a, P: gpio::InterruptPin<'a>> SyscallDriver for Button<'a, P> {
+impl<'a, P: gpio::InterruptPin<'a>, A: Alarm<'a>> SyscallDriver for Button<'a, P, A> {
     /// Configure interrupts and read state for buttons.
     ///
     /// `data` is the index of the button in the button array as passed to
@@ -144,11 +301,13 @@ impl<'a, P: gpio::InterruptPin<'a>> SyscallDriver for Button<'a, P> {
     /// - `2`: Disable interrupts for a button. No affect or reliance on
     ///   registered callback.
     /// - `3`: Read the current state of the button.
+    /// - `4`: Set the debounce interval of a button in milliseconds. Zero
+    ///   disables debouncing for the button.
     fn command(
         &self,
         command_num: usize,
         data: usize,
-        _: usize,
+        interval_ms: usize,
         processid: ProcessId,
     ) -> CommandReturn {
         let pins = self.pins;
@@ -165,6 +324,11 @@ impl<'a, P: gpio::InterruptPin<'a>> SyscallDriver for Button<'a, P> {
                     self.apps
                         .enter(processid, |cntr, _| {
                             cntr.subscribe_map |= 1 << data;
+                            if let Some(debounce) = self.debounce.get(data) {
+                                if debounce.expiration.is_none() {
+                                    debounce.reported.set(self.get_button_state(data as u32));
+                                }
+                            }
                             let _ = pins[data]
                                 .0
                                 .enable_interrupts(gpio::InterruptEdge::EitherEdge);
@@ -216,6 +380,28 @@ impl<'a, P: gpio::InterruptPin<'a>> SyscallDriver for Button<'a, P> {
                 }
             }
 
+            // set the debounce interval of a button
+            4 => {
+                if data >= pins.len() {
+                    CommandReturn::failure(ErrorCode::INVAL) /* impossible button */
+                } else if let Some(alarm) = self.alarm {
+                    let debounce = &self.debounce[data];
+                    debounce.interval_ms.set(interval_ms as u32);
+                    if interval_ms == 0 && debounce.expiration.take().is_some() {
+                        // Report the edge that was being debounced right
+                        // away.
+                        let button_state = self.get_button_state(data as u32);
+                        if button_state != debounce.reported.get() {
+                            self.report(data as u32, button_state);
+                        }
+                        self.arm_debounce_alarm(alarm);
+                    }
+                    CommandReturn::success()
+                } else {
+                    CommandReturn::failure(ErrorCode::NOSUPPORT)
+                }
+            }
+
             // default
             _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
         }
@@ -226,27 +412,48 @@ impl<'a, P: gpio::InterruptPin<'a>> SyscallDriver for Button<'a, P> {
     }
 }
 
-impl<'a, P: gpio::InterruptPin<'a>> gpio::ClientWithValue for Button<'a, P> {
+impl<'a, P: gpio::InterruptPin<'a>, A: Alarm<'a>> gpio::ClientWithValue for Button<'a, P, A> {
     fn fired(&self, pin_num: u32) {
+        if let (Some(alarm), Some(debounce)) = (self.alarm, self.debounce.get(pin_num as usize)) {
+            let interval_ms = debounce.interval_ms.get();
+            if interval_ms > 0 {
+                // Restart the interval on every edge, so the state is only
+                // reported once the pin stopped bouncing.
+                debounce
+                    .expiration
+                    .set((alarm.now(), alarm.ticks_from_ms(interval_ms)));
+                self.arm_debounce_alarm(alarm);
+                return;
+            }
+        }
+
         // Read the value of the pin and get the button state.
         let button_state = self.get_button_state(pin_num);
-        let interrupt_count = Cell::new(0);
+        self.report(pin_num, button_state);
+    }
+}
 
-        // schedule callback with the pin number and value
-        self.apps.each(|_, cntr, upcalls| {
-            if cntr.subscribe_map & (1 << pin_num) != 0 {
-                interrupt_count.set(interrupt_count.get() + 1);
-                upcalls
-                    .schedule_upcall(UPCALL_NUM, (pin_num as usize, button_state as usize, 0))
-                    .ok();
-            }
-        });
+impl<'a, P: gpio::InterruptPin<'a>, A: Alarm<'a>> time::AlarmClient for Button<'a, P, A> {
+    fn alarm(&self) {
+        let alarm = match self.alarm {
+            Some(alarm) => alarm,
+            None => return,
+        };
+        let now = alarm.now();
 
-        // It's possible we got an interrupt for a process that has since died
-        // (and didn't unregister the interrupt). Lazily disable interrupts for
-        // this button if so.
-        if interrupt_count.get() == 0 {
-            self.pins[pin_num as usize].0.disable_interrupts();
+        for (pin_num, debounce) in self.debounce.iter().enumerate().take(self.pins.len()) {
+            let expired = debounce.expiration.map_or(false, |(reference, dt)| {
+                !now.within_range(*reference, reference.wrapping_add(*dt))
+            });
+            if expired {
+                debounce.expiration.clear();
+                let button_state = self.get_button_state(pin_num as u32);
+                if button_state != debounce.reported.get() {
+                    self.report(pin_num as u32, button_state);
+                }
+            }
         }
+
+        self.arm_debounce_alarm(alarm);
     }
 }
@@ -40,10 +40,38 @@
 //! When the buffer has been written successfully, the buffer is released from
 //! the driver. Successive writes must call `allow` each time a buffer is to be
 //! written.
+//!
+//! Cooked Mode
+//! -----------
+//!
+//! By default reads return the raw bytes received. A process can instead
+//! select cooked mode with command 4, in which case a read completes once a
+//! whole line has been entered. In cooked mode the console echoes input back
+//! to the terminal, treats backspace and delete as erasing the last UTF-8
+//! character of the line, ends a line on carriage return, line feed or both,
+//! and ignores other control characters. The line is returned terminated by
+//! a single `\n`. Characters that would not fit in the process's buffer
+//! (leaving room for the `\n`) are dropped whole, so a line never ends in a
+//! partial UTF-8 sequence.
+//!
+//! Input Focus
+//! -----------
+//!
+//! Without an input focus, only one process can read at a time and other
+//! processes get `BUSY` until its read completes. To share one serial link
+//! between several interactive processes, the board (usually through the
+//! process console's `focus` command) can direct input to one process with
+//! the `InputFocus` trait. Reads from other processes are then queued and
+//! start once their process gets the focus. Moving the focus away from a
+//! process that is reading aborts its receive: bytes it already received
+//! are returned to it, while a read without any input, or a cooked mode
+//! line being edited, is queued again until the process has the focus back.
+
+use core::cell::Cell;
 
 use kernel::grant::{AllowRoCount, AllowRwCount, Grant, GrantKernelData, UpcallCount};
 use kernel::hil::uart;
-use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
+use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer, WriteableProcessSlice};
 use kernel::syscall::{CommandReturn, SyscallDriver};
 use kernel::utilities::cells::{OptionalCell, TakeCell};
 use kernel::{ErrorCode, ProcessId};
@@ -56,6 +84,13 @@ pub const DRIVER_NUM: usize = driver::NUM::Console as usize;
 /// Boards may pass different-size buffers if needed.
 pub const DEFAULT_BUF_SIZE: usize = 64;
 
+/// Number of echoed bytes that can be queued in cooked mode while a write is
+/// in progress. Echo beyond this is dropped.
+const ECHO_BUF_LEN: usize = 16;
+
+const BACKSPACE: u8 = 0x08;
+const DELETE: u8 = 0x7F;
+
 /// Ids for read-only allow buffers
 mod ro_allow {
     /// Before the allow syscall was handled by the kernel,
@@ -82,6 +117,31 @@ pub struct App {
     write_remaining: usize, // How many bytes didn't fit in the buffer and still need to be printed.
     pending_write: bool,
     read_len: usize,
+    /// Whether reads use the cooked mode line discipline.
+    cooked: bool,
+    /// Cooked mode: number of bytes of the current line in the read buffer.
+    line_len: usize,
+    /// Cooked mode: continuation bytes still expected for the UTF-8
+    /// character being entered.
+    utf8_expected: u8,
+    /// Cooked mode: continuation bytes of a dropped character to discard.
+    utf8_discard: u8,
+    /// Cooked mode: the last line ended with a carriage return, so a line
+    /// feed that follows it belongs to the same line ending.
+    after_cr: bool,
+    /// A read is waiting for this process to get the input focus.
+    pending_read: bool,
+}
+
+/// Selects which process receives console input, so that several
+/// interactive processes can share a console.
+pub trait InputFocus {
+    /// The process that input is directed to, if any.
+    fn focus(&self) -> Option<ProcessId>;
+
+    /// Direct input to `processid`. With `None`, input goes to whichever
+    /// process reads first.
+    fn set_focus(&self, processid: Option<ProcessId>);
 }
 
 pub struct Console<'a> {
@@ -96,6 +156,10 @@ pub struct Console<'a> {
     tx_buffer: TakeCell<'static, [u8]>,
     rx_in_progress: OptionalCell<ProcessId>,
     rx_buffer: TakeCell<'static, [u8]>,
+    rx_buffer_len: usize,
+    focus: OptionalCell<ProcessId>,
+    echo_buffer: Cell<[u8; ECHO_BUF_LEN]>,
+    echo_len: Cell<usize>,
 }
 
 impl<'a> Console<'a> {
@@ -116,7 +180,11 @@ impl<'a> Console<'a> {
             tx_in_progress: OptionalCell::empty(),
             tx_buffer: TakeCell::new(tx_buffer),
             rx_in_progress: OptionalCell::empty(),
+            rx_buffer_len: rx_buffer.len(),
             rx_buffer: TakeCell::new(rx_buffer),
+            focus: OptionalCell::empty(),
+            echo_buffer: Cell::new([0; ECHO_BUF_LEN]),
+            echo_len: Cell::new(0),
         }
     }
 
@@ -157,7 +225,7 @@ impl<'a> Console<'a> {
     /// Internal helper function for sending data for an existing transaction.
     /// Cannot fail. If can't send now, it will schedule for sending later.
     fn send(&self, processid: ProcessId, app: &mut App, kernel_data: &GrantKernelData) {
-        if self.tx_in_progress.is_none() {
+        if self.tx_in_progress.is_none() && self.tx_buffer.is_some() {
             self.tx_in_progress.set(processid);
             self.tx_buffer.take().map(|buffer| {
                 let transaction_len = kernel_data
@@ -208,9 +276,13 @@ impl<'a> Console<'a> {
         kernel_data: &GrantKernelData,
         len: usize,
     ) -> Result<(), ErrorCode> {
-        if self.rx_buffer.is_none() {
-            // For now, we tolerate only one concurrent receive operation on this console.
-            // Competing apps will have to retry until success.
+        if app.pending_read
+            || self.rx_in_progress.contains(&processid)
+            || (self.focus.is_none() && self.rx_buffer.is_none())
+        {
+            // Without an input focus, we tolerate only one concurrent receive
+            // operation on this console. Competing apps will have to retry
+            // until success.
             return Err(ErrorCode::BUSY);
         }
 
@@ -218,18 +290,247 @@ impl<'a> Console<'a> {
             .get_readwrite_processbuffer(rw_allow::READ)
             .map_or(0, |read| read.len())
             .min(len);
-        if read_len > self.rx_buffer.map_or(0, |buf| buf.len()) {
+        if !app.cooked && read_len > self.rx_buffer_len {
             // For simplicity, impose a small maximum receive length
-            // instead of doing incremental reads
-            Err(ErrorCode::INVAL)
+            // instead of doing incremental reads. Cooked mode edits the line
+            // in place in the process's buffer and receives one byte at a
+            // time, so lines are not limited by the size of our buffer.
+            return Err(ErrorCode::INVAL);
+        }
+
+        app.read_len = read_len;
+        app.line_len = 0;
+        app.utf8_expected = 0;
+        app.utf8_discard = 0;
+        if self.rx_buffer.is_some() && self.may_read(processid) {
+            self.start_receive(processid, app);
         } else {
-            // Note: We have ensured above that rx_buffer is present
-            app.read_len = read_len;
-            self.rx_buffer.take().map(|buffer| {
-                self.rx_in_progress.set(processid);
-                let _ = self.uart.receive_buffer(buffer, app.read_len);
+            app.pending_read = true;
+        }
+        Ok(())
+    }
+
+    /// Whether input can go to `processid`: either it has the input focus or
+    /// no existing process has it.
+    fn may_read(&self, processid: ProcessId) -> bool {
+        self.focus.map_or(true, |&mut focus| {
+            focus == processid || self.apps.enter(focus, |_, _| ()).is_err()
+        })
+    }
+
+    /// Start receiving for a read set up by `receive_new()`.
+    fn start_receive(&self, processid: ProcessId, app: &mut App) {
+        if let Some(buffer) = self.rx_buffer.take() {
+            self.rx_in_progress.set(processid);
+            let len = if app.cooked { 1 } else { app.read_len };
+            let _ = self.uart.receive_buffer(buffer, len);
+        }
+    }
+
+    /// Start a queued read of a process that may now receive input.
+    fn start_pending_read(&self) {
+        if self.rx_buffer.is_none() {
+            return;
+        }
+        for cntr in self.apps.iter() {
+            let processid = cntr.processid();
+            if !self.may_read(processid) {
+                continue;
+            }
+            let started = cntr.enter(|app, _| {
+                if app.pending_read {
+                    app.pending_read = false;
+                    self.start_receive(processid, app);
+                    true
+                } else {
+                    false
+                }
             });
-            Ok(())
+            if started {
+                break;
+            }
+        }
+    }
+}
+
+impl Console<'_> {
+    /// Queue bytes to echo back to the terminal in cooked mode and send them
+    /// if the UART is idle. A sequence that does not fit in the echo queue
+    /// is dropped whole.
+    fn echo(&self, bytes: &[u8]) {
+        let len = self.echo_len.get();
+        if len + bytes.len() <= ECHO_BUF_LEN {
+            let mut queue = self.echo_buffer.get();
+            queue[len..len + bytes.len()].copy_from_slice(bytes);
+            self.echo_buffer.set(queue);
+            self.echo_len.set(len + bytes.len());
+        }
+        self.flush_echo();
+    }
+
+    /// Transmit queued echo bytes if no write is in progress. Returns `true`
+    /// if a transmission was started.
+    fn flush_echo(&self) -> bool {
+        if self.tx_in_progress.is_some() || self.echo_len.get() == 0 {
+            return false;
+        }
+        self.tx_buffer.take().is_some_and(|buffer| {
+            let len = self.echo_len.get().min(buffer.len());
+            buffer[..len].copy_from_slice(&self.echo_buffer.get()[..len]);
+            self.echo_len.set(0);
+            match self.uart.transmit_buffer(buffer, len) {
+                Ok(()) => true,
+                Err((_, buffer)) => {
+                    self.tx_buffer.replace(buffer);
+                    false
+                }
+            }
+        })
+    }
+
+    /// Remove the last character of the line, including all bytes of a
+    /// multi-byte UTF-8 character.
+    fn erase_char(app: &mut App, data: &WriteableProcessSlice) {
+        while app.line_len > 0 {
+            app.line_len -= 1;
+            if data[app.line_len].get() & 0xC0 != 0x80 {
+                break;
+            }
+        }
+        app.utf8_expected = 0;
+    }
+
+    /// Apply the cooked mode line discipline to one received byte. Returns
+    /// `Some` with the result of the read once it is complete.
+    fn cooked_input(
+        &self,
+        app: &mut App,
+        kernel_data: &GrantKernelData,
+        byte: u8,
+    ) -> Option<Result<(), ErrorCode>> {
+        if core::mem::replace(&mut app.after_cr, false) && byte == b'\n' {
+            return None;
+        }
+        kernel_data
+            .get_readwrite_processbuffer(rw_allow::READ)
+            .and_then(|read| {
+                read.mut_enter(|data| {
+                    let size = app.read_len.min(data.len());
+                    // The process may have swapped in a smaller buffer.
+                    app.line_len = app.line_len.min(size);
+                    // Keep room for the line feed that ends the line.
+                    let capacity = size.saturating_sub(1);
+                    match byte {
+                        b'\r' | b'\n' => {
+                            if app.utf8_expected > 0 {
+                                Self::erase_char(app, data);
+                            }
+                            if app.line_len < size {
+                                data[app.line_len].set(b'\n');
+                                app.line_len += 1;
+                            }
+                            app.after_cr = byte == b'\r';
+                            self.echo(b"\r\n");
+                            Some(Ok(()))
+                        }
+                        BACKSPACE | DELETE => {
+                            if app.line_len > 0 {
+                                Self::erase_char(app, data);
+                                self.echo(b"\x08 \x08");
+                            }
+                            None
+                        }
+                        0x00..=0x1F if byte != b'\t' => None,
+                        0x80..=0xBF => {
+                            // UTF-8 continuation byte.
+                            if app.utf8_discard > 0 {
+                                app.utf8_discard -= 1;
+                            } else if app.utf8_expected > 0 {
+                                app.utf8_expected -= 1;
+                                data[app.line_len].set(byte);
+                                app.line_len += 1;
+                                self.echo(&[byte]);
+                            }
+                            None
+                        }
+                        _ => {
+                            // A new character ends any truncated one.
+                            if app.utf8_expected > 0 {
+                                Self::erase_char(app, data);
+                            }
+                            app.utf8_discard = 0;
+                            let width = match byte {
+                                0x00..=0x7F => 1,
+                                0xC2..=0xDF => 2,
+                                0xE0..=0xEF => 3,
+                                0xF0..=0xF4 => 4,
+                                _ => return None,
+                            };
+                            if app.line_len + width > capacity {
+                                app.utf8_discard = width as u8 - 1;
+                            } else {
+                                data[app.line_len].set(byte);
+                                app.line_len += 1;
+                                app.utf8_expected = width as u8 - 1;
+                                self.echo(&[byte]);
+                            }
+                            None
+                        }
+                    }
+                })
+            })
+            .unwrap_or(Some(Err(ErrorCode::NOMEM)))
+    }
+
+    /// Handle a byte received for a process reading in cooked mode, then
+    /// either complete its read or wait for the next byte.
+    fn cooked_received(
+        &self,
+        buffer: &'static mut [u8],
+        rx_len: usize,
+        rcode: Result<(), ErrorCode>,
+        error: uart::Error,
+    ) {
+        let done = self.rx_in_progress.map_or(true, |&mut processid| {
+            self.apps
+                .enter(processid, |app, kernel_data| {
+                    let result = match error {
+                        uart::Error::None if rx_len > 0 => self
+                            .cooked_input(app, kernel_data, buffer[0])
+                            .map(|ret| (ret, if ret.is_ok() { app.line_len } else { 0 })),
+                        uart::Error::None => None,
+                        uart::Error::Aborted => Some((rcode, app.line_len)),
+                        _ => Some((Err(ErrorCode::FAIL), 0)),
+                    };
+                    result.is_some_and(|(ret, len)| {
+                        kernel_data
+                            .schedule_upcall(2, (kernel::errorcode::into_statuscode(ret), len, 0))
+                            .ok();
+                        true
+                    })
+                })
+                .unwrap_or(true)
+        });
+
+        if done {
+            self.rx_in_progress.clear();
+            self.rx_buffer.replace(buffer);
+        } else if let Err((_, buffer)) = self.uart.receive_buffer(buffer, 1) {
+            if let Some(processid) = self.rx_in_progress.take() {
+                let _ = self.apps.enter(processid, |_, kernel_data| {
+                    kernel_data
+                        .schedule_upcall(
+                            2,
+                            (
+                                kernel::errorcode::into_statuscode(Err(ErrorCode::FAIL)),
+                                0,
+                                0,
+                            ),
+                        )
+                        .ok();
+                });
+            }
+            self.rx_buffer.replace(buffer);
         }
     }
 }
@@ -263,8 +564,11 @@ impl SyscallDriver for Console<'_> {
     ///        passed in `arg1`
     /// - `2`: Receives into a buffer passed via `allow`, up to the length
     ///        passed in `arg1`
-    /// - `3`: Cancel any in progress receives and return (via callback)
-    ///        what has been received so far.
+    /// - `3`: Cancel this process's receive, in progress or waiting for the
+    ///        input focus, and return (via callback) what has been received
+    ///        so far.
+    /// - `4`: Select how this process's receives behave: `0` for raw bytes
+    ///        (the default), `1` for cooked mode line input.
     fn command(
         &self,
         cmd_num: usize,
@@ -289,9 +593,42 @@ impl SyscallDriver for Console<'_> {
                     }
                     3 => {
                         // Abort RX
-                        let _ = self.uart.receive_abort();
+                        if app.pending_read {
+                            app.pending_read = false;
+                            let len = if app.cooked { app.line_len } else { 0 };
+                            kernel_data
+                                .schedule_upcall(
+                                    2,
+                                    (
+                                        kernel::errorcode::into_statuscode(Err(ErrorCode::CANCEL)),
+                                        len,
+                                        0,
+                                    ),
+                                )
+                                .ok();
+                        } else if self.rx_in_progress.contains(&processid) {
+                            let _ = self.uart.receive_abort();
+                        }
                         Ok(())
                     }
+                    4 => {
+                        // Set line discipline
+                        if app.pending_read || self.rx_in_progress.contains(&processid) {
+                            Err(ErrorCode::BUSY)
+                        } else {
+                            match arg1 {
+                                0 => {
+                                    app.cooked = false;
+                                    Ok(())
+                                }
+                                1 => {
+                                    app.cooked = true;
+                                    Ok(())
+                                }
+                                _ => Err(ErrorCode::INVAL),
+                            }
+                        }
+                    }
                     _ => Err(ErrorCode::NOSUPPORT),
                 }
             })
@@ -334,9 +671,10 @@ impl uart::TransmitClient for Console<'_> {
             })
         });
 
-        // If we are not printing more from the current AppSlice,
-        // see if any other applications have pending messages.
-        if self.tx_in_progress.is_none() {
+        // If we are not printing more from the current AppSlice, echo any
+        // cooked mode input typed in the meantime, then see if any other
+        // applications have pending messages.
+        if self.tx_in_progress.is_none() && !self.flush_echo() {
             for cntr in self.apps.iter() {
                 let processid = cntr.processid();
                 let started_tx = cntr.enter(|app, kernel_data| {
@@ -363,6 +701,32 @@ impl uart::ReceiveClient for Console<'_> {
         rcode: Result<(), ErrorCode>,
         error: uart::Error,
     ) {
+        let reader = self.rx_in_progress.extract();
+        let cooked = reader.is_some_and(|processid| {
+            self.apps
+                .enter(processid, |app, _| app.cooked)
+                .unwrap_or(false)
+        });
+        if error == uart::Error::Aborted
+            && (cooked || rx_len == 0)
+            && reader.is_some_and(|processid| !self.may_read(processid))
+        {
+            // The input focus moved away from the reader. Keep its read,
+            // and any line it was editing, until it gets the focus back.
+            if let Some(processid) = reader {
+                let _ = self.apps.enter(processid, |app, _| app.pending_read = true);
+            }
+            self.rx_in_progress.clear();
+            self.rx_buffer.replace(buffer);
+            self.start_pending_read();
+            return;
+        }
+        if cooked {
+            self.cooked_received(buffer, rx_len, rcode, error);
+            self.start_pending_read();
+            return;
+        }
+
         self.rx_in_progress
             .take()
             .map(|processid| {
@@ -458,5 +822,27 @@ impl uart::ReceiveClient for Console<'_> {
 
         // Whatever happens, we want to make sure to replace the rx_buffer for future transactions
         self.rx_buffer.replace(buffer);
+        self.start_pending_read();
+    }
+}
+
+impl InputFocus for Console<'_> {
+    fn focus(&self) -> Option<ProcessId> {
+        self.focus.extract()
+    }
+
+    fn set_focus(&self, processid: Option<ProcessId>) {
+        match processid {
+            Some(processid) => self.focus.set(processid),
+            None => self.focus.clear(),
+        }
+        let reader = self.rx_in_progress.extract();
+        if reader.is_some_and(|processid| !self.may_read(processid)) {
+            // `received_buffer()` queues the read again and starts the
+            // focused process's read once the abort completes.
+            let _ = self.uart.receive_abort();
+        } else {
+            self.start_pending_read();
+        }
     }
 }
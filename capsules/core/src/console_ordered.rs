@@ -54,6 +54,54 @@
 //!
 //! ```
 //!
+//! Quotas and priority output
+//! --------------------------
+//!
+//! To keep one chatty process from monopolizing the console, each process
+//! can be limited to a byte rate with `set_quota()`. Every process has a
+//! budget of `burst` bytes that refills at `bytes_per_second`; a write is
+//! charged in full when it is issued, and is rejected with `RESERVE` if the
+//! process has less budget left than the write (or than `burst`, for writes
+//! longer than that). Quotas are disabled by default.
+//!
+//! With `set_quota_policy(QuotaPolicy::Drop)`, writes over the quota are
+//! dropped instead: they complete normally without being printed, and the
+//! next printed write of the process starts with a `[<name>] dropped N bytes`
+//! line, so that missing output is never silent.
+//!
+//! Kernel consoles, such as the process console, can be given a priority
+//! lane with `set_priority_output()`: while they have output waiting, no
+//! process data is pushed into the debug buffer, so their output is delayed
+//! by at most the process data already queued.
+//!
+//! All of these are off by default. A board opts in by calling the setters
+//! on the console after finalizing its component, for example:
+//!
+//! ```rust
+//! console.set_quota(500, 200);
+//! console.set_quota_policy(QuotaPolicy::Drop);
+//! console.set_priority_output(process_console);
+//! ```
+//!
+//! Log levels and process prefixes
+//! -------------------------------
+//!
+//! Each process selects the log level of its writes with command 5; the
+//! default is `Info`. Writes above the level filter set with
+//! `set_level_filter()`, e.g. by the `loglevel` process console command, are
+//! dropped but still complete normally. With `set_process_prefixes(true)`
+//! every write starts with the name of the process and its level, e.g.
+//! `[blink] INFO: `, so that interleaved output of several processes can be
+//! told apart.
+//!
+//! Prefixes are off and every level is printed by default. A board opts in
+//! after finalizing the console and the process console components:
+//!
+//! ```rust
+//! console.set_process_prefixes(true);
+//! process_console.set_log_level_filter(console);
+//! ```
+//!
 //! Usage
 //! -----
 //!
@@ -68,15 +116,24 @@
 //! command(CONSOLE_DRIVER_NUM, 1, len_to_write_in_bytes)
 //! ```
 //!
+//! The command fails with `RESERVE` if the write exceeds the process's
+//! quota; the process should retry later. If the board drops writes over the
+//! quota instead, the command succeeds.
+//!
+//! ```c
+//! // (Optional) Mark the following writes as debug output
+//! command(CONSOLE_DRIVER_NUM, 5, 3)
+//! ```
+//!
 
 use core::cell::Cell;
 use core::cmp;
 
-use kernel::debug::debug_available_len;
+use kernel::debug::{debug_available_len, debug_process_dropped, debug_process_prefix};
 use kernel::debug_process_slice;
 
 use kernel::grant::{AllowRoCount, AllowRwCount, Grant, GrantKernelData, UpcallCount};
-use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Ticks};
 use kernel::hil::uart;
 use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
 use kernel::syscall::{CommandReturn, SyscallDriver};
@@ -107,6 +164,61 @@ mod rw_allow {
     pub const COUNT: u8 = 2;
 }
 
+/// Severity of process output, selected by each process for its writes.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Error = 0,
+    Warn = 1,
+    #[default]
+    Info = 2,
+    Debug = 3,
+}
+
+impl LogLevel {
+    pub fn from_usize(level: usize) -> Option<LogLevel> {
+        match level {
+            0 => Some(LogLevel::Error),
+            1 => Some(LogLevel::Warn),
+            2 => Some(LogLevel::Info),
+            3 => Some(LogLevel::Debug),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LogLevel::Error => "ERROR",
+            LogLevel::Warn => "WARN",
+            LogLevel::Info => "INFO",
+            LogLevel::Debug => "DEBUG",
+        }
+    }
+}
+
+/// What happens to a process write that exceeds the process's quota.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum QuotaPolicy {
+    /// Fail the write with `RESERVE`, so the process can retry it later.
+    #[default]
+    Reject,
+    /// Complete the write without printing it, and count the dropped bytes.
+    Drop,
+}
+
+/// A console that drops process output above a log level.
+pub trait LogLevelFilter {
+    /// The most verbose level that is printed.
+    fn level_filter(&self) -> LogLevel;
+
+    fn set_level_filter(&self, level: LogLevel);
+}
+
+/// A kernel console whose output takes priority over process writes.
+pub trait PriorityOutput {
+    /// Whether the console has output waiting to be transmitted.
+    fn output_pending(&self) -> bool;
+}
+
 #[derive(Default)]
 pub struct App {
     write_position: usize, // Current write position
@@ -116,6 +228,11 @@ pub struct App {
     tx_counter: usize,     // Used to keep order of writes
     read_len: usize,       // Read length
     rx_counter: usize,     // Used to order reads (no starvation)
+    quota_used: usize,     // Bytes charged against the quota and not yet refilled
+    quota_stamp: u32,      // Alarm ticks up to which the quota was refilled
+    log_level: LogLevel,   // Level of the process's writes
+    prefix_pending: bool,  // The current write still needs its prefix
+    dropped: usize,        // Bytes dropped over the quota and not yet reported
 }
 
 pub struct ConsoleOrdered<'a, A: Alarm<'a>> {
@@ -134,6 +251,13 @@ pub struct ConsoleOrdered<'a, A: Alarm<'a>> {
     rx_in_progress: OptionalCell<ProcessId>,
     rx_buffer: TakeCell<'static, [u8]>,
 
+    quota_rate: Cell<u32>, // Bytes per second each process may write, 0 for no quota
+    quota_burst: Cell<usize>, // Bytes a process may write at once
+    quota_policy: Cell<QuotaPolicy>, // Whether writes over the quota fail or are dropped
+    priority: OptionalCell<&'a dyn PriorityOutput>, // Kernel console served first
+    level_filter: Cell<LogLevel>, // Most verbose level printed
+    process_prefixes: Cell<bool>, // Whether writes start with the process name and level
+
     atomic_size: Cell<usize>, // The maximum size write the capsule promises atomicity;
     // larger writes may be broken into atomic_size chunks.
     // This must be smaller than the debug buffer size or a long
@@ -172,16 +296,99 @@ impl<'a, A: Alarm<'a>> ConsoleOrdered<'a, A> {
             rx_in_progress: OptionalCell::empty(),
             rx_buffer: TakeCell::new(rx_buffer),
 
+            quota_rate: Cell::new(0),
+            quota_burst: Cell::new(0),
+            quota_policy: Cell::new(QuotaPolicy::Reject),
+            priority: OptionalCell::empty(),
+            level_filter: Cell::new(LogLevel::Debug),
+            process_prefixes: Cell::new(false),
+
             atomic_size: Cell::new(atomic_size),
             retry_timer: Cell::new(retry_timer),
             write_timer: Cell::new(write_timer),
         }
     }
 
+    /// Limit each process to writing `bytes_per_second` on average, and
+    /// `burst` bytes at once. A rate of 0 disables quotas.
+    pub fn set_quota(&self, bytes_per_second: u32, burst: usize) {
+        self.quota_rate.set(bytes_per_second);
+        self.quota_burst.set(burst);
+    }
+
+    /// Select whether writes over the quota fail or are dropped.
+    pub fn set_quota_policy(&self, policy: QuotaPolicy) {
+        self.quota_policy.set(policy);
+    }
+
+    /// Hold off process writes while `priority` has output waiting.
+    pub fn set_priority_output(&self, priority: &'a dyn PriorityOutput) {
+        self.priority.set(priority);
+    }
+
+    /// Start each write with the name of the process and the level of the
+    /// write.
+    pub fn set_process_prefixes(&self, enabled: bool) {
+        self.process_prefixes.set(enabled);
+    }
+
+    fn priority_pending(&self) -> bool {
+        self.priority
+            .map_or(false, |priority| priority.output_pending())
+    }
+
+    /// Refill the process's quota for the time elapsed since the last write,
+    /// then charge a write of `len` bytes against it.
+    fn charge_quota(
+        &self,
+        app: &mut App,
+        kernel_data: &GrantKernelData,
+        len: usize,
+    ) -> Result<(), ErrorCode> {
+        let rate = self.quota_rate.get();
+        let len = kernel_data
+            .get_readonly_processbuffer(ro_allow::WRITE)
+            .map_or(0, |write| write.len())
+            .min(len);
+        if rate == 0 || len == 0 {
+            return Ok(());
+        }
+
+        let now = self.alarm.now();
+        if app.quota_used > 0 {
+            let elapsed = now.wrapping_sub(A::Ticks::from(app.quota_stamp));
+            let elapsed_ms = self.alarm.ticks_to_ms(elapsed) as u64;
+            let refilled = (elapsed_ms * rate as u64 / 1000) as usize;
+            if refilled >= app.quota_used {
+                app.quota_used = 0;
+            } else if refilled > 0 {
+                // Only advance by the time the refilled bytes took, so
+                // that frequent writes still see the fractional refill.
+                app.quota_used -= refilled;
+                let refill_ms = (refilled as u64 * 1000 / rate as u64) as u32;
+                app.quota_stamp = A::Ticks::from(app.quota_stamp)
+                    .wrapping_add(self.alarm.ticks_from_ms(refill_ms))
+                    .into_u32();
+            }
+        }
+        if app.quota_used == 0 {
+            app.quota_stamp = now.into_u32();
+        }
+
+        let burst = self.quota_burst.get();
+        let required = cmp::min(len, burst);
+        if app.quota_used + required > burst {
+            return Err(ErrorCode::RESERVE);
+        }
+        app.quota_used = app.quota_used.saturating_add(len);
+        Ok(())
+    }
+
     /// Internal helper function for starting up a new print; allocate a sequence number and
     /// start the send state machine.
     fn send_new(
         &self,
+        processid: ProcessId,
         app: &mut App,
         kernel_data: &GrantKernelData,
         len: usize,
@@ -199,6 +406,7 @@ impl<'a, A: Alarm<'a>> ConsoleOrdered<'a, A> {
         if app.write_len == 0 {
             return Err(ErrorCode::NOMEM);
         }
+        app.prefix_pending = self.process_prefixes.get();
         // Order the prints through a global counter.
         app.tx_counter = self.tx_counter.get();
         self.tx_counter.set(app.tx_counter.wrapping_add(1));
@@ -208,12 +416,19 @@ impl<'a, A: Alarm<'a>> ConsoleOrdered<'a, A> {
         if self.tx_in_progress.get() {
             // A prior print is outstanding, enqueue
             app.pending_write = true;
+        } else if self.priority_pending() {
+            // The priority lane has output waiting, retry after it
+            app.pending_write = true;
+            self.alarm.set_alarm(
+                self.alarm.now(),
+                self.alarm.ticks_from_ms(self.retry_timer.get()),
+            );
         } else if app.write_len <= debug_space_avail {
             // Space for the full write, make it
-            app.write_position = self.send(app, kernel_data).map_or(0, |len| len);
+            app.write_position = self.send(processid, app, kernel_data).map_or(0, |len| len);
         } else if self.atomic_size.get() <= debug_space_avail {
             // Space for a partial write, make it
-            app.write_position = self.send(app, kernel_data).map_or(0, |len| len);
+            app.write_position = self.send(processid, app, kernel_data).map_or(0, |len| len);
         } else {
             // No space even for a partial, minimum size write: enqueue
             app.pending_write = true;
@@ -231,6 +446,7 @@ impl<'a, A: Alarm<'a>> ConsoleOrdered<'a, A> {
     /// data must check before calling.
     fn send(
         &self,
+        processid: ProcessId,
         app: &mut App,
         kernel_data: &GrantKernelData,
     ) -> Result<usize, kernel::process::Error> {
@@ -241,6 +457,14 @@ impl<'a, A: Alarm<'a>> ConsoleOrdered<'a, A> {
             .get_readonly_processbuffer(ro_allow::WRITE)
             .and_then(|write| {
                 write.enter(|data| {
+                    if app.write_position == 0 && app.dropped > 0 && debug_available_len() > 0 {
+                        debug_process_dropped(processid, app.dropped);
+                        app.dropped = 0;
+                    }
+                    if app.prefix_pending && debug_available_len() > 0 {
+                        app.prefix_pending = false;
+                        debug_process_prefix(processid, app.log_level.name());
+                    }
                     // The slice might have become shorter than the requested
                     // write; if so, just write what there is.
                     let remaining_len = app.write_len - app.write_position;
@@ -325,6 +549,7 @@ impl<'a, A: Alarm<'a>> AlarmClient for ConsoleOrdered<'a, A> {
             // Check if the current writer is finished; if so, issue an upcall, if not,
             // try to write more.
             for cntr in self.apps.iter() {
+                let processid = cntr.processid();
                 cntr.enter(|app, kernel_data| {
                     // This is the in-progress write
                     if app.writing {
@@ -340,10 +565,11 @@ impl<'a, A: Alarm<'a>> AlarmClient for ConsoleOrdered<'a, A> {
                             let debug_space_avail = debug_available_len();
                             let minimum_write = cmp::min(remaining_len, self.atomic_size.get());
 
-                            // Write, or if there isn't space for a minimum write, retry later
-                            if minimum_write <= debug_space_avail {
+                            // Write, or if there isn't space for a minimum write
+                            // or the priority lane has output waiting, retry later
+                            if minimum_write <= debug_space_avail && !self.priority_pending() {
                                 app.write_position +=
-                                    self.send(app, kernel_data).map_or(0, |len| len);
+                                    self.send(processid, app, kernel_data).map_or(0, |len| len);
                             } else {
                                 self.alarm.set_alarm(
                                     self.alarm.now(),
@@ -387,7 +613,7 @@ impl<'a, A: Alarm<'a>> AlarmClient for ConsoleOrdered<'a, A> {
                 self.apps.enter(pid, |app, kernel_data| {
                     app.pending_write = false;
                     let len = app.write_len;
-                    let _ = self.send_new(app, kernel_data, len);
+                    let _ = self.send_new(pid, app, kernel_data, len);
                 })
             });
         }
@@ -413,7 +639,10 @@ impl<'a, A: Alarm<'a>> SyscallDriver for ConsoleOrdered<'a, A> {
     ///
     /// - `0`: Driver check.
     /// - `1`: Transmits a buffer passed via `allow`, up to the length
-    ///        passed in `arg1`
+    ///        passed in `arg1`. Fails with `RESERVE` if the write exceeds
+    ///        the process's quota, unless the quota policy drops it.
+    /// - `5`: Sets the log level of the following writes to `arg1`: `0`
+    ///        error, `1` warning, `2` info and `3` debug.
     fn command(&self, cmd_num: usize, arg1: usize, _: usize, appid: ProcessId) -> CommandReturn {
         let res = self
             .apps
@@ -423,7 +652,28 @@ impl<'a, A: Alarm<'a>> SyscallDriver for ConsoleOrdered<'a, A> {
                     1 => {
                         // putstr
                         let len = arg1;
-                        self.send_new(app, kernel_data, len)
+                        let write_len = kernel_data
+                            .get_readonly_processbuffer(ro_allow::WRITE)
+                            .map_or(0, |write| write.len())
+                            .min(len);
+                        if app.writing || app.pending_write {
+                            Err(ErrorCode::BUSY)
+                        } else if app.log_level > self.level_filter.get() {
+                            // Filtered out, complete the write without
+                            // printing it.
+                            let _ = kernel_data.schedule_upcall(1, (write_len, 0, 0));
+                            Ok(())
+                        } else if self.charge_quota(app, kernel_data, len).is_ok() {
+                            self.send_new(appid, app, kernel_data, len)
+                        } else if self.quota_policy.get() == QuotaPolicy::Drop {
+                            // Over the quota, complete the write and report
+                            // it with the next one.
+                            app.dropped = app.dropped.saturating_add(write_len);
+                            let _ = kernel_data.schedule_upcall(1, (write_len, 0, 0));
+                            Ok(())
+                        } else {
+                            Err(ErrorCode::RESERVE)
+                        }
                     }
                     2 => {
                         // getnstr
@@ -435,6 +685,9 @@ impl<'a, A: Alarm<'a>> SyscallDriver for ConsoleOrdered<'a, A> {
                         let _ = self.uart.receive_abort();
                         Ok(())
                     }
+                    5 => LogLevel::from_usize(arg1)
+                        .map(|level| app.log_level = level)
+                        .ok_or(ErrorCode::INVAL),
                     _ => Err(ErrorCode::NOSUPPORT),
                 }
             })
@@ -451,6 +704,16 @@ impl<'a, A: Alarm<'a>> SyscallDriver for ConsoleOrdered<'a, A> {
     }
 }
 
+impl<'a, A: Alarm<'a>> LogLevelFilter for ConsoleOrdered<'a, A> {
+    fn level_filter(&self) -> LogLevel {
+        self.level_filter.get()
+    }
+
+    fn set_level_filter(&self, level: LogLevel) {
+        self.level_filter.set(level);
+    }
+}
+
 impl<'a, A: Alarm<'a>> uart::ReceiveClient for ConsoleOrdered<'a, A> {
     fn received_buffer(
         &self,
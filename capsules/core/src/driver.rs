@@ -23,9 +23,21 @@ pub enum NUM {
     LowLevelDebug         = 0x00008,
     ReadOnlyState         = 0x00009,
     Pwm                   = 0x00010,
+    PeriodicTimer         = 0x00011,
 
     // Kernel
     Ipc                   = 0x10000,
+    Deadline              = 0x10001,
+    ProcessInfo           = 0x10002,
+    Reset                 = 0x10003,
+    AppLoader             = 0x10004,
+    ProcessFaults         = 0x10005,
+    UserspaceDriver       = 0x10006,
+    Benchmark             = 0x10007,
+    FaultSupervisor       = 0x10008,
+    ProcessSuspend        = 0x10009,
+    ProcessIntegrity      = 0x1000A,
+    FirmwareUpdate        = 0x1000B,
 
     // HW Buses
     Spi                   = 0x20001,
@@ -41,6 +53,13 @@ pub enum NUM {
     Udp                   = 0x30002,
     LoRaPhySPI            = 0x30003,
     LoRaPhyGPIO           = 0x30004,
+    Tcp                   = 0x30005,
+    Coap                  = 0x30006,
+    LoRa                  = 0x30007,
+    LoRaWan               = 0x30008,
+    BluetoothHci          = 0x30009,
+    Thread                = 0x3000A,
+    Wifi                  = 0x3000B,
 
     // Cryptography
     Rng                   = 0x40001,
@@ -55,6 +74,8 @@ pub enum NUM {
     NvmStorage            = 0x50001,
     SdCard                = 0x50002,
     KVSystem              = 0x50003,
+    FileSystem            = 0x50004,
+    Log                   = 0x50005,
 
     // Sensors
     Temperature           = 0x60000,
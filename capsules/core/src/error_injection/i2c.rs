@@ -0,0 +1,148 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Error-injecting wrapper around an `I2CDevice`.
+//!
+//! Supports all `Fault` kinds. A timeout is reported as
+//! `Error::ArbitrationLost`, which is what most controllers report when
+//! the bus is stuck.
+
+use core::cell::Cell;
+
+use kernel::hil::i2c::{Error, I2CClient, I2CDevice};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+
+use super::{ErrorInjectionControl, Fault, InjectionConfig};
+
+pub struct ErrorInjectingI2CDevice<'a, A: Alarm<'a>, I: I2CDevice> {
+    name: &'static str,
+    device: &'a I,
+    alarm: &'a A,
+    client: OptionalCell<&'a dyn I2CClient>,
+    config: InjectionConfig,
+    /// Number of bytes read by the operation in flight, used to pick a byte
+    /// to corrupt.
+    read_len: Cell<usize>,
+    /// Completion held back for a delayed callback.
+    pending_buffer: TakeCell<'static, [u8]>,
+    pending_status: Cell<Result<(), Error>>,
+}
+
+impl<'a, A: Alarm<'a>, I: I2CDevice> ErrorInjectingI2CDevice<'a, A, I> {
+    pub fn new(
+        name: &'static str,
+        device: &'a I,
+        alarm: &'a A,
+    ) -> ErrorInjectingI2CDevice<'a, A, I> {
+        ErrorInjectingI2CDevice {
+            name,
+            device,
+            alarm,
+            client: OptionalCell::empty(),
+            config: InjectionConfig::new(),
+            read_len: Cell::new(0),
+            pending_buffer: TakeCell::empty(),
+            pending_status: Cell::new(Ok(())),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn I2CClient) {
+        self.client.set(client);
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: I2CDevice> I2CClient for ErrorInjectingI2CDevice<'a, A, I> {
+    fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), Error>) {
+        match self.config.roll() {
+            Fault::None => {
+                self.client.map(|c| c.command_complete(buffer, status));
+            }
+            Fault::Nak => {
+                self.client
+                    .map(|c| c.command_complete(buffer, Err(Error::DataNak)));
+            }
+            Fault::Corrupt => {
+                let len = core::cmp::min(self.read_len.get(), buffer.len());
+                if status.is_ok() && len > 0 {
+                    let index = self.config.random_index(len);
+                    buffer[index] ^= self.config.random_mask();
+                }
+                self.client.map(|c| c.command_complete(buffer, status));
+            }
+            fault @ (Fault::Timeout | Fault::Delay) => {
+                self.pending_buffer.replace(buffer);
+                self.pending_status.set(if fault == Fault::Timeout {
+                    Err(Error::ArbitrationLost)
+                } else {
+                    status
+                });
+                let delay = self.alarm.ticks_from_ms(self.config.delay_for(fault));
+                self.alarm.set_alarm(self.alarm.now(), delay);
+            }
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: I2CDevice> AlarmClient for ErrorInjectingI2CDevice<'a, A, I> {
+    fn alarm(&self) {
+        self.pending_buffer.take().map(|buffer| {
+            let status = self.pending_status.get();
+            self.client.map(|c| c.command_complete(buffer, status));
+        });
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: I2CDevice> I2CDevice for ErrorInjectingI2CDevice<'a, A, I> {
+    fn enable(&self) {
+        self.device.enable();
+    }
+
+    fn disable(&self) {
+        self.device.disable();
+    }
+
+    fn write_read(
+        &self,
+        data: &'static mut [u8],
+        write_len: usize,
+        read_len: usize,
+    ) -> Result<(), (Error, &'static mut [u8])> {
+        if self.pending_buffer.is_some() {
+            return Err((Error::Busy, data));
+        }
+        self.read_len.set(read_len);
+        self.device.write_read(data, write_len, read_len)
+    }
+
+    fn write(&self, data: &'static mut [u8], len: usize) -> Result<(), (Error, &'static mut [u8])> {
+        if self.pending_buffer.is_some() {
+            return Err((Error::Busy, data));
+        }
+        self.read_len.set(0);
+        self.device.write(data, len)
+    }
+
+    fn read(
+        &self,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (Error, &'static mut [u8])> {
+        if self.pending_buffer.is_some() {
+            return Err((Error::Busy, buffer));
+        }
+        self.read_len.set(len);
+        self.device.read(buffer, len)
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: I2CDevice> ErrorInjectionControl for ErrorInjectingI2CDevice<'a, A, I> {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn config(&self) -> &InjectionConfig {
+        &self.config
+    }
+}
@@ -0,0 +1,204 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Error-injection shims for bus virtualizers.
+//!
+//! These capsules sit between a capsule and the virtualized bus device it
+//! normally uses (e.g. between `RF233` and a `VirtualSpiMasterDevice`, or
+//! between `SI7021` and an `I2CDevice`) and, when armed, randomly inject
+//! bus errors into the completed operations:
+//!
+//! - `Nak`: the operation completes with a NAK (I2C) or a failure (SPI,
+//!   UART) status.
+//! - `Timeout`: the completion callback is held back for a long time and
+//!   then delivered with an error status.
+//! - `Corrupt`: one byte of the received data is flipped.
+//! - `Delay`: the completion callback is delivered late, but unmodified.
+//!
+//! This module is only built with the `error_injection` feature of
+//! `capsules-core`. The shims are only present in a kernel if a board
+//! instantiates them, and they are disarmed (`Fault::None`) until
+//! configured. They are controlled at runtime from the process console with
+//! the `inject` command, see `ProcessConsole::set_error_injectors()`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let si7021_i2c = static_init!(
+//!     capsules_core::virtualizers::virtual_i2c::I2CDevice,
+//!     capsules_core::virtualizers::virtual_i2c::I2CDevice::new(mux_i2c, 0x40)
+//! );
+//! let faulty_i2c = static_init!(
+//!     capsules_core::error_injection::i2c::ErrorInjectingI2CDevice<'static, VirtualMuxAlarm<'static, Ast>, I2CDevice>,
+//!     capsules_core::error_injection::i2c::ErrorInjectingI2CDevice::new("si7021", si7021_i2c, alarm)
+//! );
+//! si7021_i2c.set_client(faulty_i2c);
+//! alarm.set_alarm_client(faulty_i2c);
+//! // Pass `faulty_i2c` to the SI7021 instead of `si7021_i2c`.
+//!
+//! let injectors = static_init!(
+//!     [&'static dyn capsules_core::error_injection::ErrorInjectionControl; 1],
+//!     [faulty_i2c]
+//! );
+//! process_console.set_error_injectors(injectors);
+//! ```
+
+use core::cell::Cell;
+
+pub mod i2c;
+pub mod spi;
+pub mod uart;
+
+/// Delay used for `Fault::Timeout`, in milliseconds.
+pub const TIMEOUT_MS: u32 = 1000;
+
+/// Default delay used for `Fault::Delay`, in milliseconds.
+pub const DEFAULT_DELAY_MS: u32 = 10;
+
+/// Kind of error that a shim injects into a bus operation.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Fault {
+    /// Pass every operation through unmodified.
+    None,
+    /// Complete the operation with a NAK or failure status.
+    Nak,
+    /// Hold the completion back for `TIMEOUT_MS`, then report an error.
+    Timeout,
+    /// Flip a byte of the received data.
+    Corrupt,
+    /// Deliver the completion late, but unmodified.
+    Delay,
+}
+
+impl Fault {
+    /// Parse the name used on the process console.
+    pub fn from_name(name: &str) -> Option<Fault> {
+        match name {
+            "off" | "none" => Some(Fault::None),
+            "nak" => Some(Fault::Nak),
+            "timeout" => Some(Fault::Timeout),
+            "corrupt" => Some(Fault::Corrupt),
+            "delay" => Some(Fault::Delay),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Fault::None => "off",
+            Fault::Nak => "nak",
+            Fault::Timeout => "timeout",
+            Fault::Corrupt => "corrupt",
+            Fault::Delay => "delay",
+        }
+    }
+}
+
+/// Injection state shared by all shims.
+///
+/// Faults are injected with a probability of `1 / one_in` per operation,
+/// using a small xorshift generator. The generator does not need to be
+/// cryptographically secure, it only needs to spread faults over time.
+pub struct InjectionConfig {
+    fault: Cell<Fault>,
+    one_in: Cell<u32>,
+    delay_ms: Cell<u32>,
+    state: Cell<u32>,
+    operations: Cell<u32>,
+    injected: Cell<u32>,
+}
+
+impl InjectionConfig {
+    pub const fn new() -> InjectionConfig {
+        InjectionConfig {
+            fault: Cell::new(Fault::None),
+            one_in: Cell::new(1),
+            delay_ms: Cell::new(DEFAULT_DELAY_MS),
+            state: Cell::new(0x2545_f491),
+            operations: Cell::new(0),
+            injected: Cell::new(0),
+        }
+    }
+
+    fn next_random(&self) -> u32 {
+        let mut x = self.state.get();
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.state.set(x);
+        x
+    }
+
+    /// Decide which fault, if any, to inject into the next operation.
+    pub fn roll(&self) -> Fault {
+        self.operations.set(self.operations.get().wrapping_add(1));
+        let fault = self.fault.get();
+        if fault == Fault::None {
+            return Fault::None;
+        }
+        if self.next_random() % self.one_in.get() == 0 {
+            self.injected.set(self.injected.get().wrapping_add(1));
+            fault
+        } else {
+            Fault::None
+        }
+    }
+
+    /// Pick a random index in `0..len`, used to choose the corrupted byte.
+    pub fn random_index(&self, len: usize) -> usize {
+        if len == 0 {
+            0
+        } else {
+            self.next_random() as usize % len
+        }
+    }
+
+    /// A random non-zero byte mask, so a corrupted byte always changes.
+    pub fn random_mask(&self) -> u8 {
+        (self.next_random() as u8) | 0x01
+    }
+
+    /// How long a delayed callback should be held back for `fault`.
+    pub fn delay_for(&self, fault: Fault) -> u32 {
+        match fault {
+            Fault::Timeout => TIMEOUT_MS,
+            _ => self.delay_ms.get(),
+        }
+    }
+}
+
+/// Runtime control interface of an error-injection shim, used by the
+/// process console.
+pub trait ErrorInjectionControl {
+    /// Name of the shim, as used on the process console.
+    fn name(&self) -> &'static str;
+
+    /// Access to the shared injection state of the shim.
+    fn config(&self) -> &InjectionConfig;
+
+    /// Arm (or disarm, with `Fault::None`) the shim. A fault is injected
+    /// with a probability of `1 / one_in` per operation. `delay_ms` is used
+    /// for `Fault::Delay`.
+    fn configure(&self, fault: Fault, one_in: u32, delay_ms: u32) -> Result<(), kernel::ErrorCode> {
+        if one_in == 0 {
+            return Err(kernel::ErrorCode::INVAL);
+        }
+        let config = self.config();
+        config.fault.set(fault);
+        config.one_in.set(one_in);
+        config.delay_ms.set(delay_ms);
+        Ok(())
+    }
+
+    /// Currently configured fault and rate.
+    fn current(&self) -> (Fault, u32) {
+        (self.config().fault.get(), self.config().one_in.get())
+    }
+
+    /// Number of operations seen and number of faults injected.
+    fn statistics(&self) -> (u32, u32) {
+        (self.config().operations.get(), self.config().injected.get())
+    }
+}
@@ -0,0 +1,177 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Error-injecting wrapper around a `SpiMasterDevice`.
+//!
+//! SPI has no acknowledgement, so `Fault::Nak` is reported as a `FAIL`
+//! status and `Fault::Timeout` as a late `FAIL`. Corruption flips a byte
+//! of the read buffer.
+
+use core::cell::Cell;
+
+use kernel::hil::spi::{ClockPhase, ClockPolarity, SpiMasterClient, SpiMasterDevice};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+use super::{ErrorInjectionControl, Fault, InjectionConfig};
+
+pub struct ErrorInjectingSpiMasterDevice<'a, A: Alarm<'a>, S: SpiMasterDevice> {
+    name: &'static str,
+    device: &'a S,
+    alarm: &'a A,
+    client: OptionalCell<&'static dyn SpiMasterClient>,
+    config: InjectionConfig,
+    /// Completion held back for a delayed callback.
+    pending_write: TakeCell<'static, [u8]>,
+    pending_read: TakeCell<'static, [u8]>,
+    pending_len: Cell<usize>,
+    pending_status: Cell<Result<(), ErrorCode>>,
+    pending: Cell<bool>,
+}
+
+impl<'a, A: Alarm<'a>, S: SpiMasterDevice> ErrorInjectingSpiMasterDevice<'a, A, S> {
+    pub fn new(
+        name: &'static str,
+        device: &'a S,
+        alarm: &'a A,
+    ) -> ErrorInjectingSpiMasterDevice<'a, A, S> {
+        ErrorInjectingSpiMasterDevice {
+            name,
+            device,
+            alarm,
+            client: OptionalCell::empty(),
+            config: InjectionConfig::new(),
+            pending_write: TakeCell::empty(),
+            pending_read: TakeCell::empty(),
+            pending_len: Cell::new(0),
+            pending_status: Cell::new(Ok(())),
+            pending: Cell::new(false),
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, S: SpiMasterDevice> SpiMasterClient
+    for ErrorInjectingSpiMasterDevice<'a, A, S>
+{
+    fn read_write_done(
+        &self,
+        write_buffer: &'static mut [u8],
+        mut read_buffer: Option<&'static mut [u8]>,
+        len: usize,
+        status: Result<(), ErrorCode>,
+    ) {
+        match self.config.roll() {
+            Fault::None => {
+                self.client
+                    .map(|c| c.read_write_done(write_buffer, read_buffer, len, status));
+            }
+            Fault::Nak => {
+                self.client.map(|c| {
+                    c.read_write_done(write_buffer, read_buffer, len, Err(ErrorCode::FAIL))
+                });
+            }
+            Fault::Corrupt => {
+                if let Some(buf) = read_buffer.as_mut() {
+                    let count = core::cmp::min(len, buf.len());
+                    if status.is_ok() && count > 0 {
+                        let index = self.config.random_index(count);
+                        buf[index] ^= self.config.random_mask();
+                    }
+                }
+                self.client
+                    .map(|c| c.read_write_done(write_buffer, read_buffer, len, status));
+            }
+            fault @ (Fault::Timeout | Fault::Delay) => {
+                self.pending.set(true);
+                self.pending_write.replace(write_buffer);
+                read_buffer.map(|buf| self.pending_read.replace(buf));
+                self.pending_len.set(len);
+                self.pending_status.set(if fault == Fault::Timeout {
+                    Err(ErrorCode::FAIL)
+                } else {
+                    status
+                });
+                let delay = self.alarm.ticks_from_ms(self.config.delay_for(fault));
+                self.alarm.set_alarm(self.alarm.now(), delay);
+            }
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, S: SpiMasterDevice> AlarmClient for ErrorInjectingSpiMasterDevice<'a, A, S> {
+    fn alarm(&self) {
+        if !self.pending.get() {
+            return;
+        }
+        self.pending.set(false);
+        self.pending_write.take().map(|write_buffer| {
+            let read_buffer = self.pending_read.take();
+            let len = self.pending_len.get();
+            let status = self.pending_status.get();
+            self.client
+                .map(|c| c.read_write_done(write_buffer, read_buffer, len, status));
+        });
+    }
+}
+
+impl<'a, A: Alarm<'a>, S: SpiMasterDevice> SpiMasterDevice
+    for ErrorInjectingSpiMasterDevice<'a, A, S>
+{
+    fn set_client(&self, client: &'static dyn SpiMasterClient) {
+        self.client.set(client);
+    }
+
+    fn configure(&self, cpol: ClockPolarity, cpal: ClockPhase, rate: u32) -> Result<(), ErrorCode> {
+        self.device.configure(cpol, cpal, rate)
+    }
+
+    fn read_write_bytes(
+        &self,
+        write_buffer: &'static mut [u8],
+        read_buffer: Option<&'static mut [u8]>,
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8], Option<&'static mut [u8]>)> {
+        if self.pending.get() {
+            return Err((ErrorCode::BUSY, write_buffer, read_buffer));
+        }
+        self.device.read_write_bytes(write_buffer, read_buffer, len)
+    }
+
+    fn set_rate(&self, rate: u32) -> Result<(), ErrorCode> {
+        self.device.set_rate(rate)
+    }
+
+    fn get_rate(&self) -> u32 {
+        self.device.get_rate()
+    }
+
+    fn set_polarity(&self, polarity: ClockPolarity) -> Result<(), ErrorCode> {
+        self.device.set_polarity(polarity)
+    }
+
+    fn get_polarity(&self) -> ClockPolarity {
+        self.device.get_polarity()
+    }
+
+    fn set_phase(&self, phase: ClockPhase) -> Result<(), ErrorCode> {
+        self.device.set_phase(phase)
+    }
+
+    fn get_phase(&self) -> ClockPhase {
+        self.device.get_phase()
+    }
+}
+
+impl<'a, A: Alarm<'a>, S: SpiMasterDevice> ErrorInjectionControl
+    for ErrorInjectingSpiMasterDevice<'a, A, S>
+{
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn config(&self) -> &InjectionConfig {
+        &self.config
+    }
+}
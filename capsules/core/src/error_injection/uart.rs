@@ -0,0 +1,191 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Error-injecting wrapper around a UART device.
+//!
+//! Faults are only injected on the receive path; transmissions are passed
+//! through unmodified. `Fault::Nak` is reported as a framing error and
+//! `Fault::Timeout` as a late overrun error.
+
+use core::cell::Cell;
+
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks};
+use kernel::hil::uart;
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+use super::{ErrorInjectionControl, Fault, InjectionConfig};
+
+pub struct ErrorInjectingUartDevice<'a, A: Alarm<'a>> {
+    name: &'static str,
+    device: &'a dyn uart::UartData<'a>,
+    alarm: &'a A,
+    tx_client: OptionalCell<&'a dyn uart::TransmitClient>,
+    rx_client: OptionalCell<&'a dyn uart::ReceiveClient>,
+    config: InjectionConfig,
+    /// Reception held back for a delayed callback.
+    pending_buffer: TakeCell<'static, [u8]>,
+    pending_len: Cell<usize>,
+    pending_status: Cell<Result<(), ErrorCode>>,
+    pending_error: Cell<uart::Error>,
+}
+
+impl<'a, A: Alarm<'a>> ErrorInjectingUartDevice<'a, A> {
+    pub fn new(
+        name: &'static str,
+        device: &'a dyn uart::UartData<'a>,
+        alarm: &'a A,
+    ) -> ErrorInjectingUartDevice<'a, A> {
+        ErrorInjectingUartDevice {
+            name,
+            device,
+            alarm,
+            tx_client: OptionalCell::empty(),
+            rx_client: OptionalCell::empty(),
+            config: InjectionConfig::new(),
+            pending_buffer: TakeCell::empty(),
+            pending_len: Cell::new(0),
+            pending_status: Cell::new(Ok(())),
+            pending_error: Cell::new(uart::Error::None),
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> uart::TransmitClient for ErrorInjectingUartDevice<'a, A> {
+    fn transmitted_buffer(
+        &self,
+        tx_buffer: &'static mut [u8],
+        tx_len: usize,
+        rval: Result<(), ErrorCode>,
+    ) {
+        self.tx_client
+            .map(|c| c.transmitted_buffer(tx_buffer, tx_len, rval));
+    }
+
+    fn transmitted_word(&self, rval: Result<(), ErrorCode>) {
+        self.tx_client.map(|c| c.transmitted_word(rval));
+    }
+}
+
+impl<'a, A: Alarm<'a>> uart::ReceiveClient for ErrorInjectingUartDevice<'a, A> {
+    fn received_buffer(
+        &self,
+        rx_buffer: &'static mut [u8],
+        rx_len: usize,
+        rval: Result<(), ErrorCode>,
+        error: uart::Error,
+    ) {
+        match self.config.roll() {
+            Fault::None => {
+                self.rx_client
+                    .map(|c| c.received_buffer(rx_buffer, rx_len, rval, error));
+            }
+            Fault::Nak => {
+                self.rx_client.map(|c| {
+                    c.received_buffer(
+                        rx_buffer,
+                        rx_len,
+                        Err(ErrorCode::FAIL),
+                        uart::Error::FramingError,
+                    )
+                });
+            }
+            Fault::Corrupt => {
+                let count = core::cmp::min(rx_len, rx_buffer.len());
+                if rval.is_ok() && count > 0 {
+                    let index = self.config.random_index(count);
+                    rx_buffer[index] ^= self.config.random_mask();
+                }
+                self.rx_client
+                    .map(|c| c.received_buffer(rx_buffer, rx_len, rval, error));
+            }
+            fault @ (Fault::Timeout | Fault::Delay) => {
+                self.pending_buffer.replace(rx_buffer);
+                self.pending_len.set(rx_len);
+                if fault == Fault::Timeout {
+                    self.pending_status.set(Err(ErrorCode::FAIL));
+                    self.pending_error.set(uart::Error::OverrunError);
+                } else {
+                    self.pending_status.set(rval);
+                    self.pending_error.set(error);
+                }
+                let delay = self.alarm.ticks_from_ms(self.config.delay_for(fault));
+                self.alarm.set_alarm(self.alarm.now(), delay);
+            }
+        }
+    }
+
+    fn received_word(&self, word: u32, rval: Result<(), ErrorCode>, error: uart::Error) {
+        self.rx_client.map(|c| c.received_word(word, rval, error));
+    }
+}
+
+impl<'a, A: Alarm<'a>> AlarmClient for ErrorInjectingUartDevice<'a, A> {
+    fn alarm(&self) {
+        self.pending_buffer.take().map(|buffer| {
+            let len = self.pending_len.get();
+            let status = self.pending_status.get();
+            let error = self.pending_error.get();
+            self.rx_client
+                .map(|c| c.received_buffer(buffer, len, status, error));
+        });
+    }
+}
+
+impl<'a, A: Alarm<'a>> uart::Transmit<'a> for ErrorInjectingUartDevice<'a, A> {
+    fn set_transmit_client(&self, client: &'a dyn uart::TransmitClient) {
+        self.tx_client.set(client);
+    }
+
+    fn transmit_buffer(
+        &self,
+        tx_buffer: &'static mut [u8],
+        tx_len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        self.device.transmit_buffer(tx_buffer, tx_len)
+    }
+
+    fn transmit_word(&self, word: u32) -> Result<(), ErrorCode> {
+        self.device.transmit_word(word)
+    }
+
+    fn transmit_abort(&self) -> Result<(), ErrorCode> {
+        self.device.transmit_abort()
+    }
+}
+
+impl<'a, A: Alarm<'a>> uart::Receive<'a> for ErrorInjectingUartDevice<'a, A> {
+    fn set_receive_client(&self, client: &'a dyn uart::ReceiveClient) {
+        self.rx_client.set(client);
+    }
+
+    fn receive_buffer(
+        &self,
+        rx_buffer: &'static mut [u8],
+        rx_len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if self.pending_buffer.is_some() {
+            return Err((ErrorCode::BUSY, rx_buffer));
+        }
+        self.device.receive_buffer(rx_buffer, rx_len)
+    }
+
+    fn receive_word(&self) -> Result<(), ErrorCode> {
+        self.device.receive_word()
+    }
+
+    fn receive_abort(&self) -> Result<(), ErrorCode> {
+        self.device.receive_abort()
+    }
+}
+
+impl<'a, A: Alarm<'a>> ErrorInjectionControl for ErrorInjectingUartDevice<'a, A> {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn config(&self) -> &InjectionConfig {
+        &self.config
+    }
+}
@@ -34,6 +34,17 @@
 //! }
 //! ```
 //!
+//! Boards can also let apps drive several pins of a GPIO port at once, for
+//! example to write a parallel bus without glitches. Each port is given
+//! with the mask of its pins that apps may use:
+//!
+//! ```rust
+//! gpio.set_ports(static_init!(
+//!     [(&'static dyn kernel::hil::gpio::Port, u32); 1],
+//!     [(&peripherals.pins, 0x01ff_ffcc)]
+//! ));
+//! ```
+//!
 //! Syscall Interface
 //! -----------------
 //!
@@ -55,6 +66,8 @@
 use crate::driver;
 pub const DRIVER_NUM: usize = driver::NUM::Gpio as usize;
 
+use core::cell::Cell;
+
 use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
 use kernel::hil::gpio;
 use kernel::hil::gpio::{Configure, Input, InterruptWithValue, Output};
@@ -69,6 +82,8 @@ const UPCALL_NUM: usize = 0;
 
 pub struct GPIO<'a, IP: gpio::InterruptPin<'a>> {
     pins: &'a [Option<&'a gpio::InterruptValueWrapper<'a, IP>>],
+    /// Ports apps can drive as a whole, with the mask of pins they may use.
+    ports: Cell<&'a [(&'a dyn gpio::Port, u32)]>,
     apps: Grant<(), UpcallCount<1>, AllowRoCount<0>, AllowRwCount<0>>,
 }
 
@@ -84,10 +99,35 @@ impl<'a, IP: gpio::InterruptPin<'a>> GPIO<'a, IP> {
         }
         Self {
             pins: pins,
+            ports: Cell::new(&[]),
             apps: grant,
         }
     }
 
+    /// Let apps drive the pins in the mask given with each port at once.
+    /// The pins must also be exposed individually for apps to configure
+    /// them as outputs.
+    pub fn set_ports(&self, ports: &'a [(&'a dyn gpio::Port, u32)]) {
+        self.ports.set(ports);
+    }
+
+    /// Run `f` on port `port_index` if `mask` only contains pins apps may
+    /// use.
+    fn with_port<F: FnOnce(&dyn gpio::Port, u32)>(
+        &self,
+        port_index: usize,
+        mask: u32,
+        f: F,
+    ) -> CommandReturn {
+        match self.ports.get().get(port_index) {
+            Some(&(port, allowed)) if mask & !allowed == 0 => {
+                f(port, allowed);
+                CommandReturn::success()
+            }
+            _ => CommandReturn::failure(ErrorCode::INVAL),
+        }
+    }
+
     fn configure_input_pin(&self, pin_num: u32, config: usize) -> CommandReturn {
         let maybe_pin = self.pins[pin_num as usize];
         if let Some(pin) = maybe_pin {
@@ -188,6 +228,11 @@ impl<'a, IP: gpio::InterruptPin<'a>> SyscallDriver for GPIO<'a, IP> {
     /// - `7`: Configure interrupt on `pin` with `irq_config` in 0x00XX00000
     /// - `8`: Disable interrupt on `pin`.
     /// - `9`: Disable `pin`.
+    /// - `10`: Set the pins in mask `data2` of port `data1` at once.
+    /// - `11`: Clear the pins in mask `data2` of port `data1` at once.
+    /// - `12`: Toggle the pins in mask `data2` of port `data1` at once.
+    /// - `13`: Read the pins of port `data1` that apps may use.
+    /// - `14`: Write `data2` to the pins of port `data1` that apps may use.
     fn command(
         &self,
         command_num: usize,
@@ -331,6 +376,28 @@ impl<'a, IP: gpio::InterruptPin<'a>> SyscallDriver for GPIO<'a, IP> {
                 }
             }
 
+            // set pins of a port
+            10 => self.with_port(data1, data2 as u32, |port, _| port.set_pins(data2 as u32)),
+
+            // clear pins of a port
+            11 => self.with_port(data1, data2 as u32, |port, _| port.clear_pins(data2 as u32)),
+
+            // toggle pins of a port
+            12 => self.with_port(data1, data2 as u32, |port, _| {
+                port.toggle_pins(data2 as u32)
+            }),
+
+            // read pins of a port
+            13 => match self.ports.get().get(data1) {
+                Some(&(port, allowed)) => CommandReturn::success_u32(port.read_pins() & allowed),
+                None => CommandReturn::failure(ErrorCode::INVAL),
+            },
+
+            // write pins of a port
+            14 => self.with_port(data1, 0, |port, allowed| {
+                port.write_pins(allowed, data2 as u32)
+            }),
+
             // default
             _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
         }
@@ -14,11 +14,22 @@
 //! on top of the mux) because there is no way to mux the slave (it can't
 //! listen on more than one address) and because the application may want
 //! to be able to talk to any I2C address.
+//!
+//! As a slave, an application can implement a register-map style device.
+//! When the hardware reports that a master's write ended in a repeated
+//! start (`SlaveTransmissionType::WriteBeforeRead`), the written bytes and
+//! the read request that follows are delivered as one upcall, so the
+//! application can pick the response based on the register it was asked
+//! for. While the application prepares that response the hardware stretches
+//! the clock. An optional stretch timeout bounds how long the bus is held:
+//! if the application has not answered in time, the capsule releases the
+//! bus with an empty response and reports the timeout.
 
 use core::cell::Cell;
 use core::cmp;
 
 use kernel::hil;
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks};
 use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
 use kernel::syscall::{CommandReturn, SyscallDriver};
 use kernel::utilities::cells::{OptionalCell, TakeCell};
@@ -60,13 +71,26 @@ enum MasterAction {
     WriteRead(u8),
 }
 
-pub struct I2CMasterSlaveDriver<'a, I: hil::i2c::I2CMasterSlave> {
+pub struct I2CMasterSlaveDriver<'a, I: hil::i2c::I2CMasterSlave, A: Alarm<'a>> {
     i2c: &'a I,
+    alarm: &'a A,
     listening: Cell<bool>,
     master_action: Cell<MasterAction>, // Whether we issued a write or read as master
     master_buffer: TakeCell<'static, [u8]>,
     slave_buffer1: TakeCell<'static, [u8]>,
     slave_buffer2: TakeCell<'static, [u8]>,
+    /// Length of a received write whose transaction continues with a read
+    /// that has not been requested yet.
+    pending_write: OptionalCell<usize>,
+    /// Whether the hardware is stretching the clock waiting for the app to
+    /// provide data to send.
+    read_requested: Cell<bool>,
+    /// Whether the outstanding read is the empty response sent after a
+    /// stretch timeout, which the app does not need to hear about.
+    timeout_read: Cell<bool>,
+    /// How long to stretch the clock waiting for the app, in ms. Zero
+    /// stretches for as long as the app takes.
+    stretch_timeout_ms: Cell<u32>,
     app: OptionalCell<ProcessId>,
     apps: Grant<
         App,
@@ -76,9 +100,10 @@ pub struct I2CMasterSlaveDriver<'a, I: hil::i2c::I2CMasterSlave> {
     >,
 }
 
-impl<'a, I: hil::i2c::I2CMasterSlave> I2CMasterSlaveDriver<'a, I> {
+impl<'a, I: hil::i2c::I2CMasterSlave, A: Alarm<'a>> I2CMasterSlaveDriver<'a, I, A> {
     pub fn new(
         i2c: &'a I,
+        alarm: &'a A,
         master_buffer: &'static mut [u8],
         slave_buffer1: &'static mut [u8],
         slave_buffer2: &'static mut [u8],
@@ -88,21 +113,36 @@ impl<'a, I: hil::i2c::I2CMasterSlave> I2CMasterSlaveDriver<'a, I> {
             AllowRoCount<{ ro_allow::COUNT }>,
             AllowRwCount<{ rw_allow::COUNT }>,
         >,
-    ) -> I2CMasterSlaveDriver<'a, I> {
+    ) -> I2CMasterSlaveDriver<'a, I, A> {
         I2CMasterSlaveDriver {
             i2c,
+            alarm,
             listening: Cell::new(false),
             master_action: Cell::new(MasterAction::Write),
             master_buffer: TakeCell::new(master_buffer),
             slave_buffer1: TakeCell::new(slave_buffer1),
             slave_buffer2: TakeCell::new(slave_buffer2),
+            pending_write: OptionalCell::empty(),
+            read_requested: Cell::new(false),
+            timeout_read: Cell::new(false),
+            stretch_timeout_ms: Cell::new(0),
             app: OptionalCell::empty(),
             apps: grant,
         }
     }
+
+    fn schedule_upcall(&self, data: (usize, usize, usize)) {
+        self.app.map(|app| {
+            let _ = self.apps.enter(*app, |_, kernel_data| {
+                kernel_data.schedule_upcall(0, data).ok();
+            });
+        });
+    }
 }
 
-impl<I: hil::i2c::I2CMasterSlave> hil::i2c::I2CHwMasterClient for I2CMasterSlaveDriver<'_, I> {
+impl<'a, I: hil::i2c::I2CMasterSlave, A: Alarm<'a>> hil::i2c::I2CHwMasterClient
+    for I2CMasterSlaveDriver<'a, I, A>
+{
     fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), hil::i2c::Error>) {
         // Map I2C error to a number we can pass back to the application
         let status = kernel::errorcode::into_statuscode(match status {
@@ -189,7 +229,9 @@ impl<I: hil::i2c::I2CMasterSlave> hil::i2c::I2CHwMasterClient for I2CMasterSlave
     }
 }
 
-impl<I: hil::i2c::I2CMasterSlave> hil::i2c::I2CHwSlaveClient for I2CMasterSlaveDriver<'_, I> {
+impl<'a, I: hil::i2c::I2CMasterSlave, A: Alarm<'a>> hil::i2c::I2CHwSlaveClient
+    for I2CMasterSlaveDriver<'a, I, A>
+{
     fn command_complete(
         &self,
         buffer: &'static mut [u8],
@@ -202,63 +244,71 @@ impl<I: hil::i2c::I2CMasterSlave> hil::i2c::I2CHwSlaveClient for I2CMasterSlaveD
         //   - on read, just signal upper layer and replace the read buffer
         //     in this driver
         match transmission_type {
-            hil::i2c::SlaveTransmissionType::Write => {
+            hil::i2c::SlaveTransmissionType::Write
+            | hil::i2c::SlaveTransmissionType::WriteBeforeRead => {
                 self.app.map(|app| {
                     let _ = self.apps.enter(*app, |_, kernel_data| {
-                        kernel_data
+                        let _ = kernel_data
                             .get_readwrite_processbuffer(rw_allow::SLAVE_RX)
                             .and_then(|slave_rx| {
-                                slave_rx.mut_enter(move |app_rx| {
+                                slave_rx.mut_enter(|app_rx| {
                                     // Check bounds for write length
-                                    // Because this (somewhat incorrectly) doesn't report
-                                    // back how many bytes were read, the result of mut_map_or
-                                    // is ignored. Note that this requires userspace to keep
-                                    // track of this information, and if read_len is longer
-                                    // than the buffer could lead to array overrun errors in
-                                    // userspace. The I2C syscall API should pass back lengths.
-                                    // -pal 3/5/21
                                     let buf_len = cmp::min(app_rx.len(), buffer.len());
-                                    let read_len = cmp::min(buf_len, length as usize);
+                                    let read_len = cmp::min(buf_len, length);
 
-                                    for (i, c) in buffer[0..read_len].iter_mut().enumerate() {
+                                    for (i, c) in buffer[0..read_len].iter().enumerate() {
                                         app_rx[i].set(*c);
                                     }
-
-                                    self.slave_buffer1.replace(buffer);
-                                    0
                                 })
-                            })
-                            .unwrap_or(0);
-
-                        kernel_data.schedule_upcall(0, (3, length as usize, 0)).ok();
+                            });
                     });
                 });
+                self.slave_buffer1.replace(buffer);
+
+                // The write is reported together with the read request that
+                // follows it. If the app already provided data to send, the
+                // hardware will not ask for it, so report the write on its
+                // own.
+                match transmission_type {
+                    hil::i2c::SlaveTransmissionType::WriteBeforeRead
+                        if self.slave_buffer2.is_some() =>
+                    {
+                        self.pending_write.set(length);
+                    }
+                    _ => self.schedule_upcall((3, length, 0)),
+                }
             }
 
             hil::i2c::SlaveTransmissionType::Read => {
                 self.slave_buffer2.replace(buffer);
 
-                // Notify the app that the read finished
-                self.app.map(|app| {
-                    let _ = self.apps.enter(*app, |_, kernel_data| {
-                        kernel_data.schedule_upcall(0, (4, length as usize, 0)).ok();
-                    });
-                });
+                // The app never saw the read that timed out, so it does not
+                // need to hear that it finished.
+                if !self.timeout_read.replace(false) {
+                    // Notify the app that the read finished
+                    self.schedule_upcall((4, length, 0));
+                }
             }
         }
     }
 
     fn read_expected(&self) {
         // Pass this up to the client. Not much we can do until the application
-        // has setup a buffer to read from.
-        self.app.map(|app| {
-            let _ = self.apps.enter(*app, |_, kernel_data| {
-                // Ask the app to setup a read buffer. The app must call
-                // command 3 after it has setup the shared read buffer with
-                // the correct bytes.
-                kernel_data.schedule_upcall(0, (2, 0, 0)).ok();
-            });
-        });
+        // has setup a buffer to read from. The app must call command 4 after
+        // it has setup the shared read buffer with the correct bytes.
+        self.read_requested.set(true);
+        match self.pending_write.take() {
+            // Deliver the write and the read request as one transaction so
+            // the app can answer based on what was written.
+            Some(write_len) => self.schedule_upcall((5, write_len, 0)),
+            None => self.schedule_upcall((2, 0, 0)),
+        }
+
+        let timeout_ms = self.stretch_timeout_ms.get();
+        if timeout_ms > 0 {
+            self.alarm
+                .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(timeout_ms));
+        }
     }
 
     fn write_expected(&self) {
@@ -273,7 +323,29 @@ impl<I: hil::i2c::I2CMasterSlave> hil::i2c::I2CHwSlaveClient for I2CMasterSlaveD
     }
 }
 
-impl<I: hil::i2c::I2CMasterSlave> SyscallDriver for I2CMasterSlaveDriver<'_, I> {
+impl<'a, I: hil::i2c::I2CMasterSlave, A: Alarm<'a>> AlarmClient for I2CMasterSlaveDriver<'a, I, A> {
+    fn alarm(&self) {
+        if !self.read_requested.replace(false) {
+            return;
+        }
+
+        // The app did not provide data in time. Send an empty response so
+        // the hardware stops stretching the clock and the master can finish
+        // the transaction.
+        if let Some(buffer) = self.slave_buffer2.take() {
+            self.timeout_read.set(true);
+            if let Err((_, buffer)) = hil::i2c::I2CSlave::read_send(self.i2c, buffer, 0) {
+                self.timeout_read.set(false);
+                self.slave_buffer2.replace(buffer);
+            }
+        }
+        self.schedule_upcall((6, 0, 0));
+    }
+}
+
+impl<'a, I: hil::i2c::I2CMasterSlave, A: Alarm<'a>> SyscallDriver
+    for I2CMasterSlaveDriver<'a, I, A>
+{
     fn command(
         &self,
         command_num: usize,
@@ -410,6 +482,9 @@ impl<I: hil::i2c::I2CMasterSlave> SyscallDriver for I2CMasterSlaveDriver<'_, I>
             // Prepare for a read from another Master by passing what's
             // in the shared slice to the lower level I2C hardware driver.
             4 => {
+                if self.read_requested.replace(false) {
+                    let _ = self.alarm.disarm();
+                }
                 let _ = self.apps.enter(app, |_, kernel_data| {
                     // Because this (somewhat incorrectly) doesn't report
                     // back how many bytes are being read, the result of mut_map_or
@@ -448,6 +523,10 @@ impl<I: hil::i2c::I2CMasterSlave> SyscallDriver for I2CMasterSlaveDriver<'_, I>
             // Stop listening for messages as an I2C slave
             5 => {
                 hil::i2c::I2CSlave::disable(self.i2c);
+                if self.read_requested.replace(false) {
+                    let _ = self.alarm.disarm();
+                }
+                self.pending_write.clear();
 
                 // We are no longer listening for I2C messages from a different
                 // master device.
@@ -463,9 +542,17 @@ impl<I: hil::i2c::I2CMasterSlave> SyscallDriver for I2CMasterSlaveDriver<'_, I>
                 if address > 0x7f {
                     return CommandReturn::failure(ErrorCode::INVAL);
                 }
-                // TODO verify errors
-                let _ = hil::i2c::I2CSlave::set_address(self.i2c, address);
-                CommandReturn::success()
+                match hil::i2c::I2CSlave::set_address(self.i2c, address) {
+                    Ok(()) => {
+                        // Some hardware only latches the address when it
+                        // starts listening, so apply it right away.
+                        if self.listening.get() {
+                            hil::i2c::I2CSlave::listen(self.i2c);
+                        }
+                        CommandReturn::success()
+                    }
+                    Err(e) => CommandReturn::failure(e.into()),
+                }
             }
 
             // Perform write-to then read-from a slave device.
@@ -505,6 +592,13 @@ impl<I: hil::i2c::I2CMasterSlave> SyscallDriver for I2CMasterSlaveDriver<'_, I>
                 CommandReturn::success()
             }
 
+            // Set how long, in ms, to stretch the clock waiting for the app
+            // to provide data for a read. Zero waits indefinitely.
+            8 => {
+                self.stretch_timeout_ms.set(data as u32);
+                CommandReturn::success()
+            }
+
             // default
             _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
         }
@@ -12,17 +12,23 @@ pub mod stream;
 
 pub mod adc;
 pub mod alarm;
+pub mod bus_trace;
 pub mod button;
 pub mod console;
 pub mod console_ordered;
 pub mod driver;
+#[cfg(feature = "error_injection")]
+pub mod error_injection;
 pub mod gpio;
 pub mod i2c_master;
 pub mod i2c_master_slave_driver;
 pub mod led;
 pub mod low_level_debug;
+pub mod low_power_alarm;
+pub mod packet_trace;
 pub mod process_console;
 pub mod rng;
 pub mod spi_controller;
 pub mod spi_peripheral;
+pub mod syscall_trace;
 pub mod virtualizers;
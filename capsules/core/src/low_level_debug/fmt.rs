@@ -11,17 +11,35 @@ use super::DebugEntry;
 //   2. LowLevelDebug: App ## alert code ##\n
 //   3. LowLevelDebug: App ## prints ##\n
 //   4. LowLevelDebug: App ## prints ## ##\n
+//   5. LowLevelDebug: App ## event ## ## at ## ms id ##\n
 //
-// Each ## above is a usize printed in hexadecimal, with a leading 0x.
+// Each ## above is a usize printed in hexadecimal, with a leading 0x. The
+// timestamp and ID of an event are u32s, also printed in hexadecimal.
 
-// The longest message is either 1 or 4, depending on the size of a usize.
-pub const BUF_LEN: usize = max(45 + 2 * USIZE_DIGITS, 35 + 3 * USIZE_DIGITS);
+// The longest message is 1, 4 or 5, depending on the size of a usize.
+pub const BUF_LEN: usize = max(
+    max(45 + 2 * USIZE_DIGITS, 35 + 3 * USIZE_DIGITS),
+    49 + 3 * USIZE_DIGITS + 2 * U32_DIGITS,
+);
+
+// Binary frames, sent instead of messages with `Framing::Binary`:
+//
+//   | 0xA5 | kind | app | short ID | timestamp | value 1 | value 2 | checksum |
+//
+// kind is 0 for dropped entries, 1 for alert codes, 2 and 3 for one or two
+// printed numbers and 4 for events. The app number, ShortID, timestamp and
+// values are u32s in little-endian order; usize values are truncated to their
+// low 32 bits. Fields an entry does not have are 0. The checksum is the
+// wrapping sum of all preceding bytes, so that a host can find the start of
+// the next frame after lost or corrupted bytes.
+pub const FRAME_LEN: usize = 2 + 5 * 4 + 1;
+const FRAME_START: u8 = 0xA5;
 
 // Formats the given DebugEntry using the provided buffer. Returns the length of
 // the message.
 pub(crate) fn format_entry(app_num: usize, entry: DebugEntry, buffer: &mut [u8]) -> usize {
     use core::fmt::write;
-    use DebugEntry::{AlertCode, Dropped, Print1, Print2};
+    use DebugEntry::{AlertCode, Dropped, Event, Print1, Print2};
     let mut adapter = WriteAdapter::new(buffer);
     let _ = match entry {
         Dropped(count) => write(
@@ -49,13 +67,67 @@ pub(crate) fn format_entry(app_num: usize, entry: DebugEntry, buffer: &mut [u8])
                 app_num, num1, num2
             ),
         ),
+        Event {
+            code,
+            arg,
+            timestamp,
+            short_id,
+        } => write(
+            &mut adapter,
+            format_args!(
+                "LowLevelDebug: App 0x{:x} event 0x{:x} 0x{:x} at 0x{:x} ms id 0x{:x}\n",
+                app_num, code, arg, timestamp, short_id
+            ),
+        ),
     };
     adapter.finish()
 }
 
+// Encodes the given DebugEntry as a binary frame in the provided buffer.
+// Returns the length of the frame.
+pub(crate) fn format_frame(app_num: usize, entry: DebugEntry, buffer: &mut [u8]) -> usize {
+    use DebugEntry::{AlertCode, Dropped, Event, Print1, Print2};
+    let (kind, short_id, timestamp, value1, value2) = match entry {
+        Dropped(count) => (0, 0, 0, count, 0),
+        AlertCode(code) => (1, 0, 0, code, 0),
+        Print1(num) => (2, 0, 0, num, 0),
+        Print2(num1, num2) => (3, 0, 0, num1, num2),
+        Event {
+            code,
+            arg,
+            timestamp,
+            short_id,
+        } => (4, short_id, timestamp, code, arg),
+    };
+
+    let frame = match buffer.get_mut(..FRAME_LEN) {
+        Some(frame) => frame,
+        None => return 0,
+    };
+    frame[0] = FRAME_START;
+    frame[1] = kind;
+    let fields = [
+        app_num as u32,
+        short_id,
+        timestamp,
+        value1 as u32,
+        value2 as u32,
+    ];
+    for (chunk, field) in frame[2..FRAME_LEN - 1].chunks_mut(4).zip(fields) {
+        chunk.copy_from_slice(&field.to_le_bytes());
+    }
+    frame[FRAME_LEN - 1] = frame[..FRAME_LEN - 1]
+        .iter()
+        .fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    FRAME_LEN
+}
+
 // The length of a hex-formatted usize, excluding the leading 0x.
 const USIZE_DIGITS: usize = 2 * core::mem::size_of::<usize>();
 
+// The length of a hex-formatted u32, excluding the leading 0x.
+const U32_DIGITS: usize = 8;
+
 // const implementation of max
 const fn max(a: usize, b: usize) -> usize {
     [a, b][(b > a) as usize]
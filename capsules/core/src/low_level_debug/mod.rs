@@ -4,14 +4,28 @@
 
 //! Provides low-level debugging functionality to userspace. The system call
 //! interface is documented in doc/syscalls/00008_low_level_debug.md.
+//!
+//! Events (command 4) are stamped with the time they were emitted, read from
+//! the clock given with `set_clock()`, and with the ShortID of the process.
+//! With `set_framing(Framing::Binary)` every entry is sent as a fixed-size
+//! binary frame instead of a line of text, which is cheaper to send and lets
+//! a host tool decode high-rate event streams:
+//!
+//! ```rust,ignore
+//! lldb.set_clock(hardware_timer);
+//! lldb.set_framing(capsules_core::low_level_debug::Framing::Binary);
+//! ```
 
 mod fmt;
 
 use core::cell::Cell;
 
 use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::time::{ConvertTicks, Time};
 use kernel::hil::uart::{Transmit, TransmitClient};
+use kernel::process::ShortID;
 use kernel::syscall::CommandReturn;
+use kernel::utilities::cells::OptionalCell;
 use kernel::{ErrorCode, ProcessId};
 
 // LowLevelDebug requires a &mut [u8] buffer of length at least BUF_LEN.
@@ -19,6 +33,29 @@ pub use fmt::BUF_LEN;
 
 pub const DRIVER_NUM: usize = crate::driver::NUM::LowLevelDebug as usize;
 
+/// Source of the timestamps of events.
+pub trait EventClock {
+    /// The current time in milliseconds.
+    fn now_ms(&self) -> u32;
+}
+
+impl<T: Time> EventClock for T {
+    fn now_ms(&self) -> u32 {
+        self.ticks_to_ms(self.now())
+    }
+}
+
+/// How entries are sent over the UART.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Framing {
+    /// One line of text per entry.
+    #[default]
+    Text,
+    /// One binary frame per entry, as described in
+    /// doc/syscalls/00008_low_level_debug.md.
+    Binary,
+}
+
 pub struct LowLevelDebug<'u, U: Transmit<'u>> {
     buffer: Cell<Option<&'static mut [u8]>>,
     grant: Grant<AppData, UpcallCount<0>, AllowRoCount<0>, AllowRwCount<0>>,
@@ -30,6 +67,8 @@ pub struct LowLevelDebug<'u, U: Transmit<'u>> {
     // application's message was dropped.
     grant_failed: Cell<bool>,
     uart: &'u U,
+    clock: OptionalCell<&'u dyn EventClock>,
+    framing: Cell<Framing>,
 }
 
 impl<'u, U: Transmit<'u>> LowLevelDebug<'u, U> {
@@ -43,8 +82,20 @@ impl<'u, U: Transmit<'u>> LowLevelDebug<'u, U> {
             grant,
             grant_failed: Cell::new(false),
             uart,
+            clock: OptionalCell::empty(),
+            framing: Cell::new(Framing::Text),
         }
     }
+
+    /// Stamp events with the time read from `clock`. Without a clock, events
+    /// carry a timestamp of 0.
+    pub fn set_clock(&self, clock: &'u dyn EventClock) {
+        self.clock.set(clock);
+    }
+
+    pub fn set_framing(&self, framing: Framing) {
+        self.framing.set(framing);
+    }
 }
 
 impl<'u, U: Transmit<'u>> kernel::syscall::SyscallDriver for LowLevelDebug<'u, U> {
@@ -60,6 +111,22 @@ impl<'u, U: Transmit<'u>> kernel::syscall::SyscallDriver for LowLevelDebug<'u, U
             1 => self.push_entry(DebugEntry::AlertCode(r2), caller_id),
             2 => self.push_entry(DebugEntry::Print1(r2), caller_id),
             3 => self.push_entry(DebugEntry::Print2(r2, r3), caller_id),
+            4 => {
+                let timestamp = self.clock.map_or(0, |clock| clock.now_ms());
+                let short_id = match caller_id.short_app_id() {
+                    ShortID::Fixed(id) => id.get(),
+                    ShortID::LocallyUnique => 0,
+                };
+                self.push_entry(
+                    DebugEntry::Event {
+                        code: r2,
+                        arg: r3,
+                        timestamp,
+                        short_id,
+                    },
+                    caller_id,
+                )
+            }
             _ => return CommandReturn::failure(ErrorCode::NOSUPPORT),
         }
         CommandReturn::success()
@@ -153,7 +220,10 @@ impl<'u, U: Transmit<'u>> LowLevelDebug<'u, U> {
 
     // Immediately prints the provided entry to the UART.
     fn transmit_entry(&self, buffer: &'static mut [u8], app_num: usize, entry: DebugEntry) {
-        let msg_len = fmt::format_entry(app_num, entry, buffer);
+        let msg_len = match self.framing.get() {
+            Framing::Text => fmt::format_entry(app_num, entry, buffer),
+            Framing::Binary => fmt::format_frame(app_num, entry, buffer),
+        };
         // The uart's error message is ignored because we cannot do anything if
         // it fails anyway.
         let _ = self
@@ -165,8 +235,8 @@ impl<'u, U: Transmit<'u>> LowLevelDebug<'u, U> {
     }
 }
 
-// Length of the debug queue for each app. Each queue entry takes 3 words (tag
-// and 2 usizes to print). The queue will be allocated in an app's grant region
+// Length of the debug queue for each app. Each queue entry takes 5 words (tag,
+// 2 usizes to print and the timestamp and ShortID of events). The queue will be allocated in an app's grant region
 // when that app first uses the debug driver.
 const QUEUE_SIZE: usize = 4;
 
@@ -181,4 +251,12 @@ pub(crate) enum DebugEntry {
     AlertCode(usize),     // Display a predefined alert code
     Print1(usize),        // Print a single number
     Print2(usize, usize), // Print two numbers
+    // An event code with an argument, emitted at `timestamp` milliseconds by
+    // the process with ShortID `short_id` (0 if it is locally unique)
+    Event {
+        code: usize,
+        arg: usize,
+        timestamp: u32,
+        short_id: u32,
+    },
 }
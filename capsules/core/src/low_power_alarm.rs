@@ -0,0 +1,246 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Alarm that migrates to a low-power clock while the chip sleeps.
+//!
+//! `LowPowerAlarm` provides an `Alarm` on top of a high-frequency counter
+//! (e.g. an nRF52 TIMER running from HFCLK). When the chip is about to go
+//! to sleep and the next expiration is far enough in the future, it stops
+//! the high-frequency counter and arms a low-frequency alarm (e.g. the
+//! 32 kHz RTC) for the pending expiration instead. On wake-up, it accounts
+//! for the time spent asleep, restarts the high-frequency counter and
+//! re-arms the expiration on it. To the alarm mux and its clients, time
+//! appears to have advanced continuously at the high-frequency rate.
+//!
+//! The low-frequency alarm only needs to be accurate to one of its own
+//! ticks: it is armed to fire at or slightly before the expiration, and the
+//! remainder is then waited out on the high-frequency counter.
+//!
+//! If no expiration is pending, the low-frequency alarm is armed for half
+//! its range anyway, so that elapsed time is never lost to a wraparound of
+//! the low-frequency counter.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let lp_alarm = static_init!(
+//!     capsules_core::low_power_alarm::LowPowerAlarm<'static, nrf52::timer::TimerAlarm, nrf52::rtc::Rtc>,
+//!     capsules_core::low_power_alarm::LowPowerAlarm::new(&base_peripherals.timer1, &base_peripherals.rtc2, 160)
+//! );
+//! base_peripherals.timer1.set_alarm_client(lp_alarm);
+//! base_peripherals.rtc2.set_alarm_client(lp_alarm);
+//! base_peripherals.rtc2.start().unwrap();
+//! lp_alarm.start().unwrap();
+//! chip.set_low_power_alarm(lp_alarm);
+//! // Use `lp_alarm` as the alarm of the `MuxAlarm`.
+//! ```
+
+use core::cell::Cell;
+
+use kernel::hil::time::{self, Alarm, Counter, Frequency, LowPowerHandover, Ticks, Time};
+use kernel::utilities::cells::OptionalCell;
+use kernel::ErrorCode;
+
+pub struct LowPowerAlarm<'a, H: Alarm<'a> + Counter<'a>, L: Alarm<'a>> {
+    hf: &'a H,
+    lf: &'a L,
+    client: OptionalCell<&'a dyn time::AlarmClient>,
+    /// Virtual time minus high-frequency counter value.
+    offset: Cell<H::Ticks>,
+    /// Pending expiration, in virtual time.
+    reference: Cell<H::Ticks>,
+    dt: Cell<H::Ticks>,
+    armed: Cell<bool>,
+    /// Expirations closer than this (in high-frequency ticks) are not worth
+    /// migrating to the low-frequency clock.
+    threshold: H::Ticks,
+    /// Whether the high-frequency counter is stopped and the low-frequency
+    /// alarm is in charge.
+    migrated: Cell<bool>,
+    /// Virtual time and low-frequency counter value when migrating.
+    sleep_virtual: Cell<H::Ticks>,
+    sleep_lf: Cell<L::Ticks>,
+}
+
+impl<'a, H: Alarm<'a> + Counter<'a>, L: Alarm<'a>> LowPowerAlarm<'a, H, L> {
+    pub fn new(hf: &'a H, lf: &'a L, threshold: u32) -> LowPowerAlarm<'a, H, L> {
+        let zero = H::Ticks::from(0);
+        LowPowerAlarm {
+            hf,
+            lf,
+            client: OptionalCell::empty(),
+            offset: Cell::new(zero),
+            reference: Cell::new(zero),
+            dt: Cell::new(zero),
+            armed: Cell::new(false),
+            threshold: H::Ticks::from(threshold),
+            migrated: Cell::new(false),
+            sleep_virtual: Cell::new(zero),
+            sleep_lf: Cell::new(L::Ticks::from(0)),
+        }
+    }
+
+    /// Start the high-frequency counter. The low-frequency alarm must
+    /// already be running.
+    pub fn start(&self) -> Result<(), ErrorCode> {
+        self.hf.start()
+    }
+
+    /// Whether the alarm currently runs on the low-frequency clock.
+    pub fn is_migrated(&self) -> bool {
+        self.migrated.get()
+    }
+
+    fn hf_to_lf(ticks: H::Ticks) -> L::Ticks {
+        let val = ticks.into_u32() as u64 * L::Frequency::frequency() as u64
+            / H::Frequency::frequency() as u64;
+        let half = L::Ticks::half_max_value().into_u32() as u64;
+        L::Ticks::from_or_max(core::cmp::min(val, half))
+    }
+
+    fn lf_to_hf(ticks: L::Ticks) -> H::Ticks {
+        let val = ticks.into_u32() as u64 * H::Frequency::frequency() as u64
+            / L::Frequency::frequency() as u64;
+        H::Ticks::from_or_max(val)
+    }
+
+    fn expiration(&self) -> H::Ticks {
+        self.reference.get().wrapping_add(self.dt.get())
+    }
+
+    /// Arm the high-frequency alarm for the pending expiration.
+    fn arm_hf(&self) {
+        let reference = self.reference.get().wrapping_sub(self.offset.get());
+        self.hf.set_alarm(reference, self.dt.get());
+    }
+}
+
+impl<'a, H: Alarm<'a> + Counter<'a>, L: Alarm<'a>> LowPowerHandover for LowPowerAlarm<'a, H, L> {
+    fn prepare_sleep(&self) -> bool {
+        if self.migrated.get() {
+            return true;
+        }
+        let now = self.now();
+        let lf_dt = if self.armed.get() {
+            let expiration = self.expiration();
+            if !now.within_range(self.reference.get(), expiration) {
+                // Already expired, the callback is about to be delivered.
+                return false;
+            }
+            let remaining = expiration.wrapping_sub(now);
+            if remaining < self.threshold {
+                return false;
+            }
+            Self::hf_to_lf(remaining)
+        } else {
+            L::Ticks::half_max_value()
+        };
+
+        let _ = self.hf.disarm();
+        if self.hf.stop().is_err() {
+            if self.armed.get() {
+                self.arm_hf();
+            }
+            return false;
+        }
+        let lf_now = self.lf.now();
+        self.sleep_virtual.set(now);
+        self.sleep_lf.set(lf_now);
+        self.migrated.set(true);
+        self.lf.set_alarm(lf_now, lf_dt);
+        true
+    }
+
+    fn wakeup(&self) {
+        if !self.migrated.get() {
+            return;
+        }
+        let _ = self.lf.disarm();
+        let elapsed = Self::lf_to_hf(self.lf.now().wrapping_sub(self.sleep_lf.get()));
+        let now = self.sleep_virtual.get().wrapping_add(elapsed);
+        let _ = self.hf.start();
+        self.offset.set(now.wrapping_sub(self.hf.now()));
+        self.migrated.set(false);
+        if self.armed.get() {
+            // If the expiration has already passed, the high-frequency alarm
+            // fires right away.
+            self.arm_hf();
+        }
+    }
+}
+
+impl<'a, H: Alarm<'a> + Counter<'a>, L: Alarm<'a>> Time for LowPowerAlarm<'a, H, L> {
+    type Frequency = H::Frequency;
+    type Ticks = H::Ticks;
+
+    fn now(&self) -> Self::Ticks {
+        if self.migrated.get() {
+            let elapsed = Self::lf_to_hf(self.lf.now().wrapping_sub(self.sleep_lf.get()));
+            self.sleep_virtual.get().wrapping_add(elapsed)
+        } else {
+            self.hf.now().wrapping_add(self.offset.get())
+        }
+    }
+}
+
+impl<'a, H: Alarm<'a> + Counter<'a>, L: Alarm<'a>> Alarm<'a> for LowPowerAlarm<'a, H, L> {
+    fn set_alarm_client(&self, client: &'a dyn time::AlarmClient) {
+        self.client.set(client);
+    }
+
+    fn set_alarm(&self, reference: Self::Ticks, dt: Self::Ticks) {
+        self.wakeup();
+        self.reference.set(reference);
+        self.dt.set(dt);
+        self.armed.set(true);
+        self.arm_hf();
+    }
+
+    fn get_alarm(&self) -> Self::Ticks {
+        self.expiration()
+    }
+
+    fn disarm(&self) -> Result<(), ErrorCode> {
+        self.armed.set(false);
+        if self.migrated.get() {
+            // Leave the low-frequency alarm armed, it still bounds the
+            // time spent asleep.
+            Ok(())
+        } else {
+            self.hf.disarm()
+        }
+    }
+
+    fn is_armed(&self) -> bool {
+        self.armed.get()
+    }
+
+    fn minimum_dt(&self) -> Self::Ticks {
+        self.hf.minimum_dt()
+    }
+}
+
+/// Both the high- and the low-frequency alarm call back here: the
+/// low-frequency one while migrated, the high-frequency one otherwise.
+impl<'a, H: Alarm<'a> + Counter<'a>, L: Alarm<'a>> time::AlarmClient for LowPowerAlarm<'a, H, L> {
+    fn alarm(&self) {
+        if self.migrated.get() {
+            self.wakeup();
+            return;
+        }
+        if !self.armed.get() {
+            return;
+        }
+        let now = self.now();
+        if now.within_range(self.reference.get(), self.expiration()) {
+            // Not expired yet, e.g. a stale callback from before a
+            // migration. Make sure the high-frequency alarm is armed.
+            self.arm_hf();
+            return;
+        }
+        self.armed.set(false);
+        self.client.map(|client| client.alarm());
+    }
+}
@@ -0,0 +1,267 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Network packet tracing.
+//!
+//! `PacketTraceBuffer` records the packets the network stack sends and
+//! receives into a ring buffer: when the packet was seen, the layer and
+//! direction, its length, and its first bytes, up to the snap length. When
+//! the ring is full, the oldest packet is overwritten.
+//!
+//! Two layers are traced:
+//!
+//!   * `Layer::Lowpan`: every 802.15.4 frame of 6LoWPAN, from its MAC header
+//!     on and without the FCS. Frames are recorded before they are secured
+//!     and after they are unsecured, so the payload is in the clear even if
+//!     the header has security enabled, and there is no MIC.
+//!   * `Layer::Ip`: every IPv6 packet, before it is compressed and
+//!     fragmented or after it is reassembled and decompressed, and the
+//!     packets sent and received over Ethernet.
+//!
+//! Comparing both layers shows how a packet was fragmented, and which
+//! fragments or packets got lost.
+//!
+//! The trace is dumped from the process console with the `nettrace`
+//! command, see `ProcessConsole::set_packet_trace()`, either as a table in
+//! hex or as the hex of a pcap file of one layer, which `xxd -r -p` turns
+//! into a file for Wireshark.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let nettrace = static_init!(
+//!     capsules_core::packet_trace::PacketTraceBuffer<'static, VirtualMuxAlarm<'static, Rtc>>,
+//!     capsules_core::packet_trace::PacketTraceBuffer::new(
+//!         alarm,
+//!         static_init!(
+//!             [capsules_core::packet_trace::Packet; 32],
+//!             [Default::default(); 32]
+//!         )
+//!     )
+//! );
+//! sixlowpan.set_tracer(nettrace);
+//! ip_send.set_tracer(nettrace);
+//! ip_receive.set_tracer(nettrace);
+//! process_console.set_packet_trace(nettrace);
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+
+use kernel::hil::time::{ConvertTicks, Ticks, Time};
+use kernel::utilities::cells::TakeCell;
+use kernel::ErrorCode;
+
+/// Largest number of bytes stored per packet, which holds a whole 802.15.4
+/// frame.
+pub const MAX_SNAP_LEN: usize = 128;
+
+/// Number of bytes stored per packet until the snap length is changed, which
+/// holds the headers of most packets.
+pub const DEFAULT_SNAP_LEN: usize = 64;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Layer {
+    /// 802.15.4 frames carrying 6LoWPAN.
+    Lowpan,
+    /// Uncompressed IPv6 packets.
+    Ip,
+}
+
+impl Layer {
+    /// The pcap link type of the packets of the layer.
+    pub fn link_type(&self) -> u32 {
+        match self {
+            // LINKTYPE_IEEE802_15_4_NOFCS
+            Layer::Lowpan => 230,
+            // LINKTYPE_IPV6
+            Layer::Ip => 229,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Transmit,
+    Receive,
+}
+
+/// A single recorded packet.
+#[derive(Copy, Clone, Debug)]
+pub struct Packet {
+    /// Sequence number, incremented for every packet recorded.
+    pub seq: u32,
+    /// Time the packet was seen, in microseconds of the trace clock.
+    pub timestamp_us: u64,
+    pub layer: Layer,
+    pub direction: Direction,
+    /// Length of the whole packet.
+    pub len: usize,
+    /// First bytes of the packet, valid up to `captured`.
+    pub data: [u8; MAX_SNAP_LEN],
+    pub captured: usize,
+}
+
+impl Default for Packet {
+    fn default() -> Packet {
+        Packet {
+            seq: 0,
+            timestamp_us: 0,
+            layer: Layer::Ip,
+            direction: Direction::Transmit,
+            len: 0,
+            data: [0; MAX_SNAP_LEN],
+            captured: 0,
+        }
+    }
+}
+
+/// Interface used by the network stack to report packets.
+pub trait PacketTracer {
+    /// A packet of `len` bytes was sent or received. `data` holds the first
+    /// bytes of the packet, at most `len`.
+    fn packet(&self, layer: Layer, direction: Direction, data: &[u8], len: usize);
+}
+
+/// Interface used by the process console to inspect the trace.
+pub trait PacketTraceLog {
+    /// Number of recorded packets.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Recorded packet `index`, oldest first.
+    fn entry(&self, index: usize) -> Option<Packet>;
+
+    /// Drop all recorded packets.
+    fn clear(&self);
+
+    /// Start or stop recording new packets.
+    fn set_enabled(&self, enabled: bool);
+
+    fn is_enabled(&self) -> bool;
+
+    /// Number of bytes recorded of new packets.
+    fn snap_len(&self) -> usize;
+
+    /// Set the number of bytes recorded of new packets. Returns INVAL if
+    /// `snap_len` is 0 or larger than `MAX_SNAP_LEN`.
+    fn set_snap_len(&self, snap_len: usize) -> Result<(), ErrorCode>;
+}
+
+pub struct PacketTraceBuffer<'a, T: Time> {
+    time: &'a T,
+    ring: TakeCell<'a, [Packet]>,
+    /// Index of the oldest recorded packet.
+    head: Cell<usize>,
+    len: Cell<usize>,
+    seq: Cell<u32>,
+    snap_len: Cell<usize>,
+    enabled: Cell<bool>,
+}
+
+impl<'a, T: Time> PacketTraceBuffer<'a, T> {
+    pub fn new(time: &'a T, ring: &'a mut [Packet]) -> PacketTraceBuffer<'a, T> {
+        PacketTraceBuffer {
+            time,
+            ring: TakeCell::new(ring),
+            head: Cell::new(0),
+            len: Cell::new(0),
+            seq: Cell::new(0),
+            snap_len: Cell::new(DEFAULT_SNAP_LEN),
+            enabled: Cell::new(true),
+        }
+    }
+
+    /// The current time in microseconds, split into whole milliseconds and
+    /// the remaining ticks so that it does not overflow the conversion.
+    fn now_us(&self) -> u64 {
+        let now = self.time.now();
+        let ms = self.time.ticks_to_ms(now);
+        // Both conversions round down, so this does not wrap.
+        let remainder = now.wrapping_sub(self.time.ticks_from_ms(ms));
+        ms as u64 * 1000 + self.time.ticks_to_us(remainder) as u64
+    }
+
+    fn record(&self, packet: Packet) {
+        self.ring.map(|ring| {
+            if ring.is_empty() {
+                return;
+            }
+            let capacity = ring.len();
+            let len = self.len.get();
+            if len < capacity {
+                ring[(self.head.get() + len) % capacity] = packet;
+                self.len.set(len + 1);
+            } else {
+                // Full, overwrite the oldest packet.
+                ring[self.head.get()] = packet;
+                self.head.set((self.head.get() + 1) % capacity);
+            }
+        });
+    }
+}
+
+impl<'a, T: Time> PacketTracer for PacketTraceBuffer<'a, T> {
+    fn packet(&self, layer: Layer, direction: Direction, data: &[u8], len: usize) {
+        if !self.enabled.get() {
+            return;
+        }
+        let captured = cmp::min(cmp::min(len, data.len()), self.snap_len.get());
+        let mut packet = Packet {
+            seq: self.seq.get(),
+            timestamp_us: self.now_us(),
+            layer,
+            direction,
+            len,
+            captured,
+            ..Default::default()
+        };
+        self.seq.set(self.seq.get().wrapping_add(1));
+        packet.data[..captured].copy_from_slice(&data[..captured]);
+        self.record(packet);
+    }
+}
+
+impl<'a, T: Time> PacketTraceLog for PacketTraceBuffer<'a, T> {
+    fn len(&self) -> usize {
+        self.len.get()
+    }
+
+    fn entry(&self, index: usize) -> Option<Packet> {
+        if index >= self.len.get() {
+            return None;
+        }
+        self.ring
+            .map(|ring| ring[(self.head.get() + index) % ring.len()])
+    }
+
+    fn clear(&self) {
+        self.head.set(0);
+        self.len.set(0);
+    }
+
+    fn set_enabled(&self, enabled: bool) {
+        self.enabled.set(enabled);
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.enabled.get()
+    }
+
+    fn snap_len(&self) -> usize {
+        self.snap_len.get()
+    }
+
+    fn set_snap_len(&self, snap_len: usize) -> Result<(), ErrorCode> {
+        if snap_len == 0 || snap_len > MAX_SNAP_LEN {
+            return Err(ErrorCode::INVAL);
+        }
+        self.snap_len.set(snap_len);
+        Ok(())
+    }
+}
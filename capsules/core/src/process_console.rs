@@ -14,18 +14,35 @@ use core::str;
 use kernel::capabilities::ProcessManagementCapability;
 use kernel::hil::time::ConvertTicks;
 use kernel::utilities::cells::MapCell;
+use kernel::utilities::cells::OptionalCell;
 use kernel::utilities::cells::TakeCell;
 use kernel::ProcessId;
 
+use kernel::crash_report::CrashReport;
 use kernel::debug;
+use kernel::hil::nonvolatile_storage::{NonvolatileStorage, NonvolatileStorageClient};
+use kernel::hil::reset::{Reset, ResetMode};
 use kernel::hil::time::{Alarm, AlarmClient};
 use kernel::hil::uart;
 use kernel::introspection::KernelInfo;
-use kernel::process::{ProcessPrinter, ProcessPrinterContext, State};
+use kernel::platform::chip::ClockIntrospection;
+use kernel::process::{FaultReason, ProcessPrinter, ProcessPrinterContext, State};
+use kernel::reboot_reason::{self, RebootReason};
+use kernel::scheduler::priority::PriorityControl;
+use kernel::syscall::SyscallClass;
 use kernel::utilities::binary_write::BinaryWrite;
 use kernel::ErrorCode;
 use kernel::Kernel;
 
+use crate::bus_trace::{Bus, BusTraceLog, Direction, TRACE_DATA_LEN};
+use crate::console::InputFocus;
+use crate::console_ordered::{LogLevel, LogLevelFilter, PriorityOutput};
+#[cfg(feature = "error_injection")]
+use crate::error_injection::{ErrorInjectionControl, Fault};
+use crate::packet_trace::{self, Layer, PacketTraceLog};
+use crate::syscall_trace::{self, SyscallTraceLog};
+use crate::virtualizers::virtual_uart::UartMuxStatistics;
+
 /// Buffer to hold outgoing data that is passed to the UART hardware.
 pub const WRITE_BUF_LEN: usize = 500;
 /// Buffer responses are initially held in until copied to the TX buffer and
@@ -39,11 +56,46 @@ pub const READ_BUF_LEN: usize = 4;
 pub const COMMAND_BUF_LEN: usize = 32;
 /// Default size for the history command.
 pub const DEFAULT_COMMAND_HISTORY_LEN: usize = 10;
+/// Marks nonvolatile storage that holds a saved command history.
+const HISTORY_MAGIC: [u8; 4] = *b"TKCH";
+/// Bytes of saved command history before the commands: the magic and the
+/// number of commands.
+const HISTORY_HEADER_LEN: usize = HISTORY_MAGIC.len() + 1;
+
+/// Bytes of nonvolatile storage, and of the buffer passed to
+/// `ProcessConsole::set_history_storage()`, needed to save a command history
+/// of `command_history_len` entries.
+pub const fn history_storage_len(command_history_len: usize) -> usize {
+    HISTORY_HEADER_LEN + command_history_len * COMMAND_BUF_LEN
+}
+/// Bytes of the crash report printed at a time by the `crash` command.
+const CRASH_REPORT_CHUNK_LEN: usize = 128;
 
 /// List of valid commands for printing help. Consolidated as these are
 /// displayed in a few different cases.
+#[cfg(feature = "error_injection")]
 const VALID_COMMANDS_STR: &[u8] =
-    b"help status list stop start fault boot terminate process kernel reset panic\r\n";
+    b"help status list stop start fault boot terminate process grants kernel reset bootloader panic inject bustrace strace nettrace uart term focus clocks crash memory irqlat loglevel priority map\r\n";
+#[cfg(not(feature = "error_injection"))]
+const VALID_COMMANDS_STR: &[u8] =
+    b"help status list stop start fault boot terminate process grants kernel reset bootloader panic bustrace strace nettrace uart term focus clocks crash memory irqlat loglevel priority map\r\n";
+
+/// Terminal width assumed until one is set or probed.
+pub const DEFAULT_TERM_WIDTH: usize = 80;
+
+/// Sequence to query the terminal size: save the cursor, move it as far
+/// to the bottom right as possible, request its position and restore it.
+/// The terminal answers with `ESC [ <rows> ; <cols> R`.
+const TERM_SIZE_PROBE: &[u8] = b"\x1B[s\x1B[999;999H\x1B[6n\x1B[u";
+
+/// Clear the screen and move the cursor to the top left.
+const ANSI_CLEAR: &str = "\x1B[2J\x1B[H";
+const ANSI_RESET: &str = "\x1B[0m";
+const ANSI_BOLD: &str = "\x1B[1m";
+const ANSI_RED: &str = "\x1B[31m";
+const ANSI_GREEN: &str = "\x1B[32m";
+const ANSI_YELLOW: &str = "\x1B[33m";
+const ANSI_CYAN: &str = "\x1B[36m";
 
 /// Escape character for ANSI escape sequences.
 const ESC: u8 = '\x1B' as u8;
@@ -66,6 +118,9 @@ const CR: u8 = '\x0D' as u8;
 /// Newline ANSI character
 const NLINE: u8 = '\x0A' as u8;
 
+/// Horizontal tab character, which completes the word before the cursor
+const TAB: u8 = b'\t';
+
 /// Upper limit for ASCII characters
 const ASCII_LIMIT: u8 = 128;
 
@@ -85,10 +140,51 @@ enum WriterState {
         process_id: ProcessId,
         context: Option<ProcessPrinterContext>,
     },
+    ProcessMap {
+        process_id: ProcessId,
+        context: Option<ProcessPrinterContext>,
+    },
     List {
         index: isize,
         total: isize,
     },
+    BusTrace {
+        index: isize,
+        total: isize,
+    },
+    UartStats {
+        index: isize,
+        total: isize,
+    },
+    SyscallTrace {
+        index: isize,
+        total: isize,
+    },
+    PacketTrace {
+        index: isize,
+        total: isize,
+    },
+    PacketTracePcap {
+        index: isize,
+        total: isize,
+        layer: Layer,
+    },
+    Clocks {
+        index: isize,
+        total: isize,
+    },
+    CrashReport {
+        index: isize,
+        total: isize,
+    },
+    Memory {
+        index: isize,
+        total: isize,
+    },
+    IrqLatency {
+        index: isize,
+        total: isize,
+    },
 }
 
 impl Default for WriterState {
@@ -107,6 +203,10 @@ enum EscKey {
     Home,
     End,
     Delete,
+    /// Cursor position report, the answer to `TERM_SIZE_PROBE`.
+    CursorReport {
+        cols: u16,
+    },
 }
 
 /// Escape state machine to check if
@@ -130,7 +230,10 @@ enum EscState {
     /// starts with a bracket character '[' and is waiting
     /// for the next character to determine the corresponding EscKey.
     Bracket,
-    Bracket3,
+
+    /// This state is reached while reading the numeric parameters
+    /// of a sequence such as `ESC [ 3 ~` or `ESC [ 24 ; 80 R`.
+    Params { first: u16, second: Option<u16> },
 
     /// This state is reached when the current character does not match
     /// any of the expected characters in the escape sequence.
@@ -158,8 +261,59 @@ impl EscState {
             (Bracket, b'C') => Complete(Right),
             (Bracket, b'H') => Complete(Home),
             (Bracket, b'F') => Complete(End),
-            (Bracket, b'3') => Bracket3,
-            (Bracket3, b'~') => Complete(Delete),
+            (Bracket, b'0'..=b'9') => Params {
+                first: (data - b'0') as u16,
+                second: None,
+            },
+            (
+                Params {
+                    first,
+                    second: None,
+                },
+                b'0'..=b'9',
+            ) => Params {
+                first: first
+                    .saturating_mul(10)
+                    .saturating_add((data - b'0') as u16),
+                second: None,
+            },
+            (
+                Params {
+                    first,
+                    second: None,
+                },
+                b';',
+            ) => Params {
+                first,
+                second: Some(0),
+            },
+            (
+                Params {
+                    first,
+                    second: Some(second),
+                },
+                b'0'..=b'9',
+            ) => Params {
+                first,
+                second: Some(
+                    second
+                        .saturating_mul(10)
+                        .saturating_add((data - b'0') as u16),
+                ),
+            },
+            (
+                Params {
+                    first: 3,
+                    second: None,
+                },
+                b'~',
+            ) => Complete(Delete),
+            (
+                Params {
+                    second: Some(cols), ..
+                },
+                b'R',
+            ) => Complete(CursorReport { cols }),
             _ => {
                 if EscState::terminator_esc_char(data) {
                     UnrecognizedDone
@@ -173,7 +327,7 @@ impl EscState {
     /// Checks if the escape state machine is in the middle
     /// of an escape sequence
     fn in_progress(&self) -> bool {
-        matches!(self, EscState::Bracket) || matches!(self, EscState::Bracket3)
+        matches!(self, EscState::Bracket) || matches!(self, EscState::Params { .. })
     }
 
     /// Checks if the escape state machine is at the start
@@ -187,6 +341,74 @@ impl EscState {
     }
 }
 
+/// A command that board code adds to the process console with
+/// `ProcessConsole::set_commands()`, e.g. to toggle power rails or run radio
+/// tests.
+pub trait ConsoleCommand {
+    /// Name the command is invoked with. It must be a single word.
+    fn name(&self) -> &str;
+
+    /// One-line description printed by `help <name>`.
+    fn help(&self) -> &str;
+
+    /// Run the command. `args` is the rest of the command line after the
+    /// name, trimmed. The output written to `out` is limited to a few hundred
+    /// bytes, writes beyond that fail.
+    fn execute(&self, args: &str, out: &mut dyn fmt::Write);
+}
+
+/// A `ConsoleCommand` that calls a function with a reference to board state.
+///
+/// ```rust,ignore
+/// let rails = static_init!(
+///     CommandFn<'static, PowerRails>,
+///     CommandFn::new("rails", "rails [on|off]: switch the sensor rails", power_rails, |rails, args, out| {
+///         match args {
+///             "on" => rails.enable(),
+///             "off" => rails.disable(),
+///             _ => {}
+///         }
+///         let _ = write!(out, "rails: {}\r\n", rails.is_enabled());
+///     })
+/// );
+/// ```
+pub struct CommandFn<'a, T: ?Sized> {
+    name: &'static str,
+    help: &'static str,
+    state: &'a T,
+    handler: fn(&T, &str, &mut dyn fmt::Write),
+}
+
+impl<'a, T: ?Sized> CommandFn<'a, T> {
+    pub fn new(
+        name: &'static str,
+        help: &'static str,
+        state: &'a T,
+        handler: fn(&T, &str, &mut dyn fmt::Write),
+    ) -> CommandFn<'a, T> {
+        CommandFn {
+            name,
+            help,
+            state,
+            handler,
+        }
+    }
+}
+
+impl<T: ?Sized> ConsoleCommand for CommandFn<'_, T> {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn help(&self) -> &str {
+        self.help
+    }
+
+    fn execute(&self, args: &str, out: &mut dyn fmt::Write) {
+        (self.handler)(self.state, args, out)
+    }
+}
+
 /// Data structure to hold addresses about how the kernel is stored in memory on
 /// the chip.
 ///
@@ -244,6 +466,13 @@ pub struct ProcessConsole<
     /// received after finishing echoing the last newline character.
     execute: Cell<bool>,
 
+    /// Whether to use ANSI colors and screen control in the output. Off
+    /// for dumb terminals.
+    ansi: Cell<bool>,
+
+    /// Width of the terminal, in characters.
+    term_width: Cell<usize>,
+
     /// Reference to the kernel object so we can access process state.
     kernel: &'static Kernel,
 
@@ -253,6 +482,58 @@ pub struct ProcessConsole<
     /// Function used to reset the device in bootloader mode
     reset_function: Option<fn() -> !>,
 
+    /// Chip reset used by `reset`, if there is no `reset_function`, and by
+    /// `bootloader`.
+    reset_controller: OptionalCell<&'a dyn Reset>,
+
+    /// Error-injection shims that can be controlled with the `inject`
+    /// command.
+    #[cfg(feature = "error_injection")]
+    error_injectors: OptionalCell<&'a [&'a dyn ErrorInjectionControl]>,
+
+    /// Bus transaction trace dumped by the `bustrace` command.
+    bus_trace: OptionalCell<&'a dyn BusTraceLog>,
+
+    /// System call trace dumped by the `strace` command.
+    syscall_trace: OptionalCell<&'a dyn SyscallTraceLog>,
+
+    /// Network packet trace dumped by the `nettrace` command.
+    packet_trace: OptionalCell<&'a dyn PacketTraceLog>,
+
+    /// UART mux whose counters the `uart` command reports.
+    uart_stats: OptionalCell<&'a dyn UartMuxStatistics>,
+
+    /// Console whose input focus the `focus` command selects.
+    input_focus: OptionalCell<&'a dyn InputFocus>,
+
+    /// Chip peripheral clocks listed by the `clocks` command.
+    clocks: OptionalCell<&'a dyn ClockIntrospection>,
+
+    /// Crash report of a previous boot, printed by the `crash` command.
+    crash_report: OptionalCell<&'a dyn CrashReport>,
+
+    /// Console whose log level filter the `loglevel` command sets.
+    log_filter: OptionalCell<&'a dyn LogLevelFilter>,
+
+    /// Scheduler whose process priorities the `priority` command sets.
+    priority_control: OptionalCell<&'a dyn PriorityControl>,
+
+    /// Additional commands installed by the board.
+    commands: OptionalCell<&'a [&'a dyn ConsoleCommand]>,
+
+    /// Nonvolatile storage the command history is saved to.
+    history_storage: OptionalCell<&'a dyn NonvolatileStorage<'a>>,
+
+    /// Address of the saved command history in `history_storage`.
+    history_storage_address: Cell<usize>,
+
+    /// Buffer for reading and writing the saved command history.
+    history_storage_buffer: TakeCell<'a, [u8]>,
+
+    /// The history changed while it was being written, so it needs another
+    /// write.
+    history_dirty: Cell<bool>,
+
     /// This capsule needs to use potentially dangerous APIs related to
     /// processes, and requires a capability to access those APIs.
     capability: C,
@@ -341,8 +622,10 @@ impl<'a, const COMMAND_HISTORY_LEN: usize> CommandHistory<'a, COMMAND_HISTORY_LE
         }
     }
 
-    /// Creates an empty space in the history for the next command
-    fn make_space(&mut self, cmd: &[u8]) {
+    /// Creates an empty space in the history for the next command. Returns
+    /// whether `cmd` was added, which it is not if it repeats the last
+    /// command.
+    fn make_space(&mut self, cmd: &[u8]) -> bool {
         let mut cmd_arr = [0; COMMAND_BUF_LEN];
         cmd_arr.copy_from_slice(cmd);
 
@@ -350,6 +633,44 @@ impl<'a, const COMMAND_HISTORY_LEN: usize> CommandHistory<'a, COMMAND_HISTORY_LE
             self.cmds.rotate_right(1);
             self.cmds[0].clear();
             self.cmds[1].write(&cmd_arr);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Serialize the saved commands, newest first, into `buf`. Returns the
+    /// number of bytes used.
+    fn save(&self, buf: &mut [u8]) -> usize {
+        let count = self.cmds[1..].iter().take_while(|cmd| cmd.len > 0).count();
+        buf[..HISTORY_MAGIC.len()].copy_from_slice(&HISTORY_MAGIC);
+        buf[HISTORY_MAGIC.len()] = count as u8;
+        for (cmd, chunk) in self.cmds[1..=count]
+            .iter()
+            .zip(buf[HISTORY_HEADER_LEN..].chunks_mut(COMMAND_BUF_LEN))
+        {
+            chunk.copy_from_slice(&cmd.buf);
+        }
+        HISTORY_HEADER_LEN + count * COMMAND_BUF_LEN
+    }
+
+    /// Replace the saved commands with the ones serialized in `buf` by
+    /// `save()`. Storage that does not hold a saved history is ignored.
+    fn load(&mut self, buf: &[u8]) {
+        if buf.len() < HISTORY_HEADER_LEN || buf[..HISTORY_MAGIC.len()] != HISTORY_MAGIC {
+            return;
+        }
+        let count = cmp::min(
+            buf[HISTORY_MAGIC.len()] as usize,
+            COMMAND_HISTORY_LEN.saturating_sub(1),
+        );
+        for (cmd, chunk) in self.cmds[1..=count]
+            .iter_mut()
+            .zip(buf[HISTORY_HEADER_LEN..].chunks_exact(COMMAND_BUF_LEN))
+        {
+            let mut cmd_arr = [EOL; COMMAND_BUF_LEN];
+            cmd_arr.copy_from_slice(chunk);
+            cmd.write(&cmd_arr);
         }
     }
 
@@ -418,6 +739,9 @@ impl ConsoleWriter {
 impl fmt::Write for ConsoleWriter {
     fn write_str(&mut self, s: &str) -> fmt::Result {
         let curr = (s).as_bytes().len();
+        if curr > self.buf.len() - self.size {
+            return Err(fmt::Error);
+        }
         self.buf[self.size..self.size + curr].copy_from_slice(&(s).as_bytes()[..]);
         self.size += curr;
         Ok(())
@@ -435,6 +759,15 @@ impl BinaryWrite for ConsoleWriter {
     }
 }
 
+/// Tell the next boot that the user reset the chip.
+fn record_reboot(mode: ResetMode) {
+    let reason = match mode {
+        ResetMode::Normal => RebootReason::UserCommand,
+        ResetMode::Bootloader => RebootReason::Update,
+    };
+    reboot_reason::record(reason, format_args!("process console"));
+}
+
 impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCapability>
     ProcessConsole<'a, COMMAND_HISTORY_LEN, A, C>
 {
@@ -476,13 +809,136 @@ impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCap
 
             running: Cell::new(false),
             execute: Cell::new(false),
+            ansi: Cell::new(false),
+            term_width: Cell::new(DEFAULT_TERM_WIDTH),
             kernel: kernel,
             kernel_addresses: kernel_addresses,
             reset_function: reset_function,
+            reset_controller: OptionalCell::empty(),
+            #[cfg(feature = "error_injection")]
+            error_injectors: OptionalCell::empty(),
+            bus_trace: OptionalCell::empty(),
+            syscall_trace: OptionalCell::empty(),
+            packet_trace: OptionalCell::empty(),
+            uart_stats: OptionalCell::empty(),
+            input_focus: OptionalCell::empty(),
+            clocks: OptionalCell::empty(),
+            crash_report: OptionalCell::empty(),
+            log_filter: OptionalCell::empty(),
+            priority_control: OptionalCell::empty(),
+            history_storage: OptionalCell::empty(),
+            history_storage_address: Cell::new(0),
+            history_storage_buffer: TakeCell::empty(),
+            history_dirty: Cell::new(false),
+            commands: OptionalCell::empty(),
             capability: capability,
         }
     }
 
+    /// Install additional commands. They take precedence over the built-in
+    /// commands with the same name.
+    pub fn set_commands(&self, commands: &'a [&'a dyn ConsoleCommand]) {
+        self.commands.set(commands);
+    }
+
+    /// Register the error-injection shims that the `inject` command
+    /// controls.
+    #[cfg(feature = "error_injection")]
+    pub fn set_error_injectors(&self, injectors: &'a [&'a dyn ErrorInjectionControl]) {
+        self.error_injectors.set(injectors);
+    }
+
+    /// Register the chip reset used by the `reset` and `bootloader`
+    /// commands.
+    pub fn set_reset(&self, reset: &'a dyn Reset) {
+        self.reset_controller.set(reset);
+    }
+
+    /// Register the bus transaction trace that the `bustrace` command dumps.
+    pub fn set_bus_trace(&self, trace: &'a dyn BusTraceLog) {
+        self.bus_trace.set(trace);
+    }
+
+    /// Register the system call trace that the `strace` command dumps.
+    pub fn set_syscall_trace(&self, trace: &'a dyn SyscallTraceLog) {
+        self.syscall_trace.set(trace);
+    }
+
+    /// Register the network packet trace that the `nettrace` command dumps.
+    pub fn set_packet_trace(&self, trace: &'a dyn PacketTraceLog) {
+        self.packet_trace.set(trace);
+    }
+
+    /// Register the UART mux whose counters the `uart` command reports.
+    pub fn set_uart_stats(&self, stats: &'a dyn UartMuxStatistics) {
+        self.uart_stats.set(stats);
+    }
+
+    /// Register the console whose input focus the `focus` command selects.
+    pub fn set_input_focus(&self, focus: &'a dyn InputFocus) {
+        self.input_focus.set(focus);
+    }
+
+    /// Register the chip peripheral clocks that the `clocks` command lists.
+    pub fn set_clock_introspection(&self, clocks: &'a dyn ClockIntrospection) {
+        self.clocks.set(clocks);
+    }
+
+    /// Register the stored crash report that the `crash` command prints and
+    /// clears.
+    pub fn set_crash_report(&self, report: &'a dyn CrashReport) {
+        self.crash_report.set(report);
+    }
+
+    /// Register the console whose log level filter the `loglevel` command
+    /// sets.
+    pub fn set_log_level_filter(&self, filter: &'a dyn LogLevelFilter) {
+        self.log_filter.set(filter);
+    }
+
+    /// Register the scheduler whose process priorities the `priority`
+    /// command sets.
+    pub fn set_priority_control(&self, control: &'a dyn PriorityControl) {
+        self.priority_control.set(control);
+    }
+
+    /// Save the command history at `address` of `storage` whenever a command
+    /// is added to it, and load the history saved there by a previous boot.
+    /// `buffer` and the storage region must hold at least
+    /// `history_storage_len(COMMAND_HISTORY_LEN)` bytes.
+    pub fn set_history_storage(
+        &'a self,
+        storage: &'a dyn NonvolatileStorage<'a>,
+        address: usize,
+        buffer: &'a mut [u8],
+    ) -> Result<(), ErrorCode> {
+        let len = history_storage_len(COMMAND_HISTORY_LEN);
+        if COMMAND_HISTORY_LEN < 2 || COMMAND_HISTORY_LEN > u8::MAX as usize {
+            return Err(ErrorCode::NOSUPPORT);
+        }
+        if buffer.len() < len {
+            return Err(ErrorCode::SIZE);
+        }
+        storage.set_client(self);
+        self.history_storage.set(storage);
+        self.history_storage_address.set(address);
+        storage.read(buffer, address, len)
+    }
+
+    /// Write the command history to the history storage, if there is one.
+    fn save_history(&self) {
+        self.history_storage.map(|storage| {
+            match self.history_storage_buffer.take() {
+                Some(buffer) => {
+                    let len = self.command_history.map_or(0, |ht| ht.save(buffer));
+                    let _ = storage.write(buffer, self.history_storage_address.get(), len);
+                }
+                // A read or write is in progress, so save once it is done.
+                None => self.history_dirty.set(true),
+            }
+        });
+    }
+
     /// Start the process console listening for user commands.
     pub fn start(&self) -> Result<(), ErrorCode> {
         if self.running.get() == false {
@@ -519,8 +975,7 @@ impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCap
         let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
 
         let _ = self.write_bytes(b"Welcome to the process console.\r\n");
-        let _ = self.write_bytes(b"Valid commands are: ");
-        let _ = self.write_bytes(VALID_COMMANDS_STR);
+        self.write_valid_commands();
         self.prompt();
     }
 
@@ -541,6 +996,13 @@ impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCap
                 process_id,
                 context,
             },
+            WriterState::ProcessMap {
+                process_id,
+                context,
+            } => WriterState::ProcessMap {
+                process_id,
+                context,
+            },
             WriterState::List { index, total } => {
                 // Next state just increments index, unless we are at end in
                 // which next state is just the empty state.
@@ -553,6 +1015,101 @@ impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCap
                     }
                 }
             }
+            WriterState::BusTrace { index, total } => {
+                if index + 1 == total {
+                    WriterState::Empty
+                } else {
+                    WriterState::BusTrace {
+                        index: index + 1,
+                        total,
+                    }
+                }
+            }
+            WriterState::UartStats { index, total } => {
+                if index + 1 == total {
+                    WriterState::Empty
+                } else {
+                    WriterState::UartStats {
+                        index: index + 1,
+                        total,
+                    }
+                }
+            }
+            WriterState::SyscallTrace { index, total } => {
+                if index + 1 == total {
+                    WriterState::Empty
+                } else {
+                    WriterState::SyscallTrace {
+                        index: index + 1,
+                        total,
+                    }
+                }
+            }
+            WriterState::PacketTrace { index, total } => {
+                if index + 1 == total {
+                    WriterState::Empty
+                } else {
+                    WriterState::PacketTrace {
+                        index: index + 1,
+                        total,
+                    }
+                }
+            }
+            WriterState::PacketTracePcap {
+                index,
+                total,
+                layer,
+            } => {
+                if index + 1 == total {
+                    WriterState::Empty
+                } else {
+                    WriterState::PacketTracePcap {
+                        index: index + 1,
+                        total,
+                        layer,
+                    }
+                }
+            }
+            WriterState::Clocks { index, total } => {
+                if index + 1 == total {
+                    WriterState::Empty
+                } else {
+                    WriterState::Clocks {
+                        index: index + 1,
+                        total,
+                    }
+                }
+            }
+            WriterState::CrashReport { index, total } => {
+                if index + 1 == total {
+                    WriterState::Empty
+                } else {
+                    WriterState::CrashReport {
+                        index: index + 1,
+                        total,
+                    }
+                }
+            }
+            WriterState::Memory { index, total } => {
+                if index + 1 == total {
+                    WriterState::Empty
+                } else {
+                    WriterState::Memory {
+                        index: index + 1,
+                        total,
+                    }
+                }
+            }
+            WriterState::IrqLatency { index, total } => {
+                if index + 1 == total {
+                    WriterState::Empty
+                } else {
+                    WriterState::IrqLatency {
+                        index: index + 1,
+                        total,
+                    }
+                }
+            }
             WriterState::Empty => WriterState::Empty,
         }
     }
@@ -687,6 +1244,16 @@ impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCap
                         }
                     });
             }
+            WriterState::ProcessMap {
+                process_id,
+                context,
+            } => {
+                if self.print_map_chunk(process_id, context) {
+                    // As with `ProcessPrint`, the state is now Empty without
+                    // going through this match again, so print the prompt here.
+                    self.prompt();
+                }
+            }
             WriterState::List { index, total: _ } => {
                 let mut local_index = -1;
                 self.kernel
@@ -699,11 +1266,25 @@ impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCap
                             let process_id = process.processid();
                             let (grants_used, grants_total) =
                                 info.number_app_grant_uses(process_id, &self.capability);
+                            let state = process.get_state();
+                            let (color, reset) = if self.ansi.get() {
+                                let color = match state {
+                                    State::Running => ANSI_GREEN,
+                                    State::Yielded => ANSI_CYAN,
+                                    State::StoppedRunning | State::StoppedYielded => ANSI_YELLOW,
+                                    State::Faulted | State::CredentialsFailed => ANSI_RED,
+                                    _ => "",
+                                };
+                                (color, ANSI_RESET)
+                            } else {
+                                ("", "")
+                            };
+                            let name_width = self.list_name_width();
                             let mut console_writer = ConsoleWriter::new();
                             let _ = write(
                                 &mut console_writer,
                                 format_args!(
-                                    " {:<7?}{:<20}{:6}{:10}{:10}  {:2}/{:2}   {:?}\r\n",
+                                    " {:<7?}{:<w$.w$}{:6}{:10}{:10}  {:2}/{:2}   {}{:?}{}\r\n",
                                     process_id,
                                     pname,
                                     process.debug_timeslice_expiration_count(),
@@ -711,7 +1292,10 @@ impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCap
                                     process.get_restart_count(),
                                     grants_used,
                                     grants_total,
-                                    process.get_state(),
+                                    color,
+                                    state,
+                                    reset,
+                                    w = name_width,
                                 ),
                             );
 
@@ -719,62 +1303,383 @@ impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCap
                         }
                     });
             }
-            WriterState::Empty => {
-                self.prompt();
-            }
-            _ => {}
-        }
-    }
-
-    // Process the command in the command buffer and clear the buffer.
-    fn read_command(&self) {
-        self.command_buffer.map(|command| {
-            let mut terminator = 0;
-            let len = command.len();
-            for i in 0..len {
-                if command[i] == 0 {
-                    terminator = i;
-                    break;
-                }
-            }
-
-            // A command is valid only if it starts inside the buffer,
-            // ends before the beginning of the buffer, and ends after
-            // it starts.
-            if terminator > 0 {
-                let cmd_str = str::from_utf8(&command[0..terminator]);
-
-                match cmd_str {
-                    Ok(s) => {
-                        let clean_str = s.trim();
-
-                        // Check if the command history is enabled by the user
-                        // and check if the command is not full of whitespaces
-                        if COMMAND_HISTORY_LEN > 1 {
-                            if clean_str.len() > 0 {
-                                self.command_history.map(|ht| {
-                                    ht.make_space(&command);
-                                });
+            WriterState::BusTrace { index, total: _ } => {
+                self.bus_trace.map(|trace| {
+                    trace.entry(index as usize).map(|transaction| {
+                        let bus = match transaction.bus {
+                            Bus::I2C => "i2c",
+                            Bus::SMBus => "smbus",
+                            Bus::Spi => "spi",
+                        };
+                        let direction = match transaction.direction {
+                            Direction::Write => "W",
+                            Direction::Read => "R",
+                            Direction::WriteRead => "WR",
+                        };
+                        let mut console_writer = ConsoleWriter::new();
+                        let _ = write(
+                            &mut console_writer,
+                            format_args!(
+                                " {:<7}{:<6}{:#04x}  {:<4}{:>5}{:>5}{:>9}  ",
+                                transaction.seq,
+                                bus,
+                                transaction.address,
+                                direction,
+                                transaction.write_len,
+                                transaction.read_len,
+                                transaction.duration_us,
+                            ),
+                        );
+                        let written = cmp::min(transaction.write_len, TRACE_DATA_LEN);
+                        for byte in &transaction.write_data[..written] {
+                            let _ = write(&mut console_writer, format_args!("{:02x}", byte));
+                        }
+                        if transaction.direction != Direction::Write {
+                            let _ = write(&mut console_writer, format_args!(" -> "));
+                            let read = cmp::min(transaction.read_len, TRACE_DATA_LEN);
+                            for byte in &transaction.read_data[..read] {
+                                let _ = write(&mut console_writer, format_args!("{:02x}", byte));
                             }
                         }
-
-                        if clean_str.starts_with("help") {
-                            let _ = self.write_bytes(b"Welcome to the process console.\r\n");
-                            let _ = self.write_bytes(b"Valid commands are: ");
-                            let _ = self.write_bytes(VALID_COMMANDS_STR);
-                        } else if clean_str.starts_with("start") {
-                            let argument = clean_str.split_whitespace().nth(1);
+                        let _ = write(
+                            &mut console_writer,
+                            format_args!("  {:?}\r\n", transaction.status),
+                        );
+                        let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+                    });
+                });
+            }
+            WriterState::SyscallTrace { index, total: _ } => {
+                self.syscall_trace.map(|trace| {
+                    if let Some(event) = trace.entry(index as usize) {
+                        let call = match event.class {
+                            SyscallClass::Yield => "yield",
+                            SyscallClass::Subscribe => "subscribe",
+                            SyscallClass::Command => "command",
+                            SyscallClass::ReadWriteAllow => "allow-rw",
+                            SyscallClass::ReadOnlyAllow => "allow-ro",
+                            SyscallClass::UserspaceReadableAllow => "allow-ur",
+                            SyscallClass::Memop => "memop",
+                            SyscallClass::Exit => "exit",
+                        };
+                        let mut console_writer = ConsoleWriter::new();
+                        let _ = write(
+                            &mut console_writer,
+                            format_args!(
+                                " {:<7}{:>9}  {:<5}{:<10}{:<#9x}{:<4}{:<#12x}{:<#12x}{:>7}  ",
+                                event.seq,
+                                event.timestamp_ms,
+                                event.process,
+                                call,
+                                event.driver_number,
+                                event.subdriver_number,
+                                event.args[0],
+                                event.args[1],
+                                event.duration_us,
+                            ),
+                        );
+                        let _ = match event.status {
+                            syscall_trace::Status::NoReturn => {
+                                write(&mut console_writer, format_args!("-\r\n"))
+                            }
+                            syscall_trace::Status::Success => {
+                                write(&mut console_writer, format_args!("Ok\r\n"))
+                            }
+                            syscall_trace::Status::Failure(error) => {
+                                write(&mut console_writer, format_args!("{:?}\r\n", error))
+                            }
+                        };
+                        let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+                    }
+                });
+            }
+            WriterState::PacketTrace { index, total: _ } => {
+                self.packet_trace.map(|trace| {
+                    if let Some(packet) = trace.entry(index as usize) {
+                        let layer = match packet.layer {
+                            Layer::Lowpan => "lowpan",
+                            Layer::Ip => "ip",
+                        };
+                        let direction = match packet.direction {
+                            packet_trace::Direction::Transmit => "TX",
+                            packet_trace::Direction::Receive => "RX",
+                        };
+                        let mut console_writer = ConsoleWriter::new();
+                        let _ = write(
+                            &mut console_writer,
+                            format_args!(
+                                " {:<7}{:>12}  {:<7}{:<4}{:>5}  ",
+                                packet.seq, packet.timestamp_us, layer, direction, packet.len,
+                            ),
+                        );
+                        for byte in &packet.data[..packet.captured] {
+                            let _ = write(&mut console_writer, format_args!("{:02x}", byte));
+                        }
+                        let _ = write(&mut console_writer, format_args!("\r\n"));
+                        let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+                    }
+                });
+            }
+            WriterState::PacketTracePcap {
+                index,
+                total,
+                layer,
+            } => {
+                // Skip the packets of the other layer, which do not fit the
+                // link type of the file.
+                let next = self.packet_trace.and_then(|trace| {
+                    (index..total).find_map(|index| {
+                        trace
+                            .entry(index as usize)
+                            .filter(|packet| packet.layer == layer)
+                            .map(|packet| (index, packet))
+                    })
+                });
+                match next {
+                    Some((index, packet)) => {
+                        self.writer_state.replace(WriterState::PacketTracePcap {
+                            index,
+                            total,
+                            layer,
+                        });
+                        // pcap record header, followed by the packet.
+                        let mut console_writer = ConsoleWriter::new();
+                        let record = [
+                            (packet.timestamp_us / 1_000_000) as u32,
+                            (packet.timestamp_us % 1_000_000) as u32,
+                            packet.captured as u32,
+                            packet.len as u32,
+                        ];
+                        for field in record {
+                            for byte in field.to_le_bytes() {
+                                let _ = write(&mut console_writer, format_args!("{:02x}", byte));
+                            }
+                        }
+                        for byte in &packet.data[..packet.captured] {
+                            let _ = write(&mut console_writer, format_args!("{:02x}", byte));
+                        }
+                        let _ = write(&mut console_writer, format_args!("\r\n"));
+                        let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+                    }
+                    None => {
+                        self.writer_state.replace(WriterState::Empty);
+                        self.prompt();
+                    }
+                }
+            }
+            WriterState::UartStats { index, total: _ } => {
+                self.uart_stats.map(|stats| {
+                    if let Some(device) = stats.device_stats(index as usize) {
+                        let name = if device.name.is_empty() {
+                            "-"
+                        } else {
+                            device.name
+                        };
+                        let mut console_writer = ConsoleWriter::new();
+                        let _ = write(
+                            &mut console_writer,
+                            format_args!(
+                                " {:<4}{:<16}{:>10}{:>10}{:>9}{:>8}{:>7}\r\n",
+                                index,
+                                name,
+                                device.tx_bytes,
+                                device.rx_bytes,
+                                device.rx_dropped,
+                                device.tx_errors,
+                                device.tx_queue_max,
+                            ),
+                        );
+                        let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+                    }
+                });
+            }
+            WriterState::Clocks { index, total: _ } => {
+                self.clocks.map(|clocks| {
+                    if let Some(clock) = clocks.peripheral_clock(index as usize) {
+                        let mut console_writer = ConsoleWriter::new();
+                        let _ = write(
+                            &mut console_writer,
+                            format_args!(
+                                " {:<12}{:<5}",
+                                clock.name,
+                                if clock.enabled { "on" } else { "off" },
+                            ),
+                        );
+                        let _ = match clock.frequency {
+                            Some(frequency) => {
+                                write(&mut console_writer, format_args!("{:>11}", frequency))
+                            }
+                            None => write(&mut console_writer, format_args!("{:>11}", "?")),
+                        };
+                        let _ = write(&mut console_writer, format_args!("  {}\r\n", clock.owner));
+                        let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+                    }
+                });
+            }
+            WriterState::CrashReport { index, total: _ } => {
+                self.crash_report.map(|report| {
+                    let mut chunk = [0; CRASH_REPORT_CHUNK_LEN];
+                    let len =
+                        report.read_report(index as usize * CRASH_REPORT_CHUNK_LEN, &mut chunk);
+                    let _ = self.write_bytes(&chunk[..len]);
+                });
+            }
+            WriterState::Memory { index, total: _ } => {
+                let mut local_index = -1;
+                self.kernel
+                    .process_each_capability(&self.capability, |process| {
+                        local_index += 1;
+                        if local_index == index {
+                            let info: KernelInfo = KernelInfo::new(self.kernel);
+                            let process_id = process.processid();
+                            let high_water = process.debug_memory_high_water();
+                            let grant_high_water = info
+                                .app_grant_stats(process_id, &self.capability)
+                                .map_or(0, |stats| stats.high_water);
+                            let mut console_writer = ConsoleWriter::new();
+                            let _ = write(
+                                &mut console_writer,
+                                format_args!(
+                                    " {:<7?}{:<w$.w$}",
+                                    process_id,
+                                    process.get_process_name(),
+                                    w = self.list_name_width(),
+                                ),
+                            );
+                            for mark in [high_water.stack, high_water.heap] {
+                                let _ = match mark {
+                                    Some(bytes) => {
+                                        write(&mut console_writer, format_args!("{:>7}", bytes))
+                                    }
+                                    None => write(&mut console_writer, format_args!("{:>7}", "?")),
+                                };
+                            }
+                            let _ = write(
+                                &mut console_writer,
+                                format_args!(
+                                    "{:>10}{:>8}{:>11}\r\n",
+                                    high_water.app_memory, grant_high_water, high_water.allocated,
+                                ),
+                            );
+                            let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+                        }
+                    });
+            }
+            WriterState::IrqLatency { index, total: _ } => {
+                if let Some(recorder) = kernel::irq_latency::recorder() {
+                    // Only interrupts that fired are listed.
+                    let irq = (0..recorder.num_irqs())
+                        .filter(|irq| recorder.stats(*irq).is_some_and(|stats| stats.count > 0))
+                        .nth(index as usize);
+                    if let Some((irq, stats)) =
+                        irq.and_then(|irq| recorder.stats(irq).map(|stats| (irq, stats)))
+                    {
+                        let us = |cycles| recorder.cycles_to_us(cycles);
+                        let mut console_writer = ConsoleWriter::new();
+                        let _ = write(
+                            &mut console_writer,
+                            format_args!(
+                                " {:<5}{:>8}{:>8}{:>8}{:>8}{:>8} ",
+                                irq,
+                                stats.count,
+                                us(stats.min),
+                                us(stats.average()),
+                                us(stats.max),
+                                us(stats.max - stats.min),
+                            ),
+                        );
+                        for bucket in stats.buckets {
+                            let _ = write(&mut console_writer, format_args!(" {}", bucket));
+                        }
+                        let _ = write(&mut console_writer, format_args!("\r\n"));
+                        let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+                    }
+                }
+            }
+            WriterState::Empty => {
+                self.prompt();
+            }
+            _ => {}
+        }
+    }
+
+    // Process the command in the command buffer and clear the buffer.
+    fn read_command(&self) {
+        self.command_buffer.map(|command| {
+            let mut terminator = 0;
+            let len = command.len();
+            for i in 0..len {
+                if command[i] == 0 {
+                    terminator = i;
+                    break;
+                }
+            }
+
+            // A command is valid only if it starts inside the buffer,
+            // ends before the beginning of the buffer, and ends after
+            // it starts.
+            if terminator > 0 {
+                let cmd_str = str::from_utf8(&command[0..terminator]);
+
+                match cmd_str {
+                    Ok(s) => {
+                        let clean_str = s.trim();
+
+                        // Check if the command history is enabled by the user
+                        // and check if the command is not full of whitespaces
+                        if COMMAND_HISTORY_LEN > 1 {
+                            if !clean_str.is_empty()
+                                && self
+                                    .command_history
+                                    .map_or(false, |ht| ht.make_space(command))
+                            {
+                                self.save_history();
+                            }
+                        }
+
+                        let (name, args) = clean_str
+                            .split_once(char::is_whitespace)
+                            .map_or((clean_str, ""), |(name, args)| (name, args.trim()));
+                        let command = self.commands.and_then(|commands| {
+                            commands.iter().find(|command| command.name() == name)
+                        });
+
+                        if let Some(command) = command {
+                            let mut console_writer = ConsoleWriter::new();
+                            command.execute(args, &mut console_writer);
+                            let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+                        } else if clean_str.starts_with("help") {
+                            self.help_command(args);
+                        } else if clean_str.starts_with("start") {
+                            let argument = clean_str.split_whitespace().nth(1);
                             argument.map(|name| {
                                 self.kernel
                                     .process_each_capability(&self.capability, |proc| {
                                         let proc_name = proc.get_process_name();
                                         if proc_name == name {
-                                            proc.resume();
                                             let mut console_writer = ConsoleWriter::new();
-                                            let _ = write(
-                                                &mut console_writer,
-                                                format_args!("Process {} resumed.\r\n", name),
-                                            );
+                                            match proc.get_state() {
+                                                State::StoppedRunning | State::StoppedYielded => {
+                                                    proc.resume();
+                                                    let _ = write(
+                                                        &mut console_writer,
+                                                        format_args!(
+                                                            "Process {} resumed. Queued tasks: {}\r\n",
+                                                            name,
+                                                            proc.pending_tasks()
+                                                        ),
+                                                    );
+                                                }
+                                                _ => {
+                                                    let _ = write(
+                                                        &mut console_writer,
+                                                        format_args!(
+                                                            "Process {} is not stopped.\r\n",
+                                                            name
+                                                        ),
+                                                    );
+                                                }
+                                            }
 
                                             let _ = self.write_bytes(
                                                 &(console_writer.buf)[..console_writer.size],
@@ -789,12 +1694,28 @@ impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCap
                                     .process_each_capability(&self.capability, |proc| {
                                         let proc_name = proc.get_process_name();
                                         if proc_name == name {
-                                            proc.stop();
                                             let mut console_writer = ConsoleWriter::new();
-                                            let _ = write(
-                                                &mut console_writer,
-                                                format_args!("Process {} stopped\r\n", proc_name),
-                                            );
+                                            match proc.get_state() {
+                                                State::Running | State::Yielded => {
+                                                    proc.stop();
+                                                    let _ = write(
+                                                        &mut console_writer,
+                                                        format_args!(
+                                                            "Process {} stopped\r\n",
+                                                            proc_name
+                                                        ),
+                                                    );
+                                                }
+                                                state => {
+                                                    let _ = write(
+                                                        &mut console_writer,
+                                                        format_args!(
+                                                            "Process {} cannot be stopped, it is {:?}.\r\n",
+                                                            proc_name, state
+                                                        ),
+                                                    );
+                                                }
+                                            }
 
                                             let _ = self.write_bytes(
                                                 &(console_writer.buf)[..console_writer.size],
@@ -809,7 +1730,7 @@ impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCap
                                     .process_each_capability(&self.capability, |proc| {
                                         let proc_name = proc.get_process_name();
                                         if proc_name == name {
-                                            proc.set_fault_state();
+                                            proc.set_fault_state(FaultReason::Requested);
                                             let mut console_writer = ConsoleWriter::new();
                                             let _ = write(
                                                 &mut console_writer,
@@ -845,6 +1766,8 @@ impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCap
                                         }
                                     });
                             });
+                        } else if clean_str.starts_with("bootloader") {
+                            self.reset_command(ResetMode::Bootloader);
                         } else if clean_str.starts_with("boot") {
                             let argument = clean_str.split_whitespace().nth(1);
                             argument.map(|name| {
@@ -859,8 +1782,25 @@ impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCap
                                     });
                             });
                         } else if clean_str.starts_with("list") {
-                            let _ = self.write_bytes(b" PID    Name                Quanta  ");
-                            let _ = self.write_bytes(b"Syscalls  Restarts  Grants  State\r\n");
+                            let (clear, bold, reset) = if self.ansi.get() {
+                                // Redraw the list from the top of the screen.
+                                (ANSI_CLEAR, ANSI_BOLD, ANSI_RESET)
+                            } else {
+                                ("", "", "")
+                            };
+                            let mut console_writer = ConsoleWriter::new();
+                            let _ = write(
+                                &mut console_writer,
+                                format_args!(
+                                    "{}{} PID    {:<w$}Quanta  Syscalls  Restarts  Grants  State{}\r\n",
+                                    clear,
+                                    bold,
+                                    "Name",
+                                    reset,
+                                    w = self.list_name_width(),
+                                ),
+                            );
+                            let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
 
                             // Count the number of current processes.
                             let mut count = 0;
@@ -942,6 +1882,8 @@ impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCap
                                         }
                                     });
                             });
+                        } else if clean_str.starts_with("grants") {
+                            self.grants_command(clean_str);
                         } else if clean_str.starts_with("kernel") {
                             let mut console_writer = ConsoleWriter::new();
                             let _ = write(
@@ -960,19 +1902,48 @@ impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCap
                             // start state.
                             self.writer_state.replace(WriterState::KernelStart);
                         } else if clean_str.starts_with("reset") {
-                            self.reset_function.map_or_else(
-                                || {
-                                    let _ = self.write_bytes(b"Reset function is not implemented");
-                                },
-                                |f| {
-                                    f();
-                                },
-                            );
+                            match self.reset_function {
+                                Some(f) => {
+                                    record_reboot(ResetMode::Normal);
+                                    f()
+                                }
+                                None => self.reset_command(ResetMode::Normal),
+                            }
                         } else if clean_str.starts_with("panic") {
                             panic!("Process Console forced a kernel panic.");
+                        } else if cfg!(feature = "error_injection")
+                            && clean_str.starts_with("inject")
+                        {
+                            #[cfg(feature = "error_injection")]
+                            self.inject_command(clean_str);
+                        } else if clean_str.starts_with("bustrace") {
+                            self.bus_trace_command(clean_str);
+                        } else if clean_str.starts_with("strace") {
+                            self.syscall_trace_command(clean_str);
+                        } else if clean_str.starts_with("nettrace") {
+                            self.packet_trace_command(clean_str);
+                        } else if clean_str.starts_with("uart") {
+                            self.uart_command(clean_str);
+                        } else if clean_str.starts_with("term") {
+                            self.term_command(clean_str);
+                        } else if clean_str.starts_with("focus") {
+                            self.focus_command(clean_str);
+                        } else if clean_str.starts_with("clocks") {
+                            self.clocks_command();
+                        } else if clean_str.starts_with("crash") {
+                            self.crash_command(clean_str);
+                        } else if clean_str.starts_with("memory") {
+                            self.memory_command();
+                        } else if clean_str.starts_with("irqlat") {
+                            self.irq_latency_command(clean_str);
+                        } else if clean_str.starts_with("loglevel") {
+                            self.log_level_command(clean_str);
+                        } else if clean_str.starts_with("map") {
+                            self.map_command(clean_str);
+                        } else if clean_str.starts_with("priority") {
+                            self.priority_command(clean_str);
                         } else {
-                            let _ = self.write_bytes(b"Valid commands are: ");
-                            let _ = self.write_bytes(VALID_COMMANDS_STR);
+                            self.write_valid_commands();
                         }
                     }
                     Err(_e) => {
@@ -995,8 +1966,933 @@ impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCap
         }
     }
 
+    /// Reset the chip with the registered reset controller.
+    fn reset_command(&self, mode: ResetMode) {
+        match self.reset_controller.extract() {
+            Some(reset) if reset.supports(mode) => {
+                record_reboot(mode);
+                let error = reset.reset(mode);
+                let mut console_writer = ConsoleWriter::new();
+                let _ = write(
+                    &mut console_writer,
+                    format_args!("Reset failed: {:?}\r\n", error),
+                );
+                let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+            }
+            Some(_) => {
+                let _ = self.write_bytes(b"Not supported by this chip.\r\n");
+            }
+            None => {
+                let _ = self.write_bytes(b"Reset function is not implemented");
+            }
+        }
+    }
+
+    /// Handle `grants <process name>`.
+    ///
+    /// Prints how many bytes each driver uses in the grant region of the
+    /// process, and how full the grant region is.
+    fn grants_command(&self, command: &str) {
+        let name = match command.split_whitespace().nth(1) {
+            Some(name) => name,
+            None => {
+                let _ = self.write_bytes(b"Usage: grants <process name>\r\n");
+                return;
+            }
+        };
+        let mut processid = None;
+        self.kernel
+            .process_each_capability(&self.capability, |proc| {
+                if processid.is_none() && proc.get_process_name() == name {
+                    processid = Some(proc.processid());
+                }
+            });
+        let processid = match processid {
+            Some(processid) => processid,
+            None => {
+                let _ = self.write_bytes(b"Unknown process.\r\n");
+                return;
+            }
+        };
+
+        self.print_grant_usage(processid);
+    }
+
+    /// Handle `map <process name>`: print the memory map of a process,
+    /// followed by its grant usage.
+    fn map_command(&self, command: &str) {
+        let name = match command.split_whitespace().nth(1) {
+            Some(name) => name,
+            None => {
+                let _ = self.write_bytes(b"Usage: map <process name>\r\n");
+                return;
+            }
+        };
+        let mut processid = None;
+        self.kernel
+            .process_each_capability(&self.capability, |proc| {
+                if processid.is_none() && proc.get_process_name() == name {
+                    processid = Some(proc.processid());
+                }
+            });
+        match processid {
+            Some(process_id) => {
+                self.print_map_chunk(process_id, None);
+            }
+            None => {
+                let _ = self.write_bytes(b"Unknown process.\r\n");
+            }
+        }
+    }
+
+    /// Print the next chunk of the memory map of a process. Once the map is
+    /// complete, prints the grant usage, leaves the writer state Empty and
+    /// returns `true`.
+    fn print_map_chunk(
+        &self,
+        process_id: ProcessId,
+        context: Option<ProcessPrinterContext>,
+    ) -> bool {
+        let mut console_writer = ConsoleWriter::new();
+        let new_context = self.kernel.process_map_or_external(
+            None,
+            process_id,
+            |process| {
+                self.process_printer
+                    .print_memory_map(process, &mut console_writer, context)
+            },
+            &self.capability,
+        );
+        let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+
+        if new_context.is_some() {
+            self.writer_state.replace(WriterState::ProcessMap {
+                process_id,
+                context: new_context,
+            });
+            false
+        } else {
+            self.writer_state.replace(WriterState::Empty);
+            self.print_grant_usage(process_id);
+            true
+        }
+    }
+
+    /// Print how many bytes each driver has allocated in the grant region of
+    /// a process, and the overall grant region usage.
+    fn print_grant_usage(&self, processid: ProcessId) {
+        let info = KernelInfo::new(self.kernel);
+        let _ = self.write_bytes(b" Driver      Bytes\r\n");
+        info.app_grant_allocations(processid, &self.capability, |driver_num, size| {
+            let mut console_writer = ConsoleWriter::new();
+            let _ = write(
+                &mut console_writer,
+                format_args!(" {:<#10x}  {}\r\n", driver_num, size),
+            );
+            let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+        });
+        if let Some(stats) = info.app_grant_stats(processid, &self.capability) {
+            let mut console_writer = ConsoleWriter::new();
+            let _ = write(
+                &mut console_writer,
+                format_args!(
+                    " Allocated: {}   High water: {}   Available: {}\r\n Failed allocations: {}",
+                    stats.allocated, stats.high_water, stats.available, stats.failed_allocations
+                ),
+            );
+            let _ = match stats.last_failed_driver_num {
+                Some(driver_num) => write(
+                    &mut console_writer,
+                    format_args!(" (last driver {:#x})\r\n", driver_num),
+                ),
+                None => write(&mut console_writer, format_args!("\r\n")),
+            };
+            let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+        }
+    }
+
+    /// Handle `inject [<shim> <fault> [one_in] [delay_ms]]`.
+    ///
+    /// Without arguments, prints the state of every registered shim.
+    #[cfg(feature = "error_injection")]
+    fn inject_command(&self, command: &str) {
+        let injectors = match self.error_injectors.extract() {
+            Some(injectors) => injectors,
+            None => {
+                let _ = self.write_bytes(b"No error injectors registered.\r\n");
+                return;
+            }
+        };
+
+        let mut args = command.split_whitespace().skip(1);
+        let name = match args.next() {
+            Some(name) => name,
+            None => {
+                let _ = self.write_bytes(b" Name        Fault    Rate    Ops     Injected\r\n");
+                for injector in injectors.iter() {
+                    let (fault, one_in) = injector.current();
+                    let (ops, injected) = injector.statistics();
+                    let mut console_writer = ConsoleWriter::new();
+                    let _ = write(
+                        &mut console_writer,
+                        format_args!(
+                            " {:<12}{:<9}1/{:<6}{:<8}{}\r\n",
+                            injector.name(),
+                            fault.name(),
+                            one_in,
+                            ops,
+                            injected
+                        ),
+                    );
+                    let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+                }
+                return;
+            }
+        };
+
+        let fault = args.next().and_then(Fault::from_name);
+        let one_in = args.next().map_or(Some(1), |arg| arg.parse::<u32>().ok());
+        let delay_ms = args
+            .next()
+            .map_or(Some(crate::error_injection::DEFAULT_DELAY_MS), |arg| {
+                arg.parse::<u32>().ok()
+            });
+        let (fault, one_in, delay_ms) = match (fault, one_in, delay_ms) {
+            (Some(fault), Some(one_in), Some(delay_ms)) => (fault, one_in, delay_ms),
+            _ => {
+                let _ = self.write_bytes(
+                    b"Usage: inject <shim> <off|nak|timeout|corrupt|delay> [one_in] [delay_ms]\r\n",
+                );
+                return;
+            }
+        };
+
+        match injectors.iter().find(|injector| injector.name() == name) {
+            Some(injector) => {
+                let mut console_writer = ConsoleWriter::new();
+                let _ = match injector.configure(fault, one_in, delay_ms) {
+                    Ok(()) => write(
+                        &mut console_writer,
+                        format_args!("{}: injecting {} 1/{}\r\n", name, fault.name(), one_in),
+                    ),
+                    Err(e) => write(
+                        &mut console_writer,
+                        format_args!("{}: cannot inject {}: {:?}\r\n", name, fault.name(), e),
+                    ),
+                };
+                let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+            }
+            None => {
+                let _ = self.write_bytes(b"Unknown error injector.\r\n");
+            }
+        }
+    }
+
+    /// Handle `bustrace [on|off|clear]`.
+    ///
+    /// Without arguments, dumps the recorded transactions, oldest first.
+    fn bus_trace_command(&self, command: &str) {
+        let trace = match self.bus_trace.extract() {
+            Some(trace) => trace,
+            None => {
+                let _ = self.write_bytes(b"No bus trace registered.\r\n");
+                return;
+            }
+        };
+
+        match command.split_whitespace().nth(1) {
+            None => {
+                if trace.is_empty() {
+                    let _ = self.write_bytes(b"No bus transactions recorded.\r\n");
+                    return;
+                }
+                let _ = self
+                    .write_bytes(b" Seq    Bus   Addr  Dir  WLen RLen Time(us)  Data  Status\r\n");
+                // Start the state machine to print each separately.
+                self.write_state(WriterState::BusTrace {
+                    index: -1,
+                    total: trace.len() as isize,
+                });
+            }
+            Some("on") => {
+                trace.set_enabled(true);
+                let _ = self.write_bytes(b"Bus tracing enabled.\r\n");
+            }
+            Some("off") => {
+                trace.set_enabled(false);
+                let _ = self.write_bytes(b"Bus tracing disabled.\r\n");
+            }
+            Some("clear") => {
+                trace.clear();
+                let _ = self.write_bytes(b"Bus trace cleared.\r\n");
+            }
+            Some(_) => {
+                let _ = self.write_bytes(b"Usage: bustrace [on|off|clear]\r\n");
+            }
+        }
+    }
+
+    /// Handle `strace [on|off|clear|all|pid <id>]`.
+    ///
+    /// Without arguments, dumps the recorded system calls.
+    fn syscall_trace_command(&self, command: &str) {
+        let trace = match self.syscall_trace.extract() {
+            Some(trace) => trace,
+            None => {
+                let _ = self.write_bytes(b"No syscall trace registered.\r\n");
+                return;
+            }
+        };
+
+        let mut args = command.split_whitespace().skip(1);
+        match (args.next(), args.next()) {
+            (None, _) => {
+                if trace.is_empty() {
+                    let _ = self.write_bytes(b"No system calls recorded.\r\n");
+                    return;
+                }
+                let _ = self.write_bytes(
+                    b" Seq     Time(ms)  PID  Call      Driver   Sub Arg0        Arg1        Dur(us)  Result\r\n",
+                );
+                // Start the state machine to print each separately.
+                self.write_state(WriterState::SyscallTrace {
+                    index: -1,
+                    total: trace.len() as isize,
+                });
+            }
+            (Some("on"), None) => {
+                trace.set_enabled(true);
+                let _ = self.write_bytes(b"Syscall tracing enabled.\r\n");
+            }
+            (Some("off"), None) => {
+                trace.set_enabled(false);
+                let _ = self.write_bytes(b"Syscall tracing disabled.\r\n");
+            }
+            (Some("clear"), None) => {
+                trace.clear();
+                let _ = self.write_bytes(b"Syscall trace cleared.\r\n");
+            }
+            (Some("all"), None) => {
+                trace.set_process_filter(None);
+                let _ = self.write_bytes(b"Tracing all processes.\r\n");
+            }
+            (Some("pid"), Some(pid)) => match pid.parse::<usize>() {
+                Ok(pid) => {
+                    trace.set_process_filter(Some(pid));
+                    let mut console_writer = ConsoleWriter::new();
+                    let _ = write(
+                        &mut console_writer,
+                        format_args!("Tracing process {}.\r\n", pid),
+                    );
+                    let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+                }
+                Err(_) => {
+                    let _ = self.write_bytes(b"Invalid PID.\r\n");
+                }
+            },
+            _ => {
+                let _ = self.write_bytes(b"Usage: strace [on|off|clear|all|pid <id>]\r\n");
+            }
+        }
+    }
+
+    /// Handle `nettrace [on|off|clear|snap [<len>]|pcap <lowpan|ip>]`.
+    ///
+    /// Without arguments, dumps the recorded packets. `pcap` dumps the
+    /// packets of one layer as the hex of a pcap file.
+    fn packet_trace_command(&self, command: &str) {
+        let trace = match self.packet_trace.extract() {
+            Some(trace) => trace,
+            None => {
+                let _ = self.write_bytes(b"No packet trace registered.\r\n");
+                return;
+            }
+        };
+
+        let mut args = command.split_whitespace().skip(1);
+        match (args.next(), args.next()) {
+            (None, _) => {
+                if trace.is_empty() {
+                    let _ = self.write_bytes(b"No packets recorded.\r\n");
+                    return;
+                }
+                let _ = self.write_bytes(b" Seq        Time(us)  Layer  Dir   Len  Data\r\n");
+                // Start the state machine to print each separately.
+                self.write_state(WriterState::PacketTrace {
+                    index: -1,
+                    total: trace.len() as isize,
+                });
+            }
+            (Some("on"), None) => {
+                trace.set_enabled(true);
+                let _ = self.write_bytes(b"Packet tracing enabled.\r\n");
+            }
+            (Some("off"), None) => {
+                trace.set_enabled(false);
+                let _ = self.write_bytes(b"Packet tracing disabled.\r\n");
+            }
+            (Some("clear"), None) => {
+                trace.clear();
+                let _ = self.write_bytes(b"Packet trace cleared.\r\n");
+            }
+            (Some("snap"), len) => {
+                let result = match len {
+                    Some(len) => len
+                        .parse::<usize>()
+                        .map_err(|_| ErrorCode::INVAL)
+                        .and_then(|len| trace.set_snap_len(len)),
+                    None => Ok(()),
+                };
+                let mut console_writer = ConsoleWriter::new();
+                let _ = match result {
+                    Ok(()) => write(
+                        &mut console_writer,
+                        format_args!("Snap length: {} bytes.\r\n", trace.snap_len()),
+                    ),
+                    Err(_) => write(
+                        &mut console_writer,
+                        format_args!(
+                            "Invalid snap length, must be 1 to {}.\r\n",
+                            packet_trace::MAX_SNAP_LEN
+                        ),
+                    ),
+                };
+                let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+            }
+            (Some("pcap"), Some(layer @ ("lowpan" | "ip"))) => {
+                let layer = if layer == "lowpan" {
+                    Layer::Lowpan
+                } else {
+                    Layer::Ip
+                };
+                // pcap global header: magic number, version 2.4, UTC, the
+                // snap length and the link type.
+                let header = [
+                    0xa1b2c3d4,
+                    0x00040002,
+                    0,
+                    0,
+                    packet_trace::MAX_SNAP_LEN as u32,
+                    layer.link_type(),
+                ];
+                let mut console_writer = ConsoleWriter::new();
+                for field in header {
+                    for byte in field.to_le_bytes() {
+                        let _ = write(&mut console_writer, format_args!("{:02x}", byte));
+                    }
+                }
+                let _ = write(&mut console_writer, format_args!("\r\n"));
+                let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+                if !trace.is_empty() {
+                    self.write_state(WriterState::PacketTracePcap {
+                        index: -1,
+                        total: trace.len() as isize,
+                        layer,
+                    });
+                }
+            }
+            _ => {
+                let _ = self.write_bytes(
+                    b"Usage: nettrace [on|off|clear|snap [<len>]|pcap <lowpan|ip>]\r\n",
+                );
+            }
+        }
+    }
+
+    /// Handle `uart [reset]`.
+    ///
+    /// Without arguments, prints the counters of every device on the UART
+    /// mux.
+    fn uart_command(&self, command: &str) {
+        let stats = match self.uart_stats.extract() {
+            Some(stats) => stats,
+            None => {
+                let _ = self.write_bytes(b"No UART mux registered.\r\n");
+                return;
+            }
+        };
+
+        match command.split_whitespace().nth(1) {
+            None => {
+                let mut console_writer = ConsoleWriter::new();
+                let _ = write(
+                    &mut console_writer,
+                    format_args!(
+                        "Max transmissions queued: {}\r\n \
+                         Dev Name              TX bytes  RX bytes  Dropped  TX err  Queue\r\n",
+                        stats.tx_queue_max()
+                    ),
+                );
+                let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+                let total = stats.device_count() as isize;
+                if total > 0 {
+                    // Start the state machine to print each separately.
+                    self.write_state(WriterState::UartStats { index: -1, total });
+                }
+            }
+            Some("reset") => {
+                stats.reset_stats();
+                let _ = self.write_bytes(b"UART statistics reset.\r\n");
+            }
+            Some(_) => {
+                let _ = self.write_bytes(b"Usage: uart [reset]\r\n");
+            }
+        }
+    }
+
+    /// Handle `focus [<process name>|none]`.
+    ///
+    /// Without arguments, prints the process that console input goes to.
+    fn focus_command(&self, command: &str) {
+        let focus = match self.input_focus.extract() {
+            Some(focus) => focus,
+            None => {
+                let _ = self.write_bytes(b"No console registered.\r\n");
+                return;
+            }
+        };
+
+        match command.split_whitespace().nth(1) {
+            None => {}
+            Some("none") => focus.set_focus(None),
+            Some(name) => {
+                let mut processid = None;
+                self.kernel
+                    .process_each_capability(&self.capability, |proc| {
+                        if processid.is_none() && proc.get_process_name() == name {
+                            processid = Some(proc.processid());
+                        }
+                    });
+                match processid {
+                    Some(processid) => focus.set_focus(Some(processid)),
+                    None => {
+                        let _ = self.write_bytes(b"Unknown process.\r\n");
+                        return;
+                    }
+                }
+            }
+        }
+
+        let mut console_writer = ConsoleWriter::new();
+        match focus.focus() {
+            Some(processid) => {
+                self.kernel.process_map_or_external(
+                    (),
+                    processid,
+                    |proc| {
+                        let _ = write(
+                            &mut console_writer,
+                            format_args!("Input focus: {}\r\n", proc.get_process_name()),
+                        );
+                    },
+                    &self.capability,
+                );
+            }
+            None => {
+                let _ = write(&mut console_writer, format_args!("Input focus: none\r\n"));
+            }
+        }
+        let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+    }
+
+    /// Handle `loglevel [error|warn|info|debug]`.
+    ///
+    /// Without arguments, prints the most verbose level of process output
+    /// that is printed.
+    fn log_level_command(&self, command: &str) {
+        let filter = match self.log_filter.extract() {
+            Some(filter) => filter,
+            None => {
+                let _ = self.write_bytes(b"No console registered.\r\n");
+                return;
+            }
+        };
+
+        match command.split_whitespace().nth(1) {
+            None => {}
+            Some("error") => filter.set_level_filter(LogLevel::Error),
+            Some("warn") => filter.set_level_filter(LogLevel::Warn),
+            Some("info") => filter.set_level_filter(LogLevel::Info),
+            Some("debug") => filter.set_level_filter(LogLevel::Debug),
+            Some(_) => {
+                let _ = self.write_bytes(b"Usage: loglevel [error|warn|info|debug]\r\n");
+                return;
+            }
+        }
+
+        let mut console_writer = ConsoleWriter::new();
+        let _ = write(
+            &mut console_writer,
+            format_args!("Log level: {}\r\n", filter.level_filter().name()),
+        );
+        let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+    }
+
+    /// Handle `priority <process name> [<level>]`.
+    ///
+    /// Without a level, prints the priority level of the process.
+    fn priority_command(&self, command: &str) {
+        let control = match self.priority_control.extract() {
+            Some(control) => control,
+            None => {
+                let _ = self.write_bytes(b"No priority scheduler registered.\r\n");
+                return;
+            }
+        };
+
+        let mut args = command.split_whitespace().skip(1);
+        let name = args.next();
+        let level = args.next().map(|level| level.parse::<u8>());
+        let (name, level) = match (name, level) {
+            (Some(name), None) => (name, None),
+            (Some(name), Some(Ok(level))) => (name, Some(level)),
+            _ => {
+                let _ = self.write_bytes(b"Usage: priority <process name> [<level>]\r\n");
+                return;
+            }
+        };
+
+        let mut processid = None;
+        self.kernel
+            .process_each_capability(&self.capability, |proc| {
+                if processid.is_none() && proc.get_process_name() == name {
+                    processid = Some(proc.processid());
+                }
+            });
+        let processid = match processid {
+            Some(processid) => processid,
+            None => {
+                let _ = self.write_bytes(b"Unknown process.\r\n");
+                return;
+            }
+        };
+
+        let mut console_writer = ConsoleWriter::new();
+        if let Some(level) = level {
+            if let Err(error) = control.set_priority(processid, level, &self.capability) {
+                let _ = write(
+                    &mut console_writer,
+                    format_args!("Setting priority failed: {:?}\r\n", error),
+                );
+                let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+                return;
+            }
+        }
+        match control.priority(processid) {
+            Some(level) => {
+                let _ = write(
+                    &mut console_writer,
+                    format_args!("Priority of {}: {}\r\n", name, level),
+                );
+            }
+            None => {
+                let _ = write(
+                    &mut console_writer,
+                    format_args!("Priority of {}: fixed\r\n", name),
+                );
+            }
+        }
+        let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+    }
+
+    /// Handle `clocks`: list the clock state of each chip peripheral.
+    fn clocks_command(&self) {
+        let total = match self.clocks.extract() {
+            Some(clocks) => clocks.peripheral_count() as isize,
+            None => {
+                let _ = self.write_bytes(b"No clock introspection registered.\r\n");
+                return;
+            }
+        };
+        let _ = self.write_bytes(b" Peripheral  Clock  Freq (Hz)  Driver\r\n");
+        if total > 0 {
+            // Start the state machine to print each separately.
+            self.write_state(WriterState::Clocks { index: -1, total });
+        }
+    }
+
+    /// Handle `memory`: list the memory high-water marks of each process.
+    fn memory_command(&self) {
+        let mut console_writer = ConsoleWriter::new();
+        let _ = write(
+            &mut console_writer,
+            format_args!(
+                " PID    {:<w$}  Stack   Heap  App mem  Grants  Allocated\r\n",
+                "Name",
+                w = self.list_name_width(),
+            ),
+        );
+        let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+
+        let mut count = 0;
+        self.kernel.process_each_capability(&self.capability, |_| {
+            count += 1;
+        });
+        if count > 0 {
+            // Start the state machine to print each separately.
+            self.write_state(WriterState::Memory {
+                index: -1,
+                total: count,
+            });
+        }
+    }
+
+    /// Handle `irqlat [reset]`: print or clear the interrupt latency
+    /// statistics.
+    fn irq_latency_command(&self, command: &str) {
+        let recorder = match kernel::irq_latency::recorder() {
+            Some(recorder) if kernel::irq_latency::ENABLED => recorder,
+            _ => {
+                let _ = self.write_bytes(b"Interrupt latency is not recorded.\r\n");
+                return;
+            }
+        };
+
+        match command.split_whitespace().nth(1) {
+            None => {
+                let mut console_writer = ConsoleWriter::new();
+                let _ = write(
+                    &mut console_writer,
+                    format_args!(
+                        " IRQ     Count  Min us  Avg us  Max us  Jitter  Histogram (< {} << n cycles)\r\n",
+                        kernel::irq_latency::FIRST_BUCKET_CYCLES,
+                    ),
+                );
+                let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+
+                let total = (0..recorder.num_irqs())
+                    .filter(|irq| recorder.stats(*irq).is_some_and(|stats| stats.count > 0))
+                    .count() as isize;
+                if total > 0 {
+                    // Start the state machine to print each separately.
+                    self.write_state(WriterState::IrqLatency { index: -1, total });
+                }
+            }
+            Some("reset") => {
+                recorder.reset();
+                let _ = self.write_bytes(b"Interrupt latency statistics cleared.\r\n");
+            }
+            Some(_) => {
+                let _ = self.write_bytes(b"Usage: irqlat [reset]\r\n");
+            }
+        }
+    }
+
+    /// Handle `crash [clear]`: print or clear the crash report stored by a
+    /// previous boot.
+    fn crash_command(&self, command: &str) {
+        let report = match self.crash_report.extract() {
+            Some(report) => report,
+            None => {
+                let _ = self.write_bytes(b"No crash report storage registered.\r\n");
+                return;
+            }
+        };
+
+        match command.split_whitespace().nth(1) {
+            None => {
+                let len = report.report_len();
+                if len == 0 {
+                    let _ = self.write_bytes(b"No crash report stored.\r\n");
+                    return;
+                }
+                // Start the state machine to print the report in chunks.
+                let total = ((len + CRASH_REPORT_CHUNK_LEN - 1) / CRASH_REPORT_CHUNK_LEN) as isize;
+                self.write_state(WriterState::CrashReport { index: -1, total });
+            }
+            Some("clear") => match report.clear_report() {
+                Ok(()) => {
+                    let _ = self.write_bytes(b"Crash report cleared.\r\n");
+                }
+                Err(e) => {
+                    let mut console_writer = ConsoleWriter::new();
+                    let _ = write(
+                        &mut console_writer,
+                        format_args!("Could not clear the crash report: {:?}\r\n", e),
+                    );
+                    let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+                }
+            },
+            Some(_) => {
+                let _ = self.write_bytes(b"Usage: crash [clear]\r\n");
+            }
+        }
+    }
+
+    /// Print the built-in commands and the commands installed by the board.
+    fn write_valid_commands(&self) {
+        let _ = self.write_bytes(b"Valid commands are: ");
+        let _ = self.write_bytes(VALID_COMMANDS_STR);
+        self.commands.map(|commands| {
+            if !commands.is_empty() {
+                let mut console_writer = ConsoleWriter::new();
+                let _ = write(&mut console_writer, format_args!("Board commands are:"));
+                for command in commands.iter() {
+                    let _ = write(&mut console_writer, format_args!(" {}", command.name()));
+                }
+                let _ = write(&mut console_writer, format_args!("\r\n"));
+                let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+            }
+        });
+    }
+
+    /// Call `f` with every name that can complete a word: command names for
+    /// the first word of a line, process names for later words.
+    fn for_each_completion(&self, first_word: bool, f: &mut dyn FnMut(&str)) {
+        if first_word {
+            if let Ok(names) = str::from_utf8(VALID_COMMANDS_STR) {
+                names.split_whitespace().for_each(&mut *f);
+            }
+            self.commands.map(|commands| {
+                for command in commands.iter() {
+                    f(command.name());
+                }
+            });
+        } else {
+            self.kernel
+                .process_each_capability(&self.capability, |proc| f(proc.get_process_name()));
+        }
+    }
+
+    /// Complete the word that ends at `index`, the end of `command`. A single
+    /// match is completed and followed by a space. Several matches are
+    /// completed as far as they agree, and listed if they do not agree on
+    /// any more characters. Returns the new length of the command.
+    fn complete(&self, command: &mut [u8], index: usize) -> usize {
+        let line = match str::from_utf8(&command[..index]) {
+            Ok(line) => line,
+            Err(_) => return index,
+        };
+        let word_start = line.rfind(' ').map_or(0, |space| space + 1);
+        let first_word = line[..word_start].trim().is_empty();
+        let prefix = &line[word_start..];
+
+        // The longest prefix shared by all matches.
+        let mut common = [EOL; COMMAND_BUF_LEN];
+        let mut common_len = 0;
+        let mut matches = 0;
+        self.for_each_completion(first_word, &mut |name| {
+            if !name.starts_with(prefix) {
+                return;
+            }
+            let name = name.as_bytes();
+            if matches == 0 {
+                common_len = cmp::min(name.len(), COMMAND_BUF_LEN);
+                common[..common_len].copy_from_slice(&name[..common_len]);
+            } else {
+                common_len = common[..common_len]
+                    .iter()
+                    .zip(name.iter())
+                    .take_while(|(a, b)| a == b)
+                    .count();
+            }
+            matches += 1;
+        });
+
+        if matches > 1 && common_len == prefix.len() {
+            // Nothing to complete, so show the candidates and redraw the line.
+            let mut console_writer = ConsoleWriter::new();
+            let _ = write(&mut console_writer, format_args!("\r\n"));
+            self.for_each_completion(first_word, &mut |name| {
+                if name.starts_with(prefix) {
+                    let _ = write(&mut console_writer, format_args!("{}  ", name));
+                }
+            });
+            let _ = write(&mut console_writer, format_args!("\r\n"));
+            let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+            self.prompt();
+            let _ = self.write_bytes(&command[..index]);
+            return index;
+        }
+
+        let mut index = index;
+        let completion = common[prefix.len()..common_len]
+            .iter()
+            .chain(if matches == 1 { &b" "[..] } else { &[] });
+        for byte in completion {
+            // Keep room for the terminating EOL.
+            if index + 1 >= command.len() {
+                break;
+            }
+            command[index] = *byte;
+            let _ = self.write_byte(*byte);
+            index += 1;
+        }
+        command[index] = EOL;
+        index
+    }
+
+    /// Handle `help [command]`. The built-in commands are described in
+    /// doc/Process_Console.md, board commands print their help text.
+    fn help_command(&self, args: &str) {
+        if args.is_empty() {
+            let _ = self.write_bytes(b"Welcome to the process console.\r\n");
+            self.write_valid_commands();
+            return;
+        }
+        let command = self
+            .commands
+            .and_then(|commands| commands.iter().find(|command| command.name() == args));
+        match command {
+            Some(command) => {
+                let mut console_writer = ConsoleWriter::new();
+                let _ = write(
+                    &mut console_writer,
+                    format_args!("{}: {}\r\n", command.name(), command.help()),
+                );
+                let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+            }
+            None => {
+                let _ = self.write_bytes(b"No help for this command.\r\n");
+            }
+        }
+    }
+
+    /// Handle `term [ansi|raw|probe|width <columns>]`.
+    ///
+    /// Without arguments, prints the current terminal settings.
+    fn term_command(&self, command: &str) {
+        let mut args = command.split_whitespace().skip(1);
+        match (args.next(), args.next().map(|arg| arg.parse::<usize>())) {
+            (None, _) => {}
+            (Some("ansi"), None) => self.ansi.set(true),
+            (Some("raw"), None) => self.ansi.set(false),
+            (Some("probe"), None) => {
+                // The answer is handled by `received_buffer()`, which
+                // also turns on ANSI output since the terminal evidently
+                // supports it.
+                let _ = self.write_bytes(TERM_SIZE_PROBE);
+                return;
+            }
+            (Some("width"), Some(Ok(width))) if width >= 20 => self.term_width.set(width),
+            _ => {
+                let _ = self.write_bytes(b"Usage: term [ansi|raw|probe|width <columns>]\r\n");
+                return;
+            }
+        }
+        let mut console_writer = ConsoleWriter::new();
+        let _ = write(
+            &mut console_writer,
+            format_args!(
+                "Terminal: {}, {} columns\r\n",
+                if self.ansi.get() { "ansi" } else { "raw" },
+                self.term_width.get()
+            ),
+        );
+        let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+    }
+
+    /// Width of the name column of `list`, so that rows fit the terminal.
+    fn list_name_width(&self) -> usize {
+        self.term_width.get().saturating_sub(60).clamp(8, 32)
+    }
+
     fn prompt(&self) {
-        let _ = self.write_bytes(b"tock$ ");
+        if self.ansi.get() {
+            let _ = self.write_bytes(b"\x1B[1;32mtock$\x1B[0m ");
+        } else {
+            let _ = self.write_bytes(b"tock$ ");
+        }
     }
 
     /// Start or iterate the state machine for an asynchronous write operation
@@ -1102,6 +2998,35 @@ impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCap
     }
 }
 
+impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCapability>
+    NonvolatileStorageClient<'a> for ProcessConsole<'a, COMMAND_HISTORY_LEN, A, C>
+{
+    fn read_done(&self, buffer: &'a mut [u8], length: usize) {
+        self.command_history.map(|ht| {
+            ht.load(&buffer[..cmp::min(length, buffer.len())]);
+        });
+        self.history_storage_buffer.replace(buffer);
+        if self.history_dirty.replace(false) {
+            self.save_history();
+        }
+    }
+
+    fn write_done(&self, buffer: &'a mut [u8], _length: usize) {
+        self.history_storage_buffer.replace(buffer);
+        if self.history_dirty.replace(false) {
+            self.save_history();
+        }
+    }
+}
+
+impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCapability>
+    PriorityOutput for ProcessConsole<'a, COMMAND_HISTORY_LEN, A, C>
+{
+    fn output_pending(&self) -> bool {
+        self.tx_in_progress.get() || self.writer_state.get() != WriterState::Empty
+    }
+}
+
 impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCapability>
     uart::TransmitClient for ProcessConsole<'a, COMMAND_HISTORY_LEN, A, C>
 {
@@ -1198,6 +3123,10 @@ impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCap
                                         };
                                     });
                                 }
+                                EscKey::CursorReport { cols } if cols >= 20 => {
+                                    self.term_width.set(cols as usize);
+                                    self.ansi.set(true);
+                                }
                                 EscKey::Left if cursor > 0 => {
                                     let _ = self.write_byte(BS);
                                     self.cursor.set(cursor - 1);
@@ -1300,6 +3229,21 @@ impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCap
                                     });
                                 }
                             }
+                        } else if read_buf[0] == TAB && !esc_state.in_progress() {
+                            // Only complete at the end of the line.
+                            if cursor == index {
+                                let new_index = self.complete(command, index);
+                                self.cursor.set(new_index);
+                                self.command_index.set(new_index);
+
+                                if COMMAND_HISTORY_LEN > 1 {
+                                    self.command_history.map(|ht| {
+                                        ht.cmds[0].clear();
+                                        ht.write_to_first(command);
+                                        ht.cmd_is_modified = false;
+                                    });
+                                }
+                            }
                         } else if (COMMAND_HISTORY_LEN > 1) && (esc_state.has_started()) {
                             self.command_history
                                 .map(|ht| ht.modified_byte = previous_byte);
@@ -4,6 +4,12 @@
 
 //! Provides userspace applications with the ability to communicate over the SPI
 //! bus as a peripheral. Only supports chip select 0.
+//!
+//! Upcall 0 is called when a read/write operation completes, with the number
+//! of bytes transferred. This is less than requested if the controller ended
+//! the transfer by bringing the chip select high first. Upcalls 1 and 2 are
+//! called when the chip select is brought low and high, if the hardware can
+//! detect it.
 
 use core::cell::Cell;
 use core::cmp;
@@ -46,6 +52,9 @@ pub const DEFAULT_WRITE_BUF_LENGTH: usize = 1024;
 pub struct PeripheralApp {
     len: usize,
     index: usize,
+    /// Length of the chunk handed to the SPI device, which starts at
+    /// `index`.
+    chunk_len: usize,
 }
 
 pub struct SpiPeripheral<'a, S: SpiSlaveDevice> {
@@ -56,7 +65,7 @@ pub struct SpiPeripheral<'a, S: SpiSlaveDevice> {
     kernel_len: Cell<usize>,
     grants: Grant<
         PeripheralApp,
-        UpcallCount<2>,
+        UpcallCount<3>,
         AllowRoCount<{ ro_allow::COUNT }>,
         AllowRwCount<{ rw_allow::COUNT }>,
     >,
@@ -68,7 +77,7 @@ impl<'a, S: SpiSlaveDevice> SpiPeripheral<'a, S> {
         spi_slave: &'a S,
         grants: Grant<
             PeripheralApp,
-            UpcallCount<2>,
+            UpcallCount<3>,
             AllowRoCount<{ ro_allow::COUNT }>,
             AllowRwCount<{ rw_allow::COUNT }>,
         >,
@@ -92,8 +101,12 @@ impl<'a, S: SpiSlaveDevice> SpiPeripheral<'a, S> {
     }
 
     // Assumes checks for busy/etc. already done
-    // Updates app.index to be index + length of op
-    fn do_next_read_write(&self, app: &mut PeripheralApp, kernel_data: &GrantKernelData) {
+    // Updates app.chunk_len to be the length of the op
+    fn do_next_read_write(
+        &self,
+        app: &mut PeripheralApp,
+        kernel_data: &GrantKernelData,
+    ) -> Result<(), ErrorCode> {
         let write_len = self.kernel_write.map_or(0, |kwbuf| {
             let mut start = app.index;
             let tmp_len = kernel_data
@@ -111,15 +124,31 @@ impl<'a, S: SpiSlaveDevice> SpiPeripheral<'a, S> {
                     })
                 })
                 .unwrap_or(0);
-            app.index = start + tmp_len;
+            app.index = start;
             tmp_len
         });
-        // TODO verify SPI return value
-        let _ = self.spi_slave.read_write_bytes(
-            self.kernel_write.take(),
-            self.kernel_read.take(),
-            write_len,
-        );
+        app.chunk_len = write_len;
+        if write_len == 0 {
+            // the app revoked or shrank its write buffer
+            return Err(ErrorCode::INVAL);
+        }
+        self.spi_slave
+            .read_write_bytes(self.kernel_write.take(), self.kernel_read.take(), write_len)
+            .map_err(|(err, write, read)| {
+                self.kernel_write.put(write);
+                self.kernel_read.put(read);
+                err
+            })
+    }
+
+    /// End the current read/write operation of the app.
+    fn complete(&self, app: &mut PeripheralApp, kernel_data: &GrantKernelData) {
+        self.busy.set(false);
+        let len = app.index;
+        app.len = 0;
+        app.index = 0;
+        app.chunk_len = 0;
+        kernel_data.schedule_upcall(0, (len, 0, 0)).ok();
     }
 }
 
@@ -152,6 +181,8 @@ impl<S: SpiSlaveDevice> SyscallDriver for SpiPeripheral<'_, S> {
     /// - 6: get clock polarity on current peripheral
     ///   - 0 is idle low
     ///   - non-zero is idle high
+    /// - 7: set the byte sent while no read/write is armed
+    ///   - the lowest 8 bits of arg1
     /// - x: lock spi
     ///   - if you perform an operation without the lock,
     ///     it implicitly acquires the lock before the
@@ -209,9 +240,13 @@ impl<S: SpiSlaveDevice> SyscallDriver for SpiPeripheral<'_, S> {
                     if len >= arg1 && arg1 > 0 {
                         app.len = arg1;
                         app.index = 0;
-                        self.busy.set(true);
-                        self.do_next_read_write(app, kernel_data);
-                        CommandReturn::success()
+                        match self.do_next_read_write(app, kernel_data) {
+                            Ok(()) => {
+                                self.busy.set(true);
+                                CommandReturn::success()
+                            }
+                            Err(err) => CommandReturn::failure(err),
+                        }
                     } else {
                         /* write buffer too small, or zero length write */
                         CommandReturn::failure(ErrorCode::INVAL)
@@ -247,6 +282,10 @@ impl<S: SpiSlaveDevice> SyscallDriver for SpiPeripheral<'_, S> {
             6 /* get polarity */ => {
                 CommandReturn::success_u32(self.spi_slave.get_polarity() as u32)
             }
+            7 /* set write byte */ => {
+                self.spi_slave.set_write_byte(arg1 as u8);
+                CommandReturn::success()
+            }
             _ => CommandReturn::failure(ErrorCode::NOSUPPORT)
         }
     }
@@ -266,8 +305,11 @@ impl<S: SpiSlaveDevice> SpiSlaveClient for SpiPeripheral<'_, S> {
     ) {
         self.current_process.map(|process_id| {
             let _ = self.grants.enter(*process_id, move |app, kernel_data| {
+                // Fewer bytes than requested are transferred if the
+                // controller ends the operation early.
+                let length = cmp::min(length, app.chunk_len);
                 let rbuf = readbuf.map(|src| {
-                    let index = app.index;
+                    let index = app.index + length;
                     let _ = kernel_data
                         .get_readwrite_processbuffer(rw_allow::READ)
                         .and_then(|read| {
@@ -302,14 +344,13 @@ impl<S: SpiSlaveDevice> SpiSlaveClient for SpiPeripheral<'_, S> {
                 self.kernel_read.put(rbuf);
                 self.kernel_write.put(writebuf);
 
-                if app.index == app.len {
-                    self.busy.set(false);
-                    let len = app.len;
-                    app.len = 0;
-                    app.index = 0;
-                    kernel_data.schedule_upcall(0, (len, 0, 0)).ok();
-                } else {
-                    self.do_next_read_write(app, kernel_data);
+                let ended_early = length < app.chunk_len;
+                app.index += length;
+                if app.index == app.len
+                    || ended_early
+                    || self.do_next_read_write(app, kernel_data).is_err()
+                {
+                    self.complete(app, kernel_data);
                 }
             });
         });
@@ -324,4 +365,14 @@ impl<S: SpiSlaveDevice> SpiSlaveClient for SpiPeripheral<'_, S> {
             });
         });
     }
+
+    // Simple callback for when chip has been deselected
+    fn chip_deselected(&self) {
+        self.current_process.map(|process_id| {
+            let _ = self.grants.enter(*process_id, move |app, kernel_data| {
+                let len = app.len;
+                kernel_data.schedule_upcall(2, (len, 0, 0)).ok();
+            });
+        });
+    }
 }
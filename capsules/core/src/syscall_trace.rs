@@ -0,0 +1,319 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! System call tracing.
+//!
+//! `SyscallTraceBuffer` records the system calls processes make into a ring
+//! buffer: when the call was made, the calling process, the system call
+//! class, driver and subdriver numbers and arguments, and the result and
+//! how long the kernel took to handle it. When the ring is full, the oldest
+//! system call is overwritten.
+//!
+//! Recording can be restricted to a single process, so that a misbehaving
+//! process can be followed without the trace filling up with the system
+//! calls of other processes.
+//!
+//! The trace is dumped from the process console with the `strace` command,
+//! see `ProcessConsole::set_syscall_trace()`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let strace = static_init!(
+//!     capsules_core::syscall_trace::SyscallTraceBuffer<'static, VirtualMuxAlarm<'static, Rtc>>,
+//!     capsules_core::syscall_trace::SyscallTraceBuffer::new(
+//!         alarm,
+//!         static_init!(
+//!             [capsules_core::syscall_trace::SyscallEvent; 64],
+//!             [Default::default(); 64]
+//!         )
+//!     )
+//! );
+//! board_kernel.set_syscall_tracer(strace, &process_management_capability);
+//! process_console.set_syscall_trace(strace);
+//! ```
+
+use core::cell::Cell;
+
+use kernel::hil::time::{ConvertTicks, Ticks, Time};
+use kernel::syscall::{Syscall, SyscallClass, SyscallReturn, SyscallTracer};
+use kernel::utilities::cells::TakeCell;
+use kernel::{ErrorCode, ProcessId};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Status {
+    /// The system call does not return a value (`yield` and `exit`).
+    NoReturn,
+    Success,
+    Failure(ErrorCode),
+}
+
+/// A single recorded system call.
+#[derive(Copy, Clone, Debug)]
+pub struct SyscallEvent {
+    /// Sequence number, incremented for every system call recorded.
+    pub seq: u32,
+    /// Time the system call was made, in milliseconds of the trace clock.
+    pub timestamp_ms: u32,
+    /// Identifier (`ProcessId::id()`) of the calling process.
+    pub process: usize,
+    pub class: SyscallClass,
+    /// Driver number. For `yield` and `exit` the variant called, and for
+    /// `memop` the operand.
+    pub driver_number: usize,
+    pub subdriver_number: usize,
+    /// First two arguments: the arguments of a command, the address and
+    /// size of an allow, the upcall pointer and application data of a
+    /// subscribe, or the argument of a memop or exit.
+    pub args: [usize; 2],
+    pub status: Status,
+    /// Time the kernel took to handle the system call.
+    pub duration_us: u32,
+    start: u32,
+}
+
+impl Default for SyscallEvent {
+    fn default() -> SyscallEvent {
+        SyscallEvent {
+            seq: 0,
+            timestamp_ms: 0,
+            process: 0,
+            class: SyscallClass::Yield,
+            driver_number: 0,
+            subdriver_number: 0,
+            args: [0; 2],
+            status: Status::NoReturn,
+            duration_us: 0,
+            start: 0,
+        }
+    }
+}
+
+impl SyscallEvent {
+    fn decode(syscall: &Syscall) -> SyscallEvent {
+        let (class, driver_number, subdriver_number, args) = match *syscall {
+            Syscall::Yield { which, address } => {
+                (SyscallClass::Yield, which, 0, [address as usize, 0])
+            }
+            Syscall::Subscribe {
+                driver_number,
+                subdriver_number,
+                upcall_ptr,
+                appdata,
+            } => (
+                SyscallClass::Subscribe,
+                driver_number,
+                subdriver_number,
+                [upcall_ptr as usize, appdata],
+            ),
+            Syscall::Command {
+                driver_number,
+                subdriver_number,
+                arg0,
+                arg1,
+            } => (
+                SyscallClass::Command,
+                driver_number,
+                subdriver_number,
+                [arg0, arg1],
+            ),
+            Syscall::ReadWriteAllow {
+                driver_number,
+                subdriver_number,
+                allow_address,
+                allow_size,
+            } => (
+                SyscallClass::ReadWriteAllow,
+                driver_number,
+                subdriver_number,
+                [allow_address as usize, allow_size],
+            ),
+            Syscall::UserspaceReadableAllow {
+                driver_number,
+                subdriver_number,
+                allow_address,
+                allow_size,
+            } => (
+                SyscallClass::UserspaceReadableAllow,
+                driver_number,
+                subdriver_number,
+                [allow_address as usize, allow_size],
+            ),
+            Syscall::ReadOnlyAllow {
+                driver_number,
+                subdriver_number,
+                allow_address,
+                allow_size,
+            } => (
+                SyscallClass::ReadOnlyAllow,
+                driver_number,
+                subdriver_number,
+                [allow_address as usize, allow_size],
+            ),
+            Syscall::Memop { operand, arg0 } => (SyscallClass::Memop, operand, 0, [arg0, 0]),
+            Syscall::Exit {
+                which,
+                completion_code,
+            } => (SyscallClass::Exit, which, 0, [completion_code, 0]),
+        };
+        SyscallEvent {
+            class,
+            driver_number,
+            subdriver_number,
+            args,
+            ..Default::default()
+        }
+    }
+}
+
+/// Interface used by the process console to inspect the trace.
+pub trait SyscallTraceLog {
+    /// Number of recorded system calls.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Recorded system call `index`, oldest first.
+    fn entry(&self, index: usize) -> Option<SyscallEvent>;
+
+    /// Drop all recorded system calls.
+    fn clear(&self);
+
+    /// Start or stop recording new system calls.
+    fn set_enabled(&self, enabled: bool);
+
+    fn is_enabled(&self) -> bool;
+
+    /// Only record the system calls of the process with identifier
+    /// `process` (see `ProcessId::id()`), or of all processes if `None`.
+    fn set_process_filter(&self, process: Option<usize>);
+
+    fn process_filter(&self) -> Option<usize>;
+}
+
+pub struct SyscallTraceBuffer<'a, T: Time> {
+    time: &'a T,
+    ring: TakeCell<'a, [SyscallEvent]>,
+    /// Index of the oldest recorded system call.
+    head: Cell<usize>,
+    len: Cell<usize>,
+    in_flight: Cell<Option<SyscallEvent>>,
+    seq: Cell<u32>,
+    enabled: Cell<bool>,
+    filter: Cell<Option<usize>>,
+}
+
+impl<'a, T: Time> SyscallTraceBuffer<'a, T> {
+    pub fn new(time: &'a T, ring: &'a mut [SyscallEvent]) -> SyscallTraceBuffer<'a, T> {
+        SyscallTraceBuffer {
+            time,
+            ring: TakeCell::new(ring),
+            head: Cell::new(0),
+            len: Cell::new(0),
+            in_flight: Cell::new(None),
+            seq: Cell::new(0),
+            enabled: Cell::new(true),
+            filter: Cell::new(None),
+        }
+    }
+
+    fn record(&self, event: SyscallEvent) {
+        self.ring.map(|ring| {
+            if ring.is_empty() {
+                return;
+            }
+            let capacity = ring.len();
+            let len = self.len.get();
+            if len < capacity {
+                ring[(self.head.get() + len) % capacity] = event;
+                self.len.set(len + 1);
+            } else {
+                // Full, overwrite the oldest system call.
+                ring[self.head.get()] = event;
+                self.head.set((self.head.get() + 1) % capacity);
+            }
+        });
+    }
+}
+
+impl<'a, T: Time> SyscallTracer for SyscallTraceBuffer<'a, T> {
+    fn syscall_entry(&self, process_id: ProcessId, syscall: &Syscall) {
+        if !self.enabled.get()
+            || self
+                .filter
+                .get()
+                .map_or(false, |process| process != process_id.id())
+        {
+            return;
+        }
+        let now = self.time.now();
+        let mut event = SyscallEvent::decode(syscall);
+        event.seq = self.seq.get();
+        event.timestamp_ms = self.time.ticks_to_ms(now);
+        event.process = process_id.id();
+        event.start = now.into_u32();
+        self.seq.set(self.seq.get().wrapping_add(1));
+        match event.class {
+            SyscallClass::Yield | SyscallClass::Exit => self.record(event),
+            _ => self.in_flight.set(Some(event)),
+        }
+    }
+
+    fn syscall_exit(&self, process_id: ProcessId, _syscall: &Syscall, result: &SyscallReturn) {
+        if let Some(mut event) = self.in_flight.take() {
+            if event.process != process_id.id() {
+                return;
+            }
+            let elapsed = self.time.now().wrapping_sub(T::Ticks::from(event.start));
+            event.duration_us = self.time.ticks_to_us(elapsed);
+            event.status = match result.error_code() {
+                Some(error) => Status::Failure(error),
+                None => Status::Success,
+            };
+            self.record(event);
+        }
+    }
+}
+
+impl<'a, T: Time> SyscallTraceLog for SyscallTraceBuffer<'a, T> {
+    fn len(&self) -> usize {
+        self.len.get()
+    }
+
+    fn entry(&self, index: usize) -> Option<SyscallEvent> {
+        if index >= self.len.get() {
+            return None;
+        }
+        self.ring
+            .map(|ring| ring[(self.head.get() + index) % ring.len()])
+    }
+
+    fn clear(&self) {
+        self.head.set(0);
+        self.len.set(0);
+    }
+
+    fn set_enabled(&self, enabled: bool) {
+        self.enabled.set(enabled);
+        if !enabled {
+            self.in_flight.set(None);
+        }
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.enabled.get()
+    }
+
+    fn set_process_filter(&self, process: Option<usize>) {
+        self.filter.set(process);
+        self.in_flight.set(None);
+    }
+
+    fn process_filter(&self) -> Option<usize> {
+        self.filter.get()
+    }
+}
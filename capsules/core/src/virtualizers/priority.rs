@@ -0,0 +1,81 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Priorities of virtual devices.
+//!
+//! A mux serves one queued operation of its virtual devices at a time. By
+//! default all devices have the same priority and take turns, so a device
+//! that queues a new operation as soon as the last one completes, e.g. a bulk
+//! transfer, does not keep the others waiting. A latency-critical device can
+//! be given a higher priority with `set_priority()`, so that its queued
+//! operations are served before those of devices with lower priority.
+//! Devices with the same priority still take turns.
+
+use core::cell::Cell;
+
+/// Priority of the operations of a virtual device.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    High = 0,
+    #[default]
+    Normal = 1,
+    Low = 2,
+}
+
+/// Chooses which queued operation of its devices a mux serves next.
+pub struct Arbiter {
+    /// Index of the device that was served last.
+    last: Cell<usize>,
+}
+
+impl Arbiter {
+    pub const fn new() -> Arbiter {
+        Arbiter {
+            last: Cell::new(usize::MAX),
+        }
+    }
+
+    /// Returns the index, priority and device of the queued operation to
+    /// serve next, without marking it as served. `queued` returns the
+    /// priority of a device that has an operation queued. The device with
+    /// the highest priority wins, and among those with the same priority
+    /// the first one after the device that was served last.
+    pub fn peek<'a, T: 'a>(
+        &self,
+        devices: impl Iterator<Item = &'a T>,
+        queued: impl Fn(&T) -> Option<Priority>,
+    ) -> Option<(usize, Priority, &'a T)> {
+        let last = self.last.get();
+        devices
+            .enumerate()
+            .filter_map(|(index, device)| queued(device).map(|priority| (index, priority, device)))
+            // Devices after the last one come first, then those before it
+            // and the last one itself.
+            .min_by_key(|(index, priority, _)| (*priority, *index <= last, *index))
+    }
+
+    /// Record that the device at `index` was served.
+    pub fn served(&self, index: usize) {
+        self.last.set(index);
+    }
+
+    /// Returns the device to serve next, see `peek()`, and marks it as
+    /// served.
+    pub fn next<'a, T: 'a>(
+        &self,
+        devices: impl Iterator<Item = &'a T>,
+        queued: impl Fn(&T) -> Option<Priority>,
+    ) -> Option<&'a T> {
+        self.peek(devices, queued).map(|(index, _, device)| {
+            self.served(index);
+            device
+        })
+    }
+}
+
+impl Default for Arbiter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
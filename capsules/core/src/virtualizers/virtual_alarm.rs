@@ -4,11 +4,16 @@
 
 //! Virtualize the Alarm interface to enable multiple users of an underlying
 //! alarm hardware peripheral.
+//!
+//! `MuxAlarm` is a `kernel::platform::idle::IdleSource`: it reports the time
+//! left until the next alarm as the wake latency budget, so that the chip
+//! only sleeps in states it can wake up from in time.
 
 use core::cell::Cell;
 
 use kernel::collections::list::{List, ListLink, ListNode};
-use kernel::hil::time::{self, Alarm, Ticks, Time};
+use kernel::hil::time::{self, Alarm, ConvertTicks, Ticks, Time};
+use kernel::platform::idle::{IdleSource, SleepBudget, SleepDepth};
 use kernel::utilities::cells::OptionalCell;
 use kernel::ErrorCode;
 
@@ -214,6 +219,8 @@ pub struct MuxAlarm<'a, A: Alarm<'a>> {
     firing: Cell<bool>,
     /// Reference to next alarm
     next_tick_vals: Cell<Option<(A::Ticks, A::Ticks)>>,
+    /// Deepest sleep the underlying alarm keeps counting in.
+    sleep_depth: Cell<SleepDepth>,
 }
 
 impl<'a, A: Alarm<'a>> MuxAlarm<'a, A> {
@@ -224,9 +231,19 @@ impl<'a, A: Alarm<'a>> MuxAlarm<'a, A> {
             alarm: alarm,
             firing: Cell::new(false),
             next_tick_vals: Cell::new(None),
+            sleep_depth: Cell::new(SleepDepth::Deep),
         }
     }
 
+    /// Set the deepest sleep the underlying alarm keeps counting in. While
+    /// an alarm is pending, the chip will not sleep deeper than this. The
+    /// default, `SleepDepth::Deep`, is right for alarms driven by a
+    /// low-frequency clock; alarms on timers clocked from a high-speed clock
+    /// should use `SleepDepth::Shallow`.
+    pub fn set_sleep_depth(&self, depth: SleepDepth) {
+        self.sleep_depth.set(depth);
+    }
+
     pub fn set_alarm(&self, reference: A::Ticks, dt: A::Ticks) {
         self.next_tick_vals.set(Some((reference, dt)));
         self.alarm.set_alarm(reference, dt);
@@ -238,6 +255,28 @@ impl<'a, A: Alarm<'a>> MuxAlarm<'a, A> {
     }
 }
 
+impl<'a, A: Alarm<'a>> IdleSource for MuxAlarm<'a, A> {
+    fn sleep_budget(&self) -> SleepBudget {
+        let next = self.next_tick_vals.get();
+        match next.filter(|_| self.enabled.get() > 0) {
+            None => SleepBudget::UNCONSTRAINED,
+            Some((reference, dt)) => {
+                let now = self.alarm.now();
+                let expiration = reference.wrapping_add(dt);
+                let remaining = if now.within_range(reference, expiration) {
+                    expiration.wrapping_sub(now)
+                } else {
+                    A::Ticks::from(0u32)
+                };
+                SleepBudget {
+                    depth: self.sleep_depth.get(),
+                    wake_latency_us: Some(self.alarm.ticks_to_us(remaining)),
+                }
+            }
+        }
+    }
+}
+
 impl<'a, A: Alarm<'a>> time::AlarmClient for MuxAlarm<'a, A> {
     /// When the underlying alarm has fired, we have to multiplex this event back to the virtual
     /// alarms that should now fire.
@@ -37,12 +37,15 @@ use kernel::hil;
 use kernel::utilities::cells::{OptionalCell, TakeCell};
 use kernel::ErrorCode;
 
+use crate::virtualizers::priority::{Arbiter, Priority};
+
 /// Handle keeping a list of active users of flash hardware and serialize their
 /// requests. After each completed request the list is checked to see if there
 /// is another flash user with an outstanding read, write, or erase request.
 pub struct MuxFlash<'a, F: hil::flash::Flash + 'static> {
     flash: &'a F,
     users: List<'a, FlashUser<'a, F>>,
+    arbiter: Arbiter,
     inflight: OptionalCell<&'a FlashUser<'a, F>>,
 }
 
@@ -74,18 +77,19 @@ impl<'a, F: hil::flash::Flash> MuxFlash<'a, F> {
         MuxFlash {
             flash: flash,
             users: List::new(),
+            arbiter: Arbiter::new(),
             inflight: OptionalCell::empty(),
         }
     }
 
-    /// Scan the list of users and find the first user that has a pending
-    /// request, then issue that request to the flash hardware.
+    /// Scan the list of users and find the user with the highest priority
+    /// that has a pending request, then issue that request to the flash
+    /// hardware.
     fn do_next_op(&self) {
         if self.inflight.is_none() {
-            let mnode = self
-                .users
-                .iter()
-                .find(|node| node.operation.get() != Op::Idle);
+            let mnode = self.arbiter.next(self.users.iter(), |node| {
+                (node.operation.get() != Op::Idle).then_some(node.priority.get())
+            });
             mnode.map(|node| {
                 node.buffer.take().map_or_else(
                     || {
@@ -139,6 +143,7 @@ pub struct FlashUser<'a, F: hil::flash::Flash + 'static> {
     mux: &'a MuxFlash<'a, F>,
     buffer: TakeCell<'static, F::Page>,
     operation: Cell<Op>,
+    priority: Cell<Priority>,
     next: ListLink<'a, FlashUser<'a, F>>,
     client: OptionalCell<&'a dyn hil::flash::Client<FlashUser<'a, F>>>,
 }
@@ -149,10 +154,17 @@ impl<'a, F: hil::flash::Flash> FlashUser<'a, F> {
             mux: mux,
             buffer: TakeCell::empty(),
             operation: Cell::new(Op::Idle),
+            priority: Cell::new(Priority::Normal),
             next: ListLink::empty(),
             client: OptionalCell::empty(),
         }
     }
+
+    /// Serve the operations of this device before those of devices with a
+    /// lower priority, see `virtualizers::priority`.
+    pub fn set_priority(&self, priority: Priority) {
+        self.priority.set(priority);
+    }
 }
 
 impl<'a, F: hil::flash::Flash, C: hil::flash::Client<Self>> hil::flash::HasClient<'a, C>
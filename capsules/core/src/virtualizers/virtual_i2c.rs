@@ -5,7 +5,9 @@
 //! Virtualize an I2C master bus.
 //!
 //! `MuxI2C` provides shared access to a single I2C Master Bus for multiple
-//! users. `I2CDevice` provides access to a specific I2C address.
+//! users. `I2CDevice` provides access to a specific I2C address. Both device
+//! types implement `i2c::SMBusDeviceOps`, so SMBus block transfers and PEC
+//! framing are available to drivers on a shared bus.
 
 use core::cell::Cell;
 
@@ -13,25 +15,36 @@ use kernel::collections::list::{List, ListLink, ListNode};
 use kernel::deferred_call::{DeferredCall, DeferredCallClient};
 use kernel::hil::i2c::{self, Error, I2CClient, I2CHwMasterClient, NoSMBus};
 use kernel::utilities::cells::{OptionalCell, TakeCell};
+
+use crate::bus_trace::{Bus, BusTracer, Direction, Status};
+use crate::virtualizers::priority::{Arbiter, Priority};
+
 // `NoSMBus` provides a placeholder for `SMBusMaster` in case the board doesn't have a SMBus
 pub struct MuxI2C<'a, I: i2c::I2CMaster, S: i2c::SMBusMaster = NoSMBus> {
     i2c: &'a I,
     smbus: Option<&'a S>,
     i2c_devices: List<'a, I2CDevice<'a, I, S>>,
     smbus_devices: List<'a, SMBusDevice<'a, I, S>>,
+    i2c_arbiter: Arbiter,
+    smbus_arbiter: Arbiter,
     enabled: Cell<usize>,
     i2c_inflight: OptionalCell<&'a I2CDevice<'a, I, S>>,
     smbus_inflight: OptionalCell<&'a SMBusDevice<'a, I, S>>,
     deferred_call: DeferredCall,
+    tracer: OptionalCell<&'a dyn BusTracer>,
 }
 
 impl<I: i2c::I2CMaster, S: i2c::SMBusMaster> I2CHwMasterClient for MuxI2C<'_, I, S> {
     fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), Error>) {
         if self.i2c_inflight.is_some() {
+            self.tracer
+                .map(|tracer| tracer.complete(Bus::I2C, Some(buffer), status.into()));
             self.i2c_inflight.take().map(move |device| {
                 device.command_complete(buffer, status);
             });
         } else if self.smbus_inflight.is_some() {
+            self.tracer
+                .map(|tracer| tracer.complete(Bus::SMBus, Some(buffer), status.into()));
             self.smbus_inflight.take().map(move |device| {
                 device.command_complete(buffer, status);
             });
@@ -47,13 +60,38 @@ impl<'a, I: i2c::I2CMaster, S: i2c::SMBusMaster> MuxI2C<'a, I, S> {
             smbus,
             i2c_devices: List::new(),
             smbus_devices: List::new(),
+            i2c_arbiter: Arbiter::new(),
+            smbus_arbiter: Arbiter::new(),
             enabled: Cell::new(0),
             i2c_inflight: OptionalCell::empty(),
             smbus_inflight: OptionalCell::empty(),
             deferred_call: DeferredCall::new(),
+            tracer: OptionalCell::empty(),
         }
     }
 
+    /// Record every transaction issued on this bus into `tracer`.
+    pub fn set_tracer(&self, tracer: &'a dyn BusTracer) {
+        self.tracer.set(tracer);
+    }
+
+    fn trace_start(&self, bus: Bus, addr: u8, op: Op, buffer: &[u8]) {
+        self.tracer.map(|tracer| {
+            let (direction, write_len, read_len) = match op {
+                Op::Write(len) => (Direction::Write, len, 0),
+                Op::Read(len) => (Direction::Read, 0, len),
+                Op::WriteRead(wlen, rlen) => (Direction::WriteRead, wlen, rlen),
+                Op::Idle | Op::CommandComplete(_) => return,
+            };
+            tracer.start(bus, addr, direction, buffer, write_len, read_len);
+        });
+    }
+
+    fn trace_error(&self, bus: Bus, error: Error) {
+        self.tracer
+            .map(|tracer| tracer.complete(bus, None, Status::I2C(error)));
+    }
+
     fn enable(&self) {
         let enabled = self.enabled.get();
         self.enabled.set(enabled + 1);
@@ -74,19 +112,42 @@ impl<'a, I: i2c::I2CMaster, S: i2c::SMBusMaster> MuxI2C<'a, I, S> {
         if self.i2c_inflight.is_none() && self.smbus_inflight.is_none() {
             // Nothing is currently in flight
 
+            let i2c_next = self.i2c_arbiter.peek(self.i2c_devices.iter(), |node| {
+                (node.operation.get() != Op::Idle).then_some(node.priority.get())
+            });
+            let smbus_next = match self.smbus {
+                Some(_) => self.smbus_arbiter.peek(self.smbus_devices.iter(), |node| {
+                    (node.operation.get() != Op::Idle).then_some(node.priority.get())
+                }),
+                None => None,
+            };
+            // I2C operations go first, unless an SMBus operation has a higher
+            // priority.
+            let smbus_first = match (i2c_next, smbus_next) {
+                (Some((_, i2c_priority, _)), Some((_, smbus_priority, _))) => {
+                    smbus_priority < i2c_priority
+                }
+                _ => false,
+            };
+
             // Try to do the next I2C operation
-            let mnode = self
-                .i2c_devices
-                .iter()
-                .find(|node| node.operation.get() != Op::Idle);
+            let mnode = match smbus_first {
+                true => None,
+                false => i2c_next.map(|(index, _, node)| {
+                    self.i2c_arbiter.served(index);
+                    node
+                }),
+            };
             mnode.map(|node| {
                 node.buffer.take().map(|buf| {
+                    self.trace_start(Bus::I2C, node.addr, node.operation.get(), buf);
                     match node.operation.get() {
                         Op::Write(len) => match self.i2c.write(node.addr, buf, len) {
                             Ok(_) => {}
                             Err((error, buffer)) => {
                                 node.buffer.replace(buffer);
                                 node.operation.set(Op::CommandComplete(Err(error)));
+                                self.trace_error(Bus::I2C, error);
                                 node.mux.do_next_op_async();
                             }
                         },
@@ -95,6 +156,7 @@ impl<'a, I: i2c::I2CMaster, S: i2c::SMBusMaster> MuxI2C<'a, I, S> {
                             Err((error, buffer)) => {
                                 node.buffer.replace(buffer);
                                 node.operation.set(Op::CommandComplete(Err(error)));
+                                self.trace_error(Bus::I2C, error);
                                 node.mux.do_next_op_async();
                             }
                         },
@@ -104,6 +166,7 @@ impl<'a, I: i2c::I2CMaster, S: i2c::SMBusMaster> MuxI2C<'a, I, S> {
                                 Err((error, buffer)) => {
                                     node.buffer.replace(buffer);
                                     node.operation.set(Op::CommandComplete(Err(error)));
+                                    self.trace_error(Bus::I2C, error);
                                     node.mux.do_next_op_async();
                                 }
                             }
@@ -120,50 +183,56 @@ impl<'a, I: i2c::I2CMaster, S: i2c::SMBusMaster> MuxI2C<'a, I, S> {
 
             if self.i2c_inflight.is_none() && self.smbus.is_some() {
                 // No I2C operation in flight, try SMBus next
-                let mnode = self
-                    .smbus_devices
-                    .iter()
-                    .find(|node| node.operation.get() != Op::Idle);
+                let mnode = smbus_next.map(|(index, _, node)| {
+                    self.smbus_arbiter.served(index);
+                    node
+                });
                 mnode.map(|node| {
-                    node.buffer.take().map(|buf| match node.operation.get() {
-                        Op::Write(len) => {
-                            match self.smbus.unwrap().smbus_write(node.addr, buf, len) {
-                                Ok(_) => {}
-                                Err(e) => {
-                                    node.buffer.replace(e.1);
-                                    node.operation.set(Op::CommandComplete(Err(e.0)));
-                                    node.mux.do_next_op_async();
-                                }
-                            };
-                        }
-                        Op::Read(len) => {
-                            match self.smbus.unwrap().smbus_read(node.addr, buf, len) {
-                                Ok(_) => {}
-                                Err(e) => {
-                                    node.buffer.replace(e.1);
-                                    node.operation.set(Op::CommandComplete(Err(e.0)));
-                                    node.mux.do_next_op_async();
-                                }
-                            };
-                        }
-                        Op::WriteRead(wlen, rlen) => {
-                            match self
-                                .smbus
-                                .unwrap()
-                                .smbus_write_read(node.addr, buf, wlen, rlen)
-                            {
-                                Ok(_) => {}
-                                Err(e) => {
-                                    node.buffer.replace(e.1);
-                                    node.operation.set(Op::CommandComplete(Err(e.0)));
-                                    node.mux.do_next_op_async();
-                                }
-                            };
-                        }
-                        Op::CommandComplete(err) => {
-                            self.command_complete(buf, err);
+                    node.buffer.take().map(|buf| {
+                        self.trace_start(Bus::SMBus, node.addr, node.operation.get(), buf);
+                        match node.operation.get() {
+                            Op::Write(len) => {
+                                match self.smbus.unwrap().smbus_write(node.addr, buf, len) {
+                                    Ok(_) => {}
+                                    Err(e) => {
+                                        node.buffer.replace(e.1);
+                                        node.operation.set(Op::CommandComplete(Err(e.0)));
+                                        self.trace_error(Bus::SMBus, e.0);
+                                        node.mux.do_next_op_async();
+                                    }
+                                };
+                            }
+                            Op::Read(len) => {
+                                match self.smbus.unwrap().smbus_read(node.addr, buf, len) {
+                                    Ok(_) => {}
+                                    Err(e) => {
+                                        node.buffer.replace(e.1);
+                                        node.operation.set(Op::CommandComplete(Err(e.0)));
+                                        self.trace_error(Bus::SMBus, e.0);
+                                        node.mux.do_next_op_async();
+                                    }
+                                };
+                            }
+                            Op::WriteRead(wlen, rlen) => {
+                                match self
+                                    .smbus
+                                    .unwrap()
+                                    .smbus_write_read(node.addr, buf, wlen, rlen)
+                                {
+                                    Ok(_) => {}
+                                    Err(e) => {
+                                        node.buffer.replace(e.1);
+                                        node.operation.set(Op::CommandComplete(Err(e.0)));
+                                        self.trace_error(Bus::SMBus, e.0);
+                                        node.mux.do_next_op_async();
+                                    }
+                                };
+                            }
+                            Op::CommandComplete(err) => {
+                                self.command_complete(buf, err);
+                            }
+                            Op::Idle => unreachable!(),
                         }
-                        Op::Idle => unreachable!(),
                     });
                     node.operation.set(Op::Idle);
                     self.smbus_inflight.set(node);
@@ -210,6 +279,7 @@ pub struct I2CDevice<'a, I: i2c::I2CMaster, S: i2c::SMBusMaster = NoSMBus> {
     enabled: Cell<bool>,
     buffer: TakeCell<'static, [u8]>,
     operation: Cell<Op>,
+    priority: Cell<Priority>,
     next: ListLink<'a, I2CDevice<'a, I, S>>,
     client: OptionalCell<&'a dyn I2CClient>,
 }
@@ -222,11 +292,18 @@ impl<'a, I: i2c::I2CMaster, S: i2c::SMBusMaster> I2CDevice<'a, I, S> {
             enabled: Cell::new(false),
             buffer: TakeCell::empty(),
             operation: Cell::new(Op::Idle),
+            priority: Cell::new(Priority::Normal),
             next: ListLink::empty(),
             client: OptionalCell::empty(),
         }
     }
 
+    /// Serve the operations of this device before those of devices with a
+    /// lower priority, see `virtualizers::priority`.
+    pub fn set_priority(&self, priority: Priority) {
+        self.priority.set(priority);
+    }
+
     pub fn set_client(&'a self, client: &'a dyn I2CClient) {
         self.mux.i2c_devices.push_head(self);
         self.client.set(client);
@@ -307,12 +384,19 @@ impl<I: i2c::I2CMaster> i2c::I2CDevice for I2CDevice<'_, I> {
     }
 }
 
+impl<I: i2c::I2CMaster> i2c::SMBusDeviceOps for I2CDevice<'_, I> {
+    fn smbus_address(&self) -> u8 {
+        self.addr
+    }
+}
+
 pub struct SMBusDevice<'a, I: i2c::I2CMaster, S: i2c::SMBusMaster> {
     mux: &'a MuxI2C<'a, I, S>,
     addr: u8,
     enabled: Cell<bool>,
     buffer: TakeCell<'static, [u8]>,
     operation: Cell<Op>,
+    priority: Cell<Priority>,
     next: ListLink<'a, SMBusDevice<'a, I, S>>,
     client: OptionalCell<&'a dyn I2CClient>,
 }
@@ -329,11 +413,18 @@ impl<'a, I: i2c::I2CMaster, S: i2c::SMBusMaster> SMBusDevice<'a, I, S> {
             enabled: Cell::new(false),
             buffer: TakeCell::empty(),
             operation: Cell::new(Op::Idle),
+            priority: Cell::new(Priority::Normal),
             next: ListLink::empty(),
             client: OptionalCell::empty(),
         }
     }
 
+    /// Serve the operations of this device before those of devices with a
+    /// lower priority, see `virtualizers::priority`.
+    pub fn set_priority(&self, priority: Priority) {
+        self.priority.set(priority);
+    }
+
     pub fn set_client(&'a self, client: &'a dyn I2CClient) {
         self.mux.smbus_devices.push_head(self);
         self.client.set(client);
@@ -461,3 +552,9 @@ impl<'a, I: i2c::I2CMaster, S: i2c::SMBusMaster> i2c::SMBusDevice for SMBusDevic
         }
     }
 }
+
+impl<'a, I: i2c::I2CMaster, S: i2c::SMBusMaster> i2c::SMBusDeviceOps for SMBusDevice<'a, I, S> {
+    fn smbus_address(&self) -> u8 {
+        self.addr
+    }
+}
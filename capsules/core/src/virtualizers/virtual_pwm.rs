@@ -7,6 +7,11 @@
 //! `MuxPwm` provides shared access to a single PWM interface for multiple
 //! users. `PwmPinUser` provides access to a specific PWM pin.
 //!
+//! Users whose pins the hardware reports as independent (see
+//! `hil::pwm::Pwm::is_independent`) run at the same time. A user that would
+//! disturb a running user, for example because its pin shares a counter with
+//! the running pin, is queued and starts once the conflicting user stops.
+//!
 //! Usage
 //! -----
 //!
@@ -24,6 +29,8 @@
 //! virtual_pwm_buzzer.add_to_mux();
 //! ```
 
+use core::cell::Cell;
+
 use kernel::collections::list::{List, ListLink, ListNode};
 use kernel::hil;
 use kernel::utilities::cells::OptionalCell;
@@ -32,95 +39,64 @@ use kernel::ErrorCode;
 pub struct MuxPwm<'a, P: hil::pwm::Pwm> {
     pwm: &'a P,
     devices: List<'a, PwmPinUser<'a, P>>,
-    inflight: OptionalCell<&'a PwmPinUser<'a, P>>,
 }
 
 impl<'a, P: hil::pwm::Pwm> MuxPwm<'a, P> {
     pub const fn new(pwm: &'a P) -> MuxPwm<'a, P> {
         MuxPwm {
-            pwm: pwm,
+            pwm,
             devices: List::new(),
-            inflight: OptionalCell::empty(),
         }
     }
 
-    /// If we are not currently doing anything, scan the list of devices for
-    /// one with an outstanding operation and run that.
+    /// Whether `node` can change the hardware configuration of its pin
+    /// without disturbing a signal another user is outputting.
+    fn can_start(&self, node: &PwmPinUser<'a, P>) -> bool {
+        self.devices.iter().all(|other| {
+            core::ptr::eq(other, node)
+                || !other.running.get()
+                || self.pwm.is_independent(&node.pin, &other.pin)
+        })
+    }
+
+    /// Start every queued user that no longer conflicts with a running one.
     fn do_next_op(&self) {
-        if self.inflight.is_none() {
-            let mnode = self.devices.iter().find(|node| node.operation.is_some());
-            mnode.map(|node| {
-                let started = node.operation.take().map_or(false, |operation| {
-                    match operation {
-                        Operation::Simple {
-                            frequency_hz,
-                            duty_cycle,
-                        } => {
-                            let _ = self.pwm.start(&node.pin, frequency_hz, duty_cycle);
-                            true
-                        }
-                        Operation::Stop => {
-                            // Can't stop if nothing is running
-                            false
-                        }
-                    }
-                });
-                if started {
-                    self.inflight.set(node);
-                } else {
-                    // Keep looking for something to do.
-                    self.do_next_op();
-                }
-            });
-        } else {
-            // We are running so we do whatever the inflight user wants, if
-            // there is some command there.
-            self.inflight.map(|node| {
-                node.operation.take().map(|operation| {
-                    match operation {
-                        Operation::Simple {
-                            frequency_hz,
-                            duty_cycle,
-                        } => {
-                            // Changed some parameter.
-                            let _ = self.pwm.start(&node.pin, frequency_hz, duty_cycle);
-                        }
-                        Operation::Stop => {
-                            // Ok we got a stop.
-                            let _ = self.pwm.stop(&node.pin);
-                            self.inflight.clear();
-                        }
-                    }
-                    // Recurse in case there is more to do.
-                    self.do_next_op();
-                });
-            });
+        for node in self.devices.iter() {
+            if node.pending.is_none() || !self.can_start(node) {
+                continue;
+            }
+            if let Some(pending) = node.pending.take() {
+                let result = self
+                    .pwm
+                    .start(&node.pin, pending.frequency_hz, pending.duty_cycle);
+                node.running.set(result.is_ok());
+            }
         }
     }
 }
 
+/// A start request waiting for a conflicting user to stop.
 #[derive(Copy, Clone, PartialEq)]
-enum Operation {
-    Simple {
-        frequency_hz: usize,
-        duty_cycle: usize,
-    },
-    Stop,
+struct PendingStart {
+    frequency_hz: usize,
+    duty_cycle: usize,
 }
 
 pub struct PwmPinUser<'a, P: hil::pwm::Pwm> {
     mux: &'a MuxPwm<'a, P>,
     pin: P::Pin,
-    operation: OptionalCell<Operation>,
+    pending: OptionalCell<PendingStart>,
+    running: Cell<bool>,
     next: ListLink<'a, PwmPinUser<'a, P>>,
 }
 
 impl<'a, P: hil::pwm::Pwm> PwmPinUser<'a, P> {
     pub const fn new(mux: &'a MuxPwm<'a, P>, pin: P::Pin) -> PwmPinUser<'a, P> {
         PwmPinUser {
-            mux: mux,
-            pin: pin,
-            operation: OptionalCell::empty(),
+            mux,
+            pin,
+            pending: OptionalCell::empty(),
+            running: Cell::new(false),
             next: ListLink::empty(),
         }
     }
@@ -137,19 +113,37 @@ impl<'a, P: hil::pwm::Pwm> ListNode<'a, PwmPinUser<'a, P>> for PwmPinUser<'a, P>
 }
 
 impl<P: hil::pwm::Pwm> hil::pwm::PwmPin for PwmPinUser<'_, P> {
+    /// Start or update the signal. If another user is running a pin that
+    /// is not independent of this one, the request is queued, `Ok(())` is
+    /// returned, and the signal starts once the other user stops.
     fn start(&self, frequency_hz: usize, duty_cycle: usize) -> Result<(), ErrorCode> {
-        self.operation.set(Operation::Simple {
-            frequency_hz,
-            duty_cycle,
-        });
-        self.mux.do_next_op();
-        Ok(())
+        if self.running.get() || self.mux.can_start(self) {
+            self.pending.clear();
+            let result = self.mux.pwm.start(&self.pin, frequency_hz, duty_cycle);
+            if result.is_ok() {
+                self.running.set(true);
+            }
+            result
+        } else {
+            self.pending.set(PendingStart {
+                frequency_hz,
+                duty_cycle,
+            });
+            Ok(())
+        }
     }
 
     fn stop(&self) -> Result<(), ErrorCode> {
-        self.operation.set(Operation::Stop);
-        self.mux.do_next_op();
-        Ok(())
+        self.pending.clear();
+        if self.running.get() {
+            self.running.set(false);
+            let result = self.mux.pwm.stop(&self.pin);
+            // Stopping may let queued users start.
+            self.mux.do_next_op();
+            result
+        } else {
+            Ok(())
+        }
     }
 
     fn get_maximum_frequency_hz(&self) -> usize {
@@ -159,4 +153,14 @@ impl<P: hil::pwm::Pwm> hil::pwm::PwmPin for PwmPinUser<'_, P> {
     fn get_maximum_duty_cycle(&self) -> usize {
         self.mux.pwm.get_maximum_duty_cycle()
     }
+
+    /// Changing the mode may affect other pins sharing hardware, so it
+    /// returns `BUSY` while a conflicting user is running.
+    fn set_mode(&self, mode: hil::pwm::Mode) -> Result<(), ErrorCode> {
+        if self.running.get() || self.mux.can_start(self) {
+            self.mux.pwm.set_mode(&self.pin, mode)
+        } else {
+            Err(ErrorCode::BUSY)
+        }
+    }
 }
@@ -12,13 +12,19 @@ use kernel::hil::spi::SpiMasterClient;
 use kernel::utilities::cells::{OptionalCell, TakeCell};
 use kernel::ErrorCode;
 
+use crate::virtualizers::priority::{Arbiter, Priority};
+
+use crate::bus_trace::{Bus, BusTracer, Direction, Status};
+
 /// The Mux struct manages multiple Spi clients. Each client may have
 /// at most one outstanding Spi request.
 pub struct MuxSpiMaster<'a, Spi: hil::spi::SpiMaster> {
     spi: &'a Spi,
     devices: List<'a, VirtualSpiMasterDevice<'a, Spi>>,
+    arbiter: Arbiter,
     inflight: OptionalCell<&'a VirtualSpiMasterDevice<'a, Spi>>,
     deferred_call: DeferredCall,
+    tracer: OptionalCell<&'a dyn BusTracer>,
 }
 
 impl<Spi: hil::spi::SpiMaster> hil::spi::SpiMasterClient for MuxSpiMaster<'_, Spi> {
@@ -29,6 +35,9 @@ impl<Spi: hil::spi::SpiMaster> hil::spi::SpiMasterClient for MuxSpiMaster<'_, Sp
         len: usize,
         status: Result<(), ErrorCode>,
     ) {
+        self.tracer.map(|tracer| {
+            tracer.complete(Bus::Spi, read_buffer.as_deref(), status.into());
+        });
         let dev = self.inflight.take();
         // Need to do next op before signaling so we get some kind of
         // sharing. Otherwise a call to read_write in the callback
@@ -46,17 +55,47 @@ impl<'a, Spi: hil::spi::SpiMaster> MuxSpiMaster<'a, Spi> {
         Self {
             spi,
             devices: List::new(),
+            arbiter: Arbiter::new(),
             inflight: OptionalCell::empty(),
             deferred_call: DeferredCall::new(),
+            tracer: OptionalCell::empty(),
         }
     }
 
-    fn do_next_op(&self) {
-        if self.inflight.is_none() {
-            let mnode = self
+    /// Record every transaction issued on this bus into `tracer`. As SPI
+    /// devices have no address, the index of the device on the mux is
+    /// recorded instead.
+    pub fn set_tracer(&self, tracer: &'a dyn BusTracer) {
+        self.tracer.set(tracer);
+    }
+
+    fn trace_start(
+        &self,
+        node: &VirtualSpiMasterDevice<'a, Spi>,
+        txbuffer: &[u8],
+        read: bool,
+        len: usize,
+    ) {
+        self.tracer.map(|tracer| {
+            let index = self
                 .devices
                 .iter()
-                .find(|node| node.operation.get() != Op::Idle);
+                .position(|device| core::ptr::eq(device, node))
+                .unwrap_or(0);
+            let (direction, read_len) = if read {
+                (Direction::WriteRead, len)
+            } else {
+                (Direction::Write, 0)
+            };
+            tracer.start(Bus::Spi, index as u8, direction, txbuffer, len, read_len);
+        });
+    }
+
+    fn do_next_op(&self) {
+        if self.inflight.is_none() {
+            let mnode = self.arbiter.next(self.devices.iter(), |node| {
+                (node.operation.get() != Op::Idle).then_some(node.priority.get())
+            });
             mnode.map(|node| {
                 let configuration = node.configuration.get();
                 let cs = configuration.chip_select;
@@ -81,9 +120,13 @@ impl<'a, Spi: hil::spi::SpiMaster> MuxSpiMaster<'a, Spi> {
                                 self.do_next_op_async();
                             } else {
                                 let rxbuffer = node.rxbuffer.take();
+                                self.trace_start(node, txbuffer, rxbuffer.is_some(), len);
                                 if let Err((e, write_buffer, read_buffer)) =
                                     self.spi.read_write_bytes(txbuffer, rxbuffer, len)
                                 {
+                                    self.tracer.map(|tracer| {
+                                        tracer.complete(Bus::Spi, None, Status::Error(e))
+                                    });
                                     node.txbuffer.replace(write_buffer);
                                     read_buffer.map(|buffer| {
                                         node.rxbuffer.replace(buffer);
@@ -174,6 +217,7 @@ pub struct VirtualSpiMasterDevice<'a, Spi: hil::spi::SpiMaster> {
     txbuffer: TakeCell<'static, [u8]>,
     rxbuffer: TakeCell<'static, [u8]>,
     operation: Cell<Op>,
+    priority: Cell<Priority>,
     next: ListLink<'a, VirtualSpiMasterDevice<'a, Spi>>,
     client: OptionalCell<&'a dyn hil::spi::SpiMasterClient>,
 }
@@ -194,11 +238,18 @@ impl<'a, Spi: hil::spi::SpiMaster> VirtualSpiMasterDevice<'a, Spi> {
             txbuffer: TakeCell::empty(),
             rxbuffer: TakeCell::empty(),
             operation: Cell::new(Op::Idle),
+            priority: Cell::new(Priority::Normal),
             next: ListLink::empty(),
             client: OptionalCell::empty(),
         }
     }
 
+    /// Serve the operations of this device before those of devices with a
+    /// lower priority, see `virtualizers::priority`.
+    pub fn set_priority(&self, priority: Priority) {
+        self.priority.set(priority);
+    }
+
     /// Must be called right after `static_init!()`.
     pub fn setup(&'a self) {
         self.mux.devices.push_head(self);
@@ -345,6 +396,12 @@ impl<Spi: hil::spi::SpiSlave> hil::spi::SpiSlaveClient for SpiSlaveDevice<'_, Sp
             client.chip_selected();
         });
     }
+
+    fn chip_deselected(&self) {
+        self.client.map(move |client| {
+            client.chip_deselected();
+        });
+    }
 }
 
 impl<'a, Spi: hil::spi::SpiSlave> hil::spi::SpiSlaveDevice for SpiSlaveDevice<'a, Spi> {
@@ -361,6 +418,10 @@ impl<'a, Spi: hil::spi::SpiSlave> hil::spi::SpiSlaveDevice for SpiSlaveDevice<'a
         self.spi.set_phase(cpal)
     }
 
+    fn set_write_byte(&self, write_byte: u8) {
+        self.spi.set_write_byte(write_byte)
+    }
+
     fn read_write_bytes(
         &self,
         write_buffer: Option<&'static mut [u8]>,
@@ -44,6 +44,33 @@
 //! hil::uart::UART::set_transmit_client(console_uart, console);
 //! hil::uart::UART::set_receive_client(console_uart, console);
 //! ```
+//!
+//! Statistics
+//! ----------
+//!
+//! The mux keeps per-device counters of the bytes transmitted and received,
+//! the bytes each receiving device missed because it had no read
+//! outstanding (or a read shorter than the data that arrived), failed
+//! transmissions, and the longest transmit queue each device had to wait
+//! in. They are exposed through the `UartMuxStatistics` trait, which the
+//! process console `uart` command uses. Devices can be given a name with
+//! `UartDevice::set_name()` to tell them apart.
+//!
+//! DMA transmissions
+//! -----------------
+//!
+//! If the UART implements `uart::TransmitAdvanced`, the board can pass it to
+//! the mux with a staging buffer:
+//!
+//! ```rust
+//! uart_mux.set_transmit_advanced(&sam4l::usart::USART0, &mut TX_DMA_BUF);
+//! ```
+//!
+//! The mux then copies the queued transmissions of several devices, in the
+//! order it would serve them, into the staging buffer and sends them with a
+//! single DMA transfer, instead of waiting for each transmission to complete
+//! before starting the next one. Transmissions that do not fit into the
+//! buffer are sent on their own.
 
 use core::cell::Cell;
 use core::cmp;
@@ -54,16 +81,60 @@ use kernel::hil::uart;
 use kernel::utilities::cells::{OptionalCell, TakeCell};
 use kernel::ErrorCode;
 
+use crate::virtualizers::priority::{Arbiter, Priority};
+
 pub const RX_BUF_LEN: usize = 64;
 
+/// Counters kept by the mux for each `UartDevice`.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct UartDeviceStats {
+    /// Name set with `UartDevice::set_name()`, empty if none.
+    pub name: &'static str,
+    /// Whether the device is passed incoming data.
+    pub receiver: bool,
+    /// Bytes (or words, for `transmit_word()`) successfully transmitted.
+    pub tx_bytes: u32,
+    /// Bytes copied into the device's receive buffers.
+    pub rx_bytes: u32,
+    /// Received bytes the device missed because it had no receive
+    /// outstanding or its receive was shorter than the incoming data.
+    pub rx_dropped: u32,
+    /// Transmissions that completed with an error.
+    pub tx_errors: u32,
+    /// Largest number of transmissions pending on the mux, including this
+    /// device's own, when this device queued a transmission.
+    pub tx_queue_max: usize,
+}
+
+/// Interface used by the process console to inspect the mux counters.
+pub trait UartMuxStatistics {
+    /// Number of devices attached to the mux.
+    fn device_count(&self) -> usize;
+
+    /// Counters of device `index`, in the order of the mux's device list.
+    fn device_stats(&self, index: usize) -> Option<UartDeviceStats>;
+
+    /// Largest number of transmissions that were pending on the mux at once.
+    fn tx_queue_max(&self) -> usize;
+
+    /// Reset all counters and high-water marks.
+    fn reset_stats(&self);
+}
+
 pub struct MuxUart<'a> {
     uart: &'a dyn uart::Uart<'a>,
     speed: u32,
     devices: List<'a, UartDevice<'a>>,
+    arbiter: Arbiter,
     inflight: OptionalCell<&'a UartDevice<'a>>,
     buffer: TakeCell<'static, [u8]>,
     completing_read: Cell<bool>,
     deferred_call: DeferredCall,
+    tx_queue_max: Cell<usize>,
+    uart_advanced: OptionalCell<&'a dyn uart::TransmitAdvanced<'a>>,
+    dma_buffer: TakeCell<'static, [u8]>,
+    /// Whether the staging buffer is being transmitted.
+    dma_inflight: Cell<bool>,
 }
 
 impl<'a> uart::TransmitClient for MuxUart<'a> {
@@ -73,6 +144,12 @@ impl<'a> uart::TransmitClient for MuxUart<'a> {
         tx_len: usize,
         rcode: Result<(), ErrorCode>,
     ) {
+        if self.dma_inflight.replace(false) {
+            self.dma_buffer.replace(tx_buffer);
+            self.complete_batch(rcode);
+            self.do_next_op();
+            return;
+        }
         self.inflight.map(move |device| {
             self.inflight.clear();
             device.transmitted_buffer(tx_buffer, tx_len, rcode);
@@ -108,23 +185,29 @@ impl<'a> uart::ReceiveClient for MuxUart<'a> {
         // copies the underlying UART read into each of the client buffers.
         self.devices.iter().for_each(|device| {
             if device.receiver {
-                device.rx_buffer.take().map(|rxbuf| {
+                let copied = device.rx_buffer.take().map_or(0, |rxbuf| {
                     let state = device.state.get();
                     // Copy the read into the buffer starting at rx_position
                     let position = device.rx_position.get();
                     let remaining = device.rx_len.get() - position;
                     let len = cmp::min(rx_len, remaining);
-                    if state == UartDeviceReceiveState::Receiving
+                    let copied = if state == UartDeviceReceiveState::Receiving
                         || state == UartDeviceReceiveState::Aborting
                     {
                         // debug!("Have {} bytes, copying in bytes {}-{}, {} remain", rx_len, position, position + len, remaining);
                         for i in 0..len {
                             rxbuf[position + i] = buffer[i];
                         }
-                    }
+                        len
+                    } else {
+                        0
+                    };
                     device.rx_position.set(position + len);
                     device.rx_buffer.replace(rxbuf);
+                    copied
                 });
+                device.count(&device.rx_bytes, copied);
+                device.count(&device.rx_dropped, rx_len - copied);
             }
         });
         // If the underlying read completes a client read, issue a callback to
@@ -195,13 +278,29 @@ impl<'a> MuxUart<'a> {
             uart,
             speed,
             devices: List::new(),
+            arbiter: Arbiter::new(),
             inflight: OptionalCell::empty(),
             buffer: TakeCell::new(buffer),
             completing_read: Cell::new(false),
             deferred_call: DeferredCall::new(),
+            tx_queue_max: Cell::new(0),
+            uart_advanced: OptionalCell::empty(),
+            dma_buffer: TakeCell::empty(),
+            dma_inflight: Cell::new(false),
         }
     }
 
+    /// Transmit through `uart`, which must be the UART the mux was created
+    /// with, combining queued transmissions in `buffer`.
+    pub fn set_transmit_advanced(
+        &self,
+        uart: &'a dyn uart::TransmitAdvanced<'a>,
+        buffer: &'static mut [u8],
+    ) {
+        self.uart_advanced.set(uart);
+        self.dma_buffer.replace(buffer);
+    }
+
     pub fn initialize(&self) {
         let _ = self.uart.configure(uart::Parameters {
             baud_rate: self.speed,
@@ -213,8 +312,10 @@ impl<'a> MuxUart<'a> {
     }
 
     fn do_next_op(&self) {
-        if self.inflight.is_none() {
-            let mnode = self.devices.iter().find(|node| node.operation.is_some());
+        if self.inflight.is_none() && !self.dma_inflight.get() && !self.start_batch() {
+            let mnode = self.arbiter.next(self.devices.iter(), |node| {
+                node.operation.is_some().then_some(node.priority.get())
+            });
             mnode.map(|node| {
                 node.tx_buffer.take().map(|buf| {
                     node.operation.map(move |op| match op {
@@ -245,6 +346,71 @@ impl<'a> MuxUart<'a> {
         }
     }
 
+    /// Copy queued transmissions into the staging buffer, in the order they
+    /// would be served, and transmit them with DMA. Stops at the first
+    /// operation that is not a transmission or does not fit. Returns whether
+    /// the staging buffer is being transmitted.
+    fn start_batch(&self) -> bool {
+        let uart = match self.uart_advanced.extract() {
+            Some(uart) => uart,
+            None => return false,
+        };
+        let buffer = match self.dma_buffer.take() {
+            Some(buffer) => buffer,
+            None => return false,
+        };
+        let capacity = cmp::min(buffer.len(), uart.max_dma_len());
+        let mut batch_len = 0;
+        while let Some((index, _, node)) = self.arbiter.peek(self.devices.iter(), |node| {
+            node.operation.is_some().then_some(node.priority.get())
+        }) {
+            let tx_len = match node.operation.extract() {
+                Some(Operation::Transmit { len }) => {
+                    cmp::min(len, node.tx_buffer.map_or(0, |buf| buf.len()))
+                }
+                _ => break,
+            };
+            if batch_len + tx_len > capacity {
+                break;
+            }
+            node.tx_buffer.map(|buf| {
+                buffer[batch_len..batch_len + tx_len].copy_from_slice(&buf[..tx_len]);
+            });
+            batch_len += tx_len;
+            node.operation.clear();
+            node.batched.set(tx_len);
+            self.arbiter.served(index);
+        }
+
+        if batch_len == 0 {
+            self.dma_buffer.replace(buffer);
+            return false;
+        }
+        match uart.transmit_buffer_dma(buffer, batch_len) {
+            Ok(()) => {
+                self.dma_inflight.set(true);
+                true
+            }
+            Err((ecode, buffer)) => {
+                self.dma_buffer.replace(buffer);
+                self.complete_batch(Err(ecode));
+                false
+            }
+        }
+    }
+
+    /// Return the buffers of the transmissions in the staging buffer.
+    fn complete_batch(&self, rcode: Result<(), ErrorCode>) {
+        self.devices.iter().for_each(|device| {
+            if let Some(tx_len) = device.batched.take() {
+                if let Some(buf) = device.tx_buffer.take() {
+                    let tx_len = if rcode.is_ok() { tx_len } else { 0 };
+                    uart::TransmitClient::transmitted_buffer(device, buf, tx_len, rcode);
+                }
+            }
+        });
+    }
+
     /// Starts a new UART reception, return value denotes whether starting
     /// the reception will issue a callback before the new read. A callback
     /// needs to be issued before the new read if a read was ongoing; the
@@ -292,6 +458,52 @@ impl<'a> MuxUart<'a> {
     fn do_next_op_async(&self) {
         self.deferred_call.set();
     }
+
+    /// Update the queue high-water marks after `device` queued a
+    /// transmission.
+    fn record_tx_queued(&self, device: &UartDevice) {
+        let pending = self
+            .devices
+            .iter()
+            .filter(|node| node.operation.is_some())
+            .count()
+            + self.inflight.map_or(0, |_| 1)
+            + self
+                .devices
+                .iter()
+                .filter(|node| node.batched.is_some())
+                .count();
+        device
+            .tx_queue_max
+            .set(cmp::max(device.tx_queue_max.get(), pending));
+        self.tx_queue_max
+            .set(cmp::max(self.tx_queue_max.get(), pending));
+    }
+}
+
+impl<'a> UartMuxStatistics for MuxUart<'a> {
+    fn device_count(&self) -> usize {
+        self.devices.iter().count()
+    }
+
+    fn device_stats(&self, index: usize) -> Option<UartDeviceStats> {
+        self.devices.iter().nth(index).map(|device| device.stats())
+    }
+
+    fn tx_queue_max(&self) -> usize {
+        self.tx_queue_max.get()
+    }
+
+    fn reset_stats(&self) {
+        self.tx_queue_max.set(0);
+        self.devices.iter().for_each(|device| {
+            device.tx_bytes.set(0);
+            device.rx_bytes.set(0);
+            device.rx_dropped.set(0);
+            device.tx_errors.set(0);
+            device.tx_queue_max.set(0);
+        });
+    }
 }
 
 impl DeferredCallClient for MuxUart<'_> {
@@ -327,9 +539,18 @@ pub struct UartDevice<'a> {
     rx_position: Cell<usize>,
     rx_len: Cell<usize>,
     operation: OptionalCell<Operation>,
+    /// Length of the transmission in the mux's staging buffer, if any.
+    batched: OptionalCell<usize>,
+    priority: Cell<Priority>,
     next: ListLink<'a, UartDevice<'a>>,
     rx_client: OptionalCell<&'a dyn uart::ReceiveClient>,
     tx_client: OptionalCell<&'a dyn uart::TransmitClient>,
+    name: Cell<&'static str>,
+    tx_bytes: Cell<u32>,
+    rx_bytes: Cell<u32>,
+    rx_dropped: Cell<u32>,
+    tx_errors: Cell<u32>,
+    tx_queue_max: Cell<usize>,
 }
 
 impl<'a> UartDevice<'a> {
@@ -344,9 +565,17 @@ impl<'a> UartDevice<'a> {
             rx_position: Cell::new(0),
             rx_len: Cell::new(0),
             operation: OptionalCell::empty(),
+            batched: OptionalCell::empty(),
+            priority: Cell::new(Priority::Normal),
             next: ListLink::empty(),
             rx_client: OptionalCell::empty(),
             tx_client: OptionalCell::empty(),
+            name: Cell::new(""),
+            tx_bytes: Cell::new(0),
+            rx_bytes: Cell::new(0),
+            rx_dropped: Cell::new(0),
+            tx_errors: Cell::new(0),
+            tx_queue_max: Cell::new(0),
         }
     }
 
@@ -354,6 +583,40 @@ impl<'a> UartDevice<'a> {
     pub fn setup(&'a self) {
         self.mux.devices.push_head(self);
     }
+
+    /// Name reported with this device's statistics.
+    pub fn set_name(&self, name: &'static str) {
+        self.name.set(name);
+    }
+
+    /// Serve the operations of this device before those of devices with a
+    /// lower priority, see `virtualizers::priority`.
+    pub fn set_priority(&self, priority: Priority) {
+        self.priority.set(priority);
+    }
+
+    pub fn stats(&self) -> UartDeviceStats {
+        UartDeviceStats {
+            name: self.name.get(),
+            receiver: self.receiver,
+            tx_bytes: self.tx_bytes.get(),
+            rx_bytes: self.rx_bytes.get(),
+            rx_dropped: self.rx_dropped.get(),
+            tx_errors: self.tx_errors.get(),
+            tx_queue_max: self.tx_queue_max.get(),
+        }
+    }
+
+    fn count(&self, counter: &Cell<u32>, amount: usize) {
+        counter.set(counter.get().wrapping_add(amount as u32));
+    }
+
+    fn count_transmit(&self, tx_len: usize, rcode: Result<(), ErrorCode>) {
+        match rcode {
+            Ok(()) => self.count(&self.tx_bytes, tx_len),
+            Err(_) => self.count(&self.tx_errors, 1),
+        }
+    }
 }
 
 impl<'a> uart::TransmitClient for UartDevice<'a> {
@@ -363,6 +626,7 @@ impl<'a> uart::TransmitClient for UartDevice<'a> {
         tx_len: usize,
         rcode: Result<(), ErrorCode>,
     ) {
+        self.count_transmit(tx_len, rcode);
         self.tx_client.map(move |client| {
             self.transmitting.set(false);
             client.transmitted_buffer(tx_buffer, tx_len, rcode);
@@ -370,6 +634,7 @@ impl<'a> uart::TransmitClient for UartDevice<'a> {
     }
 
     fn transmitted_word(&self, rcode: Result<(), ErrorCode>) {
+        self.count_transmit(1, rcode);
         self.tx_client.map(move |client| {
             self.transmitting.set(false);
             client.transmitted_word(rcode);
@@ -418,6 +683,7 @@ impl<'a> uart::Transmit<'a> for UartDevice<'a> {
             self.tx_buffer.replace(tx_data);
             self.transmitting.set(true);
             self.operation.set(Operation::Transmit { len: tx_len });
+            self.mux.record_tx_queued(self);
             self.mux.do_next_op_async();
             Ok(())
         }
@@ -429,6 +695,7 @@ impl<'a> uart::Transmit<'a> for UartDevice<'a> {
         } else {
             self.transmitting.set(true);
             self.operation.set(Operation::TransmitWord { word: word });
+            self.mux.record_tx_queued(self);
             self.mux.do_next_op_async();
             Ok(())
         }
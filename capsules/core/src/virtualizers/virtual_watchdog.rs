@@ -0,0 +1,180 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Multiplex one hardware watchdog across several logical watchers.
+//!
+//! The kernel loop feeds the watchdog through the `WatchDog` interface, so
+//! the hardware watchdog catches a hung kernel loop. A subsystem that can
+//! hang without stopping the kernel loop, such as a radio stack waiting for
+//! an interrupt that never comes, registers a `WatchdogWatcher`. While it
+//! is started, it must check in within its window. `MuxWatchdog` only feeds
+//! the hardware watchdog while all started watchers are within their
+//! windows, so a watcher that starves makes the hardware watchdog reset the
+//! chip.
+//!
+//! When a watcher starves, `MuxWatchdog` remembers it and tells its client,
+//! which can record it somewhere that survives the reset (e.g. a retained
+//! register or flash).
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let watchdog_mux = static_init!(
+//!     MuxWatchdog<'static, Rtc<'static>>,
+//!     MuxWatchdog::new(&peripherals.wdt, rtc)
+//! );
+//! let radio_watcher = static_init!(
+//!     WatchdogWatcher<'static, Rtc<'static>>,
+//!     WatchdogWatcher::new(watchdog_mux, "radio", 2000)
+//! );
+//! radio_watcher.setup();
+//!
+//! impl KernelResources<...> for Platform {
+//!     type WatchDog = MuxWatchdog<'static, Rtc<'static>>;
+//!     fn watchdog(&self) -> &Self::WatchDog {
+//!         self.watchdog_mux
+//!     }
+//! }
+//! ```
+
+use core::cell::Cell;
+
+use kernel::collections::list::{List, ListLink, ListNode};
+use kernel::hil::time::{ConvertTicks, Ticks, Time};
+use kernel::platform::watchdog::WatchDog;
+use kernel::utilities::cells::OptionalCell;
+
+/// Notified when a watcher of a `MuxWatchdog` starves.
+pub trait MuxWatchdogClient {
+    /// The watcher `name` did not check in within its window. The hardware
+    /// watchdog is no longer fed, so the chip resets soon.
+    fn watcher_starved(&self, name: &'static str);
+}
+
+/// A subsystem watched by a `MuxWatchdog`.
+pub struct WatchdogWatcher<'a, T: Time> {
+    mux: &'a MuxWatchdog<'a, T>,
+    name: &'static str,
+    /// How long the watcher may go without checking in.
+    window: T::Ticks,
+    last_check_in: Cell<T::Ticks>,
+    started: Cell<bool>,
+    next: ListLink<'a, WatchdogWatcher<'a, T>>,
+}
+
+impl<'a, T: Time> ListNode<'a, WatchdogWatcher<'a, T>> for WatchdogWatcher<'a, T> {
+    fn next(&'a self) -> &'a ListLink<'a, WatchdogWatcher<'a, T>> {
+        &self.next
+    }
+}
+
+impl<'a, T: Time> WatchdogWatcher<'a, T> {
+    /// After calling new, always call setup(). The watcher must check in at
+    /// least every `window_ms` milliseconds while it is started.
+    pub fn new(mux: &'a MuxWatchdog<'a, T>, name: &'static str, window_ms: u32) -> Self {
+        WatchdogWatcher {
+            mux,
+            name,
+            window: mux.time.ticks_from_ms(window_ms),
+            last_check_in: Cell::new(T::Ticks::from(0)),
+            started: Cell::new(false),
+            next: ListLink::empty(),
+        }
+    }
+
+    /// Register the watcher with the mux.
+    pub fn setup(&'a self) {
+        self.mux.watchers.push_head(self);
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Start watching, e.g. when the subsystem starts an operation.
+    pub fn start(&self) {
+        self.check_in();
+        self.started.set(true);
+    }
+
+    /// Stop watching, e.g. when the subsystem is idle.
+    pub fn stop(&self) {
+        self.started.set(false);
+    }
+
+    /// Tell the mux that the subsystem is making progress.
+    pub fn check_in(&self) {
+        self.last_check_in.set(self.mux.time.now());
+    }
+
+    fn starved(&self, now: T::Ticks) -> bool {
+        self.started.get() && now.wrapping_sub(self.last_check_in.get()) > self.window
+    }
+}
+
+/// Feeds a hardware watchdog only while all started watchers check in.
+pub struct MuxWatchdog<'a, T: Time> {
+    watchdog: &'a dyn WatchDog,
+    time: &'a T,
+    watchers: List<'a, WatchdogWatcher<'a, T>>,
+    /// The first watcher that starved.
+    starved: OptionalCell<&'static str>,
+    client: OptionalCell<&'a dyn MuxWatchdogClient>,
+}
+
+impl<'a, T: Time> MuxWatchdog<'a, T> {
+    pub fn new(watchdog: &'a dyn WatchDog, time: &'a T) -> Self {
+        MuxWatchdog {
+            watchdog,
+            time,
+            watchers: List::new(),
+            starved: OptionalCell::empty(),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn MuxWatchdogClient) {
+        self.client.set(client);
+    }
+
+    /// The watcher that starved, if any. The hardware watchdog is not fed
+    /// anymore once a watcher starved.
+    pub fn starved_watcher(&self) -> Option<&'static str> {
+        self.starved.extract()
+    }
+}
+
+impl<'a, T: Time> WatchDog for MuxWatchdog<'a, T> {
+    fn setup(&self) {
+        self.watchdog.setup();
+    }
+
+    fn tickle(&self) {
+        if self.starved.is_some() {
+            return;
+        }
+        let now = self.time.now();
+        match self.watchers.iter().find(|watcher| watcher.starved(now)) {
+            Some(watcher) => {
+                self.starved.set(watcher.name);
+                self.client
+                    .map(|client| client.watcher_starved(watcher.name));
+            }
+            None => self.watchdog.tickle(),
+        }
+    }
+
+    fn suspend(&self) {
+        self.watchdog.suspend();
+    }
+
+    fn resume(&self) {
+        // Windows keep running while the chip sleeps, so a started watcher
+        // that does not check in because it waits for an event that never
+        // comes still starves.
+        self.watchdog.resume();
+        self.tickle();
+    }
+}
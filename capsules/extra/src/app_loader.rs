@@ -0,0 +1,369 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! System call driver to install applications at runtime.
+//!
+//! A process streams a TBF binary to this driver, which writes it to the
+//! unused end of the app flash region and then asks the kernel to create a
+//! process for it. The new process starts once its credentials pass the
+//! board's checking policy, without rebooting. Because the binary is stored
+//! in the app flash region, it is also loaded on the next boot.
+//!
+//! Installing applications affects the whole system, so only the processes
+//! whose fixed `ShortID` the board lists may use this driver. Other processes
+//! get `NOSUPPORT` for every command but the existence check.
+//!
+//! `storage` must write to the app flash region using absolute addresses,
+//! e.g. the chip's flash controller wrapped in
+//! `capsules_extra::nonvolatile_to_pages::NonvolatileToPages`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let loader = static_init!(
+//!     kernel::process::DynamicProcessLoader<nrf52840::chip::NRF52<Nrf52840DefaultPeripherals>>,
+//!     kernel::process::DynamicProcessLoader::new(
+//!         board_kernel,
+//!         chip,
+//!         app_flash,
+//!         dynamic_app_memory,
+//!         &mut PROCESSES,
+//!         &FAULT_RESPONSE,
+//!         &process_management_capability,
+//!     )
+//! );
+//! let app_loader = static_init!(
+//!     capsules_extra::app_loader::AppLoader<'static, ProcessMgmtCap>,
+//!     capsules_extra::app_loader::AppLoader::new(
+//!         board_kernel,
+//!         loader,
+//!         nv_to_page,
+//!         board_kernel.create_grant(capsules_extra::app_loader::DRIVER_NUM, &grant_cap),
+//!         static_init!([u8; 512], [0; 512]),
+//!         static_init!(
+//!             [ShortID; 1],
+//!             [ShortID::Fixed(NonZeroU32::new(0x2f7d).unwrap())]
+//!         ),
+//!         ProcessMgmtCap
+//!     )
+//! );
+//! hil::nonvolatile_storage::NonvolatileStorage::set_client(nv_to_page, app_loader);
+//! ```
+//!
+//! Command Interface
+//! -----------------
+//!
+//! - `0`: Driver existence check.
+//! - `1`: Reserve flash for a binary of `data1` bytes (the total size in its
+//!   TBF header). Returns `BUSY` if another installation is in progress and
+//!   `NOMEM` if there is not enough free flash.
+//! - `2`: Write `data2` bytes of the read-only allow buffer `0` to offset
+//!   `data1` of the binary. Upcall `0` is called with the status and the
+//!   number of bytes written.
+//! - `3`: Create a process for the binary and start it. Upcall `1` is called
+//!   with the status and the identifier of the new process.
+//! - `4`: Abort the installation. The reserved flash is marked as padding so
+//!   a partially written binary is never loaded. Upcall `1` is called with
+//!   the status.
+
+use core::cell::Cell;
+use core::cmp;
+
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::nonvolatile_storage::{NonvolatileStorage, NonvolatileStorageClient};
+use kernel::process::{self, DynamicProcessLoading, ProcessLoadError, ShortID};
+use kernel::processbuffer::ReadableProcessBuffer;
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::{ErrorCode, Kernel, ProcessId};
+
+/// Syscall driver number.
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::AppLoader as usize;
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    pub const WRITE: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+mod upcall {
+    pub const WRITE_DONE: usize = 0;
+    pub const INSTALL_DONE: usize = 1;
+    pub const COUNT: u8 = 2;
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    /// Flash is reserved and the binary is being written.
+    Reserved,
+    Writing,
+    /// Writing the padding header in front of the binary before loading it.
+    WritingPadding,
+    /// Marking the reserved flash as padding.
+    Aborting,
+}
+
+pub struct AppLoader<'a, C: ProcessManagementCapability> {
+    kernel: &'static Kernel,
+    loader: &'a dyn DynamicProcessLoading,
+    storage: &'a dyn NonvolatileStorage<'a>,
+    apps: Grant<
+        (),
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<0>,
+    >,
+    buffer: TakeCell<'a, [u8]>,
+    allowed: &'a [ShortID],
+    capability: C,
+    state: Cell<State>,
+    /// Process installing a binary.
+    owner: OptionalCell<ProcessId>,
+    padding_start: Cell<usize>,
+    binary_start: Cell<usize>,
+    binary_length: Cell<usize>,
+}
+
+impl<'a, C: ProcessManagementCapability> AppLoader<'a, C> {
+    /// Only the processes with a `ShortID` in `allowed` may install
+    /// applications. `buffer` limits how many bytes one write command can
+    /// write and must be at least `process::PADDING_HEADER_LENGTH` long.
+    pub fn new(
+        kernel: &'static Kernel,
+        loader: &'a dyn DynamicProcessLoading,
+        storage: &'a dyn NonvolatileStorage<'a>,
+        grant: Grant<
+            (),
+            UpcallCount<{ upcall::COUNT }>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<0>,
+        >,
+        buffer: &'a mut [u8],
+        allowed: &'a [ShortID],
+        capability: C,
+    ) -> AppLoader<'a, C> {
+        AppLoader {
+            kernel,
+            loader,
+            storage,
+            apps: grant,
+            buffer: TakeCell::new(buffer),
+            allowed,
+            capability,
+            state: Cell::new(State::Idle),
+            owner: OptionalCell::empty(),
+            padding_start: Cell::new(0),
+            binary_start: Cell::new(0),
+            binary_length: Cell::new(0),
+        }
+    }
+
+    fn is_allowed(&self, processid: ProcessId) -> bool {
+        self.kernel.process_map_or_external(
+            false,
+            processid,
+            |process| self.allowed.contains(&process.short_app_id()),
+            &self.capability,
+        )
+    }
+
+    /// Whether `processid` may continue the installation in progress. An
+    /// installation whose owner no longer exists is abandoned.
+    fn is_owner(&self, processid: ProcessId) -> bool {
+        if let Some(owner) = self.owner.extract() {
+            let exists =
+                self.kernel
+                    .process_map_or_external(false, owner, |_| true, &self.capability);
+            if !exists && self.state.get() == State::Reserved {
+                self.owner.clear();
+                self.state.set(State::Idle);
+            }
+        }
+        self.owner.contains(&processid)
+    }
+
+    fn reserve(&self, length: usize, processid: ProcessId) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        let (padding_start, binary_start) =
+            self.loader.find_flash(length).map_err(|e| match e {
+                ProcessLoadError::NotEnoughFlash => ErrorCode::NOMEM,
+                _ => ErrorCode::INVAL,
+            })?;
+        self.padding_start.set(padding_start);
+        self.binary_start.set(binary_start);
+        self.binary_length.set(length);
+        self.owner.set(processid);
+        self.state.set(State::Reserved);
+        Ok(())
+    }
+
+    fn write(&self, offset: usize, length: usize, processid: ProcessId) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Reserved {
+            return Err(ErrorCode::BUSY);
+        }
+        if length == 0 || offset + length > self.binary_length.get() {
+            return Err(ErrorCode::INVAL);
+        }
+        let buffer = self.buffer.take().ok_or(ErrorCode::BUSY)?;
+        let copied = self
+            .apps
+            .enter(processid, |_, kernel_data| {
+                kernel_data
+                    .get_readonly_processbuffer(ro_allow::WRITE)
+                    .and_then(|write| {
+                        write.enter(|app_buffer| {
+                            let len = cmp::min(cmp::min(length, app_buffer.len()), buffer.len());
+                            app_buffer[..len].copy_to_slice(&mut buffer[..len]);
+                            len
+                        })
+                    })
+                    .unwrap_or(0)
+            })
+            .unwrap_or(0);
+        if copied == 0 {
+            self.buffer.replace(buffer);
+            return Err(ErrorCode::RESERVE);
+        }
+        match self
+            .storage
+            .write(buffer, self.binary_start.get() + offset, copied)
+        {
+            Ok(()) => {
+                self.state.set(State::Writing);
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Write a padding header of `length` bytes at `address`.
+    fn write_padding(&self, address: usize, length: usize) -> Result<(), ErrorCode> {
+        let buffer = self.buffer.take().ok_or(ErrorCode::BUSY)?;
+        let header = process::padding_header(length as u32);
+        if buffer.len() < header.len() {
+            self.buffer.replace(buffer);
+            return Err(ErrorCode::SIZE);
+        }
+        buffer[..header.len()].copy_from_slice(&header);
+        self.storage.write(buffer, address, header.len())
+    }
+
+    fn install(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Reserved {
+            return Err(ErrorCode::BUSY);
+        }
+        let padding_start = self.padding_start.get();
+        let binary_start = self.binary_start.get();
+        if padding_start < binary_start {
+            self.write_padding(padding_start, binary_start - padding_start)?;
+            self.state.set(State::WritingPadding);
+        } else {
+            self.load();
+        }
+        Ok(())
+    }
+
+    fn abort(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Reserved {
+            return Err(ErrorCode::BUSY);
+        }
+        // Cover the gap in front of the binary as well, so a later
+        // installation starts after this one.
+        let padding_start = self.padding_start.get();
+        let length = self.binary_start.get() + self.binary_length.get() - padding_start;
+        self.write_padding(padding_start, length)?;
+        self.state.set(State::Aborting);
+        Ok(())
+    }
+
+    /// Create the process and report the result to the owner.
+    fn load(&self) {
+        let result = self.loader.load(self.binary_start.get());
+        let (status, id) = match result {
+            Ok(processid) => (into_statuscode(Ok(())), processid.id()),
+            Err(ProcessLoadError::NotEnoughMemory) | Err(ProcessLoadError::NoProcessSlot) => {
+                (into_statuscode(Err(ErrorCode::NOMEM)), 0)
+            }
+            Err(_) => (into_statuscode(Err(ErrorCode::FAIL)), 0),
+        };
+        self.finish(upcall::INSTALL_DONE, status, id);
+    }
+
+    fn finish(&self, upcall: usize, status: usize, value: usize) {
+        self.state.set(State::Idle);
+        if let Some(owner) = self.owner.take() {
+            let _ = self.apps.enter(owner, |_, kernel_data| {
+                let _ = kernel_data.schedule_upcall(upcall, (status, value, 0));
+            });
+        }
+    }
+}
+
+impl<'a, C: ProcessManagementCapability> NonvolatileStorageClient<'a> for AppLoader<'a, C> {
+    fn read_done(&self, buffer: &'a mut [u8], _length: usize) {
+        self.buffer.replace(buffer);
+    }
+
+    fn write_done(&self, buffer: &'a mut [u8], length: usize) {
+        self.buffer.replace(buffer);
+        match self.state.get() {
+            State::Writing => {
+                self.state.set(State::Reserved);
+                self.owner.map(|owner| {
+                    let _ = self.apps.enter(*owner, |_, kernel_data| {
+                        let _ = kernel_data.schedule_upcall(
+                            upcall::WRITE_DONE,
+                            (into_statuscode(Ok(())), length, 0),
+                        );
+                    });
+                });
+            }
+            State::WritingPadding => self.load(),
+            State::Aborting => self.finish(upcall::INSTALL_DONE, into_statuscode(Ok(())), 0),
+            State::Idle | State::Reserved => {}
+        }
+    }
+}
+
+impl<'a, C: ProcessManagementCapability> SyscallDriver for AppLoader<'a, C> {
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        if command_num == 0 {
+            return CommandReturn::success();
+        }
+        if !self.is_allowed(processid) {
+            return CommandReturn::failure(ErrorCode::NOSUPPORT);
+        }
+        let result = match command_num {
+            1 => {
+                // Release an installation abandoned by its owner first.
+                let _ = self.is_owner(processid);
+                self.reserve(data1, processid)
+            }
+            2..=4 if !self.is_owner(processid) => Err(ErrorCode::RESERVE),
+            2 => self.write(data1, data2, processid),
+            3 => self.install(),
+            4 => self.abort(),
+            _ => Err(ErrorCode::NOSUPPORT),
+        };
+        CommandReturn::from(result)
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
@@ -0,0 +1,338 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Fault policy that restarts faulted processes with exponential backoff.
+//!
+//! A faulted process is stopped, and restarted after a delay that doubles
+//! with every restart of the process, from `Backoff::base_delay_ms` up to
+//! `Backoff::max_delay_ms`. This keeps a process that faults right after starting
+//! from monopolizing the system. Once a process has been restarted
+//! `Backoff::max_restarts` times it stays stopped.
+//!
+//! Every fault is recorded in a fault log kept for each slot of the processes
+//! array, so it survives restarts of the process. A supervisor process,
+//! identified by its fixed `ShortID`, is notified of every fault with an
+//! upcall and can read the fault log with this system call driver.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let fault_policy = static_init!(
+//!     capsules_extra::backoff_fault_policy::BackoffRestartFaultPolicy<
+//!         'static,
+//!         VirtualMuxAlarm<'static, Rtc>,
+//!         ProcessMgmtCap,
+//!     >,
+//!     capsules_extra::backoff_fault_policy::BackoffRestartFaultPolicy::new(
+//!         board_kernel,
+//!         fault_alarm,
+//!         static_init!(
+//!             [capsules_extra::backoff_fault_policy::ProcessFaultLog; NUM_PROCS],
+//!             [Default::default(); NUM_PROCS]
+//!         ),
+//!         board_kernel.create_grant(
+//!             capsules_extra::backoff_fault_policy::DRIVER_NUM,
+//!             &grant_cap
+//!         ),
+//!         ShortID::Fixed(NonZeroU32::new(0x2f7d).unwrap()),
+//!         capsules_extra::backoff_fault_policy::Backoff {
+//!             base_delay_ms: 100,
+//!             max_delay_ms: 10000,
+//!             max_restarts: 8,
+//!         },
+//!         ProcessMgmtCap
+//!     )
+//! );
+//! fault_alarm.set_alarm_client(fault_policy);
+//! ```
+//!
+//! and pass `fault_policy` as the fault policy when loading processes.
+//!
+//! Command Interface
+//! -----------------
+//!
+//! All commands but the existence check return `NOSUPPORT` to processes
+//! other than the supervisor. Processes are identified by their index in
+//! the processes array.
+//!
+//! - `0`: Driver existence check.
+//! - `1`: Number of faults of process `data1`.
+//! - `2`: Fault `data2` of process `data1`, `0` being the most recent: the
+//!   fault reason, the time of the fault in milliseconds and how many times
+//!   the process had been restarted. Only the last `FAULT_LOG_LEN` faults are
+//!   kept.
+//!
+//! Upcall `0` is called on every fault with the index of the process, the
+//! fault reason, and `1` if the process will be restarted or `0` if it stays
+//! stopped.
+//!
+//! Fault reasons are numbered as `kernel::process::FaultReason`, with `0` for
+//! an unknown reason.
+
+use core::cell::Cell;
+use core::cmp;
+
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Ticks};
+use kernel::process::{self, FaultAction, FaultReason, Process, ProcessFaultPolicy, ShortID};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::TakeCell;
+use kernel::{ErrorCode, Kernel, ProcessId};
+
+/// Syscall driver number.
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::ProcessFaults as usize;
+
+/// Number of faults kept in the log of each process.
+pub const FAULT_LOG_LEN: usize = 4;
+
+#[derive(Copy, Clone)]
+pub struct FaultRecord {
+    pub reason: Option<FaultReason>,
+    /// Time of the fault, in milliseconds of the policy's alarm.
+    pub timestamp_ms: u32,
+    /// How many times the process had been restarted before this fault.
+    pub restart_count: usize,
+}
+
+/// How faulted processes are restarted.
+#[derive(Copy, Clone)]
+pub struct Backoff {
+    /// Delay before the first restart of a process.
+    pub base_delay_ms: u32,
+    /// Longest delay before restarting a process.
+    pub max_delay_ms: u32,
+    /// Processes that were restarted this many times stay stopped.
+    pub max_restarts: usize,
+}
+
+/// Faults of the process in one slot of the processes array.
+#[derive(Copy, Clone, Default)]
+pub struct ProcessFaultLog {
+    faults: usize,
+    /// Most recent fault first.
+    records: [Option<FaultRecord>; FAULT_LOG_LEN],
+    /// Pending restart, as the reference and delay in ticks of the alarm.
+    restart: Option<(u32, u32)>,
+}
+
+impl ProcessFaultLog {
+    fn record(&mut self, record: FaultRecord) {
+        self.faults += 1;
+        self.records.rotate_right(1);
+        self.records[0] = Some(record);
+    }
+}
+
+pub struct BackoffRestartFaultPolicy<'a, A: Alarm<'a>, C: ProcessManagementCapability> {
+    kernel: &'static Kernel,
+    alarm: &'a A,
+    logs: TakeCell<'a, [ProcessFaultLog]>,
+    apps: Grant<(), UpcallCount<1>, AllowRoCount<0>, AllowRwCount<0>>,
+    supervisor: ShortID,
+    backoff: Backoff,
+    capability: C,
+    /// A restart is pending and the alarm is armed.
+    armed: Cell<bool>,
+}
+
+impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> BackoffRestartFaultPolicy<'a, A, C> {
+    /// `logs` must have one entry per slot of the processes array.
+    pub fn new(
+        kernel: &'static Kernel,
+        alarm: &'a A,
+        logs: &'a mut [ProcessFaultLog],
+        grant: Grant<(), UpcallCount<1>, AllowRoCount<0>, AllowRwCount<0>>,
+        supervisor: ShortID,
+        backoff: Backoff,
+        capability: C,
+    ) -> BackoffRestartFaultPolicy<'a, A, C> {
+        BackoffRestartFaultPolicy {
+            kernel,
+            alarm,
+            logs: TakeCell::new(logs),
+            apps: grant,
+            supervisor,
+            backoff,
+            capability,
+            armed: Cell::new(false),
+        }
+    }
+
+    /// Delay before restarting a process that was restarted `restarts` times.
+    fn delay_ms(&self, restarts: usize) -> u32 {
+        let factor = 1u32 << cmp::min(restarts, 31);
+        cmp::min(
+            self.backoff.base_delay_ms.saturating_mul(factor),
+            self.backoff.max_delay_ms,
+        )
+    }
+
+    fn is_supervisor(&self, processid: ProcessId) -> bool {
+        self.kernel.process_map_or_external(
+            false,
+            processid,
+            |process| process.short_app_id() == self.supervisor,
+            &self.capability,
+        )
+    }
+
+    fn notify_supervisor(&self, index: usize, reason: Option<FaultReason>, restart: bool) {
+        let reason = reason.map_or(0, |reason| reason as usize);
+        self.kernel
+            .process_each_capability(&self.capability, |process| {
+                if process.short_app_id() == self.supervisor {
+                    let _ = self.apps.enter(process.processid(), |_, kernel_data| {
+                        let _ = kernel_data.schedule_upcall(0, (index, reason, restart as usize));
+                    });
+                }
+            });
+    }
+
+    /// Arm the alarm for the earliest pending restart.
+    fn arm(&self) {
+        let now = self.alarm.now();
+        let next = self.logs.map_or(None, |logs| {
+            logs.iter()
+                .filter_map(|log| log.restart)
+                .map(|(reference, dt)| {
+                    let reference = A::Ticks::from(reference);
+                    let end = reference.wrapping_add(A::Ticks::from(dt));
+                    if now.within_range(reference, end) {
+                        end.wrapping_sub(now).into_u32()
+                    } else {
+                        // Overdue, restart right away.
+                        0
+                    }
+                })
+                .min()
+        });
+        match next {
+            Some(dt) => {
+                self.armed.set(true);
+                self.alarm.set_alarm(now, A::Ticks::from(dt));
+            }
+            None => {
+                if self.armed.take() {
+                    let _ = self.alarm.disarm();
+                }
+            }
+        }
+    }
+
+    fn restart(&self, index: usize) {
+        self.kernel
+            .process_each_capability(&self.capability, |process| {
+                if process.processid().index_external(&self.capability) == Some(index)
+                    && process.get_state() == process::State::Faulted
+                {
+                    process.try_restart(None);
+                }
+            });
+    }
+}
+
+impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> ProcessFaultPolicy
+    for BackoffRestartFaultPolicy<'a, A, C>
+{
+    fn action(&self, process: &dyn Process) -> FaultAction {
+        let index = match process.processid().index_external(&self.capability) {
+            Some(index) => index,
+            None => return FaultAction::Stop,
+        };
+        let now = self.alarm.now();
+        let reason = process.get_fault_reason();
+        let restarts = process.get_restart_count();
+        let restart = restarts < self.backoff.max_restarts;
+        self.logs.map(|logs| {
+            if let Some(log) = logs.get_mut(index) {
+                log.record(FaultRecord {
+                    reason,
+                    timestamp_ms: self.alarm.ticks_to_ms(now),
+                    restart_count: restarts,
+                });
+                log.restart = if restart {
+                    let delay = self.alarm.ticks_from_ms(self.delay_ms(restarts));
+                    Some((now.into_u32(), delay.into_u32()))
+                } else {
+                    None
+                };
+            }
+        });
+        self.arm();
+        self.notify_supervisor(index, reason, restart);
+        // The process is restarted by the alarm.
+        FaultAction::Stop
+    }
+}
+
+impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> AlarmClient
+    for BackoffRestartFaultPolicy<'a, A, C>
+{
+    fn alarm(&self) {
+        self.armed.set(false);
+        let now = self.alarm.now();
+        for index in 0..self.logs.map_or(0, |logs| logs.len()) {
+            let due = self.logs.map_or(false, |logs| match logs[index].restart {
+                Some((reference, dt)) => {
+                    let reference = A::Ticks::from(reference);
+                    if now.within_range(reference, reference.wrapping_add(A::Ticks::from(dt))) {
+                        false
+                    } else {
+                        logs[index].restart = None;
+                        true
+                    }
+                }
+                None => false,
+            });
+            // Restart outside of the log, the process may fault again right
+            // away.
+            if due {
+                self.restart(index);
+            }
+        }
+        self.arm();
+    }
+}
+
+impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> SyscallDriver
+    for BackoffRestartFaultPolicy<'a, A, C>
+{
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        if command_num == 0 {
+            return CommandReturn::success();
+        }
+        if !self.is_supervisor(processid) {
+            return CommandReturn::failure(ErrorCode::NOSUPPORT);
+        }
+        let log = match self.logs.map_or(None, |logs| logs.get(data1).copied()) {
+            Some(log) => log,
+            None => return CommandReturn::failure(ErrorCode::INVAL),
+        };
+        match command_num {
+            1 => CommandReturn::success_u32(log.faults as u32),
+            2 => match log.records.get(data2).copied().flatten() {
+                Some(record) => CommandReturn::success_u32_u32_u32(
+                    record.reason.map_or(0, |reason| reason as u32),
+                    record.timestamp_ms,
+                    record.restart_count as u32,
+                ),
+                None => CommandReturn::failure(ErrorCode::INVAL),
+            },
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
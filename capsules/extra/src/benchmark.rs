@@ -0,0 +1,382 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Microbenchmarks of the kernel/process interface.
+//!
+//! Measures, with the CPU cycle counter:
+//!
+//! - the round-trip latency from a command to the upcall it schedules and
+//!   back to the next command of the process,
+//! - the cycles needed to copy a read-only allow buffer into the kernel,
+//! - the latency from a process notifying an IPC service to the service
+//!   running its IPC callback.
+//!
+//! A benchmark collects one sample per iteration. Once it has all samples,
+//! the minimum, median, 90th and 99th percentile and maximum are printed
+//! with `debug!()`, and passed to the process that started the benchmark,
+//! so that ABI and scheduler changes can be compared on real boards.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let cycle_counter = static_init!(cortexm4::dwt::Dwt, cortexm4::dwt::Dwt::new());
+//! let benchmark = static_init!(
+//!     capsules_extra::benchmark::LatencyBenchmark<'static, cortexm4::dwt::Dwt>,
+//!     capsules_extra::benchmark::LatencyBenchmark::new(
+//!         cycle_counter,
+//!         static_init!([u32; 256], [0; 256]),
+//!         static_init!([u8; 1024], [0; 1024]),
+//!         board_kernel.create_grant(capsules_extra::benchmark::DRIVER_NUM, &grant_cap),
+//!     )
+//! );
+//! ```
+//!
+//! Command Interface
+//! -----------------
+//!
+//! - `0`: Driver existence check.
+//! - `1`: Start benchmark `data1` (`0`: round trip, `1`: allow buffer copy,
+//!   `2`: IPC notify) with `data2` iterations, or as many as the kernel
+//!   can keep samples for if `data2` is `0`. Returns `BUSY` while another
+//!   benchmark is running.
+//! - `2`: Round trip: schedules upcall `0` right away. The process calls
+//!   this command again from the upcall; each call after the first is a
+//!   sample.
+//! - `3`: Copy: copies read-only allow buffer `0`, up to the size of the
+//!   kernel buffer, into the kernel. Each call is a sample.
+//! - `4`: IPC notify: the client calls this right before it notifies the
+//!   service.
+//! - `5`: IPC notify: the service calls this first thing in its IPC
+//!   callback. Each call after command `4` is a sample.
+//! - `6`: Stop the running benchmark.
+//!
+//! When the benchmark is complete, upcall `1` is scheduled for the process
+//! that started it, with the median, 99th percentile and maximum in
+//! cycles.
+
+use core::cell::Cell;
+
+use kernel::debug;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::hw_debug::CycleCounter;
+use kernel::processbuffer::ReadableProcessBuffer;
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::{ErrorCode, ProcessId};
+
+/// Syscall driver number.
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Benchmark as usize;
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    pub const BUFFER: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+mod upcall {
+    pub const ROUND_TRIP: usize = 0;
+    pub const DONE: usize = 1;
+    /// The number of upcalls the kernel stores for this grant
+    pub const COUNT: u8 = 2;
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Benchmark {
+    RoundTrip,
+    Copy,
+    IpcNotify,
+}
+
+impl Benchmark {
+    fn from_usize(benchmark: usize) -> Option<Benchmark> {
+        match benchmark {
+            0 => Some(Benchmark::RoundTrip),
+            1 => Some(Benchmark::Copy),
+            2 => Some(Benchmark::IpcNotify),
+            _ => None,
+        }
+    }
+}
+
+/// Summary of the samples of a benchmark, in cycles.
+#[derive(Copy, Clone, Debug)]
+pub struct Percentiles {
+    pub min: u32,
+    pub p50: u32,
+    pub p90: u32,
+    pub p99: u32,
+    pub max: u32,
+}
+
+impl Percentiles {
+    /// Sorts `samples`, which must not be empty.
+    fn of(samples: &mut [u32]) -> Percentiles {
+        samples.sort_unstable();
+        let percentile = |p: usize| samples[(samples.len() - 1) * p / 100];
+        Percentiles {
+            min: percentile(0),
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+            max: percentile(100),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct App;
+
+pub struct LatencyBenchmark<'a, C: CycleCounter> {
+    counter: &'a C,
+    samples: TakeCell<'a, [u32]>,
+    /// The running benchmark and the number of samples it collects.
+    running: OptionalCell<(Benchmark, usize)>,
+    /// The process that started the benchmark.
+    owner: OptionalCell<ProcessId>,
+    count: Cell<usize>,
+    /// Cycle count of the last round-trip command or IPC notify.
+    mark: OptionalCell<u32>,
+    copy_buffer: TakeCell<'a, [u8]>,
+    /// Bytes copied by the last copy sample.
+    copy_len: Cell<usize>,
+    apps: Grant<
+        App,
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<0>,
+    >,
+}
+
+impl<'a, C: CycleCounter> LatencyBenchmark<'a, C> {
+    /// A benchmark runs for at most `samples.len()` iterations. The copy
+    /// benchmark copies up to `copy_buffer.len()` bytes.
+    pub fn new(
+        counter: &'a C,
+        samples: &'a mut [u32],
+        copy_buffer: &'a mut [u8],
+        grant: Grant<
+            App,
+            UpcallCount<{ upcall::COUNT }>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<0>,
+        >,
+    ) -> LatencyBenchmark<'a, C> {
+        LatencyBenchmark {
+            counter,
+            samples: TakeCell::new(samples),
+            running: OptionalCell::empty(),
+            owner: OptionalCell::empty(),
+            count: Cell::new(0),
+            mark: OptionalCell::empty(),
+            copy_buffer: TakeCell::new(copy_buffer),
+            copy_len: Cell::new(0),
+            apps: grant,
+        }
+    }
+
+    fn now(&self) -> u32 {
+        // Only the low bits are used, so that 32-bit counters can wrap.
+        self.counter.count() as u32
+    }
+
+    fn start(&self, benchmark: usize, iterations: usize, processid: ProcessId) -> CommandReturn {
+        if self.running.is_some() {
+            return CommandReturn::failure(ErrorCode::BUSY);
+        }
+        let benchmark = match Benchmark::from_usize(benchmark) {
+            Some(benchmark) => benchmark,
+            None => return CommandReturn::failure(ErrorCode::INVAL),
+        };
+        let capacity = self.samples.map_or(0, |samples| samples.len());
+        let iterations = if iterations == 0 {
+            capacity
+        } else {
+            iterations
+        };
+        if iterations == 0 || iterations > capacity {
+            return CommandReturn::failure(ErrorCode::INVAL);
+        }
+        self.counter.start();
+        self.running.set((benchmark, iterations));
+        self.owner.set(processid);
+        self.count.set(0);
+        self.mark.clear();
+        CommandReturn::success()
+    }
+
+    fn stop(&self) {
+        self.running.clear();
+        self.owner.clear();
+        self.mark.clear();
+    }
+
+    fn is_running(&self, benchmark: Benchmark) -> bool {
+        self.running
+            .extract()
+            .is_some_and(|(running, _)| running == benchmark)
+    }
+
+    fn add_sample(&self, sample: u32) {
+        let (benchmark, iterations) = match self.running.extract() {
+            Some(running) => running,
+            None => return,
+        };
+        let count = self.count.get();
+        self.samples.map(|samples| samples[count] = sample);
+        self.count.set(count + 1);
+        if count + 1 < iterations {
+            return;
+        }
+
+        let percentiles = match self
+            .samples
+            .map(|samples| Percentiles::of(&mut samples[..iterations]))
+        {
+            Some(percentiles) => percentiles,
+            None => return,
+        };
+        let name = match benchmark {
+            Benchmark::RoundTrip => "round trip",
+            Benchmark::Copy => "allow copy",
+            Benchmark::IpcNotify => "IPC notify",
+        };
+        debug!(
+            "Benchmark {}: {} iterations, cycles min {} p50 {} p90 {} p99 {} max {}",
+            name,
+            iterations,
+            percentiles.min,
+            percentiles.p50,
+            percentiles.p90,
+            percentiles.p99,
+            percentiles.max
+        );
+        if benchmark == Benchmark::Copy && percentiles.p50 > 0 {
+            debug!(
+                "Benchmark allow copy: {} bytes, {} bytes per 1000 cycles at p50",
+                self.copy_len.get(),
+                self.copy_len.get() as u64 * 1000 / percentiles.p50 as u64
+            );
+        }
+        if let Some(owner) = self.owner.extract() {
+            let _ = self.apps.enter(owner, |_, kernel_data| {
+                let _ = kernel_data.schedule_upcall(
+                    upcall::DONE,
+                    (
+                        percentiles.p50 as usize,
+                        percentiles.p99 as usize,
+                        percentiles.max as usize,
+                    ),
+                );
+            });
+        }
+        self.stop();
+    }
+
+    fn round_trip(&self, processid: ProcessId) -> CommandReturn {
+        if !self.is_running(Benchmark::RoundTrip) {
+            return CommandReturn::failure(ErrorCode::RESERVE);
+        }
+        // A sample spans from one call of the command to the next: the
+        // return to the process, the upcall and the system call back into
+        // the kernel.
+        let now = self.now();
+        if let Some(mark) = self.mark.extract() {
+            self.add_sample(now.wrapping_sub(mark));
+        }
+        if self.running.is_none() {
+            return CommandReturn::success();
+        }
+        self.mark.set(now);
+        self.apps
+            .enter(processid, |_, kernel_data| {
+                let _ = kernel_data.schedule_upcall(upcall::ROUND_TRIP, (0, 0, 0));
+            })
+            .map_err(ErrorCode::from)
+            .into()
+    }
+
+    fn copy(&self, processid: ProcessId) -> CommandReturn {
+        if !self.is_running(Benchmark::Copy) {
+            return CommandReturn::failure(ErrorCode::RESERVE);
+        }
+        let result = self
+            .copy_buffer
+            .map_or(Err(ErrorCode::NOMEM), |copy_buffer| {
+                self.apps
+                    .enter(processid, |_, kernel_data| {
+                        kernel_data
+                            .get_readonly_processbuffer(ro_allow::BUFFER)
+                            .and_then(|buffer| {
+                                buffer.enter(|data| {
+                                    let len = data.len().min(copy_buffer.len());
+                                    let cycles = self.counter.profile_closure(|| {
+                                        data[..len].copy_to_slice(&mut copy_buffer[..len]);
+                                    });
+                                    (len, cycles as u32)
+                                })
+                            })
+                            .map_err(ErrorCode::from)
+                    })
+                    .map_err(ErrorCode::from)
+                    .and_then(|result| result)
+            });
+        match result {
+            Ok((0, _)) => CommandReturn::failure(ErrorCode::SIZE),
+            Ok((len, cycles)) => {
+                self.copy_len.set(len);
+                self.add_sample(cycles);
+                CommandReturn::success_u32(cycles)
+            }
+            Err(e) => CommandReturn::failure(e),
+        }
+    }
+
+    fn ipc_notify(&self, command_num: usize) -> CommandReturn {
+        if !self.is_running(Benchmark::IpcNotify) {
+            return CommandReturn::failure(ErrorCode::RESERVE);
+        }
+        let now = self.now();
+        if command_num == 4 {
+            self.mark.set(now);
+            return CommandReturn::success();
+        }
+        match self.mark.take() {
+            Some(mark) => {
+                self.add_sample(now.wrapping_sub(mark));
+                CommandReturn::success()
+            }
+            None => CommandReturn::failure(ErrorCode::ALREADY),
+        }
+    }
+}
+
+impl<'a, C: CycleCounter> SyscallDriver for LatencyBenchmark<'a, C> {
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+            1 => self.start(data1, data2, processid),
+            2 => self.round_trip(processid),
+            3 => self.copy(processid),
+            4 | 5 => self.ipc_notify(command_num),
+            6 => {
+                self.stop();
+                CommandReturn::success()
+            }
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
@@ -11,15 +11,29 @@
 //! driver but processes can request an advertising or scanning interval.
 //! Processes can also control the TX power used for their advertisements.
 //!
+//! The driver works with any radio that implements
+//! `kernel::hil::ble_advertising`. Scanning is passive or active: an active
+//! scan answers scannable advertisements with a `SCAN_REQ` and reports the
+//! `SCAN_RSP` as well, on radios that can time the request. Other radios scan
+//! passively. Processes can filter the packets they are told about by PDU
+//! type and by advertiser address.
+//!
+//! The HIL works at the level of single link layer packets, so it needs a
+//! radio the kernel drives directly. BLE controllers that run their own stack,
+//! like the nRF51822 behind `nrf51822_serialization` on imix, cannot
+//! implement it and are not supported by this driver.
+//!
 //! Data payloads are limited to 31 bytes since the maximum advertising channel
 //! protocol data unit (PDU) is 37 bytes and includes a 6-byte header.
 //!
 //! ### Allow system calls
 //!
-//! There is one ReadWrite and one ReadOnly allow buffers, both at index `0`.
+//! There is one ReadWrite and two ReadOnly allow buffers.
 //!
-//! * ReadOnly: Advertising data, containing the full _payload_ (i.e. excluding the header) the
-//!             process wishes to advertise.
+//! * ReadOnly 0: Advertising data, containing the full _payload_ (i.e. excluding the header) the
+//!               process wishes to advertise.
+//! * ReadOnly 1: Scan filter, a list of 6-byte advertiser addresses. If it holds at least one
+//!               address, only packets from these addresses are reported.
 //! * ReadWrite: Passive scanning buffer, which is populated during BLE scans with complete (i.e.
 //!              including headers) advertising packets received on channels 37, 38 and 39.
 //!
@@ -53,7 +67,10 @@
 //!
 //! * 0: start advertisement
 //! * 1: stop advertisement or scanning
-//! * 5: start scanning
+//! * 2: set the transmit power
+//! * 5: start scanning, passively if `data` is 0 and actively if it is 1
+//! * 6: only report packets whose PDU type `t` has bit `1 << t` set in `data`, or all packets
+//!      if `data` is 0
 //!
 //! The possible return codes from the `command` system call indicate the following:
 //!
@@ -64,28 +81,21 @@
 //! Usage
 //! -----
 //!
-//! You need a device that provides the `kernel::BleAdvertisementDriver` trait along with a virtual
-//! timer to perform events and not block the entire kernel
+//! You need a radio that provides the `kernel::hil::ble_advertising::BleAdvertisementDriver` and
+//! `BleConfig` traits along with a virtual timer to perform events and not block the entire
+//! kernel. `components::ble::BLEComponent` sets both up:
 //!
 //! ```rust
-//! # use kernel::static_init;
-//! # use capsules::virtual_alarm::VirtualMuxAlarm;
-//!
-//! let ble_radio = static_init!(
-//! nrf5x::ble_advertising_driver::BLE<
-//!     'static,
-//!     nrf52::radio::Radio, VirtualMuxAlarm<'static, Rtc>
-//! >,
-//! nrf5x::ble_advertising_driver::BLE::new(
-//!     &mut nrf52::radio::RADIO,
-//!     board_kernel.create_grant(&grant_cap),
-//!     &mut nrf5x::ble_advertising_driver::BUF,
-//!     ble_radio_virtual_alarm));
-//! nrf5x::ble_advertising_hil::BleAdvertisementDriver::set_rx_client(&nrf52::radio::RADIO,
-//!                                                                   ble_radio);
-//! nrf5x::ble_advertising_hil::BleAdvertisementDriver::set_tx_client(&nrf52::radio::RADIO,
-//!                                                                   ble_radio);
-//! ble_radio_virtual_alarm.set_client(ble_radio);
+//! let ble_radio = components::ble::BLEComponent::new(
+//!     board_kernel,
+//!     capsules_extra::ble_advertising_driver::DRIVER_NUM,
+//!     &base_peripherals.ble_radio,
+//!     mux_alarm,
+//! )
+//! .finalize(components::ble_component_static!(
+//!     nrf52840::rtc::Rtc,
+//!     nrf52840::ble_radio::Radio
+//! ));
 //! ```
 //!
 //! ### Authors
@@ -126,8 +136,9 @@ pub const DRIVER_NUM: usize = driver::NUM::BleAdvertising as usize;
 /// Ids for read-only allow buffers
 mod ro_allow {
     pub const ADV_DATA: usize = 0;
+    pub const SCAN_FILTER: usize = 1;
     /// The number of allow buffers the kernel stores for this grant
-    pub const COUNT: u8 = 1;
+    pub const COUNT: u8 = 2;
 }
 
 /// Ids for read-write allow buffers
@@ -140,6 +151,9 @@ mod rw_allow {
 const PACKET_ADDR_LEN: usize = 6;
 pub const PACKET_LENGTH: usize = 39;
 const ADV_HEADER_TXADD_OFFSET: usize = 6;
+const ADV_HEADER_PDU_TYPE_MASK: u8 = 0x0f;
+// Header (2 bytes) + ScanA (6 bytes) + AdvA (6 bytes)
+const SCAN_REQ_LENGTH: usize = 2 + 2 * PACKET_ADDR_LEN;
 
 #[derive(PartialEq, Debug)]
 enum BLEState {
@@ -176,7 +190,6 @@ const ADV_IND: AdvPduType = 0b0000;
 #[allow(dead_code)]
 const ADV_DIRECTED_IND: AdvPduType = 0b0001;
 const ADV_NONCONN_IND: AdvPduType = 0b0010;
-#[allow(dead_code)]
 const SCAN_REQ: AdvPduType = 0b0011;
 #[allow(dead_code)]
 const SCAN_RESP: AdvPduType = 0b0100;
@@ -194,6 +207,12 @@ pub struct App {
     pdu_type: AdvPduType,
     advertisement_interval_ms: u32,
     tx_power: u8,
+
+    // Scanning meta-data
+    active_scan: bool,
+    /// The PDU types to report, one bit per type, or 0 for all of them.
+    scan_filter_pdu_types: u16,
+
     /// The state of an app-specific pseudo random number.
     ///
     /// For example, it can be used for the pseudo-random `advDelay` parameter.
@@ -211,6 +230,8 @@ impl Default for App {
             process_status: Some(BLEState::Idle),
             tx_power: 0,
             advertisement_interval_ms: 200,
+            active_scan: false,
+            scan_filter_pdu_types: 0,
             // Just use any non-zero starting value by default
             random_nonce: 0xdeadbeef,
         }
@@ -299,6 +320,65 @@ impl App {
             .unwrap_or(Err(ErrorCode::FAIL))
     }
 
+    // Listen for advertisements on `channel`. Active scans hand the radio a
+    // SCAN_REQ from our address, and fall back to passive scans if the radio
+    // cannot send it.
+    fn receive_advertisement<'a, B, A>(
+        &mut self,
+        processid: kernel::ProcessId,
+        ble: &BLE<'a, B, A>,
+        channel: RadioChannel,
+    ) where
+        B: ble_advertising::BleAdvertisementDriver<'a> + ble_advertising::BleConfig,
+        A: kernel::hil::time::Alarm<'a>,
+    {
+        if self.active_scan && self.generate_random_address(processid).is_ok() {
+            if let Some(kernel_tx) = ble.kernel_tx.take() {
+                kernel_tx[0] = SCAN_REQ | 1 << ADV_HEADER_TXADD_OFFSET;
+                kernel_tx[1] = (2 * PACKET_ADDR_LEN) as u8;
+                kernel_tx[2..2 + PACKET_ADDR_LEN].copy_from_slice(&self.address);
+                // The radio fills in the address of the advertiser
+                kernel_tx[2 + PACKET_ADDR_LEN..SCAN_REQ_LENGTH].fill(0);
+                match ble
+                    .radio
+                    .receive_advertisement_and_scan(channel, kernel_tx, SCAN_REQ_LENGTH)
+                {
+                    Ok(()) => {
+                        ble.scan_requesting.set(true);
+                        return;
+                    }
+                    Err((_, kernel_tx)) => ble.kernel_tx.replace(kernel_tx),
+                };
+            }
+        }
+        ble.radio.receive_advertisement(channel);
+    }
+
+    // Whether a received packet passes the scan filter of the process.
+    fn scan_filter_accepts(&self, kernel_data: &GrantKernelData, packet: &[u8]) -> bool {
+        let pdu_type = packet[0] & ADV_HEADER_PDU_TYPE_MASK;
+        if self.scan_filter_pdu_types != 0 && self.scan_filter_pdu_types & (1 << pdu_type) == 0 {
+            return false;
+        }
+        // All advertising channel PDUs start with the address of their sender
+        let sender = match packet.get(2..2 + PACKET_ADDR_LEN) {
+            Some(sender) => sender,
+            None => return false,
+        };
+        kernel_data
+            .get_readonly_processbuffer(ro_allow::SCAN_FILTER)
+            .and_then(|filter| {
+                filter.enter(|filter| {
+                    filter.len() < PACKET_ADDR_LEN
+                        || filter.chunks(PACKET_ADDR_LEN).any(|address| {
+                            address.len() == PACKET_ADDR_LEN
+                                && address.iter().zip(sender).all(|(a, b)| a.get() == *b)
+                        })
+                })
+            })
+            .unwrap_or(true)
+    }
+
     // Returns a new pseudo-random number and updates the randomness state.
     //
     // Uses the [Xorshift](https://en.wikipedia.org/wiki/Xorshift) algorithm to
@@ -339,6 +419,8 @@ where
     alarm: &'a A,
     sending_app: OptionalCell<kernel::ProcessId>,
     receiving_app: OptionalCell<kernel::ProcessId>,
+    /// The radio holds `kernel_tx` for the scan request of an active scan.
+    scan_requesting: Cell<bool>,
 }
 
 impl<'a, B, A> BLE<'a, B, A>
@@ -365,6 +447,7 @@ where
             alarm: alarm,
             sending_app: OptionalCell::empty(),
             receiving_app: OptionalCell::empty(),
+            scan_requesting: Cell::new(false),
         }
     }
 
@@ -399,6 +482,31 @@ where
                 .set_alarm(A::Ticks::from(next_ref), A::Ticks::from(next_dt));
         }
     }
+
+    // Move the scan of an app on to the next advertising channel, or end the
+    // scanning event after channel 39.
+    fn scan_next_channel(&self, processid: kernel::ProcessId, app: &mut App) {
+        match app.process_status {
+            Some(BLEState::Scanning(RadioChannel::AdvertisingChannel37)) => {
+                app.process_status = Some(BLEState::Scanning(RadioChannel::AdvertisingChannel38));
+                self.receiving_app.set(processid);
+                let _ = self.radio.set_tx_power(app.tx_power);
+                app.receive_advertisement(processid, self, RadioChannel::AdvertisingChannel38);
+            }
+            Some(BLEState::Scanning(RadioChannel::AdvertisingChannel38)) => {
+                app.process_status = Some(BLEState::Scanning(RadioChannel::AdvertisingChannel39));
+                self.receiving_app.set(processid);
+                app.receive_advertisement(processid, self, RadioChannel::AdvertisingChannel39);
+            }
+            Some(BLEState::Scanning(RadioChannel::AdvertisingChannel39)) => {
+                self.busy.set(false);
+                app.process_status = Some(BLEState::ScanningIdle);
+                app.set_next_alarm::<A::Frequency>(self.alarm.now().into_u32());
+            }
+            // Invalid state => don't care
+            _ => (),
+        }
+    }
 }
 
 // Timer alarm
@@ -460,8 +568,11 @@ where
                                 Some(BLEState::Scanning(RadioChannel::AdvertisingChannel37));
                             self.receiving_app.set(processid);
                             let _ = self.radio.set_tx_power(app.tx_power);
-                            self.radio
-                                .receive_advertisement(RadioChannel::AdvertisingChannel37);
+                            app.receive_advertisement(
+                                processid,
+                                self,
+                                RadioChannel::AdvertisingChannel37,
+                            );
                         }
                         _ => debug!(
                             "app: {:?} \t invalid state {:?}",
@@ -493,7 +604,10 @@ where
                 // Packets that are bigger than 39 bytes are likely `Channel PDUs` which should
                 // only be sent on the other 37 RadioChannel channels.
 
-                if len <= PACKET_LENGTH as u8 && result == Ok(()) {
+                if len <= PACKET_LENGTH as u8
+                    && result == Ok(())
+                    && app.scan_filter_accepts(kernel_data, &buf[..len as usize])
+                {
                     // write to buffer in userland
 
                     let success = kernel_data
@@ -517,29 +631,10 @@ where
                     }
                 }
 
-                match app.process_status {
-                    Some(BLEState::Scanning(RadioChannel::AdvertisingChannel37)) => {
-                        app.process_status =
-                            Some(BLEState::Scanning(RadioChannel::AdvertisingChannel38));
-                        self.receiving_app.set(*processid);
-                        let _ = self.radio.set_tx_power(app.tx_power);
-                        self.radio
-                            .receive_advertisement(RadioChannel::AdvertisingChannel38);
-                    }
-                    Some(BLEState::Scanning(RadioChannel::AdvertisingChannel38)) => {
-                        app.process_status =
-                            Some(BLEState::Scanning(RadioChannel::AdvertisingChannel39));
-                        self.receiving_app.set(*processid);
-                        self.radio
-                            .receive_advertisement(RadioChannel::AdvertisingChannel39);
-                    }
-                    Some(BLEState::Scanning(RadioChannel::AdvertisingChannel39)) => {
-                        self.busy.set(false);
-                        app.process_status = Some(BLEState::ScanningIdle);
-                        app.set_next_alarm::<A::Frequency>(self.alarm.now().into_u32());
-                    }
-                    // Invalid state => don't care
-                    _ => (),
+                // During an active scan, the radio reports the end of the
+                // scan request exchange with a TX event.
+                if !self.scan_requesting.get() {
+                    self.scan_next_channel(*processid, app);
                 }
             });
             self.reset_active_alarm();
@@ -557,6 +652,15 @@ where
     // re-transmissions for invalid CRCs
     fn transmit_event(&self, buf: &'static mut [u8], _crc_ok: Result<(), ErrorCode>) {
         self.kernel_tx.replace(buf);
+        if self.scan_requesting.replace(false) {
+            self.receiving_app.map(|processid| {
+                let _ = self
+                    .app
+                    .enter(*processid, |app, _| self.scan_next_channel(*processid, app));
+            });
+            self.reset_active_alarm();
+            return;
+        }
         self.sending_app.map(|processid| {
             let _ = self.app.enter(*processid, |app, kernel_data| {
                 match app.process_status {
@@ -690,11 +794,16 @@ where
                     .unwrap_or_else(|err| err.into())
             }
 
-            // Passive scanning mode
+            // Passive or active scanning mode
+            //
+            // data - 0 for passive and 1 for active scanning
             5 => {
                 self.app
                     .enter(processid, |app, _| {
-                        if let Some(BLEState::Idle) = app.process_status {
+                        if data > 1 {
+                            Err(ErrorCode::INVAL)
+                        } else if let Some(BLEState::Idle) = app.process_status {
+                            app.active_scan = data == 1;
                             app.process_status = Some(BLEState::ScanningIdle);
                             app.set_next_alarm::<A::Frequency>(self.alarm.now().into_u32());
                             Ok(())
@@ -715,6 +824,20 @@ where
                     )
             }
 
+            // Scan filter
+            //
+            // data - The PDU types to report, bit `1 << t` for type `t`, or 0 for all of them
+            6 => self
+                .app
+                .enter(processid, |app, _| match u16::try_from(data) {
+                    Ok(pdu_types) => {
+                        app.scan_filter_pdu_types = pdu_types;
+                        CommandReturn::success()
+                    }
+                    Err(_) => CommandReturn::failure(ErrorCode::INVAL),
+                })
+                .unwrap_or_else(|err| err.into()),
+
             _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
         }
         .into()
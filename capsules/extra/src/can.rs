@@ -28,6 +28,27 @@
 //! - if it's greater the 0, the message will be copied to the RW buffer
 //!   but no upcall will be done
 //!
+//! Sharing the controller
+//! -----------------------
+//!
+//! Several processes can use the controller at the same time. The process
+//! that configures, enables or disables the peripheral (commands 1 to 4 and
+//! 9) owns its configuration until it disables it; other processes get
+//! `RESERVE` for these commands. Sending and receiving are open to every
+//! process:
+//!
+//! - Each process can set a receive filter (command 10) on the identifier of
+//!   messages. Received messages are copied to the buffers of all receiving
+//!   processes whose filter matches. The controller receives while at least
+//!   one process does.
+//! - Each process can queue one message at a time. While the controller is
+//!   transmitting, messages wait in a queue that is served by the priority
+//!   of the sending process (command 11, lower values first) and then in the
+//!   order the messages were queued. The message is copied from the process
+//!   buffer when it is transmitted.
+//! - Changes of the controller state that no process requested, such as
+//!   entering bus-off, are reported to every process.
+//!
 //! Usage
 //! -----
 //!
@@ -49,6 +70,7 @@
 //! ```
 //!
 
+use core::cell::Cell;
 use core::mem::size_of;
 
 use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
@@ -66,6 +88,10 @@ pub const BYTE3_MASK: usize = 0xff0000;
 pub const BYTE2_MASK: usize = 0xff00;
 pub const BYTE1_MASK: usize = 0xff;
 
+/// Bit of the filter identifier (command 10) that selects extended
+/// identifiers.
+pub const FILTER_EXTENDED: usize = 1 << 31;
+
 mod error_upcalls {
     pub const ERROR_TX: usize = 100;
     pub const ERROR_RX: usize = 101;
@@ -78,7 +104,8 @@ mod up_calls {
     pub const UPCALL_MESSAGE_RECEIVED: usize = 3;
     pub const UPCALL_RECEIVED_STOPPED: usize = 4;
     pub const UPCALL_TRANSMISSION_ERROR: usize = 5;
-    pub const COUNT: u8 = 6;
+    pub const UPCALL_STATE_CHANGED: usize = 6;
+    pub const COUNT: u8 = 7;
 }
 
 mod ro_allow {
@@ -106,16 +133,38 @@ pub struct CanCapsule<'a, Can: can::Can> {
         AllowRoCount<{ ro_allow::COUNT }>,
         AllowRwCount<{ rw_allow::COUNT }>,
     >,
+    // The process that owns the configuration of the peripheral.
     processid: OptionalCell<ProcessId>,
+    // The process whose message is being transmitted.
+    tx_owner: OptionalCell<ProcessId>,
+    // Sequence number for queued messages from different processes.
+    tx_counter: Cell<usize>,
 
     // Variable used to store the current state of the CAN peripheral
     // during an `enable` or `disable` command.
     peripheral_state: OptionalCell<can::State>,
+    // Whether an `enable` or `disable` command is waiting for its state
+    // change. Other state changes are reported to all processes.
+    state_requested: Cell<bool>,
 }
 
 pub struct App {
     receive_index: usize,
     lost_messages: u32,
+    // Whether the process receives messages, and whether it waits for the
+    // receive process to stop.
+    receiving: bool,
+    stopping: bool,
+    // Receive filter: messages whose identifier matches `filter_id` in the
+    // bits set in `filter_mask`. A mask of 0 accepts all messages.
+    filter_id: u32,
+    filter_mask: u32,
+    filter_extended: bool,
+    // Message waiting to be transmitted, its queue position and the
+    // priority of the process.
+    tx_pending: Option<(can::Id, usize)>,
+    tx_counter: usize,
+    tx_priority: u8,
 }
 
 impl Default for App {
@@ -123,10 +172,30 @@ impl Default for App {
         App {
             receive_index: 0,
             lost_messages: 0,
+            receiving: false,
+            stopping: false,
+            filter_id: 0,
+            filter_mask: 0,
+            filter_extended: false,
+            tx_pending: None,
+            tx_counter: 0,
+            tx_priority: u8::MAX,
         }
     }
 }
 
+impl App {
+    fn accepts(&self, id: can::Id) -> bool {
+        let (id, extended) = match id {
+            can::Id::Standard(id) => (id as u32, false),
+            can::Id::Extended(id) => (id, true),
+        };
+        self.filter_mask == 0
+            || (extended == self.filter_extended
+                && id & self.filter_mask == self.filter_id & self.filter_mask)
+    }
+}
+
 impl<'a, Can: can::Can> CanCapsule<'a, Can> {
     pub fn new(
         can: &'a Can,
@@ -146,16 +215,28 @@ impl<'a, Can: can::Can> CanCapsule<'a, Can> {
             processes: grant,
             peripheral_state: OptionalCell::empty(),
             processid: OptionalCell::empty(),
+            tx_owner: OptionalCell::empty(),
+            tx_counter: Cell::new(0),
+            state_requested: Cell::new(false),
         }
     }
 
     fn schedule_callback(&self, callback_number: usize, data: (usize, usize, usize)) {
         self.processid.map(|processid| {
-            let _ = self.processes.enter(*processid, |_app, kernel_data| {
-                kernel_data
-                    .schedule_upcall(callback_number, (data.0, data.1, data.2))
-                    .ok();
-            });
+            self.schedule_callback_to(*processid, callback_number, data);
+        });
+    }
+
+    fn schedule_callback_to(
+        &self,
+        processid: ProcessId,
+        callback_number: usize,
+        data: (usize, usize, usize),
+    ) {
+        let _ = self.processes.enter(processid, |_app, kernel_data| {
+            kernel_data
+                .schedule_upcall(callback_number, (data.0, data.1, data.2))
+                .ok();
         });
     }
 
@@ -179,6 +260,10 @@ impl<'a, Can: can::Can> CanCapsule<'a, Can> {
                                     self.can_tx.take().map_or(
                                         Err(ErrorCode::NOMEM),
                                         |dest_buffer| {
+                                            if length > buffer.len() || length > dest_buffer.len() {
+                                                self.can_tx.replace(dest_buffer);
+                                                return Err(ErrorCode::SIZE);
+                                            }
                                             for i in 0..length {
                                                 dest_buffer[i] = buffer[i].get();
                                             }
@@ -199,6 +284,156 @@ impl<'a, Can: can::Can> CanCapsule<'a, Can> {
             .unwrap_or_else(|err| err.into())
     }
 
+    /// Transmit the message of `processid` right away if the controller is
+    /// idle, otherwise queue it until the messages before it are sent.
+    fn queue_send(
+        &self,
+        mut processid: ProcessId,
+        id: can::Id,
+        length: usize,
+    ) -> Result<(), ErrorCode> {
+        if self.can_tx.is_none() {
+            self.processes
+                .enter(processid, |app, _| {
+                    if app.tx_pending.is_some() || self.tx_owner.contains(&processid) {
+                        Err(ErrorCode::BUSY)
+                    } else {
+                        app.tx_pending = Some((id, length));
+                        app.tx_counter = self.tx_counter.get();
+                        self.tx_counter.set(app.tx_counter.wrapping_add(1));
+                        Ok(())
+                    }
+                })
+                .unwrap_or_else(|err| err.into())
+        } else {
+            self.process_send_command(&mut processid, id, length)
+                .map(|()| self.tx_owner.set(processid))
+        }
+    }
+
+    /// Transmit the next queued message: the one of the process with the
+    /// highest priority, and among those the one queued first. Messages that
+    /// fail to start are reported to their process.
+    fn send_next(&self) {
+        while self.can_tx.is_some() {
+            let mut next: Option<(ProcessId, u8, usize)> = None;
+            for cntr in self.processes.iter() {
+                let processid = cntr.processid();
+                cntr.enter(|app, _| {
+                    if app.tx_pending.is_some() {
+                        // Checks whether app.tx_counter is earlier than the
+                        // best one so far, handling wraparound.
+                        let earlier = next.map_or(true, |(_, priority, counter)| {
+                            app.tx_priority < priority
+                                || (app.tx_priority == priority
+                                    && counter.wrapping_sub(app.tx_counter) < usize::MAX / 2)
+                        });
+                        if earlier {
+                            next = Some((processid, app.tx_priority, app.tx_counter));
+                        }
+                    }
+                });
+            }
+
+            let mut processid = match next {
+                Some((processid, _, _)) => processid,
+                None => return,
+            };
+            let pending = self
+                .processes
+                .enter(processid, |app, _| app.tx_pending.take())
+                .ok()
+                .flatten();
+            if let Some((id, length)) = pending {
+                match self.process_send_command(&mut processid, id, length) {
+                    Ok(()) => self.tx_owner.set(processid),
+                    Err(err) => self.schedule_callback_to(
+                        processid,
+                        up_calls::UPCALL_TRANSMISSION_ERROR,
+                        (error_upcalls::ERROR_TX, err as usize, 0),
+                    ),
+                }
+            }
+        }
+    }
+
+    /// Add `processid` to the receiving processes, starting the receive
+    /// process of the controller if it is the first one.
+    fn start_receive(&self, processid: ProcessId) -> Result<(), ErrorCode> {
+        self.processes
+            .enter(processid, |app, kernel| {
+                kernel
+                    .get_readwrite_processbuffer(rw_allow::RW_ALLOW_BUFFER)
+                    .map_or_else(
+                        |err| err.into(),
+                        |buffer_ref| {
+                            buffer_ref
+                                .enter(|buffer| {
+                                    // make sure that the receiving buffer can have at least
+                                    // 2 messages of 8 bytes each and 4 another bytes for the counter
+                                    if buffer.len()
+                                        >= 2 * can::STANDARD_CAN_PACKET_SIZE + size_of::<u32>()
+                                    {
+                                        Ok(())
+                                    } else {
+                                        Err(ErrorCode::SIZE)
+                                    }
+                                })
+                                .unwrap_or_else(|err| err.into())
+                        },
+                    )?;
+                if app.receiving {
+                    return Err(ErrorCode::ALREADY);
+                }
+                // The controller is already receiving for other processes,
+                // or will restart when it has stopped.
+                if let Some(dest_buffer) = self.can_rx.take() {
+                    if let Err((err, buffer)) = self.can.start_receive_process(dest_buffer) {
+                        self.can_rx.replace(buffer);
+                        return Err(err);
+                    }
+                }
+                app.receiving = true;
+                app.stopping = false;
+                Ok(())
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    /// Remove `processid` from the receiving processes, stopping the receive
+    /// process of the controller if it was the last one.
+    fn stop_receive(&self, processid: ProcessId) -> Result<(), ErrorCode> {
+        self.processes
+            .enter(processid, |app, _| {
+                if !app.receiving {
+                    return Err(ErrorCode::ALREADY);
+                }
+                app.receiving = false;
+                Ok(())
+            })
+            .unwrap_or_else(|err| err.into())?;
+
+        if self.receivers() > 0 {
+            // Other processes still receive, so this one is done.
+            self.schedule_callback_to(processid, up_calls::UPCALL_RECEIVED_STOPPED, (0, 0, 0));
+            Ok(())
+        } else {
+            self.can.stop_receive().map(|()| {
+                let _ = self
+                    .processes
+                    .enter(processid, |app, _| app.stopping = true);
+            })
+        }
+    }
+
+    fn receivers(&self) -> usize {
+        self.processes
+            .iter()
+            .map(|cntr| cntr.enter(|app, _| app.receiving))
+            .filter(|receiving| *receiving)
+            .count()
+    }
+
     pub fn is_valid_process(&self, processid: ProcessId) -> bool {
         self.processid.map_or(true, |owning_process| {
             self.processes
@@ -221,13 +456,15 @@ impl<'a, Can: can::Can> SyscallDriver for CanCapsule<'a, Can> {
             return CommandReturn::success();
         }
 
-        // Check to see if the process or no process at all
-        // owns the capsule. Only one application can use the
-        // capsule at a time.
-        if !self.is_valid_process(processid) {
-            return CommandReturn::failure(ErrorCode::RESERVE);
-        } else {
-            self.processid.set(processid);
+        // The configuration of the peripheral belongs to the process that
+        // set it, or to no process at all. Only one application can
+        // configure the capsule at a time.
+        if matches!(command_num, 1..=4 | 9) {
+            if !self.is_valid_process(processid) {
+                return CommandReturn::failure(ErrorCode::RESERVE);
+            } else {
+                self.processid.set(processid);
+            }
         }
 
         match command_num {
@@ -252,82 +489,48 @@ impl<'a, Can: can::Can> SyscallDriver for CanCapsule<'a, Can> {
 
             // Enable the peripheral
             3 => match self.can.enable() {
-                Ok(_) => CommandReturn::success(),
+                Ok(_) => {
+                    self.state_requested.set(true);
+                    CommandReturn::success()
+                }
                 Err(err) => CommandReturn::failure(err),
             },
 
             // Disable the peripheral
             4 => match self.can.disable() {
-                Ok(_) => CommandReturn::success(),
+                Ok(_) => {
+                    self.state_requested.set(true);
+                    CommandReturn::success()
+                }
                 Err(err) => CommandReturn::failure(err),
             },
 
             // Send a message with a 16-bit identifier
             5 => {
                 let id = can::Id::Standard(arg1 as u16);
-                self.processid
-                    .map_or(
-                        CommandReturn::failure(ErrorCode::BUSY),
-                        |processid| match self.process_send_command(processid, id, arg2) {
-                            Ok(_) => CommandReturn::success(),
-                            Err(err) => CommandReturn::failure(err),
-                        },
-                    )
+                match self.queue_send(processid, id, arg2) {
+                    Ok(_) => CommandReturn::success(),
+                    Err(err) => CommandReturn::failure(err),
+                }
             }
 
             // Send a message with a 32-bit identifier
             6 => {
                 let id = can::Id::Extended(arg1 as u32);
-                self.processid
-                    .map_or(
-                        CommandReturn::failure(ErrorCode::BUSY),
-                        |processid| match self.process_send_command(processid, id, arg2) {
-                            Ok(_) => CommandReturn::success(),
-                            Err(err) => CommandReturn::failure(err),
-                        },
-                    )
+                match self.queue_send(processid, id, arg2) {
+                    Ok(_) => CommandReturn::success(),
+                    Err(err) => CommandReturn::failure(err),
+                }
             }
 
             // Start receiving messages
-            7 => {
-                self.can_rx
-                    .take()
-                    .map(|dest_buffer| {
-                        self.processes
-                            .enter(processid, |_, kernel| {
-                                match kernel.get_readwrite_processbuffer(0).map_or_else(
-                                    |err| err.into(),
-                                    |buffer_ref| {
-                                        buffer_ref
-                                            .enter(|buffer| {
-                                                // make sure that the receiving buffer can have at least
-                                                // 2 messages of 8 bytes each and 4 another bytes for the counter
-                                                if buffer.len()
-                                                    >= 2 * can::STANDARD_CAN_PACKET_SIZE
-                                                        + size_of::<u32>()
-                                                {
-                                                    Ok(())
-                                                } else {
-                                                    Err(ErrorCode::SIZE)
-                                                }
-                                            })
-                                            .unwrap_or_else(|err| err.into())
-                                    },
-                                ) {
-                                    Ok(_) => match self.can.start_receive_process(dest_buffer) {
-                                        Ok(_) => CommandReturn::success(),
-                                        Err((err, _)) => CommandReturn::failure(err),
-                                    },
-                                    Err(err) => CommandReturn::failure(err.into()),
-                                }
-                            })
-                            .unwrap_or_else(|err| err.into())
-                    })
-                    .unwrap_or(CommandReturn::failure(ErrorCode::NOMEM))
-            }
+            7 => match self.start_receive(processid) {
+                Ok(_) => CommandReturn::success(),
+                Err(err) => CommandReturn::failure(err),
+            },
 
             // Stop receiving messages
-            8 => match self.can.stop_receive() {
+            8 => match self.stop_receive(processid) {
                 Ok(_) => CommandReturn::success(),
                 Err(err) => CommandReturn::failure(err),
             },
@@ -346,6 +549,29 @@ impl<'a, Can: can::Can> SyscallDriver for CanCapsule<'a, Can> {
                 }
             }
 
+            // Set the receive filter
+            10 => self
+                .processes
+                .enter(processid, |app, _| {
+                    app.filter_extended = arg1 & FILTER_EXTENDED != 0;
+                    app.filter_id = (arg1 & !FILTER_EXTENDED) as u32;
+                    app.filter_mask = arg2 as u32;
+                    CommandReturn::success()
+                })
+                .unwrap_or_else(|err| CommandReturn::failure(err.into())),
+
+            // Set the transmit priority
+            11 => match u8::try_from(arg1) {
+                Ok(priority) => self
+                    .processes
+                    .enter(processid, |app, _| {
+                        app.tx_priority = priority;
+                        CommandReturn::success()
+                    })
+                    .unwrap_or_else(|err| CommandReturn::failure(err.into())),
+                Err(_) => CommandReturn::failure(ErrorCode::INVAL),
+            },
+
             _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
         }
     }
@@ -357,9 +583,26 @@ impl<'a, Can: can::Can> SyscallDriver for CanCapsule<'a, Can> {
 
 impl<'a, Can: can::Can> can::ControllerClient for CanCapsule<'a, Can> {
     // This callback must be called after an `enable` or `disable` command was sent.
-    // It stores the new state of the peripheral.
+    // It stores the new state of the peripheral. State changes that no command
+    // requested, e.g. entering bus-off, are reported to all processes.
     fn state_changed(&self, state: can::State) {
-        self.peripheral_state.replace(state);
+        if self.state_requested.get() {
+            self.peripheral_state.replace(state);
+            return;
+        }
+
+        let data = match state {
+            can::State::Running => (0, 0, 0),
+            can::State::Disabled => (1, 0, 0),
+            can::State::Error(err) => (2, err as usize, 0),
+        };
+        for cntr in self.processes.iter() {
+            cntr.enter(|_, kernel_data| {
+                kernel_data
+                    .schedule_upcall(up_calls::UPCALL_STATE_CHANGED, data)
+                    .ok();
+            });
+        }
     }
 
     // This callback must be called after an `enable` command was sent and after a
@@ -368,6 +611,7 @@ impl<'a, Can: can::Can> can::ControllerClient for CanCapsule<'a, Can> {
     // If the state is different or the status is an error, send to the userspace an
     // error callback.
     fn enabled(&self, status: Result<(), ErrorCode>) {
+        self.state_requested.set(false);
         match status {
             Ok(_) => match self.peripheral_state.take() {
                 Some(can::State::Running) => {
@@ -396,6 +640,7 @@ impl<'a, Can: can::Can> can::ControllerClient for CanCapsule<'a, Can> {
     // If the state is different or the status is an error, send to the userspace an
     // error callback.
     fn disabled(&self, status: Result<(), ErrorCode>) {
+        self.state_requested.set(false);
         match status {
             Ok(_) => match self.peripheral_state.take() {
                 Some(can::State::Disabled) => {
@@ -431,15 +676,21 @@ impl<'a, Can: can::Can> can::TransmitClient<{ can::STANDARD_CAN_PACKET_SIZE }>
         buffer: &'static mut [u8; can::STANDARD_CAN_PACKET_SIZE],
     ) {
         self.can_tx.replace(buffer);
-        match status {
-            Ok(()) => self.schedule_callback(up_calls::UPCALL_MESSAGE_SENT, (0, 0, 0)),
-            Err(err) => {
-                self.schedule_callback(
-                    up_calls::UPCALL_TRANSMISSION_ERROR,
-                    (error_upcalls::ERROR_TX, err as usize, 0),
-                );
+        if let Some(processid) = self.tx_owner.take() {
+            match status {
+                Ok(()) => {
+                    self.schedule_callback_to(processid, up_calls::UPCALL_MESSAGE_SENT, (0, 0, 0))
+                }
+                Err(err) => {
+                    self.schedule_callback_to(
+                        processid,
+                        up_calls::UPCALL_TRANSMISSION_ERROR,
+                        (error_upcalls::ERROR_TX, err as usize, 0),
+                    );
+                }
             }
         }
+        self.send_next();
     }
 }
 
@@ -447,7 +698,8 @@ impl<'a, Can: can::Can> can::ReceiveClient<{ can::STANDARD_CAN_PACKET_SIZE }>
     for CanCapsule<'a, Can>
 {
     // This callback is called when a new message is received on any receiving
-    // fifo.
+    // fifo. The message is copied to every receiving process whose filter
+    // accepts it.
     fn message_received(
         &self,
         id: can::Id,
@@ -455,93 +707,119 @@ impl<'a, Can: can::Can> can::ReceiveClient<{ can::STANDARD_CAN_PACKET_SIZE }>
         len: usize,
         status: Result<(), can::Error>,
     ) {
-        let mut new_buffer = false;
-        let mut shared_len = 0;
-        match status {
-            Ok(_) => {
-                match self.processid.map_or(Err(ErrorCode::NOMEM), |processid| {
-                    self.processes
-                        .enter(*processid, |app_data, kernel_data| {
-                            kernel_data
-                                .get_readwrite_processbuffer(rw_allow::RW_ALLOW_BUFFER)
-                                .map_or_else(
-                                    |err| err.into(),
-                                    |buffer_ref| {
-                                        buffer_ref
-                                            .mut_enter(|user_buffer| {
-                                                shared_len = user_buffer.len();
-                                                // For now, the first 4 bytes (the size of u32) represent the number
-                                                // of messages that the user has not read yet, represented as Little Endian.
-                                                // When the userspace reads the buffer, the counter will be set
-                                                // to 0 so that the capsule knows. This will be changed after
-                                                // https://github.com/tock/tock/pull/3252 and
-                                                // https://github.com/tock/tock/pull/3258 are merged.
-                                                let mut tmp_buf: [u8; size_of::<u32>()] =
-                                                    [0; size_of::<u32>()];
-                                                user_buffer[0..size_of::<u32>()]
-                                                    .copy_to_slice(&mut tmp_buf);
-                                                let contor = u32::from_le_bytes(tmp_buf);
-                                                if contor == 0 {
-                                                    new_buffer = true;
-                                                    app_data.receive_index = size_of::<u32>();
-                                                }
-                                                user_buffer[0..size_of::<u32>()]
-                                                    .copy_from_slice(&(contor + 1).to_le_bytes());
-                                                if app_data.receive_index + len > user_buffer.len()
-                                                {
-                                                    app_data.lost_messages =
-                                                        app_data.lost_messages + 1;
-                                                    Err(ErrorCode::SIZE)
-                                                } else {
-                                                    let r = user_buffer[app_data.receive_index
-                                                        ..app_data.receive_index + len]
-                                                        .copy_from_slice_or_err(&buffer[0..len]);
-                                                    if r.is_ok() {
-                                                        app_data.receive_index =
-                                                            app_data.receive_index + len;
-                                                    }
-                                                    r
-                                                }
-                                            })
-                                            .unwrap_or_else(|err| err.into())
-                                    },
-                                )
-                        })
-                        .unwrap_or_else(|err| err.into())
-                }) {
-                    Err(err) => self.schedule_callback(
-                        up_calls::UPCALL_TRANSMISSION_ERROR,
-                        (error_upcalls::ERROR_RX, err as usize, 0),
-                    ),
+        if let Err(err) = status {
+            let kernel_err: ErrorCode = err.into();
+            for cntr in self.processes.iter() {
+                cntr.enter(|app, kernel_data| {
+                    if app.receiving {
+                        kernel_data
+                            .schedule_upcall(
+                                up_calls::UPCALL_TRANSMISSION_ERROR,
+                                (error_upcalls::ERROR_RX, kernel_err.into(), 0),
+                            )
+                            .ok();
+                    }
+                });
+            }
+            return;
+        }
+
+        for cntr in self.processes.iter() {
+            cntr.enter(|app_data, kernel_data| {
+                if !app_data.receiving || !app_data.accepts(id) {
+                    return;
+                }
+                let mut new_buffer = false;
+                let mut shared_len = 0;
+                let res = kernel_data
+                    .get_readwrite_processbuffer(rw_allow::RW_ALLOW_BUFFER)
+                    .map_or_else(
+                        |err| err.into(),
+                        |buffer_ref| {
+                            buffer_ref
+                                .mut_enter(|user_buffer| {
+                                    shared_len = user_buffer.len();
+                                    // For now, the first 4 bytes (the size of u32) represent the number
+                                    // of messages that the user has not read yet, represented as Little Endian.
+                                    // When the userspace reads the buffer, the counter will be set
+                                    // to 0 so that the capsule knows. This will be changed after
+                                    // https://github.com/tock/tock/pull/3252 and
+                                    // https://github.com/tock/tock/pull/3258 are merged.
+                                    let mut tmp_buf: [u8; size_of::<u32>()] = [0; size_of::<u32>()];
+                                    user_buffer[0..size_of::<u32>()].copy_to_slice(&mut tmp_buf);
+                                    let contor = u32::from_le_bytes(tmp_buf);
+                                    if contor == 0 {
+                                        new_buffer = true;
+                                        app_data.receive_index = size_of::<u32>();
+                                    }
+                                    user_buffer[0..size_of::<u32>()]
+                                        .copy_from_slice(&(contor + 1).to_le_bytes());
+                                    if app_data.receive_index + len > user_buffer.len() {
+                                        app_data.lost_messages += 1;
+                                        Err(ErrorCode::SIZE)
+                                    } else {
+                                        let r = user_buffer
+                                            [app_data.receive_index..app_data.receive_index + len]
+                                            .copy_from_slice_or_err(&buffer[0..len]);
+                                        if r.is_ok() {
+                                            app_data.receive_index += len;
+                                        }
+                                        r
+                                    }
+                                })
+                                .unwrap_or_else(|err| err.into())
+                        },
+                    );
+                match res {
+                    Err(err) => {
+                        kernel_data
+                            .schedule_upcall(
+                                up_calls::UPCALL_TRANSMISSION_ERROR,
+                                (error_upcalls::ERROR_RX, err as usize, 0),
+                            )
+                            .ok();
+                    }
                     Ok(_) => {
                         if new_buffer {
-                            self.schedule_callback(
-                                up_calls::UPCALL_MESSAGE_RECEIVED,
-                                (
-                                    0,
-                                    shared_len as usize,
-                                    match id {
-                                        can::Id::Standard(u16) => u16 as usize,
-                                        can::Id::Extended(u32) => u32 as usize,
-                                    },
-                                ),
-                            )
+                            kernel_data
+                                .schedule_upcall(
+                                    up_calls::UPCALL_MESSAGE_RECEIVED,
+                                    (
+                                        0,
+                                        shared_len,
+                                        match id {
+                                            can::Id::Standard(u16) => u16 as usize,
+                                            can::Id::Extended(u32) => u32 as usize,
+                                        },
+                                    ),
+                                )
+                                .ok();
                         }
                     }
                 }
-            }
-            Err(err) => {
-                let kernel_err: ErrorCode = err.into();
-                self.schedule_callback(
-                    up_calls::UPCALL_TRANSMISSION_ERROR,
-                    (error_upcalls::ERROR_RX, kernel_err.into(), 0),
-                )
-            }
-        };
+            });
+        }
     }
 
     fn stopped(&self, buffer: &'static mut [u8; can::STANDARD_CAN_PACKET_SIZE]) {
-        self.can_rx.replace(buffer);
-        self.schedule_callback(up_calls::UPCALL_RECEIVED_STOPPED, (0, 0, 0));
+        for cntr in self.processes.iter() {
+            cntr.enter(|app, kernel_data| {
+                if app.stopping {
+                    app.stopping = false;
+                    kernel_data
+                        .schedule_upcall(up_calls::UPCALL_RECEIVED_STOPPED, (0, 0, 0))
+                        .ok();
+                }
+            });
+        }
+
+        // A process may have started receiving while the controller stopped.
+        if self.receivers() > 0 {
+            if let Err((_, buffer)) = self.can.start_receive_process(buffer) {
+                self.can_rx.replace(buffer);
+            }
+        } else {
+            self.can_rx.replace(buffer);
+        }
     }
 }
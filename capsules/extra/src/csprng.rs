@@ -0,0 +1,505 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Cryptographically secure pseudorandom number generator layered over a
+//! hardware entropy source.
+//!
+//! Hardware entropy sources such as the SAM4L or STM32 TRNGs produce only a
+//! few words per millisecond, so serving every random byte directly from them
+//! makes large userspace requests slow and starves other consumers. `Csprng`
+//! instead collects a 256-bit seed from an `Entropy32` source and expands it
+//! with the ChaCha20 block function, using the "fast key erasure"
+//! construction: every block produces both the output words and the key for
+//! the next block, so a later compromise of the state does not reveal
+//! earlier outputs.
+//!
+//! The generator reseeds from the entropy source periodically, mixing fresh
+//! entropy into the key. Requests that arrive before the first seed has been
+//! collected wait for it; after that, requests never wait on the entropy
+//! source.
+//!
+//! `Csprng` implements `hil::rng::Rng`, so it can serve the rng syscall
+//! driver, and `hil::rng::Random` together with `fill_bytes()` for in-kernel
+//! consumers that need random values synchronously, such as TCP initial
+//! sequence numbers or nonces. Synchronous consumers should check
+//! `is_seeded()` before relying on the output for security.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! # use kernel::static_init;
+//!
+//! let csprng_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!     VirtualMuxAlarm::new(mux_alarm)
+//! );
+//! csprng_alarm.setup();
+//! let csprng = static_init!(
+//!     capsules_extra::csprng::Csprng<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     capsules_extra::csprng::Csprng::new(&sam4l::trng::TRNG, csprng_alarm, 60_000)
+//! );
+//! sam4l::trng::TRNG.set_client(csprng);
+//! csprng_alarm.set_alarm_client(csprng);
+//! csprng.register();
+//! csprng.start();
+//! ```
+
+use core::cell::Cell;
+
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil::entropy::{self, Entropy32};
+use kernel::hil::rng::{self, Random, Rng};
+use kernel::hil::time::{self, ConvertTicks};
+use kernel::utilities::cells::OptionalCell;
+use kernel::ErrorCode;
+
+/// Number of words in a ChaCha20 key, and in the seed collected from the
+/// entropy source.
+const KEY_WORDS: usize = 8;
+
+/// Number of output words each ChaCha20 block yields after the next key has
+/// been taken from it.
+const OUTPUT_WORDS: usize = 16 - KEY_WORDS;
+
+/// Maximum number of words handed to the `rng::Client` in one callback, so
+/// a large request does not keep the kernel busy for long.
+const WORDS_PER_CALLBACK: usize = 64;
+
+/// "expand 32-byte k"
+const CHACHA_CONSTANTS: [u32; 4] = [0x61707865, 0x3320646e, 0x79622d32, 0x6b206574];
+
+fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(12);
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(7);
+}
+
+/// The ChaCha20 block function with a 64-bit block counter and an all-zero
+/// nonce.
+fn chacha20_block(key: &[u32; KEY_WORDS], counter: u64) -> [u32; 16] {
+    let mut input = [0; 16];
+    input[..4].copy_from_slice(&CHACHA_CONSTANTS);
+    input[4..12].copy_from_slice(key);
+    input[12] = counter as u32;
+    input[13] = (counter >> 32) as u32;
+
+    let mut state = input;
+    for _ in 0..10 {
+        quarter_round(&mut state, 0, 4, 8, 12);
+        quarter_round(&mut state, 1, 5, 9, 13);
+        quarter_round(&mut state, 2, 6, 10, 14);
+        quarter_round(&mut state, 3, 7, 11, 15);
+        quarter_round(&mut state, 0, 5, 10, 15);
+        quarter_round(&mut state, 1, 6, 11, 12);
+        quarter_round(&mut state, 2, 7, 8, 13);
+        quarter_round(&mut state, 3, 4, 9, 14);
+    }
+    for (word, input) in state.iter_mut().zip(input.iter()) {
+        *word = word.wrapping_add(*input);
+    }
+    state
+}
+
+pub struct Csprng<'a, A: time::Alarm<'a>> {
+    entropy: &'a dyn Entropy32<'a>,
+    alarm: &'a A,
+    /// How often to mix fresh entropy into the key, in milliseconds.
+    reseed_interval_ms: u32,
+    key: Cell<[u32; KEY_WORDS]>,
+    counter: Cell<u64>,
+    /// Output words of the current block. Words are zeroed once used.
+    output: Cell<[u32; OUTPUT_WORDS]>,
+    /// Index of the next unused word in `output`.
+    output_index: Cell<usize>,
+    /// Entropy collected for the next reseed.
+    seed: Cell<[u32; KEY_WORDS]>,
+    seed_words: Cell<usize>,
+    seeded: Cell<bool>,
+    reseeding: Cell<bool>,
+    client: OptionalCell<&'a dyn rng::Client>,
+    /// Whether the client asked for randomness that has not been delivered.
+    requested: Cell<bool>,
+    deferred_call: DeferredCall,
+}
+
+impl<'a, A: time::Alarm<'a>> Csprng<'a, A> {
+    pub fn new(
+        entropy: &'a dyn Entropy32<'a>,
+        alarm: &'a A,
+        reseed_interval_ms: u32,
+    ) -> Csprng<'a, A> {
+        Csprng {
+            entropy,
+            alarm,
+            reseed_interval_ms,
+            key: Cell::new([0; KEY_WORDS]),
+            counter: Cell::new(0),
+            output: Cell::new([0; OUTPUT_WORDS]),
+            output_index: Cell::new(OUTPUT_WORDS),
+            seed: Cell::new([0; KEY_WORDS]),
+            seed_words: Cell::new(0),
+            seeded: Cell::new(false),
+            reseeding: Cell::new(false),
+            client: OptionalCell::empty(),
+            requested: Cell::new(false),
+            deferred_call: DeferredCall::new(),
+        }
+    }
+
+    /// Start collecting the initial seed. Periodic reseeding follows
+    /// automatically once the generator is seeded.
+    pub fn start(&self) {
+        self.start_reseed();
+    }
+
+    /// Whether the generator has been seeded from the entropy source. Output
+    /// produced before then is predictable.
+    pub fn is_seeded(&self) -> bool {
+        self.seeded.get()
+    }
+
+    /// Fill `buf` with random bytes.
+    pub fn fill_bytes(&self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(4) {
+            let bytes = self.next_word().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    /// Generate the next block, replacing the key and the output words.
+    fn refill(&self) {
+        let counter = self.counter.get();
+        let block = chacha20_block(&self.key.get(), counter);
+        self.counter.set(counter.wrapping_add(1));
+
+        let mut key = [0; KEY_WORDS];
+        key.copy_from_slice(&block[..KEY_WORDS]);
+        self.key.set(key);
+
+        let mut output = [0; OUTPUT_WORDS];
+        output.copy_from_slice(&block[KEY_WORDS..]);
+        self.output.set(output);
+        self.output_index.set(0);
+    }
+
+    fn next_word(&self) -> u32 {
+        if self.output_index.get() >= OUTPUT_WORDS {
+            self.refill();
+        }
+        let index = self.output_index.get();
+        let mut output = self.output.get();
+        let word = output[index];
+        output[index] = 0;
+        self.output.set(output);
+        self.output_index.set(index + 1);
+        word
+    }
+
+    /// Mix `input` into the key and rekey, discarding output generated from
+    /// the previous key.
+    fn mix(&self, input: &[u32]) {
+        let mut key = self.key.get();
+        for (word, input) in key.iter_mut().zip(input.iter()) {
+            *word ^= *input;
+        }
+        self.key.set(key);
+        self.refill();
+        self.output.set([0; OUTPUT_WORDS]);
+        self.output_index.set(OUTPUT_WORDS);
+    }
+
+    fn start_reseed(&self) {
+        if self.reseeding.get() {
+            return;
+        }
+        self.seed_words.set(0);
+        if self.entropy.get().is_ok() {
+            self.reseeding.set(true);
+        } else {
+            // Try again at the next interval.
+            self.arm_reseed_alarm();
+        }
+    }
+
+    fn arm_reseed_alarm(&self) {
+        self.alarm.set_alarm(
+            self.alarm.now(),
+            self.alarm.ticks_from_ms(self.reseed_interval_ms),
+        );
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> Rng<'a> for Csprng<'a, A> {
+    fn get(&self) -> Result<(), ErrorCode> {
+        self.requested.set(true);
+        if self.seeded.get() {
+            self.deferred_call.set();
+        } else {
+            // The request is served once the initial seed arrives.
+            self.start_reseed();
+        }
+        Ok(())
+    }
+
+    fn cancel(&self) -> Result<(), ErrorCode> {
+        self.requested.set(false);
+        Ok(())
+    }
+
+    fn set_client(&'a self, client: &'a dyn rng::Client) {
+        self.client.set(client);
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> Random<'a> for Csprng<'a, A> {
+    fn initialize(&'a self) {
+        self.start();
+    }
+
+    /// Mix `seed` into the key as additional input. This never weakens the
+    /// generator, but does not count as seeding it.
+    fn reseed(&self, seed: u32) {
+        self.mix(&[seed]);
+    }
+
+    fn random(&self) -> u32 {
+        self.next_word()
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> entropy::Client32 for Csprng<'a, A> {
+    fn entropy_available(
+        &self,
+        entropy: &mut dyn Iterator<Item = u32>,
+        error: Result<(), ErrorCode>,
+    ) -> entropy::Continue {
+        if error.is_err() {
+            self.reseeding.set(false);
+            self.arm_reseed_alarm();
+            return entropy::Continue::Done;
+        }
+
+        let mut seed = self.seed.get();
+        let mut seed_words = self.seed_words.get();
+        while seed_words < KEY_WORDS {
+            match entropy.next() {
+                Some(word) => {
+                    seed[seed_words] = word;
+                    seed_words += 1;
+                }
+                None => break,
+            }
+        }
+
+        if seed_words < KEY_WORDS {
+            self.seed.set(seed);
+            self.seed_words.set(seed_words);
+            return entropy::Continue::More;
+        }
+
+        self.mix(&seed);
+        self.seed.set([0; KEY_WORDS]);
+        self.seed_words.set(0);
+        self.seeded.set(true);
+        self.reseeding.set(false);
+        self.arm_reseed_alarm();
+        if self.requested.get() {
+            self.deferred_call.set();
+        }
+        entropy::Continue::Done
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> time::AlarmClient for Csprng<'a, A> {
+    fn alarm(&self) {
+        self.start_reseed();
+    }
+}
+
+/// Yields at most `remaining` words from the generator.
+struct CsprngIter<'a, 'b, A: time::Alarm<'b>> {
+    csprng: &'a Csprng<'b, A>,
+    remaining: usize,
+}
+
+impl<'a, 'b, A: time::Alarm<'b>> Iterator for CsprngIter<'a, 'b, A> {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<u32> {
+        if self.remaining == 0 {
+            None
+        } else {
+            self.remaining -= 1;
+            Some(self.csprng.next_word())
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> DeferredCallClient for Csprng<'a, A> {
+    fn handle_deferred_call(&self) {
+        if !self.requested.get() || !self.seeded.get() {
+            return;
+        }
+        self.requested.set(false);
+        let mut iter = CsprngIter {
+            csprng: self,
+            remaining: WORDS_PER_CALLBACK,
+        };
+        let more = self.client.map_or(false, |client| {
+            client.randomness_available(&mut iter, Ok(())) == rng::Continue::More
+        });
+        if more {
+            self.requested.set(true);
+            self.deferred_call.set();
+        }
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sha_software::from_hex;
+    use kernel::hil::time::{Alarm, AlarmClient, Freq1KHz, Ticks32, Time};
+
+    struct FakeEntropy;
+
+    impl<'a> Entropy32<'a> for FakeEntropy {
+        fn get(&self) -> Result<(), ErrorCode> {
+            Ok(())
+        }
+
+        fn cancel(&self) -> Result<(), ErrorCode> {
+            Ok(())
+        }
+
+        fn set_client(&'a self, _: &'a dyn entropy::Client32) {}
+    }
+
+    struct FakeAlarm;
+
+    impl Time for FakeAlarm {
+        type Frequency = Freq1KHz;
+        type Ticks = Ticks32;
+
+        fn now(&self) -> Ticks32 {
+            0u32.into()
+        }
+    }
+
+    impl<'a> Alarm<'a> for FakeAlarm {
+        fn set_alarm_client(&self, _: &'a dyn AlarmClient) {}
+
+        fn set_alarm(&self, _: Ticks32, _: Ticks32) {}
+
+        fn get_alarm(&self) -> Ticks32 {
+            0u32.into()
+        }
+
+        fn disarm(&self) -> Result<(), ErrorCode> {
+            Ok(())
+        }
+
+        fn is_armed(&self) -> bool {
+            false
+        }
+
+        fn minimum_dt(&self) -> Ticks32 {
+            1u32.into()
+        }
+    }
+
+    /// The block for a key and counter, as bytes.
+    fn keystream(key: [u8; 32], counter: u64) -> [u8; 64] {
+        let mut key_words = [0; KEY_WORDS];
+        for (word, bytes) in key_words.iter_mut().zip(key.chunks(4)) {
+            *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        let mut block = [0; 64];
+        for (bytes, word) in block
+            .chunks_mut(4)
+            .zip(chacha20_block(&key_words, counter).iter())
+        {
+            bytes.copy_from_slice(&word.to_le_bytes());
+        }
+        block
+    }
+
+    #[test]
+    fn quarter_round_rfc8439() {
+        // RFC 8439, section 2.1.1
+        let mut state = [0; 16];
+        state[..4].copy_from_slice(&[0x11111111, 0x01020304, 0x9b8d6f43, 0x01234567]);
+        quarter_round(&mut state, 0, 1, 2, 3);
+        assert_eq!(state[..4], [0xea2a92f4, 0xcb1cf8ce, 0x4581472e, 0x5881c4bb]);
+    }
+
+    #[test]
+    fn block_function_rfc8439() {
+        // RFC 8439, appendix A.1, test vectors 1 to 4, which use an all-zero
+        // nonce
+        let mut key = [0; 32];
+        assert_eq!(
+            keystream(key, 0),
+            from_hex(
+                "76b8e0ada0f13d90405d6ae55386bd28bdd219b8a08ded1aa836efcc8b770dc7\
+                 da41597c5157488d7724e03fb8d84a376a43b8f41518a11cc387b669b2ee6586"
+            )
+        );
+        assert_eq!(
+            keystream(key, 1),
+            from_hex(
+                "9f07e7be5551387a98ba977c732d080dcb0f29a048e3656912c6533e32ee7aed\
+                 29b721769ce64e43d57133b074d839d531ed1f28510afb45ace10a1f4b794d6f"
+            )
+        );
+        key[31] = 0x01;
+        assert_eq!(
+            keystream(key, 1),
+            from_hex(
+                "3aeb5224ecf849929b9d828db1ced4dd832025e8018b8160b82284f3c949aa5a\
+                 8eca00bbb4a73bdad192b5c42f73f2fd4e273644c8b36125a64addeb006c13a0"
+            )
+        );
+        key = [0; 32];
+        key[1] = 0xff;
+        assert_eq!(
+            keystream(key, 2),
+            from_hex(
+                "72d54dfbf12ec44b362692df94137f328fea8da73990265ec1bbbea1ae9af0ca\
+                 13b25aa26cb4a648cb9b9d1be65b2c0924a66c54d545ec1b7374f4872e99f096"
+            )
+        );
+    }
+
+    #[test]
+    fn output_uses_fast_key_erasure() {
+        // The seed becomes the key, which is replaced by the first half of
+        // block 0. The output starts with the second half of block 1.
+        let csprng = Csprng::new(&FakeEntropy, &FakeAlarm, 1000);
+        csprng.start();
+        let mut seed = (0..KEY_WORDS as u32).map(|i| {
+            let byte = 4 * i as u8;
+            u32::from_le_bytes([byte, byte + 1, byte + 2, byte + 3])
+        });
+        entropy::Client32::entropy_available(&csprng, &mut seed, Ok(()));
+        assert!(csprng.is_seeded());
+
+        let mut output = [0; 32];
+        csprng.fill_bytes(&mut output);
+        assert_eq!(
+            output,
+            from_hex("6882deffb7ec6a53c7e582a7f9627d576bd694a4ed5fe547916be8d5f7284ceb")
+        );
+    }
+}
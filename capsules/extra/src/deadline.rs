@@ -0,0 +1,90 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! System call driver to declare periodic deadlines to an earliest deadline
+//! first scheduler (`kernel::scheduler::edf::EDFSched`).
+//!
+//! A process declares a period and a relative deadline, and then calls
+//! `job complete` each time it has finished the work of one period. Until
+//! it does, the scheduler treats its job as due at the deadline of the
+//! current period.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let deadline = static_init!(
+//!     capsules_extra::deadline::DeadlineDriver,
+//!     capsules_extra::deadline::DeadlineDriver::new(scheduler)
+//! );
+//! ```
+//!
+//! Command Interface
+//! -----------------
+//!
+//! - `0`: Driver existence check.
+//! - `1`: Declare a periodic deadline. `data1` is the period and `data2`
+//!   the deadline relative to the start of each period, both in
+//!   microseconds. A deadline of 0 is the same as the period. Returns
+//!   `INVAL` if the deadline is longer than the period and `SIZE` if the
+//!   period is too long for the scheduler's clock.
+//! - `2`: The job of the current period completed.
+//! - `3`: Clear the deadline, the process becomes best-effort again.
+//! - `4`: Number of deadlines missed since the deadline was declared.
+
+use kernel::scheduler::edf::DeadlineControl;
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::{ErrorCode, ProcessId};
+
+/// Syscall driver number.
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Deadline as usize;
+
+pub struct DeadlineDriver<'a> {
+    scheduler: &'a dyn DeadlineControl,
+}
+
+impl<'a> DeadlineDriver<'a> {
+    pub fn new(scheduler: &'a dyn DeadlineControl) -> DeadlineDriver<'a> {
+        DeadlineDriver { scheduler }
+    }
+}
+
+impl<'a> SyscallDriver for DeadlineDriver<'a> {
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+
+            1 => {
+                let period_us = data1 as u32;
+                let deadline_us = if data2 == 0 { period_us } else { data2 as u32 };
+                self.scheduler
+                    .set_deadline(processid, period_us, deadline_us)
+                    .into()
+            }
+
+            2 => self.scheduler.job_complete(processid).into(),
+
+            3 => self.scheduler.clear_deadline(processid).into(),
+
+            4 => match self.scheduler.deadline_misses(processid) {
+                Ok(misses) => CommandReturn::success_u32(misses),
+                Err(e) => CommandReturn::failure(e),
+            },
+
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, _processid: ProcessId) -> Result<(), kernel::process::Error> {
+        // All state is kept by the scheduler.
+        Ok(())
+    }
+}
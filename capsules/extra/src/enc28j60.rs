@@ -0,0 +1,839 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Driver for the Microchip ENC28J60 SPI Ethernet controller.
+//!
+//! <https://www.microchip.com/en-us/product/ENC28J60>
+//!
+//! The ENC28J60 is a 10BASE-T Ethernet MAC and PHY with 8 KB of buffer
+//! memory, attached over SPI and signaling events on an active-low interrupt
+//! line. This driver implements `hil::ethernet::EthernetAdapter` on top of
+//! it, so that boards without an on-chip MAC can join wired networks.
+//!
+//! The buffer memory holds a ring buffer of received frames and a single
+//! frame being transmitted. Every access to the chip is one SPI transaction,
+//! and the driver runs them from fixed sequences of operations (initializing
+//! the chip, sending a frame, receiving one, ...), taking the values written
+//! from its own state and switching register banks as the operations need.
+//! One sequence runs at a time: interrupts, configuration changes and frames
+//! to send that arrive while one runs are handled once it is done.
+//!
+//! The link runs in half duplex, which works with any link partner as the
+//! ENC28J60 does not autonegotiate.
+//!
+//! Usage
+//! -----
+//! ```rust,ignore
+//! let enc28j60 = components::enc28j60::Enc28j60Component::new(
+//!     mux_spi,
+//!     chip_select,
+//!     &gpio_port[ENC28J60_INT_PIN],
+//!     mux_alarm,
+//!     [0x02, 0x00, 0x00, 0x00, 0x00, 0x01],
+//! )
+//! .finalize(components::enc28j60_component_static!(
+//!     nrf52840::spi::SPIM,
+//!     nrf52840::rtc::Rtc
+//! ));
+//! enc28j60.set_receive_client(client);
+//! enc28j60.enable();
+//! ```
+
+use core::cell::Cell;
+
+use kernel::debug;
+use kernel::hil::ethernet::{
+    self, AddressFilter, EthernetAdapter, MacAddress, ReceiveClient, TransmitClient,
+};
+use kernel::hil::gpio;
+use kernel::hil::spi::{self, SpiMasterDevice};
+use kernel::hil::time::{self, Alarm, ConvertTicks};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// The length of the SPI buffers: an opcode and a control byte, followed by
+/// a frame.
+pub const BUF_LEN: usize = ethernet::MAX_FRAME_LEN + 2;
+
+const SPI_SPEED: u32 = 8_000_000;
+
+/// Time the oscillator needs to start after a soft reset.
+const RESET_DELAY_MS: u32 = 2;
+
+// Layout of the buffer memory.
+const RX_START: u16 = 0x0000;
+const RX_END: u16 = 0x19ff;
+const TX_START: u16 = 0x1a00;
+
+/// The length of the frame check sequence, added by the MAC.
+const FCS_LEN: usize = 4;
+
+/// SPI instruction opcodes, or'ed with a register address.
+mod opcode {
+    pub const READ_CONTROL: u8 = 0x00;
+    pub const READ_BUFFER: u8 = 0x3a;
+    pub const WRITE_CONTROL: u8 = 0x40;
+    pub const WRITE_BUFFER: u8 = 0x7a;
+    pub const BIT_SET: u8 = 0x80;
+    pub const BIT_CLEAR: u8 = 0xa0;
+    pub const SOFT_RESET: u8 = 0xff;
+}
+
+/// Registers, encoded as their bank in bits 5 and 6 and their address in the
+/// low bits. MAC and MII registers, whose reads return a dummy byte first,
+/// have the top bit set. Registers at `COMMON` and above are in every bank.
+mod reg {
+    pub const ADDR_MASK: u8 = 0x1f;
+    pub const BANK_SHIFT: u8 = 5;
+    pub const BANK_MASK: u8 = 0x03;
+    pub const MAC_MII: u8 = 0x80;
+    pub const COMMON: u8 = 0x1b;
+
+    pub const EIE: u8 = 0x1b;
+    pub const EIR: u8 = 0x1c;
+    pub const ECON2: u8 = 0x1e;
+    pub const ECON1: u8 = 0x1f;
+
+    pub const ERDPTL: u8 = 0x00;
+    pub const ERDPTH: u8 = 0x01;
+    pub const EWRPTL: u8 = 0x02;
+    pub const EWRPTH: u8 = 0x03;
+    pub const ETXSTL: u8 = 0x04;
+    pub const ETXSTH: u8 = 0x05;
+    pub const ETXNDL: u8 = 0x06;
+    pub const ETXNDH: u8 = 0x07;
+    pub const ERXSTL: u8 = 0x08;
+    pub const ERXSTH: u8 = 0x09;
+    pub const ERXNDL: u8 = 0x0a;
+    pub const ERXNDH: u8 = 0x0b;
+    pub const ERXRDPTL: u8 = 0x0c;
+    pub const ERXRDPTH: u8 = 0x0d;
+
+    pub const ERXFCON: u8 = 0x20 | 0x18;
+    pub const EPKTCNT: u8 = 0x20 | 0x19;
+
+    pub const MACON1: u8 = MAC_MII | 0x40;
+    pub const MACON3: u8 = MAC_MII | 0x40 | 0x02;
+    pub const MACON4: u8 = MAC_MII | 0x40 | 0x03;
+    pub const MABBIPG: u8 = MAC_MII | 0x40 | 0x04;
+    pub const MAIPGL: u8 = MAC_MII | 0x40 | 0x06;
+    pub const MAIPGH: u8 = MAC_MII | 0x40 | 0x07;
+    pub const MAMXFLL: u8 = MAC_MII | 0x40 | 0x0a;
+    pub const MAMXFLH: u8 = MAC_MII | 0x40 | 0x0b;
+    pub const MIREGADR: u8 = MAC_MII | 0x40 | 0x14;
+    pub const MIWRL: u8 = MAC_MII | 0x40 | 0x16;
+    pub const MIWRH: u8 = MAC_MII | 0x40 | 0x17;
+
+    /// MAADR1 to MAADR6, the bytes of the MAC address in order.
+    pub const MAADR: [u8; 6] = [
+        MAC_MII | 0x60 | 0x04,
+        MAC_MII | 0x60 | 0x05,
+        MAC_MII | 0x60 | 0x02,
+        MAC_MII | 0x60 | 0x03,
+        MAC_MII | 0x60,
+        MAC_MII | 0x60 | 0x01,
+    ];
+    pub const MISTAT: u8 = MAC_MII | 0x60 | 0x0a;
+
+    // PHY registers, written through MIREGADR and MIWR.
+    pub const PHCON1: u8 = 0x00;
+    pub const PHCON2: u8 = 0x10;
+}
+
+mod bits {
+    pub const EIE_INTIE: u8 = 0x80;
+    pub const EIE_PKTIE: u8 = 0x40;
+    pub const EIE_TXIE: u8 = 0x08;
+    pub const EIE_TXERIE: u8 = 0x02;
+    pub const EIE_RXERIE: u8 = 0x01;
+
+    pub const EIR_TXIF: u8 = 0x08;
+    pub const EIR_TXERIF: u8 = 0x02;
+    pub const EIR_RXERIF: u8 = 0x01;
+
+    pub const ECON1_TXRST: u8 = 0x80;
+    pub const ECON1_TXRTS: u8 = 0x08;
+    pub const ECON1_RXEN: u8 = 0x04;
+    pub const ECON1_BSEL: u8 = 0x03;
+
+    pub const ECON2_PKTDEC: u8 = 0x40;
+
+    pub const ERXFCON_UCEN: u8 = 0x80;
+    pub const ERXFCON_CRCEN: u8 = 0x20;
+    pub const ERXFCON_MCEN: u8 = 0x02;
+    pub const ERXFCON_BCEN: u8 = 0x01;
+
+    pub const MACON1_TXPAUS: u8 = 0x08;
+    pub const MACON1_RXPAUS: u8 = 0x04;
+    pub const MACON1_MARXEN: u8 = 0x01;
+
+    pub const MACON3_PADCFG0: u8 = 0x20;
+    pub const MACON3_TXCRCEN: u8 = 0x10;
+    pub const MACON3_FRMLNEN: u8 = 0x02;
+
+    pub const MACON4_DEFER: u8 = 0x40;
+
+    pub const MISTAT_BUSY: u8 = 0x01;
+
+    /// High byte of PHCON2: disable the loopback of half duplex frames.
+    pub const PHCON2_HDLDIS_H: u8 = 0x01;
+
+    /// Received Ok, in the third byte of the receive status vector.
+    pub const RSV_RXOK: u8 = 0x80;
+}
+
+/// A value written to a register.
+#[derive(Copy, Clone, Debug)]
+enum Value {
+    Const(u8),
+    /// Byte `i` of the MAC address.
+    Address(usize),
+    /// The receive filter of the address filter.
+    Filter,
+    /// The start of the next received frame.
+    ReadPtrLow,
+    ReadPtrHigh,
+    /// The last byte of the frame being sent.
+    TxEndLow,
+    TxEndHigh,
+    /// The end of the space freed by the last received frame.
+    RxFreeLow,
+    RxFreeHigh,
+}
+
+/// One SPI transaction.
+#[derive(Copy, Clone, Debug)]
+enum Op {
+    SoftReset,
+    Write(u8, Value),
+    Set(u8, u8),
+    Clear(u8, u8),
+    /// Read a register into `read_value`.
+    Read(u8),
+    /// Wait for the PHY register write to complete.
+    WaitMii,
+    /// Write the frame being sent to the transmit buffer.
+    WriteFrame,
+    /// Read the next pointer and the receive status vector of a frame.
+    ReadHeader,
+    /// Read a received frame and pass it to the client.
+    ReadFrame,
+}
+
+impl Op {
+    /// The bank that must be selected for the operation.
+    fn bank(&self) -> Option<u8> {
+        match self {
+            Op::Write(reg, _) | Op::Set(reg, _) | Op::Clear(reg, _) | Op::Read(reg) => {
+                if reg & reg::ADDR_MASK < reg::COMMON {
+                    Some((reg >> reg::BANK_SHIFT) & reg::BANK_MASK)
+                } else {
+                    None
+                }
+            }
+            Op::WaitMii => Some(3),
+            Op::SoftReset | Op::WriteFrame | Op::ReadHeader | Op::ReadFrame => None,
+        }
+    }
+}
+
+const fn low(value: u16) -> Value {
+    Value::Const(value as u8)
+}
+
+const fn high(value: u16) -> Value {
+    Value::Const((value >> 8) as u8)
+}
+
+const MAX_RX_FRAME_LEN: u16 = (ethernet::MAX_FRAME_LEN + FCS_LEN) as u16;
+
+const INIT: &[Op] = &[
+    Op::SoftReset,
+    Op::Write(reg::ERXSTL, low(RX_START)),
+    Op::Write(reg::ERXSTH, high(RX_START)),
+    Op::Write(reg::ERXNDL, low(RX_END)),
+    Op::Write(reg::ERXNDH, high(RX_END)),
+    // The whole receive buffer is free
+    Op::Write(reg::ERXRDPTL, low(RX_END)),
+    Op::Write(reg::ERXRDPTH, high(RX_END)),
+    Op::Write(reg::ETXSTL, low(TX_START)),
+    Op::Write(reg::ETXSTH, high(TX_START)),
+    Op::Write(reg::ERXFCON, Value::Filter),
+    Op::Write(
+        reg::MACON1,
+        Value::Const(bits::MACON1_MARXEN | bits::MACON1_TXPAUS | bits::MACON1_RXPAUS),
+    ),
+    Op::Write(
+        reg::MACON3,
+        Value::Const(bits::MACON3_PADCFG0 | bits::MACON3_TXCRCEN | bits::MACON3_FRMLNEN),
+    ),
+    Op::Write(reg::MACON4, Value::Const(bits::MACON4_DEFER)),
+    Op::Write(reg::MAMXFLL, low(MAX_RX_FRAME_LEN)),
+    Op::Write(reg::MAMXFLH, high(MAX_RX_FRAME_LEN)),
+    // Inter-packet gaps recommended for half duplex
+    Op::Write(reg::MABBIPG, Value::Const(0x12)),
+    Op::Write(reg::MAIPGL, Value::Const(0x12)),
+    Op::Write(reg::MAIPGH, Value::Const(0x0c)),
+    Op::Write(reg::MAADR[0], Value::Address(0)),
+    Op::Write(reg::MAADR[1], Value::Address(1)),
+    Op::Write(reg::MAADR[2], Value::Address(2)),
+    Op::Write(reg::MAADR[3], Value::Address(3)),
+    Op::Write(reg::MAADR[4], Value::Address(4)),
+    Op::Write(reg::MAADR[5], Value::Address(5)),
+    // Half duplex PHY
+    Op::Write(reg::MIREGADR, Value::Const(reg::PHCON1)),
+    Op::Write(reg::MIWRL, Value::Const(0)),
+    Op::Write(reg::MIWRH, Value::Const(0)),
+    Op::WaitMii,
+    Op::Write(reg::MIREGADR, Value::Const(reg::PHCON2)),
+    Op::Write(reg::MIWRL, Value::Const(0)),
+    Op::Write(reg::MIWRH, Value::Const(bits::PHCON2_HDLDIS_H)),
+    Op::WaitMii,
+    Op::Set(
+        reg::EIE,
+        bits::EIE_INTIE | bits::EIE_PKTIE | bits::EIE_TXIE | bits::EIE_TXERIE | bits::EIE_RXERIE,
+    ),
+    Op::Set(reg::ECON1, bits::ECON1_RXEN),
+];
+
+const CONFIGURE: &[Op] = &[
+    Op::Write(reg::MAADR[0], Value::Address(0)),
+    Op::Write(reg::MAADR[1], Value::Address(1)),
+    Op::Write(reg::MAADR[2], Value::Address(2)),
+    Op::Write(reg::MAADR[3], Value::Address(3)),
+    Op::Write(reg::MAADR[4], Value::Address(4)),
+    Op::Write(reg::MAADR[5], Value::Address(5)),
+    Op::Write(reg::ERXFCON, Value::Filter),
+];
+
+const TRANSMIT: &[Op] = &[
+    // Reset the transmit logic, which can stall after an error
+    Op::Set(reg::ECON1, bits::ECON1_TXRST),
+    Op::Clear(reg::ECON1, bits::ECON1_TXRST),
+    Op::Clear(reg::EIR, bits::EIR_TXIF | bits::EIR_TXERIF),
+    Op::Write(reg::EWRPTL, low(TX_START)),
+    Op::Write(reg::EWRPTH, high(TX_START)),
+    Op::WriteFrame,
+    Op::Write(reg::ETXNDL, Value::TxEndLow),
+    Op::Write(reg::ETXNDH, Value::TxEndHigh),
+    Op::Set(reg::ECON1, bits::ECON1_TXRTS),
+];
+
+const SERVICE: &[Op] = &[Op::Read(reg::EIR)];
+
+const ACKNOWLEDGE_TX: &[Op] = &[Op::Clear(reg::EIR, bits::EIR_TXIF | bits::EIR_TXERIF)];
+
+// The packet interrupt flag is unreliable, so the packet count is polled.
+const POLL_RX: &[Op] = &[Op::Read(reg::EPKTCNT)];
+
+const RECEIVE: &[Op] = &[
+    Op::Write(reg::ERDPTL, Value::ReadPtrLow),
+    Op::Write(reg::ERDPTH, Value::ReadPtrHigh),
+    Op::ReadHeader,
+    Op::ReadFrame,
+    Op::Write(reg::ERXRDPTL, Value::RxFreeLow),
+    Op::Write(reg::ERXRDPTH, Value::RxFreeHigh),
+    Op::Set(reg::ECON2, bits::ECON2_PKTDEC),
+];
+
+const CLEAR_RX_ERROR: &[Op] = &[Op::Clear(reg::EIR, bits::EIR_RXERIF)];
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Sequence {
+    Init,
+    Configure,
+    Transmit,
+    Service,
+    AcknowledgeTx,
+    PollRx,
+    Receive,
+    ClearRxError,
+}
+
+impl Sequence {
+    fn ops(self) -> &'static [Op] {
+        match self {
+            Sequence::Init => INIT,
+            Sequence::Configure => CONFIGURE,
+            Sequence::Transmit => TRANSMIT,
+            Sequence::Service => SERVICE,
+            Sequence::AcknowledgeTx => ACKNOWLEDGE_TX,
+            Sequence::PollRx => POLL_RX,
+            Sequence::Receive => RECEIVE,
+            Sequence::ClearRxError => CLEAR_RX_ERROR,
+        }
+    }
+}
+
+/// A bank that does not exist, so that the bank is selected again.
+const UNKNOWN_BANK: u8 = 0xff;
+
+pub struct Enc28j60<'a, S: SpiMasterDevice, A: Alarm<'a>> {
+    spi: &'a S,
+    int_pin: &'a dyn gpio::InterruptPin<'a>,
+    alarm: &'a A,
+    spi_tx: TakeCell<'static, [u8]>,
+    spi_rx: TakeCell<'static, [u8]>,
+    tx_client: OptionalCell<&'a dyn TransmitClient>,
+    rx_client: OptionalCell<&'a dyn ReceiveClient>,
+
+    address: Cell<MacAddress>,
+    filter: Cell<AddressFilter>,
+    enabled: Cell<bool>,
+    /// The chip is initialized.
+    ready: Cell<bool>,
+
+    sequence: OptionalCell<Sequence>,
+    op_idx: Cell<usize>,
+    bank: Cell<u8>,
+    /// The bank selected once the running bank switch is done.
+    switching: OptionalCell<u8>,
+    read_value: Cell<u8>,
+    /// The interrupt flags read when servicing the interrupt.
+    eir: Cell<u8>,
+
+    interrupt_pending: Cell<bool>,
+    config_pending: Cell<bool>,
+
+    /// The frame being sent, and its length.
+    tx_frame: TakeCell<'static, [u8]>,
+    tx_len: Cell<usize>,
+    tx_in_progress: Cell<bool>,
+
+    /// The start of the next received frame in the buffer memory.
+    next_packet: Cell<u16>,
+    /// The length of the frame being received, 0 if it is dropped.
+    rx_len: Cell<usize>,
+}
+
+impl<'a, S: SpiMasterDevice, A: Alarm<'a>> Enc28j60<'a, S, A> {
+    pub fn new(
+        spi: &'a S,
+        int_pin: &'a dyn gpio::InterruptPin<'a>,
+        alarm: &'a A,
+        spi_tx: &'static mut [u8],
+        spi_rx: &'static mut [u8],
+        address: MacAddress,
+    ) -> Enc28j60<'a, S, A> {
+        Enc28j60 {
+            spi,
+            int_pin,
+            alarm,
+            spi_tx: TakeCell::new(spi_tx),
+            spi_rx: TakeCell::new(spi_rx),
+            tx_client: OptionalCell::empty(),
+            rx_client: OptionalCell::empty(),
+            address: Cell::new(address),
+            filter: Cell::new(AddressFilter::default()),
+            enabled: Cell::new(false),
+            ready: Cell::new(false),
+            sequence: OptionalCell::empty(),
+            op_idx: Cell::new(0),
+            bank: Cell::new(0),
+            switching: OptionalCell::empty(),
+            read_value: Cell::new(0),
+            eir: Cell::new(0),
+            interrupt_pending: Cell::new(false),
+            config_pending: Cell::new(false),
+            tx_frame: TakeCell::empty(),
+            tx_len: Cell::new(0),
+            tx_in_progress: Cell::new(false),
+            next_packet: Cell::new(RX_START),
+            rx_len: Cell::new(0),
+        }
+    }
+
+    fn value(&self, value: Value) -> u8 {
+        let rx_free = match self.next_packet.get() {
+            RX_START => RX_END,
+            next => next - 1,
+        };
+        let tx_end = TX_START + self.tx_len.get() as u16;
+        match value {
+            Value::Const(value) => value,
+            Value::Address(i) => self.address.get()[i],
+            Value::Filter => {
+                let filter = self.filter.get();
+                if filter.promiscuous {
+                    bits::ERXFCON_CRCEN
+                } else {
+                    let mut value = bits::ERXFCON_UCEN | bits::ERXFCON_CRCEN;
+                    if filter.broadcast {
+                        value |= bits::ERXFCON_BCEN;
+                    }
+                    if filter.multicast {
+                        value |= bits::ERXFCON_MCEN;
+                    }
+                    value
+                }
+            }
+            Value::ReadPtrLow => self.next_packet.get() as u8,
+            Value::ReadPtrHigh => (self.next_packet.get() >> 8) as u8,
+            Value::TxEndLow => tx_end as u8,
+            Value::TxEndHigh => (tx_end >> 8) as u8,
+            Value::RxFreeLow => rx_free as u8,
+            Value::RxFreeHigh => (rx_free >> 8) as u8,
+        }
+    }
+
+    /// Start the next pending work, if the chip is idle.
+    fn idle(&self) {
+        if !self.ready.get() || self.sequence.is_some() {
+            return;
+        }
+        if self.interrupt_pending.take() || !self.int_pin.read() {
+            self.run(Sequence::Service);
+        } else if self.config_pending.take() {
+            self.run(Sequence::Configure);
+        } else if self.tx_frame.is_some() && !self.tx_in_progress.get() {
+            self.tx_in_progress.set(true);
+            self.run(Sequence::Transmit);
+        }
+    }
+
+    fn run(&self, sequence: Sequence) {
+        self.sequence.set(sequence);
+        self.op_idx.set(0);
+        self.next_op();
+    }
+
+    /// Start the current operation of the running sequence, or finish the
+    /// sequence.
+    fn next_op(&self) {
+        let sequence = match self.sequence.extract() {
+            Some(sequence) => sequence,
+            None => return,
+        };
+        let op = match sequence.ops().get(self.op_idx.get()) {
+            Some(op) => *op,
+            None => {
+                self.sequence.clear();
+                self.sequence_done(sequence);
+                return;
+            }
+        };
+
+        if let Some(bank) = op.bank().filter(|bank| *bank != self.bank.get()) {
+            // Clear the bank select bits, then set those of the bank
+            if self.bank.get() != 0 {
+                self.switching.set(0);
+                self.transfer(2, false, |buf| {
+                    buf[0] = opcode::BIT_CLEAR | reg::ECON1;
+                    buf[1] = bits::ECON1_BSEL;
+                });
+            } else {
+                self.switching.set(bank);
+                self.transfer(2, false, |buf| {
+                    buf[0] = opcode::BIT_SET | reg::ECON1;
+                    buf[1] = bank;
+                });
+            }
+            return;
+        }
+
+        match op {
+            Op::SoftReset => self.transfer(1, false, |buf| buf[0] = opcode::SOFT_RESET),
+            Op::Write(reg, value) => {
+                let value = self.value(value);
+                self.transfer(2, false, |buf| {
+                    buf[0] = opcode::WRITE_CONTROL | (reg & reg::ADDR_MASK);
+                    buf[1] = value;
+                })
+            }
+            Op::Set(reg, mask) => self.transfer(2, false, |buf| {
+                buf[0] = opcode::BIT_SET | (reg & reg::ADDR_MASK);
+                buf[1] = mask;
+            }),
+            Op::Clear(reg, mask) => self.transfer(2, false, |buf| {
+                buf[0] = opcode::BIT_CLEAR | (reg & reg::ADDR_MASK);
+                buf[1] = mask;
+            }),
+            Op::Read(_) | Op::WaitMii => {
+                let reg = match op {
+                    Op::Read(reg) => reg,
+                    _ => reg::MISTAT,
+                };
+                // MAC and MII registers are read after a dummy byte
+                let len = if reg & reg::MAC_MII != 0 { 3 } else { 2 };
+                self.transfer(len, true, |buf| {
+                    buf[0] = opcode::READ_CONTROL | (reg & reg::ADDR_MASK);
+                })
+            }
+            Op::WriteFrame => {
+                let len = self.tx_len.get();
+                self.transfer(len + 2, false, |buf| {
+                    buf[0] = opcode::WRITE_BUFFER;
+                    // Send with the settings of MACON3
+                    buf[1] = 0;
+                    self.tx_frame.map(|frame| {
+                        buf[2..len + 2].copy_from_slice(&frame[..len]);
+                    });
+                })
+            }
+            Op::ReadHeader => self.transfer(7, true, |buf| buf[0] = opcode::READ_BUFFER),
+            Op::ReadFrame => {
+                let len = self.rx_len.get();
+                if len == 0 {
+                    // The frame is dropped
+                    self.op_idx.set(self.op_idx.get() + 1);
+                    self.next_op();
+                } else {
+                    self.transfer(len + 1, true, |buf| buf[0] = opcode::READ_BUFFER)
+                }
+            }
+        }
+    }
+
+    /// Start an SPI transaction of `len` bytes, whose first bytes are set
+    /// by `fill`. The bytes read are kept if `read` is set.
+    fn transfer<F: FnOnce(&mut [u8])>(&self, len: usize, read: bool, fill: F) {
+        let buf = match self.spi_tx.take() {
+            Some(buf) => buf,
+            None => return self.abort(),
+        };
+        fill(buf);
+        let read_buf = if read { self.spi_rx.take() } else { None };
+        if let Err((_, buf, read_buf)) = self.spi.read_write_bytes(buf, read_buf, len) {
+            self.spi_tx.replace(buf);
+            if let Some(read_buf) = read_buf {
+                self.spi_rx.replace(read_buf);
+            }
+            self.abort();
+        }
+    }
+
+    /// Handle the bytes read by the current operation, and continue the
+    /// sequence.
+    fn op_done(&self) {
+        let op = match self
+            .sequence
+            .extract()
+            .and_then(|sequence| sequence.ops().get(self.op_idx.get()))
+        {
+            Some(op) => *op,
+            None => return,
+        };
+        match op {
+            Op::SoftReset => {
+                self.bank.set(0);
+                self.op_idx.set(self.op_idx.get() + 1);
+                self.alarm
+                    .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(RESET_DELAY_MS));
+                return;
+            }
+            Op::Read(reg) => {
+                let offset = if reg & reg::MAC_MII != 0 { 2 } else { 1 };
+                self.spi_rx.map(|buf| self.read_value.set(buf[offset]));
+            }
+            Op::WaitMii => {
+                if self
+                    .spi_rx
+                    .map_or(false, |buf| buf[2] & bits::MISTAT_BUSY != 0)
+                {
+                    // Read MISTAT again
+                    return self.next_op();
+                }
+            }
+            Op::ReadHeader => self.spi_rx.map_or((), |buf| {
+                let next = u16::from_le_bytes([buf[1], buf[2]]);
+                let count = u16::from_le_bytes([buf[3], buf[4]]) as usize;
+                let received_ok = buf[5] & bits::RSV_RXOK != 0;
+                self.next_packet.set(next);
+                let valid = received_ok
+                    && (ethernet::HEADER_LEN + FCS_LEN..=ethernet::MAX_FRAME_LEN + FCS_LEN)
+                        .contains(&count);
+                self.rx_len.set(if valid { count - FCS_LEN } else { 0 });
+            }),
+            Op::ReadFrame => {
+                let len = self.rx_len.get();
+                self.spi_rx.map(|buf| {
+                    self.rx_client
+                        .map(|client| client.received_frame(&buf[1..len + 1]));
+                });
+            }
+            _ => {}
+        }
+        self.op_idx.set(self.op_idx.get() + 1);
+        self.next_op();
+    }
+
+    fn sequence_done(&self, sequence: Sequence) {
+        match sequence {
+            Sequence::Init => {
+                self.ready.set(true);
+                self.idle();
+            }
+            Sequence::Configure | Sequence::Transmit | Sequence::ClearRxError => self.idle(),
+            Sequence::Service => {
+                let eir = self.read_value.get();
+                self.eir.set(eir);
+                if eir & (bits::EIR_TXIF | bits::EIR_TXERIF) != 0 {
+                    self.run(Sequence::AcknowledgeTx);
+                } else {
+                    self.run(Sequence::PollRx);
+                }
+            }
+            Sequence::AcknowledgeTx => {
+                let result = if self.eir.get() & bits::EIR_TXERIF != 0 {
+                    Err(ErrorCode::FAIL)
+                } else {
+                    Ok(())
+                };
+                let frame = if self.tx_in_progress.take() {
+                    self.tx_frame.take()
+                } else {
+                    None
+                };
+                // Continue before the callback, so that frames the client
+                // sends from it are queued
+                self.run(Sequence::PollRx);
+                if let Some(frame) = frame {
+                    self.tx_client
+                        .map(|client| client.transmit_done(frame, result));
+                }
+            }
+            Sequence::PollRx => {
+                if self.read_value.get() > 0 {
+                    self.run(Sequence::Receive);
+                } else if self.eir.get() & bits::EIR_RXERIF != 0 {
+                    self.run(Sequence::ClearRxError);
+                } else {
+                    self.idle();
+                }
+            }
+            // More frames can have arrived, or the transmission completed
+            Sequence::Receive => self.run(Sequence::Service),
+        }
+    }
+
+    /// Stop the running sequence after an SPI error.
+    fn abort(&self) {
+        let sequence = self.sequence.take();
+        debug!("ENC28J60: SPI transaction failed in {:?}", sequence);
+        self.switching.clear();
+        self.bank.set(UNKNOWN_BANK);
+        match sequence {
+            Some(Sequence::Init) => self.enabled.set(false),
+            Some(Sequence::Transmit) => {
+                self.tx_in_progress.set(false);
+                if let Some(frame) = self.tx_frame.take() {
+                    self.tx_client
+                        .map(|client| client.transmit_done(frame, Err(ErrorCode::FAIL)));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<'a, S: SpiMasterDevice, A: Alarm<'a>> EthernetAdapter<'a> for Enc28j60<'a, S, A> {
+    fn set_transmit_client(&self, client: &'a dyn TransmitClient) {
+        self.tx_client.set(client);
+    }
+
+    fn set_receive_client(&self, client: &'a dyn ReceiveClient) {
+        self.rx_client.set(client);
+    }
+
+    fn enable(&self) -> Result<(), ErrorCode> {
+        if self.enabled.get() {
+            return Err(ErrorCode::ALREADY);
+        }
+        if self.sequence.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.spi.configure(
+            spi::ClockPolarity::IdleLow,
+            spi::ClockPhase::SampleLeading,
+            SPI_SPEED,
+        )?;
+        self.int_pin.make_input();
+        self.int_pin
+            .enable_interrupts(gpio::InterruptEdge::FallingEdge);
+        self.enabled.set(true);
+        self.run(Sequence::Init);
+        Ok(())
+    }
+
+    fn get_address(&self) -> MacAddress {
+        self.address.get()
+    }
+
+    fn set_address(&self, address: MacAddress) -> Result<(), ErrorCode> {
+        self.address.set(address);
+        self.config_pending.set(self.ready.get());
+        self.idle();
+        Ok(())
+    }
+
+    fn get_filter(&self) -> AddressFilter {
+        self.filter.get()
+    }
+
+    fn set_filter(&self, filter: AddressFilter) -> Result<(), ErrorCode> {
+        self.filter.set(filter);
+        self.config_pending.set(self.ready.get());
+        self.idle();
+        Ok(())
+    }
+
+    fn transmit(
+        &self,
+        frame: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if !self.enabled.get() {
+            return Err((ErrorCode::OFF, frame));
+        }
+        if self.tx_frame.is_some() {
+            return Err((ErrorCode::BUSY, frame));
+        }
+        if !(ethernet::HEADER_LEN..=ethernet::MAX_FRAME_LEN).contains(&len) || len > frame.len() {
+            return Err((ErrorCode::SIZE, frame));
+        }
+        self.tx_frame.replace(frame);
+        self.tx_len.set(len);
+        self.idle();
+        Ok(())
+    }
+}
+
+impl<'a, S: SpiMasterDevice, A: Alarm<'a>> spi::SpiMasterClient for Enc28j60<'a, S, A> {
+    fn read_write_done(
+        &self,
+        write_buffer: &'static mut [u8],
+        read_buffer: Option<&'static mut [u8]>,
+        _len: usize,
+        status: Result<(), ErrorCode>,
+    ) {
+        self.spi_tx.replace(write_buffer);
+        if let Some(read_buffer) = read_buffer {
+            self.spi_rx.replace(read_buffer);
+        }
+        if status.is_err() {
+            return self.abort();
+        }
+        match self.switching.take() {
+            Some(bank) => {
+                self.bank.set(bank);
+                self.next_op();
+            }
+            None => self.op_done(),
+        }
+    }
+}
+
+impl<'a, S: SpiMasterDevice, A: Alarm<'a>> time::AlarmClient for Enc28j60<'a, S, A> {
+    fn alarm(&self) {
+        // The chip is out of reset
+        self.next_op();
+    }
+}
+
+impl<'a, S: SpiMasterDevice, A: Alarm<'a>> gpio::Client for Enc28j60<'a, S, A> {
+    fn fired(&self) {
+        self.interrupt_pending.set(true);
+        self.idle();
+    }
+}
@@ -0,0 +1,664 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Wi-Fi station with an ESP32 module running the ESP-AT firmware of
+//! Espressif, attached to a UART.
+//!
+//! The module runs the network stack. `Esp32At` implements
+//! `hil::wifi::Station` by sending it AT commands, and passes the data of
+//! sockets through with `AT+CIPSEND` and `+IPD`. The module is used as a
+//! station with multiple connections, one per socket, which `initialize`
+//! sets up; until then, and after the module restarts until it is set up
+//! again, operations return OFF.
+//!
+//! The UART is a `UartDevice` of a mux, set up with the baud rate of the
+//! module (115200 by default), and the module should be the only one sending
+//! on it.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let wifi = components::esp32_at::Esp32AtComponent::new(
+//!     board_kernel,
+//!     capsules_extra::wifi::DRIVER_NUM,
+//!     esp32_uart_mux,
+//! )
+//! .finalize(components::esp32_at_component_static!());
+//! ```
+
+use core::cell::Cell;
+use core::cmp::min;
+
+use kernel::hil::uart;
+use kernel::hil::wifi::{self, Network, Protocol, Security, MAX_PASSPHRASE_LEN, MAX_SSID_LEN};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// The length of the buffer AT commands are written into. It holds a join
+/// command with the longest SSID and passphrase, even if all their
+/// characters are escaped.
+pub const TX_BUF_LEN: usize = 256;
+
+/// The length of the buffer received socket data is passed in.
+pub const RX_BUF_LEN: usize = 256;
+
+/// The length of the buffer received lines are collected in. Longer lines
+/// are cut.
+pub const LINE_BUF_LEN: usize = 128;
+
+/// The module supports 5 connections at once.
+const NUM_SOCKETS: usize = 5;
+
+/// The longest data `AT+CIPSEND` sends at once.
+const MAX_SEND_LEN: usize = 2048;
+
+/// The commands that set the module up: no echo of commands, station mode
+/// and multiple connections.
+const INIT_COMMANDS: [&[u8]; 3] = [b"ATE0", b"AT+CWMODE=1", b"AT+CIPMUX=1"];
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Operation {
+    None,
+    /// Setting the module up with this entry of `INIT_COMMANDS`.
+    Init(usize),
+    Scan,
+    Join,
+    Leave,
+    Open(usize),
+    /// Waiting for the prompt to send data on a socket.
+    SendPrompt(usize),
+    /// Sending data on a socket, until the module confirms it.
+    SendData(usize),
+    Close(usize),
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum RxState {
+    /// Receiving a line.
+    Line,
+    /// Receiving data of a socket, of which this many bytes remain.
+    Data(usize, usize),
+}
+
+/// Writes an AT command into a buffer.
+struct CommandWriter {
+    buf: &'static mut [u8],
+    len: usize,
+}
+
+impl CommandWriter {
+    fn bytes(&mut self, bytes: &[u8]) -> Result<(), ErrorCode> {
+        let end = self.len + bytes.len();
+        if end > self.buf.len() {
+            return Err(ErrorCode::SIZE);
+        }
+        self.buf[self.len..end].copy_from_slice(bytes);
+        self.len = end;
+        Ok(())
+    }
+
+    fn decimal(&mut self, value: usize) -> Result<(), ErrorCode> {
+        let mut digits = [0; 20];
+        let mut start = digits.len();
+        let mut value = value;
+        loop {
+            start -= 1;
+            digits[start] = b'0' + (value % 10) as u8;
+            value /= 10;
+            if value == 0 {
+                break;
+            }
+        }
+        self.bytes(&digits[start..])
+    }
+
+    /// A string parameter in quotes, with the characters that end it escaped.
+    fn quoted(&mut self, string: &[u8]) -> Result<(), ErrorCode> {
+        self.bytes(b"\"")?;
+        for byte in string {
+            if matches!(byte, b'"' | b',' | b'\\') {
+                self.bytes(b"\\")?;
+            }
+            self.bytes(&[*byte])?;
+        }
+        self.bytes(b"\"")
+    }
+}
+
+/// Parse a decimal number, with an optional sign.
+fn parse_decimal(bytes: &[u8]) -> Option<i32> {
+    let (negative, digits) = match bytes.strip_prefix(b"-") {
+        Some(digits) => (true, digits),
+        None => (false, bytes),
+    };
+    if digits.is_empty() || digits.len() > 9 {
+        return None;
+    }
+    let mut value: i32 = 0;
+    for digit in digits {
+        if !digit.is_ascii_digit() {
+            return None;
+        }
+        value = value * 10 + (digit - b'0') as i32;
+    }
+    Some(if negative { -value } else { value })
+}
+
+/// Split `bytes` at the first `separator`, which is dropped.
+fn split_once(bytes: &[u8], separator: u8) -> Option<(&[u8], &[u8])> {
+    let position = bytes.iter().position(|byte| *byte == separator)?;
+    Some((&bytes[..position], &bytes[position + 1..]))
+}
+
+/// The socket of a `<socket>,CONNECT` or `<socket>,CLOSED` line.
+fn parse_socket_event(line: &[u8], event: &[u8]) -> Option<usize> {
+    let (socket, rest) = split_once(line, b',')?;
+    if rest != event {
+        return None;
+    }
+    let socket = parse_decimal(socket)? as usize;
+    if socket < NUM_SOCKETS {
+        Some(socket)
+    } else {
+        None
+    }
+}
+
+/// The socket and length of the data of `+IPD,<socket>,<len>`, which is
+/// followed by a `:` and the data.
+fn parse_data_header(header: &[u8]) -> Option<(usize, usize)> {
+    let (socket, len) = split_once(header.strip_prefix(b"+IPD,")?, b',')?;
+    let socket = parse_decimal(socket)?;
+    let len = parse_decimal(len)?;
+    if socket < 0 || socket as usize >= NUM_SOCKETS || len <= 0 {
+        return None;
+    }
+    Some((socket as usize, len as usize))
+}
+
+fn security(ecn: i32) -> Security {
+    match ecn {
+        0 => Security::Open,
+        1 => Security::Wep,
+        2 => Security::WpaPsk,
+        3 => Security::Wpa2Psk,
+        4 => Security::WpaWpa2Psk,
+        5 => Security::Wpa2Enterprise,
+        6 => Security::Wpa3Psk,
+        7 => Security::Wpa2Wpa3Psk,
+        _ => Security::Unknown,
+    }
+}
+
+/// Parse a network found by `AT+CWLAP`:
+/// `+CWLAP:(<ecn>,"<ssid>",<rssi>,"<mac>",<channel>,...)`.
+fn parse_network(line: &[u8]) -> Option<Network> {
+    let (ecn, rest) = split_once(line.strip_prefix(b"+CWLAP:(")?, b',')?;
+    let rest = rest.strip_prefix(b"\"")?;
+    // The SSID is not escaped, so it ends at the quote that is followed by
+    // the RSSI.
+    let ssid_len = rest.windows(3).position(|end| {
+        end[0] == b'"' && end[1] == b',' && (end[2] == b'-' || end[2].is_ascii_digit())
+    })?;
+    if ssid_len > MAX_SSID_LEN {
+        return None;
+    }
+    let ssid = &rest[..ssid_len];
+    let (rssi, rest) = split_once(&rest[ssid_len + 2..], b',')?;
+    let (_mac, rest) = split_once(rest.strip_prefix(b"\"")?, b'"')?;
+    let rest = rest.strip_prefix(b",")?;
+    let channel_len = rest
+        .iter()
+        .position(|byte| *byte == b',' || *byte == b')')
+        .unwrap_or(rest.len());
+
+    let mut network = Network {
+        ssid: [0; MAX_SSID_LEN],
+        ssid_len,
+        rssi: parse_decimal(rssi)?.clamp(i8::MIN as i32, 0) as i8,
+        channel: u8::try_from(parse_decimal(&rest[..channel_len])?).ok()?,
+        security: security(parse_decimal(ecn)?),
+    };
+    network.ssid[..ssid_len].copy_from_slice(ssid);
+    Some(network)
+}
+
+pub struct Esp32At<'a> {
+    uart: &'a dyn uart::UartData<'a>,
+    scan_client: OptionalCell<&'a dyn wifi::ScanClient>,
+    station_client: OptionalCell<&'a dyn wifi::StationClient>,
+    socket_client: OptionalCell<&'a dyn wifi::SocketClient>,
+    operation: Cell<Operation>,
+    /// Whether the module is set up.
+    ready: Cell<bool>,
+    joined: Cell<bool>,
+    /// The open sockets, one bit each.
+    open_sockets: Cell<u8>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    /// The buffer passed to `send`, while it waits for the prompt.
+    send_buffer: TakeCell<'static, [u8]>,
+    send_len: Cell<usize>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    rx_state: Cell<RxState>,
+    line_buffer: TakeCell<'static, [u8]>,
+    line_len: Cell<usize>,
+}
+
+impl<'a> Esp32At<'a> {
+    /// `tx_buffer`, `rx_buffer` and `line_buffer` should be `TX_BUF_LEN`,
+    /// `RX_BUF_LEN` and `LINE_BUF_LEN` bytes long.
+    pub fn new(
+        uart: &'a dyn uart::UartData<'a>,
+        tx_buffer: &'static mut [u8],
+        rx_buffer: &'static mut [u8],
+        line_buffer: &'static mut [u8],
+    ) -> Esp32At<'a> {
+        Esp32At {
+            uart,
+            scan_client: OptionalCell::empty(),
+            station_client: OptionalCell::empty(),
+            socket_client: OptionalCell::empty(),
+            operation: Cell::new(Operation::None),
+            ready: Cell::new(false),
+            joined: Cell::new(false),
+            open_sockets: Cell::new(0),
+            tx_buffer: TakeCell::new(tx_buffer),
+            send_buffer: TakeCell::empty(),
+            send_len: Cell::new(0),
+            rx_buffer: TakeCell::new(rx_buffer),
+            rx_state: Cell::new(RxState::Line),
+            line_buffer: TakeCell::new(line_buffer),
+            line_len: Cell::new(0),
+        }
+    }
+
+    /// Start receiving from the module and set it up.
+    pub fn initialize(&self) {
+        if let Some(buffer) = self.rx_buffer.take() {
+            self.rx_state.set(RxState::Line);
+            self.receive(buffer, 1);
+        }
+        let _ = self.command(Operation::Init(0), |command| {
+            command.bytes(INIT_COMMANDS[0])
+        });
+    }
+
+    fn receive(&self, buffer: &'static mut [u8], len: usize) {
+        if let Err((_, buffer)) = self.uart.receive_buffer(buffer, len) {
+            self.rx_buffer.replace(buffer);
+        }
+    }
+
+    /// Send the AT command written by `write` and wait for its result as
+    /// `operation`.
+    fn command<F>(&self, operation: Operation, write: F) -> Result<(), ErrorCode>
+    where
+        F: FnOnce(&mut CommandWriter) -> Result<(), ErrorCode>,
+    {
+        if self.operation.get() != Operation::None {
+            return Err(ErrorCode::BUSY);
+        }
+        let buf = self.tx_buffer.take().ok_or(ErrorCode::BUSY)?;
+        let mut command = CommandWriter { buf, len: 0 };
+        let result = write(&mut command).and_then(|()| command.bytes(b"\r\n"));
+        let CommandWriter { buf, len } = command;
+        if let Err(err) = result {
+            self.tx_buffer.replace(buf);
+            return Err(err);
+        }
+        match self.uart.transmit_buffer(buf, len) {
+            Ok(()) => {
+                self.operation.set(operation);
+                Ok(())
+            }
+            Err((err, buf)) => {
+                self.tx_buffer.replace(buf);
+                Err(err)
+            }
+        }
+    }
+
+    fn is_open(&self, socket: usize) -> bool {
+        socket < NUM_SOCKETS && self.open_sockets.get() & (1 << socket) != 0
+    }
+
+    fn set_open(&self, socket: usize, open: bool) {
+        let sockets = self.open_sockets.get() & !(1 << socket);
+        self.open_sockets.set(sockets | ((open as u8) << socket));
+    }
+
+    /// The station is not in a network anymore.
+    fn lost_network(&self) {
+        if self.joined.get() {
+            self.joined.set(false);
+            self.open_sockets.set(0);
+            self.station_client.map(|client| client.disconnected());
+        }
+    }
+
+    /// `socket` was closed.
+    fn socket_closed(&self, socket: usize) {
+        if self.is_open(socket) {
+            self.set_open(socket, false);
+            self.socket_client.map(|client| client.closed(socket));
+        }
+    }
+
+    /// The module sent the prompt for the data of `AT+CIPSEND`.
+    fn send_data(&self, socket: usize) {
+        if let Some(buf) = self.send_buffer.take() {
+            self.operation.set(Operation::SendData(socket));
+            if let Err((err, buf)) = self.uart.transmit_buffer(buf, self.send_len.get()) {
+                self.operation.set(Operation::None);
+                self.socket_client
+                    .map(|client| client.send_done(socket, buf, Err(err)));
+            }
+        }
+    }
+
+    /// The module sent the final result of the pending operation.
+    fn finish(&self, result: Result<(), ErrorCode>) {
+        match self.operation.replace(Operation::None) {
+            Operation::None => {}
+            Operation::Init(step) => {
+                if result.is_ok() && step + 1 < INIT_COMMANDS.len() {
+                    let _ = self.command(Operation::Init(step + 1), |command| {
+                        command.bytes(INIT_COMMANDS[step + 1])
+                    });
+                } else {
+                    self.ready.set(result.is_ok());
+                }
+            }
+            Operation::Scan => {
+                self.scan_client.map(|client| client.scan_done(result));
+            }
+            Operation::Join => {
+                self.joined.set(result.is_ok());
+                self.station_client.map(|client| client.join_done(result));
+            }
+            Operation::Leave => {
+                if result.is_ok() {
+                    self.lost_network();
+                }
+            }
+            Operation::Open(socket) => {
+                if result.is_ok() {
+                    self.set_open(socket, true);
+                }
+                self.socket_client
+                    .map(|client| client.opened(socket, result));
+            }
+            Operation::SendPrompt(socket) | Operation::SendData(socket) => {
+                if let Some(buf) = self.send_buffer.take() {
+                    self.socket_client
+                        .map(|client| client.send_done(socket, buf, result));
+                }
+            }
+            Operation::Close(socket) => {
+                // The socket is closed already if the module reported it.
+                if result.is_ok() {
+                    self.socket_closed(socket);
+                }
+            }
+        }
+    }
+
+    /// Handle a line the module sent, without its line ending.
+    fn line(&self, line: &[u8]) {
+        let operation = self.operation.get();
+        match line {
+            b"" => {}
+            b"OK" => match operation {
+                // The result comes after the data
+                Operation::SendPrompt(_) | Operation::SendData(_) => {}
+                _ => self.finish(Ok(())),
+            },
+            b"SEND OK" => self.finish(Ok(())),
+            b"ERROR" | b"FAIL" | b"SEND FAIL" => self.finish(Err(ErrorCode::FAIL)),
+            b"WIFI DISCONNECT" => self.lost_network(),
+            b"ready" => {
+                // The module restarted, which resets its setup.
+                self.ready.set(false);
+                self.finish(Err(ErrorCode::FAIL));
+                self.lost_network();
+                let _ = self.command(Operation::Init(0), |command| {
+                    command.bytes(INIT_COMMANDS[0])
+                });
+            }
+            _ if line.starts_with(b"+CWLAP:") => {
+                if operation == Operation::Scan {
+                    if let Some(network) = parse_network(line) {
+                        self.scan_client.map(|client| client.network_found(network));
+                    }
+                }
+            }
+            _ => {
+                if let Some(socket) = parse_socket_event(line, b"CLOSED") {
+                    self.socket_closed(socket);
+                }
+                // Other lines, like `WIFI GOT IP` while joining, `<socket>,CONNECT`
+                // before the result of opening it, or `busy p...`, are
+                // followed by a result.
+            }
+        }
+    }
+
+    /// Handle a byte the module sent between socket data.
+    fn received_byte(&self, byte: u8) {
+        let len = self.line_len.get();
+        if byte == b'\n' {
+            self.line_len.set(0);
+            // Keep the line in a copy, since clients may send commands.
+            let mut line = [0; LINE_BUF_LEN];
+            let line_len = self.line_buffer.map_or(0, |buffer| {
+                let len = min(len, min(buffer.len(), LINE_BUF_LEN));
+                line[..len].copy_from_slice(&buffer[..len]);
+                len
+            });
+            let line = &line[..line_len];
+            self.line(line.strip_suffix(b"\r").unwrap_or(line));
+            return;
+        }
+        if byte == b'>' && len == 0 {
+            if let Operation::SendPrompt(socket) = self.operation.get() {
+                self.send_data(socket);
+                return;
+            }
+        }
+        if byte == b':' {
+            let data = self
+                .line_buffer
+                .map_or(None, |buffer| parse_data_header(&buffer[..len]));
+            if let Some((socket, data_len)) = data {
+                self.line_len.set(0);
+                self.rx_state.set(RxState::Data(socket, data_len));
+                return;
+            }
+        }
+        self.line_buffer.map(|buffer| {
+            if len < buffer.len() {
+                buffer[len] = byte;
+                self.line_len.set(len + 1);
+            }
+        });
+    }
+}
+
+impl<'a> wifi::Station<'a> for Esp32At<'a> {
+    fn set_scan_client(&self, client: &'a dyn wifi::ScanClient) {
+        self.scan_client.set(client);
+    }
+
+    fn set_station_client(&self, client: &'a dyn wifi::StationClient) {
+        self.station_client.set(client);
+    }
+
+    fn set_socket_client(&self, client: &'a dyn wifi::SocketClient) {
+        self.socket_client.set(client);
+    }
+
+    fn scan(&self) -> Result<(), ErrorCode> {
+        if !self.ready.get() {
+            return Err(ErrorCode::OFF);
+        }
+        self.command(Operation::Scan, |command| command.bytes(b"AT+CWLAP"))
+    }
+
+    fn join(&self, ssid: &[u8], passphrase: &[u8]) -> Result<(), ErrorCode> {
+        if !self.ready.get() {
+            return Err(ErrorCode::OFF);
+        }
+        if ssid.is_empty() || ssid.len() > MAX_SSID_LEN || passphrase.len() > MAX_PASSPHRASE_LEN {
+            return Err(ErrorCode::SIZE);
+        }
+        self.command(Operation::Join, |command| {
+            command.bytes(b"AT+CWJAP=")?;
+            command.quoted(ssid)?;
+            command.bytes(b",")?;
+            command.quoted(passphrase)
+        })
+    }
+
+    fn leave(&self) -> Result<(), ErrorCode> {
+        if !self.joined.get() {
+            return Err(ErrorCode::OFF);
+        }
+        self.command(Operation::Leave, |command| command.bytes(b"AT+CWQAP"))
+    }
+
+    fn is_joined(&self) -> bool {
+        self.joined.get()
+    }
+
+    fn num_sockets(&self) -> usize {
+        NUM_SOCKETS
+    }
+
+    fn open(
+        &self,
+        socket: usize,
+        protocol: Protocol,
+        address: [u8; 4],
+        port: u16,
+    ) -> Result<(), ErrorCode> {
+        if !self.joined.get() {
+            return Err(ErrorCode::OFF);
+        }
+        if socket >= NUM_SOCKETS {
+            return Err(ErrorCode::INVAL);
+        }
+        if self.is_open(socket) {
+            return Err(ErrorCode::ALREADY);
+        }
+        self.command(Operation::Open(socket), |command| {
+            command.bytes(b"AT+CIPSTART=")?;
+            command.decimal(socket)?;
+            command.bytes(match protocol {
+                Protocol::Tcp => b",\"TCP\",\"",
+                Protocol::Udp => b",\"UDP\",\"",
+            })?;
+            for (i, byte) in address.iter().enumerate() {
+                if i > 0 {
+                    command.bytes(b".")?;
+                }
+                command.decimal(*byte as usize)?;
+            }
+            command.bytes(b"\",")?;
+            command.decimal(port as usize)
+        })
+    }
+
+    fn send(
+        &self,
+        socket: usize,
+        buf: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if !self.is_open(socket) {
+            return Err((ErrorCode::INVAL, buf));
+        }
+        if len == 0 || len > buf.len() || len > MAX_SEND_LEN {
+            return Err((ErrorCode::SIZE, buf));
+        }
+        let result = self.command(Operation::SendPrompt(socket), |command| {
+            command.bytes(b"AT+CIPSEND=")?;
+            command.decimal(socket)?;
+            command.bytes(b",")?;
+            command.decimal(len)
+        });
+        match result {
+            Ok(()) => {
+                // The prompt comes after the command is sent
+                self.send_buffer.replace(buf);
+                self.send_len.set(len);
+                Ok(())
+            }
+            Err(err) => Err((err, buf)),
+        }
+    }
+
+    fn close(&self, socket: usize) -> Result<(), ErrorCode> {
+        if !self.is_open(socket) {
+            return Err(ErrorCode::INVAL);
+        }
+        self.command(Operation::Close(socket), |command| {
+            command.bytes(b"AT+CIPCLOSE=")?;
+            command.decimal(socket)
+        })
+    }
+}
+
+impl uart::TransmitClient for Esp32At<'_> {
+    fn transmitted_buffer(
+        &self,
+        buffer: &'static mut [u8],
+        _tx_len: usize,
+        _rcode: Result<(), ErrorCode>,
+    ) {
+        // Data is only sent after the command that announced it, and the
+        // result of sending it comes from the module.
+        if self.tx_buffer.is_none() {
+            self.tx_buffer.replace(buffer);
+        } else {
+            self.send_buffer.replace(buffer);
+        }
+    }
+
+    fn transmitted_word(&self, _rcode: Result<(), ErrorCode>) {}
+}
+
+impl uart::ReceiveClient for Esp32At<'_> {
+    fn received_buffer(
+        &self,
+        buffer: &'static mut [u8],
+        rx_len: usize,
+        _rcode: Result<(), ErrorCode>,
+        _error: uart::Error,
+    ) {
+        match self.rx_state.get() {
+            RxState::Line => {
+                if rx_len > 0 {
+                    self.received_byte(buffer[0]);
+                }
+            }
+            RxState::Data(socket, remaining) => {
+                let remaining = remaining - min(rx_len, remaining);
+                self.socket_client
+                    .map(|client| client.received(socket, &buffer[..rx_len]));
+                if remaining == 0 {
+                    self.rx_state.set(RxState::Line);
+                } else {
+                    self.rx_state.set(RxState::Data(socket, remaining));
+                }
+            }
+        }
+        let next_len = match self.rx_state.get() {
+            RxState::Line => 1,
+            RxState::Data(_, remaining) => min(remaining, buffer.len()),
+        };
+        self.receive(buffer, next_len);
+    }
+
+    fn received_word(&self, _word: u32, _rcode: Result<(), ErrorCode>, _err: uart::Error) {}
+}
@@ -0,0 +1,637 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! System call driver to update the kernel and applications in place.
+//!
+//! Boards with A/B firmware banks run from the active bank while this driver
+//! writes a new image, kernel and applications, to the inactive one. A
+//! process receives the image however the board allows, e.g. over UDP, USB or
+//! UART, and streams it to this driver in order. The driver computes the
+//! SHA-256 digest of the image as it is written, and once it is complete
+//! checks the image's ECDSA P-256 signature over the digest. Only a signed
+//! image is handed to the bootloader, by setting the flags record to swap the
+//! banks on the next boot. The process then reboots the board, e.g. with the
+//! reset driver.
+//!
+//! Rollback is left to the bootloader. After swapping the banks it marks the
+//! new image as on trial and counts the boots it makes. Once the new image
+//! works, a process confirms it with this driver. If the image doesn't get
+//! confirmed within the bootloader's limit of boots, e.g. because the kernel
+//! crashes, the bootloader swaps the banks back.
+//!
+//! Updating the firmware affects the whole system, so only the processes
+//! whose fixed `ShortID` the board lists may use this driver. Other processes
+//! get `NOSUPPORT` for every command but the existence check.
+//!
+//! `storage` must write to the inactive bank and the flags record using
+//! absolute addresses, e.g. the chip's flash controller wrapped in
+//! `capsules_extra::nonvolatile_to_pages::NonvolatileToPages`.
+//!
+//! Flags Record
+//! ------------
+//!
+//! The record shared with the bootloader is [`flags::LEN`] bytes of little
+//! endian `u32`s: [`flags::MAGIC`], the state, the length of the image in the
+//! inactive bank and the number of boots the image on trial made. The driver
+//! writes the record with a boot count of 0. The states are:
+//!
+//! - [`flags::NONE`]: No update is pending. Written when an update starts,
+//!   so a partially written image is never swapped in.
+//! - [`flags::PENDING`]: Swap the banks on the next boot.
+//! - [`flags::TRIAL`]: Written by the bootloader after swapping the banks.
+//! - [`flags::CONFIRMED`]: The running image works, keep it.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let firmware_update = static_init!(
+//!     capsules_extra::firmware_update::FirmwareUpdate<'static, ProcessMgmtCap>,
+//!     capsules_extra::firmware_update::FirmwareUpdate::new(
+//!         board_kernel,
+//!         nv_to_page,
+//!         sha,
+//!         ecdsa,
+//!         board_kernel.create_grant(capsules_extra::firmware_update::DRIVER_NUM, &grant_cap),
+//!         static_init!([u8; 512], [0; 512]),
+//!         static_init!([u8; 32], [0; 32]),
+//!         static_init!([u8; 64], [0; 64]),
+//!         0x80000,
+//!         0x78000,
+//!         0xff000,
+//!         static_init!(
+//!             [ShortID; 1],
+//!             [ShortID::Fixed(NonZeroU32::new(0x2f7d).unwrap())]
+//!         ),
+//!         ProcessMgmtCap
+//!     )
+//! );
+//! hil::nonvolatile_storage::NonvolatileStorage::set_client(nv_to_page, firmware_update);
+//! hil::digest::DigestData::set_data_client(sha, firmware_update);
+//! hil::digest::DigestHash::set_hash_client(sha, firmware_update);
+//! hil::public_key_crypto::signature::SignatureVerify::set_verify_client(ecdsa, firmware_update);
+//! ```
+//!
+//! Command Interface
+//! -----------------
+//!
+//! - `0`: Driver existence check.
+//! - `1`: Start an update with an image of `data1` bytes. Returns `BUSY` if
+//!   another update is in progress and `SIZE` if the image doesn't fit the
+//!   inactive bank. Upcall `1` is called with the status once the flags
+//!   record is cleared.
+//! - `2`: Write `data2` bytes of the read-only allow buffer `0` to offset
+//!   `data1` of the image. The image must be written in order, so `data1`
+//!   must be the number of bytes written so far. Upcall `0` is called with
+//!   the status and the number of bytes written.
+//! - `3`: Check the signature in the read-only allow buffer `1` against the
+//!   complete image and, if it is valid, set the flags record to swap the
+//!   banks. Upcall `1` is called with the status, which is `FAIL` if the
+//!   signature is invalid.
+//! - `4`: Abort the update.
+//! - `5`: Confirm the running image. Upcall `1` is called with the status and
+//!   the state of the flags record before the call, the record is only
+//!   written if it was [`flags::TRIAL`].
+//!
+//! A failed write or check ends the update, it has to be started again.
+
+use core::cell::Cell;
+use core::cmp;
+
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::digest::{ClientData, ClientHash, DigestDataHash};
+use kernel::hil::nonvolatile_storage::{NonvolatileStorage, NonvolatileStorageClient};
+use kernel::hil::public_key_crypto::signature::{ClientVerify, SignatureVerify};
+use kernel::process::ShortID;
+use kernel::processbuffer::ReadableProcessBuffer;
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::{LeasableBuffer, LeasableMutableBuffer};
+use kernel::{ErrorCode, Kernel, ProcessId};
+
+/// Syscall driver number.
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::FirmwareUpdate as usize;
+
+/// The length of the SHA-256 digest of an image.
+pub const HASH_LEN: usize = 32;
+
+/// The length of the ECDSA P-256 signature of an image, `r || s`.
+pub const SIGNATURE_LEN: usize = 64;
+
+/// The record telling the bootloader what to do at boot.
+pub mod flags {
+    /// Marks a valid record, anything else is treated as [`NONE`].
+    pub const MAGIC: u32 = 0x5550_4454;
+    /// The length of the record in bytes.
+    pub const LEN: usize = 16;
+
+    pub const NONE: u32 = 0;
+    pub const PENDING: u32 = 1;
+    pub const TRIAL: u32 = 2;
+    pub const CONFIRMED: u32 = 3;
+}
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    pub const IMAGE: usize = 0;
+    pub const SIGNATURE: usize = 1;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 2;
+}
+
+mod upcall {
+    pub const WRITE_DONE: usize = 0;
+    pub const DONE: usize = 1;
+    pub const COUNT: u8 = 2;
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    /// Writing the flags record to start an update.
+    Starting,
+    /// The image is being written.
+    Receiving,
+    Writing,
+    /// Adding the written bytes to the digest.
+    Hashing,
+    /// Computing the digest of the complete image.
+    Digesting,
+    Verifying,
+    /// Writing the flags record to swap the banks.
+    Swapping,
+    /// Reading the flags record to confirm the running image.
+    ReadingFlags,
+    Confirming,
+}
+
+pub struct FirmwareUpdate<'a, C: ProcessManagementCapability> {
+    kernel: &'static Kernel,
+    storage: &'a dyn NonvolatileStorage<'static>,
+    digest: &'a dyn DigestDataHash<'a, HASH_LEN>,
+    verifier: &'a dyn SignatureVerify<'a, HASH_LEN, SIGNATURE_LEN>,
+    apps: Grant<
+        (),
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<0>,
+    >,
+    buffer: TakeCell<'static, [u8]>,
+    hash: TakeCell<'static, [u8; HASH_LEN]>,
+    signature: TakeCell<'static, [u8; SIGNATURE_LEN]>,
+    /// Absolute address and length of the inactive bank.
+    bank_start: usize,
+    bank_length: usize,
+    /// Absolute address of the flags record.
+    flags_address: usize,
+    allowed: &'a [ShortID],
+    capability: C,
+    state: Cell<State>,
+    /// Process updating the firmware or confirming the running image.
+    owner: OptionalCell<ProcessId>,
+    image_length: Cell<usize>,
+    /// Bytes of the image written and added to the digest.
+    written: Cell<usize>,
+    /// Bytes of the write in progress.
+    chunk_length: Cell<usize>,
+    /// State of the flags record before a confirmation.
+    previous_flags: Cell<u32>,
+}
+
+impl<'a, C: ProcessManagementCapability> FirmwareUpdate<'a, C> {
+    /// Only the processes with a `ShortID` in `allowed` may update the
+    /// firmware. `buffer` limits how many bytes one write command can write
+    /// and must be at least `flags::LEN` long.
+    pub fn new(
+        kernel: &'static Kernel,
+        storage: &'a dyn NonvolatileStorage<'static>,
+        digest: &'a dyn DigestDataHash<'a, HASH_LEN>,
+        verifier: &'a dyn SignatureVerify<'a, HASH_LEN, SIGNATURE_LEN>,
+        grant: Grant<
+            (),
+            UpcallCount<{ upcall::COUNT }>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<0>,
+        >,
+        buffer: &'static mut [u8],
+        hash: &'static mut [u8; HASH_LEN],
+        signature: &'static mut [u8; SIGNATURE_LEN],
+        bank_start: usize,
+        bank_length: usize,
+        flags_address: usize,
+        allowed: &'a [ShortID],
+        capability: C,
+    ) -> FirmwareUpdate<'a, C> {
+        FirmwareUpdate {
+            kernel,
+            storage,
+            digest,
+            verifier,
+            apps: grant,
+            buffer: TakeCell::new(buffer),
+            hash: TakeCell::new(hash),
+            signature: TakeCell::new(signature),
+            bank_start,
+            bank_length,
+            flags_address,
+            allowed,
+            capability,
+            state: Cell::new(State::Idle),
+            owner: OptionalCell::empty(),
+            image_length: Cell::new(0),
+            written: Cell::new(0),
+            chunk_length: Cell::new(0),
+            previous_flags: Cell::new(flags::NONE),
+        }
+    }
+
+    fn is_allowed(&self, processid: ProcessId) -> bool {
+        self.kernel.process_map_or_external(
+            false,
+            processid,
+            |process| self.allowed.contains(&process.short_app_id()),
+            &self.capability,
+        )
+    }
+
+    /// Whether `processid` may continue the update in progress. An update
+    /// whose owner no longer exists is abandoned.
+    fn is_owner(&self, processid: ProcessId) -> bool {
+        if let Some(owner) = self.owner.extract() {
+            let exists =
+                self.kernel
+                    .process_map_or_external(false, owner, |_| true, &self.capability);
+            if !exists && self.state.get() == State::Receiving {
+                self.digest.clear_data();
+                self.owner.clear();
+                self.state.set(State::Idle);
+            }
+        }
+        self.owner.contains(&processid)
+    }
+
+    /// Write the flags record with `state`.
+    fn write_flags(&self, state: u32, image_length: usize) -> Result<(), ErrorCode> {
+        let buffer = self.buffer.take().ok_or(ErrorCode::BUSY)?;
+        if buffer.len() < flags::LEN {
+            self.buffer.replace(buffer);
+            return Err(ErrorCode::SIZE);
+        }
+        buffer[0..4].copy_from_slice(&flags::MAGIC.to_le_bytes());
+        buffer[4..8].copy_from_slice(&state.to_le_bytes());
+        buffer[8..12].copy_from_slice(&(image_length as u32).to_le_bytes());
+        buffer[12..16].copy_from_slice(&0u32.to_le_bytes());
+        self.storage.write(buffer, self.flags_address, flags::LEN)
+    }
+
+    fn start(&self, length: usize, processid: ProcessId) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        if length == 0 {
+            return Err(ErrorCode::INVAL);
+        }
+        if length > self.bank_length {
+            return Err(ErrorCode::SIZE);
+        }
+        self.write_flags(flags::NONE, 0)?;
+        self.digest.clear_data();
+        self.image_length.set(length);
+        self.written.set(0);
+        self.owner.set(processid);
+        self.state.set(State::Starting);
+        Ok(())
+    }
+
+    fn write(&self, offset: usize, length: usize, processid: ProcessId) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Receiving {
+            return Err(ErrorCode::BUSY);
+        }
+        if length == 0 || offset != self.written.get() || offset + length > self.image_length.get()
+        {
+            return Err(ErrorCode::INVAL);
+        }
+        let buffer = self.buffer.take().ok_or(ErrorCode::BUSY)?;
+        let copied = self
+            .apps
+            .enter(processid, |_, kernel_data| {
+                kernel_data
+                    .get_readonly_processbuffer(ro_allow::IMAGE)
+                    .and_then(|image| {
+                        image.enter(|app_buffer| {
+                            let len = cmp::min(cmp::min(length, app_buffer.len()), buffer.len());
+                            app_buffer[..len].copy_to_slice(&mut buffer[..len]);
+                            len
+                        })
+                    })
+                    .unwrap_or(0)
+            })
+            .unwrap_or(0);
+        if copied == 0 {
+            self.buffer.replace(buffer);
+            return Err(ErrorCode::RESERVE);
+        }
+        self.storage
+            .write(buffer, self.bank_start + offset, copied)?;
+        self.chunk_length.set(copied);
+        self.state.set(State::Writing);
+        Ok(())
+    }
+
+    fn finish(&self, processid: ProcessId) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Receiving {
+            return Err(ErrorCode::BUSY);
+        }
+        if self.written.get() != self.image_length.get() {
+            return Err(ErrorCode::SIZE);
+        }
+        let signature = self.signature.take().ok_or(ErrorCode::BUSY)?;
+        let copied = self
+            .apps
+            .enter(processid, |_, kernel_data| {
+                kernel_data
+                    .get_readonly_processbuffer(ro_allow::SIGNATURE)
+                    .and_then(|allowed| {
+                        allowed.enter(|allowed| {
+                            if allowed.len() == SIGNATURE_LEN {
+                                allowed.copy_to_slice(signature);
+                                Ok(())
+                            } else {
+                                Err(ErrorCode::SIZE)
+                            }
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::RESERVE))
+            })
+            .unwrap_or_else(|err| Err(err.into()));
+        self.signature.replace(signature);
+        copied?;
+
+        let hash = self.hash.take().ok_or(ErrorCode::BUSY)?;
+        self.digest.run(hash).map_err(|(e, hash)| {
+            self.hash.replace(hash);
+            e
+        })?;
+        self.state.set(State::Digesting);
+        Ok(())
+    }
+
+    fn abort(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Receiving {
+            return Err(ErrorCode::BUSY);
+        }
+        self.digest.clear_data();
+        self.owner.clear();
+        self.state.set(State::Idle);
+        Ok(())
+    }
+
+    fn confirm(&self, processid: ProcessId) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        let buffer = self.buffer.take().ok_or(ErrorCode::BUSY)?;
+        if buffer.len() < flags::LEN {
+            self.buffer.replace(buffer);
+            return Err(ErrorCode::SIZE);
+        }
+        self.storage.read(buffer, self.flags_address, flags::LEN)?;
+        self.owner.set(processid);
+        self.state.set(State::ReadingFlags);
+        Ok(())
+    }
+
+    /// End the update or confirmation with `result` and report it to the
+    /// owner.
+    fn done(&self, upcall: usize, result: Result<(), ErrorCode>, value: usize) {
+        if result.is_err() {
+            self.digest.clear_data();
+        }
+        self.state.set(State::Idle);
+        if let Some(owner) = self.owner.take() {
+            let _ = self.apps.enter(owner, |_, kernel_data| {
+                let _ = kernel_data.schedule_upcall(upcall, (into_statuscode(result), value, 0));
+            });
+        }
+    }
+}
+
+impl<'a, C: ProcessManagementCapability> NonvolatileStorageClient<'static>
+    for FirmwareUpdate<'a, C>
+{
+    fn read_done(&self, buffer: &'static mut [u8], length: usize) {
+        if self.state.get() != State::ReadingFlags {
+            self.buffer.replace(buffer);
+            return;
+        }
+        if length != flags::LEN {
+            self.buffer.replace(buffer);
+            self.done(upcall::DONE, Err(ErrorCode::FAIL), 0);
+            return;
+        }
+
+        let word =
+            |i: usize| u32::from_le_bytes([buffer[i], buffer[i + 1], buffer[i + 2], buffer[i + 3]]);
+        let state = if word(0) == flags::MAGIC {
+            word(4)
+        } else {
+            flags::NONE
+        };
+        let image_length = word(8) as usize;
+        self.buffer.replace(buffer);
+        self.previous_flags.set(state);
+
+        if state != flags::TRIAL {
+            self.done(upcall::DONE, Ok(()), state as usize);
+            return;
+        }
+        match self.write_flags(flags::CONFIRMED, image_length) {
+            Ok(()) => self.state.set(State::Confirming),
+            Err(e) => self.done(upcall::DONE, Err(e), state as usize),
+        }
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], length: usize) {
+        match self.state.get() {
+            State::Starting => {
+                self.buffer.replace(buffer);
+                if length == flags::LEN {
+                    self.state.set(State::Receiving);
+                    self.owner.map(|owner| {
+                        let _ = self.apps.enter(*owner, |_, kernel_data| {
+                            let _ = kernel_data
+                                .schedule_upcall(upcall::DONE, (into_statuscode(Ok(())), 0, 0));
+                        });
+                    });
+                } else {
+                    self.done(upcall::DONE, Err(ErrorCode::FAIL), 0);
+                }
+            }
+            State::Writing => {
+                if length != self.chunk_length.get() {
+                    self.buffer.replace(buffer);
+                    self.done(upcall::WRITE_DONE, Err(ErrorCode::FAIL), 0);
+                    return;
+                }
+                // Add what was written to the digest, so the signature
+                // covers the bytes in flash.
+                let mut data = LeasableMutableBuffer::new(buffer);
+                data.slice(..length);
+                match self.digest.add_mut_data(data) {
+                    Ok(()) => self.state.set(State::Hashing),
+                    Err((e, data)) => {
+                        self.buffer.replace(data.take());
+                        self.done(upcall::WRITE_DONE, Err(e), 0);
+                    }
+                }
+            }
+            State::Swapping => {
+                self.buffer.replace(buffer);
+                let result = if length == flags::LEN {
+                    Ok(())
+                } else {
+                    Err(ErrorCode::FAIL)
+                };
+                self.done(upcall::DONE, result, 0);
+            }
+            State::Confirming => {
+                self.buffer.replace(buffer);
+                let result = if length == flags::LEN {
+                    Ok(())
+                } else {
+                    Err(ErrorCode::FAIL)
+                };
+                self.done(upcall::DONE, result, self.previous_flags.get() as usize);
+            }
+            _ => {
+                self.buffer.replace(buffer);
+            }
+        }
+    }
+}
+
+impl<'a, C: ProcessManagementCapability> ClientData<HASH_LEN> for FirmwareUpdate<'a, C> {
+    fn add_data_done(&self, _result: Result<(), ErrorCode>, _data: LeasableBuffer<'static, u8>) {}
+
+    fn add_mut_data_done(
+        &self,
+        result: Result<(), ErrorCode>,
+        data: LeasableMutableBuffer<'static, u8>,
+    ) {
+        self.buffer.replace(data.take());
+        if self.state.get() != State::Hashing {
+            return;
+        }
+        match result {
+            Ok(()) => {
+                let length = self.chunk_length.get();
+                self.written.set(self.written.get() + length);
+                self.state.set(State::Receiving);
+                self.owner.map(|owner| {
+                    let _ = self.apps.enter(*owner, |_, kernel_data| {
+                        let _ = kernel_data.schedule_upcall(
+                            upcall::WRITE_DONE,
+                            (into_statuscode(Ok(())), length, 0),
+                        );
+                    });
+                });
+            }
+            Err(e) => self.done(upcall::WRITE_DONE, Err(e), 0),
+        }
+    }
+}
+
+impl<'a, C: ProcessManagementCapability> ClientHash<HASH_LEN> for FirmwareUpdate<'a, C> {
+    fn hash_done(&self, result: Result<(), ErrorCode>, digest: &'static mut [u8; HASH_LEN]) {
+        if self.state.get() != State::Digesting {
+            self.hash.replace(digest);
+            return;
+        }
+        if let Err(e) = result {
+            self.hash.replace(digest);
+            self.done(upcall::DONE, Err(e), 0);
+            return;
+        }
+        let Some(signature) = self.signature.take() else {
+            self.hash.replace(digest);
+            self.done(upcall::DONE, Err(ErrorCode::FAIL), 0);
+            return;
+        };
+        match self.verifier.verify(digest, signature) {
+            Ok(()) => self.state.set(State::Verifying),
+            Err((e, digest, signature)) => {
+                self.hash.replace(digest);
+                self.signature.replace(signature);
+                self.done(upcall::DONE, Err(e), 0);
+            }
+        }
+    }
+}
+
+impl<'a, C: ProcessManagementCapability> ClientVerify<HASH_LEN, SIGNATURE_LEN>
+    for FirmwareUpdate<'a, C>
+{
+    fn verification_done(
+        &self,
+        result: Result<bool, ErrorCode>,
+        hash: &'static mut [u8; HASH_LEN],
+        signature: &'static mut [u8; SIGNATURE_LEN],
+    ) {
+        self.hash.replace(hash);
+        self.signature.replace(signature);
+        if self.state.get() != State::Verifying {
+            return;
+        }
+        let result = match result {
+            Ok(true) => self.write_flags(flags::PENDING, self.image_length.get()),
+            Ok(false) => Err(ErrorCode::FAIL),
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(()) => self.state.set(State::Swapping),
+            Err(e) => self.done(upcall::DONE, Err(e), 0),
+        }
+    }
+}
+
+impl<'a, C: ProcessManagementCapability> SyscallDriver for FirmwareUpdate<'a, C> {
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        if command_num == 0 {
+            return CommandReturn::success();
+        }
+        if !self.is_allowed(processid) {
+            return CommandReturn::failure(ErrorCode::NOSUPPORT);
+        }
+        let result = match command_num {
+            1 | 5 => {
+                // Release an update abandoned by its owner first.
+                let _ = self.is_owner(processid);
+                if command_num == 1 {
+                    self.start(data1, processid)
+                } else {
+                    self.confirm(processid)
+                }
+            }
+            2..=4 if !self.is_owner(processid) => Err(ErrorCode::RESERVE),
+            2 => self.write(data1, data2, processid),
+            3 => self.finish(processid),
+            4 => self.abort(),
+            _ => Err(ErrorCode::NOSUPPORT),
+        };
+        CommandReturn::from(result)
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
@@ -0,0 +1,250 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Keeps the kernel crash report in a reserved flash page.
+//!
+//! When the kernel panics, the report is written to the page through
+//! `hil::flash`. The flash driver completes its operations with interrupts
+//! or deferred calls, which the panic routine services while the report is
+//! stored. At boot, `load()` reads the page back, and the report is then
+//! available through the `CrashReport` interface until it is cleared.
+//!
+//! The page must be reserved for the report, e.g. by shrinking the kernel
+//! `rom` region in the board's `layout.ld`.
+//!
+//! Storage Format
+//! --------------
+//!
+//! The page starts with the magic number and the length of the report, both
+//! little endian `u32`, followed by the report text.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let crash_report = components::crash_report::FlashCrashReportComponent::new(
+//!     virtual_crash_flash,
+//!     0x3f,
+//! )
+//! .finalize(components::flash_crash_report_component_static!(
+//!     capsules_core::virtualizers::virtual_flash::FlashUser<'static, nrf52833::nvmc::Nvmc>
+//! ));
+//! process_console.set_crash_report(crash_report);
+//! ```
+
+use core::cell::Cell;
+use core::fmt;
+
+use kernel::crash_report::{CrashReport, CrashReportStorage};
+use kernel::debug;
+use kernel::hil::flash::{self, Flash};
+use kernel::utilities::cells::TakeCell;
+use kernel::ErrorCode;
+
+const MAGIC: u32 = 0x4352_5348;
+
+const HEADER_LEN: usize = 8;
+
+/// How often the panic routine is polled for each flash operation before
+/// giving up on storing the report.
+const POLL_LIMIT: usize = 1_000_000;
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum State {
+    Idle,
+    Loading,
+    /// Erasing the page before writing the report of a panic.
+    Erasing,
+    Writing,
+    Clearing,
+}
+
+/// Writes the report text after the header, dropping what does not fit.
+struct PageWriter<'a> {
+    text: &'a mut [u8],
+    len: usize,
+}
+
+impl fmt::Write for PageWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        if end > self.text.len() {
+            return Err(fmt::Error);
+        }
+        self.text[self.len..end].copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+pub struct FlashCrashReport<'a, F: Flash + 'static> {
+    flash: &'a F,
+    page_number: usize,
+    /// The page, which holds the stored report once it is loaded.
+    buffer: TakeCell<'static, F::Page>,
+    /// Length of the report in `buffer`.
+    len: Cell<usize>,
+    state: Cell<State>,
+}
+
+impl<'a, F: Flash + 'static> FlashCrashReport<'a, F> {
+    pub fn new(
+        flash: &'a F,
+        page_number: usize,
+        buffer: &'static mut F::Page,
+    ) -> FlashCrashReport<'a, F> {
+        FlashCrashReport {
+            flash,
+            page_number,
+            buffer: TakeCell::new(buffer),
+            len: Cell::new(0),
+            state: Cell::new(State::Idle),
+        }
+    }
+
+    /// Read the report stored by a previous boot. If there is one, its
+    /// first line is printed with `debug!()`.
+    pub fn load(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        let buffer = self.buffer.take().ok_or(ErrorCode::BUSY)?;
+        self.state.set(State::Loading);
+        self.flash
+            .read_page(self.page_number, buffer)
+            .map_err(|(error, buffer)| {
+                self.buffer.replace(buffer);
+                self.state.set(State::Idle);
+                error
+            })
+    }
+
+    fn read_u32(page: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes([
+            page[offset],
+            page[offset + 1],
+            page[offset + 2],
+            page[offset + 3],
+        ])
+    }
+}
+
+impl<'a, F: Flash + 'static> CrashReportStorage for FlashCrashReport<'a, F> {
+    fn store(&self, report: &mut dyn FnMut(&mut dyn fmt::Write), poll: &dyn Fn()) {
+        // The report cannot be stored while another flash operation is in
+        // progress.
+        if self.state.get() != State::Idle {
+            return;
+        }
+        let len = match self.buffer.map(|buffer| {
+            let page = buffer.as_mut();
+            let mut writer = PageWriter {
+                text: &mut page[HEADER_LEN..],
+                len: 0,
+            };
+            report(&mut writer);
+            let len = writer.len;
+            page[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+            page[4..8].copy_from_slice(&(len as u32).to_le_bytes());
+            len
+        }) {
+            Some(len) => len,
+            None => return,
+        };
+        self.len.set(len);
+
+        self.state.set(State::Erasing);
+        if self.flash.erase_page(self.page_number).is_err() {
+            self.state.set(State::Idle);
+            return;
+        }
+        // `erase_complete()` starts writing the page.
+        for _ in 0..POLL_LIMIT {
+            if self.state.get() == State::Idle {
+                break;
+            }
+            poll();
+        }
+    }
+}
+
+impl<'a, F: Flash + 'static> CrashReport for FlashCrashReport<'a, F> {
+    fn report_len(&self) -> usize {
+        self.len.get()
+    }
+
+    fn read_report(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let len = self.len.get();
+        self.buffer
+            .map(|buffer| {
+                let report = &buffer.as_mut()[HEADER_LEN..HEADER_LEN + len];
+                let count = buf.len().min(len.saturating_sub(offset));
+                buf[..count].copy_from_slice(&report[offset..offset + count]);
+                count
+            })
+            .unwrap_or(0)
+    }
+
+    fn clear_report(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.flash.erase_page(self.page_number)?;
+        self.state.set(State::Clearing);
+        self.len.set(0);
+        Ok(())
+    }
+}
+
+impl<'a, F: Flash + 'static> flash::Client<F> for FlashCrashReport<'a, F> {
+    fn read_complete(&self, buffer: &'static mut F::Page, error: flash::Error) {
+        self.state.set(State::Idle);
+        let page = buffer.as_mut();
+        let len = Self::read_u32(page, 4) as usize;
+        if error == flash::Error::CommandComplete
+            && Self::read_u32(page, 0) == MAGIC
+            && len <= page.len() - HEADER_LEN
+        {
+            self.len.set(len);
+            let report = &page[HEADER_LEN..HEADER_LEN + len];
+            let first_line = report.split(|b| *b == b'\r').next().unwrap_or(&[]);
+            debug!(
+                "Crash report of a previous boot ({} bytes): {}",
+                len,
+                core::str::from_utf8(first_line).unwrap_or("")
+            );
+        }
+        self.buffer.replace(buffer);
+    }
+
+    fn write_complete(&self, buffer: &'static mut F::Page, _error: flash::Error) {
+        self.buffer.replace(buffer);
+        self.state.set(State::Idle);
+    }
+
+    fn erase_complete(&self, error: flash::Error) {
+        match self.state.get() {
+            State::Erasing => {
+                let written = match self.buffer.take() {
+                    Some(buffer) if error == flash::Error::CommandComplete => {
+                        self.state.set(State::Writing);
+                        self.flash
+                            .write_page(self.page_number, buffer)
+                            .map_err(|(_, buffer)| self.buffer.replace(buffer))
+                            .is_ok()
+                    }
+                    Some(buffer) => {
+                        self.buffer.replace(buffer);
+                        false
+                    }
+                    None => false,
+                };
+                if !written {
+                    self.state.set(State::Idle);
+                }
+            }
+            _ => self.state.set(State::Idle),
+        }
+    }
+}
@@ -0,0 +1,1193 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! A small file system in a region of flash pages, with directories and
+//! named files that can be appended to and truncated.
+//!
+//! The table of directories and files is kept in RAM and in two flash pages.
+//! Every change writes the whole table to the page holding the older copy,
+//! with a higher sequence number and a checksum, so losing power while the
+//! table is written leaves the newer copy intact. At mount, the valid copy
+//! with the highest sequence number is used, and flash without a valid copy
+//! is formatted.
+//!
+//! File data is never overwritten either. Data appended to a partly filled
+//! page is written together with the page's old data to a free page, which
+//! replaces the old page once the table is written. A file changes only
+//! when its table entry is written, so an append is either complete or has
+//! no effect.
+//!
+//! Paths are names separated by `/`, e.g. `logs/today`. Names are up to
+//! `NAME_LEN` bytes long, and files hold up to `MAX_FILE_PAGES` pages of
+//! data. Operations run one at a time, others return `BUSY` in the meantime.
+//!
+//! Storage Format
+//! --------------
+//!
+//! Pages 0 and 1 of the region hold the copies of the table, the other pages
+//! hold file data. A table starts with a header of the magic number and the
+//! sequence number (both little endian `u32`), the number of entries (`u16`)
+//! and two reserved bytes, and the FNV-1a checksum of the rest of the header
+//! and the entries (`u32`). `ENTRY_LEN` bytes long entries follow:
+//!
+//! ```text
+//! 0      1        2          3          4      8             8 + NAME_LEN
+//! +------+--------+----------+----------+------+-------------+--------------
+//! | kind | parent | name len | reserved | size | name        | pages
+//! +------+--------+----------+----------+------+-------------+--------------
+//! ```
+//!
+//! The kind is 0 for a free entry, 1 for a file and 2 for a directory. The
+//! parent is the index of the directory's entry, or `0xff` in the root
+//! directory. The size is a little endian `u32`, and the pages are the
+//! numbers of the file's data pages in the region, `MAX_FILE_PAGES` little
+//! endian `u16`s, `0xffff` for none.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let flash_fs = components::flash_fs::FlashFsComponent::new(
+//!     virtual_fs_flash,
+//!     board_kernel,
+//!     capsules_extra::flash_fs_driver::DRIVER_NUM,
+//!     0xe0, // First page of the region
+//!     32,   // Pages of the region
+//! )
+//! .finalize(components::flash_fs_component_static!(
+//!     capsules_core::virtualizers::virtual_flash::FlashUser<'static, nrf52840::nvmc::Nvmc>,
+//!     16
+//! ));
+//! ```
+
+use core::cell::Cell;
+
+use kernel::hil::flash::{self, Flash};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// The longest name of a file or directory.
+pub const NAME_LEN: usize = 16;
+
+/// The most data pages a file can have.
+pub const MAX_FILE_PAGES: usize = 8;
+
+/// The length of an entry of the table in flash.
+pub const ENTRY_LEN: usize = 8 + NAME_LEN + 2 * MAX_FILE_PAGES;
+
+const HEADER_LEN: usize = 16;
+
+const MAGIC: u32 = 0x5446_5331;
+
+/// The parent of the entries in the root directory.
+const ROOT: u8 = 0xff;
+
+const NO_PAGE: u16 = 0xffff;
+
+/// The number of pages holding copies of the table.
+const TABLE_PAGES: usize = 2;
+
+pub trait FileSystemClient {
+    /// `mount()` finished. The file system is formatted if flash held none.
+    fn mount_done(&self, result: Result<(), ErrorCode>);
+
+    /// `create()`, `remove()` or `truncate()` finished.
+    fn update_done(&self, result: Result<(), ErrorCode>);
+
+    /// `append()` finished, the data was appended if `result` is `Ok`.
+    fn append_done(&self, result: Result<(), ErrorCode>, data: &'static mut [u8]);
+
+    /// `read()` finished, with the number of bytes read into `buf`.
+    fn read_done(&self, result: Result<usize, ErrorCode>, buf: &'static mut [u8]);
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Kind {
+    Free,
+    File,
+    Directory,
+}
+
+/// A file or directory in the table.
+#[derive(Copy, Clone)]
+pub struct Entry {
+    kind: Kind,
+    parent: u8,
+    name_len: u8,
+    size: u32,
+    name: [u8; NAME_LEN],
+    pages: [u16; MAX_FILE_PAGES],
+}
+
+impl Default for Entry {
+    fn default() -> Entry {
+        Entry {
+            kind: Kind::Free,
+            parent: ROOT,
+            name_len: 0,
+            size: 0,
+            name: [0; NAME_LEN],
+            pages: [NO_PAGE; MAX_FILE_PAGES],
+        }
+    }
+}
+
+impl Entry {
+    fn name(&self) -> &[u8] {
+        &self.name[..self.name_len as usize]
+    }
+
+    fn write_to(&self, buf: &mut [u8]) {
+        buf[0] = match self.kind {
+            Kind::Free => 0,
+            Kind::File => 1,
+            Kind::Directory => 2,
+        };
+        buf[1] = self.parent;
+        buf[2] = self.name_len;
+        buf[3] = 0;
+        buf[4..8].copy_from_slice(&self.size.to_le_bytes());
+        buf[8..8 + NAME_LEN].copy_from_slice(&self.name);
+        for (i, page) in self.pages.iter().enumerate() {
+            let offset = 8 + NAME_LEN + 2 * i;
+            buf[offset..offset + 2].copy_from_slice(&page.to_le_bytes());
+        }
+    }
+
+    fn read_from(buf: &[u8]) -> Option<Entry> {
+        let kind = match buf[0] {
+            0 => Kind::Free,
+            1 => Kind::File,
+            2 => Kind::Directory,
+            _ => return None,
+        };
+        let mut entry = Entry {
+            kind,
+            parent: buf[1],
+            name_len: buf[2].min(NAME_LEN as u8),
+            size: u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]),
+            ..Entry::default()
+        };
+        entry.name.copy_from_slice(&buf[8..8 + NAME_LEN]);
+        for (i, page) in entry.pages.iter_mut().enumerate() {
+            let offset = 8 + NAME_LEN + 2 * i;
+            *page = u16::from_le_bytes([buf[offset], buf[offset + 1]]);
+        }
+        Some(entry)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum State {
+    Unmounted,
+    Idle,
+    /// Reading copy `n` of the table.
+    MountRead(usize),
+    /// Reading the partly filled last page of a file to append to it.
+    AppendRead,
+    /// Erasing the free page that the appended data is written to.
+    AppendErase,
+    AppendWrite,
+    Read,
+    /// Erasing the page of the older copy of the table to replace it.
+    CommitErase,
+    CommitWrite,
+}
+
+/// The operation to report once it finishes.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Op {
+    Mount,
+    Update,
+    Append,
+    Read,
+}
+
+/// FNV-1a checksum of a copy of the table.
+fn checksum(header: &[u8], entries: &[u8]) -> u32 {
+    header.iter().chain(entries).fold(0x811c_9dc5, |hash, b| {
+        (hash ^ *b as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+/// Split `path` into the path of its directory and its name.
+fn split_path(path: &[u8]) -> Result<(&[u8], &[u8]), ErrorCode> {
+    let path = match path.iter().rposition(|b| *b != b'/') {
+        Some(end) => &path[..=end],
+        None => return Err(ErrorCode::INVAL),
+    };
+    let (directory, name) = match path.iter().rposition(|b| *b == b'/') {
+        Some(slash) => (&path[..slash], &path[slash + 1..]),
+        None => (&path[..0], path),
+    };
+    if name.len() > NAME_LEN {
+        return Err(ErrorCode::SIZE);
+    }
+    Ok((directory, name))
+}
+
+pub struct FlashFs<'a, F: Flash + 'static> {
+    flash: &'a F,
+    first_page: usize,
+    num_pages: usize,
+    page_len: usize,
+    buffer: TakeCell<'static, F::Page>,
+    /// The table as it is stored in flash.
+    entries: TakeCell<'static, [Entry]>,
+    /// Sequence number of the newest copy of the table.
+    sequence: Cell<u32>,
+    /// Whether a valid copy of the table was found while mounting.
+    found: Cell<bool>,
+    state: Cell<State>,
+    op: Cell<Op>,
+    /// The index and entry the operation works on, with the changes that are
+    /// not written to the table yet.
+    pending: Cell<Option<(usize, Entry)>>,
+    /// The data of the append or read.
+    data: TakeCell<'static, [u8]>,
+    data_len: Cell<usize>,
+    /// The bytes of the data appended or read so far.
+    done: Cell<usize>,
+    /// The offset in the file to read from.
+    offset: Cell<usize>,
+    /// The page the appended data is written to, and how many bytes of data
+    /// it gets.
+    data_page: Cell<usize>,
+    chunk: Cell<usize>,
+    client: OptionalCell<&'a dyn FileSystemClient>,
+}
+
+impl<'a, F: Flash + 'static> FlashFs<'a, F> {
+    /// The file system takes `num_pages` flash pages from `first_page`, and
+    /// holds up to `entries.len()` files and directories.
+    pub fn new(
+        flash: &'a F,
+        first_page: usize,
+        num_pages: usize,
+        buffer: &'static mut F::Page,
+        entries: &'static mut [Entry],
+    ) -> FlashFs<'a, F> {
+        let page_len = buffer.as_mut().len();
+        FlashFs {
+            flash,
+            first_page,
+            num_pages,
+            page_len,
+            buffer: TakeCell::new(buffer),
+            entries: TakeCell::new(entries),
+            sequence: Cell::new(0),
+            found: Cell::new(false),
+            state: Cell::new(State::Unmounted),
+            op: Cell::new(Op::Mount),
+            pending: Cell::new(None),
+            data: TakeCell::empty(),
+            data_len: Cell::new(0),
+            done: Cell::new(0),
+            offset: Cell::new(0),
+            data_page: Cell::new(0),
+            chunk: Cell::new(0),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn FileSystemClient) {
+        self.client.set(client);
+    }
+
+    /// Load the table from flash, or format the region if it holds no file
+    /// system.
+    ///
+    /// Return values:
+    ///   - Ok(()): `mount_done` is called once mounted.
+    ///   - Err(BUSY): an operation is in progress.
+    ///   - Err(SIZE): the table doesn't fit in a page, or the region is too
+    ///     small or too large.
+    pub fn mount(&self) -> Result<(), ErrorCode> {
+        match self.state.get() {
+            State::Unmounted | State::Idle => {}
+            _ => return Err(ErrorCode::BUSY),
+        }
+        let entries_len = self.entries.map_or(0, |entries| entries.len());
+        if HEADER_LEN + entries_len * ENTRY_LEN > self.page_len
+            || entries_len > ROOT as usize
+            || self.num_pages <= TABLE_PAGES
+            || self.num_pages > NO_PAGE as usize
+        {
+            return Err(ErrorCode::SIZE);
+        }
+
+        self.state.set(State::Unmounted);
+        self.entries
+            .map(|entries| entries.iter_mut().for_each(|e| *e = Entry::default()));
+        self.sequence.set(0);
+        self.found.set(false);
+        self.op.set(Op::Mount);
+        self.read_page(0, State::MountRead(0))
+    }
+
+    /// Create the file or directory `path`.
+    ///
+    /// Return values:
+    ///   - Ok(()): `update_done` is called once created.
+    ///   - Err(OFF): the file system is not mounted.
+    ///   - Err(BUSY): an operation is in progress.
+    ///   - Err(INVAL): `path` is empty, or its directory is a file.
+    ///   - Err(SIZE): the name is longer than `NAME_LEN`.
+    ///   - Err(NOSUPPORT): the directory of `path` doesn't exist.
+    ///   - Err(ALREADY): `path` exists.
+    ///   - Err(NOMEM): the table is full.
+    pub fn create(&self, path: &[u8], directory: bool) -> Result<(), ErrorCode> {
+        self.check_idle()?;
+        let (directory_path, name) = split_path(path)?;
+
+        let change = self
+            .entries
+            .map(|entries| {
+                let parent = Self::lookup(entries, directory_path)?;
+                if let Some(parent) = parent {
+                    if entries[parent].kind != Kind::Directory {
+                        return Err(ErrorCode::INVAL);
+                    }
+                }
+                if Self::find(entries, parent, name).is_some() {
+                    return Err(ErrorCode::ALREADY);
+                }
+                let index = entries
+                    .iter()
+                    .position(|e| e.kind == Kind::Free)
+                    .ok_or(ErrorCode::NOMEM)?;
+
+                let mut entry = Entry {
+                    kind: if directory {
+                        Kind::Directory
+                    } else {
+                        Kind::File
+                    },
+                    parent: parent.map_or(ROOT, |p| p as u8),
+                    name_len: name.len() as u8,
+                    ..Entry::default()
+                };
+                entry.name[..name.len()].copy_from_slice(name);
+                Ok((index, entry))
+            })
+            .unwrap_or(Err(ErrorCode::FAIL))?;
+
+        self.update(change)
+    }
+
+    /// Remove the file or the empty directory `path`.
+    ///
+    /// Return values:
+    ///   - Ok(()): `update_done` is called once removed.
+    ///   - Err(OFF): the file system is not mounted.
+    ///   - Err(BUSY): an operation is in progress.
+    ///   - Err(INVAL): `path` is the root directory or a directory that isn't
+    ///     empty.
+    ///   - Err(NOSUPPORT): `path` doesn't exist.
+    pub fn remove(&self, path: &[u8]) -> Result<(), ErrorCode> {
+        self.check_idle()?;
+
+        let index = self
+            .entries
+            .map(|entries| {
+                let index = Self::lookup(entries, path)?.ok_or(ErrorCode::INVAL)?;
+                if entries
+                    .iter()
+                    .any(|e| e.kind != Kind::Free && e.parent as usize == index)
+                {
+                    return Err(ErrorCode::INVAL);
+                }
+                Ok(index)
+            })
+            .unwrap_or(Err(ErrorCode::FAIL))?;
+
+        self.update((index, Entry::default()))
+    }
+
+    /// Shorten the file `path` to `len` bytes.
+    ///
+    /// Return values:
+    ///   - Ok(()): `update_done` is called once truncated.
+    ///   - Err(OFF): the file system is not mounted.
+    ///   - Err(BUSY): an operation is in progress.
+    ///   - Err(INVAL): `path` is not a file, or shorter than `len`.
+    ///   - Err(NOSUPPORT): `path` doesn't exist.
+    pub fn truncate(&self, path: &[u8], len: usize) -> Result<(), ErrorCode> {
+        self.check_idle()?;
+        let (index, mut entry) = self.lookup_file(path)?;
+        if len > entry.size as usize {
+            return Err(ErrorCode::INVAL);
+        }
+
+        entry.size = len as u32;
+        let pages = (len + self.page_len - 1) / self.page_len;
+        entry.pages[pages..].iter_mut().for_each(|p| *p = NO_PAGE);
+        self.update((index, entry))
+    }
+
+    /// Append the first `len` bytes of `data` to the file `path`.
+    ///
+    /// Return values:
+    ///   - Ok(()): `append_done` is called once appended.
+    ///   - Err(OFF): the file system is not mounted.
+    ///   - Err(BUSY): an operation is in progress.
+    ///   - Err(INVAL): `path` is not a file.
+    ///   - Err(NOSUPPORT): `path` doesn't exist.
+    ///   - Err(SIZE): `len` is 0 or longer than `data`, or the file would be
+    ///     longer than `MAX_FILE_PAGES` pages.
+    pub fn append(
+        &self,
+        path: &[u8],
+        data: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if let Err(e) = self.check_idle() {
+            return Err((e, data));
+        }
+        let file = match self.lookup_file(path) {
+            Ok(file) => file,
+            Err(e) => return Err((e, data)),
+        };
+        if len == 0
+            || len > data.len()
+            || file.1.size as usize + len > MAX_FILE_PAGES * self.page_len
+        {
+            return Err((ErrorCode::SIZE, data));
+        }
+
+        self.pending.set(Some(file));
+        self.data.replace(data);
+        self.data_len.set(len);
+        self.done.set(0);
+        self.op.set(Op::Append);
+        self.append_next().map_err(|e| {
+            self.pending.set(None);
+            self.state.set(State::Idle);
+            (e, self.data.take().unwrap())
+        })
+    }
+
+    /// Read up to `len` bytes of the file `path` from `offset` into `buf`.
+    ///
+    /// Return values:
+    ///   - Ok(()): `read_done` is called with the number of bytes read.
+    ///   - Err(OFF): the file system is not mounted.
+    ///   - Err(BUSY): an operation is in progress.
+    ///   - Err(INVAL): `path` is not a file.
+    ///   - Err(NOSUPPORT): `path` doesn't exist.
+    ///   - Err(SIZE): `len` is 0 or longer than `buf`, or `offset` is not
+    ///     before the end of the file.
+    pub fn read(
+        &self,
+        path: &[u8],
+        offset: usize,
+        buf: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if let Err(e) = self.check_idle() {
+            return Err((e, buf));
+        }
+        let file = match self.lookup_file(path) {
+            Ok(file) => file,
+            Err(e) => return Err((e, buf)),
+        };
+        let size = file.1.size as usize;
+        if len == 0 || len > buf.len() || offset >= size {
+            return Err((ErrorCode::SIZE, buf));
+        }
+
+        self.pending.set(Some(file));
+        self.data.replace(buf);
+        self.data_len.set(len.min(size - offset));
+        self.done.set(0);
+        self.offset.set(offset);
+        self.op.set(Op::Read);
+        self.read_next().map_err(|e| {
+            self.pending.set(None);
+            self.state.set(State::Idle);
+            (e, self.data.take().unwrap())
+        })
+    }
+
+    /// The size of `path` and whether it is a directory.
+    pub fn stat(&self, path: &[u8]) -> Result<(usize, bool), ErrorCode> {
+        if self.state.get() == State::Unmounted {
+            return Err(ErrorCode::OFF);
+        }
+        self.entries
+            .map(|entries| {
+                Ok(match Self::lookup(entries, path)? {
+                    Some(index) => (
+                        entries[index].size as usize,
+                        entries[index].kind == Kind::Directory,
+                    ),
+                    None => (0, true),
+                })
+            })
+            .unwrap_or(Err(ErrorCode::BUSY))
+    }
+
+    /// Copy the name of the `index`th entry of the directory `path` to
+    /// `name`. Returns the length of the name and whether it is a directory,
+    /// or `NOSUPPORT` if the directory has no more entries.
+    pub fn directory_entry(
+        &self,
+        path: &[u8],
+        index: usize,
+        name: &mut [u8],
+    ) -> Result<(usize, bool), ErrorCode> {
+        if self.state.get() == State::Unmounted {
+            return Err(ErrorCode::OFF);
+        }
+        self.entries
+            .map(|entries| {
+                let directory = Self::lookup(entries, path)?;
+                if let Some(directory) = directory {
+                    if entries[directory].kind != Kind::Directory {
+                        return Err(ErrorCode::INVAL);
+                    }
+                }
+                let parent = directory.map_or(ROOT, |d| d as u8);
+                let entry = entries
+                    .iter()
+                    .filter(|e| e.kind != Kind::Free && e.parent == parent)
+                    .nth(index)
+                    .ok_or(ErrorCode::NOSUPPORT)?;
+
+                let len = entry.name().len().min(name.len());
+                name[..len].copy_from_slice(&entry.name()[..len]);
+                Ok((entry.name().len(), entry.kind == Kind::Directory))
+            })
+            .unwrap_or(Err(ErrorCode::BUSY))
+    }
+
+    fn check_idle(&self) -> Result<(), ErrorCode> {
+        match self.state.get() {
+            State::Idle => Ok(()),
+            State::Unmounted => Err(ErrorCode::OFF),
+            _ => Err(ErrorCode::BUSY),
+        }
+    }
+
+    /// Find the entry with `name` in the directory `parent`.
+    fn find(entries: &[Entry], parent: Option<usize>, name: &[u8]) -> Option<usize> {
+        let parent = parent.map_or(ROOT, |p| p as u8);
+        entries
+            .iter()
+            .position(|e| e.kind != Kind::Free && e.parent == parent && e.name() == name)
+    }
+
+    /// Find the entry of `path`, `None` for the root directory.
+    fn lookup(entries: &[Entry], path: &[u8]) -> Result<Option<usize>, ErrorCode> {
+        let mut index: Option<usize> = None;
+        for name in path.split(|b| *b == b'/').filter(|n| !n.is_empty()) {
+            if let Some(directory) = index {
+                if entries[directory].kind != Kind::Directory {
+                    return Err(ErrorCode::NOSUPPORT);
+                }
+            }
+            index = Some(Self::find(entries, index, name).ok_or(ErrorCode::NOSUPPORT)?);
+        }
+        Ok(index)
+    }
+
+    fn lookup_file(&self, path: &[u8]) -> Result<(usize, Entry), ErrorCode> {
+        self.entries
+            .map(|entries| match Self::lookup(entries, path)? {
+                Some(index) if entries[index].kind == Kind::File => Ok((index, entries[index])),
+                _ => Err(ErrorCode::INVAL),
+            })
+            .unwrap_or(Err(ErrorCode::BUSY))
+    }
+
+    /// Write the table with `change` applied.
+    fn update(&self, change: (usize, Entry)) -> Result<(), ErrorCode> {
+        self.pending.set(Some(change));
+        self.op.set(Op::Update);
+        self.commit().map_err(|e| {
+            self.pending.set(None);
+            self.state.set(State::Idle);
+            e
+        })
+    }
+
+    /// Find a data page that neither the table nor the pending entry uses.
+    fn free_page(&self) -> Result<usize, ErrorCode> {
+        let pending = self.pending.get().map(|(_, entry)| entry);
+        self.entries
+            .map(|entries| {
+                (TABLE_PAGES..self.num_pages).find(|page| {
+                    let page = *page as u16;
+                    !entries
+                        .iter()
+                        .chain(pending.iter())
+                        .any(|e| e.kind == Kind::File && e.pages.contains(&page))
+                })
+            })
+            .flatten()
+            .ok_or(ErrorCode::NOMEM)
+    }
+
+    fn read_page(&self, page: usize, state: State) -> Result<(), ErrorCode> {
+        let buffer = self.buffer.take().ok_or(ErrorCode::BUSY)?;
+        if let Err((e, buffer)) = self.flash.read_page(self.first_page + page, buffer) {
+            self.buffer.replace(buffer);
+            return Err(e);
+        }
+        self.state.set(state);
+        Ok(())
+    }
+
+    fn write_page(&self, page: usize, state: State) -> Result<(), ErrorCode> {
+        let buffer = self.buffer.take().ok_or(ErrorCode::BUSY)?;
+        if let Err((e, buffer)) = self.flash.write_page(self.first_page + page, buffer) {
+            self.buffer.replace(buffer);
+            return Err(e);
+        }
+        self.state.set(state);
+        Ok(())
+    }
+
+    fn erase_page(&self, page: usize, state: State) -> Result<(), ErrorCode> {
+        self.flash.erase_page(self.first_page + page)?;
+        self.state.set(state);
+        Ok(())
+    }
+
+    /// Load the copy of the table in the buffer if it is valid and newer than
+    /// the one loaded so far.
+    fn load_table(&self) {
+        let page = match self.buffer.take() {
+            Some(page) => page,
+            None => return,
+        };
+        let buf = page.as_mut();
+
+        let read_u32 = |offset: usize| {
+            u32::from_le_bytes([
+                buf[offset],
+                buf[offset + 1],
+                buf[offset + 2],
+                buf[offset + 3],
+            ])
+        };
+        let sequence = read_u32(4);
+        let count = u16::from_le_bytes([buf[8], buf[9]]) as usize;
+        let entries_len = self.entries.map_or(0, |entries| entries.len());
+        let valid = read_u32(0) == MAGIC
+            && count <= entries_len
+            && checksum(&buf[..12], &buf[HEADER_LEN..HEADER_LEN + count * ENTRY_LEN])
+                == read_u32(12)
+            && (!self.found.get() || (sequence.wrapping_sub(self.sequence.get()) as i32) > 0);
+
+        if valid {
+            self.entries.map(|entries| {
+                for (i, entry) in entries.iter_mut().enumerate() {
+                    *entry = if i < count {
+                        let offset = HEADER_LEN + i * ENTRY_LEN;
+                        Entry::read_from(&buf[offset..offset + ENTRY_LEN]).unwrap_or_default()
+                    } else {
+                        Entry::default()
+                    };
+                }
+            });
+            self.sequence.set(sequence);
+            self.found.set(true);
+        }
+        self.buffer.replace(page);
+    }
+
+    /// Write the table with the pending change to the page of its older
+    /// copy, starting with erasing that page.
+    fn commit(&self) -> Result<(), ErrorCode> {
+        let sequence = self.sequence.get().wrapping_add(1);
+        let pending = self.pending.get();
+
+        self.buffer
+            .map(|page| {
+                let buf = page.as_mut();
+                buf.fill(0xff);
+                self.entries.map(|entries| {
+                    for (i, entry) in entries.iter().enumerate() {
+                        let entry = match pending {
+                            Some((index, changed)) if index == i => changed,
+                            _ => *entry,
+                        };
+                        let offset = HEADER_LEN + i * ENTRY_LEN;
+                        entry.write_to(&mut buf[offset..offset + ENTRY_LEN]);
+                    }
+                    buf[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+                    buf[4..8].copy_from_slice(&sequence.to_le_bytes());
+                    buf[8..10].copy_from_slice(&(entries.len() as u16).to_le_bytes());
+                    buf[10..12].copy_from_slice(&[0; 2]);
+                    let sum = checksum(
+                        &buf[..12],
+                        &buf[HEADER_LEN..HEADER_LEN + entries.len() * ENTRY_LEN],
+                    );
+                    buf[12..16].copy_from_slice(&sum.to_le_bytes());
+                });
+            })
+            .ok_or(ErrorCode::BUSY)?;
+
+        self.erase_page(sequence as usize % TABLE_PAGES, State::CommitErase)
+    }
+
+    /// Write the next page of the appended data, or the table once all of
+    /// it is written.
+    fn append_next(&self) -> Result<(), ErrorCode> {
+        if self.done.get() == self.data_len.get() {
+            return self.commit();
+        }
+        let (_, entry) = self.pending.get().ok_or(ErrorCode::FAIL)?;
+        let offset = entry.size as usize;
+
+        if offset % self.page_len != 0 {
+            // Keep the data already in the last page
+            let page = entry.pages[offset / self.page_len];
+            self.read_page(page as usize, State::AppendRead)
+        } else {
+            self.buffer
+                .map(|page| page.as_mut().fill(0xff))
+                .ok_or(ErrorCode::BUSY)?;
+            self.write_data(0)
+        }
+    }
+
+    /// Copy as much data as fits into the buffer from `offset`, and start
+    /// writing it to a free page.
+    fn write_data(&self, offset: usize) -> Result<(), ErrorCode> {
+        let done = self.done.get();
+        let chunk = (self.page_len - offset).min(self.data_len.get() - done);
+        self.buffer
+            .map(|page| {
+                self.data.map(|data| {
+                    page.as_mut()[offset..offset + chunk].copy_from_slice(&data[done..done + chunk])
+                })
+            })
+            .flatten()
+            .ok_or(ErrorCode::BUSY)?;
+
+        let page = self.free_page()?;
+        self.data_page.set(page);
+        self.chunk.set(chunk);
+        self.erase_page(page, State::AppendErase)
+    }
+
+    /// Read the next page of the file, or report the data once all of it is
+    /// read.
+    fn read_next(&self) -> Result<(), ErrorCode> {
+        if self.done.get() == self.data_len.get() {
+            self.finish(Ok(()));
+            return Ok(());
+        }
+        let (_, entry) = self.pending.get().ok_or(ErrorCode::FAIL)?;
+        let position = self.offset.get() + self.done.get();
+        let page = entry.pages[position / self.page_len];
+        self.read_page(page as usize, State::Read)
+    }
+
+    /// Report the end of the operation to the client.
+    fn finish(&self, result: Result<(), ErrorCode>) {
+        let op = self.op.get();
+        self.pending.set(None);
+        self.state.set(if op == Op::Mount && result.is_err() {
+            State::Unmounted
+        } else {
+            State::Idle
+        });
+
+        self.client.map(|client| match op {
+            Op::Mount => client.mount_done(result),
+            Op::Update => client.update_done(result),
+            Op::Append => {
+                if let Some(data) = self.data.take() {
+                    client.append_done(result, data);
+                }
+            }
+            Op::Read => {
+                if let Some(buf) = self.data.take() {
+                    client.read_done(result.map(|()| self.done.get()), buf);
+                }
+            }
+        });
+    }
+
+    /// Continue the operation with `next`, or end it if that fails.
+    fn continue_with(&self, next: Result<(), ErrorCode>) {
+        if let Err(e) = next {
+            self.finish(Err(e));
+        }
+    }
+}
+
+impl<'a, F: Flash + 'static> flash::Client<F> for FlashFs<'a, F> {
+    fn read_complete(&self, buffer: &'static mut F::Page, error: flash::Error) {
+        self.buffer.replace(buffer);
+        let ok = error == flash::Error::CommandComplete;
+
+        match self.state.get() {
+            State::MountRead(copy) => {
+                if ok {
+                    self.load_table();
+                }
+                if copy + 1 < TABLE_PAGES {
+                    self.continue_with(self.read_page(copy + 1, State::MountRead(copy + 1)));
+                } else if self.found.get() {
+                    self.finish(Ok(()));
+                } else {
+                    // Format the region with an empty table
+                    self.continue_with(self.commit());
+                }
+            }
+            State::AppendRead if ok => {
+                let offset = self
+                    .pending
+                    .get()
+                    .map_or(0, |(_, entry)| entry.size as usize % self.page_len);
+                self.continue_with(self.write_data(offset));
+            }
+            State::Read if ok => {
+                let position = self.offset.get() + self.done.get();
+                let in_page = position % self.page_len;
+                let chunk = (self.page_len - in_page).min(self.data_len.get() - self.done.get());
+                let done = self.done.get();
+                self.buffer.map(|page| {
+                    self.data.map(|data| {
+                        data[done..done + chunk]
+                            .copy_from_slice(&page.as_mut()[in_page..in_page + chunk])
+                    })
+                });
+                self.done.set(done + chunk);
+                self.continue_with(self.read_next());
+            }
+            _ => self.finish(Err(ErrorCode::FAIL)),
+        }
+    }
+
+    fn write_complete(&self, buffer: &'static mut F::Page, error: flash::Error) {
+        self.buffer.replace(buffer);
+        if error != flash::Error::CommandComplete {
+            self.finish(Err(ErrorCode::FAIL));
+            return;
+        }
+
+        match self.state.get() {
+            State::AppendWrite => {
+                if let Some((index, mut entry)) = self.pending.get() {
+                    let offset = entry.size as usize;
+                    entry.pages[offset / self.page_len] = self.data_page.get() as u16;
+                    entry.size += self.chunk.get() as u32;
+                    self.pending.set(Some((index, entry)));
+                    self.done.set(self.done.get() + self.chunk.get());
+                }
+                self.continue_with(self.append_next());
+            }
+            State::CommitWrite => {
+                self.sequence.set(self.sequence.get().wrapping_add(1));
+                if let Some((index, entry)) = self.pending.get() {
+                    self.entries.map(|entries| entries[index] = entry);
+                }
+                self.finish(Ok(()));
+            }
+            _ => self.finish(Err(ErrorCode::FAIL)),
+        }
+    }
+
+    fn erase_complete(&self, error: flash::Error) {
+        if error != flash::Error::CommandComplete {
+            self.finish(Err(ErrorCode::FAIL));
+            return;
+        }
+
+        match self.state.get() {
+            State::AppendErase => {
+                self.continue_with(self.write_page(self.data_page.get(), State::AppendWrite))
+            }
+            State::CommitErase => {
+                let page = self.sequence.get().wrapping_add(1) as usize % TABLE_PAGES;
+                self.continue_with(self.write_page(page, State::CommitWrite));
+            }
+            _ => self.finish(Err(ErrorCode::FAIL)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use core::cell::RefCell;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    const PAGE_LEN: usize = 256;
+    const NUM_PAGES: usize = 8;
+
+    type Image = [[u8; PAGE_LEN]; NUM_PAGES];
+
+    struct TestPage([u8; PAGE_LEN]);
+
+    impl Default for TestPage {
+        fn default() -> Self {
+            TestPage([0; PAGE_LEN])
+        }
+    }
+
+    impl AsMut<[u8]> for TestPage {
+        fn as_mut(&mut self) -> &mut [u8] {
+            &mut self.0
+        }
+    }
+
+    #[derive(Copy, Clone)]
+    enum Operation {
+        Read(usize),
+        Write(usize),
+        Erase(usize),
+    }
+
+    /// Flash that completes operations when `run()` is called, and loses
+    /// power in the middle of the operation after the next `power` ones.
+    struct FakeFlash {
+        pages: RefCell<Image>,
+        power: Cell<usize>,
+        operation: Cell<Option<Operation>>,
+        buffer: TakeCell<'static, TestPage>,
+        client: OptionalCell<&'static dyn flash::Client<FakeFlash>>,
+    }
+
+    impl FakeFlash {
+        /// Complete the pending operation, returns whether there was one and
+        /// the power stayed on.
+        fn complete(&self) -> bool {
+            let operation = match self.operation.take() {
+                Some(operation) => operation,
+                None => return false,
+            };
+            let torn = self.power.get() == 0;
+            // Lost power only changes the first half of the page
+            let len = if torn { PAGE_LEN / 2 } else { PAGE_LEN };
+            let mut pages = self.pages.borrow_mut();
+            match operation {
+                Operation::Read(page) => {
+                    self.buffer.map(|buf| buf.0 = pages[page]);
+                }
+                Operation::Write(page) => {
+                    self.buffer
+                        .map(|buf| pages[page][..len].copy_from_slice(&buf.0[..len]));
+                }
+                Operation::Erase(page) => pages[page][..len].fill(0xff),
+            }
+            drop(pages);
+            if torn {
+                return false;
+            }
+            self.power.set(self.power.get().saturating_sub(1));
+
+            let done = flash::Error::CommandComplete;
+            self.client.map(|client| match operation {
+                Operation::Read(_) => client.read_complete(self.buffer.take().unwrap(), done),
+                Operation::Write(_) => client.write_complete(self.buffer.take().unwrap(), done),
+                Operation::Erase(_) => client.erase_complete(done),
+            });
+            true
+        }
+
+        fn run(&self) {
+            while self.complete() {}
+        }
+
+        fn image(&self) -> Image {
+            *self.pages.borrow()
+        }
+    }
+
+    impl Flash for FakeFlash {
+        type Page = TestPage;
+
+        fn read_page(
+            &self,
+            page_number: usize,
+            buf: &'static mut TestPage,
+        ) -> Result<(), (ErrorCode, &'static mut TestPage)> {
+            self.buffer.replace(buf);
+            self.operation.set(Some(Operation::Read(page_number)));
+            Ok(())
+        }
+
+        fn write_page(
+            &self,
+            page_number: usize,
+            buf: &'static mut TestPage,
+        ) -> Result<(), (ErrorCode, &'static mut TestPage)> {
+            self.buffer.replace(buf);
+            self.operation.set(Some(Operation::Write(page_number)));
+            Ok(())
+        }
+
+        fn erase_page(&self, page_number: usize) -> Result<(), ErrorCode> {
+            self.operation.set(Some(Operation::Erase(page_number)));
+            Ok(())
+        }
+    }
+
+    struct TestClient {
+        mounted: Cell<Option<Result<(), ErrorCode>>>,
+        updated: Cell<Option<Result<(), ErrorCode>>>,
+        appended: Cell<Option<Result<(), ErrorCode>>>,
+        read: Cell<Option<Result<usize, ErrorCode>>>,
+        buffer: TakeCell<'static, [u8]>,
+    }
+
+    impl FileSystemClient for TestClient {
+        fn mount_done(&self, result: Result<(), ErrorCode>) {
+            self.mounted.set(Some(result));
+        }
+
+        fn update_done(&self, result: Result<(), ErrorCode>) {
+            self.updated.set(Some(result));
+        }
+
+        fn append_done(&self, result: Result<(), ErrorCode>, data: &'static mut [u8]) {
+            self.appended.set(Some(result));
+            self.buffer.replace(data);
+        }
+
+        fn read_done(&self, result: Result<usize, ErrorCode>, buf: &'static mut [u8]) {
+            self.read.set(Some(result));
+            self.buffer.replace(buf);
+        }
+    }
+
+    struct Board {
+        flash: &'static FakeFlash,
+        fs: &'static FlashFs<'static, FakeFlash>,
+        client: &'static TestClient,
+    }
+
+    impl Board {
+        /// Start from `image`, and lose power after `power` flash
+        /// operations.
+        fn boot(image: Image, power: usize) -> Board {
+            let flash = Box::leak(Box::new(FakeFlash {
+                pages: RefCell::new(image),
+                power: Cell::new(power),
+                operation: Cell::new(None),
+                buffer: TakeCell::empty(),
+                client: OptionalCell::empty(),
+            }));
+            let fs = Box::leak(Box::new(FlashFs::new(
+                &*flash,
+                0,
+                NUM_PAGES,
+                Box::leak(Box::new(TestPage::default())),
+                Box::leak(Box::new([Entry::default(); 4])),
+            )));
+            let client = Box::leak(Box::new(TestClient {
+                mounted: Cell::new(None),
+                updated: Cell::new(None),
+                appended: Cell::new(None),
+                read: Cell::new(None),
+                buffer: TakeCell::empty(),
+            }));
+            flash.client.set(fs);
+            fs.set_client(client);
+            fs.mount().unwrap();
+            flash.run();
+            Board { flash, fs, client }
+        }
+
+        fn create(&self, path: &[u8]) -> Option<Result<(), ErrorCode>> {
+            self.client.updated.set(None);
+            self.fs.create(path, false).unwrap();
+            self.flash.run();
+            self.client.updated.get()
+        }
+
+        fn append(&self, path: &[u8], data: &[u8]) -> Option<Result<(), ErrorCode>> {
+            self.client.appended.set(None);
+            let buf = Box::leak(data.to_vec().into_boxed_slice());
+            assert!(self.fs.append(path, buf, data.len()).is_ok());
+            self.flash.run();
+            self.client.appended.get()
+        }
+
+        fn contents(&self, path: &[u8]) -> Vec<u8> {
+            let (size, _) = self.fs.stat(path).unwrap();
+            if size == 0 {
+                return Vec::new();
+            }
+            let buf = Box::leak(std::vec![0; size].into_boxed_slice());
+            assert!(self.fs.read(path, 0, buf, size).is_ok());
+            self.flash.run();
+            assert_eq!(self.client.read.get(), Some(Ok(size)));
+            self.client.buffer.take().unwrap().to_vec()
+        }
+    }
+
+    #[test]
+    fn append_survives_power_loss() {
+        let blank = [[0xff; PAGE_LEN]; NUM_PAGES];
+        let board = Board::boot(blank, usize::MAX);
+        assert_eq!(board.create(b"log"), Some(Ok(())));
+        assert_eq!(board.append(b"log", b"hello"), Some(Ok(())));
+        let image = board.flash.image();
+
+        // The append spans a partly filled and a new page, and the power is
+        // lost in each of its flash operations in turn.
+        let data: Vec<u8> = (0..300).map(|i| i as u8).collect();
+        let mut expected = b"hello".to_vec();
+        expected.extend_from_slice(&data);
+        for power in 0.. {
+            let board = Board::boot(image, usize::MAX);
+            assert_eq!(board.client.mounted.get(), Some(Ok(())));
+            board.flash.power.set(power);
+            let appended = board.append(b"log", &data);
+
+            let board = Board::boot(board.flash.image(), usize::MAX);
+            assert_eq!(board.client.mounted.get(), Some(Ok(())));
+            let contents = board.contents(b"log");
+            if appended.is_some() {
+                assert_eq!(contents, expected);
+                break;
+            }
+            assert_eq!(contents, b"hello");
+
+            // The file system still works after the lost append.
+            assert_eq!(board.append(b"log", b"!"), Some(Ok(())));
+            assert_eq!(board.contents(b"log"), b"hello!");
+        }
+    }
+
+    #[test]
+    fn format_survives_power_loss() {
+        // Power is lost while formatting blank flash or creating the first
+        // file, the file system mounts and either has the file or not.
+        let blank = [[0xff; PAGE_LEN]; NUM_PAGES];
+        for power in 0.. {
+            let board = Board::boot(blank, power);
+            let created = match board.client.mounted.get() {
+                Some(result) => {
+                    assert_eq!(result, Ok(()));
+                    board.create(b"a")
+                }
+                None => None,
+            };
+
+            let board = Board::boot(board.flash.image(), usize::MAX);
+            assert_eq!(board.client.mounted.get(), Some(Ok(())));
+            match board.fs.stat(b"a") {
+                Ok((0, false)) => {}
+                Err(ErrorCode::NOSUPPORT) => assert_eq!(created, None),
+                result => panic!("unexpected stat of a: {:?}", result),
+            }
+            if created.is_some() {
+                assert_eq!(board.fs.stat(b"a"), Ok((0, false)));
+                break;
+            }
+            assert_eq!(board.create(b"b"), Some(Ok(())));
+        }
+    }
+}
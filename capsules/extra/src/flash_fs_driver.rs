@@ -0,0 +1,352 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Provides userspace with access to a `FlashFs` file system.
+//!
+//! Apps pass the path of a file or directory in read-only allow 0, e.g.
+//! `logs/today`. All apps share the same directory tree.
+//!
+//! Commands
+//! --------
+//!
+//! - 0: Check if the driver is present.
+//! - 1: Create the file at the path.
+//! - 2: Create the directory at the path.
+//! - 3: Remove the file or empty directory at the path.
+//! - 4: Append the data in read-only allow 1 to the file at the path. The
+//!   upcall passes the number of bytes appended, at most `BUF_LEN`.
+//! - 5: Read the file at the path from the offset in `data1` into
+//!   read-write allow 0. The upcall passes the number of bytes read. Fails
+//!   with `SIZE` if the offset is at the end of the file.
+//! - 6: Truncate the file at the path to the length in `data1`.
+//! - 7: Get the size of the file at the path, and whether it is a
+//!   directory. Returns them right away as `success_u32_u32`.
+//! - 8: Copy the name of the entry with the index in `data1` of the
+//!   directory at the path to read-write allow 0. Returns the length of the
+//!   name and whether it is a directory right away as `success_u32_u32`, or
+//!   fails with `NOSUPPORT` if the directory has no more entries.
+//!
+//! Commands 1 to 6 finish with upcall 0, which passes the status first. An
+//! app can have one such command waiting while another app's runs. A path
+//! that doesn't exist fails with `NOSUPPORT`.
+
+use core::cell::Cell;
+
+use capsules_core::driver;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, GrantKernelData, UpcallCount};
+use kernel::hil::flash::Flash;
+use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::{ErrorCode, ProcessId};
+
+use crate::flash_fs::{FileSystemClient, FlashFs, NAME_LEN};
+
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = driver::NUM::FileSystem as usize;
+
+/// The length of the buffer data is appended and read through.
+pub const BUF_LEN: usize = 256;
+
+/// The longest path apps can pass.
+pub const MAX_PATH_LEN: usize = 64;
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    pub const PATH: usize = 0;
+    pub const DATA: usize = 1;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 2;
+}
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    pub const DATA: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+/// Ids for upcalls
+mod upcalls {
+    pub const DONE: usize = 0;
+    /// The number of upcalls the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Command {
+    Create,
+    MakeDirectory,
+    Remove,
+    Append,
+    Read(usize),
+    Truncate(usize),
+}
+
+#[derive(Default)]
+pub struct App {
+    /// The command waiting for another app's command to finish.
+    pending: Option<Command>,
+}
+
+pub struct FlashFsDriver<'a, F: Flash + 'static> {
+    fs: &'a FlashFs<'a, F>,
+    apps: Grant<
+        App,
+        UpcallCount<{ upcalls::COUNT }>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+    /// The app whose command runs.
+    current: OptionalCell<ProcessId>,
+    buffer: TakeCell<'static, [u8]>,
+    /// The bytes appended by the running command.
+    append_len: Cell<usize>,
+}
+
+impl<'a, F: Flash + 'static> FlashFsDriver<'a, F> {
+    pub fn new(
+        fs: &'a FlashFs<'a, F>,
+        grant: Grant<
+            App,
+            UpcallCount<{ upcalls::COUNT }>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+        buffer: &'static mut [u8; BUF_LEN],
+    ) -> FlashFsDriver<'a, F> {
+        FlashFsDriver {
+            fs,
+            apps: grant,
+            current: OptionalCell::empty(),
+            buffer: TakeCell::new(buffer),
+            append_len: Cell::new(0),
+        }
+    }
+
+    /// Copy the path the app allowed to `path`, returning its length.
+    fn copy_path(
+        kernel_data: &GrantKernelData,
+        path: &mut [u8; MAX_PATH_LEN],
+    ) -> Result<usize, ErrorCode> {
+        kernel_data
+            .get_readonly_processbuffer(ro_allow::PATH)
+            .and_then(|buffer| {
+                buffer.enter(|allowed| {
+                    if allowed.len() > MAX_PATH_LEN {
+                        return Err(ErrorCode::SIZE);
+                    }
+                    allowed.copy_to_slice(&mut path[..allowed.len()]);
+                    Ok(allowed.len())
+                })
+            })
+            .unwrap_or(Err(ErrorCode::RESERVE))
+    }
+
+    /// Start `command` of `processid`.
+    fn start(&self, processid: ProcessId, command: Command) -> Result<(), ErrorCode> {
+        self.apps
+            .enter(processid, |_, kernel_data| {
+                let mut path = [0; MAX_PATH_LEN];
+                let path_len = Self::copy_path(kernel_data, &mut path)?;
+                let path = &path[..path_len];
+
+                match command {
+                    Command::Create => self.fs.create(path, false),
+                    Command::MakeDirectory => self.fs.create(path, true),
+                    Command::Remove => self.fs.remove(path),
+                    Command::Truncate(len) => self.fs.truncate(path, len),
+                    Command::Append => {
+                        let buffer = self.buffer.take().ok_or(ErrorCode::BUSY)?;
+                        let len = kernel_data
+                            .get_readonly_processbuffer(ro_allow::DATA)
+                            .and_then(|data| {
+                                data.enter(|data| {
+                                    let len = data.len().min(buffer.len());
+                                    data[..len].copy_to_slice(&mut buffer[..len]);
+                                    len
+                                })
+                            })
+                            .unwrap_or(0);
+
+                        self.append_len.set(len);
+                        self.fs.append(path, buffer, len).map_err(|(e, buffer)| {
+                            self.buffer.replace(buffer);
+                            e
+                        })
+                    }
+                    Command::Read(offset) => {
+                        let buffer = self.buffer.take().ok_or(ErrorCode::BUSY)?;
+                        let len = kernel_data
+                            .get_readwrite_processbuffer(rw_allow::DATA)
+                            .map_or(0, |data| data.len())
+                            .min(buffer.len());
+
+                        self.fs
+                            .read(path, offset, buffer, len)
+                            .map_err(|(e, buffer)| {
+                                self.buffer.replace(buffer);
+                                e
+                            })
+                    }
+                }
+            })
+            .unwrap_or_else(|err| Err(err.into()))?;
+
+        self.current.set(processid);
+        Ok(())
+    }
+
+    /// Report the end of the running command with `args` after the status,
+    /// and start the next waiting one.
+    fn finish(&self, result: Result<(), ErrorCode>, args: (usize, usize)) {
+        self.current.take().map(|processid| {
+            let _ = self.apps.enter(processid, |_, kernel_data| {
+                kernel_data
+                    .schedule_upcall(
+                        upcalls::DONE,
+                        (kernel::errorcode::into_statuscode(result), args.0, args.1),
+                    )
+                    .ok();
+            });
+        });
+
+        for app in self.apps.iter() {
+            let processid = app.processid();
+            let command = app.enter(|app, _| app.pending.take());
+            if let Some(command) = command {
+                match self.start(processid, command) {
+                    Ok(()) => break,
+                    Err(e) => {
+                        let _ = self.apps.enter(processid, |_, kernel_data| {
+                            kernel_data
+                                .schedule_upcall(
+                                    upcalls::DONE,
+                                    (kernel::errorcode::into_statuscode(Err(e)), 0, 0),
+                                )
+                                .ok();
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    /// Run the synchronous commands, which read the table in RAM.
+    fn query(&self, command_num: usize, index: usize, processid: ProcessId) -> CommandReturn {
+        self.apps
+            .enter(processid, |_, kernel_data| {
+                let mut path = [0; MAX_PATH_LEN];
+                let path_len = Self::copy_path(kernel_data, &mut path)?;
+                let path = &path[..path_len];
+
+                if command_num == 7 {
+                    return self.fs.stat(path);
+                }
+
+                let mut name = [0; NAME_LEN];
+                let (len, directory) = self.fs.directory_entry(path, index, &mut name)?;
+                kernel_data
+                    .get_readwrite_processbuffer(rw_allow::DATA)
+                    .and_then(|data| {
+                        data.mut_enter(|data| {
+                            let copied = data.len().min(len);
+                            data[..copied].copy_from_slice(&name[..copied]);
+                        })
+                    })
+                    .map_err(ErrorCode::from)?;
+                Ok((len, directory))
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+            .map_or_else(CommandReturn::failure, |(value, directory)| {
+                CommandReturn::success_u32_u32(value as u32, directory as u32)
+            })
+    }
+}
+
+impl<'a, F: Flash + 'static> FileSystemClient for FlashFsDriver<'a, F> {
+    fn mount_done(&self, _result: Result<(), ErrorCode>) {}
+
+    fn update_done(&self, result: Result<(), ErrorCode>) {
+        self.finish(result, (0, 0));
+    }
+
+    fn append_done(&self, result: Result<(), ErrorCode>, data: &'static mut [u8]) {
+        self.buffer.replace(data);
+        self.finish(result, (self.append_len.get(), 0));
+    }
+
+    fn read_done(&self, result: Result<usize, ErrorCode>, buf: &'static mut [u8]) {
+        let result = result.and_then(|len| {
+            self.current.map_or(Err(ErrorCode::RESERVE), |processid| {
+                self.apps
+                    .enter(*processid, |_, kernel_data| {
+                        kernel_data
+                            .get_readwrite_processbuffer(rw_allow::DATA)
+                            .and_then(|data| {
+                                data.mut_enter(|data| {
+                                    let copied = data.len().min(len);
+                                    data[..copied].copy_from_slice(&buf[..copied]);
+                                    copied
+                                })
+                            })
+                            .map_err(ErrorCode::from)
+                    })
+                    .unwrap_or_else(|err| Err(err.into()))
+            })
+        });
+        self.buffer.replace(buf);
+
+        match result {
+            Ok(len) => self.finish(Ok(()), (len, 0)),
+            Err(e) => self.finish(Err(e), (0, 0)),
+        }
+    }
+}
+
+impl<'a, F: Flash + 'static> SyscallDriver for FlashFsDriver<'a, F> {
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        _data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        let command = match command_num {
+            0 => return CommandReturn::success(),
+            1 => Command::Create,
+            2 => Command::MakeDirectory,
+            3 => Command::Remove,
+            4 => Command::Append,
+            5 => Command::Read(data1),
+            6 => Command::Truncate(data1),
+            7 | 8 => return self.query(command_num, data1, processid),
+            _ => return CommandReturn::failure(ErrorCode::NOSUPPORT),
+        };
+
+        if self.current.is_none() {
+            match self.start(processid, command) {
+                Ok(()) => CommandReturn::success(),
+                Err(e) => CommandReturn::failure(e),
+            }
+        } else {
+            // Another command runs, wait for it unless this app already
+            // has a command waiting
+            self.apps
+                .enter(processid, |app, _| {
+                    if app.pending.is_some() {
+                        CommandReturn::failure(ErrorCode::BUSY)
+                    } else {
+                        app.pending = Some(command);
+                        CommandReturn::success()
+                    }
+                })
+                .unwrap_or_else(|err| err.into())
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
@@ -0,0 +1,403 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Provides a Bluetooth host stack in userspace with an HCI transport to an
+//! external Bluetooth controller over UART (H:4).
+//!
+//! On the UART, every HCI packet is preceded by a packet indicator byte. The
+//! capsule sends HCI commands and ACL data packets from the process, and
+//! splits the bytes from the controller into HCI events and ACL data packets,
+//! which it copies to the process. Synchronous and isochronous data packets
+//! are dropped.
+//!
+//! The controller serves a single host, so one process at a time uses the
+//! driver: the first one to send a packet, until it exits. Packets the
+//! controller sends while there is no such process are dropped.
+//!
+//! The UART is a `UartDevice` of a mux, and the controller should be the only
+//! one sending on it.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let hci = components::hci_uart::HciUartComponent::new(
+//!     board_kernel,
+//!     capsules_extra::hci_uart::DRIVER_NUM,
+//!     controller_uart_mux,
+//! )
+//! .finalize(components::hci_uart_component_static!());
+//! ```
+
+use core::cell::Cell;
+use core::cmp::min;
+
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::uart;
+use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::{ErrorCode, ProcessId};
+
+/// Syscall driver number.
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::BluetoothHci as usize;
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    pub const COMMAND: usize = 0;
+    pub const ACL_DATA: usize = 1;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 2;
+}
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    pub const EVENT: usize = 0;
+    pub const ACL_DATA: usize = 1;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 2;
+}
+
+mod upcall {
+    pub const SENT: usize = 0;
+    pub const EVENT: usize = 1;
+    pub const ACL_DATA: usize = 2;
+    pub const COUNT: u8 = 3;
+}
+
+// Bluetooth Core Specification Version 5.3, Vol 4, Part A, section 2
+// HCI packet indicators
+const COMMAND: u8 = 0x01;
+const ACL_DATA: u8 = 0x02;
+const SYNCHRONOUS_DATA: u8 = 0x03;
+const EVENT: u8 = 0x04;
+const ISO_DATA: u8 = 0x05;
+
+// Bluetooth Core Specification Version 5.3, Vol 4, Part E, section 5.4
+// HCI packet headers, which end with the length of the parameters or data
+const COMMAND_HEADER_LEN: usize = 3;
+const ACL_DATA_HEADER_LEN: usize = 4;
+const SYNCHRONOUS_DATA_HEADER_LEN: usize = 3;
+const EVENT_HEADER_LEN: usize = 2;
+const ISO_DATA_HEADER_LEN: usize = 4;
+const MAX_HEADER_LEN: usize = 4;
+
+/// The length of the transmit and receive buffers. Packets with their
+/// indicator must fit in the transmit buffer and payloads of received packets
+/// in the receive buffer: all events do, as do ACL data packets of LE
+/// controllers.
+pub const BUF_LEN: usize = 260;
+
+/// The length of the header of packets with `indicator`.
+fn header_len(indicator: u8) -> Option<usize> {
+    match indicator {
+        ACL_DATA => Some(ACL_DATA_HEADER_LEN),
+        SYNCHRONOUS_DATA => Some(SYNCHRONOUS_DATA_HEADER_LEN),
+        EVENT => Some(EVENT_HEADER_LEN),
+        ISO_DATA => Some(ISO_DATA_HEADER_LEN),
+        _ => None,
+    }
+}
+
+/// The length of the payload that follows `header`.
+fn payload_len(indicator: u8, header: &[u8]) -> usize {
+    match indicator {
+        ACL_DATA => u16::from_le_bytes([header[2], header[3]]) as usize,
+        SYNCHRONOUS_DATA => header[2] as usize,
+        EVENT => header[1] as usize,
+        // The top two bits of the length are reserved
+        ISO_DATA => (u16::from_le_bytes([header[2], header[3]]) & 0x3fff) as usize,
+        _ => 0,
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum RxState {
+    /// Receiving the indicator of the next packet.
+    Indicator,
+    /// Receiving the header of a packet.
+    Header(u8),
+    /// Receiving the payload of a packet.
+    Payload(u8),
+    /// Dropping the rest of a packet, of this many bytes.
+    Discard(usize),
+}
+
+#[derive(Default)]
+pub struct App;
+
+pub struct HciUart<'a> {
+    uart: &'a dyn uart::UartData<'a>,
+    apps: Grant<
+        App,
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+    /// The process that runs the host stack.
+    host: OptionalCell<ProcessId>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    rx_state: Cell<RxState>,
+    /// The header of the packet being received.
+    rx_header: Cell<[u8; MAX_HEADER_LEN]>,
+}
+
+impl<'a> HciUart<'a> {
+    /// `tx_buffer` and `rx_buffer` should be `BUF_LEN` bytes long.
+    pub fn new(
+        uart: &'a dyn uart::UartData<'a>,
+        grant: Grant<
+            App,
+            UpcallCount<{ upcall::COUNT }>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+        tx_buffer: &'static mut [u8],
+        rx_buffer: &'static mut [u8],
+    ) -> HciUart<'a> {
+        HciUart {
+            uart,
+            apps: grant,
+            host: OptionalCell::empty(),
+            tx_buffer: TakeCell::new(tx_buffer),
+            rx_buffer: TakeCell::new(rx_buffer),
+            rx_state: Cell::new(RxState::Indicator),
+            rx_header: Cell::new([0; MAX_HEADER_LEN]),
+        }
+    }
+
+    /// Start receiving from the controller.
+    pub fn initialize(&self) {
+        if let Some(buffer) = self.rx_buffer.take() {
+            self.rx_state.set(RxState::Indicator);
+            self.receive(buffer, 1);
+        }
+    }
+
+    fn receive(&self, buffer: &'static mut [u8], len: usize) {
+        if let Err((_, buffer)) = self.uart.receive_buffer(buffer, len) {
+            self.rx_buffer.replace(buffer);
+        }
+    }
+
+    /// Make `processid` the host, unless another process is.
+    fn claim(&self, processid: ProcessId) -> Result<(), ErrorCode> {
+        let taken = self.host.map_or(false, |host| {
+            *host != processid && self.apps.enter(*host, |_, _| {}).is_ok()
+        });
+        if taken {
+            Err(ErrorCode::RESERVE)
+        } else {
+            self.host.set(processid);
+            Ok(())
+        }
+    }
+
+    /// Send the first `len` bytes of the read-only allow `allow_num` of
+    /// `processid` to the controller, after `indicator`.
+    fn send(
+        &self,
+        processid: ProcessId,
+        indicator: u8,
+        allow_num: usize,
+        header_len: usize,
+        len: usize,
+    ) -> Result<(), ErrorCode> {
+        self.claim(processid)?;
+        let buffer = self.tx_buffer.take().ok_or(ErrorCode::BUSY)?;
+        if len < header_len || len >= buffer.len() {
+            self.tx_buffer.replace(buffer);
+            return Err(ErrorCode::SIZE);
+        }
+        let result = self
+            .apps
+            .enter(processid, |_, kernel_data| {
+                kernel_data
+                    .get_readonly_processbuffer(allow_num)
+                    .and_then(|packet| {
+                        packet.enter(|packet| {
+                            if packet.len() < len {
+                                return Err(ErrorCode::SIZE);
+                            }
+                            buffer[0] = indicator;
+                            packet[..len].copy_to_slice(&mut buffer[1..len + 1]);
+                            Ok(())
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::NOMEM))
+            })
+            .unwrap_or_else(|err| Err(err.into()));
+        match result {
+            Ok(()) => self
+                .uart
+                .transmit_buffer(buffer, len + 1)
+                .map_err(|(err, buffer)| {
+                    self.tx_buffer.replace(buffer);
+                    err
+                }),
+            Err(err) => {
+                self.tx_buffer.replace(buffer);
+                Err(err)
+            }
+        }
+    }
+
+    /// Copy a received event or ACL data packet to the host.
+    fn deliver(&self, indicator: u8, payload: &[u8]) {
+        let (allow_num, upcall_num) = match indicator {
+            EVENT => (rw_allow::EVENT, upcall::EVENT),
+            ACL_DATA => (rw_allow::ACL_DATA, upcall::ACL_DATA),
+            _ => return,
+        };
+        let header_len = header_len(indicator).unwrap_or(0);
+        let header = self.rx_header.get();
+        let packet_len = header_len + payload.len();
+        if let Some(host) = self.host.take() {
+            let result = self.apps.enter(host, |_, kernel_data| {
+                let copied = kernel_data
+                    .get_readwrite_processbuffer(allow_num)
+                    .and_then(|buffer| {
+                        buffer.mut_enter(|buffer| {
+                            if buffer.len() < packet_len {
+                                return 0;
+                            }
+                            buffer[..header_len].copy_from_slice(&header[..header_len]);
+                            buffer[header_len..packet_len].copy_from_slice(payload);
+                            packet_len
+                        })
+                    })
+                    .unwrap_or(0);
+                let status = if copied < packet_len {
+                    Err(ErrorCode::SIZE)
+                } else {
+                    Ok(())
+                };
+                kernel_data
+                    .schedule_upcall(upcall_num, (into_statuscode(status), copied, 0))
+                    .ok();
+            });
+            // Keep the host while it exists
+            if result.is_ok() {
+                self.host.set(host);
+            }
+        }
+    }
+}
+
+impl SyscallDriver for HciUart<'_> {
+    /// Send HCI commands and ACL data to the controller.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver existence check.
+    /// - `1`: Send the HCI command in the first `arg1` bytes of read-only
+    ///   allow 0.
+    /// - `2`: Send the ACL data packet in the first `arg1` bytes of read-only
+    ///   allow 1.
+    fn command(
+        &self,
+        command_num: usize,
+        arg1: usize,
+        _: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+            1 => self
+                .send(
+                    processid,
+                    COMMAND,
+                    ro_allow::COMMAND,
+                    COMMAND_HEADER_LEN,
+                    arg1,
+                )
+                .into(),
+            2 => self
+                .send(
+                    processid,
+                    ACL_DATA,
+                    ro_allow::ACL_DATA,
+                    ACL_DATA_HEADER_LEN,
+                    arg1,
+                )
+                .into(),
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+impl uart::TransmitClient for HciUart<'_> {
+    fn transmitted_buffer(
+        &self,
+        buffer: &'static mut [u8],
+        _tx_len: usize,
+        rcode: Result<(), ErrorCode>,
+    ) {
+        self.tx_buffer.replace(buffer);
+        self.host.map(|host| {
+            let _ = self.apps.enter(*host, |_, kernel_data| {
+                kernel_data
+                    .schedule_upcall(upcall::SENT, (into_statuscode(rcode), 0, 0))
+                    .ok();
+            });
+        });
+    }
+
+    fn transmitted_word(&self, _rcode: Result<(), ErrorCode>) {}
+}
+
+impl uart::ReceiveClient for HciUart<'_> {
+    fn received_buffer(
+        &self,
+        buffer: &'static mut [u8],
+        rx_len: usize,
+        rcode: Result<(), ErrorCode>,
+        _error: uart::Error,
+    ) {
+        let (next_state, next_len) = match self.rx_state.get() {
+            // On errors, look for the indicator of the next packet
+            _ if rcode.is_err() => (RxState::Indicator, 1),
+            RxState::Indicator => match header_len(buffer[0]) {
+                Some(len) => (RxState::Header(buffer[0]), len),
+                None => (RxState::Indicator, 1),
+            },
+            RxState::Header(indicator) => {
+                let mut header = [0; MAX_HEADER_LEN];
+                header[..rx_len].copy_from_slice(&buffer[..rx_len]);
+                self.rx_header.set(header);
+                let len = payload_len(indicator, &header);
+                if len == 0 {
+                    self.deliver(indicator, &[]);
+                    (RxState::Indicator, 1)
+                } else if len <= buffer.len() {
+                    (RxState::Payload(indicator), len)
+                } else {
+                    (RxState::Discard(len), buffer.len())
+                }
+            }
+            RxState::Payload(indicator) => {
+                self.deliver(indicator, &buffer[..rx_len]);
+                (RxState::Indicator, 1)
+            }
+            RxState::Discard(len) => {
+                let remaining = len - rx_len;
+                if remaining == 0 {
+                    (RxState::Indicator, 1)
+                } else {
+                    (RxState::Discard(remaining), min(remaining, buffer.len()))
+                }
+            }
+        };
+        self.rx_state.set(next_state);
+        self.receive(buffer, next_len);
+    }
+
+    fn received_word(&self, _word: u32, _rcode: Result<(), ErrorCode>, _error: uart::Error) {}
+}
@@ -0,0 +1,501 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Binary control channel for host tools.
+//!
+//! The process console is meant for humans, and parsing its output is
+//! fragile. This capsule serves a framed binary protocol with the same kind
+//! of functionality on a dedicated UART (or USB CDC) interface, so that test
+//! rigs and other host automation can list processes, read their counters,
+//! fetch crash information and control processes reliably.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let host_control = components::host_control::HostControlComponent::new(
+//!     board_kernel,
+//!     control_uart_mux,
+//! )
+//! .finalize(components::host_control_component_static!());
+//! host_control.set_reset(&cortexm4::scb::ScbReset);
+//! let _ = host_control.start();
+//! ```
+//!
+//! Framing
+//! -------
+//!
+//! Requests and responses use the same frame, with all integers little
+//! endian:
+//!
+//! ```text
+//! 0xA5 | command (u8) | sequence (u8) | length (u16) | payload | CRC (u16)
+//! ```
+//!
+//! The CRC is CRC-16/CCITT-FALSE over everything after the sync byte and
+//! before the CRC. Frames with a bad CRC are dropped, so the host retries
+//! after a timeout. A response repeats the sequence number of the request,
+//! sets the top bit of the command, and starts its payload with a status
+//! byte: `0` for success or the `ErrorCode` value otherwise.
+//!
+//! Commands
+//! --------
+//!
+//! Processes are identified by their index, counting from 0 in the order the
+//! kernel stores them.
+//!
+//! - `0x01` Ping. Response: protocol version (u8), kernel major and minor
+//!   version (u16 each), frames dropped for a bad CRC (u16), frames dropped
+//!   because a response was still being sent (u16).
+//! - `0x02` List processes, starting at index `start` (u8, optional).
+//!   Response: number of processes (u8), then for each process that fits:
+//!   index (u8), state (u8), restart count (u16), name length (u8), name.
+//!   Hosts ask again starting after the last listed index to get the rest.
+//! - `0x03` Process counters of process `index` (u8). Response: syscalls,
+//!   dropped upcalls, timeslice expirations, restarts, context switches (u32
+//!   each), CPU time in microseconds (u64), allocated grant bytes, grant high
+//!   water mark and failed grant allocations (u32 each).
+//! - `0x04` Crash dump of process `index` (u8). Response: fault reason (u8,
+//!   `0` if none recorded), state (u8), length of the stored state (u16) and
+//!   the architecture-specific stored state (registers) of the process.
+//! - `0x05` Reboot reason. Response: recorded reboot reason (u8, `0` if
+//!   none), reset cause (u8, `0` if unknown), message length (u8), message.
+//! - `0x06` Control process `index` (u8) with `action` (u8): `1` stop, `2`
+//!   resume, `3` fault, `4` terminate, `5` boot a terminated process.
+//! - `0x07` Reset the chip, into the bootloader if `mode` (u8) is `1`. Does
+//!   not respond if the reset succeeds.
+//!
+//! Process states are numbered `0` Running, `1` Yielded, `2` StoppedRunning,
+//! `3` StoppedYielded, `4` Faulted, `5` Terminated, `6` CredentialsUnchecked,
+//! `7` CredentialsApproved and `8` CredentialsFailed. Reset causes are
+//! numbered `1` PowerOn, `2` Brownout, `3` ExternalPin, `4` Watchdog, `5`
+//! Software and `6` Other.
+
+use core::cell::Cell;
+
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::hil::reset::{Reset, ResetCause, ResetMode};
+use kernel::hil::uart;
+use kernel::process::{FaultReason, Process, State};
+use kernel::reboot_reason::{self, RebootReason};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::{ErrorCode, Kernel};
+
+/// Version of the protocol, reported by `Ping`.
+pub const PROTOCOL_VERSION: u8 = 1;
+
+/// First byte of every frame.
+pub const SYNC: u8 = 0xA5;
+
+const HEADER_LEN: usize = 5;
+const CRC_LEN: usize = 2;
+
+/// Longest payload of a frame, in bytes.
+pub const MAX_PAYLOAD_LEN: usize = 250;
+
+/// Size of the frame and response buffers.
+pub const BUF_LEN: usize = HEADER_LEN + MAX_PAYLOAD_LEN + CRC_LEN;
+
+/// Size of the UART receive buffer.
+pub const READ_BUF_LEN: usize = 1;
+
+/// Longest process name listed, in bytes.
+const NAME_LEN: usize = 32;
+
+mod command {
+    pub const PING: u8 = 0x01;
+    pub const LIST: u8 = 0x02;
+    pub const COUNTERS: u8 = 0x03;
+    pub const CRASH_DUMP: u8 = 0x04;
+    pub const REBOOT_REASON: u8 = 0x05;
+    pub const CONTROL: u8 = 0x06;
+    pub const RESET: u8 = 0x07;
+}
+
+/// CRC-16/CCITT-FALSE.
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0xffff, |crc, byte| {
+        (0..8).fold(crc ^ ((*byte as u16) << 8), |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+fn state_code(state: State) -> u8 {
+    match state {
+        State::Running => 0,
+        State::Yielded => 1,
+        State::StoppedRunning => 2,
+        State::StoppedYielded => 3,
+        State::Faulted => 4,
+        State::Terminated => 5,
+        State::CredentialsUnchecked => 6,
+        State::CredentialsApproved => 7,
+        State::CredentialsFailed => 8,
+    }
+}
+
+fn reset_cause_code(cause: ResetCause) -> u8 {
+    match cause {
+        ResetCause::PowerOn => 1,
+        ResetCause::Brownout => 2,
+        ResetCause::ExternalPin => 3,
+        ResetCause::Watchdog => 4,
+        ResetCause::Software => 5,
+        ResetCause::Other => 6,
+    }
+}
+
+/// Builds the payload of a response after the status byte.
+struct Payload<'b> {
+    buffer: &'b mut [u8],
+    len: usize,
+}
+
+impl Payload<'_> {
+    /// Append `bytes`, failing with `SIZE` if they do not fit.
+    fn push(&mut self, bytes: &[u8]) -> Result<(), ErrorCode> {
+        let end = self.len + bytes.len();
+        if end > self.buffer.len() {
+            return Err(ErrorCode::SIZE);
+        }
+        self.buffer[self.len..end].copy_from_slice(bytes);
+        self.len = end;
+        Ok(())
+    }
+
+    fn push_u32(&mut self, value: usize) -> Result<(), ErrorCode> {
+        self.push(&(value as u32).to_le_bytes())
+    }
+
+    fn remaining(&self) -> usize {
+        self.buffer.len() - self.len
+    }
+}
+
+pub struct HostControl<'a, C: ProcessManagementCapability> {
+    uart: &'a dyn uart::UartData<'a>,
+    kernel: &'static Kernel,
+    reset: OptionalCell<&'a dyn Reset>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    frame: TakeCell<'static, [u8]>,
+    frame_len: Cell<usize>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    crc_errors: Cell<u16>,
+    busy_drops: Cell<u16>,
+    running: Cell<bool>,
+    capability: C,
+}
+
+impl<'a, C: ProcessManagementCapability> HostControl<'a, C> {
+    pub fn new(
+        uart: &'a dyn uart::UartData<'a>,
+        kernel: &'static Kernel,
+        rx_buffer: &'static mut [u8; READ_BUF_LEN],
+        frame: &'static mut [u8; BUF_LEN],
+        tx_buffer: &'static mut [u8; BUF_LEN],
+        capability: C,
+    ) -> HostControl<'a, C> {
+        HostControl {
+            uart,
+            kernel,
+            reset: OptionalCell::empty(),
+            rx_buffer: TakeCell::new(rx_buffer),
+            frame: TakeCell::new(frame),
+            frame_len: Cell::new(0),
+            tx_buffer: TakeCell::new(tx_buffer),
+            crc_errors: Cell::new(0),
+            busy_drops: Cell::new(0),
+            running: Cell::new(false),
+            capability,
+        }
+    }
+
+    /// Allow hosts to reset the chip.
+    pub fn set_reset(&self, reset: &'a dyn Reset) {
+        self.reset.set(reset);
+    }
+
+    /// Start receiving requests.
+    pub fn start(&self) -> Result<(), ErrorCode> {
+        if self.running.get() {
+            return Err(ErrorCode::ALREADY);
+        }
+        self.rx_buffer
+            .take()
+            .map_or(Err(ErrorCode::BUSY), |buffer| {
+                self.running.set(true);
+                self.uart.receive_buffer(buffer, 1).map_err(|(e, buffer)| {
+                    self.running.set(false);
+                    self.rx_buffer.replace(buffer);
+                    e
+                })
+            })
+    }
+
+    /// Add a received byte to the frame, and handle the frame once it is
+    /// complete.
+    fn receive_byte(&self, byte: u8) {
+        let complete = self.frame.map_or(false, |frame| {
+            let len = self.frame_len.get();
+            if len == 0 && byte != SYNC {
+                return false;
+            }
+            frame[len] = byte;
+            let len = len + 1;
+            self.frame_len.set(len);
+            if len < HEADER_LEN {
+                return false;
+            }
+            let payload_len = u16::from_le_bytes([frame[3], frame[4]]) as usize;
+            if payload_len > MAX_PAYLOAD_LEN {
+                self.frame_len.set(0);
+                return false;
+            }
+            len == HEADER_LEN + payload_len + CRC_LEN
+        });
+        if complete {
+            let len = self.frame_len.replace(0);
+            self.frame.map(|frame| self.handle_frame(&frame[..len]));
+        }
+    }
+
+    fn handle_frame(&self, frame: &[u8]) {
+        let crc_start = frame.len() - CRC_LEN;
+        let crc = u16::from_le_bytes([frame[crc_start], frame[crc_start + 1]]);
+        if crc16(&frame[1..crc_start]) != crc {
+            self.crc_errors.set(self.crc_errors.get().wrapping_add(1));
+            return;
+        }
+        let tx_buffer = match self.tx_buffer.take() {
+            Some(tx_buffer) => tx_buffer,
+            None => {
+                self.busy_drops.set(self.busy_drops.get().wrapping_add(1));
+                return;
+            }
+        };
+
+        let command = frame[1];
+        let request = &frame[HEADER_LEN..crc_start];
+        let mut payload = Payload {
+            buffer: &mut tx_buffer[HEADER_LEN + 1..HEADER_LEN + MAX_PAYLOAD_LEN],
+            len: 0,
+        };
+        let status = match self.handle_command(command, request, &mut payload) {
+            Ok(()) => 0,
+            Err(e) => {
+                payload.len = 0;
+                usize::from(e) as u8
+            }
+        };
+        let payload_len = payload.len + 1;
+
+        tx_buffer[0] = SYNC;
+        tx_buffer[1] = command | 0x80;
+        tx_buffer[2] = frame[2];
+        tx_buffer[3..5].copy_from_slice(&(payload_len as u16).to_le_bytes());
+        tx_buffer[HEADER_LEN] = status;
+        let crc_start = HEADER_LEN + payload_len;
+        let crc = crc16(&tx_buffer[1..crc_start]);
+        tx_buffer[crc_start..crc_start + CRC_LEN].copy_from_slice(&crc.to_le_bytes());
+
+        if let Err((_, tx_buffer)) = self.uart.transmit_buffer(tx_buffer, crc_start + CRC_LEN) {
+            self.tx_buffer.replace(tx_buffer);
+        }
+    }
+
+    /// Run `closure` on the process at `index`.
+    fn nth_process<F, R>(&self, index: Option<&u8>, closure: F) -> Result<R, ErrorCode>
+    where
+        F: FnOnce(&dyn Process) -> Result<R, ErrorCode>,
+    {
+        let index = *index.ok_or(ErrorCode::INVAL)? as usize;
+        let mut count = 0;
+        let mut closure = Some(closure);
+        let mut result = Err(ErrorCode::INVAL);
+        self.kernel
+            .process_each_capability(&self.capability, |process| {
+                if count == index {
+                    if let Some(closure) = closure.take() {
+                        result = closure(process);
+                    }
+                }
+                count += 1;
+            });
+        result
+    }
+
+    fn handle_command(
+        &self,
+        command: u8,
+        request: &[u8],
+        payload: &mut Payload,
+    ) -> Result<(), ErrorCode> {
+        match command {
+            command::PING => {
+                payload.push(&[PROTOCOL_VERSION])?;
+                payload.push(&kernel::KERNEL_MAJOR_VERSION.to_le_bytes())?;
+                payload.push(&kernel::KERNEL_MINOR_VERSION.to_le_bytes())?;
+                payload.push(&self.crc_errors.get().to_le_bytes())?;
+                payload.push(&self.busy_drops.get().to_le_bytes())
+            }
+            command::LIST => {
+                self.list_processes(request.first().map_or(0, |start| *start), payload)
+            }
+            command::COUNTERS => self.nth_process(request.first(), |process| {
+                let grant_stats = process.debug_grant_stats();
+                payload.push_u32(process.debug_syscall_count())?;
+                payload.push_u32(process.debug_dropped_upcall_count())?;
+                payload.push_u32(process.debug_timeslice_expiration_count())?;
+                payload.push_u32(process.get_restart_count())?;
+                payload.push_u32(process.debug_context_switch_count())?;
+                payload.push(&process.debug_cpu_time_us().to_le_bytes())?;
+                payload.push_u32(grant_stats.allocated)?;
+                payload.push_u32(grant_stats.high_water)?;
+                payload.push_u32(grant_stats.failed_allocations)
+            }),
+            command::CRASH_DUMP => self.nth_process(request.first(), |process| {
+                let reason = process
+                    .get_fault_reason()
+                    .map_or(0, |reason: FaultReason| reason as u8);
+                payload.push(&[reason, state_code(process.get_state())])?;
+                let len_offset = payload.len;
+                payload.push(&[0, 0])?;
+                let len = process.get_stored_state(&mut payload.buffer[payload.len..])?;
+                payload.buffer[len_offset..len_offset + 2]
+                    .copy_from_slice(&(len as u16).to_le_bytes());
+                payload.len += len;
+                Ok(())
+            }),
+            command::REBOOT_REASON => {
+                let record = reboot_reason::last();
+                let reason = record
+                    .and_then(|record| record.reason)
+                    .map_or(0, |reason| reason as u8);
+                let cause = record
+                    .and_then(|record| record.cause)
+                    .map_or(0, reset_cause_code);
+                payload.push(&[reason, cause])?;
+                let message = record
+                    .as_ref()
+                    .map_or(&[][..], |record| record.message().as_bytes());
+                let message = &message[..message.len().min(payload.remaining() - 1)];
+                payload.push(&[message.len() as u8])?;
+                payload.push(message)
+            }
+            command::CONTROL => {
+                let action = *request.get(1).ok_or(ErrorCode::INVAL)?;
+                self.nth_process(request.first(), |process| match action {
+                    1 => {
+                        process.stop();
+                        Ok(())
+                    }
+                    2 => {
+                        process.resume();
+                        Ok(())
+                    }
+                    3 => {
+                        process.set_fault_state(FaultReason::Requested);
+                        Ok(())
+                    }
+                    4 => {
+                        process.terminate(None);
+                        Ok(())
+                    }
+                    5 if process.get_state() == State::Terminated => {
+                        process.try_restart(None);
+                        Ok(())
+                    }
+                    5 => Err(ErrorCode::BUSY),
+                    _ => Err(ErrorCode::INVAL),
+                })
+            }
+            command::RESET => {
+                let mode = match request.first() {
+                    Some(1) => ResetMode::Bootloader,
+                    _ => ResetMode::Normal,
+                };
+                self.reset.map_or(Err(ErrorCode::NOSUPPORT), |reset| {
+                    if !reset.supports(mode) {
+                        return Err(ErrorCode::NOSUPPORT);
+                    }
+                    let reason = match mode {
+                        ResetMode::Normal => RebootReason::UserCommand,
+                        ResetMode::Bootloader => RebootReason::Update,
+                    };
+                    reboot_reason::record(reason, format_args!("host control"));
+                    Err(reset.reset(mode))
+                })
+            }
+            _ => Err(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn list_processes(&self, start: u8, payload: &mut Payload) -> Result<(), ErrorCode> {
+        let mut count = 0;
+        self.kernel
+            .process_each_capability(&self.capability, |_| count += 1);
+        payload.push(&[count as u8])?;
+
+        let mut index = 0;
+        let mut full = false;
+        self.kernel
+            .process_each_capability(&self.capability, |process| {
+                if index >= start as usize && !full {
+                    let name = process.get_process_name().as_bytes();
+                    let name = &name[..name.len().min(NAME_LEN)];
+                    let restarts = process.get_restart_count().min(u16::MAX as usize) as u16;
+                    let restarts = restarts.to_le_bytes();
+                    let entry = [
+                        index as u8,
+                        state_code(process.get_state()),
+                        restarts[0],
+                        restarts[1],
+                        name.len() as u8,
+                    ];
+                    if payload.remaining() >= entry.len() + name.len() {
+                        let _ = payload.push(&entry);
+                        let _ = payload.push(name);
+                    } else {
+                        full = true;
+                    }
+                }
+                index += 1;
+            });
+        Ok(())
+    }
+}
+
+impl<'a, C: ProcessManagementCapability> uart::TransmitClient for HostControl<'a, C> {
+    fn transmitted_buffer(
+        &self,
+        buffer: &'static mut [u8],
+        _tx_len: usize,
+        _rcode: Result<(), ErrorCode>,
+    ) {
+        self.tx_buffer.replace(buffer);
+    }
+}
+
+impl<'a, C: ProcessManagementCapability> uart::ReceiveClient for HostControl<'a, C> {
+    fn received_buffer(
+        &self,
+        buffer: &'static mut [u8],
+        rx_len: usize,
+        rcode: Result<(), ErrorCode>,
+        _error: uart::Error,
+    ) {
+        if rcode.is_ok() && rx_len == 1 {
+            self.receive_byte(buffer[0]);
+        }
+        if let Err((_, buffer)) = self.uart.receive_buffer(buffer, 1) {
+            self.running.set(false);
+            self.rx_buffer.replace(buffer);
+        }
+    }
+}
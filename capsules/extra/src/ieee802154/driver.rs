@@ -7,16 +7,36 @@
 //! Implements a userspace interface for sending and receiving IEEE 802.15.4
 //! frames. Also provides a minimal list-based interface for managing keys and
 //! known link neighbors, which is needed for 802.15.4 security.
+//!
+//! Keys are either shared with all neighbors, or specific to one neighbor, in
+//! which case they take precedence over shared keys for frames exchanged with
+//! that neighbor. The frame counter of each neighbor is tracked to reject
+//! replayed frames. The outgoing frame counter is kept in memory, unless the
+//! board persists it with `set_frame_counter_store`, so that it does not
+//! restart from zero when the board reboots. Keys can only be rotated in place
+//! by processes if the board enabled it with `enable_key_rotation`.
+//!
+//! The addressing of the node, its short and long address and PAN ID, is
+//! shared by all processes. By default any process may change it, which is
+//! convenient during development. A board restricts it to the processes that
+//! manage the network with `restrict_address_config`.
+//!
+//! If the board gives the driver the radio with `set_radio_config`, processes
+//! can also set the channel, and scan the energy on the channels to pick a
+//! quiet one at runtime.
 
 use crate::ieee802154::{device, framer};
 use crate::net::ieee802154::{AddressMode, Header, KeyId, MacAddress, PanID, SecurityLevel};
 use crate::net::stream::{decode_bytes, decode_u8, encode_bytes, encode_u8, SResult};
 
 use core::cell::Cell;
-use core::cmp::min;
+use core::cmp::{self, min};
 
+use kernel::capabilities::Ieee802154KeyManagementCapability;
 use kernel::deferred_call::{DeferredCall, DeferredCallClient};
 use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::radio;
+use kernel::process::ShortID;
 use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
 use kernel::syscall::{CommandReturn, SyscallDriver};
 use kernel::utilities::cells::{MapCell, OptionalCell, TakeCell};
@@ -25,6 +45,14 @@ use kernel::{ErrorCode, ProcessId};
 const MAX_NEIGHBORS: usize = 4;
 const MAX_KEYS: usize = 4;
 
+/// The channels of the 2.4 GHz band.
+const FIRST_CHANNEL: u8 = 11;
+const NUM_CHANNELS: usize = 16;
+/// Bits of the channels in the channel mask of an energy scan.
+const CHANNEL_MASK: u32 = 0xFFFF << FIRST_CHANNEL;
+/// Energy reported for channels that were not scanned.
+const NOT_SCANNED: i8 = i8::MIN;
+
 /// Ids for read-only allow buffers
 mod ro_allow {
     pub const WRITE: usize = 0;
@@ -47,6 +75,8 @@ pub const DRIVER_NUM: usize = driver::NUM::Ieee802154 as usize;
 struct DeviceDescriptor {
     short_addr: u16,
     long_addr: [u8; 8],
+    /// The lowest frame counter accepted in frames from this neighbor.
+    frame_counter: u32,
 }
 
 impl Default for DeviceDescriptor {
@@ -54,6 +84,7 @@ impl Default for DeviceDescriptor {
         DeviceDescriptor {
             short_addr: 0,
             long_addr: [0; 8],
+            frame_counter: 0,
         }
     }
 }
@@ -139,6 +170,9 @@ struct KeyDescriptor {
     level: SecurityLevel,
     key_id: KeyId,
     key: [u8; 16],
+    /// The long address of the neighbor this key is specific to, or `None` if
+    /// the key is shared with all neighbors.
+    peer: Option<[u8; 8]>,
 }
 
 impl Default for KeyDescriptor {
@@ -147,6 +181,7 @@ impl Default for KeyDescriptor {
             level: SecurityLevel::None,
             key_id: KeyId::Implicit,
             key: [0; 16],
+            peer: None,
         }
     }
 }
@@ -164,6 +199,7 @@ impl KeyDescriptor {
                 level: level,
                 key_id: key_id,
                 key: key,
+                peer: None,
             }
         );
     }
@@ -189,11 +225,38 @@ pub struct RadioDriver<'a> {
     keys: MapCell<[KeyDescriptor; MAX_KEYS]>,
     /// Actual number of keys in the fixed size array of keys.
     num_keys: Cell<usize>,
+    /// Capability to rotate keys, if processes may do so.
+    key_management_cap: OptionalCell<&'a dyn Ieee802154KeyManagementCapability>,
+    /// Processes that may change the addressing, if not all of them.
+    address_managers: OptionalCell<&'a [ShortID]>,
+
+    /// Kernel protocol, such as Thread's MLE, that manages keys and neighbors
+    /// besides those processes configure.
+    kernel_keys: OptionalCell<&'a dyn framer::KeyProcedure>,
+    kernel_devices: OptionalCell<&'a dyn framer::DeviceProcedure>,
+
+    /// Outgoing frame counter, used unless a frame counter store is set.
+    frame_counter: Cell<u32>,
+    /// Persistent store of the outgoing frame counter.
+    frame_counter_store: OptionalCell<&'a dyn framer::FrameCounterProcedure>,
+
+    /// Radio to set the channel of and scan, if the board allows it.
+    radio: OptionalCell<&'a dyn radio::RadioConfig<'a>>,
+    /// App whose energy scan is in progress.
+    scan_app: OptionalCell<ProcessId>,
+    /// Channels left to scan, as a mask of channel numbers.
+    scan_channels: Cell<u32>,
+    /// Measurements per channel, and those already taken on the current one.
+    scan_samples: Cell<u32>,
+    scan_sample: Cell<u32>,
+    /// The strongest energy measured on each channel by the last scan, in
+    /// dBm.
+    energy: Cell<[i8; NUM_CHANNELS]>,
 
     /// Grant of apps that use this radio driver.
     apps: Grant<
         App,
-        UpcallCount<2>,
+        UpcallCount<3>,
         AllowRoCount<{ ro_allow::COUNT }>,
         AllowRwCount<{ rw_allow::COUNT }>,
     >,
@@ -218,7 +281,7 @@ impl<'a> RadioDriver<'a> {
         mac: &'a dyn device::MacDevice<'a>,
         grant: Grant<
             App,
-            UpcallCount<2>,
+            UpcallCount<3>,
             AllowRoCount<{ ro_allow::COUNT }>,
             AllowRwCount<{ rw_allow::COUNT }>,
         >,
@@ -230,6 +293,18 @@ impl<'a> RadioDriver<'a> {
             num_neighbors: Cell::new(0),
             keys: MapCell::new(Default::default()),
             num_keys: Cell::new(0),
+            key_management_cap: OptionalCell::empty(),
+            address_managers: OptionalCell::empty(),
+            kernel_keys: OptionalCell::empty(),
+            kernel_devices: OptionalCell::empty(),
+            frame_counter: Cell::new(0),
+            frame_counter_store: OptionalCell::empty(),
+            radio: OptionalCell::empty(),
+            scan_app: OptionalCell::empty(),
+            scan_channels: Cell::new(0),
+            scan_samples: Cell::new(0),
+            scan_sample: Cell::new(0),
+            energy: Cell::new([NOT_SCANNED; NUM_CHANNELS]),
             apps: grant,
             current_app: OptionalCell::empty(),
             kernel_tx: TakeCell::new(kernel_tx),
@@ -239,6 +314,107 @@ impl<'a> RadioDriver<'a> {
         }
     }
 
+    /// Persist the outgoing frame counter with `store`. Otherwise, the frame
+    /// counter restarts from zero when the board reboots, and nonces are
+    /// reused with the same keys.
+    pub fn set_frame_counter_store(&self, store: &'a dyn framer::FrameCounterProcedure) {
+        self.frame_counter_store.set(store);
+    }
+
+    /// Look up keys and neighbors that processes have not configured with
+    /// `keys` and `devices`, which a kernel protocol managing the link, such
+    /// as Thread's MLE, implements.
+    pub fn set_kernel_procedures(
+        &self,
+        keys: &'a dyn framer::KeyProcedure,
+        devices: &'a dyn framer::DeviceProcedure,
+    ) {
+        self.kernel_keys.set(keys);
+        self.kernel_devices.set(devices);
+    }
+
+    /// Allow processes to set the channel of `radio` with command `5`, and to
+    /// scan the energy on its channels with command `29`. The driver must be
+    /// the energy detect client of `radio`.
+    pub fn set_radio_config(&self, radio: &'a dyn radio::RadioConfig<'a>) {
+        self.radio.set(radio);
+    }
+
+    /// Only allow the processes with a `ShortID` in `allowed` to set the short
+    /// and long address and the PAN ID with commands `2` to `4`. Other
+    /// processes get NOSUPPORT.
+    pub fn restrict_address_config(&self, allowed: &'a [ShortID]) {
+        self.address_managers.set(allowed);
+    }
+
+    /// Whether `processid` may change the addressing.
+    fn may_configure_address(&self, processid: ProcessId) -> bool {
+        self.address_managers
+            .map_or(true, |allowed| allowed.contains(&processid.short_app_id()))
+    }
+
+    /// Allow processes to rotate keys with command `28`, which the driver does
+    /// with `cap`.
+    pub fn enable_key_rotation(&self, cap: &'a dyn Ieee802154KeyManagementCapability) {
+        self.key_management_cap.set(cap);
+    }
+
+    /// Replace the key at `index` with `key`, keeping its security level, key
+    /// ID and neighbor. Frame counters are not reset, as they are tracked per
+    /// device rather than per key. Returns `Err(ErrorCode::INVAL)` if `index`
+    /// is invalid.
+    pub fn rotate_key(
+        &self,
+        index: usize,
+        key: [u8; 16],
+        _cap: &dyn Ieee802154KeyManagementCapability,
+    ) -> Result<(), ErrorCode> {
+        if index < self.num_keys.get() {
+            self.keys.map(|keys| keys[index].key = key);
+            Ok(())
+        } else {
+            Err(ErrorCode::INVAL)
+        }
+    }
+
+    /// Start measuring the energy on the lowest channel left to scan, or
+    /// finish the scan if there is none.
+    fn scan_next_channel(&self) {
+        let channels = self.scan_channels.get();
+        if channels == 0 {
+            self.finish_scan(Ok(()));
+            return;
+        }
+        let channel = channels.trailing_zeros() as u8;
+        let result = self.radio.map_or(Err(ErrorCode::NOSUPPORT), |radio| {
+            radio.energy_detect(channel)
+        });
+        if let Err(e) = result {
+            self.finish_scan(Err(e));
+        }
+    }
+
+    /// End the scan and report the quietest channel scanned to the app that
+    /// started it.
+    fn finish_scan(&self, result: Result<(), ErrorCode>) {
+        self.scan_channels.set(0);
+        let quietest = self
+            .energy
+            .get()
+            .iter()
+            .enumerate()
+            .filter(|(_, energy)| **energy != NOT_SCANNED)
+            .min_by_key(|(_, energy)| **energy)
+            .map_or(0, |(i, _)| i + FIRST_CHANNEL as usize);
+        self.scan_app.take().map(|processid| {
+            let _ = self.apps.enter(processid, |_app, upcalls| {
+                upcalls
+                    .schedule_upcall(2, (kernel::errorcode::into_statuscode(result), quietest, 0))
+                    .ok();
+            });
+        });
+    }
+
     // Neighbor management functions
 
     /// Add a new neighbor to the end of the list if there is still space
@@ -248,9 +424,10 @@ impl<'a> RadioDriver<'a> {
     fn add_neighbor(&self, new_neighbor: DeviceDescriptor) -> Option<usize> {
         self.neighbors.and_then(|neighbors| {
             let num_neighbors = self.num_neighbors.get();
-            let position = neighbors[..num_neighbors]
-                .iter()
-                .position(|neighbor| *neighbor == new_neighbor);
+            let position = neighbors[..num_neighbors].iter().position(|neighbor| {
+                neighbor.short_addr == new_neighbor.short_addr
+                    && neighbor.long_addr == new_neighbor.long_addr
+            });
             match position {
                 Some(index) => Some(index),
                 None => {
@@ -285,6 +462,16 @@ impl<'a> RadioDriver<'a> {
         }
     }
 
+    /// Returns the first neighbor that `pred` holds for.
+    fn find_neighbor(&self, pred: impl Fn(&DeviceDescriptor) -> bool) -> Option<DeviceDescriptor> {
+        self.neighbors.and_then(|neighbors| {
+            neighbors[..self.num_neighbors.get()]
+                .iter()
+                .find(|neighbor| pred(neighbor))
+                .copied()
+        })
+    }
+
     /// Gets the `DeviceDescriptor` corresponding to the neighbor at a
     /// particular `index`, if the `index` is valid. Otherwise, returns `None`
     fn get_neighbor(&self, index: usize) -> Option<DeviceDescriptor> {
@@ -339,6 +526,17 @@ impl<'a> RadioDriver<'a> {
         }
     }
 
+    /// Add a new key specific to the neighbor at `neighbor_index`, like
+    /// `add_key`. Returns `None` if the index is invalid.
+    fn add_peer_key(&self, neighbor_index: usize, new_key: KeyDescriptor) -> Option<usize> {
+        self.get_neighbor(neighbor_index).and_then(|neighbor| {
+            self.add_key(KeyDescriptor {
+                peer: Some(neighbor.long_addr),
+                ..new_key
+            })
+        })
+    }
+
     /// Gets the `DeviceDescriptor` corresponding to the key at a
     /// particular `index`, if the `index` is valid. Otherwise, returns `None`
     fn get_key(&self, index: usize) -> Option<KeyDescriptor> {
@@ -497,29 +695,85 @@ impl framer::DeviceProcedure for RadioDriver<'_> {
     /// Gets the long address corresponding to the neighbor that matches the given
     /// MAC address. If no such neighbor exists, returns `None`.
     fn lookup_addr_long(&self, addr: MacAddress) -> Option<[u8; 8]> {
-        self.neighbors.and_then(|neighbors| {
-            neighbors[..self.num_neighbors.get()]
-                .iter()
-                .find(|neighbor| match addr {
-                    MacAddress::Short(addr) => addr == neighbor.short_addr,
-                    MacAddress::Long(addr) => addr == neighbor.long_addr,
-                })
-                .map(|neighbor| neighbor.long_addr)
+        self.find_neighbor(|neighbor| match addr {
+            MacAddress::Short(addr) => addr == neighbor.short_addr,
+            MacAddress::Long(addr) => addr == neighbor.long_addr,
+        })
+        .map(|neighbor| neighbor.long_addr)
+        .or_else(|| {
+            self.kernel_devices
+                .and_then(|devices| devices.lookup_addr_long(addr))
         })
     }
+
+    fn check_frame_counter(&self, device_addr: [u8; 8], frame_counter: u32) -> bool {
+        match self.find_neighbor(|neighbor| neighbor.long_addr == device_addr) {
+            Some(neighbor) => frame_counter >= neighbor.frame_counter,
+            None => self.kernel_devices.map_or(false, |devices| {
+                devices.check_frame_counter(device_addr, frame_counter)
+            }),
+        }
+    }
+
+    fn update_frame_counter(&self, device_addr: [u8; 8], frame_counter: u32) {
+        let num_neighbors = self.num_neighbors.get();
+        self.neighbors.map(|neighbors| {
+            neighbors[..num_neighbors]
+                .iter_mut()
+                .filter(|neighbor| neighbor.long_addr == device_addr)
+                .for_each(|neighbor| neighbor.frame_counter = frame_counter + 1);
+        });
+        self.kernel_devices
+            .map(|devices| devices.update_frame_counter(device_addr, frame_counter));
+    }
 }
 
 impl framer::KeyProcedure for RadioDriver<'_> {
     /// Gets the key corresponding to the key that matches the given security
-    /// level `level` and key ID `key_id`. If no such key matches, returns
-    /// `None`.
-    fn lookup_key(&self, level: SecurityLevel, key_id: KeyId) -> Option<[u8; 16]> {
-        self.keys.and_then(|keys| {
-            keys[..self.num_keys.get()]
-                .iter()
-                .find(|key| key.level == level && key.key_id == key_id)
-                .map(|key| key.key)
-        })
+    /// level `level` and key ID `key_id`. A key specific to the neighbor with
+    /// the long address `device_addr` is preferred over a shared key. If no
+    /// such key matches, returns `None`.
+    fn lookup_key(
+        &self,
+        level: SecurityLevel,
+        key_id: KeyId,
+        device_addr: Option<[u8; 8]>,
+    ) -> Option<[u8; 16]> {
+        self.keys
+            .and_then(|keys| {
+                let mut matching = keys[..self.num_keys.get()]
+                    .iter()
+                    .filter(|key| key.level == level && key.key_id == key_id);
+                let peer_key = device_addr.and_then(|addr| {
+                    matching
+                        .clone()
+                        .find(|key| key.peer == Some(addr))
+                        .map(|key| key.key)
+                });
+                peer_key.or_else(|| matching.find(|key| key.peer.is_none()).map(|key| key.key))
+            })
+            .or_else(|| {
+                self.kernel_keys
+                    .and_then(|keys| keys.lookup_key(level, key_id, device_addr))
+            })
+    }
+}
+
+impl framer::FrameCounterProcedure for RadioDriver<'_> {
+    fn next_frame_counter(&self) -> Option<u32> {
+        self.frame_counter_store.map_or_else(
+            || {
+                // 0xffffffff is reserved to signal an exhausted counter
+                let frame_counter = self.frame_counter.get();
+                if frame_counter == 0xffffffff {
+                    None
+                } else {
+                    self.frame_counter.set(frame_counter + 1);
+                    Some(frame_counter)
+                }
+            },
+            |store| store.next_frame_counter(),
+        )
     }
 }
 
@@ -545,6 +799,7 @@ impl SyscallDriver for RadioDriver<'_> {
     //
     // - `0`: Setup callback for when frame is received.
     // - `1`: Setup callback for when frame is transmitted.
+    // - `2`: Setup callback for when an energy scan is done.
 
     /// IEEE 802.15.4 MAC device control.
     ///
@@ -560,18 +815,20 @@ impl SyscallDriver for RadioDriver<'_> {
     ///
     /// - `0`: Driver check.
     /// - `1`: Return radio status. Ok(())/OFF = on/off.
-    /// - `2`: Set short MAC address.
+    /// - `2`: Set short MAC address. Commands `2` to `4` return NOSUPPORT if
+    ///        the board restricted the addressing to other processes.
     /// - `3`: Set long MAC address.
     ///        app_cfg (in): 8 bytes: the long MAC address.
     /// - `4`: Set PAN ID.
-    /// - `5`: Set channel.
+    /// - `5`: Set channel, if the board gave the driver the radio. Returns
+    ///        NOSUPPORT otherwise.
     /// - `6`: Set transmission power.
     /// - `7`: Commit any configuration changes.
     /// - `8`: Get the short MAC address.
     /// - `9`: Get the long MAC address.
     ///        app_cfg (out): 8 bytes: the long MAC address.
     /// - `10`: Get the PAN ID.
-    /// - `11`: Get the channel.
+    /// - `11`: Get the channel, if the board gave the driver the radio.
     /// - `12`: Get the transmission power.
     /// - `13`: Get the maximum number of neighbors.
     /// - `14`: Get the current number of neighbors.
@@ -595,13 +852,35 @@ impl SyscallDriver for RadioDriver<'_> {
     ///                      9 bytes: the key ID (might not use all bytes) +
     ///                      16 bytes: the key.
     /// - `25`: Remove the key at an index.
+    /// - `26`: Transmit a frame to the given short address.
+    ///        app_cfg (in): 1 byte: the security level +
+    ///                      1 byte: the key ID mode +
+    ///                      9 bytes: the key ID (might not use all bytes).
+    /// - `27`: Add a new key specific to the neighbor at an index.
+    ///        app_cfg (in): the same 27 bytes as for command `24`.
+    /// - `28`: Rotate the key at an index, if the board enabled key rotation.
+    ///        Returns NOSUPPORT otherwise.
+    ///        app_cfg (in): 16 bytes: the new key.
+    /// - `29`: Scan the energy on the channels set in the channel mask `arg1`,
+    ///        where bit `n` is channel `n`, keeping the strongest of `arg2`
+    ///        measurements per channel. The radio does not receive while
+    ///        measuring. Returns NOSUPPORT if the board did not give the
+    ///        driver the radio, and BUSY if a scan is in progress. When done,
+    ///        upcall `2` reports the quietest channel.
+    /// - `30`: Get the energy measured on each channel by the last scan.
+    ///        app_cfg (out): 16 bytes: the energy in dBm, as an `i8`, of
+    ///                       channels 11 to 26. -128 if it was not scanned.
     fn command(
         &self,
         command_number: usize,
         arg1: usize,
-        _: usize,
+        arg2: usize,
         processid: ProcessId,
     ) -> CommandReturn {
+        if (2..=4).contains(&command_number) && !self.may_configure_address(processid) {
+            return CommandReturn::failure(ErrorCode::NOSUPPORT);
+        }
+
         match command_number {
             0 => CommandReturn::success(),
             1 => {
@@ -638,8 +917,15 @@ impl SyscallDriver for RadioDriver<'_> {
                 self.mac.set_pan(arg1 as u16);
                 CommandReturn::success()
             }
-            // XXX: Setting channel DEPRECATED by MAC layer channel control
-            5 => CommandReturn::failure(ErrorCode::NOSUPPORT),
+            5 => self
+                .radio
+                .map_or(CommandReturn::failure(ErrorCode::NOSUPPORT), |radio| {
+                    if arg1 > u8::MAX as usize {
+                        CommandReturn::failure(ErrorCode::INVAL)
+                    } else {
+                        radio.set_channel(arg1 as u8).into()
+                    }
+                }),
             // XXX: Setting tx power DEPRECATED by MAC layer tx power control
             6 => CommandReturn::failure(ErrorCode::NOSUPPORT),
             7 => {
@@ -673,8 +959,12 @@ impl SyscallDriver for RadioDriver<'_> {
                 let pan = self.mac.get_pan();
                 CommandReturn::success_u32(pan as u32 + 1)
             }
-            // XXX: Getting channel DEPRECATED by MAC layer channel control
-            11 => CommandReturn::failure(ErrorCode::NOSUPPORT),
+            11 => self
+                .radio
+                .map_or(CommandReturn::failure(ErrorCode::NOSUPPORT), |radio| {
+                    // Guarantee that the channel is positive by adding 1
+                    CommandReturn::success_u32(radio.get_channel() as u32 + 1)
+                }),
             // XXX: Getting tx power DEPRECATED by MAC layer tx power control
             12 => CommandReturn::failure(ErrorCode::NOSUPPORT),
             13 => {
@@ -883,6 +1173,100 @@ impl SyscallDriver for RadioDriver<'_> {
                         },
                     )
             }
+            27 => self
+                .apps
+                .enter(processid, |_, kernel_data| {
+                    kernel_data
+                        .get_readwrite_processbuffer(rw_allow::CFG)
+                        .and_then(|cfg| {
+                            cfg.enter(|cfg| {
+                                if cfg.len() != 27 {
+                                    return CommandReturn::failure(ErrorCode::SIZE);
+                                }
+
+                                let mut tmp_cfg: [u8; 27] = [0; 27];
+                                cfg.copy_to_slice(&mut tmp_cfg);
+
+                                KeyDescriptor::decode(&tmp_cfg)
+                                    .done()
+                                    .and_then(|(_, new_key)| self.add_peer_key(arg1, new_key))
+                                    .map_or(CommandReturn::failure(ErrorCode::INVAL), |index| {
+                                        CommandReturn::success_u32(index as u32 + 1)
+                                    })
+                            })
+                        })
+                        .unwrap_or(CommandReturn::failure(ErrorCode::INVAL))
+                })
+                .unwrap_or_else(|err| CommandReturn::failure(err.into())),
+            28 => self.key_management_cap.map_or(
+                CommandReturn::failure(ErrorCode::NOSUPPORT),
+                |cap| {
+                    self.apps
+                        .enter(processid, |_, kernel_data| {
+                            kernel_data
+                                .get_readwrite_processbuffer(rw_allow::CFG)
+                                .and_then(|cfg| {
+                                    cfg.enter(|cfg| {
+                                        if cfg.len() != 16 {
+                                            return CommandReturn::failure(ErrorCode::SIZE);
+                                        }
+                                        let mut key = [0u8; 16];
+                                        cfg.copy_to_slice(&mut key);
+                                        self.rotate_key(arg1, key, *cap).into()
+                                    })
+                                })
+                                .unwrap_or(CommandReturn::failure(ErrorCode::INVAL))
+                        })
+                        .unwrap_or_else(|err| CommandReturn::failure(err.into()))
+                },
+            ),
+            29 => {
+                if self.radio.is_none() {
+                    return CommandReturn::failure(ErrorCode::NOSUPPORT);
+                } else if self.scan_app.is_some() {
+                    return CommandReturn::failure(ErrorCode::BUSY);
+                }
+                let channels = arg1 as u32 & CHANNEL_MASK;
+                if channels == 0 || arg2 == 0 {
+                    return CommandReturn::failure(ErrorCode::INVAL);
+                }
+
+                self.energy.set([NOT_SCANNED; NUM_CHANNELS]);
+                self.scan_channels.set(channels);
+                self.scan_samples.set(arg2 as u32);
+                self.scan_sample.set(0);
+                let channel = channels.trailing_zeros() as u8;
+                match self.radio.map(|radio| radio.energy_detect(channel)) {
+                    Some(Ok(())) => {
+                        self.scan_app.set(processid);
+                        CommandReturn::success()
+                    }
+                    Some(Err(e)) => {
+                        self.scan_channels.set(0);
+                        CommandReturn::failure(e)
+                    }
+                    None => CommandReturn::failure(ErrorCode::NOSUPPORT),
+                }
+            }
+            30 => self
+                .apps
+                .enter(processid, |_, kernel_data| {
+                    kernel_data
+                        .get_readwrite_processbuffer(rw_allow::CFG)
+                        .and_then(|cfg| {
+                            cfg.mut_enter(|cfg| {
+                                if cfg.len() != NUM_CHANNELS {
+                                    return CommandReturn::failure(ErrorCode::SIZE);
+                                }
+                                for (dst, energy) in cfg.iter().zip(self.energy.get().iter()) {
+                                    dst.set(*energy as u8);
+                                }
+                                CommandReturn::success()
+                            })
+                        })
+                        .unwrap_or(CommandReturn::failure(ErrorCode::INVAL))
+                })
+                .unwrap_or_else(|err| CommandReturn::failure(err.into())),
             _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
         }
     }
@@ -913,6 +1297,36 @@ impl device::TxClient for RadioDriver<'_> {
     }
 }
 
+impl radio::EnergyDetectClient for RadioDriver<'_> {
+    fn energy_detect_done(&self, channel: u8, result: Result<i8, ErrorCode>) {
+        if !(FIRST_CHANNEL..FIRST_CHANNEL + NUM_CHANNELS as u8).contains(&channel)
+            || self.scan_channels.get() & (1 << channel) == 0
+        {
+            // Not measured for a scan
+            return;
+        }
+        match result {
+            Ok(energy) => {
+                let mut energies = self.energy.get();
+                let index = (channel - FIRST_CHANNEL) as usize;
+                energies[index] = cmp::max(energies[index], energy);
+                self.energy.set(energies);
+
+                let sample = self.scan_sample.get() + 1;
+                if sample < self.scan_samples.get() {
+                    self.scan_sample.set(sample);
+                } else {
+                    self.scan_sample.set(0);
+                    self.scan_channels
+                        .set(self.scan_channels.get() & !(1 << channel));
+                }
+                self.scan_next_channel();
+            }
+            Err(e) => self.finish_scan(Err(e)),
+        }
+    }
+}
+
 /// Encode two PAN IDs into a single usize.
 #[inline]
 fn encode_pans(dst_pan: &Option<PanID>, src_pan: &Option<PanID>) -> usize {
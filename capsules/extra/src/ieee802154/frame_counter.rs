@@ -0,0 +1,256 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Persistent IEEE 802.15.4 outgoing frame counter.
+//!
+//! A frame counter must never be used twice with the same key, as that reuses
+//! the CCM* nonce. `FrameCounterStore` keeps the outgoing frame counter in the
+//! key-value store, so that it does not restart from zero when the board
+//! reboots. Rather than storing every frame counter, it reserves blocks of
+//! `RESERVED_COUNTERS` frame counters by storing the end of the block, and
+//! continues from the last stored end after a reboot. The frame counters of a
+//! block that were not used before the reboot are skipped.
+//!
+//! No secured frames can be sent until the stored frame counter is loaded and
+//! the first block is reserved. The next block is reserved once half of the
+//! current one is used.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let frame_counter_store = static_init!(
+//!     capsules_extra::ieee802154::frame_counter::FrameCounterStore<'static, K, T>,
+//!     capsules_extra::ieee802154::frame_counter::FrameCounterStore::new(
+//!         kv_store, perms, &mut KEY_BUF, &mut VALUE_BUF));
+//! kv_store.set_client(frame_counter_store);
+//! radio_driver.set_frame_counter_store(frame_counter_store);
+//! frame_counter_store.load();
+//! ```
+
+use crate::ieee802154::framer::FrameCounterProcedure;
+use crate::kv_store::KVStore;
+
+use core::cell::Cell;
+
+use kernel::hil::kv_system::{self, KVSystem};
+use kernel::storage_permissions::StoragePermissions;
+use kernel::utilities::cells::TakeCell;
+use kernel::ErrorCode;
+
+/// The number of frame counters reserved with each write to the store.
+pub const RESERVED_COUNTERS: u32 = 1024;
+
+/// The key the frame counter is stored under.
+pub const KEY: &[u8] = b"ieee802154-frame-counter";
+pub const KEY_LEN: usize = KEY.len();
+
+/// The length of the value buffer, which also holds the header the key-value
+/// store adds to the 4-byte frame counter.
+pub const VALUE_BUF_LEN: usize = 16;
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    Loading,
+    Deleting,
+    Storing,
+}
+
+pub struct FrameCounterStore<
+    'a,
+    K: KVSystem<'a> + KVSystem<'a, K = T>,
+    T: 'static + kv_system::KeyType,
+> {
+    kv_store: &'a KVStore<'a, K, T>,
+    perms: StoragePermissions,
+    state: Cell<State>,
+    /// Whether the stored frame counter was loaded.
+    loaded: Cell<bool>,
+
+    /// The next frame counter to use.
+    frame_counter: Cell<u32>,
+    /// The end of the reserved block: frame counters below it can be used.
+    reserved: Cell<u32>,
+    /// The end of the block being reserved.
+    reserving: Cell<u32>,
+
+    key: TakeCell<'static, [u8]>,
+    value: TakeCell<'static, [u8]>,
+}
+
+impl<'a, K: KVSystem<'a, K = T>, T: kv_system::KeyType> FrameCounterStore<'a, K, T> {
+    /// `key` must hold `KEY`, and `value` must be `VALUE_BUF_LEN` bytes long.
+    pub fn new(
+        kv_store: &'a KVStore<'a, K, T>,
+        perms: StoragePermissions,
+        key: &'static mut [u8],
+        value: &'static mut [u8],
+    ) -> FrameCounterStore<'a, K, T> {
+        FrameCounterStore {
+            kv_store,
+            perms,
+            state: Cell::new(State::Idle),
+            loaded: Cell::new(false),
+            frame_counter: Cell::new(0),
+            reserved: Cell::new(0),
+            reserving: Cell::new(0),
+            key: TakeCell::new(key),
+            value: TakeCell::new(value),
+        }
+    }
+
+    /// Load the stored frame counter and reserve the first block after it.
+    /// Without a stored frame counter, the frame counter starts from zero.
+    pub fn load(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        let key = self.key.take().ok_or(ErrorCode::NOMEM)?;
+        let value = match self.value.take() {
+            Some(value) => value,
+            None => {
+                self.key.replace(key);
+                return Err(ErrorCode::NOMEM);
+            }
+        };
+        match self.kv_store.get(key, value, self.perms) {
+            Ok(()) => {
+                self.state.set(State::Loading);
+                Ok(())
+            }
+            Err((key, value, result)) => {
+                self.key.replace(key);
+                self.value.replace(value);
+                result.and(Err(ErrorCode::FAIL))
+            }
+        }
+    }
+
+    /// Start reserving the block ending at `end`. The previous end is deleted
+    /// first, as the store does not overwrite values.
+    fn reserve(&self, end: u32) {
+        if self.state.get() != State::Idle {
+            return;
+        }
+        if let Some(key) = self.key.take() {
+            self.reserving.set(end);
+            match self.kv_store.delete(key, self.perms) {
+                Ok(()) => self.state.set(State::Deleting),
+                Err((key, _)) => {
+                    self.key.replace(key);
+                    self.store();
+                }
+            }
+        }
+    }
+
+    /// Write the end of the block being reserved.
+    fn store(&self) {
+        let (key, value) = match (self.key.take(), self.value.take()) {
+            (Some(key), Some(value)) => (key, value),
+            (key, value) => {
+                key.map(|key| self.key.replace(key));
+                value.map(|value| self.value.replace(value));
+                self.state.set(State::Idle);
+                return;
+            }
+        };
+        value[..4].copy_from_slice(&self.reserving.get().to_le_bytes());
+        match self.kv_store.set(key, value, 4, self.perms) {
+            Ok(()) => self.state.set(State::Storing),
+            Err((key, value, _)) => {
+                self.key.replace(key);
+                self.value.replace(value);
+                self.state.set(State::Idle);
+            }
+        }
+    }
+}
+
+impl<'a, K: KVSystem<'a, K = T>, T: kv_system::KeyType> FrameCounterProcedure
+    for FrameCounterStore<'a, K, T>
+{
+    fn next_frame_counter(&self) -> Option<u32> {
+        if !self.loaded.get() {
+            // Retry if the store was busy when the frame counter was loaded
+            let _ = self.load();
+            return None;
+        }
+        let frame_counter = self.frame_counter.get();
+        let reserved = self.reserved.get();
+        // 0xffffffff is reserved to signal an exhausted counter
+        if frame_counter >= reserved || frame_counter == 0xffffffff {
+            self.reserve(reserved.saturating_add(RESERVED_COUNTERS));
+            return None;
+        }
+        self.frame_counter.set(frame_counter + 1);
+        if reserved - frame_counter <= RESERVED_COUNTERS / 2 {
+            self.reserve(reserved.saturating_add(RESERVED_COUNTERS));
+        }
+        Some(frame_counter)
+    }
+}
+
+impl<'a, K: KVSystem<'a, K = T>, T: kv_system::KeyType> kv_system::StoreClient<T>
+    for FrameCounterStore<'a, K, T>
+{
+    fn get_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: &'static mut [u8],
+        ret_buf: &'static mut [u8],
+    ) {
+        self.key.replace(key);
+        let frame_counter = match result {
+            Ok(()) => u32::from_le_bytes([ret_buf[0], ret_buf[1], ret_buf[2], ret_buf[3]]),
+            Err(_) => 0,
+        };
+        self.value.replace(ret_buf);
+        self.state.set(State::Idle);
+
+        self.loaded.set(true);
+        self.frame_counter.set(frame_counter);
+        self.reserved.set(frame_counter);
+        self.reserve(frame_counter.saturating_add(RESERVED_COUNTERS));
+    }
+
+    fn set_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: &'static mut [u8],
+        value: &'static mut [u8],
+    ) {
+        self.key.replace(key);
+        self.value.replace(value);
+        self.state.set(State::Idle);
+        if result.is_ok() {
+            self.reserved.set(self.reserving.get());
+        }
+    }
+
+    fn delete_complete(&self, _result: Result<(), ErrorCode>, key: &'static mut [u8]) {
+        // There is no previous end to delete when the first block is reserved
+        self.key.replace(key);
+        self.store();
+    }
+    fn next_key_complete(
+        &self,
+        _result: Result<(), ErrorCode>,
+        _key: T,
+        _length: usize,
+        _position: usize,
+    ) {
+    }
+
+    fn garbage_collect_complete(&self, _result: Result<(), ErrorCode>) {}
+
+    fn usage_complete(
+        &self,
+        _result: Result<(), ErrorCode>,
+        _live_bytes: usize,
+        _dead_bytes: usize,
+    ) {
+    }
+}
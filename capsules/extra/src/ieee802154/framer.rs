@@ -65,6 +65,7 @@
 //!     capsules::ieee802154::RadioDriver::new(mac_device, board_kernel.create_grant(&grant_cap), &mut RADIO_BUF));
 //! mac_device.set_key_procedure(radio_capsule);
 //! mac_device.set_device_procedure(radio_capsule);
+//! mac_device.set_frame_counter_procedure(radio_capsule);
 //! mac_device.set_transmit_client(radio_capsule);
 //! mac_device.set_receive_client(radio_capsule);
 //! ```
@@ -131,6 +132,12 @@ impl Frame {
         self.buf
     }
 
+    /// The MAC header and the payload appended so far. The frame is not
+    /// secured yet, so there is no MIC, and there is no FCS.
+    pub fn unsecured_bytes(&self) -> &[u8] {
+        &self.buf[radio::PSDU_OFFSET..radio::PSDU_OFFSET + self.info.unsecured_length()]
+    }
+
     /// Calculates how much more data this frame can hold
     pub fn remaining_data_capacity(&self) -> usize {
         self.buf.len() - radio::PSDU_OFFSET - radio::MFR_SIZE - self.info.secured_length()
@@ -237,6 +244,15 @@ fn get_ccm_nonce(device_addr: &[u8; 8], frame_counter: u32, level: SecurityLevel
     }
 }
 
+/// Recovers the extended device address and the frame counter that a CCM*
+/// nonce was computed from.
+fn get_ccm_nonce_source(nonce: &[u8; 13]) -> ([u8; 8], u32) {
+    let mut device_addr = [0u8; 8];
+    device_addr.copy_from_slice(&nonce[..8]);
+    let frame_counter = u32::from_be_bytes([nonce[8], nonce[9], nonce[10], nonce[11]]);
+    (device_addr, frame_counter)
+}
+
 /// The needed buffer size might be bigger than an MTU, because
 /// the CCM* authentication procedure
 ///
@@ -252,8 +268,15 @@ pub const CRYPT_BUF_SIZE: usize = radio::MAX_MTU + 3 * 16;
 /// implicitly with some equivalent logic.
 pub trait KeyProcedure {
     /// Lookup the KeyDescriptor matching the provided security level and key ID
-    /// mode and return the key associated with it.
-    fn lookup_key(&self, level: SecurityLevel, key_id: KeyId) -> Option<[u8; 16]>;
+    /// mode and return the key associated with it. `device_addr` is the
+    /// extended address of the peer the frame is exchanged with, if it is
+    /// known, so that keys can be specific to a peer.
+    fn lookup_key(
+        &self,
+        level: SecurityLevel,
+        key_id: KeyId,
+        device_addr: Option<[u8; 8]>,
+    ) -> Option<[u8; 16]>;
 }
 
 /// IEEE 802.15.4-2015, 9.2.5, DeviceDescriptor lookup procedure.
@@ -267,6 +290,28 @@ pub trait DeviceProcedure {
     /// address is already long, a long address should be returned only if the
     /// given address matches a known DeviceDescriptor.
     fn lookup_addr_long(&self, addr: MacAddress) -> Option<[u8; 8]>;
+
+    /// IEEE 802.15.4-2015, 9.2.3, steps g and h. Returns whether the frame
+    /// counter of a frame received from the device with the extended address
+    /// `device_addr` is not lower than the FrameCounter of its
+    /// DeviceDescriptor, that is, whether the frame is not a replay.
+    fn check_frame_counter(&self, device_addr: [u8; 8], frame_counter: u32) -> bool;
+
+    /// IEEE 802.15.4-2015, 9.2.3, step o. Called once a frame from the device
+    /// has been authenticated, so that frames with this frame counter or lower
+    /// ones are rejected from now on.
+    fn update_frame_counter(&self, device_addr: [u8; 8], frame_counter: u32);
+}
+
+/// IEEE 802.15.4-2015, 9.2.1, outgoing frame counter.
+/// Trait to be implemented by an upper layer that manages the macFrameCounter
+/// PIB attribute. Since a frame counter must never be used twice with the same
+/// key, implementations should persist the frame counter across reboots.
+pub trait FrameCounterProcedure {
+    /// Return the frame counter to secure the next outgoing frame with and
+    /// advance it, or `None` if no frame counter is available. This is the
+    /// case when the frame counter is exhausted, which is a counter error.
+    fn next_frame_counter(&self) -> Option<u32>;
 }
 
 /// This state enum describes the state of the transmission pipeline.
@@ -325,6 +370,8 @@ pub struct Framer<'a, M: Mac<'a>, A: AES128CCM<'a>> {
     key_procedure: OptionalCell<&'a dyn KeyProcedure>,
     /// DeviceDescriptor lookup procedure
     device_procedure: OptionalCell<&'a dyn DeviceProcedure>,
+    /// Outgoing frame counter procedure
+    frame_counter_procedure: OptionalCell<&'a dyn FrameCounterProcedure>,
 
     /// Transmission pipeline state. This should never be `None`, except when
     /// transitioning between states. That is, any method that consumes the
@@ -347,6 +394,7 @@ impl<'a, M: Mac<'a>, A: AES128CCM<'a>> Framer<'a, M, A> {
             data_sequence: Cell::new(0),
             key_procedure: OptionalCell::empty(),
             device_procedure: OptionalCell::empty(),
+            frame_counter_procedure: OptionalCell::empty(),
             tx_state: MapCell::new(TxState::Idle),
             tx_client: OptionalCell::empty(),
             rx_state: MapCell::new(RxState::Idle),
@@ -364,11 +412,25 @@ impl<'a, M: Mac<'a>, A: AES128CCM<'a>> Framer<'a, M, A> {
         self.device_procedure.set(device_procedure);
     }
 
+    /// Sets the IEEE 802.15.4 outgoing frame counter procedure to be used.
+    /// Without one, no secured frames can be sent.
+    pub fn set_frame_counter_procedure(
+        &self,
+        frame_counter_procedure: &'a dyn FrameCounterProcedure,
+    ) {
+        self.frame_counter_procedure.set(frame_counter_procedure);
+    }
+
     /// Look up the key using the IEEE 802.15.4 KeyDescriptor lookup procedure
     /// implemented elsewhere.
-    fn lookup_key(&self, level: SecurityLevel, key_id: KeyId) -> Option<[u8; 16]> {
+    fn lookup_key(
+        &self,
+        level: SecurityLevel,
+        key_id: KeyId,
+        device_addr: Option<[u8; 8]>,
+    ) -> Option<[u8; 16]> {
         self.key_procedure
-            .and_then(|key_procedure| key_procedure.lookup_key(level, key_id))
+            .and_then(|key_procedure| key_procedure.lookup_key(level, key_id, device_addr))
     }
 
     /// Look up the extended address of a device using the IEEE 802.15.4
@@ -380,6 +442,14 @@ impl<'a, M: Mac<'a>, A: AES128CCM<'a>> Framer<'a, M, A> {
         })
     }
 
+    /// Check the frame counter of a frame received from a device against the
+    /// DeviceDescriptor of the device.
+    fn check_frame_counter(&self, device_addr: [u8; 8], frame_counter: u32) -> bool {
+        self.device_procedure.map_or(false, |device_procedure| {
+            device_procedure.check_frame_counter(device_addr, frame_counter)
+        })
+    }
+
     /// IEEE 802.15.4-2015, 9.2.1, outgoing frame security procedure
     /// Performs the first checks in the security procedure. The rest of the
     /// steps are performed as part of the transmission pipeline.
@@ -430,15 +500,9 @@ impl<'a, M: Mac<'a>, A: AES128CCM<'a>> Framer<'a, M, A> {
                     if header.version == FrameVersion::V2003 {
                         None
                     } else {
-                        // Step e: Lookup the key.
-                        let key = match self.lookup_key(security.level, security.key_id) {
-                            Some(key) => key,
-                            None => {
-                                return None;
-                            }
-                        };
-
-                        // Step f: Obtain the extended source address
+                        // Step f: Obtain the extended source address. This
+                        // is done before the key lookup of step e, so that
+                        // keys specific to the source device can be found.
                         // TODO: For Thread, when the frame's security header
                         // specifies `KeyIdMode::Source4Index`, the source
                         // address used for the nonce is actually a constant
@@ -450,6 +514,18 @@ impl<'a, M: Mac<'a>, A: AES128CCM<'a>> Framer<'a, M, A> {
                             }
                         };
 
+                        // Step e: Lookup the key.
+                        let key = match self.lookup_key(
+                            security.level,
+                            security.key_id,
+                            Some(device_addr),
+                        ) {
+                            Some(key) => key,
+                            None => {
+                                return None;
+                            }
+                        };
+
                         // Step g, h: Check frame counter
                         let frame_counter = match security.frame_counter {
                             Some(frame_counter) => {
@@ -457,7 +533,10 @@ impl<'a, M: Mac<'a>, A: AES128CCM<'a>> Framer<'a, M, A> {
                                     // Counter error
                                     return None;
                                 }
-                                // TODO: Check frame counter against source device
+                                if !self.check_frame_counter(device_addr, frame_counter) {
+                                    // Replayed frame
+                                    return None;
+                                }
                                 frame_counter
                             }
                             // TSCH mode, where ASN is used instead, not supported
@@ -708,24 +787,25 @@ impl<'a, M: Mac<'a>, A: AES128CCM<'a>> MacDevice<'a> for Framer<'a, M, A> {
         // specification.
         let src_addr_long = self.get_address_long();
         let security_desc = security_needed.and_then(|(level, key_id)| {
-            self.lookup_key(level, key_id).map(|key| {
-                // TODO: lookup frame counter for device
-                let frame_counter = 0;
-                let nonce = get_ccm_nonce(&src_addr_long, frame_counter, level);
-                (
-                    Security {
-                        level: level,
-                        asn_in_nonce: false,
-                        frame_counter: Some(frame_counter),
-                        key_id: key_id,
-                    },
-                    key,
-                    nonce,
-                )
-            })
+            let key = self.lookup_key(level, key_id, self.lookup_addr_long(Some(dst_addr)))?;
+            let frame_counter = self
+                .frame_counter_procedure
+                .and_then(|frame_counter_procedure| frame_counter_procedure.next_frame_counter())?;
+            let nonce = get_ccm_nonce(&src_addr_long, frame_counter, level);
+            Some((
+                Security {
+                    level,
+                    asn_in_nonce: false,
+                    frame_counter: Some(frame_counter),
+                    key_id,
+                },
+                key,
+                nonce,
+            ))
         });
         if security_needed.is_some() && security_desc.is_none() {
-            // If security was requested, fail when desired key was not found.
+            // If security was requested, fail when desired key was not found
+            // or the frame counter is exhausted.
             return Err(buf);
         }
 
@@ -897,6 +977,15 @@ impl<'a, M: Mac<'a>, A: AES128CCM<'a>> CCMClient for Framer<'a, M, A> {
                 match state {
                     RxState::Decrypting(info) => {
                         let next_state = if tag_is_valid {
+                            // IEEE 802.15.4-2015: 9.2.3, step o: update the
+                            // frame counter of the source device
+                            if let Some((_, _, nonce)) = info.security_params {
+                                let (device_addr, frame_counter) = get_ccm_nonce_source(&nonce);
+                                self.device_procedure.map(|device_procedure| {
+                                    device_procedure
+                                        .update_frame_counter(device_addr, frame_counter)
+                                });
+                            }
                             RxState::ReadyToYield(info, buf)
                         } else {
                             RxState::ReadyToReturn(buf)
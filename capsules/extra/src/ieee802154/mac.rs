@@ -19,6 +19,16 @@ use kernel::hil::radio;
 use kernel::utilities::cells::OptionalCell;
 use kernel::ErrorCode;
 
+/// Client of the capture mode of a Mac layer, such as a sniffer. It is passed
+/// every frame the radio receives, before any filtering by address or CRC.
+pub trait CaptureClient {
+    /// `buf` holds the frame starting at `radio::PSDU_OFFSET`, and `frame_len`
+    /// is the length of the frame without its FCS. `crc_valid` is whether the
+    /// FCS of the frame was valid, and `metadata` is the link quality the radio
+    /// measured.
+    fn capture(&self, buf: &[u8], frame_len: usize, crc_valid: bool, metadata: radio::RxMetadata);
+}
+
 pub trait Mac<'a> {
     /// Initializes the layer; may require a buffer to temporarily retaining frames to be
     /// transmitted
@@ -30,6 +40,8 @@ pub trait Mac<'a> {
     fn set_transmit_client(&self, client: &'a dyn radio::TxClient);
     /// Sets the notified client for frame receptions
     fn set_receive_client(&self, client: &'a dyn radio::RxClient);
+    /// Sets the client that is passed every received frame
+    fn set_capture_client(&self, client: &'a dyn CaptureClient);
     /// Sets the buffer for packet reception
     fn set_receive_buffer(&self, buffer: &'static mut [u8]);
 
@@ -76,6 +88,7 @@ pub struct AwakeMac<'a, R: radio::Radio<'a>> {
 
     tx_client: OptionalCell<&'a dyn radio::TxClient>,
     rx_client: OptionalCell<&'a dyn radio::RxClient>,
+    capture_client: OptionalCell<&'a dyn CaptureClient>,
 }
 
 impl<'a, R: radio::Radio<'a>> AwakeMac<'a, R> {
@@ -84,6 +97,7 @@ impl<'a, R: radio::Radio<'a>> AwakeMac<'a, R> {
             radio: radio,
             tx_client: OptionalCell::empty(),
             rx_client: OptionalCell::empty(),
+            capture_client: OptionalCell::empty(),
         }
     }
 }
@@ -138,6 +152,10 @@ impl<'a, R: radio::Radio<'a>> Mac<'a> for AwakeMac<'a, R> {
         self.rx_client.set(client);
     }
 
+    fn set_capture_client(&self, client: &'a dyn CaptureClient) {
+        self.capture_client.set(client);
+    }
+
     fn set_receive_buffer(&self, buffer: &'static mut [u8]) {
         self.radio.set_receive_buffer(buffer);
     }
@@ -167,6 +185,9 @@ impl<'a, R: radio::Radio<'a>> radio::RxClient for AwakeMac<'a, R> {
         crc_valid: bool,
         result: Result<(), ErrorCode>,
     ) {
+        self.capture_client
+            .map(|client| client.capture(buf, frame_len, crc_valid, self.radio.get_rx_metadata()));
+
         // Filter packets by destination because radio is in promiscuous mode
         let mut addr_match = false;
         if let Some((_, (header, _))) = Header::decode(&buf[radio::PSDU_OFFSET..], false).done() {
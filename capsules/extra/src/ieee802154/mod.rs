@@ -5,8 +5,10 @@
 //! Support for IEEE 802.15.4.
 
 pub mod device;
+pub mod frame_counter;
 pub mod framer;
 pub mod mac;
+pub mod sniffer;
 pub mod virtual_mac;
 pub mod xmac;
 
@@ -0,0 +1,223 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Promiscuous IEEE 802.15.4 sniffer.
+//!
+//! `Sniffer` is a capture client of the 802.15.4 MAC mux, and streams every
+//! frame the radio receives over a UART, such as the console or a USB CDC
+//! serial port, in the pcap format. Piping the UART into Wireshark shows the
+//! traffic of the whole network, for example:
+//!
+//! ```text
+//! $ stty -F /dev/ttyACM0 raw 115200 && wireshark -k -i /dev/ttyACM0
+//! ```
+//!
+//! Frames are written with the IEEE 802.15.4 TAP link type, which carries the
+//! RSSI and LQI of each frame along with it, if the radio measures them. The
+//! FCS of each frame is recomputed, as radios do not keep it, and inverted for
+//! frames whose FCS was invalid, so that Wireshark still reports them as bad.
+//! Timestamps count from the start of the capture.
+//!
+//! Frames are sent one at a time from a single buffer, so frames received
+//! while the UART is busy are dropped. Nothing else should write to the UART
+//! during the capture, as that corrupts the pcap stream.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let sniffer = static_init!(
+//!     capsules_extra::ieee802154::sniffer::Sniffer<'static, UartDevice, Rtc>,
+//!     capsules_extra::ieee802154::sniffer::Sniffer::new(uart_device, rtc, &mut SNIFFER_BUF));
+//! uart_device.set_transmit_client(sniffer);
+//! mux_mac.enable_capture(sniffer, &capture_cap);
+//! sniffer.start();
+//! ```
+
+use crate::ieee802154::mac::CaptureClient;
+
+use core::cell::Cell;
+
+use kernel::hil::radio;
+use kernel::hil::time::{Frequency, Ticks, Time};
+use kernel::hil::uart;
+use kernel::utilities::cells::TakeCell;
+use kernel::ErrorCode;
+
+/// The pcap link type of IEEE 802.15.4 frames with a TAP header.
+const LINKTYPE_IEEE802_15_4_TAP: u32 = 283;
+
+const PCAP_HEADER_LEN: usize = 24;
+const PCAP_RECORD_HEADER_LEN: usize = 16;
+
+/// The TAP header, followed by the FCS type, RSS and LQI TLVs.
+const TAP_HEADER_MAX_LEN: usize = 4 + 3 * 8;
+
+/// TAP TLV types
+const TLV_FCS_TYPE: u16 = 0;
+const TLV_RSS: u16 = 1;
+const TLV_LQI: u16 = 10;
+
+/// The FCS type of frames with a 16-bit CRC.
+const FCS_TYPE_CRC16: u8 = 1;
+
+/// The length of the buffer, which holds one pcap record.
+pub const BUF_LEN: usize = PCAP_RECORD_HEADER_LEN + TAP_HEADER_MAX_LEN + radio::MAX_FRAME_SIZE;
+
+/// The 16-bit ITU-T CRC of IEEE 802.15.4 frames.
+fn fcs(frame: &[u8]) -> u16 {
+    frame.iter().fold(0u16, |crc, byte| {
+        (0..8).fold(crc ^ (*byte as u16), |crc, _| {
+            if crc & 1 != 0 {
+                (crc >> 1) ^ 0x8408
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
+
+/// Write a TLV with a value of up to 4 bytes at `off`, returning the offset
+/// after it.
+fn put_tlv(buf: &mut [u8], off: usize, tlv_type: u16, value: &[u8]) -> usize {
+    buf[off..off + 2].copy_from_slice(&tlv_type.to_le_bytes());
+    buf[off + 2..off + 4].copy_from_slice(&(value.len() as u16).to_le_bytes());
+    buf[off + 4..off + 8].fill(0);
+    buf[off + 4..off + 4 + value.len()].copy_from_slice(value);
+    off + 8
+}
+
+pub struct Sniffer<'a, U: uart::Transmit<'a>, T: Time> {
+    uart: &'a U,
+    time: &'a T,
+    buffer: TakeCell<'static, [u8]>,
+
+    /// The ticks of the last timestamp.
+    last_now: Cell<T::Ticks>,
+    /// The ticks since the start of the capture, which do not wrap around
+    /// like those of `time`.
+    elapsed: Cell<u64>,
+
+    /// The number of frames dropped while the UART was busy.
+    dropped: Cell<usize>,
+}
+
+impl<'a, U: uart::Transmit<'a>, T: Time> Sniffer<'a, U, T> {
+    pub fn new(uart: &'a U, time: &'a T, buffer: &'static mut [u8]) -> Sniffer<'a, U, T> {
+        Sniffer {
+            uart,
+            time,
+            buffer: TakeCell::new(buffer),
+            last_now: Cell::new(T::Ticks::from(0)),
+            elapsed: Cell::new(0),
+            dropped: Cell::new(0),
+        }
+    }
+
+    /// Start the capture by sending the pcap file header.
+    pub fn start(&self) -> Result<(), ErrorCode> {
+        let buf = self.buffer.take().ok_or(ErrorCode::BUSY)?;
+        self.last_now.set(self.time.now());
+        self.elapsed.set(0);
+
+        buf[0..4].copy_from_slice(&0xa1b2c3d4u32.to_le_bytes());
+        // Version 2.4
+        buf[4..6].copy_from_slice(&2u16.to_le_bytes());
+        buf[6..8].copy_from_slice(&4u16.to_le_bytes());
+        // Time zone and accuracy of timestamps
+        buf[8..16].fill(0);
+        // Maximum length of captured packets
+        buf[16..20].copy_from_slice(&(BUF_LEN as u32).to_le_bytes());
+        buf[20..24].copy_from_slice(&LINKTYPE_IEEE802_15_4_TAP.to_le_bytes());
+        self.uart
+            .transmit_buffer(buf, PCAP_HEADER_LEN)
+            .map_err(|(ecode, buf)| {
+                self.buffer.replace(buf);
+                ecode
+            })
+    }
+
+    /// The number of frames dropped because the UART was busy.
+    pub fn dropped_frames(&self) -> usize {
+        self.dropped.get()
+    }
+
+    /// The timestamp of a frame received now, in seconds and microseconds.
+    fn timestamp(&self) -> (u32, u32) {
+        let now = self.time.now();
+        let ticks = now.wrapping_sub(self.last_now.get()).into_u32() as u64;
+        self.last_now.set(now);
+        let elapsed = self.elapsed.get() + ticks;
+        self.elapsed.set(elapsed);
+
+        let frequency = T::Frequency::frequency() as u64;
+        let seconds = elapsed / frequency;
+        let micros = (elapsed % frequency) * 1_000_000 / frequency;
+        (seconds as u32, micros as u32)
+    }
+}
+
+impl<'a, U: uart::Transmit<'a>, T: Time> CaptureClient for Sniffer<'a, U, T> {
+    fn capture(&self, buf: &[u8], frame_len: usize, crc_valid: bool, metadata: radio::RxMetadata) {
+        if frame_len + radio::MFR_SIZE > radio::MAX_FRAME_SIZE
+            || radio::PSDU_OFFSET + frame_len > buf.len()
+        {
+            return;
+        }
+        let record = match self.buffer.take() {
+            Some(record) => record,
+            None => {
+                self.dropped.set(self.dropped.get() + 1);
+                return;
+            }
+        };
+        let (seconds, micros) = self.timestamp();
+
+        // TAP header
+        let mut off = PCAP_RECORD_HEADER_LEN + 4;
+        off = put_tlv(record, off, TLV_FCS_TYPE, &[FCS_TYPE_CRC16]);
+        if let Some(rssi) = metadata.rssi {
+            off = put_tlv(record, off, TLV_RSS, &(rssi as f32).to_le_bytes());
+        }
+        if let Some(lqi) = metadata.lqi {
+            off = put_tlv(record, off, TLV_LQI, &[lqi]);
+        }
+        let tap_len = off - PCAP_RECORD_HEADER_LEN;
+        record[PCAP_RECORD_HEADER_LEN] = 0;
+        record[PCAP_RECORD_HEADER_LEN + 1] = 0;
+        record[PCAP_RECORD_HEADER_LEN + 2..PCAP_RECORD_HEADER_LEN + 4]
+            .copy_from_slice(&(tap_len as u16).to_le_bytes());
+
+        // Frame, followed by its FCS
+        let frame = &buf[radio::PSDU_OFFSET..radio::PSDU_OFFSET + frame_len];
+        record[off..off + frame_len].copy_from_slice(frame);
+        off += frame_len;
+        let fcs = if crc_valid { fcs(frame) } else { !fcs(frame) };
+        record[off..off + radio::MFR_SIZE].copy_from_slice(&fcs.to_le_bytes());
+        off += radio::MFR_SIZE;
+
+        // Record header
+        let len = (off - PCAP_RECORD_HEADER_LEN) as u32;
+        record[0..4].copy_from_slice(&seconds.to_le_bytes());
+        record[4..8].copy_from_slice(&micros.to_le_bytes());
+        record[8..12].copy_from_slice(&len.to_le_bytes());
+        record[12..16].copy_from_slice(&len.to_le_bytes());
+
+        if let Err((_, record)) = self.uart.transmit_buffer(record, off) {
+            self.buffer.replace(record);
+            self.dropped.set(self.dropped.get() + 1);
+        }
+    }
+}
+
+impl<'a, U: uart::Transmit<'a>, T: Time> uart::TransmitClient for Sniffer<'a, U, T> {
+    fn transmitted_buffer(
+        &self,
+        tx_buffer: &'static mut [u8],
+        _tx_len: usize,
+        _rval: Result<(), ErrorCode>,
+    ) {
+        self.buffer.replace(tx_buffer);
+    }
+}
@@ -12,6 +12,12 @@
 //! Every radio frame received is provided to all listening clients so that each
 //! client can perform its own frame filtering logic.
 //!
+//! The mux also has a capture mode, which a holder of the
+//! `Ieee802154CaptureCapability` can enable to pass every frame the radio
+//! receives to a capture client, such as a sniffer. This includes frames
+//! addressed to other nodes and frames with an invalid FCS. For this, the mux
+//! must be the capture client of the underlying `Mac` layer.
+//!
 //! Usage
 //! -----
 //!
@@ -30,14 +36,20 @@
 //!     capsules::ieee802154::virtual_mac::MacUser<'static>,
 //!     capsules::ieee802154::virtual_mac::MacUser::new(mux_mac));
 //! mux_mac.add_user(virtual_mac);
+//!
+//! // Pass captured frames to the mux.
+//! awake_mac.set_capture_client(mux_mac);
 //! ```
 
+use crate::ieee802154::mac::CaptureClient;
 use crate::ieee802154::{device, framer};
 use crate::net::ieee802154::{Header, KeyId, MacAddress, PanID, SecurityLevel};
 
 use core::cell::Cell;
 
+use kernel::capabilities::Ieee802154CaptureCapability;
 use kernel::collections::list::{List, ListLink, ListNode};
+use kernel::hil::radio;
 use kernel::utilities::cells::{MapCell, OptionalCell};
 use kernel::ErrorCode;
 
@@ -48,6 +60,8 @@ pub struct MuxMac<'a> {
     mac: &'a dyn device::MacDevice<'a>,
     users: List<'a, MacUser<'a>>,
     inflight: OptionalCell<&'a MacUser<'a>>,
+    /// Client of the capture mode, while it is enabled.
+    capture_client: OptionalCell<&'a dyn CaptureClient>,
 }
 
 impl device::TxClient for MuxMac<'_> {
@@ -67,15 +81,38 @@ impl device::RxClient for MuxMac<'_> {
     }
 }
 
+impl CaptureClient for MuxMac<'_> {
+    fn capture(&self, buf: &[u8], frame_len: usize, crc_valid: bool, metadata: radio::RxMetadata) {
+        self.capture_client
+            .map(|client| client.capture(buf, frame_len, crc_valid, metadata));
+    }
+}
+
 impl<'a> MuxMac<'a> {
     pub const fn new(mac: &'a dyn device::MacDevice<'a>) -> MuxMac<'a> {
         MuxMac {
             mac: mac,
             users: List::new(),
             inflight: OptionalCell::empty(),
+            capture_client: OptionalCell::empty(),
         }
     }
 
+    /// Enables the capture mode, passing every frame the radio receives to
+    /// `client`.
+    pub fn enable_capture(
+        &self,
+        client: &'a dyn CaptureClient,
+        _cap: &dyn Ieee802154CaptureCapability,
+    ) {
+        self.capture_client.set(client);
+    }
+
+    /// Disables the capture mode.
+    pub fn disable_capture(&self) {
+        self.capture_client.clear();
+    }
+
     /// Registers a MAC user with this MAC mux device. Each MAC user should only
     /// be registered once.
     pub fn add_user(&self, user: &'a MacUser<'a>) {
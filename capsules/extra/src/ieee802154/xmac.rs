@@ -82,7 +82,7 @@
 // Date: Nov 21 2017
 //
 
-use crate::ieee802154::mac::Mac;
+use crate::ieee802154::mac::{CaptureClient, Mac};
 use crate::net::ieee802154::{FrameType, FrameVersion, Header, MacAddress, PanID};
 use core::cell::Cell;
 use kernel::hil::radio;
@@ -154,6 +154,7 @@ pub struct XMac<'a, R: radio::Radio<'a>, A: Alarm<'a>> {
     rng: &'a dyn Rng<'a>,
     tx_client: OptionalCell<&'a dyn radio::TxClient>,
     rx_client: OptionalCell<&'a dyn radio::RxClient>,
+    capture_client: OptionalCell<&'a dyn CaptureClient>,
     state: Cell<XMacState>,
     delay_sleep: Cell<bool>,
 
@@ -176,6 +177,7 @@ impl<'a, R: radio::Radio<'a>, A: Alarm<'a>> XMac<'a, R, A> {
             rng: rng,
             tx_client: OptionalCell::empty(),
             rx_client: OptionalCell::empty(),
+            capture_client: OptionalCell::empty(),
             state: Cell::new(XMacState::STARTUP),
             delay_sleep: Cell::new(false),
             tx_header: Cell::new(None),
@@ -404,6 +406,10 @@ impl<'a, R: radio::Radio<'a>, A: Alarm<'a>> Mac<'a> for XMac<'a, R, A> {
         self.rx_client.set(client);
     }
 
+    fn set_capture_client(&self, client: &'a dyn CaptureClient) {
+        self.capture_client.set(client);
+    }
+
     fn set_receive_buffer(&self, buffer: &'static mut [u8]) {
         self.radio.set_receive_buffer(buffer);
     }
@@ -579,6 +585,9 @@ impl<'a, R: radio::Radio<'a>, A: Alarm<'a>> radio::RxClient for XMac<'a, R, A> {
         let mut data_received: bool = false;
         let mut continue_sleep: bool = true;
 
+        self.capture_client
+            .map(|client| client.capture(buf, frame_len, crc_valid, self.radio.get_rx_metadata()));
+
         // First, check to make sure we can decode the MAC header (especially
         // the destination address) to see if we can backoff/send pending
         // transmission.
@@ -4,6 +4,32 @@
 
 //! KV Driver
 //!
+//! Keys are stored with the storage ID (the `write_id` in the persistent ACL
+//! of the TBF header) of the app that set them. An app can only get keys
+//! whose storage ID is in its read list and delete keys whose storage ID is
+//! in its access list, and needs a `write_id` to set keys.
+//!
+//! Each app has its own namespace, so apps can use the same key names without
+//! seeing each other's keys. The namespace is the ShortID of the app, or its
+//! storage ID if the ShortID is only locally unique. The namespace takes the
+//! first 5 bytes of the key buffer, so keys are truncated 5 bytes earlier.
+//!
+//! If the board sets a quota on the `KVStore`, setting a key fails with
+//! `NOMEM` once the values set with the storage ID of the app would exceed
+//! it.
+//!
+//! Command 4 lists the keys an app can read, one at a time. It takes the
+//! position to start from, 0 for the first key. The upcall passes the length
+//! of the value and the position of the next key, and the hashed key is
+//! written to the read-write allow buffer. There are no more keys once the
+//! upcall reports `NOSUPPORT`.
+//!
+//! Command 5 runs a garbage collection of the store, which frees the regions
+//! that only hold deleted keys. Garbage collection otherwise runs when the
+//! store is idle after a delete, so apps can use this to schedule it.
+//!
+//! Command 6 reports how many bytes of the store are used by valid and by
+//! deleted keys. The upcall passes the live bytes and the dead bytes.
 
 use capsules_core::driver;
 /// Syscall driver number.
@@ -11,14 +37,23 @@ pub const DRIVER_NUM: usize = driver::NUM::KVSystem as usize;
 
 use crate::kv_store::KVStore;
 use core::cell::Cell;
-use kernel::grant::Grant;
 use kernel::grant::{AllowRoCount, AllowRwCount, UpcallCount};
+use kernel::grant::{Grant, GrantKernelData};
 use kernel::hil::kv_system;
+use kernel::process::ShortID;
 use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
+use kernel::storage_permissions::StoragePermissions;
 use kernel::syscall::{CommandReturn, SyscallDriver};
 use kernel::utilities::cells::{OptionalCell, TakeCell};
 use kernel::{ErrorCode, ProcessId};
 
+/// The keys of an app are stored after its namespace, which is a byte for
+/// the kind of the ID and the ID itself.
+const NAMESPACE_LEN: usize = 5;
+const NAMESPACE_NONE: u8 = 0;
+const NAMESPACE_SHORT_ID: u8 = 1;
+const NAMESPACE_WRITE_ID: u8 = 2;
+
 /// Ids for read-only allow buffers
 mod ro_allow {
     // unhashed key
@@ -86,38 +121,67 @@ impl<'a, K: kv_system::KVSystem<'a, K = T>, T: kv_system::KeyType> KVSystemDrive
         }
     }
 
+    /// Copy the key allowed by the app to the data buffer, after the
+    /// namespace of the app.
+    fn copy_key(
+        &self,
+        processid: ProcessId,
+        perms: &StoragePermissions,
+        kernel_data: &GrantKernelData,
+    ) -> Result<(), ErrorCode> {
+        let (kind, id) = match processid.short_app_id() {
+            ShortID::Fixed(id) => (NAMESPACE_SHORT_ID, id.get()),
+            // Without a fixed ShortID the app is identified by its storage
+            // ID, which also stays the same across reboots
+            ShortID::LocallyUnique => match perms.get_write_id() {
+                Some(write_id) => (NAMESPACE_WRITE_ID, write_id),
+                None => (NAMESPACE_NONE, 0),
+            },
+        };
+
+        kernel_data
+            .get_readonly_processbuffer(ro_allow::UNHASHED_KEY)
+            .and_then(|buffer| {
+                buffer.enter(|unhashed_key| {
+                    self.data_buffer.map_or(Err(ErrorCode::NOMEM), |buf| {
+                        let (prefix, key_buf) = buf.split_at_mut(NAMESPACE_LEN);
+                        prefix[0] = kind;
+                        prefix[1..].copy_from_slice(&id.to_le_bytes());
+
+                        // Determine the size of the static buffer we have
+                        let static_buffer_len = key_buf.len().min(unhashed_key.len());
+
+                        // Copy the data into the static buffer, the rest is
+                        // cleared so that it isn't hashed with the key
+                        unhashed_key[..static_buffer_len]
+                            .copy_to_slice(&mut key_buf[..static_buffer_len]);
+                        key_buf[static_buffer_len..].fill(0);
+
+                        Ok(())
+                    })
+                })
+            })
+            .unwrap_or(Err(ErrorCode::RESERVE))
+    }
+
     fn run(&self) -> Result<(), ErrorCode> {
         self.processid.map_or(Err(ErrorCode::RESERVE), |processid| {
             self.apps
                 .enter(*processid, |app, kernel_data| {
+                    // Look up the permissions before taking the buffers, so
+                    // they are not lost if the app has none.
+                    let perms = processid
+                        .get_storage_permissions()
+                        .ok_or(ErrorCode::INVAL)?;
+
                     if let Some(operation) = app.op.get() {
                         match operation {
                             UserSpaceOp::Get => {
-                                kernel_data
-                                    .get_readonly_processbuffer(ro_allow::UNHASHED_KEY)
-                                    .and_then(|buffer| {
-                                        buffer.enter(|unhashed_key| {
-                                            self.data_buffer.map_or(Err(ErrorCode::NOMEM), |buf| {
-                                                // Determine the size of the static buffer we have
-                                                let static_buffer_len =
-                                                    buf.len().min(unhashed_key.len());
-
-                                                // Copy the data into the static buffer
-                                                unhashed_key[..static_buffer_len]
-                                                    .copy_to_slice(&mut buf[..static_buffer_len]);
-
-                                                Ok(())
-                                            })
-                                        })
-                                    })
-                                    .unwrap_or(Err(ErrorCode::RESERVE))?;
+                                self.copy_key(*processid, &perms, kernel_data)?;
 
                                 if let Some(Some(Err(e))) =
                                     self.data_buffer.take().map(|data_buffer| {
                                         self.dest_buffer.take().map(|dest_buffer| {
-                                            let perms = processid
-                                                .get_storage_permissions()
-                                                .ok_or(ErrorCode::INVAL)?;
                                             if let Err((data, dest, e)) =
                                                 self.kv.get(data_buffer, dest_buffer, perms)
                                             {
@@ -133,24 +197,7 @@ impl<'a, K: kv_system::KVSystem<'a, K = T>, T: kv_system::KeyType> KVSystemDrive
                                 }
                             }
                             UserSpaceOp::Set => {
-                                kernel_data
-                                    .get_readonly_processbuffer(ro_allow::UNHASHED_KEY)
-                                    .and_then(|buffer| {
-                                        buffer.enter(|unhashed_key| {
-                                            self.data_buffer.map_or(Err(ErrorCode::NOMEM), |buf| {
-                                                // Determine the size of the static buffer we have
-                                                let static_buffer_len =
-                                                    buf.len().min(unhashed_key.len());
-
-                                                // Copy the data into the static buffer
-                                                unhashed_key[..static_buffer_len]
-                                                    .copy_to_slice(&mut buf[..static_buffer_len]);
-
-                                                Ok(())
-                                            })
-                                        })
-                                    })
-                                    .unwrap_or(Err(ErrorCode::RESERVE))?;
+                                self.copy_key(*processid, &perms, kernel_data)?;
 
                                 let mut static_buffer_len = 0;
 
@@ -175,9 +222,6 @@ impl<'a, K: kv_system::KVSystem<'a, K = T>, T: kv_system::KeyType> KVSystemDrive
                                 if let Some(Some(Err(e))) =
                                     self.data_buffer.take().map(|data_buffer| {
                                         self.dest_buffer.take().map(|dest_buffer| {
-                                            let perms = processid
-                                                .get_storage_permissions()
-                                                .ok_or(ErrorCode::INVAL)?;
                                             if let Err((data, dest, e)) = self.kv.set(
                                                 data_buffer,
                                                 dest_buffer,
@@ -196,29 +240,9 @@ impl<'a, K: kv_system::KVSystem<'a, K = T>, T: kv_system::KeyType> KVSystemDrive
                                 }
                             }
                             UserSpaceOp::Delete => {
-                                kernel_data
-                                    .get_readonly_processbuffer(ro_allow::UNHASHED_KEY)
-                                    .and_then(|buffer| {
-                                        buffer.enter(|unhashed_key| {
-                                            self.data_buffer.map_or(Err(ErrorCode::NOMEM), |buf| {
-                                                // Determine the size of the static buffer we have
-                                                let static_buffer_len =
-                                                    buf.len().min(unhashed_key.len());
-
-                                                // Copy the data into the static buffer
-                                                unhashed_key[..static_buffer_len]
-                                                    .copy_to_slice(&mut buf[..static_buffer_len]);
-
-                                                Ok(())
-                                            })
-                                        })
-                                    })
-                                    .unwrap_or(Err(ErrorCode::RESERVE))?;
+                                self.copy_key(*processid, &perms, kernel_data)?;
 
                                 if let Some(Err(e)) = self.data_buffer.take().map(|data_buffer| {
-                                    let perms = processid
-                                        .get_storage_permissions()
-                                        .ok_or(ErrorCode::INVAL)?;
                                     if let Err((data, e)) = self.kv.delete(data_buffer, perms) {
                                         self.data_buffer.replace(data);
                                         return Err(e);
@@ -228,6 +252,15 @@ impl<'a, K: kv_system::KVSystem<'a, K = T>, T: kv_system::KeyType> KVSystemDrive
                                     return e;
                                 }
                             }
+                            UserSpaceOp::List => {
+                                self.kv.next_key(app.position, perms)?;
+                            }
+                            UserSpaceOp::GarbageCollect => {
+                                self.kv.garbage_collect()?;
+                            }
+                            UserSpaceOp::Usage => {
+                                self.kv.usage()?;
+                            }
                         }
                     }
 
@@ -372,6 +405,96 @@ impl<'a, K: kv_system::KVSystem<'a, K = T>, T: kv_system::KeyType> kv_system::St
             })
         });
     }
+
+    fn next_key_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: T,
+        length: usize,
+        position: usize,
+    ) {
+        self.processid.map(move |id| {
+            self.apps.enter(*id, move |app, upcalls| {
+                if app.op.get() == Some(UserSpaceOp::List) {
+                    if let Err(e) = result {
+                        upcalls
+                            .schedule_upcall(
+                                upcalls::VALUE,
+                                (kernel::errorcode::into_statuscode(e.into()), 0, 0),
+                            )
+                            .ok();
+                    } else {
+                        let ret = upcalls
+                            .get_readwrite_processbuffer(rw_allow::VALUE)
+                            .and_then(|buffer| {
+                                buffer.mut_enter(|data| {
+                                    let key = key.as_ref();
+                                    let len = data.len().min(key.len());
+                                    data[..len].copy_from_slice(&key[..len]);
+                                })
+                            });
+
+                        if ret.is_err() {
+                            upcalls
+                                .schedule_upcall(
+                                    upcalls::VALUE,
+                                    (
+                                        kernel::errorcode::into_statuscode(Err(ErrorCode::RESERVE)),
+                                        0,
+                                        0,
+                                    ),
+                                )
+                                .ok();
+                        } else {
+                            upcalls
+                                .schedule_upcall(upcalls::VALUE, (0, length, position))
+                                .ok();
+                        }
+                    }
+
+                    self.processid.clear();
+                }
+            })
+        });
+    }
+
+    fn garbage_collect_complete(&self, result: Result<(), ErrorCode>) {
+        self.processid.map(move |id| {
+            self.apps.enter(*id, move |app, upcalls| {
+                if app.op.get() == Some(UserSpaceOp::GarbageCollect) {
+                    upcalls
+                        .schedule_upcall(
+                            upcalls::VALUE,
+                            (kernel::errorcode::into_statuscode(result), 0, 0),
+                        )
+                        .ok();
+
+                    self.processid.clear();
+                }
+            })
+        });
+    }
+
+    fn usage_complete(&self, result: Result<(), ErrorCode>, live_bytes: usize, dead_bytes: usize) {
+        self.processid.map(move |id| {
+            self.apps.enter(*id, move |app, upcalls| {
+                if app.op.get() == Some(UserSpaceOp::Usage) {
+                    upcalls
+                        .schedule_upcall(
+                            upcalls::VALUE,
+                            (
+                                kernel::errorcode::into_statuscode(result),
+                                live_bytes,
+                                dead_bytes,
+                            ),
+                        )
+                        .ok();
+
+                    self.processid.clear();
+                }
+            })
+        });
+    }
 }
 
 impl<'a, K: kv_system::KVSystem<'a, K = T>, T: kv_system::KeyType> SyscallDriver
@@ -380,7 +503,7 @@ impl<'a, K: kv_system::KVSystem<'a, K = T>, T: kv_system::KeyType> SyscallDriver
     fn command(
         &self,
         command_num: usize,
-        _data1: usize,
+        data1: usize,
         _data2: usize,
         processid: ProcessId,
     ) -> CommandReturn {
@@ -412,14 +535,20 @@ impl<'a, K: kv_system::KVSystem<'a, K = T>, T: kv_system::KeyType> SyscallDriver
             // check if present
             0 => CommandReturn::success(),
 
-            // get, set, delete
-            1 | 2 | 3 => {
+            // get, set, delete, list, garbage collect, usage
+            1 | 2 | 3 | 4 | 5 | 6 => {
                 if match_or_empty_or_nonexistant {
                     self.processid.set(processid);
                     let _ = self.apps.enter(processid, |app, _| match command_num {
                         1 => app.op.set(Some(UserSpaceOp::Get)),
                         2 => app.op.set(Some(UserSpaceOp::Set)),
                         3 => app.op.set(Some(UserSpaceOp::Delete)),
+                        4 => {
+                            app.op.set(Some(UserSpaceOp::List));
+                            app.position = data1;
+                        }
+                        5 => app.op.set(Some(UserSpaceOp::GarbageCollect)),
+                        6 => app.op.set(Some(UserSpaceOp::Usage)),
                         _ => {}
                     });
                     let ret = self.run();
@@ -447,6 +576,12 @@ impl<'a, K: kv_system::KVSystem<'a, K = T>, T: kv_system::KeyType> SyscallDriver
                                     1 => app.op.set(Some(UserSpaceOp::Get)),
                                     2 => app.op.set(Some(UserSpaceOp::Set)),
                                     3 => app.op.set(Some(UserSpaceOp::Delete)),
+                                    4 => {
+                                        app.op.set(Some(UserSpaceOp::List));
+                                        app.position = data1;
+                                    }
+                                    5 => app.op.set(Some(UserSpaceOp::GarbageCollect)),
+                                    6 => app.op.set(Some(UserSpaceOp::Usage)),
                                     _ => {}
                                 }
                                 CommandReturn::success()
@@ -471,10 +606,15 @@ enum UserSpaceOp {
     Get,
     Set,
     Delete,
+    List,
+    GarbageCollect,
+    Usage,
 }
 
 #[derive(Default)]
 pub struct App {
     pending_run_app: Option<ProcessId>,
     op: Cell<Option<UserSpaceOp>>,
+    /// The position to list keys from.
+    position: usize,
 }
@@ -45,6 +45,11 @@ enum Operation {
     Get,
     Set,
     Delete,
+    NextKey,
+    GarbageCollect,
+    Usage,
+    /// Adding up the bytes stored with each write ID
+    Count,
 }
 
 const HEADER_VERSION: u8 = 0;
@@ -80,6 +85,13 @@ impl KeyHeader {
     }
 }
 
+/// The bytes of values stored with one write ID, to enforce quotas.
+#[derive(Clone, Copy, Default)]
+pub struct WriteIdUsage {
+    write_id: Option<u32>,
+    bytes: usize,
+}
+
 pub struct KVStore<'a, K: KVSystem<'a> + KVSystem<'a, K = T>, T: 'static + kv_system::KeyType> {
     mux_kv: &'a MuxKVStore<'a, K, T>,
     next: ListLink<'a, KVStore<'a, K, T>>,
@@ -95,6 +107,14 @@ pub struct KVStore<'a, K: KVSystem<'a> + KVSystem<'a, K = T>, T: 'static + kv_sy
 
     valid_ids: OptionalCell<StoragePermissions>,
     next_valid_ids: OptionalCell<StoragePermissions>,
+
+    /// The position to list the keys from for `next_key()`.
+    position: Cell<usize>,
+
+    /// The most bytes of values that can be stored with one write ID.
+    quota: OptionalCell<usize>,
+    /// The bytes stored with each write ID, if there is a quota.
+    usage: TakeCell<'static, [WriteIdUsage]>,
 }
 
 impl<'a, K: KVSystem<'a, K = T>, T: kv_system::KeyType> ListNode<'a, KVStore<'a, K, T>>
@@ -122,6 +142,9 @@ impl<'a, K: KVSystem<'a, K = T>, T: kv_system::KeyType> KVStore<'a, K, T> {
             header_value: TakeCell::new(header_value),
             valid_ids: OptionalCell::empty(),
             next_valid_ids: OptionalCell::empty(),
+            position: Cell::new(0),
+            quota: OptionalCell::empty(),
+            usage: TakeCell::empty(),
         }
     }
 
@@ -129,6 +152,82 @@ impl<'a, K: KVSystem<'a, K = T>, T: kv_system::KeyType> KVStore<'a, K, T> {
         self.client.set(client);
     }
 
+    /// Limit the bytes of values that can be set with each write ID to
+    /// `quota`, so that one app can't fill the store. `usage` keeps track of
+    /// the bytes stored with up to `usage.len()` write IDs, keys with other
+    /// write IDs can't be set.
+    ///
+    /// The keys already stored are counted first. Operations started in the
+    /// meantime wait until that's done.
+    pub fn set_quota(
+        &self,
+        quota: usize,
+        usage: &'static mut [WriteIdUsage],
+    ) -> Result<(), ErrorCode> {
+        if self.mux_kv.operation.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        let hashed_key = self.hashed_key.take().ok_or(ErrorCode::NOMEM)?;
+
+        self.quota.set(quota);
+        self.usage.replace(usage);
+        self.mux_kv.operation.set(Operation::Count);
+
+        if let Err((hashed_key, e)) = self.mux_kv.kv.next_key(0, hashed_key) {
+            self.hashed_key.replace(hashed_key);
+            self.mux_kv.operation.clear();
+            return e.and(Err(ErrorCode::FAIL));
+        }
+        Ok(())
+    }
+
+    /// Check that `bytes` more can be stored with `write_id`.
+    fn check_quota(&self, write_id: u32, bytes: usize) -> Result<(), ErrorCode> {
+        let quota = match self.quota.extract() {
+            Some(quota) => quota,
+            None => return Ok(()),
+        };
+
+        self.usage.map_or(Err(ErrorCode::NOMEM), |usage| {
+            let stored = match usage.iter().find(|u| u.write_id == Some(write_id)) {
+                Some(entry) => entry.bytes,
+                None if usage.iter().any(|u| u.write_id.is_none()) => 0,
+                None => return Err(ErrorCode::NOMEM),
+            };
+
+            if stored.saturating_add(bytes) > quota {
+                Err(ErrorCode::NOMEM)
+            } else {
+                Ok(())
+            }
+        })
+    }
+
+    /// Add `bytes` to the bytes stored with `write_id`, if it fits in the
+    /// usage table.
+    fn add_usage(&self, write_id: u32, bytes: usize) {
+        self.usage.map(|usage| {
+            let entry = match usage.iter().position(|u| u.write_id == Some(write_id)) {
+                Some(i) => Some(i),
+                None => usage.iter().position(|u| u.write_id.is_none()),
+            };
+
+            if let Some(entry) = entry.and_then(|i| usage.get_mut(i)) {
+                entry.write_id = Some(write_id);
+                entry.bytes = entry.bytes.saturating_add(bytes);
+            }
+        });
+    }
+
+    /// Remove `bytes` from the bytes stored with `write_id`.
+    fn remove_usage(&self, write_id: u32, bytes: usize) {
+        self.usage.map(|usage| {
+            if let Some(entry) = usage.iter_mut().find(|u| u.write_id == Some(write_id)) {
+                entry.bytes = entry.bytes.saturating_sub(bytes);
+            }
+        });
+    }
+
     pub fn get(
         &self,
         unhashed_key: &'static mut [u8],
@@ -280,6 +379,126 @@ impl<'a, K: KVSystem<'a, K = T>, T: kv_system::KeyType> KVStore<'a, K, T> {
             }
         }
     }
+
+    /// Find the next key from `position` that `perms` allow reading. The key
+    /// is passed to `next_key_complete()`, with the position of the key
+    /// after it.
+    pub fn next_key(&self, position: usize, perms: StoragePermissions) -> Result<(), ErrorCode> {
+        if self.mux_kv.operation.is_none() {
+            let hashed_key = self.hashed_key.take().ok_or(ErrorCode::NOMEM)?;
+
+            self.mux_kv.operation.set(Operation::NextKey);
+            self.valid_ids.set(perms);
+
+            if let Err((hashed_key, e)) = self.mux_kv.kv.next_key(position, hashed_key) {
+                self.hashed_key.replace(hashed_key);
+                self.mux_kv.operation.clear();
+                return e.and(Err(ErrorCode::FAIL));
+            }
+            Ok(())
+        } else {
+            // Another app is already running, queue this app as long as we
+            // don't already have data queued.
+            if self.next_operation.is_none() {
+                self.next_operation.set(Operation::NextKey);
+                self.position.set(position);
+                self.next_valid_ids.set(perms);
+
+                Ok(())
+            } else {
+                Err(ErrorCode::BUSY)
+            }
+        }
+    }
+
+    /// Run a garbage collection of the underlying store, which calls
+    /// `garbage_collect_complete()` once done.
+    pub fn garbage_collect(&self) -> Result<(), ErrorCode> {
+        if self.mux_kv.operation.is_none() {
+            self.mux_kv.operation.set(Operation::GarbageCollect);
+
+            if let Err(e) = self.mux_kv.kv.garbage_collect() {
+                self.mux_kv.operation.clear();
+                return e.and(Err(ErrorCode::FAIL));
+            }
+            Ok(())
+        } else {
+            // Another app is already running, queue this app as long as we
+            // don't already have data queued.
+            if self.next_operation.is_none() {
+                self.next_operation.set(Operation::GarbageCollect);
+                Ok(())
+            } else {
+                Err(ErrorCode::BUSY)
+            }
+        }
+    }
+
+    /// Find how many bytes of the underlying store are used by valid and by
+    /// invalidated objects, which are passed to `usage_complete()`.
+    pub fn usage(&self) -> Result<(), ErrorCode> {
+        if self.mux_kv.operation.is_none() {
+            self.mux_kv.operation.set(Operation::Usage);
+
+            if let Err(e) = self.mux_kv.kv.usage() {
+                self.mux_kv.operation.clear();
+                return Err(e);
+            }
+            Ok(())
+        } else {
+            // Another app is already running, queue this app as long as we
+            // don't already have data queued.
+            if self.next_operation.is_none() {
+                self.next_operation.set(Operation::Usage);
+                Ok(())
+            } else {
+                Err(ErrorCode::BUSY)
+            }
+        }
+    }
+
+    /// Start a queued operation that doesn't need a key to be hashed, and
+    /// report if that fails.
+    fn start_queued(&self, op: Operation) {
+        match op {
+            Operation::NextKey => self.continue_next_key(self.position.get()),
+            Operation::GarbageCollect => {
+                if let Err(e) = self.mux_kv.kv.garbage_collect() {
+                    self.mux_kv.operation.clear();
+                    self.client.map(move |cb| {
+                        cb.garbage_collect_complete(e.and(Err(ErrorCode::FAIL)));
+                    });
+                }
+            }
+            Operation::Usage => {
+                if let Err(e) = self.mux_kv.kv.usage() {
+                    self.mux_kv.operation.clear();
+                    self.client.map(move |cb| {
+                        cb.usage_complete(Err(e), 0, 0);
+                    });
+                }
+            }
+            Operation::Get | Operation::Set | Operation::Delete | Operation::Count => {}
+        }
+    }
+
+    /// Continue looking for a key from `position`, or report that there are
+    /// no more if that fails.
+    fn continue_next_key(&self, position: usize) {
+        self.hashed_key.take().map(|hashed_key| {
+            if let Err((hashed_key, e)) = self.mux_kv.kv.next_key(position, hashed_key) {
+                let key = *hashed_key;
+                self.hashed_key.replace(hashed_key);
+                // Counting the stored keys ends here too, without a client
+                // to report to
+                if self.mux_kv.operation.take() == Some(Operation::NextKey) {
+                    self.client.map(move |cb| {
+                        cb.next_key_complete(e.and(Err(ErrorCode::FAIL)), key, 0, 0);
+                    });
+                }
+            }
+        });
+    }
 }
 
 impl<'a, K: KVSystem<'a, K = T>, T: kv_system::KeyType + core::fmt::Debug> kv_system::Client<T>
@@ -317,6 +536,10 @@ impl<'a, K: KVSystem<'a, K = T>, T: kv_system::KeyType + core::fmt::Debug> kv_sy
                             cb.delete_complete(result, unhashed_key);
                         });
                     }
+                    Operation::NextKey
+                    | Operation::GarbageCollect
+                    | Operation::Usage
+                    | Operation::Count => {}
                 });
             } else {
                 match op {
@@ -336,6 +559,20 @@ impl<'a, K: KVSystem<'a, K = T>, T: kv_system::KeyType + core::fmt::Debug> kv_sy
                     }
                     Operation::Set => {
                         self.value.take().map(|value| {
+                            let header = KeyHeader::new_from_buf(value);
+                            if let Err(e) =
+                                self.check_quota(header.write_id, header.length as usize)
+                            {
+                                self.hashed_key.replace(hashed_key);
+                                self.mux_kv.operation.clear();
+                                self.unhashed_key.take().map(|unhashed_key| {
+                                    self.client.map(move |cb| {
+                                        cb.set_complete(Err(e), unhashed_key, value);
+                                    });
+                                });
+                                return;
+                            }
+
                             if let Err((key, value, e)) =
                                 self.mux_kv.kv.append_key(hashed_key, value)
                             {
@@ -362,6 +599,10 @@ impl<'a, K: KVSystem<'a, K = T>, T: kv_system::KeyType + core::fmt::Debug> kv_sy
                             }
                         });
                     }
+                    Operation::NextKey
+                    | Operation::GarbageCollect
+                    | Operation::Usage
+                    | Operation::Count => {}
                 }
             }
         });
@@ -379,8 +620,20 @@ impl<'a, K: KVSystem<'a, K = T>, T: kv_system::KeyType + core::fmt::Debug> kv_sy
         self.value.replace(value);
 
         self.mux_kv.operation.map(|op| match op {
-            Operation::Get | Operation::Delete => {}
+            Operation::Get
+            | Operation::Delete
+            | Operation::NextKey
+            | Operation::GarbageCollect
+            | Operation::Usage
+            | Operation::Count => {}
             Operation::Set => {
+                if result.is_ok() {
+                    self.value.map(|value| {
+                        let header = KeyHeader::new_from_buf(value);
+                        self.add_usage(header.write_id, header.length as usize);
+                    });
+                }
+
                 self.unhashed_key.take().map(|unhashed_key| {
                     self.value.take().map(|value| {
                         self.client.map(move |cb| {
@@ -404,7 +657,16 @@ impl<'a, K: KVSystem<'a, K = T>, T: kv_system::KeyType + core::fmt::Debug> kv_sy
         self.hashed_key.replace(key);
 
         self.mux_kv.operation.map(|op| match op {
-            Operation::Set => {}
+            Operation::Set | Operation::GarbageCollect | Operation::Usage => {}
+            Operation::Count => {
+                let header = KeyHeader::new_from_buf(ret_buf);
+                if header.version == HEADER_VERSION {
+                    self.add_usage(header.write_id, header.length as usize);
+                }
+
+                self.header_value.replace(ret_buf);
+                self.continue_next_key(self.position.get());
+            }
             Operation::Delete => {
                 let mut access_allowed = false;
 
@@ -475,6 +737,39 @@ impl<'a, K: KVSystem<'a, K = T>, T: kv_system::KeyType + core::fmt::Debug> kv_sy
                 });
                 self.mux_kv.operation.clear();
             }
+            Operation::NextKey => {
+                let header = KeyHeader::new_from_buf(ret_buf);
+                let mut read_allowed = false;
+
+                // The buffer only holds the header, so the read reports an
+                // error even if the header was read.
+                if header.version == HEADER_VERSION {
+                    self.valid_ids.map(|perms| {
+                        read_allowed = perms.check_read_permission(header.write_id);
+                    });
+                }
+
+                self.header_value.replace(ret_buf);
+
+                if read_allowed {
+                    self.hashed_key.map(|hashed_key| {
+                        let key = *hashed_key;
+                        self.client.map(move |cb| {
+                            cb.next_key_complete(
+                                Ok(()),
+                                key,
+                                header.length as usize,
+                                self.position.get(),
+                            );
+                        });
+                    });
+                    self.mux_kv.operation.clear();
+                } else {
+                    // Skip keys the caller can't read and keys not stored
+                    // by this capsule, such as the main key of TicKV
+                    self.continue_next_key(self.position.get());
+                }
+            }
         });
 
         self.mux_kv.do_next_op();
@@ -484,8 +779,21 @@ impl<'a, K: KVSystem<'a, K = T>, T: kv_system::KeyType + core::fmt::Debug> kv_sy
         self.hashed_key.replace(key);
 
         self.mux_kv.operation.map(|op| match op {
-            Operation::Set | Operation::Get => {}
+            Operation::Set
+            | Operation::Get
+            | Operation::NextKey
+            | Operation::GarbageCollect
+            | Operation::Usage
+            | Operation::Count => {}
             Operation::Delete => {
+                if result.is_ok() {
+                    // The header of the key was read to check the permissions
+                    self.header_value.map(|header_value| {
+                        let header = KeyHeader::new_from_buf(header_value);
+                        self.remove_usage(header.write_id, header.length as usize);
+                    });
+                }
+
                 self.unhashed_key.take().map(|unhashed_key| {
                     self.client.map(move |cb| {
                         cb.delete_complete(result, unhashed_key);
@@ -499,10 +807,82 @@ impl<'a, K: KVSystem<'a, K = T>, T: kv_system::KeyType + core::fmt::Debug> kv_sy
         self.mux_kv.do_next_op();
     }
 
-    fn garbage_collect_complete(&self, _result: Result<(), ErrorCode>) {
+    fn garbage_collect_complete(&self, result: Result<(), ErrorCode>) {
+        if self.mux_kv.operation.contains(&Operation::GarbageCollect) {
+            self.mux_kv.operation.clear();
+            self.client.map(move |cb| {
+                cb.garbage_collect_complete(result);
+            });
+        }
+
         self.mux_kv.perform_cleanup.set(false);
         self.mux_kv.do_next_op();
     }
+
+    fn next_key_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: &'static mut T,
+        position: usize,
+    ) {
+        self.hashed_key.replace(key);
+
+        self.mux_kv.operation.map(|op| match op {
+            Operation::Get
+            | Operation::Set
+            | Operation::Delete
+            | Operation::GarbageCollect
+            | Operation::Usage => {}
+            Operation::NextKey | Operation::Count => {
+                if result.is_err() {
+                    if *op == Operation::NextKey {
+                        self.hashed_key.map(|hashed_key| {
+                            let key = *hashed_key;
+                            self.client.map(move |cb| {
+                                cb.next_key_complete(result, key, 0, 0);
+                            });
+                        });
+                    }
+                    self.mux_kv.operation.clear();
+                    return;
+                }
+
+                // Read the header of the key, to check the permissions
+                self.position.set(position);
+                self.header_value.take().map(|header_value| {
+                    header_value.iter_mut().for_each(|m| *m = 0xFF);
+
+                    self.hashed_key.take().map(|hashed_key| {
+                        if let Err((hashed_key, header_value, e)) =
+                            self.mux_kv.kv.get_value(hashed_key, header_value)
+                        {
+                            let key = *hashed_key;
+                            self.hashed_key.replace(hashed_key);
+                            self.header_value.replace(header_value);
+                            if self.mux_kv.operation.take() == Some(Operation::NextKey) {
+                                self.client.map(move |cb| {
+                                    cb.next_key_complete(e.and(Err(ErrorCode::FAIL)), key, 0, 0);
+                                });
+                            }
+                        }
+                    });
+                });
+            }
+        });
+
+        self.mux_kv.do_next_op();
+    }
+
+    fn usage_complete(&self, result: Result<(), ErrorCode>, live_bytes: usize, dead_bytes: usize) {
+        if self.mux_kv.operation.contains(&Operation::Usage) {
+            self.mux_kv.operation.clear();
+            self.client.map(move |cb| {
+                cb.usage_complete(result, live_bytes, dead_bytes);
+            });
+        }
+
+        self.mux_kv.do_next_op();
+    }
 }
 
 pub struct MuxKVStore<'a, K: KVSystem<'a> + KVSystem<'a, K = T>, T: 'static + kv_system::KeyType> {
@@ -535,6 +915,17 @@ impl<'a, K: KVSystem<'a> + KVSystem<'a, K = T>, T: 'static + kv_system::KeyType>
             node.next_operation.map(|op| {
                 self.operation.set(op.clone());
 
+                match *op {
+                    Operation::NextKey | Operation::GarbageCollect | Operation::Usage => {
+                        // These don't need a key to be hashed
+                        node.next_operation.clear();
+                        node.valid_ids.insert(node.next_valid_ids.take());
+                        node.start_queued(*op);
+                        return;
+                    }
+                    Operation::Get | Operation::Set | Operation::Delete | Operation::Count => {}
+                }
+
                 node.unhashed_key.take().map(|unhashed_key| {
                     node.hashed_key.take().map(|hashed_key| {
                         match op {
@@ -575,6 +966,10 @@ impl<'a, K: KVSystem<'a> + KVSystem<'a, K = T>, T: 'static + kv_system::KeyType>
                                     });
                                 }
                             }
+                            Operation::NextKey
+                            | Operation::GarbageCollect
+                            | Operation::Usage
+                            | Operation::Count => {}
                         };
                     });
                 });
@@ -17,6 +17,9 @@ pub mod analog_comparator;
 pub mod analog_sensor;
 pub mod apds9960;
 pub mod app_flash_driver;
+pub mod app_loader;
+pub mod backoff_fault_policy;
+pub mod benchmark;
 pub mod ble_advertising_driver;
 pub mod bme280;
 pub mod bmp280;
@@ -26,15 +29,25 @@ pub mod buzzer_pwm;
 pub mod can;
 pub mod ccs811;
 pub mod crc;
+pub mod csprng;
 pub mod ctap;
 pub mod dac;
+pub mod deadline;
 pub mod debug_process_restart;
+pub mod enc28j60;
+pub mod esp32_at;
+pub mod firmware_update;
+pub mod flash_crash_report;
+pub mod flash_fs;
+pub mod flash_fs_driver;
 pub mod fm25cl;
 pub mod ft6x06;
 pub mod fxos8700cq;
 pub mod gpio_async;
+pub mod hci_uart;
 pub mod hd44780;
 pub mod hmac;
+pub mod host_control;
 pub mod hts221;
 pub mod humidity;
 pub mod ieee802154;
@@ -44,6 +57,9 @@ pub mod kv_store;
 pub mod l3gd20;
 pub mod led_matrix;
 pub mod log;
+pub mod log_driver;
+pub mod lora;
+pub mod lorawan;
 pub mod lpm013m126;
 pub mod lps25hb;
 pub mod lsm303agr;
@@ -57,14 +73,21 @@ pub mod mlx90614;
 pub mod mx25r6435f;
 pub mod ninedof;
 pub mod nonvolatile_storage_driver;
+pub mod nonvolatile_to_blocks;
 pub mod nonvolatile_to_pages;
 pub mod nrf51822_serialization;
 pub mod panic_button;
 pub mod pca9544a;
+pub mod periodic_timer;
+pub mod persistent_short_id;
+pub mod process_info;
+pub mod process_integrity;
+pub mod process_suspend;
 pub mod proximity;
 pub mod public_key_crypto;
 pub mod pwm;
 pub mod read_only_state;
+pub mod reset;
 pub mod rf233;
 pub mod rf233_const;
 pub mod screen;
@@ -73,11 +96,15 @@ pub mod segger_rtt;
 pub mod seven_segment;
 pub mod sha;
 pub mod sha256;
+pub mod sha512;
+pub mod sha_software;
 pub mod sht3x;
 pub mod si7021;
 pub mod sip_hash;
 pub mod sound_pressure;
 pub mod st77xx;
+pub mod supervisor_fault_policy;
+pub mod sx127x;
 pub mod symmetric_encryption;
 pub mod temperature;
 pub mod temperature_rp2040;
@@ -87,3 +114,5 @@ pub mod tickv;
 pub mod touch;
 pub mod tsl2561;
 pub mod usb;
+pub mod userspace_driver;
+pub mod wifi;
@@ -752,12 +752,15 @@ impl<'a, F: Flash + 'static> LogWrite<'a> for Log<'a, F> {
     ///     * Ok(()): append succeeded.
     ///     * FAIL: write failed due to flash error.
     fn sync(&self) -> Result<(), ErrorCode> {
-        if self.append_entry_id.get() % self.page_size == PAGE_HEADER_SIZE {
-            // Pagebuffer empty, don't need to flush.
-            return Ok(());
-        } else if self.state.get() != State::Idle {
+        if self.state.get() != State::Idle {
             // Log busy, try appending again later.
             return Err(ErrorCode::BUSY);
+        } else if self.append_entry_id.get() % self.page_size == PAGE_HEADER_SIZE {
+            // Pagebuffer empty, don't need to flush.
+            self.state.set(State::Sync);
+            self.error.set(Ok(()));
+            self.deferred_client_callback();
+            return Ok(());
         }
 
         self.pagebuffer
@@ -0,0 +1,519 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Provides userspace with access to a shared persistent log.
+//!
+//! The driver stores each entry in the log with a sequence number and a
+//! CRC-32 of the sequence number and data, and checks the CRC when apps read
+//! the entry back. Sequence numbers increase by one per entry, so apps can
+//! tell from a gap that entries were overwritten by a circular log. At boot
+//! the driver reads the whole log to continue after the newest sequence
+//! number, and queues commands until it is done.
+//!
+//! Appended entries are durable once the log is synced, until then they may
+//! be lost on a power loss. Each app reads the log with its own position,
+//! which starts at the oldest entry.
+//!
+//! Commands
+//! --------
+//!
+//! - 0: Check if the driver is present.
+//! - 1: Append the data in read-only allow 0 as a new entry. The upcall
+//!   passes the entry's sequence number and whether older entries were
+//!   overwritten.
+//! - 2: Sync the log to storage.
+//! - 3: Move the app's read position to the oldest entry in the log.
+//! - 4: Read the entry at the app's read position into read-write allow 0,
+//!   and move the position to the next entry. The upcall passes the length of
+//!   the entry's data and its sequence number. Fails with `NOSUPPORT` when
+//!   there are no more entries, and with `FAIL` if the entry's CRC doesn't
+//!   match, in which case the position still moves past it.
+//!
+//! Commands 1, 2 and 4 finish with upcall 0, which passes the status first.
+//! An app can have one such command waiting while another app's runs.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let log_driver = components::log_driver::LogDriverComponent::new(
+//!     log,
+//!     board_kernel,
+//!     capsules_extra::log_driver::DRIVER_NUM,
+//! )
+//! .finalize(components::log_driver_component_static!(
+//!     capsules_extra::log::Log<'static, nrf52840::nvmc::Nvmc>
+//! ));
+//! ```
+
+use core::cell::Cell;
+
+use capsules_core::driver;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::log::{LogRead, LogReadClient, LogWrite, LogWriteClient};
+use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::{ErrorCode, ProcessId};
+
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = driver::NUM::Log as usize;
+
+/// The length of the buffer entries are appended and read through, including
+/// the header.
+pub const BUF_LEN: usize = 256;
+
+/// The sequence number and CRC stored in front of each entry's data.
+pub const HEADER_LEN: usize = 8;
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    pub const DATA: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    pub const DATA: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+/// Ids for upcalls
+mod upcalls {
+    pub const DONE: usize = 0;
+    /// The number of upcalls the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Command {
+    Append,
+    Sync,
+    Read,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum State {
+    /// Reading the log at boot to find the newest sequence number.
+    Recover,
+    Idle,
+    Append,
+    Sync,
+    /// Moving the log's read position to the app's.
+    Seek,
+    Read,
+}
+
+#[derive(Default)]
+pub struct App {
+    /// The command waiting for another app's command to finish.
+    pending: Option<Command>,
+    /// The entry the app reads next, or `None` for the oldest entry.
+    position: Option<usize>,
+}
+
+/// CRC-32 (IEEE 802.3) of the bytes of all `chunks`.
+fn crc32(chunks: &[&[u8]]) -> u32 {
+    let mut crc = 0xFFFF_FFFF;
+    for &byte in chunks.iter().flat_map(|chunk| chunk.iter()) {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            if crc & 1 != 0 {
+                crc = (crc >> 1) ^ 0xEDB8_8320;
+            } else {
+                crc >>= 1;
+            }
+        }
+    }
+    !crc
+}
+
+/// Fill in the header of the entry with `len` bytes of data after the header
+/// in `buffer`, with sequence number `seq`.
+fn encode_entry(buffer: &mut [u8], seq: u32, len: usize) {
+    buffer[0..4].copy_from_slice(&seq.to_le_bytes());
+    let crc = crc32(&[&buffer[0..4], &buffer[HEADER_LEN..HEADER_LEN + len]]);
+    buffer[4..HEADER_LEN].copy_from_slice(&crc.to_le_bytes());
+}
+
+/// The sequence number of the entry of `length` bytes in `buffer`, if its
+/// CRC matches.
+fn parse_entry(buffer: &[u8], length: usize) -> Option<u32> {
+    if length < HEADER_LEN {
+        return None;
+    }
+    let seq = u32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]);
+    let crc = u32::from_le_bytes([buffer[4], buffer[5], buffer[6], buffer[7]]);
+
+    if crc32(&[&buffer[0..4], &buffer[HEADER_LEN..length]]) == crc {
+        Some(seq)
+    } else {
+        None
+    }
+}
+
+pub struct LogDriver<'a, L: LogRead<'a, EntryID = usize> + LogWrite<'a>> {
+    log: &'a L,
+    apps: Grant<
+        App,
+        UpcallCount<{ upcalls::COUNT }>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+    state: Cell<State>,
+    /// The app whose command runs.
+    current: OptionalCell<ProcessId>,
+    buffer: TakeCell<'static, [u8]>,
+    /// The sequence number of the next appended entry.
+    next_seq: Cell<u32>,
+}
+
+impl<'a, L: LogRead<'a, EntryID = usize> + LogWrite<'a>> LogDriver<'a, L> {
+    pub fn new(
+        log: &'a L,
+        grant: Grant<
+            App,
+            UpcallCount<{ upcalls::COUNT }>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+        buffer: &'static mut [u8; BUF_LEN],
+    ) -> LogDriver<'a, L> {
+        LogDriver {
+            log,
+            apps: grant,
+            state: Cell::new(State::Recover),
+            current: OptionalCell::empty(),
+            buffer: TakeCell::new(buffer),
+            next_seq: Cell::new(0),
+        }
+    }
+
+    /// Read the log to continue after its newest sequence number. Call this
+    /// once at boot, commands wait until it is done.
+    pub fn recover(&self) -> Result<(), ErrorCode> {
+        self.state.set(State::Recover);
+        self.log.seek(self.log.log_start()).map_err(|e| {
+            self.state.set(State::Idle);
+            e
+        })
+    }
+
+    /// Read the next entry at the log's read position. Returns the error if
+    /// the read couldn't start, with `NOSUPPORT` at the end of the log.
+    fn read_next(&self) -> Result<(), ErrorCode> {
+        let buffer = self.buffer.take().ok_or(ErrorCode::BUSY)?;
+        let len = buffer.len();
+        self.log.read(buffer, len).map_err(|(e, buffer)| {
+            self.buffer.replace(buffer);
+            if e == ErrorCode::FAIL {
+                ErrorCode::NOSUPPORT
+            } else {
+                e
+            }
+        })
+    }
+
+    /// Start `command` of `processid`.
+    fn start(&self, processid: ProcessId, command: Command) -> Result<(), ErrorCode> {
+        match command {
+            Command::Append => {
+                let buffer = self.buffer.take().ok_or(ErrorCode::BUSY)?;
+                let seq = self.next_seq.get();
+                let len = self
+                    .apps
+                    .enter(processid, |_, kernel_data| {
+                        kernel_data
+                            .get_readonly_processbuffer(ro_allow::DATA)
+                            .and_then(|data| {
+                                data.enter(|data| {
+                                    if data.len() == 0 {
+                                        Err(ErrorCode::INVAL)
+                                    } else if HEADER_LEN + data.len() > buffer.len() {
+                                        Err(ErrorCode::SIZE)
+                                    } else {
+                                        data.copy_to_slice(
+                                            &mut buffer[HEADER_LEN..HEADER_LEN + data.len()],
+                                        );
+                                        Ok(data.len())
+                                    }
+                                })
+                            })
+                            .unwrap_or(Err(ErrorCode::RESERVE))
+                    })
+                    .unwrap_or_else(|err| Err(err.into()));
+                let len = match len {
+                    Ok(len) => len,
+                    Err(e) => {
+                        self.buffer.replace(buffer);
+                        return Err(e);
+                    }
+                };
+
+                encode_entry(buffer, seq, len);
+
+                self.log
+                    .append(buffer, HEADER_LEN + len)
+                    .map_err(|(e, buffer)| {
+                        self.buffer.replace(buffer);
+                        e
+                    })?;
+                self.state.set(State::Append);
+            }
+            Command::Sync => {
+                self.log.sync()?;
+                self.state.set(State::Sync);
+            }
+            Command::Read => {
+                // Entries before the oldest one were overwritten, continue
+                // with the oldest one
+                let position = self
+                    .apps
+                    .enter(processid, |app, _| app.position)
+                    .map_err(ErrorCode::from)?;
+                let oldest = self.log.log_start();
+                let position = position.map_or(oldest, |position| position.max(oldest));
+
+                self.log.seek(position)?;
+                self.state.set(State::Seek);
+            }
+        }
+
+        self.current.set(processid);
+        Ok(())
+    }
+
+    /// Report the end of the running command with `args` after the status,
+    /// and start the next waiting one.
+    fn finish(&self, result: Result<(), ErrorCode>, args: (usize, usize)) {
+        self.state.set(State::Idle);
+        if let Some(processid) = self.current.take() {
+            let _ = self.apps.enter(processid, |_, kernel_data| {
+                kernel_data
+                    .schedule_upcall(
+                        upcalls::DONE,
+                        (kernel::errorcode::into_statuscode(result), args.0, args.1),
+                    )
+                    .ok();
+            });
+        }
+
+        self.run_next();
+    }
+
+    /// Start the next command that waits, failing those that can't start.
+    fn run_next(&self) {
+        for app in self.apps.iter() {
+            let processid = app.processid();
+            let command = app.enter(|app, _| app.pending.take());
+            if let Some(command) = command {
+                match self.start(processid, command) {
+                    Ok(()) => break,
+                    Err(e) => {
+                        let _ = self.apps.enter(processid, |_, kernel_data| {
+                            kernel_data
+                                .schedule_upcall(
+                                    upcalls::DONE,
+                                    (kernel::errorcode::into_statuscode(Err(e)), 0, 0),
+                                )
+                                .ok();
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<'a, L: LogRead<'a, EntryID = usize> + LogWrite<'a>> LogReadClient for LogDriver<'a, L> {
+    fn read_done(&self, buffer: &'static mut [u8], length: usize, error: Result<(), ErrorCode>) {
+        let entry = error.map(|()| parse_entry(buffer, length));
+
+        match self.state.get() {
+            State::Recover => {
+                self.buffer.replace(buffer);
+                if let Ok(Some(seq)) = entry {
+                    if seq >= self.next_seq.get() {
+                        self.next_seq.set(seq.wrapping_add(1));
+                    }
+                }
+
+                // Stop at the end of the log
+                if error.is_err() || self.read_next().is_err() {
+                    self.state.set(State::Idle);
+                    self.run_next();
+                }
+            }
+            State::Read => {
+                let next = self.log.next_read_entry_id();
+                let result = self.current.map_or(Err(ErrorCode::RESERVE), |processid| {
+                    self.apps
+                        .enter(*processid, |app, kernel_data| {
+                            app.position = Some(next);
+                            let seq = entry?.ok_or(ErrorCode::FAIL)?;
+                            let data = &buffer[HEADER_LEN..length];
+                            kernel_data
+                                .get_readwrite_processbuffer(rw_allow::DATA)
+                                .and_then(|allowed| {
+                                    allowed.mut_enter(|allowed| {
+                                        let copied = allowed.len().min(data.len());
+                                        allowed[..copied].copy_from_slice(&data[..copied]);
+                                    })
+                                })
+                                .map_err(ErrorCode::from)?;
+                            Ok((data.len(), seq as usize))
+                        })
+                        .unwrap_or_else(|err| Err(err.into()))
+                });
+                self.buffer.replace(buffer);
+
+                match result {
+                    Ok(args) => self.finish(Ok(()), args),
+                    Err(e) => self.finish(Err(e), (0, 0)),
+                }
+            }
+            _ => {
+                self.buffer.replace(buffer);
+            }
+        }
+    }
+
+    fn seek_done(&self, error: Result<(), ErrorCode>) {
+        match self.state.get() {
+            State::Recover => {
+                if error.is_err() || self.read_next().is_err() {
+                    self.state.set(State::Idle);
+                    self.run_next();
+                }
+            }
+            State::Seek => match error.and_then(|()| self.read_next()) {
+                Ok(()) => self.state.set(State::Read),
+                Err(e) => self.finish(Err(e), (0, 0)),
+            },
+            _ => {}
+        }
+    }
+}
+
+impl<'a, L: LogRead<'a, EntryID = usize> + LogWrite<'a>> LogWriteClient for LogDriver<'a, L> {
+    fn append_done(
+        &self,
+        buffer: &'static mut [u8],
+        _length: usize,
+        records_lost: bool,
+        error: Result<(), ErrorCode>,
+    ) {
+        self.buffer.replace(buffer);
+        let seq = self.next_seq.get();
+        if error.is_ok() {
+            self.next_seq.set(seq.wrapping_add(1));
+        }
+        self.finish(error, (seq as usize, records_lost as usize));
+    }
+
+    fn sync_done(&self, error: Result<(), ErrorCode>) {
+        if self.state.get() == State::Sync {
+            self.finish(error, (0, 0));
+        }
+    }
+
+    fn erase_done(&self, _error: Result<(), ErrorCode>) {}
+}
+
+impl<'a, L: LogRead<'a, EntryID = usize> + LogWrite<'a>> SyscallDriver for LogDriver<'a, L> {
+    fn command(
+        &self,
+        command_num: usize,
+        _data1: usize,
+        _data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        let command = match command_num {
+            0 => return CommandReturn::success(),
+            1 => Command::Append,
+            2 => Command::Sync,
+            3 => {
+                return self
+                    .apps
+                    .enter(processid, |app, _| {
+                        app.position = None;
+                        CommandReturn::success()
+                    })
+                    .unwrap_or_else(|err| err.into());
+            }
+            4 => Command::Read,
+            _ => return CommandReturn::failure(ErrorCode::NOSUPPORT),
+        };
+
+        if self.state.get() == State::Idle {
+            match self.start(processid, command) {
+                Ok(()) => CommandReturn::success(),
+                Err(e) => CommandReturn::failure(e),
+            }
+        } else {
+            // Another command runs, wait for it unless this app already
+            // has a command waiting
+            self.apps
+                .enter(processid, |app, _| {
+                    if app.pending.is_some() {
+                        CommandReturn::failure(ErrorCode::BUSY)
+                    } else {
+                        app.pending = Some(command);
+                        CommandReturn::success()
+                    }
+                })
+                .unwrap_or_else(|err| err.into())
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(&[b"123456789"]), 0xCBF4_3926);
+        assert_eq!(crc32(&[b"1234", b"", b"56789"]), 0xCBF4_3926);
+        assert_eq!(crc32(&[]), 0);
+    }
+
+    #[test]
+    fn entries_keep_their_sequence_number() {
+        let mut buffer = [0; BUF_LEN];
+        buffer[HEADER_LEN..HEADER_LEN + 5].copy_from_slice(b"hello");
+        for seq in [0, 1, 0x1234_5678, u32::MAX] {
+            encode_entry(&mut buffer, seq, 5);
+            assert_eq!(parse_entry(&buffer, HEADER_LEN + 5), Some(seq));
+        }
+
+        // An entry without data is valid too, a shorter one is not
+        encode_entry(&mut buffer, 3, 0);
+        assert_eq!(parse_entry(&buffer, HEADER_LEN), Some(3));
+        assert_eq!(parse_entry(&buffer, HEADER_LEN - 1), None);
+    }
+
+    #[test]
+    fn crc_detects_corrupted_entries() {
+        let mut buffer = [0; BUF_LEN];
+        buffer[HEADER_LEN..HEADER_LEN + 5].copy_from_slice(b"hello");
+        encode_entry(&mut buffer, 42, 5);
+
+        // Any flipped bit in the sequence number, CRC or data is caught
+        for bit in 0..8 * (HEADER_LEN + 5) {
+            let mut corrupted = buffer;
+            corrupted[bit / 8] ^= 1 << (bit % 8);
+            assert_eq!(parse_entry(&corrupted, HEADER_LEN + 5), None);
+        }
+
+        // Entries that were cut short don't match either
+        assert_eq!(parse_entry(&buffer, HEADER_LEN + 4), None);
+    }
+}
@@ -0,0 +1,336 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Provides userspace with access to raw LoRa packets.
+//!
+//! Processes send and receive packets through a `hil::lora::LoRaRadio`,
+//! and can change its modulation, which it shares between all of them.
+//!
+//! Each process can send one packet at a time. Packets are copied from the
+//! process when the radio is free, and packets that wait for it are sent in
+//! the order of the processes in the grant. The radio receives while at
+//! least one process does, and every received packet is copied to all
+//! receiving processes.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let lora = components::lora::LoRaComponent::new(
+//!     board_kernel,
+//!     capsules_extra::lora::DRIVER_NUM,
+//!     sx127x,
+//! )
+//! .finalize(components::lora_component_static!());
+//! ```
+
+use core::cmp::min;
+
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::lora::{self, Bandwidth, CodingRate, Config, LoRaRadio, RxMetadata};
+use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::{ErrorCode, ProcessId};
+
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::LoRa as usize;
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    pub const WRITE: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    pub const READ: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+mod upcall {
+    pub const TX_DONE: usize = 0;
+    pub const RX: usize = 1;
+    pub const COUNT: u8 = 2;
+}
+
+#[derive(Default)]
+pub struct App {
+    /// The length of the packet the process is sending, if any.
+    pending_tx: Option<usize>,
+    receiving: bool,
+}
+
+pub struct LoRaDriver<'a, R: LoRaRadio<'a>> {
+    radio: &'a R,
+    apps: Grant<
+        App,
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+    /// The process whose packet the radio is sending.
+    current_tx: OptionalCell<ProcessId>,
+    kernel_tx: TakeCell<'static, [u8]>,
+}
+
+impl<'a, R: LoRaRadio<'a>> LoRaDriver<'a, R> {
+    pub fn new(
+        radio: &'a R,
+        grant: Grant<
+            App,
+            UpcallCount<{ upcall::COUNT }>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+        kernel_tx: &'static mut [u8],
+    ) -> LoRaDriver<'a, R> {
+        LoRaDriver {
+            radio,
+            apps: grant,
+            current_tx: OptionalCell::empty(),
+            kernel_tx: TakeCell::new(kernel_tx),
+        }
+    }
+
+    /// Copy the pending packet of `processid` and pass it to the radio.
+    fn perform_tx(&self, processid: ProcessId) -> Result<(), ErrorCode> {
+        let buf = self.kernel_tx.take().ok_or(ErrorCode::BUSY)?;
+        let len = self
+            .apps
+            .enter(processid, |app, kernel_data| {
+                let len = app.pending_tx.take().ok_or(ErrorCode::INVAL)?;
+                kernel_data
+                    .get_readonly_processbuffer(ro_allow::WRITE)
+                    .and_then(|write| {
+                        write.enter(|payload| {
+                            if payload.len() < len {
+                                return Err(ErrorCode::SIZE);
+                            }
+                            payload[..len].copy_to_slice(&mut buf[..len]);
+                            Ok(len)
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::NOMEM))
+            })
+            .unwrap_or_else(|err| Err(err.into()));
+        let len = match len {
+            Ok(len) => len,
+            Err(e) => {
+                self.kernel_tx.replace(buf);
+                return Err(e);
+            }
+        };
+        match self.radio.transmit(buf, len) {
+            Ok(()) => {
+                self.current_tx.set(processid);
+                Ok(())
+            }
+            Err((e, buf)) => {
+                self.kernel_tx.replace(buf);
+                Err(e)
+            }
+        }
+    }
+
+    /// Send the next waiting packet, if the radio is free. Packets that
+    /// cannot be sent are reported to their process right away.
+    fn do_next_tx(&self) {
+        while self.current_tx.is_none() {
+            let next = self.apps.iter().find_map(|app| {
+                let processid = app.processid();
+                app.enter(|app, _| app.pending_tx.map(|_| processid))
+            });
+            let processid = match next {
+                Some(processid) => processid,
+                None => return,
+            };
+            if let Err(e) = self.perform_tx(processid) {
+                let _ = self.apps.enter(processid, |app, kernel_data| {
+                    app.pending_tx = None;
+                    kernel_data
+                        .schedule_upcall(upcall::TX_DONE, (into_statuscode(Err(e)), 0, 0))
+                        .ok();
+                });
+            }
+        }
+    }
+
+    /// Receive while at least one process receives.
+    fn update_receive(&self) -> Result<(), ErrorCode> {
+        let receiving = self
+            .apps
+            .iter()
+            .any(|app| app.enter(|app, _| app.receiving));
+        if receiving {
+            self.radio.start_receive()
+        } else {
+            self.radio.stop_receive()
+        }
+    }
+
+    /// Apply `change` to the configuration of the radio.
+    fn configure<F: FnOnce(&mut Config) -> Option<()>>(&self, change: F) -> CommandReturn {
+        let mut config = self.radio.get_config();
+        if change(&mut config).is_none() {
+            return CommandReturn::failure(ErrorCode::INVAL);
+        }
+        self.radio.set_config(config).into()
+    }
+}
+
+impl<'a, R: LoRaRadio<'a>> lora::TxClient for LoRaDriver<'a, R> {
+    fn transmit_done(&self, buf: &'static mut [u8], result: Result<(), ErrorCode>) {
+        self.kernel_tx.replace(buf);
+        if let Some(processid) = self.current_tx.take() {
+            let _ = self.apps.enter(processid, |_, kernel_data| {
+                kernel_data
+                    .schedule_upcall(upcall::TX_DONE, (into_statuscode(result), 0, 0))
+                    .ok();
+            });
+        }
+        self.do_next_tx();
+    }
+}
+
+impl<'a, R: LoRaRadio<'a>> lora::RxClient for LoRaDriver<'a, R> {
+    fn receive(
+        &self,
+        buf: &'static mut [u8],
+        len: usize,
+        metadata: RxMetadata,
+        result: Result<(), ErrorCode>,
+    ) {
+        let packet = &buf[..len];
+        let signal = (metadata.rssi as u16 as usize) | (metadata.snr as u8 as usize) << 16;
+        self.apps.each(|_, app, kernel_data| {
+            if !app.receiving {
+                return;
+            }
+            let copied = kernel_data
+                .get_readwrite_processbuffer(rw_allow::READ)
+                .and_then(|read| {
+                    read.mut_enter(|rbuf| {
+                        let len = min(rbuf.len(), packet.len());
+                        rbuf[..len].copy_from_slice(&packet[..len]);
+                        len
+                    })
+                })
+                .unwrap_or(0);
+            let status = if result.is_ok() && copied < packet.len() {
+                into_statuscode(Err(ErrorCode::SIZE))
+            } else {
+                into_statuscode(result)
+            };
+            kernel_data
+                .schedule_upcall(upcall::RX, (status, copied, signal))
+                .ok();
+        });
+        self.radio.set_receive_buffer(buf);
+    }
+}
+
+impl<'a, R: LoRaRadio<'a>> SyscallDriver for LoRaDriver<'a, R> {
+    /// Send and receive packets, and configure the radio.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver existence check.
+    /// - `1`: Send the first `arg1` bytes of read-only allow 0 as one packet.
+    /// - `2`: Start receiving packets into read-write allow 0.
+    /// - `3`: Stop receiving packets.
+    /// - `4`: Set the frequency to `arg1` Hz.
+    /// - `5`: Set the spreading factor to `arg1`.
+    /// - `6`: Set the bandwidth to `arg1` Hz, rounded down.
+    /// - `7`: Set the coding rate to 4/`arg1`.
+    /// - `8`: Set the transmit power to `arg1` dBm, as a signed integer.
+    /// - `9`: Set the sync word to `arg1`.
+    fn command(
+        &self,
+        command_num: usize,
+        arg1: usize,
+        _arg2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+            1 => {
+                if arg1 == 0 || arg1 > lora::MAX_PAYLOAD_LEN {
+                    return CommandReturn::failure(ErrorCode::SIZE);
+                }
+                let queued = self
+                    .apps
+                    .enter(processid, |app, _| {
+                        if app.pending_tx.is_some() || self.current_tx.contains(&processid) {
+                            Err(ErrorCode::BUSY)
+                        } else {
+                            app.pending_tx = Some(arg1);
+                            Ok(())
+                        }
+                    })
+                    .unwrap_or_else(|err| Err(err.into()));
+                if let Err(e) = queued {
+                    return CommandReturn::failure(e);
+                }
+                if self.current_tx.is_none() {
+                    // Report errors of the packet of the process right away
+                    if let Err(e) = self.perform_tx(processid) {
+                        let _ = self.apps.enter(processid, |app, _| app.pending_tx = None);
+                        return CommandReturn::failure(e);
+                    }
+                }
+                CommandReturn::success()
+            }
+            2 | 3 => {
+                let receiving = command_num == 2;
+                let result = self
+                    .apps
+                    .enter(processid, |app, _| app.receiving = receiving)
+                    .map_err(ErrorCode::from);
+                match result.and_then(|()| self.update_receive()) {
+                    Ok(()) => CommandReturn::success(),
+                    Err(e) => CommandReturn::failure(e),
+                }
+            }
+            4 => self.configure(|config| {
+                config.frequency = arg1 as u32;
+                Some(())
+            }),
+            5 => self.configure(|config| {
+                config.spreading_factor = u8::try_from(arg1).ok()?;
+                Some(())
+            }),
+            6 => self.configure(|config| {
+                config.bandwidth = Bandwidth::from_hz(arg1 as u32)?;
+                Some(())
+            }),
+            7 => self.configure(|config| {
+                config.coding_rate = match arg1 {
+                    5 => CodingRate::Cr4_5,
+                    6 => CodingRate::Cr4_6,
+                    7 => CodingRate::Cr4_7,
+                    8 => CodingRate::Cr4_8,
+                    _ => return None,
+                };
+                Some(())
+            }),
+            8 => self.configure(|config| {
+                config.tx_power = i8::try_from(arg1 as i32).ok()?;
+                Some(())
+            }),
+            9 => self.configure(|config| {
+                config.sync_word = u8::try_from(arg1).ok()?;
+                Some(())
+            }),
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
@@ -0,0 +1,223 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Provides userspace with access to a LoRaWAN network.
+//!
+//! Processes join the network and send uplinks through a `mac::LoRaWan`,
+//! which all of them share. The MAC is busy with one join or uplink at a
+//! time, so processes retry commands that fail with `BUSY`. The payloads of
+//! downlinks are copied to all processes.
+
+use core::cmp::min;
+
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::{ErrorCode, ProcessId};
+
+use super::mac::{self, LoRaWan};
+
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::LoRaWan as usize;
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    pub const UPLINK: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    pub const DOWNLINK: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+mod upcall {
+    pub const JOIN_DONE: usize = 0;
+    pub const SEND_DONE: usize = 1;
+    pub const DOWNLINK: usize = 2;
+    pub const COUNT: u8 = 3;
+}
+
+/// The flag of `command` 2 for confirmed uplinks.
+const CONFIRMED: usize = 1 << 8;
+
+#[derive(Default)]
+pub struct App {}
+
+pub struct LoRaWanDriver<'a, M: LoRaWan<'a>> {
+    mac: &'a M,
+    apps: Grant<
+        App,
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+    /// The process whose join or uplink the MAC is busy with.
+    current: OptionalCell<ProcessId>,
+    kernel_tx: TakeCell<'static, [u8]>,
+}
+
+impl<'a, M: LoRaWan<'a>> LoRaWanDriver<'a, M> {
+    /// `kernel_tx` must be at least `mac::MAX_PAYLOAD_LEN` bytes long.
+    pub fn new(
+        mac: &'a M,
+        grant: Grant<
+            App,
+            UpcallCount<{ upcall::COUNT }>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+        kernel_tx: &'static mut [u8],
+    ) -> LoRaWanDriver<'a, M> {
+        LoRaWanDriver {
+            mac,
+            apps: grant,
+            current: OptionalCell::empty(),
+            kernel_tx: TakeCell::new(kernel_tx),
+        }
+    }
+
+    /// Copy the first `len` bytes of the uplink buffer of `processid` and
+    /// pass them to the MAC.
+    fn send(
+        &self,
+        processid: ProcessId,
+        port: u8,
+        len: usize,
+        confirmed: bool,
+    ) -> Result<(), ErrorCode> {
+        let buf = self.kernel_tx.take().ok_or(ErrorCode::BUSY)?;
+        let result = if len > buf.len() {
+            Err(ErrorCode::SIZE)
+        } else {
+            self.apps
+                .enter(processid, |_, kernel_data| {
+                    kernel_data
+                        .get_readonly_processbuffer(ro_allow::UPLINK)
+                        .and_then(|uplink| {
+                            uplink.enter(|payload| {
+                                if payload.len() < len {
+                                    return Err(ErrorCode::SIZE);
+                                }
+                                payload[..len].copy_to_slice(&mut buf[..len]);
+                                Ok(())
+                            })
+                        })
+                        .unwrap_or(Err(ErrorCode::NOMEM))
+                })
+                .unwrap_or_else(|err| Err(err.into()))
+        };
+        let result = result.and_then(|()| self.mac.send(port, &buf[..len], confirmed));
+        self.kernel_tx.replace(buf);
+        result
+    }
+
+    /// Report the end of a join or uplink to the process that started it.
+    fn done(&self, upcall_num: usize, result: Result<(), ErrorCode>) {
+        if let Some(processid) = self.current.take() {
+            let _ = self.apps.enter(processid, |_, kernel_data| {
+                kernel_data
+                    .schedule_upcall(upcall_num, (into_statuscode(result), 0, 0))
+                    .ok();
+            });
+        }
+    }
+}
+
+impl<'a, M: LoRaWan<'a>> mac::Client for LoRaWanDriver<'a, M> {
+    fn join_done(&self, result: Result<(), ErrorCode>) {
+        self.done(upcall::JOIN_DONE, result);
+    }
+
+    fn send_done(&self, result: Result<(), ErrorCode>) {
+        self.done(upcall::SEND_DONE, result);
+    }
+
+    fn receive(&self, port: u8, payload: &[u8]) {
+        self.apps.each(|_, _, kernel_data| {
+            let copied = kernel_data
+                .get_readwrite_processbuffer(rw_allow::DOWNLINK)
+                .and_then(|downlink| {
+                    downlink.mut_enter(|buf| {
+                        let len = min(buf.len(), payload.len());
+                        buf[..len].copy_from_slice(&payload[..len]);
+                        len
+                    })
+                })
+                .unwrap_or(0);
+            let result = if copied < payload.len() {
+                Err(ErrorCode::SIZE)
+            } else {
+                Ok(())
+            };
+            kernel_data
+                .schedule_upcall(
+                    upcall::DOWNLINK,
+                    (into_statuscode(result), copied, port as usize),
+                )
+                .ok();
+        });
+    }
+}
+
+impl<'a, M: LoRaWan<'a>> SyscallDriver for LoRaWanDriver<'a, M> {
+    /// Join the network and send uplinks.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver existence check.
+    /// - `1`: Join the network.
+    /// - `2`: Send the first `arg1` bytes of read-only allow 0 as an uplink
+    ///   on the port in the low byte of `arg2`, confirmed if bit 8 of `arg2`
+    ///   is set.
+    /// - `3`: Set the data rate of uplinks to `arg1`.
+    /// - `4`: Whether the device joined the network.
+    fn command(
+        &self,
+        command_num: usize,
+        arg1: usize,
+        arg2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+            1 => {
+                if self.current.is_some() {
+                    return CommandReturn::failure(ErrorCode::BUSY);
+                }
+                let result = self.mac.join();
+                if result.is_ok() {
+                    self.current.set(processid);
+                }
+                result.into()
+            }
+            2 => {
+                if self.current.is_some() {
+                    return CommandReturn::failure(ErrorCode::BUSY);
+                }
+                let port = arg2 as u8;
+                let result = self.send(processid, port, arg1, arg2 & CONFIRMED != 0);
+                if result.is_ok() {
+                    self.current.set(processid);
+                }
+                result.into()
+            }
+            3 => u8::try_from(arg1)
+                .map_err(|_| ErrorCode::INVAL)
+                .and_then(|data_rate| self.mac.set_data_rate(data_rate))
+                .into(),
+            4 => CommandReturn::success_u32(self.mac.is_joined() as u32),
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
@@ -0,0 +1,1336 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Class A LoRaWAN 1.0.4 MAC layer for the EU868 region.
+//!
+//! `LoRaWanMac` joins a network over the air (OTAA) with the credentials of
+//! the board, and then sends uplinks and receives the downlinks that answer
+//! them. As in Class A, the radio only receives in the two windows that open
+//! after each uplink: the first on the frequency of the uplink, one second
+//! after it (five seconds after a join request), and the second on 869.525
+//! MHz one second later. The first window stays open until the second opens,
+//! and the second stays open for `RX2_WINDOW_MS`, so a downlink that is still
+//! being received when its window closes is lost.
+//!
+//! All cryptography runs on the AES hardware: the MICs are AES-CMACs, built
+//! from one ECB operation for the subkeys and one CBC-MAC, and payloads are
+//! encrypted by XORing them with ECB-encrypted counter blocks. The join
+//! accept is decrypted with an ECB encryption, as the network encrypts it
+//! with a decryption.
+//!
+//! The DevNonce, the session and the frame counters are kept in the key-value
+//! store, so that a device that reboots neither reuses a DevNonce nor its
+//! frame counters. The DevNonce is stored before each join request. Uplink
+//! frame counters are reserved in blocks of `RESERVED_COUNTERS`, like the
+//! IEEE 802.15.4 frame counters, and the downlink frame counter is stored
+//! along with each reservation; downlinks received after the last one may be
+//! accepted again after a reboot.
+//!
+//! Not supported: MAC commands, which are skipped, ADR, retransmissions of
+//! unacknowledged confirmed uplinks, and duty cycle limits, which the users
+//! of the MAC must keep.
+//!
+//! Usage
+//! -----
+//!
+//! The MAC is created by `components::lorawan::LoRaWanComponent`, along with
+//! its syscall driver.
+
+use core::cell::Cell;
+
+use kernel::hil::kv_system::{self, KVSystem};
+use kernel::hil::lora::{self, Bandwidth, CodingRate, LoRaRadio, RxMetadata};
+use kernel::hil::symmetric_encryption::{
+    self, AES128, AES128CBC, AES128ECB, AES128_BLOCK_SIZE, AES128_KEY_SIZE,
+};
+use kernel::hil::time::{self, Alarm, ConvertTicks};
+use kernel::storage_permissions::StoragePermissions;
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+use crate::kv_store::KVStore;
+
+/// The longest payload of an uplink at each data rate, DR0 to DR5.
+const MAX_PAYLOAD_LENS: [usize; 6] = [51, 51, 51, 115, 222, 222];
+
+/// The length of the longest payload, at the highest data rates.
+pub const MAX_PAYLOAD_LEN: usize = 222;
+
+/// The length of the buffer AES operations run in: a block before the longest
+/// frame, padded to a block, and a block to derive the subkeys of a CMAC in.
+pub const CRYPT_BUF_LEN: usize = 18 * AES128_BLOCK_SIZE;
+const SUBKEY_OFFSET: usize = CRYPT_BUF_LEN - AES128_BLOCK_SIZE;
+
+/// The number of uplink frame counters reserved with each write to the store.
+pub const RESERVED_COUNTERS: u32 = 64;
+
+/// The key the DevNonce, session and frame counters are stored under.
+pub const KEY: &[u8] = b"lorawan-session";
+pub const KEY_LEN: usize = KEY.len();
+
+/// The length of the stored record.
+const RECORD_LEN: usize = 50;
+
+/// The length of the value buffer, which also holds the header the key-value
+/// store adds to the record.
+pub const VALUE_BUF_LEN: usize = 64;
+
+/// How long the second receive window stays open, in milliseconds.
+const RX2_WINDOW_MS: u32 = 3000;
+const JOIN_ACCEPT_DELAY_MS: u32 = 5000;
+
+/// The parameters of the EU868 region.
+mod eu868 {
+    /// The default channels, which joins use.
+    pub const DEFAULT_CHANNELS: [u32; 3] = [868_100_000, 868_300_000, 868_500_000];
+    /// The number of channels, including those a join accept adds.
+    pub const CHANNELS: usize = 8;
+    pub const RX2_FREQUENCY: u32 = 869_525_000;
+    pub const RX2_DATA_RATE: u8 = 0;
+    pub const MAX_DATA_RATE: u8 = 5;
+    pub const TX_POWER: i8 = 14;
+    pub const SYNC_WORD: u8 = 0x34;
+}
+
+/// The MAC header: the message type and the major version, LoRaWAN R1.
+mod mhdr {
+    pub const JOIN_REQUEST: u8 = 0x00;
+    pub const JOIN_ACCEPT: u8 = 0x20;
+    pub const UNCONFIRMED_UP: u8 = 0x40;
+    pub const UNCONFIRMED_DOWN: u8 = 0x60;
+    pub const CONFIRMED_UP: u8 = 0x80;
+    pub const CONFIRMED_DOWN: u8 = 0xa0;
+}
+
+const FCTRL_ACK: u8 = 0x20;
+const FCTRL_FOPTS_LEN: u8 = 0x0f;
+
+/// The length of a data frame up to its FPort: MHDR, DevAddr, FCtrl and FCnt.
+const FHDR_LEN: usize = 8;
+const MIC_LEN: usize = 4;
+
+/// The identity of a device and the root key it joins networks with. The
+/// EUIs are most significant byte first, as they are usually written.
+#[derive(Copy, Clone)]
+pub struct Credentials {
+    pub dev_eui: [u8; 8],
+    pub join_eui: [u8; 8],
+    pub app_key: [u8; AES128_KEY_SIZE],
+}
+
+pub trait Client {
+    /// Called when a join started with `join` is done: with `Ok(())` once the
+    /// device joined, and `Err(NOACK)` if no join accept was received.
+    fn join_done(&self, result: Result<(), ErrorCode>);
+
+    /// Called when an uplink passed to `send` is done, after the receive
+    /// windows that follow it. Confirmed uplinks that the network did not
+    /// acknowledge are done with `Err(NOACK)`.
+    fn send_done(&self, result: Result<(), ErrorCode>);
+
+    /// Called with the application payload of a downlink on `port`, before
+    /// the uplink it answers is done.
+    fn receive(&self, port: u8, payload: &[u8]);
+}
+
+pub trait LoRaWan<'a> {
+    fn set_client(&self, client: &'a dyn Client);
+
+    /// Whether the device joined a network.
+    fn is_joined(&self) -> bool;
+
+    /// Join a network, replacing the current session. `join_done` is called
+    /// once the device joined or gave up, if `Ok(())` is returned.
+    ///
+    /// Return values:
+    ///   - Ok(()): a join request will be sent.
+    ///   - Err(BUSY): the MAC is busy with another uplink, or the stored
+    ///     session is not loaded yet.
+    ///   - Err(FAIL): all DevNonces are used.
+    fn join(&self) -> Result<(), ErrorCode>;
+
+    /// Set the data rate of the uplinks that follow, DR0 to DR5.
+    fn set_data_rate(&self, data_rate: u8) -> Result<(), ErrorCode>;
+
+    /// Send `payload` on `port`, 1 to 223. `send_done` is called once the
+    /// uplink is done, if `Ok(())` is returned.
+    ///
+    /// Return values:
+    ///   - Ok(()): the uplink will be sent.
+    ///   - Err(OFF): the device did not join a network.
+    ///   - Err(BUSY): the MAC is busy with another uplink.
+    ///   - Err(INVAL): `port` is not an application port.
+    ///   - Err(SIZE): `payload` is too long for the data rate.
+    fn send(&self, port: u8, payload: &[u8], confirmed: bool) -> Result<(), ErrorCode>;
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Exchange {
+    Join,
+    Uplink,
+}
+
+/// Where the MAC is in the current exchange.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Phase {
+    Idle,
+    /// Waiting for the DevNonce or frame counters to be stored.
+    Reserve,
+    /// Preparing the frame to send.
+    Prepare,
+    Transmit,
+    WaitRx1,
+    Rx1,
+    Rx2,
+    /// Checking a received frame.
+    Check,
+    /// Waiting for the session of a join to be stored.
+    Commit,
+}
+
+/// What the running AES operation is for.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Step {
+    JoinRequestMic,
+    JoinAcceptDecrypt,
+    JoinAcceptMic,
+    SessionKeys,
+    UplinkEncrypt,
+    UplinkMic,
+    DownlinkMic,
+    DownlinkDecrypt,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum StoreState {
+    Idle,
+    Loading,
+    Deleting,
+    Storing,
+}
+
+#[derive(Copy, Clone)]
+struct Session {
+    dev_addr: u32,
+    nwk_s_key: [u8; AES128_KEY_SIZE],
+    app_s_key: [u8; AES128_KEY_SIZE],
+    rx1_dr_offset: u8,
+    rx2_data_rate: u8,
+    /// The delay of the first receive window, in seconds.
+    rx_delay: u8,
+    /// The uplink channels, 0 for unused ones. The channels a join accept
+    /// adds are not stored.
+    channels: [u32; eu868::CHANNELS],
+}
+
+impl Default for Session {
+    fn default() -> Session {
+        let mut channels = [0; eu868::CHANNELS];
+        channels[..eu868::DEFAULT_CHANNELS.len()].copy_from_slice(&eu868::DEFAULT_CHANNELS);
+        Session {
+            dev_addr: 0,
+            nwk_s_key: [0; AES128_KEY_SIZE],
+            app_s_key: [0; AES128_KEY_SIZE],
+            rx1_dr_offset: 0,
+            rx2_data_rate: eu868::RX2_DATA_RATE,
+            rx_delay: 1,
+            channels,
+        }
+    }
+}
+
+/// Write a block that starts the MIC (B0) or that encrypts a payload (Ai) of
+/// a data frame.
+fn data_block(block: &mut [u8], first: u8, downlink: bool, dev_addr: u32, fcnt: u32, last: u8) {
+    block[0] = first;
+    block[1..5].fill(0);
+    block[5] = downlink as u8;
+    block[6..10].copy_from_slice(&dev_addr.to_le_bytes());
+    block[10..14].copy_from_slice(&fcnt.to_le_bytes());
+    block[14] = 0;
+    block[15] = last;
+}
+
+/// Multiply a block by x in GF(2^128), which derives the subkeys of a CMAC.
+fn double(block: &[u8]) -> [u8; AES128_BLOCK_SIZE] {
+    let mut out = [0; AES128_BLOCK_SIZE];
+    for (i, byte) in out.iter_mut().enumerate() {
+        *byte = block[i] << 1 | block.get(i + 1).map_or(0, |next| next >> 7);
+    }
+    if block[0] & 0x80 != 0 {
+        out[AES128_BLOCK_SIZE - 1] ^= 0x87;
+    }
+    out
+}
+
+pub struct LoRaWanMac<
+    'a,
+    R: LoRaRadio<'a>,
+    A: Alarm<'a>,
+    E: AES128<'a> + AES128ECB + AES128CBC,
+    K: KVSystem<'a> + KVSystem<'a, K = T>,
+    T: 'static + kv_system::KeyType,
+> {
+    radio: &'a R,
+    alarm: &'a A,
+    aes: &'a E,
+    kv_store: &'a KVStore<'a, K, T>,
+    perms: StoragePermissions,
+    credentials: Credentials,
+    client: OptionalCell<&'a dyn Client>,
+
+    exchange: Cell<Exchange>,
+    phase: Cell<Phase>,
+    step: Cell<Step>,
+    data_rate: Cell<u8>,
+    /// The index of the channel of the last uplink.
+    channel: Cell<usize>,
+    tx_frequency: Cell<u32>,
+    tx_data_rate: Cell<u8>,
+    tx_done_at: Cell<A::Ticks>,
+
+    joined: Cell<bool>,
+    session: Cell<Session>,
+    /// The next DevNonce, and the one of the current join.
+    dev_nonce: Cell<u16>,
+    join_nonce: Cell<u16>,
+    /// The next uplink frame counter, and the end of the reserved ones.
+    fcnt_up: Cell<u32>,
+    fcnt_up_reserved: Cell<u32>,
+    /// The lowest downlink frame counter that is accepted.
+    fcnt_down: Cell<u32>,
+    /// The frame counter of the frame being sent or checked.
+    fcnt: Cell<u32>,
+    confirmed: Cell<bool>,
+    acked: Cell<bool>,
+    /// A confirmed downlink waits for the acknowledgement of the next uplink.
+    ack_pending: Cell<bool>,
+
+    tx_buf: TakeCell<'static, [u8]>,
+    tx_len: Cell<usize>,
+    /// The frame being checked.
+    rx_buf: TakeCell<'static, [u8]>,
+    rx_len: Cell<usize>,
+    crypt_buf: TakeCell<'static, [u8]>,
+    /// The length of the message of the CMAC whose subkeys are derived.
+    cmac_len: OptionalCell<usize>,
+    /// The end of the CBC-MAC, whose last block holds the MIC.
+    mac_end: Cell<usize>,
+
+    store_state: Cell<StoreState>,
+    store_pending: Cell<bool>,
+    loaded: Cell<bool>,
+    /// The end of the uplink frame counters being reserved.
+    reserving: Cell<u32>,
+    key: TakeCell<'static, [u8]>,
+    value: TakeCell<'static, [u8]>,
+}
+
+impl<
+        'a,
+        R: LoRaRadio<'a>,
+        A: Alarm<'a>,
+        E: AES128<'a> + AES128ECB + AES128CBC,
+        K: KVSystem<'a, K = T>,
+        T: kv_system::KeyType,
+    > LoRaWanMac<'a, R, A, E, K, T>
+{
+    /// `tx_buf` must be `lora::MAX_PAYLOAD_LEN` bytes long, `crypt_buf`
+    /// `CRYPT_BUF_LEN` bytes, `key` must hold `KEY` and `value` must be
+    /// `VALUE_BUF_LEN` bytes long.
+    pub fn new(
+        radio: &'a R,
+        alarm: &'a A,
+        aes: &'a E,
+        kv_store: &'a KVStore<'a, K, T>,
+        perms: StoragePermissions,
+        credentials: Credentials,
+        tx_buf: &'static mut [u8],
+        crypt_buf: &'static mut [u8],
+        key: &'static mut [u8],
+        value: &'static mut [u8],
+    ) -> LoRaWanMac<'a, R, A, E, K, T> {
+        LoRaWanMac {
+            radio,
+            alarm,
+            aes,
+            kv_store,
+            perms,
+            credentials,
+            client: OptionalCell::empty(),
+            exchange: Cell::new(Exchange::Join),
+            phase: Cell::new(Phase::Idle),
+            step: Cell::new(Step::JoinRequestMic),
+            data_rate: Cell::new(eu868::MAX_DATA_RATE),
+            channel: Cell::new(0),
+            tx_frequency: Cell::new(0),
+            tx_data_rate: Cell::new(0),
+            tx_done_at: Cell::new(A::Ticks::from(0)),
+            joined: Cell::new(false),
+            session: Cell::new(Session::default()),
+            dev_nonce: Cell::new(0),
+            join_nonce: Cell::new(0),
+            fcnt_up: Cell::new(0),
+            fcnt_up_reserved: Cell::new(0),
+            fcnt_down: Cell::new(0),
+            fcnt: Cell::new(0),
+            confirmed: Cell::new(false),
+            acked: Cell::new(false),
+            ack_pending: Cell::new(false),
+            tx_buf: TakeCell::new(tx_buf),
+            tx_len: Cell::new(0),
+            rx_buf: TakeCell::empty(),
+            rx_len: Cell::new(0),
+            crypt_buf: TakeCell::new(crypt_buf),
+            cmac_len: OptionalCell::empty(),
+            mac_end: Cell::new(0),
+            store_state: Cell::new(StoreState::Idle),
+            store_pending: Cell::new(false),
+            loaded: Cell::new(false),
+            reserving: Cell::new(0),
+            key: TakeCell::new(key),
+            value: TakeCell::new(value),
+        }
+    }
+
+    /// Load the stored DevNonce, session and frame counters. Without a stored
+    /// record, the device has not joined and the DevNonce starts from zero.
+    pub fn load(&self) -> Result<(), ErrorCode> {
+        if self.store_state.get() != StoreState::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        let key = self.key.take().ok_or(ErrorCode::NOMEM)?;
+        let value = match self.value.take() {
+            Some(value) => value,
+            None => {
+                self.key.replace(key);
+                return Err(ErrorCode::NOMEM);
+            }
+        };
+        match self.kv_store.get(key, value, self.perms) {
+            Ok(()) => {
+                self.store_state.set(StoreState::Loading);
+                Ok(())
+            }
+            Err((key, value, result)) => {
+                self.key.replace(key);
+                self.value.replace(value);
+                result.and(Err(ErrorCode::FAIL))
+            }
+        }
+    }
+
+    /// Store the current DevNonce, session and frame counters, reserving the
+    /// next `RESERVED_COUNTERS` uplink frame counters. The previous record is
+    /// deleted first, as the store does not overwrite values.
+    fn store(&self) {
+        if self.store_state.get() != StoreState::Idle {
+            self.store_pending.set(true);
+            return;
+        }
+        if let Some(key) = self.key.take() {
+            match self.kv_store.delete(key, self.perms) {
+                Ok(()) => self.store_state.set(StoreState::Deleting),
+                Err((key, _)) => {
+                    self.key.replace(key);
+                    self.write_record();
+                }
+            }
+        }
+    }
+
+    fn write_record(&self) {
+        let (key, value) = match (self.key.take(), self.value.take()) {
+            (Some(key), Some(value)) => (key, value),
+            (key, value) => {
+                key.map(|key| self.key.replace(key));
+                value.map(|value| self.value.replace(value));
+                return self.stored(Err(ErrorCode::NOMEM));
+            }
+        };
+        let session = self.session.get();
+        let reserving = self.fcnt_up.get().saturating_add(RESERVED_COUNTERS);
+        self.reserving.set(reserving);
+        value[0] = self.joined.get() as u8;
+        value[1..3].copy_from_slice(&self.dev_nonce.get().to_le_bytes());
+        value[3..7].copy_from_slice(&session.dev_addr.to_le_bytes());
+        value[7..23].copy_from_slice(&session.nwk_s_key);
+        value[23..39].copy_from_slice(&session.app_s_key);
+        value[39..43].copy_from_slice(&reserving.to_le_bytes());
+        value[43..47].copy_from_slice(&self.fcnt_down.get().to_le_bytes());
+        value[47] = session.rx1_dr_offset;
+        value[48] = session.rx2_data_rate;
+        value[49] = session.rx_delay;
+        match self.kv_store.set(key, value, RECORD_LEN, self.perms) {
+            Ok(()) => self.store_state.set(StoreState::Storing),
+            Err((key, value, result)) => {
+                self.key.replace(key);
+                self.value.replace(value);
+                self.stored(result.and(Err(ErrorCode::FAIL)));
+            }
+        }
+    }
+
+    fn read_record(&self, value: &[u8]) {
+        let word = |offset: usize| {
+            u32::from_le_bytes(value[offset..offset + 4].try_into().unwrap_or([0; 4]))
+        };
+        self.dev_nonce.set(u16::from_le_bytes([value[1], value[2]]));
+        if value[0] & 1 == 0 {
+            return;
+        }
+        let mut session = Session {
+            dev_addr: word(3),
+            rx1_dr_offset: value[47],
+            rx2_data_rate: value[48],
+            rx_delay: value[49],
+            ..Session::default()
+        };
+        session.nwk_s_key.copy_from_slice(&value[7..23]);
+        session.app_s_key.copy_from_slice(&value[23..39]);
+        self.session.set(session);
+        // The frame counters reserved before the reboot may have been used
+        self.fcnt_up.set(word(39));
+        self.fcnt_up_reserved.set(word(39));
+        self.fcnt_down.set(word(43));
+        self.joined.set(true);
+    }
+
+    /// Continue the exchange that waits for the store, once the latest
+    /// state is stored.
+    fn stored(&self, result: Result<(), ErrorCode>) {
+        self.store_state.set(StoreState::Idle);
+        if result.is_ok() {
+            self.fcnt_up_reserved.set(self.reserving.get());
+        }
+        if self.store_pending.take() {
+            return self.store();
+        }
+        match self.phase.get() {
+            Phase::Reserve => {
+                let result = result.and_then(|()| match self.exchange.get() {
+                    Exchange::Join => self.prepare_join_request(),
+                    Exchange::Uplink if self.fcnt_up.get() < self.fcnt_up_reserved.get() => {
+                        self.prepare_uplink()
+                    }
+                    Exchange::Uplink => Err(ErrorCode::FAIL),
+                });
+                if let Err(e) = result {
+                    self.finish(Err(e));
+                }
+            }
+            // The device joined even if the session could not be stored; the
+            // next uplink stores it again
+            Phase::Commit => self.finish(Ok(())),
+            _ => {}
+        }
+    }
+
+    /// Encrypt `crypt_buf[..len]` with `key` in ECB mode.
+    fn start_ecb(&self, key: &[u8], len: usize) -> Result<(), ErrorCode> {
+        self.aes.set_key(key)?;
+        self.aes.set_mode_aes128ecb(true)?;
+        self.crypt(0, len)
+    }
+
+    /// Compute the CMAC of `crypt_buf[..len]` with `key`, starting with the
+    /// encryption of the zero block that the subkeys are derived from.
+    fn start_cmac(&self, key: &[u8], len: usize) -> Result<(), ErrorCode> {
+        self.aes.set_key(key)?;
+        self.aes.set_mode_aes128ecb(true)?;
+        self.crypt_buf.map(|buf| buf[SUBKEY_OFFSET..].fill(0));
+        self.cmac_len.set(len);
+        self.crypt(SUBKEY_OFFSET, CRYPT_BUF_LEN)
+    }
+
+    /// Pad the message of the CMAC, mask its last block with a subkey, and
+    /// compute its CBC-MAC.
+    fn cbc_mac(&self, len: usize) -> Result<(), ErrorCode> {
+        let end = self.crypt_buf.map_or(0, |buf| {
+            let k1 = double(&buf[SUBKEY_OFFSET..]);
+            // Complete last blocks are masked with K1, padded ones with K2
+            let end = (len + AES128_BLOCK_SIZE - 1) / AES128_BLOCK_SIZE * AES128_BLOCK_SIZE;
+            let (end, subkey) = if len > 0 && end == len {
+                (end, k1)
+            } else {
+                let end = end.max(AES128_BLOCK_SIZE);
+                buf[len] = 0x80;
+                buf[len + 1..end].fill(0);
+                (end, double(&k1))
+            };
+            for (byte, k) in buf[end - AES128_BLOCK_SIZE..end].iter_mut().zip(subkey) {
+                *byte ^= k;
+            }
+            end
+        });
+        self.mac_end.set(end);
+        self.aes.set_iv(&[0; AES128_BLOCK_SIZE])?;
+        self.aes.set_mode_aes128cbc(true)?;
+        self.crypt(0, end)
+    }
+
+    fn crypt(&self, start: usize, stop: usize) -> Result<(), ErrorCode> {
+        let buf = self.crypt_buf.take().ok_or(ErrorCode::BUSY)?;
+        self.aes.start_message();
+        match self.aes.crypt(None, buf, start, stop) {
+            None => Ok(()),
+            Some((result, _, buf)) => {
+                self.crypt_buf.replace(buf);
+                result.and(Err(ErrorCode::FAIL))
+            }
+        }
+    }
+
+    /// The MIC computed by the last CMAC.
+    fn mic(&self) -> [u8; MIC_LEN] {
+        let start = self.mac_end.get() - AES128_BLOCK_SIZE;
+        self.crypt_buf.map_or([0; MIC_LEN], |buf| {
+            buf[start..start + MIC_LEN]
+                .try_into()
+                .unwrap_or([0; MIC_LEN])
+        })
+    }
+
+    /// Fill the crypt buffer with the blocks that encrypt a payload of `len`
+    /// bytes, and return their length.
+    fn cipher_blocks(&self, downlink: bool, fcnt: u32, len: usize) -> usize {
+        let dev_addr = self.session.get().dev_addr;
+        let blocks = (len + AES128_BLOCK_SIZE - 1) / AES128_BLOCK_SIZE;
+        self.crypt_buf.map(|buf| {
+            for (i, block) in buf.chunks_mut(AES128_BLOCK_SIZE).take(blocks).enumerate() {
+                data_block(block, 0x01, downlink, dev_addr, fcnt, i as u8 + 1);
+            }
+        });
+        blocks * AES128_BLOCK_SIZE
+    }
+
+    /// XOR `payload` with the encrypted blocks in the crypt buffer.
+    fn apply_cipher(&self, payload: &mut [u8]) {
+        self.crypt_buf.map(|buf| {
+            for (byte, k) in payload.iter_mut().zip(buf.iter()) {
+                *byte ^= k;
+            }
+        });
+    }
+
+    /// Start the MIC of a data frame of `len` bytes in `frame`.
+    fn start_data_mic(&self, frame: &[u8], len: usize, downlink: bool) -> Result<(), ErrorCode> {
+        let session = self.session.get();
+        let fcnt = self.fcnt.get();
+        self.crypt_buf.map(|buf| {
+            data_block(buf, 0x49, downlink, session.dev_addr, fcnt, len as u8);
+            buf[AES128_BLOCK_SIZE..AES128_BLOCK_SIZE + len].copy_from_slice(&frame[..len]);
+        });
+        self.start_cmac(&session.nwk_s_key, AES128_BLOCK_SIZE + len)
+    }
+
+    fn prepare_join_request(&self) -> Result<(), ErrorCode> {
+        self.phase.set(Phase::Prepare);
+        let credentials = self.credentials;
+        let len = self.tx_buf.map_or(0, |frame| {
+            frame[0] = mhdr::JOIN_REQUEST;
+            for (byte, eui) in frame[1..9]
+                .iter_mut()
+                .zip(credentials.join_eui.iter().rev())
+            {
+                *byte = *eui;
+            }
+            for (byte, eui) in frame[9..17]
+                .iter_mut()
+                .zip(credentials.dev_eui.iter().rev())
+            {
+                *byte = *eui;
+            }
+            frame[17..19].copy_from_slice(&self.join_nonce.get().to_le_bytes());
+            self.crypt_buf
+                .map(|buf| buf[..19].copy_from_slice(&frame[..19]));
+            19
+        });
+        self.tx_len.set(len);
+        self.step.set(Step::JoinRequestMic);
+        self.start_cmac(&credentials.app_key, len)
+    }
+
+    fn prepare_uplink(&self) -> Result<(), ErrorCode> {
+        self.phase.set(Phase::Prepare);
+        let fcnt = self.fcnt_up.get();
+        self.fcnt_up.set(fcnt + 1);
+        self.fcnt.set(fcnt);
+        if self.fcnt_up_reserved.get() - fcnt <= RESERVED_COUNTERS / 2 {
+            self.store();
+        }
+        let session = self.session.get();
+        let ack = if self.ack_pending.take() {
+            FCTRL_ACK
+        } else {
+            0
+        };
+        self.tx_buf.map(|frame| {
+            frame[0] = if self.confirmed.get() {
+                mhdr::CONFIRMED_UP
+            } else {
+                mhdr::UNCONFIRMED_UP
+            };
+            frame[1..5].copy_from_slice(&session.dev_addr.to_le_bytes());
+            frame[5] = ack;
+            frame[6..8].copy_from_slice(&(fcnt as u16).to_le_bytes());
+        });
+        let payload_len = self.tx_len.get() - FHDR_LEN - 1;
+        if payload_len == 0 {
+            return self.uplink_encrypted();
+        }
+        self.step.set(Step::UplinkEncrypt);
+        let len = self.cipher_blocks(false, fcnt, payload_len);
+        self.start_ecb(&session.app_s_key, len)
+    }
+
+    fn uplink_encrypted(&self) -> Result<(), ErrorCode> {
+        let len = self.tx_len.get();
+        let frame = self.tx_buf.take().ok_or(ErrorCode::FAIL)?;
+        self.apply_cipher(&mut frame[FHDR_LEN + 1..len]);
+        self.step.set(Step::UplinkMic);
+        let result = self.start_data_mic(frame, len, false);
+        self.tx_buf.replace(frame);
+        result
+    }
+
+    /// Append the MIC to the frame to send, and send it on the next channel.
+    fn transmit(&self) -> Result<(), ErrorCode> {
+        let len = self.tx_len.get();
+        let mic = self.mic();
+        let frame = self.tx_buf.take().ok_or(ErrorCode::FAIL)?;
+        frame[len..len + MIC_LEN].copy_from_slice(&mic);
+
+        let session = self.session.get();
+        let channels: &[u32] = match self.exchange.get() {
+            Exchange::Join => &eu868::DEFAULT_CHANNELS,
+            Exchange::Uplink => &session.channels,
+        };
+        // Channels are used in turn, skipping the unused ones
+        let mut channel = self.channel.get();
+        let frequency = loop {
+            channel = (channel + 1) % channels.len();
+            if channels[channel] != 0 {
+                break channels[channel];
+            }
+        };
+        self.channel.set(channel);
+        self.tx_frequency.set(frequency);
+        self.tx_data_rate.set(self.data_rate.get());
+
+        let result = self.configure(frequency, self.data_rate.get(), false);
+        if let Err(e) = result {
+            self.tx_buf.replace(frame);
+            return Err(e);
+        }
+        match self.radio.transmit(frame, len + MIC_LEN) {
+            Ok(()) => {
+                self.phase.set(Phase::Transmit);
+                Ok(())
+            }
+            Err((e, frame)) => {
+                self.tx_buf.replace(frame);
+                Err(e)
+            }
+        }
+    }
+
+    /// Set the radio to send or receive at `frequency` and `data_rate`.
+    fn configure(&self, frequency: u32, data_rate: u8, downlink: bool) -> Result<(), ErrorCode> {
+        let mut config = self.radio.get_config();
+        config.frequency = frequency;
+        config.spreading_factor = 12 - data_rate.min(eu868::MAX_DATA_RATE);
+        config.bandwidth = Bandwidth::Khz125;
+        config.coding_rate = CodingRate::Cr4_5;
+        config.tx_power = eu868::TX_POWER;
+        config.sync_word = eu868::SYNC_WORD;
+        config.preamble_len = 8;
+        config.invert_iq = downlink;
+        self.radio.set_config(config)
+    }
+
+    /// The delay of the first receive window after an uplink, in
+    /// milliseconds.
+    fn rx1_delay_ms(&self) -> u32 {
+        match self.exchange.get() {
+            Exchange::Join => JOIN_ACCEPT_DELAY_MS,
+            Exchange::Uplink => self.session.get().rx_delay.max(1) as u32 * 1000,
+        }
+    }
+
+    fn open_rx1(&self) -> Result<(), ErrorCode> {
+        let data_rate = match self.exchange.get() {
+            Exchange::Join => self.tx_data_rate.get(),
+            Exchange::Uplink => self
+                .tx_data_rate
+                .get()
+                .saturating_sub(self.session.get().rx1_dr_offset),
+        };
+        self.configure(self.tx_frequency.get(), data_rate, true)?;
+        self.radio.start_receive()?;
+        self.phase.set(Phase::Rx1);
+        self.alarm.set_alarm(
+            self.tx_done_at.get(),
+            self.alarm.ticks_from_ms(self.rx1_delay_ms() + 1000),
+        );
+        Ok(())
+    }
+
+    fn open_rx2(&self) -> Result<(), ErrorCode> {
+        let data_rate = match self.exchange.get() {
+            Exchange::Join => eu868::RX2_DATA_RATE,
+            Exchange::Uplink => self.session.get().rx2_data_rate,
+        };
+        self.configure(eu868::RX2_FREQUENCY, data_rate, true)?;
+        self.radio.start_receive()?;
+        self.phase.set(Phase::Rx2);
+        self.alarm.set_alarm(
+            self.tx_done_at.get(),
+            self.alarm
+                .ticks_from_ms(self.rx1_delay_ms() + 1000 + RX2_WINDOW_MS),
+        );
+        Ok(())
+    }
+
+    /// Whether `frame` may answer the current exchange, judging from its
+    /// header.
+    fn accepts(&self, frame: &[u8]) -> bool {
+        match self.exchange.get() {
+            Exchange::Join => {
+                frame.first() == Some(&mhdr::JOIN_ACCEPT)
+                    && (frame.len() == 17 || frame.len() == 33)
+            }
+            Exchange::Uplink => {
+                frame.len() >= FHDR_LEN + MIC_LEN
+                    && (frame[0] == mhdr::UNCONFIRMED_DOWN || frame[0] == mhdr::CONFIRMED_DOWN)
+                    && frame[1..5] == self.session.get().dev_addr.to_le_bytes()
+                    && FHDR_LEN + (frame[5] & FCTRL_FOPTS_LEN) as usize + MIC_LEN <= frame.len()
+            }
+        }
+    }
+
+    fn check_join_accept(&self) -> Result<(), ErrorCode> {
+        let len = self.rx_len.get();
+        self.rx_buf.map(|frame| {
+            self.crypt_buf
+                .map(|buf| buf[..len - 1].copy_from_slice(&frame[1..len]))
+        });
+        self.step.set(Step::JoinAcceptDecrypt);
+        self.start_ecb(&self.credentials.app_key, len - 1)
+    }
+
+    fn join_accept_decrypted(&self) -> Result<(), ErrorCode> {
+        let len = self.rx_len.get();
+        self.rx_buf.map(|frame| {
+            self.crypt_buf.map(|buf| {
+                frame[1..len].copy_from_slice(&buf[..len - 1]);
+                buf[..len - MIC_LEN].copy_from_slice(&frame[..len - MIC_LEN]);
+            })
+        });
+        self.step.set(Step::JoinAcceptMic);
+        self.start_cmac(&self.credentials.app_key, len - MIC_LEN)
+    }
+
+    /// Parse a join accept with a valid MIC, and derive the session keys.
+    fn join_accepted(&self) -> Result<(), ErrorCode> {
+        let len = self.rx_len.get();
+        let frame = self.rx_buf.take().ok_or(ErrorCode::FAIL)?;
+        let mut session = Session {
+            dev_addr: u32::from_le_bytes([frame[7], frame[8], frame[9], frame[10]]),
+            rx1_dr_offset: (frame[11] >> 4) & 0x07,
+            rx2_data_rate: frame[11] & 0x0f,
+            rx_delay: frame[12] & 0x0f,
+            ..Session::default()
+        };
+        // A CFList of type 0 adds up to five channels, in units of 100 Hz
+        if len == 33 && frame[28] == 0 {
+            for (channel, bytes) in session.channels[eu868::DEFAULT_CHANNELS.len()..]
+                .iter_mut()
+                .zip(frame[13..28].chunks(3))
+            {
+                *channel = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0]) * 100;
+            }
+        }
+        self.session.set(session);
+
+        // The keys are derived from AppNonce | NetID | DevNonce
+        let nonce = self.join_nonce.get().to_le_bytes();
+        self.crypt_buf.map(|buf| {
+            for (i, block) in buf.chunks_mut(AES128_BLOCK_SIZE).take(2).enumerate() {
+                block.fill(0);
+                block[0] = i as u8 + 1;
+                block[1..7].copy_from_slice(&frame[1..7]);
+                block[7..9].copy_from_slice(&nonce);
+            }
+        });
+        self.rx_buf.replace(frame);
+        self.step.set(Step::SessionKeys);
+        self.start_ecb(&self.credentials.app_key, 2 * AES128_BLOCK_SIZE)
+    }
+
+    fn session_keys_derived(&self) {
+        let mut session = self.session.get();
+        self.crypt_buf.map(|buf| {
+            session.nwk_s_key.copy_from_slice(&buf[..AES128_KEY_SIZE]);
+            session
+                .app_s_key
+                .copy_from_slice(&buf[AES128_BLOCK_SIZE..AES128_BLOCK_SIZE + AES128_KEY_SIZE]);
+        });
+        self.session.set(session);
+        self.joined.set(true);
+        self.fcnt_up.set(0);
+        self.fcnt_up_reserved.set(0);
+        self.fcnt_down.set(0);
+        self.ack_pending.set(false);
+        self.phase.set(Phase::Commit);
+        self.store();
+    }
+
+    fn check_downlink(&self) -> Result<(), ErrorCode> {
+        let len = self.rx_len.get();
+        let frame = self.rx_buf.take().ok_or(ErrorCode::FAIL)?;
+        // The 16 bits of the frame counter in the frame are its low bits
+        let low = u16::from_le_bytes([frame[6], frame[7]]) as u32;
+        let next = self.fcnt_down.get();
+        let mut fcnt = (next & !0xffff) | low;
+        if fcnt < next {
+            fcnt = fcnt.wrapping_add(0x10000);
+        }
+        self.fcnt.set(fcnt);
+        self.step.set(Step::DownlinkMic);
+        let result = self.start_data_mic(frame, len - MIC_LEN, true);
+        self.rx_buf.replace(frame);
+        result
+    }
+
+    /// Handle the MIC of a downlink, and decrypt its payload if it is valid.
+    fn downlink_checked(&self) -> Result<(), ErrorCode> {
+        let len = self.rx_len.get() - MIC_LEN;
+        let mic = self.mic();
+        let frame = self.rx_buf.take().ok_or(ErrorCode::FAIL)?;
+        let valid = frame[len..len + MIC_LEN] == mic && self.fcnt.get() >= self.fcnt_down.get();
+        let start = FHDR_LEN + (frame[5] & FCTRL_FOPTS_LEN) as usize;
+        let port = frame.get(start).copied();
+        let acked = frame[5] & FCTRL_ACK != 0;
+        let confirmed = frame[0] == mhdr::CONFIRMED_DOWN;
+        self.rx_buf.replace(frame);
+        if !valid {
+            self.finish(Ok(()));
+            return Ok(());
+        }
+
+        self.fcnt_down.set(self.fcnt.get().wrapping_add(1));
+        self.acked.set(acked);
+        self.ack_pending.set(confirmed);
+        match port {
+            Some(port) if len > start + 1 => {
+                let session = self.session.get();
+                let key = if port == 0 {
+                    session.nwk_s_key
+                } else {
+                    session.app_s_key
+                };
+                self.step.set(Step::DownlinkDecrypt);
+                let blocks = self.cipher_blocks(true, self.fcnt.get(), len - start - 1);
+                self.start_ecb(&key, blocks)
+            }
+            _ => {
+                self.finish(Ok(()));
+                Ok(())
+            }
+        }
+    }
+
+    fn downlink_decrypted(&self) {
+        let len = self.rx_len.get() - MIC_LEN;
+        self.rx_buf.map(|frame| {
+            let start = FHDR_LEN + (frame[5] & FCTRL_FOPTS_LEN) as usize;
+            let port = frame[start];
+            self.apply_cipher(&mut frame[start + 1..len]);
+            // The MAC commands on port 0 are not supported
+            if port != 0 {
+                self.client
+                    .map(|client| client.receive(port, &frame[start + 1..len]));
+            }
+        });
+        self.finish(Ok(()));
+    }
+
+    /// Continue the exchange once the running AES operation is done.
+    fn step_done(&self) {
+        let result = match self.step.get() {
+            Step::JoinRequestMic | Step::UplinkMic => self.transmit(),
+            Step::JoinAcceptDecrypt => self.join_accept_decrypted(),
+            Step::JoinAcceptMic => {
+                let len = self.rx_len.get();
+                let valid = self
+                    .rx_buf
+                    .map_or(false, |frame| frame[len - MIC_LEN..len] == self.mic());
+                if valid {
+                    self.join_accepted()
+                } else {
+                    Err(ErrorCode::NOACK)
+                }
+            }
+            Step::SessionKeys => {
+                self.session_keys_derived();
+                Ok(())
+            }
+            Step::UplinkEncrypt => self.uplink_encrypted(),
+            Step::DownlinkMic => self.downlink_checked(),
+            Step::DownlinkDecrypt => {
+                self.downlink_decrypted();
+                Ok(())
+            }
+        };
+        if let Err(e) = result {
+            self.finish(Err(e));
+        }
+    }
+
+    /// End the current exchange and report it to the client.
+    fn finish(&self, result: Result<(), ErrorCode>) {
+        if let Some(frame) = self.rx_buf.take() {
+            self.radio.set_receive_buffer(frame);
+        }
+        self.phase.set(Phase::Idle);
+        match self.exchange.get() {
+            Exchange::Join => {
+                let result = if result.is_ok() && !self.joined.get() {
+                    Err(ErrorCode::NOACK)
+                } else {
+                    result
+                };
+                self.client.map(|client| client.join_done(result));
+            }
+            Exchange::Uplink => {
+                let result = if result.is_ok() && self.confirmed.get() && !self.acked.get() {
+                    Err(ErrorCode::NOACK)
+                } else {
+                    result
+                };
+                self.client.map(|client| client.send_done(result));
+            }
+        }
+    }
+}
+
+impl<
+        'a,
+        R: LoRaRadio<'a>,
+        A: Alarm<'a>,
+        E: AES128<'a> + AES128ECB + AES128CBC,
+        K: KVSystem<'a, K = T>,
+        T: kv_system::KeyType,
+    > LoRaWan<'a> for LoRaWanMac<'a, R, A, E, K, T>
+{
+    fn set_client(&self, client: &'a dyn Client) {
+        self.client.set(client);
+    }
+
+    fn is_joined(&self) -> bool {
+        self.joined.get()
+    }
+
+    fn join(&self) -> Result<(), ErrorCode> {
+        if !self.loaded.get() {
+            // Retry if the store was busy when the session was loaded
+            let _ = self.load();
+            return Err(ErrorCode::BUSY);
+        }
+        if self.phase.get() != Phase::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        let dev_nonce = self.dev_nonce.get();
+        if dev_nonce == u16::MAX {
+            return Err(ErrorCode::FAIL);
+        }
+        self.joined.set(false);
+        self.join_nonce.set(dev_nonce);
+        self.dev_nonce.set(dev_nonce + 1);
+        self.exchange.set(Exchange::Join);
+        // The DevNonce must be stored before it is used
+        self.phase.set(Phase::Reserve);
+        self.store();
+        Ok(())
+    }
+
+    fn set_data_rate(&self, data_rate: u8) -> Result<(), ErrorCode> {
+        if data_rate > eu868::MAX_DATA_RATE {
+            return Err(ErrorCode::INVAL);
+        }
+        self.data_rate.set(data_rate);
+        Ok(())
+    }
+
+    fn send(&self, port: u8, payload: &[u8], confirmed: bool) -> Result<(), ErrorCode> {
+        if !self.joined.get() {
+            return Err(ErrorCode::OFF);
+        }
+        if self.phase.get() != Phase::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        if !(1..=223).contains(&port) {
+            return Err(ErrorCode::INVAL);
+        }
+        if payload.len() > MAX_PAYLOAD_LENS[self.data_rate.get() as usize] {
+            return Err(ErrorCode::SIZE);
+        }
+        let len = self.tx_buf.map_or(0, |frame| {
+            frame[FHDR_LEN] = port;
+            frame[FHDR_LEN + 1..FHDR_LEN + 1 + payload.len()].copy_from_slice(payload);
+            FHDR_LEN + 1 + payload.len()
+        });
+        self.tx_len.set(len);
+        self.exchange.set(Exchange::Uplink);
+        self.confirmed.set(confirmed);
+        self.acked.set(false);
+        if self.fcnt_up.get() >= self.fcnt_up_reserved.get() {
+            self.phase.set(Phase::Reserve);
+            self.store();
+        } else if let Err(e) = self.prepare_uplink() {
+            self.phase.set(Phase::Idle);
+            return Err(e);
+        }
+        Ok(())
+    }
+}
+
+impl<
+        'a,
+        R: LoRaRadio<'a>,
+        A: Alarm<'a>,
+        E: AES128<'a> + AES128ECB + AES128CBC,
+        K: KVSystem<'a, K = T>,
+        T: kv_system::KeyType,
+    > lora::TxClient for LoRaWanMac<'a, R, A, E, K, T>
+{
+    fn transmit_done(&self, buf: &'static mut [u8], result: Result<(), ErrorCode>) {
+        self.tx_buf.replace(buf);
+        if self.phase.get() != Phase::Transmit {
+            return;
+        }
+        if let Err(e) = result {
+            return self.finish(Err(e));
+        }
+        self.tx_done_at.set(self.alarm.now());
+        self.phase.set(Phase::WaitRx1);
+        self.alarm.set_alarm(
+            self.tx_done_at.get(),
+            self.alarm.ticks_from_ms(self.rx1_delay_ms()),
+        );
+    }
+}
+
+impl<
+        'a,
+        R: LoRaRadio<'a>,
+        A: Alarm<'a>,
+        E: AES128<'a> + AES128ECB + AES128CBC,
+        K: KVSystem<'a, K = T>,
+        T: kv_system::KeyType,
+    > lora::RxClient for LoRaWanMac<'a, R, A, E, K, T>
+{
+    fn receive(
+        &self,
+        buf: &'static mut [u8],
+        len: usize,
+        _metadata: RxMetadata,
+        result: Result<(), ErrorCode>,
+    ) {
+        let window = matches!(self.phase.get(), Phase::Rx1 | Phase::Rx2);
+        if !window || result.is_err() || !self.accepts(&buf[..len]) {
+            // Keep listening for the rest of the window
+            self.radio.set_receive_buffer(buf);
+            return;
+        }
+        let _ = self.alarm.disarm();
+        let _ = self.radio.stop_receive();
+        self.rx_buf.replace(buf);
+        self.rx_len.set(len);
+        self.phase.set(Phase::Check);
+        let result = match self.exchange.get() {
+            Exchange::Join => self.check_join_accept(),
+            Exchange::Uplink => self.check_downlink(),
+        };
+        if let Err(e) = result {
+            self.finish(Err(e));
+        }
+    }
+}
+
+impl<
+        'a,
+        R: LoRaRadio<'a>,
+        A: Alarm<'a>,
+        E: AES128<'a> + AES128ECB + AES128CBC,
+        K: KVSystem<'a, K = T>,
+        T: kv_system::KeyType,
+    > time::AlarmClient for LoRaWanMac<'a, R, A, E, K, T>
+{
+    fn alarm(&self) {
+        let result = match self.phase.get() {
+            Phase::WaitRx1 => self.open_rx1(),
+            Phase::Rx1 => self.open_rx2(),
+            Phase::Rx2 => {
+                let _ = self.radio.stop_receive();
+                self.finish(Ok(()));
+                Ok(())
+            }
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            let _ = self.radio.stop_receive();
+            self.finish(Err(e));
+        }
+    }
+}
+
+impl<
+        'a,
+        R: LoRaRadio<'a>,
+        A: Alarm<'a>,
+        E: AES128<'a> + AES128ECB + AES128CBC,
+        K: KVSystem<'a, K = T>,
+        T: kv_system::KeyType,
+    > symmetric_encryption::Client<'a> for LoRaWanMac<'a, R, A, E, K, T>
+{
+    fn crypt_done(&'a self, _source: Option<&'static mut [u8]>, dest: &'static mut [u8]) {
+        self.crypt_buf.replace(dest);
+        if let Some(len) = self.cmac_len.take() {
+            if let Err(e) = self.cbc_mac(len) {
+                self.finish(Err(e));
+            }
+            return;
+        }
+        self.step_done();
+    }
+}
+
+impl<
+        'a,
+        R: LoRaRadio<'a>,
+        A: Alarm<'a>,
+        E: AES128<'a> + AES128ECB + AES128CBC,
+        K: KVSystem<'a, K = T>,
+        T: kv_system::KeyType,
+    > kv_system::StoreClient<T> for LoRaWanMac<'a, R, A, E, K, T>
+{
+    fn get_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: &'static mut [u8],
+        ret_buf: &'static mut [u8],
+    ) {
+        self.key.replace(key);
+        if result.is_ok() && ret_buf.len() >= RECORD_LEN {
+            self.read_record(ret_buf);
+        }
+        self.value.replace(ret_buf);
+        self.store_state.set(StoreState::Idle);
+        self.loaded.set(true);
+    }
+
+    fn set_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: &'static mut [u8],
+        value: &'static mut [u8],
+    ) {
+        self.key.replace(key);
+        self.value.replace(value);
+        self.stored(result);
+    }
+
+    fn delete_complete(&self, _result: Result<(), ErrorCode>, key: &'static mut [u8]) {
+        // There is no previous record to delete before the first join
+        self.key.replace(key);
+        self.write_record();
+    }
+    fn next_key_complete(
+        &self,
+        _result: Result<(), ErrorCode>,
+        _key: T,
+        _length: usize,
+        _position: usize,
+    ) {
+    }
+
+    fn garbage_collect_complete(&self, _result: Result<(), ErrorCode>) {}
+
+    fn usage_complete(
+        &self,
+        _result: Result<(), ErrorCode>,
+        _live_bytes: usize,
+        _dead_bytes: usize,
+    ) {
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sha_software::from_hex;
+
+    #[test]
+    fn cmac_subkeys_rfc4493() {
+        // L = AES-128(K, 0) for the key of the RFC 4493 examples.
+        let l: [u8; AES128_BLOCK_SIZE] = from_hex("7df76b0c1ab899b33e42f047b91b546f");
+        let k1 = double(&l);
+        assert_eq!(k1, from_hex("fbeed618357133667c85e08f7236a8de"));
+        assert_eq!(double(&k1), from_hex("f7ddac306ae266ccf90bc11ee46d513b"));
+    }
+
+    #[test]
+    fn double_without_carry() {
+        let mut block = [0; AES128_BLOCK_SIZE];
+        block[0] = 0x40;
+        block[15] = 0x81;
+        let mut doubled = [0; AES128_BLOCK_SIZE];
+        doubled[0] = 0x80;
+        doubled[14] = 0x01;
+        doubled[15] = 0x02;
+        assert_eq!(double(&block), doubled);
+    }
+
+    #[test]
+    fn mic_and_cipher_blocks() {
+        let mut block = [0xff; AES128_BLOCK_SIZE];
+        // B0 of a 13-byte uplink of device 0x26011234 with FCnt 0x0102.
+        data_block(&mut block, 0x49, false, 0x2601_1234, 0x0102, 13);
+        assert_eq!(
+            block,
+            [0x49, 0, 0, 0, 0, 0, 0x34, 0x12, 0x01, 0x26, 0x02, 0x01, 0, 0, 0, 13]
+        );
+        // A2 of a downlink, whose direction byte is 1.
+        data_block(&mut block, 0x01, true, 0x2601_1234, 0x0001_0000, 2);
+        assert_eq!(
+            block,
+            [0x01, 0, 0, 0, 0, 1, 0x34, 0x12, 0x01, 0x26, 0, 0, 0x01, 0, 0, 2]
+        );
+    }
+
+    #[test]
+    fn default_session() {
+        let session = Session::default();
+        assert_eq!(session.channels[..3], eu868::DEFAULT_CHANNELS);
+        assert!(session.channels[3..].iter().all(|channel| *channel == 0));
+        assert_eq!(session.rx2_data_rate, eu868::RX2_DATA_RATE);
+        assert_eq!(session.rx_delay, 1);
+    }
+
+    #[test]
+    fn payload_lengths() {
+        // Every data rate has a maximum payload, and the largest fits the
+        // transmit buffer with the frame header, FPort and MIC.
+        assert_eq!(MAX_PAYLOAD_LENS.len(), eu868::MAX_DATA_RATE as usize + 1);
+        assert_eq!(MAX_PAYLOAD_LENS.iter().max(), Some(&MAX_PAYLOAD_LEN));
+        assert!(FHDR_LEN + 1 + MAX_PAYLOAD_LEN + MIC_LEN <= lora::MAX_PAYLOAD_LEN);
+        // The CMAC of the longest frame, B0 followed by the frame padded to
+        // a block, leaves room for the subkeys.
+        let mic_input = AES128_BLOCK_SIZE + FHDR_LEN + 1 + MAX_PAYLOAD_LEN;
+        let blocks = (mic_input + AES128_BLOCK_SIZE - 1) / AES128_BLOCK_SIZE;
+        assert!(blocks * AES128_BLOCK_SIZE <= SUBKEY_OFFSET);
+    }
+}
@@ -0,0 +1,12 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Support for LoRaWAN, on top of a `hil::lora::LoRaRadio`.
+
+pub mod mac;
+
+mod driver;
+
+pub use self::driver::LoRaWanDriver;
+pub use self::driver::DRIVER_NUM;
@@ -0,0 +1,144 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! A packet buffer with room for headers in front of its data.
+//!
+//! A `PacketBuffer` keeps the data of a packet somewhere in a larger buffer:
+//! the bytes in front of the data are the headroom and the bytes behind it
+//! the tailroom. A layer that sends the packet prepends its header into the
+//! headroom, or appends a trailer into the tailroom, instead of copying the
+//! data into a buffer of its own behind the header.
+//!
+//! The buffer is owned by the layer the packet currently is in: it is passed
+//! down by value when the packet is sent, and returned to the sender with
+//! the result once it is sent.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! // Leave room for an 8 byte header in front of the payload.
+//! let mut packet = PacketBuffer::with_range(buf, 8..8 + payload.len());
+//! packet.data_mut().copy_from_slice(payload);
+//! packet.prepend(&header)?;
+//! ```
+
+use core::cmp;
+use core::ops::Range;
+
+use kernel::utilities::leasable_buffer::LeasableMutableBuffer;
+use kernel::ErrorCode;
+
+pub struct PacketBuffer {
+    buf: &'static mut [u8],
+    /// Start of the data, which is also the size of the headroom.
+    head: usize,
+    /// End of the data.
+    tail: usize,
+}
+
+impl PacketBuffer {
+    /// A buffer whose data is all of `buf`, without headroom or tailroom.
+    pub fn new(buf: &'static mut [u8]) -> PacketBuffer {
+        let tail = buf.len();
+        PacketBuffer { buf, head: 0, tail }
+    }
+
+    /// A buffer whose data is `range` of `buf`. The range is cut to the end
+    /// of `buf`.
+    pub fn with_range(buf: &'static mut [u8], range: Range<usize>) -> PacketBuffer {
+        let tail = cmp::min(range.end, buf.len());
+        let head = cmp::min(range.start, tail);
+        PacketBuffer { buf, head, tail }
+    }
+
+    /// Number of bytes that can be prepended to the data.
+    pub fn headroom(&self) -> usize {
+        self.head
+    }
+
+    /// Number of bytes that can be appended to the data.
+    pub fn tailroom(&self) -> usize {
+        self.buf.len() - self.tail
+    }
+
+    pub fn len(&self) -> usize {
+        self.tail - self.head
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The range of the underlying buffer the data is in.
+    pub fn range(&self) -> Range<usize> {
+        self.head..self.tail
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.buf[self.head..self.tail]
+    }
+
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        &mut self.buf[self.head..self.tail]
+    }
+
+    /// Grow the data by `len` bytes in front, and return them to write a
+    /// header into. Returns SIZE if the headroom is smaller than `len`.
+    pub fn push(&mut self, len: usize) -> Result<&mut [u8], ErrorCode> {
+        if len > self.head {
+            return Err(ErrorCode::SIZE);
+        }
+        self.head -= len;
+        Ok(&mut self.buf[self.head..self.head + len])
+    }
+
+    /// Copy `header` in front of the data. Returns SIZE if the headroom is
+    /// too small.
+    pub fn prepend(&mut self, header: &[u8]) -> Result<(), ErrorCode> {
+        self.push(header.len())
+            .map(|front| front.copy_from_slice(header))
+    }
+
+    /// Copy `trailer` behind the data. Returns SIZE if the tailroom is too
+    /// small.
+    pub fn append(&mut self, trailer: &[u8]) -> Result<(), ErrorCode> {
+        if trailer.len() > self.tailroom() {
+            return Err(ErrorCode::SIZE);
+        }
+        self.buf[self.tail..self.tail + trailer.len()].copy_from_slice(trailer);
+        self.tail += trailer.len();
+        Ok(())
+    }
+
+    /// Shrink the data by `len` bytes in front, which undoes a `push` or
+    /// `prepend` of `len` bytes. Returns SIZE if the data is shorter than
+    /// `len`.
+    pub fn pull(&mut self, len: usize) -> Result<(), ErrorCode> {
+        if len > self.len() {
+            return Err(ErrorCode::SIZE);
+        }
+        self.head += len;
+        Ok(())
+    }
+
+    /// Retrieve the underlying buffer.
+    pub fn into_buf(self) -> &'static mut [u8] {
+        self.buf
+    }
+
+    /// A buffer whose data is the accessible part of `lease`.
+    pub fn from_lease(lease: LeasableMutableBuffer<'static, u8>) -> PacketBuffer {
+        let range = lease.active_range();
+        PacketBuffer::with_range(lease.take(), range)
+    }
+
+    /// A leasable buffer whose accessible part is the data.
+    pub fn into_lease(self) -> LeasableMutableBuffer<'static, u8> {
+        let range = self.range();
+        let mut lease = LeasableMutableBuffer::new(self.buf);
+        lease.slice(range);
+        lease
+    }
+}
@@ -0,0 +1,421 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! This file contains the structs and methods associated with CoAP messages
+//! (RFC 7252): the fixed header and token, options, and the payload. Options
+//! are not decoded into a struct; instead, `CoapMessage::options` iterates
+//! over the raw option region of a received message, so decoding does not
+//! need to copy or store them.
+
+use crate::net::stream::SResult;
+use crate::net::stream::{decode_u16, decode_u8};
+use crate::net::stream::{encode_bytes, encode_u16, encode_u8};
+
+/// The default UDP port of CoAP.
+pub const COAP_PORT: u16 = 5683;
+
+/// The size of the fixed part of the header.
+pub const COAP_HDR_LEN: usize = 4;
+
+pub const MAX_TOKEN_LEN: usize = 8;
+
+/// The byte separating the options from the payload.
+pub const PAYLOAD_MARKER: u8 = 0xff;
+
+const COAP_VERSION: u8 = 1;
+
+/// Message types.
+pub mod msg_type {
+    pub const CON: u8 = 0;
+    pub const NON: u8 = 1;
+    pub const ACK: u8 = 2;
+    pub const RST: u8 = 3;
+}
+
+/// Method and response codes, as `class << 5 | detail`.
+pub mod code {
+    pub const EMPTY: u8 = 0x00;
+    pub const GET: u8 = 0x01;
+    pub const POST: u8 = 0x02;
+    pub const PUT: u8 = 0x03;
+    pub const DELETE: u8 = 0x04;
+    pub const BAD_OPTION: u8 = 0x82;
+    pub const NOT_FOUND: u8 = 0x84;
+    pub const REQUEST_ENTITY_TOO_LARGE: u8 = 0x8d;
+    pub const SERVICE_UNAVAILABLE: u8 = 0xa3;
+
+    /// Whether `code` is a request, rather than a response or an empty
+    /// message.
+    pub fn is_request(code: u8) -> bool {
+        code != EMPTY && code >> 5 == 0
+    }
+}
+
+/// Option numbers.
+pub mod option_num {
+    pub const URI_HOST: u16 = 3;
+    pub const URI_PORT: u16 = 7;
+    pub const URI_PATH: u16 = 11;
+
+    /// Whether an option must be understood by the receiver of a message.
+    pub fn is_critical(number: u16) -> bool {
+        number & 1 == 1
+    }
+}
+
+/// The `CoapHeader` struct holds the fixed header of a CoAP message and its
+/// token.
+#[derive(Copy, Clone, Debug)]
+pub struct CoapHeader {
+    msg_type: u8,
+    code: u8,
+    message_id: u16,
+    token: [u8; MAX_TOKEN_LEN],
+    token_len: u8,
+}
+
+impl CoapHeader {
+    /// Create a header. Tokens longer than `MAX_TOKEN_LEN` are truncated.
+    pub fn new(msg_type: u8, code: u8, message_id: u16, token: &[u8]) -> CoapHeader {
+        let token_len = core::cmp::min(token.len(), MAX_TOKEN_LEN);
+        let mut header = CoapHeader {
+            msg_type: msg_type & 0b11,
+            code,
+            message_id,
+            token: [0; MAX_TOKEN_LEN],
+            token_len: token_len as u8,
+        };
+        header.token[..token_len].copy_from_slice(&token[..token_len]);
+        header
+    }
+
+    pub fn get_msg_type(&self) -> u8 {
+        self.msg_type
+    }
+
+    pub fn get_code(&self) -> u8 {
+        self.code
+    }
+
+    pub fn get_message_id(&self) -> u16 {
+        self.message_id
+    }
+
+    pub fn get_token(&self) -> &[u8] {
+        &self.token[..self.token_len as usize]
+    }
+
+    /// The size of the header including the token.
+    pub fn get_hdr_size(&self) -> usize {
+        COAP_HDR_LEN + self.token_len as usize
+    }
+
+    /// This function serializes the `CoapHeader` and its token into the
+    /// provided buffer.
+    ///
+    /// # Arguments
+    ///
+    /// `buf` - A mutable buffer to serialize the `CoapHeader` into
+    /// `offset` - The current offset into the provided buffer
+    ///
+    /// # Return Value
+    ///
+    /// This function returns the new offset into the buffer wrapped in an
+    /// SResult.
+    pub fn encode(&self, buf: &mut [u8], offset: usize) -> SResult<usize> {
+        stream_len_cond!(buf, self.get_hdr_size() + offset);
+
+        let first = COAP_VERSION << 6 | self.msg_type << 4 | self.token_len;
+        let mut off = offset;
+        off = enc_consume!(buf, off; encode_u8, first);
+        off = enc_consume!(buf, off; encode_u8, self.code);
+        off = enc_consume!(buf, off; encode_u16, self.message_id);
+        off = enc_consume!(buf, off; encode_bytes, self.get_token());
+        stream_done!(off, off);
+    }
+
+    /// This function deserializes the `CoapHeader` and token from the provided
+    /// buffer.
+    ///
+    /// # Arguments
+    ///
+    /// `buf` - The byte array corresponding to a serialized CoAP message
+    ///
+    /// # Return Value
+    ///
+    /// This function returns a `CoapHeader` struct wrapped in an SResult, or
+    /// an error if the version or the token length are invalid.
+    pub fn decode(buf: &[u8]) -> SResult<CoapHeader> {
+        stream_len_cond!(buf, COAP_HDR_LEN);
+        let off = 0;
+        let (off, first) = dec_try!(buf, off; decode_u8);
+        let (off, code) = dec_try!(buf, off; decode_u8);
+        let (off, message_id) = dec_try!(buf, off; decode_u16);
+        let token_len = (first & 0xf) as usize;
+        stream_cond!(first >> 6 == COAP_VERSION && token_len <= MAX_TOKEN_LEN);
+        stream_len_cond!(buf, off + token_len);
+        let header = CoapHeader::new(
+            (first >> 4) & 0b11,
+            code,
+            message_id,
+            &buf[off..off + token_len],
+        );
+        stream_done!(off + token_len, header);
+    }
+}
+
+/// Encode the option delta or length `value` as a 4-bit field and the number
+/// of extended bytes that follow it.
+fn option_field(value: u16) -> (u8, usize) {
+    match value {
+        0..=12 => (value as u8, 0),
+        13..=268 => (13, 1),
+        _ => (14, 2),
+    }
+}
+
+/// This function serializes an option into the provided buffer. Options must
+/// be encoded in the order of their numbers.
+///
+/// # Arguments
+///
+/// `buf` - A mutable buffer to serialize the option into
+/// `prev` - The number of the previous option of the message, or 0
+/// `number` - The number of the option
+/// `value` - The value of the option
+///
+/// # Return Value
+///
+/// This function returns the number of bytes written wrapped in an SResult.
+pub fn encode_option(buf: &mut [u8], prev: u16, number: u16, value: &[u8]) -> SResult {
+    stream_cond!(number >= prev && value.len() <= u16::MAX as usize);
+    let delta = number - prev;
+    let len = value.len() as u16;
+    let (delta_field, delta_ext) = option_field(delta);
+    let (len_field, len_ext) = option_field(len);
+    stream_len_cond!(buf, 1 + delta_ext + len_ext + value.len());
+
+    let mut off = enc_consume!(buf; encode_u8, delta_field << 4 | len_field);
+    for (value, ext) in [(delta, delta_ext), (len, len_ext)] {
+        match ext {
+            1 => off = enc_consume!(buf, off; encode_u8, (value - 13) as u8),
+            2 => off = enc_consume!(buf, off; encode_u16, value - 269),
+            _ => {}
+        }
+    }
+    off = enc_consume!(buf, off; encode_bytes, value);
+    stream_done!(off);
+}
+
+/// This function serializes a path such as `sensors/temp` into Uri-Path
+/// options, one per segment. A leading `/` is ignored, and an empty path
+/// (the root resource) is encoded without options.
+///
+/// # Return Value
+///
+/// This function returns the number of bytes written wrapped in an SResult.
+pub fn encode_uri_path(buf: &mut [u8], path: &[u8]) -> SResult {
+    let path = path.strip_prefix(b"/").unwrap_or(path);
+    let mut off = 0;
+    let mut prev = 0;
+    if !path.is_empty() {
+        for segment in path.split(|b| *b == b'/') {
+            off = enc_consume!(buf, off; encode_option, prev, option_num::URI_PATH, segment);
+            prev = option_num::URI_PATH;
+        }
+    }
+    stream_done!(off);
+}
+
+/// Decode the 4-bit option delta or length `field`, reading its extended
+/// bytes from `buf`. Returns the value and the number of bytes read.
+fn decode_option_field(field: u8, buf: &[u8]) -> Option<(u16, usize)> {
+    match field {
+        0..=12 => Some((field as u16, 0)),
+        13 => buf.first().map(|ext| (*ext as u16 + 13, 1)),
+        14 => buf.get(..2).map(|ext| {
+            (
+                (u16::from_be_bytes([ext[0], ext[1]])).saturating_add(269),
+                2,
+            )
+        }),
+        _ => None,
+    }
+}
+
+/// Decode the option at the start of `buf`. Returns its delta, its value and
+/// the rest of the buffer, or `None` if the option is malformed.
+fn decode_option(buf: &[u8]) -> Option<(u16, &[u8], &[u8])> {
+    let first = *buf.first()?;
+    let (delta, delta_len) = decode_option_field(first >> 4, &buf[1..])?;
+    let (len, len_len) = decode_option_field(first & 0xf, &buf[1 + delta_len..])?;
+    let start = 1 + delta_len + len_len;
+    let end = start + len as usize;
+    let value = buf.get(start..end)?;
+    Some((delta, value, &buf[end..]))
+}
+
+/// An iterator over the options of a received message, yielding the number
+/// and the value of each option.
+pub struct CoapOptions<'b> {
+    buf: &'b [u8],
+    number: u16,
+}
+
+impl<'b> Iterator for CoapOptions<'b> {
+    type Item = (u16, &'b [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // The options were validated by `CoapMessage::decode`, so they end at
+        // the payload marker or at the end of the message.
+        if self.buf.first().map_or(true, |b| *b == PAYLOAD_MARKER) {
+            return None;
+        }
+        let (delta, value, rest) = decode_option(self.buf)?;
+        self.buf = rest;
+        self.number = self.number.saturating_add(delta);
+        Some((self.number, value))
+    }
+}
+
+/// A received CoAP message, borrowing its options and payload from the
+/// buffer it was decoded from.
+pub struct CoapMessage<'b> {
+    pub header: CoapHeader,
+    options: &'b [u8],
+    pub payload: &'b [u8],
+}
+
+impl<'b> CoapMessage<'b> {
+    /// Decode a message, checking that its header and options are well
+    /// formed. Returns `None` for malformed messages, which are silently
+    /// ignored by CoAP endpoints.
+    pub fn decode(buf: &'b [u8]) -> Option<CoapMessage<'b>> {
+        let (off, header) = CoapHeader::decode(buf).done()?;
+        let options = &buf[off..];
+        let mut rest = options;
+        while let Some(first) = rest.first() {
+            if *first == PAYLOAD_MARKER {
+                break;
+            }
+            let (_, _, next) = decode_option(rest)?;
+            rest = next;
+        }
+        let options = &options[..options.len() - rest.len()];
+        let payload = match rest.split_first() {
+            // A payload marker must be followed by a payload.
+            Some((_, [])) => return None,
+            Some((_, payload)) => payload,
+            None => rest,
+        };
+        Some(CoapMessage {
+            header,
+            options,
+            payload,
+        })
+    }
+
+    /// Iterate over the options of the message.
+    pub fn options(&self) -> CoapOptions<'b> {
+        CoapOptions {
+            buf: self.options,
+            number: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // CON GET /sensors/temp with message ID 0x1234 and token 0xaabb.
+    const GET_TEMP: [u8; 19] = [
+        0x42, 0x01, 0x12, 0x34, 0xaa, 0xbb, 0xb7, b's', b'e', b'n', b's', b'o', b'r', b's', 0x04,
+        b't', b'e', b'm', b'p',
+    ];
+
+    #[test]
+    fn encode_request() {
+        let mut buf = [0; 32];
+        let header = CoapHeader::new(msg_type::CON, code::GET, 0x1234, &[0xaa, 0xbb]);
+        let off = match header.encode(&mut buf, 0) {
+            SResult::Done(_, off) => off,
+            _ => panic!("failed to encode CoAP header"),
+        };
+        assert_eq!(off, header.get_hdr_size());
+        let len = match encode_uri_path(&mut buf[off..], b"/sensors/temp") {
+            SResult::Done(len, ()) => len,
+            _ => panic!("failed to encode Uri-Path"),
+        };
+        assert_eq!(&buf[..off + len], &GET_TEMP);
+    }
+
+    #[test]
+    fn decode_request() {
+        let message = CoapMessage::decode(&GET_TEMP).expect("failed to decode CoAP message");
+        assert_eq!(message.header.get_msg_type(), msg_type::CON);
+        assert_eq!(message.header.get_code(), code::GET);
+        assert!(code::is_request(message.header.get_code()));
+        assert_eq!(message.header.get_message_id(), 0x1234);
+        assert_eq!(message.header.get_token(), &[0xaa, 0xbb]);
+
+        let mut options = message.options();
+        assert_eq!(
+            options.next(),
+            Some((option_num::URI_PATH, &b"sensors"[..]))
+        );
+        assert_eq!(options.next(), Some((option_num::URI_PATH, &b"temp"[..])));
+        assert_eq!(options.next(), None);
+        assert!(message.payload.is_empty());
+    }
+
+    #[test]
+    fn extended_option_fields() {
+        let mut buf = [0; 32];
+        let value = [0x5a; 20];
+        match encode_option(&mut buf, 0, 300, &value) {
+            SResult::Done(24, ()) => {}
+            _ => panic!("failed to encode option"),
+        }
+        // Delta 300 = 269 + 31 and length 20 = 13 + 7.
+        assert_eq!(&buf[..4], &[0xed, 0x00, 0x1f, 0x07]);
+
+        buf[24] = PAYLOAD_MARKER;
+        buf[25..28].copy_from_slice(b"abc");
+        let mut message = [0; 32];
+        message[..4].copy_from_slice(&[0x50, code::POST, 0, 1]);
+        message[4..].copy_from_slice(&buf[..28]);
+        let message = CoapMessage::decode(&message).expect("failed to decode message");
+        assert_eq!(message.header.get_msg_type(), msg_type::NON);
+        let mut options = message.options();
+        assert_eq!(options.next(), Some((300, &value[..])));
+        assert_eq!(options.next(), None);
+        assert_eq!(message.payload, b"abc");
+    }
+
+    #[test]
+    fn options_must_be_in_order() {
+        let mut buf = [0; 8];
+        match encode_option(&mut buf, option_num::URI_PATH, option_num::URI_PORT, &[]) {
+            SResult::Error(()) => {}
+            _ => panic!("encoded an option out of order"),
+        }
+    }
+
+    #[test]
+    fn malformed_messages_are_ignored() {
+        // Version 2.
+        assert!(CoapMessage::decode(&[0x80, code::GET, 0, 1]).is_none());
+        // Token length 9.
+        assert!(CoapMessage::decode(&[0x49, code::GET, 0, 1, 1, 2, 3, 4, 5, 6, 7, 8, 9]).is_none());
+        // Truncated token.
+        assert!(CoapMessage::decode(&[0x42, code::GET, 0, 1, 0xaa]).is_none());
+        // Payload marker without a payload.
+        assert!(CoapMessage::decode(&[0x40, code::GET, 0, 1, PAYLOAD_MARKER]).is_none());
+        // Reserved option delta.
+        assert!(CoapMessage::decode(&[0x40, code::GET, 0, 1, 0xf1, 0]).is_none());
+        // Option value past the end of the message.
+        assert!(CoapMessage::decode(&[0x40, code::GET, 0, 1, 0xb4, b'a']).is_none());
+    }
+}
@@ -0,0 +1,912 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! CoAP userspace interface.
+//!
+//! Implements the message layer of CoAP (RFC 7252) on a UDP port bound by
+//! the kernel, normally 5683, so that processes can act as CoAP clients and
+//! servers without each bringing their own implementation:
+//!
+//! - As a server, a process registers resources by their path. Requests for
+//!   a registered path are passed to the process, which answers them with a
+//!   response code and payload. Responses to confirmable requests are
+//!   piggybacked on the acknowledgment. The driver itself answers requests
+//!   for unknown paths (4.04), requests for a resource that is still serving
+//!   another request (5.03), and pings.
+//! - As a client, a process sends confirmable GET, POST, PUT and DELETE
+//!   requests, one at a time. The driver retransmits a request with
+//!   exponential backoff until it is acknowledged, matches the response by
+//!   its message ID and token, and acknowledges separate responses.
+//!
+//! The implementation is deliberately small:
+//!
+//! - The resource table is shared by all processes, and each resource serves
+//!   one request at a time. Retransmissions of that request are ignored, but
+//!   there is no other deduplication: a retransmitted request whose response
+//!   was lost is passed to the process again.
+//! - Only the Uri-Path option is interpreted. Requests with other critical
+//!   options are answered with 4.02, and responses carry no options.
+//! - All messages are sent from a single kernel buffer, so the request and
+//!   response payloads are copied from the process buffers when a message is
+//!   (re)transmitted. They must not change until the exchange completes.
+
+use crate::net::coap::{
+    code, encode_uri_path, msg_type, option_num, CoapHeader, CoapMessage, COAP_HDR_LEN,
+    MAX_TOKEN_LEN, PAYLOAD_MARKER,
+};
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::network_capabilities::NetworkCapability;
+use crate::net::udp::udp_recv::UDPRecvClient;
+use crate::net::udp::udp_send::{UDPSendClient, UDPSender};
+
+use core::cell::Cell;
+use core::mem::size_of;
+
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, GrantKernelData, UpcallCount};
+use kernel::hil::time::{self, Alarm, ConvertTicks, Ticks};
+use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{MapCell, OptionalCell};
+use kernel::utilities::leasable_buffer::LeasableMutableBuffer;
+use kernel::{ErrorCode, ProcessId};
+
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Coap as usize;
+
+/// The number of resources the driver can serve at a time.
+pub const MAX_NUM_RESOURCES: usize = 4;
+
+/// The maximum length of the path of a resource or a request.
+pub const MAX_PATH_LEN: usize = 32;
+
+/// Granularity of the retransmission and exchange timers.
+const TICK_MS: u32 = 250;
+/// Time to wait for the acknowledgment of the first transmission of a
+/// request. It doubles with every retransmission.
+const ACK_TIMEOUT_MS: u32 = 2000;
+/// The number of retransmissions of a request before it fails.
+const MAX_RETRANSMIT: u8 = 4;
+/// Time to wait for a separate response once a request was acknowledged.
+const RESPONSE_TIMEOUT_MS: u32 = 10000;
+/// Time a process has to respond to a request for one of its resources.
+const PROCESSING_TIMEOUT_MS: u32 = 5000;
+/// Length of the tokens of requests sent by the driver.
+const TOKEN_LEN: usize = 4;
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    /// Path of a request, or of a resource to register.
+    pub const PATH: usize = 0;
+    /// IPv6 address to send a request to.
+    pub const REMOTE: usize = 1;
+    /// Payload of a request.
+    pub const REQUEST: usize = 2;
+    /// Payload of a response.
+    pub const RESPONSE: usize = 3;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 4;
+}
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    /// Payload of a request received for a resource.
+    pub const REQUEST: usize = 0;
+    /// Payload of the response to a request.
+    pub const RESPONSE: usize = 1;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 2;
+}
+
+/// Ids for upcalls
+mod upcall {
+    /// A request of the process completed.
+    pub const RESPONSE: usize = 0;
+    /// A request arrived for a resource of the process.
+    pub const REQUEST: usize = 1;
+    /// The number of upcalls the kernel stores for this grant
+    pub const COUNT: u8 = 2;
+}
+
+/// A request being served by a resource.
+#[derive(Copy, Clone)]
+struct Request {
+    remote_addr: IPAddr,
+    remote_port: u16,
+    header: CoapHeader,
+}
+
+/// An entry of the resource table.
+pub struct Resource {
+    owner: OptionalCell<ProcessId>,
+    /// The path, without a leading `/`.
+    path: Cell<[u8; MAX_PATH_LEN]>,
+    path_len: Cell<usize>,
+    request: OptionalCell<Request>,
+    /// Code of the response to the request, set once the process responded.
+    response: OptionalCell<u8>,
+    /// Time left for the process to respond, zero if stopped.
+    timer_ms: Cell<u32>,
+}
+
+impl Default for Resource {
+    fn default() -> Resource {
+        Resource {
+            owner: OptionalCell::empty(),
+            path: Cell::new([0; MAX_PATH_LEN]),
+            path_len: Cell::new(0),
+            request: OptionalCell::empty(),
+            response: OptionalCell::empty(),
+            timer_ms: Cell::new(0),
+        }
+    }
+}
+
+impl Resource {
+    pub fn new() -> Resource {
+        Resource::default()
+    }
+
+    fn has_path(&self, path: &[u8]) -> bool {
+        &self.path.get()[..self.path_len.get()] == path
+    }
+}
+
+/// A confirmable request sent by a process.
+#[derive(Copy, Clone)]
+struct Exchange {
+    remote_addr: IPAddr,
+    remote_port: u16,
+    method: u8,
+    message_id: u16,
+    token: [u8; TOKEN_LEN],
+    /// The request has to be (re)transmitted.
+    output: bool,
+    /// The server acknowledged the request, so the response comes in a
+    /// separate message.
+    acked: bool,
+    retries: u8,
+    /// Time left on the retransmission or response timer, zero if stopped.
+    timer_ms: u32,
+}
+
+#[derive(Default)]
+pub struct App {
+    exchange: Option<Exchange>,
+}
+
+pub struct CoAPDriver<'a, A: Alarm<'a>> {
+    /// UDP sender bound to the CoAP port.
+    sender: &'a dyn UDPSender<'a>,
+
+    /// Alarm driving retransmissions and timeouts.
+    alarm: &'a A,
+
+    /// Grant of apps that use this driver.
+    apps: Grant<
+        App,
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+
+    /// Resource table, shared by all processes.
+    resources: &'a [Resource],
+
+    kernel_buffer: MapCell<LeasableMutableBuffer<'static, u8>>,
+
+    /// An empty message or an error response sent by the driver itself.
+    pending_reply: OptionalCell<(IPAddr, u16, CoapHeader)>,
+
+    next_message_id: Cell<u16>,
+
+    net_cap: &'static NetworkCapability,
+}
+
+impl<'a, A: Alarm<'a>> CoAPDriver<'a, A> {
+    pub fn new(
+        sender: &'a dyn UDPSender<'a>,
+        alarm: &'a A,
+        grant: Grant<
+            App,
+            UpcallCount<{ upcall::COUNT }>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+        resources: &'a [Resource],
+        kernel_buffer: LeasableMutableBuffer<'static, u8>,
+        net_cap: &'static NetworkCapability,
+    ) -> CoAPDriver<'a, A> {
+        CoAPDriver {
+            sender,
+            alarm,
+            apps: grant,
+            resources,
+            kernel_buffer: MapCell::new(kernel_buffer),
+            pending_reply: OptionalCell::empty(),
+            next_message_id: Cell::new(0),
+            net_cap,
+        }
+    }
+
+    /// The resource `id` if it belongs to `processid`.
+    fn resource(&self, id: usize, processid: ProcessId) -> Result<&Resource, ErrorCode> {
+        self.resources
+            .get(id)
+            .filter(|res| res.owner.contains(&processid))
+            .ok_or(ErrorCode::INVAL)
+    }
+
+    /// Whether a table entry can be handed out, either because it is unused
+    /// or because its process no longer exists.
+    fn is_free(&self, res: &Resource) -> bool {
+        res.owner
+            .map_or(true, |owner| self.apps.enter(*owner, |_, _| ()).is_err())
+    }
+
+    /// The maximum length of the payload of a response.
+    fn max_payload_len(&self) -> usize {
+        self.kernel_buffer.map_or(0, |buf| {
+            buf.len().saturating_sub(COAP_HDR_LEN + MAX_TOKEN_LEN + 1)
+        })
+    }
+
+    fn next_message_id(&self) -> u16 {
+        let message_id = self.next_message_id.get();
+        self.next_message_id.set(message_id.wrapping_add(1));
+        message_id
+    }
+
+    /// Queue an empty acknowledgment or reset of message `message_id`.
+    fn reply_empty(&self, remote_addr: IPAddr, remote_port: u16, ty: u8, message_id: u16) {
+        let header = CoapHeader::new(ty, code::EMPTY, message_id, &[]);
+        self.pending_reply.set((remote_addr, remote_port, header));
+    }
+
+    /// Queue a response without payload to a request, piggybacked on the
+    /// acknowledgment if the request is confirmable.
+    fn reply_error(&self, remote_addr: IPAddr, remote_port: u16, request: &CoapHeader, code: u8) {
+        let header = if request.get_msg_type() == msg_type::CON {
+            CoapHeader::new(
+                msg_type::ACK,
+                code,
+                request.get_message_id(),
+                request.get_token(),
+            )
+        } else {
+            CoapHeader::new(
+                msg_type::NON,
+                code,
+                self.next_message_id(),
+                request.get_token(),
+            )
+        };
+        self.pending_reply.set((remote_addr, remote_port, header));
+    }
+
+    /// Start the alarm if it is not running yet.
+    fn start_timer(&self) {
+        if !self.alarm.is_armed() {
+            self.alarm
+                .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(TICK_MS));
+        }
+    }
+
+    /// Send the next pending message if the kernel buffer is available: first
+    /// a reply of the driver, then responses of resources, then requests of
+    /// processes.
+    fn transmit_next(&self) {
+        if self.kernel_buffer.is_none() {
+            return;
+        }
+        if let Some((remote_addr, remote_port, header)) = self.pending_reply.take() {
+            let len = self
+                .kernel_buffer
+                .map_or(None, |buf| header.encode(&mut buf[..], 0).done())
+                .map_or(0, |(off, _)| off);
+            self.transmit(remote_addr, remote_port, len);
+            return;
+        }
+        if let Some(res) = self.resources.iter().find(|res| res.response.is_some()) {
+            if let Some((remote_addr, remote_port, len)) = self.encode_response(res) {
+                self.transmit(remote_addr, remote_port, len);
+            } else {
+                // The response does not fit in a message, or the process is
+                // gone, so the request cannot be answered.
+                self.transmit_next();
+            }
+            return;
+        }
+
+        let mut next = None;
+        self.apps.each(|processid, app, _| {
+            if next.is_none() && app.exchange.is_some_and(|exchange| exchange.output) {
+                next = Some(processid);
+            }
+        });
+        if let Some(processid) = next {
+            match self.encode_request(processid) {
+                Ok((remote_addr, remote_port, len)) => {
+                    self.transmit(remote_addr, remote_port, len);
+                }
+                Err(err) => {
+                    let _ = self.apps.enter(processid, |app, kernel_data| {
+                        app.exchange = None;
+                        kernel_data
+                            .schedule_upcall(upcall::RESPONSE, (into_statuscode(Err(err)), 0, 0))
+                            .ok();
+                    });
+                    self.transmit_next();
+                }
+            }
+        }
+    }
+
+    /// Encode the response of `res` into the kernel buffer, and finish its
+    /// request.
+    fn encode_response(&self, res: &Resource) -> Option<(IPAddr, u16, usize)> {
+        let response_code = res.response.take()?;
+        let request = res.request.take()?;
+        res.timer_ms.set(0);
+        let owner = res.owner.extract()?;
+        let header = if request.header.get_msg_type() == msg_type::CON {
+            CoapHeader::new(
+                msg_type::ACK,
+                response_code,
+                request.header.get_message_id(),
+                request.header.get_token(),
+            )
+        } else {
+            CoapHeader::new(
+                msg_type::NON,
+                response_code,
+                self.next_message_id(),
+                request.header.get_token(),
+            )
+        };
+        self.apps
+            .enter(owner, |_, kernel_data| {
+                self.kernel_buffer.map_or(None, |buf| {
+                    let (off, _) = header.encode(&mut buf[..], 0).done()?;
+                    encode_payload(kernel_data, ro_allow::RESPONSE, &mut buf[off..])
+                        .ok()
+                        .map(|len| (request.remote_addr, request.remote_port, off + len))
+                })
+            })
+            .ok()
+            .flatten()
+    }
+
+    /// Encode the request of `processid` into the kernel buffer, and start
+    /// its retransmission timer.
+    fn encode_request(&self, processid: ProcessId) -> Result<(IPAddr, u16, usize), ErrorCode> {
+        self.apps
+            .enter(processid, |app, kernel_data| {
+                let exchange = app.exchange.as_mut().ok_or(ErrorCode::FAIL)?;
+                let mut path = [0; MAX_PATH_LEN];
+                let path_len = copy_from_allow(kernel_data, ro_allow::PATH, &mut path)?;
+                let header = CoapHeader::new(
+                    msg_type::CON,
+                    exchange.method,
+                    exchange.message_id,
+                    &exchange.token,
+                );
+                let len = self.kernel_buffer.map_or(Err(ErrorCode::NOMEM), |buf| {
+                    let (off, _) = header
+                        .encode(&mut buf[..], 0)
+                        .done()
+                        .ok_or(ErrorCode::SIZE)?;
+                    let (path_off, _) = encode_uri_path(&mut buf[off..], &path[..path_len])
+                        .done()
+                        .ok_or(ErrorCode::SIZE)?;
+                    let off = off + path_off;
+                    encode_payload(kernel_data, ro_allow::REQUEST, &mut buf[off..])
+                        .map(|len| off + len)
+                })?;
+                exchange.output = false;
+                exchange.timer_ms = ACK_TIMEOUT_MS << exchange.retries;
+                self.start_timer();
+                Ok((exchange.remote_addr, exchange.remote_port, len))
+            })
+            .unwrap_or(Err(ErrorCode::FAIL))
+    }
+
+    /// Queue a message with the first `len` bytes of the kernel buffer.
+    fn transmit(&self, remote_addr: IPAddr, remote_port: u16, len: usize) {
+        if let Some(mut buf) = self.kernel_buffer.take() {
+            buf.slice(0..len);
+            if let Err(mut buf) = self
+                .sender
+                .send_to(remote_addr, remote_port, buf, self.net_cap)
+            {
+                // The message is lost; requests are recovered by their
+                // retransmission timer.
+                buf.reset();
+                self.kernel_buffer.replace(buf);
+            }
+        }
+    }
+
+    /// Pass a request to the resource it is for, or answer it.
+    fn request_arrived(&self, remote_addr: IPAddr, remote_port: u16, msg: &CoapMessage) {
+        let header = &msg.header;
+        if !matches!(header.get_msg_type(), msg_type::CON | msg_type::NON) {
+            return;
+        }
+        let mut path = [0; MAX_PATH_LEN];
+        let path_len = match request_path(msg, &mut path) {
+            Ok(path_len) => path_len,
+            Err(code) => {
+                self.reply_error(remote_addr, remote_port, header, code);
+                return;
+            }
+        };
+        let id = match self
+            .resources
+            .iter()
+            .position(|res| res.owner.is_some() && res.has_path(&path[..path_len]))
+        {
+            Some(id) => id,
+            None => {
+                self.reply_error(remote_addr, remote_port, header, code::NOT_FOUND);
+                return;
+            }
+        };
+        let res = &self.resources[id];
+        if let Some(request) = res.request.extract() {
+            let duplicate = request.remote_addr == remote_addr
+                && request.remote_port == remote_port
+                && request.header.get_message_id() == header.get_message_id();
+            if !duplicate {
+                self.reply_error(remote_addr, remote_port, header, code::SERVICE_UNAVAILABLE);
+            }
+            return;
+        }
+
+        let delivered = res.owner.map_or(Err(ErrorCode::FAIL), |owner| {
+            self.apps
+                .enter(*owner, |_, kernel_data| {
+                    let len = kernel_data
+                        .get_readwrite_processbuffer(rw_allow::REQUEST)
+                        .and_then(|dest| {
+                            dest.mut_enter(|dest| {
+                                if dest.len() < msg.payload.len() {
+                                    return Err(ErrorCode::SIZE);
+                                }
+                                dest[..msg.payload.len()].copy_from_slice(msg.payload);
+                                Ok(msg.payload.len())
+                            })
+                        })
+                        .unwrap_or(if msg.payload.is_empty() {
+                            Ok(0)
+                        } else {
+                            Err(ErrorCode::SIZE)
+                        })?;
+                    kernel_data
+                        .schedule_upcall(upcall::REQUEST, (id, header.get_code() as usize, len))
+                        .ok();
+                    Ok(())
+                })
+                .unwrap_or(Err(ErrorCode::FAIL))
+        });
+        match delivered {
+            Ok(()) => {
+                res.request.set(Request {
+                    remote_addr,
+                    remote_port,
+                    header: *header,
+                });
+                res.timer_ms.set(PROCESSING_TIMEOUT_MS);
+                self.start_timer();
+            }
+            Err(ErrorCode::SIZE) => self.reply_error(
+                remote_addr,
+                remote_port,
+                header,
+                code::REQUEST_ENTITY_TOO_LARGE,
+            ),
+            Err(_) => self.reply_error(remote_addr, remote_port, header, code::NOT_FOUND),
+        }
+    }
+
+    /// Match an empty message or a response to the request of a process.
+    /// Returns whether it matched a request.
+    fn response_arrived(&self, remote_addr: IPAddr, msg: &CoapMessage) -> bool {
+        let header = &msg.header;
+        let mut matched = false;
+        self.apps.each(|_, app, kernel_data| {
+            let exchange = match app.exchange {
+                Some(exchange) if !matched && exchange.remote_addr == remote_addr => exchange,
+                _ => return,
+            };
+            let same_message = header.get_message_id() == exchange.message_id;
+            let same_token = header.get_token() == &exchange.token[..];
+            let result = match (header.get_msg_type(), header.get_code()) {
+                // The server acknowledged the request and will respond
+                // separately.
+                (msg_type::ACK, code::EMPTY) if same_message => {
+                    app.exchange = Some(Exchange {
+                        acked: true,
+                        output: false,
+                        timer_ms: RESPONSE_TIMEOUT_MS,
+                        ..exchange
+                    });
+                    matched = true;
+                    return;
+                }
+                (msg_type::RST, _) if same_message => Err(ErrorCode::FAIL),
+                (msg_type::ACK, _) if same_message && same_token => Ok(()),
+                (msg_type::CON | msg_type::NON, _) if same_token => Ok(()),
+                _ => return,
+            };
+            matched = true;
+            app.exchange = None;
+            let len = if result.is_ok() {
+                kernel_data
+                    .get_readwrite_processbuffer(rw_allow::RESPONSE)
+                    .and_then(|dest| {
+                        dest.mut_enter(|dest| {
+                            let len = core::cmp::min(dest.len(), msg.payload.len());
+                            dest[..len].copy_from_slice(&msg.payload[..len]);
+                            len
+                        })
+                    })
+                    .unwrap_or(0)
+            } else {
+                0
+            };
+            kernel_data
+                .schedule_upcall(
+                    upcall::RESPONSE,
+                    (into_statuscode(result), header.get_code() as usize, len),
+                )
+                .ok();
+        });
+        matched
+    }
+
+    /// Add a resource with `path` to the table, returning its number.
+    fn register(&self, path: &[u8], processid: ProcessId) -> Result<usize, ErrorCode> {
+        let path = path.strip_prefix(b"/").unwrap_or(path);
+        if path.len() > MAX_PATH_LEN {
+            return Err(ErrorCode::SIZE);
+        }
+        if self
+            .resources
+            .iter()
+            .any(|res| !self.is_free(res) && res.has_path(path))
+        {
+            return Err(ErrorCode::ALREADY);
+        }
+        let id = self
+            .resources
+            .iter()
+            .position(|res| self.is_free(res))
+            .ok_or(ErrorCode::NOMEM)?;
+        let res = &self.resources[id];
+        let mut stored = [0; MAX_PATH_LEN];
+        stored[..path.len()].copy_from_slice(path);
+        res.path.set(stored);
+        res.path_len.set(path.len());
+        res.request.clear();
+        res.response.clear();
+        res.timer_ms.set(0);
+        res.owner.set(processid);
+        Ok(id)
+    }
+
+    /// Remote address of a request, read from the process's allow buffer.
+    fn remote_addr(&self, processid: ProcessId) -> Result<IPAddr, ErrorCode> {
+        self.apps
+            .enter(processid, |_, kernel_data| {
+                kernel_data
+                    .get_readonly_processbuffer(ro_allow::REMOTE)
+                    .and_then(|remote| {
+                        remote.enter(|remote| {
+                            if remote.len() != size_of::<IPAddr>() {
+                                return Err(ErrorCode::INVAL);
+                            }
+                            let mut addr = IPAddr::new();
+                            remote.copy_to_slice(&mut addr.0);
+                            Ok(addr)
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::INVAL))
+            })
+            .unwrap_or(Err(ErrorCode::NOMEM))
+    }
+}
+
+/// Build the path of a request from its Uri-Path options, joining the
+/// segments with `/`. Returns the code to respond with if the request has
+/// an unsupported critical option, or a path that cannot match a resource.
+fn request_path(msg: &CoapMessage, path: &mut [u8; MAX_PATH_LEN]) -> Result<usize, u8> {
+    let mut len = 0;
+    for (number, value) in msg.options() {
+        match number {
+            option_num::URI_PATH => {
+                let start = if len > 0 { len + 1 } else { 0 };
+                let end = start + value.len();
+                if end > MAX_PATH_LEN {
+                    return Err(code::NOT_FOUND);
+                }
+                if len > 0 {
+                    path[len] = b'/';
+                }
+                path[start..end].copy_from_slice(value);
+                len = end;
+            }
+            option_num::URI_HOST | option_num::URI_PORT => {}
+            number if option_num::is_critical(number) => return Err(code::BAD_OPTION),
+            _ => {}
+        }
+    }
+    Ok(len)
+}
+
+/// Copy read-only allow `allow_num` into `dest`, returning its length. A
+/// missing buffer is empty.
+fn copy_from_allow(
+    kernel_data: &GrantKernelData,
+    allow_num: usize,
+    dest: &mut [u8],
+) -> Result<usize, ErrorCode> {
+    kernel_data
+        .get_readonly_processbuffer(allow_num)
+        .and_then(|src| {
+            src.enter(|src| {
+                if src.len() > dest.len() {
+                    return Err(ErrorCode::SIZE);
+                }
+                src.copy_to_slice(&mut dest[..src.len()]);
+                Ok(src.len())
+            })
+        })
+        .unwrap_or(Ok(0))
+}
+
+/// Encode read-only allow `allow_num` as the payload of a message, with its
+/// marker, into `buf`. Returns the number of bytes written.
+fn encode_payload(
+    kernel_data: &GrantKernelData,
+    allow_num: usize,
+    buf: &mut [u8],
+) -> Result<usize, ErrorCode> {
+    match buf.split_first_mut() {
+        Some((marker, rest)) => {
+            let len = copy_from_allow(kernel_data, allow_num, rest)?;
+            *marker = PAYLOAD_MARKER;
+            Ok(if len > 0 { len + 1 } else { 0 })
+        }
+        None => copy_from_allow(kernel_data, allow_num, &mut []),
+    }
+}
+
+impl<'a, A: Alarm<'a>> SyscallDriver for CoAPDriver<'a, A> {
+    /// CoAP control
+    ///
+    /// Resources are referred to by the number returned by command `3`.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Send a confirmable request with method `arg1` (1 GET, 2 POST,
+    ///   3 PUT, 4 DELETE) to port `arg2` of the IPv6 address in read-only
+    ///   allow `1`. The path is read from read-only allow `0`, and the
+    ///   payload from read-only allow `2`. Returns BUSY if a request of the
+    ///   process is in progress.
+    /// - `2`: Cancel the request in progress.
+    /// - `3`: Register a resource with the path in read-only allow `0`.
+    ///   Returns the resource number, ALREADY if the path is registered, and
+    ///   NOMEM if the resource table is full.
+    /// - `4`: Unregister resource `arg1`.
+    /// - `5`: Respond to the request for resource `arg1` with response code
+    ///   `arg2` and the payload in read-only allow `3`.
+    /// - `6`: Returns the maximum length of the payload of a response.
+    fn command(
+        &self,
+        command_num: usize,
+        arg1: usize,
+        arg2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        let result = match command_num {
+            0 => Ok(()),
+
+            1 => {
+                let method = arg1 as u8;
+                if !matches!(method, code::GET | code::POST | code::PUT | code::DELETE)
+                    || arg1 > u8::MAX as usize
+                    || arg2 == 0
+                    || arg2 > u16::MAX as usize
+                {
+                    Err(ErrorCode::INVAL)
+                } else {
+                    self.remote_addr(processid).and_then(|remote_addr| {
+                        // Tokens only have to be hard to guess for a
+                        // server that did not see the request, so the clock
+                        // is mixed with the message ID.
+                        let message_id = self.next_message_id();
+                        let token = self
+                            .alarm
+                            .now()
+                            .into_u32()
+                            .wrapping_mul(2654435761)
+                            .wrapping_add(message_id as u32)
+                            .to_be_bytes();
+                        self.apps
+                            .enter(processid, |app, _| {
+                                if app.exchange.is_some() {
+                                    return Err(ErrorCode::BUSY);
+                                }
+                                app.exchange = Some(Exchange {
+                                    remote_addr,
+                                    remote_port: arg2 as u16,
+                                    method,
+                                    message_id,
+                                    token,
+                                    output: true,
+                                    acked: false,
+                                    retries: 0,
+                                    timer_ms: 0,
+                                });
+                                Ok(())
+                            })
+                            .unwrap_or(Err(ErrorCode::NOMEM))
+                    })
+                }
+            }
+
+            2 => self
+                .apps
+                .enter(processid, |app, _| {
+                    app.exchange.take().map(|_| ()).ok_or(ErrorCode::INVAL)
+                })
+                .unwrap_or(Err(ErrorCode::NOMEM)),
+
+            3 => {
+                let mut path = [0; MAX_PATH_LEN + 1];
+                let registered = self
+                    .apps
+                    .enter(processid, |_, kernel_data| {
+                        copy_from_allow(kernel_data, ro_allow::PATH, &mut path)
+                    })
+                    .unwrap_or(Err(ErrorCode::NOMEM))
+                    .and_then(|len| self.register(&path[..len], processid));
+                return match registered {
+                    Ok(id) => CommandReturn::success_u32(id as u32),
+                    Err(err) => CommandReturn::failure(err),
+                };
+            }
+
+            4 => self.resource(arg1, processid).map(|res| {
+                res.owner.clear();
+                res.request.clear();
+                res.response.clear();
+                res.timer_ms.set(0);
+            }),
+
+            5 => self.resource(arg1, processid).and_then(|res| {
+                if res.request.is_none() || res.response.is_some() {
+                    Err(ErrorCode::INVAL)
+                } else if !(0x40..0xc0).contains(&arg2) {
+                    // Only codes of the success and error classes (2 to 5)
+                    // are responses.
+                    Err(ErrorCode::INVAL)
+                } else {
+                    res.response.set(arg2 as u8);
+                    Ok(())
+                }
+            }),
+
+            6 => return CommandReturn::success_u32(self.max_payload_len() as u32),
+
+            _ => Err(ErrorCode::NOSUPPORT),
+        };
+        self.transmit_next();
+        match result {
+            Ok(()) => CommandReturn::success(),
+            Err(err) => CommandReturn::failure(err),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+impl<'a, A: Alarm<'a>> UDPRecvClient for CoAPDriver<'a, A> {
+    fn receive(
+        &self,
+        src_addr: IPAddr,
+        _dst_addr: IPAddr,
+        src_port: u16,
+        _dst_port: u16,
+        payload: &[u8],
+    ) {
+        // Malformed messages are silently ignored (RFC 7252, section 4.2).
+        let msg = match CoapMessage::decode(payload) {
+            Some(msg) => msg,
+            None => return,
+        };
+        let header = &msg.header;
+        if code::is_request(header.get_code()) {
+            self.request_arrived(src_addr, src_port, &msg);
+        } else if header.get_code() == code::EMPTY && header.get_msg_type() == msg_type::CON {
+            // A ping.
+            self.reply_empty(src_addr, src_port, msg_type::RST, header.get_message_id());
+        } else if self.response_arrived(src_addr, &msg) {
+            if header.get_msg_type() == msg_type::CON {
+                self.reply_empty(src_addr, src_port, msg_type::ACK, header.get_message_id());
+            }
+        } else if header.get_msg_type() == msg_type::CON {
+            // A confirmable response to a request the driver does not know.
+            self.reply_empty(src_addr, src_port, msg_type::RST, header.get_message_id());
+        }
+        self.transmit_next();
+    }
+}
+
+impl<'a, A: Alarm<'a>> UDPSendClient for CoAPDriver<'a, A> {
+    fn send_done(
+        &self,
+        _result: Result<(), ErrorCode>,
+        mut dgram: LeasableMutableBuffer<'static, u8>,
+    ) {
+        // Lost requests are recovered by the retransmission timer, and lost
+        // responses by the retransmissions of the client.
+        dgram.reset();
+        self.kernel_buffer.replace(dgram);
+        self.transmit_next();
+    }
+}
+
+impl<'a, A: Alarm<'a>> time::AlarmClient for CoAPDriver<'a, A> {
+    fn alarm(&self) {
+        let mut running = false;
+        for res in self.resources.iter() {
+            let remaining = res.timer_ms.get();
+            if remaining == 0 {
+                continue;
+            }
+            res.timer_ms.set(remaining.saturating_sub(TICK_MS));
+            if res.timer_ms.get() == 0 {
+                // The process did not respond in time. The client gives up
+                // or retransmits the request, which is then served again.
+                res.request.clear();
+                res.response.clear();
+            } else {
+                running = true;
+            }
+        }
+        self.apps.each(|_, app, kernel_data| {
+            let mut exchange = match app.exchange {
+                Some(exchange) if exchange.timer_ms > 0 => exchange,
+                _ => return,
+            };
+            exchange.timer_ms = exchange.timer_ms.saturating_sub(TICK_MS);
+            if exchange.timer_ms > 0 {
+                running = true;
+            } else if !exchange.acked && exchange.retries < MAX_RETRANSMIT {
+                exchange.retries += 1;
+                exchange.output = true;
+            } else {
+                app.exchange = None;
+                kernel_data
+                    .schedule_upcall(
+                        upcall::RESPONSE,
+                        (into_statuscode(Err(ErrorCode::NOACK)), 0, 0),
+                    )
+                    .ok();
+                return;
+            }
+            app.exchange = Some(exchange);
+        });
+        if running {
+            self.alarm
+                .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(TICK_MS));
+        }
+        self.transmit_next();
+    }
+}
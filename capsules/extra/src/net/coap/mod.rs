@@ -0,0 +1,16 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+pub mod driver;
+
+pub use self::driver::CoAPDriver;
+pub use self::driver::DRIVER_NUM;
+
+// Reexport the exports of the [`coap`] module, to avoid redundant
+// module paths (e.g. `capsules::net::coap::coap::CoapHeader`)
+mod coap;
+pub use coap::{code, msg_type, option_num};
+pub use coap::{encode_option, encode_uri_path};
+pub use coap::{CoapHeader, CoapMessage, CoapOptions};
+pub use coap::{COAP_HDR_LEN, COAP_PORT, MAX_TOKEN_LEN, PAYLOAD_MARKER};
@@ -0,0 +1,8 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+pub mod record;
+pub mod session;
+
+pub use self::session::{DtlsClient, DtlsSession};
@@ -0,0 +1,313 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! This file contains the structs and constants of the DTLS 1.2 wire format
+//! (RFC 6347): the record header that precedes every record of a datagram,
+//! and the handshake header that precedes every handshake message. The bodies
+//! of handshake messages are encoded and decoded by the session, as it only
+//! needs the few fields that PSK key exchange uses.
+
+use crate::net::stream::SResult;
+use crate::net::stream::{decode_u16, decode_u8};
+use crate::net::stream::{encode_u16, encode_u8};
+
+/// The protocol version of DTLS 1.2 on the wire.
+pub const DTLS_1_2: u16 = 0xfefd;
+
+/// The size of the record header.
+pub const RECORD_HDR_LEN: usize = 13;
+
+/// The size of the handshake header.
+pub const HANDSHAKE_HDR_LEN: usize = 12;
+
+/// TLS_PSK_WITH_AES_128_CCM_8 (RFC 6655), the only supported cipher suite.
+pub const TLS_PSK_WITH_AES_128_CCM_8: u16 = 0xc0a8;
+
+/// The explicit part of the nonce sent at the start of an encrypted record,
+/// which DTLS sets to the epoch and sequence number of the record.
+pub const EXPLICIT_NONCE_LEN: usize = 8;
+
+/// The size of the authentication tag of AES-128-CCM-8.
+pub const CCM_8_TAG_LEN: usize = 8;
+
+/// The bytes an encrypted record adds to its plaintext, besides the header.
+pub const CIPHER_OVERHEAD: usize = EXPLICIT_NONCE_LEN + CCM_8_TAG_LEN;
+
+pub const RANDOM_LEN: usize = 32;
+pub const VERIFY_DATA_LEN: usize = 12;
+pub const MASTER_SECRET_LEN: usize = 48;
+
+/// Record content types.
+pub mod content_type {
+    pub const CHANGE_CIPHER_SPEC: u8 = 20;
+    pub const ALERT: u8 = 21;
+    pub const HANDSHAKE: u8 = 22;
+    pub const APPLICATION_DATA: u8 = 23;
+}
+
+/// Handshake message types.
+pub mod handshake_type {
+    pub const CLIENT_HELLO: u8 = 1;
+    pub const SERVER_HELLO: u8 = 2;
+    pub const HELLO_VERIFY_REQUEST: u8 = 3;
+    pub const SERVER_KEY_EXCHANGE: u8 = 12;
+    pub const SERVER_HELLO_DONE: u8 = 14;
+    pub const CLIENT_KEY_EXCHANGE: u8 = 16;
+    pub const FINISHED: u8 = 20;
+}
+
+/// Alert levels and descriptions.
+pub mod alert {
+    pub const WARNING: u8 = 1;
+    pub const FATAL: u8 = 2;
+
+    pub const CLOSE_NOTIFY: u8 = 0;
+    pub const HANDSHAKE_FAILURE: u8 = 40;
+}
+
+/// The `RecordHeader` struct holds the header of a DTLS record. The sequence
+/// number is 48 bits long on the wire.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RecordHeader {
+    pub content_type: u8,
+    pub version: u16,
+    pub epoch: u16,
+    pub seq_num: u64,
+    pub length: u16,
+}
+
+impl RecordHeader {
+    pub fn new(content_type: u8, epoch: u16, seq_num: u64, length: u16) -> RecordHeader {
+        RecordHeader {
+            content_type,
+            version: DTLS_1_2,
+            epoch,
+            seq_num,
+            length,
+        }
+    }
+
+    /// The epoch and sequence number as one 64-bit value, which is how DTLS
+    /// uses them in nonces and additional authenticated data.
+    pub fn get_epoch_seq(&self) -> u64 {
+        (self.epoch as u64) << 48 | (self.seq_num & 0xffff_ffff_ffff)
+    }
+
+    /// This function serializes the `RecordHeader` into the provided buffer.
+    ///
+    /// # Arguments
+    ///
+    /// `buf` - A mutable buffer to serialize the `RecordHeader` into
+    /// `offset` - The current offset into the provided buffer
+    ///
+    /// # Return Value
+    ///
+    /// This function returns the new offset into the buffer wrapped in an
+    /// SResult.
+    pub fn encode(&self, buf: &mut [u8], offset: usize) -> SResult<usize> {
+        stream_len_cond!(buf, RECORD_HDR_LEN + offset);
+
+        let mut off = offset;
+        off = enc_consume!(buf, off; encode_u8, self.content_type);
+        off = enc_consume!(buf, off; encode_u16, self.version);
+        buf[off..off + 8].copy_from_slice(&self.get_epoch_seq().to_be_bytes());
+        off += 8;
+        off = enc_consume!(buf, off; encode_u16, self.length);
+        stream_done!(off, off);
+    }
+
+    /// This function deserializes the `RecordHeader` from the provided
+    /// buffer. It does not check that the record body is in the buffer.
+    ///
+    /// # Return Value
+    ///
+    /// This function returns a `RecordHeader` struct wrapped in an SResult,
+    /// or an error if the version is not DTLS 1.2.
+    pub fn decode(buf: &[u8]) -> SResult<RecordHeader> {
+        stream_len_cond!(buf, RECORD_HDR_LEN);
+        let off = 0;
+        let (off, content_type) = dec_try!(buf, off; decode_u8);
+        let (off, version) = dec_try!(buf, off; decode_u16);
+        let mut epoch_seq = [0; 8];
+        epoch_seq.copy_from_slice(&buf[off..off + 8]);
+        let epoch_seq = u64::from_be_bytes(epoch_seq);
+        let (off, length) = dec_try!(buf, off + 8; decode_u16);
+        stream_cond!(version == DTLS_1_2);
+        let header = RecordHeader {
+            content_type,
+            version,
+            epoch: (epoch_seq >> 48) as u16,
+            seq_num: epoch_seq & 0xffff_ffff_ffff,
+            length,
+        };
+        stream_done!(off, header);
+    }
+}
+
+/// Encode a 24-bit length, as used by handshake headers.
+fn encode_u24(buf: &mut [u8], value: u32) -> SResult {
+    stream_len_cond!(buf, 3);
+    buf[..3].copy_from_slice(&value.to_be_bytes()[1..]);
+    stream_done!(3);
+}
+
+/// Decode a 24-bit length, as used by handshake headers.
+fn decode_u24(buf: &[u8]) -> SResult<u32> {
+    stream_len_cond!(buf, 3);
+    stream_done!(3, u32::from_be_bytes([0, buf[0], buf[1], buf[2]]));
+}
+
+/// The `HandshakeHeader` struct holds the header of a handshake message.
+/// DTLS can fragment handshake messages over several records; this header
+/// describes one fragment.
+#[derive(Copy, Clone, Debug)]
+pub struct HandshakeHeader {
+    pub msg_type: u8,
+    pub length: u32,
+    pub message_seq: u16,
+    pub fragment_offset: u32,
+    pub fragment_length: u32,
+}
+
+impl HandshakeHeader {
+    /// Create the header of an unfragmented message of `length` bytes.
+    pub fn new(msg_type: u8, length: usize, message_seq: u16) -> HandshakeHeader {
+        HandshakeHeader {
+            msg_type,
+            length: length as u32,
+            message_seq,
+            fragment_offset: 0,
+            fragment_length: length as u32,
+        }
+    }
+
+    /// Whether the fragment holds the whole message.
+    pub fn is_complete(&self) -> bool {
+        self.fragment_offset == 0 && self.fragment_length == self.length
+    }
+
+    /// This function serializes the `HandshakeHeader` into the provided
+    /// buffer.
+    ///
+    /// # Arguments
+    ///
+    /// `buf` - A mutable buffer to serialize the `HandshakeHeader` into
+    /// `offset` - The current offset into the provided buffer
+    ///
+    /// # Return Value
+    ///
+    /// This function returns the new offset into the buffer wrapped in an
+    /// SResult.
+    pub fn encode(&self, buf: &mut [u8], offset: usize) -> SResult<usize> {
+        stream_len_cond!(buf, HANDSHAKE_HDR_LEN + offset);
+
+        let mut off = offset;
+        off = enc_consume!(buf, off; encode_u8, self.msg_type);
+        off = enc_consume!(buf, off; encode_u24, self.length);
+        off = enc_consume!(buf, off; encode_u16, self.message_seq);
+        off = enc_consume!(buf, off; encode_u24, self.fragment_offset);
+        off = enc_consume!(buf, off; encode_u24, self.fragment_length);
+        stream_done!(off, off);
+    }
+
+    /// This function deserializes the `HandshakeHeader` from the provided
+    /// buffer.
+    ///
+    /// # Return Value
+    ///
+    /// This function returns a `HandshakeHeader` struct wrapped in an
+    /// SResult, or an error if the fragment does not fit in the message.
+    pub fn decode(buf: &[u8]) -> SResult<HandshakeHeader> {
+        stream_len_cond!(buf, HANDSHAKE_HDR_LEN);
+        let off = 0;
+        let (off, msg_type) = dec_try!(buf, off; decode_u8);
+        let (off, length) = dec_try!(buf, off; decode_u24);
+        let (off, message_seq) = dec_try!(buf, off; decode_u16);
+        let (off, fragment_offset) = dec_try!(buf, off; decode_u24);
+        let (off, fragment_length) = dec_try!(buf, off; decode_u24);
+        stream_cond!(fragment_offset + fragment_length <= length);
+        let header = HandshakeHeader {
+            msg_type,
+            length,
+            message_seq,
+            fragment_offset,
+            fragment_length,
+        };
+        stream_done!(off, header);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_header_round_trip() {
+        let header = RecordHeader::new(content_type::APPLICATION_DATA, 1, 0x0102_0304_0506, 0x20);
+        assert_eq!(header.get_epoch_seq(), 0x0001_0102_0304_0506);
+
+        let mut buf = [0; RECORD_HDR_LEN];
+        match header.encode(&mut buf, 0) {
+            SResult::Done(_, RECORD_HDR_LEN) => {}
+            _ => panic!("failed to encode record header"),
+        }
+        assert_eq!(
+            buf,
+            [23, 0xfe, 0xfd, 0x00, 0x01, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x00, 0x20]
+        );
+        match RecordHeader::decode(&buf) {
+            SResult::Done(RECORD_HDR_LEN, decoded) => assert_eq!(decoded, header),
+            _ => panic!("failed to decode record header"),
+        }
+    }
+
+    #[test]
+    fn other_versions_are_rejected() {
+        // A DTLS 1.0 record.
+        let buf = [22, 0xfe, 0xff, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        match RecordHeader::decode(&buf) {
+            SResult::Error(()) => {}
+            _ => panic!("decoded a DTLS 1.0 record"),
+        }
+    }
+
+    #[test]
+    fn handshake_header_round_trip() {
+        let header = HandshakeHeader::new(handshake_type::FINISHED, VERIFY_DATA_LEN, 3);
+        let mut buf = [0; HANDSHAKE_HDR_LEN];
+        match header.encode(&mut buf, 0) {
+            SResult::Done(_, HANDSHAKE_HDR_LEN) => {}
+            _ => panic!("failed to encode handshake header"),
+        }
+        assert_eq!(buf, [20, 0, 0, 12, 0, 3, 0, 0, 0, 0, 0, 12]);
+
+        let decoded = match HandshakeHeader::decode(&buf) {
+            SResult::Done(HANDSHAKE_HDR_LEN, decoded) => decoded,
+            _ => panic!("failed to decode handshake header"),
+        };
+        assert_eq!(decoded.msg_type, handshake_type::FINISHED);
+        assert_eq!(decoded.length, 12);
+        assert_eq!(decoded.message_seq, 3);
+        assert!(decoded.is_complete());
+    }
+
+    #[test]
+    fn handshake_fragments() {
+        // The second half of a 300-byte message.
+        let buf = [2, 0, 0x01, 0x2c, 0, 1, 0, 0, 0x96, 0, 0, 0x96];
+        let header = match HandshakeHeader::decode(&buf) {
+            SResult::Done(_, header) => header,
+            _ => panic!("failed to decode handshake header"),
+        };
+        assert_eq!(header.fragment_offset, 150);
+        assert!(!header.is_complete());
+
+        // A fragment that ends past the end of the message.
+        let buf = [2, 0, 0x01, 0x2c, 0, 1, 0, 0, 0x96, 0, 0, 0x97];
+        match HandshakeHeader::decode(&buf) {
+            SResult::Error(()) => {}
+            _ => panic!("decoded a fragment past the end of its message"),
+        }
+    }
+}
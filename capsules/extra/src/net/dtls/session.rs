@@ -0,0 +1,1500 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! DTLS 1.2 client sessions (RFC 6347) on top of UDP.
+//!
+//! A `DtlsSession` wraps a UDP send and receive binding and encrypts and
+//! authenticates the data a kernel client exchanges with one server. It only
+//! supports pre-shared keys with the TLS_PSK_WITH_AES_128_CCM_8 cipher suite
+//! (RFC 6655), so it needs no public-key cryptography: records are protected
+//! by an `AES128CCM` implementation, such as the AES-CCM virtualizer on top of
+//! the hardware AES engine, and the handshake uses HMAC-SHA256 computed with a
+//! `Digest` implementation, such as `Sha256Software`.
+//!
+//! The implementation is kept small:
+//!
+//!   * The session is a client, and has one server at a time. The handshake
+//!     is started by `connect` and reported by `DtlsClient::connected`.
+//!   * Handshake messages must not be fragmented, which holds for PSK
+//!     handshakes over the usual path MTUs. Fragments are ignored.
+//!   * Flights are retransmitted with exponential backoff, starting from one
+//!     second. The handshake fails with `NOACK` after `MAX_RETRANSMISSIONS`
+//!     retransmissions of the same flight.
+//!   * Records are decrypted one at a time. An encrypted record that arrives
+//!     while another one is being decrypted, or while data is being
+//!     encrypted, is dropped like a lost datagram.
+//!   * Each `send` is one record of at most `MAX_DATA_LEN` bytes, and a send
+//!     completes once the datagram is sent: like UDP, DTLS does not
+//!     acknowledge data.
+//!   * There is no renegotiation or session resumption.
+//!
+//! Usage
+//! -----
+//! `components::dtls::DtlsComponent` creates a session bound to a local UDP
+//! port, with the PSK identity and key it uses. Its client then connects to
+//! the server and sends data once the handshake completed:
+//!
+//! ```rust,ignore
+//! session.set_client(telemetry);
+//! session.connect(server_addr, 5684)?;
+//! // ... later, in `DtlsClient::connected`:
+//! session.send(&reading)?;
+//! ```
+
+use core::cell::Cell;
+
+use crate::net::dtls::record::{alert, content_type, handshake_type};
+use crate::net::dtls::record::{HandshakeHeader, RecordHeader};
+use crate::net::dtls::record::{
+    CCM_8_TAG_LEN, CIPHER_OVERHEAD, DTLS_1_2, EXPLICIT_NONCE_LEN, HANDSHAKE_HDR_LEN,
+    MASTER_SECRET_LEN, RANDOM_LEN, RECORD_HDR_LEN, TLS_PSK_WITH_AES_128_CCM_8, VERIFY_DATA_LEN,
+};
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::network_capabilities::NetworkCapability;
+use crate::net::stream::SResult;
+use crate::net::stream::{decode_bytes, decode_u16, decode_u8};
+use crate::net::stream::{encode_bytes, encode_u16, encode_u8};
+use crate::net::udp::udp_recv::UDPRecvClient;
+use crate::net::udp::udp_send::{UDPSendClient, UDPSender};
+
+use kernel::hil::digest;
+use kernel::hil::rng::Random;
+use kernel::hil::symmetric_encryption::{CCMClient, AES128CCM};
+use kernel::hil::time::{self, Alarm, ConvertTicks};
+use kernel::utilities::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::{LeasableBuffer, LeasableMutableBuffer};
+use kernel::ErrorCode;
+
+/// The largest application data record the session sends or receives.
+pub const MAX_DATA_LEN: usize = 160;
+
+/// The largest PSK, so that the premaster secret fits in one HMAC block.
+pub const MAX_PSK_LEN: usize = 30;
+
+pub const MAX_PSK_IDENTITY_LEN: usize = 32;
+
+/// The length of the additional authenticated data of a record, which
+/// precedes the plaintext in the CCM buffer.
+const AAD_LEN: usize = 13;
+
+/// The buffer lengths a session needs.
+pub const TX_BUF_LEN: usize = RECORD_HDR_LEN + CIPHER_OVERHEAD + MAX_DATA_LEN;
+pub const CCM_BUF_LEN: usize = AAD_LEN + MAX_DATA_LEN + CCM_8_TAG_LEN;
+pub const TRANSCRIPT_BUF_LEN: usize = 384;
+pub const HASH_BUF_LEN: usize = HMAC_BLOCK_LEN + DIGEST_LEN + PRF_SEED_LEN;
+
+/// Longer cookies (up to 255 bytes) are allowed by the standard, but servers
+/// use short ones in practice.
+const MAX_COOKIE_LEN: usize = 32;
+
+pub const DIGEST_LEN: usize = 32;
+const HMAC_BLOCK_LEN: usize = 64;
+const IPAD: u8 = 0x36;
+const OPAD: u8 = 0x5c;
+
+/// The longest PRF label ("client finished") followed by two randoms.
+const PRF_SEED_LEN: usize = 15 + 2 * RANDOM_LEN;
+
+/// The key block holds the client and server write keys, then the client
+/// and server implicit nonces (the "IVs"). CCM needs no MAC keys.
+const KEY_LEN: usize = 16;
+const SALT_LEN: usize = 4;
+const KEY_BLOCK_LEN: usize = 2 * KEY_LEN + 2 * SALT_LEN;
+const CCM_NONCE_LEN: usize = SALT_LEN + EXPLICIT_NONCE_LEN;
+
+const CLIENT_HELLO_MAX_LEN: usize =
+    HANDSHAKE_HDR_LEN + 2 + RANDOM_LEN + 1 + 1 + MAX_COOKIE_LEN + 2 + 2 + 1 + 1;
+const FINISHED_LEN: usize = HANDSHAKE_HDR_LEN + VERIFY_DATA_LEN;
+
+const INITIAL_TIMEOUT_MS: u32 = 1000;
+pub const MAX_RETRANSMISSIONS: u32 = 5;
+
+pub trait DtlsClient {
+    /// The handshake started by `connect` completed. It fails with `FAIL` if
+    /// the server rejected it or answered with something unsupported, and
+    /// with `NOACK` if the server did not answer.
+    fn connected(&self, result: Result<(), ErrorCode>);
+
+    /// The record of a `send` was sent.
+    fn send_done(&self, result: Result<(), ErrorCode>);
+
+    /// Application data was received from the server.
+    fn receive(&self, data: &[u8]);
+
+    /// The session was closed, by `close` or by the server. The result is
+    /// `FAIL` if the server ended the session with a fatal alert.
+    fn closed(&self, result: Result<(), ErrorCode>);
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum State {
+    Closed,
+    /// The ClientHello was sent, waiting for a HelloVerifyRequest or for the
+    /// ServerHello to ServerHelloDone flight.
+    Hello,
+    /// Computing the keys and the verify data of the Finished messages.
+    Deriving,
+    /// The final flight was sent, waiting for the server's ChangeCipherSpec
+    /// and Finished.
+    Finished,
+    Connected,
+    /// Sending a close_notify alert.
+    Closing,
+}
+
+/// The value being computed while the session is deriving its keys.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Derive {
+    MasterSecret,
+    KeyBlock,
+    /// The transcript hash for the client Finished.
+    ClientFinishedHash,
+    ClientVerifyData,
+    /// The transcript hash for the server Finished, which includes the
+    /// client Finished.
+    ServerFinishedHash,
+    ServerVerifyData,
+}
+
+/// What the running hash computes. PRF outputs are computed with P_SHA256
+/// (RFC 5246, section 5): A(i) = HMAC(secret, A(i - 1)), with A(0) the seed,
+/// and each output block is HMAC(secret, A(i) + seed). Each HMAC takes an
+/// inner and an outer hash.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum HashStep {
+    Transcript,
+    InnerA,
+    OuterA,
+    InnerOutput,
+    OuterOutput,
+}
+
+/// The CCM operation in progress.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Crypt {
+    Idle,
+    /// Encrypting the client Finished, which follows the other records of
+    /// the final flight at this offset of the transmit buffer.
+    Finished(usize),
+    Data,
+    Alert,
+    Decrypt(RecordHeader),
+}
+
+/// What the datagram being sent carries.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Sending {
+    Handshake,
+    Data,
+    Alert,
+}
+
+/// The state of a P_SHA256 computation.
+struct Prf {
+    secret: [u8; HMAC_BLOCK_LEN],
+    seed: [u8; PRF_SEED_LEN],
+    seed_len: usize,
+    a: Option<[u8; DIGEST_LEN]>,
+    inner: [u8; DIGEST_LEN],
+    output: [u8; MASTER_SECRET_LEN],
+    output_len: usize,
+    produced: usize,
+}
+
+impl Prf {
+    fn new() -> Prf {
+        Prf {
+            secret: [0; HMAC_BLOCK_LEN],
+            seed: [0; PRF_SEED_LEN],
+            seed_len: 0,
+            a: None,
+            inner: [0; DIGEST_LEN],
+            output: [0; MASTER_SECRET_LEN],
+            output_len: 0,
+            produced: 0,
+        }
+    }
+
+    /// Start computing `output_len` bytes of PRF(secret, label, seed). The
+    /// secret must fit in one HMAC block.
+    fn start(&mut self, secret: &[u8], label: &[u8], seed: &[&[u8]], output_len: usize) {
+        self.secret = [0; HMAC_BLOCK_LEN];
+        self.secret[..secret.len()].copy_from_slice(secret);
+        self.seed_len = 0;
+        for part in core::iter::once(&label).chain(seed) {
+            self.seed[self.seed_len..self.seed_len + part.len()].copy_from_slice(part);
+            self.seed_len += part.len();
+        }
+        self.a = None;
+        self.output_len = output_len;
+        self.produced = 0;
+    }
+
+    fn seed(&self) -> &[u8] {
+        &self.seed[..self.seed_len]
+    }
+
+    /// Add an output block, returning whether the output is complete.
+    fn append(&mut self, block: &[u8; DIGEST_LEN]) -> bool {
+        let len = core::cmp::min(DIGEST_LEN, self.output_len - self.produced);
+        self.output[self.produced..self.produced + len].copy_from_slice(&block[..len]);
+        self.produced += len;
+        self.produced == self.output_len
+    }
+
+    /// Write the secret XORed with `pad`, followed by `parts`, into `buf` as
+    /// the input of an HMAC hash. Returns the length of the input.
+    fn hmac_input(&self, buf: &mut [u8], pad: u8, parts: &[&[u8]]) -> usize {
+        for (b, s) in buf.iter_mut().zip(self.secret.iter()) {
+            *b = s ^ pad;
+        }
+        let mut len = HMAC_BLOCK_LEN;
+        for part in parts {
+            buf[len..len + part.len()].copy_from_slice(part);
+            len += part.len();
+        }
+        len
+    }
+}
+
+/// The values of a handshake, and the keys of the session it establishes.
+struct Handshake {
+    client_random: [u8; RANDOM_LEN],
+    server_random: [u8; RANDOM_LEN],
+    cookie: [u8; MAX_COOKIE_LEN],
+    cookie_len: usize,
+    prf: Prf,
+    master_secret: [u8; MASTER_SECRET_LEN],
+    key_block: [u8; KEY_BLOCK_LEN],
+    client_verify: [u8; VERIFY_DATA_LEN],
+    server_verify: [u8; VERIFY_DATA_LEN],
+}
+
+impl Handshake {
+    fn new() -> Handshake {
+        Handshake {
+            client_random: [0; RANDOM_LEN],
+            server_random: [0; RANDOM_LEN],
+            cookie: [0; MAX_COOKIE_LEN],
+            cookie_len: 0,
+            prf: Prf::new(),
+            master_secret: [0; MASTER_SECRET_LEN],
+            key_block: [0; KEY_BLOCK_LEN],
+            client_verify: [0; VERIFY_DATA_LEN],
+            server_verify: [0; VERIFY_DATA_LEN],
+        }
+    }
+
+    fn cookie(&self) -> &[u8] {
+        &self.cookie[..self.cookie_len]
+    }
+
+    /// The message_seq of the ClientHello. The ClientHello that answers a
+    /// HelloVerifyRequest is the second message of the client.
+    fn client_hello_seq(&self) -> u16 {
+        if self.cookie_len > 0 {
+            1
+        } else {
+            0
+        }
+    }
+
+    fn client_key(&self) -> &[u8] {
+        &self.key_block[..KEY_LEN]
+    }
+
+    fn server_key(&self) -> &[u8] {
+        &self.key_block[KEY_LEN..2 * KEY_LEN]
+    }
+
+    fn client_salt(&self) -> &[u8] {
+        &self.key_block[2 * KEY_LEN..2 * KEY_LEN + SALT_LEN]
+    }
+
+    fn server_salt(&self) -> &[u8] {
+        &self.key_block[2 * KEY_LEN + SALT_LEN..]
+    }
+}
+
+/// This function serializes a ClientHello offering only
+/// TLS_PSK_WITH_AES_128_CCM_8, without session ID or extensions.
+fn encode_client_hello(buf: &mut [u8], message_seq: u16, random: &[u8], cookie: &[u8]) -> SResult {
+    let body_len = 2 + RANDOM_LEN + 1 + 1 + cookie.len() + 2 + 2 + 1 + 1;
+    let header = HandshakeHeader::new(handshake_type::CLIENT_HELLO, body_len, message_seq);
+    let mut off = enc_consume!(buf; header; encode, 0);
+    off = enc_consume!(buf, off; encode_u16, DTLS_1_2);
+    off = enc_consume!(buf, off; encode_bytes, random);
+    off = enc_consume!(buf, off; encode_u8, 0);
+    off = enc_consume!(buf, off; encode_u8, cookie.len() as u8);
+    off = enc_consume!(buf, off; encode_bytes, cookie);
+    off = enc_consume!(buf, off; encode_u16, 2);
+    off = enc_consume!(buf, off; encode_u16, TLS_PSK_WITH_AES_128_CCM_8);
+    off = enc_consume!(buf, off; encode_u8, 1);
+    off = enc_consume!(buf, off; encode_u8, 0);
+    stream_done!(off);
+}
+
+/// This function serializes a ClientKeyExchange carrying the PSK identity.
+fn encode_client_key_exchange(buf: &mut [u8], message_seq: u16, identity: &[u8]) -> SResult {
+    let header = HandshakeHeader::new(
+        handshake_type::CLIENT_KEY_EXCHANGE,
+        2 + identity.len(),
+        message_seq,
+    );
+    let mut off = enc_consume!(buf; header; encode, 0);
+    off = enc_consume!(buf, off; encode_u16, identity.len() as u16);
+    off = enc_consume!(buf, off; encode_bytes, identity);
+    stream_done!(off);
+}
+
+/// This function serializes a Finished message.
+fn encode_finished(buf: &mut [u8], message_seq: u16, verify_data: &[u8]) -> SResult {
+    let header = HandshakeHeader::new(handshake_type::FINISHED, VERIFY_DATA_LEN, message_seq);
+    let mut off = enc_consume!(buf; header; encode, 0);
+    off = enc_consume!(buf, off; encode_bytes, verify_data);
+    stream_done!(off);
+}
+
+/// This function deserializes the cookie of a HelloVerifyRequest, returning
+/// its length.
+fn decode_hello_verify_request(buf: &[u8], cookie: &mut [u8; MAX_COOKIE_LEN]) -> SResult<usize> {
+    // The server version may be DTLS 1.0 here, and is ignored.
+    let (off, _) = dec_try!(buf; decode_u16);
+    let (off, len) = dec_try!(buf, off; decode_u8);
+    let len = len as usize;
+    stream_cond!(len <= MAX_COOKIE_LEN);
+    let off = dec_consume!(buf, off; decode_bytes, &mut cookie[..len]);
+    stream_done!(off, len);
+}
+
+/// This function deserializes the fields of a ServerHello that PSK key
+/// exchange uses, returning the cipher suite. Extensions are ignored.
+fn decode_server_hello(buf: &[u8], random: &mut [u8; RANDOM_LEN]) -> SResult<u16> {
+    let (off, version) = dec_try!(buf; decode_u16);
+    stream_cond!(version == DTLS_1_2);
+    let off = dec_consume!(buf, off; decode_bytes, random);
+    let (off, session_id_len) = dec_try!(buf, off; decode_u8);
+    let off = off + session_id_len as usize;
+    stream_len_cond!(buf, off);
+    let (off, cipher_suite) = dec_try!(buf, off; decode_u16);
+    let (off, compression) = dec_try!(buf, off; decode_u8);
+    stream_cond!(compression == 0);
+    stream_done!(off, cipher_suite);
+}
+
+/// Write the additional authenticated data of a record.
+fn encode_aad(buf: &mut [u8], epoch_seq: u64, content_type: u8, len: usize) {
+    buf[..8].copy_from_slice(&epoch_seq.to_be_bytes());
+    buf[8] = content_type;
+    buf[9..11].copy_from_slice(&DTLS_1_2.to_be_bytes());
+    buf[11..13].copy_from_slice(&(len as u16).to_be_bytes());
+}
+
+/// Write a record with the given header and body at `off`, returning the
+/// offset after it.
+fn encode_record(buf: &mut [u8], off: usize, header: RecordHeader, body: &[u8]) -> Option<usize> {
+    let (_, off) = header.encode(buf, off).done()?;
+    buf.get_mut(off..off + body.len())?.copy_from_slice(body);
+    Some(off + body.len())
+}
+
+pub struct DtlsSession<'a, A: Alarm<'a>, C: AES128CCM<'a>, D: digest::Digest<'a, DIGEST_LEN>> {
+    sender: &'a dyn UDPSender<'a>,
+    alarm: &'a A,
+    ccm: &'a C,
+    sha: &'a D,
+    rng: &'a dyn Random<'a>,
+    net_cap: &'static NetworkCapability,
+    client: OptionalCell<&'a dyn DtlsClient>,
+    psk_identity: &'static [u8],
+    psk: &'static [u8],
+
+    remote_addr: Cell<IPAddr>,
+    remote_port: Cell<u16>,
+    state: Cell<State>,
+    hs: MapCell<Handshake>,
+    derive: Cell<Derive>,
+    hash_step: Cell<HashStep>,
+    retransmissions: Cell<u32>,
+
+    /// The next message_seq expected from the server, once its ServerHello
+    /// was received.
+    rx_msg_seq: Cell<Option<u16>>,
+    /// The next record sequence number of epochs 0 and 1.
+    tx_seq: Cell<[u64; 2]>,
+    /// Whether the server's ChangeCipherSpec was received, after which its
+    /// records are encrypted.
+    peer_ccs: Cell<bool>,
+    /// The highest sequence number received from the server, and a bitmap of
+    /// the 64 sequence numbers up to it that were received.
+    replay_max: Cell<Option<u64>>,
+    replay_window: Cell<u64>,
+    crypt: Cell<Crypt>,
+    sending: Cell<Sending>,
+
+    tx_buffer: TakeCell<'static, [u8]>,
+    /// The additional authenticated data, plaintext and tag of the record
+    /// being encrypted or decrypted.
+    ccm_buffer: TakeCell<'static, [u8]>,
+    /// The handshake messages, for the Finished hashes.
+    transcript: TakeCell<'static, [u8]>,
+    transcript_len: Cell<usize>,
+    hash_buffer: TakeCell<'static, [u8]>,
+    digest: TakeCell<'static, [u8; DIGEST_LEN]>,
+}
+
+impl<'a, A: Alarm<'a>, C: AES128CCM<'a>, D: digest::Digest<'a, DIGEST_LEN>>
+    DtlsSession<'a, A, C, D>
+{
+    pub fn new(
+        sender: &'a dyn UDPSender<'a>,
+        alarm: &'a A,
+        ccm: &'a C,
+        sha: &'a D,
+        rng: &'a dyn Random<'a>,
+        net_cap: &'static NetworkCapability,
+        psk_identity: &'static [u8],
+        psk: &'static [u8],
+        tx_buffer: &'static mut [u8],
+        ccm_buffer: &'static mut [u8],
+        transcript: &'static mut [u8],
+        hash_buffer: &'static mut [u8],
+        digest: &'static mut [u8; DIGEST_LEN],
+    ) -> DtlsSession<'a, A, C, D> {
+        DtlsSession {
+            sender,
+            alarm,
+            ccm,
+            sha,
+            rng,
+            net_cap,
+            client: OptionalCell::empty(),
+            psk_identity,
+            psk,
+            remote_addr: Cell::new(IPAddr::new()),
+            remote_port: Cell::new(0),
+            state: Cell::new(State::Closed),
+            hs: MapCell::new(Handshake::new()),
+            derive: Cell::new(Derive::MasterSecret),
+            hash_step: Cell::new(HashStep::Transcript),
+            retransmissions: Cell::new(0),
+            rx_msg_seq: Cell::new(None),
+            tx_seq: Cell::new([0; 2]),
+            peer_ccs: Cell::new(false),
+            replay_max: Cell::new(None),
+            replay_window: Cell::new(0),
+            crypt: Cell::new(Crypt::Idle),
+            sending: Cell::new(Sending::Handshake),
+            tx_buffer: TakeCell::new(tx_buffer),
+            ccm_buffer: TakeCell::new(ccm_buffer),
+            transcript: TakeCell::new(transcript),
+            transcript_len: Cell::new(0),
+            hash_buffer: TakeCell::new(hash_buffer),
+            digest: TakeCell::new(digest),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn DtlsClient) {
+        self.client.set(client);
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.state.get() == State::Connected
+    }
+
+    /// Start a handshake with the server at `addr` and `port`. The result is
+    /// reported by `DtlsClient::connected`.
+    ///
+    /// Returns BUSY if the session is not closed or is still finishing an
+    /// operation of its previous connection, and INVAL if the PSK identity
+    /// or the PSK is too long or empty.
+    pub fn connect(&self, addr: IPAddr, port: u16) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Closed
+            || self.crypt.get() != Crypt::Idle
+            || self.tx_buffer.is_none()
+            || self.hash_buffer.is_none()
+            || self.transcript.is_none()
+            || self.digest.is_none()
+        {
+            return Err(ErrorCode::BUSY);
+        }
+        if self.psk.is_empty()
+            || self.psk.len() > MAX_PSK_LEN
+            || self.psk_identity.is_empty()
+            || self.psk_identity.len() > MAX_PSK_IDENTITY_LEN
+        {
+            return Err(ErrorCode::INVAL);
+        }
+
+        self.remote_addr.set(addr);
+        self.remote_port.set(port);
+        self.hs.map(|hs| {
+            for chunk in hs.client_random.chunks_mut(4) {
+                chunk.copy_from_slice(&self.rng.random().to_be_bytes());
+            }
+            hs.cookie_len = 0;
+        });
+        self.tx_seq.set([0; 2]);
+        self.peer_ccs.set(false);
+        self.replay_max.set(None);
+        self.replay_window.set(0);
+        self.retransmissions.set(0);
+        self.sha.clear_data();
+
+        self.state.set(State::Hello);
+        let result = self.send_client_hello();
+        if result.is_err() {
+            self.state.set(State::Closed);
+        }
+        result
+    }
+
+    /// Send `data` to the server in one record. The completion is reported
+    /// by `DtlsClient::send_done`.
+    ///
+    /// Returns INVAL if the session is not connected, SIZE if the data is
+    /// longer than `MAX_DATA_LEN`, and BUSY if a send is in progress.
+    pub fn send(&self, data: &[u8]) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Connected {
+            return Err(ErrorCode::INVAL);
+        }
+        if data.len() > MAX_DATA_LEN {
+            return Err(ErrorCode::SIZE);
+        }
+        if !self.tx_ready() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.ccm_buffer
+            .map(|buf| buf[AAD_LEN..AAD_LEN + data.len()].copy_from_slice(data));
+        self.encrypt(content_type::APPLICATION_DATA, data.len(), Crypt::Data)
+    }
+
+    /// Close the session. A connected session sends a close_notify alert to
+    /// the server, and `DtlsClient::closed` is called once it is sent. A
+    /// handshake in progress is abandoned without a callback.
+    ///
+    /// Returns ALREADY if the session is closed or closing, and BUSY if a
+    /// send is in progress.
+    pub fn close(&self) -> Result<(), ErrorCode> {
+        match self.state.get() {
+            State::Closed | State::Closing => Err(ErrorCode::ALREADY),
+            State::Connected => {
+                if !self.tx_ready() {
+                    return Err(ErrorCode::BUSY);
+                }
+                self.ccm_buffer.map(|buf| {
+                    buf[AAD_LEN] = alert::WARNING;
+                    buf[AAD_LEN + 1] = alert::CLOSE_NOTIFY;
+                });
+                self.encrypt(content_type::ALERT, 2, Crypt::Alert)?;
+                self.state.set(State::Closing);
+                Ok(())
+            }
+            State::Hello | State::Deriving | State::Finished => {
+                let _ = self.alarm.disarm();
+                self.state.set(State::Closed);
+                Ok(())
+            }
+        }
+    }
+
+    /// Whether a record can be encrypted and sent.
+    fn tx_ready(&self) -> bool {
+        self.crypt.get() == Crypt::Idle && self.tx_buffer.is_some() && self.ccm_buffer.is_some()
+    }
+
+    fn next_seq(&self, epoch: usize) -> u64 {
+        let mut seq = self.tx_seq.get();
+        let next = seq[epoch];
+        seq[epoch] += 1;
+        self.tx_seq.set(seq);
+        next
+    }
+
+    fn fail_handshake(&self, result: Result<(), ErrorCode>) {
+        let _ = self.alarm.disarm();
+        self.state.set(State::Closed);
+        self.client.map(|client| client.connected(result));
+    }
+
+    fn end_session(&self, result: Result<(), ErrorCode>) {
+        self.state.set(State::Closed);
+        self.client.map(|client| client.closed(result));
+    }
+
+    fn arm_retransmission(&self) {
+        let timeout = INITIAL_TIMEOUT_MS << self.retransmissions.get();
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(timeout));
+    }
+
+    fn send_datagram(&self, buf: &'static mut [u8], len: usize, sending: Sending) {
+        self.sending.set(sending);
+        let mut dgram = LeasableMutableBuffer::new(buf);
+        dgram.slice(0..len);
+        if let Err(mut dgram) = self.sender.send_to(
+            self.remote_addr.get(),
+            self.remote_port.get(),
+            dgram,
+            self.net_cap,
+        ) {
+            dgram.reset();
+            self.tx_buffer.replace(dgram.take());
+            self.sent(sending, Err(ErrorCode::FAIL));
+        }
+    }
+
+    /// Report that a datagram was sent. A handshake flight that failed to be
+    /// sent is sent again on the next retransmission.
+    fn sent(&self, sending: Sending, result: Result<(), ErrorCode>) {
+        match sending {
+            Sending::Handshake => {}
+            Sending::Data => {
+                self.client.map(|client| client.send_done(result));
+            }
+            Sending::Alert => self.end_session(Ok(())),
+        }
+    }
+
+    /// Send the ClientHello, starting the transcript over with it. The
+    /// server answers a retransmitted ClientHello with its whole flight, so
+    /// the messages of the server received so far are forgotten.
+    fn send_client_hello(&self) -> Result<(), ErrorCode> {
+        self.arm_retransmission();
+        let mut msg = [0; CLIENT_HELLO_MAX_LEN];
+        let len = self
+            .hs
+            .map(|hs| {
+                encode_client_hello(
+                    &mut msg,
+                    hs.client_hello_seq(),
+                    &hs.client_random,
+                    hs.cookie(),
+                )
+                .done()
+            })
+            .flatten()
+            .ok_or(ErrorCode::FAIL)?
+            .0;
+        self.rx_msg_seq.set(None);
+        self.transcript_len.set(0);
+        self.append_transcript(&msg[..len])?;
+
+        if !self.tx_ready() {
+            return Err(ErrorCode::BUSY);
+        }
+        let buf = self.tx_buffer.take().ok_or(ErrorCode::BUSY)?;
+        let header = RecordHeader::new(content_type::HANDSHAKE, 0, self.next_seq(0), len as u16);
+        match encode_record(buf, 0, header, &msg[..len]) {
+            Some(end) => {
+                self.send_datagram(buf, end, Sending::Handshake);
+                Ok(())
+            }
+            None => {
+                self.tx_buffer.replace(buf);
+                Err(ErrorCode::SIZE)
+            }
+        }
+    }
+
+    /// Send the final flight of the client: the ClientKeyExchange, the
+    /// ChangeCipherSpec and the encrypted Finished.
+    fn send_final_flight(&self) -> Result<(), ErrorCode> {
+        self.arm_retransmission();
+        if !self.tx_ready() {
+            return Err(ErrorCode::BUSY);
+        }
+        let (seq, verify_data) = self
+            .hs
+            .map(|hs| (hs.client_hello_seq(), hs.client_verify))
+            .ok_or(ErrorCode::FAIL)?;
+        let mut msg = [0; HANDSHAKE_HDR_LEN + 2 + MAX_PSK_IDENTITY_LEN];
+        let (len, _) = encode_client_key_exchange(&mut msg, seq + 1, self.psk_identity)
+            .done()
+            .ok_or(ErrorCode::FAIL)?;
+
+        let off = self
+            .tx_buffer
+            .map(|buf| {
+                let cke =
+                    RecordHeader::new(content_type::HANDSHAKE, 0, self.next_seq(0), len as u16);
+                let off = encode_record(buf, 0, cke, &msg[..len])?;
+                let ccs =
+                    RecordHeader::new(content_type::CHANGE_CIPHER_SPEC, 0, self.next_seq(0), 1);
+                encode_record(buf, off, ccs, &[1])
+            })
+            .flatten()
+            .ok_or(ErrorCode::SIZE)?;
+        self.ccm_buffer.map(|buf| {
+            let _ = encode_finished(&mut buf[AAD_LEN..], seq + 2, &verify_data);
+        });
+        self.encrypt(content_type::HANDSHAKE, FINISHED_LEN, Crypt::Finished(off))
+    }
+
+    /// Encrypt the `len` bytes of plaintext in the CCM buffer as a record of
+    /// epoch 1.
+    fn encrypt(&self, content_type: u8, len: usize, crypt: Crypt) -> Result<(), ErrorCode> {
+        let epoch_seq = 1 << 48 | self.next_seq(1);
+        let buf = self.ccm_buffer.take().ok_or(ErrorCode::BUSY)?;
+        encode_aad(buf, epoch_seq, content_type, len);
+        let result = self
+            .hs
+            .map(|hs| self.start_ccm(buf, hs.client_key(), hs.client_salt(), epoch_seq, len, true))
+            .unwrap_or(Err(ErrorCode::FAIL));
+        if result.is_ok() {
+            self.crypt.set(crypt);
+        }
+        result
+    }
+
+    /// Start decrypting a received record of epoch 1.
+    fn decrypt(&self, header: RecordHeader, body: &[u8]) {
+        if self.crypt.get() != Crypt::Idle
+            || body.len() < CIPHER_OVERHEAD
+            || body.len() - CIPHER_OVERHEAD > MAX_DATA_LEN
+            || !self.replay_fresh(header.seq_num)
+        {
+            return;
+        }
+        let Some(buf) = self.ccm_buffer.take() else {
+            return;
+        };
+        let len = body.len() - CIPHER_OVERHEAD;
+        let mut explicit_nonce = [0; EXPLICIT_NONCE_LEN];
+        explicit_nonce.copy_from_slice(&body[..EXPLICIT_NONCE_LEN]);
+        encode_aad(buf, header.get_epoch_seq(), header.content_type, len);
+        buf[AAD_LEN..AAD_LEN + len + CCM_8_TAG_LEN].copy_from_slice(&body[EXPLICIT_NONCE_LEN..]);
+        let result = self
+            .hs
+            .map(|hs| {
+                self.start_ccm(
+                    buf,
+                    hs.server_key(),
+                    hs.server_salt(),
+                    u64::from_be_bytes(explicit_nonce),
+                    len,
+                    false,
+                )
+            })
+            .unwrap_or(Err(ErrorCode::FAIL));
+        if result.is_ok() {
+            self.crypt.set(Crypt::Decrypt(header));
+        }
+    }
+
+    /// Start a CCM operation on `buf`, which holds the additional
+    /// authenticated data followed by `len` bytes of data and the tag. The
+    /// buffer is kept if the operation fails to start.
+    fn start_ccm(
+        &self,
+        buf: &'static mut [u8],
+        key: &[u8],
+        salt: &[u8],
+        explicit_nonce: u64,
+        len: usize,
+        encrypting: bool,
+    ) -> Result<(), ErrorCode> {
+        let mut nonce = [0; CCM_NONCE_LEN];
+        nonce[..SALT_LEN].copy_from_slice(salt);
+        nonce[SALT_LEN..].copy_from_slice(&explicit_nonce.to_be_bytes());
+        let result = self
+            .ccm
+            .set_key(key)
+            .and_then(|()| self.ccm.set_nonce(&nonce));
+        if let Err(e) = result {
+            self.ccm_buffer.replace(buf);
+            return Err(e);
+        }
+        self.ccm
+            .crypt(buf, 0, AAD_LEN, len, CCM_8_TAG_LEN, true, encrypting)
+            .map_err(|(e, buf)| {
+                self.ccm_buffer.replace(buf);
+                e
+            })
+    }
+
+    /// Whether a record with this sequence number was not received yet.
+    fn replay_fresh(&self, seq: u64) -> bool {
+        match self.replay_max.get() {
+            Some(max) if seq <= max => {
+                let age = max - seq;
+                age < 64 && self.replay_window.get() & (1 << age) == 0
+            }
+            _ => true,
+        }
+    }
+
+    /// Record that an authenticated record with this sequence number was
+    /// received.
+    fn replay_mark(&self, seq: u64) {
+        match self.replay_max.get() {
+            Some(max) if seq <= max => {
+                self.replay_window
+                    .set(self.replay_window.get() | 1 << (max - seq));
+            }
+            max => {
+                let shift = max.map_or(64, |max| seq - max);
+                let window = if shift >= 64 {
+                    0
+                } else {
+                    self.replay_window.get() << shift
+                };
+                self.replay_window.set(window | 1);
+                self.replay_max.set(Some(seq));
+            }
+        }
+    }
+
+    fn append_transcript(&self, msg: &[u8]) -> Result<(), ErrorCode> {
+        let len = self.transcript_len.get();
+        self.transcript
+            .map(|buf| {
+                buf.get_mut(len..len + msg.len())
+                    .map(|dest| dest.copy_from_slice(msg))
+            })
+            .flatten()
+            .ok_or(ErrorCode::NOMEM)?;
+        self.transcript_len.set(len + msg.len());
+        Ok(())
+    }
+
+    /// Handle the records of a datagram from the server. Handshake records
+    /// are not encrypted before the ChangeCipherSpec of the server.
+    fn receive_records(&self, mut records: &[u8]) {
+        while let Some((off, header)) = RecordHeader::decode(records).done() {
+            let Some(body) = records.get(off..off + header.length as usize) else {
+                return;
+            };
+            records = &records[off + body.len()..];
+            match header.epoch {
+                0 => self.receive_plaintext(header.content_type, body),
+                1 if self.peer_ccs.get() => self.decrypt(header, body),
+                _ => {}
+            }
+            if self.state.get() == State::Closed {
+                return;
+            }
+        }
+    }
+
+    fn receive_plaintext(&self, content_type: u8, body: &[u8]) {
+        match (self.state.get(), content_type) {
+            (State::Hello, content_type::HANDSHAKE) => {
+                let mut msgs = body;
+                while let Some((off, header)) = HandshakeHeader::decode(msgs).done() {
+                    let end = off + header.fragment_length as usize;
+                    let Some(msg) = msgs.get(..end) else {
+                        return;
+                    };
+                    if header.is_complete() {
+                        if let Err(e) = self.receive_handshake(header, msg, &msg[off..]) {
+                            self.fail_handshake(Err(e));
+                            return;
+                        }
+                    }
+                    if self.state.get() != State::Hello {
+                        return;
+                    }
+                    msgs = &msgs[end..];
+                }
+            }
+            (State::Finished, content_type::CHANGE_CIPHER_SPEC) => self.peer_ccs.set(true),
+            // Alerts are not authenticated before the handshake completes,
+            // so only a failed handshake is reported.
+            (State::Hello | State::Deriving | State::Finished, content_type::ALERT)
+                if body.first() == Some(&alert::FATAL) =>
+            {
+                self.fail_handshake(Err(ErrorCode::FAIL));
+            }
+            _ => {}
+        }
+    }
+
+    /// Handle a handshake message of the first flight of the server. Returns
+    /// an error if the handshake must be abandoned.
+    fn receive_handshake(
+        &self,
+        header: HandshakeHeader,
+        msg: &[u8],
+        body: &[u8],
+    ) -> Result<(), ErrorCode> {
+        let expected = self.rx_msg_seq.get();
+        match header.msg_type {
+            handshake_type::HELLO_VERIFY_REQUEST if expected.is_none() => {
+                self.hs
+                    .map(|hs| {
+                        decode_hello_verify_request(body, &mut hs.cookie)
+                            .done()
+                            .map(|(_, len)| hs.cookie_len = len)
+                    })
+                    .flatten()
+                    .ok_or(ErrorCode::FAIL)?;
+                self.retransmissions.set(0);
+                // The transmit buffer may still be in use, in which case the
+                // ClientHello is sent on the next retransmission.
+                match self.send_client_hello() {
+                    Err(ErrorCode::BUSY) => Ok(()),
+                    result => result,
+                }
+            }
+            handshake_type::SERVER_HELLO if expected.is_none() => {
+                let cipher_suite = self
+                    .hs
+                    .map(|hs| {
+                        decode_server_hello(body, &mut hs.server_random)
+                            .done()
+                            .map(|(_, suite)| suite)
+                    })
+                    .flatten()
+                    .ok_or(ErrorCode::FAIL)?;
+                if cipher_suite != TLS_PSK_WITH_AES_128_CCM_8 {
+                    return Err(ErrorCode::FAIL);
+                }
+                self.append_transcript(msg)?;
+                self.rx_msg_seq.set(Some(header.message_seq + 1));
+                Ok(())
+            }
+            // Retransmitted or out of order messages are dropped, and the
+            // server sends them again.
+            _ if expected != Some(header.message_seq) => Ok(()),
+            handshake_type::SERVER_KEY_EXCHANGE => {
+                // The PSK identity hint is not used.
+                self.append_transcript(msg)?;
+                self.rx_msg_seq.set(Some(header.message_seq + 1));
+                Ok(())
+            }
+            handshake_type::SERVER_HELLO_DONE => {
+                self.append_transcript(msg)?;
+                self.rx_msg_seq.set(Some(header.message_seq + 1));
+                self.start_deriving()
+            }
+            // Certificates are not supported.
+            _ => Err(ErrorCode::FAIL),
+        }
+    }
+
+    /// Start computing the master secret from the PSK, once the
+    /// ClientKeyExchange is added to the transcript.
+    fn start_deriving(&self) -> Result<(), ErrorCode> {
+        let _ = self.alarm.disarm();
+        let seq = self.hs.map_or(0, |hs| hs.client_hello_seq());
+        let mut msg = [0; HANDSHAKE_HDR_LEN + 2 + MAX_PSK_IDENTITY_LEN];
+        let (len, _) = encode_client_key_exchange(&mut msg, seq + 1, self.psk_identity)
+            .done()
+            .ok_or(ErrorCode::FAIL)?;
+        self.append_transcript(&msg[..len])?;
+
+        // The premaster secret of plain PSK is a string of zeros as long as
+        // the PSK, followed by the PSK, each with its 16-bit length.
+        let n = self.psk.len();
+        let mut premaster = [0; 2 * (2 + MAX_PSK_LEN)];
+        premaster[..2].copy_from_slice(&(n as u16).to_be_bytes());
+        premaster[2 + n..4 + n].copy_from_slice(&(n as u16).to_be_bytes());
+        premaster[4 + n..4 + 2 * n].copy_from_slice(self.psk);
+        self.hs.map(|hs| {
+            hs.prf.start(
+                &premaster[..4 + 2 * n],
+                b"master secret",
+                &[&hs.client_random[..], &hs.server_random[..]],
+                MASTER_SECRET_LEN,
+            )
+        });
+        self.state.set(State::Deriving);
+        self.derive.set(Derive::MasterSecret);
+        self.start_hash(HashStep::InnerA)
+    }
+
+    /// Start hashing the input of `step`.
+    fn start_hash(&self, step: HashStep) -> Result<(), ErrorCode> {
+        self.hash_step.set(step);
+        let (buf, len) = if step == HashStep::Transcript {
+            let buf = self.transcript.take().ok_or(ErrorCode::BUSY)?;
+            (buf, self.transcript_len.get())
+        } else {
+            let buf = self.hash_buffer.take().ok_or(ErrorCode::BUSY)?;
+            let len = self.hs.map_or(0, |hs| {
+                let prf = &hs.prf;
+                let a = prf.a.as_ref().map_or(&[][..], |a| &a[..]);
+                match step {
+                    HashStep::InnerA if prf.a.is_none() => prf.hmac_input(buf, IPAD, &[prf.seed()]),
+                    HashStep::InnerA => prf.hmac_input(buf, IPAD, &[a]),
+                    HashStep::InnerOutput => prf.hmac_input(buf, IPAD, &[a, prf.seed()]),
+                    _ => prf.hmac_input(buf, OPAD, &[&prf.inner]),
+                }
+            });
+            (buf, len)
+        };
+        let mut data = LeasableMutableBuffer::new(buf);
+        data.slice(0..len);
+        self.sha.add_mut_data(data).map_err(|(e, data)| {
+            self.return_hash_buffer(data.take());
+            e
+        })
+    }
+
+    fn return_hash_buffer(&self, buf: &'static mut [u8]) {
+        if self.hash_step.get() == HashStep::Transcript {
+            self.transcript.replace(buf);
+        } else {
+            self.hash_buffer.replace(buf);
+        }
+    }
+
+    /// Continue with the hash after `step`, whose result is `digest`.
+    fn hash_step_done(&self, step: HashStep, digest: &[u8; DIGEST_LEN]) -> Result<(), ErrorCode> {
+        let next = match step {
+            HashStep::Transcript => return self.derive_next(digest),
+            HashStep::InnerA | HashStep::InnerOutput => {
+                self.hs.map(|hs| hs.prf.inner = *digest);
+                if step == HashStep::InnerA {
+                    HashStep::OuterA
+                } else {
+                    HashStep::OuterOutput
+                }
+            }
+            HashStep::OuterA => {
+                self.hs.map(|hs| hs.prf.a = Some(*digest));
+                HashStep::InnerOutput
+            }
+            HashStep::OuterOutput => {
+                if self.hs.map_or(true, |hs| hs.prf.append(digest)) {
+                    return self.derive_next(digest);
+                }
+                HashStep::InnerA
+            }
+        };
+        self.start_hash(next)
+    }
+
+    /// Use the result of the current derivation, a PRF output or a
+    /// transcript hash, and start the next one. Once the verify data of both
+    /// Finished messages is known, the final flight is sent.
+    fn derive_next(&self, digest: &[u8; DIGEST_LEN]) -> Result<(), ErrorCode> {
+        let next = self
+            .hs
+            .map(|hs| match self.derive.get() {
+                Derive::MasterSecret => {
+                    hs.master_secret
+                        .copy_from_slice(&hs.prf.output[..MASTER_SECRET_LEN]);
+                    hs.prf.start(
+                        &hs.master_secret,
+                        b"key expansion",
+                        &[&hs.server_random[..], &hs.client_random[..]],
+                        KEY_BLOCK_LEN,
+                    );
+                    Some((Derive::KeyBlock, HashStep::InnerA))
+                }
+                Derive::KeyBlock => {
+                    hs.key_block
+                        .copy_from_slice(&hs.prf.output[..KEY_BLOCK_LEN]);
+                    Some((Derive::ClientFinishedHash, HashStep::Transcript))
+                }
+                Derive::ClientFinishedHash => {
+                    hs.prf.start(
+                        &hs.master_secret,
+                        b"client finished",
+                        &[&digest[..]],
+                        VERIFY_DATA_LEN,
+                    );
+                    Some((Derive::ClientVerifyData, HashStep::InnerA))
+                }
+                Derive::ClientVerifyData => {
+                    hs.client_verify
+                        .copy_from_slice(&hs.prf.output[..VERIFY_DATA_LEN]);
+                    Some((Derive::ServerFinishedHash, HashStep::Transcript))
+                }
+                Derive::ServerFinishedHash => {
+                    hs.prf.start(
+                        &hs.master_secret,
+                        b"server finished",
+                        &[&digest[..]],
+                        VERIFY_DATA_LEN,
+                    );
+                    Some((Derive::ServerVerifyData, HashStep::InnerA))
+                }
+                Derive::ServerVerifyData => {
+                    hs.server_verify
+                        .copy_from_slice(&hs.prf.output[..VERIFY_DATA_LEN]);
+                    None
+                }
+            })
+            .ok_or(ErrorCode::FAIL)?;
+
+        let Some((next, step)) = next else {
+            self.state.set(State::Finished);
+            self.retransmissions.set(0);
+            // The flight is sent on the first retransmission if the transmit
+            // buffer is in use.
+            return match self.send_final_flight() {
+                Err(ErrorCode::BUSY) => Ok(()),
+                result => result,
+            };
+        };
+        if next == Derive::ServerFinishedHash {
+            // The server Finished covers the client Finished.
+            let (seq, verify_data) = self
+                .hs
+                .map(|hs| (hs.client_hello_seq(), hs.client_verify))
+                .ok_or(ErrorCode::FAIL)?;
+            let mut msg = [0; FINISHED_LEN];
+            let _ = encode_finished(&mut msg, seq + 2, &verify_data);
+            self.append_transcript(&msg)?;
+        }
+        self.derive.set(next);
+        self.start_hash(step)
+    }
+
+    /// Handle a decrypted record.
+    fn receive_decrypted(&self, content_type: u8, data: &[u8]) {
+        match (self.state.get(), content_type) {
+            (State::Finished, content_type::HANDSHAKE) => {
+                let Some((off, header)) = HandshakeHeader::decode(data).done() else {
+                    return;
+                };
+                if header.msg_type != handshake_type::FINISHED || !header.is_complete() {
+                    return;
+                }
+                let verified = self
+                    .hs
+                    .map_or(false, |hs| data.get(off..) == Some(&hs.server_verify[..]));
+                let _ = self.alarm.disarm();
+                if verified {
+                    self.state.set(State::Connected);
+                    self.client.map(|client| client.connected(Ok(())));
+                } else {
+                    self.fail_handshake(Err(ErrorCode::FAIL));
+                }
+            }
+            (State::Connected, content_type::APPLICATION_DATA) => {
+                self.client.map(|client| client.receive(data));
+            }
+            (_, content_type::ALERT) => {
+                let (level, description) = match data {
+                    [level, description] => (*level, *description),
+                    _ => return,
+                };
+                let result = if description == alert::CLOSE_NOTIFY {
+                    Ok(())
+                } else if level == alert::FATAL {
+                    Err(ErrorCode::FAIL)
+                } else {
+                    return;
+                };
+                match self.state.get() {
+                    State::Finished => self.fail_handshake(Err(ErrorCode::FAIL)),
+                    State::Connected => self.end_session(result),
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, C: AES128CCM<'a>, D: digest::Digest<'a, DIGEST_LEN>> UDPRecvClient
+    for DtlsSession<'a, A, C, D>
+{
+    fn receive(
+        &self,
+        src_addr: IPAddr,
+        _dst_addr: IPAddr,
+        src_port: u16,
+        _dst_port: u16,
+        payload: &[u8],
+    ) {
+        if self.state.get() != State::Closed
+            && src_addr == self.remote_addr.get()
+            && src_port == self.remote_port.get()
+        {
+            self.receive_records(payload);
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, C: AES128CCM<'a>, D: digest::Digest<'a, DIGEST_LEN>> UDPSendClient
+    for DtlsSession<'a, A, C, D>
+{
+    fn send_done(
+        &self,
+        result: Result<(), ErrorCode>,
+        mut dgram: LeasableMutableBuffer<'static, u8>,
+    ) {
+        dgram.reset();
+        self.tx_buffer.replace(dgram.take());
+        self.sent(self.sending.get(), result);
+    }
+}
+
+impl<'a, A: Alarm<'a>, C: AES128CCM<'a>, D: digest::Digest<'a, DIGEST_LEN>> CCMClient
+    for DtlsSession<'a, A, C, D>
+{
+    fn crypt_done(&self, buf: &'static mut [u8], res: Result<(), ErrorCode>, tag_is_valid: bool) {
+        let crypt = self.crypt.replace(Crypt::Idle);
+        if let Crypt::Decrypt(header) = crypt {
+            // The data is copied out of the buffer so that the client can
+            // send from its callbacks.
+            let len = header.length as usize - CIPHER_OVERHEAD;
+            let mut data = [0; MAX_DATA_LEN];
+            data[..len].copy_from_slice(&buf[AAD_LEN..AAD_LEN + len]);
+            self.ccm_buffer.replace(buf);
+            if res.is_ok() && tag_is_valid && self.replay_fresh(header.seq_num) {
+                self.replay_mark(header.seq_num);
+                self.receive_decrypted(header.content_type, &data[..len]);
+            }
+            return;
+        }
+        if self.state.get() == State::Closed {
+            // The handshake was abandoned while its Finished was encrypted.
+            self.ccm_buffer.replace(buf);
+            return;
+        }
+
+        // Encryption: send the record after the start of the datagram.
+        let (start, sending) = match crypt {
+            Crypt::Finished(off) => (off, Sending::Handshake),
+            Crypt::Data => (0, Sending::Data),
+            _ => (0, Sending::Alert),
+        };
+        let mut epoch_seq = [0; 8];
+        epoch_seq.copy_from_slice(&buf[..8]);
+        let content_type = buf[8];
+        let len = u16::from_be_bytes([buf[11], buf[12]]) as usize;
+        let header = RecordHeader::new(
+            content_type,
+            1,
+            u64::from_be_bytes(epoch_seq),
+            (len + CIPHER_OVERHEAD) as u16,
+        );
+        let end = self.tx_buffer.map(|tx| {
+            let (_, off) = header.encode(tx, start).done()?;
+            tx.get_mut(off..off + EXPLICIT_NONCE_LEN)?
+                .copy_from_slice(&epoch_seq);
+            let off = off + EXPLICIT_NONCE_LEN;
+            tx.get_mut(off..off + len + CCM_8_TAG_LEN)?
+                .copy_from_slice(&buf[AAD_LEN..AAD_LEN + len + CCM_8_TAG_LEN]);
+            Some(off + len + CCM_8_TAG_LEN)
+        });
+        self.ccm_buffer.replace(buf);
+
+        match (res, end.flatten(), self.tx_buffer.take()) {
+            (Ok(()), Some(end), Some(tx)) => self.send_datagram(tx, end, sending),
+            (_, _, tx) => {
+                if let Some(tx) = tx {
+                    self.tx_buffer.replace(tx);
+                }
+                self.sent(sending, Err(ErrorCode::FAIL));
+            }
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, C: AES128CCM<'a>, D: digest::Digest<'a, DIGEST_LEN>> time::AlarmClient
+    for DtlsSession<'a, A, C, D>
+{
+    fn alarm(&self) {
+        let retransmit: fn(&Self) -> Result<(), ErrorCode> = match self.state.get() {
+            State::Hello => Self::send_client_hello,
+            State::Finished => Self::send_final_flight,
+            _ => return,
+        };
+        if self.retransmissions.get() >= MAX_RETRANSMISSIONS {
+            self.fail_handshake(Err(ErrorCode::NOACK));
+            return;
+        }
+        self.retransmissions.set(self.retransmissions.get() + 1);
+        // A busy transmit buffer delays the flight to the next
+        // retransmission, which is armed regardless.
+        match retransmit(self) {
+            Ok(()) | Err(ErrorCode::BUSY) => {}
+            Err(e) => self.fail_handshake(Err(e)),
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, C: AES128CCM<'a>, D: digest::Digest<'a, DIGEST_LEN>>
+    digest::ClientData<DIGEST_LEN> for DtlsSession<'a, A, C, D>
+{
+    fn add_data_done(&self, _result: Result<(), ErrorCode>, _data: LeasableBuffer<'static, u8>) {}
+
+    fn add_mut_data_done(
+        &self,
+        result: Result<(), ErrorCode>,
+        mut data: LeasableMutableBuffer<'static, u8>,
+    ) {
+        data.reset();
+        self.return_hash_buffer(data.take());
+        if self.state.get() != State::Deriving {
+            return;
+        }
+        let result = result.and_then(|()| {
+            let digest = self.digest.take().ok_or(ErrorCode::BUSY)?;
+            self.sha.run(digest).map_err(|(e, digest)| {
+                self.digest.replace(digest);
+                e
+            })
+        });
+        if let Err(e) = result {
+            self.fail_handshake(Err(e));
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, C: AES128CCM<'a>, D: digest::Digest<'a, DIGEST_LEN>>
+    digest::ClientHash<DIGEST_LEN> for DtlsSession<'a, A, C, D>
+{
+    fn hash_done(&self, result: Result<(), ErrorCode>, digest: &'static mut [u8; DIGEST_LEN]) {
+        let value = *digest;
+        self.digest.replace(digest);
+        if self.state.get() != State::Deriving {
+            return;
+        }
+        if let Err(e) = result.and_then(|()| self.hash_step_done(self.hash_step.get(), &value)) {
+            self.fail_handshake(Err(e));
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, C: AES128CCM<'a>, D: digest::Digest<'a, DIGEST_LEN>>
+    digest::ClientVerify<DIGEST_LEN> for DtlsSession<'a, A, C, D>
+{
+    fn verification_done(
+        &self,
+        _result: Result<bool, ErrorCode>,
+        compare: &'static mut [u8; DIGEST_LEN],
+    ) {
+        self.digest.replace(compare);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::sha256::Sha256Software;
+    use crate::sha_software::from_hex;
+    use std::boxed::Box;
+    use std::vec;
+
+    /// Compute HMAC(secret, parts) with the inner and outer hashes that the
+    /// session would request.
+    fn hmac(sha: &Sha256Software, prf: &Prf, parts: &[&[u8]]) -> [u8; DIGEST_LEN] {
+        let buf = Box::leak(vec![0; HASH_BUF_LEN].into_boxed_slice());
+        let len = prf.hmac_input(buf, IPAD, parts);
+        let inner = sha.digest_in_chunks(&buf[..len]);
+        let buf = Box::leak(vec![0; HASH_BUF_LEN].into_boxed_slice());
+        let len = prf.hmac_input(buf, OPAD, &[&inner]);
+        sha.digest_in_chunks(&buf[..len])
+    }
+
+    /// Run a PRF computation through the steps of `HashStep`.
+    fn run(prf: &mut Prf) {
+        let sha = Sha256Software::new();
+        loop {
+            let a = match prf.a {
+                None => hmac(&sha, prf, &[prf.seed()]),
+                Some(a) => hmac(&sha, prf, &[&a]),
+            };
+            prf.a = Some(a);
+            let block = hmac(&sha, prf, &[&a, prf.seed()]);
+            if prf.append(&block) {
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn prf_sha256() {
+        // The TLS 1.2 PRF test vector for SHA-256 published on the IETF TLS
+        // mailing list, truncated to the size of a master secret.
+        let secret: [u8; 16] = from_hex("9bbe436ba940f017b17652849a71db35");
+        let seed: [u8; 16] = from_hex("a0ba9f936cda311827a6f796ffd5198c");
+        let expected: [u8; MASTER_SECRET_LEN] = from_hex(
+            "e3f229ba727be17b8d122620557cd453c2aab21d07c3d495329b52d4e61edb5a\
+             6b301791e90d35c9c9a46b4e14baf9af",
+        );
+
+        let mut prf = Prf::new();
+        prf.start(&secret, b"test label", &[&seed], MASTER_SECRET_LEN);
+        run(&mut prf);
+        assert_eq!(prf.output, expected);
+
+        // Shorter outputs are prefixes, whatever the previous computation.
+        prf.start(
+            &secret,
+            b"test label",
+            &[&seed[..8], &seed[8..]],
+            VERIFY_DATA_LEN,
+        );
+        run(&mut prf);
+        assert_eq!(prf.output[..VERIFY_DATA_LEN], expected[..VERIFY_DATA_LEN]);
+    }
+
+    #[test]
+    fn client_hello() {
+        let mut buf = [0; CLIENT_HELLO_MAX_LEN];
+        let len = match encode_client_hello(&mut buf, 1, &[0x11; RANDOM_LEN], &[1, 2, 3]) {
+            SResult::Done(len, ()) => len,
+            _ => panic!("failed to encode ClientHello"),
+        };
+        assert_eq!(len, HANDSHAKE_HDR_LEN + 45);
+        assert_eq!(buf[..12], [1, 0, 0, 45, 0, 1, 0, 0, 0, 0, 0, 45]);
+        assert_eq!(buf[12..14], [0xfe, 0xfd]);
+        assert_eq!(buf[14..46], [0x11; RANDOM_LEN]);
+        // No session ID, the cookie, one cipher suite and no compression.
+        assert_eq!(buf[46..len], [0, 3, 1, 2, 3, 0, 2, 0xc0, 0xa8, 1, 0]);
+    }
+
+    #[test]
+    fn hello_verify_request() {
+        let mut cookie = [0; MAX_COOKIE_LEN];
+        match decode_hello_verify_request(&[0xfe, 0xff, 2, 0xab, 0xcd], &mut cookie) {
+            SResult::Done(5, 2) => assert_eq!(cookie[..2], [0xab, 0xcd]),
+            _ => panic!("failed to decode HelloVerifyRequest"),
+        }
+
+        let mut long = [0; 3 + MAX_COOKIE_LEN + 1];
+        long[..3].copy_from_slice(&[0xfe, 0xfd, MAX_COOKIE_LEN as u8 + 1]);
+        match decode_hello_verify_request(&long, &mut cookie) {
+            SResult::Error(()) => {}
+            _ => panic!("accepted a cookie longer than MAX_COOKIE_LEN"),
+        }
+    }
+
+    #[test]
+    fn server_hello() {
+        let mut hello = [0; 2 + RANDOM_LEN + 1 + 4 + 3];
+        hello[..2].copy_from_slice(&DTLS_1_2.to_be_bytes());
+        hello[2..34].copy_from_slice(&[0x22; RANDOM_LEN]);
+        // A four-byte session ID, which the client ignores.
+        hello[34..39].copy_from_slice(&[4, 9, 9, 9, 9]);
+        hello[39..].copy_from_slice(&[0xc0, 0xa8, 0]);
+
+        let mut random = [0; RANDOM_LEN];
+        match decode_server_hello(&hello, &mut random) {
+            SResult::Done(_, suite) => assert_eq!(suite, TLS_PSK_WITH_AES_128_CCM_8),
+            _ => panic!("failed to decode ServerHello"),
+        }
+        assert_eq!(random, [0x22; RANDOM_LEN]);
+
+        // Compression is not supported.
+        hello[41] = 1;
+        match decode_server_hello(&hello, &mut random) {
+            SResult::Error(()) => {}
+            _ => panic!("accepted a compression method"),
+        }
+    }
+
+    #[test]
+    fn additional_data() {
+        let mut aad = [0; AAD_LEN];
+        encode_aad(
+            &mut aad,
+            0x0001_0000_0000_0007,
+            content_type::APPLICATION_DATA,
+            5,
+        );
+        assert_eq!(aad, [0, 1, 0, 0, 0, 0, 0, 7, 23, 0xfe, 0xfd, 0, 5]);
+    }
+}
@@ -23,10 +23,53 @@ pub struct ICMP6Header {
 
 #[derive(Copy, Clone)]
 pub enum ICMP6HeaderOptions {
-    Type1 { unused: u32 },
-    Type3 { unused: u32 },
-    Type128 { id: u16, seqno: u16 },
-    Type129 { id: u16, seqno: u16 },
+    Type1 {
+        unused: u32,
+    },
+    Type3 {
+        unused: u32,
+    },
+    Type128 {
+        id: u16,
+        seqno: u16,
+    },
+    Type129 {
+        id: u16,
+        seqno: u16,
+    },
+    /// Multicast Listener Discovery messages (RFC 2710), which are followed
+    /// by a multicast address.
+    Type130 {
+        max_delay: u16,
+        reserved: u16,
+    },
+    Type131 {
+        max_delay: u16,
+        reserved: u16,
+    },
+    Type132 {
+        max_delay: u16,
+        reserved: u16,
+    },
+    Type133 {
+        reserved: u32,
+    },
+    Type134 {
+        cur_hop_limit: u8,
+        flags: u8,
+        router_lifetime: u16,
+    },
+    Type135 {
+        reserved: u32,
+    },
+    Type136 {
+        flags: u32,
+    },
+    /// The first word of the base of an RPL control message, which depends
+    /// on the code of the message.
+    Type155 {
+        base: u32,
+    },
 }
 
 #[derive(Copy, Clone)]
@@ -35,6 +78,14 @@ pub enum ICMP6Type {
     Type3,   // Time Exceeded
     Type128, // Echo Request
     Type129, // Echo Reply
+    Type130, // Multicast Listener Query
+    Type131, // Multicast Listener Report
+    Type132, // Multicast Listener Done
+    Type133, // Router Solicitation
+    Type134, // Router Advertisement
+    Type135, // Neighbor Solicitation
+    Type136, // Neighbor Advertisement
+    Type155, // RPL Control Message
 }
 
 impl ICMP6Header {
@@ -44,6 +95,27 @@ impl ICMP6Header {
             ICMP6Type::Type3 => ICMP6HeaderOptions::Type3 { unused: 0 },
             ICMP6Type::Type128 => ICMP6HeaderOptions::Type128 { id: 0, seqno: 0 },
             ICMP6Type::Type129 => ICMP6HeaderOptions::Type129 { id: 0, seqno: 0 },
+            ICMP6Type::Type130 => ICMP6HeaderOptions::Type130 {
+                max_delay: 0,
+                reserved: 0,
+            },
+            ICMP6Type::Type131 => ICMP6HeaderOptions::Type131 {
+                max_delay: 0,
+                reserved: 0,
+            },
+            ICMP6Type::Type132 => ICMP6HeaderOptions::Type132 {
+                max_delay: 0,
+                reserved: 0,
+            },
+            ICMP6Type::Type133 => ICMP6HeaderOptions::Type133 { reserved: 0 },
+            ICMP6Type::Type134 => ICMP6HeaderOptions::Type134 {
+                cur_hop_limit: 0,
+                flags: 0,
+                router_lifetime: 0,
+            },
+            ICMP6Type::Type135 => ICMP6HeaderOptions::Type135 { reserved: 0 },
+            ICMP6Type::Type136 => ICMP6HeaderOptions::Type136 { flags: 0 },
+            ICMP6Type::Type155 => ICMP6HeaderOptions::Type155 { base: 0 },
         };
 
         ICMP6Header {
@@ -55,12 +127,7 @@ impl ICMP6Header {
     }
 
     pub fn set_type(&mut self, icmp_type: ICMP6Type) {
-        match icmp_type {
-            ICMP6Type::Type1 => self.set_options(ICMP6HeaderOptions::Type1 { unused: 0 }),
-            ICMP6Type::Type3 => self.set_options(ICMP6HeaderOptions::Type3 { unused: 0 }),
-            ICMP6Type::Type128 => self.set_options(ICMP6HeaderOptions::Type128 { id: 0, seqno: 0 }),
-            ICMP6Type::Type129 => self.set_options(ICMP6HeaderOptions::Type129 { id: 0, seqno: 0 }),
-        }
+        self.set_options(Self::new(icmp_type).options);
     }
 
     pub fn set_code(&mut self, code: u8) {
@@ -85,6 +152,14 @@ impl ICMP6Header {
             ICMP6HeaderOptions::Type3 { .. } => ICMP6Type::Type3,
             ICMP6HeaderOptions::Type128 { .. } => ICMP6Type::Type128,
             ICMP6HeaderOptions::Type129 { .. } => ICMP6Type::Type129,
+            ICMP6HeaderOptions::Type130 { .. } => ICMP6Type::Type130,
+            ICMP6HeaderOptions::Type131 { .. } => ICMP6Type::Type131,
+            ICMP6HeaderOptions::Type132 { .. } => ICMP6Type::Type132,
+            ICMP6HeaderOptions::Type133 { .. } => ICMP6Type::Type133,
+            ICMP6HeaderOptions::Type134 { .. } => ICMP6Type::Type134,
+            ICMP6HeaderOptions::Type135 { .. } => ICMP6Type::Type135,
+            ICMP6HeaderOptions::Type136 { .. } => ICMP6Type::Type136,
+            ICMP6HeaderOptions::Type155 { .. } => ICMP6Type::Type155,
         }
     }
 
@@ -94,6 +169,14 @@ impl ICMP6Header {
             ICMP6Type::Type3 => 3,
             ICMP6Type::Type128 => 128,
             ICMP6Type::Type129 => 129,
+            ICMP6Type::Type130 => 130,
+            ICMP6Type::Type131 => 131,
+            ICMP6Type::Type132 => 132,
+            ICMP6Type::Type133 => 133,
+            ICMP6Type::Type134 => 134,
+            ICMP6Type::Type135 => 135,
+            ICMP6Type::Type136 => 136,
+            ICMP6Type::Type155 => 155,
         }
     }
 
@@ -136,14 +219,43 @@ impl ICMP6Header {
         off = enc_consume!(buf, off; encode_u16, self.cksum);
 
         match self.options {
-            ICMP6HeaderOptions::Type1 { unused } | ICMP6HeaderOptions::Type3 { unused } => {
-                off = enc_consume!(buf, off; encode_u32, unused);
+            ICMP6HeaderOptions::Type1 { unused: word }
+            | ICMP6HeaderOptions::Type3 { unused: word }
+            | ICMP6HeaderOptions::Type133 { reserved: word }
+            | ICMP6HeaderOptions::Type135 { reserved: word }
+            | ICMP6HeaderOptions::Type136 { flags: word }
+            | ICMP6HeaderOptions::Type155 { base: word } => {
+                off = enc_consume!(buf, off; encode_u32, word);
             }
             ICMP6HeaderOptions::Type128 { id, seqno }
             | ICMP6HeaderOptions::Type129 { id, seqno } => {
                 off = enc_consume!(buf, off; encode_u16, id);
                 off = enc_consume!(buf, off; encode_u16, seqno);
             }
+            ICMP6HeaderOptions::Type130 {
+                max_delay,
+                reserved,
+            }
+            | ICMP6HeaderOptions::Type131 {
+                max_delay,
+                reserved,
+            }
+            | ICMP6HeaderOptions::Type132 {
+                max_delay,
+                reserved,
+            } => {
+                off = enc_consume!(buf, off; encode_u16, max_delay);
+                off = enc_consume!(buf, off; encode_u16, reserved);
+            }
+            ICMP6HeaderOptions::Type134 {
+                cur_hop_limit,
+                flags,
+                router_lifetime,
+            } => {
+                off = enc_consume!(buf, off; encode_u8, cur_hop_limit);
+                off = enc_consume!(buf, off; encode_u8, flags);
+                off = enc_consume!(buf, off; encode_u16, router_lifetime);
+            }
         }
 
         stream_done!(off, off);
@@ -167,6 +279,14 @@ impl ICMP6Header {
             3 => ICMP6Type::Type3,
             128 => ICMP6Type::Type128,
             129 => ICMP6Type::Type129,
+            130 => ICMP6Type::Type130,
+            131 => ICMP6Type::Type131,
+            132 => ICMP6Type::Type132,
+            133 => ICMP6Type::Type133,
+            134 => ICMP6Type::Type134,
+            135 => ICMP6Type::Type135,
+            136 => ICMP6Type::Type136,
+            155 => ICMP6Type::Type155,
             _ => return SResult::Error(()),
         };
 
@@ -175,34 +295,43 @@ impl ICMP6Header {
         let (off, code) = dec_try!(buf, off; decode_u8);
         icmp_header.set_code(code);
         let (off, cksum) = dec_try!(buf, off; decode_u16);
-        icmp_header.set_cksum(u16::from_be(cksum));
+        icmp_header.set_cksum(cksum);
 
-        match icmp_type {
-            ICMP6Type::Type1 => {
-                let (_off, unused) = dec_try!(buf, off; decode_u32);
-                let unused = u32::from_be(unused);
-                icmp_header.set_options(ICMP6HeaderOptions::Type1 { unused });
-            }
-            ICMP6Type::Type3 => {
-                let (_off, unused) = dec_try!(buf, off; decode_u32);
-                let unused = u32::from_be(unused);
-                icmp_header.set_options(ICMP6HeaderOptions::Type3 { unused });
-            }
-            ICMP6Type::Type128 => {
-                let (_off, id) = dec_try!(buf, off; decode_u16);
-                let id = u16::from_be(id);
-                let (_off, seqno) = dec_try!(buf, off; decode_u16);
-                let seqno = u16::from_be(seqno);
-                icmp_header.set_options(ICMP6HeaderOptions::Type128 { id, seqno });
-            }
-            ICMP6Type::Type129 => {
-                let (_off, id) = dec_try!(buf, off; decode_u16);
-                let id = u16::from_be(id);
-                let (_off, seqno) = dec_try!(buf, off; decode_u16);
-                let seqno = u16::from_be(seqno);
-                icmp_header.set_options(ICMP6HeaderOptions::Type129 { id, seqno });
-            }
-        }
+        // The second word of the header holds the type-specific fields
+        let (off, word) = dec_try!(buf, off; decode_u32);
+        icmp_header.set_options(match icmp_type {
+            ICMP6Type::Type1 => ICMP6HeaderOptions::Type1 { unused: word },
+            ICMP6Type::Type3 => ICMP6HeaderOptions::Type3 { unused: word },
+            ICMP6Type::Type128 => ICMP6HeaderOptions::Type128 {
+                id: (word >> 16) as u16,
+                seqno: word as u16,
+            },
+            ICMP6Type::Type129 => ICMP6HeaderOptions::Type129 {
+                id: (word >> 16) as u16,
+                seqno: word as u16,
+            },
+            ICMP6Type::Type130 => ICMP6HeaderOptions::Type130 {
+                max_delay: (word >> 16) as u16,
+                reserved: word as u16,
+            },
+            ICMP6Type::Type131 => ICMP6HeaderOptions::Type131 {
+                max_delay: (word >> 16) as u16,
+                reserved: word as u16,
+            },
+            ICMP6Type::Type132 => ICMP6HeaderOptions::Type132 {
+                max_delay: (word >> 16) as u16,
+                reserved: word as u16,
+            },
+            ICMP6Type::Type133 => ICMP6HeaderOptions::Type133 { reserved: word },
+            ICMP6Type::Type134 => ICMP6HeaderOptions::Type134 {
+                cur_hop_limit: (word >> 24) as u8,
+                flags: (word >> 16) as u8,
+                router_lifetime: word as u16,
+            },
+            ICMP6Type::Type135 => ICMP6HeaderOptions::Type135 { reserved: word },
+            ICMP6Type::Type136 => ICMP6HeaderOptions::Type136 { flags: word },
+            ICMP6Type::Type155 => ICMP6HeaderOptions::Type155 { base: word },
+        });
 
         stream_done!(off, icmp_header);
     }
@@ -11,6 +11,7 @@
 //!
 //! - Author: Conor McAvity <cmcavity@stanford.edu>
 
+use crate::net::buffer::PacketBuffer;
 use crate::net::icmpv6::ICMP6Header;
 use crate::net::ipv6::ip_utils::IPAddr;
 use crate::net::ipv6::ipv6_send::{IP6SendClient, IP6Sender};
@@ -18,7 +19,6 @@ use crate::net::ipv6::TransportHeader;
 use crate::net::network_capabilities::NetworkCapability;
 
 use kernel::utilities::cells::OptionalCell;
-use kernel::utilities::leasable_buffer::LeasableMutableBuffer;
 use kernel::ErrorCode;
 
 /// A trait for a client of an `ICMP6Sender`.
@@ -91,19 +91,16 @@ impl<'a, T: IP6Sender<'a>> ICMP6Sender<'a> for ICMP6SendStruct<'a, T> {
         let total_len = buf.len() + icmp_header.get_hdr_size();
         icmp_header.set_len(total_len as u16);
         let transport_header = TransportHeader::ICMP(icmp_header);
-        self.ip_send_struct.send_to(
-            dest,
-            transport_header,
-            &LeasableMutableBuffer::new(buf),
-            net_cap,
-        )
+        self.ip_send_struct
+            .send_to(dest, transport_header, PacketBuffer::new(buf), net_cap)
+            .map_err(|(ecode, _)| ecode)
     }
 }
 
 impl<'a, T: IP6Sender<'a>> IP6SendClient for ICMP6SendStruct<'a, T> {
     /// Forwards callback received from the `IP6Sender` to the
     /// `ICMP6SendClient`.
-    fn send_done(&self, result: Result<(), ErrorCode>) {
+    fn send_done(&self, result: Result<(), ErrorCode>, _payload: PacketBuffer) {
         self.client.map(|client| client.send_done(result));
     }
 }
@@ -311,7 +311,7 @@ impl Security {
         let asn_in_nonce = (scf & security_control::ASN_IN_NONCE) != 0;
 
         // Frame counter field
-        let frame_counter_present = (scf & security_control::FRAME_COUNTER_SUPPRESSION) != 0;
+        let frame_counter_present = (scf & security_control::FRAME_COUNTER_SUPPRESSION) == 0;
         let (off, frame_counter) = if frame_counter_present {
             let (off, frame_counter_be) = dec_try!(buf, off; decode_u32);
             (off, Some(u32::from_be(frame_counter_be)))
@@ -861,3 +861,63 @@ impl Header<'_> {
         stream_done!(off, (dst_pan, dst_addr, src_pan, src_addr));
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn security_frame_counter_present() {
+        // Security level 5, key identifier mode 1, frame counter 0x01020304
+        // (little endian on the air), key index 1.
+        let buf = [0x0d, 0x04, 0x03, 0x02, 0x01, 0x01];
+        match Security::decode(&buf) {
+            SResult::Done(off, security) => {
+                assert_eq!(off, buf.len());
+                assert_eq!(security.level, SecurityLevel::EncMic32);
+                assert_eq!(security.frame_counter, Some(0x01020304));
+                assert_eq!(security.key_id, KeyId::Index(1));
+            }
+            _ => panic!("failed to decode the auxiliary security header"),
+        }
+    }
+
+    #[test]
+    fn security_frame_counter_suppressed() {
+        // As above, with the frame counter suppression bit set and the
+        // frame counter left out.
+        let buf = [0x2d, 0x01];
+        match Security::decode(&buf) {
+            SResult::Done(off, security) => {
+                assert_eq!(off, buf.len());
+                assert_eq!(security.frame_counter, None);
+                assert_eq!(security.key_id, KeyId::Index(1));
+            }
+            _ => panic!("failed to decode the auxiliary security header"),
+        }
+    }
+
+    #[test]
+    fn security_round_trip() {
+        for frame_counter in [Some(0xdeadbeef), None] {
+            let security = Security {
+                level: SecurityLevel::EncMic64,
+                asn_in_nonce: false,
+                frame_counter,
+                key_id: KeyId::Source4Index([1, 2, 3, 4], 5),
+            };
+            let mut buf = [0; 16];
+            let len = match security.encode(&mut buf) {
+                SResult::Done(len, ()) => len,
+                _ => panic!("failed to encode the auxiliary security header"),
+            };
+            match Security::decode(&buf[..len]) {
+                SResult::Done(off, decoded) => {
+                    assert_eq!(off, len);
+                    assert_eq!(decoded, security);
+                }
+                _ => panic!("failed to decode the auxiliary security header"),
+            }
+        }
+    }
+}
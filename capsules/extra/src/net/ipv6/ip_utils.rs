@@ -6,9 +6,10 @@
 //! of the IP stack. Note that this file also contains the definition for the
 //! [IPAddr](struct.IPAddr.html) struct and associated helper functions.
 
-use crate::net::icmpv6::{ICMP6Header, ICMP6HeaderOptions};
+use crate::net::icmpv6::ICMP6Header;
 use crate::net::ieee802154::MacAddress;
-use crate::net::ipv6::IP6Header;
+use crate::net::ipv6::{IP6Header, ICMP_HDR_LEN};
+use crate::net::tcp::{TCPHeader, TCP_HDR_LEN};
 use crate::net::udp::UDPHeader;
 
 #[derive(Copy, Clone, PartialEq)]
@@ -30,6 +31,9 @@ pub mod ip6_nh {
     pub const MOBILITY: u8 = 135;
 }
 
+/// The EtherType of IPv6 packets sent over Ethernet (RFC 2464).
+pub const ETHERTYPE_IPV6: u16 = 0x86dd;
+
 #[derive(Copy, Clone, Debug)]
 pub struct IPAddr(pub [u8; 16]);
 
@@ -184,6 +188,43 @@ pub fn compute_udp_checksum(
     sum as u16 //Return result as u16 in host byte order */
 }
 
+/// Computes the TCP checksum of a segment, including the IPv6 pseudo-header.
+/// The checksum field of `tcp_header` is included in the sum, so it should be
+/// zero when computing the checksum of an outgoing segment. For a received
+/// segment, the result is zero if its checksum is correct. `payload` holds
+/// everything following the bare 20-byte header. The result is in host byte
+/// order.
+pub fn compute_tcp_checksum(ip6_header: &IP6Header, tcp_header: &TCPHeader, payload: &[u8]) -> u16 {
+    let mut sum: u32 = 0;
+
+    // Pseudo-header: addresses, upper-layer length and next header
+    for addr in [&ip6_header.src_addr, &ip6_header.dst_addr] {
+        for word in addr.0.chunks(2) {
+            sum += u16::from_be_bytes([word[0], word[1]]) as u32;
+        }
+    }
+    sum += tcp_header.get_len() as u32;
+    sum += ip6_nh::TCP as u32;
+
+    // TCP header, as it is put on the wire
+    let mut header = [0; TCP_HDR_LEN];
+    let _ = tcp_header.encode(&mut header, 0);
+    for word in header.chunks(2) {
+        sum += u16::from_be_bytes([word[0], word[1]]) as u32;
+    }
+
+    // Payload, padded with a zero byte if its length is odd
+    let payload_len = tcp_header.get_len() as usize - tcp_header.get_hdr_size();
+    for word in payload[..payload_len].chunks(2) {
+        sum += u16::from_be_bytes([word[0], *word.get(1).unwrap_or(&0)]) as u32;
+    }
+
+    while sum > 0xffff {
+        sum = (sum >> 16) + (sum & 0xffff);
+    }
+    !(sum as u16)
+}
+
 pub fn compute_icmp_checksum(
     ipv6_header: &IP6Header,
     icmp_header: &ICMP6Header,
@@ -194,22 +235,10 @@ pub fn compute_icmp_checksum(
     // add ipv6 pseudo-header
     sum += compute_ipv6_ph_sum(ipv6_header);
 
-    // add type and code
-    let msb = (icmp_header.get_type_as_int() as u32) << 8;
-    let lsb = icmp_header.get_code() as u32;
-    sum += msb + lsb;
-
-    // add options
-    match icmp_header.get_options() {
-        ICMP6HeaderOptions::Type1 { unused } | ICMP6HeaderOptions::Type3 { unused } => {
-            sum += unused >> 16; // upper 16 bits
-            sum += unused & 0xffff; // lower 16 bits
-        }
-        ICMP6HeaderOptions::Type128 { id, seqno } | ICMP6HeaderOptions::Type129 { id, seqno } => {
-            sum += id as u32;
-            sum += seqno as u32;
-        }
-    }
+    // add icmp header, as it is put on the wire
+    let mut header = [0; ICMP_HDR_LEN];
+    let _ = icmp_header.encode(&mut header, 0);
+    sum += compute_sum(&header, ICMP_HDR_LEN as u16);
 
     // add icmp payload
     let payload_len = icmp_header.get_len() - icmp_header.get_hdr_size() as u16;
@@ -217,15 +246,9 @@ pub fn compute_icmp_checksum(
 
     // carry overflow
     while sum > 0xffff {
-        let sum_upper = sum >> 16;
-        let sum_lower = sum & 0xffff;
-        sum += sum_upper + sum_lower;
+        sum = (sum >> 16) + (sum & 0xffff);
     }
-
-    sum = !sum;
-    sum = sum & 0xffff;
-
-    sum as u16
+    !(sum as u16)
 }
 
 pub fn compute_ipv6_ph_sum(ip6_header: &IP6Header) -> u32 {
@@ -245,7 +268,7 @@ pub fn compute_ipv6_ph_sum(ip6_header: &IP6Header) -> u32 {
         i += 2;
     }
 
-    sum += ip6_header.payload_len as u32;
+    sum += ip6_header.get_payload_len() as u32;
     sum += ip6_header.next_header as u32;
 
     sum
@@ -257,7 +280,12 @@ pub fn compute_sum(buf: &[u8], len: u16) -> u32 {
     let mut i: usize = 0;
     while i < (len as usize) {
         let msb = (buf[i] as u32) << 8;
-        let lsb = buf[i + 1] as u32;
+        // An odd trailing byte is padded with zero
+        let lsb = if i + 1 < len as usize {
+            buf[i + 1] as u32
+        } else {
+            0
+        };
         sum += msb + lsb;
         i += 2;
     }
@@ -71,11 +71,13 @@
 // (as required by 6LoWPAN) difficult.
 
 use crate::net::icmpv6::ICMP6Header;
-use crate::net::ipv6::ip_utils::{compute_icmp_checksum, compute_udp_checksum, ip6_nh, IPAddr};
+use crate::net::ipv6::ip_utils::{
+    compute_icmp_checksum, compute_tcp_checksum, compute_udp_checksum, ip6_nh, IPAddr,
+};
 use crate::net::stream::SResult;
 use crate::net::stream::{decode_bytes, decode_u16, decode_u8};
 use crate::net::stream::{encode_bytes, encode_u16, encode_u8};
-use crate::net::tcp::TCPHeader;
+use crate::net::tcp::{TCPHeader, TCP_HDR_LEN};
 use crate::net::udp::UDPHeader;
 
 use kernel::utilities::leasable_buffer::LeasableMutableBuffer;
@@ -286,13 +288,10 @@ impl IP6Header {
                 Ok(())
             }
             ip6_nh::ICMP => {
-                // Untested (10/5/18)
-                let mut icmp_header: [u8; ICMP_HDR_LEN] = [0; ICMP_HDR_LEN];
-                icmp_header.copy_from_slice(&buf[..ICMP_HDR_LEN]);
-                let checksum = match ICMP6Header::decode(&icmp_header).done() {
+                let checksum = match ICMP6Header::decode(buf).done() {
                     Some((_offset, mut hdr)) => {
                         hdr.set_len(buf.len() as u16);
-                        u16::from_be(compute_icmp_checksum(&self, &hdr, &buf[ICMP_HDR_LEN..]))
+                        compute_icmp_checksum(self, &hdr, &buf[ICMP_HDR_LEN..])
                     }
                     None => 0xffff, //Will be dropped, as ones comp -0 checksum is invalid
                 };
@@ -301,6 +300,16 @@ impl IP6Header {
                 }
                 Ok(())
             }
+            ip6_nh::TCP => {
+                let checksum = match TCPHeader::decode(buf).done() {
+                    Some((_offset, hdr)) => compute_tcp_checksum(self, &hdr, &buf[TCP_HDR_LEN..]),
+                    None => 0xffff, //Will be dropped, as ones comp -0 checksum is invalid
+                };
+                if checksum != 0 {
+                    return Err(ErrorCode::FAIL); //Incorrect cksum
+                }
+                Ok(())
+            }
             _ => Err(ErrorCode::NOSUPPORT),
         }
     }
@@ -361,20 +370,46 @@ impl<'a> IPPayload<'a> {
         for i in 0..payload.len() {
             self.payload[i] = payload[i];
         }
+        self.set_header(transport_header, payload.len())
+    }
+
+    /// This function sets the `TransportHeader` for the `IPPayload`, for a
+    /// payload of `payload_len` bytes that is already in place.
+    ///
+    /// # Arguments
+    ///
+    /// `transport_header` - The new `TransportHeader` header for the payload
+    /// `payload_len` - The length of the transport payload
+    ///
+    /// # Return Value
+    ///
+    /// `(u8, u16)` - Returns a tuple of the `ip6_nh` type of the
+    /// `transport_header` and the total length of the `IPPayload`
+    /// (when serialized)
+    pub fn set_header(
+        &mut self,
+        transport_header: TransportHeader,
+        payload_len: usize,
+    ) -> (u8, u16) {
         match transport_header {
             TransportHeader::UDP(mut udp_header) => {
-                let length = (payload.len() + udp_header.get_hdr_size()) as u16;
+                let length = (payload_len + udp_header.get_hdr_size()) as u16;
                 udp_header.set_len(length);
-                self.header = transport_header;
+                self.header = TransportHeader::UDP(udp_header);
                 (ip6_nh::UDP, length)
             }
             TransportHeader::ICMP(mut icmp_header) => {
-                let length = (payload.len() + icmp_header.get_hdr_size()) as u16;
+                let length = (payload_len + icmp_header.get_hdr_size()) as u16;
                 icmp_header.set_len(length);
-                self.header = transport_header;
+                self.header = TransportHeader::ICMP(icmp_header);
                 (ip6_nh::ICMP, length)
             }
-            _ => (ip6_nh::NO_NEXT, payload.len() as u16),
+            TransportHeader::TCP(mut tcp_header) => {
+                let length = (payload_len + tcp_header.get_hdr_size()) as u16;
+                tcp_header.set_len(length);
+                self.header = TransportHeader::TCP(tcp_header);
+                (ip6_nh::TCP, length)
+            }
         }
     }
 
@@ -393,9 +428,7 @@ impl<'a> IPPayload<'a> {
         let (offset, _) = match self.header {
             TransportHeader::UDP(udp_header) => udp_header.encode(buf, offset).done().unwrap(),
             TransportHeader::ICMP(icmp_header) => icmp_header.encode(buf, offset).done().unwrap(),
-            _ => {
-                unimplemented!();
-            }
+            TransportHeader::TCP(tcp_header) => tcp_header.encode(buf, offset).done().unwrap(),
         };
         let payload_length = self.get_payload_length();
         let offset = enc_consume!(buf, offset; encode_bytes, &self.payload[..payload_length]);
@@ -410,8 +443,8 @@ impl<'a> IPPayload<'a> {
             TransportHeader::ICMP(icmp_header) => {
                 icmp_header.get_len() as usize - icmp_header.get_hdr_size()
             }
-            _ => {
-                unimplemented!();
+            TransportHeader::TCP(tcp_header) => {
+                tcp_header.get_len() as usize - tcp_header.get_hdr_size()
             }
         }
     }
@@ -443,6 +476,32 @@ impl<'a> IP6Packet<'a> {
         }
     }
 
+    /// This function returns a new `IP6Packet` struct whose transport
+    /// payload is all of `payload`, in place rather than copied. The lengths
+    /// and the next header of `header` and `transport_header` are set to
+    /// match.
+    ///
+    /// # Arguments
+    ///
+    /// `header` - The `IP6Header` for the `IP6Packet`
+    /// `transport_header` - The `TransportHeader` for the payload
+    /// `payload` - The transport payload
+    pub fn with_payload(
+        header: IP6Header,
+        transport_header: TransportHeader,
+        payload: &'a mut [u8],
+    ) -> IP6Packet<'a> {
+        let payload_len = payload.len();
+        let mut ip6_packet = IP6Packet {
+            header: header,
+            payload: IPPayload::new(transport_header, payload),
+        };
+        let (next_header, len) = ip6_packet.payload.set_header(transport_header, payload_len);
+        ip6_packet.header.set_next_header(next_header);
+        ip6_packet.header.set_payload_len(len);
+        ip6_packet
+    }
+
     pub fn reset(&mut self) {
         self.header = IP6Header::default();
     }
@@ -459,7 +518,7 @@ impl<'a> IP6Packet<'a> {
         let transport_hdr_size = match self.payload.header {
             TransportHeader::UDP(udp_hdr) => udp_hdr.get_hdr_size(),
             TransportHeader::ICMP(icmp_header) => icmp_header.get_hdr_size(),
-            _ => unimplemented!(),
+            TransportHeader::TCP(tcp_header) => tcp_header.get_hdr_size(),
         };
         40 + transport_hdr_size
     }
@@ -482,11 +541,14 @@ impl<'a> IP6Packet<'a> {
                 udp_header.set_cksum(cksum);
             }
             TransportHeader::ICMP(ref mut icmp_header) => {
+                icmp_header.set_cksum(0);
                 let cksum = compute_icmp_checksum(&self.header, &icmp_header, self.payload.payload);
                 icmp_header.set_cksum(cksum);
             }
-            _ => {
-                unimplemented!();
+            TransportHeader::TCP(ref mut tcp_header) => {
+                tcp_header.set_cksum(0);
+                let cksum = compute_tcp_checksum(&self.header, tcp_header, self.payload.payload);
+                tcp_header.set_cksum(cksum);
             }
         }
     }
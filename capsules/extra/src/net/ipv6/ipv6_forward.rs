@@ -0,0 +1,125 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Forwarding of IPv6 packets, for nodes that route the packets of others.
+//!
+//! `IP6Forwarder` is the forward client of the IPv6 receiver, so it receives
+//! the unicast packets addressed to other nodes. It sends them on through the
+//! UDP send mux, keeping their IPv6 header, and the `Router` of the IPv6
+//! sender, such as `Rpl`, chooses their next hop. The hop limit of each
+//! packet is decremented, and packets whose hop limit runs out are dropped
+//! without an ICMPv6 Time Exceeded message.
+//!
+//! Packets are copied into a single buffer, so a packet that arrives while
+//! another one is being forwarded is dropped, as are packets larger than the
+//! buffer. If it fits, the payload is copied behind the headroom an Ethernet
+//! sender needs to prepend its headers in place. The IPv6 sender rebuilds the transport header of each packet, so
+//! only packets carrying UDP, TCP or the ICMPv6 messages the stack knows are
+//! forwarded; packets with extension headers are dropped.
+
+use crate::net::icmpv6::ICMP6Header;
+use crate::net::ipv6::ip_utils::ip6_nh;
+use crate::net::ipv6::ipv6_recv::IP6RecvClient;
+use crate::net::ipv6::ipv6_send;
+use crate::net::ipv6::{IP6Header, TransportHeader};
+use crate::net::network_capabilities::NetworkCapability;
+use crate::net::tcp::TCPHeader;
+use crate::net::udp::udp_send::{UDPSendClient, UDPSender};
+use crate::net::udp::UDPHeader;
+
+use kernel::utilities::cells::MapCell;
+use kernel::utilities::leasable_buffer::LeasableMutableBuffer;
+use kernel::ErrorCode;
+
+pub struct IP6Forwarder<'a> {
+    /// Sender of forwarded packets, through the UDP send mux.
+    sender: &'a dyn UDPSender<'a>,
+    net_cap: &'static NetworkCapability,
+    buffer: MapCell<LeasableMutableBuffer<'static, u8>>,
+}
+
+impl<'a> IP6Forwarder<'a> {
+    pub fn new(
+        sender: &'a dyn UDPSender<'a>,
+        net_cap: &'static NetworkCapability,
+        buffer: LeasableMutableBuffer<'static, u8>,
+    ) -> IP6Forwarder<'a> {
+        IP6Forwarder {
+            sender,
+            net_cap,
+            buffer: MapCell::new(buffer),
+        }
+    }
+
+    /// The transport header of a packet, and the offset of its payload.
+    fn decode_transport(
+        &self,
+        next_header: u8,
+        payload: &[u8],
+    ) -> Option<(TransportHeader, usize)> {
+        match next_header {
+            ip6_nh::UDP => UDPHeader::decode(payload)
+                .done()
+                .map(|(offset, udp_header)| (TransportHeader::UDP(udp_header), offset)),
+            ip6_nh::TCP => TCPHeader::decode(payload)
+                .done()
+                .map(|(offset, tcp_header)| (TransportHeader::TCP(tcp_header), offset)),
+            ip6_nh::ICMP => ICMP6Header::decode(payload)
+                .done()
+                .map(|(offset, icmp_header)| (TransportHeader::ICMP(icmp_header), offset)),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> IP6RecvClient for IP6Forwarder<'a> {
+    fn receive(&self, mut header: IP6Header, payload: &[u8]) {
+        let hop_limit = header.get_hop_limit();
+        if hop_limit <= 1 {
+            return;
+        }
+        let (transport_header, offset) =
+            match self.decode_transport(header.get_next_header(), payload) {
+                Some(transport) => transport,
+                None => return,
+            };
+        let body = &payload[offset..];
+
+        // Drop the packet while another one is being forwarded
+        let mut buf = match self.buffer.take() {
+            Some(buf) => buf,
+            None => return,
+        };
+        if body.len() > buf.len() {
+            self.buffer.replace(buf);
+            return;
+        }
+        let headroom = match ipv6_send::eth_headroom(&transport_header) {
+            headroom if headroom + body.len() <= buf.len() => headroom,
+            _ => 0,
+        };
+        buf[headroom..headroom + body.len()].copy_from_slice(body);
+        buf.slice(headroom..headroom + body.len());
+
+        header.set_hop_limit(hop_limit - 1);
+        if let Err(mut buf) = self
+            .sender
+            .forward(header, transport_header, buf, self.net_cap)
+        {
+            buf.reset();
+            self.buffer.replace(buf);
+        }
+    }
+}
+
+impl<'a> UDPSendClient for IP6Forwarder<'a> {
+    fn send_done(
+        &self,
+        _result: Result<(), ErrorCode>,
+        mut dgram: LeasableMutableBuffer<'static, u8>,
+    ) {
+        dgram.reset();
+        self.buffer.replace(dgram);
+    }
+}
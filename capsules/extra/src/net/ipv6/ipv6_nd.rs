@@ -0,0 +1,728 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! IPv6 Neighbor Discovery (RFC 4861) for hosts.
+//!
+//! `NeighborDiscovery` keeps a neighbor cache mapping IPv6 addresses to
+//! link-layer addresses, and is the `NeighborResolver` of the IPv6 sender:
+//! every packet is sent to the link-layer address of its next hop rather than
+//! to a fixed gateway MAC address. The next hop of an on-link destination is
+//! the destination itself, and that of any other destination is the default
+//! router.
+//!
+//! Messages are sent through the UDP send mux, which passes packets of any
+//! transport protocol to the shared IPv6 sender, and the IPv6 receiver passes
+//! all received ICMPv6 messages to this capsule. The implementation follows
+//! what a host on a 6LoWPAN network needs:
+//!
+//! - At start, the capsule sends up to `MAX_RTR_SOLICITATIONS` Router
+//!   Solicitations. Router Advertisements set the default router and the
+//!   on-link prefixes, and addresses are autoconfigured from the prefixes
+//!   with the autonomous flag (see `slaac`). Router lifetimes and prefix lifetimes are not timed:
+//!   a router advertising a lifetime of zero stops being the default router,
+//!   and a prefix with a valid lifetime of zero is removed.
+//! - Neighbor Solicitations for one of the interface addresses are answered
+//!   with a Neighbor Advertisement, and the link-layer address of the sender
+//!   is cached.
+//! - Link-local addresses are formed from the MAC address on 6LoWPAN (RFC
+//!   4944, section 7), so uncached link-local neighbors are resolved from
+//!   their address. Other on-link addresses are resolved with up to
+//!   `MAX_MULTICAST_SOLICIT` Neighbor Solicitations; until an answer arrives,
+//!   packets to them are sent to the gateway.
+//! - Cache entries do not expire and there is no reachability detection. When
+//!   the cache is full, the least recently used entry is replaced.
+//! - Link-layer address options use the IEEE 802.15.4 format of RFC 4944,
+//!   section 8. Duplicate Address Detection and redirects are not supported.
+
+use crate::net::icmpv6::{ICMP6Header, ICMP6HeaderOptions, ICMP6Type};
+use crate::net::ieee802154::MacAddress;
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::ipv6::ipv6_recv::IP6RecvClient;
+use crate::net::ipv6::ipv6_send::NeighborResolver;
+use crate::net::ipv6::slaac::InterfaceList;
+use crate::net::ipv6::{IP6Header, TransportHeader, ICMP_HDR_LEN};
+use crate::net::network_capabilities::NetworkCapability;
+use crate::net::udp::udp_send::{UDPSendClient, UDPSender};
+
+use core::cell::Cell;
+
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil::time::{self, Alarm, ConvertTicks};
+use kernel::utilities::cells::{MapCell, OptionalCell};
+use kernel::utilities::leasable_buffer::LeasableMutableBuffer;
+use kernel::ErrorCode;
+
+/// The number of entries of the neighbor cache.
+pub const NEIGHBOR_CACHE_SIZE: usize = 8;
+
+/// The size of the buffer messages are built in: a target address and a
+/// link-layer address option with an extended address.
+pub const TX_BUF_LEN: usize = 16 + 16;
+
+/// The number of on-link prefixes learned from Router Advertisements.
+const MAX_ON_LINK_PREFIXES: usize = 2;
+
+/// Granularity of the retransmission timers, RETRANS_TIMER of RFC 4861.
+const TICK_MS: u32 = 1000;
+/// The number of Neighbor Solicitations sent for an address before giving up.
+const MAX_MULTICAST_SOLICIT: u8 = 3;
+/// The number of Router Solicitations sent at start.
+const MAX_RTR_SOLICITATIONS: u8 = 3;
+/// Seconds between two Router Solicitations.
+const RTR_SOLICITATION_INTERVAL: u8 = 4;
+
+/// Hop limit of all Neighbor Discovery messages. Receivers check it to make
+/// sure that a message was not forwarded by a router.
+const ND_HOP_LIMIT: u8 = 255;
+
+/// All-routers multicast address, ff02::2.
+const ALL_ROUTERS: IPAddr = IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2]);
+/// All-nodes multicast address, ff02::1.
+const ALL_NODES: IPAddr = IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+
+/// Neighbor Discovery option types.
+mod nd_option {
+    pub const SOURCE_LL_ADDR: u8 = 1;
+    pub const TARGET_LL_ADDR: u8 = 2;
+    pub const PREFIX_INFO: u8 = 3;
+}
+
+/// Flags of Neighbor Advertisements.
+mod na_flags {
+    pub const ROUTER: u32 = 1 << 31;
+    pub const SOLICITED: u32 = 1 << 30;
+    pub const OVERRIDE: u32 = 1 << 29;
+}
+
+/// Flags of the Prefix Information option.
+mod prefix_flags {
+    pub const ON_LINK: u8 = 0x80;
+    pub const AUTONOMOUS: u8 = 0x40;
+}
+
+/// The solicited-node multicast address of `addr`, ff02::1:ffXX:XXXX, which
+/// Neighbor Solicitations are sent to.
+fn solicited_node(addr: IPAddr) -> IPAddr {
+    let mut group = IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0xff, 0, 0, 0]);
+    group.0[13..].copy_from_slice(&addr.0[13..]);
+    group
+}
+
+/// The MAC address a link-local address was formed from, the inverse of
+/// `IPAddr::generate_from_mac`.
+fn mac_from_link_local(addr: IPAddr) -> MacAddress {
+    let iid = &addr.0[8..];
+    if iid[..6] == [0, 0, 0, 0xff, 0xfe, 0] {
+        MacAddress::Short(u16::from_be_bytes([iid[6], iid[7]]))
+    } else {
+        let mut long_addr = [0; 8];
+        long_addr.copy_from_slice(iid);
+        long_addr[0] ^= 0b00000010;
+        MacAddress::Long(long_addr)
+    }
+}
+
+/// Whether the first `prefix_len` bits of `addr` and `prefix` are equal.
+fn matches_prefix(addr: IPAddr, prefix: IPAddr, prefix_len: u8) -> bool {
+    let mut masked = prefix;
+    masked.set_prefix(&addr.0, prefix_len);
+    masked == prefix
+}
+
+/// Write a source or target link-layer address option for `mac_addr` to the
+/// start of `buf`, and return its length.
+fn encode_ll_addr_option(buf: &mut [u8], option_type: u8, mac_addr: MacAddress) -> usize {
+    // The address is padded with zeros to a multiple of 8 bytes
+    let len = match mac_addr {
+        MacAddress::Short(short_addr) => {
+            buf[2..4].copy_from_slice(&short_addr.to_be_bytes());
+            buf[4..8].fill(0);
+            8
+        }
+        MacAddress::Long(long_addr) => {
+            buf[2..10].copy_from_slice(&long_addr);
+            buf[10..16].fill(0);
+            16
+        }
+    };
+    buf[0] = option_type;
+    buf[1] = (len / 8) as u8;
+    len
+}
+
+/// Read the address of a source or target link-layer address option.
+fn decode_ll_addr_option(option: &[u8]) -> Option<MacAddress> {
+    match option[1] {
+        1 => Some(MacAddress::Short(u16::from_be_bytes([
+            option[2], option[3],
+        ]))),
+        2 => {
+            let mut long_addr = [0; 8];
+            long_addr.copy_from_slice(&option[2..10]);
+            Some(MacAddress::Long(long_addr))
+        }
+        _ => None,
+    }
+}
+
+/// Iterator over the options at the end of a Neighbor Discovery message,
+/// which yields the type and the bytes of each option.
+struct NdOptions<'b>(&'b [u8]);
+
+impl<'b> NdOptions<'b> {
+    /// Returns `None` if an option is truncated or has a length of zero, in
+    /// which case the whole message must be discarded.
+    fn new(buf: &'b [u8]) -> Option<NdOptions<'b>> {
+        let mut rest = buf;
+        while !rest.is_empty() {
+            let len = 8 * *rest.get(1)? as usize;
+            if len == 0 || len > rest.len() {
+                return None;
+            }
+            rest = &rest[len..];
+        }
+        Some(NdOptions(buf))
+    }
+}
+
+impl<'b> Iterator for NdOptions<'b> {
+    type Item = (u8, &'b [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let len = 8 * *self.0.get(1)? as usize;
+        let (option, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some((option[0], option))
+    }
+}
+
+/// An entry of the neighbor cache.
+pub struct Neighbor {
+    /// Address of the neighbor, empty if the entry is free.
+    ip_addr: OptionalCell<IPAddr>,
+    /// Link-layer address of the neighbor, empty while it is being resolved.
+    mac_addr: OptionalCell<MacAddress>,
+    is_router: Cell<bool>,
+    /// A Neighbor Solicitation has to be sent.
+    solicit: Cell<bool>,
+    /// The number of Neighbor Solicitations sent while resolving.
+    solicits: Cell<u8>,
+    /// Value of the cache clock when the entry was last used.
+    last_used: Cell<u32>,
+}
+
+impl Default for Neighbor {
+    fn default() -> Neighbor {
+        Neighbor {
+            ip_addr: OptionalCell::empty(),
+            mac_addr: OptionalCell::empty(),
+            is_router: Cell::new(false),
+            solicit: Cell::new(false),
+            solicits: Cell::new(0),
+            last_used: Cell::new(0),
+        }
+    }
+}
+
+impl Neighbor {
+    pub fn new() -> Neighbor {
+        Neighbor::default()
+    }
+
+    fn is_resolving(&self) -> bool {
+        self.ip_addr.is_some() && self.mac_addr.is_none()
+    }
+
+    fn free(&self) {
+        self.ip_addr.clear();
+        self.mac_addr.clear();
+        self.is_router.set(false);
+        self.solicit.set(false);
+        self.solicits.set(0);
+    }
+}
+
+/// A Neighbor Advertisement to send.
+#[derive(Copy, Clone)]
+struct Advert {
+    dst: IPAddr,
+    target: IPAddr,
+    flags: u32,
+}
+
+pub struct NeighborDiscovery<'a, A: Alarm<'a>> {
+    /// Sender of messages, through the UDP send mux.
+    sender: &'a dyn UDPSender<'a>,
+
+    /// Alarm driving the solicitation retransmissions.
+    alarm: &'a A,
+
+    net_cap: &'static NetworkCapability,
+
+    /// Addresses of this node, which it answers solicitations for and
+    /// autoconfigures from advertised prefixes.
+    interface_list: &'a InterfaceList,
+
+    neighbors: &'a [Neighbor],
+
+    /// Incremented each time a neighbor is used, to find the least recently
+    /// used entry.
+    clock: Cell<u32>,
+
+    /// On-link prefixes and their lengths.
+    prefixes: [OptionalCell<(IPAddr, u8)>; MAX_ON_LINK_PREFIXES],
+
+    default_router: OptionalCell<IPAddr>,
+
+    kernel_buffer: MapCell<LeasableMutableBuffer<'static, u8>>,
+
+    /// Neighbor Advertisement to send in reply to a solicitation.
+    pending_advert: OptionalCell<Advert>,
+
+    /// A Router Solicitation has to be sent.
+    solicit_router: Cell<bool>,
+    /// The number of Router Solicitations left to send.
+    router_solicits: Cell<u8>,
+    /// Seconds until the next Router Solicitation.
+    router_timer: Cell<u8>,
+
+    /// Messages are sent from a deferred call, as solicitations are queued
+    /// while the IPv6 sender resolves the next hop of a packet.
+    deferred_call: DeferredCall,
+}
+
+impl<'a, A: Alarm<'a>> NeighborDiscovery<'a, A> {
+    pub fn new(
+        sender: &'a dyn UDPSender<'a>,
+        alarm: &'a A,
+        net_cap: &'static NetworkCapability,
+        interface_list: &'a InterfaceList,
+        neighbors: &'a [Neighbor],
+        kernel_buffer: LeasableMutableBuffer<'static, u8>,
+    ) -> NeighborDiscovery<'a, A> {
+        NeighborDiscovery {
+            sender,
+            alarm,
+            net_cap,
+            interface_list,
+            neighbors,
+            clock: Cell::new(0),
+            prefixes: core::array::from_fn(|_| OptionalCell::empty()),
+            default_router: OptionalCell::empty(),
+            kernel_buffer: MapCell::new(kernel_buffer),
+            pending_advert: OptionalCell::empty(),
+            solicit_router: Cell::new(false),
+            router_solicits: Cell::new(0),
+            router_timer: Cell::new(0),
+            deferred_call: DeferredCall::new(),
+        }
+    }
+
+    /// Start soliciting routers, to learn the default router and the on-link
+    /// prefixes.
+    pub fn start(&self) {
+        self.router_solicits.set(MAX_RTR_SOLICITATIONS);
+        self.solicit_router.set(true);
+        self.deferred_call.set();
+    }
+
+    /// The default router learned from Router Advertisements, if any.
+    pub fn get_default_router(&self) -> Option<IPAddr> {
+        self.default_router.extract()
+    }
+
+    /// The cached link-layer address of `ip_addr`, if any.
+    pub fn lookup(&self, ip_addr: IPAddr) -> Option<MacAddress> {
+        self.find(ip_addr).and_then(|neighbor| {
+            self.clock.set(self.clock.get().wrapping_add(1));
+            neighbor.last_used.set(self.clock.get());
+            neighbor.mac_addr.extract()
+        })
+    }
+
+    fn find(&self, ip_addr: IPAddr) -> Option<&Neighbor> {
+        self.neighbors
+            .iter()
+            .find(|neighbor| neighbor.ip_addr.contains(&ip_addr))
+    }
+
+    /// The entry of `ip_addr`, a new one if there is none. A new entry
+    /// replaces a free one, or else the least recently used one.
+    fn find_or_insert(&self, ip_addr: IPAddr) -> &Neighbor {
+        self.find(ip_addr).unwrap_or_else(|| {
+            let clock = self.clock.get();
+            let neighbor = self
+                .neighbors
+                .iter()
+                .max_by_key(|neighbor| {
+                    if neighbor.ip_addr.is_some() {
+                        clock.wrapping_sub(neighbor.last_used.get())
+                    } else {
+                        u32::MAX
+                    }
+                })
+                .unwrap();
+            if neighbor
+                .ip_addr
+                .extract()
+                .is_some_and(|old| self.default_router.contains(&old))
+            {
+                self.default_router.clear();
+            }
+            neighbor.free();
+            neighbor.ip_addr.set(ip_addr);
+            neighbor.last_used.set(clock);
+            neighbor
+        })
+    }
+
+    /// Cache the link-layer address of a neighbor.
+    fn update(&self, ip_addr: IPAddr, mac_addr: MacAddress) -> &Neighbor {
+        let neighbor = self.find_or_insert(ip_addr);
+        neighbor.mac_addr.set(mac_addr);
+        neighbor.solicit.set(false);
+        neighbor.solicits.set(0);
+        neighbor
+    }
+
+    fn is_on_link(&self, addr: IPAddr) -> bool {
+        addr.is_unicast_link_local()
+            || self.prefixes.iter().any(|prefix| {
+                prefix
+                    .extract()
+                    .is_some_and(|(prefix, len)| matches_prefix(addr, prefix, len))
+            })
+    }
+
+    /// Add or remove an on-link prefix.
+    fn update_prefix(&self, prefix: IPAddr, prefix_len: u8, valid: bool) {
+        let existing = self
+            .prefixes
+            .iter()
+            .find(|entry| entry.contains(&(prefix, prefix_len)));
+        match (existing, valid) {
+            (Some(entry), false) => entry.clear(),
+            (None, true) => {
+                // Without a free entry, the newest prefix replaces the last one
+                let entry = self
+                    .prefixes
+                    .iter()
+                    .find(|entry| entry.is_none())
+                    .unwrap_or(&self.prefixes[MAX_ON_LINK_PREFIXES - 1]);
+                entry.set((prefix, prefix_len));
+            }
+            _ => {}
+        }
+    }
+
+    fn receive_router_advert(&self, src_addr: IPAddr, router_lifetime: u16, body: &[u8]) {
+        // The reachable time and retransmission timer are not used
+        if !src_addr.is_unicast_link_local() || body.len() < 8 {
+            return;
+        }
+        let options = match NdOptions::new(&body[8..]) {
+            Some(options) => options,
+            None => return,
+        };
+        for (option_type, option) in options {
+            match option_type {
+                nd_option::SOURCE_LL_ADDR => {
+                    if let Some(mac_addr) = decode_ll_addr_option(option) {
+                        self.update(src_addr, mac_addr);
+                    }
+                }
+                nd_option::PREFIX_INFO if option.len() == 32 => {
+                    let prefix_len = option[2];
+                    let valid_lifetime =
+                        u32::from_be_bytes([option[4], option[5], option[6], option[7]]);
+                    let flags = option[3];
+                    if flags & prefix_flags::ON_LINK != 0 && prefix_len <= 128 {
+                        let mut prefix = IPAddr::new();
+                        prefix.set_prefix(&option[16..32], prefix_len);
+                        self.update_prefix(prefix, prefix_len, valid_lifetime > 0);
+                    }
+                    if flags & prefix_flags::AUTONOMOUS != 0 {
+                        self.interface_list.autoconfigure(
+                            &option[16..32],
+                            prefix_len,
+                            valid_lifetime > 0,
+                        );
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if router_lifetime > 0 {
+            if let Some(neighbor) = self.find(src_addr) {
+                neighbor.is_router.set(true);
+            }
+            self.default_router.set(src_addr);
+        } else if self.default_router.contains(&src_addr) {
+            self.default_router.clear();
+        }
+
+        // A router answered, stop soliciting
+        self.router_solicits.set(0);
+        self.solicit_router.set(false);
+    }
+
+    fn receive_neighbor_solicit(&self, src_addr: IPAddr, body: &[u8]) {
+        if body.len() < 16 {
+            return;
+        }
+        let mut target = IPAddr::new();
+        target.0.copy_from_slice(&body[..16]);
+        if target.is_multicast() || !self.interface_list.contains(target) {
+            return;
+        }
+        let options = match NdOptions::new(&body[16..]) {
+            Some(options) => options,
+            None => return,
+        };
+
+        // A solicitation from the unspecified address is answered to all
+        // nodes, as the sender has no address yet.
+        let advert = if src_addr.is_unspecified() {
+            Advert {
+                dst: ALL_NODES,
+                target,
+                flags: na_flags::OVERRIDE,
+            }
+        } else {
+            for (option_type, option) in options {
+                if option_type == nd_option::SOURCE_LL_ADDR {
+                    if let Some(mac_addr) = decode_ll_addr_option(option) {
+                        self.update(src_addr, mac_addr);
+                    }
+                }
+            }
+            Advert {
+                dst: src_addr,
+                target,
+                flags: na_flags::SOLICITED | na_flags::OVERRIDE,
+            }
+        };
+        self.pending_advert.set(advert);
+    }
+
+    fn receive_neighbor_advert(&self, flags: u32, body: &[u8]) {
+        if body.len() < 16 {
+            return;
+        }
+        let mut target = IPAddr::new();
+        target.0.copy_from_slice(&body[..16]);
+        if target.is_multicast() {
+            return;
+        }
+        let options = match NdOptions::new(&body[16..]) {
+            Some(options) => options,
+            None => return,
+        };
+
+        // Advertisements only update existing entries
+        let neighbor = match self.find(target) {
+            Some(neighbor) => neighbor,
+            None => return,
+        };
+        for (option_type, option) in options {
+            if option_type == nd_option::TARGET_LL_ADDR {
+                if let Some(mac_addr) = decode_ll_addr_option(option) {
+                    if neighbor.mac_addr.is_none() || flags & na_flags::OVERRIDE != 0 {
+                        self.update(target, mac_addr);
+                    }
+                }
+            }
+        }
+        if neighbor.mac_addr.is_none() {
+            return;
+        }
+        let is_router = flags & na_flags::ROUTER != 0;
+        neighbor.is_router.set(is_router);
+        if !is_router && self.default_router.contains(&target) {
+            self.default_router.clear();
+        }
+    }
+
+    /// Write the body of a Neighbor Solicitation or Advertisement to `buf`,
+    /// and return its length.
+    fn encode_neighbor_message(&self, buf: &mut [u8], target: IPAddr, option_type: u8) -> usize {
+        buf[..16].copy_from_slice(&target.0);
+        16 + encode_ll_addr_option(
+            &mut buf[16..],
+            option_type,
+            self.interface_list.get_mac_addr(),
+        )
+    }
+
+    /// Send the next pending message, if the buffer is free.
+    fn transmit_next(&self) {
+        let mut buf = match self.kernel_buffer.take() {
+            Some(buf) => buf,
+            None => return,
+        };
+
+        let message = if let Some(advert) = self.pending_advert.take() {
+            let mut icmp_header = ICMP6Header::new(ICMP6Type::Type136);
+            icmp_header.set_options(ICMP6HeaderOptions::Type136 {
+                flags: advert.flags,
+            });
+            let len = self.encode_neighbor_message(
+                &mut buf[..],
+                advert.target,
+                nd_option::TARGET_LL_ADDR,
+            );
+            Some((advert.dst, icmp_header, len))
+        } else if let Some(neighbor) = self.neighbors.iter().find(|n| n.solicit.get()) {
+            neighbor.solicit.set(false);
+            neighbor.solicits.set(neighbor.solicits.get() + 1);
+            neighbor.ip_addr.extract().map(|target| {
+                let len =
+                    self.encode_neighbor_message(&mut buf[..], target, nd_option::SOURCE_LL_ADDR);
+                (
+                    solicited_node(target),
+                    ICMP6Header::new(ICMP6Type::Type135),
+                    len,
+                )
+            })
+        } else if self.solicit_router.take() {
+            self.router_solicits
+                .set(self.router_solicits.get().saturating_sub(1));
+            self.router_timer.set(RTR_SOLICITATION_INTERVAL);
+            let len = encode_ll_addr_option(
+                &mut buf[..],
+                nd_option::SOURCE_LL_ADDR,
+                self.interface_list.get_mac_addr(),
+            );
+            Some((ALL_ROUTERS, ICMP6Header::new(ICMP6Type::Type133), len))
+        } else {
+            None
+        };
+
+        match message {
+            Some((dst, icmp_header, len)) => {
+                buf.slice(0..len);
+                if let Err(mut buf) = self.sender.send_transport(
+                    dst,
+                    TransportHeader::ICMP(icmp_header),
+                    buf,
+                    self.net_cap,
+                ) {
+                    // The message is lost; solicitations are retransmitted
+                    buf.reset();
+                    self.kernel_buffer.replace(buf);
+                }
+                self.start_timer();
+            }
+            None => {
+                self.kernel_buffer.replace(buf);
+            }
+        }
+    }
+
+    fn timer_needed(&self) -> bool {
+        self.router_solicits.get() > 0
+            || self
+                .neighbors
+                .iter()
+                .any(|neighbor| neighbor.is_resolving())
+    }
+
+    fn start_timer(&self) {
+        if !self.alarm.is_armed() && self.timer_needed() {
+            self.alarm
+                .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(TICK_MS));
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> NeighborResolver for NeighborDiscovery<'a, A> {
+    fn resolve(&self, dst: IPAddr) -> Option<MacAddress> {
+        let next_hop = if self.is_on_link(dst) {
+            dst
+        } else {
+            self.default_router.extract()?
+        };
+        if let Some(mac_addr) = self.lookup(next_hop) {
+            return Some(mac_addr);
+        }
+        if next_hop.is_unicast_link_local() {
+            return Some(mac_from_link_local(next_hop));
+        }
+        let neighbor = self.find_or_insert(next_hop);
+        if neighbor.solicits.get() == 0 && !neighbor.solicit.get() {
+            neighbor.solicit.set(true);
+            self.deferred_call.set();
+        }
+        None
+    }
+}
+
+impl<'a, A: Alarm<'a>> IP6RecvClient for NeighborDiscovery<'a, A> {
+    fn receive(&self, header: IP6Header, payload: &[u8]) {
+        if header.get_hop_limit() != ND_HOP_LIMIT {
+            return;
+        }
+        let icmp_header = match ICMP6Header::decode(payload).done() {
+            Some((_, icmp_header)) if icmp_header.get_code() == 0 => icmp_header,
+            _ => return,
+        };
+        let body = &payload[ICMP_HDR_LEN..];
+        match icmp_header.get_options() {
+            ICMP6HeaderOptions::Type134 {
+                router_lifetime, ..
+            } => self.receive_router_advert(header.src_addr, router_lifetime, body),
+            ICMP6HeaderOptions::Type135 { .. } => {
+                self.receive_neighbor_solicit(header.src_addr, body)
+            }
+            ICMP6HeaderOptions::Type136 { flags } => self.receive_neighbor_advert(flags, body),
+            _ => return,
+        }
+        self.deferred_call.set();
+    }
+}
+
+impl<'a, A: Alarm<'a>> UDPSendClient for NeighborDiscovery<'a, A> {
+    fn send_done(
+        &self,
+        _result: Result<(), ErrorCode>,
+        mut dgram: LeasableMutableBuffer<'static, u8>,
+    ) {
+        dgram.reset();
+        self.kernel_buffer.replace(dgram);
+        self.transmit_next();
+    }
+}
+
+impl<'a, A: Alarm<'a>> time::AlarmClient for NeighborDiscovery<'a, A> {
+    fn alarm(&self) {
+        for neighbor in self.neighbors.iter().filter(|n| n.is_resolving()) {
+            if neighbor.solicits.get() >= MAX_MULTICAST_SOLICIT {
+                // Unreachable, packets go to the gateway again
+                neighbor.free();
+            } else {
+                neighbor.solicit.set(true);
+            }
+        }
+        if self.router_solicits.get() > 0 {
+            self.router_timer
+                .set(self.router_timer.get().saturating_sub(1));
+            if self.router_timer.get() == 0 {
+                self.solicit_router.set(true);
+            }
+        }
+        self.start_timer();
+        self.transmit_next();
+    }
+}
+
+impl<'a, A: Alarm<'a>> DeferredCallClient for NeighborDiscovery<'a, A> {
+    fn handle_deferred_call(&self) {
+        self.transmit_next();
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
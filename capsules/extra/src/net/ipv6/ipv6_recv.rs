@@ -2,13 +2,25 @@
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 // Copyright Tock Contributors 2022.
 
+use crate::net::ipv6::ip_utils::{ip6_nh, IPAddr, ETHERTYPE_IPV6};
+use crate::net::ipv6::slaac::InterfaceList;
 use crate::net::ipv6::IP6Header;
 use crate::net::sixlowpan::sixlowpan_state::SixlowpanRxClient;
 
+use capsules_core::packet_trace::{Direction, Layer, PacketTracer};
+
 use kernel::debug;
+use kernel::hil::ethernet;
 use kernel::utilities::cells::OptionalCell;
 use kernel::ErrorCode;
 
+/// ICMPv6 type of RPL control messages (RFC 6550, section 6).
+const RPL_ICMP_TYPE: u8 = 155;
+
+/// ICMPv6 types of Multicast Listener Discovery messages (RFC 2710): Query,
+/// Report and Done.
+const MLD_ICMP_TYPES: core::ops::RangeInclusive<u8> = 130..=132;
+
 // To provide some context for the entire rx chain:
 /*
 - The radio in the kernel has a single `RxClient`, which is set as the mac layer
@@ -30,6 +42,20 @@ use kernel::ErrorCode;
   udp_recv, a `UDPReceive` struct.
 - The UDPReceive struct is a field of the UDPDriver, which ultimately passes the
   packets up to userland.
+- If a TCP client is set, `IP6RecvStruct` passes packets carrying TCP segments to
+  it instead. This is the `TCPDriver`, which handles TCP for userland.
+- Likewise, if an ICMP client is set, `IP6RecvStruct` passes ICMPv6 messages to
+  it. This is `NeighborDiscovery`, which resolves link-layer addresses.
+  RPL control messages go to the RPL client instead, `Rpl`, which maintains
+  the routes of a multi-hop network, and MLD messages go to the MLD client,
+  `MulticastListener`, which reports the multicast groups of the node.
+- If a forward client and the interface list are set, unicast packets to any
+  other address are passed to the forward client, an `IP6Forwarder`, which
+  sends them on towards their destination.
+- On an Ethernet interface, `IP6RecvStruct` is instead the receive client of
+  the `EthernetAdapter`, and receives uncompressed packets directly. A board
+  with both interfaces has an `IP6RecvStruct` for each, and routes between
+  them by giving each one a forwarder that sends through the other interface.
 */
 
 pub trait IP6RecvClient {
@@ -43,44 +69,143 @@ pub trait IP6RecvClient {
 /// that are not among the local addresses of this device.
 pub trait IP6Receiver<'a> {
     fn set_client(&self, client: &'a dyn IP6RecvClient);
+
+    /// Set the client that receives packets carrying TCP segments. All other
+    /// packets, and TCP packets while no TCP client is set, are passed to the
+    /// client set with `set_client`.
+    fn set_tcp_client(&self, client: &'a dyn IP6RecvClient);
+
+    /// Set the client that receives packets carrying ICMPv6 messages. All
+    /// other packets, and ICMPv6 packets while no ICMP client is set, are
+    /// passed to the client set with `set_client`.
+    fn set_icmp_client(&self, client: &'a dyn IP6RecvClient);
+
+    /// Set the client that receives RPL control messages, which are ICMPv6
+    /// messages of type 155. They are passed to the ICMP client while no RPL
+    /// client is set.
+    fn set_rpl_client(&self, client: &'a dyn IP6RecvClient);
+
+    /// Set the client that receives Multicast Listener Discovery messages,
+    /// which are ICMPv6 messages of types 130 to 132. They are passed to the
+    /// ICMP client while no MLD client is set.
+    fn set_mld_client(&self, client: &'a dyn IP6RecvClient);
+
+    /// Set the addresses of the node, which packets are received locally
+    /// for when a forward client is set.
+    fn set_interface_list(&self, interface_list: &'a InterfaceList);
+
+    /// Set the client that receives unicast packets to addresses that are
+    /// not in the interface list, to forward them. Without a forward client,
+    /// all packets are received locally.
+    fn set_forward_client(&self, client: &'a dyn IP6RecvClient);
+
+    /// Set the tracer that is given every received packet.
+    fn set_tracer(&self, tracer: &'a dyn PacketTracer);
 }
 
 pub struct IP6RecvStruct<'a> {
     client: OptionalCell<&'a dyn IP6RecvClient>,
+    tcp_client: OptionalCell<&'a dyn IP6RecvClient>,
+    icmp_client: OptionalCell<&'a dyn IP6RecvClient>,
+    rpl_client: OptionalCell<&'a dyn IP6RecvClient>,
+    mld_client: OptionalCell<&'a dyn IP6RecvClient>,
+    interface_list: OptionalCell<&'a InterfaceList>,
+    forward_client: OptionalCell<&'a dyn IP6RecvClient>,
+    tracer: OptionalCell<&'a dyn PacketTracer>,
 }
 
 impl<'a> IP6Receiver<'a> for IP6RecvStruct<'a> {
     fn set_client(&self, client: &'a dyn IP6RecvClient) {
         self.client.set(client);
     }
+
+    fn set_tcp_client(&self, client: &'a dyn IP6RecvClient) {
+        self.tcp_client.set(client);
+    }
+
+    fn set_icmp_client(&self, client: &'a dyn IP6RecvClient) {
+        self.icmp_client.set(client);
+    }
+
+    fn set_rpl_client(&self, client: &'a dyn IP6RecvClient) {
+        self.rpl_client.set(client);
+    }
+
+    fn set_mld_client(&self, client: &'a dyn IP6RecvClient) {
+        self.mld_client.set(client);
+    }
+
+    fn set_interface_list(&self, interface_list: &'a InterfaceList) {
+        self.interface_list.set(interface_list);
+    }
+
+    fn set_forward_client(&self, client: &'a dyn IP6RecvClient) {
+        self.forward_client.set(client);
+    }
+
+    fn set_tracer(&self, tracer: &'a dyn PacketTracer) {
+        self.tracer.set(tracer);
+    }
 }
 
 impl<'a> IP6RecvStruct<'a> {
+    /// Whether a packet to `dst` is forwarded rather than received locally.
+    fn is_forwarded(&self, dst: IPAddr) -> bool {
+        self.forward_client.is_some()
+            && !dst.is_multicast()
+            && self
+                .interface_list
+                .map_or(false, |list| !list.contains(dst))
+    }
+
     pub fn new() -> IP6RecvStruct<'a> {
         IP6RecvStruct {
             client: OptionalCell::empty(),
+            tcp_client: OptionalCell::empty(),
+            icmp_client: OptionalCell::empty(),
+            rpl_client: OptionalCell::empty(),
+            mld_client: OptionalCell::empty(),
+            interface_list: OptionalCell::empty(),
+            forward_client: OptionalCell::empty(),
+            tracer: OptionalCell::empty(),
         }
     }
-}
 
-impl<'a> SixlowpanRxClient for IP6RecvStruct<'a> {
-    fn receive(&self, buf: &[u8], len: usize, result: Result<(), ErrorCode>) {
-        // TODO: Drop here?
-        if len > buf.len() || result != Ok(()) {
-            return;
-        }
+    /// Pass a received packet to the client that handles it.
+    fn receive_packet(&self, buf: &[u8]) {
+        self.tracer
+            .map(|tracer| tracer.packet(Layer::Ip, Direction::Receive, buf, buf.len()));
         match IP6Header::decode(buf).done() {
             Some((offset, ip6_header)) => {
-                let checksum_result = ip6_header.check_transport_checksum(&buf[offset..len]);
+                let checksum_result = ip6_header.check_transport_checksum(&buf[offset..]);
                 if checksum_result == Err(ErrorCode::FAIL) {
                     debug!("cksum fail!: {:?}", checksum_result);
                     return; //Dropped.
                 }
-                // Note: Protocols for which checksum verification is not implemented (TCP, etc.)
+                // Note: Protocols for which checksum verification is not implemented
                 // are automatically assumed as fine, rather than dropped
 
-                self.client
-                    .map(|client| client.receive(ip6_header, &buf[offset..len]));
+                let client = match ip6_header.get_next_header() {
+                    _ if self.is_forwarded(ip6_header.get_dst_addr()) => &self.forward_client,
+                    ip6_nh::TCP if self.tcp_client.is_some() => &self.tcp_client,
+                    // The first byte of an ICMPv6 message is its type
+                    ip6_nh::ICMP
+                        if buf.get(offset) == Some(&RPL_ICMP_TYPE) && self.rpl_client.is_some() =>
+                    {
+                        &self.rpl_client
+                    }
+                    ip6_nh::ICMP
+                        if buf
+                            .get(offset)
+                            .map_or(false, |icmp_type| MLD_ICMP_TYPES.contains(icmp_type))
+                            && self.mld_client.is_some() =>
+                    {
+                        &self.mld_client
+                    }
+                    ip6_nh::ICMP if self.icmp_client.is_some() => &self.icmp_client,
+                    _ => &self.client,
+                };
+                client.map(|client| client.receive(ip6_header, &buf[offset..]));
             }
             None => {
                 debug!("failed to decode ipv6 header");
@@ -89,3 +214,29 @@ impl<'a> SixlowpanRxClient for IP6RecvStruct<'a> {
         }
     }
 }
+
+impl<'a> SixlowpanRxClient for IP6RecvStruct<'a> {
+    fn receive(&self, buf: &[u8], len: usize, result: Result<(), ErrorCode>) {
+        // TODO: Drop here?
+        if len > buf.len() || result != Ok(()) {
+            return;
+        }
+        self.receive_packet(&buf[..len]);
+    }
+}
+
+impl<'a> ethernet::ReceiveClient for IP6RecvStruct<'a> {
+    fn received_frame(&self, frame: &[u8]) {
+        if frame.len() < ethernet::HEADER_LEN || frame[12..14] != ETHERTYPE_IPV6.to_be_bytes() {
+            return;
+        }
+        let packet = &frame[ethernet::HEADER_LEN..];
+        // Short frames are padded, so the packet ends where its header says
+        match IP6Header::decode(packet).done() {
+            Some((_, ip6_header)) if ip6_header.get_total_len() as usize <= packet.len() => {
+                self.receive_packet(&packet[..ip6_header.get_total_len() as usize]);
+            }
+            _ => debug!("failed to decode ipv6 header"),
+        }
+    }
+}
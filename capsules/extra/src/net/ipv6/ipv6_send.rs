@@ -8,39 +8,95 @@
 //! must be implemented by upper layers to receive the `send_done` callback
 //! when a transmission has completed.
 //!
-//! This file also includes an implementation of the `IP6Sender` trait, which
-//! sends an IPv6 packet using 6LoWPAN.
+//! This file also includes two implementations of the `IP6Sender` trait:
+//! `IP6SendStruct` sends an IPv6 packet using 6LoWPAN over 802.15.4, and
+//! `IP6EthSendStruct` sends it uncompressed in a single Ethernet frame.
+//!
+//! The transport payload is passed down in a `PacketBuffer`, which the
+//! `IP6Sender` owns until it returns it with `send_done`. The payload is not
+//! copied into a packet of the IP layer: `IP6SendStruct` compresses the
+//! headers and fragments the payload straight into the 802.15.4 frames, and
+//! `IP6EthSendStruct` prepends the headers of the frame into the headroom of
+//! the buffer if it is exactly `eth_headroom()`, and otherwise copies the
+//! packet into its frame buffer.
 
 // Additional Work and Known Problems
 // ----------------------------------
 // The main areas for additional work is with regards to the interface provided
 // by `IP6Sender`. The current interface differs from the one provided in
 // the networking stack overview document, and should be changed to better
-// reflect that document. Additionally, the specific implementation is
-// over 6LoWPAN, and should be separated from the generic IPv6 sending
-// interface.
+// reflect that document. Additionally, the gateway and the neighbor cache
+// use 802.15.4 addresses, which the Ethernet implementation converts.
 
 use crate::ieee802154::device::{MacDevice, TxClient};
-use crate::net::ieee802154::MacAddress;
-use crate::net::ipv6::ip_utils::IPAddr;
-use crate::net::ipv6::{IP6Header, IP6Packet, TransportHeader};
+use crate::net::buffer::PacketBuffer;
+use crate::net::ieee802154::{KeyId, MacAddress, SecurityLevel};
+use crate::net::ipv6::ip_utils::{IPAddr, ETHERTYPE_IPV6};
+use crate::net::ipv6::slaac::InterfaceList;
+use crate::net::ipv6::{IP6Header, IP6Packet, IPPayload, TransportHeader};
 use crate::net::network_capabilities::{IpVisibilityCapability, NetworkCapability};
 use crate::net::sixlowpan::sixlowpan_state::TxState;
 
+use capsules_core::packet_trace::{self, Direction, Layer, PacketTracer};
+
 use core::cell::Cell;
+use core::cmp;
 
 use kernel::debug;
+use kernel::hil::ethernet::{self, EthernetAdapter};
 use kernel::hil::time::{self, ConvertTicks};
-use kernel::utilities::cells::{OptionalCell, TakeCell};
-use kernel::utilities::leasable_buffer::LeasableMutableBuffer;
+use kernel::utilities::cells::{MapCell, OptionalCell, TakeCell};
 use kernel::ErrorCode;
 
+/// The headroom in front of a transport payload that `IP6EthSendStruct`
+/// needs to prepend the Ethernet, IPv6 and transport headers in place.
+pub fn eth_headroom(transport_header: &TransportHeader) -> usize {
+    let transport_hdr_size = match transport_header {
+        TransportHeader::UDP(header) => header.get_hdr_size(),
+        TransportHeader::ICMP(header) => header.get_hdr_size(),
+        TransportHeader::TCP(header) => header.get_hdr_size(),
+    };
+    ethernet::HEADER_LEN + 40 + transport_hdr_size
+}
+
 /// This trait must be implemented by upper layers in order to receive
 /// the `send_done` callback when a transmission has completed. The upper
 /// layer must then call `IP6Sender.set_client` in order to receive this
 /// callback.
 pub trait IP6SendClient {
-    fn send_done(&self, result: Result<(), ErrorCode>);
+    /// The packet was sent, or failed to be. `payload` is the buffer given
+    /// to `send_to` or `forward`, with its data range as it was given.
+    fn send_done(&self, result: Result<(), ErrorCode>, payload: PacketBuffer);
+}
+
+/// This trait is implemented by a neighbor cache, such as `NeighborDiscovery`,
+/// to tell the `IP6Sender` which link-layer address to send a packet to.
+pub trait NeighborResolver {
+    /// Returns the MAC address of the next hop towards `dst`, or `None` if
+    /// it is not known, in which case the packet is sent to the gateway.
+    fn resolve(&self, dst: IPAddr) -> Option<MacAddress>;
+}
+
+/// This trait is implemented by a routing protocol, such as `Rpl`, to tell
+/// the `IP6Sender` which node to send a packet to on its way to `dst`.
+pub trait Router {
+    /// Returns the address of the next hop towards `dst`, or `None` if there
+    /// is no route, in which case the packet is sent to `dst` directly. The
+    /// `NeighborResolver` then resolves the MAC address of the next hop.
+    fn next_hop(&self, dst: IPAddr) -> Option<IPAddr>;
+}
+
+/// This trait is implemented by a protocol that manages link-layer keys, such
+/// as Thread's MLE, to tell the `IP6Sender` how to secure the frames of a
+/// packet.
+pub trait LinkSecurity {
+    /// Returns the security level and key ID to secure the frames of a packet
+    /// to `dst` with, or `None` to send them unsecured.
+    fn security(
+        &self,
+        dst: IPAddr,
+        transport_header: &TransportHeader,
+    ) -> Option<(SecurityLevel, KeyId)>;
 }
 
 /// This trait provides a basic IPv6 sending interface. It exposes basic
@@ -64,6 +120,14 @@ pub trait IP6Sender<'a> {
     /// from this instance of `IP6Sender`
     fn set_addr(&self, src_addr: IPAddr);
 
+    /// This method sets the addresses of the node, which the source address
+    /// of each packet is then selected from. It replaces the address set with
+    /// `set_addr`.
+    ///
+    /// # Arguments
+    /// `interface_list` - Addresses of the node
+    fn set_interface_list(&self, interface_list: &'a InterfaceList);
+
     /// This method sets the gateway/next hop MAC address for this `IP6Sender`
     /// instance.
     ///
@@ -71,6 +135,37 @@ pub trait IP6Sender<'a> {
     /// `gateway` - MAC address to send the constructed packet to
     fn set_gateway(&self, gateway: MacAddress);
 
+    /// This method sets the neighbor cache that is asked for the MAC address
+    /// of the next hop of each packet. Without one, or for destinations it
+    /// cannot resolve, packets are sent to the gateway MAC address.
+    ///
+    /// # Arguments
+    /// `resolver` - Neighbor cache to resolve next hop MAC addresses with
+    fn set_resolver(&self, resolver: &'a dyn NeighborResolver);
+
+    /// This method sets the routing table that is asked for the next hop of
+    /// each packet. Without one, packets are sent to their destination
+    /// directly.
+    ///
+    /// # Arguments
+    /// `router` - Routing table to look up next hops in
+    fn set_router(&self, router: &'a dyn Router);
+
+    /// This method sets the protocol that is asked how to secure the frames
+    /// of each packet. Without one, frames are sent unsecured, as they are on
+    /// links without link-layer security.
+    ///
+    /// # Arguments
+    /// `security` - Protocol managing the link-layer keys
+    fn set_link_security(&self, security: &'a dyn LinkSecurity);
+
+    /// This method sets the tracer that is given every packet sent, and
+    /// every frame sent for it.
+    ///
+    /// # Arguments
+    /// `tracer` - Trace to record the packets in
+    fn set_tracer(&self, tracer: &'a dyn PacketTracer);
+
     /// This method sets the `IP6Header` for the `IP6Sender` instance
     ///
     /// # Arguments
@@ -79,7 +174,8 @@ pub trait IP6Sender<'a> {
     fn set_header(&mut self, ip6_header: IP6Header);
 
     /// This method sends the provided transport header and payload to the
-    /// given destination IP address
+    /// given destination IP address. The payload is returned with
+    /// `send_done`, or with the error if the packet cannot be sent.
     ///
     /// # Arguments
     /// `dst` - IPv6 address to send the packet to
@@ -89,25 +185,49 @@ pub trait IP6Sender<'a> {
         &self,
         dst: IPAddr,
         transport_header: TransportHeader,
-        payload: &LeasableMutableBuffer<'static, u8>,
+        payload: PacketBuffer,
+        net_cap: &'static NetworkCapability,
+    ) -> Result<(), (ErrorCode, PacketBuffer)>;
+
+    /// This method sends a packet of another node towards its destination.
+    /// Unlike `send_to`, the packet keeps the given IPv6 header, including
+    /// its source address and hop limit.
+    ///
+    /// # Arguments
+    /// `ip6_header` - The `IP6Header` of the packet being forwarded
+    /// `transport_header` - The `TransportHeader` for the packet being sent
+    /// `payload` - The transport payload for the packet being sent
+    fn forward(
+        &self,
+        ip6_header: IP6Header,
+        transport_header: TransportHeader,
+        payload: PacketBuffer,
         net_cap: &'static NetworkCapability,
-    ) -> Result<(), ErrorCode>;
+    ) -> Result<(), (ErrorCode, PacketBuffer)>;
 }
 
 /// This struct is a specific implementation of the `IP6Sender` trait. This
 /// struct sends the packet using 6LoWPAN over a generic `MacDevice` object.
 pub struct IP6SendStruct<'a, A: time::Alarm<'a>> {
-    // We want the ip6_packet field to be a TakeCell so that it is easy to mutate
-    ip6_packet: TakeCell<'static, IP6Packet<'static>>,
+    // The headers and the payload of the packet being sent. The payload stays
+    // in the buffer of the sender, and is compressed into each frame from
+    // there.
+    ip6_header: Cell<IP6Header>,
+    transport_header: OptionalCell<TransportHeader>,
+    payload: MapCell<PacketBuffer>,
     alarm: &'a A, // Alarm so we can introduce a small delay between fragments to ensure
     // successful reception on receivers with slow copies out of the radio buffer
     // (imix)
     src_addr: Cell<IPAddr>,
+    interface_list: OptionalCell<&'a InterfaceList>,
     gateway: Cell<MacAddress>,
+    resolver: OptionalCell<&'a dyn NeighborResolver>,
+    router: OptionalCell<&'a dyn Router>,
+    link_security: OptionalCell<&'a dyn LinkSecurity>,
+    tracer: OptionalCell<&'a dyn PacketTracer>,
     tx_buf: TakeCell<'static, [u8]>,
     sixlowpan: TxState<'a>,
     radio: &'a dyn MacDevice<'a>,
-    dst_mac_addr: MacAddress,
     src_mac_addr: MacAddress,
     client: OptionalCell<&'a dyn IP6SendClient>,
     ip_vis: &'static IpVisibilityCapability,
@@ -122,40 +242,70 @@ impl<'a, A: time::Alarm<'a>> IP6Sender<'a> for IP6SendStruct<'a, A> {
         self.src_addr.set(src_addr);
     }
 
+    fn set_interface_list(&self, interface_list: &'a InterfaceList) {
+        self.interface_list.set(interface_list);
+    }
+
     fn set_gateway(&self, gateway: MacAddress) {
         self.gateway.set(gateway);
     }
 
+    fn set_resolver(&self, resolver: &'a dyn NeighborResolver) {
+        self.resolver.set(resolver);
+    }
+
+    fn set_router(&self, router: &'a dyn Router) {
+        self.router.set(router);
+    }
+
+    fn set_link_security(&self, security: &'a dyn LinkSecurity) {
+        self.link_security.set(security);
+    }
+
+    fn set_tracer(&self, tracer: &'a dyn PacketTracer) {
+        self.tracer.set(tracer);
+    }
+
     fn set_header(&mut self, ip6_header: IP6Header) {
-        self.ip6_packet
-            .map(|ip6_packet| ip6_packet.header = ip6_header);
+        self.ip6_header.set(ip6_header);
     }
 
     fn send_to(
         &self,
         dst: IPAddr,
         transport_header: TransportHeader,
-        payload: &LeasableMutableBuffer<'static, u8>,
+        payload: PacketBuffer,
         net_cap: &'static NetworkCapability,
-    ) -> Result<(), ErrorCode> {
+    ) -> Result<(), (ErrorCode, PacketBuffer)> {
         if !net_cap.remote_addr_valid(dst, self.ip_vis) {
-            return Err(ErrorCode::FAIL);
+            return Err((ErrorCode::FAIL, payload));
         }
-        let _ = self.sixlowpan.init(
-            self.src_mac_addr,
-            self.dst_mac_addr,
-            self.radio.get_pan(),
-            None,
-        );
-        self.init_packet(dst, transport_header, payload);
-        let ret = self.send_next_fragment();
-        ret
+        let ip6_header = IP6Header {
+            src_addr: self
+                .interface_list
+                .map_or(self.src_addr.get(), |list| list.select_source(dst)),
+            dst_addr: dst,
+            ..IP6Header::default()
+        };
+        self.send_packet(ip6_header, transport_header, payload)
+    }
+
+    fn forward(
+        &self,
+        ip6_header: IP6Header,
+        transport_header: TransportHeader,
+        payload: PacketBuffer,
+        net_cap: &'static NetworkCapability,
+    ) -> Result<(), (ErrorCode, PacketBuffer)> {
+        if !net_cap.remote_addr_valid(ip6_header.get_dst_addr(), self.ip_vis) {
+            return Err((ErrorCode::FAIL, payload));
+        }
+        self.send_packet(ip6_header, transport_header, payload)
     }
 }
 
 impl<'a, A: time::Alarm<'a>> IP6SendStruct<'a, A> {
     pub fn new(
-        ip6_packet: &'static mut IP6Packet<'static>,
         alarm: &'a A,
         tx_buf: &'static mut [u8],
         sixlowpan: TxState<'a>,
@@ -165,38 +315,89 @@ impl<'a, A: time::Alarm<'a>> IP6SendStruct<'a, A> {
         ip_vis: &'static IpVisibilityCapability,
     ) -> IP6SendStruct<'a, A> {
         IP6SendStruct {
-            ip6_packet: TakeCell::new(ip6_packet),
+            ip6_header: Cell::new(IP6Header::default()),
+            transport_header: OptionalCell::empty(),
+            payload: MapCell::empty(),
             alarm: alarm,
             src_addr: Cell::new(IPAddr::new()),
+            interface_list: OptionalCell::empty(),
             gateway: Cell::new(dst_mac_addr),
+            resolver: OptionalCell::empty(),
+            router: OptionalCell::empty(),
+            link_security: OptionalCell::empty(),
+            tracer: OptionalCell::empty(),
             tx_buf: TakeCell::new(tx_buf),
             sixlowpan: sixlowpan,
             radio: radio,
-            dst_mac_addr: dst_mac_addr,
             src_mac_addr: src_mac_addr,
             client: OptionalCell::empty(),
             ip_vis: ip_vis,
         }
     }
 
+    // Multicast packets are broadcast on the link (RFC 4944, section 9);
+    // unicast packets go to the next hop the router names, or else to their
+    // destination, at the MAC address the resolver names, or else at the
+    // gateway.
+    fn next_hop(&self, dst: IPAddr) -> MacAddress {
+        if dst.is_multicast() {
+            return MacAddress::Short(0xffff);
+        }
+        let next_hop = self
+            .router
+            .and_then(|router| router.next_hop(dst))
+            .unwrap_or(dst);
+        self.resolver
+            .and_then(|resolver| resolver.resolve(next_hop))
+            .unwrap_or(self.gateway.get())
+    }
+
+    fn security(
+        &self,
+        dst: IPAddr,
+        transport_header: &TransportHeader,
+    ) -> Option<(SecurityLevel, KeyId)> {
+        self.link_security
+            .and_then(|security| security.security(dst, transport_header))
+    }
+
+    fn send_packet(
+        &self,
+        ip6_header: IP6Header,
+        transport_header: TransportHeader,
+        payload: PacketBuffer,
+    ) -> Result<(), (ErrorCode, PacketBuffer)> {
+        if self.payload.is_some() {
+            return Err((ErrorCode::BUSY, payload));
+        }
+        let dst = ip6_header.get_dst_addr();
+        let _ = self.sixlowpan.init(
+            self.src_mac_addr,
+            self.next_hop(dst),
+            self.radio.get_pan(),
+            self.security(dst, &transport_header),
+        );
+        self.init_packet(ip6_header, transport_header, payload);
+        self.send_next_fragment().map_err(|ecode| {
+            // The packet did not start, so it is still here.
+            let payload = self.payload.take().unwrap();
+            (ecode, payload)
+        })
+    }
+
     fn init_packet(
         &self,
-        dst_addr: IPAddr,
+        ip6_header: IP6Header,
         transport_header: TransportHeader,
-        payload: &LeasableMutableBuffer<'static, u8>,
+        mut payload: PacketBuffer,
     ) {
-        self.ip6_packet.map_or_else(
-            || {
-                debug!("init packet failed.");
-            },
-            |ip6_packet| {
-                ip6_packet.header = IP6Header::default();
-                ip6_packet.header.src_addr = self.src_addr.get();
-                ip6_packet.header.dst_addr = dst_addr;
-                ip6_packet.set_payload(transport_header, payload);
-                ip6_packet.set_transport_checksum();
-            },
-        );
+        let mut ip6_packet =
+            IP6Packet::with_payload(ip6_header, transport_header, payload.data_mut());
+        ip6_packet.set_transport_checksum();
+        self.tracer.map(|tracer| trace_packet(*tracer, &ip6_packet));
+        self.ip6_header.set(ip6_packet.header);
+        self.transport_header.set(ip6_packet.payload.header);
+        self.payload.replace(payload);
     }
 
     // Returns BUSY if the tx_buf is not there
@@ -205,37 +406,61 @@ impl<'a, A: time::Alarm<'a>> IP6SendStruct<'a, A> {
         // However, this led to a race condition where when multiple apps transmitted
         // simultaneously, it was possible for send_complete to trigger another
         // transmission before the below closure would exit, leading to this function
-        // being called again by another app before ip6_packet is replaced.
+        // being called again by another app before the payload is replaced.
         // To fix this, we pass a bool out of the closure to indicate whether send_completed()
         // should be called once the closure exits
         let (ret, call_send_complete) = self
-            .ip6_packet
-            .map(move |ip6_packet| match self.tx_buf.take() {
-                Some(tx_buf) => {
-                    let next_frame = self.sixlowpan.next_fragment(ip6_packet, tx_buf, self.radio);
-                    match next_frame {
-                        Ok((is_done, frame)) => {
-                            if is_done {
-                                self.tx_buf.replace(frame.into_buf());
-                                //self.send_completed(Ok(()));
-                                (Ok(()), true)
-                            } else {
-                                match self.radio.transmit(frame) {
-                                    Ok(()) => (Ok(()), false),
-                                    Err((ecode, _buf)) => (Err(ecode), false),
+            .payload
+            .map(|payload| {
+                let transport_header = match self.transport_header.extract() {
+                    Some(transport_header) => transport_header,
+                    None => return (Err(ErrorCode::FAIL), false),
+                };
+                // The packet is only put together around the payload,
+                // which is not copied.
+                let ip6_packet = IP6Packet {
+                    header: self.ip6_header.get(),
+                    payload: IPPayload::new(transport_header, payload.data_mut()),
+                };
+                match self.tx_buf.take() {
+                    Some(tx_buf) => {
+                        let next_frame =
+                            self.sixlowpan
+                                .next_fragment(&ip6_packet, tx_buf, self.radio);
+                        match next_frame {
+                            Ok((is_done, frame)) => {
+                                if is_done {
+                                    self.tx_buf.replace(frame.into_buf());
+                                    (Ok(()), true)
+                                } else {
+                                    self.tracer.map(|tracer| {
+                                        let bytes = frame.unsecured_bytes();
+                                        tracer.packet(
+                                            Layer::Lowpan,
+                                            Direction::Transmit,
+                                            bytes,
+                                            bytes.len(),
+                                        );
+                                    });
+                                    match self.radio.transmit(frame) {
+                                        Ok(()) => (Ok(()), false),
+                                        Err((ecode, buf)) => {
+                                            self.tx_buf.replace(buf);
+                                            (Err(ecode), false)
+                                        }
+                                    }
                                 }
                             }
-                        }
-                        Err((retcode, buf)) => {
-                            self.tx_buf.replace(buf);
-                            //self.send_completed(retcode);
-                            (retcode, true)
+                            Err((retcode, buf)) => {
+                                self.tx_buf.replace(buf);
+                                (retcode, true)
+                            }
                         }
                     }
-                }
-                None => {
-                    debug!("Missing tx_buf");
-                    (Err(ErrorCode::BUSY), false)
+                    None => {
+                        debug!("Missing tx_buf");
+                        (Err(ErrorCode::BUSY), false)
+                    }
                 }
             })
             .unwrap_or((Err(ErrorCode::NOMEM), false));
@@ -247,9 +472,15 @@ impl<'a, A: time::Alarm<'a>> IP6SendStruct<'a, A> {
     }
 
     fn send_completed(&self, result: Result<(), ErrorCode>) {
-        self.client.map(move |client| {
-            client.send_done(result);
-        });
+        self.transport_header.clear();
+        match self.payload.take() {
+            Some(payload) => {
+                self.client.map(move |client| {
+                    client.send_done(result, payload);
+                });
+            }
+            None => debug!("Missing payload in send done."),
+        }
     }
 }
 
@@ -267,9 +498,7 @@ impl<'a, A: time::Alarm<'a>> TxClient for IP6SendStruct<'a, A> {
         self.tx_buf.replace(tx_buf);
         if result != Ok(()) {
             debug!("Send Failed: {:?}, acked: {}", result, acked);
-            self.client.map(move |client| {
-                client.send_done(result);
-            });
+            self.send_completed(result);
         } else {
             // Below code adds delay between fragments. Despite some efforts
             // to fix this bug, I find that without it the receiving imix cannot
@@ -284,3 +513,316 @@ impl<'a, A: time::Alarm<'a>> TxClient for IP6SendStruct<'a, A> {
         }
     }
 }
+
+/// This struct is an implementation of the `IP6Sender` trait for Ethernet,
+/// or any other link whose MTU fits a full IPv6 packet. Packets are sent
+/// uncompressed and unfragmented, each in a single frame.
+///
+/// Multicast packets are sent to the multicast MAC address of their group
+/// (RFC 2464, section 7), and unicast packets follow the router and the
+/// resolver like with `IP6SendStruct`. The resolver and `set_gateway` name
+/// 802.15.4 addresses: only extended addresses that are EUI-64s built from
+/// a MAC address are used, and others fall back to the gateway MAC address
+/// given to `new`.
+pub struct IP6EthSendStruct<'a> {
+    ip6_header: Cell<IP6Header>,
+    // The payload of the packet being sent, unless it is sent in place.
+    payload: MapCell<PacketBuffer>,
+    // The length of the headers prepended to the payload and the length of
+    // the frame, if the packet is sent in place.
+    in_place: OptionalCell<(usize, usize)>,
+    src_addr: Cell<IPAddr>,
+    interface_list: OptionalCell<&'a InterfaceList>,
+    gateway: Cell<ethernet::MacAddress>,
+    resolver: OptionalCell<&'a dyn NeighborResolver>,
+    router: OptionalCell<&'a dyn Router>,
+    tracer: OptionalCell<&'a dyn PacketTracer>,
+    tx_buf: TakeCell<'static, [u8]>,
+    ethernet: &'a dyn EthernetAdapter<'a>,
+    client: OptionalCell<&'a dyn IP6SendClient>,
+    ip_vis: &'static IpVisibilityCapability,
+}
+
+impl<'a> IP6Sender<'a> for IP6EthSendStruct<'a> {
+    fn set_client(&self, client: &'a dyn IP6SendClient) {
+        self.client.set(client);
+    }
+
+    fn set_addr(&self, src_addr: IPAddr) {
+        self.src_addr.set(src_addr);
+    }
+
+    fn set_interface_list(&self, interface_list: &'a InterfaceList) {
+        self.interface_list.set(interface_list);
+    }
+
+    fn set_gateway(&self, gateway: MacAddress) {
+        if let Some(gateway) = eui48(gateway) {
+            self.gateway.set(gateway);
+        }
+    }
+
+    fn set_resolver(&self, resolver: &'a dyn NeighborResolver) {
+        self.resolver.set(resolver);
+    }
+
+    fn set_router(&self, router: &'a dyn Router) {
+        self.router.set(router);
+    }
+
+    fn set_link_security(&self, _security: &'a dyn LinkSecurity) {}
+
+    fn set_tracer(&self, tracer: &'a dyn PacketTracer) {
+        self.tracer.set(tracer);
+    }
+
+    fn set_header(&mut self, ip6_header: IP6Header) {
+        self.ip6_header.set(ip6_header);
+    }
+
+    fn send_to(
+        &self,
+        dst: IPAddr,
+        transport_header: TransportHeader,
+        payload: PacketBuffer,
+        net_cap: &'static NetworkCapability,
+    ) -> Result<(), (ErrorCode, PacketBuffer)> {
+        if !net_cap.remote_addr_valid(dst, self.ip_vis) {
+            return Err((ErrorCode::FAIL, payload));
+        }
+        let ip6_header = IP6Header {
+            src_addr: self
+                .interface_list
+                .map_or(self.src_addr.get(), |list| list.select_source(dst)),
+            dst_addr: dst,
+            ..IP6Header::default()
+        };
+        self.send_packet(ip6_header, transport_header, payload)
+    }
+
+    fn forward(
+        &self,
+        ip6_header: IP6Header,
+        transport_header: TransportHeader,
+        payload: PacketBuffer,
+        net_cap: &'static NetworkCapability,
+    ) -> Result<(), (ErrorCode, PacketBuffer)> {
+        if !net_cap.remote_addr_valid(ip6_header.get_dst_addr(), self.ip_vis) {
+            return Err((ErrorCode::FAIL, payload));
+        }
+        self.send_packet(ip6_header, transport_header, payload)
+    }
+}
+
+impl<'a> IP6EthSendStruct<'a> {
+    pub fn new(
+        tx_buf: &'static mut [u8],
+        ethernet: &'a dyn EthernetAdapter<'a>,
+        gateway: ethernet::MacAddress,
+        ip_vis: &'static IpVisibilityCapability,
+    ) -> IP6EthSendStruct<'a> {
+        IP6EthSendStruct {
+            ip6_header: Cell::new(IP6Header::default()),
+            payload: MapCell::empty(),
+            in_place: OptionalCell::empty(),
+            src_addr: Cell::new(IPAddr::new()),
+            interface_list: OptionalCell::empty(),
+            gateway: Cell::new(gateway),
+            resolver: OptionalCell::empty(),
+            router: OptionalCell::empty(),
+            tracer: OptionalCell::empty(),
+            tx_buf: TakeCell::new(tx_buf),
+            ethernet,
+            client: OptionalCell::empty(),
+            ip_vis,
+        }
+    }
+
+    fn next_hop(&self, dst: IPAddr) -> ethernet::MacAddress {
+        if dst.is_multicast() {
+            return [0x33, 0x33, dst.0[12], dst.0[13], dst.0[14], dst.0[15]];
+        }
+        let next_hop = self
+            .router
+            .and_then(|router| router.next_hop(dst))
+            .unwrap_or(dst);
+        self.resolver
+            .and_then(|resolver| resolver.resolve(next_hop))
+            .and_then(eui48)
+            .unwrap_or(self.gateway.get())
+    }
+
+    fn send_packet(
+        &self,
+        ip6_header: IP6Header,
+        transport_header: TransportHeader,
+        mut payload: PacketBuffer,
+    ) -> Result<(), (ErrorCode, PacketBuffer)> {
+        if self.payload.is_some() || self.in_place.is_some() {
+            return Err((ErrorCode::BUSY, payload));
+        }
+        let dst = self.next_hop(ip6_header.get_dst_addr());
+        let mut ip6_packet =
+            IP6Packet::with_payload(ip6_header, transport_header, payload.data_mut());
+        ip6_packet.set_transport_checksum();
+        let (ip6_header, transport_header) = (ip6_packet.header, ip6_packet.payload.header);
+        let headers_len = eth_headroom(&transport_header);
+        let len = ethernet::HEADER_LEN + ip6_packet.get_total_len() as usize;
+        if len > ethernet::MAX_FRAME_LEN {
+            return Err((ErrorCode::SIZE, payload));
+        }
+
+        // The frame has to start at the start of the buffer, so the headers
+        // are only prepended in place if they fill the headroom exactly.
+        if payload.headroom() != headers_len {
+            return self.send_copy(dst, ip6_header, transport_header, payload, len);
+        }
+        let encoded = match payload.push(headers_len) {
+            Ok(headers) => {
+                headers[0..6].copy_from_slice(&dst);
+                headers[6..12].copy_from_slice(&self.ethernet.get_address());
+                headers[12..14].copy_from_slice(&ETHERTYPE_IPV6.to_be_bytes());
+                ip6_header
+                    .encode(&mut headers[ethernet::HEADER_LEN..])
+                    .done()
+                    .and_then(|(offset, _)| {
+                        let offset = ethernet::HEADER_LEN + offset;
+                        match transport_header {
+                            TransportHeader::UDP(header) => header.encode(headers, offset).done(),
+                            TransportHeader::ICMP(header) => header.encode(headers, offset).done(),
+                            TransportHeader::TCP(header) => header.encode(headers, offset).done(),
+                        }
+                    })
+                    .map_or(false, |(offset, _)| offset == headers_len)
+            }
+            Err(_) => false,
+        };
+        if !encoded {
+            let _ = payload.pull(headers_len);
+            return Err((ErrorCode::FAIL, payload));
+        }
+        self.tracer.map(|tracer| {
+            let packet = &payload.data()[ethernet::HEADER_LEN..];
+            tracer.packet(Layer::Ip, Direction::Transmit, packet, packet.len());
+        });
+        let range = payload.range();
+        self.in_place.set((headers_len, range.end));
+        self.ethernet
+            .transmit(payload.into_buf(), len)
+            .map_err(|(ecode, frame)| {
+                self.in_place.clear();
+                (
+                    ecode,
+                    PacketBuffer::with_range(frame, headers_len..range.end),
+                )
+            })
+    }
+
+    // Encode the packet into `tx_buf`, as the payload does not have the
+    // headroom for the headers.
+    fn send_copy(
+        &self,
+        dst: ethernet::MacAddress,
+        ip6_header: IP6Header,
+        transport_header: TransportHeader,
+        mut payload: PacketBuffer,
+        len: usize,
+    ) -> Result<(), (ErrorCode, PacketBuffer)> {
+        let frame = match self.tx_buf.take() {
+            Some(frame) => frame,
+            None => return Err((ErrorCode::BUSY, payload)),
+        };
+        if len > frame.len() {
+            self.tx_buf.replace(frame);
+            return Err((ErrorCode::SIZE, payload));
+        }
+        frame[0..6].copy_from_slice(&dst);
+        frame[6..12].copy_from_slice(&self.ethernet.get_address());
+        frame[12..14].copy_from_slice(&ETHERTYPE_IPV6.to_be_bytes());
+        let ip6_packet = IP6Packet {
+            header: ip6_header,
+            payload: IPPayload::new(transport_header, payload.data_mut()),
+        };
+        let encoded = ip6_packet
+            .encode(&mut frame[ethernet::HEADER_LEN..])
+            .done()
+            .is_some();
+        if !encoded {
+            self.tx_buf.replace(frame);
+            return Err((ErrorCode::FAIL, payload));
+        }
+        self.tracer.map(|tracer| {
+            let packet = &frame[ethernet::HEADER_LEN..len];
+            tracer.packet(Layer::Ip, Direction::Transmit, packet, packet.len());
+        });
+        match self.ethernet.transmit(frame, len) {
+            Ok(()) => {
+                self.payload.replace(payload);
+                Ok(())
+            }
+            Err((ecode, frame)) => {
+                self.tx_buf.replace(frame);
+                Err((ecode, payload))
+            }
+        }
+    }
+}
+
+impl<'a> ethernet::TransmitClient for IP6EthSendStruct<'a> {
+    fn transmit_done(&self, frame: &'static mut [u8], result: Result<(), ErrorCode>) {
+        let payload = match self.in_place.take() {
+            // Drop the headers again.
+            Some((headers_len, end)) => Some(PacketBuffer::with_range(frame, headers_len..end)),
+            None => {
+                self.tx_buf.replace(frame);
+                self.payload.take()
+            }
+        };
+        match payload {
+            Some(payload) => {
+                self.client.map(move |client| {
+                    client.send_done(result, payload);
+                });
+            }
+            None => debug!("Missing payload in transmit done."),
+        }
+    }
+}
+
+/// Give `tracer` the headers of `ip6_packet`, followed by as much of its
+/// payload as fits the largest snap length. The packet is only encoded as it
+/// is compressed, so the headers are encoded here.
+fn trace_packet(tracer: &dyn PacketTracer, ip6_packet: &IP6Packet) {
+    let mut buf = [0; packet_trace::MAX_SNAP_LEN];
+    let headers =
+        ip6_packet
+            .header
+            .encode(&mut buf)
+            .done()
+            .and_then(|(offset, _)| match ip6_packet.payload.header {
+                TransportHeader::UDP(header) => header.encode(&mut buf, offset).done(),
+                TransportHeader::ICMP(header) => header.encode(&mut buf, offset).done(),
+                TransportHeader::TCP(header) => header.encode(&mut buf, offset).done(),
+            });
+    if let Some((offset, _)) = headers {
+        let len = ip6_packet.get_total_len() as usize;
+        let payload = ip6_packet.get_payload();
+        let copied = cmp::min(
+            cmp::min(buf.len() - offset, len.saturating_sub(offset)),
+            payload.len(),
+        );
+        buf[offset..offset + copied].copy_from_slice(&payload[..copied]);
+        tracer.packet(Layer::Ip, Direction::Transmit, &buf[..offset + copied], len);
+    }
+}
+
+/// The MAC address an EUI-64 is built from (RFC 2464, section 4), if it is
+/// an extended 802.15.4 address built that way.
+fn eui48(addr: MacAddress) -> Option<ethernet::MacAddress> {
+    match addr {
+        MacAddress::Long(eui64) if eui64[3] == 0xff && eui64[4] == 0xfe => {
+            Some([eui64[0], eui64[1], eui64[2], eui64[5], eui64[6], eui64[7]])
+        }
+        _ => None,
+    }
+}
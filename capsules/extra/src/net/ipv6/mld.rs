@@ -0,0 +1,367 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Multicast Listener Discovery (MLDv1, RFC 2710) for hosts.
+//!
+//! `MulticastListener` keeps the multicast groups the node listens to, and
+//! tells the routers and snooping switches of the link about them, so that
+//! group traffic is delivered to the node on links that filter multicast.
+//! Groups are joined and left through the `GroupMembership` trait, which the
+//! UDP port table uses on behalf of kernel capsules and apps. A group joined
+//! several times stays joined until it is left as many times.
+//!
+//! The implementation follows the host side of RFC 2710:
+//!
+//! - Joining a group sends an unsolicited Report for it, repeated once after
+//!   `UNSOLICITED_REPORT_INTERVAL` seconds, and leaving it sends a Done to
+//!   the all-routers address.
+//! - Queries, for all groups or for one, are answered with a Report for each
+//!   queried group after a delay picked in the Maximum Response Delay of the
+//!   query. A Report of another node for the group cancels the pending one.
+//! - Groups of interface-local scope and the all-nodes group, which every
+//!   node is a member of, are never reported.
+//! - Messages are sent from the link-local address with a hop limit of 1,
+//!   through the UDP send mux. The IPv6 stack does not support extension
+//!   headers, so they lack the Router Alert option, and received messages
+//!   are not checked for it.
+//! - The solicited-node groups of the interface addresses are not reported.
+
+use crate::net::icmpv6::{ICMP6Header, ICMP6HeaderOptions, ICMP6Type};
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::ipv6::ipv6_recv::IP6RecvClient;
+use crate::net::ipv6::slaac::InterfaceList;
+use crate::net::ipv6::{IP6Header, TransportHeader, ICMP_HDR_LEN};
+use crate::net::network_capabilities::NetworkCapability;
+use crate::net::udp::udp_send::{UDPSendClient, UDPSender};
+
+use core::cell::Cell;
+
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil::time::{self, Alarm, ConvertTicks, Ticks};
+use kernel::utilities::cells::{MapCell, OptionalCell};
+use kernel::utilities::leasable_buffer::LeasableMutableBuffer;
+use kernel::ErrorCode;
+
+/// The number of groups the node can listen to.
+pub const MAX_GROUPS: usize = 8;
+
+/// The size of the buffer messages are built in: a multicast address.
+pub const TX_BUF_LEN: usize = 16;
+
+/// All-nodes multicast address, ff02::1, which every node listens to.
+pub const ALL_NODES: IPAddr = IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+
+/// All-routers multicast address, ff02::2, which Done messages are sent to.
+const ALL_ROUTERS: IPAddr = IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2]);
+
+/// Granularity of the report timers.
+const TICK_MS: u32 = 1000;
+/// Seconds between the unsolicited Reports of a joined group.
+const UNSOLICITED_REPORT_INTERVAL: u8 = 10;
+
+/// Hop limit of all MLD messages, which never leave the link.
+const MLD_HOP_LIMIT: u8 = 1;
+
+/// The multicast groups a node listens to.
+pub trait GroupMembership {
+    /// Join `group`. Returns `INVAL` if it is not a multicast address, and
+    /// `NOMEM` if no more groups can be joined.
+    fn join(&self, group: IPAddr) -> Result<(), ErrorCode>;
+
+    /// Leave `group`, which was joined before. Returns `INVAL` if the group
+    /// is not joined.
+    fn leave(&self, group: IPAddr) -> Result<(), ErrorCode>;
+
+    /// Whether packets to `group` are received.
+    fn is_member(&self, group: IPAddr) -> bool;
+}
+
+/// Whether a group is reported: groups of interface-local scope and the
+/// all-nodes group are not (RFC 2710, section 5).
+fn is_reported(group: IPAddr) -> bool {
+    group.0[1] & 0x0f > 1 && group != ALL_NODES
+}
+
+pub struct MulticastGroup {
+    addr: OptionalCell<IPAddr>,
+    /// The number of joins of the group that were not left.
+    users: Cell<u8>,
+    /// Seconds until the next Report, if one is pending.
+    report_timer: Cell<Option<u8>>,
+    /// Whether the unsolicited Report is sent again.
+    repeat: Cell<bool>,
+    /// The group was left, and a Done is pending.
+    leaving: Cell<bool>,
+}
+
+impl Default for MulticastGroup {
+    fn default() -> MulticastGroup {
+        MulticastGroup {
+            addr: OptionalCell::empty(),
+            users: Cell::new(0),
+            report_timer: Cell::new(None),
+            repeat: Cell::new(false),
+            leaving: Cell::new(false),
+        }
+    }
+}
+
+impl MulticastGroup {
+    pub fn new() -> MulticastGroup {
+        MulticastGroup::default()
+    }
+
+    fn is_joined(&self) -> bool {
+        self.users.get() > 0
+    }
+
+    /// Report the group after `delay` seconds, unless a Report is due
+    /// earlier.
+    fn schedule_report(&self, delay: u8) {
+        if self.report_timer.get().map_or(true, |timer| delay < timer) {
+            self.report_timer.set(Some(delay));
+        }
+    }
+}
+
+pub struct MulticastListener<'a, A: Alarm<'a>> {
+    sender: &'a dyn UDPSender<'a>,
+    alarm: &'a A,
+    net_cap: &'static NetworkCapability,
+    interface_list: &'a InterfaceList,
+    groups: &'a [MulticastGroup],
+    kernel_buffer: MapCell<LeasableMutableBuffer<'static, u8>>,
+
+    /// Messages are sent from a deferred call, as groups are joined and left
+    /// from the system calls of apps.
+    deferred_call: DeferredCall,
+}
+
+impl<'a, A: Alarm<'a>> MulticastListener<'a, A> {
+    pub fn new(
+        sender: &'a dyn UDPSender<'a>,
+        alarm: &'a A,
+        net_cap: &'static NetworkCapability,
+        interface_list: &'a InterfaceList,
+        groups: &'a [MulticastGroup],
+        kernel_buffer: LeasableMutableBuffer<'static, u8>,
+    ) -> MulticastListener<'a, A> {
+        MulticastListener {
+            sender,
+            alarm,
+            net_cap,
+            interface_list,
+            groups,
+            kernel_buffer: MapCell::new(kernel_buffer),
+            deferred_call: DeferredCall::new(),
+        }
+    }
+
+    fn find(&self, group: IPAddr) -> Option<&MulticastGroup> {
+        self.groups.iter().find(|entry| entry.addr.contains(&group))
+    }
+
+    /// A delay picked in `0..=max_delay` seconds. Nodes answering the same
+    /// query spread their Reports, so that one of them suppresses the others.
+    fn random_delay(&self, max_delay: u8, salt: usize) -> u8 {
+        let seed = self.alarm.now().into_u32() ^ (salt as u32).wrapping_mul(0x9e37_79b9);
+        (seed % (max_delay as u32 + 1)) as u8
+    }
+
+    fn receive_query(&self, max_delay_ms: u16, group: IPAddr) {
+        let max_delay = (max_delay_ms as u32 / TICK_MS).min(u8::MAX as u32) as u8;
+        for (i, entry) in self.groups.iter().enumerate() {
+            let queried = entry.addr.map_or(false, |addr| {
+                is_reported(*addr) && (group.is_unspecified() || group == *addr)
+            });
+            if queried && entry.is_joined() {
+                entry.schedule_report(self.random_delay(max_delay, i));
+            }
+        }
+    }
+
+    fn receive_report(&self, group: IPAddr) {
+        if let Some(entry) = self.find(group) {
+            entry.report_timer.set(None);
+            entry.repeat.set(false);
+        }
+    }
+
+    /// Send the next pending message, if the buffer is free.
+    fn transmit_next(&self) {
+        let mut buf = match self.kernel_buffer.take() {
+            Some(buf) => buf,
+            None => return,
+        };
+
+        let message = if let Some(entry) = self.groups.iter().find(|g| g.leaving.get()) {
+            entry.leaving.set(false);
+            let group = entry.addr.take();
+            group.map(|group| (ALL_ROUTERS, ICMP6Type::Type132, group))
+        } else if let Some(entry) = self.groups.iter().find(|g| g.report_timer.get() == Some(0)) {
+            if entry.repeat.take() {
+                entry.report_timer.set(Some(UNSOLICITED_REPORT_INTERVAL));
+            } else {
+                entry.report_timer.set(None);
+            }
+            entry
+                .addr
+                .extract()
+                .map(|group| (group, ICMP6Type::Type131, group))
+        } else {
+            None
+        };
+
+        match message {
+            Some((dst, icmp_type, group)) => {
+                buf[..16].copy_from_slice(&group.0);
+                buf.slice(0..16);
+                let mut ip6_header = IP6Header::new();
+                ip6_header.set_hop_limit(MLD_HOP_LIMIT);
+                ip6_header.src_addr = self.interface_list.get_link_local();
+                ip6_header.dst_addr = dst;
+                if let Err(mut buf) = self.sender.forward(
+                    ip6_header,
+                    TransportHeader::ICMP(ICMP6Header::new(icmp_type)),
+                    buf,
+                    self.net_cap,
+                ) {
+                    // The message is lost; routers query again
+                    buf.reset();
+                    self.kernel_buffer.replace(buf);
+                }
+                self.start_timer();
+            }
+            None => {
+                self.kernel_buffer.replace(buf);
+            }
+        }
+    }
+
+    fn timer_needed(&self) -> bool {
+        self.groups
+            .iter()
+            .any(|entry| entry.report_timer.get().map_or(false, |timer| timer > 0))
+    }
+
+    fn start_timer(&self) {
+        if !self.alarm.is_armed() && self.timer_needed() {
+            self.alarm
+                .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(TICK_MS));
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> GroupMembership for MulticastListener<'a, A> {
+    fn join(&self, group: IPAddr) -> Result<(), ErrorCode> {
+        if !group.is_multicast() {
+            return Err(ErrorCode::INVAL);
+        }
+        if let Some(entry) = self.find(group) {
+            entry.users.set(entry.users.get().saturating_add(1));
+            if entry.leaving.take() {
+                // The Done was not sent yet, so the group stays reported
+                entry.users.set(1);
+            }
+            return Ok(());
+        }
+        let entry = self
+            .groups
+            .iter()
+            .find(|entry| entry.addr.is_none())
+            .ok_or(ErrorCode::NOMEM)?;
+        entry.addr.set(group);
+        entry.users.set(1);
+        if is_reported(group) {
+            entry.report_timer.set(Some(0));
+            entry.repeat.set(true);
+            self.deferred_call.set();
+        }
+        Ok(())
+    }
+
+    fn leave(&self, group: IPAddr) -> Result<(), ErrorCode> {
+        let entry = self
+            .find(group)
+            .filter(|entry| entry.is_joined())
+            .ok_or(ErrorCode::INVAL)?;
+        entry.users.set(entry.users.get() - 1);
+        if !entry.is_joined() {
+            entry.report_timer.set(None);
+            entry.repeat.set(false);
+            if is_reported(group) {
+                entry.leaving.set(true);
+                self.deferred_call.set();
+            } else {
+                entry.addr.clear();
+            }
+        }
+        Ok(())
+    }
+
+    fn is_member(&self, group: IPAddr) -> bool {
+        group == ALL_NODES || self.find(group).map_or(false, |entry| entry.is_joined())
+    }
+}
+
+impl<'a, A: Alarm<'a>> IP6RecvClient for MulticastListener<'a, A> {
+    fn receive(&self, header: IP6Header, payload: &[u8]) {
+        if header.get_hop_limit() != MLD_HOP_LIMIT {
+            return;
+        }
+        let icmp_header = match ICMP6Header::decode(payload).done() {
+            Some((_, icmp_header)) if icmp_header.get_code() == 0 => icmp_header,
+            _ => return,
+        };
+        let group = match payload.get(ICMP_HDR_LEN..ICMP_HDR_LEN + 16) {
+            Some(addr) => {
+                let mut group = IPAddr::new();
+                group.0.copy_from_slice(addr);
+                group
+            }
+            None => return,
+        };
+        match icmp_header.get_options() {
+            ICMP6HeaderOptions::Type130 { max_delay, .. } => self.receive_query(max_delay, group),
+            ICMP6HeaderOptions::Type131 { .. } => self.receive_report(group),
+            _ => return,
+        }
+        self.start_timer();
+        self.deferred_call.set();
+    }
+}
+
+impl<'a, A: Alarm<'a>> UDPSendClient for MulticastListener<'a, A> {
+    fn send_done(
+        &self,
+        _result: Result<(), ErrorCode>,
+        mut dgram: LeasableMutableBuffer<'static, u8>,
+    ) {
+        dgram.reset();
+        self.kernel_buffer.replace(dgram);
+        self.transmit_next();
+    }
+}
+
+impl<'a, A: Alarm<'a>> time::AlarmClient for MulticastListener<'a, A> {
+    fn alarm(&self) {
+        for entry in self.groups.iter() {
+            if let Some(timer) = entry.report_timer.get() {
+                entry.report_timer.set(Some(timer.saturating_sub(1)));
+            }
+        }
+        self.start_timer();
+        self.transmit_next();
+    }
+}
+
+impl<'a, A: Alarm<'a>> DeferredCallClient for MulticastListener<'a, A> {
+    fn handle_deferred_call(&self) {
+        self.transmit_next();
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
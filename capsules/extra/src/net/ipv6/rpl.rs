@@ -0,0 +1,1101 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! RPL, the IPv6 Routing Protocol for Low-Power and Lossy Networks (RFC
+//! 6550), in storing mode.
+//!
+//! `Rpl` lets the nodes of a 6LoWPAN network form a multi-hop mesh: a
+//! Destination-Oriented DAG (DODAG) rooted at a border router. It is the
+//! `Router` of the IPv6 sender. Packets to a node below this one are sent to
+//! the child the route to it was learned from, and all other packets to the
+//! preferred parent, towards the root. With an `IP6Forwarder`, the node
+//! forwards the packets of other nodes along the same routes.
+//!
+//! Control messages are sent through the UDP send mux, which passes packets
+//! of any transport protocol to the shared IPv6 sender, and the IPv6 receiver
+//! passes all received RPL control messages to this capsule. The
+//! implementation covers a single DODAG of a single RPL instance:
+//!
+//! - A node joins the first DODAG it receives a DODAG Information Object
+//!   (DIO) of, if the DODAG uses storing mode and Objective Function Zero
+//!   (RFC 6552). At start, it solicits DIOs with up to `MAX_DIS` DODAG
+//!   Information Solicitations. A new version of the DODAG makes it select
+//!   its parents again (global repair).
+//! - The preferred parent is the candidate parent with the lowest rank, and
+//!   the rank of the node follows from it. Parents are dropped once their
+//!   rank is not lower than that of the node; without parents, the node
+//!   advertises an infinite rank until it finds a new one. There is no link
+//!   metric, and unreachable parents are not detected.
+//! - DIOs are sent on a Trickle timer (RFC 6206) with the parameters of the
+//!   DODAG Configuration option, at a granularity of one second. They carry
+//!   the Prefix Information option of the root, and addresses are
+//!   autoconfigured from it (see `slaac`).
+//! - Each node sends Destination Advertisement Objects (DAOs) for its global
+//!   addresses to its preferred parent, which stores a route through the node
+//!   and advertises the route to its own parent in turn. DAOs are sent again
+//!   when the preferred parent changes or increments its DTSN, and are not
+//!   acknowledged. Routes do not expire: a No-Path DAO removes them, and when
+//!   the routing table is full, new routes are dropped.
+//! - Sequence counters are compared with serial number arithmetic rather
+//!   than as the lollipop counters of RFC 6550, section 7.2. Security,
+//!   multicast routing and the RPL option of the Hop-by-Hop header, and so
+//!   loop detection on the data path, are not supported.
+
+use crate::net::icmpv6::{ICMP6Header, ICMP6HeaderOptions, ICMP6Type};
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::ipv6::ipv6_recv::IP6RecvClient;
+use crate::net::ipv6::ipv6_send::Router;
+use crate::net::ipv6::slaac::{InterfaceList, SLAAC_PREFIX_LEN};
+use crate::net::ipv6::{IP6Header, TransportHeader};
+use crate::net::network_capabilities::NetworkCapability;
+use crate::net::udp::udp_send::{UDPSendClient, UDPSender};
+
+use core::cell::Cell;
+
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil::time::{self, Alarm, ConvertTicks};
+use kernel::utilities::cells::{MapCell, OptionalCell};
+use kernel::utilities::leasable_buffer::LeasableMutableBuffer;
+use kernel::ErrorCode;
+
+/// The number of entries of the routing table.
+pub const ROUTE_TABLE_SIZE: usize = 8;
+
+/// The size of the buffer messages are built in: a DIO base with a DODAG
+/// Configuration option and a Prefix Information option.
+pub const TX_BUF_LEN: usize = 24 + 2 + CONFIG_LEN + 2 + PREFIX_INFO_LEN;
+
+/// The number of candidate parents.
+const MAX_PARENTS: usize = 3;
+/// The number of addresses advertised in a single DAO.
+const MAX_DAO_TARGETS: usize = 3;
+
+/// Granularity of the Trickle timer.
+const TICK_MS: u32 = 1000;
+/// The number of DODAG Information Solicitations sent at start.
+const MAX_DIS: u8 = 3;
+/// Seconds between two DODAG Information Solicitations.
+const DIS_INTERVAL: u8 = 4;
+
+/// All-RPL-nodes multicast address, ff02::1a.
+const ALL_RPL_NODES: IPAddr = IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x1a]);
+
+/// Codes of RPL control messages.
+mod code {
+    pub const DIS: u8 = 0x00;
+    pub const DIO: u8 = 0x01;
+    pub const DAO: u8 = 0x02;
+}
+
+/// RPL control message option types.
+mod rpl_option {
+    pub const PAD1: u8 = 0x00;
+    pub const PADN: u8 = 0x01;
+    pub const DODAG_CONFIG: u8 = 0x04;
+    pub const TARGET: u8 = 0x05;
+    pub const TRANSIT_INFO: u8 = 0x06;
+    pub const PREFIX_INFO: u8 = 0x08;
+}
+
+/// The length of the body of the DODAG Configuration option.
+const CONFIG_LEN: usize = 14;
+/// The length of the body of the Prefix Information option.
+const PREFIX_INFO_LEN: usize = 30;
+
+const INFINITE_RANK: u16 = 0xffff;
+/// Mode of Operation: storing mode without multicast support.
+const MOP_STORING: u8 = 2;
+/// Objective Code Point of Objective Function Zero.
+const OCP_OF0: u16 = 0;
+/// The Grounded flag of the DIO base.
+const GROUNDED: u8 = 0x80;
+/// The D flag of the DAO base, set when the DODAGID is present.
+const DAO_DODAG_ID: u8 = 0x40;
+/// The autonomous flag of the Prefix Information option.
+const PREFIX_AUTONOMOUS: u8 = 0x40;
+/// Objective Function Zero computes the rank of a node as the rank of its
+/// preferred parent plus this many times MinHopRankIncrease.
+const STEP_OF_RANK: u16 = 3;
+
+/// Values the root advertises in its DODAG Configuration option, which also
+/// apply to DODAGs advertised without one.
+const DIO_INTERVAL_DOUBLINGS: u8 = 8;
+/// Two to the power of this many milliseconds, about a second.
+const DIO_INTERVAL_MIN: u8 = 10;
+const DIO_REDUNDANCY_CONSTANT: u8 = 10;
+const MAX_RANK_INCREASE: u16 = 7 * MIN_HOP_RANK_INCREASE;
+const MIN_HOP_RANK_INCREASE: u16 = 256;
+/// An infinite lifetime.
+const DEFAULT_LIFETIME: u8 = 0xff;
+const LIFETIME_UNIT: u16 = 60;
+
+const INSTANCE_ID: u8 = 0;
+/// Initial value of the sequence counters.
+const SEQUENCE_INIT: u8 = 240;
+
+/// Whether the sequence number `a` is newer than `b`.
+fn is_newer(a: u8, b: u8) -> bool {
+    (a.wrapping_sub(b) as i8) > 0
+}
+
+fn default_config() -> [u8; CONFIG_LEN] {
+    let mut config = [0; CONFIG_LEN];
+    config[1] = DIO_INTERVAL_DOUBLINGS;
+    config[2] = DIO_INTERVAL_MIN;
+    config[3] = DIO_REDUNDANCY_CONSTANT;
+    config[4..6].copy_from_slice(&MAX_RANK_INCREASE.to_be_bytes());
+    config[6..8].copy_from_slice(&MIN_HOP_RANK_INCREASE.to_be_bytes());
+    config[8..10].copy_from_slice(&OCP_OF0.to_be_bytes());
+    config[11] = DEFAULT_LIFETIME;
+    config[12..14].copy_from_slice(&LIFETIME_UNIT.to_be_bytes());
+    config
+}
+
+/// Iterator over the options of an RPL control message, which yields the
+/// type and the bytes of each option except padding.
+struct RplOptions<'b>(&'b [u8]);
+
+impl<'b> RplOptions<'b> {
+    /// Returns `None` if an option is truncated, in which case the whole
+    /// message must be discarded.
+    fn new(buf: &'b [u8]) -> Option<RplOptions<'b>> {
+        let mut rest = buf;
+        while let Some(&option_type) = rest.first() {
+            let len = match option_type {
+                rpl_option::PAD1 => 1,
+                _ => 2 + *rest.get(1)? as usize,
+            };
+            if len > rest.len() {
+                return None;
+            }
+            rest = &rest[len..];
+        }
+        Some(RplOptions(buf))
+    }
+}
+
+impl<'b> Iterator for RplOptions<'b> {
+    type Item = (u8, &'b [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let option_type = *self.0.first()?;
+            let len = match option_type {
+                rpl_option::PAD1 => 1,
+                _ => 2 + self.0[1] as usize,
+            };
+            let (option, rest) = self.0.split_at(len);
+            self.0 = rest;
+            if option_type != rpl_option::PAD1 && option_type != rpl_option::PADN {
+                return Some((option_type, option));
+            }
+        }
+    }
+}
+
+/// The DODAG the node is part of.
+#[derive(Copy, Clone)]
+struct Dodag {
+    instance_id: u8,
+    id: IPAddr,
+    version: u8,
+    /// The Grounded flag, Mode of Operation and DODAG preference.
+    flags: u8,
+    /// Body of the DODAG Configuration option, which nodes advertise as they
+    /// received it.
+    config: [u8; CONFIG_LEN],
+    /// Body of the Prefix Information option, if the root advertises one.
+    prefix_info: Option<[u8; PREFIX_INFO_LEN]>,
+}
+
+impl Dodag {
+    fn min_hop_rank_increase(&self) -> u16 {
+        u16::from_be_bytes([self.config[6], self.config[7]])
+    }
+
+    fn objective_code_point(&self) -> u16 {
+        u16::from_be_bytes([self.config[8], self.config[9]])
+    }
+
+    /// The minimum and maximum interval of the Trickle timer in ticks, and
+    /// its redundancy constant.
+    fn trickle_params(&self) -> (u32, u32, u8) {
+        let doublings = self.config[1].min(31);
+        let interval_min_ms = 1u32 << self.config[2].min(31);
+        let interval_min = (interval_min_ms / TICK_MS).max(1);
+        let interval_max = interval_min.saturating_mul(1 << doublings);
+        (interval_min, interval_max, self.config[3])
+    }
+}
+
+/// A candidate parent.
+#[derive(Copy, Clone)]
+struct Parent {
+    addr: IPAddr,
+    rank: u16,
+    dtsn: u8,
+}
+
+/// An entry of the routing table, a route to a node below this one.
+pub struct Route {
+    /// Address of the node, empty if the entry is free.
+    target: OptionalCell<IPAddr>,
+    /// Link-local address of the child the route goes through.
+    next_hop: Cell<IPAddr>,
+    path_sequence: Cell<u8>,
+    /// Zero for a removed route, which is freed once its removal is
+    /// advertised to the preferred parent.
+    path_lifetime: Cell<u8>,
+    /// A DAO has to be sent to the preferred parent for the route.
+    advertise: Cell<bool>,
+}
+
+impl Default for Route {
+    fn default() -> Route {
+        Route {
+            target: OptionalCell::empty(),
+            next_hop: Cell::new(IPAddr::new()),
+            path_sequence: Cell::new(0),
+            path_lifetime: Cell::new(0),
+            advertise: Cell::new(false),
+        }
+    }
+}
+
+impl Route {
+    pub fn new() -> Route {
+        Route::default()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.target.is_some() && self.path_lifetime.get() > 0
+    }
+
+    fn free(&self) {
+        self.target.clear();
+        self.advertise.set(false);
+    }
+}
+
+pub struct Rpl<'a, A: Alarm<'a>> {
+    /// Sender of control messages, through the UDP send mux.
+    sender: &'a dyn UDPSender<'a>,
+
+    /// Alarm driving the Trickle timer and the solicitations.
+    alarm: &'a A,
+
+    net_cap: &'static NetworkCapability,
+
+    /// Addresses of this node, which it advertises to its parent and
+    /// autoconfigures from the prefix of the root.
+    interface_list: &'a InterfaceList,
+
+    routes: &'a [Route],
+
+    kernel_buffer: MapCell<LeasableMutableBuffer<'static, u8>>,
+
+    dodag: OptionalCell<Dodag>,
+    is_root: Cell<bool>,
+    rank: Cell<u16>,
+    parents: [OptionalCell<Parent>; MAX_PARENTS],
+    preferred_parent: OptionalCell<IPAddr>,
+
+    /// Destination Advertisement Trigger Sequence Number, which the root
+    /// advertises.
+    dtsn: Cell<u8>,
+    dao_sequence: Cell<u8>,
+    /// Path sequence of the addresses of this node.
+    path_sequence: Cell<u8>,
+    /// A DAO has to be sent for the global addresses of this node.
+    advertise_addrs: Cell<bool>,
+
+    /// Trickle interval and the ticks until a DIO is sent in it, and until it
+    /// ends.
+    interval: Cell<u32>,
+    dio_timer: Cell<u32>,
+    interval_timer: Cell<u32>,
+    /// The number of consistent DIOs received in the interval.
+    counter: Cell<u8>,
+    send_dio: Cell<bool>,
+    /// State of the random number generator that picks the time of DIOs.
+    random: Cell<u32>,
+
+    /// A DODAG Information Solicitation has to be sent.
+    send_dis: Cell<bool>,
+    /// The number of solicitations left to send.
+    dis_left: Cell<u8>,
+    /// Seconds until the next solicitation.
+    dis_timer: Cell<u8>,
+
+    /// Messages are sent from a deferred call, as they are queued on receive.
+    deferred_call: DeferredCall,
+}
+
+impl<'a, A: Alarm<'a>> Rpl<'a, A> {
+    pub fn new(
+        sender: &'a dyn UDPSender<'a>,
+        alarm: &'a A,
+        net_cap: &'static NetworkCapability,
+        interface_list: &'a InterfaceList,
+        routes: &'a [Route],
+        kernel_buffer: LeasableMutableBuffer<'static, u8>,
+    ) -> Rpl<'a, A> {
+        // Seed the generator from the MAC address, so that neighbors pick
+        // different times
+        let mut seed: u32 = 1;
+        for byte in interface_list.get_link_local().0[8..].iter() {
+            seed = seed.wrapping_mul(31).wrapping_add(*byte as u32);
+        }
+        Rpl {
+            sender,
+            alarm,
+            net_cap,
+            interface_list,
+            routes,
+            kernel_buffer: MapCell::new(kernel_buffer),
+            dodag: OptionalCell::empty(),
+            is_root: Cell::new(false),
+            rank: Cell::new(INFINITE_RANK),
+            parents: core::array::from_fn(|_| OptionalCell::empty()),
+            preferred_parent: OptionalCell::empty(),
+            dtsn: Cell::new(SEQUENCE_INIT),
+            dao_sequence: Cell::new(SEQUENCE_INIT),
+            path_sequence: Cell::new(SEQUENCE_INIT),
+            advertise_addrs: Cell::new(false),
+            interval: Cell::new(0),
+            dio_timer: Cell::new(0),
+            interval_timer: Cell::new(0),
+            counter: Cell::new(0),
+            send_dio: Cell::new(false),
+            random: Cell::new(seed | 1),
+            send_dis: Cell::new(false),
+            dis_left: Cell::new(0),
+            dis_timer: Cell::new(0),
+            deferred_call: DeferredCall::new(),
+        }
+    }
+
+    /// Start soliciting DIOs, to join a DODAG as a router.
+    pub fn start(&self) {
+        self.dis_left.set(MAX_DIS);
+        self.send_dis.set(true);
+        self.deferred_call.set();
+    }
+
+    /// Start a DODAG as its root. `dodag_id` is a global address of the
+    /// root, which is added to the interface list, and the nodes of the
+    /// DODAG autoconfigure their addresses from its 64-bit prefix.
+    pub fn start_root(&self, dodag_id: IPAddr) -> Result<(), ErrorCode> {
+        match self.interface_list.add(dodag_id) {
+            Ok(()) | Err(ErrorCode::ALREADY) => {}
+            Err(e) => return Err(e),
+        }
+        let mut prefix_info = [0; PREFIX_INFO_LEN];
+        prefix_info[0] = SLAAC_PREFIX_LEN;
+        prefix_info[1] = PREFIX_AUTONOMOUS;
+        // Infinite valid and preferred lifetimes
+        prefix_info[2..10].fill(0xff);
+        prefix_info[14..22].copy_from_slice(&dodag_id.0[..8]);
+        let dodag = Dodag {
+            instance_id: INSTANCE_ID,
+            id: dodag_id,
+            version: SEQUENCE_INIT,
+            flags: GROUNDED | MOP_STORING << 3,
+            config: default_config(),
+            prefix_info: Some(prefix_info),
+        };
+        self.is_root.set(true);
+        self.join(dodag);
+        self.rank.set(dodag.min_hop_rank_increase());
+        Ok(())
+    }
+
+    /// The rank of the node in its DODAG.
+    pub fn get_rank(&self) -> u16 {
+        self.rank.get()
+    }
+
+    /// The preferred parent of the node, if it has one.
+    pub fn get_parent(&self) -> Option<IPAddr> {
+        self.preferred_parent.extract()
+    }
+
+    fn random(&self) -> u32 {
+        // xorshift32
+        let mut x = self.random.get();
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.random.set(x);
+        x
+    }
+
+    /// Start a Trickle interval, which a DIO is sent at a random time in the
+    /// second half of.
+    fn start_interval(&self) {
+        let interval = self.interval.get();
+        let half = interval / 2;
+        let dio_time = half + self.random() % (interval - half);
+        self.dio_timer.set(dio_time.max(1));
+        self.interval_timer.set(interval);
+        self.counter.set(0);
+    }
+
+    /// Restart the Trickle timer with its minimum interval, after an
+    /// inconsistency.
+    fn reset_trickle(&self) {
+        if let Some(dodag) = self.dodag.extract() {
+            let (interval_min, _, _) = dodag.trickle_params();
+            if self.interval.get() != interval_min {
+                self.interval.set(interval_min);
+                self.start_interval();
+            }
+        }
+    }
+
+    /// Join `dodag`, or a new version of it, without parents.
+    fn join(&self, dodag: Dodag) {
+        self.dodag.set(dodag);
+        for parent in self.parents.iter() {
+            parent.clear();
+        }
+        self.preferred_parent.clear();
+        self.rank.set(INFINITE_RANK);
+        self.send_dis.set(false);
+        self.dis_left.set(0);
+        self.interval.set(0);
+        self.reset_trickle();
+        self.start_timer();
+    }
+
+    /// Send DAOs for the addresses of this node and all routes.
+    fn advertise_all(&self) {
+        if self.is_root.get() {
+            return;
+        }
+        self.advertise_addrs.set(true);
+        for route in self.routes.iter().filter(|route| route.target.is_some()) {
+            route.advertise.set(true);
+        }
+    }
+
+    /// Select the preferred parent, and compute the rank of the node from it.
+    fn select_parent(&self, dodag: Dodag) {
+        let current = self.preferred_parent.extract();
+        // Keep the current parent unless another one has a lower rank
+        let best = self
+            .parents
+            .iter()
+            .filter_map(|parent| parent.extract())
+            .min_by_key(|parent| (parent.rank, Some(parent.addr) != current));
+        let best = match best {
+            Some(best) => best,
+            None => {
+                self.preferred_parent.clear();
+                if self.rank.get() != INFINITE_RANK {
+                    // Advertise the infinite rank, so that children leave
+                    self.rank.set(INFINITE_RANK);
+                    self.reset_trickle();
+                }
+                return;
+            }
+        };
+
+        let rank_increase = STEP_OF_RANK.saturating_mul(dodag.min_hop_rank_increase());
+        let rank = best.rank.saturating_add(rank_increase);
+        self.rank.set(rank);
+        for parent in self.parents.iter() {
+            if parent.extract().is_some_and(|parent| parent.rank >= rank) {
+                parent.clear();
+            }
+        }
+        self.preferred_parent.set(best.addr);
+        if current != Some(best.addr) {
+            self.reset_trickle();
+            self.advertise_all();
+        }
+    }
+
+    /// Add or update a candidate parent.
+    fn update_parent(&self, dodag: Dodag, addr: IPAddr, rank: u16, dtsn: u8) {
+        let existing = self
+            .parents
+            .iter()
+            .find(|parent| parent.extract().is_some_and(|parent| parent.addr == addr));
+        if rank == INFINITE_RANK || (rank >= self.rank.get() && existing.is_none()) {
+            if let Some(entry) = existing {
+                entry.clear();
+                self.select_parent(dodag);
+            }
+            return;
+        }
+
+        let entry = match existing {
+            Some(entry) => {
+                let is_preferred = self.preferred_parent.contains(&addr);
+                if entry
+                    .extract()
+                    .is_some_and(|parent| is_preferred && is_newer(dtsn, parent.dtsn))
+                {
+                    // The parent asks for DAOs again
+                    self.advertise_all();
+                }
+                entry
+            }
+            // Without a free entry, the new parent replaces the worst one if
+            // it is better
+            None => match self.parents.iter().find(|parent| parent.is_none()) {
+                Some(entry) => entry,
+                None => {
+                    let worst = self
+                        .parents
+                        .iter()
+                        .max_by_key(|parent| parent.map_or(0, |parent| parent.rank))
+                        .unwrap();
+                    if worst.map_or(false, |worst| worst.rank <= rank) {
+                        return;
+                    }
+                    worst
+                }
+            },
+        };
+        entry.set(Parent { addr, rank, dtsn });
+        self.select_parent(dodag);
+    }
+
+    fn receive_dis(&self, dst_addr: IPAddr) {
+        if self.dodag.is_none() {
+            return;
+        }
+        if dst_addr.is_multicast() {
+            self.reset_trickle();
+        } else {
+            self.send_dio.set(true);
+        }
+    }
+
+    fn receive_dio(&self, src_addr: IPAddr, message: &[u8]) {
+        if message.len() < 24 || self.is_root.get() {
+            return;
+        }
+        let instance_id = message[0];
+        let version = message[1];
+        let rank = u16::from_be_bytes([message[2], message[3]]);
+        let flags = message[4];
+        let dtsn = message[5];
+        let mut dodag_id = IPAddr::new();
+        dodag_id.0.copy_from_slice(&message[8..24]);
+        if (flags >> 3) & 0b111 != MOP_STORING {
+            return;
+        }
+
+        let options = match RplOptions::new(&message[24..]) {
+            Some(options) => options,
+            None => return,
+        };
+        let mut config = default_config();
+        let mut prefix_info = None;
+        for (option_type, option) in options {
+            match option_type {
+                rpl_option::DODAG_CONFIG if option.len() == 2 + CONFIG_LEN => {
+                    config.copy_from_slice(&option[2..]);
+                }
+                rpl_option::PREFIX_INFO if option.len() == 2 + PREFIX_INFO_LEN => {
+                    let mut body = [0; PREFIX_INFO_LEN];
+                    body.copy_from_slice(&option[2..]);
+                    prefix_info = Some(body);
+                }
+                _ => {}
+            }
+        }
+
+        let dodag = match self.dodag.extract() {
+            Some(dodag) if dodag.instance_id != instance_id || dodag.id != dodag_id => return,
+            Some(dodag) if dodag.version == version => dodag,
+            Some(dodag) if !is_newer(version, dodag.version) => return,
+            // A new DODAG, or a new version of it
+            _ => {
+                let dodag = Dodag {
+                    instance_id,
+                    id: dodag_id,
+                    version,
+                    flags,
+                    config,
+                    prefix_info,
+                };
+                if dodag.objective_code_point() != OCP_OF0 || dodag.min_hop_rank_increase() == 0 {
+                    return;
+                }
+                self.join(dodag);
+                dodag
+            }
+        };
+        self.counter.set(self.counter.get().saturating_add(1));
+
+        if let Some(prefix_info) = dodag.prefix_info {
+            if prefix_info[1] & PREFIX_AUTONOMOUS != 0 {
+                let valid_lifetime = u32::from_be_bytes([
+                    prefix_info[2],
+                    prefix_info[3],
+                    prefix_info[4],
+                    prefix_info[5],
+                ]);
+                let addrs = self.interface_list.iter().count();
+                self.interface_list.autoconfigure(
+                    &prefix_info[14..],
+                    prefix_info[0],
+                    valid_lifetime > 0,
+                );
+                if self.interface_list.iter().count() != addrs && !self.is_root.get() {
+                    self.advertise_addrs.set(true);
+                }
+            }
+        }
+
+        self.update_parent(dodag, src_addr, rank, dtsn);
+    }
+
+    fn receive_dao(&self, src_addr: IPAddr, message: &[u8]) {
+        let dodag = match self.dodag.extract() {
+            Some(dodag) => dodag,
+            None => return,
+        };
+        // A DAO from the preferred parent would make a loop
+        if message.len() < 4
+            || message[0] != dodag.instance_id
+            || self.preferred_parent.contains(&src_addr)
+        {
+            return;
+        }
+        let offset = if message[1] & DAO_DODAG_ID != 0 {
+            if message.len() < 20 || message[4..20] != dodag.id.0 {
+                return;
+            }
+            20
+        } else {
+            4
+        };
+        let options = match RplOptions::new(&message[offset..]) {
+            Some(options) => options,
+            None => return,
+        };
+
+        // Each Transit Information option applies to the targets before it
+        let mut targets = [None; MAX_DAO_TARGETS];
+        let mut count = 0;
+        for (option_type, option) in options {
+            match option_type {
+                rpl_option::TARGET if option.len() == 20 && option[3] == 128 => {
+                    if count < MAX_DAO_TARGETS {
+                        let mut target = IPAddr::new();
+                        target.0.copy_from_slice(&option[4..20]);
+                        targets[count] = Some(target);
+                        count += 1;
+                    }
+                }
+                rpl_option::TRANSIT_INFO if option.len() >= 6 => {
+                    for target in targets[..count].iter().flatten() {
+                        self.update_route(*target, src_addr, option[4], option[5]);
+                    }
+                    count = 0;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Store a route to `target` through `next_hop`, or remove it if its
+    /// lifetime is zero.
+    fn update_route(&self, target: IPAddr, next_hop: IPAddr, path_sequence: u8, lifetime: u8) {
+        if self.interface_list.contains(target) {
+            return;
+        }
+        let existing = self
+            .routes
+            .iter()
+            .find(|route| route.target.contains(&target));
+
+        if lifetime == 0 {
+            if let Some(route) = existing.filter(|route| route.next_hop.get() == next_hop) {
+                if self.is_root.get() {
+                    route.free();
+                } else {
+                    route.path_lifetime.set(0);
+                    route.advertise.set(true);
+                }
+            }
+            return;
+        }
+
+        let route = match existing {
+            Some(route)
+                if route.is_valid() && is_newer(route.path_sequence.get(), path_sequence) =>
+            {
+                // Stale information
+                return;
+            }
+            Some(route) => route,
+            None => match self.routes.iter().find(|route| route.target.is_none()) {
+                Some(route) => route,
+                None => return,
+            },
+        };
+        route.target.set(target);
+        route.next_hop.set(next_hop);
+        route.path_sequence.set(path_sequence);
+        route.path_lifetime.set(lifetime);
+        route.advertise.set(!self.is_root.get());
+    }
+
+    /// Write a DIO to `buf`, and return its length.
+    fn encode_dio(&self, buf: &mut [u8], dodag: Dodag) -> usize {
+        buf[0] = dodag.instance_id;
+        buf[1] = dodag.version;
+        buf[2..4].copy_from_slice(&self.rank.get().to_be_bytes());
+        buf[4] = dodag.flags;
+        buf[5] = self.dtsn.get();
+        buf[6] = 0;
+        buf[7] = 0;
+        buf[8..24].copy_from_slice(&dodag.id.0);
+        let mut len = 24;
+        buf[len] = rpl_option::DODAG_CONFIG;
+        buf[len + 1] = CONFIG_LEN as u8;
+        buf[len + 2..len + 2 + CONFIG_LEN].copy_from_slice(&dodag.config);
+        len += 2 + CONFIG_LEN;
+        if let Some(prefix_info) = dodag.prefix_info {
+            buf[len] = rpl_option::PREFIX_INFO;
+            buf[len + 1] = PREFIX_INFO_LEN as u8;
+            buf[len + 2..len + 2 + PREFIX_INFO_LEN].copy_from_slice(&prefix_info);
+            len += 2 + PREFIX_INFO_LEN;
+        }
+        len
+    }
+
+    /// Write the base of a DAO to `buf`, and return its length.
+    fn encode_dao_base(&self, buf: &mut [u8], dodag: Dodag) -> usize {
+        self.dao_sequence
+            .set(self.dao_sequence.get().wrapping_add(1));
+        buf[0] = dodag.instance_id;
+        buf[1] = 0;
+        buf[2] = 0;
+        buf[3] = self.dao_sequence.get();
+        4
+    }
+
+    /// Write a Target option for `target` to `buf`, and return its length.
+    fn encode_target(buf: &mut [u8], target: IPAddr) -> usize {
+        buf[0] = rpl_option::TARGET;
+        buf[1] = 18;
+        buf[2] = 0;
+        buf[3] = 128;
+        buf[4..20].copy_from_slice(&target.0);
+        20
+    }
+
+    /// Write a Transit Information option to `buf`, and return its length.
+    fn encode_transit_info(buf: &mut [u8], path_sequence: u8, path_lifetime: u8) -> usize {
+        buf[0] = rpl_option::TRANSIT_INFO;
+        buf[1] = 4;
+        buf[2] = 0;
+        buf[3] = 0;
+        buf[4] = path_sequence;
+        buf[5] = path_lifetime;
+        6
+    }
+
+    /// Write a DAO for the global addresses of this node to `buf`, and return
+    /// its length, or `None` if the node has no global address.
+    fn encode_addrs_dao(&self, buf: &mut [u8], dodag: Dodag) -> Option<usize> {
+        let mut addrs = self
+            .interface_list
+            .iter()
+            .filter(|addr| !addr.is_unicast_link_local())
+            .take(MAX_DAO_TARGETS)
+            .peekable();
+        addrs.peek()?;
+        let mut len = self.encode_dao_base(buf, dodag);
+        for addr in addrs {
+            len += Self::encode_target(&mut buf[len..], addr);
+        }
+        self.path_sequence
+            .set(self.path_sequence.get().wrapping_add(1));
+        len +=
+            Self::encode_transit_info(&mut buf[len..], self.path_sequence.get(), DEFAULT_LIFETIME);
+        Some(len)
+    }
+
+    /// The next message to send: its destination, code and length.
+    fn next_message(&self, buf: &mut [u8]) -> Option<(IPAddr, u8, usize)> {
+        let dodag = match self.dodag.extract() {
+            Some(dodag) => dodag,
+            None if self.send_dis.take() => {
+                self.dis_left.set(self.dis_left.get().saturating_sub(1));
+                self.dis_timer.set(DIS_INTERVAL);
+                // Flags and a reserved byte, padded to the four bytes of the
+                // ICMPv6 header
+                buf[..4].copy_from_slice(&[0, 0, rpl_option::PADN, 0]);
+                return Some((ALL_RPL_NODES, code::DIS, 4));
+            }
+            None => return None,
+        };
+
+        if self.send_dio.take() {
+            return Some((ALL_RPL_NODES, code::DIO, self.encode_dio(buf, dodag)));
+        }
+
+        let parent = self.preferred_parent.extract()?;
+        if self.advertise_addrs.take() {
+            if let Some(len) = self.encode_addrs_dao(buf, dodag) {
+                return Some((parent, code::DAO, len));
+            }
+        }
+        let route = self.routes.iter().find(|route| route.advertise.get())?;
+        route.advertise.set(false);
+        let target = route.target.extract()?;
+        let mut len = self.encode_dao_base(buf, dodag);
+        len += Self::encode_target(&mut buf[len..], target);
+        len += Self::encode_transit_info(
+            &mut buf[len..],
+            route.path_sequence.get(),
+            route.path_lifetime.get(),
+        );
+        if !route.is_valid() {
+            // The No-Path DAO is sent, forget the route
+            route.free();
+        }
+        Some((parent, code::DAO, len))
+    }
+
+    /// Send the next pending message, if the buffer is free.
+    fn transmit_next(&self) {
+        let mut buf = match self.kernel_buffer.take() {
+            Some(buf) => buf,
+            None => return,
+        };
+
+        match self.next_message(&mut buf[..]) {
+            Some((dst, message_code, len)) => {
+                // The first word of the message is part of the ICMPv6 header
+                let base = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]);
+                let mut icmp_header = ICMP6Header::new(ICMP6Type::Type155);
+                icmp_header.set_code(message_code);
+                icmp_header.set_options(ICMP6HeaderOptions::Type155 { base });
+                buf.slice(4..len);
+                if let Err(mut buf) = self.sender.send_transport(
+                    dst,
+                    TransportHeader::ICMP(icmp_header),
+                    buf,
+                    self.net_cap,
+                ) {
+                    // The message is lost; DIOs and solicitations are sent
+                    // again on the timer
+                    buf.reset();
+                    self.kernel_buffer.replace(buf);
+                }
+            }
+            None => {
+                self.kernel_buffer.replace(buf);
+            }
+        }
+    }
+
+    fn start_timer(&self) {
+        if !self.alarm.is_armed() && (self.dodag.is_some() || self.dis_left.get() > 0) {
+            self.alarm
+                .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(TICK_MS));
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> Router for Rpl<'a, A> {
+    fn next_hop(&self, dst: IPAddr) -> Option<IPAddr> {
+        if dst.is_multicast()
+            || dst.is_unicast_link_local()
+            || self.dodag.is_none()
+            || self.interface_list.contains(dst)
+        {
+            return None;
+        }
+        // Packets without a route down go up, towards the root
+        self.routes
+            .iter()
+            .find(|route| route.is_valid() && route.target.contains(&dst))
+            .map(|route| route.next_hop.get())
+            .or_else(|| self.preferred_parent.extract())
+    }
+}
+
+impl<'a, A: Alarm<'a>> IP6RecvClient for Rpl<'a, A> {
+    fn receive(&self, header: IP6Header, payload: &[u8]) {
+        let icmp_header = match ICMP6Header::decode(payload).done() {
+            Some((_, icmp_header)) => icmp_header,
+            None => return,
+        };
+        // Control messages are exchanged between neighbors
+        if !header.get_src_addr().is_unicast_link_local() {
+            return;
+        }
+        // The base of the message follows the type, code and checksum
+        let message = &payload[4..];
+        match icmp_header.get_code() {
+            code::DIS => self.receive_dis(header.get_dst_addr()),
+            code::DIO => self.receive_dio(header.get_src_addr(), message),
+            code::DAO => self.receive_dao(header.get_src_addr(), message),
+            _ => return,
+        }
+        self.deferred_call.set();
+    }
+}
+
+impl<'a, A: Alarm<'a>> UDPSendClient for Rpl<'a, A> {
+    fn send_done(
+        &self,
+        _result: Result<(), ErrorCode>,
+        mut dgram: LeasableMutableBuffer<'static, u8>,
+    ) {
+        dgram.reset();
+        self.kernel_buffer.replace(dgram);
+        self.transmit_next();
+    }
+}
+
+impl<'a, A: Alarm<'a>> time::AlarmClient for Rpl<'a, A> {
+    fn alarm(&self) {
+        if let Some(dodag) = self.dodag.extract() {
+            let (_, interval_max, redundancy) = dodag.trickle_params();
+            let dio_timer = self.dio_timer.get();
+            if dio_timer > 0 {
+                self.dio_timer.set(dio_timer - 1);
+                // A redundancy constant of zero disables suppression
+                if dio_timer == 1 && (redundancy == 0 || self.counter.get() < redundancy) {
+                    self.send_dio.set(true);
+                }
+            }
+            let interval_timer = self.interval_timer.get().saturating_sub(1);
+            self.interval_timer.set(interval_timer);
+            if interval_timer == 0 {
+                self.interval
+                    .set(self.interval.get().saturating_mul(2).min(interval_max));
+                self.start_interval();
+            }
+        } else if self.dis_left.get() > 0 {
+            self.dis_timer.set(self.dis_timer.get().saturating_sub(1));
+            if self.dis_timer.get() == 0 {
+                self.send_dis.set(true);
+            }
+        }
+        self.start_timer();
+        self.transmit_next();
+    }
+}
+
+impl<'a, A: Alarm<'a>> DeferredCallClient for Rpl<'a, A> {
+    fn handle_deferred_call(&self) {
+        self.transmit_next();
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dodag(config: [u8; CONFIG_LEN]) -> Dodag {
+        Dodag {
+            instance_id: INSTANCE_ID,
+            id: IPAddr::new(),
+            version: SEQUENCE_INIT,
+            flags: GROUNDED | MOP_STORING << 3,
+            config,
+            prefix_info: None,
+        }
+    }
+
+    #[test]
+    fn sequence_numbers_wrap() {
+        assert!(is_newer(SEQUENCE_INIT + 1, SEQUENCE_INIT));
+        assert!(!is_newer(SEQUENCE_INIT, SEQUENCE_INIT));
+        assert!(!is_newer(SEQUENCE_INIT, SEQUENCE_INIT + 1));
+        assert!(is_newer(0, 255));
+        assert!(is_newer(10, 250));
+        assert!(!is_newer(250, 10));
+    }
+
+    #[test]
+    fn options_skip_padding() {
+        let message = [
+            rpl_option::PAD1,
+            rpl_option::PADN,
+            1,
+            0,
+            rpl_option::TRANSIT_INFO,
+            4,
+            0,
+            0,
+            SEQUENCE_INIT,
+            DEFAULT_LIFETIME,
+            rpl_option::PAD1,
+            rpl_option::TARGET,
+            0,
+        ];
+        let mut options = RplOptions::new(&message).expect("valid options rejected");
+        assert_eq!(
+            options.next(),
+            Some((rpl_option::TRANSIT_INFO, &message[4..10]))
+        );
+        assert_eq!(options.next(), Some((rpl_option::TARGET, &message[11..])));
+        assert_eq!(options.next(), None);
+    }
+
+    #[test]
+    fn truncated_options_are_rejected() {
+        assert!(RplOptions::new(&[]).is_some());
+        assert!(RplOptions::new(&[rpl_option::TARGET]).is_none());
+        assert!(RplOptions::new(&[rpl_option::PAD1, rpl_option::TARGET, 2, 0]).is_none());
+    }
+
+    #[test]
+    fn default_configuration() {
+        let dodag = dodag(default_config());
+        assert_eq!(dodag.min_hop_rank_increase(), MIN_HOP_RANK_INCREASE);
+        assert_eq!(dodag.objective_code_point(), OCP_OF0);
+        // Imin is 2^10 ms, rounded to one tick, and Imax 2^8 Imin.
+        assert_eq!(dodag.trickle_params(), (1, 256, DIO_REDUNDANCY_CONSTANT));
+    }
+
+    #[test]
+    fn trickle_parameters_are_clamped() {
+        let mut config = default_config();
+        // Imin of 2^16 ms, about 65 seconds, doubled 20 times.
+        config[1] = 20;
+        config[2] = 16;
+        assert_eq!(dodag(config).trickle_params().0, 65);
+        assert_eq!(dodag(config).trickle_params().1, 65 << 20);
+
+        // Values that would overflow saturate instead.
+        config[1] = 255;
+        config[2] = 255;
+        let (interval_min, interval_max, _) = dodag(config).trickle_params();
+        assert_eq!(interval_min, (1 << 31) / TICK_MS);
+        assert_eq!(interval_max, u32::MAX);
+    }
+
+    #[test]
+    fn routes_are_valid_until_freed() {
+        let route = Route::new();
+        assert!(!route.is_valid());
+        route.target.set(IPAddr::new());
+        route.path_lifetime.set(DEFAULT_LIFETIME);
+        assert!(route.is_valid());
+        // A No-Path DAO sets the lifetime to zero.
+        route.path_lifetime.set(0);
+        assert!(!route.is_valid());
+        route.path_lifetime.set(DEFAULT_LIFETIME);
+        route.free();
+        assert!(!route.is_valid());
+    }
+}
@@ -0,0 +1,138 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! IPv6 Stateless Address Autoconfiguration (RFC 4862).
+//!
+//! `InterfaceList` holds the addresses of the node. It starts with the
+//! link-local address formed from the MAC address of the node, and global
+//! addresses are added by autoconfiguration from the prefixes that routers
+//! advertise: `NeighborDiscovery` calls `autoconfigure` for each Prefix
+//! Information option with the autonomous flag set. All addresses use the
+//! interface identifier formed from the MAC address: the modified EUI-64 of
+//! an extended address (RFC 4291, appendix A), or the identifier of a short
+//! address (RFC 4944, section 6).
+//!
+//! Like the prefixes of `NeighborDiscovery`, addresses are not timed: an
+//! address is removed when its prefix is advertised with a valid lifetime of
+//! zero. Duplicate Address Detection is not performed, as the interface
+//! identifiers are formed from link-layer addresses, which are unique on the
+//! network.
+
+use crate::net::ieee802154::MacAddress;
+use crate::net::ipv6::ip_utils::IPAddr;
+
+use kernel::utilities::cells::OptionalCell;
+use kernel::ErrorCode;
+
+/// The number of addresses of the node, including its link-local address.
+pub const MAX_INTERFACE_ADDRS: usize = 4;
+
+/// The length of the prefixes addresses are autoconfigured for, which leaves
+/// 64 bits for the interface identifier.
+pub const SLAAC_PREFIX_LEN: u8 = 64;
+
+pub struct InterfaceList {
+    mac_addr: MacAddress,
+    link_local: IPAddr,
+    /// Global and statically configured addresses.
+    addrs: [OptionalCell<IPAddr>; MAX_INTERFACE_ADDRS - 1],
+}
+
+impl InterfaceList {
+    /// Create the list of a node with the link-layer address `mac_addr`,
+    /// which holds the link-local address formed from it.
+    pub fn new(mac_addr: MacAddress) -> InterfaceList {
+        InterfaceList {
+            mac_addr,
+            link_local: IPAddr::generate_from_mac(mac_addr),
+            addrs: core::array::from_fn(|_| OptionalCell::empty()),
+        }
+    }
+
+    /// The link-layer address the interface identifiers are formed from.
+    pub fn get_mac_addr(&self) -> MacAddress {
+        self.mac_addr
+    }
+
+    /// The link-local address of the node.
+    pub fn get_link_local(&self) -> IPAddr {
+        self.link_local
+    }
+
+    /// The addresses of the node, starting with its link-local address.
+    pub fn iter(&self) -> impl Iterator<Item = IPAddr> + '_ {
+        core::iter::once(self.link_local).chain(self.addrs.iter().filter_map(|addr| addr.extract()))
+    }
+
+    pub fn contains(&self, addr: IPAddr) -> bool {
+        self.link_local == addr || self.addrs.iter().any(|entry| entry.contains(&addr))
+    }
+
+    /// Add an address to the node, for example a statically configured one.
+    /// Returns `ALREADY` if the node has the address, and `NOMEM` if the list
+    /// is full.
+    pub fn add(&self, addr: IPAddr) -> Result<(), ErrorCode> {
+        if self.contains(addr) {
+            return Err(ErrorCode::ALREADY);
+        }
+        self.addrs
+            .iter()
+            .find(|entry| entry.is_none())
+            .map(|entry| entry.set(addr))
+            .ok_or(ErrorCode::NOMEM)
+    }
+
+    /// Remove an address from the node. The link-local address cannot be
+    /// removed.
+    pub fn remove(&self, addr: IPAddr) -> Result<(), ErrorCode> {
+        self.addrs
+            .iter()
+            .find(|entry| entry.contains(&addr))
+            .map(|entry| entry.clear())
+            .ok_or(ErrorCode::INVAL)
+    }
+
+    /// The address formed from the 64-bit `prefix` and the interface
+    /// identifier of the node.
+    pub fn form_address(&self, prefix: &[u8]) -> IPAddr {
+        let mut addr = IPAddr::generate_from_mac(self.mac_addr);
+        addr.set_prefix(prefix, SLAAC_PREFIX_LEN);
+        addr
+    }
+
+    /// Handle an advertised prefix with the autonomous flag: add the address
+    /// formed from it, or remove it if the prefix is no longer valid. Prefixes
+    /// of other lengths than `SLAAC_PREFIX_LEN` and link-local prefixes are
+    /// ignored, as RFC 4862 requires.
+    pub fn autoconfigure(&self, prefix: &[u8], prefix_len: u8, valid: bool) {
+        if prefix_len != SLAAC_PREFIX_LEN {
+            return;
+        }
+        let addr = self.form_address(prefix);
+        if addr.is_unicast_link_local() {
+            return;
+        }
+        // A known address, or a full list, leave nothing to do
+        let _ = if valid {
+            self.add(addr)
+        } else {
+            self.remove(addr)
+        };
+    }
+
+    /// The source address of packets to `dst`: the link-local address for
+    /// link-local and link-scope multicast destinations, and otherwise the
+    /// first global address, if the node has one (RFC 6724, rule 2).
+    pub fn select_source(&self, dst: IPAddr) -> IPAddr {
+        let link_scope =
+            dst.is_unicast_link_local() || (dst.is_multicast() && dst.0[1] & 0x0f <= 2);
+        if link_scope {
+            return self.link_local;
+        }
+        self.addrs
+            .iter()
+            .find_map(|entry| entry.extract())
+            .unwrap_or(self.link_local)
+    }
+}
@@ -0,0 +1,280 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! This file contains the structs and constants of the DNS wire format (RFC
+//! 1035) as multicast DNS (RFC 6762) uses it: the message header, and the
+//! encoding and matching of domain names. Names are given as slices of
+//! labels, such as `[b"tock", b"local"]` for `tock.local`, so that the names
+//! of records can be assembled from the configured labels without copying
+//! them. Names are always encoded without compression.
+
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::stream::SResult;
+use crate::net::stream::{decode_u16, encode_bytes, encode_u16, encode_u8};
+
+/// The UDP port of multicast DNS.
+pub const MDNS_PORT: u16 = 5353;
+
+/// The link-local multicast address of multicast DNS, ff02::fb.
+pub const MDNS_ADDR: IPAddr = IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xfb]);
+
+/// The size of the message header.
+pub const DNS_HDR_LEN: usize = 12;
+
+/// The longest label of a name.
+pub const MAX_LABEL_LEN: usize = 63;
+
+/// Compression pointers followed while matching a name, so that pointer
+/// loops cannot stall the responder.
+const MAX_POINTERS: usize = 16;
+
+/// Header flags.
+pub mod flags {
+    /// The message is a response.
+    pub const RESPONSE: u16 = 0x8000;
+    pub const OPCODE_MASK: u16 = 0x7800;
+    /// The responder is the authority for the names it answers for.
+    pub const AUTHORITATIVE: u16 = 0x0400;
+}
+
+/// Resource record types.
+pub mod rr_type {
+    pub const PTR: u16 = 12;
+    pub const TXT: u16 = 16;
+    pub const AAAA: u16 = 28;
+    pub const SRV: u16 = 33;
+    /// Only valid in questions: all records of the name.
+    pub const ANY: u16 = 255;
+}
+
+/// The Internet class, the only one multicast DNS uses.
+pub const CLASS_IN: u16 = 1;
+/// The class of questions for all classes.
+pub const CLASS_ANY: u16 = 255;
+pub const CLASS_MASK: u16 = 0x7fff;
+/// The top bit of the class of a question asks for a unicast response.
+pub const UNICAST_RESPONSE: u16 = 0x8000;
+/// The top bit of the class of a record tells caches to flush the other
+/// records of the name and type, as the record is unique.
+pub const CACHE_FLUSH: u16 = 0x8000;
+
+/// The `DnsHeader` struct holds the header of a DNS message.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DnsHeader {
+    pub id: u16,
+    pub flags: u16,
+    pub qdcount: u16,
+    pub ancount: u16,
+    pub nscount: u16,
+    pub arcount: u16,
+}
+
+impl DnsHeader {
+    /// This function serializes the `DnsHeader` into the provided buffer.
+    ///
+    /// # Arguments
+    ///
+    /// `buf` - A mutable buffer to serialize the `DnsHeader` into
+    /// `offset` - The current offset into the provided buffer
+    ///
+    /// # Return Value
+    ///
+    /// This function returns the new offset into the buffer wrapped in an
+    /// SResult.
+    pub fn encode(&self, buf: &mut [u8], offset: usize) -> SResult<usize> {
+        stream_len_cond!(buf, DNS_HDR_LEN + offset);
+
+        let mut off = offset;
+        off = enc_consume!(buf, off; encode_u16, self.id);
+        off = enc_consume!(buf, off; encode_u16, self.flags);
+        off = enc_consume!(buf, off; encode_u16, self.qdcount);
+        off = enc_consume!(buf, off; encode_u16, self.ancount);
+        off = enc_consume!(buf, off; encode_u16, self.nscount);
+        off = enc_consume!(buf, off; encode_u16, self.arcount);
+        stream_done!(off, off);
+    }
+
+    /// This function deserializes the `DnsHeader` from the provided buffer.
+    pub fn decode(buf: &[u8]) -> SResult<DnsHeader> {
+        stream_len_cond!(buf, DNS_HDR_LEN);
+        let off = 0;
+        let (off, id) = dec_try!(buf, off; decode_u16);
+        let (off, flags) = dec_try!(buf, off; decode_u16);
+        let (off, qdcount) = dec_try!(buf, off; decode_u16);
+        let (off, ancount) = dec_try!(buf, off; decode_u16);
+        let (off, nscount) = dec_try!(buf, off; decode_u16);
+        let (off, arcount) = dec_try!(buf, off; decode_u16);
+        let header = DnsHeader {
+            id,
+            flags,
+            qdcount,
+            ancount,
+            nscount,
+            arcount,
+        };
+        stream_done!(off, header);
+    }
+}
+
+/// The length of `labels` once encoded as a name.
+pub fn name_len(labels: &[&[u8]]) -> usize {
+    labels.iter().map(|label| label.len() + 1).sum::<usize>() + 1
+}
+
+/// Encode `labels` as an uncompressed name. Labels longer than
+/// `MAX_LABEL_LEN` are an error.
+pub fn encode_name(buf: &mut [u8], labels: &[&[u8]]) -> SResult {
+    stream_len_cond!(buf, name_len(labels));
+    let mut off = 0;
+    for label in labels {
+        stream_cond!(!label.is_empty() && label.len() <= MAX_LABEL_LEN);
+        off = enc_consume!(buf, off; encode_u8, label.len() as u8);
+        off = enc_consume!(buf, off; encode_bytes, label);
+    }
+    off = enc_consume!(buf, off; encode_u8, 0);
+    stream_done!(off);
+}
+
+/// The offset after the name at `offset` in `msg`, which ends with either
+/// the root label or a compression pointer. Returns `None` if the name runs
+/// past the end of the message.
+pub fn skip_name(msg: &[u8], mut offset: usize) -> Option<usize> {
+    loop {
+        let len = *msg.get(offset)? as usize;
+        match len {
+            0 => return Some(offset + 1),
+            // A compression pointer ends the name
+            _ if len & 0xc0 == 0xc0 => return msg.get(offset + 1).map(|_| offset + 2),
+            _ if len & 0xc0 != 0 => return None,
+            _ => offset += len + 1,
+        }
+    }
+}
+
+/// Whether the possibly compressed name at `offset` in `msg` is `labels`.
+/// Names are compared case-insensitively, like DNS does for ASCII letters.
+pub fn name_matches(msg: &[u8], mut offset: usize, labels: &[&[u8]]) -> bool {
+    let mut labels = labels.iter();
+    let mut pointers = 0;
+    loop {
+        let len = match msg.get(offset) {
+            Some(len) => *len as usize,
+            None => return false,
+        };
+        if len & 0xc0 == 0xc0 {
+            pointers += 1;
+            match msg.get(offset + 1) {
+                Some(low) if pointers <= MAX_POINTERS => {
+                    offset = (len & 0x3f) << 8 | *low as usize;
+                    continue;
+                }
+                _ => return false,
+            }
+        } else if len & 0xc0 != 0 {
+            return false;
+        }
+        if len == 0 {
+            return labels.next().is_none();
+        }
+        let label = match msg.get(offset + 1..offset + 1 + len) {
+            Some(label) => label,
+            None => return false,
+        };
+        match labels.next() {
+            Some(expected) if expected.eq_ignore_ascii_case(label) => offset += len + 1,
+            _ => return false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A query for the AAAA record of tock.local, asking for a unicast
+    // response, followed by a question for the SRV record of
+    // imix._coap._udp.local whose suffix is compressed.
+    const QUERY: [u8; 46] = [
+        0x12, 0x34, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // header
+        4, b't', b'o', b'c', b'k', 5, b'l', b'o', b'c', b'a', b'l', 0, // tock.local
+        0x00, 28, 0x80, 0x01, // AAAA, IN with the unicast bit
+        4, b'I', b'M', b'I', b'X', 5, b'_', b'c', b'o', b'a', b'p', 4, b'_', b'u', b'd', b'p',
+        0xc0, 17, // IMIX._coap._udp, then a pointer to local
+    ];
+
+    #[test]
+    fn header_round_trip() {
+        let header = match DnsHeader::decode(&QUERY) {
+            SResult::Done(DNS_HDR_LEN, header) => header,
+            _ => panic!("failed to decode DNS header"),
+        };
+        assert_eq!(header.id, 0x1234);
+        assert_eq!(header.flags, 0);
+        assert_eq!(header.qdcount, 2);
+
+        let mut buf = [0; DNS_HDR_LEN];
+        match header.encode(&mut buf, 0) {
+            SResult::Done(_, DNS_HDR_LEN) => assert_eq!(buf, QUERY[..DNS_HDR_LEN]),
+            _ => panic!("failed to encode DNS header"),
+        }
+    }
+
+    #[test]
+    fn encode_names() {
+        let mut buf = [0; 16];
+        let labels: [&[u8]; 2] = [b"tock", b"local"];
+        assert_eq!(name_len(&labels), 12);
+        match encode_name(&mut buf, &labels) {
+            SResult::Done(12, ()) => assert_eq!(buf[..12], QUERY[12..24]),
+            _ => panic!("failed to encode name"),
+        }
+
+        match encode_name(&mut buf, &[b"", b"local"]) {
+            SResult::Error(()) => {}
+            _ => panic!("encoded an empty label"),
+        }
+        let mut buf = [0; 80];
+        match encode_name(&mut buf, &[&[b'a'; MAX_LABEL_LEN + 1]]) {
+            SResult::Error(()) => {}
+            _ => panic!("encoded a label longer than MAX_LABEL_LEN"),
+        }
+    }
+
+    #[test]
+    fn match_names() {
+        assert!(name_matches(&QUERY, 12, &[b"tock", b"local"]));
+        assert!(name_matches(&QUERY, 12, &[b"TOCK", b"Local"]));
+        assert!(!name_matches(&QUERY, 12, &[b"tock"]));
+        assert!(!name_matches(&QUERY, 12, &[b"tock", b"local", b"com"]));
+        assert!(!name_matches(&QUERY, 12, &[b"imix", b"local"]));
+
+        // Through the compression pointer.
+        assert!(name_matches(
+            &QUERY,
+            28,
+            &[b"imix", b"_coap", b"_udp", b"local"]
+        ));
+        assert!(!name_matches(&QUERY, 28, &[b"imix", b"_coap", b"_udp"]));
+    }
+
+    #[test]
+    fn skip_names() {
+        assert_eq!(skip_name(&QUERY, 12), Some(24));
+        // The compressed name ends with its pointer.
+        assert_eq!(skip_name(&QUERY, 28), Some(QUERY.len()));
+        // Truncated names.
+        assert_eq!(skip_name(&QUERY[..20], 12), None);
+        assert_eq!(skip_name(&QUERY[..QUERY.len() - 1], 28), None);
+        // Reserved label types.
+        assert_eq!(skip_name(&[0x40, 0], 0), None);
+    }
+
+    #[test]
+    fn pointer_loops_do_not_match() {
+        // A name whose pointer points to itself.
+        let msg = [4, b't', b'o', b'c', b'k', 0xc0, 0];
+        assert!(!name_matches(&msg, 0, &[b"tock", b"local"]));
+        assert!(!name_matches(&msg, 5, &[b"tock", b"local"]));
+    }
+}
@@ -0,0 +1,9 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+pub mod message;
+pub mod responder;
+
+pub use self::message::{MDNS_ADDR, MDNS_PORT};
+pub use self::responder::{MdnsResponder, Service};
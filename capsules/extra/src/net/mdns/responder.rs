@@ -0,0 +1,571 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Multicast DNS responder (RFC 6762) with DNS-based service discovery (RFC
+//! 6763).
+//!
+//! An `MdnsResponder` is bound to the mDNS port, and answers the questions
+//! hosts on the link ask about the node:
+//!
+//!   * `<hostname>.local` has an AAAA record for each address of the node's
+//!     interface list, so that hosts find the node by name rather than by a
+//!     static address.
+//!   * Each configured `Service`, such as a CoAP server, is advertised with
+//!     the PTR record of its service type, for example `_coap._udp.local`,
+//!     and the SRV and TXT records of its instance. The service types are
+//!     listed by the PTR records of `_services._dns-sd._udp.local`.
+//!
+//! Answers to PTR and SRV questions carry the records a host needs next as
+//! additional records. All records are announced twice, one second apart,
+//! when `announce` is called, which should be done once the network stack
+//! is set up and again after the addresses of the node change.
+//!
+//! The implementation is kept small:
+//!
+//!   * The hostname and instance names are assumed to be unique on the link:
+//!     there is no probing, and responses of other hosts are ignored, so
+//!     conflicts are not detected.
+//!   * Answers are sent right away, without the random delay of shared
+//!     records, and known answers of queries are not used to suppress them.
+//!   * Responses are built in a single buffer, so queries that arrive while
+//!     a response is being sent are dropped, and records that do not fit in
+//!     the buffer are left out.
+//!   * Queries from ports other than the mDNS port are answered as legacy
+//!     unicast queries: the answer goes back to the querier, echoes its
+//!     question and uses short TTLs.
+//!   * Only the first `MAX_SERVICES` services are advertised.
+//!
+//! Usage
+//! -----
+//! `components::mdns::MdnsComponent` creates a responder bound to the mDNS
+//! port, joins the mDNS group ff02::fb on its binding so that queries reach
+//! it, and sets the hostname and services it advertises:
+//!
+//! ```rust,ignore
+//! static SERVICES: [Service; 1] = [Service {
+//!     instance: "imix",
+//!     service: "_coap",
+//!     protocol: "_udp",
+//!     port: 5683,
+//!     txt: &[],
+//! }];
+//! responder.announce();
+//! ```
+
+use core::cell::Cell;
+
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::ipv6::slaac::InterfaceList;
+use crate::net::mdns::message::{encode_name, name_matches, skip_name};
+use crate::net::mdns::message::{flags, rr_type, DnsHeader};
+use crate::net::mdns::message::{CACHE_FLUSH, CLASS_ANY, CLASS_IN, CLASS_MASK, UNICAST_RESPONSE};
+use crate::net::mdns::message::{DNS_HDR_LEN, MDNS_ADDR, MDNS_PORT};
+use crate::net::network_capabilities::NetworkCapability;
+use crate::net::stream::{encode_bytes, encode_u16, encode_u32, encode_u8, SResult};
+use crate::net::udp::udp_recv::UDPRecvClient;
+use crate::net::udp::udp_send::{UDPSendClient, UDPSender};
+
+use kernel::hil::time::{self, Alarm, ConvertTicks};
+use kernel::utilities::cells::MapCell;
+use kernel::utilities::leasable_buffer::LeasableMutableBuffer;
+use kernel::ErrorCode;
+
+/// The length of the buffer responses are built in.
+pub const TX_BUF_LEN: usize = 384;
+
+/// The number of services a responder advertises.
+pub const MAX_SERVICES: usize = 7;
+
+/// The TTL of address and SRV records, which change with the host (RFC
+/// 6762, section 10).
+const HOST_TTL: u32 = 120;
+/// The TTL of the other records.
+const SERVICE_TTL: u32 = 4500;
+/// The largest TTL of answers to legacy unicast queries.
+const LEGACY_TTL: u32 = 10;
+
+const ANNOUNCEMENTS: u8 = 2;
+const ANNOUNCE_INTERVAL_MS: u32 = 1000;
+/// The delay before the first announcement, and before retrying one when the
+/// buffer is busy.
+const ANNOUNCE_DELAY_MS: u32 = 100;
+
+const LOCAL: &[u8] = b"local";
+const SERVICES_NAME: [&[u8]; 4] = [b"_services", b"_dns-sd", b"_udp", LOCAL];
+
+/// A service advertised with DNS-SD, such as a CoAP server. Its instance is
+/// named `<instance>.<service>.<protocol>.local`.
+pub struct Service {
+    /// The name of the instance, which may contain spaces and capitals.
+    pub instance: &'static str,
+    /// The service type, such as `_coap`.
+    pub service: &'static str,
+    /// The transport of the service, `_udp` or `_tcp`.
+    pub protocol: &'static str,
+    pub port: u16,
+    /// The `key=value` strings of the TXT record.
+    pub txt: &'static [&'static str],
+}
+
+/// The records of the responder, except that `Address` stands for the AAAA
+/// records of all addresses of the node.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Record {
+    Address,
+    /// The PTR record of `_services._dns-sd._udp.local` to the service type
+    /// of a service.
+    ServiceType(usize),
+    /// The PTR record of the service type of a service to its instance.
+    Instance(usize),
+    Srv(usize),
+    Txt(usize),
+}
+
+impl Record {
+    /// The bit of the record in the sets of records to send.
+    fn bit(self) -> u32 {
+        match self {
+            Record::Address => 1,
+            Record::ServiceType(i) => 1 << (1 + 4 * i),
+            Record::Instance(i) => 1 << (2 + 4 * i),
+            Record::Srv(i) => 1 << (3 + 4 * i),
+            Record::Txt(i) => 1 << (4 + 4 * i),
+        }
+    }
+
+    fn rr_type(self) -> u16 {
+        match self {
+            Record::Address => rr_type::AAAA,
+            Record::ServiceType(_) | Record::Instance(_) => rr_type::PTR,
+            Record::Srv(_) => rr_type::SRV,
+            Record::Txt(_) => rr_type::TXT,
+        }
+    }
+
+    /// Whether the record is unique to the node, rather than shared with
+    /// the records of other hosts.
+    fn is_unique(self) -> bool {
+        !matches!(self, Record::ServiceType(_) | Record::Instance(_))
+    }
+
+    fn ttl(self) -> u32 {
+        match self {
+            Record::Address | Record::Srv(_) => HOST_TTL,
+            _ => SERVICE_TTL,
+        }
+    }
+}
+
+pub struct MdnsResponder<'a, A: Alarm<'a>> {
+    udp_sender: &'a dyn UDPSender<'a>,
+    alarm: &'a A,
+    net_cap: &'static NetworkCapability,
+    interface_list: &'a InterfaceList,
+    hostname: &'static str,
+    services: &'static [Service],
+    buffer: MapCell<LeasableMutableBuffer<'static, u8>>,
+
+    /// The announcements left to send.
+    announcements: Cell<u8>,
+}
+
+impl<'a, A: Alarm<'a>> MdnsResponder<'a, A> {
+    pub fn new(
+        udp_sender: &'a dyn UDPSender<'a>,
+        alarm: &'a A,
+        net_cap: &'static NetworkCapability,
+        interface_list: &'a InterfaceList,
+        hostname: &'static str,
+        services: &'static [Service],
+        buffer: LeasableMutableBuffer<'static, u8>,
+    ) -> MdnsResponder<'a, A> {
+        MdnsResponder {
+            udp_sender,
+            alarm,
+            net_cap,
+            interface_list,
+            hostname,
+            services: &services[..services.len().min(MAX_SERVICES)],
+            buffer: MapCell::new(buffer),
+            announcements: Cell::new(0),
+        }
+    }
+
+    /// Announce all records of the responder to the link.
+    pub fn announce(&self) {
+        self.announcements.set(ANNOUNCEMENTS);
+        self.alarm.set_alarm(
+            self.alarm.now(),
+            self.alarm.ticks_from_ms(ANNOUNCE_DELAY_MS),
+        );
+    }
+
+    /// All records of the responder.
+    fn records(&self) -> impl Iterator<Item = Record> {
+        core::iter::once(Record::Address).chain((0..self.services.len()).flat_map(|i| {
+            [
+                Record::ServiceType(i),
+                Record::Instance(i),
+                Record::Srv(i),
+                Record::Txt(i),
+            ]
+        }))
+    }
+
+    fn host_name(&self) -> [&[u8]; 2] {
+        [self.hostname.as_bytes(), LOCAL]
+    }
+
+    fn service_name(&self, i: usize) -> [&[u8]; 3] {
+        let service = &self.services[i];
+        [
+            service.service.as_bytes(),
+            service.protocol.as_bytes(),
+            LOCAL,
+        ]
+    }
+
+    fn instance_name(&self, i: usize) -> [&[u8]; 4] {
+        let service = &self.services[i];
+        [
+            service.instance.as_bytes(),
+            service.service.as_bytes(),
+            service.protocol.as_bytes(),
+            LOCAL,
+        ]
+    }
+
+    /// Whether `record` is the owner of the name at `offset` in `msg`.
+    fn owns_name(&self, record: Record, msg: &[u8], offset: usize) -> bool {
+        match record {
+            Record::Address => name_matches(msg, offset, &self.host_name()),
+            Record::ServiceType(i) => {
+                // Service types shared by several services are listed once
+                let first = self.services.iter().position(|service| {
+                    service.service == self.services[i].service
+                        && service.protocol == self.services[i].protocol
+                });
+                first == Some(i) && name_matches(msg, offset, &SERVICES_NAME)
+            }
+            Record::Instance(i) => name_matches(msg, offset, &self.service_name(i)),
+            Record::Srv(i) | Record::Txt(i) => name_matches(msg, offset, &self.instance_name(i)),
+        }
+    }
+
+    /// The records answering the question for the name at `offset` in `msg`
+    /// and `qtype`.
+    fn answer(&self, msg: &[u8], offset: usize, qtype: u16) -> u32 {
+        self.records()
+            .filter(|record| qtype == rr_type::ANY || qtype == record.rr_type())
+            .filter(|record| self.owns_name(*record, msg, offset))
+            .fold(0, |answers, record| answers | record.bit())
+    }
+
+    /// The records a host needs after receiving `answers`.
+    fn additional(&self, answers: u32) -> u32 {
+        self.records()
+            .filter(|record| answers & record.bit() != 0)
+            .fold(0, |additional, record| match record {
+                Record::Instance(i) => {
+                    additional | Record::Srv(i).bit() | Record::Txt(i).bit() | Record::Address.bit()
+                }
+                Record::Srv(_) => additional | Record::Address.bit(),
+                _ => additional,
+            })
+            & !answers
+    }
+
+    /// Encode the resource record of `record` with the owner name `name`.
+    fn encode_rr(
+        &self,
+        buf: &mut [u8],
+        record: Record,
+        name: &[&[u8]],
+        legacy: bool,
+        rdata: impl FnOnce(&mut [u8]) -> SResult,
+    ) -> SResult {
+        let class = if record.is_unique() && !legacy {
+            CLASS_IN | CACHE_FLUSH
+        } else {
+            CLASS_IN
+        };
+        let ttl = if legacy {
+            record.ttl().min(LEGACY_TTL)
+        } else {
+            record.ttl()
+        };
+        let mut off = enc_consume!(buf, 0; encode_name, name);
+        off = enc_consume!(buf, off; encode_u16, record.rr_type());
+        off = enc_consume!(buf, off; encode_u16, class);
+        off = enc_consume!(buf, off; encode_u32, ttl);
+        let rdata_off = off + 2;
+        let end = enc_consume!(buf, rdata_off; rdata);
+        enc_consume!(buf, off; encode_u16, (end - rdata_off) as u16);
+        stream_done!(end);
+    }
+
+    /// Encode the resource records of `record`, returning the offset after
+    /// them and their number. Records that do not fit are left out.
+    fn encode_record(
+        &self,
+        buf: &mut [u8],
+        offset: usize,
+        record: Record,
+        legacy: bool,
+    ) -> (usize, u16) {
+        let encoded = match record {
+            Record::Address => {
+                let (mut off, mut count) = (offset, 0);
+                for addr in self.interface_list.iter() {
+                    let rr =
+                        self.encode_rr(&mut buf[off..], record, &self.host_name(), legacy, |buf| {
+                            encode_bytes(buf, &addr.0)
+                        });
+                    match rr.done() {
+                        Some((len, ())) => {
+                            off += len;
+                            count += 1;
+                        }
+                        None => break,
+                    }
+                }
+                return (off, count);
+            }
+            Record::ServiceType(i) => {
+                self.encode_rr(&mut buf[offset..], record, &SERVICES_NAME, legacy, |buf| {
+                    encode_name(buf, &self.service_name(i))
+                })
+            }
+            Record::Instance(i) => self.encode_rr(
+                &mut buf[offset..],
+                record,
+                &self.service_name(i),
+                legacy,
+                |buf| encode_name(buf, &self.instance_name(i)),
+            ),
+            Record::Srv(i) => {
+                let port = self.services[i].port;
+                self.encode_rr(
+                    &mut buf[offset..],
+                    record,
+                    &self.instance_name(i),
+                    legacy,
+                    |buf| {
+                        // Priority and weight
+                        let mut off = enc_consume!(buf, 0; encode_u16, 0);
+                        off = enc_consume!(buf, off; encode_u16, 0);
+                        off = enc_consume!(buf, off; encode_u16, port);
+                        off = enc_consume!(buf, off; encode_name, &self.host_name());
+                        stream_done!(off);
+                    },
+                )
+            }
+            Record::Txt(i) => {
+                let txt = self.services[i].txt;
+                self.encode_rr(
+                    &mut buf[offset..],
+                    record,
+                    &self.instance_name(i),
+                    legacy,
+                    |buf| {
+                        let mut off = 0;
+                        for string in txt.iter().filter(|string| string.len() <= 255) {
+                            off = enc_consume!(buf, off; encode_u8, string.len() as u8);
+                            off = enc_consume!(buf, off; encode_bytes, string.as_bytes());
+                        }
+                        // A TXT record without strings holds a single empty one
+                        if txt.is_empty() {
+                            off = enc_consume!(buf, off; encode_u8, 0);
+                        }
+                        stream_done!(off);
+                    },
+                )
+            }
+        };
+        match encoded.done() {
+            Some((len, ())) => (offset + len, 1),
+            None => (offset, 0),
+        }
+    }
+
+    /// Send a response with the records of `answers` and `additional`. The
+    /// questions of legacy unicast queries are echoed with their ID.
+    fn respond(
+        &self,
+        dst: IPAddr,
+        dst_port: u16,
+        query: Option<(u16, u16, &[u8])>,
+        answers: u32,
+        additional: u32,
+    ) -> Result<(), ErrorCode> {
+        let mut buf = self.buffer.take().ok_or(ErrorCode::BUSY)?;
+        let legacy = query.is_some();
+        let (id, qdcount, questions) = query.unwrap_or((0, 0, &[]));
+        if DNS_HDR_LEN + questions.len() > buf.len() {
+            self.buffer.replace(buf);
+            return Err(ErrorCode::SIZE);
+        }
+
+        let mut off = DNS_HDR_LEN;
+        buf[off..off + questions.len()].copy_from_slice(questions);
+        off += questions.len();
+        let mut counts = [0; 2];
+        for (count, set) in counts.iter_mut().zip([answers, additional]) {
+            for record in self.records().filter(|record| set & record.bit() != 0) {
+                let (end, records) = self.encode_record(&mut buf[..], off, record, legacy);
+                off = end;
+                *count += records;
+            }
+        }
+        let header = DnsHeader {
+            id,
+            flags: flags::RESPONSE | flags::AUTHORITATIVE,
+            qdcount,
+            ancount: counts[0],
+            nscount: 0,
+            arcount: counts[1],
+        };
+        let _ = header.encode(&mut buf[..], 0);
+
+        buf.slice(0..off);
+        self.udp_sender
+            .send_to(dst, dst_port, buf, self.net_cap)
+            .map_err(|mut buf| {
+                buf.reset();
+                self.buffer.replace(buf);
+                ErrorCode::FAIL
+            })
+    }
+}
+
+impl<'a, A: Alarm<'a>> UDPRecvClient for MdnsResponder<'a, A> {
+    fn receive(
+        &self,
+        src_addr: IPAddr,
+        _dst_addr: IPAddr,
+        src_port: u16,
+        _dst_port: u16,
+        payload: &[u8],
+    ) {
+        let header = match DnsHeader::decode(payload).done() {
+            Some((_, header)) => header,
+            None => return,
+        };
+        // Responses of other hosts, and other operations than queries
+        if header.flags & (flags::RESPONSE | flags::OPCODE_MASK) != 0 {
+            return;
+        }
+
+        let mut off = DNS_HDR_LEN;
+        let mut answers = 0;
+        let mut unicast = false;
+        for _ in 0..header.qdcount {
+            let end = match skip_name(payload, off) {
+                Some(end) if end + 4 <= payload.len() => end,
+                _ => return,
+            };
+            let qtype = u16::from_be_bytes([payload[end], payload[end + 1]]);
+            let qclass = u16::from_be_bytes([payload[end + 2], payload[end + 3]]);
+            if matches!(qclass & CLASS_MASK, CLASS_IN | CLASS_ANY) {
+                unicast |= qclass & UNICAST_RESPONSE != 0;
+                answers |= self.answer(payload, off, qtype);
+            }
+            off = end + 4;
+        }
+        if answers == 0 {
+            return;
+        }
+
+        let additional = self.additional(answers);
+        let _ = if src_port != MDNS_PORT {
+            let query = (header.id, header.qdcount, &payload[DNS_HDR_LEN..off]);
+            self.respond(src_addr, src_port, Some(query), answers, additional)
+        } else if unicast {
+            self.respond(src_addr, MDNS_PORT, None, answers, additional)
+        } else {
+            self.respond(MDNS_ADDR, MDNS_PORT, None, answers, additional)
+        };
+    }
+}
+
+impl<'a, A: Alarm<'a>> UDPSendClient for MdnsResponder<'a, A> {
+    fn send_done(
+        &self,
+        _result: Result<(), ErrorCode>,
+        mut dgram: LeasableMutableBuffer<'static, u8>,
+    ) {
+        dgram.reset();
+        self.buffer.replace(dgram);
+    }
+}
+
+impl<'a, A: Alarm<'a>> time::AlarmClient for MdnsResponder<'a, A> {
+    fn alarm(&self) {
+        let announcements = self.announcements.get();
+        if announcements == 0 {
+            return;
+        }
+        let all = self.records().fold(0, |all, record| all | record.bit());
+        let delay = match self.respond(MDNS_ADDR, MDNS_PORT, None, all, 0) {
+            Ok(()) => {
+                self.announcements.set(announcements - 1);
+                ANNOUNCE_INTERVAL_MS
+            }
+            Err(_) => ANNOUNCE_DELAY_MS,
+        };
+        if self.announcements.get() > 0 {
+            self.alarm
+                .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(delay));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn all_records() -> impl Iterator<Item = Record> {
+        core::iter::once(Record::Address).chain((0..MAX_SERVICES).flat_map(|i| {
+            [
+                Record::ServiceType(i),
+                Record::Instance(i),
+                Record::Srv(i),
+                Record::Txt(i),
+            ]
+        }))
+    }
+
+    #[test]
+    fn records_have_distinct_bits() {
+        let mut all = 0;
+        for record in all_records() {
+            assert_eq!(record.bit().count_ones(), 1);
+            assert_eq!(all & record.bit(), 0, "{:?} shares its bit", record);
+            all |= record.bit();
+        }
+    }
+
+    #[test]
+    fn unique_records() {
+        // Records that name the node are unique and change with it, while
+        // PTR records are shared with the other hosts of the service.
+        for record in all_records() {
+            match record {
+                Record::Address | Record::Srv(_) => {
+                    assert!(record.is_unique());
+                    assert_eq!(record.ttl(), HOST_TTL);
+                }
+                Record::Txt(_) => {
+                    assert!(record.is_unique());
+                    assert_eq!(record.ttl(), SERVICE_TTL);
+                }
+                Record::ServiceType(_) | Record::Instance(_) => {
+                    assert!(!record.is_unique());
+                    assert_eq!(record.rr_type(), rr_type::PTR);
+                    assert_eq!(record.ttl(), SERVICE_TTL);
+                }
+            }
+        }
+    }
+}
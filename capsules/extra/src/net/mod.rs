@@ -4,14 +4,18 @@
 
 //! Modules for IPv6 over 6LoWPAN stack
 
+pub mod buffer;
 pub mod frag_utils;
 pub mod sixlowpan;
 pub mod util;
 #[macro_use]
 pub mod stream;
+pub mod coap;
+pub mod dtls;
 pub mod icmpv6;
 pub mod ieee802154;
 pub mod ipv6;
+pub mod mdns;
 pub mod network_capabilities;
 pub mod tcp;
 pub mod thread;
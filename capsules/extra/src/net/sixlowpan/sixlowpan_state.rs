@@ -237,6 +237,8 @@ use crate::net::sixlowpan::sixlowpan_compression;
 use crate::net::sixlowpan::sixlowpan_compression::{is_lowpan, ContextStore};
 use crate::net::util::{network_slice_to_u16, u16_to_network_slice};
 
+use capsules_core::packet_trace::{Direction, Layer, PacketTracer};
+
 use core::cell::Cell;
 use core::cmp::min;
 
@@ -244,7 +246,7 @@ use kernel::collections::list::{List, ListLink, ListNode};
 use kernel::hil::radio;
 use kernel::hil::time;
 use kernel::hil::time::{Frequency, Ticks};
-use kernel::utilities::cells::{MapCell, TakeCell};
+use kernel::utilities::cells::{MapCell, OptionalCell, TakeCell};
 use kernel::ErrorCode;
 
 // Reassembly timeout in seconds
@@ -798,6 +800,7 @@ pub struct Sixlowpan<'a, A: time::Alarm<'a>, C: ContextStore> {
     clock: &'a A,
     tx_dgram_tag: Cell<u16>,
     rx_client: Cell<Option<&'a dyn SixlowpanRxClient>>,
+    tracer: OptionalCell<&'a dyn PacketTracer>,
 
     // Receive state
     rx_states: List<'a, RxState<'a>>,
@@ -813,6 +816,11 @@ impl<'a, A: time::Alarm<'a>, C: ContextStore> RxClient for Sixlowpan<'a, A, C> {
         let src_mac_addr = header.src_addr.unwrap_or(MacAddress::Short(0));
         let dst_mac_addr = header.dst_addr.unwrap_or(MacAddress::Short(0));
 
+        self.tracer.map(|tracer| {
+            let frame = &buf[radio::PSDU_OFFSET..data_offset + data_len];
+            tracer.packet(Layer::Lowpan, Direction::Receive, frame, frame.len());
+        });
+
         let (rx_state, returncode) = self.receive_frame(
             &buf[data_offset..data_offset + data_len],
             data_len,
@@ -876,11 +884,18 @@ impl<'a, A: time::Alarm<'a>, C: ContextStore> Sixlowpan<'a, A, C> {
             clock: clock,
             tx_dgram_tag: Cell::new(0),
             rx_client: Cell::new(None),
+            tracer: OptionalCell::empty(),
 
             rx_states: List::new(),
         }
     }
 
+    /// Sets the tracer that is given every received frame. Transmitted
+    /// frames are traced by the sender, see `IP6Sender::set_tracer`.
+    pub fn set_tracer(&self, tracer: &'a dyn PacketTracer) {
+        self.tracer.set(tracer);
+    }
+
     fn receive_frame(
         &self,
         packet: &[u8],
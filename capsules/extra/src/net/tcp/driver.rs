@@ -0,0 +1,1011 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! TCP userspace interface.
+//!
+//! Implements a small TCP on top of the IPv6/6LoWPAN stack, and a
+//! socket-style interface that lets processes open connections to and
+//! accept connections from other hosts. Segments are queued on the UDP send
+//! mux, which passes packets of any transport protocol to the shared IPv6
+//! sender, and the IPv6 receiver passes all received TCP segments to this
+//! driver.
+//!
+//! The implementation is deliberately simple:
+//!
+//! - The driver has a fixed table of connections shared by all processes.
+//!   Each entry is a socket, which its process refers to by its index in the
+//!   table. Like UDP ports, a local port can only be bound to one socket at a
+//!   time.
+//! - Each connection has at most one segment in flight. A send completes once
+//!   the peer has acknowledged all of its data, and sends larger than one
+//!   segment are rejected.
+//! - Unacknowledged segments are retransmitted with exponential backoff,
+//!   driven by a virtual alarm. A connection is reset after `MAX_RETRIES`
+//!   retransmissions of the same segment.
+//! - A listening socket turns into the connection it accepts. To accept
+//!   another connection, a process listens on another socket.
+//! - Data is only accepted in order, and only while the process is receiving
+//!   on the socket. The advertised window is the size of the receive buffer
+//!   while receiving, and zero otherwise.
+//! - TCP options are neither sent nor interpreted, and urgent data is treated
+//!   as normal data.
+//!
+//! Initial sequence numbers are drawn from `rng`, so off-path attackers can't
+//! predict them (RFC 6528). Boards should pass a cryptographically secure
+//! generator, e.g. `capsules_extra::csprng::Csprng`.
+
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::ipv6::ipv6_recv::IP6RecvClient;
+use crate::net::ipv6::{IP6Header, TransportHeader};
+use crate::net::network_capabilities::NetworkCapability;
+use crate::net::tcp::{tcp_flags, TCPHeader, TCP_HDR_LEN};
+use crate::net::udp::udp_send::{UDPSendClient, UDPSender};
+
+use core::cell::Cell;
+use core::cmp;
+use core::mem::size_of;
+
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::rng::Random;
+use kernel::hil::time::{self, Alarm, ConvertTicks};
+use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{MapCell, OptionalCell};
+use kernel::utilities::leasable_buffer::LeasableMutableBuffer;
+use kernel::{ErrorCode, ProcessId};
+
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Tcp as usize;
+
+/// The number of connections the driver can have open at a time.
+pub const MAX_NUM_CONNECTIONS: usize = 4;
+
+/// Granularity of the retransmission and TIME-WAIT timers.
+const TICK_MS: u32 = 250;
+/// Retransmission timeout of the first transmission of a segment. It doubles
+/// with every retransmission.
+const RTO_MS: u32 = 1000;
+/// The number of retransmissions of a segment before the connection is reset.
+const MAX_RETRIES: u8 = 5;
+/// How long a closed connection stays in TIME-WAIT before it is freed.
+const TIME_WAIT_MS: u32 = 2000;
+/// First of the ports assigned to sockets that connect without binding.
+const EPHEMERAL_PORT_START: u16 = 49152;
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    /// Data to send.
+    pub const WRITE: usize = 0;
+    /// IPv6 address to connect to.
+    pub const REMOTE: usize = 1;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 2;
+}
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    /// Received data.
+    pub const READ: usize = 0;
+    /// Address and port of the peer, written when a connection is established.
+    pub const REMOTE: usize = 1;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 2;
+}
+
+/// Ids for upcalls
+mod upcall {
+    /// A connection changed state, see `event`.
+    pub const EVENT: usize = 0;
+    /// The peer acknowledged all data of a send.
+    pub const SEND_DONE: usize = 1;
+    /// Data was written to the receive buffer.
+    pub const RECEIVED: usize = 2;
+    /// The number of upcalls the kernel stores for this grant
+    pub const COUNT: u8 = 3;
+}
+
+/// Events reported through the `upcall::EVENT` upcall.
+mod event {
+    pub const CONNECTED: usize = 0;
+    pub const PEER_CLOSED: usize = 1;
+    pub const CLOSED: usize = 2;
+    pub const RESET: usize = 3;
+    pub const TIMEOUT: usize = 4;
+}
+
+/// TCP connection states (RFC 793). `Closed` is the state of sockets that
+/// are neither listening nor connected.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum State {
+    Closed,
+    Listen,
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+}
+
+impl State {
+    /// Whether both ends have agreed on sequence numbers, so that a reset
+    /// must be sent to abandon the connection.
+    fn is_synchronized(&self) -> bool {
+        !matches!(
+            self,
+            State::Closed | State::Listen | State::SynSent | State::TimeWait
+        )
+    }
+}
+
+/// An entry of the connection table.
+pub struct Connection {
+    owner: OptionalCell<ProcessId>,
+    state: Cell<State>,
+    local_port: Cell<u16>,
+    remote_addr: Cell<IPAddr>,
+    remote_port: Cell<u16>,
+    /// Oldest unacknowledged sequence number, the start of the segment in
+    /// flight.
+    snd_una: Cell<u32>,
+    /// Sequence number following the segment in flight.
+    snd_nxt: Cell<u32>,
+    /// Next sequence number expected from the peer.
+    rcv_nxt: Cell<u32>,
+    /// Advertised window: the size of the receive buffer while the process
+    /// is receiving, zero otherwise.
+    rcv_wnd: Cell<u16>,
+    /// Length of the data the process is sending, zero if none.
+    send_len: Cell<usize>,
+    /// The segment in flight has to be (re)transmitted.
+    output: Cell<bool>,
+    /// An acknowledgment has to be sent.
+    ack_now: Cell<bool>,
+    /// Time left on the retransmission or TIME-WAIT timer, zero if stopped.
+    timer_ms: Cell<u32>,
+    retries: Cell<u8>,
+}
+
+impl Default for Connection {
+    fn default() -> Connection {
+        Connection {
+            owner: OptionalCell::empty(),
+            state: Cell::new(State::Closed),
+            local_port: Cell::new(0),
+            remote_addr: Cell::new(IPAddr::new()),
+            remote_port: Cell::new(0),
+            snd_una: Cell::new(0),
+            snd_nxt: Cell::new(0),
+            rcv_nxt: Cell::new(0),
+            rcv_wnd: Cell::new(0),
+            send_len: Cell::new(0),
+            output: Cell::new(false),
+            ack_now: Cell::new(false),
+            timer_ms: Cell::new(0),
+            retries: Cell::new(0),
+        }
+    }
+}
+
+impl Connection {
+    pub fn new() -> Connection {
+        Connection::default()
+    }
+
+    /// The sequence space of the segment in flight in the current state: the
+    /// SYN, pending data, or the FIN once all data has been acknowledged.
+    fn segment_len(&self) -> usize {
+        match self.state.get() {
+            State::SynSent | State::SynReceived => 1,
+            State::Established | State::CloseWait => self.send_len.get(),
+            State::FinWait1 | State::Closing | State::LastAck => cmp::max(self.send_len.get(), 1),
+            _ => 0,
+        }
+    }
+
+    /// The control bits of the segment in flight.
+    fn segment_flags(&self) -> u8 {
+        match self.state.get() {
+            State::SynSent => tcp_flags::SYN,
+            State::SynReceived => tcp_flags::SYN | tcp_flags::ACK,
+            _ if self.send_len.get() > 0 => tcp_flags::PSH | tcp_flags::ACK,
+            _ => tcp_flags::FIN | tcp_flags::ACK,
+        }
+    }
+
+    /// Mark the segment in flight for transmission.
+    fn queue(&self) {
+        let len = self.segment_len() as u32;
+        self.snd_nxt.set(self.snd_una.get().wrapping_add(len));
+        self.output.set(len > 0);
+    }
+
+    /// The endpoint of the peer, as written to the `rw_allow::REMOTE` buffer:
+    /// the address followed by the port in network byte order.
+    fn remote_endpoint(&self) -> [u8; size_of::<IPAddr>() + 2] {
+        let mut endpoint = [0; size_of::<IPAddr>() + 2];
+        endpoint[..size_of::<IPAddr>()].copy_from_slice(&self.remote_addr.get().0);
+        endpoint[size_of::<IPAddr>()..].copy_from_slice(&self.remote_port.get().to_be_bytes());
+        endpoint
+    }
+}
+
+#[derive(Default)]
+pub struct App {}
+
+pub struct TCPDriver<'a, A: Alarm<'a>> {
+    /// Sender of segments, through the UDP send mux.
+    sender: &'a dyn UDPSender<'a>,
+
+    /// Alarm driving the retransmission and TIME-WAIT timers.
+    alarm: &'a A,
+
+    /// Source of initial sequence numbers.
+    rng: &'a dyn Random<'a>,
+
+    /// Grant of apps that use this driver.
+    apps: Grant<
+        App,
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+
+    /// Connection table, shared by all processes.
+    connections: &'a [Connection],
+
+    /// Maximum length of the payload of a segment.
+    max_seg_len: usize,
+
+    kernel_buffer: MapCell<LeasableMutableBuffer<'static, u8>>,
+
+    /// Reset to send in reply to a segment that belongs to no connection.
+    pending_reset: OptionalCell<(IPAddr, TCPHeader)>,
+
+    /// Next candidate for an ephemeral port.
+    next_port: Cell<u16>,
+
+    net_cap: &'static NetworkCapability,
+}
+
+impl<'a, A: Alarm<'a>> TCPDriver<'a, A> {
+    pub fn new(
+        sender: &'a dyn UDPSender<'a>,
+        alarm: &'a A,
+        rng: &'a dyn Random<'a>,
+        grant: Grant<
+            App,
+            UpcallCount<{ upcall::COUNT }>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+        connections: &'a [Connection],
+        max_seg_len: usize,
+        kernel_buffer: LeasableMutableBuffer<'static, u8>,
+        net_cap: &'static NetworkCapability,
+    ) -> TCPDriver<'a, A> {
+        TCPDriver {
+            sender,
+            alarm,
+            rng,
+            apps: grant,
+            connections,
+            max_seg_len,
+            kernel_buffer: MapCell::new(kernel_buffer),
+            pending_reset: OptionalCell::empty(),
+            next_port: Cell::new(EPHEMERAL_PORT_START),
+            net_cap,
+        }
+    }
+
+    /// The connection `socket` if it belongs to `processid`.
+    fn socket(&self, socket: usize, processid: ProcessId) -> Result<&Connection, ErrorCode> {
+        self.connections
+            .get(socket)
+            .filter(|conn| conn.owner.contains(&processid))
+            .ok_or(ErrorCode::INVAL)
+    }
+
+    /// Whether a table entry can be handed out, either because it is unused
+    /// or because its process no longer exists.
+    fn is_free(&self, conn: &Connection) -> bool {
+        conn.owner
+            .map_or(true, |owner| self.apps.enter(*owner, |_, _| ()).is_err())
+    }
+
+    /// Whether a socket other than `socket` is bound to `port`.
+    fn is_bound(&self, port: u16, socket: usize) -> bool {
+        self.connections
+            .iter()
+            .enumerate()
+            .any(|(i, conn)| i != socket && !self.is_free(conn) && conn.local_port.get() == port)
+    }
+
+    /// Pick an ephemeral port that no socket is bound to.
+    fn ephemeral_port(&self, socket: usize) -> u16 {
+        loop {
+            let port = self.next_port.get();
+            self.next_port
+                .set(port.checked_add(1).unwrap_or(EPHEMERAL_PORT_START));
+            if !self.is_bound(port, socket) {
+                return port;
+            }
+        }
+    }
+
+    /// Find the connection a segment belongs to: the one with matching
+    /// endpoints, or else a socket listening on the destination port.
+    fn lookup(&self, local_port: u16, remote_addr: IPAddr, remote_port: u16) -> Option<usize> {
+        let bound = |conn: &Connection| conn.owner.is_some() && conn.local_port.get() == local_port;
+        self.connections
+            .iter()
+            .position(|conn| {
+                bound(conn)
+                    && !matches!(conn.state.get(), State::Closed | State::Listen)
+                    && conn.remote_addr.get() == remote_addr
+                    && conn.remote_port.get() == remote_port
+            })
+            .or_else(|| {
+                self.connections
+                    .iter()
+                    .position(|conn| bound(conn) && conn.state.get() == State::Listen)
+            })
+    }
+
+    /// Start a handshake in `state`, which is either `SynSent` or
+    /// `SynReceived`.
+    fn open(&self, conn: &Connection, state: State) {
+        let iss = self.rng.random();
+        conn.snd_una.set(iss);
+        conn.retries.set(0);
+        conn.state.set(state);
+        conn.queue();
+    }
+
+    /// Schedule an upcall to the owner of connection `socket`.
+    fn notify(&self, socket: usize, upcall_num: usize, arg: usize) {
+        let conn = &self.connections[socket];
+        conn.owner.map(|owner| {
+            let _ = self.apps.enter(*owner, |_, kernel_data| {
+                if upcall_num == upcall::EVENT && arg == event::CONNECTED {
+                    let _ = kernel_data
+                        .get_readwrite_processbuffer(rw_allow::REMOTE)
+                        .and_then(|remote| {
+                            remote.mut_enter(|remote| {
+                                let endpoint = conn.remote_endpoint();
+                                if remote.len() >= endpoint.len() {
+                                    remote[..endpoint.len()].copy_from_slice(&endpoint);
+                                }
+                            })
+                        });
+                }
+                kernel_data
+                    .schedule_upcall(upcall_num, (socket, arg, 0))
+                    .ok();
+            });
+        });
+    }
+
+    /// Return a connection to the table, reporting `event` to its owner if
+    /// given.
+    fn free(&self, socket: usize, event: Option<usize>) {
+        let conn = &self.connections[socket];
+        conn.state.set(State::Closed);
+        conn.local_port.set(0);
+        conn.rcv_wnd.set(0);
+        conn.send_len.set(0);
+        conn.output.set(false);
+        conn.ack_now.set(false);
+        conn.timer_ms.set(0);
+        if let Some(event) = event {
+            self.notify(socket, upcall::EVENT, event);
+        }
+        conn.owner.clear();
+    }
+
+    /// Queue a reset of a synchronized connection, before it is freed.
+    fn reset(&self, conn: &Connection) {
+        if conn.state.get().is_synchronized() {
+            let mut header = TCPHeader::new();
+            header.set_src_port(conn.local_port.get());
+            header.set_dst_port(conn.remote_port.get());
+            header.set_seq_num(conn.snd_nxt.get());
+            header.set_ack_num(conn.rcv_nxt.get());
+            header.set_flags(tcp_flags::RST | tcp_flags::ACK);
+            self.pending_reset.set((conn.remote_addr.get(), header));
+        }
+    }
+
+    /// Queue a reset in reply to a segment that belongs to no connection
+    /// (RFC 793, section 3.4).
+    fn reply_reset(&self, remote_addr: IPAddr, received: &TCPHeader, data_len: usize) {
+        if received.has_flags(tcp_flags::RST) {
+            return;
+        }
+        let mut header = TCPHeader::new();
+        header.set_src_port(received.get_dst_port());
+        header.set_dst_port(received.get_src_port());
+        if received.has_flags(tcp_flags::ACK) {
+            header.set_seq_num(received.get_ack_num());
+            header.set_flags(tcp_flags::RST);
+        } else {
+            let mut len = data_len as u32;
+            if received.has_flags(tcp_flags::SYN) {
+                len += 1;
+            }
+            if received.has_flags(tcp_flags::FIN) {
+                len += 1;
+            }
+            header.set_ack_num(received.get_seq_num().wrapping_add(len));
+            header.set_flags(tcp_flags::RST | tcp_flags::ACK);
+        }
+        self.pending_reset.set((remote_addr, header));
+    }
+
+    /// Start `conn`'s timer with `ms` milliseconds, and the alarm if it is
+    /// not running yet.
+    fn start_timer(&self, conn: &Connection, ms: u32) {
+        conn.timer_ms.set(ms);
+        if !self.alarm.is_armed() {
+            self.alarm
+                .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(TICK_MS));
+        }
+    }
+
+    /// Send the next pending segment if the kernel buffer is available:
+    /// first a reset, then any connection with a segment to (re)transmit or
+    /// an acknowledgment to send.
+    fn transmit_next(&self) {
+        if self.kernel_buffer.is_none() {
+            return;
+        }
+        if let Some((remote_addr, header)) = self.pending_reset.take() {
+            self.transmit(remote_addr, header, 0);
+            return;
+        }
+        let next = self
+            .connections
+            .iter()
+            .position(|conn| conn.owner.is_some() && (conn.output.get() || conn.ack_now.get()));
+        let socket = match next {
+            Some(socket) => socket,
+            None => return,
+        };
+        let conn = &self.connections[socket];
+
+        let mut header = TCPHeader::new();
+        header.set_src_port(conn.local_port.get());
+        header.set_dst_port(conn.remote_port.get());
+        header.set_window(conn.rcv_wnd.get());
+        let mut flags = tcp_flags::ACK;
+        let mut data_len = 0;
+        if conn.output.get() {
+            flags = conn.segment_flags();
+            header.set_seq_num(conn.snd_una.get());
+            if flags & tcp_flags::PSH != 0 {
+                data_len = conn.send_len.get();
+                if self.copy_send_data(conn, data_len).is_err() {
+                    // The process revoked or shrank its buffer while the
+                    // data was in flight.
+                    self.reset(conn);
+                    self.free(socket, Some(event::RESET));
+                    self.transmit_next();
+                    return;
+                }
+            }
+            conn.output.set(false);
+            if conn.timer_ms.get() == 0 {
+                self.start_timer(conn, RTO_MS << conn.retries.get());
+            }
+        } else {
+            header.set_seq_num(conn.snd_nxt.get());
+        }
+        if flags & tcp_flags::ACK != 0 {
+            header.set_ack_num(conn.rcv_nxt.get());
+        }
+        header.set_flags(flags);
+        conn.ack_now.set(false);
+        self.transmit(conn.remote_addr.get(), header, data_len);
+    }
+
+    /// Copy the first `len` bytes of the send buffer of `conn`'s owner into
+    /// the kernel buffer.
+    fn copy_send_data(&self, conn: &Connection, len: usize) -> Result<(), ErrorCode> {
+        let owner = conn.owner.extract().ok_or(ErrorCode::FAIL)?;
+        self.apps
+            .enter(owner, |_, kernel_data| {
+                kernel_data
+                    .get_readonly_processbuffer(ro_allow::WRITE)
+                    .and_then(|write| {
+                        write.enter(|data| {
+                            self.kernel_buffer
+                                .map_or(Err(ErrorCode::NOMEM), |kernel_buffer| {
+                                    if data.len() < len || kernel_buffer.len() < len {
+                                        return Err(ErrorCode::SIZE);
+                                    }
+                                    data[0..len].copy_to_slice(&mut kernel_buffer[0..len]);
+                                    Ok(())
+                                })
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::NOMEM))
+            })
+            .unwrap_or(Err(ErrorCode::FAIL))
+    }
+
+    /// Queue a segment with the first `data_len` bytes of the kernel buffer
+    /// as its payload.
+    fn transmit(&self, remote_addr: IPAddr, header: TCPHeader, data_len: usize) {
+        if let Some(mut buf) = self.kernel_buffer.take() {
+            buf.slice(0..data_len);
+            if let Err(mut buf) = self.sender.send_transport(
+                remote_addr,
+                TransportHeader::TCP(header),
+                buf,
+                self.net_cap,
+            ) {
+                // The segment is lost; the retransmission timer recovers.
+                buf.reset();
+                self.kernel_buffer.replace(buf);
+            }
+        }
+    }
+
+    /// Handle a segment for a connection that is synchronized, or in the
+    /// middle of a passive open.
+    fn segment_arrived(&self, socket: usize, header: &TCPHeader, data: &[u8]) {
+        let conn = &self.connections[socket];
+        // Only segments starting at the next expected sequence number are
+        // accepted. Anything else is answered with an acknowledgment of what
+        // was received so far.
+        if header.get_seq_num() != conn.rcv_nxt.get() {
+            if !header.has_flags(tcp_flags::RST) {
+                conn.ack_now.set(true);
+            }
+            return;
+        }
+        if header.has_flags(tcp_flags::RST) {
+            self.free(socket, Some(event::RESET));
+            return;
+        }
+        if header.has_flags(tcp_flags::SYN) {
+            // The peer has lost the connection.
+            self.reset(conn);
+            self.free(socket, Some(event::RESET));
+            return;
+        }
+        if !header.has_flags(tcp_flags::ACK) {
+            return;
+        }
+        self.acknowledged(socket, header.get_ack_num());
+        if conn.owner.is_none() {
+            return;
+        }
+
+        let mut fin = header.has_flags(tcp_flags::FIN);
+        if !data.is_empty() {
+            let accepts_data = matches!(
+                conn.state.get(),
+                State::Established | State::FinWait1 | State::FinWait2
+            );
+            if accepts_data && self.deliver(socket, data) {
+                conn.rcv_nxt
+                    .set(conn.rcv_nxt.get().wrapping_add(data.len() as u32));
+            } else {
+                // The FIN follows data that was dropped.
+                fin = false;
+            }
+            conn.ack_now.set(true);
+        }
+        if fin {
+            let next = match conn.state.get() {
+                State::Established => State::CloseWait,
+                State::FinWait1 => State::Closing,
+                State::FinWait2 => State::TimeWait,
+                _ => return,
+            };
+            conn.rcv_nxt.set(conn.rcv_nxt.get().wrapping_add(1));
+            conn.ack_now.set(true);
+            conn.state.set(next);
+            if next == State::TimeWait {
+                self.start_timer(conn, TIME_WAIT_MS);
+            }
+            self.notify(socket, upcall::EVENT, event::PEER_CLOSED);
+        }
+    }
+
+    /// Handle an acknowledgment number received on a synchronized
+    /// connection. With one segment in flight, only an acknowledgment of all
+    /// of it makes progress.
+    fn acknowledged(&self, socket: usize, ack: u32) {
+        let conn = &self.connections[socket];
+        if conn.snd_una.get() == conn.snd_nxt.get() || ack != conn.snd_nxt.get() {
+            return;
+        }
+        conn.snd_una.set(ack);
+        conn.output.set(false);
+        conn.timer_ms.set(0);
+        conn.retries.set(0);
+
+        let send_len = conn.send_len.get();
+        match conn.state.get() {
+            State::SynReceived => {
+                conn.state.set(State::Established);
+                self.notify(socket, upcall::EVENT, event::CONNECTED);
+            }
+            State::Established
+            | State::CloseWait
+            | State::FinWait1
+            | State::Closing
+            | State::LastAck
+                if send_len > 0 =>
+            {
+                conn.send_len.set(0);
+                // A FIN waits for the data sent before the close.
+                conn.queue();
+                self.notify(socket, upcall::SEND_DONE, send_len);
+            }
+            State::FinWait1 => conn.state.set(State::FinWait2),
+            State::Closing => {
+                conn.state.set(State::TimeWait);
+                self.start_timer(conn, TIME_WAIT_MS);
+            }
+            State::LastAck => self.free(socket, Some(event::CLOSED)),
+            _ => {}
+        }
+    }
+
+    /// Write received data to the receive buffer of the owner of `socket`,
+    /// if it is receiving and the data fits.
+    fn deliver(&self, socket: usize, data: &[u8]) -> bool {
+        let conn = &self.connections[socket];
+        if data.len() > conn.rcv_wnd.get() as usize {
+            return false;
+        }
+        let delivered = conn.owner.map_or(false, |owner| {
+            self.apps
+                .enter(*owner, |_, kernel_data| {
+                    kernel_data
+                        .get_readwrite_processbuffer(rw_allow::READ)
+                        .and_then(|read| {
+                            read.mut_enter(|read| {
+                                if read.len() < data.len() {
+                                    return false;
+                                }
+                                read[..data.len()].copy_from_slice(data);
+                                true
+                            })
+                        })
+                        .unwrap_or(false)
+                })
+                .unwrap_or(false)
+        });
+        if delivered {
+            conn.rcv_wnd.set(0);
+            self.notify(socket, upcall::RECEIVED, data.len());
+        }
+        delivered
+    }
+
+    /// Read the IPv6 address to connect to from the `ro_allow::REMOTE`
+    /// buffer.
+    fn remote_addr(&self, processid: ProcessId) -> Result<IPAddr, ErrorCode> {
+        self.apps
+            .enter(processid, |_, kernel_data| {
+                kernel_data
+                    .get_readonly_processbuffer(ro_allow::REMOTE)
+                    .and_then(|remote| {
+                        remote.enter(|remote| {
+                            if remote.len() != size_of::<IPAddr>() {
+                                return Err(ErrorCode::INVAL);
+                            }
+                            let mut addr = IPAddr::new();
+                            remote.copy_to_slice(&mut addr.0);
+                            Ok(addr)
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::INVAL))
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+
+    /// The length of the receive buffer of `processid`, capped to the largest
+    /// window TCP can advertise.
+    fn receive_window(&self, processid: ProcessId) -> usize {
+        self.apps
+            .enter(processid, |_, kernel_data| {
+                kernel_data
+                    .get_readwrite_processbuffer(rw_allow::READ)
+                    .map_or(0, |read| read.len())
+            })
+            .map_or(0, |len| cmp::min(len, u16::MAX as usize))
+    }
+}
+
+impl<'a, A: Alarm<'a>> SyscallDriver for TCPDriver<'a, A> {
+    /// TCP control
+    ///
+    /// Sockets are referred to by the number returned by command `1`. A
+    /// socket is freed by commands `7` and `8`, and when its connection ends
+    /// with a `CLOSED`, `RESET` or `TIMEOUT` event.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Open a socket. Returns the socket number, or NOMEM if the
+    ///   connection table is full.
+    /// - `2`: Bind socket `arg1` to local port `arg2`. Returns BUSY if
+    ///   another socket is bound to the port, and INVAL if the port is 0 or
+    ///   the socket is listening or connected.
+    /// - `3`: Listen for a connection on socket `arg1`. Returns RESERVE if
+    ///   the socket is not bound.
+    /// - `4`: Connect socket `arg1` to port `arg2` of the IPv6 address in
+    ///   read-only allow `1`. Sockets that are not bound are bound to an
+    ///   ephemeral port.
+    /// - `5`: Send the first `arg2` bytes of read-only allow `0` on socket
+    ///   `arg1`. The buffer must not change until the send completes. Returns
+    ///   BUSY if a send is in progress, SIZE if the data does not fit in one
+    ///   segment or the buffer, and INVAL if the connection cannot send.
+    /// - `6`: Receive the next data of socket `arg1` into read-write allow
+    ///   `0`. Returns BUSY if the process is already receiving on a socket.
+    /// - `7`: Close socket `arg1`. Established connections are closed
+    ///   gracefully, and freed once the close completes. Other sockets are
+    ///   freed immediately.
+    /// - `8`: Abort socket `arg1`, resetting its connection, and free it.
+    /// - `9`: Returns the maximum length of the data of a send.
+    fn command(
+        &self,
+        command_num: usize,
+        arg1: usize,
+        arg2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        let socket = arg1;
+        let result = match command_num {
+            0 => Ok(()),
+
+            1 => {
+                return match self.connections.iter().position(|conn| self.is_free(conn)) {
+                    Some(socket) => {
+                        self.free(socket, None);
+                        self.connections[socket].owner.set(processid);
+                        CommandReturn::success_u32(socket as u32)
+                    }
+                    None => CommandReturn::failure(ErrorCode::NOMEM),
+                };
+            }
+
+            2 => self.socket(socket, processid).and_then(|conn| {
+                let port = arg2 as u16;
+                if conn.state.get() != State::Closed || port == 0 || arg2 > u16::MAX as usize {
+                    Err(ErrorCode::INVAL)
+                } else if self.is_bound(port, socket) {
+                    Err(ErrorCode::BUSY)
+                } else {
+                    conn.local_port.set(port);
+                    Ok(())
+                }
+            }),
+
+            3 => self.socket(socket, processid).and_then(|conn| {
+                if conn.state.get() != State::Closed {
+                    Err(ErrorCode::INVAL)
+                } else if conn.local_port.get() == 0 {
+                    Err(ErrorCode::RESERVE)
+                } else {
+                    conn.state.set(State::Listen);
+                    Ok(())
+                }
+            }),
+
+            4 => self.socket(socket, processid).and_then(|conn| {
+                if conn.state.get() != State::Closed || arg2 == 0 || arg2 > u16::MAX as usize {
+                    return Err(ErrorCode::INVAL);
+                }
+                let remote_addr = self.remote_addr(processid)?;
+                if conn.local_port.get() == 0 {
+                    conn.local_port.set(self.ephemeral_port(socket));
+                }
+                conn.remote_addr.set(remote_addr);
+                conn.remote_port.set(arg2 as u16);
+                conn.rcv_nxt.set(0);
+                self.open(conn, State::SynSent);
+                Ok(())
+            }),
+
+            5 => self.socket(socket, processid).and_then(|conn| {
+                let len = arg2;
+                if !matches!(conn.state.get(), State::Established | State::CloseWait) {
+                    return Err(ErrorCode::INVAL);
+                }
+                if conn.send_len.get() > 0 {
+                    return Err(ErrorCode::BUSY);
+                }
+                let available = self
+                    .apps
+                    .enter(processid, |_, kernel_data| {
+                        kernel_data
+                            .get_readonly_processbuffer(ro_allow::WRITE)
+                            .map_or(0, |write| write.len())
+                    })
+                    .unwrap_or(0);
+                if len == 0 || len > self.max_seg_len || len > available {
+                    return Err(ErrorCode::SIZE);
+                }
+                conn.send_len.set(len);
+                conn.queue();
+                Ok(())
+            }),
+
+            6 => self.socket(socket, processid).and_then(|conn| {
+                let receiving = self
+                    .connections
+                    .iter()
+                    .any(|other| other.owner.contains(&processid) && other.rcv_wnd.get() > 0);
+                if receiving {
+                    return Err(ErrorCode::BUSY);
+                }
+                let window = self.receive_window(processid);
+                if window == 0 {
+                    return Err(ErrorCode::INVAL);
+                }
+                conn.rcv_wnd.set(window as u16);
+                // Tell the peer that the window opened.
+                if conn.state.get().is_synchronized() {
+                    conn.ack_now.set(true);
+                }
+                Ok(())
+            }),
+
+            7 => self.socket(socket, processid).map(|conn| {
+                let next = match conn.state.get() {
+                    State::Established => State::FinWait1,
+                    State::CloseWait => State::LastAck,
+                    State::FinWait1
+                    | State::FinWait2
+                    | State::Closing
+                    | State::LastAck
+                    | State::TimeWait => return,
+                    _ => {
+                        // Closing a connection that is not established
+                        // abandons it.
+                        self.reset(conn);
+                        self.free(socket, None);
+                        return;
+                    }
+                };
+                conn.state.set(next);
+                if conn.send_len.get() == 0 {
+                    conn.queue();
+                }
+            }),
+
+            8 => self.socket(socket, processid).map(|conn| {
+                self.reset(conn);
+                self.free(socket, None);
+            }),
+
+            9 => return CommandReturn::success_u32(self.max_seg_len as u32),
+
+            _ => Err(ErrorCode::NOSUPPORT),
+        };
+        self.transmit_next();
+        match result {
+            Ok(()) => CommandReturn::success(),
+            Err(err) => CommandReturn::failure(err),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+impl<'a, A: Alarm<'a>> IP6RecvClient for TCPDriver<'a, A> {
+    fn receive(&self, ip_header: IP6Header, payload: &[u8]) {
+        let header = match TCPHeader::decode(payload).done() {
+            Some((_, header)) => header,
+            None => return,
+        };
+        let data_offset = header.get_data_offset();
+        if data_offset < TCP_HDR_LEN || data_offset > payload.len() {
+            return;
+        }
+        let data = &payload[data_offset..];
+        let remote_addr = ip_header.get_src_addr();
+        let socket = match self.lookup(header.get_dst_port(), remote_addr, header.get_src_port()) {
+            Some(socket) => socket,
+            None => {
+                self.reply_reset(remote_addr, &header, data.len());
+                self.transmit_next();
+                return;
+            }
+        };
+
+        let conn = &self.connections[socket];
+        match conn.state.get() {
+            State::Listen => {
+                if header.has_flags(tcp_flags::ACK) {
+                    self.reply_reset(remote_addr, &header, data.len());
+                } else if header.has_flags(tcp_flags::SYN) && !header.has_flags(tcp_flags::RST) {
+                    conn.remote_addr.set(remote_addr);
+                    conn.remote_port.set(header.get_src_port());
+                    conn.rcv_nxt.set(header.get_seq_num().wrapping_add(1));
+                    self.open(conn, State::SynReceived);
+                }
+            }
+            State::SynSent => {
+                let ack_ok = header.get_ack_num() == conn.snd_nxt.get();
+                if header.has_flags(tcp_flags::ACK) && !ack_ok {
+                    self.reply_reset(remote_addr, &header, data.len());
+                } else if header.has_flags(tcp_flags::RST) {
+                    if header.has_flags(tcp_flags::ACK) {
+                        self.free(socket, Some(event::RESET));
+                    }
+                } else if header.has_flags(tcp_flags::SYN | tcp_flags::ACK) {
+                    conn.rcv_nxt.set(header.get_seq_num().wrapping_add(1));
+                    conn.snd_una.set(header.get_ack_num());
+                    conn.output.set(false);
+                    conn.timer_ms.set(0);
+                    conn.retries.set(0);
+                    conn.state.set(State::Established);
+                    conn.ack_now.set(true);
+                    self.notify(socket, upcall::EVENT, event::CONNECTED);
+                }
+            }
+            _ => self.segment_arrived(socket, &header, data),
+        }
+        self.transmit_next();
+    }
+}
+
+impl<'a, A: Alarm<'a>> UDPSendClient for TCPDriver<'a, A> {
+    fn send_done(
+        &self,
+        _result: Result<(), ErrorCode>,
+        mut dgram: LeasableMutableBuffer<'static, u8>,
+    ) {
+        // Lost segments are recovered by the retransmission timer, so the
+        // result does not matter here.
+        dgram.reset();
+        self.kernel_buffer.replace(dgram);
+        self.transmit_next();
+    }
+}
+
+impl<'a, A: Alarm<'a>> time::AlarmClient for TCPDriver<'a, A> {
+    fn alarm(&self) {
+        for (socket, conn) in self.connections.iter().enumerate() {
+            let remaining = conn.timer_ms.get();
+            if remaining == 0 || conn.owner.is_none() {
+                continue;
+            }
+            conn.timer_ms.set(remaining.saturating_sub(TICK_MS));
+            if conn.timer_ms.get() > 0 {
+                continue;
+            }
+            if conn.state.get() == State::TimeWait {
+                self.free(socket, Some(event::CLOSED));
+            } else if conn.retries.get() >= MAX_RETRIES {
+                self.reset(conn);
+                self.free(socket, Some(event::TIMEOUT));
+            } else {
+                conn.retries.set(conn.retries.get() + 1);
+                conn.output.set(true);
+            }
+        }
+        if self
+            .connections
+            .iter()
+            .any(|conn| conn.owner.is_some() && conn.timer_ms.get() > 0)
+        {
+            self.alarm
+                .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(TICK_MS));
+        }
+        self.transmit_next();
+    }
+}
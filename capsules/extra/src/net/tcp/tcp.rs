@@ -0,0 +1,300 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! This file contains the structs and methods associated with the TCP header.
+//! This includes getters and setters for the various header fields, as well
+//! as the standard encode/decode functionality required for serializing
+//! the struct for transmission.
+
+use crate::net::stream::SResult;
+use crate::net::stream::{decode_u16, decode_u32};
+use crate::net::stream::{encode_u16, encode_u32};
+
+/// The size of a TCP header without options. Headers sent by Tock never
+/// carry options.
+pub const TCP_HDR_LEN: usize = 20;
+
+/// Control bits of the TCP header.
+pub mod tcp_flags {
+    pub const FIN: u8 = 0x01;
+    pub const SYN: u8 = 0x02;
+    pub const RST: u8 = 0x04;
+    pub const PSH: u8 = 0x08;
+    pub const ACK: u8 = 0x10;
+}
+
+// Note: All TCP Header fields are stored in host byte order, and are only
+// converted when the header is encoded or decoded.
+
+/// The `TCPHeader` struct follows the layout for the TCP segment header.
+/// Besides the header fields, it records the total length of the segment
+/// (header and payload), which is needed for the checksum and for encoding
+/// the payload but is not part of the header on the wire.
+#[derive(Copy, Clone, Debug)]
+pub struct TCPHeader {
+    src_port: u16,
+    dst_port: u16,
+    seq_num: u32,
+    ack_num: u32,
+    offset_and_control: u16,
+    window: u16,
+    cksum: u16,
+    urg_ptr: u16,
+    len: u16,
+}
+
+impl Default for TCPHeader {
+    fn default() -> TCPHeader {
+        TCPHeader {
+            src_port: 0,
+            dst_port: 0,
+            seq_num: 0,
+            ack_num: 0,
+            offset_and_control: ((TCP_HDR_LEN / 4) as u16) << 12,
+            window: 0,
+            cksum: 0,
+            urg_ptr: 0,
+            len: TCP_HDR_LEN as u16,
+        }
+    }
+}
+
+impl TCPHeader {
+    pub fn new() -> TCPHeader {
+        TCPHeader::default()
+    }
+
+    pub fn set_src_port(&mut self, port: u16) {
+        self.src_port = port;
+    }
+
+    pub fn set_dst_port(&mut self, port: u16) {
+        self.dst_port = port;
+    }
+
+    pub fn set_seq_num(&mut self, seq_num: u32) {
+        self.seq_num = seq_num;
+    }
+
+    pub fn set_ack_num(&mut self, ack_num: u32) {
+        self.ack_num = ack_num;
+    }
+
+    /// Set the control bits, a combination of the `tcp_flags` constants.
+    pub fn set_flags(&mut self, flags: u8) {
+        self.offset_and_control = (self.offset_and_control & 0xf000) | flags as u16;
+    }
+
+    pub fn set_window(&mut self, window: u16) {
+        self.window = window;
+    }
+
+    pub fn set_cksum(&mut self, cksum: u16) {
+        self.cksum = cksum;
+    }
+
+    /// Set the total length of the segment, header included.
+    pub fn set_len(&mut self, len: u16) {
+        self.len = len;
+    }
+
+    pub fn get_src_port(&self) -> u16 {
+        self.src_port
+    }
+
+    pub fn get_dst_port(&self) -> u16 {
+        self.dst_port
+    }
+
+    pub fn get_seq_num(&self) -> u32 {
+        self.seq_num
+    }
+
+    pub fn get_ack_num(&self) -> u32 {
+        self.ack_num
+    }
+
+    pub fn get_flags(&self) -> u8 {
+        self.offset_and_control as u8
+    }
+
+    pub fn has_flags(&self, flags: u8) -> bool {
+        self.get_flags() & flags == flags
+    }
+
+    pub fn get_window(&self) -> u16 {
+        self.window
+    }
+
+    pub fn get_cksum(&self) -> u16 {
+        self.cksum
+    }
+
+    pub fn get_len(&self) -> u16 {
+        self.len
+    }
+
+    /// The offset of the payload from the start of the segment, i.e. the
+    /// size of the header including any options.
+    pub fn get_data_offset(&self) -> usize {
+        ((self.offset_and_control >> 12) as usize) * 4
+    }
+
+    /// The size of the header as encoded by `encode`, which never includes
+    /// options.
+    pub fn get_hdr_size(&self) -> usize {
+        TCP_HDR_LEN
+    }
+
+    /// This function serializes the `TCPHeader` into the provided buffer.
+    ///
+    /// # Arguments
+    ///
+    /// `buf` - A mutable buffer to serialize the `TCPHeader` into
+    /// `offset` - The current offset into the provided buffer
+    ///
+    /// # Return Value
+    ///
+    /// This function returns the new offset into the buffer wrapped in an
+    /// SResult.
+    pub fn encode(&self, buf: &mut [u8], offset: usize) -> SResult<usize> {
+        stream_len_cond!(buf, self.get_hdr_size() + offset);
+
+        // Options are never encoded, so the data offset is always the size
+        // of the bare header.
+        let offset_and_control =
+            ((TCP_HDR_LEN / 4) as u16) << 12 | (self.offset_and_control & 0x1ff);
+
+        let mut off = offset;
+        off = enc_consume!(buf, off; encode_u16, self.src_port);
+        off = enc_consume!(buf, off; encode_u16, self.dst_port);
+        off = enc_consume!(buf, off; encode_u32, self.seq_num);
+        off = enc_consume!(buf, off; encode_u32, self.ack_num);
+        off = enc_consume!(buf, off; encode_u16, offset_and_control);
+        off = enc_consume!(buf, off; encode_u16, self.window);
+        off = enc_consume!(buf, off; encode_u16, self.cksum);
+        off = enc_consume!(buf, off; encode_u16, self.urg_ptr);
+        stream_done!(off, off);
+    }
+
+    /// This function deserializes the `TCPHeader` from the provided buffer.
+    /// Options are not decoded; use `get_data_offset` to find the payload.
+    ///
+    /// # Arguments
+    ///
+    /// `buf` - The byte array corresponding to a serialized `TCPHeader`
+    ///
+    /// # Return Value
+    ///
+    /// This function returns a `TCPHeader` struct wrapped in an SResult
+    pub fn decode(buf: &[u8]) -> SResult<TCPHeader> {
+        stream_len_cond!(buf, TCP_HDR_LEN);
+        let mut tcp_header = Self::new();
+        let off = 0;
+        let (off, src_port) = dec_try!(buf, off; decode_u16);
+        tcp_header.src_port = src_port;
+        let (off, dst_port) = dec_try!(buf, off; decode_u16);
+        tcp_header.dst_port = dst_port;
+        let (off, seq_num) = dec_try!(buf, off; decode_u32);
+        tcp_header.seq_num = seq_num;
+        let (off, ack_num) = dec_try!(buf, off; decode_u32);
+        tcp_header.ack_num = ack_num;
+        let (off, offset_and_control) = dec_try!(buf, off; decode_u16);
+        tcp_header.offset_and_control = offset_and_control;
+        let (off, window) = dec_try!(buf, off; decode_u16);
+        tcp_header.window = window;
+        let (off, cksum) = dec_try!(buf, off; decode_u16);
+        tcp_header.cksum = cksum;
+        let (off, urg_ptr) = dec_try!(buf, off; decode_u16);
+        tcp_header.urg_ptr = urg_ptr;
+        tcp_header.len = buf.len() as u16;
+        stream_done!(off, tcp_header);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SYN_ACK: [u8; TCP_HDR_LEN] = [
+        0x12, 0x34, 0x00, 0x50, 0x01, 0x02, 0x03, 0x04, 0x0a, 0x0b, 0x0c, 0x0d, 0x50, 0x12, 0x10,
+        0x00, 0xbe, 0xef, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn encode_syn_ack() {
+        let mut header = TCPHeader::new();
+        header.set_src_port(0x1234);
+        header.set_dst_port(80);
+        header.set_seq_num(0x0102_0304);
+        header.set_ack_num(0x0a0b_0c0d);
+        header.set_flags(tcp_flags::SYN | tcp_flags::ACK);
+        header.set_window(0x1000);
+        header.set_cksum(0xbeef);
+
+        let mut buf = [0xff; TCP_HDR_LEN + 2];
+        match header.encode(&mut buf, 2) {
+            SResult::Done(_, off) => assert_eq!(off, TCP_HDR_LEN + 2),
+            _ => panic!("failed to encode TCP header"),
+        }
+        assert_eq!(&buf[..2], &[0xff, 0xff]);
+        assert_eq!(&buf[2..], &SYN_ACK);
+    }
+
+    #[test]
+    fn decode_syn_ack() {
+        let header = match TCPHeader::decode(&SYN_ACK) {
+            SResult::Done(TCP_HDR_LEN, header) => header,
+            _ => panic!("failed to decode TCP header"),
+        };
+        assert_eq!(header.get_src_port(), 0x1234);
+        assert_eq!(header.get_dst_port(), 80);
+        assert_eq!(header.get_seq_num(), 0x0102_0304);
+        assert_eq!(header.get_ack_num(), 0x0a0b_0c0d);
+        assert_eq!(header.get_flags(), tcp_flags::SYN | tcp_flags::ACK);
+        assert!(header.has_flags(tcp_flags::SYN));
+        assert!(!header.has_flags(tcp_flags::FIN));
+        assert_eq!(header.get_window(), 0x1000);
+        assert_eq!(header.get_cksum(), 0xbeef);
+        assert_eq!(header.get_data_offset(), TCP_HDR_LEN);
+        assert_eq!(header.get_len() as usize, TCP_HDR_LEN);
+    }
+
+    #[test]
+    fn options_are_skipped_and_not_encoded() {
+        // The same segment with a 4-byte MSS option and two bytes of data.
+        let mut segment = [0; TCP_HDR_LEN + 6];
+        segment[..TCP_HDR_LEN].copy_from_slice(&SYN_ACK);
+        segment[12] = 0x60;
+        segment[TCP_HDR_LEN..].copy_from_slice(&[2, 4, 0x05, 0xb4, 0xaa, 0xbb]);
+
+        let header = match TCPHeader::decode(&segment) {
+            SResult::Done(_, header) => header,
+            _ => panic!("failed to decode TCP header"),
+        };
+        assert_eq!(header.get_data_offset(), TCP_HDR_LEN + 4);
+        assert_eq!(&segment[header.get_data_offset()..], &[0xaa, 0xbb]);
+        assert_eq!(header.get_len() as usize, segment.len());
+
+        let mut buf = [0; TCP_HDR_LEN];
+        match header.encode(&mut buf, 0) {
+            SResult::Done(_, TCP_HDR_LEN) => {}
+            _ => panic!("failed to encode TCP header"),
+        }
+        assert_eq!(buf, SYN_ACK);
+    }
+
+    #[test]
+    fn short_buffers_are_rejected() {
+        match TCPHeader::decode(&SYN_ACK[..TCP_HDR_LEN - 1]) {
+            SResult::Needed(TCP_HDR_LEN) => {}
+            _ => panic!("decoded a truncated TCP header"),
+        }
+        let mut buf = [0; TCP_HDR_LEN];
+        match TCPHeader::new().encode(&mut buf, 1) {
+            SResult::Needed(n) => assert_eq!(n, TCP_HDR_LEN + 1),
+            _ => panic!("encoded past the end of the buffer"),
+        }
+    }
+}
@@ -0,0 +1,136 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Provides userspace with control of the node's attachment to a Thread
+//! network.
+//!
+//! A process joins the network of an Active Operational Dataset, and all
+//! processes are told when the role of the node changes. Once the node is a
+//! child, processes use the network through the UDP driver as usual.
+
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::processbuffer::ReadableProcessBuffer;
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::{ErrorCode, ProcessId};
+
+use super::mle::{self, Dataset, Role, ThreadNetwork};
+
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Thread as usize;
+
+/// The RLOC16 reported while the node is not a child, which is the 802.15.4
+/// short address of nodes without one.
+const NO_RLOC16: u16 = 0xfffe;
+
+/// The longest Active Operational Dataset read from a process.
+const MAX_DATASET_LEN: usize = 254;
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    pub const DATASET: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+mod upcall {
+    pub const ROLE_CHANGED: usize = 0;
+    pub const COUNT: u8 = 1;
+}
+
+#[derive(Default)]
+pub struct App {}
+
+pub struct ThreadDriver<'a, M: ThreadNetwork<'a>> {
+    mle: &'a M,
+    apps: Grant<
+        App,
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<0>,
+    >,
+}
+
+impl<'a, M: ThreadNetwork<'a>> ThreadDriver<'a, M> {
+    pub fn new(
+        mle: &'a M,
+        grant: Grant<
+            App,
+            UpcallCount<{ upcall::COUNT }>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<0>,
+        >,
+    ) -> ThreadDriver<'a, M> {
+        ThreadDriver { mle, apps: grant }
+    }
+
+    /// Decode the dataset in the read-only allow buffer of `processid`.
+    fn dataset(&self, processid: ProcessId) -> Result<Dataset, ErrorCode> {
+        self.apps
+            .enter(processid, |_, kernel_data| {
+                kernel_data
+                    .get_readonly_processbuffer(ro_allow::DATASET)
+                    .and_then(|dataset| {
+                        dataset.enter(|tlvs| {
+                            let mut buf = [0; MAX_DATASET_LEN];
+                            let len = tlvs.len();
+                            if len > buf.len() {
+                                return Err(ErrorCode::SIZE);
+                            }
+                            tlvs.copy_to_slice(&mut buf[..len]);
+                            Dataset::decode(&buf[..len])
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::NOMEM))
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+}
+
+impl<'a, M: ThreadNetwork<'a>> mle::Client for ThreadDriver<'a, M> {
+    fn role_changed(&self, role: Role, rloc16: Option<u16>) {
+        let rloc16 = rloc16.unwrap_or(NO_RLOC16) as usize;
+        self.apps.each(|_, _, kernel_data| {
+            kernel_data
+                .schedule_upcall(upcall::ROLE_CHANGED, (role as usize, rloc16, 0))
+                .ok();
+        });
+    }
+}
+
+impl<'a, M: ThreadNetwork<'a>> SyscallDriver for ThreadDriver<'a, M> {
+    /// Join and leave a Thread network.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver existence check.
+    /// - `1`: Join the network of the Active Operational Dataset in
+    ///   read-only allow 0.
+    /// - `2`: Leave the network.
+    /// - `3`: The role of the node and its RLOC16.
+    fn command(
+        &self,
+        command_num: usize,
+        _arg1: usize,
+        _arg2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+            1 => self
+                .dataset(processid)
+                .and_then(|dataset| self.mle.join(dataset))
+                .into(),
+            2 => self.mle.detach().into(),
+            3 => CommandReturn::success_u32_u32(
+                self.mle.role() as u32,
+                self.mle.rloc16().unwrap_or(NO_RLOC16) as u32,
+            ),
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
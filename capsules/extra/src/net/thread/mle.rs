@@ -0,0 +1,1587 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Mesh Link Establishment (MLE) for a Thread Minimal End Device (Thread
+//! 1.1.1 Specification, Chapter 4).
+//!
+//! An `Mle` attaches the node as a child to a router of a Thread network,
+//! such as a commodity border router, so that the 6LoWPAN stack reaches the
+//! rest of the network through it. A Minimal End Device keeps its receiver
+//! on and never becomes a router, so it only ever talks to its parent.
+//!
+//! Attaching follows the 4-step handshake of Section 4.7.1:
+//!
+//! 1. The child multicasts a Parent Request to the link-local all-routers
+//!    address, first to routers only and then to routers and REEDs.
+//! 2. Routers answer with a Parent Response, which echoes the challenge of
+//!    the request and carries one of their own. The child waits for the
+//!    responses and picks the one with the highest parent priority, and then
+//!    the highest link margin.
+//! 3. The child sends a Child ID Request to that router, which answers its
+//!    challenge and registers the mesh-local EID of the child.
+//! 4. The parent answers with a Child ID Response that assigns the RLOC16 of
+//!    the child and carries the Network Data.
+//!
+//! The child then sends a Child Update Request every half of its timeout to
+//! keep the link alive, and attaches again once its parent stops answering.
+//! It asks its parent for new Network Data when the data version of an
+//! Advertisement of its parent changed.
+//!
+//! Once attached, the node has the RLOC and mesh-local EID addresses of the
+//! mesh-local prefix, and an address for each prefix of the Network Data
+//! whose border routers allow SLAAC.
+//!
+//! Security
+//! --------
+//! Keys are derived from the network key of the Active Operational Dataset
+//! and the key sequence with HMAC-SHA256 (Section 7.1.4): the first half is
+//! the MLE key and the second half the MAC key, whose key index is the key
+//! sequence modulo 128, plus one. MLE messages are secured at the MLE layer
+//! with AES-CCM and a 32-bit MIC, with the IPv6 source and destination
+//! addresses and the auxiliary security header as additional data, and are
+//! sent without MAC security. All other frames are secured with the MAC key
+//! once the node is attached. Messages of another key sequence are
+//! authenticated with keys derived for them, and a newer key sequence is
+//! adopted once a message of it is authenticated.
+//!
+//! `Mle` provides the parent to the 802.15.4 framer as a `DeviceProcedure`
+//! and the MAC key as a `KeyProcedure`, the parent as the next hop of all
+//! packets as a `NeighborResolver`, and the security of frames as a
+//! `LinkSecurity`.
+//!
+//! The implementation is kept small:
+//!
+//!   * The node joins a network whose Active Operational Dataset it is given,
+//!     for example by `dataset active -x` on the border router. See
+//!     Commissioning below.
+//!   * The MLE frame counter starts at a random value rather than being
+//!     persisted, and the MAC frame counter is reported to the parent as 0.
+//!   * Only context 0 of 6LoWPAN, which must be the mesh-local prefix, is
+//!     known, so packets to the SLAAC addresses that the parent compresses
+//!     with other contexts are dropped.
+//!   * Messages are decrypted one at a time, and one waiting message is
+//!     sent after the one being sent. Other messages are dropped.
+//!   * Child Update Requests and Data Responses of the parent that the child
+//!     did not ask for are ignored, apart from the Network Data they carry.
+//!
+//! Commissioning
+//! -------------
+//! Commissioning with MeshCoP (Chapter 8) is not supported. A joiner gets the
+//! network key from a commissioner over DTLS with EC-JPAKE, keyed by its
+//! PSKd, and neither is implemented in this tree. `Dataset::decode` rejects
+//! a dataset that carries a PSKc but no network key, which is what a node
+//! that expects to be commissioned is given, with `NOSUPPORT`. The
+//! Commissioning Data TLV of the Network Data is decoded but not used.
+//!
+//! Usage
+//! -----
+//! `components::thread::ThreadComponent` creates an `Mle` bound to the MLE
+//! port and installs it in the 802.15.4 driver and the IPv6 sender. The
+//! userland driver in `thread::driver` then joins the network:
+//!
+//! ```rust,ignore
+//! let dataset = Dataset::decode(active_dataset_tlvs)?;
+//! mle.join(dataset)?;
+//! // ... later, in `Client::role_changed`: the node is a child.
+//! ```
+
+use core::cell::Cell;
+
+use crate::ieee802154::framer::{DeviceProcedure, KeyProcedure};
+use crate::net::ieee802154::{KeyId, MacAddress, SecurityLevel};
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::ipv6::ipv6_send::{LinkSecurity, NeighborResolver};
+use crate::net::ipv6::slaac::InterfaceList;
+use crate::net::ipv6::TransportHeader;
+use crate::net::network_capabilities::NetworkCapability;
+use crate::net::stream::SResult;
+use crate::net::thread::tlv::{
+    tlv_len, BorderRouterTlvValue, BorderRouterTlvValueBit, LinkMode, NetworkDataTlv,
+    NetworkManagementTlv, PrefixSubTlv, Tlv, TlvType,
+};
+use crate::net::udp::udp_recv::UDPRecvClient;
+use crate::net::udp::udp_send::{UDPSendClient, UDPSender};
+
+use kernel::hil::digest;
+use kernel::hil::radio::RadioConfig;
+use kernel::hil::rng::Random;
+use kernel::hil::symmetric_encryption::{CCMClient, AES128CCM, CCM_NONCE_LENGTH};
+use kernel::hil::time::{self, Alarm, ConvertTicks};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::{LeasableBuffer, LeasableMutableBuffer};
+use kernel::ErrorCode;
+
+/// The UDP port of MLE (Section 4.3).
+pub const MLE_PORT: u16 = 19788;
+
+/// The largest MLE message, without its security header and MIC, that is
+/// received.
+pub const MAX_MESSAGE_LEN: usize = 256;
+/// The largest MLE message that is sent.
+const MAX_TX_MESSAGE_LEN: usize = 64;
+
+/// The security suite byte of secured messages; 255 means no security.
+const SECURITY_SUITE: u8 = 0;
+/// The security control field of the auxiliary header: ENC-MIC-32 with a
+/// 4-byte key source and a key index (key ID mode 2).
+const SECURITY_CONTROL: u8 = 0x15;
+const AUX_HDR_LEN: usize = 10;
+const MIC_LEN: usize = 4;
+/// The source and destination addresses followed by the auxiliary header.
+const A_DATA_LEN: usize = 2 * 16 + AUX_HDR_LEN;
+
+pub const TX_BUF_LEN: usize = 1 + AUX_HDR_LEN + MAX_TX_MESSAGE_LEN + MIC_LEN;
+pub const CCM_BUF_LEN: usize = A_DATA_LEN + MAX_MESSAGE_LEN + MIC_LEN;
+/// The key sequence followed by "Thread".
+pub const KDF_BUF_LEN: usize = 4 + KDF_LABEL.len();
+pub const DIGEST_LEN: usize = 32;
+
+const KDF_LABEL: &[u8] = b"Thread";
+const KEY_LEN: usize = 16;
+const CHALLENGE_LEN: usize = 8;
+const MAX_CHALLENGE_LEN: usize = 8;
+
+/// MLE command types (Section 4.4).
+mod command {
+    pub const ADVERTISEMENT: u8 = 4;
+    pub const DATA_REQUEST: u8 = 7;
+    pub const DATA_RESPONSE: u8 = 8;
+    pub const PARENT_REQUEST: u8 = 9;
+    pub const PARENT_RESPONSE: u8 = 10;
+    pub const CHILD_ID_REQUEST: u8 = 11;
+    pub const CHILD_ID_RESPONSE: u8 = 12;
+    pub const CHILD_UPDATE_REQUEST: u8 = 13;
+    pub const CHILD_UPDATE_RESPONSE: u8 = 14;
+}
+
+/// The Scan Mask of Parent Requests to routers, and to routers and REEDs.
+const SCAN_ROUTERS: u8 = 0x80;
+const SCAN_ROUTERS_AND_REEDS: u8 = 0xc0;
+/// The Version TLV of Thread 1.1.
+const THREAD_VERSION: u16 = 2;
+/// The timeout of the child, which the parent keeps it for without hearing
+/// from it.
+pub const CHILD_TIMEOUT_S: u32 = 240;
+
+const PARENT_RESPONSE_ROUTERS_MS: u32 = 750;
+const PARENT_RESPONSE_REEDS_MS: u32 = 1250;
+const CHILD_ID_RESPONSE_MS: u32 = 1250;
+const CHILD_UPDATE_RESPONSE_MS: u32 = 1000;
+const MAX_CHILD_ID_REQUESTS: u8 = 3;
+const MAX_CHILD_UPDATE_REQUESTS: u8 = 3;
+/// The delay before attaching again after a failed attempt, which doubles
+/// with each failed attempt up to the maximum.
+const ATTACH_BACKOFF_MIN_S: u32 = 2;
+const ATTACH_BACKOFF_MAX_S: u32 = 128;
+
+/// The number of SLAAC prefixes of the Network Data that the node forms
+/// addresses from.
+const MAX_PREFIXES: usize = 2;
+const PREFIX_LEN_BITS: u8 = 64;
+
+/// All-routers multicast address, ff02::2, which Parent Requests are sent to.
+const ALL_ROUTERS: IPAddr = IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2]);
+
+/// The role of the node in the network.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Role {
+    /// The node has not joined a network.
+    Disabled = 0,
+    /// The node is attaching to a network, or attaching again after losing
+    /// its parent.
+    Detached = 1,
+    /// The node is attached to a parent.
+    Child = 2,
+}
+
+pub trait Client {
+    /// Called when the role of the node changed. `rloc16` is the RLOC16 of
+    /// the node if it is a child.
+    fn role_changed(&self, role: Role, rloc16: Option<u16>);
+}
+
+pub trait ThreadNetwork<'a> {
+    fn set_client(&self, client: &'a dyn Client);
+
+    /// The role of the node in the network.
+    fn role(&self) -> Role;
+
+    /// The RLOC16 of the node, if it is a child.
+    fn rloc16(&self) -> Option<u16>;
+
+    /// Join the network of `dataset`: tune the radio to it and attach to a
+    /// parent. `role_changed` is called once the node is a child.
+    ///
+    /// Return values:
+    ///   - Ok(()): the node is attaching to a parent.
+    ///   - Err(ALREADY): the node joined a network.
+    ///   - Err(BUSY): the keys of a received message are being derived.
+    ///   - Err(INVAL): the radio does not support the channel.
+    fn join(&self, dataset: Dataset) -> Result<(), ErrorCode>;
+
+    /// Leave the network.
+    ///
+    /// Return values:
+    ///   - Ok(()): the node left the network.
+    ///   - Err(ALREADY): the node did not join a network.
+    fn detach(&self) -> Result<(), ErrorCode>;
+}
+
+/// The parameters of the network to join, from its Active Operational
+/// Dataset (Section 8.10.1).
+#[derive(Copy, Clone)]
+pub struct Dataset {
+    pub network_key: [u8; KEY_LEN],
+    pub pan_id: u16,
+    pub channel: u8,
+    pub mesh_local_prefix: [u8; 8],
+    pub key_sequence: u32,
+}
+
+impl Dataset {
+    /// Decodes a dataset from the Network Management TLVs of `buf`. The key
+    /// sequence is 0 unless the dataset carries it.
+    ///
+    /// Return values:
+    ///   - Err(NOSUPPORT): the dataset has a PSKc but no network key, so the
+    ///     node would have to be commissioned.
+    ///   - Err(INVAL): the network key, PAN ID, channel or mesh-local prefix
+    ///     is missing, or the TLVs are malformed.
+    pub fn decode(mut buf: &[u8]) -> Result<Dataset, ErrorCode> {
+        let mut network_key = None;
+        let mut pskc = false;
+        let mut pan_id = None;
+        let mut channel = None;
+        let mut mesh_local_prefix = None;
+        let mut key_sequence = 0;
+        while !buf.is_empty() {
+            let len = tlv_len(buf).ok_or(ErrorCode::INVAL)?;
+            if let SResult::Done(_, tlv) = NetworkManagementTlv::decode(&buf[..len]) {
+                match tlv {
+                    NetworkManagementTlv::NetworkMasterKey(key) => network_key = Some(key),
+                    NetworkManagementTlv::PanId(id) => pan_id = Some(id),
+                    NetworkManagementTlv::Channel { channel: ch, .. } => {
+                        channel = u8::try_from(ch).ok();
+                    }
+                    NetworkManagementTlv::NetworkMeshLocalPrefix(prefix) => {
+                        mesh_local_prefix = Some(prefix);
+                    }
+                    NetworkManagementTlv::NetworkKeySequenceCounter(seq) => {
+                        key_sequence = u32::from_be_bytes(seq);
+                    }
+                    NetworkManagementTlv::Pskc(_) => pskc = true,
+                    _ => {}
+                }
+            }
+            buf = &buf[len..];
+        }
+        if network_key.is_none() && pskc {
+            return Err(ErrorCode::NOSUPPORT);
+        }
+        match (network_key, pan_id, channel, mesh_local_prefix) {
+            (Some(network_key), Some(pan_id), Some(channel), Some(mesh_local_prefix)) => {
+                Ok(Dataset {
+                    network_key,
+                    pan_id,
+                    channel,
+                    mesh_local_prefix,
+                    key_sequence,
+                })
+            }
+            _ => Err(ErrorCode::INVAL),
+        }
+    }
+}
+
+/// The MLE and MAC keys of a key sequence.
+#[derive(Copy, Clone)]
+struct Keys {
+    sequence: u32,
+    mle: [u8; KEY_LEN],
+    mac: [u8; KEY_LEN],
+}
+
+impl Keys {
+    fn key_index(&self) -> u8 {
+        (self.sequence & 0x7f) as u8 + 1
+    }
+}
+
+/// A router that answered the Parent Request, and then the parent.
+#[derive(Copy, Clone)]
+struct Parent {
+    ext_addr: [u8; 8],
+    rloc16: u16,
+    /// The challenge of its Parent Response, which the Child ID Request
+    /// answers.
+    challenge: [u8; MAX_CHALLENGE_LEN],
+    challenge_len: usize,
+    /// The lowest MAC and MLE frame counters accepted from it.
+    mac_frame_counter: u32,
+    mle_frame_counter: u32,
+    priority: i8,
+    link_margin: u8,
+}
+
+impl Parent {
+    /// Whether the parent is better than `other`: its priority is higher, or
+    /// its link margin is with the same priority.
+    fn is_better_than(&self, other: &Parent) -> bool {
+        (self.priority, self.link_margin) > (other.priority, other.link_margin)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum State {
+    Disabled,
+    /// Deriving the keys of the dataset before attaching.
+    Starting,
+    /// Waiting for Parent Responses from routers, or from routers and REEDs.
+    ParentRequest {
+        reeds: bool,
+    },
+    ChildIdRequest {
+        attempts: u8,
+    },
+    /// Waiting to attach again after failing to.
+    Backoff,
+    Child,
+    /// A keep-alive Child Update Request is not answered yet.
+    ChildUpdate {
+        attempts: u8,
+    },
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Request {
+    Parent { reeds: bool },
+    ChildId,
+    ChildUpdate,
+    Data,
+}
+
+/// A received message in the CCM buffer.
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Received {
+    src: IPAddr,
+    frame_counter: u32,
+    key_sequence: u32,
+    len: usize,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Crypt {
+    Idle,
+    /// A message of `len` bytes to `dst` is being encrypted.
+    Encrypt {
+        dst: IPAddr,
+        len: usize,
+    },
+    /// The keys of the message are being derived.
+    AwaitKeys(Received),
+    Decrypt(Received),
+}
+
+/// Why keys are being derived.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Derive {
+    Join,
+    Receive,
+}
+
+/// The extended address of the node whose link-local address is `addr`.
+fn ext_addr(addr: &IPAddr) -> [u8; 8] {
+    let mut ext = [0; 8];
+    ext.copy_from_slice(&addr.0[8..]);
+    ext[0] ^= 0x02;
+    ext
+}
+
+/// The link-local address of the node with the extended address `ext`.
+fn link_local(ext: [u8; 8]) -> IPAddr {
+    IPAddr::generate_from_mac(MacAddress::Long(ext))
+}
+
+/// The TLV of type `tlv_type` among `tlvs`, if it is present and valid.
+fn find_tlv(mut tlvs: &[u8], tlv_type: TlvType) -> Option<Tlv<'_>> {
+    let tlv_type = tlv_type as u8;
+    while !tlvs.is_empty() {
+        let len = tlv_len(tlvs)?;
+        if tlvs[0] == tlv_type {
+            return Tlv::decode(&tlvs[..len]).done().map(|(_, tlv)| tlv);
+        }
+        tlvs = &tlvs[len..];
+    }
+    None
+}
+
+pub struct Mle<'a, A: Alarm<'a>, C: AES128CCM<'a>, D: digest::Digest<'a, DIGEST_LEN>> {
+    sender: &'a dyn UDPSender<'a>,
+    alarm: &'a A,
+    ccm: &'a C,
+    sha: &'a D,
+    rng: &'a dyn Random<'a>,
+    radio: &'a dyn RadioConfig<'a>,
+    interface_list: &'a InterfaceList,
+    net_cap: &'static NetworkCapability,
+    client: OptionalCell<&'a dyn Client>,
+
+    state: Cell<State>,
+    dataset: OptionalCell<Dataset>,
+    keys: OptionalCell<Keys>,
+    /// The keys of another key sequence that a message was received with.
+    other_keys: OptionalCell<Keys>,
+    /// Why and for which key sequence keys are being derived.
+    derive: OptionalCell<(Derive, u32)>,
+    /// The candidate parent while attaching, and then the parent.
+    parent: OptionalCell<Parent>,
+    rloc16: OptionalCell<u16>,
+    /// The short address of the radio before joining.
+    short_addr: Cell<u16>,
+    data_version: Cell<u8>,
+    prefixes: Cell<[Option<[u8; 8]>; MAX_PREFIXES]>,
+    attach_attempts: Cell<u32>,
+    challenge: Cell<[u8; CHALLENGE_LEN]>,
+    frame_counter: Cell<u32>,
+    pending: OptionalCell<Request>,
+    crypt: Cell<Crypt>,
+
+    tx_buffer: TakeCell<'static, [u8]>,
+    /// The additional data, message and MIC of the message being encrypted
+    /// or decrypted.
+    ccm_buffer: TakeCell<'static, [u8]>,
+    kdf_buffer: TakeCell<'static, [u8]>,
+    digest: TakeCell<'static, [u8; DIGEST_LEN]>,
+}
+
+impl<
+        'a,
+        A: Alarm<'a>,
+        C: AES128CCM<'a>,
+        D: digest::Digest<'a, DIGEST_LEN> + digest::HmacSha256,
+    > Mle<'a, A, C, D>
+{
+    pub fn new(
+        sender: &'a dyn UDPSender<'a>,
+        alarm: &'a A,
+        ccm: &'a C,
+        sha: &'a D,
+        rng: &'a dyn Random<'a>,
+        radio: &'a dyn RadioConfig<'a>,
+        interface_list: &'a InterfaceList,
+        net_cap: &'static NetworkCapability,
+        tx_buffer: &'static mut [u8],
+        ccm_buffer: &'static mut [u8],
+        kdf_buffer: &'static mut [u8],
+        digest: &'static mut [u8; DIGEST_LEN],
+    ) -> Mle<'a, A, C, D> {
+        Mle {
+            sender,
+            alarm,
+            ccm,
+            sha,
+            rng,
+            radio,
+            interface_list,
+            net_cap,
+            client: OptionalCell::empty(),
+            state: Cell::new(State::Disabled),
+            dataset: OptionalCell::empty(),
+            keys: OptionalCell::empty(),
+            other_keys: OptionalCell::empty(),
+            derive: OptionalCell::empty(),
+            parent: OptionalCell::empty(),
+            rloc16: OptionalCell::empty(),
+            short_addr: Cell::new(0),
+            data_version: Cell::new(0),
+            prefixes: Cell::new([None; MAX_PREFIXES]),
+            attach_attempts: Cell::new(0),
+            challenge: Cell::new([0; CHALLENGE_LEN]),
+            frame_counter: Cell::new(0),
+            pending: OptionalCell::empty(),
+            crypt: Cell::new(Crypt::Idle),
+            tx_buffer: TakeCell::new(tx_buffer),
+            ccm_buffer: TakeCell::new(ccm_buffer),
+            kdf_buffer: TakeCell::new(kdf_buffer),
+            digest: TakeCell::new(digest),
+        }
+    }
+
+    fn report_role(&self) {
+        let role = self.role();
+        let rloc16 = self.rloc16.extract();
+        self.client.map(|client| client.role_changed(role, rloc16));
+    }
+
+    fn mesh_local_prefix(&self) -> [u8; 8] {
+        self.dataset
+            .map_or([0; 8], |dataset| dataset.mesh_local_prefix)
+    }
+
+    /// The RLOC of the node with the RLOC16 `rloc16`.
+    fn rloc(&self, rloc16: u16) -> IPAddr {
+        let mut addr = IPAddr::new();
+        addr.0[..8].copy_from_slice(&self.mesh_local_prefix());
+        addr.0[11] = 0xff;
+        addr.0[12] = 0xfe;
+        addr.0[14..].copy_from_slice(&rloc16.to_be_bytes());
+        addr
+    }
+
+    fn ml_eid(&self) -> IPAddr {
+        self.interface_list.form_address(&self.mesh_local_prefix())
+    }
+
+    /// Forget the parent and the addresses it gave the node.
+    fn leave_parent(&self) {
+        if let Some(rloc16) = self.rloc16.take() {
+            let _ = self.interface_list.remove(self.rloc(rloc16));
+            let _ = self.interface_list.remove(self.ml_eid());
+        }
+        for prefix in self.prefixes.replace([None; MAX_PREFIXES]).iter().flatten() {
+            self.interface_list
+                .autoconfigure(prefix, PREFIX_LEN_BITS, false);
+        }
+        self.parent.clear();
+    }
+
+    // Attaching
+
+    /// Start attaching to a parent with a Parent Request to routers.
+    fn attach(&self) {
+        self.parent.clear();
+        self.new_challenge();
+        self.state.set(State::ParentRequest { reeds: false });
+        self.arm_ms(PARENT_RESPONSE_ROUTERS_MS);
+        self.send(Request::Parent { reeds: false });
+    }
+
+    /// Attach again after a delay, once attaching failed.
+    fn backoff(&self) {
+        let attempts = self.attach_attempts.get();
+        self.attach_attempts.set(attempts.saturating_add(1));
+        let delay = ATTACH_BACKOFF_MIN_S
+            .checked_shl(attempts)
+            .map_or(ATTACH_BACKOFF_MAX_S, |delay| {
+                delay.min(ATTACH_BACKOFF_MAX_S)
+            });
+        self.parent.clear();
+        self.state.set(State::Backoff);
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_seconds(delay));
+    }
+
+    /// The parent stopped answering: attach again.
+    fn lose_parent(&self) {
+        self.leave_parent();
+        self.radio.set_address(self.short_addr.get());
+        self.radio.config_commit();
+        self.attach();
+        self.report_role();
+    }
+
+    fn request_child_id(&self, attempts: u8) {
+        self.state.set(State::ChildIdRequest { attempts });
+        self.arm_ms(CHILD_ID_RESPONSE_MS);
+        self.send(Request::ChildId);
+    }
+
+    fn update_child(&self, attempts: u8) {
+        self.new_challenge();
+        self.state.set(State::ChildUpdate { attempts });
+        self.arm_ms(CHILD_UPDATE_RESPONSE_MS);
+        self.send(Request::ChildUpdate);
+    }
+
+    /// Become the child of the parent with the RLOC16 `rloc16`.
+    fn become_child(&self, rloc16: u16) {
+        self.rloc16.set(rloc16);
+        self.radio.set_address(rloc16);
+        self.radio.config_commit();
+        let _ = self.interface_list.add(self.rloc(rloc16));
+        let _ = self.interface_list.add(self.ml_eid());
+        self.attach_attempts.set(0);
+        self.keep_alive();
+        self.report_role();
+    }
+
+    /// Arm the next keep-alive Child Update Request.
+    fn keep_alive(&self) {
+        self.state.set(State::Child);
+        self.alarm.set_alarm(
+            self.alarm.now(),
+            self.alarm.ticks_from_seconds(CHILD_TIMEOUT_S / 2),
+        );
+    }
+
+    fn arm_ms(&self, ms: u32) {
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(ms));
+    }
+
+    fn new_challenge(&self) {
+        let mut challenge = [0; CHALLENGE_LEN];
+        for chunk in challenge.chunks_mut(4) {
+            chunk.copy_from_slice(&self.rng.random().to_be_bytes());
+        }
+        self.challenge.set(challenge);
+    }
+
+    // Key derivation
+
+    /// Derive the keys of the key sequence `sequence` from the network key.
+    fn start_deriving(&self, sequence: u32, derive: Derive) -> Result<(), ErrorCode> {
+        if self.derive.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        let network_key = self
+            .dataset
+            .map(|dataset| dataset.network_key)
+            .ok_or(ErrorCode::FAIL)?;
+        let buf = self.kdf_buffer.take().ok_or(ErrorCode::BUSY)?;
+        buf[..4].copy_from_slice(&sequence.to_be_bytes());
+        buf[4..KDF_BUF_LEN].copy_from_slice(KDF_LABEL);
+        if let Err(e) = self.sha.set_mode_hmacsha256(&network_key) {
+            self.kdf_buffer.replace(buf);
+            return Err(e);
+        }
+        let mut data = LeasableMutableBuffer::new(buf);
+        data.slice(0..KDF_BUF_LEN);
+        self.sha.add_mut_data(data).map_err(|(e, mut data)| {
+            data.reset();
+            self.kdf_buffer.replace(data.take());
+            e
+        })?;
+        self.derive.set((derive, sequence));
+        Ok(())
+    }
+
+    fn derived(&self, derive: Derive, sequence: u32, digest: &[u8; DIGEST_LEN]) {
+        let mut keys = Keys {
+            sequence,
+            mle: [0; KEY_LEN],
+            mac: [0; KEY_LEN],
+        };
+        keys.mle.copy_from_slice(&digest[..KEY_LEN]);
+        keys.mac.copy_from_slice(&digest[KEY_LEN..]);
+        match derive {
+            Derive::Join => {
+                if self.state.get() == State::Starting {
+                    self.keys.set(keys);
+                    self.attach();
+                }
+            }
+            Derive::Receive => {
+                self.other_keys.set(keys);
+                if let Crypt::AwaitKeys(received) = self.crypt.get() {
+                    self.crypt.set(Crypt::Idle);
+                    self.decrypt(received);
+                }
+            }
+        }
+    }
+
+    fn derive_failed(&self, derive: Derive) {
+        match derive {
+            Derive::Join => {
+                if self.state.get() == State::Starting {
+                    self.backoff();
+                }
+            }
+            Derive::Receive => {
+                if let Crypt::AwaitKeys(_) = self.crypt.get() {
+                    self.crypt.set(Crypt::Idle);
+                    self.try_send();
+                }
+            }
+        }
+    }
+
+    fn keys_of(&self, sequence: u32) -> Option<Keys> {
+        self.keys
+            .extract()
+            .filter(|keys| keys.sequence == sequence)
+            .or_else(|| {
+                self.other_keys
+                    .extract()
+                    .filter(|keys| keys.sequence == sequence)
+            })
+    }
+
+    /// Adopt a newer key sequence, whose message was authenticated. Frame
+    /// counters start over with the new keys.
+    fn adopt_keys(&self, sequence: u32) {
+        let newer = self.keys.map_or(true, |keys| sequence > keys.sequence);
+        if let Some(keys) = self
+            .other_keys
+            .extract()
+            .filter(|keys| keys.sequence == sequence)
+        {
+            if newer {
+                self.keys.set(keys);
+                self.other_keys.clear();
+                if let Some(mut parent) = self.parent.extract() {
+                    parent.mac_frame_counter = 0;
+                    parent.mle_frame_counter = 0;
+                    self.parent.set(parent);
+                }
+            }
+        }
+    }
+
+    // Sending
+
+    /// Send `message` once the messages before it are sent.
+    fn send(&self, message: Request) {
+        self.pending.set(message);
+        self.try_send();
+    }
+
+    /// Encrypt the pending message, if the buffers are free.
+    fn try_send(&self) {
+        if self.crypt.get() != Crypt::Idle || self.tx_buffer.is_none() {
+            return;
+        }
+        let (Some(message), Some(keys)) = (self.pending.extract(), self.keys.extract()) else {
+            return;
+        };
+        let Some(buf) = self.ccm_buffer.take() else {
+            return;
+        };
+        self.pending.clear();
+        let dst = match message {
+            Request::Parent { .. } => ALL_ROUTERS,
+            _ => match self.parent.extract() {
+                Some(parent) => link_local(parent.ext_addr),
+                None => {
+                    self.ccm_buffer.replace(buf);
+                    return;
+                }
+            },
+        };
+        let src = self.interface_list.select_source(dst);
+        let Some(len) = self.encode_message(
+            message,
+            &mut buf[A_DATA_LEN..A_DATA_LEN + MAX_TX_MESSAGE_LEN],
+        ) else {
+            self.ccm_buffer.replace(buf);
+            return;
+        };
+
+        let frame_counter = self.frame_counter.get();
+        self.frame_counter.set(frame_counter.wrapping_add(1));
+        buf[..16].copy_from_slice(&src.0);
+        buf[16..32].copy_from_slice(&dst.0);
+        encode_aux_header(&mut buf[32..A_DATA_LEN], frame_counter, &keys);
+        let result = self.start_ccm(buf, &keys.mle, ext_addr(&src), frame_counter, len, true);
+        if result.is_ok() {
+            self.crypt.set(Crypt::Encrypt { dst, len });
+        }
+    }
+
+    /// Encode the command and TLVs of `message` in `buf`, and return their
+    /// length.
+    fn encode_message(&self, message: Request, buf: &mut [u8]) -> Option<usize> {
+        let mode = LinkMode::ReceiverOnWhenIdle as u8 | LinkMode::SecureDataRequests as u8;
+        let challenge = self.challenge.get();
+        let mut address_registration = [0; 9];
+        // The mesh-local EID, compressed with context 0.
+        address_registration[0] = 0x80;
+        address_registration[1..].copy_from_slice(&self.ml_eid().0[8..]);
+
+        match message {
+            Request::Parent { reeds } => encode_tlvs(
+                buf,
+                command::PARENT_REQUEST,
+                &[
+                    Tlv::Mode(mode),
+                    Tlv::Challenge(&challenge),
+                    Tlv::ScanMask(if reeds {
+                        SCAN_ROUTERS_AND_REEDS
+                    } else {
+                        SCAN_ROUTERS
+                    }),
+                    Tlv::Version(THREAD_VERSION),
+                ],
+            ),
+            Request::ChildId => {
+                let parent = self.parent.extract()?;
+                let response = &parent.challenge[..parent.challenge_len];
+                encode_tlvs(
+                    buf,
+                    command::CHILD_ID_REQUEST,
+                    &[
+                        Tlv::Response(response),
+                        // The MAC frame counter is kept by the 802.15.4
+                        // driver; 0 lets the parent accept any of its frames.
+                        Tlv::LinkLayerFrameCounter(0),
+                        Tlv::MleFrameCounter(self.frame_counter.get()),
+                        Tlv::Mode(mode),
+                        Tlv::Timeout(CHILD_TIMEOUT_S),
+                        Tlv::Version(THREAD_VERSION),
+                        Tlv::TlvRequest(&[TlvType::Address16 as u8, TlvType::NetworkData as u8]),
+                        Tlv::AddressRegistration(&address_registration),
+                    ],
+                )
+            }
+            Request::ChildUpdate => encode_tlvs(
+                buf,
+                command::CHILD_UPDATE_REQUEST,
+                &[
+                    Tlv::Mode(mode),
+                    Tlv::Challenge(&challenge),
+                    Tlv::Timeout(CHILD_TIMEOUT_S),
+                    Tlv::AddressRegistration(&address_registration),
+                ],
+            ),
+            Request::Data => encode_tlvs(
+                buf,
+                command::DATA_REQUEST,
+                &[Tlv::TlvRequest(&[TlvType::NetworkData as u8])],
+            ),
+        }
+    }
+
+    /// Start a CCM operation on `buf`, which holds the additional data
+    /// followed by `len` bytes of message and the MIC. The buffer is kept if
+    /// the operation fails to start.
+    fn start_ccm(
+        &self,
+        buf: &'static mut [u8],
+        key: &[u8; KEY_LEN],
+        sender: [u8; 8],
+        frame_counter: u32,
+        len: usize,
+        encrypting: bool,
+    ) -> Result<(), ErrorCode> {
+        let mut nonce = [0; CCM_NONCE_LENGTH];
+        nonce[..8].copy_from_slice(&sender);
+        nonce[8..12].copy_from_slice(&frame_counter.to_be_bytes());
+        nonce[12] = SECURITY_CONTROL & 0x07;
+        let result = self
+            .ccm
+            .set_key(key)
+            .and_then(|()| self.ccm.set_nonce(&nonce));
+        if let Err(e) = result {
+            self.ccm_buffer.replace(buf);
+            return Err(e);
+        }
+        self.ccm
+            .crypt(buf, 0, A_DATA_LEN, len, MIC_LEN, true, encrypting)
+            .map_err(|(e, buf)| {
+                self.ccm_buffer.replace(buf);
+                e
+            })
+    }
+
+    /// Send the encrypted message in the CCM buffer to `dst`.
+    fn send_encrypted(&self, dst: IPAddr, ccm: &[u8]) {
+        let Some(tx) = self.tx_buffer.take() else {
+            return;
+        };
+        let len = ccm.len() - 32;
+        tx[0] = SECURITY_SUITE;
+        tx[1..1 + len].copy_from_slice(&ccm[32..]);
+        let mut dgram = LeasableMutableBuffer::new(tx);
+        dgram.slice(0..1 + len);
+        if let Err(mut dgram) = self.sender.send_to(dst, MLE_PORT, dgram, self.net_cap) {
+            dgram.reset();
+            self.tx_buffer.replace(dgram.take());
+        }
+    }
+
+    // Receiving
+
+    /// Start decrypting the received message in the CCM buffer.
+    fn decrypt(&self, received: Received) {
+        let Some(buf) = self.ccm_buffer.take() else {
+            return;
+        };
+        match self.keys_of(received.key_sequence) {
+            Some(keys) => {
+                let result = self.start_ccm(
+                    buf,
+                    &keys.mle,
+                    ext_addr(&received.src),
+                    received.frame_counter,
+                    received.len,
+                    false,
+                );
+                if result.is_ok() {
+                    self.crypt.set(Crypt::Decrypt(received));
+                }
+            }
+            None => {
+                // The message waits in the buffer for its keys.
+                self.ccm_buffer.replace(buf);
+                if self
+                    .start_deriving(received.key_sequence, Derive::Receive)
+                    .is_ok()
+                {
+                    self.crypt.set(Crypt::AwaitKeys(received));
+                }
+            }
+        }
+    }
+
+    /// Handle an authenticated message from `src`.
+    fn receive_message(&self, src: IPAddr, frame_counter: u32, message: &[u8]) {
+        let Some((&command, tlvs)) = message.split_first() else {
+            return;
+        };
+        let sender = ext_addr(&src);
+        let from_parent = self
+            .parent
+            .extract()
+            .filter(|parent| parent.ext_addr == sender);
+        match (self.state.get(), command) {
+            (State::ParentRequest { .. }, command::PARENT_RESPONSE) => {
+                self.receive_parent_response(sender, frame_counter, tlvs);
+            }
+            (State::ChildIdRequest { .. }, command::CHILD_ID_RESPONSE) => {
+                if let Some(parent) = from_parent {
+                    self.accept_parent_frame_counter(parent, frame_counter);
+                    if let Some(Tlv::Address16(rloc16)) = find_tlv(tlvs, TlvType::Address16) {
+                        self.receive_network_data(tlvs);
+                        self.become_child(rloc16);
+                    }
+                }
+            }
+            (State::Child | State::ChildUpdate { .. }, _) => {
+                let Some(parent) = from_parent else {
+                    return;
+                };
+                if !self.accept_parent_frame_counter(parent, frame_counter) {
+                    return;
+                }
+                self.receive_from_parent(command, tlvs);
+            }
+            _ => {}
+        }
+    }
+
+    /// Handle a message of the parent of the node.
+    fn receive_from_parent(&self, command: u8, tlvs: &[u8]) {
+        match command {
+            command::CHILD_UPDATE_RESPONSE => {
+                if find_tlv(tlvs, TlvType::Status).is_some() {
+                    // The parent no longer knows the node.
+                    self.lose_parent();
+                    return;
+                }
+                let answered = matches!(
+                    find_tlv(tlvs, TlvType::Response),
+                    Some(Tlv::Response(response)) if response == self.challenge.get()
+                );
+                if answered && matches!(self.state.get(), State::ChildUpdate { .. }) {
+                    self.keep_alive();
+                }
+                self.check_data_version(tlvs);
+            }
+            command::DATA_RESPONSE => self.receive_network_data(tlvs),
+            command::ADVERTISEMENT => self.check_data_version(tlvs),
+            _ => {}
+        }
+    }
+
+    fn receive_parent_response(&self, sender: [u8; 8], frame_counter: u32, tlvs: &[u8]) {
+        let answered = matches!(
+            find_tlv(tlvs, TlvType::Response),
+            Some(Tlv::Response(response)) if response == self.challenge.get()
+        );
+        let (
+            Some(Tlv::SourceAddress(rloc16)),
+            Some(Tlv::Challenge(challenge)),
+            Some(Tlv::LinkLayerFrameCounter(mac_frame_counter)),
+            Some(Tlv::Connectivity {
+                parent_priority, ..
+            }),
+            Some(Tlv::LinkMargin(link_margin)),
+        ) = (
+            find_tlv(tlvs, TlvType::SourceAddress),
+            find_tlv(tlvs, TlvType::Challenge),
+            find_tlv(tlvs, TlvType::LinkLayerFrameCounter),
+            find_tlv(tlvs, TlvType::Connectivity),
+            find_tlv(tlvs, TlvType::LinkMargin),
+        )
+        else {
+            return;
+        };
+        if !answered || challenge.len() > MAX_CHALLENGE_LEN {
+            return;
+        }
+        let mle_frame_counter = match find_tlv(tlvs, TlvType::MleFrameCounter) {
+            Some(Tlv::MleFrameCounter(counter)) => counter,
+            _ => frame_counter,
+        };
+        let mut candidate = Parent {
+            ext_addr: sender,
+            rloc16,
+            challenge: [0; MAX_CHALLENGE_LEN],
+            challenge_len: challenge.len(),
+            mac_frame_counter,
+            mle_frame_counter: mle_frame_counter.saturating_add(1),
+            // The priority is a signed 2-bit value in the top bits.
+            priority: (parent_priority as i8) >> 6,
+            link_margin,
+        };
+        candidate.challenge[..challenge.len()].copy_from_slice(challenge);
+        if self
+            .parent
+            .map_or(true, |parent| candidate.is_better_than(parent))
+        {
+            self.parent.set(candidate);
+        }
+    }
+
+    /// Whether the MLE frame counter of a message of the parent is not a
+    /// replay, in which case it is recorded.
+    fn accept_parent_frame_counter(&self, mut parent: Parent, frame_counter: u32) -> bool {
+        if frame_counter < parent.mle_frame_counter {
+            return false;
+        }
+        parent.mle_frame_counter = frame_counter.saturating_add(1);
+        self.parent.set(parent);
+        true
+    }
+
+    /// Ask the parent for the Network Data if the data version of its Leader
+    /// Data changed.
+    fn check_data_version(&self, tlvs: &[u8]) {
+        if let Some(Tlv::LeaderData { data_version, .. }) = find_tlv(tlvs, TlvType::LeaderData) {
+            if data_version != self.data_version.get() {
+                self.send(Request::Data);
+            }
+        }
+    }
+
+    /// Form addresses from the SLAAC prefixes of the Network Data in `tlvs`,
+    /// and remove those of prefixes that are gone.
+    fn receive_network_data(&self, tlvs: &[u8]) {
+        let Some(Tlv::NetworkData(mut data)) = find_tlv(tlvs, TlvType::NetworkData) else {
+            return;
+        };
+        if let Some(Tlv::LeaderData { data_version, .. }) = find_tlv(tlvs, TlvType::LeaderData) {
+            self.data_version.set(data_version);
+        }
+        let mut prefixes = [None; MAX_PREFIXES];
+        let mut count = 0;
+        while let Some(len) = tlv_len(data) {
+            if let SResult::Done(
+                _,
+                (
+                    NetworkDataTlv::Prefix {
+                        prefix_length_bits: PREFIX_LEN_BITS,
+                        prefix,
+                        sub_tlvs,
+                        ..
+                    },
+                    _,
+                ),
+            ) = NetworkDataTlv::decode(&data[..len])
+            {
+                if count < MAX_PREFIXES && allows_slaac(sub_tlvs) {
+                    let mut bytes = [0; 8];
+                    bytes.copy_from_slice(prefix);
+                    prefixes[count] = Some(bytes);
+                    count += 1;
+                }
+            }
+            data = &data[len..];
+        }
+
+        let old = self.prefixes.replace(prefixes);
+        for prefix in old.iter().flatten() {
+            if !prefixes.contains(&Some(*prefix)) {
+                self.interface_list
+                    .autoconfigure(prefix, PREFIX_LEN_BITS, false);
+            }
+        }
+        for prefix in prefixes.iter().flatten() {
+            self.interface_list
+                .autoconfigure(prefix, PREFIX_LEN_BITS, true);
+        }
+    }
+}
+
+/// Whether a border router of the sub-TLVs of a Prefix TLV allows SLAAC
+/// with the prefix.
+fn allows_slaac(mut sub_tlvs: &[u8]) -> bool {
+    let slaac = BorderRouterTlvValueBit::S as u16 | BorderRouterTlvValueBit::P as u16;
+    while let Some(len) = tlv_len(sub_tlvs) {
+        if let SResult::Done(_, (PrefixSubTlv::BorderRouter(entries), _)) =
+            PrefixSubTlv::decode(&sub_tlvs[..len])
+        {
+            let allowed = entries.chunks_exact(4).any(|entry| {
+                BorderRouterTlvValue::decode(entry)
+                    .done()
+                    .is_some_and(|(_, value)| value.p_bits & slaac == slaac)
+            });
+            if allowed {
+                return true;
+            }
+        }
+        sub_tlvs = &sub_tlvs[len..];
+    }
+    false
+}
+
+/// Encode `command` followed by `tlvs` in `buf`, and return their length.
+fn encode_tlvs(buf: &mut [u8], command: u8, tlvs: &[Tlv]) -> Option<usize> {
+    *buf.first_mut()? = command;
+    let mut off = 1;
+    for tlv in tlvs {
+        let (len, ()) = tlv.encode(buf.get_mut(off..)?).done()?;
+        off += len;
+    }
+    Some(off)
+}
+
+fn encode_aux_header(buf: &mut [u8], frame_counter: u32, keys: &Keys) {
+    buf[0] = SECURITY_CONTROL;
+    buf[1..5].copy_from_slice(&frame_counter.to_le_bytes());
+    buf[5..9].copy_from_slice(&keys.sequence.to_be_bytes());
+    buf[9] = keys.key_index();
+}
+
+impl<
+        'a,
+        A: Alarm<'a>,
+        C: AES128CCM<'a>,
+        D: digest::Digest<'a, DIGEST_LEN> + digest::HmacSha256,
+    > ThreadNetwork<'a> for Mle<'a, A, C, D>
+{
+    fn set_client(&self, client: &'a dyn Client) {
+        self.client.set(client);
+    }
+
+    fn role(&self) -> Role {
+        match self.state.get() {
+            State::Disabled => Role::Disabled,
+            State::Child | State::ChildUpdate { .. } => Role::Child,
+            _ => Role::Detached,
+        }
+    }
+
+    fn rloc16(&self) -> Option<u16> {
+        self.rloc16.extract()
+    }
+
+    fn join(&self, dataset: Dataset) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Disabled {
+            return Err(ErrorCode::ALREADY);
+        }
+        self.radio.set_channel(dataset.channel)?;
+        self.radio.set_pan(dataset.pan_id);
+        self.short_addr.set(self.radio.get_address());
+        self.radio.config_commit();
+
+        self.dataset.set(dataset);
+        self.keys.clear();
+        self.other_keys.clear();
+        self.attach_attempts.set(0);
+        self.frame_counter.set(self.rng.random());
+        self.start_deriving(dataset.key_sequence, Derive::Join)?;
+        self.state.set(State::Starting);
+        self.report_role();
+        Ok(())
+    }
+
+    fn detach(&self) -> Result<(), ErrorCode> {
+        if self.state.get() == State::Disabled {
+            return Err(ErrorCode::ALREADY);
+        }
+        let _ = self.alarm.disarm();
+        self.leave_parent();
+        self.state.set(State::Disabled);
+        self.pending.clear();
+        self.radio.set_address(self.short_addr.get());
+        self.radio.config_commit();
+        self.report_role();
+        Ok(())
+    }
+}
+
+impl<
+        'a,
+        A: Alarm<'a>,
+        C: AES128CCM<'a>,
+        D: digest::Digest<'a, DIGEST_LEN> + digest::HmacSha256,
+    > UDPRecvClient for Mle<'a, A, C, D>
+{
+    fn receive(
+        &self,
+        src_addr: IPAddr,
+        dst_addr: IPAddr,
+        _src_port: u16,
+        _dst_port: u16,
+        payload: &[u8],
+    ) {
+        if self.state.get() == State::Disabled
+            || self.crypt.get() != Crypt::Idle
+            || !src_addr.is_unicast_link_local()
+            || payload.len() < 1 + AUX_HDR_LEN + MIC_LEN
+            || payload.len() > 1 + AUX_HDR_LEN + MAX_MESSAGE_LEN + MIC_LEN
+            || payload[0] != SECURITY_SUITE
+            || payload[1] != SECURITY_CONTROL
+        {
+            return;
+        }
+        let aux = &payload[1..1 + AUX_HDR_LEN];
+        let frame_counter = u32::from_le_bytes([aux[1], aux[2], aux[3], aux[4]]);
+        let key_sequence = u32::from_be_bytes([aux[5], aux[6], aux[7], aux[8]]);
+        if aux[9] != (key_sequence & 0x7f) as u8 + 1 {
+            return;
+        }
+        let Some(buf) = self.ccm_buffer.take() else {
+            return;
+        };
+        buf[..16].copy_from_slice(&src_addr.0);
+        buf[16..32].copy_from_slice(&dst_addr.0);
+        buf[32..32 + payload.len() - 1].copy_from_slice(&payload[1..]);
+        self.ccm_buffer.replace(buf);
+        self.decrypt(Received {
+            src: src_addr,
+            frame_counter,
+            key_sequence,
+            len: payload.len() - 1 - AUX_HDR_LEN - MIC_LEN,
+        });
+    }
+}
+
+impl<
+        'a,
+        A: Alarm<'a>,
+        C: AES128CCM<'a>,
+        D: digest::Digest<'a, DIGEST_LEN> + digest::HmacSha256,
+    > UDPSendClient for Mle<'a, A, C, D>
+{
+    fn send_done(
+        &self,
+        _result: Result<(), ErrorCode>,
+        mut dgram: LeasableMutableBuffer<'static, u8>,
+    ) {
+        // Lost messages are sent again on the timeouts of their state.
+        dgram.reset();
+        self.tx_buffer.replace(dgram.take());
+        self.try_send();
+    }
+}
+
+impl<
+        'a,
+        A: Alarm<'a>,
+        C: AES128CCM<'a>,
+        D: digest::Digest<'a, DIGEST_LEN> + digest::HmacSha256,
+    > CCMClient for Mle<'a, A, C, D>
+{
+    fn crypt_done(&self, buf: &'static mut [u8], res: Result<(), ErrorCode>, tag_is_valid: bool) {
+        match self.crypt.replace(Crypt::Idle) {
+            Crypt::Encrypt { dst, len } => {
+                if res.is_ok() {
+                    self.send_encrypted(dst, &buf[..A_DATA_LEN + len + MIC_LEN]);
+                }
+                self.ccm_buffer.replace(buf);
+            }
+            Crypt::Decrypt(received) => {
+                // The message is copied out of the buffer so that replies
+                // can be encrypted while it is handled.
+                let mut message = [0; MAX_MESSAGE_LEN];
+                message[..received.len]
+                    .copy_from_slice(&buf[A_DATA_LEN..A_DATA_LEN + received.len]);
+                self.ccm_buffer.replace(buf);
+                if res.is_ok() && tag_is_valid {
+                    self.adopt_keys(received.key_sequence);
+                    self.receive_message(
+                        received.src,
+                        received.frame_counter,
+                        &message[..received.len],
+                    );
+                }
+            }
+            Crypt::Idle | Crypt::AwaitKeys(_) => {
+                self.ccm_buffer.replace(buf);
+            }
+        }
+        self.try_send();
+    }
+}
+
+impl<
+        'a,
+        A: Alarm<'a>,
+        C: AES128CCM<'a>,
+        D: digest::Digest<'a, DIGEST_LEN> + digest::HmacSha256,
+    > time::AlarmClient for Mle<'a, A, C, D>
+{
+    fn alarm(&self) {
+        match self.state.get() {
+            State::ParentRequest { reeds } => {
+                if self.parent.is_some() {
+                    self.request_child_id(1);
+                } else if !reeds {
+                    self.state.set(State::ParentRequest { reeds: true });
+                    self.arm_ms(PARENT_RESPONSE_REEDS_MS);
+                    self.send(Request::Parent { reeds: true });
+                } else {
+                    self.backoff();
+                }
+            }
+            State::ChildIdRequest { attempts } => {
+                if attempts < MAX_CHILD_ID_REQUESTS {
+                    self.request_child_id(attempts + 1);
+                } else {
+                    self.backoff();
+                }
+            }
+            State::Backoff => self.attach(),
+            State::Child => self.update_child(1),
+            State::ChildUpdate { attempts } => {
+                if attempts < MAX_CHILD_UPDATE_REQUESTS {
+                    self.update_child(attempts + 1);
+                } else {
+                    self.lose_parent();
+                }
+            }
+            State::Disabled | State::Starting => {}
+        }
+    }
+}
+
+impl<
+        'a,
+        A: Alarm<'a>,
+        C: AES128CCM<'a>,
+        D: digest::Digest<'a, DIGEST_LEN> + digest::HmacSha256,
+    > digest::ClientData<DIGEST_LEN> for Mle<'a, A, C, D>
+{
+    fn add_data_done(&self, _result: Result<(), ErrorCode>, _data: LeasableBuffer<'static, u8>) {}
+
+    fn add_mut_data_done(
+        &self,
+        result: Result<(), ErrorCode>,
+        mut data: LeasableMutableBuffer<'static, u8>,
+    ) {
+        data.reset();
+        self.kdf_buffer.replace(data.take());
+        let result = result.and_then(|()| {
+            let digest = self.digest.take().ok_or(ErrorCode::BUSY)?;
+            self.sha.run(digest).map_err(|(e, digest)| {
+                self.digest.replace(digest);
+                e
+            })
+        });
+        if result.is_err() {
+            if let Some((derive, _)) = self.derive.take() {
+                self.derive_failed(derive);
+            }
+        }
+    }
+}
+
+impl<
+        'a,
+        A: Alarm<'a>,
+        C: AES128CCM<'a>,
+        D: digest::Digest<'a, DIGEST_LEN> + digest::HmacSha256,
+    > digest::ClientHash<DIGEST_LEN> for Mle<'a, A, C, D>
+{
+    fn hash_done(&self, result: Result<(), ErrorCode>, digest: &'static mut [u8; DIGEST_LEN]) {
+        let value = *digest;
+        self.digest.replace(digest);
+        if let Some((derive, sequence)) = self.derive.take() {
+            match result {
+                Ok(()) => self.derived(derive, sequence, &value),
+                Err(_) => self.derive_failed(derive),
+            }
+        }
+    }
+}
+
+impl<
+        'a,
+        A: Alarm<'a>,
+        C: AES128CCM<'a>,
+        D: digest::Digest<'a, DIGEST_LEN> + digest::HmacSha256,
+    > digest::ClientVerify<DIGEST_LEN> for Mle<'a, A, C, D>
+{
+    fn verification_done(
+        &self,
+        _result: Result<bool, ErrorCode>,
+        compare: &'static mut [u8; DIGEST_LEN],
+    ) {
+        self.digest.replace(compare);
+    }
+}
+
+impl<
+        'a,
+        A: Alarm<'a>,
+        C: AES128CCM<'a>,
+        D: digest::Digest<'a, DIGEST_LEN> + digest::HmacSha256,
+    > KeyProcedure for Mle<'a, A, C, D>
+{
+    /// The MAC key of the current key sequence.
+    fn lookup_key(
+        &self,
+        level: SecurityLevel,
+        key_id: KeyId,
+        _device_addr: Option<[u8; 8]>,
+    ) -> Option<[u8; 16]> {
+        self.keys
+            .extract()
+            .filter(|keys| {
+                level == SecurityLevel::EncMic32 && key_id == KeyId::Index(keys.key_index())
+            })
+            .map(|keys| keys.mac)
+    }
+}
+
+impl<
+        'a,
+        A: Alarm<'a>,
+        C: AES128CCM<'a>,
+        D: digest::Digest<'a, DIGEST_LEN> + digest::HmacSha256,
+    > DeviceProcedure for Mle<'a, A, C, D>
+{
+    /// The parent is the only device the node exchanges secured frames with.
+    fn lookup_addr_long(&self, addr: MacAddress) -> Option<[u8; 8]> {
+        self.parent
+            .extract()
+            .filter(|parent| match addr {
+                MacAddress::Short(addr) => addr == parent.rloc16,
+                MacAddress::Long(addr) => addr == parent.ext_addr,
+            })
+            .map(|parent| parent.ext_addr)
+    }
+
+    fn check_frame_counter(&self, device_addr: [u8; 8], frame_counter: u32) -> bool {
+        self.parent.map_or(false, |parent| {
+            parent.ext_addr == device_addr && frame_counter >= parent.mac_frame_counter
+        })
+    }
+
+    fn update_frame_counter(&self, device_addr: [u8; 8], frame_counter: u32) {
+        if let Some(mut parent) = self
+            .parent
+            .extract()
+            .filter(|parent| parent.ext_addr == device_addr)
+        {
+            parent.mac_frame_counter = frame_counter.saturating_add(1);
+            self.parent.set(parent);
+        }
+    }
+}
+
+impl<
+        'a,
+        A: Alarm<'a>,
+        C: AES128CCM<'a>,
+        D: digest::Digest<'a, DIGEST_LEN> + digest::HmacSha256,
+    > NeighborResolver for Mle<'a, A, C, D>
+{
+    /// A child sends all packets to its parent, and before that only talks
+    /// to the parent it attaches to at its link-local address.
+    fn resolve(&self, dst: IPAddr) -> Option<MacAddress> {
+        let attached = self.role() == Role::Child;
+        self.parent
+            .extract()
+            .filter(|parent| attached || dst == link_local(parent.ext_addr))
+            .map(|parent| MacAddress::Long(parent.ext_addr))
+    }
+}
+
+impl<
+        'a,
+        A: Alarm<'a>,
+        C: AES128CCM<'a>,
+        D: digest::Digest<'a, DIGEST_LEN> + digest::HmacSha256,
+    > LinkSecurity for Mle<'a, A, C, D>
+{
+    /// MLE messages are secured by MLE, and other packets are secured with
+    /// the MAC key once the node is attached.
+    fn security(
+        &self,
+        _dst: IPAddr,
+        transport_header: &TransportHeader,
+    ) -> Option<(SecurityLevel, KeyId)> {
+        if let TransportHeader::UDP(header) = transport_header {
+            if header.get_src_port() == MLE_PORT {
+                return None;
+            }
+        }
+        if self.role() != Role::Child {
+            return None;
+        }
+        self.keys
+            .extract()
+            .map(|keys| (SecurityLevel::EncMic32, KeyId::Index(keys.key_index())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHANNEL: [u8; 5] = [0, 3, 0, 0, 15];
+    const PAN_ID: [u8; 4] = [1, 2, 0x12, 0x34];
+    const PSKC: [u8; 18] = [
+        4, 16, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa,
+        0xaa, 0xaa,
+    ];
+    const NETWORK_KEY: [u8; 18] = [
+        5, 16, 0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd,
+        0xee, 0xff,
+    ];
+    const KEY_SEQUENCE: [u8; 6] = [6, 4, 0, 0, 0, 2];
+    const MESH_LOCAL_PREFIX: [u8; 10] = [7, 8, 0xfd, 0xde, 0xad, 0x00, 0xbe, 0xef, 0x00, 0x00];
+
+    fn dataset(tlvs: &[&[u8]]) -> Result<Dataset, ErrorCode> {
+        let mut buf = [0; 64];
+        let mut len = 0;
+        for tlv in tlvs {
+            buf[len..len + tlv.len()].copy_from_slice(tlv);
+            len += tlv.len();
+        }
+        Dataset::decode(&buf[..len])
+    }
+
+    #[test]
+    fn decode_active_dataset() {
+        let dataset = dataset(&[
+            &CHANNEL,
+            &PAN_ID,
+            &PSKC,
+            &NETWORK_KEY,
+            &KEY_SEQUENCE,
+            &MESH_LOCAL_PREFIX,
+        ])
+        .unwrap();
+        assert_eq!(dataset.channel, 15);
+        assert_eq!(dataset.pan_id, 0x1234);
+        assert_eq!(dataset.network_key, NETWORK_KEY[2..]);
+        assert_eq!(dataset.key_sequence, 2);
+        assert_eq!(dataset.mesh_local_prefix, MESH_LOCAL_PREFIX[2..]);
+    }
+
+    #[test]
+    fn decode_rejects_incomplete_dataset() {
+        assert_eq!(
+            dataset(&[&CHANNEL, &NETWORK_KEY, &MESH_LOCAL_PREFIX]).err(),
+            Some(ErrorCode::INVAL)
+        );
+        assert_eq!(
+            dataset(&[&CHANNEL, &PAN_ID, &NETWORK_KEY[..10]]).err(),
+            Some(ErrorCode::INVAL)
+        );
+    }
+
+    #[test]
+    fn decode_rejects_commissioning() {
+        assert_eq!(
+            dataset(&[&CHANNEL, &PAN_ID, &PSKC, &MESH_LOCAL_PREFIX]).err(),
+            Some(ErrorCode::NOSUPPORT)
+        );
+    }
+}
@@ -2,4 +2,10 @@
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 // Copyright Tock Contributors 2022.
 
+pub mod mle;
 pub mod tlv;
+
+mod driver;
+
+pub use self::driver::ThreadDriver;
+pub use self::driver::DRIVER_NUM;
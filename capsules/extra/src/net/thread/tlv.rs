@@ -9,20 +9,10 @@
 //!
 //! MLE messages consist of a command type and a series of TLV parameters.
 //!
-//! This module, as it stands, implements the minimum subset of TLVs
-//! required to support MLE for attaching a Sleepy End Device (SED) to a
-//! Thread network.
-//!
-//! MLE for network attaching comprises a four-step handshake that works
-//! as follows:
-//!
-//! 1. A child device multicasts a Parent Request MLE command.
-//! 2. Each potential parent device on the network unicasts a Parent
-//!    Response MLE command.
-//! 3. The child device selects a parent based on a hierarchy of
-//!    connectivity metrics and unicasts a Child ID Request MLE
-//!    command.
-//! 4. The selected parent unicasts a Child ID Response MLE command.
+//! This module, as it stands, implements the subset of TLVs required to
+//! attach a Minimal End Device (MED) to a Thread network with MLE, see
+//! `mle.rs`, and to interpret the Network Data and Operational Datasets
+//! carried in MLE messages.
 //!
 //! A TLV is comprised of three parts:
 //!
@@ -48,34 +38,42 @@
 //! sub-TLVs). Such a value is instead returned as a slice of the original
 //! buffer passed to the decode function.
 //!
+//! All multi-byte values are sent in network byte order (Section 4.4).
+//!
 //!
 //! Author: Mateo Garcia <mateog@stanford.edu>
 
-// TODO: Move the MLE explanation above to the MLE module, when it is created.
-
-// NOTES FOR DEBUGGING:
-// - .to_be() may not have been called on values wider than one byte
-// - encode_bytes_be may have been used instead of encode_bytes
-// - decode_bytes_be may have been used instead of decode_bytes
-// - See 4.5.25 Active Operational Dataset TLV and 4.5.26 Pending Operational Dataset TLV
-//    - Are Active and Pending Timestamp TLVs, respectively, required to be sent as well
-//      if either of the dataset tlvs are sent?
-
 use crate::net::stream::SResult;
-use crate::net::stream::{decode_bytes_be, decode_u16, decode_u32, decode_u8};
-use crate::net::stream::{encode_bytes, encode_bytes_be, encode_u16, encode_u32, encode_u8};
+use crate::net::stream::{decode_bytes, decode_u16, decode_u32, decode_u8};
+use crate::net::stream::{encode_bytes, encode_u16, encode_u32, encode_u8};
 use core::mem;
 
 const TL_WIDTH: usize = 2; // Type and length fields of TLV are each one byte.
 const MAX_VALUE_FIELD_LENGTH: usize = 128; // Assume a TLV value will be no longer than 128 bytes.
 
+/// The IANA enterprise number of Thread, implied by the T bit of a Service
+/// TLV (Section 5.18.6).
+pub const THREAD_ENTERPRISE_NUMBER: u32 = 44970;
+
+/// Returns the length of the TLV at the start of `buf`, including its type
+/// and length fields, so that TLVs whose type is not implemented can be
+/// skipped. Returns `None` if `buf` is too short to hold the TLV.
+pub fn tlv_len(buf: &[u8]) -> Option<usize> {
+    let len = TL_WIDTH + *buf.get(1)? as usize;
+    if len <= buf.len() {
+        Some(len)
+    } else {
+        None
+    }
+}
+
 /// Type-Length-Value structure.
 pub enum Tlv<'a> {
     SourceAddress(u16),
     Mode(u8),
     Timeout(u32),
-    Challenge([u8; 8]), // Byte string max length 8 bytes.
-    Response([u8; 8]),  // Byte string max length 8 bytes.
+    Challenge(&'a [u8]), // Byte string of 4 to 8 bytes.
+    Response(&'a [u8]),  // Byte string of 4 to 8 bytes.
     LinkLayerFrameCounter(u32),
     // LinkQuality,                  // TLV type Not used in Thread
     // NetworkParameter,             // TLV type Not used in Thread
@@ -109,10 +107,9 @@ pub enum Tlv<'a> {
     LinkMargin(u8),
     Status(u8),
     Version(u16),
+    AddressRegistration(&'a [u8]),
     /*
-    TODO: Not required to implement MLE for SED
-    AddressRegistration
-    AddressRegistration
+    TODO: Not required to implement MLE for MED
     Channel
     PanId
     ActiveTimestamp
@@ -131,7 +128,7 @@ impl Tlv<'_> {
             Tlv::SourceAddress(ref mac_address) => {
                 let value_width = mem::size_of::<u16>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_u16, mac_address.to_be());
+                offset = enc_consume!(buf, offset; encode_u16, *mac_address);
                 stream_done!(offset)
             }
             Tlv::Mode(ref mode) => {
@@ -143,37 +140,37 @@ impl Tlv<'_> {
             Tlv::Timeout(ref max_transmit_interval) => {
                 let value_width = mem::size_of::<u32>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_u32, max_transmit_interval.to_be());
+                offset = enc_consume!(buf, offset; encode_u32, *max_transmit_interval);
                 stream_done!(offset)
             }
-            Tlv::Challenge(ref byte_str) => {
+            Tlv::Challenge(byte_str) => {
                 let value_width = byte_str.len();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_bytes_be, byte_str);
+                offset = enc_consume!(buf, offset; encode_bytes, byte_str);
                 stream_done!(offset)
             }
-            Tlv::Response(ref byte_str) => {
+            Tlv::Response(byte_str) => {
                 let value_width = byte_str.len();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_bytes_be, byte_str);
+                offset = enc_consume!(buf, offset; encode_bytes, byte_str);
                 stream_done!(offset)
             }
             Tlv::LinkLayerFrameCounter(ref frame_counter) => {
                 let value_width = mem::size_of::<u32>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_u32, frame_counter.to_be());
+                offset = enc_consume!(buf, offset; encode_u32, *frame_counter);
                 stream_done!(offset)
             }
             Tlv::MleFrameCounter(ref frame_counter) => {
                 let value_width = mem::size_of::<u32>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_u32, frame_counter.to_be());
+                offset = enc_consume!(buf, offset; encode_u32, *frame_counter);
                 stream_done!(offset)
             }
             Tlv::Address16(ref mac_address) => {
                 let value_width = mem::size_of::<u16>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_u16, mac_address.to_be());
+                offset = enc_consume!(buf, offset; encode_u16, *mac_address);
                 stream_done!(offset)
             }
             Tlv::LeaderData {
@@ -189,7 +186,7 @@ impl Tlv<'_> {
                     + mem::size_of::<u8>()
                     + mem::size_of::<u8>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_u32, partition_id.to_be());
+                offset = enc_consume!(buf, offset; encode_u32, partition_id);
                 offset = enc_consume!(buf, offset; encode_u8, weighting);
                 offset = enc_consume!(buf, offset; encode_u8, data_version);
                 offset = enc_consume!(buf, offset; encode_u8, stable_data_version);
@@ -250,7 +247,7 @@ impl Tlv<'_> {
                 offset = enc_consume!(buf, offset; encode_u8, id_sequence);
                 offset = enc_consume!(buf, offset; encode_u8, active_routers);
                 if let Some(ref buf_size) = sed_buffer_size {
-                    offset = enc_consume!(buf, offset; encode_u16, buf_size.to_be());
+                    offset = enc_consume!(buf, offset; encode_u16, *buf_size);
                 }
                 if let Some(ref datagram_cnt) = sed_datagram_count {
                     offset = enc_consume!(buf, offset; encode_u8, *datagram_cnt);
@@ -275,6 +272,12 @@ impl Tlv<'_> {
                 offset = enc_consume!(buf, offset; encode_u16, *version);
                 stream_done!(offset)
             }
+            Tlv::AddressRegistration(entries) => {
+                let value_width = entries.len();
+                let mut offset = enc_consume!(buf; self; encode_tl, value_width);
+                offset = enc_consume!(buf, offset; encode_bytes, entries);
+                stream_done!(offset)
+            }
             Tlv::ActiveOperationalDataset(ref network_mgmt_tlvs) => {
                 let value_width = network_mgmt_tlvs.len();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
@@ -301,10 +304,12 @@ impl Tlv<'_> {
     /// the TLV type.
     /// `SResult::Error` is returned if the type field does not match any
     /// implemented TLV type.
-    pub fn decode(buf: &[u8]) -> SResult<Tlv> {
+    pub fn decode(buf: &[u8]) -> SResult<Tlv<'_>> {
         let (offset, tlv_type) = dec_try!(buf; decode_u8);
         let tlv_type = TlvType::from(tlv_type);
         let (offset, length) = dec_try!(buf, offset; decode_u8);
+        let end = offset + length as usize;
+        stream_len_cond!(buf, end);
         match tlv_type {
             TlvType::SourceAddress => {
                 let (offset, mac_address) = dec_try!(buf, offset; decode_u16);
@@ -318,16 +323,8 @@ impl Tlv<'_> {
                 let (offset, max_transmit_interval) = dec_try!(buf, offset; decode_u32);
                 stream_done!(offset, Tlv::Timeout(max_transmit_interval))
             }
-            TlvType::Challenge => {
-                let mut byte_str = [0u8; 8];
-                let offset = dec_consume!(buf, offset; decode_bytes_be, &mut byte_str);
-                stream_done!(offset, Tlv::Challenge(byte_str))
-            }
-            TlvType::Response => {
-                let mut byte_str = [0u8; 8];
-                let offset = dec_consume!(buf, offset; decode_bytes_be, &mut byte_str);
-                stream_done!(offset, Tlv::Response(byte_str))
-            }
+            TlvType::Challenge => stream_done!(end, Tlv::Challenge(&buf[offset..end])),
+            TlvType::Response => stream_done!(end, Tlv::Response(&buf[offset..end])),
             TlvType::LinkLayerFrameCounter => {
                 let (offset, frame_counter) = dec_try!(buf, offset; decode_u32);
                 stream_done!(offset, Tlv::LinkLayerFrameCounter(frame_counter))
@@ -379,13 +376,13 @@ impl Tlv<'_> {
                 let (offset, active_routers) = dec_try!(buf, offset; decode_u8);
                 let mut offset = offset;
                 let mut sed_buffer_size = None;
-                if offset + mem::size_of::<u16>() < length as usize {
+                if offset + mem::size_of::<u16>() <= end {
                     let (new_offset, sed_buffer_size_raw) = dec_try!(buf, offset; decode_u16);
                     offset = new_offset;
                     sed_buffer_size = Some(sed_buffer_size_raw);
                 }
                 let mut sed_datagram_count = None;
-                if offset + mem::size_of::<u8>() < length as usize {
+                if offset + mem::size_of::<u8>() <= end {
                     let (new_offset, sed_datagram_count_raw) = dec_try!(buf, offset; decode_u8);
                     offset = new_offset;
                     sed_datagram_count = Some(sed_datagram_count_raw);
@@ -417,6 +414,9 @@ impl Tlv<'_> {
                 let (offset, version) = dec_try!(buf, offset; decode_u16);
                 stream_done!(offset, Tlv::Version(version))
             }
+            TlvType::AddressRegistration => {
+                stream_done!(end, Tlv::AddressRegistration(&buf[offset..end]))
+            }
             TlvType::ActiveOperationalDataset => stream_done!(
                 offset + length as usize,
                 Tlv::ActiveOperationalDataset(&buf[offset..offset + length as usize])
@@ -456,9 +456,9 @@ pub enum TlvType {
     LinkMargin = 16,
     Status = 17,
     Version = 18,
-    /*
-    TODO: Not required to implement MLE for SED
     AddressRegistration = 19,
+    /*
+    TODO: Not required to implement MLE for MED
     Channel = 20,
     PanId = 21,
     ActiveTimestamp = 22,
@@ -492,6 +492,7 @@ impl From<u8> for TlvType {
             16 => TlvType::LinkMargin,
             17 => TlvType::Status,
             18 => TlvType::Version,
+            19 => TlvType::AddressRegistration,
             24 => TlvType::ActiveOperationalDataset,
             25 => TlvType::PendingOperationalDataset,
             _ => TlvType::NotPresent,
@@ -518,6 +519,7 @@ impl From<&Tlv<'_>> for TlvType {
             Tlv::LinkMargin(_) => TlvType::LinkMargin,
             Tlv::Status(_) => TlvType::Status,
             Tlv::Version(_) => TlvType::Version,
+            Tlv::AddressRegistration(_) => TlvType::AddressRegistration,
             Tlv::ActiveOperationalDataset(_) => TlvType::ActiveOperationalDataset,
             Tlv::PendingOperationalDataset(_) => TlvType::PendingOperationalDataset,
         }
@@ -554,20 +556,16 @@ pub enum NetworkDataTlv<'a> {
     Prefix {
         domain_id: u8,
         prefix_length_bits: u8,
-        prefix: [u8; 3], // IPv6 prefix max length 48 bits.
+        prefix: &'a [u8], // The prefix_length_bits bits of the prefix, rounded up to bytes.
         sub_tlvs: &'a [u8],
     },
-    CommissioningData {
-        com_length: u8,
-        com_data: [u8; MAX_VALUE_FIELD_LENGTH],
-    },
+    CommissioningData(&'a [u8]),
     Service {
         thread_enterprise_number: bool,
         // See 5.18.6.
         s_id: u8,
         s_enterprise_number: u32,
-        s_service_data_length: u8,
-        s_service_data: [u8; MAX_VALUE_FIELD_LENGTH],
+        s_service_data: &'a [u8],
         sub_tlvs: &'a [u8],
     },
 }
@@ -588,29 +586,30 @@ impl NetworkDataTlv<'_> {
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width, stable);
                 offset = enc_consume!(buf, offset; encode_u8, domain_id);
                 offset = enc_consume!(buf, offset; encode_u8, prefix_length_bits);
-                offset = enc_consume!(buf, offset; encode_bytes_be, &prefix);
+                offset = enc_consume!(buf, offset; encode_bytes, prefix);
                 offset = enc_consume!(buf, offset; encode_bytes, sub_tlvs);
                 stream_done!(offset)
             }
-            NetworkDataTlv::CommissioningData {
-                com_length,
-                com_data,
-            } => {
-                let value_width = com_length as usize;
+            NetworkDataTlv::CommissioningData(com_data) => {
+                let value_width = com_data.len();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width, stable);
-                offset = enc_consume!(buf, offset; encode_bytes_be, &com_data);
+                offset = enc_consume!(buf, offset; encode_bytes, com_data);
                 stream_done!(offset)
             }
             NetworkDataTlv::Service {
                 thread_enterprise_number,
                 s_id,
                 s_enterprise_number,
-                s_service_data_length,
                 s_service_data,
                 sub_tlvs,
             } => {
+                let enterprise_number_width = if thread_enterprise_number {
+                    0
+                } else {
+                    mem::size_of::<u32>()
+                };
                 let value_width = mem::size_of::<u8>()
-                    + mem::size_of::<u32>()
+                    + enterprise_number_width
                     + mem::size_of::<u8>()
                     + s_service_data.len()
                     + sub_tlvs.len();
@@ -622,9 +621,11 @@ impl NetworkDataTlv<'_> {
                 };
                 let first_byte: u8 = t_bit | (0b1111 & s_id);
                 offset = enc_consume!(buf, offset; encode_u8, first_byte);
-                offset = enc_consume!(buf, offset; encode_u32, s_enterprise_number.to_be());
-                offset = enc_consume!(buf, offset; encode_u8, s_service_data_length);
-                offset = enc_consume!(buf, offset; encode_bytes_be, &s_service_data);
+                if !thread_enterprise_number {
+                    offset = enc_consume!(buf, offset; encode_u32, s_enterprise_number);
+                }
+                offset = enc_consume!(buf, offset; encode_u8, s_service_data.len() as u8);
+                offset = enc_consume!(buf, offset; encode_bytes, s_service_data);
                 offset = enc_consume!(buf, offset; encode_bytes, sub_tlvs);
                 stream_done!(offset)
             }
@@ -645,64 +646,59 @@ impl NetworkDataTlv<'_> {
     /// otherwise.
     /// `SResult::Error` is returned if the type field does not match any
     /// implemented TLV type.
-    pub fn decode(buf: &[u8]) -> SResult<(NetworkDataTlv, bool)> {
+    pub fn decode(buf: &[u8]) -> SResult<(NetworkDataTlv<'_>, bool)> {
         let (offset, tlv_type_field) = dec_try!(buf; decode_u8);
         let tlv_type_raw = tlv_type_field >> 1;
         let tlv_type = NetworkDataTlvType::from(tlv_type_raw);
         let stable = (tlv_type_field & 1u8) > 0;
         let (offset, length) = dec_try!(buf, offset; decode_u8);
+        let end = offset + length as usize;
+        stream_len_cond!(buf, end);
         match tlv_type {
             NetworkDataTlvType::Prefix => {
                 let (offset, domain_id) = dec_try!(buf, offset; decode_u8);
                 let (offset, prefix_length_bits) = dec_try!(buf, offset; decode_u8);
-                let mut prefix = [0u8; 3];
-                let offset = dec_consume!(buf, offset; decode_bytes_be, &mut prefix);
+                let prefix_end = offset + (prefix_length_bits as usize + 7) / 8;
+                stream_cond!(prefix_end <= end);
                 stream_done!(
-                    offset + length as usize,
+                    end,
                     (
                         NetworkDataTlv::Prefix {
                             domain_id: domain_id,
                             prefix_length_bits: prefix_length_bits,
-                            prefix: prefix,
-                            sub_tlvs: &buf[offset..offset + length as usize],
-                        },
-                        stable
-                    )
-                )
-            }
-            NetworkDataTlvType::CommissioningData => {
-                let (offset, com_length) = dec_try!(buf, offset; decode_u8);
-                let mut com_data = [0u8; MAX_VALUE_FIELD_LENGTH];
-                let offset = dec_consume!(buf, offset; decode_bytes_be, &mut com_data);
-                stream_done!(
-                    offset,
-                    (
-                        NetworkDataTlv::CommissioningData {
-                            com_length: com_length,
-                            com_data: com_data,
+                            prefix: &buf[offset..prefix_end],
+                            sub_tlvs: &buf[prefix_end..end],
                         },
                         stable
                     )
                 )
             }
+            NetworkDataTlvType::CommissioningData => stream_done!(
+                end,
+                (NetworkDataTlv::CommissioningData(&buf[offset..end]), stable)
+            ),
             NetworkDataTlvType::Service => {
                 let (offset, first_byte) = dec_try!(buf, offset; decode_u8);
                 let thread_enterprise_number = (first_byte >> 7) > 0;
                 let s_id = first_byte & 0b1111;
-                let (offset, s_enterprise_number) = dec_try!(buf, offset; decode_u32);
+                // The enterprise number is left out for Thread services.
+                let (offset, s_enterprise_number) = if thread_enterprise_number {
+                    (offset, THREAD_ENTERPRISE_NUMBER)
+                } else {
+                    dec_try!(buf, offset; decode_u32)
+                };
                 let (offset, s_service_data_length) = dec_try!(buf, offset; decode_u8);
-                let mut s_service_data = [0u8; MAX_VALUE_FIELD_LENGTH];
-                let offset = dec_consume!(buf, offset; decode_bytes_be, &mut s_service_data);
+                let data_end = offset + s_service_data_length as usize;
+                stream_cond!(data_end <= end);
                 stream_done!(
-                    offset + length as usize,
+                    end,
                     (
                         NetworkDataTlv::Service {
                             thread_enterprise_number: thread_enterprise_number,
                             s_id: s_id,
                             s_enterprise_number: s_enterprise_number,
-                            s_service_data_length: s_service_data_length,
-                            s_service_data: s_service_data,
-                            sub_tlvs: &buf[offset..offset + length as usize],
+                            s_service_data: &buf[offset..data_end],
+                            sub_tlvs: &buf[data_end..end],
                         },
                         stable
                     )
@@ -802,27 +798,21 @@ impl PrefixSubTlv<'_> {
     /// otherwise.
     /// `SResult::Error` is returned if the type field does not match any
     /// implemented TLV type.
-    pub fn decode(buf: &[u8]) -> SResult<(PrefixSubTlv, bool)> {
+    pub fn decode(buf: &[u8]) -> SResult<(PrefixSubTlv<'_>, bool)> {
         let (offset, tlv_type_field) = dec_try!(buf; decode_u8);
         let tlv_type_raw = tlv_type_field >> 1;
         let tlv_type = PrefixSubTlvType::from(tlv_type_raw);
         let stable = (tlv_type_field & 1u8) > 0;
         let (offset, length) = dec_try!(buf, offset; decode_u8);
+        let end = offset + length as usize;
+        stream_len_cond!(buf, end);
         match tlv_type {
-            PrefixSubTlvType::HasRoute => stream_done!(
-                offset + length as usize,
-                (
-                    PrefixSubTlv::HasRoute(&buf[offset..offset + length as usize]),
-                    stable
-                )
-            ),
-            PrefixSubTlvType::BorderRouter => stream_done!(
-                offset + length as usize,
-                (
-                    PrefixSubTlv::BorderRouter(&buf[offset..offset + length as usize]),
-                    stable
-                )
-            ),
+            PrefixSubTlvType::HasRoute => {
+                stream_done!(end, (PrefixSubTlv::HasRoute(&buf[offset..end]), stable))
+            }
+            PrefixSubTlvType::BorderRouter => {
+                stream_done!(end, (PrefixSubTlv::BorderRouter(&buf[offset..end]), stable))
+            }
             PrefixSubTlvType::SixLoWpanId => {
                 let (offset, first_byte) = dec_try!(buf, offset; decode_u8);
                 let context_id_compress = (first_byte & 0b1_0000) > 0;
@@ -879,15 +869,15 @@ impl From<&PrefixSubTlv<'_>> for PrefixSubTlvType {
 /// Used in Has Route TLV.
 pub struct HasRouteTlvValue {
     // See 5.18.1.
-    r_border_router_16: u16,
-    r_preference: u8,
+    pub r_border_router_16: u16,
+    pub r_preference: u8,
 }
 
 impl HasRouteTlvValue {
     /// Serializes this Has Route TLV value into `buf`.
     pub fn encode(&self, buf: &mut [u8]) -> SResult {
         stream_len_cond!(buf, 3);
-        let mut offset = enc_consume!(buf, 0; encode_u16, self.r_border_router_16.to_be());
+        let mut offset = enc_consume!(buf, 0; encode_u16, self.r_border_router_16);
         let last_byte = ((self.r_preference & 0b11) as u8) << 6;
         offset = enc_consume!(buf, offset; encode_u8, last_byte);
         stream_done!(offset)
@@ -912,8 +902,8 @@ impl HasRouteTlvValue {
 /// Used in Border Router TLV.
 pub struct BorderRouterTlvValue {
     // See 5.18.3.
-    p_border_router_16: u16,
-    p_bits: u16,
+    pub p_border_router_16: u16,
+    pub p_bits: u16,
 }
 
 /// Used in Border Router TLV value.
@@ -934,8 +924,8 @@ impl BorderRouterTlvValue {
     /// Serializes this Border Route TLV value into `buf`.
     pub fn encode(&self, buf: &mut [u8]) -> SResult {
         stream_len_cond!(buf, 4); // Each Border Router TLV value is 32 bits wide.
-        let mut offset = enc_consume!(buf, 0; encode_u16, self.p_border_router_16.to_be());
-        offset = enc_consume!(buf, offset; encode_u16, self.p_bits.to_be());
+        let mut offset = enc_consume!(buf, 0; encode_u16, self.p_border_router_16);
+        offset = enc_consume!(buf, offset; encode_u16, self.p_bits);
         stream_done!(offset)
     }
 
@@ -954,15 +944,15 @@ impl BorderRouterTlvValue {
 }
 
 /// These TLVs are contained within the value of a Service TLV.
-pub enum ServiceSubTlv {
+pub enum ServiceSubTlv<'a> {
     Server {
         // See 5.18.6.
         s_server_16: u16,
-        s_server_data: [u8; MAX_VALUE_FIELD_LENGTH],
+        s_server_data: &'a [u8],
     },
 }
 
-impl ServiceSubTlv {
+impl ServiceSubTlv<'_> {
     /// Serializes TLV data in `buf` into the format specific to the
     /// Service sub-TLV type.
     pub fn encode(&self, buf: &mut [u8], stable: bool) -> SResult {
@@ -973,8 +963,8 @@ impl ServiceSubTlv {
             } => {
                 let value_width = mem::size_of::<u16>() + s_server_data.len();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width, stable);
-                offset = enc_consume!(buf, offset; encode_u16, s_server_16.to_be());
-                offset = enc_consume!(buf, offset; encode_bytes_be, &s_server_data);
+                offset = enc_consume!(buf, offset; encode_u16, s_server_16);
+                offset = enc_consume!(buf, offset; encode_bytes, s_server_data);
                 stream_done!(offset)
             }
         }
@@ -994,23 +984,24 @@ impl ServiceSubTlv {
     /// otherwise.
     /// `SResult::Error` is returned if the type field does not match any
     /// implemented TLV type.
-    pub fn decode(buf: &[u8]) -> SResult<(ServiceSubTlv, bool)> {
+    pub fn decode(buf: &[u8]) -> SResult<(ServiceSubTlv<'_>, bool)> {
         let (offset, tlv_type_field) = dec_try!(buf; decode_u8);
         let tlv_type_raw = tlv_type_field >> 1;
         let tlv_type = ServiceSubTlvType::from(tlv_type_raw);
         let stable = (tlv_type_field & 1u8) > 0;
-        let (offset, _) = dec_try!(buf, offset; decode_u8);
+        let (offset, length) = dec_try!(buf, offset; decode_u8);
+        let end = offset + length as usize;
+        stream_len_cond!(buf, end);
         match tlv_type {
             ServiceSubTlvType::Server => {
                 let (offset, s_server_16) = dec_try!(buf, offset; decode_u16);
-                let mut s_server_data = [0u8; MAX_VALUE_FIELD_LENGTH];
-                let offset = dec_consume!(buf, offset; decode_bytes_be, &mut s_server_data);
+                stream_cond!(offset <= end);
                 stream_done!(
-                    offset,
+                    end,
                     (
                         ServiceSubTlv::Server {
                             s_server_16: s_server_16,
-                            s_server_data: s_server_data,
+                            s_server_data: &buf[offset..end],
                         },
                         stable
                     )
@@ -1038,8 +1029,8 @@ impl From<u8> for ServiceSubTlvType {
     }
 }
 
-impl From<&ServiceSubTlv> for ServiceSubTlvType {
-    fn from(service_sub_tlv: &ServiceSubTlv) -> Self {
+impl From<&ServiceSubTlv<'_>> for ServiceSubTlvType {
+    fn from(service_sub_tlv: &ServiceSubTlv<'_>) -> Self {
         match *service_sub_tlv {
             ServiceSubTlv::Server { .. } => ServiceSubTlvType::Server,
         }
@@ -1070,13 +1061,13 @@ pub enum NetworkManagementTlv<'a> {
         policy_bits: u8,
     },
     ActiveTimestamp {
-        timestamp_seconds: [u8; 3], // Timestamp seconds is a 48-bit Unix time value.
+        timestamp_seconds: [u8; 6], // Timestamp seconds is a 48-bit Unix time value.
         timestamp_ticks: u16,
         u_bit: bool,
     },
     CommissionerUdpPort(u16),
     PendingTimestamp {
-        timestamp_seconds: [u8; 3], // Timestamp seconds is a 48-bit Unix time value.
+        timestamp_seconds: [u8; 6], // Timestamp seconds is a 48-bit Unix time value.
         timestamp_ticks: u16,
         u_bit: bool,
     },
@@ -1098,77 +1089,77 @@ impl NetworkManagementTlv<'_> {
                 let value_width = mem::size_of::<u8>() + mem::size_of::<u16>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
                 offset = enc_consume!(buf, offset; encode_u8, channel_page);
-                offset = enc_consume!(buf, offset; encode_u16, channel.to_be());
+                offset = enc_consume!(buf, offset; encode_u16, channel);
                 stream_done!(offset)
             }
             NetworkManagementTlv::PanId(ref pan_id) => {
                 let value_width = mem::size_of::<u16>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_u16, pan_id.to_be());
+                offset = enc_consume!(buf, offset; encode_u16, *pan_id);
                 stream_done!(offset)
             }
             NetworkManagementTlv::ExtendedPanId(ref extended_pan_id) => {
                 let value_width = extended_pan_id.len();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_bytes_be, extended_pan_id);
+                offset = enc_consume!(buf, offset; encode_bytes, extended_pan_id);
                 stream_done!(offset)
             }
             NetworkManagementTlv::NetworkName(ref network_name) => {
                 stream_cond!(network_name.len() <= 16);
                 let value_width = network_name.len();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_bytes_be, network_name);
+                offset = enc_consume!(buf, offset; encode_bytes, network_name);
                 stream_done!(offset)
             }
             NetworkManagementTlv::Pskc(ref pskc) => {
                 stream_cond!(pskc.len() <= 16);
                 let value_width = pskc.len();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_bytes_be, pskc);
+                offset = enc_consume!(buf, offset; encode_bytes, pskc);
                 stream_done!(offset)
             }
             NetworkManagementTlv::NetworkMasterKey(ref network_key) => {
                 let value_width = network_key.len();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_bytes_be, network_key);
+                offset = enc_consume!(buf, offset; encode_bytes, network_key);
                 stream_done!(offset)
             }
             NetworkManagementTlv::NetworkKeySequenceCounter(ref counter) => {
                 let value_width = counter.len();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_bytes_be, counter);
+                offset = enc_consume!(buf, offset; encode_bytes, counter);
                 stream_done!(offset)
             }
             NetworkManagementTlv::NetworkMeshLocalPrefix(ref prefix) => {
                 let value_width = prefix.len();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_bytes_be, prefix);
+                offset = enc_consume!(buf, offset; encode_bytes, prefix);
                 stream_done!(offset)
             }
             NetworkManagementTlv::SteeringData(ref bloom_filter) => {
                 stream_cond!(bloom_filter.len() <= 16);
                 let value_width = bloom_filter.len();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_bytes_be, bloom_filter);
+                offset = enc_consume!(buf, offset; encode_bytes, bloom_filter);
                 stream_done!(offset)
             }
             NetworkManagementTlv::BorderAgentLocator(ref rloc_16) => {
                 let value_width = mem::size_of::<u16>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_u16, rloc_16.to_be());
+                offset = enc_consume!(buf, offset; encode_u16, *rloc_16);
                 stream_done!(offset)
             }
             NetworkManagementTlv::CommissionerId(ref commissioner_id) => {
                 stream_cond!(commissioner_id.len() <= 64);
                 let value_width = commissioner_id.len();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_bytes_be, commissioner_id);
+                offset = enc_consume!(buf, offset; encode_bytes, commissioner_id);
                 stream_done!(offset)
             }
             NetworkManagementTlv::CommissionerSessionId(ref session_id) => {
                 let value_width = mem::size_of::<u16>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_u16, session_id.to_be());
+                offset = enc_consume!(buf, offset; encode_u16, *session_id);
                 stream_done!(offset)
             }
             NetworkManagementTlv::SecurityPolicy {
@@ -1177,7 +1168,7 @@ impl NetworkManagementTlv<'_> {
             } => {
                 let value_width = mem::size_of::<u16>() + mem::size_of::<u8>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_u16, rotation_time.to_be());
+                offset = enc_consume!(buf, offset; encode_u16, rotation_time);
                 offset = enc_consume!(buf, offset; encode_u8, policy_bits);
                 stream_done!(offset)
             }
@@ -1188,16 +1179,16 @@ impl NetworkManagementTlv<'_> {
             } => {
                 let value_width = timestamp_seconds.len() + mem::size_of::<u16>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_bytes_be, &timestamp_seconds);
+                offset = enc_consume!(buf, offset; encode_bytes, &timestamp_seconds);
                 let u_bit_val = if u_bit { 1u16 } else { 0u16 };
                 let end_bytes = (timestamp_ticks << 1) | u_bit_val;
-                offset = enc_consume!(buf, offset; encode_u16, end_bytes.to_be());
+                offset = enc_consume!(buf, offset; encode_u16, end_bytes);
                 stream_done!(offset)
             }
             NetworkManagementTlv::CommissionerUdpPort(ref udp_port) => {
                 let value_width = mem::size_of::<u16>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_u16, udp_port.to_be());
+                offset = enc_consume!(buf, offset; encode_u16, *udp_port);
                 stream_done!(offset)
             }
             NetworkManagementTlv::PendingTimestamp {
@@ -1207,16 +1198,16 @@ impl NetworkManagementTlv<'_> {
             } => {
                 let value_width = timestamp_seconds.len() + mem::size_of::<u16>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_bytes_be, &timestamp_seconds);
+                offset = enc_consume!(buf, offset; encode_bytes, &timestamp_seconds);
                 let u_bit_val = if u_bit { 1u16 } else { 0u16 };
                 let end_bytes = (timestamp_ticks << 1) | u_bit_val;
-                offset = enc_consume!(buf, offset; encode_u16, end_bytes.to_be());
+                offset = enc_consume!(buf, offset; encode_u16, end_bytes);
                 stream_done!(offset)
             }
             NetworkManagementTlv::DelayTimer(ref time_remaining) => {
                 let value_width = mem::size_of::<u32>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_u32, time_remaining.to_be());
+                offset = enc_consume!(buf, offset; encode_u32, *time_remaining);
                 stream_done!(offset)
             }
             NetworkManagementTlv::ChannelMask(ref entries) => {
@@ -1241,10 +1232,12 @@ impl NetworkManagementTlv<'_> {
     /// otherwise.
     /// `SResult::Error` is returned if the type field does not match any
     /// implemented TLV type.
-    pub fn decode(buf: &[u8]) -> SResult<NetworkManagementTlv> {
+    pub fn decode(buf: &[u8]) -> SResult<NetworkManagementTlv<'_>> {
         let (offset, tlv_type_raw) = dec_try!(buf; decode_u8);
         let tlv_type = NetworkManagementTlvType::from(tlv_type_raw);
         let (offset, length) = dec_try!(buf, offset; decode_u8);
+        let end = offset + length as usize;
+        stream_len_cond!(buf, end);
         match tlv_type {
             NetworkManagementTlvType::Channel => {
                 let (offset, channel_page) = dec_try!(buf, offset; decode_u8);
@@ -1263,27 +1256,30 @@ impl NetworkManagementTlv<'_> {
             }
             NetworkManagementTlvType::ExtendedPanId => {
                 let mut extended_pan_id = [0u8; 8];
-                let offset = dec_consume!(buf, offset; decode_bytes_be, &mut extended_pan_id);
+                let offset = dec_consume!(buf, offset; decode_bytes, &mut extended_pan_id);
                 stream_done!(offset, NetworkManagementTlv::ExtendedPanId(extended_pan_id))
             }
             NetworkManagementTlvType::NetworkName => {
                 let mut network_name = [0u8; 16];
-                let offset = dec_consume!(buf, offset; decode_bytes_be, &mut network_name);
+                stream_cond!(length as usize <= network_name.len());
+                let offset =
+                    dec_consume!(buf, offset; decode_bytes, &mut network_name[..length as usize]);
                 stream_done!(offset, NetworkManagementTlv::NetworkName(network_name))
             }
             NetworkManagementTlvType::Pskc => {
                 let mut pskc = [0u8; 16];
-                let offset = dec_consume!(buf, offset; decode_bytes_be, &mut pskc);
+                stream_cond!(length as usize <= pskc.len());
+                let offset = dec_consume!(buf, offset; decode_bytes, &mut pskc[..length as usize]);
                 stream_done!(offset, NetworkManagementTlv::Pskc(pskc))
             }
             NetworkManagementTlvType::NetworkMasterKey => {
                 let mut network_key = [0u8; 16];
-                let offset = dec_consume!(buf, offset; decode_bytes_be, &mut network_key);
+                let offset = dec_consume!(buf, offset; decode_bytes, &mut network_key);
                 stream_done!(offset, NetworkManagementTlv::NetworkMasterKey(network_key))
             }
             NetworkManagementTlvType::NetworkKeySequenceCounter => {
                 let mut counter = [0u8; 4];
-                let offset = dec_consume!(buf, offset; decode_bytes_be, &mut counter);
+                let offset = dec_consume!(buf, offset; decode_bytes, &mut counter);
                 stream_done!(
                     offset,
                     NetworkManagementTlv::NetworkKeySequenceCounter(counter)
@@ -1291,12 +1287,14 @@ impl NetworkManagementTlv<'_> {
             }
             NetworkManagementTlvType::NetworkMeshLocalPrefix => {
                 let mut prefix = [0u8; 8];
-                let offset = dec_consume!(buf, offset; decode_bytes_be, &mut prefix);
+                let offset = dec_consume!(buf, offset; decode_bytes, &mut prefix);
                 stream_done!(offset, NetworkManagementTlv::NetworkMeshLocalPrefix(prefix))
             }
             NetworkManagementTlvType::SteeringData => {
                 let mut bloom_filter = [0u8; 16];
-                let offset = dec_consume!(buf, offset; decode_bytes_be, &mut bloom_filter);
+                stream_cond!(length as usize <= bloom_filter.len());
+                let offset =
+                    dec_consume!(buf, offset; decode_bytes, &mut bloom_filter[..length as usize]);
                 stream_done!(offset, NetworkManagementTlv::SteeringData(bloom_filter))
             }
             NetworkManagementTlvType::BorderAgentLocator => {
@@ -1305,7 +1303,8 @@ impl NetworkManagementTlv<'_> {
             }
             NetworkManagementTlvType::CommissionerId => {
                 let mut commissioner_id = [0u8; 64];
-                let offset = dec_consume!(buf, offset; decode_bytes_be, &mut commissioner_id);
+                stream_cond!(length as usize <= commissioner_id.len());
+                let offset = dec_consume!(buf, offset; decode_bytes, &mut commissioner_id[..length as usize]);
                 stream_done!(
                     offset,
                     NetworkManagementTlv::CommissionerId(commissioner_id)
@@ -1330,8 +1329,8 @@ impl NetworkManagementTlv<'_> {
                 )
             }
             NetworkManagementTlvType::ActiveTimestamp => {
-                let mut timestamp_seconds = [0u8; 3];
-                let offset = dec_consume!(buf, offset; decode_bytes_be, &mut timestamp_seconds);
+                let mut timestamp_seconds = [0u8; 6];
+                let offset = dec_consume!(buf, offset; decode_bytes, &mut timestamp_seconds);
                 let (offset, timestamp_ticks) = dec_try!(buf, offset; decode_u16);
                 stream_done!(
                     offset,
@@ -1347,8 +1346,8 @@ impl NetworkManagementTlv<'_> {
                 stream_done!(offset, NetworkManagementTlv::CommissionerUdpPort(udp_port))
             }
             NetworkManagementTlvType::PendingTimestamp => {
-                let mut timestamp_seconds = [0u8; 3];
-                let offset = dec_consume!(buf; decode_bytes_be, &mut timestamp_seconds);
+                let mut timestamp_seconds = [0u8; 6];
+                let offset = dec_consume!(buf, offset; decode_bytes, &mut timestamp_seconds);
                 let (offset, timestamp_ticks) = dec_try!(buf, offset; decode_u16);
                 stream_done!(
                     offset,
@@ -1363,10 +1362,9 @@ impl NetworkManagementTlv<'_> {
                 let (offset, time_remaining) = dec_try!(buf, offset; decode_u32);
                 stream_done!(offset, NetworkManagementTlv::DelayTimer(time_remaining))
             }
-            NetworkManagementTlvType::ChannelMask => stream_done!(
-                offset + length as usize,
-                NetworkManagementTlv::ChannelMask(&buf[offset..offset + length as usize])
-            ),
+            NetworkManagementTlvType::ChannelMask => {
+                stream_done!(end, NetworkManagementTlv::ChannelMask(&buf[offset..end]))
+            }
             NetworkManagementTlvType::NotPresent => stream_err!(),
         }
     }
@@ -1482,9 +1480,10 @@ pub struct ChannelMaskEntry {
 impl ChannelMaskEntry {
     /// Serializes this Channel Mask Entry into `buf`.
     pub fn encode(&self, buf: &mut [u8]) -> SResult {
+        stream_cond!(self.mask_length as usize <= self.channel_mask.len());
         let mut offset = enc_consume!(buf, 0; encode_u8, self.channel_page);
         offset = enc_consume!(buf, offset; encode_u8, self.mask_length);
-        offset = enc_consume!(buf, offset; encode_bytes_be, &self.channel_mask);
+        offset = enc_consume!(buf, offset; encode_bytes, &self.channel_mask[..self.mask_length as usize]);
         stream_done!(offset)
     }
 
@@ -1493,7 +1492,9 @@ impl ChannelMaskEntry {
         let (offset, channel_page) = dec_try!(buf; decode_u8);
         let (offset, mask_length) = dec_try!(buf, offset; decode_u8);
         let mut channel_mask = [0u8; MAX_VALUE_FIELD_LENGTH];
-        let offset = dec_consume!(buf, offset; decode_bytes_be, &mut channel_mask);
+        stream_cond!(mask_length as usize <= channel_mask.len());
+        let offset =
+            dec_consume!(buf, offset; decode_bytes, &mut channel_mask[..mask_length as usize]);
         stream_done!(
             offset,
             ChannelMaskEntry {
@@ -1504,3 +1505,104 @@ impl ChannelMaskEntry {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(tlv: &Tlv, buf: &mut [u8]) -> usize {
+        match tlv.encode(buf) {
+            SResult::Done(len, ()) => len,
+            _ => panic!("failed to encode TLV"),
+        }
+    }
+
+    #[test]
+    fn multi_byte_values_are_big_endian() {
+        let mut buf = [0; 16];
+
+        let len = encode(&Tlv::SourceAddress(0x1234), &mut buf);
+        assert_eq!(&buf[..len], &[0, 2, 0x12, 0x34]);
+
+        let len = encode(&Tlv::Timeout(0x0001_0203), &mut buf);
+        assert_eq!(&buf[..len], &[2, 4, 0x00, 0x01, 0x02, 0x03]);
+
+        let len = encode(&Tlv::MleFrameCounter(0x0a0b_0c0d), &mut buf);
+        assert_eq!(&buf[..len], &[8, 4, 0x0a, 0x0b, 0x0c, 0x0d]);
+
+        let len = encode(
+            &Tlv::LeaderData {
+                partition_id: 0x1122_3344,
+                weighting: 64,
+                data_version: 1,
+                stable_data_version: 2,
+                leader_router_id: 3,
+            },
+            &mut buf,
+        );
+        assert_eq!(&buf[..len], &[11, 8, 0x11, 0x22, 0x33, 0x44, 64, 1, 2, 3]);
+    }
+
+    #[test]
+    fn decode_big_endian_values() {
+        match Tlv::decode(&[10, 2, 0xfc, 0x01]) {
+            SResult::Done(4, Tlv::Address16(rloc16)) => assert_eq!(rloc16, 0xfc01),
+            _ => panic!("failed to decode Address16 TLV"),
+        }
+        match Tlv::decode(&[5, 4, 0x00, 0x00, 0x01, 0x00]) {
+            SResult::Done(6, Tlv::LinkLayerFrameCounter(frame_counter)) => {
+                assert_eq!(frame_counter, 0x100)
+            }
+            _ => panic!("failed to decode Link-layer Frame Counter TLV"),
+        }
+    }
+
+    #[test]
+    fn decode_checks_length() {
+        // The length field claims more bytes than the buffer holds.
+        assert!(!matches!(
+            Tlv::decode(&[3, 8, 1, 2, 3, 4]),
+            SResult::Done(_, _)
+        ));
+        assert_eq!(tlv_len(&[3, 8, 1, 2, 3, 4]), None);
+        assert_eq!(tlv_len(&[3, 4, 1, 2, 3, 4]), Some(6));
+    }
+
+    #[test]
+    fn service_enterprise_number() {
+        let mut buf = [0; 16];
+        let service = NetworkDataTlv::Service {
+            thread_enterprise_number: false,
+            s_id: 2,
+            s_enterprise_number: 0x0000_abcd,
+            s_service_data: &[0x5c],
+            sub_tlvs: &[],
+        };
+        let len = match service.encode(&mut buf, true) {
+            SResult::Done(len, ()) => len,
+            _ => panic!("failed to encode Service TLV"),
+        };
+        assert_eq!(&buf[2..len], &[0x02, 0x00, 0x00, 0xab, 0xcd, 1, 0x5c]);
+
+        match NetworkDataTlv::decode(&buf[..len]) {
+            SResult::Done(
+                off,
+                (
+                    NetworkDataTlv::Service {
+                        thread_enterprise_number: false,
+                        s_id: 2,
+                        s_enterprise_number,
+                        s_service_data,
+                        ..
+                    },
+                    true,
+                ),
+            ) => {
+                assert_eq!(off, len);
+                assert_eq!(s_enterprise_number, 0x0000_abcd);
+                assert_eq!(s_service_data, &[0x5c]);
+            }
+            _ => panic!("failed to decode Service TLV"),
+        }
+    }
+}
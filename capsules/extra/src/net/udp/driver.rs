@@ -7,10 +7,18 @@
 //! Implements a userspace interface for sending and receiving UDP messages.
 //! Processes use this driver to send UDP packets from a common interface
 //! and bind to UDP ports for receiving packets.
+//! Datagrams are copied into a pool of kernel buffers when they are queued,
+//! so that each app can queue up to `MAX_APP_PENDING_TX` of them without
+//! waiting for the previous ones to be sent. The pool is shared by all apps
+//! and sent in the order datagrams were queued: the UDP send mux holds a
+//! single datagram of the driver at a time, and the next one is passed to it
+//! as soon as the previous one is done.
 //! Also exposes a list of interface addresses to the application (currently
 //! hard-coded).
 
 use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::ipv6::mld::ALL_NODES;
+use crate::net::ipv6::slaac::InterfaceList;
 use crate::net::network_capabilities::NetworkCapability;
 use crate::net::stream::encode_u16;
 use crate::net::stream::encode_u8;
@@ -21,17 +29,16 @@ use crate::net::udp::udp_send::{UDPSendClient, UDPSender};
 use crate::net::util::host_slice_to_u16;
 
 use core::cell::Cell;
-use core::convert::TryFrom;
 use core::convert::TryInto;
+use core::mem;
 use core::mem::size_of;
-use core::{cmp, mem};
 
 use kernel::capabilities::UdpDriverCapability;
 use kernel::debug;
 use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
 use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
 use kernel::syscall::{CommandReturn, SyscallDriver};
-use kernel::utilities::cells::MapCell;
+use kernel::utilities::cells::{MapCell, OptionalCell};
 use kernel::utilities::leasable_buffer::LeasableMutableBuffer;
 use kernel::{ErrorCode, ProcessId};
 
@@ -89,10 +96,47 @@ impl UDPEndpoint {
     }
 }
 
+/// The number of multicast groups each app can join.
+pub const MAX_APP_GROUPS: usize = 2;
+
+/// The number of kernel buffers datagrams are queued in.
+pub const TX_POOL_LEN: usize = 4;
+
+/// The number of datagrams each app can have queued or being sent, so that
+/// one app cannot take the whole pool.
+pub const MAX_APP_PENDING_TX: usize = 3;
+
+/// A datagram queued by an app.
+#[derive(Copy, Clone)]
+struct PendingTx {
+    processid: ProcessId,
+    dst: UDPEndpoint,
+    src_port: u16,
+    /// Order in which datagrams were queued.
+    seq: u32,
+}
+
+/// A kernel buffer of the transmit pool, which holds a datagram from the time
+/// it is queued until it is sent.
+pub struct TxSlot {
+    buffer: MapCell<LeasableMutableBuffer<'static, u8>>,
+    pending: OptionalCell<PendingTx>,
+}
+
+impl TxSlot {
+    pub fn new(buffer: &'static mut [u8]) -> TxSlot {
+        TxSlot {
+            buffer: MapCell::new(LeasableMutableBuffer::new(buffer)),
+            pending: OptionalCell::empty(),
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct App {
-    pending_tx: Option<[UDPEndpoint; 2]>,
     bound_port: Option<UDPEndpoint>,
+    /// Multicast groups received on the bound port.
+    groups: [Option<IPAddr>; MAX_APP_GROUPS],
 }
 
 #[allow(dead_code)]
@@ -107,11 +151,11 @@ pub struct UDPDriver<'a> {
         AllowRoCount<{ ro_allow::COUNT }>,
         AllowRwCount<{ rw_allow::COUNT }>,
     >,
-    /// ID of app whose transmission request is being processed.
-    current_app: Cell<Option<ProcessId>>,
+    /// Index of the pool slot whose datagram is being sent.
+    in_flight: OptionalCell<usize>,
 
     /// List of IP Addresses of the interfaces on the device
-    interface_list: &'static [IPAddr],
+    interface_list: &'static InterfaceList,
 
     /// Maximum length payload that an app can transmit via this driver
     max_tx_pyld_len: usize,
@@ -119,7 +163,10 @@ pub struct UDPDriver<'a> {
     /// UDP bound port table (manages kernel bindings)
     port_table: &'static UdpPortManager,
 
-    kernel_buffer: MapCell<LeasableMutableBuffer<'static, u8>>,
+    /// Buffers of the queued datagrams.
+    tx_pool: &'a [TxSlot],
+    /// Order of the next queued datagram.
+    next_seq: Cell<u32>,
 
     driver_send_cap: &'static dyn UdpDriverCapability,
 
@@ -135,56 +182,54 @@ impl<'a> UDPDriver<'a> {
             AllowRoCount<{ ro_allow::COUNT }>,
             AllowRwCount<{ rw_allow::COUNT }>,
         >,
-        interface_list: &'static [IPAddr],
+        interface_list: &'static InterfaceList,
         max_tx_pyld_len: usize,
         port_table: &'static UdpPortManager,
-        kernel_buffer: LeasableMutableBuffer<'static, u8>,
+        tx_pool: &'a [TxSlot],
         driver_send_cap: &'static dyn UdpDriverCapability,
         net_cap: &'static NetworkCapability,
     ) -> UDPDriver<'a> {
         UDPDriver {
             sender: sender,
             apps: grant,
-            current_app: Cell::new(None),
+            in_flight: OptionalCell::empty(),
             interface_list: interface_list,
             max_tx_pyld_len: max_tx_pyld_len,
             port_table: port_table,
-            kernel_buffer: MapCell::new(kernel_buffer),
+            tx_pool: tx_pool,
+            next_seq: Cell::new(0),
             driver_send_cap: driver_send_cap,
             net_cap: net_cap,
         }
     }
 
-    /// If the driver is currently idle and there are pending transmissions,
-    /// pick an app with a pending transmission and return its `ProcessId`.
-    fn get_next_tx_if_idle(&self) -> Option<ProcessId> {
-        if self.current_app.get().is_some() {
+    /// If the driver is currently idle and there are queued datagrams, return
+    /// the pool slot of the one that was queued first.
+    fn get_next_tx_if_idle(&self) -> Option<usize> {
+        if self.in_flight.is_some() {
             // Tx already in progress
             return None;
         }
-        let mut pending_app = None;
-        for app in self.apps.iter() {
-            let processid = app.processid();
-            app.enter(|app, _| {
-                if app.pending_tx.is_some() {
-                    pending_app = Some(processid);
-                }
-            });
-            if pending_app.is_some() {
-                break;
-            }
-        }
-        pending_app
+        let next_seq = self.next_seq.get();
+        self.tx_pool
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, slot)| {
+                slot.pending
+                    .map(|pending| (idx, next_seq.wrapping_sub(pending.seq)))
+            })
+            .max_by_key(|(_, age)| *age)
+            .map(|(idx, _)| idx)
     }
 
-    /// Performs `processid`'s pending transmission asynchronously. If the
-    /// transmission is not successful, the error is returned to the app via its
-    /// `tx_callback`. Assumes that the driver is currently idle and the app has
-    /// a pending transmission.
+    /// Sends the datagram in `slot` asynchronously. If the transmission is
+    /// not successful, the error is returned to the app that queued it via
+    /// its `tx_callback`. Assumes that the driver is currently idle.
     #[inline]
-    fn perform_tx_async(&self, processid: ProcessId) {
-        let result = self.perform_tx_sync(processid);
-        if result != Ok(()) {
+    fn perform_tx_async(&self, slot: usize) {
+        let owner = self.tx_pool[slot].pending.map(|pending| pending.processid);
+        let result = self.perform_tx_sync(slot);
+        if let (Err(_), Some(processid)) = (result, owner) {
             let _ = self.apps.enter(processid, |_app, upcalls| {
                 upcalls
                     .schedule_upcall(1, (kernel::errorcode::into_statuscode(result), 0, 0))
@@ -193,95 +238,117 @@ impl<'a> UDPDriver<'a> {
         }
     }
 
-    /// Performs `processid`'s pending transmission synchronously. The result is
-    /// returned immediately to the app. Assumes that the driver is currently
-    /// idle and the app has a pending transmission.
+    /// Sends the datagram in `slot` synchronously. The result is returned
+    /// immediately, and the slot is freed if the datagram could not be
+    /// passed to the UDP send mux. Assumes that the driver is currently idle.
     #[inline]
-    fn perform_tx_sync(&self, processid: ProcessId) -> Result<(), ErrorCode> {
-        self.apps.enter(processid, |app, kernel_data| {
-            let addr_ports = match app.pending_tx.take() {
-                Some(pending_tx) => pending_tx,
-                None => {
-                    return Ok(());
-                }
-            };
-            let dst_addr = addr_ports[1].addr;
-            let dst_port = addr_ports[1].port;
-            let src_port = addr_ports[0].port;
-
-            // Send UDP payload. Copy payload into packet buffer held by this driver, then queue
-            // it on the udp_mux.
-            let result = kernel_data
-                .get_readonly_processbuffer(ro_allow::WRITE)
-                .and_then(|write| {
-                    write.enter(|payload| {
-                        self.kernel_buffer.take().map_or(
-                            Err(ErrorCode::NOMEM),
-                            |mut kernel_buffer| {
-                                if payload.len() > kernel_buffer.len() {
-                                    self.kernel_buffer.replace(kernel_buffer);
-                                    return Err(ErrorCode::SIZE);
-                                }
-                                payload.copy_to_slice(&mut kernel_buffer[0..payload.len()]);
-                                kernel_buffer.slice(0..payload.len());
-                                match self.sender.driver_send_to(
-                                    dst_addr,
-                                    dst_port,
-                                    src_port,
-                                    kernel_buffer,
-                                    self.driver_send_cap,
-                                    self.net_cap,
-                                ) {
-                                    Ok(_) => Ok(()),
-                                    Err(mut buf) => {
-                                        buf.reset();
-                                        self.kernel_buffer.replace(buf);
-                                        Err(ErrorCode::FAIL)
-                                    }
-                                }
-                            },
-                        )
-                    })
-                })
-                .unwrap_or(Err(ErrorCode::NOMEM));
-            if result == Ok(()) {
-                self.current_app.set(Some(processid));
+    fn perform_tx_sync(&self, slot: usize) -> Result<(), ErrorCode> {
+        let slot_ref = &self.tx_pool[slot];
+        let pending = slot_ref.pending.extract().ok_or(ErrorCode::FAIL)?;
+        let buffer = match slot_ref.buffer.take() {
+            Some(buffer) => buffer,
+            None => {
+                slot_ref.pending.clear();
+                return Err(ErrorCode::NOMEM);
+            }
+        };
+        match self.sender.driver_send_to(
+            pending.dst.addr,
+            pending.dst.port,
+            pending.src_port,
+            buffer,
+            self.driver_send_cap,
+            self.net_cap,
+        ) {
+            Ok(_) => {
+                self.in_flight.set(slot);
+                Ok(())
             }
-            result
-        })?
+            Err(mut buf) => {
+                buf.reset();
+                slot_ref.buffer.replace(buf);
+                slot_ref.pending.clear();
+                Err(ErrorCode::FAIL)
+            }
+        }
     }
 
-    /// Schedule the next transmission if there is one pending. Performs the
-    /// transmission eventually, returning any errors via asynchronous callbacks.
+    /// Send the queued datagrams, until one is passed to the UDP send mux.
+    /// Performs the transmissions eventually, returning any errors via
+    /// asynchronous callbacks.
     #[inline]
-    #[allow(dead_code)]
     fn do_next_tx_queued(&self) {
-        self.get_next_tx_if_idle()
-            .map(|processid| self.perform_tx_async(processid));
+        while let Some(slot) = self.get_next_tx_if_idle() {
+            self.perform_tx_async(slot);
+        }
     }
 
     /// Schedule the next transmission if there is one pending. If the next
-    /// transmission happens to be the one that was just queued, then the
-    /// transmission is immediate. Hence, errors must be returned immediately.
-    /// On the other hand, if it is some other app, then return any errors via
-    /// callbacks.
+    /// transmission happens to be the datagram that was just queued in
+    /// `new_slot`, then the transmission is immediate. Hence, errors must be
+    /// returned immediately. On the other hand, if it is another datagram,
+    /// then return any errors via callbacks.
     #[inline]
-    fn do_next_tx_immediate(&self, new_processid: ProcessId) -> Result<u32, ErrorCode> {
-        self.get_next_tx_if_idle().map_or(Ok(0), |processid| {
-            if processid == new_processid {
-                let sync_result = self.perform_tx_sync(processid);
-                if sync_result == Ok(()) {
-                    Ok(1) //Indicates packet passed to radio
-                } else {
-                    Err(ErrorCode::try_from(sync_result).unwrap())
-                }
+    fn do_next_tx_immediate(&self, new_slot: usize) -> Result<u32, ErrorCode> {
+        self.get_next_tx_if_idle().map_or(Ok(0), |slot| {
+            if slot == new_slot {
+                self.perform_tx_sync(slot).map(|_| 1) //Indicates packet passed to radio
             } else {
-                self.perform_tx_async(processid);
+                self.do_next_tx_queued();
                 Ok(0) //indicates async transmission
             }
         })
     }
 
+    /// Copy the payload in the write buffer of an app into a free pool slot,
+    /// to be sent from `src_port` to `dst`. Returns the slot.
+    fn queue_tx(
+        &self,
+        processid: ProcessId,
+        kernel_data: &kernel::grant::GrantKernelData,
+        src_port: u16,
+        dst: UDPEndpoint,
+    ) -> Result<usize, ErrorCode> {
+        let queued = self
+            .tx_pool
+            .iter()
+            .filter(|slot| slot.pending.map_or(false, |p| p.processid == processid))
+            .count();
+        if queued >= MAX_APP_PENDING_TX {
+            return Err(ErrorCode::BUSY);
+        }
+        let (idx, slot) = self
+            .tx_pool
+            .iter()
+            .enumerate()
+            .find(|(_, slot)| slot.pending.is_none() && slot.buffer.is_some())
+            .ok_or(ErrorCode::BUSY)?;
+        kernel_data
+            .get_readonly_processbuffer(ro_allow::WRITE)
+            .and_then(|write| {
+                write.enter(|payload| {
+                    slot.buffer.map_or(Err(ErrorCode::NOMEM), |buffer| {
+                        if payload.len() > buffer.len() {
+                            return Err(ErrorCode::SIZE);
+                        }
+                        payload.copy_to_slice(&mut buffer[0..payload.len()]);
+                        buffer.slice(0..payload.len());
+                        Ok(())
+                    })
+                })
+            })
+            .unwrap_or(Err(ErrorCode::NOMEM))?;
+        let seq = self.next_seq.get();
+        self.next_seq.set(seq.wrapping_add(1));
+        slot.pending.set(PendingTx {
+            processid,
+            dst,
+            src_port,
+            seq,
+        });
+        Ok(idx)
+    }
+
     #[inline]
     fn parse_ip_port_pair(&self, buf: &[u8]) -> Option<UDPEndpoint> {
         if buf.len() != size_of::<UDPEndpoint>() {
@@ -303,6 +370,64 @@ impl<'a> UDPDriver<'a> {
             Some(pair)
         }
     }
+
+    /// Join or leave the multicast group in the cfg buffer of `processid`.
+    fn update_group(&self, processid: ProcessId, join: bool) -> Result<(), ErrorCode> {
+        self.apps
+            .enter(processid, |app, kernel_data| {
+                if app.bound_port.is_none() {
+                    // Groups are received on the bound port
+                    return Err(ErrorCode::RESERVE);
+                }
+                let group = kernel_data
+                    .get_readwrite_processbuffer(rw_allow::CFG)
+                    .and_then(|cfg| {
+                        cfg.enter(|cfg| {
+                            if cfg.len() != size_of::<IPAddr>() {
+                                return None;
+                            }
+                            let mut group = IPAddr::new();
+                            cfg.copy_to_slice(&mut group.0);
+                            Some(group)
+                        })
+                    })
+                    .unwrap_or(None)
+                    .filter(|group| group.is_multicast())
+                    .ok_or(ErrorCode::INVAL)?;
+                let joined = app.groups.iter().position(|g| *g == Some(group));
+                if join {
+                    if joined.is_some() {
+                        return Err(ErrorCode::ALREADY);
+                    }
+                    let slot = app
+                        .groups
+                        .iter_mut()
+                        .find(|g| g.is_none())
+                        .ok_or(ErrorCode::NOMEM)?;
+                    self.port_table
+                        .join_user_group(group, self.driver_send_cap)?;
+                    *slot = Some(group);
+                } else {
+                    let idx = joined.ok_or(ErrorCode::INVAL)?;
+                    app.groups[idx] = None;
+                    self.port_table
+                        .leave_user_group(group, self.driver_send_cap)?;
+                }
+                Ok(())
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+
+    /// Leave all groups joined by `app`, as it unbinds its port.
+    fn leave_groups(&self, app: &mut App) {
+        for group in app.groups.iter_mut() {
+            if let Some(group) = group.take() {
+                let _ = self
+                    .port_table
+                    .leave_user_group(group, self.driver_send_cap);
+            }
+        }
+    }
 }
 
 impl<'a> SyscallDriver for UDPDriver<'a> {
@@ -347,7 +472,11 @@ impl<'a> SyscallDriver for UDPDriver<'a> {
     ///                       limited by `app_cfg` length.
     ///        Returns INVAL if the cfg buffer is the wrong size, or not available.
     /// - `2`: Transmit payload.
-    ///        Returns BUSY is this process already has a pending tx.
+    ///        The payload is copied out of the write buffer, which the app can reuse right
+    ///        away, and queued. Each queued payload gets its own tx callback.
+    ///        Returns BUSY if this process already has MAX_APP_PENDING_TX payloads queued,
+    ///        or if the transmit pool of the driver is full, and SIZE if the payload does
+    ///        not fit in a pool buffer.
     ///        Returns INVAL if no valid buffer has been loaded into the write buffer,
     ///        or if the config buffer is the wrong length, or if the destination and source
     ///        port/address pairs cannot be parsed.
@@ -359,15 +488,12 @@ impl<'a> SyscallDriver for UDPDriver<'a> {
     ///        is returned with value 1, this means the the packet was successfully passed
     ///        the radio without any errors, which tells the userland application that it does
     ///        not need to wait for a callback to check if any errors occurred while the packet
-    ///        was being passed down to the radio. Any successful return value indicates that
-    ///        the app should wait for a send_done() callback before attempting to queue another
-    ///        packet.
+    ///        was being passed down to the radio. Either way, a send_done() callback follows
+    ///        once the packet is sent, and the app can queue further packets before it.
     ///        Currently, only will transmit if the app has bound to the port passed in the tx_cfg
     ///        buf as the source address. If no port is bound, returns RESERVE, if it tries to
     ///        send on a port other than the port which is bound, returns INVALID.
-    ///        Notably, the currently transmit implementation allows for starvation - an
-    ///        an app with a lower app id can send constantly and starve an app with a
-    ///        later ID.
+    ///        Payloads of all apps are sent in the order they were queued.
     /// - `3`: Bind to the address in rx_cfg. Returns Ok(()) if that addr/port combo is free,
     ///        returns INVAL if the address requested is not a local interface, or if the port
     ///        requested is 0. Returns BUSY if that port is already bound to by another app.
@@ -382,6 +508,14 @@ impl<'a> SyscallDriver for UDPDriver<'a> {
     ///        /// - `4`: Returns the maximum payload that can be transmitted by apps using this driver.
     ///        This represents the size of the payload buffer in the kernel. Apps can use this
     ///        syscall to ensure they do not attempt to send too-large messages.
+    /// - `5`: Join the multicast group in app_cfg, which holds its 16-byte address, so
+    ///        that packets sent to the group on the bound port are received. Returns
+    ///        RESERVE if no port is bound, INVAL if app_cfg does not hold a multicast
+    ///        address, ALREADY if the group is joined, and NOMEM if no more groups can
+    ///        be joined. Unbinding the port leaves all groups. Packets sent to the
+    ///        all-nodes group, ff02::1, are always received.
+    /// - `6`: Leave the multicast group in app_cfg. Returns RESERVE if no port is
+    ///        bound, and INVAL if the group is not joined.
 
     fn command(
         &self,
@@ -405,15 +539,17 @@ impl<'a> SyscallDriver for UDPDriver<'a> {
                                     if cfg.len() != arg1 * size_of::<IPAddr>() {
                                         return CommandReturn::failure(ErrorCode::INVAL);
                                     }
-                                    let n_ifaces_to_copy =
-                                        cmp::min(arg1, self.interface_list.len());
                                     let iface_size = size_of::<IPAddr>();
-                                    for i in 0..n_ifaces_to_copy {
+                                    for (i, iface) in
+                                        self.interface_list.iter().take(arg1).enumerate()
+                                    {
                                         cfg[i * iface_size..(i + 1) * iface_size]
-                                            .copy_from_slice(&self.interface_list[i].0);
+                                            .copy_from_slice(&iface.0);
                                     }
                                     // Returns total number of interfaces
-                                    CommandReturn::success_u32(self.interface_list.len() as u32)
+                                    CommandReturn::success_u32(
+                                        self.interface_list.iter().count() as u32
+                                    )
                                 })
                             })
                             .unwrap_or(CommandReturn::failure(ErrorCode::INVAL))
@@ -426,10 +562,6 @@ impl<'a> SyscallDriver for UDPDriver<'a> {
                 let res = self
                     .apps
                     .enter(processid, |app, kernel_data| {
-                        if app.pending_tx.is_some() {
-                            // Cannot support more than one pending tx per process.
-                            return Err(ErrorCode::BUSY);
-                        }
                         if app.bound_port.is_none() {
                             // Currently, apps need to bind to a port before they can send from said port
                             return Err(ErrorCode::RESERVE);
@@ -465,15 +597,16 @@ impl<'a> SyscallDriver for UDPDriver<'a> {
                                 })
                             })
                             .unwrap_or(None);
-                        if next_tx.is_none() {
-                            return Err(ErrorCode::INVAL);
+                        match next_tx {
+                            Some([src, dst]) => {
+                                self.queue_tx(processid, kernel_data, src.port, dst)
+                            }
+                            None => Err(ErrorCode::INVAL),
                         }
-                        app.pending_tx = next_tx;
-                        Ok(())
                     })
                     .unwrap_or_else(|err| Err(err.into()));
                 match res {
-                    Ok(_) => self.do_next_tx_immediate(processid).map_or_else(
+                    Ok(slot) => self.do_next_tx_immediate(slot).map_or_else(
                         |err| CommandReturn::failure(err.into()),
                         |v| CommandReturn::success_u32(v),
                     ),
@@ -512,16 +645,11 @@ impl<'a> SyscallDriver for UDPDriver<'a> {
                             // If zero address, close any already bound socket
                             if requested_addr.is_zero() {
                                 app.bound_port = None;
+                                self.leave_groups(app);
                                 return Ok(None);
                             }
                             // Check that requested addr is a local interface
-                            let mut requested_is_local = false;
-                            for i in 0..self.interface_list.len() {
-                                if requested_addr.addr == self.interface_list[i] {
-                                    requested_is_local = true;
-                                }
-                            }
-                            if !requested_is_local {
+                            if !self.interface_list.contains(requested_addr.addr) {
                                 return Err(Err(ErrorCode::INVAL));
                             }
                             Ok(Some(requested_addr))
@@ -556,6 +684,12 @@ impl<'a> SyscallDriver for UDPDriver<'a> {
                 }
             }
             4 => CommandReturn::success_u32(self.max_tx_pyld_len as u32),
+            5 => self
+                .update_group(processid, true)
+                .map_or_else(CommandReturn::failure, |_| CommandReturn::success()),
+            6 => self
+                .update_group(processid, false)
+                .map_or_else(CommandReturn::failure, |_| CommandReturn::success()),
             _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
         }
     }
@@ -571,17 +705,19 @@ impl<'a> UDPSendClient for UDPDriver<'a> {
         result: Result<(), ErrorCode>,
         mut dgram: LeasableMutableBuffer<'static, u8>,
     ) {
-        // Replace the returned kernel buffer. Now we can send the next msg.
+        // Return the buffer to its pool slot. Now we can send the next msg.
         dgram.reset();
-        self.kernel_buffer.replace(dgram);
-        self.current_app.get().map(|processid| {
-            let _ = self.apps.enter(processid, |_app, upcalls| {
-                upcalls
-                    .schedule_upcall(1, (kernel::errorcode::into_statuscode(result), 0, 0))
-                    .ok();
-            });
-        });
-        self.current_app.set(None);
+        if let Some(slot) = self.in_flight.take() {
+            let slot = &self.tx_pool[slot];
+            slot.buffer.replace(dgram);
+            if let Some(pending) = slot.pending.take() {
+                let _ = self.apps.enter(pending.processid, |_app, upcalls| {
+                    upcalls
+                        .schedule_upcall(1, (kernel::errorcode::into_statuscode(result), 0, 0))
+                        .ok();
+                });
+            }
+        }
         self.do_next_tx_queued();
     }
 }
@@ -599,7 +735,11 @@ impl<'a> UDPRecvClient for UDPDriver<'a> {
             if app.bound_port.is_some() {
                 let mut for_me = false;
                 app.bound_port.as_ref().map(|requested_addr| {
-                    if requested_addr.addr == dst_addr && requested_addr.port == dst_port {
+                    if requested_addr.port == dst_port
+                        && (requested_addr.addr == dst_addr
+                            || dst_addr == ALL_NODES
+                            || app.groups.contains(&Some(dst_addr)))
+                    {
                         for_me = true;
                     }
                 });
@@ -35,7 +35,16 @@
 //! such that removing an app automatically unbinds it. This file is able to query the
 //! userspace UDP driver to check which ports are bound, and vice-versa, such that
 //! exclusive access to ports between userspace apps and capsules is still enforced.
+//!
+//! Multicast packets are only delivered to a capsule bound to the destination
+//! port if the capsule joined the destination group on its binding, with
+//! `join_group`. The table keeps these memberships, and forwards joins and
+//! leaves, including those of apps made through the UDP driver, to the
+//! `GroupMembership` implementation of the board, if one is set, so that the
+//! groups are reported to the routers of the link.
 
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::ipv6::mld::{GroupMembership, ALL_NODES};
 use crate::net::network_capabilities::{NetworkCapability, UdpVisibilityCapability};
 
 use core::fmt;
@@ -52,6 +61,10 @@ use kernel::ErrorCode;
 // is.
 pub const MAX_NUM_BOUND_PORTS: usize = 16;
 
+// Sets the maximum number of multicast groups joined by capsules, counting a group once
+// for every binding that joined it.
+pub const MAX_NUM_GROUP_MEMBERSHIPS: usize = 8;
+
 /// The SocketBindingEntry struct is stored in the PORT_TABLE and conveys what port is bound
 /// at the given index if one is bound. If no port is bound, the value stored
 /// at that location in the table is Unbound.
@@ -78,10 +91,13 @@ pub struct UdpSocket {
 
 /// The UdpPortManager maintains a reference to the port_array, which manages what
 /// ports are bound at any given moment, and user_ports, which provides a
-/// handle to userspace port bindings in the UDP driver.
+/// handle to userspace port bindings in the UDP driver. It also holds the
+/// multicast groups joined by capsules, as (group, table index, port) entries.
 pub struct UdpPortManager {
     port_array: TakeCell<'static, [Option<SocketBindingEntry>]>,
     user_ports: OptionalCell<&'static dyn PortQuery>,
+    memberships: [OptionalCell<(IPAddr, usize, u16)>; MAX_NUM_GROUP_MEMBERSHIPS],
+    groups: OptionalCell<&'static dyn GroupMembership>,
     udp_vis: &'static UdpVisibilityCapability,
 }
 
@@ -160,6 +176,8 @@ impl UdpPortManager {
         UdpPortManager {
             port_array: TakeCell::new(used_kernel_ports),
             user_ports: OptionalCell::empty(),
+            memberships: Default::default(),
+            groups: OptionalCell::empty(),
             udp_vis: udp_vis,
         }
     }
@@ -174,6 +192,12 @@ impl UdpPortManager {
         self.user_ports.replace(user_ports_ref);
     }
 
+    /// Set the multicast listener that the groups joined through this table
+    /// are reported by.
+    pub fn set_group_membership(&self, groups: &'static dyn GroupMembership) {
+        self.groups.replace(groups);
+    }
+
     /// Called by capsules that would like to eventually be able to bind to a
     /// UDP port. This call will succeed unless MAX_NUM_BOUND_PORTS capsules
     /// have already bound to a port.
@@ -296,7 +320,84 @@ impl UdpPortManager {
         self.port_array.map(|table| {
             table[idx] = Some(SocketBindingEntry::Unbound);
         });
+        // Leave the groups joined on the binding
+        for membership in self.memberships.iter() {
+            if let Some((group, _, _)) = membership.extract().filter(|entry| entry.1 == idx) {
+                membership.clear();
+                self.groups.map(|groups| groups.leave(group));
+            }
+        }
         // Search the list and return the appropriate socket
         Ok(UdpSocket::new(idx, &self))
     }
+
+    /// Receive the packets sent to the multicast `group` on the port of
+    /// `binding`. Returns INVAL if `group` is not a multicast address,
+    /// ALREADY if the binding already joined it, and NOMEM if no more groups
+    /// can be joined.
+    pub fn join_group(&self, binding: &UdpPortBindingRx, group: IPAddr) -> Result<(), ErrorCode> {
+        if !group.is_multicast() {
+            return Err(ErrorCode::INVAL);
+        }
+        if self
+            .memberships
+            .iter()
+            .any(|membership| membership.contains(&(group, binding.idx, binding.port)))
+        {
+            return Err(ErrorCode::ALREADY);
+        }
+        let membership = self
+            .memberships
+            .iter()
+            .find(|membership| membership.is_none())
+            .ok_or(ErrorCode::NOMEM)?;
+        self.groups.map_or(Ok(()), |groups| groups.join(group))?;
+        membership.set((group, binding.idx, binding.port));
+        Ok(())
+    }
+
+    /// Stop receiving the packets sent to `group` on the port of `binding`.
+    /// Returns INVAL if the binding did not join the group.
+    pub fn leave_group(&self, binding: &UdpPortBindingRx, group: IPAddr) -> Result<(), ErrorCode> {
+        let membership = self
+            .memberships
+            .iter()
+            .find(|membership| membership.contains(&(group, binding.idx, binding.port)))
+            .ok_or(ErrorCode::INVAL)?;
+        membership.clear();
+        self.groups.map_or(Ok(()), |groups| groups.leave(group))
+    }
+
+    /// Whether the capsule bound to `port` receives the packets sent to the
+    /// multicast `group`. Every node receives the packets sent to all nodes.
+    pub fn is_member(&self, port: u16, group: IPAddr) -> bool {
+        group == ALL_NODES
+            || self.memberships.iter().any(|membership| {
+                membership.map_or(false, |(addr, _, bound_port)| {
+                    *addr == group && *bound_port == port
+                })
+            })
+    }
+
+    /// Join `group` on behalf of an app. The UDP driver keeps the groups
+    /// joined by each app, and calls this so that they are reported.
+    pub fn join_user_group(
+        &self,
+        group: IPAddr,
+        _driver_cap: &dyn UdpDriverCapability,
+    ) -> Result<(), ErrorCode> {
+        if !group.is_multicast() {
+            return Err(ErrorCode::INVAL);
+        }
+        self.groups.map_or(Ok(()), |groups| groups.join(group))
+    }
+
+    /// Leave `group` on behalf of an app that joined it.
+    pub fn leave_user_group(
+        &self,
+        group: IPAddr,
+        _driver_cap: &dyn UdpDriverCapability,
+    ) -> Result<(), ErrorCode> {
+        self.groups.map_or(Ok(()), |groups| groups.leave(group))
+    }
 }
@@ -8,12 +8,14 @@
 //! appropriate capsule / app. Once again, port binding for userspace apps is managed separately
 //! by the UDP userspace driver, which must correctly check bindings of kernel apps to ensure
 //! correctness when dispatching received packets to the appropriate client.
+//! Packets sent to a multicast group are only dispatched to a capsule if it
+//! joined the group in the port table.
 
-use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::ipv6::ip_utils::{ip6_nh, IPAddr};
 use crate::net::ipv6::ipv6_recv::IP6RecvClient;
 use crate::net::ipv6::IP6Header;
 use crate::net::udp::driver::UDPDriver;
-use crate::net::udp::udp_port_table::{PortQuery, UdpPortBindingRx};
+use crate::net::udp::udp_port_table::{PortQuery, UdpPortBindingRx, UdpPortManager};
 use crate::net::udp::UDPHeader;
 
 use kernel::collections::list::{List, ListLink, ListNode};
@@ -23,6 +25,7 @@ use kernel::utilities::cells::{MapCell, OptionalCell};
 pub struct MuxUdpReceiver<'a> {
     rcvr_list: List<'a, UDPReceiver<'a>>,
     driver: OptionalCell<&'static UDPDriver<'static>>,
+    port_table: OptionalCell<&'static UdpPortManager>,
 }
 
 impl<'a> MuxUdpReceiver<'a> {
@@ -30,6 +33,7 @@ impl<'a> MuxUdpReceiver<'a> {
         MuxUdpReceiver {
             rcvr_list: List::new(),
             driver: OptionalCell::empty(),
+            port_table: OptionalCell::empty(),
         }
     }
 
@@ -40,10 +44,27 @@ impl<'a> MuxUdpReceiver<'a> {
     pub fn set_driver(&self, driver_ref: &'static UDPDriver) {
         self.driver.replace(driver_ref);
     }
+
+    /// Set the port table that holds the multicast groups joined by
+    /// capsules. Without it, capsules receive the packets sent to any group.
+    pub fn set_port_table(&self, port_table: &'static UdpPortManager) {
+        self.port_table.replace(port_table);
+    }
+
+    /// Whether the capsule bound to `port` receives a packet sent to `dst`.
+    fn is_receiver(&self, port: u16, dst: IPAddr) -> bool {
+        !dst.is_multicast()
+            || self
+                .port_table
+                .map_or(true, |port_table| port_table.is_member(port, dst))
+    }
 }
 
 impl<'a> IP6RecvClient for MuxUdpReceiver<'a> {
     fn receive(&self, ip_header: IP6Header, payload: &[u8]) {
+        if ip_header.get_next_header() != ip6_nh::UDP {
+            return;
+        }
         match UDPHeader::decode(payload).done() {
             Some((offset, udp_header)) => {
                 let len = udp_header.get_len() as usize;
@@ -56,15 +77,17 @@ impl<'a> IP6RecvClient for MuxUdpReceiver<'a> {
                     match rcvr.binding.take() {
                         Some(binding) => {
                             if binding.get_port() == dst_port {
-                                rcvr.client.map(|client| {
-                                    client.receive(
-                                        ip_header.get_src_addr(),
-                                        ip_header.get_dst_addr(),
-                                        udp_header.get_src_port(),
-                                        udp_header.get_dst_port(),
-                                        &payload[offset..],
-                                    );
-                                });
+                                if self.is_receiver(dst_port, ip_header.get_dst_addr()) {
+                                    rcvr.client.map(|client| {
+                                        client.receive(
+                                            ip_header.get_src_addr(),
+                                            ip_header.get_dst_addr(),
+                                            udp_header.get_src_port(),
+                                            udp_header.get_dst_port(),
+                                            &payload[offset..],
+                                        );
+                                    });
+                                }
                                 rcvr.binding.replace(binding);
                                 break;
                             }
@@ -19,9 +19,10 @@
 //! the userspace driver must queue app packets on its own, as it can only pass a single
 //! packet to the MuxUdpSender queue at a time.
 
+use crate::net::buffer::PacketBuffer;
 use crate::net::ipv6::ip_utils::IPAddr;
 use crate::net::ipv6::ipv6_send::{IP6SendClient, IP6Sender};
-use crate::net::ipv6::TransportHeader;
+use crate::net::ipv6::{IP6Header, TransportHeader};
 use crate::net::network_capabilities::{NetworkCapability, UdpVisibilityCapability};
 use crate::net::udp::udp_port_table::UdpPortBindingTx;
 use crate::net::udp::UDPHeader;
@@ -64,13 +65,7 @@ impl<'a, T: IP6Sender<'a>> MuxUdpSender<'a, T> {
         // Otherwise, packet is queued.
         if list_empty {
             ret = match caller.tx_buffer.take() {
-                Some(buf) => {
-                    let ret = self
-                        .ip_sender
-                        .send_to(dest, transport_header, &buf, net_cap);
-                    caller.tx_buffer.replace(buf); //Replace buffer as soon as sent.
-                    ret
-                }
+                Some(buf) => self.ip_send(caller, dest, transport_header, buf, net_cap),
                 None => {
                     debug!("No buffer available to take.");
                     Err(ErrorCode::FAIL)
@@ -85,18 +80,46 @@ impl<'a, T: IP6Sender<'a>> MuxUdpSender<'a, T> {
     fn add_client(&self, sender: &'a UDPSendStruct<'a, T>) {
         self.sender_list.push_tail(sender);
     }
+
+    // Packets of other nodes keep the IPv6 header they were received with.
+    // The IP layer owns the buffer until `send_done`, unless it fails to
+    // send the packet, in which case the buffer goes back to the sender.
+    fn ip_send(
+        &self,
+        sender: &UDPSendStruct<'a, T>,
+        dest: IPAddr,
+        transport_header: TransportHeader,
+        buf: LeasableMutableBuffer<'static, u8>,
+        net_cap: &'static NetworkCapability,
+    ) -> Result<(), ErrorCode> {
+        let payload = PacketBuffer::from_lease(buf);
+        match sender.next_ip6_header.take() {
+            Some(ip6_header) => {
+                self.ip_sender
+                    .forward(ip6_header, transport_header, payload, net_cap)
+            }
+            None => self
+                .ip_sender
+                .send_to(dest, transport_header, payload, net_cap),
+        }
+        .map_err(|(ecode, payload)| {
+            sender.tx_buffer.replace(payload.into_lease());
+            ecode
+        })
+    }
 }
 
 /// This function implements the `IP6SendClient` trait for the `UDPSendStruct`,
 /// and is necessary to receive callbacks from the lower (IP) layer. When
 /// the UDP layer receives this callback, it forwards it to the `UDPSendClient`.
 impl<'a, T: IP6Sender<'a>> IP6SendClient for MuxUdpSender<'a, T> {
-    fn send_done(&self, result: Result<(), ErrorCode>) {
+    fn send_done(&self, result: Result<(), ErrorCode>, payload: PacketBuffer) {
         let last_sender = self.sender_list.pop_head();
         let next_sender_option = self.sender_list.head(); // must check here, because udp driver
                                                           // could queue addl. sends in response to
                                                           // send_done.
         last_sender.map(|last_sender| {
+            last_sender.tx_buffer.replace(payload.into_lease());
             last_sender
                 .client
                 .map(|client| match last_sender.tx_buffer.take() {
@@ -116,13 +139,13 @@ impl<'a, T: IP6Sender<'a>> IP6SendClient for MuxUdpSender<'a, T> {
                     Some(buf) => match next_sender.next_th.take() {
                         Some(th) => match next_sender.net_cap.take() {
                             Some(net_cap) => {
-                                let ret = self.ip_sender.send_to(
+                                let ret = self.ip_send(
+                                    next_sender,
                                     next_sender.next_dest.get(),
                                     th,
-                                    &buf,
+                                    buf,
                                     net_cap,
                                 );
-                                next_sender.tx_buffer.replace(buf);
                                 if ret != Ok(()) {
                                     debug!("IP send_to failed: {:?}", ret);
                                 }
@@ -233,6 +256,46 @@ pub trait UDPSender<'a> {
         net_cap: &'static NetworkCapability,
     ) -> Result<(), LeasableMutableBuffer<'static, u8>>;
 
+    /// This function is identical to `send()` except that it takes in any
+    /// completed `TransportHeader`. This lets other transport protocols, such
+    /// as TCP, share the sending queue with UDP.
+    ///
+    /// # Arguments
+    /// `dest` - IP address to send the packet to
+    /// `transport_header` - Completed transport header to be sent to the destination
+    /// `buf` - A byte array containing the transport payload
+    ///
+    /// # Return Value
+    /// Returns any synchronous errors or success. Note that any asynchrounous
+    /// errors are returned via the callback.
+    fn send_transport(
+        &'a self,
+        dest: IPAddr,
+        transport_header: TransportHeader,
+        buf: LeasableMutableBuffer<'static, u8>,
+        net_cap: &'static NetworkCapability,
+    ) -> Result<(), LeasableMutableBuffer<'static, u8>>;
+
+    /// This function forwards a packet of another node, which keeps its
+    /// IPv6 header rather than being sent from this node. It lets a router
+    /// share the sending queue with the transport protocols.
+    ///
+    /// # Arguments
+    /// `ip6_header` - IPv6 header the packet was received with
+    /// `transport_header` - Transport header of the packet
+    /// `buf` - A byte array containing the transport payload
+    ///
+    /// # Return Value
+    /// Returns any synchronous errors or success. Note that any asynchrounous
+    /// errors are returned via the callback.
+    fn forward(
+        &'a self,
+        ip6_header: IP6Header,
+        transport_header: TransportHeader,
+        buf: LeasableMutableBuffer<'static, u8>,
+        net_cap: &'static NetworkCapability,
+    ) -> Result<(), LeasableMutableBuffer<'static, u8>>;
+
     fn get_binding(&self) -> Option<UdpPortBindingTx>;
 
     fn is_bound(&self) -> bool;
@@ -250,6 +313,7 @@ pub struct UDPSendStruct<'a, T: IP6Sender<'a>> {
     tx_buffer: MapCell<LeasableMutableBuffer<'static, u8>>,
     next_dest: Cell<IPAddr>,
     next_th: OptionalCell<TransportHeader>,
+    next_ip6_header: OptionalCell<IP6Header>,
     binding: MapCell<UdpPortBindingTx>,
     udp_vis: &'static UdpVisibilityCapability,
     net_cap: OptionalCell<&'static NetworkCapability>,
@@ -321,7 +385,16 @@ impl<'a, T: IP6Sender<'a>> UDPSender<'a> for UDPSendStruct<'a, T> {
         net_cap: &'static NetworkCapability,
     ) -> Result<(), LeasableMutableBuffer<'static, u8>> {
         udp_header.set_len((buf.len() + udp_header.get_hdr_size()) as u16);
-        let transport_header = TransportHeader::UDP(udp_header);
+        self.send_transport(dest, TransportHeader::UDP(udp_header), buf, net_cap)
+    }
+
+    fn send_transport(
+        &'a self,
+        dest: IPAddr,
+        transport_header: TransportHeader,
+        buf: LeasableMutableBuffer<'static, u8>,
+        net_cap: &'static NetworkCapability,
+    ) -> Result<(), LeasableMutableBuffer<'static, u8>> {
         self.tx_buffer.replace(buf);
         self.next_dest.replace(dest);
         self.next_th.replace(transport_header); // th = transport header
@@ -334,6 +407,21 @@ impl<'a, T: IP6Sender<'a>> UDPSender<'a> for UDPSendStruct<'a, T> {
         }
     }
 
+    fn forward(
+        &'a self,
+        ip6_header: IP6Header,
+        transport_header: TransportHeader,
+        buf: LeasableMutableBuffer<'static, u8>,
+        net_cap: &'static NetworkCapability,
+    ) -> Result<(), LeasableMutableBuffer<'static, u8>> {
+        self.next_ip6_header.set(ip6_header);
+        self.send_transport(ip6_header.get_dst_addr(), transport_header, buf, net_cap)
+            .map_err(|buf| {
+                self.next_ip6_header.clear();
+                buf
+            })
+    }
+
     fn get_binding(&self) -> Option<UdpPortBindingTx> {
         self.binding.take()
     }
@@ -359,6 +447,7 @@ impl<'a, T: IP6Sender<'a>> UDPSendStruct<'a, T> {
             tx_buffer: MapCell::empty(),
             next_dest: Cell::new(IPAddr::new()),
             next_th: OptionalCell::empty(),
+            next_ip6_header: OptionalCell::empty(),
             binding: MapCell::empty(),
             udp_vis: udp_vis,
             net_cap: OptionalCell::empty(),
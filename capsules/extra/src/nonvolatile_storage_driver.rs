@@ -4,10 +4,14 @@
 
 //! This provides kernel and userspace access to nonvolatile memory.
 //!
-//! This is an initial implementation that does not provide safety for
-//! individual userland applications. Each application has full access to
-//! the entire memory space that has been provided to userland. Future revisions
-//! should update this to limit applications to only their allocated regions.
+//! The memory space provided to userland can be split into regions, each
+//! owned by the application with a storage ID (the `write_id` in the
+//! persistent ACL of its TBF header). An application can write to a region
+//! if it owns it or the region's storage ID is in its access (modify) list,
+//! and read from it if it owns it or the storage ID is in its read list.
+//! Each read or write must stay within one region. If the board does not
+//! provide any regions, every application has full access to the entire
+//! memory space that has been provided to userland.
 //!
 //! However, the kernel accessible memory does not have to be the same range
 //! as the userspace accessible address space. The kernel memory can overlap
@@ -54,6 +58,11 @@
 //!         0,                           // The byte start address of the region
 //!                                      // that is accessible by the kernel.
 //!         3000,                        // The length of the kernel region.
+//!         &[
+//!             // Two 1000 byte regions for the apps with storage IDs 1 and 2.
+//!             StorageRegion { storage_id: 1, offset: 0, length: 1000 },
+//!             StorageRegion { storage_id: 2, offset: 1000, length: 1000 },
+//!         ],
 //!         &mut capsules::nonvolatile_storage_driver::BUFFER));
 //! hil::nonvolatile_storage::NonvolatileStorage::set_client(fm25cl, nonvolatile_storage);
 //! ```
@@ -88,6 +97,17 @@ mod rw_allow {
 
 pub const BUF_LEN: usize = 512;
 
+/// A part of the userspace accessible memory owned by one application.
+#[derive(Clone, Copy)]
+pub struct StorageRegion {
+    /// The storage ID (TBF `write_id`) of the application owning the region.
+    pub storage_id: u32,
+    /// The start of the region, relative to the userspace accessible memory.
+    pub offset: usize,
+    /// The length of the region in bytes.
+    pub length: usize,
+}
+
 #[derive(Clone, Copy, PartialEq)]
 pub enum NonvolatileCommand {
     UserspaceRead,
@@ -144,6 +164,9 @@ pub struct NonvolatileStorage<'a> {
     kernel_start_address: usize,
     // How many bytes allocated to kernel.
     kernel_length: usize,
+    // The per-application regions of the userspace accessible memory. If
+    // empty, all applications share the userspace accessible memory.
+    regions: &'a [StorageRegion],
 
     // Optional client for the kernel. Only needed if the kernel intends to use
     // this nonvolatile storage.
@@ -174,6 +197,7 @@ impl<'a> NonvolatileStorage<'a> {
         userspace_length: usize,
         kernel_start_address: usize,
         kernel_length: usize,
+        regions: &'a [StorageRegion],
         buffer: &'static mut [u8],
     ) -> NonvolatileStorage<'a> {
         NonvolatileStorage {
@@ -185,6 +209,7 @@ impl<'a> NonvolatileStorage<'a> {
             userspace_length: userspace_length,
             kernel_start_address: kernel_start_address,
             kernel_length: kernel_length,
+            regions,
             kernel_client: OptionalCell::empty(),
             kernel_pending_command: Cell::new(false),
             kernel_command: Cell::new(NonvolatileCommand::KernelRead),
@@ -194,6 +219,53 @@ impl<'a> NonvolatileStorage<'a> {
         }
     }
 
+    // The region owned by `processid`, if any.
+    fn owned_region(&self, processid: ProcessId) -> Option<&StorageRegion> {
+        let write_id = processid.get_storage_permissions()?.get_write_id()?;
+        self.regions
+            .iter()
+            .find(|region| region.storage_id == write_id)
+    }
+
+    // Check that `processid` may do `command` on `length` bytes at `offset`
+    // of the userspace accessible memory.
+    fn check_permissions(
+        &self,
+        command: NonvolatileCommand,
+        offset: usize,
+        length: usize,
+        processid: ProcessId,
+    ) -> Result<(), ErrorCode> {
+        if self.regions.is_empty() {
+            return Ok(());
+        }
+
+        let region = self
+            .regions
+            .iter()
+            .find(|region| {
+                offset >= region.offset && offset + length <= region.offset + region.length
+            })
+            .ok_or(ErrorCode::INVAL)?;
+        let perms = processid.get_storage_permissions().ok_or(ErrorCode::FAIL)?;
+        let owner = perms.get_write_id() == Some(region.storage_id);
+
+        let allowed = match command {
+            NonvolatileCommand::UserspaceRead => {
+                owner || perms.check_read_permission(region.storage_id)
+            }
+            NonvolatileCommand::UserspaceWrite => {
+                owner || perms.check_write_permission(region.storage_id)
+            }
+            _ => false,
+        };
+        if allowed {
+            Ok(())
+        } else {
+            Err(ErrorCode::FAIL)
+        }
+    }
+
     // Check so see if we are doing something. If not, go ahead and do this
     // command. If so, this is queued and will be run when the pending
     // command completes.
@@ -256,6 +328,10 @@ impl<'a> NonvolatileStorage<'a> {
                             // put it.
                             let active_len = cmp::min(length, allow_buf_len);
 
+                            // Check that the app may access this part of the
+                            // storage.
+                            self.check_permissions(command, offset, active_len, processid)?;
+
                             // First need to determine if we can execute this or must
                             // queue it.
                             if self.current_user.is_none() {
@@ -549,6 +625,10 @@ impl SyscallDriver for NonvolatileStorage<'_> {
     /// - `1`: Return the number of bytes available to userspace.
     /// - `2`: Start a read from the nonvolatile storage.
     /// - `3`: Start a write to the nonvolatile_storage.
+    /// - `4`: Return the offset and length of the region owned by this app.
+    ///
+    /// Reads and writes return `INVAL` if they are not within one region
+    /// and `FAIL` if the app does not have permission to access the region.
     fn command(
         &self,
         command_num: usize,
@@ -596,6 +676,20 @@ impl SyscallDriver for NonvolatileStorage<'_> {
                 }
             }
 
+            4 /* Where the region owned by this app is */ => {
+                if self.regions.is_empty() {
+                    CommandReturn::success_u32_u32(0, self.userspace_length as u32)
+                } else {
+                    match self.owned_region(processid) {
+                        Some(region) => CommandReturn::success_u32_u32(
+                            region.offset as u32,
+                            region.length as u32,
+                        ),
+                        None => CommandReturn::failure(ErrorCode::NOSUPPORT),
+                    }
+                }
+            }
+
             _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
         }
     }
@@ -0,0 +1,343 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Map arbitrary nonvolatile reads and writes to block operations.
+//!
+//! This splits reads and writes into a series of block level reads and writes,
+//! reading blocks first that a write only covers in part. Once a write is done
+//! it flushes the storage, so written data is durable when the client gets its
+//! callback. While it is handling a read or write it returns `BUSY` to all
+//! additional requests.
+//!
+//! This module is the `NonvolatileToPages` counterpart for block storage, like
+//! SD cards. It lets the nonvolatile storage driver run over any block device.
+//!
+//! ```plain
+//! hil::nonvolatile_storage::NonvolatileStorage
+//!                ┌─────────────┐
+//!                │             │
+//!                │ This module │
+//!                │             │
+//!                └─────────────┘
+//!     hil::block_storage::BlockStorage
+//! ```
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::{hil, static_init};
+//!
+//! let nv_to_blocks = static_init!(
+//!     capsules_extra::nonvolatile_to_blocks::NonvolatileToBlocks<'static, SDCard>,
+//!     capsules_extra::nonvolatile_to_blocks::NonvolatileToBlocks::new(
+//!         sdcard,
+//!         static_init!([u8; 512], [0; 512])));
+//! hil::block_storage::BlockStorage::set_client(sdcard, nv_to_blocks);
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::hil;
+use kernel::hil::block_storage::BlockStorage;
+use kernel::utilities::cells::NumericCellExt;
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// This module is either waiting to do something, handling a read/write, or
+/// flushing the storage after a write.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    Idle,
+    Read,
+    Write,
+    Flush,
+}
+
+pub struct NonvolatileToBlocks<'a, B: BlockStorage<'a>> {
+    /// The module providing a `BlockStorage` interface.
+    driver: &'a B,
+    /// Callback to the user of this capsule.
+    client: OptionalCell<&'static dyn hil::nonvolatile_storage::NonvolatileStorageClient<'static>>,
+    /// Buffer holding at least one block of the underlying storage.
+    blockbuffer: TakeCell<'static, [u8]>,
+    /// Current state of this capsule.
+    state: Cell<State>,
+    /// Temporary holding place for the user's buffer.
+    buffer: TakeCell<'static, [u8]>,
+    /// Absolute address of where we are reading or writing. This gets updated
+    /// as the operation proceeds across blocks.
+    address: Cell<usize>,
+    /// Total length to read or write. We need to store this to return it to the
+    /// client.
+    length: Cell<usize>,
+    /// How many bytes are left to read or write.
+    remaining_length: Cell<usize>,
+    /// Where we are in the user buffer.
+    buffer_index: Cell<usize>,
+}
+
+impl<'a, B: BlockStorage<'a>> NonvolatileToBlocks<'a, B> {
+    pub fn new(driver: &'a B, buffer: &'static mut [u8]) -> NonvolatileToBlocks<'a, B> {
+        NonvolatileToBlocks {
+            driver,
+            client: OptionalCell::empty(),
+            blockbuffer: TakeCell::new(buffer),
+            state: Cell::new(State::Idle),
+            buffer: TakeCell::empty(),
+            address: Cell::new(0),
+            length: Cell::new(0),
+            remaining_length: Cell::new(0),
+            buffer_index: Cell::new(0),
+        }
+    }
+
+    /// Start reading the block at the current address, to copy from it or to
+    /// update part of it.
+    fn read_block(&self, blockbuffer: &'static mut [u8]) -> Result<(), ErrorCode> {
+        let block = self.address.get() / self.driver.block_size();
+        self.driver
+            .read_blocks(blockbuffer, block as u64, 1)
+            .map_err(|(error_code, blockbuffer)| {
+                self.blockbuffer.replace(blockbuffer);
+                error_code
+            })
+    }
+
+    /// Start writing the block at the current address, after copying the
+    /// user's data into it.
+    fn write_block(
+        &self,
+        blockbuffer: &'static mut [u8],
+        buffer: &mut [u8],
+    ) -> Result<(), ErrorCode> {
+        let block_size = self.driver.block_size();
+        // This will get us our offset into the block.
+        let block_index = self.address.get() % block_size;
+        // Length is either the rest of the block or how much we have left.
+        let len = cmp::min(block_size - block_index, self.remaining_length.get());
+        // And where we left off in the user buffer.
+        let buffer_index = self.buffer_index.get();
+        // Which block we are going to write.
+        let block = self.address.get() / block_size;
+
+        blockbuffer[block_index..block_index + len]
+            .copy_from_slice(&buffer[buffer_index..buffer_index + len]);
+
+        self.remaining_length.subtract(len);
+        self.address.add(len);
+        self.buffer_index.set(buffer_index + len);
+        self.driver
+            .write_blocks(blockbuffer, block as u64, 1)
+            .map_err(|(error_code, blockbuffer)| {
+                self.blockbuffer.replace(blockbuffer);
+                error_code
+            })
+    }
+
+    /// Continue a write with the next block, or flush the storage once all
+    /// blocks are written.
+    fn continue_write(&self, blockbuffer: &'static mut [u8]) -> Result<(), ErrorCode> {
+        let block_size = self.driver.block_size();
+
+        if self.remaining_length.get() == 0 {
+            self.blockbuffer.replace(blockbuffer);
+            self.state.set(State::Flush);
+            self.driver.flush()
+        } else if self.remaining_length.get() >= block_size {
+            // Write an entire block!
+            self.buffer.map_or(Err(ErrorCode::FAIL), |buffer| {
+                self.write_block(blockbuffer, buffer)
+            })
+        } else {
+            // Write a partial block!
+            self.read_block(blockbuffer)
+        }
+    }
+
+    /// Give the user's buffer back, with 0 bytes read or written if the
+    /// operation failed.
+    fn done(&self, result: Result<(), ErrorCode>) {
+        let state = self.state.get();
+        self.state.set(State::Idle);
+        let length = result.map_or(0, |()| self.length.get());
+
+        self.buffer.take().map(move |buffer| {
+            self.client.map(move |client| {
+                if state == State::Read {
+                    client.read_done(buffer, length);
+                } else {
+                    client.write_done(buffer, length);
+                }
+            });
+        });
+    }
+}
+
+impl<'a, B: BlockStorage<'a>> hil::nonvolatile_storage::NonvolatileStorage<'static>
+    for NonvolatileToBlocks<'a, B>
+{
+    fn set_client(&self, client: &'static dyn hil::nonvolatile_storage::NonvolatileStorageClient) {
+        self.client.set(client);
+    }
+
+    fn read(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        if length > buffer.len() {
+            return Err(ErrorCode::SIZE);
+        }
+
+        self.blockbuffer
+            .take()
+            .map_or(Err(ErrorCode::RESERVE), move |blockbuffer| {
+                // Just start reading. We'll worry about how much of the block
+                // we want later.
+                self.state.set(State::Read);
+                self.buffer.replace(buffer);
+                self.address.set(address);
+                self.length.set(length);
+                self.remaining_length.set(length);
+                self.buffer_index.set(0);
+
+                self.read_block(blockbuffer).map_err(|e| {
+                    self.state.set(State::Idle);
+                    e
+                })
+            })
+    }
+
+    fn write(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        if length > buffer.len() {
+            return Err(ErrorCode::SIZE);
+        }
+
+        self.blockbuffer
+            .take()
+            .map_or(Err(ErrorCode::RESERVE), move |blockbuffer| {
+                let block_size = self.driver.block_size();
+
+                self.state.set(State::Write);
+                self.address.set(address);
+                self.length.set(length);
+                self.remaining_length.set(length);
+                self.buffer_index.set(0);
+
+                let result = if address % block_size == 0 && length >= block_size {
+                    // This write is aligned to a block and we are writing an
+                    // entire block or more.
+                    let result = self.write_block(blockbuffer, buffer);
+                    self.buffer.replace(buffer);
+                    result
+                } else {
+                    // Need to do a read first.
+                    self.buffer.replace(buffer);
+                    self.read_block(blockbuffer)
+                };
+                result.map_err(|e| {
+                    self.state.set(State::Idle);
+                    e
+                })
+            })
+    }
+}
+
+impl<'a, B: BlockStorage<'a>> hil::block_storage::BlockStorageClient
+    for NonvolatileToBlocks<'a, B>
+{
+    fn read_complete(&self, blockbuffer: &'static mut [u8], result: Result<(), ErrorCode>) {
+        if result.is_err() {
+            self.blockbuffer.replace(blockbuffer);
+            self.done(result);
+            return;
+        }
+
+        let result = match self.state.get() {
+            State::Read => {
+                // OK we got a block from storage. Copy what we actually want
+                // from it out of it.
+                let block_size = self.driver.block_size();
+                // This will get us our offset into the block.
+                let block_index = self.address.get() % block_size;
+                // Length is either the rest of the block or how much we have left.
+                let len = cmp::min(block_size - block_index, self.remaining_length.get());
+                // And where we left off in the user buffer.
+                let buffer_index = self.buffer_index.get();
+
+                // Copy what we read from the block buffer to the user buffer.
+                self.buffer.map(|buffer| {
+                    buffer[buffer_index..buffer_index + len]
+                        .copy_from_slice(&blockbuffer[block_index..block_index + len]);
+                });
+
+                // Decide if we are done.
+                self.remaining_length.subtract(len);
+                self.address.add(len);
+                self.buffer_index.set(buffer_index + len);
+                if self.remaining_length.get() == 0 {
+                    // Nothing more to do. Put things back and issue callback.
+                    self.blockbuffer.replace(blockbuffer);
+                    self.done(Ok(()));
+                    Ok(())
+                } else {
+                    // More to do!
+                    self.read_block(blockbuffer)
+                }
+            }
+            State::Write => {
+                // We did a read because we're not block aligned on either or
+                // both ends. Update the part of the block we write.
+                self.buffer.map_or(Err(ErrorCode::FAIL), |buffer| {
+                    self.write_block(blockbuffer, buffer)
+                })
+            }
+            _ => {
+                self.blockbuffer.replace(blockbuffer);
+                Ok(())
+            }
+        };
+
+        if result.is_err() {
+            self.done(result);
+        }
+    }
+
+    fn write_complete(&self, blockbuffer: &'static mut [u8], result: Result<(), ErrorCode>) {
+        // After a write we could be done, need to do another write, or need to
+        // do a read.
+        let result = match result {
+            Ok(()) => self.continue_write(blockbuffer),
+            Err(error_code) => {
+                self.blockbuffer.replace(blockbuffer);
+                Err(error_code)
+            }
+        };
+
+        if result.is_err() {
+            self.done(result);
+        }
+    }
+
+    fn erase_complete(&self, _result: Result<(), ErrorCode>) {}
+
+    fn flush_complete(&self, result: Result<(), ErrorCode>) {
+        if self.state.get() == State::Flush {
+            self.done(result);
+        }
+    }
+}
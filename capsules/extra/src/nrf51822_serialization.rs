@@ -8,6 +8,11 @@
 //! This capsule handles interfacing with the UART driver, and includes some
 //! nuances that keep the Nordic BLE serialization library happy.
 //!
+//! The nRF51822 runs the SoftDevice, which only offers advertising and
+//! scanning as whole GAP procedures. It therefore cannot implement
+//! `kernel::hil::ble_advertising`, and processes talk to it through the
+//! serialization library rather than the BLE advertising driver.
+//!
 //! Usage
 //! -----
 //!
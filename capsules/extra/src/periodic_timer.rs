@@ -0,0 +1,185 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! System call driver for periodic timers.
+//!
+//! With the alarm driver, a process that wants a callback every period sets
+//! a new alarm in each callback, and the time it takes to get to that call
+//! adds up over the periods. This driver re-arms the timer in the kernel
+//! relative to the previous expiration, so the callbacks stay on the
+//! schedule of the first one. Each process has one timer, which runs for a
+//! number of periods or until it is stopped.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let periodic_timer = components::periodic_timer::PeriodicTimerComponent::new(
+//!     board_kernel,
+//!     capsules_extra::periodic_timer::DRIVER_NUM,
+//!     mux_alarm,
+//! )
+//! .finalize(components::periodic_timer_component_static!(sam4l::ast::Ast));
+//! ```
+//!
+//! Command Interface
+//! -----------------
+//!
+//! - `0`: Driver existence check.
+//! - `1`: Returns the frequency of the timer in Hz.
+//! - `2`: Start the timer with a period of `data1` ticks, for `data2`
+//!   periods or, if `data2` is `0`, until it is stopped. The first period
+//!   starts now. Returns `INVAL` if the period is `0` or at least half the
+//!   range of the timer.
+//! - `3`: Stop the timer. Returns `ALREADY` if it is not running.
+//!
+//! Upcall `0` is called when one or more periods have passed, with the
+//! current time in ticks, the number of periods since the last upcall
+//! (more than one if the process could not be notified in time), and `1`
+//! if the timer is still running or `0` if it ran its last period.
+
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::time::{self, Alarm, Frequency, Ticks};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::{ErrorCode, ProcessId};
+
+/// Syscall driver number.
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::PeriodicTimer as usize;
+
+#[derive(Default)]
+pub struct App {
+    running: bool,
+    /// Period in ticks.
+    period: u32,
+    /// Start of the current period.
+    reference: u32,
+    /// Periods left to run, 0 to run until stopped.
+    remaining: u32,
+}
+
+pub struct PeriodicTimer<'a, A: Alarm<'a>> {
+    alarm: &'a A,
+    apps: Grant<App, UpcallCount<1>, AllowRoCount<0>, AllowRwCount<0>>,
+}
+
+impl<'a, A: Alarm<'a>> PeriodicTimer<'a, A> {
+    pub fn new(
+        alarm: &'a A,
+        grant: Grant<App, UpcallCount<1>, AllowRoCount<0>, AllowRwCount<0>>,
+    ) -> PeriodicTimer<'a, A> {
+        PeriodicTimer { alarm, apps: grant }
+    }
+
+    /// Arm the alarm for the end of the earliest period, or disarm it if no
+    /// timer is running.
+    fn reset_active_alarm(&self) {
+        let now = self.alarm.now();
+        let mut earliest: Option<A::Ticks> = None;
+        for app in self.apps.iter() {
+            app.enter(|app, _| {
+                if app.running {
+                    let reference = A::Ticks::from(app.reference);
+                    let end = reference.wrapping_add(A::Ticks::from(app.period));
+                    // Periods that already ended fire right away.
+                    let dt = if now.within_range(reference, end) {
+                        end.wrapping_sub(now)
+                    } else {
+                        A::Ticks::from(0)
+                    };
+                    earliest = Some(earliest.map_or(dt, |earliest| earliest.min(dt)));
+                }
+            });
+        }
+        match earliest {
+            Some(dt) => self.alarm.set_alarm(now, dt),
+            None => {
+                let _ = self.alarm.disarm();
+            }
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> time::AlarmClient for PeriodicTimer<'a, A> {
+    fn alarm(&self) {
+        let now = self.alarm.now();
+        self.apps.each(|_, app, kernel_data| {
+            if !app.running {
+                return;
+            }
+            let reference = A::Ticks::from(app.reference);
+            let end = reference.wrapping_add(A::Ticks::from(app.period));
+            if now.within_range(reference, end) {
+                return;
+            }
+            // Advance by whole periods, so that the next period ends on the
+            // schedule of the first one even if this alarm came late.
+            let mut periods = now.wrapping_sub(reference).into_u32() / app.period;
+            if app.remaining != 0 {
+                if periods >= app.remaining {
+                    periods = app.remaining;
+                    app.running = false;
+                }
+                app.remaining -= periods;
+            }
+            app.reference = reference
+                .wrapping_add(A::Ticks::from(periods.wrapping_mul(app.period)))
+                .into_u32();
+            let _ = kernel_data.schedule_upcall(
+                0,
+                (now.into_usize(), periods as usize, app.running as usize),
+            );
+        });
+        self.reset_active_alarm();
+    }
+}
+
+impl<'a, A: Alarm<'a>> SyscallDriver for PeriodicTimer<'a, A> {
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        let result = self.apps.enter(processid, |app, _| match command_num {
+            0 => Ok(false),
+            1 => Err(CommandReturn::success_u32(<A::Frequency>::frequency())),
+            2 => {
+                let period = data1 as u32;
+                if period == 0 || A::Ticks::from(period) >= A::Ticks::half_max_value() {
+                    return Err(CommandReturn::failure(ErrorCode::INVAL));
+                }
+                app.running = true;
+                app.period = period;
+                app.reference = self.alarm.now().into_u32();
+                app.remaining = data2 as u32;
+                Ok(true)
+            }
+            3 => {
+                if app.running {
+                    app.running = false;
+                    Ok(true)
+                } else {
+                    Err(CommandReturn::failure(ErrorCode::ALREADY))
+                }
+            }
+            _ => Err(CommandReturn::failure(ErrorCode::NOSUPPORT)),
+        });
+        match result {
+            Ok(Ok(reset)) => {
+                if reset {
+                    self.reset_active_alarm();
+                }
+                CommandReturn::success()
+            }
+            Ok(Err(command_return)) => command_return,
+            Err(err) => CommandReturn::failure(err.into()),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
@@ -0,0 +1,255 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! ShortID assignment that keeps an application's ShortID across
+//! reinstalls and reboots.
+//!
+//! The first time a process with a given name is approved, it is bound to
+//! a ShortID: the one derived from its credentials if that is not bound to
+//! another name yet, otherwise the next free ID counting up from a
+//! board-chosen first ID. The binding is stored in nonvolatile storage, and
+//! later processes with the same name get the same ShortID, so an
+//! application keeps its storage permissions and IPC identity when it is
+//! updated or its credentials change.
+//!
+//! Note that this makes the process name the application's identity: any
+//! process with the same name gets the same ShortID. Boards that need
+//! ShortIDs bound to signing keys should not use this assigner.
+//!
+//! The table is read synchronously through a memory-mapped view of the
+//! storage, since ShortIDs are assigned while processes are approved, and
+//! written back through the `NonvolatileStorage` interface when a binding
+//! is added.
+//!
+//! Storage Format
+//! --------------
+//!
+//! The table starts with a header of the magic number and the next ID to
+//! assign, both little endian `u32`. `N` entries of `ENTRY_LEN` bytes
+//! follow: the ShortID (`u32`, little endian, `0` or `0xffffffff` for an
+//! empty entry), the length of the name and its first `NAME_LEN` bytes.
+//! Names longer than `NAME_LEN` bytes are told apart only by their length.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let short_ids = static_init!(
+//!     PersistentShortIds<'static, 8>,
+//!     PersistentShortIds::new(
+//!         nv_to_page,
+//!         &SHORT_ID_TABLE, // Memory-mapped view of the table in flash
+//!         SHORT_ID_TABLE_ADDRESS,
+//!         NonZeroU32::new(0x1000).unwrap(),
+//!         static_init!([u8; storage_len(8)], [0; storage_len(8)]),
+//!     )
+//! );
+//! nv_to_page.set_client(short_ids);
+//! board_kernel.get_checker().set_short_id_assigner(short_ids);
+//! ```
+
+use core::cell::Cell;
+use core::num::NonZeroU32;
+
+use kernel::hil::nonvolatile_storage::{NonvolatileStorage, NonvolatileStorageClient};
+use kernel::process::{Process, ShortID};
+use kernel::process_checker::ShortIdAssigner;
+use kernel::utilities::cells::{MapCell, TakeCell};
+
+/// Length of a table entry in bytes.
+pub const ENTRY_LEN: usize = 32;
+
+/// Bytes of the name kept in a table entry.
+pub const NAME_LEN: usize = ENTRY_LEN - 5;
+
+const HEADER_LEN: usize = 8;
+
+const MAGIC: u32 = 0x5344_4931;
+
+/// Size of the storage needed for a table of `n` entries.
+pub const fn storage_len(n: usize) -> usize {
+    HEADER_LEN + n * ENTRY_LEN
+}
+
+#[derive(Copy, Clone)]
+struct Binding {
+    id: NonZeroU32,
+    name_len: u8,
+    name: [u8; NAME_LEN],
+}
+
+impl Binding {
+    fn new(id: NonZeroU32, name: &[u8]) -> Binding {
+        let mut binding = Binding {
+            id,
+            name_len: name.len().min(u8::MAX as usize) as u8,
+            name: [0; NAME_LEN],
+        };
+        let len = name.len().min(NAME_LEN);
+        binding.name[..len].copy_from_slice(&name[..len]);
+        binding
+    }
+
+    fn matches(&self, name: &[u8]) -> bool {
+        let len = name.len().min(NAME_LEN);
+        self.name_len as usize == name.len().min(u8::MAX as usize)
+            && self.name[..len] == name[..len]
+    }
+}
+
+struct Table<const N: usize> {
+    bindings: [Option<Binding>; N],
+    next_id: u32,
+}
+
+pub struct PersistentShortIds<'a, const N: usize> {
+    storage: &'a dyn NonvolatileStorage<'a>,
+    stored: &'a [u8],
+    address: usize,
+    first_id: NonZeroU32,
+    table: MapCell<Table<N>>,
+    loaded: Cell<bool>,
+    /// The table changed since it was last written.
+    dirty: Cell<bool>,
+    buffer: TakeCell<'a, [u8]>,
+}
+
+impl<'a, const N: usize> PersistentShortIds<'a, N> {
+    /// `stored` is a readable view of the table, which is written at
+    /// `address` of `storage`. `buffer` must hold `storage_len(N)` bytes.
+    pub fn new(
+        storage: &'a dyn NonvolatileStorage<'a>,
+        stored: &'a [u8],
+        address: usize,
+        first_id: NonZeroU32,
+        buffer: &'a mut [u8],
+    ) -> PersistentShortIds<'a, N> {
+        PersistentShortIds {
+            storage,
+            stored,
+            address,
+            first_id,
+            table: MapCell::new(Table {
+                bindings: [None; N],
+                next_id: first_id.get(),
+            }),
+            loaded: Cell::new(false),
+            dirty: Cell::new(false),
+            buffer: TakeCell::new(buffer),
+        }
+    }
+
+    fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+        bytes
+            .get(offset..offset + 4)
+            .map_or(0, |b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    /// Parse the stored table, unless it was already loaded.
+    fn load(&self, table: &mut Table<N>) {
+        if self.loaded.replace(true) || Self::read_u32(self.stored, 0) != MAGIC {
+            return;
+        }
+        table.next_id = Self::read_u32(self.stored, 4).max(self.first_id.get());
+        for (i, slot) in table.bindings.iter_mut().enumerate() {
+            let offset = HEADER_LEN + i * ENTRY_LEN;
+            let entry = match self.stored.get(offset..offset + ENTRY_LEN) {
+                Some(entry) => entry,
+                None => break,
+            };
+            let id = Self::read_u32(entry, 0);
+            if id == u32::MAX {
+                continue;
+            }
+            *slot = NonZeroU32::new(id).map(|id| {
+                let mut name = [0; NAME_LEN];
+                name.copy_from_slice(&entry[5..]);
+                Binding {
+                    id,
+                    name_len: entry[4],
+                    name,
+                }
+            });
+        }
+    }
+
+    /// Write the table if it changed and the buffer is available.
+    fn flush(&self) {
+        if !self.dirty.get() {
+            return;
+        }
+        let buffer = match self.buffer.take() {
+            Some(buffer) => buffer,
+            None => return,
+        };
+        let len = storage_len(N).min(buffer.len());
+        self.table.map(|table| {
+            buffer.iter_mut().for_each(|b| *b = 0);
+            buffer[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+            buffer[4..8].copy_from_slice(&table.next_id.to_le_bytes());
+            for (i, binding) in table.bindings.iter().enumerate() {
+                let offset = HEADER_LEN + i * ENTRY_LEN;
+                if let (Some(binding), Some(entry)) =
+                    (binding, buffer.get_mut(offset..offset + ENTRY_LEN))
+                {
+                    entry[0..4].copy_from_slice(&binding.id.get().to_le_bytes());
+                    entry[4] = binding.name_len;
+                    entry[5..].copy_from_slice(&binding.name);
+                }
+            }
+        });
+        self.dirty.set(false);
+        // The interface does not return the buffer on error, so the table
+        // is then not written again until the next boot.
+        let _ = self.storage.write(buffer, self.address, len);
+    }
+
+    /// The next ID counting up from `next_id` that is not bound.
+    fn free_id(table: &mut Table<N>) -> Option<NonZeroU32> {
+        for _ in 0..=N {
+            let candidate = NonZeroU32::new(table.next_id);
+            table.next_id = table.next_id.wrapping_add(1);
+            if let Some(id) = candidate {
+                if !table.bindings.iter().flatten().any(|b| b.id == id) {
+                    return Some(id);
+                }
+            }
+        }
+        None
+    }
+}
+
+impl<'a, const N: usize> ShortIdAssigner for PersistentShortIds<'a, N> {
+    fn assign(&self, process: &dyn Process, proposed: ShortID) -> ShortID {
+        let name = process.get_process_name().as_bytes();
+        let assigned = self.table.map(|table| {
+            self.load(table);
+            if let Some(binding) = table.bindings.iter().flatten().find(|b| b.matches(name)) {
+                return Some(binding.id);
+            }
+            let slot = table.bindings.iter().position(|b| b.is_none())?;
+            let id = match proposed {
+                ShortID::Fixed(id) if !table.bindings.iter().flatten().any(|b| b.id == id) => id,
+                _ => Self::free_id(table)?,
+            };
+            table.bindings[slot] = Some(Binding::new(id, name));
+            self.dirty.set(true);
+            Some(id)
+        });
+        self.flush();
+        // If the table is full, fall back to the proposed ShortID.
+        assigned.flatten().map_or(proposed, ShortID::Fixed)
+    }
+}
+
+impl<'a, const N: usize> NonvolatileStorageClient<'a> for PersistentShortIds<'a, N> {
+    fn read_done(&self, buffer: &'a mut [u8], _length: usize) {
+        self.buffer.replace(buffer);
+    }
+
+    fn write_done(&self, buffer: &'a mut [u8], _length: usize) {
+        self.buffer.replace(buffer);
+        self.flush();
+    }
+}
@@ -0,0 +1,127 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! System call driver to read the CPU time statistics the kernel keeps for
+//! each process.
+//!
+//! The kernel counts how often it switched to each process and how long
+//! each process ran, including the time it spent in the kernel handling the
+//! process's system calls. Run time is taken from the scheduler timer, so it
+//! is only measured with schedulers that give processes a timeslice.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! struct ProcessMgmtCap;
+//! unsafe impl capabilities::ProcessManagementCapability for ProcessMgmtCap {}
+//! let process_info = static_init!(
+//!     capsules_extra::process_info::ProcessInfo<ProcessMgmtCap>,
+//!     capsules_extra::process_info::ProcessInfo::new(board_kernel, ProcessMgmtCap)
+//! );
+//! ```
+//!
+//! Command Interface
+//! -----------------
+//!
+//! - `0`: Driver existence check.
+//! - `1`: CPU time of the calling process, in microseconds, as a `u64`.
+//! - `2`: Number of times the kernel switched to the calling process.
+//! - `3`: Number of processes on the board.
+//! - `4`: CPU time of the process at index `data1`, counting from 0 in the
+//!   order the kernel stores processes, in microseconds, as a `u64`.
+//!   Returns `INVAL` if there is no such process.
+//! - `5`: Number of times the kernel switched to the process at index
+//!   `data1`.
+
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::process::Process;
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::{ErrorCode, Kernel, ProcessId};
+
+/// Syscall driver number.
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::ProcessInfo as usize;
+
+pub struct ProcessInfo<C: ProcessManagementCapability> {
+    kernel: &'static Kernel,
+    capability: C,
+}
+
+impl<C: ProcessManagementCapability> ProcessInfo<C> {
+    pub fn new(kernel: &'static Kernel, capability: C) -> Self {
+        ProcessInfo { kernel, capability }
+    }
+
+    /// Run `closure` on the process at `index`.
+    fn nth_process<F, R>(&self, index: usize, closure: F) -> Option<R>
+    where
+        F: Fn(&dyn Process) -> R,
+    {
+        let mut count = 0;
+        let mut result = None;
+        self.kernel
+            .process_each_capability(&self.capability, |process| {
+                if count == index {
+                    result = Some(closure(process));
+                }
+                count += 1;
+            });
+        result
+    }
+}
+
+impl<C: ProcessManagementCapability> SyscallDriver for ProcessInfo<C> {
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        _: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+
+            1 => self.kernel.process_map_or_external(
+                CommandReturn::failure(ErrorCode::FAIL),
+                processid,
+                |process| CommandReturn::success_u64(process.debug_cpu_time_us()),
+                &self.capability,
+            ),
+
+            2 => self.kernel.process_map_or_external(
+                CommandReturn::failure(ErrorCode::FAIL),
+                processid,
+                |process| CommandReturn::success_u32(process.debug_context_switch_count() as u32),
+                &self.capability,
+            ),
+
+            3 => {
+                let mut count = 0;
+                self.kernel
+                    .process_each_capability(&self.capability, |_| count += 1);
+                CommandReturn::success_u32(count)
+            }
+
+            4 => self
+                .nth_process(data1, |process| process.debug_cpu_time_us())
+                .map_or(CommandReturn::failure(ErrorCode::INVAL), |time| {
+                    CommandReturn::success_u64(time)
+                }),
+
+            5 => self
+                .nth_process(data1, |process| process.debug_context_switch_count())
+                .map_or(CommandReturn::failure(ErrorCode::INVAL), |count| {
+                    CommandReturn::success_u32(count as u32)
+                }),
+
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, _processid: ProcessId) -> Result<(), kernel::process::Error> {
+        // All statistics are kept by the kernel.
+        Ok(())
+    }
+}
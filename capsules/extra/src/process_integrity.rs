@@ -0,0 +1,138 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! System call driver to re-verify the credentials of running processes.
+//!
+//! Processes are checked against their credentials when they are loaded, but
+//! their flash can be corrupted or tampered with afterwards. With this driver
+//! an integrity monitoring process can have the kernel check the binary of a
+//! running process against the credentials it was approved with again, and
+//! e.g. stop the process if they no longer match. One check runs at a time.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let process_integrity = static_init!(
+//!     capsules_extra::process_integrity::ProcessIntegrity<ProcessMgmtCap>,
+//!     capsules_extra::process_integrity::ProcessIntegrity::new(
+//!         board_kernel,
+//!         board_kernel.create_grant(
+//!             capsules_extra::process_integrity::DRIVER_NUM,
+//!             &grant_cap
+//!         ),
+//!         ProcessMgmtCap
+//!     )
+//! );
+//! board_kernel
+//!     .get_checker()
+//!     .set_reverification_client(process_integrity);
+//! ```
+//!
+//! Command Interface
+//! -----------------
+//!
+//! Processes are identified by their index in the processes array.
+//!
+//! - `0`: Driver existence check.
+//! - `1`: Re-verify the credentials of process `data1`. Returns `INVAL` if
+//!   there is no process at the index, `NOSUPPORT` if it runs without
+//!   credentials, `BUSY` if a check is running, and `FAIL` if its
+//!   credentials changed since it was approved.
+//!
+//! Upcall `0` is called when a check completes, with the index of the
+//! process and the result: `0` if the credentials still accept the binary,
+//! `1` if they do not, and `2` if the check failed.
+
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::process::Process;
+use kernel::process_checker::ReverificationClient;
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::OptionalCell;
+use kernel::{ErrorCode, Kernel, ProcessId};
+
+/// Syscall driver number.
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::ProcessIntegrity as usize;
+
+pub struct ProcessIntegrity<C: ProcessManagementCapability> {
+    kernel: &'static Kernel,
+    apps: Grant<(), UpcallCount<1>, AllowRoCount<0>, AllowRwCount<0>>,
+    /// The process that requested the running check, and the index of the
+    /// checked process.
+    requester: OptionalCell<(ProcessId, usize)>,
+    capability: C,
+}
+
+impl<C: ProcessManagementCapability> ProcessIntegrity<C> {
+    pub fn new(
+        kernel: &'static Kernel,
+        grant: Grant<(), UpcallCount<1>, AllowRoCount<0>, AllowRwCount<0>>,
+        capability: C,
+    ) -> Self {
+        ProcessIntegrity {
+            kernel,
+            apps: grant,
+            requester: OptionalCell::empty(),
+            capability,
+        }
+    }
+
+    fn reverify(&self, index: usize, requester: ProcessId) -> Result<(), ErrorCode> {
+        if self.requester.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        let mut processid = None;
+        self.kernel
+            .process_each_capability(&self.capability, |process: &dyn Process| {
+                if process.processid().index_external(&self.capability) == Some(index) {
+                    processid = Some(process.processid());
+                }
+            });
+        let processid = processid.ok_or(ErrorCode::INVAL)?;
+        self.kernel
+            .get_checker()
+            .reverify_credentials(processid, &self.capability)?;
+        self.requester.set((requester, index));
+        Ok(())
+    }
+}
+
+impl<C: ProcessManagementCapability> ReverificationClient for ProcessIntegrity<C> {
+    fn reverification_done(&self, _processid: ProcessId, result: Result<bool, ErrorCode>) {
+        let status = match result {
+            Ok(true) => 0,
+            Ok(false) => 1,
+            Err(_) => 2,
+        };
+        if let Some((requester, index)) = self.requester.take() {
+            let _ = self.apps.enter(requester, |_, kernel_data| {
+                let _ = kernel_data.schedule_upcall(0, (index, status, 0));
+            });
+        }
+    }
+}
+
+impl<C: ProcessManagementCapability> SyscallDriver for ProcessIntegrity<C> {
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        _: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+
+            1 => self.reverify(data1, processid).into(),
+
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
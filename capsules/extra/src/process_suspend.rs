@@ -0,0 +1,146 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! System call driver to suspend and resume processes.
+//!
+//! A suspended process is not scheduled, but upcalls and IPC for it are still
+//! queued and delivered once it is resumed. A manager process, identified by
+//! its fixed `ShortID`, can use this to quiesce other processes, e.g. noisy
+//! ones while debugging or all others during a firmware update.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let process_suspend = static_init!(
+//!     capsules_extra::process_suspend::ProcessSuspend<ProcessMgmtCap>,
+//!     capsules_extra::process_suspend::ProcessSuspend::new(
+//!         board_kernel,
+//!         ShortID::Fixed(NonZeroU32::new(0x2f7d).unwrap()),
+//!         ProcessMgmtCap
+//!     )
+//! );
+//! ```
+//!
+//! Command Interface
+//! -----------------
+//!
+//! All commands but the existence check return `NOSUPPORT` to processes
+//! other than the manager. Processes are identified by their index in the
+//! processes array, and commands return `INVAL` if there is no process at
+//! index `data1` or it is the manager itself.
+//!
+//! - `0`: Driver existence check.
+//! - `1`: Suspend process `data1`. Returns `ALREADY` if it is suspended, and
+//!   `OFF` if it is not running.
+//! - `2`: Resume process `data1`. Returns `ALREADY` if it is not suspended.
+//! - `3`: Whether process `data1` is suspended (`1`) or not (`0`).
+
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::process::{Process, ShortID, State};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::{ErrorCode, Kernel, ProcessId};
+
+/// Syscall driver number.
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::ProcessSuspend as usize;
+
+pub struct ProcessSuspend<C: ProcessManagementCapability> {
+    kernel: &'static Kernel,
+    manager: ShortID,
+    capability: C,
+}
+
+impl<C: ProcessManagementCapability> ProcessSuspend<C> {
+    pub fn new(kernel: &'static Kernel, manager: ShortID, capability: C) -> Self {
+        ProcessSuspend {
+            kernel,
+            manager,
+            capability,
+        }
+    }
+
+    fn is_manager(&self, processid: ProcessId) -> bool {
+        self.kernel.process_map_or_external(
+            false,
+            processid,
+            |process| process.short_app_id() == self.manager,
+            &self.capability,
+        )
+    }
+
+    /// Run `closure` on the process at `index` of the processes array,
+    /// unless it is the manager.
+    fn with_process<F>(&self, index: usize, closure: F) -> CommandReturn
+    where
+        F: Fn(&dyn Process) -> CommandReturn,
+    {
+        let mut result = CommandReturn::failure(ErrorCode::INVAL);
+        self.kernel
+            .process_each_capability(&self.capability, |process| {
+                if process.processid().index_external(&self.capability) == Some(index)
+                    && process.short_app_id() != self.manager
+                {
+                    result = closure(process);
+                }
+            });
+        result
+    }
+}
+
+fn is_suspended(process: &dyn Process) -> bool {
+    matches!(
+        process.get_state(),
+        State::StoppedRunning | State::StoppedYielded
+    )
+}
+
+impl<C: ProcessManagementCapability> SyscallDriver for ProcessSuspend<C> {
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        _: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        if command_num == 0 {
+            return CommandReturn::success();
+        }
+        if !self.is_manager(processid) {
+            return CommandReturn::failure(ErrorCode::NOSUPPORT);
+        }
+        match command_num {
+            1 => self.with_process(data1, |process| match process.get_state() {
+                State::Running | State::Yielded => {
+                    process.stop();
+                    CommandReturn::success()
+                }
+                State::StoppedRunning | State::StoppedYielded => {
+                    CommandReturn::failure(ErrorCode::ALREADY)
+                }
+                _ => CommandReturn::failure(ErrorCode::OFF),
+            }),
+
+            2 => self.with_process(data1, |process| {
+                if is_suspended(process) {
+                    process.resume();
+                    CommandReturn::success()
+                } else {
+                    CommandReturn::failure(ErrorCode::ALREADY)
+                }
+            }),
+
+            3 => self.with_process(data1, |process| {
+                CommandReturn::success_u32(is_suspended(process) as u32)
+            }),
+
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, _processid: ProcessId) -> Result<(), kernel::process::Error> {
+        // The driver keeps no state for processes.
+        Ok(())
+    }
+}
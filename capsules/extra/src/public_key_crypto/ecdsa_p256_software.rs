@@ -0,0 +1,743 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Software implementation of ECDSA signature verification over NIST
+//! P-256 (secp256r1).
+//!
+//! The verifier holds one public key, given as the 64-byte uncompressed
+//! point `x || y` (big endian, without the `0x04` prefix). Signatures are
+//! the 64-byte `r || s` (big endian) over a 32-byte hash, usually SHA-256.
+//!
+//! Field and scalar arithmetic use Montgomery multiplication over 32-bit
+//! words and points use Jacobian coordinates. The verification runs in
+//! chunks through the kernel work queue: one word of the exponent of an
+//! inversion, or `BITS_PER_CHUNK` bits of the scalar multiplication, per
+//! chunk. It only handles public data, so it makes no attempt to run in
+//! constant time and must not be used for signing.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let verifier = static_init!(
+//!     EcdsaP256Software<'static>,
+//!     EcdsaP256Software::new(&PUBLIC_KEY)
+//! );
+//! kernel::deferred_call::DeferredCallClient::register(verifier);
+//! ```
+
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil::public_key_crypto::signature::{ClientVerify, SignatureVerify};
+use kernel::utilities::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::work_queue::{Priority, Work, WorkClient};
+use kernel::ErrorCode;
+
+pub const HASH_LEN: usize = 32;
+pub const SIGNATURE_LEN: usize = 64;
+pub const PUBLIC_KEY_LEN: usize = 64;
+
+/// Number of bits of the scalar multiplication in one chunk of work.
+const BITS_PER_CHUNK: usize = 16;
+
+/// A 256-bit integer, least significant word first.
+type U256 = [u32; 8];
+
+const ZERO: U256 = [0; 8];
+const ONE: U256 = [1, 0, 0, 0, 0, 0, 0, 0];
+
+/// The field prime p = 2^256 - 2^224 + 2^192 + 2^96 - 1.
+const P: U256 = [
+    0xffffffff, 0xffffffff, 0xffffffff, 0x00000000, 0x00000000, 0x00000000, 0x00000001, 0xffffffff,
+];
+
+/// The group order n.
+const N: U256 = [
+    0xfc632551, 0xf3b9cac2, 0xa7179e84, 0xbce6faad, 0xffffffff, 0xffffffff, 0x00000000, 0xffffffff,
+];
+
+/// The curve coefficient b (a is -3).
+const B: U256 = [
+    0x27d2604b, 0x3bce3c3e, 0xcc53b0f6, 0x651d06b0, 0x769886bc, 0xb3ebbd55, 0xaa3a93e7, 0x5ac635d8,
+];
+
+/// The base point G.
+const GX: U256 = [
+    0xd898c296, 0xf4a13945, 0x2deb33a0, 0x77037d81, 0x63a440f2, 0xf8bce6e5, 0xe12c4247, 0x6b17d1f2,
+];
+const GY: U256 = [
+    0x37bf51f5, 0xcbb64068, 0x6b315ece, 0x2bce3357, 0x7c0f9e16, 0x8ee7eb4a, 0xfe1a7f9b, 0x4fe342e2,
+];
+
+fn from_be_bytes(bytes: &[u8]) -> U256 {
+    let mut out = ZERO;
+    for (i, word) in out.iter_mut().enumerate() {
+        let start = 28 - 4 * i;
+        *word = u32::from_be_bytes([
+            bytes[start],
+            bytes[start + 1],
+            bytes[start + 2],
+            bytes[start + 3],
+        ]);
+    }
+    out
+}
+
+fn is_zero(a: &U256) -> bool {
+    a.iter().all(|w| *w == 0)
+}
+
+/// Whether `a >= b`.
+fn greater_equal(a: &U256, b: &U256) -> bool {
+    for (x, y) in a.iter().zip(b.iter()).rev() {
+        if x != y {
+            return x > y;
+        }
+    }
+    true
+}
+
+/// `a + b`, returning the carry.
+fn add(a: &U256, b: &U256) -> (U256, bool) {
+    let mut out = ZERO;
+    let mut carry = 0;
+    for i in 0..8 {
+        let sum = a[i] as u64 + b[i] as u64 + carry;
+        out[i] = sum as u32;
+        carry = sum >> 32;
+    }
+    (out, carry != 0)
+}
+
+/// `a - b`, returning the borrow.
+fn sub(a: &U256, b: &U256) -> (U256, bool) {
+    let mut out = ZERO;
+    let mut borrow = 0;
+    for i in 0..8 {
+        let diff = (a[i] as u64).wrapping_sub(b[i] as u64).wrapping_sub(borrow);
+        out[i] = diff as u32;
+        borrow = (diff >> 63) & 1;
+    }
+    (out, borrow != 0)
+}
+
+/// Arithmetic modulo an odd 256-bit prime, with values kept in
+/// Montgomery form (`a * 2^256 mod m`).
+struct Modulus {
+    m: U256,
+    /// `-m^-1 mod 2^32`
+    m_inv: u32,
+    /// `2^512 mod m`
+    r2: U256,
+}
+
+impl Modulus {
+    fn new(m: U256) -> Modulus {
+        let mut m_inv = m[0];
+        for _ in 0..4 {
+            m_inv = m_inv.wrapping_mul(2u32.wrapping_sub(m[0].wrapping_mul(m_inv)));
+        }
+        let mut modulus = Modulus {
+            m,
+            m_inv: m_inv.wrapping_neg(),
+            r2: ONE,
+        };
+        let mut r2 = ONE;
+        for _ in 0..512 {
+            r2 = modulus.add(&r2, &r2);
+        }
+        modulus.r2 = r2;
+        modulus
+    }
+
+    fn add(&self, a: &U256, b: &U256) -> U256 {
+        let (sum, carry) = add(a, b);
+        if carry || greater_equal(&sum, &self.m) {
+            sub(&sum, &self.m).0
+        } else {
+            sum
+        }
+    }
+
+    fn sub(&self, a: &U256, b: &U256) -> U256 {
+        let (diff, borrow) = sub(a, b);
+        if borrow {
+            add(&diff, &self.m).0
+        } else {
+            diff
+        }
+    }
+
+    /// `a * b / 2^256 mod m`
+    fn mul(&self, a: &U256, b: &U256) -> U256 {
+        let mut out = ZERO;
+        let mut top: u32 = 0;
+        for b_i in b.iter() {
+            let mut carry: u64 = 0;
+            for j in 0..8 {
+                let t = out[j] as u64 + (a[j] as u64) * (*b_i as u64) + carry;
+                out[j] = t as u32;
+                carry = t >> 32;
+            }
+            let t = top as u64 + carry;
+            top = t as u32;
+            let overflow = (t >> 32) as u32;
+
+            let k = out[0].wrapping_mul(self.m_inv);
+            let t = out[0] as u64 + (k as u64) * (self.m[0] as u64);
+            let mut carry = t >> 32;
+            for j in 1..8 {
+                let t = out[j] as u64 + (k as u64) * (self.m[j] as u64) + carry;
+                out[j - 1] = t as u32;
+                carry = t >> 32;
+            }
+            let t = top as u64 + carry;
+            out[7] = t as u32;
+            top = overflow + (t >> 32) as u32;
+        }
+        if top != 0 || greater_equal(&out, &self.m) {
+            sub(&out, &self.m).0
+        } else {
+            out
+        }
+    }
+
+    fn to_mont(&self, a: &U256) -> U256 {
+        self.mul(a, &self.r2)
+    }
+
+    fn out_of_mont(&self, a: &U256) -> U256 {
+        self.mul(a, &ONE)
+    }
+}
+
+/// The inverse of `a` (in Montgomery form) as `a^(m-2)`, computed one word
+/// of the exponent at a time.
+#[derive(Clone, Copy)]
+struct Inversion {
+    a: U256,
+    acc: U256,
+    /// Number of words of the exponent done, most significant first.
+    words: usize,
+}
+
+impl Inversion {
+    fn new(m: &Modulus, a: &U256) -> Inversion {
+        Inversion {
+            a: *a,
+            acc: m.to_mont(&ONE),
+            words: 0,
+        }
+    }
+
+    /// Square and multiply for the next word of the exponent. Returns the
+    /// inverse after the last word.
+    fn step(&mut self, m: &Modulus) -> Option<U256> {
+        let exponent = sub(&m.m, &[2, 0, 0, 0, 0, 0, 0, 0]).0;
+        let word = exponent[7 - self.words];
+        for bit in (0..32).rev() {
+            self.acc = m.mul(&self.acc, &self.acc);
+            if (word >> bit) & 1 == 1 {
+                self.acc = m.mul(&self.acc, &self.a);
+            }
+        }
+        self.words += 1;
+        if self.words == 8 {
+            Some(self.acc)
+        } else {
+            None
+        }
+    }
+}
+
+/// A point in Jacobian coordinates, in Montgomery form. `z == 0` is the
+/// point at infinity.
+#[derive(Clone, Copy)]
+struct Point {
+    x: U256,
+    y: U256,
+    z: U256,
+}
+
+struct Curve {
+    p: Modulus,
+    n: Modulus,
+}
+
+impl Curve {
+    fn new() -> Curve {
+        Curve {
+            p: Modulus::new(P),
+            n: Modulus::new(N),
+        }
+    }
+
+    /// Convert affine coordinates, checking that the point is on the
+    /// curve: `y^2 = x^3 - 3x + b`.
+    fn point(&self, x: &U256, y: &U256) -> Option<Point> {
+        if greater_equal(x, &P) || greater_equal(y, &P) {
+            return None;
+        }
+        let f = &self.p;
+        let x = f.to_mont(x);
+        let y = f.to_mont(y);
+        let x3 = f.mul(&f.mul(&x, &x), &x);
+        let three_x = f.add(&f.add(&x, &x), &x);
+        let rhs = f.add(&f.sub(&x3, &three_x), &f.to_mont(&B));
+        if f.mul(&y, &y) != rhs {
+            return None;
+        }
+        Some(Point {
+            x,
+            y,
+            z: f.to_mont(&ONE),
+        })
+    }
+
+    fn double(&self, a: &Point) -> Point {
+        let f = &self.p;
+        // dbl-2001-b, for a = -3.
+        let delta = f.mul(&a.z, &a.z);
+        let gamma = f.mul(&a.y, &a.y);
+        let beta = f.mul(&a.x, &gamma);
+        let t = f.mul(&f.sub(&a.x, &delta), &f.add(&a.x, &delta));
+        let alpha = f.add(&f.add(&t, &t), &t);
+        let beta2 = f.add(&beta, &beta);
+        let beta4 = f.add(&beta2, &beta2);
+        let beta8 = f.add(&beta4, &beta4);
+        let x = f.sub(&f.mul(&alpha, &alpha), &beta8);
+        let yz = f.add(&a.y, &a.z);
+        let z = f.sub(&f.sub(&f.mul(&yz, &yz), &gamma), &delta);
+        let gamma2 = f.mul(&gamma, &gamma);
+        let gamma2_2 = f.add(&gamma2, &gamma2);
+        let gamma2_4 = f.add(&gamma2_2, &gamma2_2);
+        let gamma2_8 = f.add(&gamma2_4, &gamma2_4);
+        let y = f.sub(&f.mul(&alpha, &f.sub(&beta4, &x)), &gamma2_8);
+        Point { x, y, z }
+    }
+
+    fn add(&self, a: &Point, b: &Point) -> Point {
+        if is_zero(&a.z) {
+            return *b;
+        }
+        if is_zero(&b.z) {
+            return *a;
+        }
+        let f = &self.p;
+        // add-2007-bl
+        let z1z1 = f.mul(&a.z, &a.z);
+        let z2z2 = f.mul(&b.z, &b.z);
+        let u1 = f.mul(&a.x, &z2z2);
+        let u2 = f.mul(&b.x, &z1z1);
+        let s1 = f.mul(&f.mul(&a.y, &b.z), &z2z2);
+        let s2 = f.mul(&f.mul(&b.y, &a.z), &z1z1);
+        let h = f.sub(&u2, &u1);
+        let s = f.sub(&s2, &s1);
+        if is_zero(&h) {
+            return if is_zero(&s) {
+                self.double(a)
+            } else {
+                Point {
+                    x: ZERO,
+                    y: ZERO,
+                    z: ZERO,
+                }
+            };
+        }
+        let r = f.add(&s, &s);
+        let h2 = f.add(&h, &h);
+        let i = f.mul(&h2, &h2);
+        let j = f.mul(&h, &i);
+        let v = f.mul(&u1, &i);
+        let x = f.sub(&f.sub(&f.mul(&r, &r), &j), &f.add(&v, &v));
+        let s1j = f.mul(&s1, &j);
+        let y = f.sub(&f.mul(&r, &f.sub(&v, &x)), &f.add(&s1j, &s1j));
+        let zz = f.add(&a.z, &b.z);
+        let z = f.mul(&f.sub(&f.sub(&f.mul(&zz, &zz), &z1z1), &z2z2), &h);
+        Point { x, y, z }
+    }
+}
+
+/// The progress of a verification.
+#[derive(Clone, Copy)]
+enum Step {
+    /// Inverting s modulo n.
+    InvertS(Inversion),
+    /// Computing `u1 * G + u2 * Q` with Shamir's trick, from the most
+    /// significant bit down, with this many bits left.
+    Multiply(usize),
+    /// Inverting z of `u1 * G + u2 * Q` modulo p.
+    InvertZ(Inversion),
+    /// The verification is done.
+    Done(bool),
+}
+
+/// A signature verification, run in chunks of work.
+struct Verification {
+    curve: Curve,
+    q: Point,
+    g: Point,
+    /// `G + Q`
+    gq: Point,
+    e: U256,
+    r: U256,
+    u1: U256,
+    u2: U256,
+    acc: Point,
+    step: Step,
+}
+
+impl Verification {
+    /// Start verifying the signature `r || s` of `hash` with the public key
+    /// `x || y`. Returns `INVAL` if the public key is not on the curve.
+    fn new(
+        public_key: &[u8; PUBLIC_KEY_LEN],
+        hash: &[u8; HASH_LEN],
+        signature: &[u8; SIGNATURE_LEN],
+    ) -> Result<Verification, ErrorCode> {
+        let curve = Curve::new();
+        let q = curve
+            .point(
+                &from_be_bytes(&public_key[..32]),
+                &from_be_bytes(&public_key[32..]),
+            )
+            .ok_or(ErrorCode::INVAL)?;
+        let g = curve.point(&GX, &GY).ok_or(ErrorCode::FAIL)?;
+        let gq = curve.add(&g, &q);
+
+        let r = from_be_bytes(&signature[..32]);
+        let s = from_be_bytes(&signature[32..]);
+        let mut e = from_be_bytes(hash);
+        if greater_equal(&e, &N) {
+            e = sub(&e, &N).0;
+        }
+        let step = if is_zero(&r) || is_zero(&s) || greater_equal(&r, &N) || greater_equal(&s, &N) {
+            Step::Done(false)
+        } else {
+            Step::InvertS(Inversion::new(&curve.n, &curve.n.to_mont(&s)))
+        };
+        Ok(Verification {
+            curve,
+            q,
+            g,
+            gq,
+            e,
+            r,
+            u1: ZERO,
+            u2: ZERO,
+            acc: Point {
+                x: ZERO,
+                y: ZERO,
+                z: ZERO,
+            },
+            step,
+        })
+    }
+
+    /// Do the next chunk of the verification. Returns whether the signature
+    /// is valid once it is done.
+    fn run_chunk(&mut self) -> Option<bool> {
+        let curve = &self.curve;
+        self.step = match self.step {
+            Step::InvertS(mut inversion) => match inversion.step(&curve.n) {
+                Some(w) => {
+                    // Multiplying a plain value by a Montgomery one gives a
+                    // plain one.
+                    self.u1 = curve.n.mul(&self.e, &w);
+                    self.u2 = curve.n.mul(&self.r, &w);
+                    Step::Multiply(256)
+                }
+                None => Step::InvertS(inversion),
+            },
+            Step::Multiply(bits) => {
+                let end = bits.saturating_sub(BITS_PER_CHUNK);
+                for i in (end..bits).rev() {
+                    self.acc = curve.double(&self.acc);
+                    let bit1 = (self.u1[i / 32] >> (i % 32)) & 1 == 1;
+                    let bit2 = (self.u2[i / 32] >> (i % 32)) & 1 == 1;
+                    self.acc = match (bit1, bit2) {
+                        (true, true) => curve.add(&self.acc, &self.gq),
+                        (true, false) => curve.add(&self.acc, &self.g),
+                        (false, true) => curve.add(&self.acc, &self.q),
+                        (false, false) => self.acc,
+                    };
+                }
+                if end > 0 {
+                    Step::Multiply(end)
+                } else if is_zero(&self.acc.z) {
+                    Step::Done(false)
+                } else {
+                    Step::InvertZ(Inversion::new(&curve.p, &self.acc.z))
+                }
+            }
+            Step::InvertZ(mut inversion) => match inversion.step(&curve.p) {
+                Some(z_inv) => {
+                    let f = &curve.p;
+                    let mut x = f.out_of_mont(&f.mul(&self.acc.x, &f.mul(&z_inv, &z_inv)));
+                    if greater_equal(&x, &N) {
+                        x = sub(&x, &N).0;
+                    }
+                    Step::Done(x == self.r)
+                }
+                None => Step::InvertZ(inversion),
+            },
+            Step::Done(valid) => Step::Done(valid),
+        };
+        match self.step {
+            Step::Done(valid) => Some(valid),
+            _ => None,
+        }
+    }
+}
+
+pub struct EcdsaP256Software<'a> {
+    public_key: &'static [u8; PUBLIC_KEY_LEN],
+    client: OptionalCell<&'a dyn ClientVerify<HASH_LEN, SIGNATURE_LEN>>,
+    deferred_call: DeferredCall,
+    work: Work,
+    verification: MapCell<Verification>,
+    result: OptionalCell<Result<bool, ErrorCode>>,
+    hash: TakeCell<'static, [u8; HASH_LEN]>,
+    signature: TakeCell<'static, [u8; SIGNATURE_LEN]>,
+}
+
+impl<'a> EcdsaP256Software<'a> {
+    /// `public_key` is the uncompressed point `x || y`, big endian.
+    pub fn new(public_key: &'static [u8; PUBLIC_KEY_LEN]) -> EcdsaP256Software<'a> {
+        EcdsaP256Software {
+            public_key,
+            client: OptionalCell::empty(),
+            deferred_call: DeferredCall::new(),
+            work: Work::new(Priority::Normal),
+            verification: MapCell::empty(),
+            result: OptionalCell::empty(),
+            hash: TakeCell::empty(),
+            signature: TakeCell::empty(),
+        }
+    }
+
+    /// Start the verification of the stored hash and signature.
+    fn start(&self) -> Result<Verification, ErrorCode> {
+        self.hash
+            .map(|hash| {
+                self.signature
+                    .map(|signature| Verification::new(self.public_key, hash, signature))
+            })
+            .flatten()
+            .unwrap_or(Err(ErrorCode::FAIL))
+    }
+}
+
+impl<'a> SignatureVerify<'a, HASH_LEN, SIGNATURE_LEN> for EcdsaP256Software<'a> {
+    fn set_verify_client(&'a self, client: &'a dyn ClientVerify<HASH_LEN, SIGNATURE_LEN>) {
+        self.client.set(client);
+    }
+
+    fn verify(
+        &'a self,
+        hash: &'static mut [u8; HASH_LEN],
+        signature: &'static mut [u8; SIGNATURE_LEN],
+    ) -> Result<
+        (),
+        (
+            ErrorCode,
+            &'static mut [u8; HASH_LEN],
+            &'static mut [u8; SIGNATURE_LEN],
+        ),
+    > {
+        if self.hash.is_some() {
+            return Err((ErrorCode::BUSY, hash, signature));
+        }
+        self.hash.replace(hash);
+        self.signature.replace(signature);
+        self.work.schedule();
+        Ok(())
+    }
+}
+
+impl<'a> DeferredCallClient for EcdsaP256Software<'a> {
+    fn handle_deferred_call(&self) {
+        if let (Some(hash), Some(signature)) = (self.hash.take(), self.signature.take()) {
+            let result = self.result.take().unwrap_or(Err(ErrorCode::FAIL));
+            self.client.map(|client| {
+                client.verification_done(result, hash, signature);
+            });
+        }
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+        self.work.register(self);
+    }
+}
+
+impl<'a> WorkClient for EcdsaP256Software<'a> {
+    fn run_work(&self) {
+        if self.verification.is_none() {
+            // The first chunk checks the public key and the signature.
+            match self.start() {
+                Ok(verification) => {
+                    self.verification.replace(verification);
+                    self.work.schedule();
+                }
+                Err(e) => {
+                    self.result.set(Err(e));
+                    self.deferred_call.set();
+                }
+            }
+            return;
+        }
+        match self
+            .verification
+            .map(|verification| verification.run_chunk())
+        {
+            Some(Some(valid)) => {
+                self.verification.take();
+                self.result.set(Ok(valid));
+                self.deferred_call.set();
+            }
+            _ => self.work.schedule(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sha_software::from_hex;
+
+    /// The P-256 public key of RFC 6979, appendix A.2.5.
+    static PUBLIC_KEY: [u8; PUBLIC_KEY_LEN] = [
+        0x60, 0xfe, 0xd4, 0xba, 0x25, 0x5a, 0x9d, 0x31, 0xc9, 0x61, 0xeb, 0x74, 0xc6, 0x35, 0x6d,
+        0x68, 0xc0, 0x49, 0xb8, 0x92, 0x3b, 0x61, 0xfa, 0x6c, 0xe6, 0x69, 0x62, 0x2e, 0x60, 0xf2,
+        0x9f, 0xb6, 0x79, 0x03, 0xfe, 0x10, 0x08, 0xb8, 0xbc, 0x99, 0xa4, 0x1a, 0xe9, 0xe9, 0x56,
+        0x28, 0xbc, 0x64, 0xf2, 0xf1, 0xb2, 0x0c, 0x2d, 0x7e, 0x9f, 0x51, 0x77, 0xa3, 0xc2, 0x94,
+        0xd4, 0x46, 0x22, 0x99,
+    ];
+
+    /// The public key with the last bit of y flipped, which is not on the
+    /// curve.
+    static OFF_CURVE_KEY: [u8; PUBLIC_KEY_LEN] = {
+        let mut key = PUBLIC_KEY;
+        key[63] ^= 1;
+        key
+    };
+
+    /// SHA-256("sample") and SHA-256("test").
+    const SAMPLE: &str = "af2bdbe1aa9b6ec1e2ade1d694f41fc71a831d0268e9891562113d8a62add1bf";
+    const TEST: &str = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+
+    /// The signatures of RFC 6979, appendix A.2.5, with SHA-256.
+    const SAMPLE_SIGNATURE: &str = "efd48b2aacb6a8fd1140dd9cd45e81d69d2c877b56aaf991c34d0ea84eaf3716\
+                                    f7cb1c942d657c41d436c7a1b6e29f65f3e900dbb9aff4064dc4ab2f843acda8";
+    const TEST_SIGNATURE: &str = "f1abb023518351cd71d881567b1ea663ed3efcf6c5132b354f28d3b0b7d38367\
+                                  019f4113742a2b14bd25926b49c649155f267e60d3814b4c0cc84250e46f0083";
+
+    /// Verify the way the work queue would, chunk by chunk.
+    fn verify_signature(
+        public_key: &[u8; PUBLIC_KEY_LEN],
+        hash: &[u8; HASH_LEN],
+        signature: &[u8; SIGNATURE_LEN],
+    ) -> Result<bool, ErrorCode> {
+        let mut verification = Verification::new(public_key, hash, signature)?;
+        loop {
+            if let Some(valid) = verification.run_chunk() {
+                return Ok(valid);
+            }
+        }
+    }
+
+    /// The group order n, big endian.
+    const ORDER: &str = "ffffffff00000000ffffffffffffffffbce6faada7179e84f3b9cac2fc632551";
+
+    fn verify(
+        public_key: &'static [u8; PUBLIC_KEY_LEN],
+        hash: &str,
+        signature: &str,
+    ) -> Result<bool, ErrorCode> {
+        verify_signature(public_key, &from_hex(hash), &from_hex(signature))
+    }
+
+    /// `signature` with `r` or `s` replaced by `value`.
+    fn replace(signature: &str, s: bool, value: &[u8; 32]) -> [u8; SIGNATURE_LEN] {
+        let mut signature: [u8; SIGNATURE_LEN] = from_hex(signature);
+        let start = if s { 32 } else { 0 };
+        signature[start..start + 32].copy_from_slice(value);
+        signature
+    }
+
+    fn verify_bytes(hash: &str, signature: &[u8; SIGNATURE_LEN]) -> Result<bool, ErrorCode> {
+        verify_signature(&PUBLIC_KEY, &from_hex(hash), signature)
+    }
+
+    #[test]
+    fn accepts_rfc6979_signatures() {
+        assert_eq!(verify(&PUBLIC_KEY, SAMPLE, SAMPLE_SIGNATURE), Ok(true));
+        assert_eq!(verify(&PUBLIC_KEY, TEST, TEST_SIGNATURE), Ok(true));
+    }
+
+    #[test]
+    fn accepts_hash_above_order() {
+        // A hash of all ones is reduced modulo n. Signed with the private
+        // key of RFC 6979 by OpenSSL.
+        assert_eq!(
+            verify(
+                &PUBLIC_KEY,
+                "ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff",
+                "b7c4177542ee16845a19ca3f1685b329728bec59dc4bb6242da3c47abc29a978\
+                 d44e1590d5ab59f0e3878c151b543db0b53250426cfa287b8ea8873ae08c72ff"
+            ),
+            Ok(true)
+        );
+    }
+
+    #[test]
+    fn rejects_other_message_or_signature() {
+        assert_eq!(verify(&PUBLIC_KEY, TEST, SAMPLE_SIGNATURE), Ok(false));
+        assert_eq!(verify(&PUBLIC_KEY, SAMPLE, TEST_SIGNATURE), Ok(false));
+        let mut signature: [u8; SIGNATURE_LEN] = from_hex(SAMPLE_SIGNATURE);
+        signature[10] ^= 0x40;
+        assert_eq!(verify_bytes(SAMPLE, &signature), Ok(false));
+    }
+
+    #[test]
+    fn rejects_zero_r_or_s() {
+        assert_eq!(
+            verify_bytes(SAMPLE, &replace(SAMPLE_SIGNATURE, false, &[0; 32])),
+            Ok(false)
+        );
+        assert_eq!(
+            verify_bytes(SAMPLE, &replace(SAMPLE_SIGNATURE, true, &[0; 32])),
+            Ok(false)
+        );
+    }
+
+    #[test]
+    fn rejects_r_or_s_not_below_order() {
+        let order: [u8; 32] = from_hex(ORDER);
+        assert_eq!(
+            verify_bytes(SAMPLE, &replace(SAMPLE_SIGNATURE, false, &order)),
+            Ok(false)
+        );
+        assert_eq!(
+            verify_bytes(SAMPLE, &replace(SAMPLE_SIGNATURE, true, &order)),
+            Ok(false)
+        );
+        assert_eq!(
+            verify_bytes(SAMPLE, &replace(SAMPLE_SIGNATURE, false, &[0xff; 32])),
+            Ok(false)
+        );
+        assert_eq!(
+            verify_bytes(SAMPLE, &replace(SAMPLE_SIGNATURE, true, &[0xff; 32])),
+            Ok(false)
+        );
+    }
+
+    #[test]
+    fn rejects_public_key_not_on_curve() {
+        assert_eq!(
+            verify(&OFF_CURVE_KEY, SAMPLE, SAMPLE_SIGNATURE),
+            Err(ErrorCode::INVAL)
+        );
+    }
+}
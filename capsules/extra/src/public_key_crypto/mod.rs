@@ -4,4 +4,6 @@
 
 //! Provides capsules for asymmetric encryption
 
+pub mod ecdsa_p256_software;
 pub mod rsa_keys;
+pub mod rsa_software;
@@ -0,0 +1,489 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Software implementation of the RSA modular exponentiation HIL.
+//!
+//! This is a fallback for chips without an RSA accelerator. It uses
+//! Montgomery multiplication over 32-bit words. The exponentiation runs in
+//! chunks through the kernel work queue, each of at most two Montgomery
+//! multiplications (one bit of the exponent) or `DOUBLINGS_PER_CHUNK`
+//! doublings, so it does not hold up interrupts and deferred calls. Verifying
+//! a signature with a small public exponent (e.g. 65537) takes few chunks,
+//! but exponentiating with a private exponent takes a long time on a
+//! microcontroller.
+//!
+//! `W` is the largest supported modulus in 32-bit words; the working
+//! state takes `4 * W` words of RAM.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let rsa = static_init!(Rsa3072Software<'static>, Rsa3072Software::new());
+//! kernel::deferred_call::DeferredCallClient::register(rsa);
+//! ```
+
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil::public_key_crypto::rsa_math::{Client, RsaCryptoBase};
+use kernel::utilities::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::work_queue::{Priority, Work, WorkClient};
+use kernel::ErrorCode;
+
+pub type Rsa2048Software<'a> = RsaSoftware<'a, 64>;
+pub type Rsa3072Software<'a> = RsaSoftware<'a, 96>;
+pub type Rsa4096Software<'a> = RsaSoftware<'a, 128>;
+
+/// Number of doublings towards `R^2 mod n` in one chunk of work.
+const DOUBLINGS_PER_CHUNK: usize = 128;
+
+/// The progress of an exponentiation.
+#[derive(Clone, Copy, PartialEq)]
+enum Step {
+    /// Nothing to do.
+    Idle,
+    /// About to load the modulus.
+    Start,
+    /// Computing `R^2 mod n` by doubling 1, after this many doublings.
+    RSquared(usize),
+    /// Square and multiply for this bit of the exponent, most significant
+    /// first.
+    Exponent(usize),
+}
+
+/// Little endian multi-word integers and the progress of an operation.
+struct Scratch<const W: usize> {
+    modulus: [u32; W],
+    base: [u32; W],
+    acc: [u32; W],
+    product: [u32; W],
+    /// `-n^-1 mod 2^32`
+    n_inv: u32,
+    /// Whether the most significant set bit of the exponent was reached.
+    started: bool,
+    step: Step,
+}
+
+pub struct RsaSoftware<'a, const W: usize> {
+    client: OptionalCell<&'a dyn Client<'a>>,
+    deferred_call: DeferredCall,
+    work: Work,
+    scratch: MapCell<Scratch<W>>,
+
+    message: TakeCell<'static, [u8]>,
+    modulus: OptionalCell<&'static [u8]>,
+    exponent: OptionalCell<&'static [u8]>,
+    result: TakeCell<'static, [u8]>,
+}
+
+impl<'a, const W: usize> RsaSoftware<'a, W> {
+    pub fn new() -> Self {
+        Self {
+            client: OptionalCell::empty(),
+            deferred_call: DeferredCall::new(),
+            work: Work::new(Priority::Normal),
+            scratch: MapCell::new(Scratch {
+                modulus: [0; W],
+                base: [0; W],
+                acc: [0; W],
+                product: [0; W],
+                n_inv: 0,
+                started: false,
+                step: Step::Idle,
+            }),
+            message: TakeCell::empty(),
+            modulus: OptionalCell::empty(),
+            exponent: OptionalCell::empty(),
+            result: TakeCell::empty(),
+        }
+    }
+
+    fn busy(&self) -> bool {
+        self.result.is_some() || self.scratch.map_or(false, |s| s.step != Step::Idle)
+    }
+
+    /// Load the first `bytes.len()` big endian bytes into `words`,
+    /// little endian word first.
+    fn load(words: &mut [u32], bytes: &[u8]) {
+        words.iter_mut().for_each(|w| *w = 0);
+        for (i, byte) in bytes.iter().rev().enumerate() {
+            words[i / 4] |= (*byte as u32) << (8 * (i % 4));
+        }
+    }
+
+    /// Store `words` as `bytes.len()` big endian bytes.
+    fn store(words: &[u32], bytes: &mut [u8]) {
+        let len = bytes.len();
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            let index = len - 1 - i;
+            *byte = (words[index / 4] >> (8 * (index % 4))) as u8;
+        }
+    }
+
+    /// Whether `a >= b`.
+    fn greater_equal(a: &[u32], b: &[u32]) -> bool {
+        for (x, y) in a.iter().zip(b.iter()).rev() {
+            if x != y {
+                return x > y;
+            }
+        }
+        true
+    }
+
+    /// `a -= b`, returning the borrow.
+    fn subtract(a: &mut [u32], b: &[u32]) -> bool {
+        let mut borrow = 0;
+        for (x, y) in a.iter_mut().zip(b.iter()) {
+            let diff = (*x as u64).wrapping_sub(*y as u64).wrapping_sub(borrow);
+            *x = diff as u32;
+            borrow = (diff >> 63) & 1;
+        }
+        borrow != 0
+    }
+
+    /// `-n^-1 mod 2^32` for odd `n`, by Newton iteration.
+    fn inverse_word(n: u32) -> u32 {
+        let mut x = n;
+        for _ in 0..4 {
+            x = x.wrapping_mul(2u32.wrapping_sub(n.wrapping_mul(x)));
+        }
+        x.wrapping_neg()
+    }
+
+    /// Montgomery multiplication: `out = a * b / 2^(32 * len) mod n`,
+    /// where `a, b < n`.
+    fn mont_mul(out: &mut [u32], a: &[u32], b: &[u32], n: &[u32], n_inv: u32) {
+        let len = n.len();
+        out.iter_mut().for_each(|w| *w = 0);
+        let mut top: u32 = 0;
+        for b_i in b.iter().take(len) {
+            let mut carry: u64 = 0;
+            for j in 0..len {
+                let t = out[j] as u64 + (a[j] as u64) * (*b_i as u64) + carry;
+                out[j] = t as u32;
+                carry = t >> 32;
+            }
+            let t = top as u64 + carry;
+            top = t as u32;
+            let overflow = (t >> 32) as u32;
+
+            let m = out[0].wrapping_mul(n_inv);
+            let t = out[0] as u64 + (m as u64) * (n[0] as u64);
+            let mut carry = t >> 32;
+            for j in 1..len {
+                let t = out[j] as u64 + (m as u64) * (n[j] as u64) + carry;
+                out[j - 1] = t as u32;
+                carry = t >> 32;
+            }
+            let t = top as u64 + carry;
+            out[len - 1] = t as u32;
+            top = overflow + (t >> 32) as u32;
+        }
+        if top != 0 || Self::greater_equal(out, n) {
+            Self::subtract(out, n);
+        }
+    }
+
+    /// Do the next chunk of computing `message ^ exponent mod modulus`.
+    /// Returns whether the result is in `result`.
+    fn exponentiate_chunk(
+        scratch: &mut Scratch<W>,
+        message: &[u8],
+        modulus: &[u8],
+        exponent: &[u8],
+        result: &mut [u8],
+    ) -> bool {
+        let op_len = modulus.len();
+        let len = (op_len + 3) / 4;
+        let n_inv = scratch.n_inv;
+        let n = &mut scratch.modulus[..len];
+        let base = &mut scratch.base[..len];
+        let acc = &mut scratch.acc[..len];
+        let product = &mut scratch.product[..len];
+        let exponent_bits = 8 * exponent.len().min(op_len);
+
+        match scratch.step {
+            Step::Idle => return true,
+            Step::Start => {
+                Self::load(n, modulus);
+                scratch.n_inv = Self::inverse_word(n[0]);
+                acc.iter_mut().for_each(|w| *w = 0);
+                acc[0] = 1;
+                scratch.started = false;
+                scratch.step = Step::RSquared(0);
+            }
+            Step::RSquared(done) => {
+                // R^2 mod n, where R = 2^(32 * len), by doubling 1.
+                let end = (done + DOUBLINGS_PER_CHUNK).min(64 * len);
+                for _ in done..end {
+                    let mut carry = 0;
+                    for w in acc.iter_mut() {
+                        let next = *w >> 31;
+                        *w = (*w << 1) | carry;
+                        carry = next;
+                    }
+                    if carry != 0 || Self::greater_equal(acc, n) {
+                        Self::subtract(acc, n);
+                    }
+                }
+                scratch.step = Step::RSquared(end);
+                if end == 64 * len {
+                    // The message into Montgomery form. Reduce it first so
+                    // that it is less than the modulus.
+                    Self::load(base, &message[..op_len.min(message.len())]);
+                    while Self::greater_equal(base, n) {
+                        Self::subtract(base, n);
+                    }
+                    Self::mont_mul(product, base, acc, n, n_inv);
+                    base.copy_from_slice(product);
+                    scratch.step = Step::Exponent(0);
+                }
+            }
+            Step::Exponent(bit) if bit < exponent_bits => {
+                // Left to right square and multiply, starting from the most
+                // significant set bit of the exponent.
+                if scratch.started {
+                    Self::mont_mul(product, acc, acc, n, n_inv);
+                    acc.copy_from_slice(product);
+                }
+                if (exponent[bit / 8] >> (7 - bit % 8)) & 1 == 1 {
+                    if scratch.started {
+                        Self::mont_mul(product, acc, base, n, n_inv);
+                        acc.copy_from_slice(product);
+                    } else {
+                        acc.copy_from_slice(base);
+                        scratch.started = true;
+                    }
+                }
+                scratch.step = Step::Exponent(bit + 1);
+            }
+            Step::Exponent(_) => {
+                if scratch.started {
+                    // Out of Montgomery form.
+                    base.iter_mut().for_each(|w| *w = 0);
+                    base[0] = 1;
+                    Self::mont_mul(product, acc, base, n, n_inv);
+                } else {
+                    // A zero exponent: the result is 1.
+                    product.iter_mut().for_each(|w| *w = 0);
+                    product[0] = 1;
+                }
+                Self::store(product, &mut result[..op_len]);
+                scratch.step = Step::Idle;
+                return true;
+            }
+        }
+        false
+    }
+}
+
+impl<'a, const W: usize> RsaCryptoBase<'a> for RsaSoftware<'a, W> {
+    fn set_client(&'a self, client: &'a dyn Client<'a>) {
+        self.client.set(client);
+    }
+
+    fn clear_data(&self) {
+        self.scratch.map(|scratch| {
+            scratch.modulus = [0; W];
+            scratch.base = [0; W];
+            scratch.acc = [0; W];
+            scratch.product = [0; W];
+        });
+    }
+
+    fn mod_exponent(
+        &self,
+        message: &'static mut [u8],
+        modulus: &'static [u8],
+        exponent: &'static [u8],
+        result: &'static mut [u8],
+    ) -> Result<
+        (),
+        (
+            ErrorCode,
+            &'static mut [u8],
+            &'static [u8],
+            &'static [u8],
+            &'static mut [u8],
+        ),
+    > {
+        if self.busy() {
+            return Err((ErrorCode::BUSY, message, modulus, exponent, result));
+        }
+        if modulus.is_empty() || modulus.len() > 4 * W || modulus[modulus.len() - 1] & 1 == 0 {
+            // Montgomery multiplication needs an odd modulus, which any
+            // RSA modulus is.
+            return Err((ErrorCode::INVAL, message, modulus, exponent, result));
+        }
+        if result.len() < modulus.len() {
+            return Err((ErrorCode::SIZE, message, modulus, exponent, result));
+        }
+
+        self.scratch.map(|scratch| scratch.step = Step::Start);
+        self.message.replace(message);
+        self.modulus.set(modulus);
+        self.exponent.set(exponent);
+        self.result.replace(result);
+        self.work.schedule();
+        Ok(())
+    }
+}
+
+impl<'a, const W: usize> WorkClient for RsaSoftware<'a, W> {
+    fn run_work(&self) {
+        let done = match (
+            self.message.take(),
+            self.modulus.extract(),
+            self.exponent.extract(),
+            self.result.take(),
+        ) {
+            (Some(message), Some(modulus), Some(exponent), Some(result)) => {
+                let done = self.scratch.map_or(true, |scratch| {
+                    Self::exponentiate_chunk(scratch, message, modulus, exponent, result)
+                });
+                self.message.replace(message);
+                self.result.replace(result);
+                done
+            }
+            (message, _, _, result) => {
+                message.map(|m| self.message.replace(m));
+                result.map(|r| self.result.replace(r));
+                true
+            }
+        };
+        if done {
+            self.deferred_call.set();
+        } else {
+            self.work.schedule();
+        }
+    }
+}
+
+impl<'a, const W: usize> DeferredCallClient for RsaSoftware<'a, W> {
+    fn handle_deferred_call(&self) {
+        if let (Some(message), Some(modulus), Some(exponent), Some(result)) = (
+            self.message.take(),
+            self.modulus.take(),
+            self.exponent.take(),
+            self.result.take(),
+        ) {
+            self.client.map(|client| {
+                client.mod_exponent_done(Ok(true), message, modulus, exponent, result);
+            });
+        }
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+        self.work.register(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sha_software::from_hex;
+
+    /// An RSA-3072 test key and the PKCS#1 v1.5 SHA-512 signature of "abc"
+    /// with it, generated with OpenSSL.
+    const MODULUS: &str = "b34145a0dc7fdeb1826b87aa80fd0650fcf15285c38f806eeabcde2979ce00a3ed26f21815bb44d22463eb1945e5397a\
+     541c053116ebd4c434a67777748b49e681e658ba14bba9ea813514738ec12ce90b07d399108879d5e5fbfdefba9f5644\
+     c5b343aceddb86113c428a15fda87d34b6bbbbc21f348bc73b7f6539395cc2ba0a58498e306370d736c0fb53bf9789e8\
+     7c3f6a003bb1a82e068433270ad65f47fa36db743221234d721ac5a4062d634b30b2cb8f673c1310686922848146580a\
+     8f6abdd188821e30ec76d9bb4ac9d4fb078d4f1ec6329a3b3f58d8dc8aa6f1196ea84079275ef238b97a59e7bbe96876\
+     532d3451c71d66f1fd0d7f9ac20c89aceb0a79abfdd00f0f31688f1c8d87144f778407234aaeee0ee75a24bbbc7baee7\
+     e2ff7b3301130eee7dbd0034eb9de7b280bce8adf88327b7bba1eb18911267c8394204e8780cbaea81e547997bfb3279\
+     0fa9eb5ec8e78952156aac8b19603a943257b16ade90f35f1f0ccb9286b34493f9313352acd83fe5af141017f2e8ffcd";
+    const PRIVATE_EXPONENT: &str = "56837425750649e686397b9ab0f26d6ffd04437dc7aaa4a3e3ab0fd618b171e1abfd95ee45dec21261f9cf67bd322a91\
+     7db2a056d6e72f231afe14be9482fd41c103f962a5cb04f0e737450d98a267187a786689834f3aee1f37859184128ec2\
+     566074932ba13d8d67f5fd4b2bc77bd64b334425268667c7da515bfb48521ab14b78dcd741475d18a19676362f4be605\
+     4ca21678d256a602c0a66141c09b42152d0ff8ce95bb249ca62b81a3ee4f4d92e6880472cbb254c9207b4d6b8230ff20\
+     1a601923030ace0c42edd0ddccdbfea3e1bd14d9fea9466cca566e0c867075bb4e9c1acd536a22729496da118d9cc810\
+     d447991310b990bd21829a8d46ce7e692c67eb4aa23a61fd2e4ff0a4972cff1acc3a1882b4ec0dc8a3510c1aad4620f5\
+     ece1dfbe049520bc15bc48243711c58c9fd7e1ff70d428a3f538ebc2b1265c961e1dfe6c6ba08c231379c77939f5326b\
+     b638552883e007f2b3f03e303abf993861ce0e81ded2c2518ebb7692bf6d2a7b807115b6c4825e309679c11316921749";
+    const SIGNATURE: &str = "3d2b836f63c50dcc18e7d930f25cf08e6ac8461f7131a5499426a9726f06cf04f45b0b0eb611a1916062ca1f346f5f9e\
+     a164ac293af3e9b02f95c4c059b36619d5d62e88ea17bc30e25dba11ae620e1f111b1ff390fdf3f2f44422398dd2383a\
+     4864e4beb27707121ab3e3a17206f68f689bee3b48be9b4fbae647e4063b1f7bb16367db736c27fd733700885acc98ed\
+     59cd8c704d52604c9c451506906dd6e562dc9befa4ab73a5ae463b32f291f54120eaa8f25dca7e5d932bfc57df9e5534\
+     e62bedb341847323dd16e2b7c4c1b51c2805fceeeef1566e14502dc68e2e3370e3cb7fd7c06ecf481a0d03765ff0ee42\
+     7a680dbb8a6736055fd749d2ba439945fb50e64f601221561ae3b48cbff4cedb7e2ba9d7d7b5f6b0e7bc33a722fb5522\
+     484978a698995caebbad1fa7668adf69ccae3adf5a17f729051d718b49b1ca9546bc8427372cb34576b4341b83acf9e9\
+     8d87d94e73cffc9ac846b72439f993c8cabae77218bb0f55be9ad360acc40414f92b0501dd4e5d703d3230e0ad34872f";
+
+    /// SHA-512("abc"), FIPS 180-2 appendix C.1.
+    const ABC_SHA512: &str = "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+                              2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f";
+
+    fn exponentiate<const L: usize>(message: &[u8], modulus: &[u8], exponent: &[u8]) -> [u8; L] {
+        let rsa = Rsa3072Software::new();
+        let mut result = [0; L];
+        let mut chunks = 0;
+        rsa.scratch.map(|scratch| {
+            scratch.step = Step::Start;
+            while !Rsa3072Software::exponentiate_chunk(
+                scratch,
+                message,
+                modulus,
+                exponent,
+                &mut result,
+            ) {
+                chunks += 1;
+            }
+        });
+        // Even a tiny exponentiation takes a few chunks.
+        assert!(chunks >= 3);
+        result
+    }
+
+    /// The PKCS#1 v1.5 encoding of SHA-512("abc") for a 3072-bit modulus.
+    fn encoded_abc() -> [u8; 384] {
+        let digest_info = from_hex::<19>("3051300d060960864801650304020305000440");
+        let mut encoded = [0xff; 384];
+        encoded[0] = 0x00;
+        encoded[1] = 0x01;
+        encoded[384 - 64 - 19 - 1] = 0x00;
+        encoded[384 - 64 - 19..384 - 64].copy_from_slice(&digest_info);
+        encoded[384 - 64..].copy_from_slice(&from_hex::<64>(ABC_SHA512));
+        encoded
+    }
+
+    #[test]
+    fn modexp_textbook() {
+        // n = 61 * 53, e = 17, d = 2753.
+        assert_eq!(exponentiate::<2>(&[65], &[0x0c, 0xa1], &[17]), [0x0a, 0xe6]);
+        assert_eq!(
+            exponentiate::<2>(&[0x0a, 0xe6], &[0x0c, 0xa1], &[0x0a, 0xc1]),
+            [0, 65]
+        );
+        // A zero exponent, and a message larger than the modulus.
+        assert_eq!(
+            exponentiate::<2>(&[0x0a, 0xe6], &[0x0c, 0xa1], &[0]),
+            [0, 1]
+        );
+        assert_eq!(
+            exponentiate::<2>(&[0x0c, 0xe2], &[0x0c, 0xa1], &[1]),
+            [0, 65]
+        );
+    }
+
+    #[test]
+    fn modexp_verifies_rsa3072_signature() {
+        let modulus = from_hex::<384>(MODULUS);
+        let signature = from_hex::<384>(SIGNATURE);
+        assert_eq!(
+            exponentiate::<384>(&signature, &modulus, &[0x01, 0x00, 0x01]),
+            encoded_abc()
+        );
+    }
+
+    #[test]
+    fn modexp_signs_with_private_exponent() {
+        let modulus = from_hex::<384>(MODULUS);
+        let exponent = from_hex::<384>(PRIVATE_EXPONENT);
+        assert_eq!(
+            exponentiate::<384>(&encoded_abc(), &modulus, &exponent),
+            from_hex::<384>(SIGNATURE)
+        );
+    }
+}
@@ -2,6 +2,8 @@
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 // Copyright Tock Contributors 2022.
 
+use core::cell::Cell;
+
 use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
 use kernel::hil;
 use kernel::syscall::{CommandReturn, SyscallDriver};
@@ -23,6 +25,11 @@ pub struct Pwm<'a, const NUM_PINS: usize> {
     apps: Grant<App, UpcallCount<1>, AllowRoCount<0>, AllowRwCount<0>>,
     /// An array of apps associated to their reserved pins.
     active_process: [OptionalCell<ProcessId>; NUM_PINS],
+    /// Pins claimed explicitly, which stay reserved when their output stops.
+    held: [Cell<bool>; NUM_PINS],
+    /// The frequency in hertz and the duty cycle in hundredths of a percent
+    /// of each running pin.
+    settings: [OptionalCell<(usize, usize)>; NUM_PINS],
 }
 
 impl<'a, const NUM_PINS: usize> Pwm<'a, NUM_PINS> {
@@ -33,9 +40,11 @@ impl<'a, const NUM_PINS: usize> Pwm<'a, NUM_PINS> {
         assert!(NUM_PINS <= (u16::MAX as usize));
         const EMPTY: OptionalCell<ProcessId> = OptionalCell::empty();
         Pwm {
-            pwm_pins: pwm_pins,
+            pwm_pins,
             apps: grant,
             active_process: [EMPTY; NUM_PINS],
+            held: core::array::from_fn(|_| Cell::new(false)),
+            settings: core::array::from_fn(|_| OptionalCell::empty()),
         }
     }
 
@@ -57,6 +66,12 @@ impl<'a, const NUM_PINS: usize> Pwm<'a, NUM_PINS> {
     pub fn release_pin(&self, pin: usize) {
         // Release the claimed pin so that it can now be used by another process.
         self.active_process[pin].clear();
+        self.held[pin].set(false);
+    }
+
+    /// Whether `processid` currently holds the claim on `pin`.
+    fn owns_pin(&self, processid: ProcessId, pin: usize) -> bool {
+        self.active_process[pin].map_or(false, |id| id == &processid)
     }
 }
 
@@ -68,11 +83,19 @@ impl<'a, const NUM_PINS: usize> SyscallDriver for Pwm<'a, NUM_PINS> {
     ///
     /// - `0`: Return number of PWM pins if this driver is included on the platform.
     /// - `1`: Start the PWM pin output. First 16 bits of `data1` are used for the duty cycle, as a
-    ///     percentage with 2 decimals, and the last 16 bits of `data1` are used for the PWM channel
-    ///     to be controlled. `data2` is used for the frequency in hertz. For the duty cycle, 100% is
-    ///     the max duty cycle for this pin.
-    /// - `2`: Stop the PWM output.
+    ///   percentage with 2 decimals, and the last 16 bits of `data1` are used for the PWM channel
+    ///   to be controlled. `data2` is used for the frequency in hertz. For the duty cycle, 100% is
+    ///   the max duty cycle for this pin.
+    /// - `2`: Stop the PWM output. The pin is released unless it was claimed with command `4`.
     /// - `3`: Return the maximum possible frequency for this pin.
+    /// - `4`: Claim the pin in `data1` exclusively, so that it stays reserved for this app while
+    ///   its output is stopped.
+    /// - `5`: Stop the output of the pin in `data1` and release it.
+    /// - `6`: Return the duty cycle of the running pin in `data1`, as a percentage with 2
+    ///   decimals.
+    /// - `7`: Return the frequency in hertz of the running pin in `data1`.
+    /// - `8`: Set the modulation mode of the pin in `data1`, which this app must have claimed.
+    ///   `data2` is `0` for trailing-edge and `1` for phase-correct modulation.
     fn command(
         &self,
         command_num: usize,
@@ -111,12 +134,14 @@ impl<'a, const NUM_PINS: usize> SyscallDriver for Pwm<'a, NUM_PINS> {
                         // Duty cycle is represented as a 4 digit number, so we divide by 10000 to get the percentage of the max duty cycle.
                         // e.g.: a duty cycle of 60.5% is represented as 6050, so the actual value of the duty cycle is
                         // 6050 * max_duty_cycle / 10000 = 0.605 * max_duty_cycle
-                        self.pwm_pins[pin]
-                            .start(
-                                frequency_hz,
-                                duty_cycle * self.pwm_pins[pin].get_maximum_duty_cycle() / 10000,
-                            )
-                            .into()
+                        let result = self.pwm_pins[pin].start(
+                            frequency_hz,
+                            duty_cycle * self.pwm_pins[pin].get_maximum_duty_cycle() / 10000,
+                        );
+                        if result.is_ok() {
+                            self.settings[pin].set((frequency_hz, duty_cycle));
+                        }
+                        result.into()
                     }
                 }
             }
@@ -135,8 +160,11 @@ impl<'a, const NUM_PINS: usize> SyscallDriver for Pwm<'a, NUM_PINS> {
                         // If there is no active app, the pwm pin isn't in use.
                         CommandReturn::failure(ErrorCode::OFF)
                     } else {
-                        // Release the pin and stop pwm output.
-                        self.release_pin(pin);
+                        // Release the pin, unless it is held, and stop pwm output.
+                        if !self.held[pin].get() {
+                            self.release_pin(pin);
+                        }
+                        self.settings[pin].clear();
                         self.pwm_pins[pin].stop().into()
                     }
                 }
@@ -152,6 +180,75 @@ impl<'a, const NUM_PINS: usize> SyscallDriver for Pwm<'a, NUM_PINS> {
                 }
             }
 
+            // Claim a pin until it is released.
+            4 => {
+                let pin = data1;
+                if pin >= NUM_PINS {
+                    CommandReturn::failure(ErrorCode::INVAL)
+                } else if !self.claim_pin(processid, pin) {
+                    CommandReturn::failure(ErrorCode::RESERVE)
+                } else {
+                    self.active_process[pin].set(processid);
+                    self.held[pin].set(true);
+                    CommandReturn::success()
+                }
+            }
+
+            // Stop and release a pin.
+            5 => {
+                let pin = data1;
+                if pin >= NUM_PINS {
+                    CommandReturn::failure(ErrorCode::INVAL)
+                } else if !self.claim_pin(processid, pin) {
+                    CommandReturn::failure(ErrorCode::RESERVE)
+                } else if self.active_process[pin].is_none() {
+                    CommandReturn::failure(ErrorCode::OFF)
+                } else {
+                    self.release_pin(pin);
+                    if self.settings[pin].take().is_some() {
+                        self.pwm_pins[pin].stop().into()
+                    } else {
+                        CommandReturn::success()
+                    }
+                }
+            }
+
+            // Get the duty cycle or the frequency of a running pin.
+            6 | 7 => {
+                let pin = data1;
+                if pin >= NUM_PINS {
+                    CommandReturn::failure(ErrorCode::INVAL)
+                } else {
+                    self.settings[pin].map_or(
+                        CommandReturn::failure(ErrorCode::OFF),
+                        |(frequency_hz, duty_cycle)| {
+                            if command_num == 6 {
+                                CommandReturn::success_u32(*duty_cycle as u32)
+                            } else {
+                                CommandReturn::success_u32(*frequency_hz as u32)
+                            }
+                        },
+                    )
+                }
+            }
+
+            // Set the modulation mode of a claimed pin.
+            8 => {
+                let pin = data1;
+                let mode = match data2 {
+                    0 => hil::pwm::Mode::TrailingEdge,
+                    1 => hil::pwm::Mode::PhaseCorrect,
+                    _ => return CommandReturn::failure(ErrorCode::INVAL),
+                };
+                if pin >= NUM_PINS {
+                    CommandReturn::failure(ErrorCode::INVAL)
+                } else if !self.owns_pin(processid, pin) {
+                    CommandReturn::failure(ErrorCode::RESERVE)
+                } else {
+                    self.pwm_pins[pin].set_mode(mode).into()
+                }
+            }
+
             _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
         }
     }
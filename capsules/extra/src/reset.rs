@@ -0,0 +1,129 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! System call driver to reset the chip, or to reset it into its
+//! bootloader, e.g. for an application that manages firmware updates.
+//!
+//! Resetting affects the whole system, so only the processes whose fixed
+//! `ShortID` the board lists may use this driver. Other processes get
+//! `NOSUPPORT` for every command but the existence check.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! struct ProcessMgmtCap;
+//! unsafe impl capabilities::ProcessManagementCapability for ProcessMgmtCap {}
+//! let reset = static_init!(
+//!     capsules_extra::reset::ResetDriver<ProcessMgmtCap>,
+//!     capsules_extra::reset::ResetDriver::new(
+//!         board_kernel,
+//!         &cortexm4::scb::ScbReset,
+//!         static_init!(
+//!             [ShortID; 1],
+//!             [ShortID::Fixed(NonZeroU32::new(0x2f7d).unwrap())]
+//!         ),
+//!         ProcessMgmtCap
+//!     )
+//! );
+//! ```
+//!
+//! Command Interface
+//! -----------------
+//!
+//! - `0`: Driver existence check.
+//! - `1`: Whether the calling process may reset the chip.
+//! - `2`: Reset the chip. Does not return on success.
+//! - `3`: Reset the chip into its bootloader. Does not return on success,
+//!   returns `NOSUPPORT` if the chip has no bootloader.
+
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::hil::reset::{Reset, ResetMode};
+use kernel::process::ShortID;
+use kernel::reboot_reason::{self, RebootReason};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::{ErrorCode, Kernel, ProcessId};
+
+/// Syscall driver number.
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Reset as usize;
+
+pub struct ResetDriver<'a, C: ProcessManagementCapability> {
+    kernel: &'static Kernel,
+    reset: &'a dyn Reset,
+    allowed: &'a [ShortID],
+    capability: C,
+}
+
+impl<'a, C: ProcessManagementCapability> ResetDriver<'a, C> {
+    /// Only the processes with a `ShortID` in `allowed` may reset the chip.
+    pub fn new(
+        kernel: &'static Kernel,
+        reset: &'a dyn Reset,
+        allowed: &'a [ShortID],
+        capability: C,
+    ) -> ResetDriver<'a, C> {
+        ResetDriver {
+            kernel,
+            reset,
+            allowed,
+            capability,
+        }
+    }
+
+    fn is_allowed(&self, processid: ProcessId) -> bool {
+        self.kernel.process_map_or_external(
+            false,
+            processid,
+            |process| self.allowed.contains(&process.short_app_id()),
+            &self.capability,
+        )
+    }
+
+    fn reset(&self, mode: ResetMode, reason: RebootReason, processid: ProcessId) -> CommandReturn {
+        if !self.reset.supports(mode) {
+            return CommandReturn::failure(ErrorCode::NOSUPPORT);
+        }
+        self.kernel.process_map_or_external(
+            (),
+            processid,
+            |process| {
+                reboot_reason::record(
+                    reason,
+                    format_args!("requested by {}", process.get_process_name()),
+                )
+            },
+            &self.capability,
+        );
+        CommandReturn::failure(self.reset.reset(mode))
+    }
+}
+
+impl<'a, C: ProcessManagementCapability> SyscallDriver for ResetDriver<'a, C> {
+    fn command(
+        &self,
+        command_num: usize,
+        _: usize,
+        _: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        if command_num == 0 {
+            return CommandReturn::success();
+        }
+        if !self.is_allowed(processid) {
+            return CommandReturn::failure(ErrorCode::NOSUPPORT);
+        }
+        match command_num {
+            1 => CommandReturn::success(),
+            2 => self.reset(ResetMode::Normal, RebootReason::UserCommand, processid),
+            3 => self.reset(ResetMode::Bootloader, RebootReason::Update, processid),
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, _processid: ProcessId) -> Result<(), kernel::process::Error> {
+        // No per-process state.
+        Ok(())
+    }
+}
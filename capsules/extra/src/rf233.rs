@@ -30,6 +30,7 @@ use crate::rf233_const::IRQ_MASK;
 use crate::rf233_const::PHY_CC_CCA_MODE_CS_OR_ED;
 use crate::rf233_const::PHY_RSSI_RX_CRC_VALID;
 use crate::rf233_const::PHY_TX_PWR;
+use crate::rf233_const::RSSI_BASE_VAL;
 use crate::rf233_const::SHORT_ADDR_0;
 use crate::rf233_const::SHORT_ADDR_1;
 use crate::rf233_const::TRX_CTRL_1;
@@ -50,6 +51,7 @@ enum InternalState {
     // RX    -- receiving a packet
     // TX    -- transmitting a packet
     // CONFIG -- reconfiguring the radio
+    // ED    -- measuring the energy on a channel
     START,
     START_PART_READ,
     START_STATUS_READ,
@@ -130,6 +132,15 @@ enum InternalState {
     CONFIG_POWER_SET,
     CONFIG_DONE,
 
+    // States of a manual energy detection: switching to the channel to
+    // measure, waiting for the measurement interrupt, reading the level and
+    // switching back to the configured channel
+    ED_CHANNEL_SET,
+    ED_MEASURING,
+    ED_MEASURED,
+    ED_LEVEL_READ,
+    ED_DONE,
+
     // RX is a short-lived state for when software has detected
     // the chip is receiving a packet (by internal state) but has
     // not received the interrupt yet. I.e., the SFD has been
@@ -213,6 +224,10 @@ pub struct RF233<'a, S: spi::SpiMasterDevice> {
     rx_client: OptionalCell<&'a dyn radio::RxClient>,
     cfg_client: OptionalCell<&'a dyn radio::ConfigClient>,
     power_client: OptionalCell<&'a dyn radio::PowerClient>,
+    ed_client: OptionalCell<&'a dyn radio::EnergyDetectClient>,
+    // Channel of the pending energy detection, and the level it measured
+    ed_channel: OptionalCell<u8>,
+    ed_level: Cell<u8>,
     addr: Cell<u16>,
     addr_long: Cell<[u8; 8]>,
     pan: Cell<u16>,
@@ -350,6 +365,10 @@ impl<'a, S: spi::SpiMasterDevice> spi::SpiMasterClient for RF233<'a, S> {
                     && interrupt_included(interrupt, InteruptFlags::IRQ_3_TRX_END)
                 {
                     self.state.set(InternalState::TX_DONE);
+                } else if state == InternalState::ED_MEASURING
+                    && interrupt_included(interrupt, InteruptFlags::IRQ_4_CCA_ED_DONE)
+                {
+                    self.state.set(InternalState::ED_MEASURED);
                 }
                 if interrupt_included(interrupt, InteruptFlags::IRQ_2_RX_START) {
                     // Start of frame
@@ -437,6 +456,19 @@ impl<'a, S: spi::SpiMasterDevice> spi::SpiMasterClient for RF233<'a, S> {
                         RF233TrxCmd::OFF as u8,
                         InternalState::SLEEP_TRX_OFF,
                     );
+                    self.ed_channel.take().map(|channel| {
+                        self.ed_client.map(|c| {
+                            c.energy_detect_done(channel, Err(ErrorCode::OFF));
+                        });
+                    });
+                } else if let Some(channel) = self.ed_channel.extract() {
+                    // Start an energy detection requested while the radio
+                    // was busy, or measure again if a frame aborted it
+                    self.state_transition_write(
+                        RF233Register::PHY_CC_CCA,
+                        channel | PHY_CC_CCA_MODE_CS_OR_ED,
+                        InternalState::ED_CHANNEL_SET,
+                    );
                 } else if self.power_client_pending.get() {
                     // fixes bug where client would start transmitting before this state completed
                     self.power_client_pending.set(false);
@@ -1018,6 +1050,36 @@ impl<'a, S: spi::SpiMasterDevice> spi::SpiMasterClient for RF233<'a, S> {
                     c.config_done(Ok(()));
                 });
             }
+            InternalState::ED_CHANNEL_SET => {
+                // Any write to PHY_ED_LEVEL starts a measurement
+                self.state_transition_write(
+                    RF233Register::PHY_ED_LEVEL,
+                    0,
+                    InternalState::ED_MEASURING,
+                );
+            }
+            // Waiting for the interrupt that the measurement is done
+            InternalState::ED_MEASURING => {}
+            InternalState::ED_MEASURED => {
+                self.state_transition_read(
+                    RF233Register::PHY_ED_LEVEL,
+                    InternalState::ED_LEVEL_READ,
+                );
+            }
+            InternalState::ED_LEVEL_READ => {
+                self.ed_level.set(result);
+                let val = self.channel.get() | PHY_CC_CCA_MODE_CS_OR_ED;
+                self.state_transition_write(RF233Register::PHY_CC_CCA, val, InternalState::ED_DONE);
+            }
+            InternalState::ED_DONE => {
+                self.state_transition_read(RF233Register::TRX_STATUS, InternalState::READY);
+                let dbm = RSSI_BASE_VAL.saturating_add(self.ed_level.get() as i8);
+                self.ed_channel.take().map(|channel| {
+                    self.ed_client.map(|c| {
+                        c.energy_detect_done(channel, Ok(dbm));
+                    });
+                });
+            }
         }
     }
 }
@@ -1060,6 +1122,9 @@ impl<'a, S: spi::SpiMasterDevice> RF233<'a, S> {
             rx_client: OptionalCell::empty(),
             cfg_client: OptionalCell::empty(),
             power_client: OptionalCell::empty(),
+            ed_client: OptionalCell::empty(),
+            ed_channel: OptionalCell::empty(),
+            ed_level: Cell::new(0),
             addr: Cell::new(0),
             addr_long: Cell::new([0x00; 8]),
             pan: Cell::new(0),
@@ -1313,6 +1378,33 @@ impl<'a, S: spi::SpiMasterDevice> radio::RadioConfig<'a> for RF233<'a, S> {
         self.channel.get()
     }
 
+    fn energy_detect(&self, channel: u8) -> Result<(), ErrorCode> {
+        if !(11..=26).contains(&channel) {
+            return Err(ErrorCode::INVAL);
+        } else if !self.radio_on.get() {
+            return Err(ErrorCode::OFF);
+        } else if self.ed_channel.is_some() || self.transmitting.get() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        self.ed_channel.set(channel);
+        if !self.receiving.get() && !self.spi_busy.get() && self.state.get() == InternalState::READY
+        {
+            self.state_transition_write(
+                RF233Register::PHY_CC_CCA,
+                channel | PHY_CC_CCA_MODE_CS_OR_ED,
+                InternalState::ED_CHANNEL_SET,
+            );
+        } else {
+            // The measurement starts on return to READY
+        }
+        Ok(())
+    }
+
+    fn set_energy_detect_client(&self, client: &'a dyn radio::EnergyDetectClient) {
+        self.ed_client.set(client);
+    }
+
     fn config_commit(&self) {
         let pending = self.config_pending.get();
         if !pending {
@@ -1350,6 +1442,11 @@ impl<'a, S: spi::SpiMasterDevice> radio::RadioData<'a> for RF233<'a, S> {
         self.rx_buf.replace(buffer);
     }
 
+    fn get_rx_metadata(&self) -> radio::RxMetadata {
+        // The LQI and ED bytes that follow the frame are not read
+        radio::RxMetadata::default()
+    }
+
     // The payload length is the length of the MAC payload, not the PSDU
     fn transmit(
         &self,
@@ -1361,7 +1458,7 @@ impl<'a, S: spi::SpiMasterDevice> radio::RadioData<'a> for RF233<'a, S> {
 
         if !self.radio_on.get() {
             return Err((ErrorCode::OFF, spi_buf));
-        } else if self.tx_buf.is_some() || self.transmitting.get() {
+        } else if self.tx_buf.is_some() || self.transmitting.get() || self.ed_channel.is_some() {
             return Err((ErrorCode::BUSY, spi_buf));
         } else if radio::PSDU_OFFSET + frame_len >= spi_buf.len() {
             // Not enough room for CRC
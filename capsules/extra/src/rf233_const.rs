@@ -76,6 +76,7 @@ pub const PHY_RSSI_RX_CRC_VALID: u8 = 1 << 7;
 pub const TRX_CTRL_2_RX_SAFE_MODE: u8 = 1 << 7;
 pub const TRX_CTRL_2_DATA_RATE_250: u8 = 0;
 pub const IRQ_TRXBUF_ACCESS_VIOLATION: u8 = 1 << 6;
+pub const IRQ_CCA_ED_DONE: u8 = 1 << 4;
 pub const IRQ_TRX_DONE: u8 = 1 << 3;
 pub const IRQ_RX_START: u8 = 1 << 2;
 pub const IRQ_PLL_LOCK: u8 = 1 << 0;
@@ -91,12 +92,15 @@ pub const TRX_CTRL_2: u8 = TRX_CTRL_2_RX_SAFE_MODE | TRX_CTRL_2_DATA_RATE_250;
 pub const PHY_CC_CCA: u8 = DEFAULT_PHY_CHANNEL | PHY_CC_CCA_MODE_CS_OR_ED;
 pub const PHY_TX_PWR: u8 = PHY_TX_PWR_4;
 pub const DEFAULT_PHY_CHANNEL: u8 = 26;
-pub const IRQ_MASK: u8 = IRQ_TRXBUF_ACCESS_VIOLATION | IRQ_TRX_DONE | IRQ_PLL_LOCK | IRQ_RX_START;
+pub const IRQ_MASK: u8 =
+    IRQ_TRXBUF_ACCESS_VIOLATION | IRQ_CCA_ED_DONE | IRQ_TRX_DONE | IRQ_PLL_LOCK | IRQ_RX_START;
 pub const XAH_CTRL_1: u8 =
     XAH_CTRL_1_AACK_UPLD_RES_FT | XAH_CTRL_1_AACK_FLTR_RES_FT | XAH_CTRL_1_AACK_PROM_MODE;
 pub const XAH_CTRL_0: u8 = 0;
 pub const CSMA_SEED_1: u8 = AACK_FVN_MODE;
 pub const TRX_RPC: u8 = 0xFF;
+// Energy of ED level 0 at 250 kb/s, in dBm.
+pub const RSSI_BASE_VAL: i8 = -94;
 pub const TRX_TRAC_MASK: u8 = 0xE0;
 pub const TRX_TRAC_SUCCESS_DATA_PENDING: u8 = 1 << 5;
 pub const TRX_TRAC_CHANNEL_ACCESS_FAILURE: u8 = 3 << 5;
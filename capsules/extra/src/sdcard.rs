@@ -5,6 +5,12 @@
 //! Provides driver for accessing an SD Card and a userspace Driver.
 //!
 //! This allows initialization and block reads or writes on top of SPI.
+//! Commands and data blocks carry CRCs, which the card checks once
+//! initialized, and the driver checks the CRC of the blocks it reads.
+//!
+//! Other capsules can use the SD card through the `BlockStorage` HIL, after
+//! a `BlockStorageClient` is set with `BlockStorage::set_client` and the card
+//! is initialized.
 //!
 //! Usage
 //! -----
@@ -71,6 +77,7 @@ use core::cmp;
 
 use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
 use kernel::hil;
+use kernel::hil::block_storage::{BlockStorage, BlockStorageClient};
 use kernel::hil::time::ConvertTicks;
 use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
 use kernel::syscall::{CommandReturn, SyscallDriver};
@@ -115,6 +122,8 @@ pub struct SDCard<'a, A: hil::time::Alarm<'a>> {
 
     is_initialized: Cell<bool>,
     card_type: Cell<SDCardType>,
+    total_size: Cell<u64>,
+    crc_enabled: Cell<bool>,
 
     detect_pin: Cell<Option<&'a dyn hil::gpio::InterruptPin<'a>>>,
 
@@ -124,6 +133,13 @@ pub struct SDCard<'a, A: hil::time::Alarm<'a>> {
     client: OptionalCell<&'static dyn SDCardClient>,
     client_buffer: TakeCell<'static, [u8]>,
     client_offset: Cell<usize>,
+
+    block_client: OptionalCell<&'a dyn BlockStorageClient>,
+    /// Whether the running transfer was started through `BlockStorage`
+    block_request: Cell<bool>,
+    operation: Cell<Operation>,
+    /// Whether the running transfer uses the multiple block commands
+    multiple: Cell<bool>,
 }
 
 /// SD card command codes
@@ -140,8 +156,12 @@ enum SDCmd {
     CMD18_ReadMultiple = 18,              //         Read multiple blocks
     CMD24_WriteSingle = 24,               //          Write single block
     CMD25_WriteMultiple = 25,             //        Write multiple blocks
+    CMD32_EraseStart = 32,                //           Set first block to erase
+    CMD33_EraseEnd = 33,                  //             Set last block to erase
+    CMD38_Erase = 38,                     //                Erase selected blocks
     CMD55_ManufSpecificCommand = 55,      // Next command will be manufacturer specific
     CMD58_ReadOCR = 58,                   //              Read operation condition register (OCR)
+    CMD59_CrcOnOff = 59,                  //             Turn CRC checks on or off
     ACMD41_ManufSpecificInit = 0x80 + 41, // Manufacturer specific Init
 }
 
@@ -171,6 +191,7 @@ enum SpiState {
     InitRepeatGenericInit,
     InitSetBlocksize,
     InitComplete,
+    InitEnableCrc,
 
     StartReadBlocks { count: u32 },
     WaitReadBlock,
@@ -178,11 +199,17 @@ enum SpiState {
     WaitReadBlocks { count: u32 },
     ReceivedBlock { count: u32 },
     ReadBlocksComplete,
+    ReadBlocksFailed,
 
     StartWriteBlocks { count: u32 },
-    WriteBlockResponse,
-    WriteBlockBusy,
-    WaitWriteBlockBusy,
+    WriteBlockResponse { count: u32 },
+    WriteBlockBusy { count: u32 },
+    WaitWriteBlockBusy { count: u32 },
+    WriteStopTran,
+
+    EraseStart { end: u32 },
+    EraseEnd,
+    Erase,
 }
 
 /// Alarm states
@@ -199,7 +226,7 @@ enum AlarmState {
     WaitForDataBlock,
     WaitForDataBlocks { count: u32 },
 
-    WaitForWriteBusy,
+    WaitForWriteBusy { count: u32 },
 }
 
 /// Error codes returned if an SD card transaction fails
@@ -210,6 +237,17 @@ enum SdCardError {
     ReadFailure = -10003,
     WriteFailure = -10004,
     TimeoutFailure = -10005,
+    CrcFailure = -10006,
+    EraseFailure = -10007,
+}
+
+/// Operations of the running transfer
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Operation {
+    Read,
+    Write,
+    Erase,
+    Flush,
 }
 
 /// SD card types, determined during initialization
@@ -226,6 +264,41 @@ enum SDCardType {
 const SUCCESS_STATUS: u8 = 0x00;
 const INITIALIZING_STATUS: u8 = 0x01;
 const DATA_TOKEN: u8 = 0xFE;
+const WRITE_MULTIPLE_TOKEN: u8 = 0xFC;
+const STOP_TRAN_TOKEN: u8 = 0xFD;
+const BLOCK_SIZE: usize = 512;
+
+/// CRC7 of a command, polynomial x^7 + x^3 + 1
+fn crc7(data: &[u8]) -> u8 {
+    let mut crc: u8 = 0;
+    for &byte in data {
+        let mut byte = byte;
+        for _ in 0..8 {
+            crc <<= 1;
+            if (byte ^ crc) & 0x80 != 0 {
+                crc ^= 0x09;
+            }
+            byte <<= 1;
+        }
+    }
+    crc & 0x7F
+}
+
+/// CRC16 of a data block, polynomial x^16 + x^12 + x^5 + 1
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            if crc & 0x8000 != 0 {
+                crc = (crc << 1) ^ 0x1021;
+            } else {
+                crc <<= 1;
+            }
+        }
+    }
+    crc
+}
 
 /// Callback functions from SDCard
 pub trait SDCardClient {
@@ -279,12 +352,18 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
             alarm_count: Cell::new(0),
             is_initialized: Cell::new(false),
             card_type: Cell::new(SDCardType::Uninitialized),
+            total_size: Cell::new(0),
+            crc_enabled: Cell::new(false),
             detect_pin: Cell::new(pin),
             txbuffer: TakeCell::new(txbuffer),
             rxbuffer: TakeCell::new(rxbuffer),
             client: OptionalCell::empty(),
             client_buffer: TakeCell::empty(),
             client_offset: Cell::new(0),
+            block_client: OptionalCell::empty(),
+            block_request: Cell::new(false),
+            operation: Cell::new(Operation::Read),
+            multiple: Cell::new(false),
         }
     }
 
@@ -352,14 +431,8 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
         write_buffer[5] = ((arg >> 8) & 0xFF) as u8;
         write_buffer[6] = ((arg >> 0) & 0xFF) as u8;
 
-        // CRC is ignored except for CMD0 and maybe CMD8
-        // Established practice it to just always use the CMD0 CRC unless we
-        // are sending a CMD8
-        if cmd == SDCmd::CMD8_CheckVoltage {
-            write_buffer[7] = 0x87; // valid crc for CMD8(0x1AA)
-        } else {
-            write_buffer[7] = 0x95; // valid crc for CMD0
-        }
+        // CRC, followed by the end bit
+        write_buffer[7] = (crc7(&write_buffer[2..7]) << 1) | 0x01;
 
         // append dummy bytes to transmission after command bytes
         // Limit to minimum length between write_buffer and recv_len
@@ -457,6 +530,131 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
         (r1, r2, r3)
     }
 
+    /// check the CRC following a data block received in read_buffer, if the
+    /// card sends valid CRCs
+    fn data_crc_valid(&self, read_buffer: &[u8]) -> bool {
+        let crc = (read_buffer[BLOCK_SIZE] as u16) << 8 | read_buffer[BLOCK_SIZE + 1] as u16;
+        !self.crc_enabled.get() || crc16(&read_buffer[..BLOCK_SIZE]) == crc
+    }
+
+    /// write the next block of the client buffer as a data packet, with
+    /// `count` blocks left including this one
+    fn write_block(
+        &self,
+        write_buffer: &'static mut [u8],
+        read_buffer: &'static mut [u8],
+        count: u32,
+    ) {
+        let offset = self.client_offset.get();
+        let bytes_written = self.client_buffer.map_or(0, |buffer| {
+            // copy over data from client buffer
+            // Limit to minimum length between write_buffer, buffer, and 512
+            // (block size)
+            for (write_byte, &client_byte) in write_buffer
+                .iter_mut()
+                .skip(1)
+                .zip(buffer.iter().skip(offset))
+                .take(BLOCK_SIZE)
+            {
+                *write_byte = client_byte;
+            }
+
+            // calculate number of bytes written
+            cmp::min(
+                write_buffer.len() - 1,
+                cmp::min(buffer.len().saturating_sub(offset), BLOCK_SIZE),
+            )
+        });
+        self.client_offset.set(offset + BLOCK_SIZE);
+
+        // set a known value for remaining bytes
+        for write_byte in write_buffer
+            .iter_mut()
+            .skip(1 + bytes_written)
+            .take(BLOCK_SIZE - bytes_written)
+        {
+            *write_byte = 0xFF;
+        }
+
+        // set up remainder of data packet
+        if self.multiple.get() {
+            write_buffer[0] = WRITE_MULTIPLE_TOKEN;
+        } else {
+            write_buffer[0] = DATA_TOKEN;
+        }
+        let crc = crc16(&write_buffer[1..BLOCK_SIZE + 1]);
+        write_buffer[BLOCK_SIZE + 1] = (crc >> 8) as u8;
+        write_buffer[BLOCK_SIZE + 2] = (crc & 0xFF) as u8;
+
+        // write data packet
+        self.state.set(SpiState::WriteBlockResponse { count });
+        self.write_bytes(write_buffer, read_buffer, BLOCK_SIZE + 3);
+    }
+
+    /// pass the buffer of a finished read to whoever started it
+    fn read_done(&self, buffer: &'static mut [u8], len: usize) {
+        if self.block_request.get() {
+            self.block_client.map(move |client| {
+                client.read_complete(buffer, Ok(()));
+            });
+        } else {
+            self.client.map(move |client| {
+                client.read_done(buffer, len);
+            });
+        }
+    }
+
+    /// pass the buffer of a finished write to whoever started it
+    fn write_done(&self, buffer: &'static mut [u8]) {
+        if self.block_request.get() {
+            self.block_client.map(move |client| {
+                client.write_complete(buffer, Ok(()));
+            });
+        } else {
+            self.client.map(move |client| {
+                client.write_done(buffer);
+            });
+        }
+    }
+
+    /// report an error to the client. An operation started through
+    /// `BlockStorage` fails instead, with its buffer if it has one
+    fn report_error(&self, error: SdCardError) {
+        if self.block_request.get() {
+            match self.operation.get() {
+                Operation::Erase => {
+                    self.block_client.map(|client| {
+                        client.erase_complete(Err(ErrorCode::FAIL));
+                    });
+                    return;
+                }
+                Operation::Flush => {
+                    self.block_client.map(|client| {
+                        client.flush_complete(Err(ErrorCode::FAIL));
+                    });
+                    return;
+                }
+                Operation::Read | Operation::Write => {
+                    if let Some(buffer) = self.client_buffer.take() {
+                        let operation = self.operation.get();
+                        self.block_client.map(move |client| {
+                            if operation == Operation::Write {
+                                client.write_complete(buffer, Err(ErrorCode::FAIL));
+                            } else {
+                                client.read_complete(buffer, Err(ErrorCode::FAIL));
+                            }
+                        });
+                        return;
+                    }
+                }
+            }
+        }
+
+        self.client.map(move |client| {
+            client.error(error as u32);
+        });
+    }
+
     /// updates SD card state on SPI transaction returns
     fn process_spi_states(
         &self,
@@ -500,9 +698,7 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.map(move |client| {
-                        client.error(SdCardError::InitializationFailure as u32);
-                    });
+                    self.report_error(SdCardError::InitializationFailure);
                 }
             }
 
@@ -570,9 +766,7 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.map(move |client| {
-                        client.error(SdCardError::InitializationFailure as u32);
-                    });
+                    self.report_error(SdCardError::InitializationFailure);
                 }
             }
 
@@ -599,9 +793,7 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.map(move |client| {
-                        client.error(SdCardError::InitializationFailure as u32);
-                    });
+                    self.report_error(SdCardError::InitializationFailure);
                 }
             }
 
@@ -666,9 +858,7 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.map(move |client| {
-                        client.error(SdCardError::InitializationFailure as u32);
-                    });
+                    self.report_error(SdCardError::InitializationFailure);
                 }
             }
 
@@ -704,9 +894,7 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.map(move |client| {
-                        client.error(SdCardError::InitializationFailure as u32);
-                    });
+                    self.report_error(SdCardError::InitializationFailure);
                 }
             }
 
@@ -727,9 +915,7 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.map(move |client| {
-                        client.error(SdCardError::InitializationFailure as u32);
-                    });
+                    self.report_error(SdCardError::InitializationFailure);
                 }
             }
 
@@ -769,19 +955,11 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                             break;
                         }
                     }
+                    self.total_size.set(total_size);
 
-                    // replace buffers
-                    self.txbuffer.replace(write_buffer);
-                    self.rxbuffer.replace(read_buffer);
-
-                    // initialization complete
-                    self.state.set(SpiState::Idle);
-                    self.is_initialized.set(true);
-
-                    // perform callback
-                    self.client.map(move |client| {
-                        client.init_done(512, total_size);
-                    });
+                    // turn on CRC checks of commands and data
+                    self.state.set(SpiState::InitEnableCrc);
+                    self.send_command(SDCmd::CMD59_CrcOnOff, 0x1, write_buffer, read_buffer, 10);
                 } else {
                     // error, send callback and quit
                     self.txbuffer.replace(write_buffer);
@@ -789,12 +967,31 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.map(move |client| {
-                        client.error(SdCardError::InitializationFailure as u32);
-                    });
+                    self.report_error(SdCardError::InitializationFailure);
                 }
             }
 
+            SpiState::InitEnableCrc => {
+                // check response, cards that don't support CRC checks are
+                // still usable without them
+                let (r1, _, _) = self.get_response(SDResponse::R1_Status, read_buffer);
+                self.crc_enabled.set(r1 == SUCCESS_STATUS);
+
+                // replace buffers
+                self.txbuffer.replace(write_buffer);
+                self.rxbuffer.replace(read_buffer);
+
+                // initialization complete
+                self.state.set(SpiState::Idle);
+                self.is_initialized.set(true);
+
+                // perform callback
+                let total_size = self.total_size.get();
+                self.client.map(move |client| {
+                    client.init_done(BLOCK_SIZE as u32, total_size);
+                });
+            }
+
             SpiState::StartReadBlocks { count } => {
                 // check response
                 let (r1, _, _) = self.get_response(SDResponse::R1_Status, read_buffer);
@@ -816,9 +1013,7 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.map(move |client| {
-                        client.error(SdCardError::ReadFailure as u32);
-                    });
+                    self.report_error(SdCardError::ReadFailure);
                 }
             }
 
@@ -846,19 +1041,24 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.map(move |client| {
-                        client.error(SdCardError::ReadFailure as u32);
-                    });
+                    self.report_error(SdCardError::ReadFailure);
                 }
             }
 
             SpiState::ReadBlockComplete => {
+                let crc_valid = self.data_crc_valid(read_buffer);
+
                 // replace buffers
                 self.txbuffer.replace(write_buffer);
                 self.rxbuffer.replace(read_buffer);
 
-                // read finished, perform callback
                 self.state.set(SpiState::Idle);
+                if !crc_valid {
+                    self.report_error(SdCardError::CrcFailure);
+                    return;
+                }
+
+                // read finished, perform callback
                 self.rxbuffer.map(|read_buffer| {
                     self.client_buffer.take().map(move |buffer| {
                         // copy data to user buffer
@@ -872,9 +1072,7 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
 
                         // callback
                         let read_len = cmp::min(read_buffer.len(), cmp::min(buffer.len(), 512));
-                        self.client.map(move |client| {
-                            client.read_done(buffer, read_len);
-                        });
+                        self.read_done(buffer, read_len);
                     });
                 });
             }
@@ -904,13 +1102,18 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.map(move |client| {
-                        client.error(SdCardError::ReadFailure as u32);
-                    });
+                    self.report_error(SdCardError::ReadFailure);
                 }
             }
 
             SpiState::ReceivedBlock { count } => {
+                if !self.data_crc_valid(read_buffer) {
+                    // terminate multiple read and report the error
+                    self.state.set(SpiState::ReadBlocksFailed);
+                    self.send_command(SDCmd::CMD12_StopRead, 0x0, write_buffer, read_buffer, 10);
+                    return;
+                }
+
                 // copy block over to client buffer
                 self.client_buffer.map(|buffer| {
                     // copy block into client buffer
@@ -955,9 +1158,7 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
 
                     // read finished, perform callback
                     self.client_buffer.take().map(move |buffer| {
-                        self.client.map(move |client| {
-                            client.read_done(buffer, self.client_offset.get());
-                        });
+                        self.read_done(buffer, self.client_offset.get());
                     });
                 } else {
                     // error, send callback and quit
@@ -966,63 +1167,27 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.map(move |client| {
-                        client.error(SdCardError::ReadFailure as u32);
-                    });
+                    self.report_error(SdCardError::ReadFailure);
                 }
             }
 
+            SpiState::ReadBlocksFailed => {
+                // replace buffers
+                self.txbuffer.replace(write_buffer);
+                self.rxbuffer.replace(read_buffer);
+                self.state.set(SpiState::Idle);
+                self.alarm_state.set(AlarmState::Idle);
+                self.alarm_count.set(0);
+                self.report_error(SdCardError::CrcFailure);
+            }
+
             SpiState::StartWriteBlocks { count } => {
                 // check response
                 let (r1, _, _) = self.get_response(SDResponse::R1_Status, read_buffer);
 
                 if r1 == SUCCESS_STATUS {
-                    if count <= 1 {
-                        let bytes_written = self.client_buffer.map_or(0, |buffer| {
-                            // copy over data from client buffer
-                            // Limit to minimum length between write_buffer,
-                            // buffer, and 512 (block size)
-                            for (write_byte, &client_byte) in
-                                write_buffer.iter_mut().skip(1).zip(buffer.iter()).take(512)
-                            {
-                                *write_byte = client_byte;
-                            }
-
-                            // calculate number of bytes written
-                            cmp::min(write_buffer.len(), cmp::min(buffer.len(), 512))
-                        });
-
-                        // set a known value for remaining bytes
-                        for write_byte in write_buffer
-                            .iter_mut()
-                            .skip(1)
-                            .skip(bytes_written)
-                            .take(512)
-                        {
-                            *write_byte = 0xFF;
-                        }
-
-                        // set up remainder of data packet
-                        write_buffer[0] = DATA_TOKEN; // Data token
-                        write_buffer[513] = 0xFF; // dummy CRC
-                        write_buffer[514] = 0xFF; // dummy CRC
-
-                        // write data packet
-                        self.state.set(SpiState::WriteBlockResponse);
-                        self.write_bytes(write_buffer, read_buffer, 515);
-                    } else {
-                        // multi-block SD card writes are unimplemented
-                        // This should have returned an error already, but if
-                        // we got here somehow, return an error and quit now
-                        self.txbuffer.replace(write_buffer);
-                        self.rxbuffer.replace(read_buffer);
-                        self.state.set(SpiState::Idle);
-                        self.alarm_state.set(AlarmState::Idle);
-                        self.alarm_count.set(0);
-                        self.client.map(move |client| {
-                            client.error(SdCardError::WriteFailure as u32);
-                        });
-                    }
+                    // write first data packet
+                    self.write_block(write_buffer, read_buffer, count);
                 } else {
                     // error, send callback and quit
                     self.txbuffer.replace(write_buffer);
@@ -1030,22 +1195,21 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.map(move |client| {
-                        client.error(SdCardError::WriteFailure as u32);
-                    });
+                    self.report_error(SdCardError::WriteFailure);
                 }
             }
 
-            SpiState::WriteBlockResponse => {
+            SpiState::WriteBlockResponse { count } => {
                 // Get data packet
-                self.state.set(SpiState::WriteBlockBusy);
+                self.state.set(SpiState::WriteBlockBusy { count });
                 self.read_bytes(write_buffer, read_buffer, 1);
             }
 
-            SpiState::WriteBlockBusy => {
-                if (read_buffer[0] & 0x1F) == 0x05 {
+            SpiState::WriteBlockBusy { count } => {
+                let data_response = read_buffer[0] & 0x1F;
+                if data_response == 0x05 {
                     // check if sd card is busy
-                    self.state.set(SpiState::WaitWriteBlockBusy);
+                    self.state.set(SpiState::WaitWriteBlockBusy { count });
                     self.read_bytes(write_buffer, read_buffer, 1);
                 } else {
                     // error, send callback and quit
@@ -1054,39 +1218,125 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.map(move |client| {
-                        client.error(SdCardError::WriteFailure as u32);
-                    });
+                    if data_response == 0x0B {
+                        // data rejected due to a CRC error
+                        self.report_error(SdCardError::CrcFailure);
+                    } else {
+                        self.report_error(SdCardError::WriteFailure);
+                    }
                 }
             }
 
-            SpiState::WaitWriteBlockBusy => {
+            SpiState::WaitWriteBlockBusy { count } => {
                 // check if line is still held low (busy state)
-                if read_buffer[0] != 0x00 {
+                if read_buffer[0] != 0x00 && count > 1 {
+                    // write next data packet
+                    self.alarm_count.set(0);
+                    self.write_block(write_buffer, read_buffer, count - 1);
+                } else if read_buffer[0] != 0x00 && count == 1 && self.multiple.get() {
+                    // all blocks written. Terminate multiple write, after
+                    // which the card is busy again
+                    self.alarm_count.set(0);
+                    write_buffer[0] = STOP_TRAN_TOKEN;
+                    write_buffer[1] = 0xFF;
+                    self.state.set(SpiState::WriteStopTran);
+                    self.write_bytes(write_buffer, read_buffer, 2);
+                } else if read_buffer[0] != 0x00 {
                     // replace buffers
                     self.txbuffer.replace(write_buffer);
                     self.rxbuffer.replace(read_buffer);
 
-                    // read finished, perform callback
+                    // operation finished, perform callback
                     self.state.set(SpiState::Idle);
                     self.alarm_count.set(0);
-                    self.client_buffer.take().map(move |buffer| {
-                        self.client.map(move |client| {
-                            client.write_done(buffer);
-                        });
-                    });
+                    match self.operation.get() {
+                        Operation::Erase => self.block_client.map(|client| {
+                            client.erase_complete(Ok(()));
+                        }),
+                        Operation::Flush => self.block_client.map(|client| {
+                            client.flush_complete(Ok(()));
+                        }),
+                        Operation::Read | Operation::Write => {
+                            self.client_buffer.take().map(move |buffer| {
+                                self.write_done(buffer);
+                            })
+                        }
+                    };
                 } else {
                     // replace buffers
                     self.txbuffer.replace(write_buffer);
                     self.rxbuffer.replace(read_buffer);
 
                     // try again after 1 ms
-                    self.alarm_state.set(AlarmState::WaitForWriteBusy);
+                    self.alarm_state.set(AlarmState::WaitForWriteBusy { count });
                     let delay = self.alarm.ticks_from_ms(1);
                     self.alarm.set_alarm(self.alarm.now(), delay);
                 }
             }
 
+            SpiState::WriteStopTran => {
+                // check if sd card is busy, with no blocks left to write
+                self.state.set(SpiState::WaitWriteBlockBusy { count: 0 });
+                self.read_bytes(write_buffer, read_buffer, 1);
+            }
+
+            SpiState::EraseStart { end } => {
+                // check response
+                let (r1, _, _) = self.get_response(SDResponse::R1_Status, read_buffer);
+
+                if r1 == SUCCESS_STATUS {
+                    // set last block to erase
+                    self.state.set(SpiState::EraseEnd);
+                    self.send_command(SDCmd::CMD33_EraseEnd, end, write_buffer, read_buffer, 10);
+                } else {
+                    // error, send callback and quit
+                    self.txbuffer.replace(write_buffer);
+                    self.rxbuffer.replace(read_buffer);
+                    self.state.set(SpiState::Idle);
+                    self.alarm_state.set(AlarmState::Idle);
+                    self.alarm_count.set(0);
+                    self.report_error(SdCardError::EraseFailure);
+                }
+            }
+
+            SpiState::EraseEnd => {
+                // check response
+                let (r1, _, _) = self.get_response(SDResponse::R1_Status, read_buffer);
+
+                if r1 == SUCCESS_STATUS {
+                    // erase the selected blocks
+                    self.state.set(SpiState::Erase);
+                    self.send_command(SDCmd::CMD38_Erase, 0x0, write_buffer, read_buffer, 10);
+                } else {
+                    // error, send callback and quit
+                    self.txbuffer.replace(write_buffer);
+                    self.rxbuffer.replace(read_buffer);
+                    self.state.set(SpiState::Idle);
+                    self.alarm_state.set(AlarmState::Idle);
+                    self.alarm_count.set(0);
+                    self.report_error(SdCardError::EraseFailure);
+                }
+            }
+
+            SpiState::Erase => {
+                // check response
+                let (r1, _, _) = self.get_response(SDResponse::R1_Status, read_buffer);
+
+                if r1 == SUCCESS_STATUS {
+                    // check if sd card is busy erasing
+                    self.state.set(SpiState::WaitWriteBlockBusy { count: 0 });
+                    self.read_bytes(write_buffer, read_buffer, 1);
+                } else {
+                    // error, send callback and quit
+                    self.txbuffer.replace(write_buffer);
+                    self.rxbuffer.replace(read_buffer);
+                    self.state.set(SpiState::Idle);
+                    self.alarm_state.set(AlarmState::Idle);
+                    self.alarm_count.set(0);
+                    self.report_error(SdCardError::EraseFailure);
+                }
+            }
+
             SpiState::Idle => {
                 // receiving an event from Idle means something was killed
 
@@ -1106,9 +1356,7 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
             self.state.set(SpiState::Idle);
             self.alarm_state.set(AlarmState::Idle);
             self.alarm_count.set(0);
-            self.client.map(move |client| {
-                client.error(SdCardError::TimeoutFailure as u32);
-            });
+            self.report_error(SdCardError::TimeoutFailure);
         } else {
             self.alarm_count.set(repeats + 1);
         }
@@ -1211,12 +1459,12 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                 self.alarm_state.set(AlarmState::Idle);
             }
 
-            AlarmState::WaitForWriteBusy => {
+            AlarmState::WaitForWriteBusy { count } => {
                 // check card initialization again
                 self.txbuffer.take().map(|write_buffer| {
                     self.rxbuffer.take().map(move |read_buffer| {
                         // check if sd card is busy
-                        self.state.set(SpiState::WaitWriteBlockBusy);
+                        self.state.set(SpiState::WaitWriteBlockBusy { count });
                         self.read_bytes(write_buffer, read_buffer, 1);
                     });
                 });
@@ -1257,6 +1505,8 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
     pub fn initialize(&self) -> Result<(), ErrorCode> {
         // if not already, set card to uninitialized again
         self.is_initialized.set(false);
+        self.crc_enabled.set(false);
+        self.block_request.set(false);
 
         // no point in initializing if the card is not installed
         if self.is_installed() {
@@ -1286,111 +1536,208 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
         sector: u32,
         count: u32,
     ) -> Result<(), ErrorCode> {
+        self.start_transfer(buffer, sector, count, false)
+            .map(|()| self.block_request.set(false))
+            .map_err(|(e, _)| e)
+    }
+
+    pub fn write_blocks(
+        &self,
+        buffer: &'static mut [u8],
+        sector: u32,
+        count: u32,
+    ) -> Result<(), ErrorCode> {
+        self.start_transfer(buffer, sector, count, true)
+            .map(|()| self.block_request.set(false))
+            .map_err(|(e, _)| e)
+    }
+
+    /// start reading or writing `count` blocks from `sector`, returning the
+    /// buffer on failure
+    fn start_transfer(
+        &self,
+        buffer: &'static mut [u8],
+        sector: u32,
+        count: u32,
+        write: bool,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
         // only if initialized and installed
-        if self.is_installed() {
-            if self.is_initialized() {
-                self.txbuffer
-                    .take()
-                    .map_or(Err(ErrorCode::NOMEM), |txbuffer| {
-                        self.rxbuffer
-                            .take()
-                            .map_or(Err(ErrorCode::NOMEM), move |rxbuffer| {
-                                // save the user buffer for later
-                                self.client_buffer.replace(buffer);
-                                self.client_offset.set(0);
-
-                                // convert block address to byte address for non-block
-                                //  access cards
-                                let mut address = sector;
-                                if self.card_type.get() != SDCardType::SDv2BlockAddressable {
-                                    address *= 512;
-                                }
+        if !self.is_installed() {
+            // sd card not installed
+            return Err((ErrorCode::UNINSTALLED, buffer));
+        }
+        if !self.is_initialized() {
+            // sd card not initialized
+            return Err((ErrorCode::RESERVE, buffer));
+        }
+        let (txbuffer, rxbuffer) = match (self.txbuffer.take(), self.rxbuffer.take()) {
+            (Some(txbuffer), Some(rxbuffer)) => (txbuffer, rxbuffer),
+            (txbuffer, rxbuffer) => {
+                txbuffer.map(|txbuffer| self.txbuffer.replace(txbuffer));
+                rxbuffer.map(|rxbuffer| self.rxbuffer.replace(rxbuffer));
+                return Err((ErrorCode::NOMEM, buffer));
+            }
+        };
 
-                                self.state.set(SpiState::StartReadBlocks { count: count });
-                                if count == 1 {
-                                    self.send_command(
-                                        SDCmd::CMD17_ReadSingle,
-                                        address,
-                                        txbuffer,
-                                        rxbuffer,
-                                        10,
-                                    );
-                                } else {
-                                    self.send_command(
-                                        SDCmd::CMD18_ReadMultiple,
-                                        address,
-                                        txbuffer,
-                                        rxbuffer,
-                                        10,
-                                    );
-                                }
+        // save the user buffer for later
+        self.client_buffer.replace(buffer);
+        self.client_offset.set(0);
+        if write {
+            self.operation.set(Operation::Write);
+        } else {
+            self.operation.set(Operation::Read);
+        }
+        self.multiple.set(count > 1);
 
-                                // command started successfully
-                                Ok(())
-                            })
-                    })
+        // convert block address to byte address for non-block access cards
+        let mut address = sector;
+        if self.card_type.get() != SDCardType::SDv2BlockAddressable {
+            address *= BLOCK_SIZE as u32;
+        }
+
+        let cmd = if write {
+            self.state.set(SpiState::StartWriteBlocks { count });
+            if count == 1 {
+                SDCmd::CMD24_WriteSingle
             } else {
-                // sd card not initialized
-                Err(ErrorCode::RESERVE)
+                SDCmd::CMD25_WriteMultiple
             }
         } else {
-            // sd card not installed
-            Err(ErrorCode::UNINSTALLED)
+            self.state.set(SpiState::StartReadBlocks { count });
+            if count == 1 {
+                SDCmd::CMD17_ReadSingle
+            } else {
+                SDCmd::CMD18_ReadMultiple
+            }
+        };
+        self.send_command(cmd, address, txbuffer, rxbuffer, 10);
+
+        // command started successfully
+        Ok(())
+    }
+
+    /// check a `BlockStorage` transfer before starting it
+    fn check_blocks(
+        &self,
+        buffer: &'static mut [u8],
+        block: u64,
+        count: usize,
+    ) -> Result<&'static mut [u8], (ErrorCode, &'static mut [u8])> {
+        if let Err(e) = self.check_range(block, count) {
+            Err((e, buffer))
+        } else if buffer.len() < count * BLOCK_SIZE {
+            Err((ErrorCode::SIZE, buffer))
+        } else {
+            Ok(buffer)
         }
     }
 
-    pub fn write_blocks(
+    /// check that no operation runs and that the blocks are on the card
+    fn check_range(&self, block: u64, count: usize) -> Result<(), ErrorCode> {
+        if self.state.get() != SpiState::Idle || self.txbuffer.is_none() {
+            Err(ErrorCode::BUSY)
+        } else if count == 0 || block + count as u64 > self.block_count() {
+            Err(ErrorCode::INVAL)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Access to the SD card for other capsules
+impl<'a, A: hil::time::Alarm<'a>> BlockStorage<'a> for SDCard<'a, A> {
+    fn set_client(&self, client: &'a dyn BlockStorageClient) {
+        self.block_client.set(client);
+    }
+
+    fn block_size(&self) -> usize {
+        BLOCK_SIZE
+    }
+
+    fn block_count(&self) -> u64 {
+        if self.is_initialized() {
+            self.total_size.get() / BLOCK_SIZE as u64
+        } else {
+            0
+        }
+    }
+
+    fn read_blocks(
         &self,
         buffer: &'static mut [u8],
-        sector: u32,
-        count: u32,
-    ) -> Result<(), ErrorCode> {
-        // only if initialized and installed
-        if self.is_installed() {
-            if self.is_initialized() {
-                self.txbuffer
+        block: u64,
+        count: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        let buffer = self.check_blocks(buffer, block, count)?;
+        self.start_transfer(buffer, block as u32, count as u32, false)
+            .map(|()| self.block_request.set(true))
+    }
+
+    fn write_blocks(
+        &self,
+        buffer: &'static mut [u8],
+        block: u64,
+        count: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        let buffer = self.check_blocks(buffer, block, count)?;
+        self.start_transfer(buffer, block as u32, count as u32, true)
+            .map(|()| self.block_request.set(true))
+    }
+
+    fn erase_blocks(&self, block: u64, count: usize) -> Result<(), ErrorCode> {
+        self.check_range(block, count)?;
+        if self.card_type.get() == SDCardType::MMC {
+            // MMC cards use different commands to select the blocks to erase
+            return Err(ErrorCode::NOSUPPORT);
+        }
+
+        self.txbuffer
+            .take()
+            .map_or(Err(ErrorCode::NOMEM), |txbuffer| {
+                self.rxbuffer
                     .take()
-                    .map_or(Err(ErrorCode::NOMEM), |txbuffer| {
-                        self.rxbuffer
-                            .take()
-                            .map_or(Err(ErrorCode::NOMEM), move |rxbuffer| {
-                                // save the user buffer for later
-                                self.client_buffer.replace(buffer);
-                                self.client_offset.set(0);
-
-                                // convert block address to byte address for non-block
-                                //  access cards
-                                let mut address = sector;
-                                if self.card_type.get() != SDCardType::SDv2BlockAddressable {
-                                    address *= 512;
-                                }
+                    .map_or(Err(ErrorCode::NOMEM), move |rxbuffer| {
+                        self.operation.set(Operation::Erase);
+                        self.block_request.set(true);
+
+                        // convert block address to byte address for non-block
+                        //  access cards
+                        let mut start = block as u32;
+                        let mut end = start + count as u32 - 1;
+                        if self.card_type.get() != SDCardType::SDv2BlockAddressable {
+                            start *= BLOCK_SIZE as u32;
+                            end *= BLOCK_SIZE as u32;
+                        }
 
-                                self.state.set(SpiState::StartWriteBlocks { count: count });
-                                if count == 1 {
-                                    self.send_command(
-                                        SDCmd::CMD24_WriteSingle,
-                                        address,
-                                        txbuffer,
-                                        rxbuffer,
-                                        10,
-                                    );
-
-                                    // command started successfully
-                                    Ok(())
-                                } else {
-                                    // can't write multiple blocks yet
-                                    Err(ErrorCode::NOSUPPORT)
-                                }
-                            })
+                        self.state.set(SpiState::EraseStart { end });
+                        self.send_command(SDCmd::CMD32_EraseStart, start, txbuffer, rxbuffer, 10);
+                        Ok(())
                     })
-            } else {
-                // sd card not initialized
-                Err(ErrorCode::RESERVE)
-            }
-        } else {
-            // sd card not installed
-            Err(ErrorCode::UNINSTALLED)
+            })
+    }
+
+    fn flush(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != SpiState::Idle || self.txbuffer.is_none() {
+            return Err(ErrorCode::BUSY);
+        }
+        if !self.is_initialized() {
+            return Err(ErrorCode::RESERVE);
         }
+
+        // writes are durable once the card is no longer busy programming them
+        self.txbuffer
+            .take()
+            .map_or(Err(ErrorCode::NOMEM), |txbuffer| {
+                self.rxbuffer
+                    .take()
+                    .map_or(Err(ErrorCode::NOMEM), move |rxbuffer| {
+                        self.operation.set(Operation::Flush);
+                        self.block_request.set(true);
+                        self.state.set(SpiState::WaitWriteBlockBusy { count: 0 });
+                        self.read_bytes(txbuffer, rxbuffer, 1);
+                        Ok(())
+                    })
+            })
     }
 }
 
@@ -1426,9 +1773,7 @@ impl<'a, A: hil::time::Alarm<'a>> hil::gpio::Client for SDCard<'a, A> {
             //  send an error callback
             self.state.set(SpiState::Idle);
             self.alarm_state.set(AlarmState::Idle);
-            self.client.map(move |client| {
-                client.error(SdCardError::CardStateChanged as u32);
-            });
+            self.report_error(SdCardError::CardStateChanged);
         }
 
         // either the card is new or gone, in either case it isn't initialized
@@ -1663,3 +2008,366 @@ impl<'a, A: hil::time::Alarm<'a>> SyscallDriver for SDCardDriver<'a, A> {
         self.grants.enter(processid, |_, _| {})
     }
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use core::cell::RefCell;
+    use hil::spi::{ClockPhase, ClockPolarity, SpiMasterClient, SpiMasterDevice};
+    use hil::time::{Alarm, AlarmClient, Freq1KHz, Ticks32, Time};
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    const NUM_BLOCKS: usize = 8;
+
+    #[test]
+    fn crc7_of_commands() {
+        // Command bytes with their CRC from the SD specification
+        let command = |cmd: u8, arg: u32| {
+            let mut bytes = [0x40 | cmd, 0, 0, 0, 0];
+            bytes[1..].copy_from_slice(&arg.to_be_bytes());
+            (crc7(&bytes) << 1) | 0x01
+        };
+        assert_eq!(command(0, 0), 0x95);
+        assert_eq!(command(8, 0x1AA), 0x87);
+        assert_eq!(command(17, 0), 0x55);
+        assert_eq!(command(55, 0), 0x65);
+        assert_eq!(command(41, 0x4000_0000), 0x77);
+    }
+
+    #[test]
+    fn crc16_of_blocks() {
+        assert_eq!(crc16(&[0xFF; BLOCK_SIZE]), 0x7FA1);
+        assert_eq!(crc16(b"123456789"), 0x31C3);
+    }
+
+    #[derive(Copy, Clone, PartialEq, Debug)]
+    enum Mode {
+        Idle,
+        Write { block: usize },
+        Read { block: usize },
+    }
+
+    /// A card behind the SPI bus, that answers every transaction when
+    /// `run()` is called.
+    struct FakeCard {
+        blocks: RefCell<[[u8; BLOCK_SIZE]; NUM_BLOCKS]>,
+        mode: Cell<Mode>,
+        /// The data response to the next byte read after a data packet
+        data_response: Cell<Option<u8>>,
+        /// Reject the data packet of this block
+        reject: Cell<Option<usize>>,
+        /// Send this block with a wrong CRC
+        corrupt: Cell<Option<usize>>,
+        commands: RefCell<Vec<(u8, u32)>>,
+        tokens: RefCell<Vec<u8>>,
+        transfer: TakeCell<'static, [u8]>,
+        receive: TakeCell<'static, [u8]>,
+        len: Cell<usize>,
+        client: OptionalCell<&'static dyn SpiMasterClient>,
+    }
+
+    impl FakeCard {
+        fn transact(&self, write: &[u8], read: &mut [u8]) {
+            read.fill(0xFF);
+            let len = write.len();
+            if len >= 8 && write[..2] == [0xFF, 0xFF] && write[2] & 0xC0 == 0x40 {
+                let cmd = write[2] & 0x3F;
+                let arg = u32::from_be_bytes([write[3], write[4], write[5], write[6]]);
+                self.commands.borrow_mut().push((cmd, arg));
+                if write[7] != (crc7(&write[2..7]) << 1) | 0x01 {
+                    // Command CRC error
+                    read[8] = 0x08;
+                    return;
+                }
+                read[8] = SUCCESS_STATUS;
+                self.mode.set(match cmd {
+                    17 | 18 => Mode::Read {
+                        block: arg as usize,
+                    },
+                    24 | 25 => Mode::Write {
+                        block: arg as usize,
+                    },
+                    _ => Mode::Idle,
+                });
+            } else if len == BLOCK_SIZE + 3 {
+                let block = match self.mode.get() {
+                    Mode::Write { block } => block,
+                    mode => panic!("data packet in {:?}", mode),
+                };
+                self.tokens.borrow_mut().push(write[0]);
+                let data = &write[1..BLOCK_SIZE + 1];
+                let crc = u16::from_be_bytes([write[BLOCK_SIZE + 1], write[BLOCK_SIZE + 2]]);
+                if crc != crc16(data) || self.reject.get() == Some(block) {
+                    self.data_response.set(Some(0x0B));
+                } else {
+                    self.blocks.borrow_mut()[block].copy_from_slice(data);
+                    self.data_response.set(Some(0x05));
+                    self.mode.set(Mode::Write { block: block + 1 });
+                }
+            } else if len == 2 && write[0] == STOP_TRAN_TOKEN {
+                self.tokens.borrow_mut().push(write[0]);
+                self.mode.set(Mode::Idle);
+            } else if len == 1 {
+                if let Some(response) = self.data_response.take() {
+                    read[0] = response;
+                } else if let Mode::Read { .. } = self.mode.get() {
+                    read[0] = DATA_TOKEN;
+                }
+            } else if len == BLOCK_SIZE + 2 {
+                let block = match self.mode.get() {
+                    Mode::Read { block } => block,
+                    mode => panic!("data read in {:?}", mode),
+                };
+                read[..BLOCK_SIZE].copy_from_slice(&self.blocks.borrow()[block]);
+                let mut crc = crc16(&read[..BLOCK_SIZE]);
+                if self.corrupt.get() == Some(block) {
+                    crc ^= 1;
+                }
+                read[BLOCK_SIZE..BLOCK_SIZE + 2].copy_from_slice(&crc.to_be_bytes());
+                self.mode.set(Mode::Read { block: block + 1 });
+            } else {
+                panic!("unexpected transaction of {} bytes", len);
+            }
+        }
+
+        fn run(&self) {
+            while let Some(write) = self.transfer.take() {
+                let read = self.receive.take();
+                let len = self.len.get();
+                self.client
+                    .map(|client| client.read_write_done(write, read, len, Ok(())));
+            }
+        }
+    }
+
+    impl SpiMasterDevice for FakeCard {
+        fn set_client(&self, client: &'static dyn SpiMasterClient) {
+            self.client.set(client);
+        }
+
+        fn configure(&self, _: ClockPolarity, _: ClockPhase, _: u32) -> Result<(), ErrorCode> {
+            Ok(())
+        }
+
+        fn read_write_bytes(
+            &self,
+            write_buffer: &'static mut [u8],
+            read_buffer: Option<&'static mut [u8]>,
+            len: usize,
+        ) -> Result<(), (ErrorCode, &'static mut [u8], Option<&'static mut [u8]>)> {
+            let read_buffer = read_buffer.unwrap();
+            self.transact(&write_buffer[..len], &mut read_buffer[..len]);
+            self.transfer.replace(write_buffer);
+            self.receive.replace(read_buffer);
+            self.len.set(len);
+            Ok(())
+        }
+
+        fn set_rate(&self, _: u32) -> Result<(), ErrorCode> {
+            Ok(())
+        }
+
+        fn get_rate(&self) -> u32 {
+            4_000_000
+        }
+
+        fn set_polarity(&self, _: ClockPolarity) -> Result<(), ErrorCode> {
+            Ok(())
+        }
+
+        fn get_polarity(&self) -> ClockPolarity {
+            ClockPolarity::IdleLow
+        }
+
+        fn set_phase(&self, _: ClockPhase) -> Result<(), ErrorCode> {
+            Ok(())
+        }
+
+        fn get_phase(&self) -> ClockPhase {
+            ClockPhase::SampleLeading
+        }
+    }
+
+    /// The card never keeps the driver waiting, so the alarm never fires.
+    struct FakeAlarm;
+
+    impl Time for FakeAlarm {
+        type Frequency = Freq1KHz;
+        type Ticks = Ticks32;
+
+        fn now(&self) -> Ticks32 {
+            0u32.into()
+        }
+    }
+
+    impl<'a> Alarm<'a> for FakeAlarm {
+        fn set_alarm_client(&self, _: &'a dyn AlarmClient) {}
+
+        fn set_alarm(&self, _: Ticks32, _: Ticks32) {
+            panic!("the driver waited for the card");
+        }
+
+        fn get_alarm(&self) -> Ticks32 {
+            0u32.into()
+        }
+
+        fn disarm(&self) -> Result<(), ErrorCode> {
+            Ok(())
+        }
+
+        fn is_armed(&self) -> bool {
+            false
+        }
+
+        fn minimum_dt(&self) -> Ticks32 {
+            Ticks32::from(1)
+        }
+    }
+
+    struct TestClient {
+        result: Cell<Option<Result<(), ErrorCode>>>,
+        buffer: TakeCell<'static, [u8]>,
+    }
+
+    impl BlockStorageClient for TestClient {
+        fn read_complete(&self, buffer: &'static mut [u8], result: Result<(), ErrorCode>) {
+            self.result.set(Some(result));
+            self.buffer.replace(buffer);
+        }
+
+        fn write_complete(&self, buffer: &'static mut [u8], result: Result<(), ErrorCode>) {
+            self.result.set(Some(result));
+            self.buffer.replace(buffer);
+        }
+
+        fn erase_complete(&self, result: Result<(), ErrorCode>) {
+            self.result.set(Some(result));
+        }
+
+        fn flush_complete(&self, result: Result<(), ErrorCode>) {
+            self.result.set(Some(result));
+        }
+    }
+
+    fn setup() -> (
+        &'static FakeCard,
+        &'static SDCard<'static, FakeAlarm>,
+        &'static TestClient,
+    ) {
+        let card = Box::leak(Box::new(FakeCard {
+            blocks: RefCell::new([[0; BLOCK_SIZE]; NUM_BLOCKS]),
+            mode: Cell::new(Mode::Idle),
+            data_response: Cell::new(None),
+            reject: Cell::new(None),
+            corrupt: Cell::new(None),
+            commands: RefCell::new(Vec::new()),
+            tokens: RefCell::new(Vec::new()),
+            transfer: TakeCell::empty(),
+            receive: TakeCell::empty(),
+            len: Cell::new(0),
+            client: OptionalCell::empty(),
+        }));
+        let sdcard = Box::leak(Box::new(SDCard::new(
+            &*card,
+            Box::leak(Box::new(FakeAlarm)),
+            None,
+            Box::leak(Box::new([0; 515])),
+            Box::leak(Box::new([0; 515])),
+        )));
+        let client = Box::leak(Box::new(TestClient {
+            result: Cell::new(None),
+            buffer: TakeCell::empty(),
+        }));
+        card.set_client(sdcard);
+        BlockStorage::set_client(sdcard, client);
+
+        // An SDHC card that was initialized with CRCs turned on
+        sdcard.is_initialized.set(true);
+        sdcard.card_type.set(SDCardType::SDv2BlockAddressable);
+        sdcard.total_size.set((NUM_BLOCKS * BLOCK_SIZE) as u64);
+        sdcard.crc_enabled.set(true);
+        (card, sdcard, client)
+    }
+
+    fn pattern(len: usize) -> &'static mut [u8] {
+        let data: Vec<u8> = (0..len).map(|i| (i * 7 + i / BLOCK_SIZE) as u8).collect();
+        Box::leak(data.into_boxed_slice())
+    }
+
+    #[test]
+    fn writes_multiple_blocks() {
+        let (card, sdcard, client) = setup();
+        assert!(BlockStorage::write_blocks(sdcard, pattern(3 * BLOCK_SIZE), 2, 3).is_ok());
+        card.run();
+
+        assert_eq!(client.result.get(), Some(Ok(())));
+        assert_eq!(*card.commands.borrow(), [(25, 2)]);
+        assert_eq!(
+            *card.tokens.borrow(),
+            [
+                WRITE_MULTIPLE_TOKEN,
+                WRITE_MULTIPLE_TOKEN,
+                WRITE_MULTIPLE_TOKEN,
+                STOP_TRAN_TOKEN
+            ]
+        );
+        let data = client.buffer.take().unwrap();
+        for (i, block) in data.chunks(BLOCK_SIZE).enumerate() {
+            assert_eq!(card.blocks.borrow()[2 + i], block);
+        }
+        assert_eq!(card.blocks.borrow()[5], [0; BLOCK_SIZE]);
+    }
+
+    #[test]
+    fn writes_single_block() {
+        let (card, sdcard, client) = setup();
+        assert!(BlockStorage::write_blocks(sdcard, pattern(BLOCK_SIZE), 7, 1).is_ok());
+        card.run();
+
+        assert_eq!(client.result.get(), Some(Ok(())));
+        assert_eq!(*card.commands.borrow(), [(24, 7)]);
+        assert_eq!(*card.tokens.borrow(), [DATA_TOKEN]);
+        assert_eq!(card.blocks.borrow()[7], *client.buffer.take().unwrap());
+    }
+
+    #[test]
+    fn rejected_block_fails_write() {
+        let (card, sdcard, client) = setup();
+        card.reject.set(Some(3));
+        assert!(BlockStorage::write_blocks(sdcard, pattern(3 * BLOCK_SIZE), 2, 3).is_ok());
+        card.run();
+
+        assert_eq!(client.result.get(), Some(Err(ErrorCode::FAIL)));
+        assert!(client.buffer.take().is_some());
+        assert_eq!(card.tokens.borrow().len(), 2);
+
+        // The driver is ready for the next operation
+        assert!(BlockStorage::flush(sdcard).is_ok());
+        card.run();
+        assert_eq!(client.result.get(), Some(Ok(())));
+    }
+
+    #[test]
+    fn reads_multiple_blocks_and_checks_crc() {
+        let (card, sdcard, client) = setup();
+        assert!(BlockStorage::write_blocks(sdcard, pattern(2 * BLOCK_SIZE), 0, 2).is_ok());
+        card.run();
+        let written = client.buffer.take().unwrap().to_vec();
+
+        assert!(BlockStorage::read_blocks(sdcard, pattern(2 * BLOCK_SIZE), 0, 2).is_ok());
+        card.run();
+        assert_eq!(client.result.get(), Some(Ok(())));
+        assert_eq!(*client.buffer.take().unwrap(), written[..]);
+        assert_eq!(card.commands.borrow()[1..], [(18, 0), (12, 0)]);
+
+        card.corrupt.set(Some(1));
+        assert!(BlockStorage::read_blocks(sdcard, pattern(2 * BLOCK_SIZE), 0, 2).is_ok());
+        card.run();
+        assert_eq!(client.result.get(), Some(Err(ErrorCode::FAIL)));
+        assert!(client.buffer.take().is_some());
+        assert_eq!(card.mode.get(), Mode::Idle);
+    }
+}
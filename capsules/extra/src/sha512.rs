@@ -0,0 +1,266 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Software implementation of SHA-512.
+//!
+//! Implementation is based on FIPS 180-4. It performs the hash using
+//! 64-bit native values, translating the input data into the
+//! endianness of the processor and translating the output into big
+//! endian format.
+//!
+//! Buffering, chunking through the kernel work queue and the client
+//! interface are shared with SHA-256 in `sha_software`.
+
+use kernel::hil::digest::Sha512;
+use kernel::ErrorCode;
+
+use crate::sha_software::{ShaCore, ShaSoftware};
+
+const SHA_BLOCK_LEN_BYTES: usize = 128;
+const SHA_512_OUTPUT_LEN_BYTES: usize = 64;
+const NUM_ROUND_CONSTANTS: usize = 80;
+
+const INITIAL_HASH_VALUES: [u64; 8] = [
+    0x6a09e667f3bcc908,
+    0xbb67ae8584caa73b,
+    0x3c6ef372fe94f82b,
+    0xa54ff53a5f1d36f1,
+    0x510e527fade682d1,
+    0x9b05688c2b3e6c1f,
+    0x1f83d9abfb41bd6b,
+    0x5be0cd19137e2179,
+];
+
+const ROUND_CONSTANTS: [u64; NUM_ROUND_CONSTANTS] = [
+    0x428a2f98d728ae22,
+    0x7137449123ef65cd,
+    0xb5c0fbcfec4d3b2f,
+    0xe9b5dba58189dbbc,
+    0x3956c25bf348b538,
+    0x59f111f1b605d019,
+    0x923f82a4af194f9b,
+    0xab1c5ed5da6d8118,
+    0xd807aa98a3030242,
+    0x12835b0145706fbe,
+    0x243185be4ee4b28c,
+    0x550c7dc3d5ffb4e2,
+    0x72be5d74f27b896f,
+    0x80deb1fe3b1696b1,
+    0x9bdc06a725c71235,
+    0xc19bf174cf692694,
+    0xe49b69c19ef14ad2,
+    0xefbe4786384f25e3,
+    0x0fc19dc68b8cd5b5,
+    0x240ca1cc77ac9c65,
+    0x2de92c6f592b0275,
+    0x4a7484aa6ea6e483,
+    0x5cb0a9dcbd41fbd4,
+    0x76f988da831153b5,
+    0x983e5152ee66dfab,
+    0xa831c66d2db43210,
+    0xb00327c898fb213f,
+    0xbf597fc7beef0ee4,
+    0xc6e00bf33da88fc2,
+    0xd5a79147930aa725,
+    0x06ca6351e003826f,
+    0x142929670a0e6e70,
+    0x27b70a8546d22ffc,
+    0x2e1b21385c26c926,
+    0x4d2c6dfc5ac42aed,
+    0x53380d139d95b3df,
+    0x650a73548baf63de,
+    0x766a0abb3c77b2a8,
+    0x81c2c92e47edaee6,
+    0x92722c851482353b,
+    0xa2bfe8a14cf10364,
+    0xa81a664bbc423001,
+    0xc24b8b70d0f89791,
+    0xc76c51a30654be30,
+    0xd192e819d6ef5218,
+    0xd69906245565a910,
+    0xf40e35855771202a,
+    0x106aa07032bbd1b8,
+    0x19a4c116b8d2d0c8,
+    0x1e376c085141ab53,
+    0x2748774cdf8eeb99,
+    0x34b0bcb5e19b48a8,
+    0x391c0cb3c5c95a63,
+    0x4ed8aa4ae3418acb,
+    0x5b9cca4f7763e373,
+    0x682e6ff3d6b2b8a3,
+    0x748f82ee5defb2fc,
+    0x78a5636f43172f60,
+    0x84c87814a1f0ab72,
+    0x8cc702081a6439ec,
+    0x90befffa23631e28,
+    0xa4506cebde82bde9,
+    0xbef9a3f7b2c67915,
+    0xc67178f2e372532b,
+    0xca273eceea26619c,
+    0xd186b8c721c0c207,
+    0xeada7dd6cde0eb1e,
+    0xf57d4f7fee6ed178,
+    0x06f067aa72176fba,
+    0x0a637dc5a2c898a6,
+    0x113f9804bef90dae,
+    0x1b710b35131c471b,
+    0x28db77f523047d84,
+    0x32caab7b40c72493,
+    0x3c9ebe0a15c9bebc,
+    0x431d67c49c100d4c,
+    0x4cc5d4becb3e42b6,
+    0x597f299cfc657e2a,
+    0x5fcb6fab3ad6faec,
+    0x6c44198c4a475817,
+];
+
+/// The SHA-512 compression function.
+pub struct Sha512Core;
+
+pub type Sha512Software<'a> =
+    ShaSoftware<'a, Sha512Core, SHA_BLOCK_LEN_BYTES, SHA_512_OUTPUT_LEN_BYTES>;
+
+impl ShaCore<SHA_BLOCK_LEN_BYTES, SHA_512_OUTPUT_LEN_BYTES> for Sha512Core {
+    type HashValues = [u64; 8];
+
+    const INITIAL_HASH_VALUES: [u64; 8] = INITIAL_HASH_VALUES;
+    const LENGTH_LEN: usize = 16;
+    // 64-bit arithmetic makes a block take several times as long as a
+    // SHA-256 block on 32-bit processors.
+    const BLOCKS_PER_CHUNK: usize = 4;
+
+    fn compress(hash_values: &mut [u64; 8], block: &[u8; SHA_BLOCK_LEN_BYTES]) {
+        let mut message_schedule: [u64; 80] = [0; 80];
+        for i in 0..16 {
+            let mut word = [0; 8];
+            word.copy_from_slice(&block[i * 8..i * 8 + 8]);
+            message_schedule[i] = u64::from_be_bytes(word);
+        }
+
+        // Message schedule
+        for i in 16..80 {
+            let w15 = message_schedule[i - 15];
+            let w2 = message_schedule[i - 2];
+            let s0 = w15.rotate_right(1) ^ w15.rotate_right(8) ^ (w15 >> 7);
+            let s1 = w2.rotate_right(19) ^ w2.rotate_right(61) ^ (w2 >> 6);
+            message_schedule[i] = message_schedule[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(message_schedule[i - 7])
+                .wrapping_add(s1);
+        }
+
+        // Compression
+        let mut hashes = *hash_values;
+        for i in 0..80 {
+            let s1 = hashes[4].rotate_right(14)
+                ^ hashes[4].rotate_right(18)
+                ^ hashes[4].rotate_right(41);
+            let ch = (hashes[4] & hashes[5]) ^ ((!hashes[4]) & hashes[6]);
+            let temp1 = hashes[7]
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(ROUND_CONSTANTS[i])
+                .wrapping_add(message_schedule[i]);
+            let s0 = hashes[0].rotate_right(28)
+                ^ hashes[0].rotate_right(34)
+                ^ hashes[0].rotate_right(39);
+            let maj = (hashes[0] & hashes[1]) ^ (hashes[0] & hashes[2]) ^ (hashes[1] & hashes[2]);
+            let temp2 = s0.wrapping_add(maj);
+
+            hashes[7] = hashes[6];
+            hashes[6] = hashes[5];
+            hashes[5] = hashes[4];
+            hashes[4] = hashes[3].wrapping_add(temp1);
+            hashes[3] = hashes[2];
+            hashes[2] = hashes[1];
+            hashes[1] = hashes[0];
+            hashes[0] = temp1.wrapping_add(temp2);
+        }
+
+        for i in 0..8 {
+            hash_values[i] = hash_values[i].wrapping_add(hashes[i]);
+        }
+    }
+
+    fn output(hash_values: &[u64; 8]) -> [u8; SHA_512_OUTPUT_LEN_BYTES] {
+        let mut bytes = [0; SHA_512_OUTPUT_LEN_BYTES];
+        for (chunk, val) in bytes.chunks_mut(8).zip(hash_values) {
+            chunk.copy_from_slice(&val.to_be_bytes());
+        }
+        bytes
+    }
+}
+
+impl Sha512 for Sha512Software<'_> {
+    /// Call before adding data to perform Sha512
+    fn set_mode_sha512(&self) -> Result<(), ErrorCode> {
+        self.set_hash_mode()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sha_software::from_hex;
+
+    static A_MILLION: [u8; 1_000_000] = [b'a'; 1_000_000];
+
+    #[test]
+    fn sha512_nist_vectors() {
+        // FIPS 180-2, appendix C.
+        let sha = Sha512Software::new();
+        assert_eq!(
+            sha.digest_in_chunks(b"abc"),
+            from_hex(
+                "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+                 2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f"
+            )
+        );
+        assert_eq!(
+            sha.digest_in_chunks(
+                b"abcdefghbcdefghicdefghijdefghijkefghijklfghijklmghijklmn\
+                  hijklmnoijklmnopjklmnopqklmnopqrlmnopqrsmnopqrstnopqrstu"
+            ),
+            from_hex(
+                "8e959b75dae313da8cf4f72814fc143f8f7779c6eb9f7fa17299aeadb6889018\
+                 501d289e4900f7e4331b99dec4b5433ac7d329eeb6dd26545e96e55b874be909"
+            )
+        );
+        // Takes many chunks of work.
+        assert_eq!(
+            sha.digest_in_chunks(&A_MILLION),
+            from_hex(
+                "e718483d0ce769644e2e42c7bc15b4638e1f98b13b2044285632a803afa973eb\
+                 de0ff244877ea60a4cb0432ce577c31beb009c5c2c49aa2e4eadb217ad8cc09b"
+            )
+        );
+    }
+
+    #[test]
+    fn sha512_padding_at_block_boundaries() {
+        // 111 bytes fit the length in the last block, 112 bytes need another.
+        let sha = Sha512Software::new();
+        assert_eq!(
+            sha.digest_in_chunks(b""),
+            from_hex(
+                "cf83e1357eefb8bdf1542850d66d8007d620e4050b5715dc83f4a921d36ce9ce\
+                 47d0d13c5d85f2b0ff8318d2877eec2f63b931bd47417a81a538327af927da3e"
+            )
+        );
+        assert_eq!(
+            sha.digest_in_chunks(&A_MILLION[..111]),
+            from_hex(
+                "fa9121c7b32b9e01733d034cfc78cbf67f926c7ed83e82200ef8681819692176\
+                 0b4beff48404df811b953828274461673c68d04e297b0eb7b2b4d60fc6b566a2"
+            )
+        );
+        assert_eq!(
+            sha.digest_in_chunks(&A_MILLION[..112]),
+            from_hex(
+                "c01d080efd492776a1c43bd23dd99d0a2e626d481e16782e75d54c2503b5dc32\
+                 bd05f0f1ba33e568b88fd2d970929b719ecbb152f58f130a407c8830604b70ca"
+            )
+        );
+    }
+}
@@ -0,0 +1,538 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Shared driver of the software SHA-2 implementations.
+//!
+//! `ShaSoftware` buffers added data into blocks, pads the message, and
+//! reports to its client from a deferred call. The hash itself is the
+//! compression function of a `ShaCore`, so `Sha256Software` and
+//! `Sha512Software` are `ShaSoftware`s with the core of their hash.
+//!
+//! Added data is hashed in chunks of `ShaCore::BLOCKS_PER_CHUNK` blocks
+//! through the kernel work queue, so hashing a large buffer, such as a
+//! process binary, does not hold up interrupts and deferred calls until it
+//! is done.
+//!
+//! It also computes HMAC (RFC 2104) over the hash, for keys of at most one
+//! block, once it is given a key with `set_hmac_mode` and until
+//! `set_hash_mode` is called.
+
+use core::cell::Cell;
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::work_queue::{Priority, Work, WorkClient};
+
+use kernel::hil::digest::Client;
+use kernel::hil::digest::{Digest, DigestData, DigestHash, DigestVerify};
+use kernel::utilities::cells::{MapCell, OptionalCell};
+use kernel::utilities::leasable_buffer::LeasableBuffer;
+use kernel::utilities::leasable_buffer::LeasableBufferDynamic;
+use kernel::utilities::leasable_buffer::LeasableMutableBuffer;
+use kernel::ErrorCode;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum State {
+    Idle,
+    Data,
+    Hash,
+    Verify,
+    CancelData,
+    CancelHash,
+    CancelVerify,
+}
+
+/// HMAC inner and outer padding bytes (RFC 2104, section 2).
+const HMAC_IPAD: u8 = 0x36;
+const HMAC_OPAD: u8 = 0x5c;
+
+/// The compression function of a SHA-2 hash with `BLOCK_LEN`-byte blocks
+/// and `OUTPUT_LEN`-byte digests (FIPS 180-4).
+pub trait ShaCore<const BLOCK_LEN: usize, const OUTPUT_LEN: usize> {
+    /// The hash values.
+    type HashValues: Copy;
+
+    const INITIAL_HASH_VALUES: Self::HashValues;
+
+    /// Length of the message length field at the end of the padding.
+    const LENGTH_LEN: usize;
+
+    /// Number of blocks hashed in one chunk of work.
+    const BLOCKS_PER_CHUNK: usize;
+
+    /// Hash `block` into `hash_values`.
+    fn compress(hash_values: &mut Self::HashValues, block: &[u8; BLOCK_LEN]);
+
+    /// The hash values in big endian format.
+    fn output(hash_values: &Self::HashValues) -> [u8; OUTPUT_LEN];
+}
+
+pub struct ShaSoftware<
+    'a,
+    H: ShaCore<BLOCK_LEN, OUTPUT_LEN>,
+    const BLOCK_LEN: usize,
+    const OUTPUT_LEN: usize,
+> {
+    state: Cell<State>,
+
+    client: OptionalCell<&'a dyn Client<OUTPUT_LEN>>,
+    input_data: OptionalCell<LeasableBufferDynamic<'static, u8>>,
+    data_buffer: MapCell<[u8; BLOCK_LEN]>,
+    buffered_length: Cell<usize>,
+    total_length: Cell<usize>,
+
+    // Used to store the hash or the hash to compare against with verify
+    output_data: Cell<Option<&'static mut [u8; OUTPUT_LEN]>>,
+
+    hash_values: Cell<H::HashValues>,
+    /// The HMAC key, zero-padded to a block, in HMAC mode.
+    hmac_key: Cell<Option<[u8; BLOCK_LEN]>>,
+    deferred_call: DeferredCall,
+    work: Work,
+}
+
+impl<'a, H: ShaCore<BLOCK_LEN, OUTPUT_LEN>, const BLOCK_LEN: usize, const OUTPUT_LEN: usize>
+    ShaSoftware<'a, H, BLOCK_LEN, OUTPUT_LEN>
+{
+    pub fn new() -> Self {
+        let s = Self {
+            state: Cell::new(State::Idle),
+            client: OptionalCell::empty(),
+            input_data: OptionalCell::empty(),
+            data_buffer: MapCell::new([0; BLOCK_LEN]),
+            buffered_length: Cell::new(0),
+            total_length: Cell::new(0),
+
+            output_data: Cell::new(None),
+            hash_values: Cell::new(H::INITIAL_HASH_VALUES),
+            hmac_key: Cell::new(None),
+
+            deferred_call: DeferredCall::new(),
+            work: Work::new(Priority::Normal),
+        };
+        s.initialize();
+        s
+    }
+
+    pub fn busy(&self) -> bool {
+        self.state.get() != State::Idle
+    }
+
+    /// Compute the plain hash of the data added from now on.
+    pub(crate) fn set_hash_mode(&self) -> Result<(), ErrorCode> {
+        if self.busy() {
+            return Err(ErrorCode::BUSY);
+        }
+        if self.hmac_key.take().is_some() {
+            self.initialize();
+        }
+        Ok(())
+    }
+
+    /// Compute the HMAC with `key` of the data added from now on. Keys
+    /// longer than a block are not supported and return `INVAL`.
+    pub(crate) fn set_hmac_mode(&self, key: &[u8]) -> Result<(), ErrorCode> {
+        if self.busy() {
+            return Err(ErrorCode::BUSY);
+        }
+        if key.len() > BLOCK_LEN {
+            return Err(ErrorCode::INVAL);
+        }
+        let mut padded = [0; BLOCK_LEN];
+        padded[..key.len()].copy_from_slice(key);
+        self.hmac_key.set(Some(padded));
+        self.initialize();
+        Ok(())
+    }
+
+    fn initialize(&self) {
+        let new_state = match self.state.get() {
+            State::Idle => State::Idle,
+            State::Data | State::CancelData => State::CancelData,
+            State::Hash | State::CancelHash => State::CancelHash,
+            State::Verify | State::CancelVerify => State::CancelVerify,
+        };
+        self.state.set(new_state);
+
+        self.buffered_length.set(0);
+        self.total_length.set(0);
+        self.data_buffer.map(|b| b.fill(0));
+        self.hash_values.set(H::INITIAL_HASH_VALUES);
+
+        // In HMAC mode, the data is appended to the key XOR ipad.
+        if let Some(key) = self.hmac_key.get() {
+            self.compress(&key.map(|b| b ^ HMAC_IPAD));
+            self.total_length.set(BLOCK_LEN);
+        }
+    }
+
+    fn compress(&self, block: &[u8; BLOCK_LEN]) {
+        let mut hash_values = self.hash_values.get();
+        H::compress(&mut hash_values, block);
+        self.hash_values.set(hash_values);
+    }
+
+    // Complete the hash of the data added, and in HMAC mode, the outer hash
+    // over the key XOR opad and that inner hash.
+    fn complete(&self) {
+        self.complete_hash();
+        if let Some(key) = self.hmac_key.get() {
+            let inner = self.hash_bytes();
+            self.hash_values.set(H::INITIAL_HASH_VALUES);
+            self.compress(&key.map(|b| b ^ HMAC_OPAD));
+            self.data_buffer.map(|b| {
+                b[..OUTPUT_LEN].copy_from_slice(&inner);
+            });
+            self.buffered_length.set(OUTPUT_LEN);
+            self.total_length.set(BLOCK_LEN + OUTPUT_LEN);
+            self.complete_hash();
+        }
+    }
+
+    fn hash_bytes(&self) -> [u8; OUTPUT_LEN] {
+        H::output(&self.hash_values.get())
+    }
+
+    // Pad the buffered data and hash the last block(s). The buffer never
+    // holds a full block, as it is hashed as soon as it fills.
+    fn complete_hash(&self) {
+        let mut buffered_length = self.buffered_length.get();
+        self.data_buffer.map(|b| {
+            // Append the 1
+            b[buffered_length] = 0x80;
+            buffered_length += 1;
+            b[buffered_length..].fill(0);
+            // The message length ends the last block, so if it does not
+            // fit after the 1, it goes in a block of its own.
+            if buffered_length > BLOCK_LEN - H::LENGTH_LEN {
+                self.compress(b);
+                b.fill(0);
+            }
+            // Any upper bytes of the length field are always zero for the
+            // data we can address.
+            let length = (self.total_length.get() as u64) * 8;
+            b[BLOCK_LEN - 8..].copy_from_slice(&length.to_be_bytes());
+            self.compress(b);
+        });
+    }
+
+    // This method hashes data in input_data, updating the
+    // internal hash state. `data_buffer` contains input data
+    // that did or does not fill a block: the implementation
+    // first fills data_buffer and computes on it, then
+    // operates on input_data. If the end of input_data does
+    // not complete a block then the remainder is stored in
+    // data_buffer.
+    //
+    // At most `BLOCKS_PER_CHUNK` blocks of input_data are
+    // computed per call. Returns whether all of input_data
+    // was consumed.
+    fn compute_chunk(&self) -> bool {
+        if let Some(mut data) = self.input_data.take() {
+            let data_length = data.len();
+            let mut buffered_length = self.buffered_length.get();
+            if buffered_length != 0 {
+                // Copy bytes into the front of the temp buffer and
+                // compute if it fills.
+                self.data_buffer.map(|b| {
+                    let copy_len = core::cmp::min(data_length, BLOCK_LEN - buffered_length);
+                    for i in 0..copy_len {
+                        b[i + buffered_length] = data[i];
+                    }
+                    data.slice(copy_len..data.len());
+                    buffered_length += copy_len;
+
+                    if buffered_length == BLOCK_LEN {
+                        self.compress(b);
+                        buffered_length = 0;
+                    }
+                });
+            }
+            // Process blocks
+            let mut blocks = 0;
+            while data.len() >= BLOCK_LEN && blocks < H::BLOCKS_PER_CHUNK {
+                let mut block = [0; BLOCK_LEN];
+                block.copy_from_slice(&data[0..BLOCK_LEN]);
+                self.compress(&block);
+                data.slice(BLOCK_LEN..data.len());
+                blocks += 1;
+            }
+            // Process tail end of block
+            let done = data.len() < BLOCK_LEN;
+            if done && data.len() != 0 {
+                self.data_buffer.map(|b| {
+                    for i in 0..data.len() {
+                        b[i] = data[i];
+                    }
+                    buffered_length = data.len();
+                    // Go to end of data.
+                    data.slice(data.len()..data.len());
+                });
+            }
+            self.total_length
+                .set(self.total_length.get() + data_length - data.len());
+            self.input_data.set(data);
+            self.buffered_length.set(buffered_length);
+            done
+        } else {
+            /* do nothing, no data */
+            true
+        }
+    }
+}
+
+impl<'a, H: ShaCore<BLOCK_LEN, OUTPUT_LEN>, const BLOCK_LEN: usize, const OUTPUT_LEN: usize>
+    DigestData<'a, OUTPUT_LEN> for ShaSoftware<'a, H, BLOCK_LEN, OUTPUT_LEN>
+{
+    fn add_data(
+        &self,
+        data: LeasableBuffer<'static, u8>,
+    ) -> Result<(), (ErrorCode, LeasableBuffer<'static, u8>)> {
+        if self.busy() {
+            Err((ErrorCode::BUSY, data))
+        } else {
+            self.state.set(State::Data);
+            self.input_data.set(LeasableBufferDynamic::Immutable(data));
+            self.work.schedule();
+            Ok(())
+        }
+    }
+
+    fn add_mut_data(
+        &self,
+        data: LeasableMutableBuffer<'static, u8>,
+    ) -> Result<(), (ErrorCode, LeasableMutableBuffer<'static, u8>)> {
+        if self.busy() {
+            Err((ErrorCode::BUSY, data))
+        } else {
+            self.state.set(State::Data);
+            self.input_data.set(LeasableBufferDynamic::Mutable(data));
+            self.work.schedule();
+            Ok(())
+        }
+    }
+
+    fn clear_data(&self) {
+        self.initialize();
+    }
+}
+
+impl<'a, H: ShaCore<BLOCK_LEN, OUTPUT_LEN>, const BLOCK_LEN: usize, const OUTPUT_LEN: usize>
+    DigestHash<'a, OUTPUT_LEN> for ShaSoftware<'a, H, BLOCK_LEN, OUTPUT_LEN>
+{
+    fn run(
+        &'a self,
+        digest: &'static mut [u8; OUTPUT_LEN],
+    ) -> Result<(), (ErrorCode, &'static mut [u8; OUTPUT_LEN])> {
+        if self.busy() {
+            Err((ErrorCode::BUSY, digest))
+        } else {
+            self.state.set(State::Hash);
+            self.complete();
+            *digest = self.hash_bytes();
+            self.output_data.set(Some(digest));
+            self.deferred_call.set();
+            Ok(())
+        }
+    }
+}
+
+impl<'a, H: ShaCore<BLOCK_LEN, OUTPUT_LEN>, const BLOCK_LEN: usize, const OUTPUT_LEN: usize>
+    DigestVerify<'a, OUTPUT_LEN> for ShaSoftware<'a, H, BLOCK_LEN, OUTPUT_LEN>
+{
+    fn verify(
+        &'a self,
+        compare: &'static mut [u8; OUTPUT_LEN],
+    ) -> Result<(), (ErrorCode, &'static mut [u8; OUTPUT_LEN])> {
+        if self.busy() {
+            Err((ErrorCode::BUSY, compare))
+        } else {
+            self.state.set(State::Verify);
+            self.complete();
+            self.output_data.set(Some(compare));
+            self.deferred_call.set();
+            Ok(())
+        }
+    }
+}
+
+impl<'a, H: ShaCore<BLOCK_LEN, OUTPUT_LEN>, const BLOCK_LEN: usize, const OUTPUT_LEN: usize>
+    Digest<'a, OUTPUT_LEN> for ShaSoftware<'a, H, BLOCK_LEN, OUTPUT_LEN>
+{
+    fn set_client(&'a self, client: &'a dyn Client<OUTPUT_LEN>) {
+        self.client.set(client);
+    }
+}
+
+impl<'a, H: ShaCore<BLOCK_LEN, OUTPUT_LEN>, const BLOCK_LEN: usize, const OUTPUT_LEN: usize>
+    DeferredCallClient for ShaSoftware<'a, H, BLOCK_LEN, OUTPUT_LEN>
+{
+    fn handle_deferred_call(&self) {
+        let prior = self.state.get();
+        self.state.set(State::Idle);
+        match prior {
+            State::Idle => {}
+            State::Verify => {
+                // Do the verification here so we don't have to store
+                // the result across the callback.
+                let output = self.output_data.replace(None).unwrap();
+                let pass = *output == self.hash_bytes();
+                self.state.set(State::Idle);
+                self.clear_data();
+                self.client.map(|c| {
+                    c.verification_done(Ok(pass), output);
+                });
+            }
+            State::Data => {
+                // Data already computed in chunks of work
+                let data = self.input_data.take().unwrap();
+                self.state.set(State::Idle);
+                match data {
+                    LeasableBufferDynamic::Mutable(buffer) => {
+                        self.client.map(|client| {
+                            client.add_mut_data_done(Ok(()), buffer);
+                        });
+                    }
+                    LeasableBufferDynamic::Immutable(buffer) => {
+                        self.client.map(|client| {
+                            client.add_data_done(Ok(()), buffer);
+                        });
+                    }
+                }
+            }
+            State::Hash => {
+                // Hash already copied in method call.
+                let output = self.output_data.replace(None).unwrap();
+                self.state.set(State::Idle);
+                self.clear_data();
+                self.client.map(|c| {
+                    c.hash_done(Ok(()), output);
+                });
+            }
+            State::CancelData => {
+                self.state.set(State::Idle);
+                self.clear_data();
+                let data = self.input_data.take().unwrap();
+                match data {
+                    LeasableBufferDynamic::Mutable(buffer) => {
+                        self.client.map(|client| {
+                            client.add_mut_data_done(Err(ErrorCode::CANCEL), buffer);
+                        });
+                    }
+                    LeasableBufferDynamic::Immutable(buffer) => {
+                        self.client.map(|client| {
+                            client.add_data_done(Err(ErrorCode::CANCEL), buffer);
+                        });
+                    }
+                }
+            }
+            State::CancelVerify => {
+                self.state.set(State::Idle);
+                self.clear_data();
+                let output = self.output_data.replace(None).unwrap();
+                self.client.map(|client| {
+                    client.verification_done(Err(ErrorCode::CANCEL), output);
+                });
+            }
+            State::CancelHash => {
+                self.state.set(State::Idle);
+                self.clear_data();
+                let output = self.output_data.replace(None).unwrap();
+                self.client.map(|client| {
+                    client.hash_done(Err(ErrorCode::CANCEL), output);
+                });
+            }
+        }
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+        self.work.register(self);
+    }
+}
+
+impl<'a, H: ShaCore<BLOCK_LEN, OUTPUT_LEN>, const BLOCK_LEN: usize, const OUTPUT_LEN: usize>
+    WorkClient for ShaSoftware<'a, H, BLOCK_LEN, OUTPUT_LEN>
+{
+    fn run_work(&self) {
+        match self.state.get() {
+            State::Data => {
+                if self.compute_chunk() {
+                    self.deferred_call.set();
+                } else {
+                    self.work.schedule();
+                }
+            }
+            // Cancelled while hashing: report the cancellation from the
+            // deferred call as usual.
+            _ => self.deferred_call.set(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl<'a, H: ShaCore<BLOCK_LEN, OUTPUT_LEN>, const BLOCK_LEN: usize, const OUTPUT_LEN: usize>
+    ShaSoftware<'a, H, BLOCK_LEN, OUTPUT_LEN>
+{
+    /// Hash `data` the way the work queue would, chunk by chunk, and
+    /// complete the digest.
+    pub(crate) fn digest_in_chunks(&self, data: &'static [u8]) -> [u8; OUTPUT_LEN] {
+        self.input_data
+            .set(LeasableBufferDynamic::Immutable(LeasableBuffer::new(data)));
+        while !self.compute_chunk() {}
+        self.complete();
+        let digest = self.hash_bytes();
+        self.initialize();
+        digest
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn from_hex<const L: usize>(hex: &str) -> [u8; L] {
+    let mut bytes = [0; L];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).unwrap();
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sha256::Sha256Software;
+
+    static A_MILLION: [u8; 1_000_000] = [b'a'; 1_000_000];
+
+    #[test]
+    fn blocks_across_chunks_and_adds() {
+        // Data added in pieces that split blocks hashes as a whole.
+        let sha = Sha256Software::new();
+        for piece in A_MILLION.chunks(999) {
+            sha.input_data
+                .set(LeasableBufferDynamic::Immutable(LeasableBuffer::new(piece)));
+            while !sha.compute_chunk() {}
+        }
+        sha.complete();
+        assert_eq!(
+            sha.hash_bytes(),
+            from_hex("cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0")
+        );
+    }
+
+    #[test]
+    fn padding_at_block_boundaries() {
+        // 55 bytes fit the length in the last block, 56 bytes need another.
+        let sha = Sha256Software::new();
+        assert_eq!(
+            sha.digest_in_chunks(&A_MILLION[..55]),
+            from_hex("9f4390f8d30c2dd92ec9f095b65e2b9ae9b0a925a5258e241c9f1e910f734318")
+        );
+        assert_eq!(
+            sha.digest_in_chunks(&A_MILLION[..56]),
+            from_hex("b35439a4ac6f0948b6d6f9e3c6af0f5f590ce20f1bde7090ef7970686ec6738a")
+        );
+        assert_eq!(
+            sha.digest_in_chunks(&A_MILLION[..64]),
+            from_hex("ffe054fe7ae0cb6dc65c3af9b61d5209f439851db43d0ba5997337df154668eb")
+        );
+    }
+}
@@ -0,0 +1,251 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Fault policy that lets a supervisor process decide what happens to
+//! faulted processes.
+//!
+//! Instead of restarting or stopping a faulted process itself, the policy
+//! stops it and sends a fault report to a supervisor process, identified by
+//! its fixed `ShortID`. The report holds the fault reason, the program counter
+//! and stack pointer of the process, and the driver of the last system call
+//! it made, so the supervisor can e.g. tell a crash in a driver call from a
+//! stack overflow. The supervisor then restarts the process or leaves it
+//! stopped with this system call driver.
+//!
+//! If the supervisor itself faults, or is not running or has not used this
+//! driver yet, nobody would handle the report, so the `fallback` action is
+//! taken instead.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let fault_policy = static_init!(
+//!     capsules_extra::supervisor_fault_policy::SupervisorFaultPolicy<
+//!         'static,
+//!         ProcessMgmtCap,
+//!     >,
+//!     capsules_extra::supervisor_fault_policy::SupervisorFaultPolicy::new(
+//!         board_kernel,
+//!         static_init!(
+//!             [Option<capsules_extra::supervisor_fault_policy::FaultReport>; NUM_PROCS],
+//!             [None; NUM_PROCS]
+//!         ),
+//!         board_kernel.create_grant(
+//!             capsules_extra::supervisor_fault_policy::DRIVER_NUM,
+//!             &grant_cap
+//!         ),
+//!         ShortID::Fixed(NonZeroU32::new(0x2f7d).unwrap()),
+//!         FaultAction::Restart,
+//!         ProcessMgmtCap
+//!     )
+//! );
+//! ```
+//!
+//! and pass `fault_policy` as the fault policy when loading processes.
+//!
+//! Command Interface
+//! -----------------
+//!
+//! All commands but the existence check return `NOSUPPORT` to processes
+//! other than the supervisor. Processes are identified by their index in
+//! the processes array, and all commands but the existence check return
+//! `INVAL` for processes without a pending fault report.
+//!
+//! - `0`: Driver existence check.
+//! - `1`: The fault reason, program counter and stack pointer of process
+//!   `data1`. The program counter and stack pointer are `0` if unknown.
+//! - `2`: The driver number of the last system call of process `data1`.
+//!   Returns `FAIL` if its last system call was not for a driver.
+//! - `3`: Restart process `data1`.
+//! - `4`: Leave process `data1` stopped.
+//!
+//! Both `3` and `4` discard the fault report.
+//!
+//! Upcall `0` is called on every fault with the index of the process, the
+//! fault reason, and the program counter (`0` if unknown).
+//!
+//! Fault reasons are numbered as `kernel::process::FaultReason`, with `0` for
+//! an unknown reason.
+
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::process::{self, FaultAction, FaultReason, Process, ProcessFaultPolicy, ShortID};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::TakeCell;
+use kernel::{ErrorCode, Kernel, ProcessId};
+
+/// Syscall driver number.
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::FaultSupervisor as usize;
+
+/// What is known about a fault of a process.
+#[derive(Copy, Clone)]
+pub struct FaultReport {
+    pub reason: Option<FaultReason>,
+    /// Program counter of the process when it faulted.
+    pub pc: Option<usize>,
+    /// Stack pointer of the process when it faulted.
+    pub sp: Option<usize>,
+    /// Driver of the last system call of the process.
+    pub driver_number: Option<usize>,
+}
+
+pub struct SupervisorFaultPolicy<'a, C: ProcessManagementCapability> {
+    kernel: &'static Kernel,
+    /// Pending report for each slot of the processes array.
+    reports: TakeCell<'a, [Option<FaultReport>]>,
+    apps: Grant<(), UpcallCount<1>, AllowRoCount<0>, AllowRwCount<0>>,
+    supervisor: ShortID,
+    fallback: FaultAction,
+    capability: C,
+}
+
+impl<'a, C: ProcessManagementCapability> SupervisorFaultPolicy<'a, C> {
+    /// `reports` must have one entry per slot of the processes array.
+    pub fn new(
+        kernel: &'static Kernel,
+        reports: &'a mut [Option<FaultReport>],
+        grant: Grant<(), UpcallCount<1>, AllowRoCount<0>, AllowRwCount<0>>,
+        supervisor: ShortID,
+        fallback: FaultAction,
+        capability: C,
+    ) -> SupervisorFaultPolicy<'a, C> {
+        SupervisorFaultPolicy {
+            kernel,
+            reports: TakeCell::new(reports),
+            apps: grant,
+            supervisor,
+            fallback,
+            capability,
+        }
+    }
+
+    fn is_supervisor(&self, processid: ProcessId) -> bool {
+        self.kernel.process_map_or_external(
+            false,
+            processid,
+            |process| process.short_app_id() == self.supervisor,
+            &self.capability,
+        )
+    }
+
+    /// Send the report of process `index` to the supervisor. Returns whether
+    /// a running supervisor got it.
+    fn notify_supervisor(&self, index: usize, report: &FaultReport) -> bool {
+        let reason = report.reason.map_or(0, |reason| reason as usize);
+        let mut notified = false;
+        self.kernel
+            .process_each_capability(&self.capability, |process| {
+                if process.short_app_id() == self.supervisor && process.is_running() {
+                    let _ = self.apps.enter(process.processid(), |_, kernel_data| {
+                        notified = kernel_data
+                            .schedule_upcall(0, (index, reason, report.pc.unwrap_or(0)))
+                            .is_ok();
+                    });
+                }
+            });
+        notified
+    }
+
+    /// Take the pending report of process `index`.
+    fn take_report(&self, index: usize) -> Option<FaultReport> {
+        self.reports.map_or(None, |reports| {
+            reports.get_mut(index).and_then(Option::take)
+        })
+    }
+
+    fn restart(&self, index: usize) {
+        self.kernel
+            .process_each_capability(&self.capability, |process| {
+                if process.processid().index_external(&self.capability) == Some(index)
+                    && process.get_state() == process::State::Faulted
+                {
+                    process.try_restart(None);
+                }
+            });
+    }
+}
+
+impl<'a, C: ProcessManagementCapability> ProcessFaultPolicy for SupervisorFaultPolicy<'a, C> {
+    fn action(&self, process: &dyn Process) -> FaultAction {
+        let index = match process.processid().index_external(&self.capability) {
+            Some(index) => index,
+            None => return self.fallback,
+        };
+        if process.short_app_id() == self.supervisor {
+            return self.fallback;
+        }
+        let (pc, sp) = process
+            .get_pc_and_sp()
+            .map_or((None, None), |(pc, sp)| (Some(pc), Some(sp)));
+        let report = FaultReport {
+            reason: process.get_fault_reason(),
+            pc,
+            sp,
+            driver_number: process
+                .debug_syscall_last()
+                .and_then(|syscall| syscall.driver_number()),
+        };
+        if !self.notify_supervisor(index, &report) {
+            return self.fallback;
+        }
+        self.reports.map(|reports| {
+            if let Some(slot) = reports.get_mut(index) {
+                *slot = Some(report);
+            }
+        });
+        // The supervisor decides what happens next.
+        FaultAction::Stop
+    }
+}
+
+impl<'a, C: ProcessManagementCapability> SyscallDriver for SupervisorFaultPolicy<'a, C> {
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        _data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        if command_num == 0 {
+            return CommandReturn::success();
+        }
+        if !self.is_supervisor(processid) {
+            return CommandReturn::failure(ErrorCode::NOSUPPORT);
+        }
+        let report = match self
+            .reports
+            .map_or(None, |reports| reports.get(data1).copied().flatten())
+        {
+            Some(report) => report,
+            None => return CommandReturn::failure(ErrorCode::INVAL),
+        };
+        match command_num {
+            1 => CommandReturn::success_u32_u32_u32(
+                report.reason.map_or(0, |reason| reason as u32),
+                report.pc.unwrap_or(0) as u32,
+                report.sp.unwrap_or(0) as u32,
+            ),
+            2 => match report.driver_number {
+                Some(driver_number) => CommandReturn::success_u32(driver_number as u32),
+                None => CommandReturn::failure(ErrorCode::FAIL),
+            },
+            3 => {
+                self.take_report(data1);
+                self.restart(data1);
+                CommandReturn::success()
+            }
+            4 => {
+                self.take_report(data1);
+                CommandReturn::success()
+            }
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
@@ -0,0 +1,782 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Driver for the Semtech SX1276, SX1277, SX1278 and SX1279 LoRa radios.
+//!
+//! <https://www.semtech.com/products/wireless-rf/lora-connect/sx1276>
+//!
+//! The SX127x radios are attached over SPI, and signal the end of a
+//! transmission or of a reception on their DIO0 line. This driver implements
+//! `hil::lora::LoRaRadio` on top of them, using the LoRa modem with explicit
+//! headers and payload CRCs. Packets are sent through the PA_BOOST pin, which
+//! the common modules use, so the transmit power ranges from 2 to 17 dBm.
+//! The radio is in the low frequency band below 525 MHz, and in the high one
+//! above.
+//!
+//! Every access to the radio is one SPI transaction, and the driver runs them
+//! from fixed sequences of operations (configuring the radio, sending a
+//! packet, reading a received one, ...), taking the values written from its
+//! own state. One sequence runs at a time: interrupts, configuration changes
+//! and packets to send that arrive while one runs are handled once it is
+//! done. A new configuration waits for the packet being sent, and the radio
+//! stops receiving while it sends a packet.
+//!
+//! Usage
+//! -----
+//! ```rust,ignore
+//! let sx127x = components::sx127x::Sx127xComponent::new(
+//!     mux_spi,
+//!     chip_select,
+//!     &gpio_port[SX127X_DIO0_PIN],
+//!     Some(&gpio_port[SX127X_RESET_PIN]),
+//!     mux_alarm,
+//! )
+//! .finalize(components::sx127x_component_static!(
+//!     nrf52840::spi::SPIM,
+//!     nrf52840::rtc::Rtc
+//! ));
+//! sx127x.enable();
+//! ```
+
+use core::cell::Cell;
+
+use kernel::debug;
+use kernel::hil::gpio;
+use kernel::hil::lora::{self, Config, LoRaRadio, RxClient, RxMetadata, TxClient};
+use kernel::hil::spi::{self, SpiMasterDevice};
+use kernel::hil::time::{self, Alarm, ConvertTicks};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// The length of the SPI buffers: an address byte followed by a packet.
+pub const BUF_LEN: usize = lora::MAX_PAYLOAD_LEN + 1;
+
+const SPI_SPEED: u32 = 8_000_000;
+
+/// The version of the SX1276 to SX1279.
+const VERSION: u8 = 0x12;
+
+const CRYSTAL_HZ: u64 = 32_000_000;
+
+/// The radio uses its low frequency band below this frequency.
+const LOW_BAND_MAX_HZ: u32 = 525_000_000;
+
+const FREQUENCY_RANGE: core::ops::RangeInclusive<u32> = 137_000_000..=1_020_000_000;
+const SPREADING_FACTOR_RANGE: core::ops::RangeInclusive<u8> = 7..=12;
+const TX_POWER_RANGE: core::ops::RangeInclusive<i8> = 2..=17;
+
+/// Register addresses are written with this bit set.
+const WRITE: u8 = 0x80;
+
+mod reg {
+    pub const FIFO: u8 = 0x00;
+    pub const OP_MODE: u8 = 0x01;
+    pub const FRF_MSB: u8 = 0x06;
+    pub const FRF_MID: u8 = 0x07;
+    pub const FRF_LSB: u8 = 0x08;
+    pub const PA_CONFIG: u8 = 0x09;
+    pub const FIFO_ADDR_PTR: u8 = 0x0d;
+    pub const FIFO_TX_BASE_ADDR: u8 = 0x0e;
+    pub const FIFO_RX_BASE_ADDR: u8 = 0x0f;
+    pub const FIFO_RX_CURRENT_ADDR: u8 = 0x10;
+    pub const IRQ_FLAGS: u8 = 0x12;
+    pub const RX_NB_BYTES: u8 = 0x13;
+    pub const PKT_SNR_VALUE: u8 = 0x19;
+    pub const PKT_RSSI_VALUE: u8 = 0x1a;
+    pub const MODEM_CONFIG_1: u8 = 0x1d;
+    pub const MODEM_CONFIG_2: u8 = 0x1e;
+    pub const PREAMBLE_MSB: u8 = 0x20;
+    pub const PREAMBLE_LSB: u8 = 0x21;
+    pub const PAYLOAD_LENGTH: u8 = 0x22;
+    pub const MODEM_CONFIG_3: u8 = 0x26;
+    pub const DETECT_OPTIMIZE: u8 = 0x31;
+    pub const INVERT_IQ: u8 = 0x33;
+    pub const DETECTION_THRESHOLD: u8 = 0x37;
+    pub const INVERT_IQ_2: u8 = 0x3b;
+    pub const SYNC_WORD: u8 = 0x39;
+    pub const DIO_MAPPING_1: u8 = 0x40;
+    pub const VERSION: u8 = 0x42;
+}
+
+mod bits {
+    pub const OP_MODE_LONG_RANGE: u8 = 0x80;
+    pub const OP_MODE_LOW_FREQUENCY: u8 = 0x08;
+    pub const MODE_SLEEP: u8 = 0x00;
+    pub const MODE_STDBY: u8 = 0x01;
+    pub const MODE_TX: u8 = 0x03;
+    pub const MODE_RX_CONTINUOUS: u8 = 0x05;
+
+    pub const PA_CONFIG_PA_BOOST: u8 = 0x80;
+    pub const PA_CONFIG_MAX_POWER: u8 = 0x70;
+
+    pub const IRQ_RX_DONE: u8 = 0x40;
+    pub const IRQ_PAYLOAD_CRC_ERROR: u8 = 0x20;
+    pub const IRQ_ALL: u8 = 0xff;
+
+    pub const MODEM_CONFIG_2_RX_PAYLOAD_CRC_ON: u8 = 0x04;
+    pub const MODEM_CONFIG_3_LOW_DATA_RATE_OPTIMIZE: u8 = 0x08;
+    pub const MODEM_CONFIG_3_AGC_AUTO_ON: u8 = 0x04;
+
+    /// Detection settings for spreading factors 7 to 12.
+    pub const DETECT_OPTIMIZE_SF7_TO_SF12: u8 = 0xc3;
+    pub const DETECTION_THRESHOLD_SF7_TO_SF12: u8 = 0x0a;
+
+    /// Inversion of the I and Q signals, for sending and receiving.
+    pub const INVERT_IQ_OFF: u8 = 0x27;
+    pub const INVERT_IQ_ON: u8 = 0x66;
+    pub const INVERT_IQ_2_OFF: u8 = 0x1d;
+    pub const INVERT_IQ_2_ON: u8 = 0x19;
+
+    pub const DIO0_RX_DONE: u8 = 0x00;
+    pub const DIO0_TX_DONE: u8 = 0x40;
+}
+
+/// The mode the radio is in.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Mode {
+    Standby,
+    Transmitting,
+    Receiving,
+}
+
+/// A value written to a register.
+#[derive(Copy, Clone, Debug)]
+enum Value {
+    Const(u8),
+    /// The operating mode with the given mode bits.
+    OpMode(u8),
+    FrfMsb,
+    FrfMid,
+    FrfLsb,
+    PaConfig,
+    ModemConfig1,
+    ModemConfig2,
+    ModemConfig3,
+    PreambleMsb,
+    PreambleLsb,
+    SyncWord,
+    InvertIq,
+    InvertIq2,
+    /// The length of the packet being sent.
+    TxLen,
+    /// The start of the received packet in the FIFO.
+    RxAddr,
+}
+
+/// Where the value of a register read is kept.
+#[derive(Copy, Clone, Debug)]
+enum Field {
+    Version,
+    IrqFlags,
+    RxLen,
+    RxAddr,
+    Snr,
+    Rssi,
+}
+
+/// One step of a sequence: an SPI transaction, or a change of the reset line
+/// or a delay.
+#[derive(Copy, Clone, Debug)]
+enum Op {
+    /// Drive the reset line, if there is one.
+    Reset(bool),
+    /// Wait for the given number of milliseconds.
+    Delay(u32),
+    Write(u8, Value),
+    Read(u8, Field),
+    /// Write the packet being sent to the FIFO.
+    WriteFifo,
+    /// Read the received packet from the FIFO into the receive buffer.
+    ReadFifo,
+}
+
+const INIT: &[Op] = &[
+    Op::Reset(false),
+    Op::Delay(1),
+    Op::Reset(true),
+    Op::Delay(6),
+    Op::Read(reg::VERSION, Field::Version),
+];
+
+const CONFIGURE: &[Op] = &[
+    // The LoRa modem can only be selected in sleep mode
+    Op::Write(reg::OP_MODE, Value::OpMode(bits::MODE_SLEEP)),
+    Op::Write(reg::OP_MODE, Value::OpMode(bits::MODE_STDBY)),
+    Op::Write(reg::FRF_MSB, Value::FrfMsb),
+    Op::Write(reg::FRF_MID, Value::FrfMid),
+    Op::Write(reg::FRF_LSB, Value::FrfLsb),
+    Op::Write(reg::PA_CONFIG, Value::PaConfig),
+    // Packets are sent and received from the start of the FIFO
+    Op::Write(reg::FIFO_TX_BASE_ADDR, Value::Const(0)),
+    Op::Write(reg::FIFO_RX_BASE_ADDR, Value::Const(0)),
+    Op::Write(reg::MODEM_CONFIG_1, Value::ModemConfig1),
+    Op::Write(reg::MODEM_CONFIG_2, Value::ModemConfig2),
+    Op::Write(reg::MODEM_CONFIG_3, Value::ModemConfig3),
+    Op::Write(reg::PREAMBLE_MSB, Value::PreambleMsb),
+    Op::Write(reg::PREAMBLE_LSB, Value::PreambleLsb),
+    Op::Write(reg::SYNC_WORD, Value::SyncWord),
+    Op::Write(reg::INVERT_IQ, Value::InvertIq),
+    Op::Write(reg::INVERT_IQ_2, Value::InvertIq2),
+    Op::Write(
+        reg::DETECT_OPTIMIZE,
+        Value::Const(bits::DETECT_OPTIMIZE_SF7_TO_SF12),
+    ),
+    Op::Write(
+        reg::DETECTION_THRESHOLD,
+        Value::Const(bits::DETECTION_THRESHOLD_SF7_TO_SF12),
+    ),
+];
+
+const TRANSMIT: &[Op] = &[
+    Op::Write(reg::OP_MODE, Value::OpMode(bits::MODE_STDBY)),
+    Op::Write(reg::FIFO_ADDR_PTR, Value::Const(0)),
+    Op::WriteFifo,
+    Op::Write(reg::PAYLOAD_LENGTH, Value::TxLen),
+    Op::Write(reg::DIO_MAPPING_1, Value::Const(bits::DIO0_TX_DONE)),
+    Op::Write(reg::IRQ_FLAGS, Value::Const(bits::IRQ_ALL)),
+    Op::Write(reg::OP_MODE, Value::OpMode(bits::MODE_TX)),
+];
+
+// The radio returns to standby by itself once the packet is sent.
+const CLEAR_IRQ: &[Op] = &[Op::Write(reg::IRQ_FLAGS, Value::Const(bits::IRQ_ALL))];
+
+const START_RX: &[Op] = &[
+    Op::Write(reg::OP_MODE, Value::OpMode(bits::MODE_STDBY)),
+    Op::Write(reg::DIO_MAPPING_1, Value::Const(bits::DIO0_RX_DONE)),
+    Op::Write(reg::IRQ_FLAGS, Value::Const(bits::IRQ_ALL)),
+    Op::Write(reg::OP_MODE, Value::OpMode(bits::MODE_RX_CONTINUOUS)),
+];
+
+const RX_DONE: &[Op] = &[
+    Op::Read(reg::IRQ_FLAGS, Field::IrqFlags),
+    Op::Read(reg::RX_NB_BYTES, Field::RxLen),
+    Op::Read(reg::FIFO_RX_CURRENT_ADDR, Field::RxAddr),
+    Op::Write(reg::FIFO_ADDR_PTR, Value::RxAddr),
+    Op::ReadFifo,
+    Op::Read(reg::PKT_SNR_VALUE, Field::Snr),
+    Op::Read(reg::PKT_RSSI_VALUE, Field::Rssi),
+    Op::Write(reg::IRQ_FLAGS, Value::Const(bits::IRQ_ALL)),
+];
+
+const STANDBY: &[Op] = &[Op::Write(reg::OP_MODE, Value::OpMode(bits::MODE_STDBY))];
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Sequence {
+    Init,
+    Configure,
+    Transmit,
+    TxDone,
+    ClearIrq,
+    StartRx,
+    RxDone,
+    Standby,
+}
+
+impl Sequence {
+    fn ops(self) -> &'static [Op] {
+        match self {
+            Sequence::Init => INIT,
+            Sequence::Configure => CONFIGURE,
+            Sequence::Transmit => TRANSMIT,
+            Sequence::TxDone | Sequence::ClearIrq => CLEAR_IRQ,
+            Sequence::StartRx => START_RX,
+            Sequence::RxDone => RX_DONE,
+            Sequence::Standby => STANDBY,
+        }
+    }
+}
+
+pub struct Sx127x<'a, S: SpiMasterDevice, A: Alarm<'a>> {
+    spi: &'a S,
+    dio0: &'a dyn gpio::InterruptPin<'a>,
+    reset: Option<&'a dyn gpio::Pin>,
+    alarm: &'a A,
+    spi_tx: TakeCell<'static, [u8]>,
+    spi_rx: TakeCell<'static, [u8]>,
+    tx_client: OptionalCell<&'a dyn TxClient>,
+    rx_client: OptionalCell<&'a dyn RxClient>,
+
+    config: Cell<Config>,
+    enabled: Cell<bool>,
+    /// The radio is configured.
+    ready: Cell<bool>,
+    mode: Cell<Mode>,
+    /// Receiving is started.
+    receiving: Cell<bool>,
+
+    sequence: OptionalCell<Sequence>,
+    op_idx: Cell<usize>,
+
+    interrupt_pending: Cell<bool>,
+    config_pending: Cell<bool>,
+
+    /// The packet being sent, and its length.
+    tx_buf: TakeCell<'static, [u8]>,
+    tx_len: Cell<usize>,
+
+    rx_buf: TakeCell<'static, [u8]>,
+    version: Cell<u8>,
+    irq_flags: Cell<u8>,
+    rx_len: Cell<u8>,
+    rx_addr: Cell<u8>,
+    snr: Cell<u8>,
+    rssi: Cell<u8>,
+}
+
+impl<'a, S: SpiMasterDevice, A: Alarm<'a>> Sx127x<'a, S, A> {
+    pub fn new(
+        spi: &'a S,
+        dio0: &'a dyn gpio::InterruptPin<'a>,
+        reset: Option<&'a dyn gpio::Pin>,
+        alarm: &'a A,
+        spi_tx: &'static mut [u8],
+        spi_rx: &'static mut [u8],
+    ) -> Sx127x<'a, S, A> {
+        Sx127x {
+            spi,
+            dio0,
+            reset,
+            alarm,
+            spi_tx: TakeCell::new(spi_tx),
+            spi_rx: TakeCell::new(spi_rx),
+            tx_client: OptionalCell::empty(),
+            rx_client: OptionalCell::empty(),
+            config: Cell::new(Config::default()),
+            enabled: Cell::new(false),
+            ready: Cell::new(false),
+            mode: Cell::new(Mode::Standby),
+            receiving: Cell::new(false),
+            sequence: OptionalCell::empty(),
+            op_idx: Cell::new(0),
+            interrupt_pending: Cell::new(false),
+            config_pending: Cell::new(false),
+            tx_buf: TakeCell::empty(),
+            tx_len: Cell::new(0),
+            rx_buf: TakeCell::empty(),
+            version: Cell::new(0),
+            irq_flags: Cell::new(0),
+            rx_len: Cell::new(0),
+            rx_addr: Cell::new(0),
+            snr: Cell::new(0),
+            rssi: Cell::new(0),
+        }
+    }
+
+    fn value(&self, value: Value) -> u8 {
+        let config = self.config.get();
+        let frf = ((config.frequency as u64) << 19) / CRYSTAL_HZ;
+        match value {
+            Value::Const(value) => value,
+            Value::OpMode(mode) => {
+                if config.frequency < LOW_BAND_MAX_HZ {
+                    bits::OP_MODE_LONG_RANGE | bits::OP_MODE_LOW_FREQUENCY | mode
+                } else {
+                    bits::OP_MODE_LONG_RANGE | mode
+                }
+            }
+            Value::FrfMsb => (frf >> 16) as u8,
+            Value::FrfMid => (frf >> 8) as u8,
+            Value::FrfLsb => frf as u8,
+            // The output power is 17 - (15 - OutputPower) dBm on PA_BOOST
+            Value::PaConfig => {
+                bits::PA_CONFIG_PA_BOOST | bits::PA_CONFIG_MAX_POWER | (config.tx_power - 2) as u8
+            }
+            Value::ModemConfig1 => {
+                (config.bandwidth as u8) << 4 | (config.coding_rate as u8 + 1) << 1
+            }
+            Value::ModemConfig2 => {
+                config.spreading_factor << 4 | bits::MODEM_CONFIG_2_RX_PAYLOAD_CRC_ON
+            }
+            Value::ModemConfig3 => {
+                // Symbols longer than 16 ms need the low data rate
+                // optimization
+                let symbol_len_us =
+                    (1_000_000u64 << config.spreading_factor) / config.bandwidth.hz() as u64;
+                if symbol_len_us > 16_000 {
+                    bits::MODEM_CONFIG_3_AGC_AUTO_ON | bits::MODEM_CONFIG_3_LOW_DATA_RATE_OPTIMIZE
+                } else {
+                    bits::MODEM_CONFIG_3_AGC_AUTO_ON
+                }
+            }
+            Value::PreambleMsb => (config.preamble_len >> 8) as u8,
+            Value::PreambleLsb => config.preamble_len as u8,
+            Value::SyncWord => config.sync_word,
+            Value::InvertIq => {
+                if config.invert_iq {
+                    bits::INVERT_IQ_ON
+                } else {
+                    bits::INVERT_IQ_OFF
+                }
+            }
+            Value::InvertIq2 => {
+                if config.invert_iq {
+                    bits::INVERT_IQ_2_ON
+                } else {
+                    bits::INVERT_IQ_2_OFF
+                }
+            }
+            Value::TxLen => self.tx_len.get() as u8,
+            Value::RxAddr => self.rx_addr.get(),
+        }
+    }
+
+    fn metadata(&self) -> RxMetadata {
+        // The SNR is in quarters of dB
+        let snr = self.snr.get() as i8 / 4;
+        let offset = if self.config.get().frequency < LOW_BAND_MAX_HZ {
+            -164
+        } else {
+            -157
+        };
+        let rssi = offset + self.rssi.get() as i16;
+        RxMetadata {
+            rssi: if snr < 0 { rssi + snr as i16 } else { rssi },
+            snr,
+        }
+    }
+
+    /// Start the next pending work, if the radio is idle.
+    fn idle(&self) {
+        if !self.ready.get() || self.sequence.is_some() {
+            return;
+        }
+        if self.interrupt_pending.take() || self.dio0.read() {
+            match self.mode.get() {
+                Mode::Transmitting => self.run(Sequence::TxDone),
+                Mode::Receiving => self.run(Sequence::RxDone),
+                Mode::Standby => self.run(Sequence::ClearIrq),
+            }
+        } else if self.mode.get() == Mode::Transmitting {
+            // Wait for the packet to be sent
+        } else if self.config_pending.take() {
+            self.run(Sequence::Configure);
+        } else if self.tx_buf.is_some() {
+            self.run(Sequence::Transmit);
+        } else if self.receiving.get() && self.mode.get() != Mode::Receiving {
+            self.run(Sequence::StartRx);
+        } else if !self.receiving.get() && self.mode.get() == Mode::Receiving {
+            self.run(Sequence::Standby);
+        }
+    }
+
+    fn run(&self, sequence: Sequence) {
+        self.sequence.set(sequence);
+        self.op_idx.set(0);
+        self.next_op();
+    }
+
+    /// Start the current operation of the running sequence, or finish the
+    /// sequence.
+    fn next_op(&self) {
+        let sequence = match self.sequence.extract() {
+            Some(sequence) => sequence,
+            None => return,
+        };
+        let op = match sequence.ops().get(self.op_idx.get()) {
+            Some(op) => *op,
+            None => {
+                self.sequence.clear();
+                self.sequence_done(sequence);
+                return;
+            }
+        };
+
+        match op {
+            Op::Reset(level) => {
+                if let Some(reset) = self.reset {
+                    if level {
+                        reset.set();
+                    } else {
+                        reset.make_output();
+                        reset.clear();
+                    }
+                }
+                self.op_idx.set(self.op_idx.get() + 1);
+                self.next_op();
+            }
+            Op::Delay(ms) => {
+                self.op_idx.set(self.op_idx.get() + 1);
+                self.alarm
+                    .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(ms));
+            }
+            Op::Write(reg, value) => {
+                let value = self.value(value);
+                self.transfer(2, false, |buf| {
+                    buf[0] = WRITE | reg;
+                    buf[1] = value;
+                })
+            }
+            Op::Read(reg, _) => self.transfer(2, true, |buf| buf[0] = reg),
+            Op::WriteFifo => {
+                let len = self.tx_len.get();
+                self.transfer(len + 1, false, |buf| {
+                    buf[0] = WRITE | reg::FIFO;
+                    self.tx_buf.map(|packet| {
+                        buf[1..len + 1].copy_from_slice(&packet[..len]);
+                    });
+                })
+            }
+            Op::ReadFifo => {
+                let len = self.rx_len.get() as usize;
+                if self.irq_flags.get() & bits::IRQ_RX_DONE == 0 || len == 0 {
+                    // There is no packet
+                    self.op_idx.set(self.op_idx.get() + 1);
+                    self.next_op();
+                } else {
+                    self.transfer(len + 1, true, |buf| buf[0] = reg::FIFO)
+                }
+            }
+        }
+    }
+
+    /// Start an SPI transaction of `len` bytes, whose first bytes are set
+    /// by `fill`. The bytes read are kept if `read` is set.
+    fn transfer<F: FnOnce(&mut [u8])>(&self, len: usize, read: bool, fill: F) {
+        let buf = match self.spi_tx.take() {
+            Some(buf) => buf,
+            None => return self.abort(),
+        };
+        fill(buf);
+        let read_buf = if read { self.spi_rx.take() } else { None };
+        if let Err((_, buf, read_buf)) = self.spi.read_write_bytes(buf, read_buf, len) {
+            self.spi_tx.replace(buf);
+            if let Some(read_buf) = read_buf {
+                self.spi_rx.replace(read_buf);
+            }
+            self.abort();
+        }
+    }
+
+    /// Handle the bytes read by the current operation, and continue the
+    /// sequence.
+    fn op_done(&self) {
+        let op = match self
+            .sequence
+            .extract()
+            .and_then(|sequence| sequence.ops().get(self.op_idx.get()))
+        {
+            Some(op) => *op,
+            None => return,
+        };
+        match op {
+            Op::Read(_, field) => {
+                let value = self.spi_rx.map_or(0, |buf| buf[1]);
+                match field {
+                    Field::Version => self.version.set(value),
+                    Field::IrqFlags => self.irq_flags.set(value),
+                    Field::RxLen => self.rx_len.set(value),
+                    Field::RxAddr => self.rx_addr.set(value),
+                    Field::Snr => self.snr.set(value),
+                    Field::Rssi => self.rssi.set(value),
+                }
+            }
+            Op::ReadFifo => {
+                let len = self.rx_len.get() as usize;
+                self.spi_rx.map(|buf| {
+                    self.rx_buf.map(|packet| {
+                        let len = core::cmp::min(len, packet.len());
+                        packet[..len].copy_from_slice(&buf[1..len + 1]);
+                    });
+                });
+            }
+            _ => {}
+        }
+        self.op_idx.set(self.op_idx.get() + 1);
+        self.next_op();
+    }
+
+    fn sequence_done(&self, sequence: Sequence) {
+        match sequence {
+            Sequence::Init => {
+                if self.version.get() == VERSION {
+                    self.run(Sequence::Configure);
+                } else {
+                    debug!("SX127x: unknown version {:#x}", self.version.get());
+                    self.enabled.set(false);
+                }
+            }
+            Sequence::Configure => {
+                self.ready.set(true);
+                self.mode.set(Mode::Standby);
+                self.idle();
+            }
+            Sequence::Transmit => {
+                self.mode.set(Mode::Transmitting);
+                self.idle();
+            }
+            Sequence::TxDone => {
+                self.mode.set(Mode::Standby);
+                let buf = self.tx_buf.take();
+                // Continue before the callback, so that packets the client
+                // sends from it are queued
+                self.idle();
+                if let Some(buf) = buf {
+                    self.tx_client
+                        .map(|client| client.transmit_done(buf, Ok(())));
+                }
+            }
+            Sequence::StartRx => {
+                self.mode.set(Mode::Receiving);
+                self.idle();
+            }
+            Sequence::RxDone => {
+                let flags = self.irq_flags.get();
+                let packet = if flags & bits::IRQ_RX_DONE != 0 {
+                    self.rx_buf.take()
+                } else {
+                    None
+                };
+                let result = if flags & bits::IRQ_PAYLOAD_CRC_ERROR != 0 {
+                    Err(ErrorCode::FAIL)
+                } else {
+                    Ok(())
+                };
+                self.idle();
+                if let Some(packet) = packet {
+                    let len = core::cmp::min(self.rx_len.get() as usize, packet.len());
+                    let metadata = self.metadata();
+                    self.rx_client
+                        .map(|client| client.receive(packet, len, metadata, result));
+                }
+            }
+            Sequence::ClearIrq => self.idle(),
+            Sequence::Standby => {
+                self.mode.set(Mode::Standby);
+                self.idle();
+            }
+        }
+    }
+
+    /// Stop the running sequence after an SPI error.
+    fn abort(&self) {
+        let sequence = self.sequence.take();
+        debug!("SX127x: SPI transaction failed in {:?}", sequence);
+        match sequence {
+            Some(Sequence::Init) | Some(Sequence::Configure) if !self.ready.get() => {
+                self.enabled.set(false)
+            }
+            Some(Sequence::Transmit) => {
+                if let Some(buf) = self.tx_buf.take() {
+                    self.tx_client
+                        .map(|client| client.transmit_done(buf, Err(ErrorCode::FAIL)));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<'a, S: SpiMasterDevice, A: Alarm<'a>> LoRaRadio<'a> for Sx127x<'a, S, A> {
+    fn set_transmit_client(&self, client: &'a dyn TxClient) {
+        self.tx_client.set(client);
+    }
+
+    fn set_receive_client(&self, client: &'a dyn RxClient) {
+        self.rx_client.set(client);
+    }
+
+    fn set_receive_buffer(&self, buf: &'static mut [u8]) {
+        self.rx_buf.replace(buf);
+    }
+
+    fn enable(&self) -> Result<(), ErrorCode> {
+        if self.enabled.get() {
+            return Err(ErrorCode::ALREADY);
+        }
+        if self.sequence.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.spi.configure(
+            spi::ClockPolarity::IdleLow,
+            spi::ClockPhase::SampleLeading,
+            SPI_SPEED,
+        )?;
+        self.dio0.make_input();
+        self.dio0.enable_interrupts(gpio::InterruptEdge::RisingEdge);
+        self.enabled.set(true);
+        self.run(Sequence::Init);
+        Ok(())
+    }
+
+    fn get_config(&self) -> Config {
+        self.config.get()
+    }
+
+    fn set_config(&self, config: Config) -> Result<(), ErrorCode> {
+        if !FREQUENCY_RANGE.contains(&config.frequency)
+            || !SPREADING_FACTOR_RANGE.contains(&config.spreading_factor)
+            || !TX_POWER_RANGE.contains(&config.tx_power)
+        {
+            return Err(ErrorCode::INVAL);
+        }
+        self.config.set(config);
+        self.config_pending.set(self.ready.get());
+        self.idle();
+        Ok(())
+    }
+
+    fn transmit(
+        &self,
+        buf: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if !self.enabled.get() {
+            return Err((ErrorCode::OFF, buf));
+        }
+        if self.tx_buf.is_some() {
+            return Err((ErrorCode::BUSY, buf));
+        }
+        if len == 0 || len > lora::MAX_PAYLOAD_LEN || len > buf.len() {
+            return Err((ErrorCode::SIZE, buf));
+        }
+        self.tx_buf.replace(buf);
+        self.tx_len.set(len);
+        self.idle();
+        Ok(())
+    }
+
+    fn start_receive(&self) -> Result<(), ErrorCode> {
+        self.receiving.set(true);
+        self.idle();
+        Ok(())
+    }
+
+    fn stop_receive(&self) -> Result<(), ErrorCode> {
+        self.receiving.set(false);
+        self.idle();
+        Ok(())
+    }
+}
+
+impl<'a, S: SpiMasterDevice, A: Alarm<'a>> spi::SpiMasterClient for Sx127x<'a, S, A> {
+    fn read_write_done(
+        &self,
+        write_buffer: &'static mut [u8],
+        read_buffer: Option<&'static mut [u8]>,
+        _len: usize,
+        status: Result<(), ErrorCode>,
+    ) {
+        self.spi_tx.replace(write_buffer);
+        if let Some(read_buffer) = read_buffer {
+            self.spi_rx.replace(read_buffer);
+        }
+        if status.is_err() {
+            return self.abort();
+        }
+        self.op_done();
+    }
+}
+
+impl<'a, S: SpiMasterDevice, A: Alarm<'a>> time::AlarmClient for Sx127x<'a, S, A> {
+    fn alarm(&self) {
+        self.next_op();
+    }
+}
+
+impl<'a, S: SpiMasterDevice, A: Alarm<'a>> gpio::Client for Sx127x<'a, S, A> {
+    fn fired(&self) {
+        self.interrupt_pending.set(true);
+        self.idle();
+    }
+}
@@ -189,4 +189,19 @@ impl<'a, S: KVSystem<'static, K = T>, T: KeyType + core::fmt::Debug> kv_system::
             }
         }
     }
+    fn next_key_complete(
+        &self,
+        _result: Result<(), ErrorCode>,
+        _key: &'static mut T,
+        _position: usize,
+    ) {
+    }
+
+    fn usage_complete(
+        &self,
+        _result: Result<(), ErrorCode>,
+        _live_bytes: usize,
+        _dead_bytes: usize,
+    ) {
+    }
 }
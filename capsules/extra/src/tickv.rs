@@ -35,8 +35,25 @@
 //!
 //!    hil::flash
 //! ```
+//!
+//! `TicKVStore` is also a process console command, `tickv`, which shows how
+//! much of the flash is used by valid and by invalidated objects and how
+//! often each region has been erased since boot, and which can start a
+//! garbage collection:
+//!
+//! ```text
+//! tock$ tickv usage
+//! tickv: adding up the usage, run `tickv` to show it
+//! tock$ tickv
+//! live bytes: 1532, dead bytes: 6144
+//! erases: 12, most in one region: 3
+//! tock$ tickv gc
+//! tickv: garbage collection started
+//! ```
 
+use capsules_core::process_console::ConsoleCommand;
 use core::cell::Cell;
+use core::fmt;
 use kernel::hil::flash::{self, Flash};
 use kernel::hil::hasher::{self, Hasher};
 use kernel::hil::kv_system::{self, KVSystem};
@@ -53,12 +70,20 @@ enum Operation {
     AppendKey,
     InvalidateKey,
     GarbageCollect,
+    NextKey,
+    Usage,
 }
 
+/// The hash of `tickv::MAIN_KEY`, which TicKV stores to mark the flash as
+/// set up. It is not listed by `next_key()`.
+const MAIN_KEY_HASH: u64 = 0x7bc9f7ff4f76f244;
+
 pub struct TickFSFlashCtrl<'a, F: Flash + 'static> {
     flash: &'a F,
     flash_read_buffer: TakeCell<'static, F::Page>,
     region_offset: usize,
+    /// The number of times each region has been erased since boot.
+    erase_counts: TakeCell<'static, [u32]>,
 }
 
 impl<'a, F: Flash> TickFSFlashCtrl<'a, F> {
@@ -71,6 +96,7 @@ impl<'a, F: Flash> TickFSFlashCtrl<'a, F> {
             flash,
             flash_read_buffer: TakeCell::new(flash_read_buffer),
             region_offset,
+            erase_counts: TakeCell::empty(),
         }
     }
 }
@@ -116,6 +142,11 @@ impl<'a, F: Flash> tickv::flash_controller::FlashController<2048> for TickFSFlas
 
     fn erase_region(&self, region_number: usize) -> Result<(), tickv::error_codes::ErrorCode> {
         let _ = self.flash.erase_page(self.region_offset + region_number);
+        self.erase_counts.map(|counts| {
+            if let Some(count) = counts.get_mut(region_number) {
+                *count = count.saturating_add(1);
+            }
+        });
 
         Err(tickv::error_codes::ErrorCode::EraseNotReady(region_number))
     }
@@ -134,6 +165,10 @@ pub struct TicKVStore<'a, F: Flash + 'static, H: Hasher<'a, 8>> {
     ret_buffer: TakeCell<'static, [u8]>,
     unhashed_key_buf: TakeCell<'static, [u8]>,
     key_buf: TakeCell<'static, [u8; 8]>,
+    /// The position to list keys from once init completes.
+    next_position: Cell<usize>,
+    /// The result of the last usage operation.
+    usage: Cell<Option<tickv::Usage>>,
 
     client: OptionalCell<&'a dyn kv_system::Client<TicKVKeyType>>,
 }
@@ -163,12 +198,26 @@ impl<'a, F: Flash, H: Hasher<'a, 8>> TicKVStore<'a, F, H> {
             ret_buffer: TakeCell::empty(),
             unhashed_key_buf: TakeCell::empty(),
             key_buf: TakeCell::empty(),
+            next_position: Cell::new(0),
+            usage: Cell::new(None),
             client: OptionalCell::empty(),
         }
     }
 
+    /// Allow values as long as `pages` flash pages, which are stored in
+    /// several objects.
+    pub fn set_max_value_pages(&self, pages: usize) {
+        self.tickv.tickv.set_max_value_regions(pages);
+    }
+
+    /// Count how often each region is erased, in `counts`, which should
+    /// have an entry for every region of the store.
+    pub fn set_erase_counts(&self, counts: &'static mut [u32]) {
+        self.tickv.tickv.controller.erase_counts.replace(counts);
+    }
+
     pub fn initialise(&self) {
-        let _ret = self.tickv.initialise(0x7bc9f7ff4f76f244);
+        let _ret = self.tickv.initialise(MAIN_KEY_HASH);
         self.operation.set(Operation::Init);
     }
 
@@ -220,9 +269,107 @@ impl<'a, F: Flash, H: Hasher<'a, 8>> TicKVStore<'a, F, H> {
                 }
                 _ => {}
             },
+            Operation::NextKey => {
+                match self.next_key(self.next_position.get(), self.key_buffer.take().unwrap()) {
+                    Err((key, error)) => {
+                        self.client.map(move |cb| {
+                            cb.next_key_complete(error, key, 0);
+                        });
+                    }
+                    _ => {}
+                }
+            }
+            Operation::Usage => match self.usage() {
+                Err(error) => {
+                    self.client.map(move |cb| {
+                        cb.usage_complete(Err(error), 0, 0);
+                    });
+                }
+                _ => {}
+            },
         }
         self.next_operation.set(Operation::None);
     }
+
+    /// Report that appending or invalidating a key failed.
+    fn operation_failed(&self) {
+        match self.operation.get() {
+            Operation::AppendKey => {
+                self.operation.set(Operation::None);
+                self.client.map(|cb| {
+                    cb.append_key_complete(
+                        Err(ErrorCode::FAIL),
+                        self.key_buffer.take().unwrap(),
+                        self.tickv.get_stored_value_buffer().unwrap(),
+                    );
+                });
+            }
+            Operation::InvalidateKey => {
+                self.operation.set(Operation::None);
+                self.client.map(|cb| {
+                    cb.invalidate_key_complete(
+                        Err(ErrorCode::FAIL),
+                        self.key_buffer.take().unwrap(),
+                    );
+                });
+            }
+            _ => {}
+        }
+    }
+
+    /// Handle the result of looking for the next key, which was either
+    /// found at `position` or not found.
+    fn next_key_done(&self, ret: Result<(u64, usize), tickv::error_codes::ErrorCode>) {
+        let (result, position) = match ret {
+            Ok((hash, position)) if hash == MAIN_KEY_HASH => {
+                // Skip the main key, this is found at most once
+                return self.next_key_done(self.tickv.next_key(position));
+            }
+            Ok((hash, position)) => {
+                self.key_buffer.map(|key| *key = hash.to_le_bytes());
+                (Ok(()), position)
+            }
+            Err(tickv::error_codes::ErrorCode::ReadNotReady(_)) => return,
+            Err(tickv::error_codes::ErrorCode::KeyNotFound) => (Err(ErrorCode::NOSUPPORT), 0),
+            Err(_) => (Err(ErrorCode::FAIL), 0),
+        };
+
+        self.operation.set(Operation::None);
+        self.client.map(|cb| {
+            cb.next_key_complete(result, self.key_buffer.take().unwrap(), position);
+        });
+    }
+
+    /// Handle the result of adding up the usage of the regions.
+    fn usage_done(&self, ret: Result<tickv::Usage, tickv::error_codes::ErrorCode>) {
+        let result = match ret {
+            Ok(usage) => {
+                self.usage.set(Some(usage));
+                Ok(usage)
+            }
+            Err(tickv::error_codes::ErrorCode::ReadNotReady(_)) => return,
+            Err(_) => Err(ErrorCode::FAIL),
+        };
+
+        self.operation.set(Operation::None);
+        self.client.map(|cb| match result {
+            Ok(usage) => cb.usage_complete(Ok(()), usage.live_bytes, usage.dead_bytes),
+            Err(e) => cb.usage_complete(Err(e), 0, 0),
+        });
+    }
+
+    /// Write the number of erases of up to 16 regions, from `first`.
+    fn write_erase_counts(&self, first: usize, out: &mut dyn fmt::Write) -> fmt::Result {
+        self.tickv.tickv.controller.erase_counts.map_or(
+            write!(out, "tickv: erases are not counted\r\n"),
+            |counts| {
+                for (region, count) in counts.iter().enumerate().skip(first).take(16) {
+                    write!(out, "region {}: {}\r\n", region, count)?;
+                }
+                Ok(())
+            },
+        )
+    }
 }
 
 impl<'a, F: Flash, H: Hasher<'a, 8>> hasher::Client<8> for TicKVStore<'a, F, H> {
@@ -276,7 +423,9 @@ impl<'a, F: Flash, H: Hasher<'a, 8>> flash::Client<F> for TicKVStore<'a, F, H> {
                         );
                     });
                 }
-                Err(tickv::error_codes::ErrorCode::EraseNotReady(_)) | Ok(_) => {}
+                Err(tickv::error_codes::ErrorCode::ReadNotReady(_))
+                | Err(tickv::error_codes::ErrorCode::EraseNotReady(_))
+                | Ok(_) => {}
                 _ => {
                     self.operation.set(Operation::None);
                     self.client.map(|cb| {
@@ -288,19 +437,15 @@ impl<'a, F: Flash, H: Hasher<'a, 8>> flash::Client<F> for TicKVStore<'a, F, H> {
                     });
                 }
             },
-            Operation::AppendKey => match ret {
+            Operation::AppendKey | Operation::InvalidateKey => match ret {
                 Ok(tickv::success_codes::SuccessCode::Complete)
                 | Ok(tickv::success_codes::SuccessCode::Written) => {
                     self.operation.set(Operation::None);
                 }
-                _ => {}
-            },
-            Operation::InvalidateKey => match ret {
-                Ok(tickv::success_codes::SuccessCode::Complete)
-                | Ok(tickv::success_codes::SuccessCode::Written) => {
-                    self.operation.set(Operation::None);
-                }
-                _ => {}
+                Err(tickv::error_codes::ErrorCode::ReadNotReady(_))
+                | Err(tickv::error_codes::ErrorCode::WriteNotReady(_))
+                | Ok(_) => {}
+                Err(_) => self.operation_failed(),
             },
             Operation::GarbageCollect => match ret {
                 Ok(tickv::success_codes::SuccessCode::Complete)
@@ -312,6 +457,20 @@ impl<'a, F: Flash, H: Hasher<'a, 8>> flash::Client<F> for TicKVStore<'a, F, H> {
                 }
                 _ => {}
             },
+            Operation::NextKey => match ret {
+                Ok(_) => match self.tickv.get_next_key() {
+                    Some(next) => self.next_key_done(Ok(next)),
+                    None => self.next_key_done(Err(tickv::error_codes::ErrorCode::ReadFail)),
+                },
+                Err(e) => self.next_key_done(Err(e)),
+            },
+            Operation::Usage => match ret {
+                Ok(_) => match self.tickv.get_usage() {
+                    Some(usage) => self.usage_done(Ok(usage)),
+                    None => self.usage_done(Err(tickv::error_codes::ErrorCode::ReadFail)),
+                },
+                Err(e) => self.usage_done(Err(e)),
+            },
             _ => unreachable!(),
         }
     }
@@ -323,6 +482,21 @@ impl<'a, F: Flash, H: Hasher<'a, 8>> flash::Client<F> for TicKVStore<'a, F, H> {
             .flash_read_buffer
             .replace(pagebuffer);
 
+        if self.tickv.is_chunk_pending() {
+            // Continue with the next chunk of the value
+            let (ret, _buf) = self.tickv.continue_operation();
+            match ret {
+                Err(tickv::error_codes::ErrorCode::ReadNotReady(_))
+                | Err(tickv::error_codes::ErrorCode::WriteNotReady(_)) => return,
+                Ok(tickv::success_codes::SuccessCode::Queued) => return,
+                Ok(_) => {}
+                Err(_) => {
+                    self.operation_failed();
+                    return;
+                }
+            }
+        }
+
         match self.operation.get() {
             Operation::Init => {
                 self.complete_init();
@@ -563,4 +737,129 @@ impl<'a, F: Flash, H: Hasher<'a, 8>> KVSystem<'a> for TicKVStore<'a, F, H> {
             }
         }
     }
+
+    fn next_key(
+        &self,
+        position: usize,
+        key: &'static mut Self::K,
+    ) -> Result<(), (&'static mut Self::K, Result<(), ErrorCode>)> {
+        match self.operation.get() {
+            Operation::None => {
+                self.operation.set(Operation::NextKey);
+
+                match self.tickv.next_key(position) {
+                    Err(tickv::error_codes::ErrorCode::ReadNotReady(_)) => {
+                        self.key_buffer.replace(key);
+                        Ok(())
+                    }
+                    // The flash is read asynchronously, so the lookup only
+                    // finishes right away if there are no more regions.
+                    Err(tickv::error_codes::ErrorCode::KeyNotFound) => {
+                        self.operation.set(Operation::None);
+                        Err((key, Err(ErrorCode::NOSUPPORT)))
+                    }
+                    _ => {
+                        self.operation.set(Operation::None);
+                        Err((key, Err(ErrorCode::FAIL)))
+                    }
+                }
+            }
+            Operation::Init => {
+                // The init process is still occurring.
+                // We can save this request and start it after init
+                self.next_operation.set(Operation::NextKey);
+                self.next_position.set(position);
+                self.key_buffer.replace(key);
+                Ok(())
+            }
+            _ => {
+                // An operation is already in process.
+                Err((key, Err(ErrorCode::BUSY)))
+            }
+        }
+    }
+
+    fn usage(&self) -> Result<(), ErrorCode> {
+        match self.operation.get() {
+            Operation::None => {
+                self.operation.set(Operation::Usage);
+
+                match self.tickv.usage() {
+                    Err(tickv::error_codes::ErrorCode::ReadNotReady(_)) => Ok(()),
+                    // The flash is read asynchronously, so the usage is
+                    // never added up right away.
+                    _ => {
+                        self.operation.set(Operation::None);
+                        Err(ErrorCode::FAIL)
+                    }
+                }
+            }
+            Operation::Init => {
+                // The init process is still occurring.
+                // We can save this request and start it after init
+                self.next_operation.set(Operation::Usage);
+                Ok(())
+            }
+            _ => {
+                // An operation is already in process.
+                Err(ErrorCode::BUSY)
+            }
+        }
+    }
+}
+
+impl<'a, F: Flash, H: Hasher<'a, 8>> ConsoleCommand for TicKVStore<'a, F, H> {
+    fn name(&self) -> &str {
+        "tickv"
+    }
+
+    fn help(&self) -> &str {
+        "tickv [usage|gc|erases [<first region>]]: show flash usage or collect garbage"
+    }
+
+    fn execute(&self, args: &str, out: &mut dyn fmt::Write) {
+        let mut args = args.split_whitespace();
+        let _ = match args.next() {
+            None => {
+                match self.usage.get() {
+                    Some(usage) => {
+                        let _ = write!(
+                            out,
+                            "live bytes: {}, dead bytes: {}\r\n",
+                            usage.live_bytes, usage.dead_bytes
+                        );
+                    }
+                    None => {
+                        let _ = write!(out, "tickv: no usage yet, run `tickv usage`\r\n");
+                    }
+                }
+                self.tickv
+                    .tickv
+                    .controller
+                    .erase_counts
+                    .map_or(Ok(()), |counts| {
+                        let total: u32 = counts.iter().fold(0, |sum, c| sum.saturating_add(*c));
+                        let max = counts.iter().max().copied().unwrap_or(0);
+                        write!(out, "erases: {}, most in one region: {}\r\n", total, max)
+                    })
+            }
+            Some("usage") => match KVSystem::usage(self) {
+                Ok(()) => write!(
+                    out,
+                    "tickv: adding up the usage, run `tickv` to show it\r\n"
+                ),
+                Err(e) => write!(out, "tickv: usage failed: {:?}\r\n", e),
+            },
+            Some("gc") => match KVSystem::garbage_collect(self) {
+                Ok(_) => write!(out, "tickv: garbage collection started\r\n"),
+                Err(e) => write!(out, "tickv: garbage collection failed: {:?}\r\n", e),
+            },
+            Some("erases") => match args.next().map(|first| first.parse::<usize>()) {
+                None => self.write_erase_counts(0, out),
+                Some(Ok(first)) => self.write_erase_counts(first, out),
+                Some(Err(_)) => write!(out, "usage: {}\r\n", self.help()),
+            },
+            Some(_) => write!(out, "usage: {}\r\n", self.help()),
+        };
+    }
 }
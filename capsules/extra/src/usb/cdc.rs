@@ -109,6 +109,11 @@ impl From<u8> for CDCCntrlMessage {
     }
 }
 
+/// Client notified when a USB host enumerates the CDC device.
+pub trait EnumerationClient {
+    fn enumerated(&self);
+}
+
 /// Implementation of the Abstract Control Model (ACM) for the Communications
 /// Class Device (CDC) over USB.
 pub struct CdcAcm<'a, U: 'a, A: 'a + Alarm<'a>> {
@@ -178,6 +183,9 @@ pub struct CdcAcm<'a, U: 'a, A: 'a + Alarm<'a>> {
     /// This was originally added for the bootloader to allow the host to tell
     /// the device to enter bootloader mode.
     host_initiated_function: Option<&'a (dyn Fn() + 'a)>,
+
+    /// Client to notify when the host enumerates the device.
+    enumeration_client: OptionalCell<&'a dyn EnumerationClient>,
 }
 
 impl<'a, U: hil::usb::UsbController<'a>, A: 'a + Alarm<'a>> CdcAcm<'a, U, A> {
@@ -311,9 +319,22 @@ impl<'a, U: hil::usb::UsbController<'a>, A: 'a + Alarm<'a>> CdcAcm<'a, U, A> {
             deferred_call_pending_droptx: Cell::new(false),
             deferred_call_pending_abortrx: Cell::new(false),
             host_initiated_function,
+            enumeration_client: OptionalCell::empty(),
         }
     }
 
+    pub fn set_enumeration_client(&self, client: &'a dyn EnumerationClient) {
+        self.enumeration_client.set(client);
+    }
+
+    /// Whether a USB host has enumerated the device.
+    pub fn is_enumerated(&self) -> bool {
+        !matches!(
+            self.state.get(),
+            State::Disabled | State::Enabled | State::Attached
+        )
+    }
+
     #[inline]
     pub fn controller(&self) -> &'a U {
         self.client_ctrl.controller()
@@ -400,6 +421,7 @@ impl<'a, U: hil::usb::UsbController<'a>, A: 'a + Alarm<'a>> hil::usb::Client<'a>
     fn bus_reset(&'a self) {
         // We take a bus reset to mean the enumeration has finished.
         self.state.set(State::Enumerated);
+        self.enumeration_client.map(|client| client.enumerated());
     }
 
     /// Handle a Control Setup transaction.
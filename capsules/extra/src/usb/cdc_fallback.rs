@@ -0,0 +1,221 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! UART that uses CDC-ACM over USB once a host enumerates the device, and a
+//! hardware UART otherwise.
+//!
+//! This lets boards with a USB device controller provide the console over the
+//! USB connector without extra wires, while keeping the console on the
+//! hardware UART when USB is not connected, e.g. during early boot or when
+//! the board is only powered.
+//!
+//! Transmissions go to CDC-ACM if the device is enumerated when they start.
+//! An outstanding receive on the hardware UART is moved to CDC-ACM when the
+//! device is enumerated; bytes it already received are dropped.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let cdc_fallback = components::cdc::CdcFallbackComponent::new(cdc, &peripherals.usart3)
+//!     .finalize(components::cdc_fallback_component_static!(
+//!         sam4l::usbc::Usbc,
+//!         sam4l::ast::Ast,
+//!         sam4l::usart::USART
+//!     ));
+//! let uart_mux = UartMuxComponent::new(cdc_fallback, 115200)
+//!     .finalize(components::uart_mux_component_static!());
+//! ```
+//!
+//! The console and the debug writer are then created on `uart_mux` as usual.
+
+use core::cell::Cell;
+
+use super::cdc::{CdcAcm, EnumerationClient};
+use kernel::hil;
+use kernel::hil::time::Alarm;
+use kernel::hil::uart;
+use kernel::utilities::cells::OptionalCell;
+use kernel::ErrorCode;
+
+pub struct CdcFallback<'a, U: 'a, A: 'a + Alarm<'a>, H: 'a + uart::Uart<'a>> {
+    cdc: &'a CdcAcm<'a, U, A>,
+    uart: &'a H,
+    tx_client: OptionalCell<&'a dyn uart::TransmitClient>,
+    rx_client: OptionalCell<&'a dyn uart::ReceiveClient>,
+    /// Whether a receive is outstanding.
+    receiving: Cell<bool>,
+    /// Whether the outstanding receive is on CDC-ACM.
+    rx_on_cdc: Cell<bool>,
+    /// Length of the outstanding receive.
+    rx_len: Cell<usize>,
+    /// Whether the receive on the hardware UART is being aborted to move it
+    /// to CDC-ACM.
+    moving_rx: Cell<bool>,
+}
+
+impl<'a, U: hil::usb::UsbController<'a>, A: 'a + Alarm<'a>, H: 'a + uart::Uart<'a>>
+    CdcFallback<'a, U, A, H>
+{
+    pub fn new(cdc: &'a CdcAcm<'a, U, A>, uart: &'a H) -> Self {
+        CdcFallback {
+            cdc,
+            uart,
+            tx_client: OptionalCell::empty(),
+            rx_client: OptionalCell::empty(),
+            receiving: Cell::new(false),
+            rx_on_cdc: Cell::new(false),
+            rx_len: Cell::new(0),
+            moving_rx: Cell::new(false),
+        }
+    }
+
+    fn start_receive(
+        &self,
+        rx_buffer: &'static mut [u8],
+        rx_len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        let on_cdc = self.cdc.is_enumerated();
+        if on_cdc {
+            uart::Receive::receive_buffer(self.cdc, rx_buffer, rx_len)
+        } else {
+            self.uart.receive_buffer(rx_buffer, rx_len)
+        }?;
+        self.receiving.set(true);
+        self.rx_on_cdc.set(on_cdc);
+        self.rx_len.set(rx_len);
+        Ok(())
+    }
+}
+
+impl<'a, U: hil::usb::UsbController<'a>, A: 'a + Alarm<'a>, H: 'a + uart::Uart<'a>>
+    EnumerationClient for CdcFallback<'a, U, A, H>
+{
+    fn enumerated(&self) {
+        if self.receiving.get() && !self.rx_on_cdc.get() && !self.moving_rx.get() {
+            // The receive is moved when the UART returns the buffer.
+            self.moving_rx.set(true);
+            if self.uart.receive_abort().is_ok() {
+                // The receive completed in the meantime.
+                self.moving_rx.set(false);
+            }
+        }
+    }
+}
+
+impl<'a, U: hil::usb::UsbController<'a>, A: 'a + Alarm<'a>, H: 'a + uart::Uart<'a>> uart::Configure
+    for CdcFallback<'a, U, A, H>
+{
+    fn configure(&self, params: uart::Parameters) -> Result<(), ErrorCode> {
+        uart::Configure::configure(self.cdc, params)?;
+        self.uart.configure(params)
+    }
+}
+
+impl<'a, U: hil::usb::UsbController<'a>, A: 'a + Alarm<'a>, H: 'a + uart::Uart<'a>>
+    uart::Transmit<'a> for CdcFallback<'a, U, A, H>
+{
+    fn set_transmit_client(&self, client: &'a dyn uart::TransmitClient) {
+        self.tx_client.set(client);
+    }
+
+    fn transmit_buffer(
+        &self,
+        tx_buffer: &'static mut [u8],
+        tx_len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if self.cdc.is_enumerated() {
+            uart::Transmit::transmit_buffer(self.cdc, tx_buffer, tx_len)
+        } else {
+            self.uart.transmit_buffer(tx_buffer, tx_len)
+        }
+    }
+
+    fn transmit_abort(&self) -> Result<(), ErrorCode> {
+        if self.cdc.is_enumerated() {
+            uart::Transmit::transmit_abort(self.cdc)
+        } else {
+            self.uart.transmit_abort()
+        }
+    }
+
+    fn transmit_word(&self, _word: u32) -> Result<(), ErrorCode> {
+        Err(ErrorCode::FAIL)
+    }
+}
+
+impl<'a, U: hil::usb::UsbController<'a>, A: 'a + Alarm<'a>, H: 'a + uart::Uart<'a>>
+    uart::Receive<'a> for CdcFallback<'a, U, A, H>
+{
+    fn set_receive_client(&self, client: &'a dyn uart::ReceiveClient) {
+        self.rx_client.set(client);
+    }
+
+    fn receive_buffer(
+        &self,
+        rx_buffer: &'static mut [u8],
+        rx_len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if self.receiving.get() {
+            Err((ErrorCode::BUSY, rx_buffer))
+        } else {
+            self.start_receive(rx_buffer, rx_len)
+        }
+    }
+
+    fn receive_abort(&self) -> Result<(), ErrorCode> {
+        if !self.receiving.get() {
+            Ok(())
+        } else if self.rx_on_cdc.get() {
+            uart::Receive::receive_abort(self.cdc)
+        } else {
+            // A receive being moved is cancelled instead once the UART
+            // returns the buffer.
+            self.moving_rx.set(false);
+            self.uart.receive_abort()
+        }
+    }
+
+    fn receive_word(&self) -> Result<(), ErrorCode> {
+        Err(ErrorCode::FAIL)
+    }
+}
+
+impl<'a, U: hil::usb::UsbController<'a>, A: 'a + Alarm<'a>, H: 'a + uart::Uart<'a>>
+    uart::TransmitClient for CdcFallback<'a, U, A, H>
+{
+    fn transmitted_buffer(
+        &self,
+        tx_buffer: &'static mut [u8],
+        tx_len: usize,
+        rval: Result<(), ErrorCode>,
+    ) {
+        self.tx_client
+            .map(move |client| client.transmitted_buffer(tx_buffer, tx_len, rval));
+    }
+}
+
+impl<'a, U: hil::usb::UsbController<'a>, A: 'a + Alarm<'a>, H: 'a + uart::Uart<'a>>
+    uart::ReceiveClient for CdcFallback<'a, U, A, H>
+{
+    fn received_buffer(
+        &self,
+        rx_buffer: &'static mut [u8],
+        rx_len: usize,
+        rval: Result<(), ErrorCode>,
+        error: uart::Error,
+    ) {
+        self.receiving.set(false);
+        let rx_buffer = if self.moving_rx.replace(false) && rval == Err(ErrorCode::CANCEL) {
+            match self.start_receive(rx_buffer, self.rx_len.get()) {
+                Ok(()) => return,
+                Err((_, rx_buffer)) => rx_buffer,
+            }
+        } else {
+            rx_buffer
+        };
+        self.rx_client
+            .map(move |client| client.received_buffer(rx_buffer, rx_len, rval, error));
+    }
+}
@@ -3,6 +3,7 @@
 // Copyright Tock Contributors 2022.
 
 pub mod cdc;
+pub mod cdc_fallback;
 pub mod ctap;
 pub mod descriptors;
 pub mod usb_user;
@@ -0,0 +1,316 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Lets one trusted process implement a peripheral driver.
+//!
+//! The driver process, identified by its fixed `ShortID`, gets access to
+//! the peripheral register ranges (`MmioWindow`s) and interrupts listed
+//! by the board, and can pin a buffer in its memory for the peripheral to
+//! DMA into. Other processes cannot use this driver. See
+//! `kernel::platform::userspace_driver` for the kernel side.
+//!
+//! A process with driver privilege can interfere with anything its
+//! peripherals can reach, so it must be trusted like kernel code. The
+//! kernel only enforces that it stays within its windows and interrupts
+//! and that DMA address registers point into its pinned buffer. The
+//! process must stop any DMA before it unpins its buffer, shrinks its
+//! memory or exits.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! struct DriverCap;
+//! unsafe impl capabilities::UserspaceDriverCapability for DriverCap {}
+//!
+//! // SPIM2 on the nRF52840, with its DMA pointer registers.
+//! let windows = static_init!(
+//!     [MmioWindow; 2],
+//!     unsafe {
+//!         [
+//!             MmioWindow::new(0x4002_3000, 0x534, MmioAccess::ReadWrite),
+//!             MmioWindow::new(0x4002_3534, 0x4, MmioAccess::DmaAddress),
+//!         ]
+//!     }
+//! );
+//! let userspace_driver = static_init!(
+//!     UserspaceDriver<'static, ProcessMgmtCap>,
+//!     UserspaceDriver::new(
+//!         board_kernel,
+//!         ShortID::Fixed(NonZeroU32::new(0x5d1).unwrap()),
+//!         windows,
+//!         &[35],
+//!         board_kernel.create_grant(userspace_driver::DRIVER_NUM, &grant_cap),
+//!         ProcessMgmtCap,
+//!         &DriverCap,
+//!     )
+//! );
+//!
+//! // Forward interrupt 35 at most 1000 times per second, in front of the
+//! // chip's interrupt service.
+//! let forwardable = static_init!([ForwardableInterrupt; 1], [ForwardableInterrupt::new(35)]);
+//! let forwarder = static_init!(
+//!     InterruptForwarding<'static, VirtualMuxAlarm<'static, Rtc>, Nrf52840DefaultPeripherals>,
+//!     InterruptForwarding::new(
+//!         forwardable,
+//!         static_init!(NvicInterruptMask, NvicInterruptMask::new()),
+//!         base_peripherals,
+//!         forwarding_alarm,
+//!         1000,
+//!         1000,
+//!     )
+//! );
+//! forwarding_alarm.set_alarm_client(forwarder);
+//! let chip = static_init!(
+//!     nrf52840::chip::NRF52<InterruptForwarding<...>>,
+//!     nrf52840::chip::NRF52::new(forwarder)
+//! );
+//!
+//! userspace_driver.set_interrupt_forwarder(forwarder);
+//! forwarder.set_client(userspace_driver);
+//! ```
+//!
+//! Command Interface
+//! -----------------
+//!
+//! All commands but the existence check return `NOSUPPORT` to processes
+//! other than the driver process.
+//!
+//! - `0`: Driver existence check.
+//! - `1`: Read the 32-bit register at address `data1`.
+//! - `2`: Write `data2` to the 32-bit register at address `data1`. Writes
+//!   to DMA address registers must be within the pinned buffer, or up to
+//!   the address right after its end.
+//! - `3`: Base address and length of window `data1`.
+//! - `4`: Forward interrupt `data1` to the process.
+//! - `5`: Acknowledge forwarded interrupt `data1`, unmasking it.
+//! - `6`: Stop forwarding interrupt `data1`.
+//! - `7`: Pin the buffer shared with read-write allow `0` for DMA. `data1`
+//!   is the alignment the peripheral requires (a power of two, `0` for
+//!   word alignment). Returns the address and length of the buffer.
+//! - `8`: Unpin the DMA buffer.
+//! - `9`: How often interrupt `data1` was forwarded, and how often it was
+//!   held masked after an acknowledgment because it fired too often.
+//!
+//! Upcall `0` is called with the interrupt number when a forwarded
+//! interrupt fires. The interrupt stays masked until it is acknowledged.
+
+use kernel::capabilities::{ProcessManagementCapability, UserspaceDriverCapability};
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::platform::userspace_driver::{
+    ForwardedInterruptClient, ForwardingStats, InterruptForwarder, MmioAccess, MmioWindow,
+};
+use kernel::process::ShortID;
+use kernel::processbuffer::ReadableProcessBuffer;
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::OptionalCell;
+use kernel::{ErrorCode, Kernel, ProcessId};
+
+/// Syscall driver number.
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::UserspaceDriver as usize;
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    pub const DMA: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+#[derive(Default)]
+pub struct App {
+    /// Start and length of the pinned DMA buffer.
+    pinned: Option<(usize, usize)>,
+}
+
+pub struct UserspaceDriver<'a, C: ProcessManagementCapability> {
+    kernel: &'static Kernel,
+    driver: ShortID,
+    windows: &'a [MmioWindow],
+    irqs: &'a [u32],
+    forwarder: OptionalCell<&'a dyn InterruptForwarder<'a>>,
+    apps: Grant<App, UpcallCount<1>, AllowRoCount<0>, AllowRwCount<{ rw_allow::COUNT }>>,
+    capability: C,
+}
+
+impl<'a, C: ProcessManagementCapability> UserspaceDriver<'a, C> {
+    /// `driver` identifies the driver process, which may access the
+    /// registers in `windows` and the interrupts in `irqs`.
+    pub fn new(
+        kernel: &'static Kernel,
+        driver: ShortID,
+        windows: &'a [MmioWindow],
+        irqs: &'a [u32],
+        grant: Grant<App, UpcallCount<1>, AllowRoCount<0>, AllowRwCount<{ rw_allow::COUNT }>>,
+        capability: C,
+        _driver_capability: &dyn UserspaceDriverCapability,
+    ) -> UserspaceDriver<'a, C> {
+        UserspaceDriver {
+            kernel,
+            driver,
+            windows,
+            irqs,
+            forwarder: OptionalCell::empty(),
+            apps: grant,
+            capability,
+        }
+    }
+
+    /// Set the forwarder delivering the interrupts in `irqs`.
+    pub fn set_interrupt_forwarder(&self, forwarder: &'a dyn InterruptForwarder<'a>) {
+        self.forwarder.set(forwarder);
+    }
+
+    fn is_driver(&self, processid: ProcessId) -> bool {
+        self.kernel.process_map_or_external(
+            false,
+            processid,
+            |process| process.short_app_id() == self.driver,
+            &self.capability,
+        )
+    }
+
+    fn window(&self, address: usize) -> Option<&MmioWindow> {
+        self.windows.iter().find(|window| window.contains(address))
+    }
+
+    /// Whether `value` points into the pinned buffer of `processid`, and
+    /// the buffer is still in memory the process has access to.
+    fn valid_dma_address(&self, processid: ProcessId, app: &App, value: usize) -> bool {
+        app.pinned.is_some_and(|(start, len)| {
+            let accessible = self.kernel.process_map_or_external(
+                false,
+                processid,
+                |process| {
+                    let addresses = process.get_addresses();
+                    start >= addresses.sram_start && start + len <= addresses.sram_app_brk
+                },
+                &self.capability,
+            );
+            accessible && value >= start && value <= start + len
+        })
+    }
+
+    fn irq_command(&self, irq: usize, command_num: usize) -> Result<(), ErrorCode> {
+        let irq = self
+            .irqs
+            .iter()
+            .copied()
+            .find(|i| *i as usize == irq)
+            .ok_or(ErrorCode::INVAL)?;
+        let forwarder = self.forwarder.extract().ok_or(ErrorCode::NOSUPPORT)?;
+        match command_num {
+            4 => forwarder.enable(irq),
+            5 => forwarder.acknowledge(irq),
+            _ => {
+                forwarder.disable(irq);
+                Ok(())
+            }
+        }
+    }
+
+    fn irq_stats(&self, irq: usize) -> Option<ForwardingStats> {
+        let irq = self.irqs.iter().copied().find(|i| *i as usize == irq)?;
+        self.forwarder.extract()?.stats(irq)
+    }
+}
+
+impl<'a, C: ProcessManagementCapability> ForwardedInterruptClient for UserspaceDriver<'a, C> {
+    fn interrupt_forwarded(&self, irq: u32) {
+        self.kernel
+            .process_each_capability(&self.capability, |process| {
+                if process.short_app_id() == self.driver {
+                    let _ = self.apps.enter(process.processid(), |_, kernel_data| {
+                        let _ = kernel_data.schedule_upcall(0, (irq as usize, 0, 0));
+                    });
+                }
+            });
+    }
+}
+
+impl<'a, C: ProcessManagementCapability> SyscallDriver for UserspaceDriver<'a, C> {
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        if command_num == 0 {
+            return CommandReturn::success();
+        }
+        if !self.is_driver(processid) {
+            return CommandReturn::failure(ErrorCode::NOSUPPORT);
+        }
+        match command_num {
+            1 => match self.window(data1).map(|window| window.read(data1)) {
+                Some(Ok(value)) => CommandReturn::success_u32(value),
+                Some(Err(e)) => CommandReturn::failure(e),
+                None => CommandReturn::failure(ErrorCode::INVAL),
+            },
+            2 => {
+                let window = match self.window(data1) {
+                    Some(window) => window,
+                    None => return CommandReturn::failure(ErrorCode::INVAL),
+                };
+                if window.access() == MmioAccess::DmaAddress {
+                    let valid = self
+                        .apps
+                        .enter(processid, |app, _| {
+                            self.valid_dma_address(processid, app, data2)
+                        })
+                        .unwrap_or(false);
+                    if !valid {
+                        return CommandReturn::failure(ErrorCode::INVAL);
+                    }
+                }
+                window.write(data1, data2 as u32).into()
+            }
+            3 => match self.windows.get(data1) {
+                Some(window) => {
+                    CommandReturn::success_u32_u32(window.base() as u32, window.size() as u32)
+                }
+                None => CommandReturn::failure(ErrorCode::INVAL),
+            },
+            4..=6 => self.irq_command(data1, command_num).into(),
+            7 => {
+                let alignment = if data1 == 0 { 4 } else { data1 };
+                if !alignment.is_power_of_two() {
+                    return CommandReturn::failure(ErrorCode::INVAL);
+                }
+                self.apps
+                    .enter(processid, |app, kernel_data| {
+                        let (start, len) = kernel_data
+                            .get_readwrite_processbuffer(rw_allow::DMA)
+                            .map_or((0, 0), |buffer| (buffer.ptr() as usize, buffer.len()));
+                        if len == 0 {
+                            CommandReturn::failure(ErrorCode::RESERVE)
+                        } else if start % alignment != 0 {
+                            CommandReturn::failure(ErrorCode::INVAL)
+                        } else {
+                            app.pinned = Some((start, len));
+                            CommandReturn::success_u32_u32(start as u32, len as u32)
+                        }
+                    })
+                    .unwrap_or_else(|err| CommandReturn::failure(err.into()))
+            }
+            8 => self
+                .apps
+                .enter(processid, |app, _| {
+                    app.pinned = None;
+                    CommandReturn::success()
+                })
+                .unwrap_or_else(|err| CommandReturn::failure(err.into())),
+            9 => match self.irq_stats(data1) {
+                Some(stats) => CommandReturn::success_u32_u32(stats.forwarded, stats.throttled),
+                None => CommandReturn::failure(ErrorCode::INVAL),
+            },
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
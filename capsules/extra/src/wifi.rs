@@ -0,0 +1,387 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Provides userspace with a Wi-Fi station: scanning for networks, joining
+//! one, and TCP and UDP sockets to IPv4 hosts through it.
+//!
+//! The station is a `hil::wifi::Station`, which runs the network stack, so
+//! the data of sockets is passed through as is. It is in one network at a
+//! time, so one process at a time uses the driver: the first one to issue a
+//! command other than the existence check, until it exits. Data received
+//! while there is no such process is dropped.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let wifi = static_init!(
+//!     capsules_extra::wifi::WifiDriver<'static, Esp32At<'static>>,
+//!     capsules_extra::wifi::WifiDriver::new(
+//!         esp32,
+//!         board_kernel.create_grant(capsules_extra::wifi::DRIVER_NUM, &grant_cap),
+//!         static_init!([u8; capsules_extra::wifi::BUF_LEN], [0; capsules_extra::wifi::BUF_LEN]),
+//!     )
+//! );
+//! esp32.set_scan_client(wifi);
+//! esp32.set_station_client(wifi);
+//! esp32.set_socket_client(wifi);
+//! ```
+
+use core::cell::Cell;
+use core::cmp::min;
+
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::wifi::{self, Network, Protocol, Security, Station};
+use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::{ErrorCode, ProcessId};
+
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Wifi as usize;
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    pub const SSID: usize = 0;
+    pub const PASSPHRASE: usize = 1;
+    pub const SEND: usize = 2;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 3;
+}
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    pub const SCAN: usize = 0;
+    pub const RECEIVE: usize = 1;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 2;
+}
+
+mod upcall {
+    pub const SCAN_DONE: usize = 0;
+    pub const JOIN_DONE: usize = 1;
+    pub const DISCONNECTED: usize = 2;
+    pub const OPENED: usize = 3;
+    pub const SEND_DONE: usize = 4;
+    pub const RECEIVED: usize = 5;
+    pub const CLOSED: usize = 6;
+    pub const COUNT: u8 = 7;
+}
+
+/// The length of the buffer data to send is copied into.
+pub const BUF_LEN: usize = 1024;
+
+/// The length of a network in the scan results: the SSID padded with zeros,
+/// its length, the RSSI, the channel and the security.
+const NETWORK_LEN: usize = wifi::MAX_SSID_LEN + 4;
+
+fn security_code(security: Security) -> u8 {
+    match security {
+        Security::Open => 0,
+        Security::Wep => 1,
+        Security::WpaPsk => 2,
+        Security::Wpa2Psk => 3,
+        Security::WpaWpa2Psk => 4,
+        Security::Wpa2Enterprise => 5,
+        Security::Wpa3Psk => 6,
+        Security::Wpa2Wpa3Psk => 7,
+        Security::Unknown => 0xff,
+    }
+}
+
+#[derive(Default)]
+pub struct App;
+
+pub struct WifiDriver<'a, S: Station<'a>> {
+    station: &'a S,
+    apps: Grant<
+        App,
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+    /// The process that uses the station.
+    owner: OptionalCell<ProcessId>,
+    /// The number of networks found by the current scan.
+    scan_count: Cell<usize>,
+    kernel_tx: TakeCell<'static, [u8]>,
+}
+
+impl<'a, S: Station<'a>> WifiDriver<'a, S> {
+    pub fn new(
+        station: &'a S,
+        grant: Grant<
+            App,
+            UpcallCount<{ upcall::COUNT }>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+        kernel_tx: &'static mut [u8],
+    ) -> WifiDriver<'a, S> {
+        WifiDriver {
+            station,
+            apps: grant,
+            owner: OptionalCell::empty(),
+            scan_count: Cell::new(0),
+            kernel_tx: TakeCell::new(kernel_tx),
+        }
+    }
+
+    /// Make `processid` the owner, unless another process is.
+    fn claim(&self, processid: ProcessId) -> Result<(), ErrorCode> {
+        let taken = self.owner.map_or(false, |owner| {
+            *owner != processid && self.apps.enter(*owner, |_, _| {}).is_ok()
+        });
+        if taken {
+            Err(ErrorCode::RESERVE)
+        } else {
+            self.owner.set(processid);
+            Ok(())
+        }
+    }
+
+    /// Schedule `upcall_num` of the owner.
+    fn notify(&self, upcall_num: usize, args: (usize, usize, usize)) {
+        self.owner.map(|owner| {
+            let _ = self.apps.enter(*owner, |_, kernel_data| {
+                kernel_data.schedule_upcall(upcall_num, args).ok();
+            });
+        });
+    }
+
+    /// Join the network in the read-only allows of `processid`.
+    fn join(&self, processid: ProcessId) -> Result<(), ErrorCode> {
+        let mut ssid = [0; wifi::MAX_SSID_LEN];
+        let mut passphrase = [0; wifi::MAX_PASSPHRASE_LEN];
+        let (ssid_len, passphrase_len) = self
+            .apps
+            .enter(processid, |_, kernel_data| {
+                let ssid_len = kernel_data
+                    .get_readonly_processbuffer(ro_allow::SSID)
+                    .and_then(|buffer| {
+                        buffer.enter(|buffer| {
+                            let len = min(buffer.len(), ssid.len());
+                            buffer[..len].copy_to_slice(&mut ssid[..len]);
+                            buffer.len()
+                        })
+                    })
+                    .unwrap_or(0);
+                let passphrase_len = kernel_data
+                    .get_readonly_processbuffer(ro_allow::PASSPHRASE)
+                    .and_then(|buffer| {
+                        buffer.enter(|buffer| {
+                            let len = min(buffer.len(), passphrase.len());
+                            buffer[..len].copy_to_slice(&mut passphrase[..len]);
+                            buffer.len()
+                        })
+                    })
+                    .unwrap_or(0);
+                (ssid_len, passphrase_len)
+            })
+            .map_err(ErrorCode::from)?;
+        if ssid_len > ssid.len() || passphrase_len > passphrase.len() {
+            return Err(ErrorCode::SIZE);
+        }
+        self.station
+            .join(&ssid[..ssid_len], &passphrase[..passphrase_len])
+    }
+
+    /// Send the first `len` bytes of the read-only allow of `processid` on
+    /// `socket`.
+    fn send(&self, processid: ProcessId, socket: usize, len: usize) -> Result<(), ErrorCode> {
+        let buffer = self.kernel_tx.take().ok_or(ErrorCode::BUSY)?;
+        if len == 0 || len > buffer.len() {
+            self.kernel_tx.replace(buffer);
+            return Err(ErrorCode::SIZE);
+        }
+        let result = self
+            .apps
+            .enter(processid, |_, kernel_data| {
+                kernel_data
+                    .get_readonly_processbuffer(ro_allow::SEND)
+                    .and_then(|data| {
+                        data.enter(|data| {
+                            if data.len() < len {
+                                return Err(ErrorCode::SIZE);
+                            }
+                            data[..len].copy_to_slice(&mut buffer[..len]);
+                            Ok(())
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::NOMEM))
+            })
+            .unwrap_or_else(|err| Err(err.into()));
+        match result {
+            Ok(()) => self
+                .station
+                .send(socket, buffer, len)
+                .map_err(|(err, buffer)| {
+                    self.kernel_tx.replace(buffer);
+                    err
+                }),
+            Err(err) => {
+                self.kernel_tx.replace(buffer);
+                Err(err)
+            }
+        }
+    }
+}
+
+impl<'a, S: Station<'a>> SyscallDriver for WifiDriver<'a, S> {
+    /// Control the Wi-Fi station.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver existence check.
+    /// - `1`: Scan for networks. They are written to read-write allow 0, in
+    ///   records of 36 bytes: the SSID padded with zeros to 32 bytes, its
+    ///   length, the RSSI in dBm as a signed byte, the channel and the
+    ///   security (0 open, 1 WEP, 2 WPA-PSK, 3 WPA2-PSK, 4 WPA/WPA2-PSK,
+    ///   5 WPA2-Enterprise, 6 WPA3-PSK, 7 WPA2/WPA3-PSK, 255 unknown).
+    /// - `2`: Join the network whose SSID is in read-only allow 0, with the
+    ///   passphrase in read-only allow 1, which is empty for open networks.
+    /// - `3`: Leave the network.
+    /// - `4`: Whether the station is in a network: success if it is, OFF
+    ///   otherwise.
+    /// - `5`: Open a socket to the IPv4 address `arg1`, whose first byte is
+    ///   the most significant one. `arg2` holds the port in bits 0-15, the
+    ///   socket in bits 16-23, and is a UDP socket if bit 24 is set and a TCP
+    ///   socket otherwise.
+    /// - `6`: Send the first `arg2` bytes of read-only allow 2 on socket
+    ///   `arg1`.
+    /// - `7`: Close socket `arg1`.
+    /// - `8`: Get the number of sockets.
+    fn command(
+        &self,
+        command_num: usize,
+        arg1: usize,
+        arg2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        if command_num == 0 {
+            return CommandReturn::success();
+        }
+        if let Err(err) = self.claim(processid) {
+            return CommandReturn::failure(err);
+        }
+        match command_num {
+            1 => {
+                let result = self.station.scan();
+                if result.is_ok() {
+                    self.scan_count.set(0);
+                }
+                result.into()
+            }
+            2 => self.join(processid).into(),
+            3 => self.station.leave().into(),
+            4 => {
+                if self.station.is_joined() {
+                    CommandReturn::success()
+                } else {
+                    CommandReturn::failure(ErrorCode::OFF)
+                }
+            }
+            5 => {
+                let protocol = if arg2 & (1 << 24) != 0 {
+                    Protocol::Udp
+                } else {
+                    Protocol::Tcp
+                };
+                let address = (arg1 as u32).to_be_bytes();
+                let socket = (arg2 >> 16) & 0xff;
+                self.station
+                    .open(socket, protocol, address, arg2 as u16)
+                    .into()
+            }
+            6 => self.send(processid, arg1, arg2).into(),
+            7 => self.station.close(arg1).into(),
+            8 => CommandReturn::success_u32(self.station.num_sockets() as u32),
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+impl<'a, S: Station<'a>> wifi::ScanClient for WifiDriver<'a, S> {
+    fn network_found(&self, network: Network) {
+        let index = self.scan_count.get();
+        self.scan_count.set(index + 1);
+        self.owner.map(|owner| {
+            let _ = self.apps.enter(*owner, |_, kernel_data| {
+                let _ = kernel_data
+                    .get_readwrite_processbuffer(rw_allow::SCAN)
+                    .and_then(|buffer| {
+                        buffer.mut_enter(|buffer| {
+                            let start = index * NETWORK_LEN;
+                            if let Some(record) = buffer.get(start..start + NETWORK_LEN) {
+                                let ssid_len = wifi::MAX_SSID_LEN;
+                                record[..ssid_len].copy_from_slice(&network.ssid);
+                                record[ssid_len].set(network.ssid_len as u8);
+                                record[ssid_len + 1].set(network.rssi as u8);
+                                record[ssid_len + 2].set(network.channel);
+                                record[ssid_len + 3].set(security_code(network.security));
+                            }
+                        })
+                    });
+            });
+        });
+    }
+
+    fn scan_done(&self, result: Result<(), ErrorCode>) {
+        self.notify(
+            upcall::SCAN_DONE,
+            (into_statuscode(result), self.scan_count.get(), 0),
+        );
+    }
+}
+
+impl<'a, S: Station<'a>> wifi::StationClient for WifiDriver<'a, S> {
+    fn join_done(&self, result: Result<(), ErrorCode>) {
+        self.notify(upcall::JOIN_DONE, (into_statuscode(result), 0, 0));
+    }
+
+    fn disconnected(&self) {
+        self.notify(upcall::DISCONNECTED, (0, 0, 0));
+    }
+}
+
+impl<'a, S: Station<'a>> wifi::SocketClient for WifiDriver<'a, S> {
+    fn opened(&self, socket: usize, result: Result<(), ErrorCode>) {
+        self.notify(upcall::OPENED, (into_statuscode(result), socket, 0));
+    }
+
+    fn send_done(&self, socket: usize, buf: &'static mut [u8], result: Result<(), ErrorCode>) {
+        self.kernel_tx.replace(buf);
+        self.notify(upcall::SEND_DONE, (into_statuscode(result), socket, 0));
+    }
+
+    /// Copy `data` to the owner, cut to the read-write allow 1.
+    fn received(&self, socket: usize, data: &[u8]) {
+        self.owner.map(|owner| {
+            let _ = self.apps.enter(*owner, |_, kernel_data| {
+                let copied = kernel_data
+                    .get_readwrite_processbuffer(rw_allow::RECEIVE)
+                    .and_then(|buffer| {
+                        buffer.mut_enter(|buffer| {
+                            let len = min(buffer.len(), data.len());
+                            buffer[..len].copy_from_slice(&data[..len]);
+                            len
+                        })
+                    })
+                    .unwrap_or(0);
+                kernel_data
+                    .schedule_upcall(upcall::RECEIVED, (socket, copied, data.len()))
+                    .ok();
+            });
+        });
+    }
+
+    fn closed(&self, socket: usize) {
+        self.notify(upcall::CLOSED, (socket, 0, 0));
+    }
+}
@@ -90,6 +90,7 @@ impl<I: InterruptService + 'static> Chip for Apollo3<I> {
         unsafe {
             loop {
                 if let Some(interrupt) = cortexm4::nvic::next_pending() {
+                    cortexm4::irq_latency::record(interrupt as usize);
                     if !self.interrupt_service.service_interrupt(interrupt) {
                         panic!("unhandled interrupt, {}", interrupt);
                     }
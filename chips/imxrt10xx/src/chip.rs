@@ -110,6 +110,7 @@ impl<I: InterruptService + 'static> Chip for Imxrt10xx<I> {
         unsafe {
             loop {
                 if let Some(interrupt) = cortexm7::nvic::next_pending() {
+                    cortexm7::irq_latency::record(interrupt as usize);
                     let handled = self.interrupt_service.service_interrupt(interrupt);
                     assert!(handled, "Unhandled interrupt number {}", interrupt);
                     let n = cortexm7::nvic::Nvic::new(interrupt);
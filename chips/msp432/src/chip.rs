@@ -112,6 +112,7 @@ impl<'a, I: InterruptService + 'a> Chip for Msp432<'a, I> {
         unsafe {
             loop {
                 if let Some(interrupt) = cortexm4::nvic::next_pending() {
+                    cortexm4::irq_latency::record(interrupt as usize);
                     if !self.interrupt_service.service_interrupt(interrupt) {
                         panic!("unhandled interrupt {}", interrupt);
                     }
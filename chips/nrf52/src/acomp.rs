@@ -229,6 +229,11 @@ impl<'a> Comparator<'a> {
         }
     }
 
+    /// Whether COMP currently owns the shared COMP/LPCOMP peripheral.
+    pub fn is_enabled(&self) -> bool {
+        self.registers.enable.matches_all(Enable::ENABLE::Enabled)
+    }
+
     /// Enables comparator
     /// Uses differential mode, with no hysteresis, and normal speed and power
     /// VIN+ = AIN5 and VIN- = AIN0
@@ -39,6 +39,7 @@
 
 use core::cell::Cell;
 use core::convert::TryFrom;
+use core::ptr::{addr_of, addr_of_mut};
 use kernel::hil::ble_advertising;
 use kernel::hil::ble_advertising::RadioChannel;
 use kernel::utilities::cells::OptionalCell;
@@ -534,12 +535,43 @@ register_bitfields! [u32,
 static mut PAYLOAD: [u8; nrf5x::constants::RADIO_PAYLOAD_LENGTH] =
     [0x00; nrf5x::constants::RADIO_PAYLOAD_LENGTH];
 
+// BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 2.3.2.1 SCAN_REQ
+// Header (2 bytes) + ScanA (6 bytes) + AdvA (6 bytes)
+const SCAN_REQ_LENGTH: usize = 14;
+
+static mut SCAN_REQ: [u8; SCAN_REQ_LENGTH] = [0x00; SCAN_REQ_LENGTH];
+
+// BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 2.3 Advertising Channel PDU
+const PDU_TYPE_MASK: u8 = 0x0f;
+const PDU_TXADD: u8 = 1 << 6;
+const PDU_RXADD: u8 = 1 << 7;
+const ADV_IND: u8 = 0b0000;
+const ADV_SCAN_IND: u8 = 0b0110;
+
+// BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 4.1 Inter Frame Space
+const T_IFS_US: u32 = 150;
+
+/// The steps of a scan request exchange, see
+/// `BleAdvertisementDriver::receive_advertisement_and_scan`.
+#[derive(Copy, Clone, PartialEq)]
+enum ScanPhase {
+    Off,
+    /// Receiving an advertisement
+    Listen,
+    /// Sending the `SCAN_REQ`
+    Request,
+    /// Receiving the `SCAN_RSP`
+    Response,
+}
+
 pub struct Radio<'a> {
     registers: StaticRef<RadioRegisters>,
     tx_power: Cell<TxPower>,
     rx_client: OptionalCell<&'a dyn ble_advertising::RxClient>,
     tx_client: OptionalCell<&'a dyn ble_advertising::TxClient>,
     buffer: TakeCell<'static, [u8]>,
+    scan_req: TakeCell<'static, [u8]>,
+    scan_phase: Cell<ScanPhase>,
 }
 
 impl<'a> Radio<'a> {
@@ -550,6 +582,8 @@ impl<'a> Radio<'a> {
             rx_client: OptionalCell::empty(),
             tx_client: OptionalCell::empty(),
             buffer: TakeCell::empty(),
+            scan_req: TakeCell::empty(),
+            scan_phase: Cell::new(ScanPhase::Off),
         }
     }
 
@@ -605,8 +639,11 @@ impl<'a> Radio<'a> {
 
         if self.registers.event_ready.is_set(Event::READY) {
             self.registers.event_ready.write(Event::READY::CLEAR);
-            self.registers.event_end.write(Event::READY::CLEAR);
-            self.registers.task_start.write(Task::ENABLE::SET);
+            // During a scan request exchange the radio starts by itself
+            if !self.registers.shorts.is_set(Shortcut::READY_START) {
+                self.registers.event_end.write(Event::READY::CLEAR);
+                self.registers.task_start.write(Task::ENABLE::SET);
+            }
         }
 
         if self.registers.event_address.is_set(Event::READY) {
@@ -626,6 +663,12 @@ impl<'a> Radio<'a> {
                 Err(ErrorCode::FAIL)
             };
 
+            if self.scan_phase.get() != ScanPhase::Off {
+                self.scan_event(result);
+                self.enable_interrupts();
+                return;
+            }
+
             match self.registers.state.get() {
                 nrf5x::constants::RADIO_STATE_TXRU
                 | nrf5x::constants::RADIO_STATE_TXIDLE
@@ -656,6 +699,70 @@ impl<'a> Radio<'a> {
         self.enable_interrupts();
     }
 
+    // One step of a scan request exchange is done. The shortcuts already turn
+    // the radio around, so only the packet pointer needs to change in time.
+    fn scan_event(&self, result: Result<(), ErrorCode>) {
+        match self.scan_phase.get() {
+            ScanPhase::Listen => {
+                let header = unsafe { PAYLOAD[0] };
+                let pdu_type = header & PDU_TYPE_MASK;
+                if result.is_ok() && (pdu_type == ADV_IND || pdu_type == ADV_SCAN_IND) {
+                    self.scan_req.map(|scan_req| {
+                        let request = unsafe { &mut *addr_of_mut!(SCAN_REQ) };
+                        let advertisement = unsafe { &*addr_of!(PAYLOAD) };
+                        request.copy_from_slice(&scan_req[..SCAN_REQ_LENGTH]);
+                        // Address the request to the advertiser
+                        request[0] = (request[0] & !PDU_RXADD) | ((header & PDU_TXADD) << 1);
+                        request[8..14].copy_from_slice(&advertisement[2..8]);
+                        self.registers.packetptr.set(request.as_ptr() as u32);
+                    });
+                    self.registers.shorts.write(
+                        Shortcut::READY_START::SET
+                            + Shortcut::END_DISABLE::SET
+                            + Shortcut::DISABLED_RXEN::SET,
+                    );
+                    self.scan_phase.set(ScanPhase::Request);
+                    self.receive_scan_packet(result);
+                } else {
+                    // Stop the radio before it sends a request
+                    self.registers.shorts.set(0);
+                    self.radio_off();
+                    self.receive_scan_packet(result);
+                    self.finish_scan();
+                }
+            }
+            ScanPhase::Request => {
+                self.set_dma_ptr();
+                self.registers
+                    .shorts
+                    .write(Shortcut::READY_START::SET + Shortcut::END_DISABLE::SET);
+                self.scan_phase.set(ScanPhase::Response);
+            }
+            ScanPhase::Response => {
+                self.registers.shorts.set(0);
+                self.radio_off();
+                self.receive_scan_packet(result);
+                self.finish_scan();
+            }
+            ScanPhase::Off => {}
+        }
+    }
+
+    fn receive_scan_packet(&self, result: Result<(), ErrorCode>) {
+        let payload = unsafe { &mut *addr_of_mut!(PAYLOAD) };
+        let len = payload[1] + 2;
+        self.rx_client
+            .map(|client| client.receive_event(payload, len, result));
+    }
+
+    fn finish_scan(&self) {
+        self.scan_phase.set(ScanPhase::Off);
+        if let Some(scan_req) = self.scan_req.take() {
+            self.tx_client
+                .map(|client| client.transmit_event(scan_req, Ok(())));
+        }
+    }
+
     pub fn enable_interrupts(&self) {
         self.registers.intenset.write(
             Interrupt::READY::SET
@@ -806,6 +913,30 @@ impl<'a> ble_advertising::BleAdvertisementDriver<'a> for Radio<'a> {
         self.enable_interrupts();
     }
 
+    fn receive_advertisement_and_scan(
+        &self,
+        channel: RadioChannel,
+        scan_req: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if len != SCAN_REQ_LENGTH || scan_req.len() < len {
+            return Err((ErrorCode::SIZE, scan_req));
+        }
+        self.scan_req.replace(scan_req);
+        self.scan_phase.set(ScanPhase::Listen);
+        self.ble_initialize(channel);
+        // Send the scan request the inter frame space after the advertisement
+        self.registers
+            .tifs
+            .write(InterFrameSpacing::TIFS.val(T_IFS_US));
+        self.registers.shorts.write(
+            Shortcut::READY_START::SET + Shortcut::END_DISABLE::SET + Shortcut::DISABLED_TXEN::SET,
+        );
+        self.rx();
+        self.enable_interrupts();
+        Ok(())
+    }
+
     fn set_receive_client(&self, client: &'a dyn ble_advertising::RxClient) {
         self.rx_client.set(client);
     }
@@ -4,13 +4,21 @@
 
 use core::fmt::Write;
 use cortexm4::{self, nvic, CortexM4, CortexMVariant};
-use kernel::hil::time::Alarm;
+use kernel::hil::time::{Alarm, LowPowerHandover};
 use kernel::platform::chip::InterruptService;
+use kernel::platform::idle::{IdleSource, SleepBudget, SleepDepth};
+use kernel::utilities::cells::OptionalCell;
+
+/// Time for HFCLK to restart from the crystal oscillator after it was
+/// released during deep sleep.
+const HFCLK_STARTUP_US: u32 = 400;
 
 pub struct NRF52<'a, I: InterruptService + 'a> {
     mpu: cortexm4::mpu::MPU,
     userspace_kernel_boundary: cortexm4::syscall::SysCall,
     interrupt_service: &'a I,
+    low_power_alarm: OptionalCell<&'a dyn LowPowerHandover>,
+    idle_arbiter: OptionalCell<&'a dyn IdleSource>,
 }
 
 impl<'a, I: InterruptService + 'a> NRF52<'a, I> {
@@ -19,8 +27,22 @@ impl<'a, I: InterruptService + 'a> NRF52<'a, I> {
             mpu: cortexm4::mpu::MPU::new(),
             userspace_kernel_boundary: cortexm4::syscall::SysCall::new(),
             interrupt_service,
+            low_power_alarm: OptionalCell::empty(),
+            idle_arbiter: OptionalCell::empty(),
         }
     }
+
+    /// Register an alarm that migrates to the RTC while the chip sleeps,
+    /// so that HFCLK can be released during long idle periods.
+    pub fn set_low_power_alarm(&self, alarm: &'a dyn LowPowerHandover) {
+        self.low_power_alarm.set(alarm);
+    }
+
+    /// Only migrate the low-power alarm to the RTC, releasing HFCLK, when
+    /// `arbiter` allows deep sleep with enough time to restart HFCLK.
+    pub fn set_idle_arbiter(&self, arbiter: &'a dyn IdleSource) {
+        self.idle_arbiter.set(arbiter);
+    }
 }
 
 /// This struct, when initialized, instantiates all peripheral drivers for the apollo3.
@@ -29,12 +51,14 @@ impl<'a, I: InterruptService + 'a> NRF52<'a, I> {
 /// constructed manually in main.rs.
 pub struct Nrf52DefaultPeripherals<'a> {
     pub acomp: crate::acomp::Comparator<'a>,
+    pub lpcomp: crate::lpcomp::LowPowerComparator<'a>,
     pub ecb: crate::aes::AesECB<'a>,
     pub pwr_clk: crate::power::Power<'a>,
     pub ieee802154_radio: crate::ieee802154_radio::Radio<'a>,
     pub ble_radio: crate::ble_radio::Radio<'a>,
     pub trng: crate::trng::Trng<'a>,
     pub rtc: crate::rtc::Rtc<'a>,
+    pub rtc2: crate::rtc::Rtc<'a>,
     pub temp: crate::temperature::Temp<'a>,
     pub timer0: crate::timer::TimerAlarm<'a>,
     pub timer1: crate::timer::TimerAlarm<'a>,
@@ -55,12 +79,14 @@ impl<'a> Nrf52DefaultPeripherals<'a> {
     pub fn new() -> Self {
         Self {
             acomp: crate::acomp::Comparator::new(),
+            lpcomp: crate::lpcomp::LowPowerComparator::new(),
             ecb: crate::aes::AesECB::new(),
             pwr_clk: crate::power::Power::new(),
             ieee802154_radio: crate::ieee802154_radio::Radio::new(),
             ble_radio: crate::ble_radio::Radio::new(),
             trng: crate::trng::Trng::new(),
             rtc: crate::rtc::Rtc::new(),
+            rtc2: crate::rtc::Rtc::new_rtc2(),
             temp: crate::temperature::Temp::new(),
             timer0: crate::timer::TimerAlarm::new(0),
             timer1: crate::timer::TimerAlarm::new(1),
@@ -87,7 +113,20 @@ impl<'a> Nrf52DefaultPeripherals<'a> {
 impl<'a> kernel::platform::chip::InterruptService for Nrf52DefaultPeripherals<'a> {
     unsafe fn service_interrupt(&self, interrupt: u32) -> bool {
         match interrupt {
-            crate::peripheral_interrupts::COMP => self.acomp.handle_interrupt(),
+            crate::peripheral_interrupts::COMP => {
+                // COMP and LPCOMP share an interrupt.
+                // Dispatch the correct handler.
+                match (self.acomp.is_enabled(), self.lpcomp.is_enabled()) {
+                    (false, false) => (),
+                    (true, false) => self.acomp.handle_interrupt(),
+                    (false, true) => self.lpcomp.handle_interrupt(),
+                    (true, true) => debug_assert!(
+                        false,
+                        "COMP and LPCOMP cannot be \
+                         enabled at the same time."
+                    ),
+                }
+            }
             crate::peripheral_interrupts::ECB => self.ecb.handle_interrupt(),
             crate::peripheral_interrupts::POWER_CLOCK => self.pwr_clk.handle_interrupt(),
             crate::peripheral_interrupts::RADIO => {
@@ -105,6 +144,7 @@ impl<'a> kernel::platform::chip::InterruptService for Nrf52DefaultPeripherals<'a
             }
             crate::peripheral_interrupts::RNG => self.trng.handle_interrupt(),
             crate::peripheral_interrupts::RTC1 => self.rtc.handle_interrupt(),
+            crate::peripheral_interrupts::RTC2 => self.rtc2.handle_interrupt(),
             crate::peripheral_interrupts::TEMP => self.temp.handle_interrupt(),
             crate::peripheral_interrupts::TIMER0 => self.timer0.handle_interrupt(),
             crate::peripheral_interrupts::TIMER1 => self.timer1.handle_interrupt(),
@@ -162,6 +202,7 @@ impl<'a, I: InterruptService + 'a> kernel::platform::chip::Chip for NRF52<'a, I>
         unsafe {
             loop {
                 if let Some(interrupt) = nvic::next_pending() {
+                    cortexm4::irq_latency::record(interrupt as usize);
                     if !self.interrupt_service.service_interrupt(interrupt) {
                         panic!("unhandled interrupt {}", interrupt);
                     }
@@ -180,9 +221,16 @@ impl<'a, I: InterruptService + 'a> kernel::platform::chip::Chip for NRF52<'a, I>
     }
 
     fn sleep(&self) {
+        let budget = self
+            .idle_arbiter
+            .map_or(SleepBudget::UNCONSTRAINED, |arbiter| arbiter.sleep_budget());
+        if budget.allows(SleepDepth::Deep, HFCLK_STARTUP_US) {
+            self.low_power_alarm.map(|alarm| alarm.prepare_sleep());
+        }
         unsafe {
             cortexm4::support::wfi();
         }
+        self.low_power_alarm.map(|alarm| alarm.wakeup());
     }
 
     unsafe fn atomic<F, R>(&self, f: F) -> R
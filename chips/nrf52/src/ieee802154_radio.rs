@@ -5,6 +5,7 @@
 //! IEEE 802.15.4 radio driver for nRF52
 
 use core::cell::Cell;
+use core::cmp;
 use core::convert::TryFrom;
 use kernel;
 use kernel::hil::radio::{self, PowerClient};
@@ -36,6 +37,8 @@ pub const IEEE802154_MAX_BE: u8 = 5;
 pub const RAM_LEN_BITS: usize = 8;
 pub const RAM_S1_BITS: usize = 0;
 pub const PREBUF_LEN_BYTES: usize = 2;
+/// Offset of the energy detect level, in dBm
+pub const ED_RSSIOFFS: i16 = -93;
 
 // artifact of entanglement with rf233 implementation, mac layer
 // places packet data starting PSDU_OFFSET=2 bytes after start of
@@ -147,8 +150,12 @@ struct RadioRegisters {
     /// Stop the bit counter
     /// - Address: 0x020 - 0x024
     task_bcstop: WriteOnly<u32, Task::Register>,
-    /// Reserved
-    _reserved1: [u32; 2],
+    /// Start the energy detect measurement used in IEEE 802.15.4 mode
+    /// - Address: 0x024 - 0x028
+    task_edstart: WriteOnly<u32, Task::Register>,
+    /// Stop the energy detect measurement
+    /// - Address: 0x028 - 0x02c
+    task_edstop: WriteOnly<u32, Task::Register>,
     /// Stop the bit counter
     /// - Address: 0x02c - 0x030
     task_ccastart: WriteOnly<u32, Task::Register>,
@@ -197,8 +204,12 @@ struct RadioRegisters {
     /// IEEE 802.15.4 length field received
     /// - Address: 0x138 - 0x13c
     event_framestart: ReadWrite<u32, Event::Register>,
-    /// Reserved
-    _reserved5: [u32; 2],
+    /// Sampling of energy detection complete
+    /// - Address: 0x13c - 0x140
+    event_edend: ReadWrite<u32, Event::Register>,
+    /// The sampling of energy detection has stopped
+    /// - Address: 0x140 - 0x144
+    event_edstopped: ReadWrite<u32, Event::Register>,
     /// Wireless medium in idle - clear to send
     /// - Address: 0x144-0x148
     event_ccaidle: ReadWrite<u32, Event::Register>,
@@ -325,12 +336,20 @@ struct RadioRegisters {
     /// - Address: 0x650 - 0x654
     modecnf0: ReadWrite<u32, RadioModeConfig::Register>,
     /// Reserved
-    _reserved16: [u32; 6],
+    _reserved16: [u32; 3],
+    /// Number of iterations of an energy detect measurement
+    /// - Address: 0x660 - 0x664
+    edcnt: ReadWrite<u32, EnergyDetectCount::Register>,
+    /// Sampled energy level of the last energy detect measurement
+    /// - Address: 0x664 - 0x668
+    edsample: ReadOnly<u32, EnergyDetectSample::Register>,
+    /// Reserved
+    _reserved17: [u32; 1],
     /// Clear Channel Assesment (CCA) control register
     /// - Address: 0x66C - 0x670
     ccactrl: ReadWrite<u32, CCAControl::Register>,
     /// Reserved
-    _reserved17: [u32; 611],
+    _reserved18: [u32; 611],
     /// Peripheral power control
     /// - Address: 0xFFC - 0x1000
     power: ReadWrite<u32, Task::Register>,
@@ -392,6 +411,10 @@ register_bitfields! [u32,
         CRCERROR OFFSET(13) NUMBITS(1),
         /// CCAIDLE event
         FRAMESTART OFFSET(14) NUMBITS(1),
+        /// EDEND event
+        EDEND OFFSET(15) NUMBITS(1),
+        /// EDSTOPPED event
+        EDSTOPPED OFFSET(16) NUMBITS(1),
         /// CCAIDLE event
         CCAIDLE OFFSET(17) NUMBITS(1),
         /// CCABUSY event
@@ -631,6 +654,17 @@ register_bitfields! [u32,
     MACHeaderMask [
         PATTERN OFFSET(0) NUMBITS(32)
     ],
+    /// Energy detect count register
+    EnergyDetectCount [
+        /// Number of iterations, minus one, of 128 us each. The largest
+        /// level of all iterations is reported
+        EDCNT OFFSET(0) NUMBITS(21)
+    ],
+    /// Energy detect sample register
+    EnergyDetectSample [
+        /// IEEE 802.15.4 energy detect level
+        EDLVL OFFSET(0) NUMBITS(8)
+    ],
     CCAControl [
         CCAMODE OFFSET(0) NUMBITS(3) [
             ED_MODE = 0,
@@ -678,6 +712,10 @@ pub struct Radio<'a> {
     channel: Cell<RadioChannel>,
     transmitting: Cell<bool>,
     timer0: OptionalCell<&'a crate::timer::TimerAlarm<'a>>,
+    rx_rssi: Cell<Option<i8>>,
+    ed_client: OptionalCell<&'a dyn radio::EnergyDetectClient>,
+    /// Channel of the energy detection in progress
+    ed_channel: OptionalCell<RadioChannel>,
 }
 
 impl<'a> AlarmClient for Radio<'a> {
@@ -704,6 +742,9 @@ impl<'a> Radio<'a> {
             channel: Cell::new(RadioChannel::DataChannel26),
             transmitting: Cell::new(false),
             timer0: OptionalCell::empty(),
+            rx_rssi: Cell::new(None),
+            ed_client: OptionalCell::empty(),
+            ed_channel: OptionalCell::empty(),
         }
     }
 
@@ -775,7 +816,11 @@ impl<'a> Radio<'a> {
         if self.registers.event_ready.is_set(Event::READY) {
             self.registers.event_ready.write(Event::READY::CLEAR);
             self.registers.event_end.write(Event::READY::CLEAR);
-            if self.transmitting.get()
+            if self.ed_channel.is_some() {
+                // Measure a single period of 8 symbols
+                self.registers.edcnt.write(EnergyDetectCount::EDCNT.val(0));
+                self.registers.task_edstart.write(Task::ENABLE::SET);
+            } else if self.transmitting.get()
                 && self.registers.state.get() == nrf5x::constants::RADIO_STATE_RXIDLE
             {
                 self.registers.task_ccastart.write(Task::ENABLE::SET);
@@ -831,6 +876,22 @@ impl<'a> Radio<'a> {
             self.enable_interrupts();
         }
 
+        if self.registers.event_edend.is_set(Event::READY) {
+            self.registers.event_edend.write(Event::READY::CLEAR);
+
+            let level = self.registers.edsample.read(EnergyDetectSample::EDLVL) as i16;
+            let dbm = cmp::min(ED_RSSIOFFS + level, i8::MAX as i16) as i8;
+
+            // Return to receiving on the configured channel
+            let channel = self.ed_channel.take();
+            self.radio_off();
+            self.radio_initialize();
+            channel.map(|channel| {
+                self.ed_client
+                    .map(|client| client.energy_detect_done(channel.get_channel_index(), Ok(dbm)));
+            });
+        }
+
         // tx or rx finished!
         if self.registers.event_end.is_set(Event::READY) {
             self.registers.event_end.write(Event::READY::CLEAR);
@@ -868,6 +929,11 @@ impl<'a> Radio<'a> {
                         // And because the length field is directly read from the packet
                         // We need to add 2 to length to get the total length
 
+                        // The RSSI is sampled once the address is received,
+                        // and the sample is the magnitude of the RSSI in dBm
+                        let rssi = self.registers.rssisample.read(RssiSample::RSSISAMPLE);
+                        self.rx_rssi.set(Some(-(rssi as i8)));
+
                         client.receive(rbuf, frame_len, self.registers.crcstatus.get() == 1, result)
                     });
                 }
@@ -887,7 +953,8 @@ impl<'a> Radio<'a> {
                 + Interrupt::CCAIDLE::SET
                 + Interrupt::CCABUSY::SET
                 + Interrupt::END::SET
-                + Interrupt::FRAMESTART::SET,
+                + Interrupt::FRAMESTART::SET
+                + Interrupt::EDEND::SET,
         );
     }
 
@@ -925,11 +992,16 @@ impl<'a> Radio<'a> {
 
         self.ieee802154_set_tx_power();
 
-        self.ieee802154_set_channel_freq(self.channel.get());
+        self.ieee802154_set_channel_freq(self.ed_channel.unwrap_or(self.channel.get()));
 
         self.set_tx_address();
         self.set_rx_address();
 
+        // Sample the RSSI of received frames
+        self.registers
+            .shorts
+            .write(Shortcut::ADDRESS_RSSISTART::SET);
+
         // First step in transmitting or receiving is entering rx mode
         self.rx();
     }
@@ -1113,6 +1185,23 @@ impl<'a> kernel::hil::radio::RadioConfig<'a> for Radio<'a> {
         }
     }
 
+    fn energy_detect(&self, channel: u8) -> Result<(), ErrorCode> {
+        let channel = RadioChannel::try_from(channel).map_err(|()| ErrorCode::INVAL)?;
+        if self.transmitting.get() || self.ed_channel.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        // Ramp up on the channel to measure, and start measuring once ready
+        self.ed_channel.set(channel);
+        self.radio_off();
+        self.radio_initialize();
+        Ok(())
+    }
+
+    fn set_energy_detect_client(&self, client: &'a dyn radio::EnergyDetectClient) {
+        self.ed_client.set(client);
+    }
+
     fn set_tx_power(&self, tx_power: i8) -> Result<(), ErrorCode> {
         // Convert u8 to TxPower
         match nrf5x::constants::TxPower::try_from(tx_power as u8) {
@@ -1137,6 +1226,13 @@ impl<'a> kernel::hil::radio::RadioData<'a> for Radio<'a> {
         self.rx_buf.replace(buffer);
     }
 
+    fn get_rx_metadata(&self) -> radio::RxMetadata {
+        radio::RxMetadata {
+            rssi: self.rx_rssi.get(),
+            lqi: None,
+        }
+    }
+
     fn set_transmit_client(&self, client: &'a dyn radio::TxClient) {
         self.tx_client.set(client);
     }
@@ -1146,7 +1242,7 @@ impl<'a> kernel::hil::radio::RadioData<'a> for Radio<'a> {
         buf: &'static mut [u8],
         frame_len: usize,
     ) -> Result<(), (ErrorCode, &'static mut [u8])> {
-        if self.tx_buf.is_some() || self.transmitting.get() {
+        if self.tx_buf.is_some() || self.transmitting.get() || self.ed_channel.is_some() {
             return Err((ErrorCode::BUSY, buf));
         } else if radio::PSDU_OFFSET + frame_len >= buf.len() {
             // Not enough room for CRC
@@ -16,10 +16,12 @@ pub mod crt1;
 pub mod ficr;
 pub mod i2c;
 pub mod ieee802154_radio;
+pub mod lpcomp;
 pub mod nvmc;
 pub mod power;
 pub mod ppi;
 pub mod pwm;
+pub mod reset;
 pub mod spi;
 pub mod uart;
 pub mod uicr;
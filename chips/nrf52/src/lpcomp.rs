@@ -0,0 +1,409 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2026.
+
+//! Low-Power Comparator Peripheral Driver, for nrf52
+//!
+//! Based on the nrf52 COMP driver in `acomp.rs`.
+//!
+//! The low-power comparator (LPCOMP) compares an input voltage (VIN+), which
+//! comes from an analog input pin (AIN0-AIN7), against a reference voltage
+//! (VIN-). The reference can be derived from a fraction of VDD (in 1/16 VDD
+//! steps) or from an external reference pin (AIN0 or AIN1).
+//!
+//! Unlike COMP, LPCOMP keeps running in System OFF and can generate an
+//! ANADETECT signal that wakes the chip up. This makes it suitable for e.g.
+//! waking a device when a battery is connected or a lid is opened. After such
+//! a wake-up the chip goes through a reset, which can be detected with
+//! `nrf52::power::Power::reset_reason_lpcomp()`.
+//!
+//! COMP and LPCOMP share the same base address and interrupt, so only one of
+//! them can be enabled at any given time. Enabling LPCOMP while COMP is
+//! enabled fails with `ErrorCode::BUSY`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let analog_comparator = components::analog_comparator::AnalogComparatorComponent::new(
+//!     &base_peripherals.lpcomp,
+//!     components::analog_comparator_component_helper!(
+//!         nrf52840::lpcomp::Channel,
+//!         &nrf52840::lpcomp::CHANNEL_AC0
+//!     ),
+//!     board_kernel,
+//!     capsules_extra::analog_comparator::DRIVER_NUM,
+//! )
+//! .finalize(components::analog_comparator_component_static!(
+//!     nrf52840::lpcomp::LowPowerComparator
+//! ));
+//! ```
+
+use core::cell::Cell;
+use kernel::hil::analog_comparator;
+use kernel::utilities::cells::OptionalCell;
+use kernel::utilities::registers::interfaces::{Readable, Writeable};
+use kernel::utilities::registers::{
+    register_bitfields, register_structs, ReadOnly, ReadWrite, WriteOnly,
+};
+use kernel::utilities::StaticRef;
+use kernel::ErrorCode;
+
+/// Like COMP, LPCOMP only has a single comparator. The HIL expects channels,
+/// so we expose a single dummy channel.
+pub struct Channel {
+    _chan_num: u32,
+}
+
+/// Only one channel
+#[derive(Copy, Clone, Debug)]
+#[repr(u8)]
+enum ChannelNumber {
+    AC0 = 0x00,
+}
+
+impl Channel {
+    /// Create a new LPCOMP channel.
+    ///
+    /// - `channel`: Channel enum representing the channel number
+    const fn new(channel: ChannelNumber) -> Channel {
+        Channel {
+            _chan_num: (channel as u32) & 0x0F,
+        }
+    }
+}
+
+/// The only channel, VIN+ and VIN- are selected with `configure()`.
+pub static mut CHANNEL_AC0: Channel = Channel::new(ChannelNumber::AC0);
+
+register_structs! {
+    LpcompRegisters {
+        /// Start comparator
+        (0x000 => tasks_start: WriteOnly<u32>),
+        /// Stop comparator
+        (0x004 => tasks_stop: WriteOnly<u32>),
+        /// Sample comparator value, updates the RESULT register
+        (0x008 => tasks_sample: WriteOnly<u32>),
+        (0x00c => _reserved0),
+        /// LPCOMP is ready and output is valid
+        (0x100 => events_ready: ReadWrite<u32>),
+        /// Downward crossing
+        (0x104 => events_down: ReadWrite<u32>),
+        /// Upward crossing
+        (0x108 => events_up: ReadWrite<u32>),
+        /// Downward or upward crossing
+        (0x10c => events_cross: ReadWrite<u32>),
+        (0x110 => _reserved1),
+        /// Shortcuts between local events and tasks
+        (0x200 => shorts: ReadWrite<u32>),
+        (0x204 => _reserved2),
+        /// Enable interrupts
+        (0x304 => intenset: ReadWrite<u32, InterruptEnable::Register>),
+        /// Disable interrupts
+        (0x308 => intenclr: ReadWrite<u32, InterruptEnable::Register>),
+        (0x30c => _reserved3),
+        /// Compare result
+        (0x400 => result: ReadOnly<u32, ComparisonResult::Register>),
+        (0x404 => _reserved4),
+        /// Enable LPCOMP
+        (0x500 => enable: ReadWrite<u32, Enable::Register>),
+        /// Input pin select
+        (0x504 => psel: ReadWrite<u32, PinSelect::Register>),
+        /// Reference select
+        (0x508 => refsel: ReadWrite<u32, ReferenceSelect::Register>),
+        /// External reference select
+        (0x50c => extrefsel: ReadWrite<u32, ExternalRefSelect::Register>),
+        (0x510 => _reserved5),
+        /// Analog detect configuration, used for wake-up from System OFF
+        (0x520 => anadetect: ReadWrite<u32, AnalogDetect::Register>),
+        (0x524 => _reserved6),
+        /// Comparator hysteresis enable
+        (0x538 => hyst: ReadWrite<u32, Hysteresis::Register>),
+        (0x53c => @END),
+    }
+}
+
+register_bitfields! [
+    u32,
+    InterruptEnable [
+        READY OFFSET(0) NUMBITS(1) [],
+        DOWN OFFSET(1) NUMBITS(1) [],
+        UP OFFSET(2) NUMBITS(1) [],
+        CROSS OFFSET(3) NUMBITS(1) []
+    ],
+    ComparisonResult [
+        RESULT OFFSET(0) NUMBITS(1) [
+            /// VIN+ < VIN-
+            Below = 0,
+            /// VIN+ > VIN-
+            Above = 1
+        ]
+    ],
+    Enable [
+        ENABLE OFFSET(0) NUMBITS(2) [
+            Disabled = 0,
+            Enabled = 1,
+            /// Written by COMP, which shares this register.
+            ComparatorEnabled = 2
+        ]
+    ],
+    PinSelect [
+        PSEL OFFSET(0) NUMBITS(3) []
+    ],
+    ReferenceSelect [
+        REFSEL OFFSET(0) NUMBITS(4) [
+            Ref1_8Vdd = 0,
+            Ref2_8Vdd = 1,
+            Ref3_8Vdd = 2,
+            Ref4_8Vdd = 3,
+            Ref5_8Vdd = 4,
+            Ref6_8Vdd = 5,
+            Ref7_8Vdd = 6,
+            ARef = 7,
+            Ref1_16Vdd = 8,
+            Ref3_16Vdd = 9,
+            Ref5_16Vdd = 10,
+            Ref7_16Vdd = 11,
+            Ref9_16Vdd = 12,
+            Ref11_16Vdd = 13,
+            Ref13_16Vdd = 14,
+            Ref15_16Vdd = 15
+        ]
+    ],
+    ExternalRefSelect [
+        EXTREFSEL OFFSET(0) NUMBITS(1) [
+            AnalogReference0 = 0,
+            AnalogReference1 = 1
+        ]
+    ],
+    AnalogDetect [
+        ANADETECT OFFSET(0) NUMBITS(2) [
+            Cross = 0,
+            Up = 1,
+            Down = 2
+        ]
+    ],
+    Hysteresis [
+        HYST OFFSET(0) NUMBITS(1) []
+    ]
+];
+
+/// Analog input pin used as VIN+.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AnalogInput {
+    AIN0 = 0,
+    AIN1 = 1,
+    AIN2 = 2,
+    AIN3 = 3,
+    AIN4 = 4,
+    AIN5 = 5,
+    AIN6 = 6,
+    AIN7 = 7,
+}
+
+/// Source of VIN-.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Reference {
+    /// A fraction of VDD, in sixteenths (1 to 15).
+    VddSixteenths(u8),
+    /// External reference on AIN0.
+    External0,
+    /// External reference on AIN1.
+    External1,
+}
+
+/// Crossing direction that wakes the chip from System OFF.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum WakeCondition {
+    Cross,
+    Up,
+    Down,
+}
+
+pub struct LowPowerComparator<'a> {
+    registers: StaticRef<LpcompRegisters>,
+    client: OptionalCell<&'a dyn analog_comparator::Client>,
+    input: Cell<AnalogInput>,
+    reference: Cell<Reference>,
+    hysteresis: Cell<bool>,
+}
+
+impl<'a> LowPowerComparator<'a> {
+    pub const fn new() -> Self {
+        LowPowerComparator {
+            registers: LPCOMP_BASE,
+            client: OptionalCell::empty(),
+            input: Cell::new(AnalogInput::AIN5),
+            reference: Cell::new(Reference::VddSixteenths(8)),
+            hysteresis: Cell::new(false),
+        }
+    }
+
+    /// Select VIN+, VIN- and whether the 50 mV hysteresis is enabled.
+    ///
+    /// Defaults to VIN+ = AIN5, VIN- = VDD/2 without hysteresis. Changes take
+    /// effect the next time the comparator is enabled.
+    pub fn configure(
+        &self,
+        input: AnalogInput,
+        reference: Reference,
+        hysteresis: bool,
+    ) -> Result<(), ErrorCode> {
+        if let Reference::VddSixteenths(n) = reference {
+            if Self::refsel_for_sixteenths(n).is_none() {
+                return Err(ErrorCode::INVAL);
+            }
+        }
+        self.input.set(input);
+        self.reference.set(reference);
+        self.hysteresis.set(hysteresis);
+        Ok(())
+    }
+
+    /// Whether LPCOMP currently owns the shared COMP/LPCOMP peripheral.
+    pub fn is_enabled(&self) -> bool {
+        self.registers.enable.matches_all(Enable::ENABLE::Enabled)
+    }
+
+    fn refsel_for_sixteenths(n: u8) -> Option<u32> {
+        match n {
+            // Multiples of 1/8 VDD.
+            2 | 4 | 6 | 8 | 10 | 12 | 14 => Some((n / 2 - 1) as u32),
+            // Odd multiples of 1/16 VDD.
+            1 | 3 | 5 | 7 | 9 | 11 | 13 | 15 => Some((n / 2 + 8) as u32),
+            _ => None,
+        }
+    }
+
+    /// Whether COMP currently owns the shared COMP/LPCOMP peripheral.
+    fn comp_is_enabled(&self) -> bool {
+        self.registers
+            .enable
+            .matches_all(Enable::ENABLE::ComparatorEnabled)
+    }
+
+    fn enable(&self) -> Result<(), ErrorCode> {
+        if self.is_enabled() {
+            return Ok(());
+        }
+        if self.comp_is_enabled() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        self.registers
+            .psel
+            .write(PinSelect::PSEL.val(self.input.get() as u32));
+        match self.reference.get() {
+            Reference::VddSixteenths(n) => {
+                // Validated in `configure()`.
+                let refsel = Self::refsel_for_sixteenths(n).unwrap_or(7);
+                self.registers
+                    .refsel
+                    .write(ReferenceSelect::REFSEL.val(refsel));
+            }
+            Reference::External0 => {
+                self.registers.refsel.write(ReferenceSelect::REFSEL::ARef);
+                self.registers
+                    .extrefsel
+                    .write(ExternalRefSelect::EXTREFSEL::AnalogReference0);
+            }
+            Reference::External1 => {
+                self.registers.refsel.write(ReferenceSelect::REFSEL::ARef);
+                self.registers
+                    .extrefsel
+                    .write(ExternalRefSelect::EXTREFSEL::AnalogReference1);
+            }
+        }
+        self.registers
+            .hyst
+            .write(Hysteresis::HYST.val(self.hysteresis.get() as u32));
+
+        self.registers.enable.write(Enable::ENABLE::Enabled);
+        self.registers.events_ready.set(0);
+        self.registers.tasks_start.set(1);
+        // Startup time is on the order of 140 us at most, spin wait is OK.
+        while self.registers.events_ready.get() == 0 {}
+        Ok(())
+    }
+
+    fn disable(&self) {
+        self.registers.tasks_stop.set(1);
+        self.registers.enable.write(Enable::ENABLE::Disabled);
+    }
+
+    /// Arm LPCOMP so that the chip wakes up from System OFF when the selected
+    /// crossing happens.
+    ///
+    /// The board must then call `nrf52::power::Power::system_off()` to enter
+    /// System OFF. Interrupts are disabled because the CPU is not running in
+    /// System OFF; the wake-up is signalled by a reset instead.
+    ///
+    /// Returns `ErrorCode::BUSY` if COMP is enabled.
+    pub fn enable_wake_from_system_off(&self, condition: WakeCondition) -> Result<(), ErrorCode> {
+        if self.comp_is_enabled() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.registers.intenclr.set(0xffff_ffff);
+        self.registers.anadetect.write(match condition {
+            WakeCondition::Cross => AnalogDetect::ANADETECT::Cross,
+            WakeCondition::Up => AnalogDetect::ANADETECT::Up,
+            WakeCondition::Down => AnalogDetect::ANADETECT::Down,
+        });
+        self.enable()?;
+        // Clear stale events so we do not wake up immediately.
+        self.registers.events_up.set(0);
+        self.registers.events_down.set(0);
+        self.registers.events_cross.set(0);
+        Ok(())
+    }
+
+    /// Handles upward crossing events (when VIN+ becomes greater than VIN-)
+    pub fn handle_interrupt(&self) {
+        if self.registers.events_up.get() == 1 {
+            self.registers.events_up.set(0);
+            self.client.map(|client| {
+                client.fired(0);
+            });
+        }
+    }
+}
+
+impl<'a> analog_comparator::AnalogComparator<'a> for LowPowerComparator<'a> {
+    type Channel = Channel;
+
+    fn comparison(&self, _: &Self::Channel) -> bool {
+        // The HIL has no way to report errors here; while COMP owns the
+        // peripheral the comparison reads as below.
+        if self.enable().is_err() {
+            return false;
+        }
+        self.registers.tasks_sample.set(1);
+        self.registers
+            .result
+            .matches_all(ComparisonResult::RESULT::Above)
+    }
+
+    fn start_comparing(&self, _: &Self::Channel) -> Result<(), ErrorCode> {
+        self.enable()?;
+        self.registers.events_up.set(0);
+        self.registers.intenset.write(InterruptEnable::UP::SET);
+        Ok(())
+    }
+
+    fn stop_comparing(&self, _: &Self::Channel) -> Result<(), ErrorCode> {
+        if self.comp_is_enabled() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.registers.intenclr.set(0xffff_ffff);
+        self.disable();
+        Ok(())
+    }
+
+    fn set_client(&self, client: &'a dyn analog_comparator::Client) {
+        self.client.set(client);
+    }
+}
+
+/// Shares its base address with COMP.
+const LPCOMP_BASE: StaticRef<LpcompRegisters> =
+    unsafe { StaticRef::new(0x40013000 as *const LpcompRegisters) };
@@ -235,6 +235,21 @@ impl Nvmc {
         while !self.is_ready() {}
     }
 
+    /// Enable or disable the instruction cache. It caches code fetched from
+    /// internal flash and, on the nRF52840, from external flash mapped by
+    /// the QSPI peripheral.
+    pub fn set_instruction_cache(&self, enabled: bool) {
+        if enabled {
+            self.registers
+                .icachecnf
+                .write(CacheConfiguration::CACHEEN::ENABLED);
+        } else {
+            self.registers
+                .icachecnf
+                .write(CacheConfiguration::CACHEEN::DISABLED);
+        }
+    }
+
     /// Check if there is an ongoing operation with the NVMC peripheral.
     pub fn is_ready(&self) -> bool {
         self.registers.ready.is_set(Ready::READY)
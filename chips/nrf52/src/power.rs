@@ -348,6 +348,39 @@ impl<'a> Power<'a> {
         self.registers.usbregstatus.is_set(UsbRegStatus::OUTPUTRDY)
     }
 
+    /// Whether the last reset was a wake-up from System OFF triggered by the
+    /// LPCOMP ANADETECT signal.
+    pub fn reset_reason_lpcomp(&self) -> bool {
+        self.registers.resetreas.is_set(ResetReason::LPCOMP)
+    }
+
+    /// Clear the LPCOMP bit of the RESETREAS register.
+    ///
+    /// RESETREAS is cumulative, so this should be called after the reset
+    /// reason has been handled.
+    pub fn clear_reset_reason_lpcomp(&self) {
+        self.registers
+            .resetreas
+            .write(ResetReason::LPCOMP::Detected);
+    }
+
+    /// Enter System OFF, the deepest power saving mode.
+    ///
+    /// The chip can only leave System OFF through a reset, for example caused
+    /// by a DETECT signal from GPIO, the ANADETECT signal from LPCOMP (see
+    /// `nrf52::lpcomp::LowPowerComparator::enable_wake_from_system_off()`) or
+    /// the reset pin. RAM is not retained unless configured otherwise.
+    pub fn system_off(&self) -> ! {
+        self.registers.systemoff.write(Task::ENABLE::SET);
+        // Entering System OFF is not instantaneous, and in debug interface
+        // mode the chip only emulates it. Never return to the caller.
+        loop {
+            unsafe {
+                cortexm4::support::wfi();
+            }
+        }
+    }
+
     /// Return the contents of the GPREGRET (general purpose retention register)
     /// register.
     ///
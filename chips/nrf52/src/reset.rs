@@ -0,0 +1,55 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Reset, optionally into a DFU bootloader.
+//!
+//! The bootloaders used with the nRF52 (e.g. the Adafruit and Arduino
+//! bootloaders) stay in bootloader mode if they find a magic value in the
+//! GPREGRET retention register after a reset. The value depends on the
+//! bootloader, so it is passed in by the board.
+
+use cortexm4::scb;
+use kernel::hil::reset::{Reset, ResetMode};
+use kernel::ErrorCode;
+
+use crate::power::Power;
+
+/// GPREGRET value of the Adafruit nRF52 bootloader to enter UF2 mode.
+pub const ADAFRUIT_UF2_MAGIC: u8 = 0x57;
+
+pub struct Nrf52Reset<'a> {
+    power: &'a Power<'a>,
+    bootloader_magic: Option<u8>,
+}
+
+impl<'a> Nrf52Reset<'a> {
+    /// `bootloader_magic` is the GPREGRET value that keeps the board's
+    /// bootloader in bootloader mode, `None` if it has no bootloader.
+    pub fn new(power: &'a Power<'a>, bootloader_magic: Option<u8>) -> Nrf52Reset<'a> {
+        Nrf52Reset {
+            power,
+            bootloader_magic,
+        }
+    }
+}
+
+impl Reset for Nrf52Reset<'_> {
+    fn supports(&self, mode: ResetMode) -> bool {
+        match mode {
+            ResetMode::Normal => true,
+            ResetMode::Bootloader => self.bootloader_magic.is_some(),
+        }
+    }
+
+    fn reset(&self, mode: ResetMode) -> ErrorCode {
+        match mode {
+            ResetMode::Normal => self.power.set_gpregret(0),
+            ResetMode::Bootloader => match self.bootloader_magic {
+                Some(magic) => self.power.set_gpregret(magic),
+                None => return ErrorCode::NOSUPPORT,
+            },
+        }
+        scb::ScbReset.reset(ResetMode::Normal)
+    }
+}
@@ -0,0 +1,331 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Quad SPI flash interface with execute in place.
+//!
+//! The QSPI peripheral maps external flash to `0x12000000`, so code,
+//! including processes, can run from it. Fetches are cached by the
+//! instruction cache of the NVMC if caching is enabled.
+//!
+//! The driver only supports execute in place. Flashes that need setup before
+//! they can be read as configured, e.g. setting the quad enable bit of their
+//! status register or entering 4-byte address mode for flashes larger than
+//! 16 MB, are set up by the board with `custom_instruction()`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let qspi = static_init!(
+//!     nrf52840::qspi::Qspi,
+//!     nrf52840::qspi::Qspi::new(&base_peripherals.nvmc)
+//! );
+//! qspi.configure(sck, csn, [io0, io1, io2, io3]);
+//! let external_flash = qspi.enable(XipConfig { .. }).unwrap();
+//! ```
+
+use kernel::hil::xip::{ExecuteInPlace, ReadMode, XipConfig};
+use kernel::utilities::cells::VolatileCell;
+use kernel::utilities::registers::interfaces::{Readable, Writeable};
+use kernel::utilities::registers::{register_bitfields, ReadWrite, WriteOnly};
+use kernel::utilities::StaticRef;
+use kernel::ErrorCode;
+use nrf52::nvmc::Nvmc;
+use nrf52::pinmux::Pinmux;
+
+const QSPI_BASE: StaticRef<QspiRegisters> =
+    unsafe { StaticRef::new(0x40029000 as *const QspiRegisters) };
+
+/// Start of the address range external flash is mapped to.
+const XIP_BASE: usize = 0x12000000;
+
+/// Size of the address range external flash is mapped to.
+const XIP_MAX_SIZE: usize = 0x08000000;
+
+/// Frequency SCK is derived from.
+const SCK_BASE_FREQUENCY_HZ: u32 = 32_000_000;
+
+/// How often the READY event is polled before giving up.
+const READY_TIMEOUT: usize = 100_000;
+
+#[repr(C)]
+struct QspiRegisters {
+    /// Activate QSPI interface
+    /// Address: 0x000 - 0x004
+    tasks_activate: WriteOnly<u32, Task::Register>,
+    /// Start transfer from external flash memory to internal RAM
+    /// Address: 0x004 - 0x008
+    _tasks_readstart: WriteOnly<u32, Task::Register>,
+    /// Start transfer from internal RAM to external flash memory
+    /// Address: 0x008 - 0x00C
+    _tasks_writestart: WriteOnly<u32, Task::Register>,
+    /// Start external flash memory erase operation
+    /// Address: 0x00C - 0x010
+    _tasks_erasestart: WriteOnly<u32, Task::Register>,
+    /// Deactivate QSPI interface
+    /// Address: 0x010 - 0x014
+    _tasks_deactivate: WriteOnly<u32, Task::Register>,
+    _reserved0: [u32; 59],
+    /// QSPI peripheral is ready
+    /// Address: 0x100 - 0x104
+    events_ready: ReadWrite<u32, Event::Register>,
+    _reserved1: [u32; 127],
+    /// Enable or disable interrupt
+    /// Address: 0x300 - 0x304
+    _inten: ReadWrite<u32>,
+    /// Enable interrupt
+    /// Address: 0x304 - 0x308
+    _intenset: ReadWrite<u32>,
+    /// Disable interrupt
+    /// Address: 0x308 - 0x30C
+    _intenclr: ReadWrite<u32>,
+    _reserved2: [u32; 125],
+    /// Enable QSPI peripheral
+    /// Address: 0x500 - 0x504
+    enable: ReadWrite<u32, Enable::Register>,
+    /// Read, write and erase transfer configuration
+    /// Address: 0x504 - 0x524
+    _transfer: [ReadWrite<u32>; 8],
+    /// Pin select for serial clock SCK
+    /// Address: 0x524 - 0x528
+    psel_sck: VolatileCell<Pinmux>,
+    /// Pin select for chip select signal CSN
+    /// Address: 0x528 - 0x52C
+    psel_csn: VolatileCell<Pinmux>,
+    _reserved3: u32,
+    /// Pin select for serial data IO0 to IO3
+    /// Address: 0x530 - 0x540
+    psel_io: [VolatileCell<Pinmux>; 4],
+    /// Address offset into the external memory for execute in place
+    /// Address: 0x540 - 0x544
+    xipoffset: ReadWrite<u32>,
+    /// Interface configuration
+    /// Address: 0x544 - 0x548
+    ifconfig0: ReadWrite<u32, IfConfig0::Register>,
+    _reserved4: [u32; 46],
+    /// Interface configuration
+    /// Address: 0x600 - 0x604
+    ifconfig1: ReadWrite<u32, IfConfig1::Register>,
+    /// Status register
+    /// Address: 0x604 - 0x608
+    _status: ReadWrite<u32>,
+    _reserved5: [u32; 3],
+    /// Set the duration required to enter/exit deep power-down mode
+    /// Address: 0x614 - 0x618
+    _dpmdur: ReadWrite<u32>,
+    _reserved6: [u32; 3],
+    /// Extended address configuration
+    /// Address: 0x624 - 0x628
+    _addrconf: ReadWrite<u32>,
+    _reserved7: [u32; 3],
+    /// Custom instruction configuration
+    /// Address: 0x634 - 0x638
+    cinstrconf: ReadWrite<u32, CinstrConf::Register>,
+    /// Custom instruction data
+    /// Address: 0x638 - 0x640
+    cinstrdat: [ReadWrite<u32>; 2],
+    /// SPI interface timing
+    /// Address: 0x640 - 0x644
+    iftiming: ReadWrite<u32, IfTiming::Register>,
+}
+
+register_bitfields![u32,
+    Task [
+        ENABLE OFFSET(0) NUMBITS(1)
+    ],
+    Event [
+        READY OFFSET(0) NUMBITS(1)
+    ],
+    Enable [
+        ENABLE OFFSET(0) NUMBITS(1)
+    ],
+    IfConfig0 [
+        /// Opcode used for reads
+        READOC OFFSET(0) NUMBITS(3) [
+            FASTREAD = 0,
+            READ2O = 1,
+            READ2IO = 2,
+            READ4O = 3,
+            READ4IO = 4
+        ],
+        /// Opcode used for writes
+        WRITEOC OFFSET(3) NUMBITS(3) [
+            PP = 0,
+            PP2O = 1,
+            PP4O = 2,
+            PP4IO = 3
+        ],
+        /// Addressing mode
+        ADDRMODE OFFSET(6) NUMBITS(1) [
+            BIT24 = 0,
+            BIT32 = 1
+        ],
+        /// Enable deep power-down mode feature
+        DPMENABLE OFFSET(7) NUMBITS(1) [],
+        /// Page size for commands PP, PP2O, PP4O and PP4IO
+        PPSIZE OFFSET(12) NUMBITS(1) [
+            BYTES256 = 0,
+            BYTES512 = 1
+        ]
+    ],
+    IfConfig1 [
+        /// Minimum amount of time that the CSN pin must stay high before it
+        /// can go low again, in 16 MHz periods
+        SCKDELAY OFFSET(0) NUMBITS(8) [],
+        /// Enter deep power-down mode
+        DPMEN OFFSET(24) NUMBITS(1) [],
+        /// Select SPI mode
+        SPIMODE OFFSET(25) NUMBITS(1) [
+            MODE0 = 0,
+            MODE3 = 1
+        ],
+        /// SCK frequency is 32 MHz / (SCKFREQ + 1)
+        SCKFREQ OFFSET(28) NUMBITS(4) []
+    ],
+    CinstrConf [
+        /// Opcode of the custom instruction
+        OPCODE OFFSET(0) NUMBITS(8) [],
+        /// Length of the custom instruction in bytes, including the opcode
+        LENGTH OFFSET(8) NUMBITS(4) [],
+        /// Level of the IO2 pin during the transmission
+        LIO2 OFFSET(12) NUMBITS(1) [],
+        /// Level of the IO3 pin during the transmission
+        LIO3 OFFSET(13) NUMBITS(1) [],
+        /// Wait until the flash is no longer busy before sending
+        WIPWAIT OFFSET(14) NUMBITS(1) [],
+        /// Send write enable before the instruction
+        WREN OFFSET(15) NUMBITS(1) []
+    ],
+    IfTiming [
+        /// Timing of the sampling of the input serial data, in 64 MHz cycles
+        RXDELAY OFFSET(8) NUMBITS(3) []
+    ]
+];
+
+pub struct Qspi<'a> {
+    registers: StaticRef<QspiRegisters>,
+    nvmc: &'a Nvmc,
+}
+
+impl<'a> Qspi<'a> {
+    pub fn new(nvmc: &'a Nvmc) -> Self {
+        Self {
+            registers: QSPI_BASE,
+            nvmc,
+        }
+    }
+
+    /// Select the pins of the flash interface.
+    pub fn configure(&self, sck: Pinmux, csn: Pinmux, io: [Pinmux; 4]) {
+        self.registers.psel_sck.set(sck);
+        self.registers.psel_csn.set(csn);
+        for (psel, pin) in self.registers.psel_io.iter().zip(io) {
+            psel.set(pin);
+        }
+    }
+
+    fn wait_ready(&self) -> Result<(), ErrorCode> {
+        for _ in 0..READY_TIMEOUT {
+            if self.registers.events_ready.is_set(Event::READY) {
+                self.registers.events_ready.write(Event::READY::CLEAR);
+                return Ok(());
+            }
+        }
+        Err(ErrorCode::FAIL)
+    }
+
+    /// Send instruction `opcode` followed by `data` to the flash, after a
+    /// write enable if `write_enable` is set, and wait until it is sent. The
+    /// interface must be enabled.
+    ///
+    /// This is meant for setting up the flash, e.g. writing its status
+    /// register. IO2 and IO3 are held high, as they are the write protect
+    /// and hold pins of most flashes in single mode.
+    ///
+    /// The possible ErrorCodes are:
+    ///    - SIZE: `data` is longer than 8 bytes
+    ///    - FAIL: the instruction was not sent
+    pub fn custom_instruction(
+        &self,
+        opcode: u8,
+        data: &[u8],
+        write_enable: bool,
+    ) -> Result<(), ErrorCode> {
+        if data.len() > 8 {
+            return Err(ErrorCode::SIZE);
+        }
+        let mut words = [0u32; 2];
+        for (i, byte) in data.iter().enumerate() {
+            words[i / 4] |= (*byte as u32) << (8 * (i % 4));
+        }
+        self.registers.cinstrdat[0].set(words[0]);
+        self.registers.cinstrdat[1].set(words[1]);
+        self.registers.events_ready.write(Event::READY::CLEAR);
+        self.registers.cinstrconf.write(
+            CinstrConf::OPCODE.val(opcode as u32)
+                + CinstrConf::LENGTH.val(data.len() as u32 + 1)
+                + CinstrConf::LIO2::SET
+                + CinstrConf::LIO3::SET
+                + CinstrConf::WIPWAIT::SET
+                + CinstrConf::WREN.val(write_enable as u32),
+        );
+        self.wait_ready()
+    }
+}
+
+impl ExecuteInPlace for Qspi<'_> {
+    fn enable(&self, config: XipConfig) -> Result<&'static [u8], ErrorCode> {
+        if config.size > XIP_MAX_SIZE {
+            return Err(ErrorCode::SIZE);
+        }
+        if config.clock_hz == 0 || config.latency_cycles > 7 {
+            return Err(ErrorCode::INVAL);
+        }
+        let read_opcode = match config.read_mode {
+            ReadMode::Single => IfConfig0::READOC::FASTREAD,
+            ReadMode::Dual => IfConfig0::READOC::READ2IO,
+            ReadMode::Quad => IfConfig0::READOC::READ4IO,
+        };
+        let address_mode = if config.size > 1 << 24 {
+            IfConfig0::ADDRMODE::BIT32
+        } else {
+            IfConfig0::ADDRMODE::BIT24
+        };
+        // Round the divider up, so SCK is not faster than requested.
+        let divider = (SCK_BASE_FREQUENCY_HZ + config.clock_hz - 1) / config.clock_hz;
+        let sck_frequency = divider.clamp(1, 16) - 1;
+
+        self.registers.enable.write(Enable::ENABLE::SET);
+        self.registers.xipoffset.set(0);
+        self.registers
+            .ifconfig0
+            .write(read_opcode + IfConfig0::WRITEOC::PP + address_mode);
+        self.registers.ifconfig1.write(
+            IfConfig1::SCKDELAY.val(1)
+                + IfConfig1::SPIMODE::MODE0
+                + IfConfig1::SCKFREQ.val(sck_frequency),
+        );
+        self.registers
+            .iftiming
+            .write(IfTiming::RXDELAY.val(config.latency_cycles as u32));
+
+        self.registers.events_ready.write(Event::READY::CLEAR);
+        self.registers.tasks_activate.write(Task::ENABLE::SET);
+        if let Err(error) = self.wait_ready() {
+            self.registers.enable.write(Enable::ENABLE::CLEAR);
+            return Err(error);
+        }
+        self.set_cache(config.cache)?;
+
+        // SAFETY: the flash is mapped to this address range from now on, and
+        // it is only read through this slice.
+        Ok(unsafe { core::slice::from_raw_parts(XIP_BASE as *const u8, config.size) })
+    }
+
+    fn set_cache(&self, enabled: bool) -> Result<(), ErrorCode> {
+        // The instruction cache also caches internal flash.
+        self.nvmc.set_instruction_cache(enabled);
+        Ok(())
+    }
+}
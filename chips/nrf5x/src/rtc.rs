@@ -14,6 +14,8 @@ use kernel::ErrorCode;
 
 const RTC1_BASE: StaticRef<RtcRegisters> =
     unsafe { StaticRef::new(0x40011000 as *const RtcRegisters) };
+const RTC2_BASE: StaticRef<RtcRegisters> =
+    unsafe { StaticRef::new(0x40024000 as *const RtcRegisters) };
 
 #[repr(C)]
 struct RtcRegisters {
@@ -105,6 +107,18 @@ impl<'a> Rtc<'a> {
         }
     }
 
+    /// RTC2, which is not used by the kernel by default. It can be used as
+    /// a second 32 kHz alarm, e.g. as the low-power backend of an alarm
+    /// that migrates to it while the chip sleeps.
+    pub const fn new_rtc2() -> Self {
+        Self {
+            registers: RTC2_BASE,
+            overflow_client: OptionalCell::empty(),
+            alarm_client: OptionalCell::empty(),
+            enabled: Cell::new(false),
+        }
+    }
+
     pub fn handle_interrupt(&self) {
         if self.registers.events_ovrflw.is_set(Event::READY) {
             self.registers.events_ovrflw.write(Event::READY::CLEAR);
@@ -26,6 +26,7 @@
 //! * Philip Levis <pal@cs.stanford.edu>
 //! * Date: August 18, 2016
 
+use core::cell::Cell;
 use kernel::hil;
 use kernel::hil::time::{Alarm, Ticks, Time};
 use kernel::utilities::cells::OptionalCell;
@@ -264,6 +265,9 @@ impl Timer {
 pub struct TimerAlarm<'a> {
     registers: StaticRef<TimerRegisters>,
     client: OptionalCell<&'a dyn hil::time::AlarmClient>,
+    /// Set while the timer is started through the `Counter` interface. A
+    /// free-running timer is not stopped and cleared when the alarm fires.
+    free_running: Cell<bool>,
 }
 
 // CC0 is used for capture
@@ -276,13 +280,16 @@ impl<'a> TimerAlarm<'a> {
         TimerAlarm {
             registers: INSTANCES[instance],
             client: OptionalCell::empty(),
+            free_running: Cell::new(false),
         }
     }
 
     fn clear_alarm(&self) {
         self.registers.events_compare[CC_COMPARE].write(Event::READY::CLEAR);
-        self.registers.tasks_stop.write(Task::ENABLE::SET);
-        self.registers.tasks_clear.write(Task::ENABLE::SET);
+        if !self.free_running.get() {
+            self.registers.tasks_stop.write(Task::ENABLE::SET);
+            self.registers.tasks_clear.write(Task::ENABLE::SET);
+        }
         self.disable_interrupts();
     }
 
@@ -321,6 +328,36 @@ impl Time for TimerAlarm<'_> {
     }
 }
 
+/// The timer can be used as a free-running counter, e.g. as the
+/// high-frequency side of an alarm that migrates to the RTC while the chip
+/// sleeps. Overflow callbacks are not supported: the timer has no spare
+/// compare channel to detect them.
+impl<'a> hil::time::Counter<'a> for TimerAlarm<'a> {
+    fn set_overflow_client(&self, _client: &'a dyn hil::time::OverflowClient) {}
+
+    fn start(&self) -> Result<(), ErrorCode> {
+        self.free_running.set(true);
+        self.registers.bitmode.write(Bitmode::BITMODE::Bit32);
+        self.registers.tasks_start.write(Task::ENABLE::SET);
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), ErrorCode> {
+        self.registers.tasks_stop.write(Task::ENABLE::SET);
+        self.free_running.set(false);
+        Ok(())
+    }
+
+    fn reset(&self) -> Result<(), ErrorCode> {
+        self.registers.tasks_clear.write(Task::ENABLE::SET);
+        Ok(())
+    }
+
+    fn is_running(&self) -> bool {
+        self.free_running.get()
+    }
+}
+
 impl<'a> Alarm<'a> for TimerAlarm<'a> {
     fn set_alarm_client(&self, client: &'a dyn hil::time::AlarmClient) {
         self.client.set(client);
@@ -4,9 +4,12 @@
 
 //! Chip trait setup.
 
+use core::cell::Cell;
 use core::fmt::Write;
+use core::sync::atomic::{fence, AtomicBool, Ordering};
 use kernel::platform::chip::Chip;
 use kernel::platform::chip::InterruptService;
+use kernel::platform::chip::MultiCore;
 
 use crate::adc;
 use crate::clocks::Clocks;
@@ -24,6 +27,10 @@ use crate::watchdog::Watchdog;
 use crate::xosc::Xosc;
 use cortexm0p::{interrupt_mask, CortexM0P, CortexMVariant};
 
+/// Hardware spinlock that is the kernel lock when both processors run
+/// processes.
+const KERNEL_SPINLOCK: usize = 31;
+
 #[repr(u8)]
 pub enum Processor {
     Processor0 = 0,
@@ -37,6 +44,9 @@ pub struct Rp2040<'a, I: InterruptService + 'a> {
     sio: &'a SIO,
     processor0_interrupt_mask: (u128, u128),
     processor1_interrupt_mask: (u128, u128),
+    processor1_launched: Cell<bool>,
+    /// Whether each processor waits for the kernel lock.
+    kernel_lock_waiting: [AtomicBool; 2],
 }
 
 impl<'a, I: InterruptService> Rp2040<'a, I> {
@@ -47,7 +57,61 @@ impl<'a, I: InterruptService> Rp2040<'a, I> {
             interrupt_service,
             sio: sio,
             processor0_interrupt_mask: interrupt_mask!(interrupts::SIO_IRQ_PROC1),
-            processor1_interrupt_mask: interrupt_mask!(interrupts::SIO_IRQ_PROC0),
+            // Peripheral interrupts are only handled by processor 0, processor
+            // 1 only wakes up on interrupts from processor 0.
+            processor1_interrupt_mask: (u128::MAX, !(1 << interrupts::SIO_IRQ_PROC1)),
+            processor1_launched: Cell::new(false),
+            kernel_lock_waiting: [AtomicBool::new(false), AtomicBool::new(false)],
+        }
+    }
+
+    /// Run processes on processor 1 as well. Processor 1 starts at `entry`
+    /// with the stack pointer `stack_pointer` and the vector table at
+    /// `vector_table`. `entry` has to enable the `SIO_IRQ_PROC1` interrupt,
+    /// set up the kernel resources for processor 1, and call
+    /// `Kernel::kernel_loop()` with them.
+    ///
+    /// Call this right before processor 0 calls `Kernel::kernel_loop()`, as
+    /// from then on the kernel is guarded by the kernel lock, see
+    /// `MultiCore`.
+    pub unsafe fn launch_processor1(&self, vector_table: u32, stack_pointer: u32, entry: u32) {
+        self.processor1_launched.set(true);
+        // Processor 1 has to see the kernel set up by processor 0.
+        fence(Ordering::SeqCst);
+        self.sio
+            .launch_processor1(vector_table, stack_pointer, entry);
+    }
+}
+
+impl<'a, I: InterruptService> MultiCore for Rp2040<'a, I> {
+    fn num_cores(&self) -> usize {
+        2
+    }
+
+    fn current_core(&self) -> usize {
+        self.sio.get_processor() as usize
+    }
+
+    fn acquire_kernel_lock(&self) {
+        let waiting = &self.kernel_lock_waiting[self.current_core()];
+        waiting.store(true, Ordering::Relaxed);
+        while !self.sio.claim_spinlock(KERNEL_SPINLOCK) {}
+        waiting.store(false, Ordering::Relaxed);
+        fence(Ordering::Acquire);
+    }
+
+    fn release_kernel_lock(&self) {
+        fence(Ordering::Release);
+        self.sio.release_spinlock(KERNEL_SPINLOCK);
+        // Hand the lock over to the other processor if it waits for it.
+        let other = &self.kernel_lock_waiting[1 - self.current_core()];
+        while other.load(Ordering::Relaxed) {}
+    }
+
+    fn send_ipi(&self, core: usize) {
+        // The FIFO of each processor can only interrupt the other one.
+        if core != self.current_core() {
+            self.sio.send_ipi();
         }
     }
 }
@@ -115,6 +179,14 @@ impl<'a, I: InterruptService> Chip for Rp2040<'a, I> {
     unsafe fn print_state(&self, writer: &mut dyn Write) {
         CortexM0P::print_cortexm_state(writer);
     }
+
+    fn multi_core(&self) -> Option<&dyn MultiCore> {
+        if self.processor1_launched.get() {
+            Some(self)
+        } else {
+            None
+        }
+    }
 }
 
 pub struct Rp2040DefaultPeripherals<'a> {
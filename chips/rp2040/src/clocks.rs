@@ -3,6 +3,7 @@
 // Copyright Tock Contributors 2022.
 
 use core::cell::Cell;
+use kernel::platform::chip::{ClockIntrospection, PeripheralClockState};
 use kernel::utilities::registers::interfaces::{ReadWriteable, Readable, Writeable};
 use kernel::utilities::registers::{register_bitfields, register_structs, ReadOnly, ReadWrite};
 use kernel::utilities::StaticRef;
@@ -1407,3 +1408,46 @@ impl Clocks {
         self.set_frequency(Clock::Rtc, freq);
     }
 }
+
+/// Peripheral clocks reported by `ClockIntrospection`: name, clock, bit in
+/// the ENABLED0 (`0`) or ENABLED1 (`1`) register and the driver in this
+/// crate that uses the peripheral.
+const INTROSPECTED_CLOCKS: [(&str, Clock, usize, u32, &str); 13] = [
+    ("UART0", Clock::Peripheral, 1, 6, "rp2040::uart"),
+    ("UART1", Clock::Peripheral, 1, 8, "rp2040::uart"),
+    ("SPI0", Clock::Peripheral, 0, 24, "rp2040::spi"),
+    ("SPI1", Clock::Peripheral, 0, 26, "rp2040::spi"),
+    ("I2C0", Clock::System, 0, 6, "rp2040::i2c"),
+    ("I2C1", Clock::System, 0, 7, "rp2040::i2c"),
+    ("PWM", Clock::System, 0, 17, "rp2040::pwm"),
+    ("ADC", Clock::Adc, 0, 1, "rp2040::adc"),
+    ("USBCTRL", Clock::Usb, 1, 11, "rp2040::usb"),
+    ("TIMER", Clock::System, 1, 5, "rp2040::timer"),
+    ("WATCHDOG", Clock::Reference, 1, 12, "rp2040::watchdog"),
+    ("IO_BANK0", Clock::System, 0, 8, "rp2040::gpio"),
+    ("XOSC", Clock::System, 1, 14, "rp2040::xosc"),
+];
+
+impl ClockIntrospection for Clocks {
+    fn peripheral_count(&self) -> usize {
+        INTROSPECTED_CLOCKS.len()
+    }
+
+    fn peripheral_clock(&self, index: usize) -> Option<PeripheralClockState> {
+        INTROSPECTED_CLOCKS
+            .get(index)
+            .map(|&(name, clock, register, bit, owner)| {
+                let enabled = if register == 0 {
+                    self.registers.enabled0.get()
+                } else {
+                    self.registers.enabled1.get()
+                };
+                PeripheralClockState {
+                    name,
+                    enabled: enabled & (1 << bit) != 0,
+                    frequency: Some(self.get_frequency(clock)),
+                    owner,
+                }
+            })
+    }
+}
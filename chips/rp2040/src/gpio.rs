@@ -100,8 +100,17 @@ register_structs! {
         /// FIFO read
         (0x058 => fifo_rd: ReadOnly<u32, FIFO_RD::Register>),
 
+        /// Spinlock state
+        (0x05c => spinlock_st: ReadOnly<u32>),
+
+        /// Not used
+        (0x060 => _reserved3),
+
+        /// Hardware spinlocks, reading claims and writing releases
+        (0x100 => spinlock: [ReadWrite<u32>; 32]),
+
         /// End
-        (0x05c => @END),
+        (0x180 => @END),
     }
 }
 
@@ -294,6 +303,7 @@ const SIO_BASE: StaticRef<SIORegisters> =
 pub struct RPPins<'a> {
     pub pins: [RPGpioPin<'a>; 30],
     gpio_registers: StaticRef<GpioRegisters>,
+    sio_registers: StaticRef<SIORegisters>,
 }
 
 impl<'a> RPPins<'a> {
@@ -332,6 +342,7 @@ impl<'a> RPPins<'a> {
                 RPGpioPin::new(RPGpio::GPIO29),
             ],
             gpio_registers: GPIO_BASE,
+            sio_registers: SIO_BASE,
         }
     }
 
@@ -661,6 +672,33 @@ impl hil::gpio::Input for RPGpioPin<'_> {
     }
 }
 
+/// The SIO drives all 30 user pins of bank 0 as one port.
+impl hil::gpio::Port for RPPins<'_> {
+    fn set_pins(&self, mask: u32) {
+        self.sio_registers.gpio_out_set.set(mask);
+    }
+
+    fn clear_pins(&self, mask: u32) {
+        self.sio_registers.gpio_out_clr.set(mask);
+    }
+
+    fn toggle_pins(&self, mask: u32) {
+        self.sio_registers.gpio_out_xor.set(mask);
+    }
+
+    fn write_pins(&self, mask: u32, value: u32) {
+        // Flip exactly the pins that differ, in one write.
+        let current = self.sio_registers.gpio_out.get();
+        self.sio_registers
+            .gpio_out_xor
+            .set((current ^ value) & mask);
+    }
+
+    fn read_pins(&self) -> u32 {
+        self.sio_registers.gpio_in.read(GPIO_IN::IN)
+    }
+}
+
 pub struct SIO {
     registers: StaticRef<SIORegisters>,
 }
@@ -673,19 +711,66 @@ impl SIO {
     }
 
     pub fn handle_proc_interrupt(&self, for_processor: Processor) {
-        match for_processor {
-            Processor::Processor0 => {
-                // read data from the fifo
-                self.registers.fifo_rd.get();
+        // Each processor can only read its own FIFO.
+        match (for_processor, self.get_processor()) {
+            (Processor::Processor0, Processor::Processor0)
+            | (Processor::Processor1, Processor::Processor1) => {
+                // The other processor only writes to the FIFO to wake this
+                // one up, so drain it to clear the interrupt.
+                while self.registers.fifo_st.is_set(FIFO_ST::VLD) {
+                    self.registers.fifo_rd.get();
+                }
                 self.registers.fifo_st.set(0xff);
             }
-            Processor::Processor1 => {
-                if self.registers.cpuid.get() == 1 {
-                    panic!("Kernel should not run on processor 1");
-                } else {
-                    panic!("SIO_PROC1_IRQ should be ignored for processor 1");
+            (for_processor, _) => panic!(
+                "SIO_IRQ_PROC{} should be ignored for the other processor",
+                for_processor as u8
+            ),
+        }
+    }
+
+    /// Interrupt the other processor with `SIO_IRQ_PROCx`. Does nothing if
+    /// its FIFO is full, it has a pending interrupt then anyway.
+    pub fn send_ipi(&self) {
+        if self.registers.fifo_st.is_set(FIFO_ST::RDY) {
+            self.registers.fifo_wr.set(0);
+        }
+    }
+
+    /// Try to claim hardware spinlock `index`. Returns whether it was free.
+    pub fn claim_spinlock(&self, index: usize) -> bool {
+        self.registers.spinlock[index].get() != 0
+    }
+
+    pub fn release_spinlock(&self, index: usize) {
+        self.registers.spinlock[index].set(1);
+    }
+
+    /// Start processor 1 at `entry` with the stack pointer `stack_pointer`
+    /// and the vector table at `vector_table`, using the protocol of the
+    /// boot ROM described in section 2.8.2 of the RP2040 datasheet.
+    pub unsafe fn launch_processor1(&self, vector_table: u32, stack_pointer: u32, entry: u32) {
+        let commands = [0, 0, 1, vector_table, stack_pointer, entry];
+        let mut sequence = 0;
+        while sequence < commands.len() {
+            let command = commands[sequence];
+            if command == 0 {
+                // Processor 1 may have written stale data to our FIFO.
+                while self.registers.fifo_st.is_set(FIFO_ST::VLD) {
+                    self.registers.fifo_rd.get();
                 }
+                cortexm0p::support::sev();
             }
+            while !self.registers.fifo_st.is_set(FIFO_ST::RDY) {}
+            self.registers.fifo_wr.set(command);
+            cortexm0p::support::sev();
+            while !self.registers.fifo_st.is_set(FIFO_ST::VLD) {}
+            // Processor 1 echoes every command, start over if it does not.
+            sequence = if self.registers.fifo_rd.get() == command {
+                sequence + 1
+            } else {
+                0
+            };
         }
     }
 
@@ -678,7 +678,17 @@ impl<'a> Pwm<'a> {
         frequency_hz: usize,
         duty_cycle: usize,
     ) -> Result<(), ErrorCode> {
-        let (top, int, frac) = match self.compute_top_int_frac(frequency_hz) {
+        // In phase-correct mode the counter counts up and then down, so a
+        // period lasts twice as many counter cycles.
+        let counter_freq_hz = if self.registers.ch[channel_number as usize]
+            .csr
+            .is_set(CSR::PH_CORRECT)
+        {
+            frequency_hz.saturating_mul(2)
+        } else {
+            frequency_hz
+        };
+        let (top, int, frac) = match self.compute_top_int_frac(counter_freq_hz) {
             Ok(result) => result,
             Err(_) => return Result::from(ErrorCode::INVAL),
         };
@@ -727,6 +737,14 @@ impl<'a> Pwm<'a> {
         self.set_enabled(channel_number, false);
         Ok(())
     }
+
+    // Select the modulation mode of a PWM channel.
+    //
+    // The mode applies to both pins of the channel and takes effect on the
+    // next start.
+    fn set_channel_mode(&self, channel_number: ChannelNumber, mode: hil::pwm::Mode) {
+        self.set_ph_correct(channel_number, mode == hil::pwm::Mode::PhaseCorrect);
+    }
 }
 
 /// Implementation of the Hardware Interface Layer (HIL)
@@ -797,6 +815,23 @@ impl hil::pwm::Pwm for Pwm<'_> {
     fn get_maximum_duty_cycle(&self) -> usize {
         u16::MAX as usize + 1
     }
+
+    /// Select trailing-edge or phase-correct modulation
+    ///
+    /// Both pins of a PWM channel share the mode. The new mode takes effect on
+    /// the next call to `start()`.
+    fn set_mode(&self, pin: &Self::Pin, mode: hil::pwm::Mode) -> Result<(), ErrorCode> {
+        let (channel_number, _) = self.gpio_to_pwm(*pin);
+        self.set_channel_mode(channel_number, mode);
+        Ok(())
+    }
+
+    /// Pins on different PWM channels have their own counter
+    fn is_independent(&self, pin: &Self::Pin, other: &Self::Pin) -> bool {
+        let (channel_number, _) = self.gpio_to_pwm(*pin);
+        let (other_channel_number, _) = self.gpio_to_pwm(*other);
+        channel_number != other_channel_number
+    }
 }
 
 /// Helper structure to control a PWM pin
@@ -865,6 +900,12 @@ impl hil::pwm::PwmPin for PwmPin<'_> {
     fn get_maximum_duty_cycle(&self) -> usize {
         hil::pwm::Pwm::get_maximum_duty_cycle(self.pwm_struct)
     }
+
+    /// Same as Pwm::set_mode
+    fn set_mode(&self, mode: hil::pwm::Mode) -> Result<(), ErrorCode> {
+        self.pwm_struct.set_channel_mode(self.channel_number, mode);
+        Ok(())
+    }
 }
 
 /// Unit tests
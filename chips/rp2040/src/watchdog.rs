@@ -2,10 +2,12 @@
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 // Copyright Tock Contributors 2022.
 
+use kernel::hil::reset::{Reset, ResetCause, ResetCauses, ResetMode};
 use kernel::utilities::cells::OptionalCell;
-use kernel::utilities::registers::interfaces::{ReadWriteable, Writeable};
+use kernel::utilities::registers::interfaces::{ReadWriteable, Readable, Writeable};
 use kernel::utilities::registers::{register_bitfields, register_structs, ReadWrite};
 use kernel::utilities::StaticRef;
+use kernel::ErrorCode;
 
 use crate::resets;
 
@@ -133,3 +135,62 @@ impl<'a> Watchdog<'a> {
         self.registers.ctrl.write(CTRL::TRIGGER::SET);
     }
 }
+
+/// Address of the pointer to the table of ROM functions.
+const ROM_FUNC_TABLE: *const u16 = 0x0000_0014 as *const u16;
+/// Address of the pointer to the ROM function that looks up a function by
+/// its two-letter code in the table.
+const ROM_TABLE_LOOKUP: *const u16 = 0x0000_0018 as *const u16;
+
+/// Look up the ROM function with the two-letter `code`.
+unsafe fn rom_func_lookup(code: &[u8; 2]) -> usize {
+    let lookup: extern "C" fn(*const u16, u32) -> usize =
+        core::mem::transmute(core::ptr::read_volatile(ROM_TABLE_LOOKUP) as usize);
+    let table = core::ptr::read_volatile(ROM_FUNC_TABLE) as *const u16;
+    lookup(table, u16::from_le_bytes(*code) as u32)
+}
+
+/// The watchdog only records watchdog resets, other resets are reported as
+/// `Other`.
+impl ResetCauses for Watchdog<'_> {
+    fn last_reset_cause(&self) -> ResetCause {
+        let reason = self.registers.reason.extract();
+        if reason.is_set(REASON::TIMER) {
+            ResetCause::Watchdog
+        } else if reason.is_set(REASON::FORCE) {
+            // `reset()` forces a watchdog reset.
+            ResetCause::Software
+        } else {
+            ResetCause::Other
+        }
+    }
+}
+
+/// Resets through the watchdog, and enters the USB mass storage and
+/// PICOBOOT bootloader in ROM (the one entered by holding BOOTSEL).
+impl Reset for Watchdog<'_> {
+    fn supports(&self, _mode: ResetMode) -> bool {
+        true
+    }
+
+    fn reset(&self, mode: ResetMode) -> ErrorCode {
+        match mode {
+            ResetMode::Normal => self.reboot(),
+            ResetMode::Bootloader => unsafe {
+                let reset_to_usb_boot = rom_func_lookup(b"UB");
+                if reset_to_usb_boot == 0 {
+                    return ErrorCode::FAIL;
+                }
+                let reset_to_usb_boot: extern "C" fn(u32, u32) -> ! =
+                    core::mem::transmute(reset_to_usb_boot);
+                // No activity LED, enable both the mass storage and the
+                // PICOBOOT interfaces.
+                reset_to_usb_boot(0, 0)
+            },
+        }
+        // The watchdog resets the chip a few cycles after the trigger.
+        loop {
+            cortexm0p::support::nop();
+        }
+    }
+}
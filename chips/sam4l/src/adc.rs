@@ -365,6 +365,30 @@ impl<'a> Adc<'a> {
         self.rx_dma.set(rx_dma);
     }
 
+    /// Starts a DMA transfer of up to `length` samples into `buffer`.
+    fn start_dma(&self, buffer: &'static mut [u16], length: usize) {
+        // receive up to the buffer's length samples
+        let dma_len = cmp::min(buffer.len(), length);
+
+        // change buffer into a [u8]
+        // this is unsafe but acceptable for the following reasons
+        //  * the buffer is aligned based on 16-bit boundary, so the 8-bit
+        //    alignment is fine
+        //  * the DMA is doing checking based on our expected data width to
+        //    make sure we don't go past dma_buf.len()/width
+        //  * we will transmute the array back to a [u16] after the DMA
+        //    transfer is complete
+        let dma_buf_ptr = unsafe { mem::transmute::<*mut u16, *mut u8>(buffer.as_mut_ptr()) };
+        let dma_buf = unsafe { slice::from_raw_parts_mut(dma_buf_ptr, buffer.len() * 2) };
+
+        self.rx_dma.map(move |dma| {
+            self.dma_running.set(true);
+            dma.enable();
+            self.rx_length.set(dma_len);
+            dma.do_transfer(self.rx_dma_peripheral, dma_buf, dma_len);
+        });
+    }
+
     /// Interrupt handler for the ADC.
     pub fn handle_interrupt(&self) {
         let status = self.registers.sr.is_set(Status::SEOC);
@@ -896,27 +920,8 @@ impl<'a> hil::adc::AdcHighSpeed<'a> for Adc<'a> {
             // clear any current status
             self.clear_status();
 
-            // receive up to the buffer's length samples
-            let dma_len = cmp::min(buffer1.len(), length1);
-
-            // change buffer into a [u8]
-            // this is unsafe but acceptable for the following reasons
-            //  * the buffer is aligned based on 16-bit boundary, so the 8-bit
-            //    alignment is fine
-            //  * the DMA is doing checking based on our expected data width to
-            //    make sure we don't go past dma_buf.len()/width
-            //  * we will transmute the array back to a [u16] after the DMA
-            //    transfer is complete
-            let dma_buf_ptr = unsafe { mem::transmute::<*mut u16, *mut u8>(buffer1.as_mut_ptr()) };
-            let dma_buf = unsafe { slice::from_raw_parts_mut(dma_buf_ptr, buffer1.len() * 2) };
-
             // set up the DMA
-            self.rx_dma.map(move |dma| {
-                self.dma_running.set(true);
-                dma.enable();
-                self.rx_length.set(dma_len);
-                dma.do_transfer(self.rx_dma_peripheral, dma_buf, dma_len);
-            });
+            self.start_dma(buffer1, length1);
 
             // start timer
             self.registers.cr.write(Control::TSTART::SET);
@@ -946,6 +951,12 @@ impl<'a> hil::adc::AdcHighSpeed<'a> for Adc<'a> {
         } else if self.next_dma_buffer.is_some() {
             // we've already got a second buffer, we don't need a third yet
             Err((ErrorCode::BUSY, buf))
+        } else if !self.dma_running.get() && length > 0 {
+            // the previous buffer filled before this one was provided, so
+            // resume sampling into it right away
+            self.start_dma(buf, length);
+
+            Ok(())
         } else {
             // store the buffer for later use
             self.next_dma_buffer.replace(buf);
@@ -999,33 +1010,12 @@ impl<'a> dma::DMAClient for Adc<'a> {
             // we need to do this quickly in order to keep from missing samples.
             // At 175000 Hz, we only have 5.8 us (~274 cycles) to do so
             self.next_dma_buffer.take().map(|buf| {
-                // first determine the buffer's length in samples
-                let dma_len = cmp::min(buf.len(), self.next_dma_length.get());
-
                 // only continue with a nonzero length. If we were given a
                 // zero-length buffer or length field, assume that the user knew
                 // what was going on, and just don't use the buffer
-                if dma_len > 0 {
-                    // change buffer into a [u8]
-                    // this is unsafe but acceptable for the following reasons
-                    //  * the buffer is aligned based on 16-bit boundary, so the
-                    //    8-bit alignment is fine
-                    //  * the DMA is doing checking based on our expected data
-                    //    width to make sure we don't go past
-                    //    dma_buf.len()/width
-                    //  * we will transmute the array back to a [u16] after the
-                    //    DMA transfer is complete
-                    let dma_buf_ptr =
-                        unsafe { mem::transmute::<*mut u16, *mut u8>(buf.as_mut_ptr()) };
-                    let dma_buf = unsafe { slice::from_raw_parts_mut(dma_buf_ptr, buf.len() * 2) };
-
-                    // set up the DMA
-                    self.rx_dma.map(move |dma| {
-                        self.dma_running.set(true);
-                        dma.enable();
-                        self.rx_length.set(dma_len);
-                        dma.do_transfer(self.rx_dma_peripheral, dma_buf, dma_len);
-                    });
+                let length = self.next_dma_length.get();
+                if cmp::min(buf.len(), length) > 0 {
+                    self.start_dma(buf, length);
                 } else {
                     // if length was zero, just keep the buffer in the takecell
                     // so we can return it when `stop_sampling` is called
@@ -1035,6 +1025,10 @@ impl<'a> dma::DMAClient for Adc<'a> {
 
             // alert client
             self.highspeed_client.map(|client| {
+                if !self.dma_running.get() && self.next_dma_length.get() > 0 {
+                    // there was no buffer to continue sampling into
+                    client.overrun();
+                }
                 dma_buffer.map(|dma_buf| {
                     // change buffer back into a [u16]
                     // the buffer was originally a [u16] so this should be okay
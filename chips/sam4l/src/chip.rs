@@ -9,12 +9,19 @@ use crate::pm;
 use core::fmt::Write;
 use cortexm4::{self, CortexM4, CortexMVariant};
 use kernel::platform::chip::{Chip, InterruptService};
+use kernel::platform::idle::{IdleSource, SleepBudget, SleepDepth};
+use kernel::utilities::cells::OptionalCell;
+
+/// Conservative bound on the time to wake up from deep sleep, which stops
+/// the main clock sources and has to restart them.
+const DEEP_SLEEP_WAKE_LATENCY_US: u32 = 100;
 
 pub struct Sam4l<I: InterruptService + 'static> {
     mpu: cortexm4::mpu::MPU,
     userspace_kernel_boundary: cortexm4::syscall::SysCall,
     pub pm: &'static crate::pm::PowerManager,
     interrupt_service: &'static I,
+    idle_arbiter: OptionalCell<&'static dyn IdleSource>,
 }
 
 impl<I: InterruptService + 'static> Sam4l<I> {
@@ -24,8 +31,15 @@ impl<I: InterruptService + 'static> Sam4l<I> {
             userspace_kernel_boundary: cortexm4::syscall::SysCall::new(),
             pm,
             interrupt_service,
+            idle_arbiter: OptionalCell::empty(),
         }
     }
+
+    /// Consult `arbiter` before entering deep sleep, in addition to the
+    /// peripheral clocks checked by `pm::deep_sleep_ready()`.
+    pub fn set_idle_arbiter(&self, arbiter: &'static dyn IdleSource) {
+        self.idle_arbiter.set(arbiter);
+    }
 }
 
 /// This struct, when initialized, instantiates all peripheral drivers for the apollo3.
@@ -242,6 +256,7 @@ impl<I: InterruptService + 'static> Chip for Sam4l<I> {
         unsafe {
             loop {
                 if let Some(interrupt) = cortexm4::nvic::next_pending() {
+                    cortexm4::irq_latency::record(interrupt as usize);
                     match self.interrupt_service.service_interrupt(interrupt) {
                         true => {}
                         false => panic!("unhandled interrupt"),
@@ -269,7 +284,10 @@ impl<I: InterruptService + 'static> Chip for Sam4l<I> {
     }
 
     fn sleep(&self) {
-        if pm::deep_sleep_ready() {
+        let budget = self
+            .idle_arbiter
+            .map_or(SleepBudget::UNCONSTRAINED, |arbiter| arbiter.sleep_budget());
+        if budget.allows(SleepDepth::Deep, DEEP_SLEEP_WAKE_LATENCY_US) && pm::deep_sleep_ready() {
             unsafe {
                 cortexm4::scb::set_sleepdeep();
             }
@@ -1161,13 +1161,18 @@ impl I2CHw {
                             twis.registers.rhr.get();
                         }
 
+                        // A repeated start instead of a stop means the
+                        // master is about to read from us as part of the
+                        // same transaction.
+                        let transmission_type = if status.is_set(StatusSlave::REP) {
+                            hil::i2c::SlaveTransmissionType::WriteBeforeRead
+                        } else {
+                            hil::i2c::SlaveTransmissionType::Write
+                        };
+
                         self.slave_client.get().map(|client| {
                             self.slave_write_buffer.take().map(|buffer| {
-                                client.command_complete(
-                                    buffer,
-                                    nbytes as usize,
-                                    hil::i2c::SlaveTransmissionType::Write,
-                                );
+                                client.command_complete(buffer, nbytes as usize, transmission_type);
                             });
                         });
                     }
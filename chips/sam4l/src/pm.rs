@@ -11,7 +11,8 @@ use crate::gpio;
 use crate::scif;
 use core::cell::Cell;
 use core::sync::atomic::Ordering;
-use kernel::platform::chip::ClockInterface;
+use kernel::hil::reset;
+use kernel::platform::chip::{ClockInterface, ClockIntrospection, PeripheralClockState};
 use kernel::utilities::registers::interfaces::{Readable, Writeable};
 use kernel::utilities::registers::{
     register_bitfields, FieldValue, ReadOnly, ReadWrite, WriteOnly,
@@ -586,6 +587,25 @@ impl PowerManager {
     }
 }
 
+impl reset::ResetCauses for PowerManager {
+    fn last_reset_cause(&self) -> reset::ResetCause {
+        let rcause = PM_REGS.rcause.extract();
+        if rcause.is_set(ResetCause::POR) || rcause.is_set(ResetCause::POR33) {
+            reset::ResetCause::PowerOn
+        } else if rcause.is_set(ResetCause::BOD) || rcause.is_set(ResetCause::BOD33) {
+            reset::ResetCause::Brownout
+        } else if rcause.is_set(ResetCause::WDT) {
+            reset::ResetCause::Watchdog
+        } else if rcause.is_set(ResetCause::EXT) {
+            reset::ResetCause::ExternalPin
+        } else if rcause.is_set(ResetCause::OCDRST) {
+            reset::ResetCause::Software
+        } else {
+            reset::ResetCause::Other
+        }
+    }
+}
+
 impl PowerManager {
     /// Sets up the system clock. This should be called as one of the first
     /// lines in the `main()` function within the platform's `main.rs`.
@@ -1160,3 +1180,74 @@ pub fn is_clock_enabled(clock: Clock) -> bool {
         Clock::PBD(v) => get_clock!(PBD_MASK_OFFSET: pbdmask & (1 << (v as u32))),
     }
 }
+
+/// Peripheral clocks reported by `ClockIntrospection`: name, clock and the
+/// driver in this crate that uses the peripheral.
+const INTROSPECTED_CLOCKS: [(&str, Clock, &str); 27] = [
+    ("PDCA", Clock::HSB(HSBClock::PDCA), "sam4l::dma"),
+    (
+        "FLASHCALW",
+        Clock::HSB(HSBClock::FLASHCALW),
+        "sam4l::flashcalw",
+    ),
+    ("USBC", Clock::HSB(HSBClock::USBC), "sam4l::usbc"),
+    ("CRCCU", Clock::HSB(HSBClock::CRCCU), "sam4l::crccu"),
+    ("AESA", Clock::HSB(HSBClock::AESA), "sam4l::aes"),
+    ("SPI", Clock::PBA(PBAClock::SPI), "sam4l::spi"),
+    ("TWIM0", Clock::PBA(PBAClock::TWIM0), "sam4l::i2c"),
+    ("TWIS0", Clock::PBA(PBAClock::TWIS0), "sam4l::i2c"),
+    ("TWIM1", Clock::PBA(PBAClock::TWIM1), "sam4l::i2c"),
+    ("TWIS1", Clock::PBA(PBAClock::TWIS1), "sam4l::i2c"),
+    ("TWIM2", Clock::PBA(PBAClock::TWIM2), "sam4l::i2c"),
+    ("TWIM3", Clock::PBA(PBAClock::TWIM3), "sam4l::i2c"),
+    ("USART0", Clock::PBA(PBAClock::USART0), "sam4l::usart"),
+    ("USART1", Clock::PBA(PBAClock::USART1), "sam4l::usart"),
+    ("USART2", Clock::PBA(PBAClock::USART2), "sam4l::usart"),
+    ("USART3", Clock::PBA(PBAClock::USART3), "sam4l::usart"),
+    ("ADCIFE", Clock::PBA(PBAClock::ADCIFE), "sam4l::adc"),
+    ("DACC", Clock::PBA(PBAClock::DACC), "sam4l::dac"),
+    ("ACIFC", Clock::PBA(PBAClock::ACIFC), "sam4l::acifc"),
+    ("GLOC", Clock::PBA(PBAClock::GLOC), "sam4l::gloc"),
+    ("TRNG", Clock::PBA(PBAClock::TRNG), "sam4l::trng"),
+    ("CRCCU (PBB)", Clock::PBB(PBBClock::CRCCU), "sam4l::crccu"),
+    ("USBC (PBB)", Clock::PBB(PBBClock::USBC), "sam4l::usbc"),
+    ("GPIO", Clock::PBC(PBCClock::GPIO), "sam4l::gpio"),
+    ("AST", Clock::PBD(PBDClock::AST), "sam4l::ast"),
+    ("WDT", Clock::PBD(PBDClock::WDT), "sam4l::wdt"),
+    ("EIC", Clock::PBD(PBDClock::EIC), "sam4l::eic"),
+];
+
+impl PowerManager {
+    /// Frequency of the clock of the bus `clock` is on.
+    fn bus_frequency(&self, clock: Clock) -> u32 {
+        let select = match clock {
+            Clock::HSB(_) => return self.get_system_frequency(),
+            Clock::PBA(_) => PM_REGS.pbasel.extract(),
+            Clock::PBB(_) => PM_REGS.pbbsel.extract(),
+            Clock::PBC(_) => PM_REGS.pbcsel.extract(),
+            Clock::PBD(_) => PM_REGS.pbdsel.extract(),
+        };
+        if select.is_set(PeripheralBusXClockSelect::PBDIV) {
+            self.get_system_frequency() >> (select.read(PeripheralBusXClockSelect::PBSEL) + 1)
+        } else {
+            self.get_system_frequency()
+        }
+    }
+}
+
+impl ClockIntrospection for PowerManager {
+    fn peripheral_count(&self) -> usize {
+        INTROSPECTED_CLOCKS.len()
+    }
+
+    fn peripheral_clock(&self, index: usize) -> Option<PeripheralClockState> {
+        INTROSPECTED_CLOCKS
+            .get(index)
+            .map(|&(name, clock, owner)| PeripheralClockState {
+                name,
+                enabled: is_clock_enabled(clock),
+                frequency: Some(self.bus_frequency(clock)),
+                owner,
+            })
+    }
+}
@@ -433,14 +433,44 @@ impl SpiHw {
 
         self.slave_client.map(|client| {
             if spi.registers.sr.is_set(Status::NSSR) {
-                // NSSR
-                client.chip_selected()
+                // NSSR is set on the rising edge of NSS, when the controller
+                // ends the operation. There is no interrupt for the falling
+                // edge, so `chip_selected` is never called.
+                if self.transfers_in_progress.get() != 0 {
+                    self.end_slave_transfer(*client);
+                }
+                client.chip_deselected()
             }
             // TODO: Do we want to support byte-level interrupts too?
             // They currently conflict with DMA.
         });
     }
 
+    /// Return the buffers of a peripheral mode transfer that the controller
+    /// ended before the buffers were filled.
+    fn end_slave_transfer(&self, client: &dyn SpiSlaveClient) {
+        self.transfers_in_progress.set(0);
+
+        // The DMA channels count down the bytes they have yet to transfer.
+        let mut remaining = 0;
+        let mut abort = |dma: &DMAChannel| {
+            remaining = cmp::max(remaining, dma.transfer_counter());
+            let buf = dma.abort_transfer();
+            dma.disable();
+            buf
+        };
+        let txbuf = self.dma_write.map_or(None, |dma| abort(dma));
+        let rxbuf = self.dma_read.map_or(None, |dma| abort(dma));
+
+        let len = self.dma_length.get().saturating_sub(remaining);
+        self.dma_length.set(0);
+
+        client.read_write_done(txbuf, rxbuf, len, Ok(()));
+        if self.transfers_in_progress.get() == 0 {
+            self.disable();
+        }
+    }
+
     /// Asynchronous buffer read/write of SPI.
     ///
     /// Returns:
@@ -926,6 +926,25 @@ impl<'a> uart::Receive<'a> for USART<'a> {
     }
 }
 
+impl<'a> uart::TransmitAdvanced<'a> for USART<'a> {
+    fn max_dma_len(&self) -> usize {
+        // The transfer counter of the PDCA is 16 bits wide.
+        u16::MAX as usize
+    }
+
+    fn transmit_buffer_dma(
+        &self,
+        tx_buffer: &'static mut [u8],
+        tx_len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if tx_len > self.max_dma_len() {
+            return Err((ErrorCode::SIZE, tx_buffer));
+        }
+        // Transmissions always use DMA.
+        uart::Transmit::transmit_buffer(self, tx_buffer, tx_len)
+    }
+}
+
 impl<'a> uart::Transmit<'a> for USART<'a> {
     fn transmit_buffer(
         &self,
@@ -4,6 +4,7 @@
 
 //! Platform Level Interrupt Control peripheral driver.
 
+use kernel::platform::userspace_driver::InterruptMask;
 use kernel::utilities::cells::VolatileCell;
 use kernel::utilities::registers::interfaces::{Readable, Writeable};
 use kernel::utilities::registers::LocalRegisterCopy;
@@ -92,6 +93,20 @@ impl Plic {
         }
     }
 
+    /// Enable interrupt `index`.
+    pub fn enable(&self, index: u32) {
+        if let Some(enable) = self.registers.enable.get(index as usize / 32) {
+            enable.set(enable.get() | 1 << (index % 32));
+        }
+    }
+
+    /// Disable interrupt `index`.
+    pub fn disable(&self, index: u32) {
+        if let Some(enable) = self.registers.enable.get(index as usize / 32) {
+            enable.set(enable.get() & !(1 << (index % 32)));
+        }
+    }
+
     /// Get the index (0-256) of the lowest number pending interrupt, or `None` if
     /// none is pending. RISC-V PLIC has a "claim" register which makes it easy
     /// to grab the highest priority pending interrupt.
@@ -150,6 +165,12 @@ impl Plic {
         self.saved[offset].set(LocalRegisterCopy::new(new_saved));
     }
 
+    /// Complete interrupt `index` at the PLIC without changing the saved
+    /// interrupts.
+    fn release(&self, index: u32) {
+        self.registers.claim.set(index);
+    }
+
     /// This is a generic implementation. There may be board specific versions as
     /// some platforms have added more bits to the `mtvec` register.
     pub fn suppress_all(&self) {
@@ -157,3 +178,19 @@ impl Plic {
         self.registers.threshold.write(priority::Priority.val(0));
     }
 }
+
+/// Masks PLIC interrupts for `InterruptForwarding`.
+///
+/// A masked interrupt is disabled. The PLIC ignores the completion of a
+/// disabled interrupt, so the completion the chip sends after servicing the
+/// masked interrupt is repeated when it is unmasked.
+impl InterruptMask for Plic {
+    fn mask(&self, irq: u32) {
+        self.disable(irq);
+    }
+
+    fn unmask(&self, irq: u32) {
+        self.enable(irq);
+        self.release(irq);
+    }
+}
@@ -8,14 +8,21 @@ use core::fmt::Write;
 use cortexm4::{self, CortexM4, CortexMVariant};
 use kernel::platform::chip::Chip;
 use kernel::platform::chip::InterruptService;
+use kernel::platform::idle::{IdleSource, SleepDepth};
+use kernel::utilities::cells::OptionalCell;
 
 use crate::dma;
 use crate::nvic;
+use crate::pwr;
+
+/// Time to wake up from Stop mode with the regulator in low-power mode.
+const STOP_WAKE_LATENCY_US: u32 = 50;
 
 pub struct Stm32f4xx<'a, I: InterruptService + 'a> {
     mpu: cortexm4::mpu::MPU,
     userspace_kernel_boundary: cortexm4::syscall::SysCall,
     interrupt_service: &'a I,
+    idle_arbiter: OptionalCell<&'a dyn IdleSource>,
 }
 
 pub struct Stm32f4xxDefaultPeripherals<'a> {
@@ -31,6 +38,7 @@ pub struct Stm32f4xxDefaultPeripherals<'a> {
     pub usart3: crate::usart::Usart<'a, dma::Dma1<'a>>,
     pub gpio_ports: crate::gpio::GpioPorts<'a>,
     pub fsmc: crate::fsmc::Fsmc<'a>,
+    pub pwr: crate::pwr::Pwr<'a>,
 }
 
 impl<'a> Stm32f4xxDefaultPeripherals<'a> {
@@ -69,6 +77,7 @@ impl<'a> Stm32f4xxDefaultPeripherals<'a> {
                 ],
                 rcc,
             ),
+            pwr: crate::pwr::Pwr::new(rcc),
         }
     }
 
@@ -147,8 +156,24 @@ impl<'a, I: InterruptService + 'a> Stm32f4xx<'a, I> {
             mpu: cortexm4::mpu::MPU::new(),
             userspace_kernel_boundary: cortexm4::syscall::SysCall::new(),
             interrupt_service,
+            idle_arbiter: OptionalCell::empty(),
         }
     }
+
+    /// Enter Stop mode when idle if `arbiter` allows deep sleep and no DMA
+    /// transfer is in progress. Without an arbiter, the chip only uses
+    /// Sleep mode.
+    ///
+    /// In Stop mode all clocks except the LSI and LSE stop, so timers such
+    /// as TIM2 stop counting: the board must tell its alarm mux so with
+    /// `MuxAlarm::set_sleep_depth(SleepDepth::Shallow)`, and peripherals
+    /// that must keep receiving need to keep the chip in shallow sleep,
+    /// e.g. with an `ActivityTracker`.
+    pub fn set_idle_arbiter(&self, arbiter: &'a dyn IdleSource, pwr: &pwr::Pwr) {
+        pwr.enable_clock();
+        pwr.configure_stop_mode();
+        self.idle_arbiter.set(arbiter);
+    }
 }
 
 impl<'a, I: InterruptService + 'a> Chip for Stm32f4xx<'a, I> {
@@ -159,6 +184,7 @@ impl<'a, I: InterruptService + 'a> Chip for Stm32f4xx<'a, I> {
         unsafe {
             loop {
                 if let Some(interrupt) = cortexm4::nvic::next_pending() {
+                    cortexm4::irq_latency::record(interrupt as usize);
                     if !self.interrupt_service.service_interrupt(interrupt) {
                         panic!("unhandled interrupt {}", interrupt);
                     }
@@ -186,8 +212,17 @@ impl<'a, I: InterruptService + 'a> Chip for Stm32f4xx<'a, I> {
     }
 
     fn sleep(&self) {
+        let stop = self.idle_arbiter.map_or(false, |arbiter| {
+            arbiter
+                .sleep_budget()
+                .allows(SleepDepth::Deep, STOP_WAKE_LATENCY_US)
+        }) && !dma::transfers_pending();
         unsafe {
-            cortexm4::scb::unset_sleepdeep();
+            if stop {
+                cortexm4::scb::set_sleepdeep();
+            } else {
+                cortexm4::scb::unset_sleepdeep();
+            }
             cortexm4::support::wfi();
         }
     }
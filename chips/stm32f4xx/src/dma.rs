@@ -3,6 +3,7 @@
 // Copyright Tock Contributors 2022.
 
 use core::fmt::Debug;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 use kernel::platform::chip::ClockInterface;
 use kernel::utilities::cells::{OptionalCell, TakeCell};
@@ -774,6 +775,16 @@ pub enum TransferMode {
     Fifo(FifoSize),
 }
 
+/// Number of streams, on either controller, with a transfer in progress.
+/// Tracked so that the chip does not enter Stop mode, which halts DMA.
+static ACTIVE_TRANSFERS: AtomicUsize = AtomicUsize::new(0);
+
+/// Whether any stream has a transfer in progress, i.e. holds a buffer that
+/// was not returned yet.
+pub fn transfers_pending() -> bool {
+    ACTIVE_TRANSFERS.load(Ordering::Relaxed) > 0
+}
+
 /// This struct refers to a DMA Stream
 ///
 /// What other microcontrollers refer to as "channel", STM32F4XX refers to as "streams".
@@ -842,6 +853,10 @@ impl<'a, DMA: StreamServer<'a>> Stream<'a, DMA> {
     }
 
     pub fn do_transfer(&self, buf: &'static mut [u8], len: usize) {
+        if self.buffer.is_none() {
+            ACTIVE_TRANSFERS.fetch_add(1, Ordering::Relaxed);
+        }
+
         self.disable_interrupt();
 
         // The numbers below are from Section 1.2 of AN4031
@@ -876,11 +891,19 @@ impl<'a, DMA: StreamServer<'a>> Stream<'a, DMA> {
 
         self.disable();
 
-        (self.buffer.take(), self.get_data_items())
+        (self.take_buffer(), self.get_data_items())
     }
 
     pub fn return_buffer(&self) -> Option<&'static mut [u8]> {
-        self.buffer.take()
+        self.take_buffer()
+    }
+
+    fn take_buffer(&self) -> Option<&'static mut [u8]> {
+        let buffer = self.buffer.take();
+        if buffer.is_some() {
+            ACTIVE_TRANSFERS.fetch_sub(1, Ordering::Relaxed);
+        }
+        buffer
     }
 
     fn set_channel(&self) {
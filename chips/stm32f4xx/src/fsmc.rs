@@ -6,6 +6,7 @@ use crate::rcc;
 use core::cell::Cell;
 use kernel::deferred_call::{DeferredCall, DeferredCallClient};
 use kernel::hil::bus8080::{Bus8080, BusWidth, Client};
+use kernel::hil::xip::{ExecuteInPlace, XipConfig};
 use kernel::platform::chip::ClockInterface;
 use kernel::utilities::cells::{OptionalCell, TakeCell};
 use kernel::utilities::registers::interfaces::{ReadWriteable, Readable};
@@ -153,6 +154,13 @@ pub enum FsmcBanks {
 pub const FSMC_BANK1: StaticRef<FsmcBank> =
     unsafe { StaticRef::new(0x60000000 as *const FsmcBank) };
 // const FSMC_BANK2_RESERVED: StaticRef<FsmcBank> = unsafe { StaticRef::new(0x0 as *const FsmcBank) };
+/// Start of the address range of NOR flash on the second chip select of
+/// bank 1, which code can execute from.
+const NOR_FLASH_BASE: usize = 0x64000000;
+
+/// Size of the address range of one chip select of bank 1.
+const NOR_FLASH_MAX_SIZE: usize = 0x04000000;
+
 pub const FSMC_BANK3: StaticRef<FsmcBank> =
     unsafe { StaticRef::new(0x68000000 as *const FsmcBank) };
 // const FSMC_BANK4_RESERVED: StaticRef<FsmcBank> = unsafe { StaticRef::new(0x0 as *const FsmcBank) };
@@ -277,6 +285,52 @@ impl<'a> Fsmc<'a> {
     }
 }
 
+/// Maps asynchronous 16-bit NOR flash on the second chip select (NE2) of
+/// bank 1. The bus is parallel, so the read mode and clock of the
+/// configuration do not apply, and `latency_cycles` is the number of HCLK
+/// cycles of the data phase of a read. The pins must be configured by the
+/// board.
+impl ExecuteInPlace for Fsmc<'_> {
+    fn enable(&self, config: XipConfig) -> Result<&'static [u8], ErrorCode> {
+        if config.size > NOR_FLASH_MAX_SIZE {
+            return Err(ErrorCode::SIZE);
+        }
+        if config.latency_cycles == 0 {
+            return Err(ErrorCode::INVAL);
+        }
+        self.set_cache(config.cache)?;
+        self.enable_clock();
+        self.registers.btr2.modify(
+            BTR::ADDSET.val(1)
+                + BTR::ADDHLD.val(1)
+                + BTR::DATAST.val(config.latency_cycles as u32)
+                + BTR::BUSTURN.val(1)
+                + BTR::ACCMOD::A,
+        );
+        self.registers.bcr2.modify(
+            BCR::MBKEN::SET
+                + BCR::MTYP::NOR
+                + BCR::MWID::BITS_16
+                + BCR::FACCEN::SET
+                + BCR::WREN::CLEAR
+                + BCR::CPSIZE::NO_BURST,
+        );
+
+        // SAFETY: the flash is mapped to this address range from now on, and
+        // it is only read through this slice.
+        Ok(unsafe { core::slice::from_raw_parts(NOR_FLASH_BASE as *const u8, config.size) })
+    }
+
+    fn set_cache(&self, enabled: bool) -> Result<(), ErrorCode> {
+        // The flash accelerator only caches internal flash.
+        if enabled {
+            Err(ErrorCode::NOSUPPORT)
+        } else {
+            Ok(())
+        }
+    }
+}
+
 impl DeferredCallClient for Fsmc<'_> {
     fn register(&'static self) {
         self.deferred_call.register(self);
@@ -743,6 +743,35 @@ impl Port<'_> {
     }
 }
 
+/// Pins of a port are set and reset with a single write to BSRR, which
+/// takes the pins to set in its low half and the pins to reset in its high
+/// half.
+impl hil::gpio::Port for Port<'_> {
+    fn set_pins(&self, mask: u32) {
+        self.registers.bsrr.set(mask & 0xffff);
+    }
+
+    fn clear_pins(&self, mask: u32) {
+        self.registers.bsrr.set((mask & 0xffff) << 16);
+    }
+
+    fn toggle_pins(&self, mask: u32) {
+        let current = self.registers.odr.get();
+        self.write_pins(mask, !current);
+    }
+
+    fn write_pins(&self, mask: u32, value: u32) {
+        let mask = mask & 0xffff;
+        self.registers
+            .bsrr
+            .set((value & mask) | ((!value & mask) << 16));
+    }
+
+    fn read_pins(&self) -> u32 {
+        self.registers.idr.get() & 0xffff
+    }
+}
+
 struct PortClock<'a>(rcc::PeripheralClock<'a>);
 
 impl ClockInterface for PortClock<'_> {
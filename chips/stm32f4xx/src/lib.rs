@@ -22,7 +22,9 @@ pub mod exti;
 pub mod fsmc;
 pub mod gpio;
 pub mod i2c;
+pub mod pwr;
 pub mod rcc;
+pub mod reset;
 pub mod spi;
 pub mod syscfg;
 pub mod tim2;
@@ -0,0 +1,109 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Power controller, used to configure the low-power modes.
+
+use kernel::platform::chip::ClockInterface;
+use kernel::utilities::registers::interfaces::ReadWriteable;
+use kernel::utilities::registers::{register_bitfields, ReadWrite};
+use kernel::utilities::StaticRef;
+
+use crate::rcc;
+
+/// Power controller
+#[repr(C)]
+struct PwrRegisters {
+    /// power control register
+    cr: ReadWrite<u32, CR::Register>,
+    /// power control/status register
+    csr: ReadWrite<u32, CSR::Register>,
+}
+
+register_bitfields![u32,
+    CR [
+        /// Flash power-down in Stop mode
+        FPDS OFFSET(9) NUMBITS(1) [],
+        /// Disable backup domain write protection
+        DBP OFFSET(8) NUMBITS(1) [],
+        /// PVD level selection
+        PLS OFFSET(5) NUMBITS(3) [],
+        /// Power voltage detector enable
+        PVDE OFFSET(4) NUMBITS(1) [],
+        /// Clear standby flag
+        CSBF OFFSET(3) NUMBITS(1) [],
+        /// Clear wakeup flag
+        CWUF OFFSET(2) NUMBITS(1) [],
+        /// Power-down deepsleep
+        PDDS OFFSET(1) NUMBITS(1) [],
+        /// Low-power deep sleep
+        LPDS OFFSET(0) NUMBITS(1) []
+    ],
+    CSR [
+        /// Backup regulator ready
+        BRR OFFSET(3) NUMBITS(1) [],
+        /// PVD output
+        PVDO OFFSET(2) NUMBITS(1) [],
+        /// Standby flag
+        SBF OFFSET(1) NUMBITS(1) [],
+        /// Wakeup flag
+        WUF OFFSET(0) NUMBITS(1) []
+    ]
+];
+
+const PWR_BASE: StaticRef<PwrRegisters> =
+    unsafe { StaticRef::new(0x40007000 as *const PwrRegisters) };
+
+pub struct Pwr<'a> {
+    registers: StaticRef<PwrRegisters>,
+    clock: PwrClock<'a>,
+}
+
+impl<'a> Pwr<'a> {
+    pub const fn new(rcc: &'a rcc::Rcc) -> Self {
+        Self {
+            registers: PWR_BASE,
+            clock: PwrClock(rcc::PeripheralClock::new(
+                rcc::PeripheralClockType::APB1(rcc::PCLK1::PWR),
+                rcc,
+            )),
+        }
+    }
+
+    pub fn is_enabled_clock(&self) -> bool {
+        self.clock.is_enabled()
+    }
+
+    pub fn enable_clock(&self) {
+        self.clock.enable();
+    }
+
+    pub fn disable_clock(&self) {
+        self.clock.disable();
+    }
+
+    /// Make deep sleep (`SLEEPDEEP`) enter Stop mode with the voltage
+    /// regulator in low-power mode, rather than Standby mode. The flash
+    /// stays powered to keep the wakeup time short.
+    pub fn configure_stop_mode(&self) {
+        self.registers
+            .cr
+            .modify(CR::PDDS::CLEAR + CR::LPDS::SET + CR::FPDS::CLEAR);
+    }
+}
+
+struct PwrClock<'a>(rcc::PeripheralClock<'a>);
+
+impl ClockInterface for PwrClock<'_> {
+    fn is_enabled(&self) -> bool {
+        self.0.is_enabled()
+    }
+
+    fn enable(&self) {
+        self.0.enable();
+    }
+
+    fn disable(&self) {
+        self.0.disable();
+    }
+}
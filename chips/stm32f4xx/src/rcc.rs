@@ -2,7 +2,8 @@
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 // Copyright Tock Contributors 2022.
 
-use kernel::platform::chip::ClockInterface;
+use kernel::hil::reset::{ResetCause, ResetCauses};
+use kernel::platform::chip::{ClockInterface, ClockIntrospection, PeripheralClockState};
 use kernel::utilities::registers::interfaces::{ReadWriteable, Readable};
 use kernel::utilities::registers::{register_bitfields, ReadWrite};
 use kernel::utilities::StaticRef;
@@ -731,6 +732,34 @@ pub struct Rcc {
     registers: StaticRef<RccRegisters>,
 }
 
+/// Reads the reset flags, and clears them so the next reset is reported
+/// correctly.
+impl ResetCauses for Rcc {
+    fn last_reset_cause(&self) -> ResetCause {
+        let csr = self.registers.csr.extract();
+        self.registers.csr.modify(CSR::RMVF::SET);
+        // The pin reset flag is set by every reset, check it last.
+        if csr.is_set(CSR::WDGRSTF) || csr.is_set(CSR::WWDGRSTF) {
+            ResetCause::Watchdog
+        } else if csr.is_set(CSR::BORRSTF) {
+            // Also set with the power-on reset flag on power on.
+            if csr.is_set(CSR::PORRSTF) {
+                ResetCause::PowerOn
+            } else {
+                ResetCause::Brownout
+            }
+        } else if csr.is_set(CSR::PORRSTF) {
+            ResetCause::PowerOn
+        } else if csr.is_set(CSR::SFTRSTF) {
+            ResetCause::Software
+        } else if csr.is_set(CSR::PADRSTF) {
+            ResetCause::ExternalPin
+        } else {
+            ResetCause::Other
+        }
+    }
+}
+
 impl Rcc {
     pub const fn new() -> Rcc {
         Rcc {
@@ -1052,6 +1081,113 @@ impl Rcc {
     fn disable_can1_clock(&self) {
         self.registers.apb1enr.modify(APB1ENR::CAN1EN::CLEAR);
     }
+
+    // PWR clock
+
+    fn is_enabled_pwr_clock(&self) -> bool {
+        self.registers.apb1enr.is_set(APB1ENR::PWREN)
+    }
+
+    fn enable_pwr_clock(&self) {
+        self.registers.apb1enr.modify(APB1ENR::PWREN::SET);
+    }
+
+    fn disable_pwr_clock(&self) {
+        self.registers.apb1enr.modify(APB1ENR::PWREN::CLEAR);
+    }
+}
+
+/// Frequency of the internal high-speed oscillator, which the chip runs on
+/// after reset.
+const HSI_FREQUENCY: u32 = 16_000_000;
+
+impl Rcc {
+    /// Frequency of the AHB clock, if the chip runs on the HSI.
+    fn ahb_frequency(&self) -> Option<u32> {
+        let cfgr = self.registers.cfgr.extract();
+        if cfgr.is_set(CFGR::SWS1) || cfgr.is_set(CFGR::SWS0) {
+            // The HSE and PLL frequencies depend on the board.
+            return None;
+        }
+        let hpre = cfgr.read(CFGR::HPRE);
+        let shift = match hpre {
+            0b1000..=0b1011 => hpre - 0b0111,
+            0b1100..=0b1111 => hpre - 0b0110,
+            _ => 0,
+        };
+        Some(HSI_FREQUENCY >> shift)
+    }
+
+    /// Frequency of an APB clock given its prescaler setting.
+    fn apb_frequency(&self, ppre: u32) -> Option<u32> {
+        let shift = if ppre & 0b100 != 0 {
+            (ppre & 0b11) + 1
+        } else {
+            0
+        };
+        self.ahb_frequency().map(|frequency| frequency >> shift)
+    }
+
+    /// Name, clock and driver of peripheral `index` for `ClockIntrospection`.
+    fn introspected_peripheral(
+        index: usize,
+    ) -> Option<(&'static str, PeripheralClockType, &'static str)> {
+        use PeripheralClockType::{AHB1, AHB2, AHB3, APB1, APB2};
+        Some(match index {
+            0 => ("DMA1", AHB1(HCLK1::DMA1), "stm32f4xx::dma"),
+            1 => ("DMA2", AHB1(HCLK1::DMA2), "stm32f4xx::dma"),
+            2 => ("GPIOA", AHB1(HCLK1::GPIOA), "stm32f4xx::gpio"),
+            3 => ("GPIOB", AHB1(HCLK1::GPIOB), "stm32f4xx::gpio"),
+            4 => ("GPIOC", AHB1(HCLK1::GPIOC), "stm32f4xx::gpio"),
+            5 => ("GPIOD", AHB1(HCLK1::GPIOD), "stm32f4xx::gpio"),
+            6 => ("GPIOE", AHB1(HCLK1::GPIOE), "stm32f4xx::gpio"),
+            7 => ("GPIOF", AHB1(HCLK1::GPIOF), "stm32f4xx::gpio"),
+            8 => ("GPIOG", AHB1(HCLK1::GPIOG), "stm32f4xx::gpio"),
+            9 => ("GPIOH", AHB1(HCLK1::GPIOH), "stm32f4xx::gpio"),
+            10 => ("RNG", AHB2(HCLK2::RNG), "stm32f4xx::trng"),
+            11 => ("OTGFS", AHB2(HCLK2::OTGFS), "-"),
+            12 => ("FMC", AHB3(HCLK3::FMC), "stm32f4xx::fsmc"),
+            13 => ("TIM2", APB1(PCLK1::TIM2), "stm32f4xx::tim2"),
+            14 => ("USART2", APB1(PCLK1::USART2), "stm32f4xx::usart"),
+            15 => ("USART3", APB1(PCLK1::USART3), "stm32f4xx::usart"),
+            16 => ("SPI3", APB1(PCLK1::SPI3), "stm32f4xx::spi"),
+            17 => ("I2C1", APB1(PCLK1::I2C1), "stm32f4xx::i2c"),
+            18 => ("CAN1", APB1(PCLK1::CAN1), "stm32f4xx::can"),
+            19 => ("PWR", APB1(PCLK1::PWR), "stm32f4xx::pwr"),
+            20 => ("USART1", APB2(PCLK2::USART1), "stm32f4xx::usart"),
+            21 => ("ADC1", APB2(PCLK2::ADC1), "stm32f4xx::adc"),
+            22 => ("SYSCFG", APB2(PCLK2::SYSCFG), "stm32f4xx::syscfg"),
+            _ => return None,
+        })
+    }
+}
+
+/// Reports the peripheral clocks that the drivers in this crate use. Clock
+/// frequencies are only known while the chip runs on the HSI.
+impl ClockIntrospection for Rcc {
+    fn peripheral_count(&self) -> usize {
+        23
+    }
+
+    fn peripheral_clock(&self, index: usize) -> Option<PeripheralClockState> {
+        let (name, clock, owner) = Self::introspected_peripheral(index)?;
+        let frequency = match clock {
+            PeripheralClockType::APB1(_) => {
+                self.apb_frequency(self.registers.cfgr.read(CFGR::PPRE1))
+            }
+            PeripheralClockType::APB2(_) => {
+                self.apb_frequency(self.registers.cfgr.read(CFGR::PPRE2))
+            }
+            _ => self.ahb_frequency(),
+        };
+        let enabled = PeripheralClock::new(clock, self).is_enabled();
+        Some(PeripheralClockState {
+            name,
+            enabled,
+            frequency,
+            owner,
+        })
+    }
 }
 
 /// Clock sources for CPU
@@ -1109,6 +1245,7 @@ pub enum PCLK1 {
     SPI3,
     I2C1,
     CAN1,
+    PWR,
 }
 
 /// Peripherals clocked by PCLK2
@@ -1157,6 +1294,7 @@ impl<'a> ClockInterface for PeripheralClock<'a> {
                 PCLK1::I2C1 => self.rcc.is_enabled_i2c1_clock(),
                 PCLK1::SPI3 => self.rcc.is_enabled_spi3_clock(),
                 PCLK1::CAN1 => self.rcc.is_enabled_can1_clock(),
+                PCLK1::PWR => self.rcc.is_enabled_pwr_clock(),
             },
             PeripheralClockType::APB2(ref v) => match v {
                 PCLK2::USART1 => self.rcc.is_enabled_usart1_clock(),
@@ -1230,6 +1368,9 @@ impl<'a> ClockInterface for PeripheralClock<'a> {
                 PCLK1::CAN1 => {
                     self.rcc.enable_can1_clock();
                 }
+                PCLK1::PWR => {
+                    self.rcc.enable_pwr_clock();
+                }
             },
             PeripheralClockType::APB2(ref v) => match v {
                 PCLK2::USART1 => {
@@ -1309,6 +1450,9 @@ impl<'a> ClockInterface for PeripheralClock<'a> {
                 PCLK1::CAN1 => {
                     self.rcc.disable_can1_clock();
                 }
+                PCLK1::PWR => {
+                    self.rcc.disable_pwr_clock();
+                }
             },
             PeripheralClockType::APB2(ref v) => match v {
                 PCLK2::USART1 => {
@@ -0,0 +1,54 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Reset, optionally into the system bootloader in ROM.
+//!
+//! The system bootloader is entered by jumping to it with interrupts
+//! disabled and the system memory mapped at address 0, as if the chip was
+//! booted with the BOOT0 pin high. It can then update the flash over the
+//! USART, USB DFU or other interfaces, depending on the part.
+
+use cortexm4::{nvic, scb, support};
+use kernel::hil::reset::{Reset, ResetMode};
+use kernel::platform::scheduler_timer::SchedulerTimer;
+use kernel::ErrorCode;
+
+use crate::syscfg::Syscfg;
+
+/// Address of the system memory, which holds the bootloader.
+const SYSTEM_MEMORY: usize = 0x1FFF_0000;
+
+pub struct Stm32f4Reset<'a> {
+    syscfg: &'a Syscfg<'a>,
+}
+
+impl<'a> Stm32f4Reset<'a> {
+    pub fn new(syscfg: &'a Syscfg<'a>) -> Stm32f4Reset<'a> {
+        Stm32f4Reset { syscfg }
+    }
+}
+
+impl Reset for Stm32f4Reset<'_> {
+    fn supports(&self, _mode: ResetMode) -> bool {
+        true
+    }
+
+    fn reset(&self, mode: ResetMode) -> ErrorCode {
+        match mode {
+            ResetMode::Normal => scb::ScbReset.reset(ResetMode::Normal),
+            ResetMode::Bootloader => unsafe {
+                // The bootloader expects the interrupts in their reset state,
+                // so that none fires until it configured them. PRIMASK is
+                // left clear, as it is after a reset.
+                cortexm4::systick::SysTick::new().reset();
+                nvic::disable_all();
+                nvic::clear_all_pending();
+                self.syscfg.enable_clock();
+                self.syscfg.map_system_flash();
+                scb::set_vector_table_offset(SYSTEM_MEMORY as *const ());
+                support::jump_to_image(SYSTEM_MEMORY)
+            },
+        }
+    }
+}
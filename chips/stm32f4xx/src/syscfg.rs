@@ -147,6 +147,11 @@ impl<'a> Syscfg<'a> {
         self.clock.disable();
     }
 
+    /// Map the system memory, which holds the ROM bootloader, at address 0.
+    pub fn map_system_flash(&self) {
+        self.registers.memrm.modify(MEMRM::MEM_MODE.val(0b001));
+    }
+
     /// Configures the SYSCFG_EXTICR{1, 2, 3, 4} registers
     pub fn configure_interrupt(&self, pinid: gpio::PinId) {
         let exticrid = self.get_exticrid_from_port_num(pinid.get_port_number());
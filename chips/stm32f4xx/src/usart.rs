@@ -477,6 +477,25 @@ impl<'a, DMA: dma::StreamServer<'a>> DeferredCallClient for Usart<'a, DMA> {
     }
 }
 
+impl<'a, DMA: dma::StreamServer<'a>> hil::uart::TransmitAdvanced<'a> for Usart<'a, DMA> {
+    fn max_dma_len(&self) -> usize {
+        // The number of data items of a DMA stream is 16 bits wide.
+        u16::MAX as usize
+    }
+
+    fn transmit_buffer_dma(
+        &self,
+        tx_buffer: &'static mut [u8],
+        tx_len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if tx_len > self.max_dma_len() || tx_len > tx_buffer.len() {
+            return Err((ErrorCode::SIZE, tx_buffer));
+        }
+        // Transmissions always use DMA.
+        hil::uart::Transmit::transmit_buffer(self, tx_buffer, tx_len)
+    }
+}
+
 impl<'a, DMA: dma::StreamServer<'a>> hil::uart::Transmit<'a> for Usart<'a, DMA> {
     fn set_transmit_client(&self, client: &'a dyn hil::uart::TransmitClient) {
         self.tx_client.set(client);
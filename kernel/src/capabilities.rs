@@ -105,3 +105,25 @@ pub unsafe trait CreatePortTableCapability {}
 /// of the networking stack. A capsule would never hold this capability although
 /// it may hold capabilities created via this capability.
 pub unsafe trait NetworkCapabilityCreationCapability {}
+
+/// The `UserspaceDriverCapability` allows the holder to give a process
+/// direct access to peripheral registers, interrupts and DMA, see
+/// `kernel::platform::userspace_driver`. Such a process is as trusted as
+/// the kernel, so only a board should create this capability.
+pub unsafe trait UserspaceDriverCapability {}
+
+/// The `KernelStorageCapability` allows the holder to create storage
+/// permissions for kernel users of persistent storage, such as the key-value
+/// store. Items stored with these permissions are kept apart from those of
+/// processes, so only a board should create this capability.
+pub unsafe trait KernelStorageCapability {}
+
+/// The `Ieee802154KeyManagementCapability` allows the holder to rotate the
+/// IEEE 802.15.4 link-layer keys of the radio driver, and to let processes do
+/// so through the driver's syscall interface.
+pub unsafe trait Ieee802154KeyManagementCapability {}
+
+/// The `Ieee802154CaptureCapability` allows the holder to enable the capture
+/// mode of the IEEE 802.15.4 MAC mux, which passes on every frame the radio
+/// receives, including frames addressed to other nodes.
+pub unsafe trait Ieee802154CaptureCapability {}
@@ -80,6 +80,29 @@ pub(crate) struct Config {
     // credentials checking, e.g., whether elf2tab and tockloader are generating
     // properly formatted footers.
     pub(crate) debug_process_credentials: bool,
+
+    /// Whether `debug_deferred!()` should send interned format string IDs and
+    /// raw arguments instead of formatted text.
+    ///
+    /// If enabled, the format strings of `debug_deferred!()` are not stored in
+    /// flash and no formatting code runs on the device. The debug output must
+    /// then be decoded on the host with `tools/decode_deferred_log.py` and the
+    /// kernel ELF.
+    pub(crate) deferred_logging: bool,
+
+    /// Whether interrupts are timestamped to record how long they wait for
+    /// their bottom half, see `irq_latency`.
+    ///
+    /// If enabled, the architecture takes a timestamp in the top half of each
+    /// interrupt, which adds a few cycles to every interrupt.
+    pub(crate) irq_latency: bool,
+
+    /// Whether the process loader decompresses TBFs marked as compressed.
+    ///
+    /// If enabled, compressed apps are decompressed into the buffer passed to
+    /// `process::set_decompression_buffer()` when they are loaded.
+    /// This adds the decompressor to the kernel.
+    pub(crate) compressed_apps: bool,
 }
 
 /// A unique instance of `Config` where compile-time configuration options are
@@ -92,4 +115,7 @@ pub(crate) const CONFIG: Config = Config {
     debug_load_processes: cfg!(feature = "debug_load_processes"),
     debug_panics: !cfg!(feature = "no_debug_panics"),
     debug_process_credentials: cfg!(feature = "debug_process_credentials"),
+    deferred_logging: cfg!(feature = "deferred_logging"),
+    irq_latency: cfg!(feature = "irq_latency"),
+    compressed_apps: cfg!(feature = "compressed_apps"),
 };
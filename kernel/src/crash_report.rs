@@ -0,0 +1,101 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Crash report kept in persistent storage across reboots.
+//!
+//! The panic report is only written to the panic writer, so it is lost if
+//! nobody watches the serial port when the kernel panics. If the board
+//! registers a `CrashReportStorage` with `set_storage()`, the panic routine
+//! also writes a short crash report to it: the panic message, the state of
+//! each process and the CPU state printed by the chip, which includes the
+//! fault registers. After the next boot the report can be printed, e.g. with
+//! the `crash` command of the process console, and cleared.
+//!
+//! `capsules_extra::flash_crash_report` keeps the report in a reserved flash
+//! page.
+
+use core::fmt::Write;
+use core::panic::PanicInfo;
+use core::ptr::addr_of_mut;
+
+use crate::deferred_call::DeferredCall;
+use crate::platform::chip::Chip;
+use crate::process::Process;
+use crate::ErrorCode;
+
+/// Storage the panic routine writes the crash report to.
+pub trait CrashReportStorage {
+    /// Store the crash report that `report` writes, replacing the stored
+    /// one. Called while panicking, so it must not return before the report
+    /// is stored. Storage that completes its operations asynchronously calls
+    /// `poll` while it waits, which services pending interrupts and deferred
+    /// calls.
+    fn store(&self, report: &mut dyn FnMut(&mut dyn Write), poll: &dyn Fn());
+}
+
+/// Access to a crash report stored by a previous boot.
+pub trait CrashReport {
+    /// Length of the stored report in bytes, `0` if there is none.
+    fn report_len(&self) -> usize;
+
+    /// Copy the stored report, starting at byte `offset`, into `buf`.
+    /// Returns how many bytes were copied.
+    fn read_report(&self, offset: usize, buf: &mut [u8]) -> usize;
+
+    /// Erase the stored report.
+    ///
+    /// The possible ErrorCodes are:
+    ///    - BUSY: the storage is busy, e.g. still loading the report
+    ///    - FAIL: the report could not be erased
+    fn clear_report(&self) -> Result<(), ErrorCode>;
+}
+
+static mut STORAGE: Option<&'static dyn CrashReportStorage> = None;
+
+/// Store a crash report in `storage` when the kernel panics.
+///
+/// Storing the report services interrupts and deferred calls after the
+/// panic report was printed, so the storage driver can complete its
+/// operations.
+pub unsafe fn set_storage(storage: &'static dyn CrashReportStorage) {
+    *addr_of_mut!(STORAGE) = Some(storage);
+}
+
+/// Write the crash report to the registered storage, if any.
+pub(crate) unsafe fn store<C: Chip>(
+    panic_info: &PanicInfo,
+    processes: &'static [Option<&'static dyn Process>],
+    chip: &'static Option<&'static C>,
+) {
+    let storage = match *addr_of_mut!(STORAGE) {
+        Some(storage) => storage,
+        None => return,
+    };
+    storage.store(
+        &mut |writer| {
+            let _ = writer.write_fmt(format_args!("{}\r\n", panic_info));
+            let _ = writer.write_fmt(format_args!(
+                "Kernel version {}\r\n",
+                option_env!("TOCK_KERNEL_VERSION").unwrap_or("unknown")
+            ));
+            for process in processes.iter().flatten() {
+                let _ = writer.write_fmt(format_args!(
+                    "{}: {:?}, {} restarts\r\n",
+                    process.get_process_name(),
+                    process.get_state(),
+                    process.get_restart_count()
+                ));
+            }
+            chip.map(|chip| chip.print_state(writer));
+        },
+        &|| {
+            chip.map(|chip| {
+                if chip.has_pending_interrupts() {
+                    chip.service_pending_interrupts();
+                }
+            });
+            DeferredCall::service_next_pending();
+        },
+    );
+}
@@ -63,7 +63,9 @@ use crate::collections::ring_buffer::RingBuffer;
 use crate::hil;
 use crate::platform::chip::Chip;
 use crate::process::Process;
+use crate::process::ProcessId;
 use crate::process::ProcessPrinter;
+use crate::process::ShortID;
 use crate::processbuffer::ReadableProcessSlice;
 use crate::utilities::binary_write::BinaryToWriteWrapper;
 use crate::utilities::cells::NumericCellExt;
@@ -97,6 +99,65 @@ pub trait IoWrite {
 ///////////////////////////////////////////////////////////////////
 // panic! support routines
 
+/// Maximum number of additional panic outputs a board can register.
+pub const PANIC_OUTPUTS_LEN: usize = 4;
+
+static mut PANIC_OUTPUTS: [Option<&'static mut dyn IoWrite>; PANIC_OUTPUTS_LEN] =
+    [None, None, None, None];
+
+/// Register an additional output for the panic report.
+///
+/// The writer passed to `panic()` or `panic_print()` remains the primary
+/// output, but everything written to it is also written to each registered
+/// output, for example a second UART, an RTT channel or a crash region in
+/// persistent memory. Like the primary writer, outputs must be synchronous.
+///
+/// Returns `NOMEM` if `PANIC_OUTPUTS_LEN` outputs are already registered.
+pub unsafe fn add_panic_output(
+    output: &'static mut dyn IoWrite,
+) -> core::result::Result<(), ErrorCode> {
+    let outputs = &mut *core::ptr::addr_of_mut!(PANIC_OUTPUTS);
+    match outputs.iter_mut().find(|slot| slot.is_none()) {
+        Some(slot) => {
+            *slot = Some(output);
+            Ok(())
+        }
+        None => Err(ErrorCode::NOMEM),
+    }
+}
+
+/// Writes to the primary panic writer and to all registered panic outputs.
+struct PanicBroadcast<'a, W: Write + IoWrite> {
+    primary: &'a mut W,
+}
+
+impl<W: Write + IoWrite> PanicBroadcast<'_, W> {
+    fn write_outputs(&mut self, buf: &[u8]) {
+        // Safety: outputs are only used while panicking, when nothing else
+        // runs anymore.
+        let outputs = unsafe { &mut *core::ptr::addr_of_mut!(PANIC_OUTPUTS) };
+        for output in outputs.iter_mut().flatten() {
+            output.write(buf);
+        }
+    }
+}
+
+impl<W: Write + IoWrite> Write for PanicBroadcast<'_, W> {
+    fn write_str(&mut self, s: &str) -> Result {
+        let result = self.primary.write_str(s);
+        self.write_outputs(s.as_bytes());
+        result
+    }
+}
+
+impl<W: Write + IoWrite> IoWrite for PanicBroadcast<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> usize {
+        let written = self.primary.write(buf);
+        self.write_outputs(buf);
+        written
+    }
+}
+
 /// Tock panic routine, without the infinite LED-blinking loop.
 ///
 /// This is useful for boards which do not feature LEDs to blink or
@@ -107,6 +168,10 @@ pub trait IoWrite {
 /// well-defined state. Care must be taken on how one interacts with
 /// the system once this function returns.
 ///
+/// The report is also written to the outputs registered with
+/// `add_panic_output()`, and a crash report is stored if the board set up
+/// `crash_report` storage.
+///
 /// **NOTE:** The supplied `writer` must be synchronous.
 pub unsafe fn panic_print<W: Write + IoWrite, C: Chip, PP: ProcessPrinter>(
     writer: &mut W,
@@ -116,12 +181,14 @@ pub unsafe fn panic_print<W: Write + IoWrite, C: Chip, PP: ProcessPrinter>(
     chip: &'static Option<&'static C>,
     process_printer: &'static Option<&'static PP>,
 ) {
+    let writer = &mut PanicBroadcast { primary: writer };
     panic_begin(nop);
     panic_banner(writer, panic_info);
     // Flush debug buffer if needed
     flush(writer);
     panic_cpu_state(chip, writer);
     panic_process_info(processes, process_printer, writer);
+    crate::crash_report::store(panic_info, processes, chip);
 }
 
 /// Tock default panic routine.
@@ -163,6 +230,10 @@ pub unsafe fn panic_begin(nop: &dyn Fn()) {
 ///
 /// **NOTE:** The supplied `writer` must be synchronous.
 pub unsafe fn panic_banner<W: Write>(writer: &mut W, panic_info: &PanicInfo) {
+    crate::reboot_reason::record(
+        crate::reboot_reason::RebootReason::Panic,
+        format_args!("{}", panic_info),
+    );
     let _ = writer.write_fmt(format_args!("\r\n{}\r\n", panic_info));
 
     // Print version of the kernel
@@ -591,6 +662,52 @@ pub fn debug_slice(slice: &ReadableProcessSlice) -> usize {
     total
 }
 
+/// Write the `[<process name>]` tag of `processid`, with the short ID of the
+/// process added to its name if it has a fixed one. Returns false if the
+/// process does not exist.
+fn write_process_name(writer: &mut DebugWriterWrapper, processid: ProcessId) -> bool {
+    processid
+        .kernel
+        .process_map_or(false, processid, |process| {
+            let _ = match process.short_app_id() {
+                ShortID::Fixed(id) => {
+                    writer.write_fmt(format_args!("[{} {:#x}]", process.get_process_name(), id))
+                }
+                ShortID::LocallyUnique => {
+                    writer.write_fmt(format_args!("[{}]", process.get_process_name()))
+                }
+            };
+            true
+        })
+}
+
+/// Write a `[<process name>] <tag>: ` prefix for output from `processid`.
+/// The short ID of the process is added to its name if it has a fixed one.
+/// Returns the number of bytes written.
+pub fn debug_process_prefix(processid: ProcessId, tag: &str) -> usize {
+    let writer = unsafe { get_debug_writer() };
+    let available = writer.available_len();
+    if write_process_name(writer, processid) {
+        let _ = writer.write_fmt(format_args!(" {}: ", tag));
+    }
+    let written = available.saturating_sub(writer.available_len());
+    writer.publish_bytes();
+    written
+}
+
+/// Write a `[<process name>] dropped <count> bytes` line, marking output from
+/// `processid` that was not printed. Returns the number of bytes written.
+pub fn debug_process_dropped(processid: ProcessId, count: usize) -> usize {
+    let writer = unsafe { get_debug_writer() };
+    let available = writer.available_len();
+    if write_process_name(writer, processid) {
+        let _ = writer.write_fmt(format_args!(" dropped {} bytes\r\n", count));
+    }
+    let written = available.saturating_sub(writer.available_len());
+    writer.publish_bytes();
+    written
+}
+
 pub fn debug_available_len() -> usize {
     let writer = unsafe { get_debug_writer() };
     writer.available_len()
@@ -665,6 +782,216 @@ macro_rules! debug_verbose {
     });
 }
 
+///////////////////////////////////////////////////////////////////
+// debug_deferred! support
+//
+// With the `deferred_logging` kernel configuration option, `debug_deferred!()`
+// does not format its message on the device. Instead, the format string is
+// placed in the `.tock_log_fmt` section of the kernel ELF, which is not loaded
+// into flash, and only the address of the string (its ID) and the raw
+// arguments are written to the debug output as a frame:
+//
+// ```text
+// 0x00 | ID (u32, little endian) | payload length (u8) | payload
+// ```
+//
+// Debug output text never contains a NUL byte, so `0x00` marks the start of a
+// frame. The payload holds each argument as a tag byte followed by its value:
+//
+// - `0x01`: unsigned integer, LEB128 encoded.
+// - `0x02`: signed integer, zigzag and LEB128 encoded.
+// - `0x03`: bool, one byte.
+// - `0x04`: char, its code point LEB128 encoded.
+// - `0x05`: string, its length LEB128 encoded followed by its UTF-8 bytes.
+//
+// `tools/decode_deferred_log.py` reads the format strings from the ELF and
+// turns the frames back into text.
+
+/// Whether `debug_deferred!()` sends interned format strings and raw
+/// arguments instead of formatted text.
+pub const DEFERRED_LOGGING: bool = crate::config::CONFIG.deferred_logging;
+
+/// Longest payload of a `debug_deferred!()` frame, in bytes. Arguments that do
+/// not fit are dropped.
+pub const DEFERRED_PAYLOAD_LEN: usize = 64;
+
+const DEFERRED_HEADER_LEN: usize = 6;
+
+/// Copy a format string into a NUL-terminated array that `debug_deferred!()`
+/// places in the `.tock_log_fmt` section.
+#[doc(hidden)]
+pub const fn deferred_intern<const N: usize>(fmt: &str) -> [u8; N] {
+    let bytes = fmt.as_bytes();
+    let mut interned = [0; N];
+    let mut i = 0;
+    while i < bytes.len() && i < N - 1 {
+        interned[i] = bytes[i];
+        i += 1;
+    }
+    interned
+}
+
+/// A `debug_deferred!()` message as it is written to the debug output.
+pub struct DeferredFrame {
+    buffer: [u8; DEFERRED_HEADER_LEN + DEFERRED_PAYLOAD_LEN],
+    len: usize,
+}
+
+impl DeferredFrame {
+    pub fn new(id: usize) -> DeferredFrame {
+        let mut buffer = [0; DEFERRED_HEADER_LEN + DEFERRED_PAYLOAD_LEN];
+        buffer[1..5].copy_from_slice(&(id as u32).to_le_bytes());
+        DeferredFrame {
+            buffer,
+            len: DEFERRED_HEADER_LEN,
+        }
+    }
+
+    /// Append an argument, or drop it if it does not fit.
+    fn push_arg(&mut self, tag: u8, value: &[u8]) {
+        let end = self.len + 1 + value.len();
+        if end <= self.buffer.len() {
+            self.buffer[self.len] = tag;
+            self.buffer[self.len + 1..end].copy_from_slice(value);
+            self.len = end;
+            self.buffer[5] = (self.len - DEFERRED_HEADER_LEN) as u8;
+        }
+    }
+
+    /// LEB128 encode `value` into `out`, returning the number of bytes used.
+    fn leb128(mut value: u64, out: &mut [u8; 10]) -> usize {
+        let mut len = 0;
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                out[len] = byte;
+                return len + 1;
+            }
+            out[len] = byte | 0x80;
+            len += 1;
+        }
+    }
+
+    pub fn push_unsigned(&mut self, value: u64) {
+        let mut encoded = [0; 10];
+        let len = Self::leb128(value, &mut encoded);
+        self.push_arg(0x01, &encoded[..len]);
+    }
+
+    pub fn push_signed(&mut self, value: i64) {
+        let mut encoded = [0; 10];
+        let len = Self::leb128(((value << 1) ^ (value >> 63)) as u64, &mut encoded);
+        self.push_arg(0x02, &encoded[..len]);
+    }
+
+    pub fn push_bool(&mut self, value: bool) {
+        self.push_arg(0x03, &[value as u8]);
+    }
+
+    pub fn push_char(&mut self, value: char) {
+        let mut encoded = [0; 10];
+        let len = Self::leb128(value as u64, &mut encoded);
+        self.push_arg(0x04, &encoded[..len]);
+    }
+
+    pub fn push_str(&mut self, value: &str) {
+        let mut encoded = [0; 10];
+        let len = Self::leb128(value.len() as u64, &mut encoded);
+        let end = self.len + 1 + len + value.len();
+        if end <= self.buffer.len() {
+            self.push_arg(0x05, &encoded[..len]);
+            self.buffer[self.len..end].copy_from_slice(value.as_bytes());
+            self.len = end;
+            self.buffer[5] = (self.len - DEFERRED_HEADER_LEN) as u8;
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buffer[..self.len]
+    }
+}
+
+/// Values that `debug_deferred!()` can send without formatting them.
+pub trait DeferredArg {
+    fn encode(&self, frame: &mut DeferredFrame);
+}
+
+macro_rules! deferred_arg {
+    ($push:ident, $as:ty, $($t:ty)*) => {
+        $(impl DeferredArg for $t {
+            fn encode(&self, frame: &mut DeferredFrame) {
+                frame.$push(*self as $as);
+            }
+        })*
+    };
+}
+
+deferred_arg!(push_unsigned, u64, u8 u16 u32 u64 usize);
+deferred_arg!(push_signed, i64, i8 i16 i32 i64 isize);
+
+impl DeferredArg for bool {
+    fn encode(&self, frame: &mut DeferredFrame) {
+        frame.push_bool(*self);
+    }
+}
+
+impl DeferredArg for char {
+    fn encode(&self, frame: &mut DeferredFrame) {
+        frame.push_char(*self);
+    }
+}
+
+impl DeferredArg for str {
+    fn encode(&self, frame: &mut DeferredFrame) {
+        frame.push_str(self);
+    }
+}
+
+impl<T: DeferredArg + ?Sized> DeferredArg for &T {
+    fn encode(&self, frame: &mut DeferredFrame) {
+        (**self).encode(frame);
+    }
+}
+
+/// Write a `debug_deferred!()` frame to the debug output. The frame is dropped
+/// if the debug buffer cannot hold all of it.
+pub fn debug_deferred_frame(frame: &DeferredFrame) {
+    let writer = unsafe { get_debug_writer() };
+    let bytes = frame.as_bytes();
+    if writer.available_len() >= bytes.len() {
+        writer.write(bytes);
+    }
+    writer.publish_bytes();
+}
+
+/// In-kernel `println()` debugging that, with the `deferred_logging`
+/// configuration option, sends an interned format string ID and the raw
+/// arguments instead of formatted text.
+///
+/// Arguments must implement `DeferredArg` (integers, `bool`, `char` and
+/// `&str`), and the format string may only refer to them positionally.
+///
+/// ```rust,ignore
+/// debug_deferred!("alarm fired at {}, {} remaining", now, remaining);
+/// ```
+#[macro_export]
+macro_rules! debug_deferred {
+    ($fmt:literal $(, $arg:expr)* $(,)?) => ({
+        if $crate::debug::DEFERRED_LOGGING {
+            #[cfg_attr(target_os = "none", link_section = ".tock_log_fmt")]
+            static FMT: [u8; $fmt.len() + 1] =
+                $crate::debug::deferred_intern::<{ $fmt.len() + 1 }>($fmt);
+            let mut _frame =
+                $crate::debug::DeferredFrame::new(core::ptr::addr_of!(FMT) as usize);
+            $($crate::debug::DeferredArg::encode(&$arg, &mut _frame);)*
+            $crate::debug::debug_deferred_frame(&_frame);
+        } else {
+            $crate::debug::debug_println(format_args!($fmt $(, $arg)*));
+        }
+    });
+}
+
 #[macro_export]
 /// Prints out the expression and its location, then returns it.
 ///
@@ -79,9 +79,10 @@ pub trait AdcHighSpeed<'a>: Adc<'a> {
     /// configuration.
     /// Expected to be called in a `buffer_ready` callback. Note that if this
     /// is not called before the second buffer is filled, samples will be
-    /// missed. Length field corresponds to the number of samples that should
-    /// be collected in the buffer. If an error occurs, the buffer will be
-    /// returned.
+    /// missed: the ADC reports an `overrun` to the client and resumes
+    /// sampling into this buffer as soon as it is provided. Length field
+    /// corresponds to the number of samples that should be collected in the
+    /// buffer. If an error occurs, the buffer will be returned.
     ///
     /// All ADC samples will be the raw ADC value left-justified in the u16.
     fn provide_buffer(
@@ -112,6 +113,12 @@ pub trait HighSpeedClient {
     /// the buffer. Expects an additional call to either provide another buffer
     /// or stop sampling
     fn samples_ready(&self, buf: &'static mut [u16], length: usize);
+
+    /// Called when a buffer filled while the ADC had no further buffer to
+    /// sample into, so samples are lost until the client calls
+    /// `provide_buffer`. Only ADCs that fill buffers without the CPU, e.g.
+    /// with DMA, can detect this; others drop samples without calling it.
+    fn overrun(&self);
 }
 
 pub trait AdcChannel<'a> {
@@ -56,6 +56,27 @@ use crate::ErrorCode;
 pub trait BleAdvertisementDriver<'a> {
     fn transmit_advertisement(&self, buf: &'static mut [u8], len: usize, channel: RadioChannel);
     fn receive_advertisement(&self, channel: RadioChannel);
+
+    /// Receive an advertisement on `channel` and, if it is scannable
+    /// (`ADV_IND` or `ADV_SCAN_IND`), answer it with the `SCAN_REQ` PDU in the
+    /// first `len` bytes of `scan_req` and receive the `SCAN_RSP`.
+    ///
+    /// The request must follow the advertisement after the inter frame space
+    /// of 150 µs, so the radio fills in its RxAdd bit and AdvA field (bytes 8
+    /// to 13) from the advertisement. The advertisement and the response, if
+    /// any, are passed to `RxClient::receive_event`, and `scan_req` is
+    /// returned with `TxClient::transmit_event` once the exchange is over.
+    ///
+    /// Radios that cannot time the request return `NOSUPPORT`.
+    fn receive_advertisement_and_scan(
+        &self,
+        _channel: RadioChannel,
+        scan_req: &'static mut [u8],
+        _len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        Err((ErrorCode::NOSUPPORT, scan_req))
+    }
+
     fn set_receive_client(&self, client: &'a dyn RxClient);
     fn set_transmit_client(&self, client: &'a dyn TxClient);
 }
@@ -0,0 +1,85 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Interface for storage that is read, written and erased in whole blocks,
+//! like SD cards, eMMC, QSPI flash or FRAM.
+//!
+//! A write completes once the device accepted the data, which it may still
+//! hold in a cache. `flush` is a barrier: it completes once all writes and
+//! erases that completed before it was called are durable. Users that need
+//! data to survive a power loss call `flush` after writing it.
+
+use crate::errorcode::ErrorCode;
+
+/// Storage read and written in blocks of `block_size()` bytes. Blocks are
+/// numbered from 0, and operations cover `count` consecutive blocks. Only one
+/// operation runs at a time.
+pub trait BlockStorage<'a> {
+    fn set_client(&self, client: &'a dyn BlockStorageClient);
+
+    /// The length of a block in bytes.
+    fn block_size(&self) -> usize;
+
+    /// The number of blocks, or 0 if the storage is not ready yet, e.g. an SD
+    /// card that was not initialized. The capacity in bytes is
+    /// `block_count() * block_size()`.
+    fn block_count(&self) -> u64;
+
+    /// Read `count` blocks from `block` into `buffer`, which must hold
+    /// `count * block_size()` bytes.
+    ///
+    /// On success `read_complete` is called once all blocks are read. Fails
+    /// with `SIZE` if the buffer is too short, `INVAL` if the blocks are past
+    /// the end of the storage, and `BUSY` while another operation runs.
+    fn read_blocks(
+        &self,
+        buffer: &'static mut [u8],
+        block: u64,
+        count: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])>;
+
+    /// Write `count` blocks from `buffer`, which must hold
+    /// `count * block_size()` bytes, starting at `block`.
+    ///
+    /// On success `write_complete` is called once all blocks are written.
+    /// Fails like `read_blocks`.
+    fn write_blocks(
+        &self,
+        buffer: &'static mut [u8],
+        block: u64,
+        count: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])>;
+
+    /// Erase `count` blocks from `block`. What erased blocks read as depends
+    /// on the device, it is usually all 0x00 or all 0xFF bytes.
+    ///
+    /// On success `erase_complete` is called once all blocks are erased.
+    /// Fails with `NOSUPPORT` if the device can't erase, otherwise like
+    /// `read_blocks`.
+    fn erase_blocks(&self, block: u64, count: usize) -> Result<(), ErrorCode>;
+
+    /// Make all completed writes and erases durable.
+    ///
+    /// On success `flush_complete` is called once they are. Fails with `BUSY`
+    /// while another operation runs.
+    fn flush(&self) -> Result<(), ErrorCode>;
+}
+
+/// Client interface for block storage.
+pub trait BlockStorageClient {
+    /// The read started by `read_blocks` finished. Returns the buffer, and
+    /// `FAIL` if the storage reported an error, e.g. a CRC mismatch.
+    fn read_complete(&self, buffer: &'static mut [u8], result: Result<(), ErrorCode>);
+
+    /// The write started by `write_blocks` finished. Returns the buffer, and
+    /// `FAIL` if the storage reported an error.
+    fn write_complete(&self, buffer: &'static mut [u8], result: Result<(), ErrorCode>);
+
+    /// The erase started by `erase_blocks` finished.
+    fn erase_complete(&self, result: Result<(), ErrorCode>);
+
+    /// The flush started by `flush` finished, all writes and erases before it
+    /// are durable unless it failed.
+    fn flush_complete(&self, result: Result<(), ErrorCode>);
+}
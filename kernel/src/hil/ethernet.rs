@@ -0,0 +1,114 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Interface for Ethernet MACs.
+//!
+//! An `EthernetAdapter` sends and receives Ethernet II frames. Frames are
+//! passed starting with the destination MAC address and ending with the
+//! payload: the preamble and the frame check sequence are added by the MAC
+//! when it transmits a frame, and are checked and removed when it receives
+//! one.
+//!
+//! Received frames are filtered by destination: frames sent to the address
+//! of the adapter are always received, and the `AddressFilter` selects which
+//! broadcast and multicast frames are received as well, or makes the adapter
+//! receive all frames.
+//!
+//! The adapter is configured and starts receiving once `enable` is called.
+//! Frames passed to `transmit` before the adapter is ready are sent once it
+//! is.
+
+use crate::ErrorCode;
+
+/// The length of a MAC address.
+pub const MAC_ADDRESS_LEN: usize = 6;
+
+/// The length of the header of a frame: the destination and source
+/// addresses and the EtherType.
+pub const HEADER_LEN: usize = 14;
+
+/// The length of the longest frame, without its frame check sequence.
+pub const MAX_FRAME_LEN: usize = HEADER_LEN + 1500;
+
+pub type MacAddress = [u8; MAC_ADDRESS_LEN];
+
+/// The broadcast address.
+pub const BROADCAST: MacAddress = [0xff; MAC_ADDRESS_LEN];
+
+/// The frames received besides those sent to the address of the adapter.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct AddressFilter {
+    /// Receive frames sent to the broadcast address.
+    pub broadcast: bool,
+    /// Receive frames sent to any multicast address.
+    pub multicast: bool,
+    /// Receive all frames, whatever their destination.
+    pub promiscuous: bool,
+}
+
+impl Default for AddressFilter {
+    fn default() -> AddressFilter {
+        AddressFilter {
+            broadcast: true,
+            multicast: true,
+            promiscuous: false,
+        }
+    }
+}
+
+pub trait TransmitClient {
+    /// Called when a frame passed to `transmit` was sent, or could not be.
+    /// `frame` is the buffer passed to `transmit`.
+    fn transmit_done(&self, frame: &'static mut [u8], result: Result<(), ErrorCode>);
+}
+
+pub trait ReceiveClient {
+    /// Called for each received frame that passed the address filter and
+    /// the frame check. `frame` is only valid for the duration of the call.
+    fn received_frame(&self, frame: &[u8]);
+}
+
+pub trait EthernetAdapter<'a> {
+    fn set_transmit_client(&self, client: &'a dyn TransmitClient);
+
+    fn set_receive_client(&self, client: &'a dyn ReceiveClient);
+
+    /// Configure the adapter with its address and filter, and start
+    /// receiving frames. The adapter is ready asynchronously.
+    ///
+    /// Return values:
+    ///   - Ok(()): the adapter is being configured.
+    ///   - Err(ALREADY): the adapter is already enabled.
+    ///   - Err(BUSY): the adapter cannot access the hardware.
+    fn enable(&self) -> Result<(), ErrorCode>;
+
+    /// The address frames are sent from and received on.
+    fn get_address(&self) -> MacAddress;
+
+    /// Change the address of the adapter. The change takes effect once the
+    /// adapter is done with the frame it is sending or receiving, if any.
+    fn set_address(&self, address: MacAddress) -> Result<(), ErrorCode>;
+
+    /// The frames received besides those sent to the address of the adapter.
+    fn get_filter(&self) -> AddressFilter;
+
+    /// Change which frames are received besides those sent to the address
+    /// of the adapter. The change takes effect like that of `set_address`.
+    fn set_filter(&self, filter: AddressFilter) -> Result<(), ErrorCode>;
+
+    /// Send the first `len` bytes of `frame`. `transmit_done` is called
+    /// once the frame is sent, if `Ok(())` is returned.
+    ///
+    /// Return values:
+    ///   - Ok(()): the frame will be sent.
+    ///   - Err(OFF): the adapter is not enabled.
+    ///   - Err(BUSY): a frame is already being sent.
+    ///   - Err(SIZE): `len` is shorter than a header, longer than
+    ///     `MAX_FRAME_LEN` or longer than `frame`.
+    fn transmit(
+        &self,
+        frame: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])>;
+}
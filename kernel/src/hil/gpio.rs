@@ -196,6 +196,30 @@ pub trait Input {
     }
 }
 
+/// Interface for driving several pins of one GPIO port at once.
+///
+/// Bit `n` of a mask selects pin `n` of the port. All selected pins change
+/// in a single write to the hardware, so a parallel bus or a group of LEDs
+/// never shows a state where only some of them changed. Pins that are not
+/// configured as outputs are left unchanged.
+pub trait Port {
+    /// Set the pins in `mask` high.
+    fn set_pins(&self, mask: u32);
+
+    /// Set the pins in `mask` low.
+    fn clear_pins(&self, mask: u32);
+
+    /// Toggle the pins in `mask`.
+    fn toggle_pins(&self, mask: u32);
+
+    /// Set the pins in `mask` to the corresponding bits of `value`.
+    fn write_pins(&self, mask: u32, value: u32);
+
+    /// Get the current state of all pins of the port, as for
+    /// `Input::read()`.
+    fn read_pins(&self) -> u32;
+}
+
 pub trait Interrupt<'a>: Input {
     /// Set the client for interrupt events.
     fn set_client(&self, client: &'a dyn Client);
@@ -0,0 +1,35 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Interface for hardware debug and profiling facilities of the CPU.
+//!
+//! `CycleCounter` counts CPU clock cycles, e.g. the DWT cycle counter of
+//! Cortex-M cores or the `mcycle` CSR of RISC-V cores. It is meant for
+//! measuring the cost of short code paths, not for keeping time: the
+//! counter may wrap, and it may stop while the CPU sleeps.
+
+pub trait CycleCounter {
+    /// Start counting cycles.
+    fn start(&self);
+
+    /// Stop counting cycles.
+    fn stop(&self);
+
+    /// The number of cycles counted. Counters narrower than 64 bits wrap,
+    /// so compute differences with `wrapping_sub` on the low bits.
+    fn count(&self) -> u64;
+
+    /// Set the count to zero.
+    fn reset(&self);
+
+    /// Count the cycles `f` takes to run.
+    fn profile_closure<F: FnOnce()>(&self, f: F) -> u64
+    where
+        Self: Sized,
+    {
+        let start = self.count();
+        f();
+        self.count().wrapping_sub(start)
+    }
+}
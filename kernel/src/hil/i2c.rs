@@ -6,6 +6,7 @@
 
 use crate::ErrorCode;
 
+use core::cmp;
 use core::convert::Into;
 use core::fmt;
 use core::fmt::{Display, Formatter};
@@ -34,6 +35,10 @@ pub enum Error {
 
     /// The underlying device has another request in progress
     Busy,
+
+    /// The packet error code (PEC) received with an SMBus transaction did
+    /// not match the one computed over the transaction.
+    PecMismatch,
 }
 
 impl Into<ErrorCode> for Error {
@@ -44,6 +49,7 @@ impl Into<ErrorCode> for Error {
             Self::Overrun => ErrorCode::SIZE,
             Self::NotSupported => ErrorCode::NOSUPPORT,
             Self::Busy => ErrorCode::BUSY,
+            Self::PecMismatch => ErrorCode::FAIL,
         }
     }
 }
@@ -57,6 +63,7 @@ impl Display for Error {
             Error::Overrun => "I2C receive overrun",
             Error::NotSupported => "I2C/SMBus command not supported",
             Error::Busy => "I2C/SMBus is busy",
+            Error::PecMismatch => "SMBus packet error code mismatch",
         };
         write!(fmt, "{}", display_str)
     }
@@ -67,6 +74,12 @@ impl Display for Error {
 pub enum SlaveTransmissionType {
     Write,
     Read,
+    /// A write that the Master ended with a repeated start condition
+    /// addressed to this device rather than a stop condition. The read that
+    /// follows belongs to the same transaction, as in an SMBus read command
+    /// where the written bytes select the register to read. Hardware that
+    /// cannot tell the two apart reports every write as `Write`.
+    WriteBeforeRead,
 }
 
 /// Interface for an I2C Master hardware driver.
@@ -286,6 +299,247 @@ pub trait SMBusDevice: I2CDevice {
     ) -> Result<(), (Error, &'static mut [u8])>;
 }
 
+/// The largest number of data bytes in an SMBus block transfer.
+pub const SMBUS_BLOCK_MAX: usize = 32;
+
+/// Continue an SMBus packet error code (PEC) computation over `data`.
+///
+/// The PEC is a CRC-8 with polynomial x^8 + x^2 + x + 1 that starts at zero
+/// and covers every byte of the transaction, including the address bytes.
+pub fn smbus_pec_update(mut crc: u8, data: &[u8]) -> u8 {
+    for byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// Compute the PEC of a transaction with the device at `addr` that writes
+/// `written` and, if `read` is not empty, then reads `read` after a repeated
+/// start.
+pub fn smbus_pec(addr: u8, written: &[u8], read: &[u8]) -> u8 {
+    let mut crc = smbus_pec_update(0, &[addr << 1]);
+    crc = smbus_pec_update(crc, written);
+    if !read.is_empty() {
+        crc = smbus_pec_update(crc, &[(addr << 1) | 1]);
+        crc = smbus_pec_update(crc, read);
+    }
+    crc
+}
+
+/// Check the PEC that ends `read` of a write-then-read transaction with the
+/// device at `addr`, or of a plain read if `written` is empty.
+pub fn smbus_pec_verify(addr: u8, written: &[u8], read: &[u8]) -> Result<(), Error> {
+    match read.split_last() {
+        Some((pec, data)) => {
+            let crc = if written.is_empty() {
+                smbus_pec_update(smbus_pec_update(0, &[(addr << 1) | 1]), data)
+            } else {
+                smbus_pec(addr, written, data)
+            };
+            if crc == *pec {
+                Ok(())
+            } else {
+                Err(Error::PecMismatch)
+            }
+        }
+        None => Err(Error::PecMismatch),
+    }
+}
+
+/// Lay out an SMBus block write in `buffer`, which holds `len` data bytes
+/// at its start. On success the buffer holds the command, the byte count,
+/// the data and, if `pec` is set, the PEC, and the total length to write is
+/// returned.
+fn smbus_block_write_frame(
+    addr: u8,
+    command: u8,
+    buffer: &mut [u8],
+    len: usize,
+    pec: bool,
+) -> Result<usize, Error> {
+    let total = len + 2 + pec as usize;
+    if len > SMBUS_BLOCK_MAX || total > buffer.len() {
+        return Err(Error::Overrun);
+    }
+    buffer.copy_within(0..len, 2);
+    buffer[0] = command;
+    buffer[1] = len as u8;
+    if pec {
+        buffer[len + 2] = smbus_pec(addr, &buffer[..len + 2], &[]);
+    }
+    Ok(total)
+}
+
+/// Extract the data of a completed SMBus block read of `command` from the
+/// device at `addr`, which was started with `smbus_block_read()`. The data
+/// is moved to the start of `buffer` and its length is returned. If `pec`
+/// is set the PEC sent by the device is checked as well.
+pub fn smbus_block_read_complete(
+    addr: u8,
+    command: u8,
+    buffer: &mut [u8],
+    pec: bool,
+) -> Result<usize, Error> {
+    let count = cmp::min(buffer[0] as usize, SMBUS_BLOCK_MAX);
+    if pec {
+        smbus_pec_verify(addr, &[command], &buffer[..count + 2])?;
+    }
+    buffer.copy_within(1..count + 1, 0);
+    Ok(count)
+}
+
+/// SMBus protocol operations built on any I2C master.
+///
+/// Block transfers are framed in software, so they work on controllers
+/// without SMBus support. All operations complete through the master's
+/// `I2CHwMasterClient::command_complete()`. A client that reads with a PEC
+/// must check it itself, with `smbus_pec_verify()` or
+/// `smbus_block_read_complete()`, since the hardware is not aware of it.
+pub trait SMBusMasterOps: I2CMaster {
+    /// Perform an SMBus quick command, which only transfers the R/W bit of
+    /// the address byte. Not every controller can issue a transfer without
+    /// data bytes; those report `Error::NotSupported` or send a single byte.
+    fn smbus_quick_command(
+        &self,
+        addr: u8,
+        read: bool,
+        buffer: &'static mut [u8],
+    ) -> Result<(), (Error, &'static mut [u8])> {
+        if read {
+            self.read(addr, buffer, 0)
+        } else {
+            self.write(addr, buffer, 0)
+        }
+    }
+
+    /// Write the first `len` bytes of `buffer` as an SMBus block write to
+    /// `command`, optionally followed by a PEC. The buffer must have room
+    /// for the command, byte count and PEC in addition to the data.
+    fn smbus_block_write(
+        &self,
+        addr: u8,
+        command: u8,
+        buffer: &'static mut [u8],
+        len: usize,
+        pec: bool,
+    ) -> Result<(), (Error, &'static mut [u8])> {
+        match smbus_block_write_frame(addr, command, buffer, len, pec) {
+            Ok(total) => self.write(addr, buffer, total),
+            Err(e) => Err((e, buffer)),
+        }
+    }
+
+    /// Start an SMBus block read from `command`. Because the byte count is
+    /// only known once it is received, the largest possible block is read,
+    /// so the buffer must hold `SMBUS_BLOCK_MAX + 2` bytes. Once the read
+    /// completes, pass the buffer to `smbus_block_read_complete()`.
+    fn smbus_block_read(
+        &self,
+        addr: u8,
+        command: u8,
+        buffer: &'static mut [u8],
+        pec: bool,
+    ) -> Result<(), (Error, &'static mut [u8])> {
+        let read_len = SMBUS_BLOCK_MAX + 1 + pec as usize;
+        if buffer.len() < read_len {
+            return Err((Error::Overrun, buffer));
+        }
+        buffer[0] = command;
+        self.write_read(addr, buffer, 1, read_len)
+    }
+
+    /// Write the first `len` bytes of `buffer` followed by their PEC, as
+    /// in the SMBus write byte and write word protocols. The buffer must
+    /// have room for the PEC.
+    fn smbus_write_pec(
+        &self,
+        addr: u8,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (Error, &'static mut [u8])> {
+        if len >= buffer.len() {
+            return Err((Error::Overrun, buffer));
+        }
+        buffer[len] = smbus_pec(addr, &buffer[..len], &[]);
+        self.write(addr, buffer, len + 1)
+    }
+}
+
+impl<T: I2CMaster + ?Sized> SMBusMasterOps for T {}
+
+/// SMBus protocol operations for an `I2CDevice`, such as a device on a
+/// virtualized bus. These match `SMBusMasterOps`, but use the address of
+/// the device.
+pub trait SMBusDeviceOps: I2CDevice {
+    /// The 7-bit address of the device, which SMBus includes in the PEC.
+    fn smbus_address(&self) -> u8;
+
+    /// Perform an SMBus quick command, see
+    /// `SMBusMasterOps::smbus_quick_command()`.
+    fn smbus_quick_command(
+        &self,
+        read: bool,
+        buffer: &'static mut [u8],
+    ) -> Result<(), (Error, &'static mut [u8])> {
+        if read {
+            self.read(buffer, 0)
+        } else {
+            self.write(buffer, 0)
+        }
+    }
+
+    /// Perform an SMBus block write, see
+    /// `SMBusMasterOps::smbus_block_write()`.
+    fn smbus_block_write(
+        &self,
+        command: u8,
+        buffer: &'static mut [u8],
+        len: usize,
+        pec: bool,
+    ) -> Result<(), (Error, &'static mut [u8])> {
+        match smbus_block_write_frame(self.smbus_address(), command, buffer, len, pec) {
+            Ok(total) => self.write(buffer, total),
+            Err(e) => Err((e, buffer)),
+        }
+    }
+
+    /// Start an SMBus block read, see `SMBusMasterOps::smbus_block_read()`.
+    fn smbus_block_read(
+        &self,
+        command: u8,
+        buffer: &'static mut [u8],
+        pec: bool,
+    ) -> Result<(), (Error, &'static mut [u8])> {
+        let read_len = SMBUS_BLOCK_MAX + 1 + pec as usize;
+        if buffer.len() < read_len {
+            return Err((Error::Overrun, buffer));
+        }
+        buffer[0] = command;
+        self.write_read(buffer, 1, read_len)
+    }
+
+    /// Write bytes followed by their PEC, see
+    /// `SMBusMasterOps::smbus_write_pec()`.
+    fn smbus_write_pec(
+        &self,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (Error, &'static mut [u8])> {
+        if len >= buffer.len() {
+            return Err((Error::Overrun, buffer));
+        }
+        buffer[len] = smbus_pec(self.smbus_address(), &buffer[..len], &[]);
+        self.write(buffer, len + 1)
+    }
+}
+
 /// Client interface for I2CDevice implementations.
 pub trait I2CClient {
     /// Called when an I2C command completed. The `error` denotes whether the command completed
@@ -355,3 +609,46 @@ impl SMBusMaster for NoSMBus {
         Err((Error::NotSupported, buffer))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pec_check_value() {
+        assert_eq!(smbus_pec_update(0, b"123456789"), 0xf4);
+    }
+
+    #[test]
+    fn block_write_frame() {
+        let mut buffer = [1, 2, 3, 0, 0, 0];
+        assert_eq!(
+            smbus_block_write_frame(0x16, 0x44, &mut buffer, 3, true),
+            Ok(6)
+        );
+        assert_eq!(buffer[..5], [0x44, 3, 1, 2, 3]);
+        assert_eq!(buffer[5], smbus_pec(0x16, &buffer[..5], &[]));
+        assert_eq!(
+            smbus_block_write_frame(0x16, 0x44, &mut buffer, 4, true),
+            Err(Error::Overrun)
+        );
+    }
+
+    #[test]
+    fn block_read_complete() {
+        let mut buffer = [0; SMBUS_BLOCK_MAX + 2];
+        buffer[..3].copy_from_slice(&[2, 0xaa, 0xbb]);
+        buffer[3] = smbus_pec(0x0b, &[0x20], &buffer[..3]);
+        assert_eq!(
+            smbus_block_read_complete(0x0b, 0x20, &mut buffer, true),
+            Ok(2)
+        );
+        assert_eq!(buffer[..2], [0xaa, 0xbb]);
+
+        buffer[..4].copy_from_slice(&[2, 0xaa, 0xbb, 0]);
+        assert_eq!(
+            smbus_block_read_complete(0x0b, 0x20, &mut buffer, true),
+            Err(Error::PecMismatch)
+        );
+    }
+}
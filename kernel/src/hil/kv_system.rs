@@ -96,6 +96,34 @@ pub trait StoreClient<K: KeyType> {
     /// `result`: Nothing on success, 'ErrorCode' on error
     /// `key`: The key buffer
     fn delete_complete(&self, result: Result<(), ErrorCode>, key: &'static mut [u8]);
+
+    /// This callback is called when the next_key operation completes
+    ///
+    /// `result`: Nothing on success, 'ErrorCode' on error. `NOSUPPORT` if
+    ///           there are no more keys.
+    /// `key`: The hashed key that was found
+    /// `length`: The length of the value of the key
+    /// `position`: The position to continue listing the keys from
+    fn next_key_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: K,
+        length: usize,
+        position: usize,
+    );
+
+    /// This callback is called when the garbage_collect operation completes
+    ///
+    /// `result`: Nothing on success, 'ErrorCode' on error
+    fn garbage_collect_complete(&self, result: Result<(), ErrorCode>);
+
+    /// This callback is called when the usage operation completes
+    ///
+    /// `result`: Nothing on success, 'ErrorCode' on error
+    /// `live_bytes`: The number of bytes used by valid objects
+    /// `dead_bytes`: The number of bytes used by invalidated objects, which
+    ///               garbage collection can reclaim
+    fn usage_complete(&self, result: Result<(), ErrorCode>, live_bytes: usize, dead_bytes: usize);
 }
 
 /// Implement this trait and use `set_client()` in order to receive callbacks.
@@ -146,6 +174,27 @@ pub trait Client<K: KeyType> {
     ///
     /// `result`: Nothing on success, 'ErrorCode' on error
     fn garbage_collect_complete(&self, result: Result<(), ErrorCode>);
+
+    /// This callback is called when the next_key operation completes
+    ///
+    /// `result`: Nothing on success, 'ErrorCode' on error. `NOSUPPORT` if
+    ///           there are no more keys.
+    /// `key`: The key buffer, which holds the found key on success
+    /// `position`: The position to continue listing the keys from
+    fn next_key_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: &'static mut K,
+        position: usize,
+    );
+
+    /// This callback is called when the usage operation completes
+    ///
+    /// `result`: Nothing on success, 'ErrorCode' on error
+    /// `live_bytes`: The number of bytes used by valid objects
+    /// `dead_bytes`: The number of bytes used by invalidated objects, which
+    ///               garbage collection can reclaim
+    fn usage_complete(&self, result: Result<(), ErrorCode>, live_bytes: usize, dead_bytes: usize);
 }
 
 pub trait KVSystem<'a> {
@@ -259,4 +308,35 @@ pub trait KVSystem<'a> {
     ///    `INVAL`: An invalid parameter was passed
     ///    `NODEVICE`: No KV store was setup
     fn garbage_collect(&self) -> Result<usize, Result<(), ErrorCode>>;
+
+    /// Finds the next valid key, to list the keys that are stored
+    ///
+    /// `position`: Where to start looking. This is 0 for the first key, and
+    ///             the position passed to `next_key_complete()` for the
+    ///             next ones.
+    /// `key`: A buffer to store the found hashed key to.
+    ///
+    /// On success nothing will be returned.
+    /// On error the key and a `Result<(), ErrorCode>` will be returned.
+    ///
+    /// The possible `Result<(), ErrorCode>`s are:
+    ///    `BUSY`: An operation is already in progress
+    ///    `NODEVICE`: No KV store was setup
+    ///    `ENOSUPPORT`: There are no more keys.
+    fn next_key(
+        &self,
+        position: usize,
+        key: &'static mut Self::K,
+    ) -> Result<(), (&'static mut Self::K, Result<(), ErrorCode>)>;
+
+    /// Adds up how many bytes of the KV Store are used by valid and by
+    /// invalidated objects
+    ///
+    /// On success `usage_complete()` will be called.
+    ///
+    /// The possible `ErrorCode`s are:
+    ///    `BUSY`: An operation is already in progress
+    ///    `NODEVICE`: No KV store was setup
+    ///    `NOSUPPORT`: The KV store doesn't keep track of its usage
+    fn usage(&self) -> Result<(), ErrorCode>;
 }
@@ -0,0 +1,196 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Interface for LoRa radios.
+//!
+//! A `LoRaRadio` sends and receives raw LoRa packets: packets with an
+//! explicit header and a payload CRC, of up to `MAX_PAYLOAD_LEN` bytes. The
+//! radio knows nothing of LoRaWAN; a stack above it takes care of addressing,
+//! security and duty cycle limits.
+//!
+//! The modulation of packets is set with a `Config`, which takes effect once
+//! the radio is done with the packet it is sending, if any. Radios that
+//! receive packets with another modulation do not receive them at all.
+//!
+//! The radio is configured once `enable` is called. Packets passed to
+//! `transmit` before the radio is ready are sent once it is. While receiving
+//! is started, the radio receives whenever it is not transmitting.
+
+use crate::ErrorCode;
+
+/// The length of the longest payload.
+pub const MAX_PAYLOAD_LEN: usize = 255;
+
+/// The bandwidth of the signal.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Bandwidth {
+    Khz7_8,
+    Khz10_4,
+    Khz15_6,
+    Khz20_8,
+    Khz31_25,
+    Khz41_7,
+    Khz62_5,
+    Khz125,
+    Khz250,
+    Khz500,
+}
+
+impl Bandwidth {
+    /// The bandwidth in Hz, rounded down.
+    pub fn hz(&self) -> u32 {
+        match self {
+            Bandwidth::Khz7_8 => 7_800,
+            Bandwidth::Khz10_4 => 10_400,
+            Bandwidth::Khz15_6 => 15_600,
+            Bandwidth::Khz20_8 => 20_800,
+            Bandwidth::Khz31_25 => 31_250,
+            Bandwidth::Khz41_7 => 41_700,
+            Bandwidth::Khz62_5 => 62_500,
+            Bandwidth::Khz125 => 125_000,
+            Bandwidth::Khz250 => 250_000,
+            Bandwidth::Khz500 => 500_000,
+        }
+    }
+
+    /// The bandwidth whose value in Hz, rounded down, is `hz`.
+    pub fn from_hz(hz: u32) -> Option<Bandwidth> {
+        [
+            Bandwidth::Khz7_8,
+            Bandwidth::Khz10_4,
+            Bandwidth::Khz15_6,
+            Bandwidth::Khz20_8,
+            Bandwidth::Khz31_25,
+            Bandwidth::Khz41_7,
+            Bandwidth::Khz62_5,
+            Bandwidth::Khz125,
+            Bandwidth::Khz250,
+            Bandwidth::Khz500,
+        ]
+        .iter()
+        .find(|bandwidth| bandwidth.hz() == hz)
+        .copied()
+    }
+}
+
+/// The coding rate of the forward error correction, 4/5 to 4/8.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CodingRate {
+    Cr4_5,
+    Cr4_6,
+    Cr4_7,
+    Cr4_8,
+}
+
+/// The modulation and transmit settings of the radio.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Config {
+    /// The carrier frequency, in Hz.
+    pub frequency: u32,
+    /// The spreading factor, as the number of bits per symbol.
+    pub spreading_factor: u8,
+    pub bandwidth: Bandwidth,
+    pub coding_rate: CodingRate,
+    /// The transmit power, in dBm.
+    pub tx_power: i8,
+    /// The sync word, which separates networks: 0x12 for private networks,
+    /// 0x34 for public LoRaWAN networks.
+    pub sync_word: u8,
+    /// The length of the preamble, in symbols.
+    pub preamble_len: u16,
+    /// Whether the I and Q signals are inverted, as in the downlinks of
+    /// LoRaWAN gateways, so that devices do not receive each other.
+    pub invert_iq: bool,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            frequency: 868_100_000,
+            spreading_factor: 7,
+            bandwidth: Bandwidth::Khz125,
+            coding_rate: CodingRate::Cr4_5,
+            tx_power: 14,
+            sync_word: 0x12,
+            preamble_len: 8,
+            invert_iq: false,
+        }
+    }
+}
+
+/// The signal quality of a received packet.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct RxMetadata {
+    /// The signal strength of the packet, in dBm.
+    pub rssi: i16,
+    /// The signal to noise ratio of the packet, in dB.
+    pub snr: i8,
+}
+
+pub trait TxClient {
+    /// Called when a packet passed to `transmit` was sent, or could not be.
+    fn transmit_done(&self, buf: &'static mut [u8], result: Result<(), ErrorCode>);
+}
+
+pub trait RxClient {
+    /// Called with the receive buffer when a packet was received into it. The
+    /// radio receives the next packet once the client gives a buffer back
+    /// with `set_receive_buffer`. Packets with a bad CRC are passed with
+    /// `Err(FAIL)`.
+    fn receive(
+        &self,
+        buf: &'static mut [u8],
+        len: usize,
+        metadata: RxMetadata,
+        result: Result<(), ErrorCode>,
+    );
+}
+
+pub trait LoRaRadio<'a> {
+    fn set_transmit_client(&self, client: &'a dyn TxClient);
+
+    fn set_receive_client(&self, client: &'a dyn RxClient);
+
+    /// Set the buffer that the next packet is received into. Packets that
+    /// arrive while no buffer is set are dropped.
+    fn set_receive_buffer(&self, buf: &'static mut [u8]);
+
+    /// Configure the radio. The radio is ready asynchronously.
+    ///
+    /// Return values:
+    ///   - Ok(()): the radio is being configured.
+    ///   - Err(ALREADY): the radio is already enabled.
+    ///   - Err(BUSY): the radio cannot access the hardware.
+    fn enable(&self) -> Result<(), ErrorCode>;
+
+    fn get_config(&self) -> Config;
+
+    /// Change the modulation and transmit settings.
+    ///
+    /// Return values:
+    ///   - Ok(()): the settings will take effect.
+    ///   - Err(INVAL): the radio does not support a setting.
+    fn set_config(&self, config: Config) -> Result<(), ErrorCode>;
+
+    /// Send the first `len` bytes of `buf` as one packet. `transmit_done` is
+    /// called once the packet is sent, if `Ok(())` is returned.
+    ///
+    /// Return values:
+    ///   - Ok(()): the packet will be sent.
+    ///   - Err(OFF): the radio is not enabled.
+    ///   - Err(BUSY): a packet is already being sent.
+    ///   - Err(SIZE): `len` is 0, longer than `MAX_PAYLOAD_LEN` or longer
+    ///     than `buf`.
+    fn transmit(
+        &self,
+        buf: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])>;
+
+    /// Start receiving packets, whenever the radio is not transmitting.
+    fn start_receive(&self) -> Result<(), ErrorCode>;
+
+    /// Stop receiving packets.
+    fn stop_receive(&self) -> Result<(), ErrorCode>;
+}
@@ -7,6 +7,7 @@
 pub mod adc;
 pub mod analog_comparator;
 pub mod ble_advertising;
+pub mod block_storage;
 pub mod bus8080;
 pub mod buzzer;
 pub mod can;
@@ -15,18 +16,22 @@ pub mod dac;
 pub mod digest;
 pub mod eic;
 pub mod entropy;
+pub mod ethernet;
 pub mod flash;
 pub mod gpio;
 pub mod gpio_async;
 pub mod hasher;
+pub mod hw_debug;
 pub mod i2c;
 pub mod kv_system;
 pub mod led;
 pub mod log;
+pub mod lora;
 pub mod nonvolatile_storage;
 pub mod public_key_crypto;
 pub mod pwm;
 pub mod radio;
+pub mod reset;
 pub mod rng;
 pub mod screen;
 pub mod sensors;
@@ -38,6 +43,8 @@ pub mod touch;
 pub mod uart;
 pub mod usb;
 pub mod usb_hid;
+pub mod wifi;
+pub mod xip;
 
 /// Shared interface for configuring components.
 pub trait Controller {
@@ -0,0 +1,49 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Interface for verifying digital signatures.
+//!
+//! A verifier checks a signature over a hash with a public key it
+//! already holds, e.g. one supplied when it was created or imported
+//! with the `keys` interfaces. `HL` is the length of the hash and `SL`
+//! the length of the signature, in bytes.
+
+use crate::ErrorCode;
+
+/// Upcall from the `SignatureVerify` trait.
+pub trait ClientVerify<const HL: usize, const SL: usize> {
+    /// This callback is called when the verification is complete.
+    ///
+    /// `result` is `Ok(true)` if the signature is valid, `Ok(false)` if
+    /// it is not, and an error if the verification could not be done.
+    ///
+    /// The possible ErrorCodes are:
+    ///    - INVAL: The public key is not valid
+    ///    - FAIL: An internal failure
+    fn verification_done(
+        &self,
+        result: Result<bool, ErrorCode>,
+        hash: &'static mut [u8; HL],
+        signature: &'static mut [u8; SL],
+    );
+}
+
+pub trait SignatureVerify<'a, const HL: usize, const SL: usize> {
+    /// Set the `ClientVerify` client to be called on completion.
+    fn set_verify_client(&'a self, client: &'a dyn ClientVerify<HL, SL>);
+
+    /// Verify that `signature` is a valid signature of `hash` with the
+    /// verifier's public key.
+    ///
+    /// On completion the `verification_done()` upcall will be scheduled.
+    ///
+    /// The possible ErrorCodes are:
+    ///    - BUSY: A verification is already ongoing
+    ///    - INVAL: The verifier has no valid public key
+    fn verify(
+        &'a self,
+        hash: &'static mut [u8; HL],
+        signature: &'static mut [u8; SL],
+    ) -> Result<(), (ErrorCode, &'static mut [u8; HL], &'static mut [u8; SL])>;
+}
@@ -6,6 +6,19 @@
 
 use crate::ErrorCode;
 
+/// How the edges of a PWM signal are placed within a period.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Mode {
+    /// The counter counts up and wraps, so every pulse starts at the
+    /// beginning of a period and only its trailing edge moves with the duty
+    /// cycle.
+    TrailingEdge,
+    /// The counter counts up and then down, so pulses are centered in the
+    /// period. This keeps the phase of outputs with different duty cycles
+    /// aligned, as motor drivers need.
+    PhaseCorrect,
+}
+
 /// PWM control for a single pin.
 pub trait Pwm {
     /// The chip-dependent type of a PWM pin.
@@ -48,6 +61,25 @@ pub trait Pwm {
     /// PWM0.start(pin, freq, dc);
     /// ```
     fn get_maximum_duty_cycle(&self) -> usize;
+
+    /// Select the modulation mode of a pin. The frequency and duty cycle
+    /// passed to `start()` keep their meaning in every mode. Hardware that
+    /// only supports `Mode::TrailingEdge` returns `NOSUPPORT` for other
+    /// modes.
+    fn set_mode(&self, _pin: &Self::Pin, mode: Mode) -> Result<(), ErrorCode> {
+        match mode {
+            Mode::TrailingEdge => Ok(()),
+            Mode::PhaseCorrect => Err(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    /// Return whether two pins can output signals with different settings
+    /// at the same time. Pins that share a counter, or peripherals that only
+    /// drive one signal at a time, are not independent. A pin is never
+    /// independent of itself.
+    fn is_independent(&self, _pin: &Self::Pin, _other: &Self::Pin) -> bool {
+        false
+    }
 }
 
 /// Higher-level PWM interface that restricts the user to a specific PWM pin.
@@ -67,4 +99,13 @@ pub trait PwmPin {
     /// Return an opaque number that represents a 100% duty cycle. This value
     /// Same as the `get_maximum_duty_cycle` function in the `Pwm` trait.
     fn get_maximum_duty_cycle(&self) -> usize;
+
+    /// Select the modulation mode. Same as the `set_mode` function in the
+    /// `Pwm` trait.
+    fn set_mode(&self, mode: Mode) -> Result<(), ErrorCode> {
+        match mode {
+            Mode::TrailingEdge => Ok(()),
+            Mode::PhaseCorrect => Err(ErrorCode::NOSUPPORT),
+        }
+    }
 }
@@ -35,6 +35,12 @@ pub trait PowerClient {
     fn changed(&self, on: bool);
 }
 
+pub trait EnergyDetectClient {
+    /// An energy detection on `channel` finished. The result is the
+    /// strongest energy measured, in dBm.
+    fn energy_detect_done(&self, channel: u8, result: Result<i8, ErrorCode>);
+}
+
 /// These constants are used for interacting with the SPI buffer, which contains
 /// a 1-byte SPI command, a 1-byte PHY header, and then the 802.15.4 frame. In
 /// theory, the number of extra bytes in front of the frame can depend on the
@@ -105,6 +111,24 @@ pub trait RadioConfig<'a> {
     fn set_pan(&self, id: u16);
     fn set_tx_power(&self, power: i8) -> Result<(), ErrorCode>;
     fn set_channel(&self, chan: u8) -> Result<(), ErrorCode>;
+
+    /// Measure the energy on `channel`, which need not be the configured
+    /// channel, and issue a callback to the energy detect client when done.
+    /// The radio does not receive while measuring, and returns to the
+    /// configured channel afterwards. Returns INVAL if `channel` is not
+    /// between 11 and 26, OFF if the radio is off, and BUSY if it is
+    /// transmitting or already measuring.
+    fn energy_detect(&self, channel: u8) -> Result<(), ErrorCode>;
+    fn set_energy_detect_client(&self, client: &'a dyn EnergyDetectClient);
+}
+
+/// Link quality of a received frame, as far as the radio measures it.
+#[derive(Copy, Clone, Default, Debug, PartialEq, Eq)]
+pub struct RxMetadata {
+    /// The received signal strength, in dBm
+    pub rssi: Option<i8>,
+    /// The link quality indicator, from 0 to 255
+    pub lqi: Option<u8>,
 }
 
 pub trait RadioData<'a> {
@@ -112,6 +136,10 @@ pub trait RadioData<'a> {
     fn set_receive_client(&self, client: &'a dyn RxClient, receive_buffer: &'static mut [u8]);
     fn set_receive_buffer(&self, receive_buffer: &'static mut [u8]);
 
+    /// The link quality of the last received frame. Only valid during the
+    /// `receive` callback for that frame.
+    fn get_rx_metadata(&self) -> RxMetadata;
+
     fn transmit(
         &self,
         spi_buf: &'static mut [u8],
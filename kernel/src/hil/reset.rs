@@ -0,0 +1,58 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Interface for resetting the chip, optionally into its bootloader.
+//!
+//! Besides a normal reset, many chips can enter a bootloader to update the
+//! firmware: a ROM bootloader (e.g. the STM32 system bootloader or the
+//! RP2040 USB mass storage bootloader), or a bootloader in flash that
+//! checks a flag kept across the reset (e.g. the nRF52 DFU bootloaders).
+//!
+//! `ResetCauses` reports why the chip was last reset, see
+//! `kernel::reboot_reason`.
+
+use crate::ErrorCode;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ResetMode {
+    /// Reset and boot the kernel again.
+    Normal,
+    /// Reset into the bootloader.
+    Bootloader,
+}
+
+pub trait Reset {
+    /// Whether the chip can reset in `mode`.
+    fn supports(&self, mode: ResetMode) -> bool;
+
+    /// Reset the chip in `mode`.
+    ///
+    /// Does not return if the reset succeeds. Otherwise, returns why it
+    /// failed:
+    /// - `NOSUPPORT`: The chip cannot reset in `mode`.
+    /// - `FAIL`: The reset was attempted but did not happen.
+    fn reset(&self, mode: ResetMode) -> ErrorCode;
+}
+
+/// Why the chip was last reset, as recorded by the hardware.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ResetCause {
+    /// The chip was powered on.
+    PowerOn,
+    /// The supply voltage dropped too low.
+    Brownout,
+    /// The reset pin was asserted.
+    ExternalPin,
+    /// The watchdog expired.
+    Watchdog,
+    /// Software requested the reset, e.g. through `Reset`.
+    Software,
+    /// The chip reports another cause, or none.
+    Other,
+}
+
+pub trait ResetCauses {
+    /// Why the chip was last reset.
+    fn last_reset_cause(&self) -> ResetCause;
+}
@@ -305,17 +305,27 @@ pub trait SpiMasterDevice {
 
 /// Trait for SPI peripherals (slaves) to receive callbacks when the
 /// corresponding controller (master) issues operations. A SPI operation
-/// begins with a callback of `chip_selected`. If the client has
-/// provided buffers with `SpiSlave::read_write_bytes`, these buffers
-/// are written from and read into until the operation completes or one
-/// of them fills, at which point a `SpiSlaveClient::read_write_done`
-/// callback is called. If the client needs to read/write more it
-/// can call `SpiSlave::read_write_bytes` again. Note that there is
-/// no notification when the chip select line goes high.
+/// begins with a callback of `chip_selected` and ends with a callback of
+/// `chip_deselected`. Hardware that can only detect one of the two edges
+/// of the chip select line only issues the corresponding callback.
+///
+/// If the client has provided buffers with `SpiSlave::read_write_bytes`,
+/// these buffers are written from and read into until one of them fills
+/// or the controller brings the chip select high, at which point a
+/// `SpiSlaveClient::read_write_done` callback is called. In the latter
+/// case, it is called before `chip_deselected`, with the number of bytes
+/// transferred so far. The buffers are then no longer armed: until the
+/// client calls `SpiSlave::read_write_bytes` again, e.g. from within
+/// `read_write_done`, the peripheral answers with the byte set by
+/// `set_write_byte` and drops received bytes.
 pub trait SpiSlaveClient {
     /// Notification that the chip select has been brought low.
     fn chip_selected(&self);
 
+    /// Notification that the chip select has been brought high, ending
+    /// the operation.
+    fn chip_deselected(&self);
+
     /// Callback issued when the controller completes an SPI operation
     /// to this peripheral. `write_buffer` and `read_buffer` are
     /// the values passed in the previous call to
@@ -367,6 +377,10 @@ pub trait SpiSlave {
     ///   - A `Some` write buffer is written.
     ///   - A `Some` read buffer is filled.
     ///   - `len` bytes are read/written
+    ///
+    /// The buffers stay armed across operations until the callback, so
+    /// they can be provided before the controller selects the chip.
+    ///
     /// Return values:
     ///   - Ok(()): the SPI bus will read/write the provided buffers on
     ///     the next SPI operation requested by the controller.
@@ -420,6 +434,10 @@ pub trait SpiSlaveDevice {
     /// Setup the SPI settings and speed of the bus.
     fn configure(&self, cpol: ClockPolarity, cpal: ClockPhase) -> Result<(), ErrorCode>;
 
+    /// Set a single byte to write in response to a read/write
+    /// operation from a controller while no buffers are armed.
+    fn set_write_byte(&self, write_byte: u8);
+
     /// Provide buffers for the peripheral to write from and read
     /// into when a controller performs a `read_write_bytes` operation.
     /// The device will issue a callback when one of four things occurs:
@@ -428,6 +446,10 @@ pub trait SpiSlaveDevice {
     ///   - A `Some` write buffer is written.
     ///   - A `Some` read buffer is filled.
     ///   - `len` bytes are read/written
+    ///
+    /// The buffers stay armed across operations until the callback, so
+    /// they can be provided before the controller selects the chip.
+    ///
     /// Return values:
     ///   - Ok(()): the SPI bus will read/write the provided buffers on
     ///     the next SPI operation requested by the controller.
@@ -121,6 +121,10 @@ pub trait CCMClient {
 
 pub const CCM_NONCE_LENGTH: usize = 13;
 
+/// The shortest nonce allowed by CCM (RFC 3610), which leaves 8 bytes for the
+/// message length.
+pub const CCM_MIN_NONCE_LENGTH: usize = 7;
+
 pub trait AES128CCM<'a> {
     /// Set the client instance which will receive `crypt_done()` callbacks
     fn set_client(&'a self, client: &'a dyn CCMClient);
@@ -128,7 +132,10 @@ pub trait AES128CCM<'a> {
     /// Set the key to be used for CCM encryption
     fn set_key(&self, key: &[u8]) -> Result<(), ErrorCode>;
 
-    /// Set the nonce (length NONCE_LENGTH) to be used for CCM encryption
+    /// Set the nonce to be used for CCM encryption. Nonces are
+    /// `CCM_NONCE_LENGTH` bytes long, as in IEEE 802.15.4; implementations
+    /// may also accept shorter nonces down to `CCM_MIN_NONCE_LENGTH` bytes,
+    /// such as the 12-byte nonces of TLS (RFC 6655).
     fn set_nonce(&self, nonce: &[u8]) -> Result<(), ErrorCode>;
 
     /// Try to begin the encryption/decryption process
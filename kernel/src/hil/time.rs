@@ -35,6 +35,14 @@ pub trait Ticks: Clone + Copy + From<u32> + fmt::Debug + Ord + PartialOrd + Eq {
     /// are 32 bits.
     fn into_u32(self) -> u32;
 
+    /// Converts the type into a `u64`, filling the higher bits with 0.
+    /// Lets users extend narrower counters to 64 bits in software. The
+    /// default implementation goes through `into_u32`, so types wider than
+    /// 32 bits must override it.
+    fn into_u64(self) -> u64 {
+        self.into_u32() as u64
+    }
+
     /// Add two values, wrapping around on overflow using standard
     /// unsigned arithmetic.
     fn wrapping_add(self, other: Self) -> Self;
@@ -257,6 +265,24 @@ pub trait Alarm<'a>: Time {
     fn minimum_dt(&self) -> Self::Ticks;
 }
 
+/// Interface for an alarm that can hand its pending expiration over to a
+/// low-power clock while the chip sleeps, so that the high-frequency clock
+/// does not need to keep running during long idle periods. It is called
+/// by the chip's `sleep` implementation, with interrupts disabled.
+pub trait LowPowerHandover {
+    /// Called right before the chip goes to sleep. The implementation may
+    /// stop its high-frequency counter and arm the low-power clock for the
+    /// pending expiration instead. Returns whether the alarm migrated to
+    /// the low-power clock.
+    fn prepare_sleep(&self) -> bool;
+
+    /// Called right after the chip wakes up, for whatever reason. If the
+    /// alarm migrated in `prepare_sleep`, it accounts for the time spent
+    /// asleep, restarts the high-frequency counter and re-arms any pending
+    /// expiration on it.
+    fn wakeup(&self);
+}
+
 /// Callback handler for when a timer fires.
 pub trait TimerClient {
     fn timer(&self);
@@ -672,6 +698,10 @@ impl Ticks for Ticks64 {
         self.0 as u32
     }
 
+    fn into_u64(self) -> u64 {
+        self.0
+    }
+
     fn wrapping_add(self, other: Self) -> Self {
         Ticks64(self.0.wrapping_add(other.0))
     }
@@ -823,6 +853,14 @@ mod tests {
         assert_eq!(t.into_u32(), 0);
     }
 
+    #[test]
+    fn test_into_u64() {
+        assert_eq!(Ticks16::from(0xFFFFu32).into_u64(), 0xFFFF);
+        assert_eq!(Ticks24::from(0x00FF_FFFFu32).into_u64(), 0x00FF_FFFF);
+        assert_eq!(Ticks32::from(0xFFFF_FFFFu32).into_u64(), 0xFFFF_FFFF);
+        assert_eq!(Ticks64::from(1u64 << 40).into_u64(), 1u64 << 40);
+    }
+
     struct Test1KHz24();
     impl Time for Test1KHz24 {
         type Frequency = Freq1KHz;
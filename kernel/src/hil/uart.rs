@@ -329,3 +329,29 @@ pub trait ReceiveAdvanced<'a>: Receive<'a> {
         interbyte_timeout: u8,
     ) -> Result<(), (ErrorCode, &'static mut [u8])>;
 }
+
+/// Trait for UARTs that transmit large buffers with DMA, at the cost of a
+/// single interrupt for the whole buffer instead of one per byte or FIFO.
+///
+/// Users that can gather data into one large buffer, such as the UART mux
+/// combining the output of several devices, use this to transmit it in one
+/// go.
+pub trait TransmitAdvanced<'a>: Transmit<'a> {
+    /// The longest transmission `transmit_buffer_dma()` accepts.
+    fn max_dma_len(&self) -> usize;
+
+    /// Transmit `tx_len` bytes of `tx_buffer` with DMA. The
+    /// `transmitted_buffer` callback of the `TransmitClient` is called once
+    /// all bytes were sent.
+    ///
+    /// Valid `ErrorCode` values are:
+    ///  - OFF: The underlying hardware is not available or has no DMA
+    ///         channel.
+    ///  - BUSY: the UART is already transmitting.
+    ///  - SIZE: `tx_len` is larger than the buffer or than `max_dma_len()`.
+    fn transmit_buffer_dma(
+        &self,
+        tx_buffer: &'static mut [u8],
+        tx_len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])>;
+}
@@ -0,0 +1,176 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Interface for Wi-Fi stations.
+//!
+//! A `Station` scans for networks, joins one of them, and opens TCP and UDP
+//! sockets to IPv4 hosts through it. The network stack runs on the Wi-Fi
+//! device, e.g. a module with AT firmware, so sockets only pass data through.
+//!
+//! The device does one operation at a time: `scan`, `join`, `leave`, `open`,
+//! `send` and `close` return BUSY while another one is in progress, until its
+//! callback. Data received on sockets and lost connections are reported at any
+//! time.
+
+use crate::ErrorCode;
+
+/// The length of the longest SSID.
+pub const MAX_SSID_LEN: usize = 32;
+
+/// The length of the longest WPA passphrase.
+pub const MAX_PASSPHRASE_LEN: usize = 63;
+
+/// The authentication a network requires.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Security {
+    Open,
+    Wep,
+    WpaPsk,
+    Wpa2Psk,
+    WpaWpa2Psk,
+    Wpa2Enterprise,
+    Wpa3Psk,
+    Wpa2Wpa3Psk,
+    Unknown,
+}
+
+/// A network found by a scan.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Network {
+    /// The SSID, valid up to `ssid_len`.
+    pub ssid: [u8; MAX_SSID_LEN],
+    pub ssid_len: usize,
+    /// The signal strength, in dBm.
+    pub rssi: i8,
+    pub channel: u8,
+    pub security: Security,
+}
+
+impl Network {
+    pub fn ssid(&self) -> &[u8] {
+        &self.ssid[..self.ssid_len]
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+pub trait ScanClient {
+    /// A scan found `network`. Called once for every network, before
+    /// `scan_done`.
+    fn network_found(&self, network: Network);
+
+    /// A `scan` finished.
+    fn scan_done(&self, result: Result<(), ErrorCode>);
+}
+
+pub trait StationClient {
+    /// A `join` finished: the station is associated with the network and
+    /// has an IP address, or failed to.
+    fn join_done(&self, result: Result<(), ErrorCode>);
+
+    /// The station left the network, because of `leave` or because it lost
+    /// the connection. All sockets are closed.
+    fn disconnected(&self);
+}
+
+pub trait SocketClient {
+    /// An `open` of `socket` finished.
+    fn opened(&self, socket: usize, result: Result<(), ErrorCode>);
+
+    /// The data passed to `send` on `socket` was sent, or could not be.
+    fn send_done(&self, socket: usize, buf: &'static mut [u8], result: Result<(), ErrorCode>);
+
+    /// `data` was received on `socket`. Long packets may be passed in
+    /// several calls.
+    fn received(&self, socket: usize, data: &[u8]);
+
+    /// `socket` was closed, by `close` or by the remote host.
+    fn closed(&self, socket: usize);
+}
+
+pub trait Station<'a> {
+    fn set_scan_client(&self, client: &'a dyn ScanClient);
+
+    fn set_station_client(&self, client: &'a dyn StationClient);
+
+    fn set_socket_client(&self, client: &'a dyn SocketClient);
+
+    /// Scan for networks.
+    ///
+    /// Return values:
+    ///   - Ok(()): networks are reported to the scan client.
+    ///   - Err(OFF): the device is not ready.
+    ///   - Err(BUSY): another operation is in progress.
+    fn scan(&self) -> Result<(), ErrorCode>;
+
+    /// Join the network `ssid`, with `passphrase` if it is not open. Both are
+    /// copied.
+    ///
+    /// Return values:
+    ///   - Ok(()): `join_done` is called once joined.
+    ///   - Err(OFF): the device is not ready.
+    ///   - Err(BUSY): another operation is in progress.
+    ///   - Err(SIZE): `ssid` is empty or longer than `MAX_SSID_LEN`, or
+    ///     `passphrase` is longer than `MAX_PASSPHRASE_LEN`.
+    fn join(&self, ssid: &[u8], passphrase: &[u8]) -> Result<(), ErrorCode>;
+
+    /// Leave the network, which calls `disconnected` once left.
+    ///
+    /// Return values:
+    ///   - Ok(()): the station is leaving the network.
+    ///   - Err(OFF): the station is not in a network.
+    ///   - Err(BUSY): another operation is in progress.
+    fn leave(&self) -> Result<(), ErrorCode>;
+
+    /// Whether the station is in a network.
+    fn is_joined(&self) -> bool;
+
+    /// The number of sockets, which are numbered from 0.
+    fn num_sockets(&self) -> usize;
+
+    /// Connect `socket` to `port` of the IPv4 host `address`. UDP sockets
+    /// only exchange datagrams with that host and port.
+    ///
+    /// Return values:
+    ///   - Ok(()): `opened` is called once connected.
+    ///   - Err(OFF): the station is not in a network.
+    ///   - Err(BUSY): another operation is in progress.
+    ///   - Err(INVAL): `socket` does not exist.
+    ///   - Err(ALREADY): `socket` is open.
+    fn open(
+        &self,
+        socket: usize,
+        protocol: Protocol,
+        address: [u8; 4],
+        port: u16,
+    ) -> Result<(), ErrorCode>;
+
+    /// Send the first `len` bytes of `buf` on `socket`, as one datagram on
+    /// UDP sockets.
+    ///
+    /// Return values:
+    ///   - Ok(()): `send_done` is called once sent.
+    ///   - Err(BUSY): another operation is in progress.
+    ///   - Err(INVAL): `socket` is not open.
+    ///   - Err(SIZE): `len` is 0, longer than `buf` or longer than the
+    ///     device can send at once.
+    fn send(
+        &self,
+        socket: usize,
+        buf: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])>;
+
+    /// Close `socket`, which calls `closed` once closed.
+    ///
+    /// Return values:
+    ///   - Ok(()): the socket is being closed.
+    ///   - Err(BUSY): another operation is in progress.
+    ///   - Err(INVAL): `socket` is not open.
+    fn close(&self, socket: usize) -> Result<(), ErrorCode>;
+}
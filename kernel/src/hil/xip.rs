@@ -0,0 +1,75 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Interface for external flash that code can execute in place from.
+//!
+//! Memory controllers such as the nRF52840 QSPI peripheral or the STM32 FSMC
+//! map external flash into the address space, so processes can run from it
+//! like from internal flash. This lets boards run apps that do not fit into
+//! internal flash. The board enables the mapping during setup and passes the
+//! mapped flash to the process loader, e.g.:
+//!
+//! ```rust,ignore
+//! let external_flash = qspi.enable(XipConfig {
+//!     size: 8 * 1024 * 1024,
+//!     clock_hz: 32_000_000,
+//!     read_mode: ReadMode::Quad,
+//!     latency_cycles: 0,
+//!     cache: true,
+//! })?;
+//! kernel::process::load_processes_from_regions(
+//!     board_kernel,
+//!     chip,
+//!     &[internal_app_flash, external_flash],
+//!     ...
+//! )
+//! ```
+//!
+//! Fetches from external flash are much slower than from internal flash, so
+//! controllers that can cache them should.
+
+use crate::ErrorCode;
+
+/// Data lines used to read from the flash.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ReadMode {
+    Single,
+    Dual,
+    Quad,
+}
+
+/// Timing and caching of execute-in-place accesses.
+#[derive(Copy, Clone, Debug)]
+pub struct XipConfig {
+    /// Size of the external flash in bytes.
+    pub size: usize,
+    /// Highest clock frequency of the flash interface in Hz. The controller
+    /// uses the highest frequency it supports that is not above it.
+    pub clock_hz: u32,
+    pub read_mode: ReadMode,
+    /// Additional latency of an access, in controller specific cycles, e.g.
+    /// wait states or a sampling delay.
+    pub latency_cycles: u8,
+    /// Whether code fetched from the flash is cached.
+    pub cache: bool,
+}
+
+/// A memory controller that can map external flash for execution in place.
+pub trait ExecuteInPlace {
+    /// Configure the controller with `config` and map the external flash.
+    /// Returns the mapped flash.
+    ///
+    /// The possible ErrorCodes are:
+    ///    - INVAL: the controller does not support the configuration
+    ///    - SIZE: the flash is larger than the address range the controller
+    ///      can map
+    ///    - FAIL: the controller did not become ready
+    fn enable(&self, config: XipConfig) -> Result<&'static [u8], ErrorCode>;
+
+    /// Enable or disable caching of code fetched from the mapped flash.
+    ///
+    /// The possible ErrorCodes are:
+    ///    - NOSUPPORT: the controller cannot cache fetches
+    fn set_cache(&self, enabled: bool) -> Result<(), ErrorCode>;
+}
@@ -147,6 +147,46 @@ impl KernelInfo {
         (used, number_of_grants)
     }
 
+    /// Calls `f` with the driver number and the number of bytes allocated for
+    /// each grant this app has allocated.
+    pub fn app_grant_allocations<F: FnMut(usize, usize)>(
+        &self,
+        app: ProcessId,
+        _capability: &dyn ProcessManagementCapability,
+        mut f: F,
+    ) {
+        let number_of_grants = self.kernel.get_grant_count_and_finalize();
+        self.kernel.process_map_or((), app, |process| {
+            for grant_num in 0..number_of_grants {
+                if let Some((driver_num, size)) = process.grant_allocation(grant_num) {
+                    f(driver_num, size);
+                }
+            }
+        });
+    }
+
+    /// Returns how the grant region of this app is used, or `None` if the app
+    /// does not exist.
+    pub fn app_grant_stats(
+        &self,
+        app: ProcessId,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> Option<process::GrantStats> {
+        self.kernel
+            .process_map_or(None, app, |process| Some(process.debug_grant_stats()))
+    }
+
+    /// Returns the most memory the stack and heap of this app have used, or
+    /// `None` if the app does not exist.
+    pub fn app_memory_high_water(
+        &self,
+        app: ProcessId,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> Option<process::MemoryHighWater> {
+        self.kernel
+            .process_map_or(None, app, |process| Some(process.debug_memory_high_water()))
+    }
+
     /// Returns the total number of times all processes have exceeded
     /// their timeslices.
     pub fn timeslice_expirations(&self, _capability: &dyn ProcessManagementCapability) -> usize {
@@ -6,6 +6,19 @@
 //!
 //! This is a special syscall driver that allows userspace applications to
 //! share memory.
+//!
+//! Clients find services either by the package name of the service process,
+//! or by a service name and interface version the service registered. A
+//! version is a `u32` with the major version in the upper 16 bits and the
+//! minor version in the lower 16 bits. A service is compatible with the
+//! version a client asks for if the major versions are equal and the minor
+//! version of the service is at least the one asked for, so services can add
+//! to their interface without breaking existing clients.
+//!
+//! A process that notifies another process can donate the rest of its
+//! timeslice to it. Once the notifying process yields, the notified process
+//! runs right away instead of waiting for its turn in the scheduler, so a
+//! request and its response do not each add a scheduling round of latency.
 
 use crate::capabilities::MemoryAllocationCapability;
 use crate::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
@@ -37,9 +50,33 @@ pub enum IPCUpcallType {
     Client,
 }
 
+/// Longest service name a process can register, in bytes.
+pub const SERVICE_NAME_LEN: usize = 32;
+
+/// A service name and interface version registered by a process.
+struct Service {
+    name: [u8; SERVICE_NAME_LEN],
+    len: usize,
+    version: u32,
+}
+
+impl Service {
+    fn name(&self) -> &[u8] {
+        &self.name[..self.len]
+    }
+}
+
+/// Whether a service offering interface version `offered` can serve a client
+/// asking for version `required`.
+fn version_compatible(offered: u32, required: u32) -> bool {
+    offered >> 16 == required >> 16 && offered & 0xffff >= required & 0xffff
+}
+
 /// State that is stored in each process's grant region to support IPC.
 #[derive(Default)]
-struct IPCData;
+struct IPCData {
+    service: Option<Service>,
+}
 
 /// The IPC mechanism struct.
 pub struct IPC<const NUM_PROCS: u8> {
@@ -63,6 +100,86 @@ impl<const NUM_PROCS: u8> IPC<NUM_PROCS> {
         }
     }
 
+    /// Copy the service name the process shared with `allow_readonly`.
+    fn search_name(&self, processid: ProcessId) -> Result<Service, ErrorCode> {
+        self.data
+            .enter(processid, |_, kernel_data| {
+                kernel_data
+                    .get_readonly_processbuffer(ro_allow::SEARCH)
+                    .and_then(|search| {
+                        search.enter(|slice| {
+                            if slice.len() == 0 || slice.len() > SERVICE_NAME_LEN {
+                                return Err(ErrorCode::INVAL);
+                            }
+                            let mut name = [0; SERVICE_NAME_LEN];
+                            slice.copy_to_slice(&mut name[..slice.len()]);
+                            Ok(Service {
+                                name,
+                                len: slice.len(),
+                                version: 0,
+                            })
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::INVAL))
+            })
+            .unwrap_or(Err(ErrorCode::NOMEM))
+    }
+
+    /// Find the process that registered the service `name`, returning its
+    /// index and the interface version it offers.
+    fn find_service(&self, name: &[u8]) -> Option<(usize, u32)> {
+        let mut found = None;
+        for app in self.data.iter() {
+            let processid = app.processid();
+            app.enter(|data, _| {
+                if let Some(service) = &data.service {
+                    if service.name() == name {
+                        found = processid.index().map(|index| (index, service.version));
+                    }
+                }
+            });
+        }
+        found
+    }
+
+    /// Register the service named in the `allow_readonly` buffer with
+    /// interface `version` for `processid`.
+    fn register_service(&self, processid: ProcessId, version: usize) -> CommandReturn {
+        let mut service = match self.search_name(processid) {
+            Ok(service) => service,
+            Err(e) => return CommandReturn::failure(e),
+        };
+        service.version = version as u32;
+        match self.find_service(service.name()) {
+            Some((index, _)) if Some(index) != processid.index() => {
+                CommandReturn::failure(ErrorCode::BUSY)
+            }
+            _ => self
+                .data
+                .enter(processid, |data, _| {
+                    data.service = Some(service);
+                    CommandReturn::success()
+                })
+                .unwrap_or(CommandReturn::failure(ErrorCode::NOMEM)),
+        }
+    }
+
+    /// Find the service named in the `allow_readonly` buffer that is
+    /// compatible with interface version `required`.
+    fn discover_service(&self, processid: ProcessId, required: usize) -> CommandReturn {
+        let search = match self.search_name(processid) {
+            Ok(search) => search,
+            Err(e) => return CommandReturn::failure(e),
+        };
+        match self.find_service(search.name()) {
+            Some((index, version)) if version_compatible(version, required as u32) => {
+                CommandReturn::success_u32_u32(index as u32, version)
+            }
+            Some(_) => CommandReturn::failure(ErrorCode::NOSUPPORT),
+            None => CommandReturn::failure(ErrorCode::NODEVICE),
+        }
+    }
+
     /// Schedule an IPC upcall for a process. This is called by the main
     /// scheduler loop if an IPC task was queued for the process.
     pub(crate) unsafe fn schedule_upcall(
@@ -120,15 +237,25 @@ impl<const NUM_PROCS: u8> SyscallDriver for IPC<NUM_PROCS> {
     ///        service descriptor if the service is found, otherwise returns an error.
     /// - `2`: Notify a service previously discovered to have the service descriptor in
     ///        `target_id`. Returns an error if `target_id` refers to an invalid service or the
-    ///        notify fails to enqueue.
+    ///        notify fails to enqueue. If the second argument is non-zero, the rest of the
+    ///        timeslice of this process is donated to the service once this process yields.
     /// - `3`: Notify a client with descriptor `target_id`, typically in response to a previous
     ///        notify from the client. Returns an error if `target_id` refers to an invalid client
-    ///        or the notify fails to enqueue.
+    ///        or the notify fails to enqueue. The second argument donates the timeslice as for
+    ///        `2`.
+    /// - `4`: Register the service named in the `allow_readonly` buffer with interface version
+    ///        `target_id`. Returns `BUSY` if another process registered the name, and `INVAL` if
+    ///        the name is empty or longer than `SERVICE_NAME_LEN` bytes.
+    /// - `5`: Discover the service named in the `allow_readonly` buffer that is compatible with
+    ///        interface version `target_id`. Returns the service descriptor and the version the
+    ///        service offers, `NOSUPPORT` if the service offers an incompatible version, or
+    ///        `NODEVICE` if no process registered the name.
+    /// - `6`: Unregister the service of this process.
     fn command(
         &self,
         command_number: usize,
         target_id: usize,
-        _: usize,
+        donate: usize,
         processid: ProcessId,
     ) -> CommandReturn {
         match command_number {
@@ -188,7 +315,12 @@ impl<const NUM_PROCS: u8> SyscallDriver for IPC<NUM_PROCS> {
                         |target| {
                             let ret = target.enqueue_task(process::Task::IPC((processid, cb_type)));
                             match ret {
-                                Ok(()) => CommandReturn::success(),
+                                Ok(()) => {
+                                    if donate != 0 {
+                                        self.data.kernel.donate_timeslice(processid, otherapp);
+                                    }
+                                    CommandReturn::success()
+                                }
                                 Err(e) => {
                                     // `enqueue_task` does not provide information on whether the
                                     // recipient has set a non-null callback. It only reports
@@ -221,7 +353,12 @@ impl<const NUM_PROCS: u8> SyscallDriver for IPC<NUM_PROCS> {
                         |target| {
                             let ret = target.enqueue_task(process::Task::IPC((processid, cb_type)));
                             match ret {
-                                Ok(()) => CommandReturn::success(),
+                                Ok(()) => {
+                                    if donate != 0 {
+                                        self.data.kernel.donate_timeslice(processid, otherapp);
+                                    }
+                                    CommandReturn::success()
+                                }
                                 Err(e) => {
                                     // `enqueue_task` does not provide information on whether the
                                     // recipient has set a non-null callback. It only reports
@@ -234,6 +371,15 @@ impl<const NUM_PROCS: u8> SyscallDriver for IPC<NUM_PROCS> {
                     )
                 })
             }
+            4 => self.register_service(processid, target_id),
+            5 => self.discover_service(processid, target_id),
+            6 => self
+                .data
+                .enter(processid, |data, _| {
+                    data.service = None;
+                    CommandReturn::success()
+                })
+                .unwrap_or(CommandReturn::failure(ErrorCode::NOMEM)),
             _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
         }
     }
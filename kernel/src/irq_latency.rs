@@ -0,0 +1,182 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Interrupt latency instrumentation.
+//!
+//! Tock handles interrupts in two halves: the top half, which the
+//! architecture runs when the interrupt fires, only marks the interrupt as
+//! pending, and the kernel loop later runs the bottom half in the chip's
+//! `service_pending_interrupts()`. Until then the kernel finishes what it is
+//! doing, e.g. a long deferred call of a capsule or a system call. This
+//! module records, per interrupt number, how long it took from the top half
+//! to the bottom half, so the impact of long-running kernel code can be
+//! measured during board bring-up.
+//!
+//! The instrumentation is only compiled in with the `irq_latency` feature
+//! of the kernel crate, as the top half has to take a timestamp. With the
+//! feature, the architecture timestamps each interrupt with a cycle counter
+//! and the chip calls `record()` before it runs a bottom half. The board
+//! registers an `IrqLatency` recorder with `set_recorder()`, and the process
+//! console prints it with the `irqlat` command.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let irq_latency = static_init!(
+//!     kernel::irq_latency::IrqLatency,
+//!     kernel::irq_latency::IrqLatency::new(
+//!         static_init!([IrqLatencyStats; 48], [IrqLatencyStats::new(); 48]),
+//!         64_000_000,
+//!     )
+//! );
+//! unsafe { kernel::irq_latency::set_recorder(irq_latency) };
+//! ```
+
+use core::ptr::addr_of;
+use core::ptr::addr_of_mut;
+
+use crate::config::CONFIG;
+use crate::utilities::cells::TakeCell;
+
+/// Whether the kernel is built with the `irq_latency` feature. The
+/// architecture only timestamps interrupts if it is.
+pub const ENABLED: bool = CONFIG.irq_latency;
+
+/// Number of histogram buckets. Bucket `i` counts latencies below
+/// `FIRST_BUCKET_CYCLES << i` cycles, the last bucket all longer ones.
+pub const NUM_BUCKETS: usize = 12;
+
+/// Upper bound of the first histogram bucket, in cycles.
+pub const FIRST_BUCKET_CYCLES: u32 = 64;
+
+/// Latency statistics of one interrupt number.
+#[derive(Copy, Clone)]
+pub struct IrqLatencyStats {
+    /// How many bottom halves were recorded.
+    pub count: u32,
+    /// Shortest latency in cycles.
+    pub min: u32,
+    /// Longest latency in cycles. `max - min` is the jitter.
+    pub max: u32,
+    /// Sum of all latencies in cycles, for the average.
+    pub sum: u64,
+    pub buckets: [u32; NUM_BUCKETS],
+}
+
+impl IrqLatencyStats {
+    pub const fn new() -> IrqLatencyStats {
+        IrqLatencyStats {
+            count: 0,
+            min: u32::MAX,
+            max: 0,
+            sum: 0,
+            buckets: [0; NUM_BUCKETS],
+        }
+    }
+
+    fn add(&mut self, cycles: u32) {
+        self.count = self.count.saturating_add(1);
+        self.min = self.min.min(cycles);
+        self.max = self.max.max(cycles);
+        self.sum = self.sum.saturating_add(cycles as u64);
+        let bucket = (0..NUM_BUCKETS - 1)
+            .find(|i| cycles < FIRST_BUCKET_CYCLES << i)
+            .unwrap_or(NUM_BUCKETS - 1);
+        self.buckets[bucket] = self.buckets[bucket].saturating_add(1);
+    }
+
+    /// Average latency in cycles, `0` if nothing was recorded.
+    pub fn average(&self) -> u32 {
+        match self.count {
+            0 => 0,
+            count => (self.sum / count as u64) as u32,
+        }
+    }
+}
+
+impl Default for IrqLatencyStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Latency statistics for the interrupt numbers below the length of its
+/// storage.
+pub struct IrqLatency {
+    stats: TakeCell<'static, [IrqLatencyStats]>,
+    /// Frequency of the cycle counter, to convert cycles to microseconds.
+    frequency_hz: u32,
+}
+
+impl IrqLatency {
+    pub fn new(stats: &'static mut [IrqLatencyStats], frequency_hz: u32) -> IrqLatency {
+        IrqLatency {
+            stats: TakeCell::new(stats),
+            frequency_hz,
+        }
+    }
+
+    /// Number of interrupts latency is recorded for.
+    pub fn num_irqs(&self) -> usize {
+        self.stats.map_or(0, |stats| stats.len())
+    }
+
+    pub fn frequency_hz(&self) -> u32 {
+        self.frequency_hz
+    }
+
+    /// Convert a number of cycles to microseconds.
+    pub fn cycles_to_us(&self, cycles: u32) -> u32 {
+        match self.frequency_hz / 1_000_000 {
+            0 => 0,
+            cycles_per_us => cycles / cycles_per_us,
+        }
+    }
+
+    /// Statistics of interrupt `irq`, `None` if it is not recorded.
+    pub fn stats(&self, irq: usize) -> Option<IrqLatencyStats> {
+        self.stats.map_or(None, |stats| stats.get(irq).copied())
+    }
+
+    /// Clear all statistics.
+    pub fn reset(&self) {
+        self.stats.map(|stats| {
+            stats
+                .iter_mut()
+                .for_each(|irq_stats| *irq_stats = IrqLatencyStats::new())
+        });
+    }
+
+    fn add(&self, irq: usize, cycles: u32) {
+        self.stats.map(|stats| {
+            if let Some(irq_stats) = stats.get_mut(irq) {
+                irq_stats.add(cycles);
+            }
+        });
+    }
+}
+
+static mut RECORDER: Option<&'static IrqLatency> = None;
+
+/// Record interrupt latency in `recorder`.
+pub unsafe fn set_recorder(recorder: &'static IrqLatency) {
+    *addr_of_mut!(RECORDER) = Some(recorder);
+}
+
+/// The registered recorder, if any.
+pub fn recorder() -> Option<&'static IrqLatency> {
+    // SAFETY: only set during board setup, before the kernel loop runs.
+    unsafe { *addr_of!(RECORDER) }
+}
+
+/// Record that the bottom half of interrupt `irq` runs `cycles` cycles
+/// after its top half. Called by the chip before the bottom half.
+pub fn record(irq: usize, cycles: u32) {
+    if ENABLED {
+        if let Some(recorder) = recorder() {
+            recorder.add(irq, cycles);
+        }
+    }
+}
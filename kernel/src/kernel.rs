@@ -21,7 +21,7 @@ use crate::errorcode::ErrorCode;
 use crate::grant::{AllowRoSize, AllowRwSize, Grant, UpcallSize};
 use crate::ipc;
 use crate::memop;
-use crate::platform::chip::Chip;
+use crate::platform::chip::{Chip, MultiCore};
 use crate::platform::mpu::MPU;
 use crate::platform::platform::ContextSwitchCallback;
 use crate::platform::platform::KernelResources;
@@ -29,15 +29,18 @@ use crate::platform::platform::{ProcessFault, SyscallDriverLookup, SyscallFilter
 use crate::platform::scheduler_timer::SchedulerTimer;
 use crate::platform::watchdog::WatchDog;
 use crate::process::{self, Process, ProcessId, ShortID, Task};
-use crate::process_checker::{self, CredentialsCheckingPolicy};
+use crate::process_checker::{
+    self, CredentialsCheckingPolicy, ReverificationClient, ShortIdAssigner,
+};
 use crate::process_loading::ProcessLoadError;
 use crate::scheduler::{Scheduler, SchedulingDecision};
 use crate::syscall::SyscallDriver;
 use crate::syscall::{ContextSwitchReason, SyscallReturn};
-use crate::syscall::{Syscall, YieldCall};
+use crate::syscall::{Syscall, SyscallTracer, YieldCall};
 use crate::syscall_driver::CommandReturn;
 use crate::upcall::{Upcall, UpcallId};
 use crate::utilities::cells::{NumericCellExt, OptionalCell};
+use crate::work_queue::Work;
 
 use tock_tbf::types::TbfFooterV2Credentials;
 use tock_tbf::types::TbfParseError;
@@ -70,6 +73,20 @@ pub struct Kernel {
     init_cap: KernelProcessInitCapability,
 
     checker: ProcessCheckerMachine,
+
+    /// Observer of all system calls, if tracing is enabled.
+    syscall_tracer: OptionalCell<&'static dyn SyscallTracer>,
+
+    /// A process that wants to donate the rest of its timeslice once it
+    /// yields, and the process it donates to.
+    timeslice_donation: OptionalCell<(ProcessId, ProcessId)>,
+
+    /// Bitmask of the cores that sleep, on chips with more than one core.
+    sleeping_cores: Cell<usize>,
+
+    /// A process got work while other cores slept, so they have to be woken
+    /// up to check whether it runs on one of them.
+    wake_sleeping_cores: Cell<bool>,
 }
 
 /// Enum used to inform scheduler why a process stopped executing (aka why
@@ -135,12 +152,59 @@ impl Kernel {
                 process: Cell::new(0),
                 footer: Cell::new(0),
                 policy: OptionalCell::empty(),
+                assigner: OptionalCell::empty(),
                 processes: processes,
                 approve_cap: KernelProcessApprovalCapability {},
+                checking: Cell::new(false),
+                rescan: Cell::new(false),
+                reverifying: OptionalCell::empty(),
+                reverification_client: OptionalCell::empty(),
             },
+            syscall_tracer: OptionalCell::empty(),
+            timeslice_donation: OptionalCell::empty(),
+            sleeping_cores: Cell::new(0),
+            wake_sleeping_cores: Cell::new(false),
         }
     }
 
+    /// Report every system call processes make, and its return value, to
+    /// `tracer`.
+    pub fn set_syscall_tracer(
+        &self,
+        tracer: &'static dyn SyscallTracer,
+        _capability: &dyn capabilities::ProcessManagementCapability,
+    ) {
+        self.syscall_tracer.set(tracer);
+    }
+
+    /// Donate the rest of the timeslice of the running process `from` to
+    /// `to` when `from` yields, e.g. after it notified an IPC service. `to`
+    /// then runs right away instead of waiting for its turn, and its time is
+    /// charged to `from`.
+    pub(crate) fn donate_timeslice(&self, from: ProcessId, to: ProcessId) {
+        self.timeslice_donation.set((from, to));
+    }
+
+    /// A process may have become ready to run. On chips with more than one
+    /// core, the cores that sleep are woken up at the end of the kernel loop
+    /// iteration, as the process may run on one of them.
+    pub(crate) fn process_work_added(&self) {
+        if self.sleeping_cores.get() != 0 {
+            self.wake_sleeping_cores.set(true);
+        }
+    }
+
+    /// Whether more than one process is ready to run, i.e. whether a running
+    /// process may have to be preempted to let another one run.
+    pub(crate) fn multiple_processes_ready(&self) -> bool {
+        self.processes
+            .iter()
+            .flatten()
+            .filter(|process| process.ready())
+            .nth(1)
+            .is_some()
+    }
+
     /// Helper function that moves all non-generic portions of process_map_or
     /// into a non-generic function to reduce code bloat from monomorphization.
     pub(crate) fn get_process(&self, processid: ProcessId) -> Option<&dyn process::Process> {
@@ -381,7 +445,7 @@ impl Kernel {
     pub fn hardfault_all_apps<C: capabilities::ProcessManagementCapability>(&self, _c: &C) {
         for p in self.processes.iter() {
             p.map(|process| {
-                process.set_fault_state();
+                process.set_fault_state(process::FaultReason::Requested);
             });
         }
     }
@@ -430,8 +494,16 @@ impl Kernel {
                     match scheduler.next() {
                         SchedulingDecision::RunProcess((processid, timeslice_us)) => {
                             self.process_map_or((), processid, |process| {
-                                let (reason, time_executed) =
+                                let result =
                                     self.do_process(resources, chip, process, ipc, timeslice_us);
+                                let (reason, time_executed) = self.run_donated_timeslices(
+                                    resources,
+                                    chip,
+                                    processid,
+                                    ipc,
+                                    timeslice_us,
+                                    result,
+                                );
                                 scheduler.result(reason, time_executed);
                             });
                         }
@@ -451,10 +523,17 @@ impl Kernel {
                                     // starts, the interrupt will not be
                                     // serviced and the chip will never wake
                                     // from sleep.
-                                    if !chip.has_pending_interrupts() && !DeferredCall::has_tasks()
+                                    if !chip.has_pending_interrupts()
+                                        && !DeferredCall::has_tasks()
+                                        && !Work::has_work()
                                     {
                                         resources.watchdog().suspend();
-                                        chip.sleep();
+                                        match chip.multi_core() {
+                                            Some(multi_core) => {
+                                                self.sleep_unlocked(chip, multi_core)
+                                            }
+                                            None => chip.sleep(),
+                                        }
                                         resources.watchdog().resume();
                                     }
                                 });
@@ -464,12 +543,99 @@ impl Kernel {
                 }
             }
         }
+
+        if let Some(multi_core) = chip.multi_core() {
+            if self.wake_sleeping_cores.take() {
+                let others = self.sleeping_cores.get() & !(1 << multi_core.current_core());
+                (0..multi_core.num_cores())
+                    .filter(|core| others & 1 << core != 0)
+                    .for_each(|core| multi_core.send_ipi(core));
+            }
+        }
+    }
+
+    /// Sleep without holding the kernel lock, so that other cores can run
+    /// kernel code meanwhile. The core is marked as sleeping before the lock
+    /// is released, so a core that then gives a process work sends an IPI.
+    fn sleep_unlocked<C: Chip>(&self, chip: &C, multi_core: &dyn MultiCore) {
+        let core = 1 << multi_core.current_core();
+        self.sleeping_cores.set(self.sleeping_cores.get() | core);
+        multi_core.release_kernel_lock();
+        chip.sleep();
+        multi_core.acquire_kernel_lock();
+        self.sleeping_cores.set(self.sleeping_cores.get() & !core);
+    }
+
+    /// Run the processes the rest of a timeslice was donated to, after the
+    /// process the scheduler chose yielded. A process that runs on a
+    /// donated timeslice can donate the rest again, e.g. an IPC service that
+    /// notifies its client with the response, so a request and its response
+    /// complete within one timeslice.
+    ///
+    /// `result` is what `do_process()` returned for the process the scheduler
+    /// chose. The time the recipients executed is charged to that process,
+    /// so the scheduler sees one process that executed for the whole time.
+    /// Returns the result with that time.
+    fn run_donated_timeslices<KR: KernelResources<C>, C: Chip, const NUM_PROCS: u8>(
+        &self,
+        resources: &KR,
+        chip: &C,
+        processid: ProcessId,
+        ipc: Option<&ipc::IPC<NUM_PROCS>>,
+        timeslice_us: Option<u32>,
+        result: (StoppedExecutingReason, Option<u32>),
+    ) -> (StoppedExecutingReason, Option<u32>) {
+        let (reason, mut total_executed) = result;
+        let mut donor = processid;
+        let mut donor_yielded = reason == StoppedExecutingReason::NoWorkLeft;
+        // A donation only applies to the process that just ran, so drop it
+        // in any case.
+        while let Some((from, to)) = self.timeslice_donation.take() {
+            if from != donor || !donor_yielded {
+                break;
+            }
+            // Kernel work still takes precedence.
+            if unsafe { resources.scheduler().do_kernel_work_now(chip) } {
+                break;
+            }
+            let remaining_us = match (timeslice_us, total_executed) {
+                (Some(timeslice), Some(executed)) => {
+                    let remaining = timeslice.saturating_sub(executed);
+                    if remaining <= MIN_QUANTA_THRESHOLD_US {
+                        break;
+                    }
+                    Some(remaining)
+                }
+                _ => None,
+            };
+            let result = self.process_map_or(None, to, |process| {
+                if process.ready() {
+                    Some(self.do_process(resources, chip, process, ipc, remaining_us))
+                } else {
+                    None
+                }
+            });
+            match result {
+                Some((recipient_reason, recipient_executed)) => {
+                    total_executed = total_executed
+                        .zip(recipient_executed)
+                        .map(|(donor_us, recipient_us)| donor_us + recipient_us);
+                    donor = to;
+                    donor_yielded = recipient_reason == StoppedExecutingReason::NoWorkLeft;
+                }
+                None => break,
+            }
+        }
+        (reason, total_executed)
     }
 
     /// Main loop of the OS.
     ///
     /// Most of the behavior of this loop is controlled by the `Scheduler`
     /// implementation in use.
+    ///
+    /// On chips with more than one core, each core calls this function with
+    /// its own resources, see `MultiCore`.
     pub fn kernel_loop<KR: KernelResources<C>, C: Chip, const NUM_PROCS: u8>(
         &self,
         resources: &KR,
@@ -477,11 +643,20 @@ impl Kernel {
         ipc: Option<&ipc::IPC<NUM_PROCS>>,
         capability: &dyn capabilities::MainLoopCapability,
     ) -> ! {
+        if let Some(multi_core) = chip.multi_core() {
+            multi_core.acquire_kernel_lock();
+        }
         resources.watchdog().setup();
         // Before we begin, verify that deferred calls were soundly setup.
         DeferredCall::verify_setup();
+        Work::verify_setup();
         loop {
             self.kernel_loop_operation(resources, chip, ipc, false, capability);
+            if let Some(multi_core) = chip.multi_core() {
+                // Let the other cores run between iterations.
+                multi_core.release_kernel_lock();
+                multi_core.acquire_kernel_lock();
+            }
         }
     }
 
@@ -593,7 +768,10 @@ impl Kernel {
                         .context_switch_hook(process);
                     process.setup_mpu();
                     chip.mpu().enable_app_mpu();
+                    process.debug_context_switched();
                     scheduler_timer.arm();
+                    // On chips with more than one core, the kernel lock is
+                    // held while the process runs, see `MultiCore`.
                     let context_switch_reason = process.switch_to();
                     scheduler_timer.disarm();
                     chip.mpu().disable_app_mpu();
@@ -610,7 +788,7 @@ impl Kernel {
                                 .is_err()
                             {
                                 // Let process deal with it as appropriate.
-                                process.set_fault_state();
+                                process.set_fault_state(process::FaultReason::Hardware);
                             }
                         }
                         Some(ContextSwitchReason::SyscallFired { syscall }) => {
@@ -633,7 +811,7 @@ impl Kernel {
                             // Something went wrong when switching to this
                             // process. Indicate this by putting it in a fault
                             // state.
-                            process.set_fault_state();
+                            process.set_fault_state(process::FaultReason::ContextSwitch);
                         }
                     }
                 }
@@ -755,6 +933,11 @@ impl Kernel {
             }
         });
 
+        // Account the time to the process for its CPU time statistics.
+        if let Some(us) = time_executed_us {
+            process.debug_cpu_time_used(us);
+        }
+
         // Reset the scheduler timer in case it unconditionally triggers
         // interrupts upon expiration. We do not want it to expire while the
         // chip is sleeping, for example.
@@ -763,6 +946,19 @@ impl Kernel {
         (return_reason, time_executed_us)
     }
 
+    /// Set the return value of the system call `syscall` of `process`, and
+    /// report it to the syscall tracer.
+    fn set_syscall_return_value(
+        &self,
+        process: &dyn process::Process,
+        syscall: &Syscall,
+        rval: SyscallReturn,
+    ) {
+        self.syscall_tracer
+            .map(|tracer| tracer.syscall_exit(process.processid(), syscall, &rval));
+        process.set_syscall_return_value(rval);
+    }
+
     /// Method to invoke a system call on a particular process. Applies the
     /// kernel system call filtering policy (if any). Handles `Yield` and
     /// `Exit`, dispatches `Memop` to `memop::memop`, and dispatches peripheral
@@ -777,6 +973,8 @@ impl Kernel {
     ) {
         // Hook for process debugging.
         process.debug_syscall_called(syscall);
+        self.syscall_tracer
+            .map(|tracer| tracer.syscall_entry(process.processid(), &syscall));
 
         // Enforce platform-specific syscall filtering here.
         //
@@ -806,7 +1004,11 @@ impl Kernel {
                 // Check all other syscalls for filtering.
                 if let Err(response) = resources.syscall_filter().filter_syscall(process, &syscall)
                 {
-                    process.set_syscall_return_value(SyscallReturn::Failure(response));
+                    self.set_syscall_return_value(
+                        process,
+                        &syscall,
+                        SyscallReturn::Failure(response),
+                    );
 
                     if config::CONFIG.trace_syscalls {
                         debug!(
@@ -835,7 +1037,7 @@ impl Kernel {
                         rval
                     );
                 }
-                process.set_syscall_return_value(rval);
+                self.set_syscall_return_value(process, &syscall, rval);
             }
             Syscall::Yield { which, address } => {
                 if config::CONFIG.trace_syscalls {
@@ -1025,7 +1227,7 @@ impl Kernel {
                             );
                         }
 
-                        process.set_syscall_return_value(rval);
+                        self.set_syscall_return_value(process, &syscall, rval);
                     }
                     Syscall::Command {
                         driver_number,
@@ -1051,7 +1253,7 @@ impl Kernel {
                                 res,
                             );
                         }
-                        process.set_syscall_return_value(res);
+                        self.set_syscall_return_value(process, &syscall, res);
                     }
                     Syscall::ReadWriteAllow {
                         driver_number,
@@ -1168,7 +1370,7 @@ impl Kernel {
                                 res
                             );
                         }
-                        process.set_syscall_return_value(res);
+                        self.set_syscall_return_value(process, &syscall, res);
                     }
                     Syscall::UserspaceReadableAllow {
                         driver_number,
@@ -1247,7 +1449,7 @@ impl Kernel {
                                 res
                             );
                         }
-                        process.set_syscall_return_value(res);
+                        self.set_syscall_return_value(process, &syscall, res);
                     }
                     Syscall::ReadOnlyAllow {
                         driver_number,
@@ -1365,7 +1567,7 @@ impl Kernel {
                             );
                         }
 
-                        process.set_syscall_return_value(res);
+                        self.set_syscall_return_value(process, &syscall, res);
                     }
                     Syscall::Yield { .. }
                     | Syscall::Exit { .. }
@@ -1386,7 +1588,11 @@ impl Kernel {
                 1 => process.try_restart(Some(completion_code as u32)),
                 // The process called an invalid variant of the Exit
                 // system call class.
-                _ => process.set_syscall_return_value(SyscallReturn::Failure(ErrorCode::NOSUPPORT)),
+                _ => self.set_syscall_return_value(
+                    process,
+                    &syscall,
+                    SyscallReturn::Failure(ErrorCode::NOSUPPORT),
+                ),
             },
         }
     }
@@ -1404,8 +1610,17 @@ pub struct ProcessCheckerMachine {
     process: Cell<usize>,
     footer: Cell<usize>,
     policy: OptionalCell<&'static dyn CredentialsCheckingPolicy<'static>>,
+    assigner: OptionalCell<&'static dyn ShortIdAssigner>,
     processes: &'static [Option<&'static dyn Process>],
     approve_cap: KernelProcessApprovalCapability,
+    /// A footer is being checked by the policy.
+    checking: Cell<bool>,
+    /// Processes were loaded while a footer was being checked; start over
+    /// once the pass over the array completes.
+    rescan: Cell<bool>,
+    /// The process whose credentials are being re-verified.
+    reverifying: OptionalCell<ProcessId>,
+    reverification_client: OptionalCell<&'static dyn ReverificationClient>,
 }
 
 #[derive(Debug)]
@@ -1431,13 +1646,23 @@ impl ProcessCheckerMachine {
             // checking a process, it just increments to the next
             // index. In case the array has None entries or the
             // process array changes under us, don't actually trust
-            // this value.
-            while proc_index < self.processes.len() && self.processes[proc_index].is_none() {
+            // this value. Processes that were already checked (e.g.
+            // before a process was loaded at runtime) are skipped too.
+            while proc_index < self.processes.len()
+                && self.processes[proc_index].map_or(true, |p| {
+                    p.get_state() != process::State::CredentialsUnchecked
+                })
+            {
                 proc_index = proc_index + 1;
                 self.process.set(proc_index);
                 self.footer.set(0);
             }
             if proc_index >= self.processes.len() {
+                if self.rescan.take() {
+                    self.process.set(0);
+                    self.footer.set(0);
+                    continue;
+                }
                 // No more processes to check.
                 return Ok(false);
             }
@@ -1458,6 +1683,7 @@ impl ProcessCheckerMachine {
             }
             match check_result {
                 FooterCheckResult::Checking => {
+                    self.checking.set(true);
                     return Ok(true);
                 }
                 FooterCheckResult::PastLastFooter => {
@@ -1487,7 +1713,7 @@ impl ProcessCheckerMachine {
                                     }
                                     p.mark_credentials_pass(
                                         None,
-                                        ShortID::LocallyUnique,
+                                        self.short_id(p, ShortID::LocallyUnique),
                                         &self.approve_cap,
                                     )
                                     .or(Err(ProcessLoadError::InternalError))?;
@@ -1518,6 +1744,99 @@ impl ProcessCheckerMachine {
     pub fn set_policy(&self, policy: &'static dyn CredentialsCheckingPolicy<'static>) {
         self.policy.replace(policy);
     }
+
+    /// Set the assigner that chooses the ShortIDs of approved processes.
+    /// Without one, processes get the ShortID derived from their credentials.
+    pub fn set_short_id_assigner(&self, assigner: &'static dyn ShortIdAssigner) {
+        self.assigner.replace(assigner);
+    }
+
+    /// The ShortID to give `process`, given the one derived from its
+    /// credentials.
+    pub(crate) fn short_id(&self, process: &dyn Process, proposed: ShortID) -> ShortID {
+        self.assigner
+            .map_or(proposed, |assigner| assigner.assign(process, proposed))
+    }
+
+    /// Whether a checking policy was set, i.e. the processes were loaded with
+    /// `load_and_check_processes`.
+    pub(crate) fn has_policy(&self) -> bool {
+        self.policy.is_some()
+    }
+
+    /// Set the client notified when a re-verification of credentials
+    /// completes.
+    pub fn set_reverification_client(&self, client: &'static dyn ReverificationClient) {
+        self.reverification_client.replace(client);
+    }
+
+    /// Check the binary of the running process `processid` against the
+    /// credentials it was approved with again, e.g. to detect corruption of
+    /// or tampering with its flash after it was loaded. The result is passed
+    /// to the reverification client.
+    ///
+    /// The possible ErrorCodes are:
+    ///    - INVAL: there is no process `processid`
+    ///    - NOSUPPORT: there is no checking policy, or the process was
+    ///      approved without credentials
+    ///    - BUSY: credentials are being checked
+    ///    - FAIL: the credentials changed since the process was approved
+    pub fn reverify_credentials(
+        &self,
+        processid: ProcessId,
+        _capability: &dyn capabilities::ProcessManagementCapability,
+    ) -> Result<(), ErrorCode> {
+        let process = self
+            .processes
+            .iter()
+            .flatten()
+            .find(|process| process.processid() == processid)
+            .ok_or(ErrorCode::INVAL)?;
+        let policy = self.policy.extract().ok_or(ErrorCode::NOSUPPORT)?;
+        let credentials = process.get_credentials().ok_or(ErrorCode::NOSUPPORT)?;
+        if self.checking.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        if !process.credentials_unchanged() {
+            return Err(ErrorCode::FAIL);
+        }
+
+        let addresses = process.get_addresses();
+        // SAFETY: the integrity region of a process is in its flash, which
+        // stays valid while the kernel runs.
+        let binary = unsafe {
+            slice::from_raw_parts(
+                addresses.flash_start as *const u8,
+                addresses.flash_integrity_end as usize - addresses.flash_start,
+            )
+        };
+        match policy.check_credentials(credentials, binary) {
+            Ok(()) => {
+                self.checking.set(true);
+                self.reverifying.set(processid);
+                Ok(())
+            }
+            // The policy accepted these credentials at approval, so it must
+            // still support them.
+            Err((ErrorCode::NOSUPPORT, _, _)) | Err((ErrorCode::ALREADY, _, _)) => {
+                Err(ErrorCode::FAIL)
+            }
+            Err((error, _, _)) => Err(error),
+        }
+    }
+
+    /// Check the processes that were loaded after boot. If a footer is
+    /// currently being checked, the new processes are checked after the
+    /// current pass completes.
+    pub(crate) fn check_new_processes(&self) -> Result<bool, ProcessLoadError> {
+        if self.checking.get() {
+            self.rescan.set(true);
+            return Ok(true);
+        }
+        self.process.set(0);
+        self.footer.set(0);
+        self.next()
+    }
 }
 
 // Returns whether a footer is being checked or not, and if not, why.
@@ -1641,12 +1960,27 @@ impl process_checker::Client<'static> for ProcessCheckerMachine {
         if config::CONFIG.debug_process_credentials {
             debug!("Checking: check_done gave result {:?}", result);
         }
+        self.checking.set(false);
+        if let Some(processid) = self.reverifying.take() {
+            let verified = result.map(|result| match result {
+                process_checker::CheckResult::Accept => true,
+                process_checker::CheckResult::Pass | process_checker::CheckResult::Reject => false,
+            });
+            self.reverification_client
+                .map(|client| client.reverification_done(processid, verified));
+            // Check processes that were loaded in the meantime.
+            if self.rescan.get() {
+                let _ = self.next();
+            }
+            return;
+        }
         match result {
             Ok(process_checker::CheckResult::Accept) => {
                 self.processes[self.process.get()].map(|p| {
                     let short_id = self.policy.map_or(ShortID::LocallyUnique, |policy| {
                         policy.to_short_id(&credentials)
                     });
+                    let short_id = self.short_id(p, short_id);
                     let _r =
                         p.mark_credentials_pass(Some(credentials), short_id, &self.approve_cap);
                 });
@@ -100,6 +100,7 @@ pub const KERNEL_MINOR_VERSION: u16 = 1;
 pub mod capabilities;
 pub mod collections;
 pub mod component;
+pub mod crash_report;
 pub mod debug;
 pub mod deferred_call;
 pub mod errorcode;
@@ -107,15 +108,18 @@ pub mod grant;
 pub mod hil;
 pub mod introspection;
 pub mod ipc;
+pub mod irq_latency;
 pub mod platform;
 pub mod process;
 pub mod process_checker;
 pub mod processbuffer;
+pub mod reboot_reason;
 pub mod scheduler;
 pub mod storage_permissions;
 pub mod syscall;
 pub mod upcall;
 pub mod utilities;
+pub mod work_queue;
 
 mod config;
 mod kernel;
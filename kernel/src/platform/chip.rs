@@ -68,6 +68,47 @@ pub trait Chip {
     /// the Display trait.
     /// Used by panic.
     unsafe fn print_state(&self, writer: &mut dyn Write);
+
+    /// Returns the interface to run processes on more than one core, if
+    /// the chip supports it. Single-core chips use the default.
+    fn multi_core(&self) -> Option<&dyn MultiCore> {
+        None
+    }
+}
+
+/// Interface for chips with more than one core that execute processes.
+///
+/// Every core runs `Kernel::kernel_loop()` with its own `KernelResources`,
+/// so each core has its own scheduler over the processes that run on it, and
+/// its own scheduler timer. A process must only be scheduled on one core.
+///
+/// The kernel is not reentrant, so all cores share one kernel lock. A core
+/// holds it for its whole kernel loop, including while it runs a process,
+/// and only releases it between iterations of the loop and while it sleeps.
+/// The lock has to be held while a process runs because the architecture
+/// code keeps the state of the running process in globals, e.g.
+/// `SYSCALL_FIRED` on Cortex-M. So only one core executes a process or
+/// kernel code at any time, and the cores take turns after every iteration
+/// of their kernel loops.
+pub trait MultiCore {
+    /// Number of cores that run a kernel loop.
+    fn num_cores(&self) -> usize;
+
+    /// Index of the core that calls this function, below `num_cores()`.
+    fn current_core(&self) -> usize;
+
+    /// Spin until the current core holds the kernel lock. This must be a
+    /// memory barrier, so that the core sees all writes of the core that
+    /// released the lock.
+    fn acquire_kernel_lock(&self);
+
+    /// Release the kernel lock. If another core waits for it, this must not
+    /// return before that core holds the lock, so that a core that acquires
+    /// it again right away does not starve the others.
+    fn release_kernel_lock(&self);
+
+    /// Interrupt `core`, to wake it up from sleep.
+    fn send_ipi(&self, core: usize);
 }
 
 /// Interface for handling interrupts on a hardware chip.
@@ -119,6 +160,30 @@ pub trait ClockInterface {
     fn disable(&self);
 }
 
+/// Clock state of one peripheral, as reported by `ClockIntrospection`.
+#[derive(Copy, Clone, Debug)]
+pub struct PeripheralClockState {
+    /// Name of the peripheral, as in the chip's datasheet.
+    pub name: &'static str,
+    /// Whether the peripheral's clock is running.
+    pub enabled: bool,
+    /// Frequency of the peripheral's clock in Hz, if known.
+    pub frequency: Option<u32>,
+    /// The driver that owns the peripheral.
+    pub owner: &'static str,
+}
+
+/// Lists the clock state of a chip's peripherals, to debug power
+/// consumption without reading clock registers by hand.
+pub trait ClockIntrospection {
+    /// Number of peripherals that are reported.
+    fn peripheral_count(&self) -> usize;
+
+    /// Clock state of peripheral `index`, or `None` if `index` is out of
+    /// range.
+    fn peripheral_clock(&self, index: usize) -> Option<PeripheralClockState>;
+}
+
 /// Helper struct for interfaces that expect clocks, but have no clock control.
 pub struct NoClockControl {}
 impl ClockInterface for NoClockControl {
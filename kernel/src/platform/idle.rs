@@ -0,0 +1,221 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Arbitration of how deeply the chip may sleep when the kernel is idle.
+//!
+//! When there is nothing to run, the kernel calls `Chip::sleep()`. Which
+//! sleep state the chip can enter depends on the rest of the system: a
+//! pending alarm must fire on time, so the chip cannot enter a state that
+//! takes longer to wake from than the time left until the alarm, and a DMA
+//! transfer or a timer clocked from a high-speed clock needs that clock to
+//! keep running.
+//!
+//! Each such constraint is reported by an `IdleSource` as a `SleepBudget`:
+//! the deepest `SleepDepth` allowed and the time by which the chip must be
+//! awake again. An `IdleArbiter` combines the budgets of all sources of a
+//! board, and chips that support it (see, e.g., `set_idle_arbiter()` on
+//! the SAM4L, nRF52 and STM32F4 chips) query it in `sleep()` and pick the
+//! deepest of their sleep states that fits with `SleepBudget::choose()`.
+//!
+//! Alarm muxes (`capsules_core::virtualizers::virtual_alarm::MuxAlarm`)
+//! are idle sources. Drivers with outstanding activity that chip-specific
+//! checks do not cover can use an `ActivityTracker`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let dma_activity = static_init!(ActivityTracker, ActivityTracker::new());
+//! let idle_arbiter = static_init!(
+//!     IdleArbiter<'static>,
+//!     IdleArbiter::new(static_init!(
+//!         [&'static dyn IdleSource; 2],
+//!         [mux_alarm, dma_activity]
+//!     ))
+//! );
+//! chip.set_idle_arbiter(idle_arbiter);
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+
+/// How deeply the chip may sleep. Deeper states compare greater.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SleepDepth {
+    /// Only the CPU clock stops, all peripherals keep running.
+    Shallow,
+    /// High-speed clocks may stop. Only peripherals running from
+    /// low-frequency clocks, such as real-time counters, and wakeup
+    /// interrupts keep working.
+    Deep,
+}
+
+/// Constraints on the sleep state the chip enters.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SleepBudget {
+    /// Deepest sleep allowed.
+    pub depth: SleepDepth,
+    /// Time within which the chip must be awake again, e.g. for the next
+    /// alarm, or `None` if no wakeup is scheduled.
+    pub wake_latency_us: Option<u32>,
+}
+
+impl SleepBudget {
+    /// The budget when nothing constrains sleep.
+    pub const UNCONSTRAINED: SleepBudget = SleepBudget {
+        depth: SleepDepth::Deep,
+        wake_latency_us: None,
+    };
+
+    /// The stricter of this budget and `other`, for each constraint.
+    pub fn restrict(self, other: SleepBudget) -> SleepBudget {
+        SleepBudget {
+            depth: cmp::min(self.depth, other.depth),
+            wake_latency_us: match (self.wake_latency_us, other.wake_latency_us) {
+                (Some(a), Some(b)) => Some(cmp::min(a, b)),
+                (a, None) => a,
+                (None, b) => b,
+            },
+        }
+    }
+
+    /// Whether a sleep state of `depth`, taking `wake_latency_us` to wake
+    /// from, fits in this budget.
+    pub fn allows(&self, depth: SleepDepth, wake_latency_us: u32) -> bool {
+        depth <= self.depth
+            && self
+                .wake_latency_us
+                .map_or(true, |budget| wake_latency_us <= budget)
+    }
+
+    /// The deepest of a chip's sleep `states` that fits in this budget.
+    /// Each state is given with its depth and wake latency, and states must
+    /// be ordered from the shallowest to the deepest.
+    pub fn choose<T: Copy>(&self, states: &[(T, SleepDepth, u32)]) -> Option<T> {
+        states
+            .iter()
+            .rev()
+            .find(|(_, depth, latency)| self.allows(*depth, *latency))
+            .map(|(state, _, _)| *state)
+    }
+}
+
+/// Something that constrains how deeply the chip may sleep.
+pub trait IdleSource {
+    /// The current constraints. Called with interrupts disabled, right
+    /// before the chip goes to sleep.
+    fn sleep_budget(&self) -> SleepBudget;
+}
+
+/// Combines the budgets of a board's idle sources.
+pub struct IdleArbiter<'a> {
+    sources: &'a [&'a dyn IdleSource],
+}
+
+impl<'a> IdleArbiter<'a> {
+    pub fn new(sources: &'a [&'a dyn IdleSource]) -> IdleArbiter<'a> {
+        IdleArbiter { sources }
+    }
+}
+
+impl<'a> IdleSource for IdleArbiter<'a> {
+    fn sleep_budget(&self) -> SleepBudget {
+        self.sources
+            .iter()
+            .fold(SleepBudget::UNCONSTRAINED, |budget, source| {
+                budget.restrict(source.sleep_budget())
+            })
+    }
+}
+
+/// Counts outstanding operations, such as DMA transfers, that need the
+/// chip to stay in shallow sleep until they complete.
+pub struct ActivityTracker {
+    active: Cell<usize>,
+}
+
+impl ActivityTracker {
+    pub const fn new() -> ActivityTracker {
+        ActivityTracker {
+            active: Cell::new(0),
+        }
+    }
+
+    /// An operation started.
+    pub fn begin(&self) {
+        self.active.set(self.active.get() + 1);
+    }
+
+    /// An operation started with `begin()` completed.
+    pub fn end(&self) {
+        self.active.set(self.active.get().saturating_sub(1));
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active.get() > 0
+    }
+}
+
+impl IdleSource for ActivityTracker {
+    fn sleep_budget(&self) -> SleepBudget {
+        if self.is_active() {
+            SleepBudget {
+                depth: SleepDepth::Shallow,
+                wake_latency_us: None,
+            }
+        } else {
+            SleepBudget::UNCONSTRAINED
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restrict_keeps_stricter_constraints() {
+        let a = SleepBudget {
+            depth: SleepDepth::Deep,
+            wake_latency_us: Some(500),
+        };
+        let b = SleepBudget {
+            depth: SleepDepth::Shallow,
+            wake_latency_us: None,
+        };
+        assert_eq!(
+            a.restrict(b),
+            SleepBudget {
+                depth: SleepDepth::Shallow,
+                wake_latency_us: Some(500),
+            }
+        );
+        assert_eq!(SleepBudget::UNCONSTRAINED.restrict(a), a);
+    }
+
+    #[test]
+    fn choose_picks_deepest_fitting_state() {
+        let states = [
+            (0, SleepDepth::Shallow, 0),
+            (1, SleepDepth::Deep, 100),
+            (2, SleepDepth::Deep, 1000),
+        ];
+        assert_eq!(SleepBudget::UNCONSTRAINED.choose(&states), Some(2));
+        let budget = SleepBudget {
+            depth: SleepDepth::Deep,
+            wake_latency_us: Some(500),
+        };
+        assert_eq!(budget.choose(&states), Some(1));
+        let budget = SleepBudget {
+            depth: SleepDepth::Shallow,
+            wake_latency_us: None,
+        };
+        assert_eq!(budget.choose(&states), Some(0));
+        let budget = SleepBudget {
+            depth: SleepDepth::Deep,
+            wake_latency_us: Some(10),
+        };
+        assert_eq!(budget.choose(&states[1..]), None);
+    }
+}
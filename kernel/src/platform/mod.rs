@@ -7,8 +7,10 @@
 //! Implementations of these traits are used by the core kernel.
 
 pub mod chip;
+pub mod idle;
 pub mod mpu;
 pub mod scheduler_timer;
+pub mod userspace_driver;
 pub mod watchdog;
 
 pub(crate) mod platform;
@@ -5,8 +5,10 @@
 //! Interface for configuring the Memory Protection Unit.
 
 use crate::process::ProcessId;
+use crate::ErrorCode;
 use core::cmp;
 use core::fmt::{self, Display};
+use core::ptr::{addr_of, addr_of_mut};
 
 /// User mode access permissions.
 #[derive(Copy, Clone)]
@@ -363,4 +365,57 @@ pub trait KernelMPU {
     /// changes to the kernel regions after this is enabled.
     #[allow(unused_variables)]
     fn enable_kernel_mpu(&self, config: &mut Self::KernelMpuConfig);
+
+    /// Mark all DMA buffers declared with `static_dma_buf!()` as read-write
+    /// kernel memory.
+    ///
+    /// Boards should call this with their other `allocate_kernel_region()`
+    /// calls, so that DMA drivers work without editing the memory protection
+    /// setup. Buffers declared after the kernel MPU is enabled are not
+    /// covered.
+    ///
+    /// The possible ErrorCodes are:
+    ///    - NOMEM: the MPU has no region left for a buffer, or a buffer does
+    ///      not meet the alignment requirements of the MPU
+    fn allocate_dma_regions(&self, config: &mut Self::KernelMpuConfig) -> Result<(), ErrorCode> {
+        for buffer in dma_buffers() {
+            self.allocate_kernel_region(
+                buffer.start_address(),
+                buffer.size(),
+                Permissions::ReadWriteOnly,
+                config,
+            )
+            .ok_or(ErrorCode::NOMEM)?;
+        }
+        Ok(())
+    }
+}
+
+/// Maximum number of buffers that can be declared with `static_dma_buf!()`.
+pub const MAX_DMA_BUFFERS: usize = 8;
+
+/// The buffers declared with `static_dma_buf!()`.
+static mut DMA_BUFFERS: [Option<Region>; MAX_DMA_BUFFERS] = [None; MAX_DMA_BUFFERS];
+
+/// Record a DMA buffer so `KernelMPU::allocate_dma_regions()` covers it.
+///
+/// This is called by `static_dma_buf!()` and should not be needed otherwise.
+///
+/// # Safety
+///
+/// Must only be called during board setup, as the list of buffers is not
+/// protected against concurrent access.
+pub unsafe fn register_dma_buffer(start_address: *const u8, size: usize) {
+    let buffers = &mut *addr_of_mut!(DMA_BUFFERS);
+    match buffers.iter_mut().find(|buffer| buffer.is_none()) {
+        Some(slot) => *slot = Some(Region::new(start_address, size)),
+        None => panic!("More than {} DMA buffers declared.", MAX_DMA_BUFFERS),
+    }
+}
+
+/// Iterate over the buffers declared with `static_dma_buf!()`.
+pub fn dma_buffers() -> impl Iterator<Item = Region> {
+    // Safety: the list is only modified during board setup.
+    let buffers = unsafe { &*addr_of!(DMA_BUFFERS) };
+    buffers.iter().flatten().copied()
 }
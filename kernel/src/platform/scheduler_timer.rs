@@ -7,7 +7,10 @@
 //! Interface for use by the Kernel to configure timers which can preempt
 //! userspace processes.
 
+use core::cell::Cell;
+
 use crate::hil::time::{self, Frequency, Ticks};
+use crate::Kernel;
 
 /// Interface for the system scheduler timer.
 ///
@@ -143,6 +146,85 @@ impl SchedulerTimer for () {
     }
 }
 
+/// A `SchedulerTimer` that only runs its inner timer when a timeslice limit
+/// is actually needed.
+///
+/// As long as the running process is the only one ready to run, nothing
+/// needs to preempt it, so the inner timer is not started and generates no
+/// periodic interrupts. This saves wakeups on battery-powered boards where a
+/// single process does most of the work. Once another process becomes ready,
+/// the next `arm()` starts the inner timer with the full timeslice.
+///
+/// While the inner timer is not running, no time is measured, so the kernel
+/// charges no execution time to the process for that part of its timeslice,
+/// and the CPU time statistics of processes undercount.
+///
+/// Usage
+/// -----
+///
+/// ```rust,ignore
+/// type SchedulerTimer = TicklessSchedulerTimer<cortexm4::systick::SysTick>;
+///
+/// scheduler_timer: TicklessSchedulerTimer::new(board_kernel, SysTick::new()),
+/// ```
+pub struct TicklessSchedulerTimer<T: SchedulerTimer> {
+    kernel: &'static Kernel,
+    timer: T,
+    /// Length of the current timeslice in microseconds.
+    timeslice_us: Cell<u32>,
+    /// Whether the inner timer was started for the current timeslice.
+    started: Cell<bool>,
+}
+
+impl<T: SchedulerTimer> TicklessSchedulerTimer<T> {
+    pub fn new(kernel: &'static Kernel, timer: T) -> Self {
+        Self {
+            kernel,
+            timer,
+            timeslice_us: Cell::new(0),
+            started: Cell::new(false),
+        }
+    }
+}
+
+impl<T: SchedulerTimer> SchedulerTimer for TicklessSchedulerTimer<T> {
+    fn start(&self, us: u32) {
+        // The inner timer is only started once the timeslice has to be
+        // enforced, see `arm()`.
+        self.timeslice_us.set(us);
+    }
+
+    fn reset(&self) {
+        self.timer.reset();
+        self.started.set(false);
+    }
+
+    fn arm(&self) {
+        if self.started.get() {
+            self.timer.arm();
+        } else if self.kernel.multiple_processes_ready() {
+            self.timer.reset();
+            self.timer.start(self.timeslice_us.get());
+            self.timer.arm();
+            self.started.set(true);
+        }
+    }
+
+    fn disarm(&self) {
+        if self.started.get() {
+            self.timer.disarm();
+        }
+    }
+
+    fn get_remaining_us(&self) -> Option<u32> {
+        if self.started.get() {
+            self.timer.get_remaining_us()
+        } else {
+            Some(self.timeslice_us.get())
+        }
+    }
+}
+
 /// Implementation of SchedulerTimer trait on top of a virtual alarm.
 ///
 /// Currently, this implementation depends slightly on the virtual alarm
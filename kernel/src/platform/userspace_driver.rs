@@ -0,0 +1,347 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Kernel support for peripheral drivers implemented in a process.
+//!
+//! A board can let one trusted process drive a peripheral itself: the
+//! process reads and writes the peripheral's registers through an
+//! `MmioWindow`, receives its interrupts through an `InterruptForwarder`,
+//! and shares DMA buffers from its own memory. The system call interface
+//! is `capsules_extra::userspace_driver`.
+//!
+//! Each window is created with an `unsafe` constructor and the driver
+//! capsule requires the `UserspaceDriverCapability`, so every register
+//! range, interrupt and process given driver privilege is listed in the
+//! board's `main.rs`.
+//!
+//! `InterruptForwarding` implements the `InterruptForwarder` on top of an
+//! interrupt controller's `InterruptMask`. It is chained in front of the
+//! chip's `InterruptService`, so forwarded interrupts are masked and passed
+//! to the driver process, and all other interrupts are serviced by the chip
+//! as usual.
+
+use core::cell::Cell;
+
+use crate::hil::time::{Alarm, AlarmClient, ConvertTicks};
+use crate::platform::chip::InterruptService;
+use crate::utilities::cells::OptionalCell;
+use crate::ErrorCode;
+
+/// How a process may access the registers of an `MmioWindow`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MmioAccess {
+    /// Registers can only be read.
+    ReadOnly,
+    /// Registers can be read and written.
+    ReadWrite,
+    /// Registers hold DMA addresses. They can be read, and only written
+    /// with addresses within a buffer the process has pinned for DMA, so
+    /// the peripheral cannot be pointed at memory the process does not own.
+    DmaAddress,
+}
+
+/// A range of memory-mapped peripheral registers a process may access
+/// with aligned 32-bit reads and writes.
+#[derive(Copy, Clone, Debug)]
+pub struct MmioWindow {
+    base: usize,
+    size: usize,
+    access: MmioAccess,
+}
+
+impl MmioWindow {
+    /// Create a window of `size` bytes of registers starting at `base`.
+    ///
+    /// # Safety
+    ///
+    /// The range must only contain peripheral registers that the kernel
+    /// does not use, and which cannot be used to access memory or
+    /// peripherals outside of the window (e.g. DMA address registers must
+    /// be in a `DmaAddress` window).
+    pub const unsafe fn new(base: usize, size: usize, access: MmioAccess) -> MmioWindow {
+        MmioWindow { base, size, access }
+    }
+
+    pub fn base(&self) -> usize {
+        self.base
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn access(&self) -> MmioAccess {
+        self.access
+    }
+
+    /// Whether the 32-bit register at `address` is within the window.
+    pub fn contains(&self, address: usize) -> bool {
+        address % 4 == 0
+            && address >= self.base
+            && address
+                .checked_add(4)
+                .is_some_and(|end| end <= self.base + self.size)
+    }
+
+    /// Read the 32-bit register at `address`.
+    pub fn read(&self, address: usize) -> Result<u32, ErrorCode> {
+        if !self.contains(address) {
+            return Err(ErrorCode::INVAL);
+        }
+        // Safety: the creator of the window guaranteed that it only covers
+        // registers, and the address is an aligned word within it.
+        Ok(unsafe { core::ptr::read_volatile(address as *const u32) })
+    }
+
+    /// Write the 32-bit register at `address`. The caller checks values
+    /// written to `DmaAddress` windows.
+    pub fn write(&self, address: usize, value: u32) -> Result<(), ErrorCode> {
+        if !self.contains(address) {
+            return Err(ErrorCode::INVAL);
+        }
+        if self.access == MmioAccess::ReadOnly {
+            return Err(ErrorCode::NOSUPPORT);
+        }
+        // Safety: as for `read()`; writes are allowed for this window.
+        unsafe { core::ptr::write_volatile(address as *mut u32, value) };
+        Ok(())
+    }
+}
+
+/// Delivers interrupts of peripherals driven by a process.
+///
+/// A forwarded interrupt is masked when it fires, so that it does not fire
+/// again until the process has handled it and acknowledged it.
+pub trait InterruptForwarder<'a> {
+    /// Set the client notified when a forwarded interrupt fires.
+    fn set_client(&self, client: &'a dyn ForwardedInterruptClient);
+
+    /// Start forwarding interrupt `irq` and unmask it.
+    ///
+    /// The possible ErrorCodes are:
+    ///    - INVAL: `irq` cannot be forwarded
+    fn enable(&self, irq: u32) -> Result<(), ErrorCode>;
+
+    /// Mask interrupt `irq` and stop forwarding it.
+    fn disable(&self, irq: u32);
+
+    /// Unmask interrupt `irq` after it was forwarded and handled.
+    ///
+    /// The possible ErrorCodes are:
+    ///    - INVAL: `irq` is not being forwarded
+    ///    - ALREADY: `irq` has not fired since it was last acknowledged
+    fn acknowledge(&self, irq: u32) -> Result<(), ErrorCode>;
+
+    /// Statistics of interrupt `irq`, or `None` if it cannot be forwarded.
+    fn stats(&self, irq: u32) -> Option<ForwardingStats>;
+}
+
+/// Client of an `InterruptForwarder`.
+pub trait ForwardedInterruptClient {
+    /// Interrupt `irq` fired and is now masked.
+    fn interrupt_forwarded(&self, irq: u32);
+}
+
+/// Statistics of a forwarded interrupt.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ForwardingStats {
+    /// How often the interrupt was forwarded.
+    pub forwarded: u32,
+    /// How often the interrupt was acknowledged.
+    pub acknowledged: u32,
+    /// How often the interrupt stayed masked after it was acknowledged
+    /// because it used up its budget of forwards.
+    pub throttled: u32,
+}
+
+/// Masks individual interrupts at the interrupt controller.
+pub trait InterruptMask {
+    /// Mask interrupt `irq`. It must stay masked even if the chip re-enables
+    /// it after servicing it, until it is unmasked.
+    fn mask(&self, irq: u32);
+
+    /// Unmask interrupt `irq`. If it is pending, it fires.
+    fn unmask(&self, irq: u32);
+}
+
+/// An interrupt the board allows `InterruptForwarding` to forward.
+pub struct ForwardableInterrupt {
+    irq: u32,
+    /// The process asked for the interrupt to be forwarded.
+    enabled: Cell<bool>,
+    /// The interrupt was forwarded and not acknowledged yet.
+    masked: Cell<bool>,
+    /// The interrupt was acknowledged without budget, and is unmasked when
+    /// the budgets are refilled.
+    throttled: Cell<bool>,
+    /// How often the interrupt can still be forwarded in this period.
+    budget: Cell<u32>,
+    stats: Cell<ForwardingStats>,
+}
+
+impl ForwardableInterrupt {
+    pub const fn new(irq: u32) -> ForwardableInterrupt {
+        ForwardableInterrupt {
+            irq,
+            enabled: Cell::new(false),
+            masked: Cell::new(false),
+            throttled: Cell::new(false),
+            budget: Cell::new(u32::MAX),
+            stats: Cell::new(ForwardingStats {
+                forwarded: 0,
+                acknowledged: 0,
+                throttled: 0,
+            }),
+        }
+    }
+
+    fn update_stats(&self, f: impl FnOnce(&mut ForwardingStats)) {
+        let mut stats = self.stats.get();
+        f(&mut stats);
+        self.stats.set(stats);
+    }
+}
+
+/// Forwards interrupts to a process, in front of the chip's interrupt
+/// service.
+///
+/// A forwarded interrupt is masked until the process acknowledges it. An
+/// interrupt that fires again right after each acknowledgment could keep
+/// the kernel busy and starve all processes, so each interrupt can only be
+/// forwarded `budget` times per `period_ms` milliseconds, counted from the
+/// first forward of a period. Once it used up its budget, an acknowledged
+/// interrupt stays masked until the period ends.
+///
+/// Interrupts in `interrupts` that are not being forwarded are masked and
+/// not passed on to the chip, as the kernel does not drive their
+/// peripherals.
+pub struct InterruptForwarding<'a, A: Alarm<'a>, I: InterruptService> {
+    interrupts: &'a [ForwardableInterrupt],
+    mask: &'a dyn InterruptMask,
+    service: &'a I,
+    alarm: &'a A,
+    budget: u32,
+    period_ms: u32,
+    client: OptionalCell<&'a dyn ForwardedInterruptClient>,
+}
+
+impl<'a, A: Alarm<'a>, I: InterruptService> InterruptForwarding<'a, A, I> {
+    /// `service` is the chip's interrupt service, which services all
+    /// interrupts not in `interrupts`.
+    pub fn new(
+        interrupts: &'a [ForwardableInterrupt],
+        mask: &'a dyn InterruptMask,
+        service: &'a I,
+        alarm: &'a A,
+        budget: u32,
+        period_ms: u32,
+    ) -> InterruptForwarding<'a, A, I> {
+        for interrupt in interrupts {
+            interrupt.budget.set(budget);
+        }
+        InterruptForwarding {
+            interrupts,
+            mask,
+            service,
+            alarm,
+            budget,
+            period_ms,
+            client: OptionalCell::empty(),
+        }
+    }
+
+    fn interrupt(&self, irq: u32) -> Option<&ForwardableInterrupt> {
+        self.interrupts
+            .iter()
+            .find(|interrupt| interrupt.irq == irq)
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: InterruptService> InterruptService for InterruptForwarding<'a, A, I> {
+    unsafe fn service_interrupt(&self, irq: u32) -> bool {
+        let interrupt = match self.interrupt(irq) {
+            Some(interrupt) => interrupt,
+            None => return self.service.service_interrupt(irq),
+        };
+        self.mask.mask(irq);
+        if !interrupt.enabled.get() {
+            return true;
+        }
+
+        interrupt.masked.set(true);
+        interrupt
+            .budget
+            .set(interrupt.budget.get().saturating_sub(1));
+        interrupt.update_stats(|stats| stats.forwarded = stats.forwarded.wrapping_add(1));
+        if !self.alarm.is_armed() {
+            let now = self.alarm.now();
+            self.alarm
+                .set_alarm(now, self.alarm.ticks_from_ms(self.period_ms));
+        }
+        self.client.map(|client| client.interrupt_forwarded(irq));
+        true
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: InterruptService> InterruptForwarder<'a>
+    for InterruptForwarding<'a, A, I>
+{
+    fn set_client(&self, client: &'a dyn ForwardedInterruptClient) {
+        self.client.set(client);
+    }
+
+    fn enable(&self, irq: u32) -> Result<(), ErrorCode> {
+        let interrupt = self.interrupt(irq).ok_or(ErrorCode::INVAL)?;
+        interrupt.enabled.set(true);
+        interrupt.masked.set(false);
+        interrupt.throttled.set(false);
+        self.mask.unmask(irq);
+        Ok(())
+    }
+
+    fn disable(&self, irq: u32) {
+        if let Some(interrupt) = self.interrupt(irq) {
+            interrupt.enabled.set(false);
+            interrupt.masked.set(false);
+            interrupt.throttled.set(false);
+            self.mask.mask(irq);
+        }
+    }
+
+    fn acknowledge(&self, irq: u32) -> Result<(), ErrorCode> {
+        let interrupt = self
+            .interrupt(irq)
+            .filter(|interrupt| interrupt.enabled.get())
+            .ok_or(ErrorCode::INVAL)?;
+        if !interrupt.masked.replace(false) {
+            return Err(ErrorCode::ALREADY);
+        }
+
+        interrupt.update_stats(|stats| stats.acknowledged = stats.acknowledged.wrapping_add(1));
+        if interrupt.budget.get() == 0 {
+            interrupt.throttled.set(true);
+            interrupt.update_stats(|stats| stats.throttled = stats.throttled.wrapping_add(1));
+        } else {
+            self.mask.unmask(irq);
+        }
+        Ok(())
+    }
+
+    fn stats(&self, irq: u32) -> Option<ForwardingStats> {
+        self.interrupt(irq).map(|interrupt| interrupt.stats.get())
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: InterruptService> AlarmClient for InterruptForwarding<'a, A, I> {
+    fn alarm(&self) {
+        // The period ended: refill the budgets and unmask the interrupts that
+        // were held back.
+        for interrupt in self.interrupts {
+            interrupt.budget.set(self.budget);
+            if interrupt.throttled.replace(false) && interrupt.enabled.get() {
+                self.mask.unmask(interrupt.irq);
+            }
+        }
+    }
+}
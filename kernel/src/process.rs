@@ -22,7 +22,13 @@ use tock_tbf::types::{CommandPermissions, TbfFooterV2Credentials};
 
 // Export all process related types via `kernel::process::`.
 pub use crate::process_loading::ProcessLoadError;
-pub use crate::process_loading::{load_and_check_processes, load_processes};
+pub use crate::process_loading::{
+    load_and_check_processes, load_and_check_processes_from_regions, load_processes,
+    load_processes_from_regions, set_decompression_buffer,
+};
+pub use crate::process_loading::{
+    padding_header, DynamicProcessLoader, DynamicProcessLoading, PADDING_HEADER_LENGTH,
+};
 pub use crate::process_policies::{
     PanicFaultPolicy, ProcessFaultPolicy, RestartFaultPolicy, StopFaultPolicy,
     StopWithDebugFaultPolicy, ThresholdRestartFaultPolicy, ThresholdRestartThenPanicFaultPolicy,
@@ -162,6 +168,18 @@ impl ProcessId {
         }
     }
 
+    /// Get the location of this app in the processes array, see `index()`.
+    ///
+    /// Unlike the identifier, the location stays the same when the process
+    /// restarts. This method is public but protected with a capability so
+    /// that capsules can keep state about a process across restarts.
+    pub fn index_external(
+        &self,
+        _capability: &dyn capabilities::ProcessManagementCapability,
+    ) -> Option<usize> {
+        self.index()
+    }
+
     /// Get a `usize` unique identifier for the app this `ProcessId` refers to.
     ///
     /// This function should not generally be used, instead code should just use
@@ -198,6 +216,15 @@ impl ProcessId {
         self.kernel
             .process_map_or(None, *self, |process| process.get_storage_permissions())
     }
+
+    /// Get the ShortID of the process, or `ShortID::LocallyUnique` if the
+    /// process no longer exists.
+    pub fn short_app_id(&self) -> ShortID {
+        self.kernel
+            .process_map_or(ShortID::LocallyUnique, *self, |process| {
+                process.short_app_id()
+            })
+    }
 }
 
 /// A compressed form of an Application Identifer.
@@ -284,6 +311,13 @@ pub trait Process {
     /// `None` if it was not made runnable or allowed to run without credentials.
     fn get_credentials(&self) -> Option<TbfFooterV2Credentials>;
 
+    /// Return whether the credentials which have made this process runnable
+    /// still hold the data they held when the process was approved. The
+    /// credentials are stored in flash, so they may have been corrupted or
+    /// replaced since. Returns `true` if the process runs without
+    /// credentials.
+    fn credentials_unchanged(&self) -> bool;
+
     /// Returns whether this process is ready to execute.
     fn ready(&self) -> bool;
 
@@ -322,6 +356,10 @@ pub trait Process {
 
     /// Move this process from running or yielded state into the stopped state.
     ///
+    /// A stopped process is not scheduled. Upcalls and IPC for it are still
+    /// queued, as long as its task queue has room, and delivered once it is
+    /// resumed.
+    ///
     /// This will fail (i.e. not do anything) if the process was not either
     /// running or yielded.
     fn stop(&self);
@@ -333,8 +371,12 @@ pub trait Process {
     fn resume(&self);
 
     /// Put this process in the fault state. This will trigger the
-    /// `FaultResponse` for this process to occur.
-    fn set_fault_state(&self);
+    /// `FaultResponse` for this process to occur. `reason` is recorded and
+    /// available to the fault policy through `get_fault_reason()`.
+    fn set_fault_state(&self, reason: FaultReason);
+
+    /// Returns why this process last faulted, or `None` if it never faulted.
+    fn get_fault_reason(&self) -> Option<FaultReason>;
 
     /// Returns how many times this process has been restarted.
     fn get_restart_count(&self) -> usize;
@@ -631,6 +673,12 @@ pub trait Process {
     /// if there is a grant associated with that driver_num.
     fn lookup_grant_from_driver_num(&self, driver_num: usize) -> Result<usize, Error>;
 
+    /// Return the driver number of grant `grant_num` and the number of bytes
+    /// allocated for it, if the grant is allocated.
+    ///
+    /// Useful for debugging/inspecting the system.
+    fn grant_allocation(&self, grant_num: usize) -> Option<(usize, usize)>;
+
     // subscribe
 
     /// Verify that an Upcall function pointer is within process-accessible
@@ -688,6 +736,15 @@ pub trait Process {
     /// representation. Returns `ErrorCode::FAIL` on an internal error.
     fn get_stored_state(&self, out: &mut [u8]) -> Result<usize, ErrorCode>;
 
+    /// Return the program counter and stack pointer the process stopped
+    /// executing at, e.g. where it faulted. Returns `None` if they are not
+    /// known.
+    fn get_pc_and_sp(&self) -> Option<(usize, usize)>;
+
+    /// Print the memory protection unit (MPU) regions currently configured
+    /// for the process.
+    fn print_mpu_config(&self, writer: &mut dyn Write);
+
     /// Print out the full state of the process: its memory map, its
     /// context, and the state of the memory protection unit (MPU).
     fn print_full_process(&self, writer: &mut dyn Write);
@@ -706,6 +763,27 @@ pub trait Process {
     /// Increment the number of times the process has exceeded its timeslice.
     fn debug_timeslice_expired(&self);
 
+    /// Returns how long this process has run, in microseconds, including the
+    /// time the kernel spent handling its system calls. Only runs with a
+    /// timeslice are measured, as the time is taken from the scheduler
+    /// timer.
+    fn debug_cpu_time_us(&self) -> u64;
+
+    /// Add the time the process ran for, in microseconds.
+    fn debug_cpu_time_used(&self, us: u32);
+
+    /// Returns how the grant region of this process is used.
+    fn debug_grant_stats(&self) -> GrantStats;
+
+    /// Returns the most memory the stack and heap of this process have used.
+    fn debug_memory_high_water(&self) -> MemoryHighWater;
+
+    /// Returns how many times the kernel switched to this process.
+    fn debug_context_switch_count(&self) -> usize;
+
+    /// Increment the number of times the kernel switched to this process.
+    fn debug_context_switched(&self);
+
     /// Increment the number of times the process called a syscall and record
     /// the last syscall that was called.
     fn debug_syscall_called(&self, last_syscall: Syscall);
@@ -898,6 +976,26 @@ pub enum FaultAction {
     Stop,
 }
 
+/// Why a process was put into the fault state.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FaultReason {
+    /// The process caused a hardware fault, e.g. an MPU violation or an
+    /// invalid instruction.
+    Hardware = 1,
+
+    /// The kernel could not switch to the process.
+    ContextSwitch = 2,
+
+    /// The kernel could not write to the stack of the process to return from
+    /// a system call or to deliver an upcall, usually because the process
+    /// overflowed its stack.
+    Stack = 3,
+
+    /// The fault was requested, e.g. from the process console or to test
+    /// fault handling.
+    Requested = 4,
+}
+
 /// Tasks that can be enqueued for a process.
 ///
 /// This is public for external implementations of `Process`.
@@ -994,6 +1092,45 @@ pub struct ProcessAddresses {
     pub sram_stack_bottom: Option<usize>,
 }
 
+/// How the grant region of a process is used.
+#[derive(Copy, Clone, Debug)]
+pub struct GrantStats {
+    /// The number of bytes allocated for grants and custom grants, including
+    /// alignment padding.
+    pub allocated: usize,
+    /// The most bytes that have ever been allocated in the grant region,
+    /// including before the process restarted.
+    pub high_water: usize,
+    /// The number of bytes the grant region can still grow before it reaches
+    /// the process-accessible memory.
+    pub available: usize,
+    /// How many grant allocations failed because the grant region was full,
+    /// including before the process restarted.
+    pub failed_allocations: usize,
+    /// The driver number of the last grant that could not be allocated.
+    pub last_failed_driver_num: Option<usize>,
+}
+
+/// The most memory a process has used, so its memory size can be set to what
+/// it needs. The marks include use before the process restarted.
+#[derive(Copy, Clone, Debug)]
+pub struct MemoryHighWater {
+    /// The deepest the stack has grown, in bytes. `None` if the process did
+    /// not tell the kernel where its stack starts. The stack pointer is only
+    /// sampled when the process is switched out, so the mark can be lower
+    /// than the true peak.
+    pub stack: Option<usize>,
+    /// The most bytes between the start of the heap and the app break.
+    /// `None` if the process did not tell the kernel where its heap starts.
+    pub heap: Option<usize>,
+    /// The most bytes of process-accessible memory, from the start of the
+    /// process memory to the app break.
+    pub app_memory: usize,
+    /// The number of bytes of memory allocated to the process, including the
+    /// grant region.
+    pub allocated: usize,
+}
+
 /// Collection of process state related to the size in memory of various process
 /// structures.
 pub struct ProcessSizes {
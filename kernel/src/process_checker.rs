@@ -7,10 +7,12 @@
 //| the [AppID TRD](../../doc/reference/trd-appid.md).
 
 pub mod basic;
+pub mod ecdsa;
+pub mod rsa;
 
 use crate::config;
 use crate::debug;
-use crate::process::{Process, ShortID, State};
+use crate::process::{Process, ProcessId, ShortID, State};
 use crate::ErrorCode;
 use tock_tbf::types::TbfFooterV2Credentials;
 
@@ -37,6 +39,14 @@ pub trait Client<'a> {
     );
 }
 
+/// Receives the result of re-verifying the credentials of a running process,
+/// see `ProcessCheckerMachine::reverify_credentials()`.
+pub trait ReverificationClient {
+    /// `result` is `Ok(true)` if the credentials the process was approved
+    /// with are still accepted for its binary, and `Ok(false)` if not.
+    fn reverification_done(&self, processid: ProcessId, result: Result<bool, ErrorCode>);
+}
+
 /// Implements a Credentials Checking Policy.
 pub trait AppCredentialsChecker<'a> {
     fn set_client(&self, _client: &'a dyn Client<'a>);
@@ -180,6 +190,16 @@ impl Compress for () {
     }
 }
 
+/// Chooses the ShortID of a process whose credentials were approved, e.g.
+/// to keep it stable when the application is reinstalled. Set it with
+/// `ProcessCheckerMachine::set_short_id_assigner()`.
+pub trait ShortIdAssigner {
+    /// `proposed` is the ShortID the Credentials Checking Policy derived
+    /// from the accepted credentials, or `LocallyUnique` if the process was
+    /// approved without credentials.
+    fn assign(&self, process: &dyn Process, proposed: ShortID) -> ShortID;
+}
+
 pub trait CredentialsCheckingPolicy<'a>:
     AppCredentialsChecker<'a> + Compress + AppUniqueness
 {
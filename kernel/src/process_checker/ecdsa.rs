@@ -0,0 +1,235 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! A Credentials Checking Policy that verifies ECDSA P-256 signatures,
+//! used to decide whether an application can be loaded. See
+//! the [AppID TRD](../../doc/reference/trd-appid.md).
+
+use crate::hil::digest::{ClientData, ClientHash, ClientVerify};
+use crate::hil::digest::{DigestDataHash, Sha256};
+use crate::hil::public_key_crypto::signature::{self, SignatureVerify};
+use crate::process::{Process, ShortID};
+use crate::process_checker::{AppCredentialsChecker, AppUniqueness};
+use crate::process_checker::{CheckResult, Client, Compress};
+use crate::utilities::cells::OptionalCell;
+use crate::utilities::cells::TakeCell;
+use crate::utilities::leasable_buffer::{LeasableBuffer, LeasableMutableBuffer};
+use crate::ErrorCode;
+use tock_tbf::types::TbfFooterV2Credentials;
+use tock_tbf::types::TbfFooterV2CredentialsType;
+
+/// Length in bytes of a SHA-256 hash.
+pub const SHA256_LEN: usize = 32;
+
+/// Length in bytes of an ECDSA P-256 signature, `r || s`.
+pub const SIGNATURE_LEN: usize = 64;
+
+pub trait Sha256Hasher<'a>: DigestDataHash<'a, SHA256_LEN> + Sha256 {}
+impl<'a, T: DigestDataHash<'a, SHA256_LEN> + Sha256> Sha256Hasher<'a> for T {}
+
+/// A Credentials Checking Policy that only runs Userspace Binaries
+/// signed by a public key known to the board. It checks
+/// `EcdsaNistP256` credentials by verifying the signature over the
+/// SHA-256 hash of the binary.
+///
+/// The verification goes through the signature HIL, so it can run on a
+/// hardware accelerator or on the software implementation in
+/// `capsules_extra`; either way the verifier holds the public key.
+///
+/// Unlike RSA credentials, an `EcdsaNistP256` credential does not name
+/// its key, so a signature that does not verify may come from another
+/// key. This policy therefore passes on such credentials rather than
+/// rejecting the binary, letting other footers be checked. A Userspace
+/// Binary without any signature that verifies fails checking.
+///
+/// The signature is the Application Identifier: each signed binary is
+/// a different application, and two binaries with the same signature
+/// cannot run at the same time.
+pub struct AppCheckerEcdsaP256 {
+    hasher: &'static dyn Sha256Hasher<'static>,
+    verifier: &'static dyn SignatureVerify<'static, SHA256_LEN, SIGNATURE_LEN>,
+    client: OptionalCell<&'static dyn Client<'static>>,
+    hash: TakeCell<'static, [u8; SHA256_LEN]>,
+    signature: TakeCell<'static, [u8; SIGNATURE_LEN]>,
+    binary: OptionalCell<&'static [u8]>,
+    credentials: OptionalCell<TbfFooterV2Credentials>,
+}
+
+impl AppCheckerEcdsaP256 {
+    pub fn new(
+        hasher: &'static dyn Sha256Hasher<'static>,
+        verifier: &'static dyn SignatureVerify<'static, SHA256_LEN, SIGNATURE_LEN>,
+        hash_buffer: &'static mut [u8; SHA256_LEN],
+        signature_buffer: &'static mut [u8; SIGNATURE_LEN],
+    ) -> AppCheckerEcdsaP256 {
+        AppCheckerEcdsaP256 {
+            hasher,
+            verifier,
+            client: OptionalCell::empty(),
+            hash: TakeCell::new(hash_buffer),
+            signature: TakeCell::new(signature_buffer),
+            binary: OptionalCell::empty(),
+            credentials: OptionalCell::empty(),
+        }
+    }
+
+    fn check_done(&self, result: Result<CheckResult, ErrorCode>) {
+        if let (Some(credentials), Some(binary)) = (self.credentials.take(), self.binary.take()) {
+            self.client
+                .map(|c| c.check_done(result, credentials, binary));
+        }
+    }
+}
+
+impl AppCredentialsChecker<'static> for AppCheckerEcdsaP256 {
+    fn require_credentials(&self) -> bool {
+        true
+    }
+
+    fn check_credentials(
+        &self,
+        credentials: TbfFooterV2Credentials,
+        binary: &'static [u8],
+    ) -> Result<(), (ErrorCode, TbfFooterV2Credentials, &'static [u8])> {
+        if self.credentials.is_some() {
+            return Err((ErrorCode::BUSY, credentials, binary));
+        }
+        let data = credentials.data();
+        if credentials.format() != TbfFooterV2CredentialsType::EcdsaNistP256
+            || data.len() < SIGNATURE_LEN
+        {
+            return Err((ErrorCode::NOSUPPORT, credentials, binary));
+        }
+        match self
+            .signature
+            .map(|signature| signature.copy_from_slice(&data[..SIGNATURE_LEN]))
+        {
+            Some(()) => {}
+            None => return Err((ErrorCode::BUSY, credentials, binary)),
+        }
+
+        if let Err(e) = self.hasher.set_mode_sha256() {
+            return Err((e, credentials, binary));
+        }
+        self.hasher.clear_data();
+        match self.hasher.add_data(LeasableBuffer::new(binary)) {
+            Ok(()) => {
+                self.credentials.set(credentials);
+                Ok(())
+            }
+            Err((e, b)) => Err((e, credentials, b.take())),
+        }
+    }
+
+    fn set_client(&self, client: &'static dyn Client<'static>) {
+        self.client.replace(client);
+    }
+}
+
+impl ClientData<SHA256_LEN> for AppCheckerEcdsaP256 {
+    fn add_mut_data_done(
+        &self,
+        _result: Result<(), ErrorCode>,
+        _data: LeasableMutableBuffer<'static, u8>,
+    ) {
+    }
+
+    fn add_data_done(&self, result: Result<(), ErrorCode>, data: LeasableBuffer<'static, u8>) {
+        self.binary.set(data.take());
+        if let Err(e) = result {
+            self.check_done(Err(e));
+            return;
+        }
+        match self.hash.take() {
+            Some(hash) => {
+                if let Err((e, hash)) = self.hasher.run(hash) {
+                    self.hash.replace(hash);
+                    self.check_done(Err(e));
+                }
+            }
+            None => self.check_done(Err(ErrorCode::FAIL)),
+        }
+    }
+}
+
+impl ClientHash<SHA256_LEN> for AppCheckerEcdsaP256 {
+    fn hash_done(&self, result: Result<(), ErrorCode>, digest: &'static mut [u8; SHA256_LEN]) {
+        if let Err(e) = result {
+            self.hash.replace(digest);
+            self.check_done(Err(e));
+            return;
+        }
+        match self.signature.take() {
+            Some(signature) => {
+                if let Err((e, hash, signature)) = self.verifier.verify(digest, signature) {
+                    self.hash.replace(hash);
+                    self.signature.replace(signature);
+                    self.check_done(Err(e));
+                }
+            }
+            None => {
+                self.hash.replace(digest);
+                self.check_done(Err(ErrorCode::FAIL));
+            }
+        }
+    }
+}
+
+impl ClientVerify<SHA256_LEN> for AppCheckerEcdsaP256 {
+    fn verification_done(
+        &self,
+        _result: Result<bool, ErrorCode>,
+        _compare: &'static mut [u8; SHA256_LEN],
+    ) {
+    }
+}
+
+impl signature::ClientVerify<SHA256_LEN, SIGNATURE_LEN> for AppCheckerEcdsaP256 {
+    fn verification_done(
+        &self,
+        result: Result<bool, ErrorCode>,
+        hash: &'static mut [u8; SHA256_LEN],
+        signature: &'static mut [u8; SIGNATURE_LEN],
+    ) {
+        self.hash.replace(hash);
+        self.signature.replace(signature);
+        self.check_done(result.map(|valid| {
+            if valid {
+                CheckResult::Accept
+            } else {
+                // Possibly signed with a key we do not know.
+                CheckResult::Pass
+            }
+        }));
+    }
+}
+
+impl AppUniqueness for AppCheckerEcdsaP256 {
+    fn different_identifier(&self, process_a: &dyn Process, process_b: &dyn Process) -> bool {
+        // Processes without credentials should not be runnable under this
+        // policy; treat them as different so they cannot block others.
+        process_a.get_credentials().map_or(true, |a| {
+            process_b
+                .get_credentials()
+                .map_or(true, |b| a.format() != b.format() || a.data() != b.data())
+        })
+    }
+}
+
+impl Compress for AppCheckerEcdsaP256 {
+    // The short ID is the first 31 bits of `r` with the top bit set, so
+    // it is non-zero. As with the SHA256 checker, 31 bits are not enough
+    // to rely on for a unique identity.
+    fn to_short_id(&self, credentials: &TbfFooterV2Credentials) -> ShortID {
+        let data = credentials.data();
+        if data.len() < 4 {
+            return ShortID::LocallyUnique;
+        }
+        let id = 0x8000_0000 | u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+        match core::num::NonZeroU32::new(id) {
+            Some(nzid) => ShortID::Fixed(nzid),
+            None => ShortID::LocallyUnique, // Should never be generated
+        }
+    }
+}
@@ -0,0 +1,384 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! A Credentials Checking Policy that verifies RSA-3072 signatures, used
+//! to decide whether an application can be loaded. See
+//! the [AppID TRD](../../doc/reference/trd-appid.md).
+//!
+//! Usage
+//! -----
+//!
+//! A board without SHA-512 or RSA hardware, such as imix, uses the software
+//! implementations in `capsules_extra`. `APP_SIGNING_KEY` is the 384-byte
+//! big endian modulus of the board's key, e.g.
+//! `*include_bytes!("app_signing_key.bin")`:
+//!
+//! ```rust,ignore
+//! let sha512 = static_init!(Sha512Software<'static>, Sha512Software::new());
+//! kernel::deferred_call::DeferredCallClient::register(sha512);
+//! let rsa = static_init!(Rsa3072Software<'static>, Rsa3072Software::new());
+//! kernel::deferred_call::DeferredCallClient::register(rsa);
+//! let checker = static_init!(
+//!     AppCheckerRsa3072,
+//!     AppCheckerRsa3072::new(
+//!         sha512,
+//!         rsa,
+//!         &APP_SIGNING_KEY,
+//!         &[0x01, 0x00, 0x01],
+//!         static_init!([u8; 64], [0; 64]),
+//!         static_init!([u8; 384], [0; 384]),
+//!         static_init!([u8; 384], [0; 384]),
+//!     )
+//! );
+//! sha512.set_client(checker);
+//! kernel::hil::public_key_crypto::rsa_math::RsaCryptoBase::set_client(rsa, checker);
+//! ```
+//!
+//! The board then uses `AppCheckerRsa3072` as the `CredentialsCheckingPolicy`
+//! of its `KernelResources` and returns `checker` from
+//! `credentials_checking_policy()`.
+
+use crate::hil::digest::{ClientData, ClientHash, ClientVerify};
+use crate::hil::digest::{DigestDataHash, Sha512};
+use crate::hil::public_key_crypto::rsa_math::{self, RsaCryptoBase};
+use crate::process::{Process, ShortID};
+use crate::process_checker::{AppCredentialsChecker, AppUniqueness};
+use crate::process_checker::{CheckResult, Client, Compress};
+use crate::utilities::cells::OptionalCell;
+use crate::utilities::cells::TakeCell;
+use crate::utilities::leasable_buffer::{LeasableBuffer, LeasableMutableBuffer};
+use crate::ErrorCode;
+use tock_tbf::types::TbfFooterV2Credentials;
+use tock_tbf::types::TbfFooterV2CredentialsType;
+
+/// Length in bytes of an RSA-3072 modulus and signature.
+pub const RSA3072_LEN: usize = 384;
+
+/// Length in bytes of a SHA-512 hash.
+pub const SHA512_LEN: usize = 64;
+
+/// DER encoding of the SHA-512 `DigestInfo` that precedes the hash in a
+/// PKCS#1 v1.5 signature (RFC 8017, section 9.2).
+const SHA512_DIGEST_INFO: [u8; 19] = [
+    0x30, 0x51, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x03, 0x05,
+    0x00, 0x04, 0x40,
+];
+
+pub trait Sha512Hasher<'a>: DigestDataHash<'a, SHA512_LEN> + Sha512 {}
+impl<'a, T: DigestDataHash<'a, SHA512_LEN> + Sha512> Sha512Hasher<'a> for T {}
+
+/// A Credentials Checking Policy that only runs Userspace Binaries
+/// signed by a public key compiled into the board. It checks
+/// `Rsa3072Key` credentials whose key matches the board's key by
+/// verifying the PKCS#1 v1.5 SHA-512 signature over the binary.
+/// Credentials of other types, or signed with other keys, are passed
+/// over, so a Userspace Binary without a valid signature from the
+/// board's key fails checking.
+///
+/// The RSA operation goes through the RSA HIL, so it can run on an
+/// accelerator (e.g. OpenTitan's OTBN) or on the software
+/// implementation in `capsules_extra`. The public exponent is provided
+/// by the board, as the credentials do not contain it.
+///
+/// The signature is the Application Identifier: each signed binary is
+/// a different application, and two binaries with the same signature
+/// cannot run at the same time.
+pub struct AppCheckerRsa3072 {
+    hasher: &'static dyn Sha512Hasher<'static>,
+    rsa: &'static dyn RsaCryptoBase<'static>,
+    public_key: &'static [u8; RSA3072_LEN],
+    public_exponent: &'static [u8],
+    client: OptionalCell<&'static dyn Client<'static>>,
+    hash: TakeCell<'static, [u8; SHA512_LEN]>,
+    signature: TakeCell<'static, [u8]>,
+    decrypted: TakeCell<'static, [u8]>,
+    binary: OptionalCell<&'static [u8]>,
+    credentials: OptionalCell<TbfFooterV2Credentials>,
+}
+
+impl AppCheckerRsa3072 {
+    /// `public_key` is the big endian modulus of the key and
+    /// `public_exponent` its big endian public exponent (usually
+    /// `&[0x01, 0x00, 0x01]`).
+    pub fn new(
+        hasher: &'static dyn Sha512Hasher<'static>,
+        rsa: &'static dyn RsaCryptoBase<'static>,
+        public_key: &'static [u8; RSA3072_LEN],
+        public_exponent: &'static [u8],
+        hash_buffer: &'static mut [u8; SHA512_LEN],
+        signature_buffer: &'static mut [u8; RSA3072_LEN],
+        decrypted_buffer: &'static mut [u8; RSA3072_LEN],
+    ) -> AppCheckerRsa3072 {
+        AppCheckerRsa3072 {
+            hasher,
+            rsa,
+            public_key,
+            public_exponent,
+            client: OptionalCell::empty(),
+            hash: TakeCell::new(hash_buffer),
+            signature: TakeCell::new(signature_buffer),
+            decrypted: TakeCell::new(decrypted_buffer),
+            binary: OptionalCell::empty(),
+            credentials: OptionalCell::empty(),
+        }
+    }
+
+    fn check_done(&self, result: Result<CheckResult, ErrorCode>) {
+        if let (Some(credentials), Some(binary)) = (self.credentials.take(), self.binary.take()) {
+            self.client
+                .map(|c| c.check_done(result, credentials, binary));
+        }
+    }
+
+    /// Whether `decrypted` is the PKCS#1 v1.5 encoding of `hash`:
+    /// `00 01 FF .. FF 00 || DigestInfo || hash`.
+    fn valid_encoding(decrypted: &[u8], hash: &[u8; SHA512_LEN]) -> bool {
+        let padding_end = RSA3072_LEN - SHA512_LEN - SHA512_DIGEST_INFO.len() - 1;
+        decrypted.len() >= RSA3072_LEN
+            && decrypted[0] == 0x00
+            && decrypted[1] == 0x01
+            && decrypted[2..padding_end].iter().all(|b| *b == 0xff)
+            && decrypted[padding_end] == 0x00
+            && decrypted[padding_end + 1..RSA3072_LEN - SHA512_LEN] == SHA512_DIGEST_INFO
+            && decrypted[RSA3072_LEN - SHA512_LEN..RSA3072_LEN] == hash[..]
+    }
+}
+
+impl AppCredentialsChecker<'static> for AppCheckerRsa3072 {
+    fn require_credentials(&self) -> bool {
+        true
+    }
+
+    fn check_credentials(
+        &self,
+        credentials: TbfFooterV2Credentials,
+        binary: &'static [u8],
+    ) -> Result<(), (ErrorCode, TbfFooterV2Credentials, &'static [u8])> {
+        if self.credentials.is_some() {
+            return Err((ErrorCode::BUSY, credentials, binary));
+        }
+        let data = credentials.data();
+        if credentials.format() != TbfFooterV2CredentialsType::Rsa3072Key
+            || data.len() < 2 * RSA3072_LEN
+            || data[..RSA3072_LEN] != self.public_key[..]
+        {
+            // Not signed by our key: let the next footer be checked.
+            return Err((ErrorCode::NOSUPPORT, credentials, binary));
+        }
+        match self.signature.map(|signature| {
+            signature[..RSA3072_LEN].copy_from_slice(&data[RSA3072_LEN..2 * RSA3072_LEN])
+        }) {
+            Some(()) => {}
+            None => return Err((ErrorCode::BUSY, credentials, binary)),
+        }
+
+        if let Err(e) = self.hasher.set_mode_sha512() {
+            return Err((e, credentials, binary));
+        }
+        self.hasher.clear_data();
+        match self.hasher.add_data(LeasableBuffer::new(binary)) {
+            Ok(()) => {
+                self.credentials.set(credentials);
+                Ok(())
+            }
+            Err((e, b)) => Err((e, credentials, b.take())),
+        }
+    }
+
+    fn set_client(&self, client: &'static dyn Client<'static>) {
+        self.client.replace(client);
+    }
+}
+
+impl ClientData<SHA512_LEN> for AppCheckerRsa3072 {
+    fn add_mut_data_done(
+        &self,
+        _result: Result<(), ErrorCode>,
+        _data: LeasableMutableBuffer<'static, u8>,
+    ) {
+    }
+
+    fn add_data_done(&self, result: Result<(), ErrorCode>, data: LeasableBuffer<'static, u8>) {
+        self.binary.set(data.take());
+        if let Err(e) = result {
+            self.check_done(Err(e));
+            return;
+        }
+        match self.hash.take() {
+            Some(hash) => {
+                if let Err((e, hash)) = self.hasher.run(hash) {
+                    self.hash.replace(hash);
+                    self.check_done(Err(e));
+                }
+            }
+            None => self.check_done(Err(ErrorCode::FAIL)),
+        }
+    }
+}
+
+impl ClientHash<SHA512_LEN> for AppCheckerRsa3072 {
+    fn hash_done(&self, result: Result<(), ErrorCode>, digest: &'static mut [u8; SHA512_LEN]) {
+        self.hash.replace(digest);
+        if let Err(e) = result {
+            self.check_done(Err(e));
+            return;
+        }
+        match (self.signature.take(), self.decrypted.take()) {
+            (Some(signature), Some(decrypted)) => {
+                if let Err((e, signature, _, _, decrypted)) = self.rsa.mod_exponent(
+                    signature,
+                    self.public_key,
+                    self.public_exponent,
+                    decrypted,
+                ) {
+                    self.signature.replace(signature);
+                    self.decrypted.replace(decrypted);
+                    self.check_done(Err(e));
+                }
+            }
+            (signature, decrypted) => {
+                signature.map(|s| self.signature.replace(s));
+                decrypted.map(|d| self.decrypted.replace(d));
+                self.check_done(Err(ErrorCode::FAIL));
+            }
+        }
+    }
+}
+
+impl ClientVerify<SHA512_LEN> for AppCheckerRsa3072 {
+    fn verification_done(
+        &self,
+        _result: Result<bool, ErrorCode>,
+        _compare: &'static mut [u8; SHA512_LEN],
+    ) {
+    }
+}
+
+impl rsa_math::Client<'static> for AppCheckerRsa3072 {
+    fn mod_exponent_done(
+        &'static self,
+        status: Result<bool, ErrorCode>,
+        message: &'static mut [u8],
+        _modulus: &'static [u8],
+        _exponent: &'static [u8],
+        result: &'static mut [u8],
+    ) {
+        let check = status.map(|_| {
+            let valid = self
+                .hash
+                .map_or(false, |hash| Self::valid_encoding(result, hash));
+            if valid {
+                CheckResult::Accept
+            } else {
+                CheckResult::Reject
+            }
+        });
+        self.signature.replace(message);
+        self.decrypted.replace(result);
+        self.rsa.clear_data();
+        self.check_done(check);
+    }
+}
+
+impl AppUniqueness for AppCheckerRsa3072 {
+    fn different_identifier(&self, process_a: &dyn Process, process_b: &dyn Process) -> bool {
+        // Processes without credentials should not be runnable under this
+        // policy; treat them as different so they cannot block others.
+        process_a.get_credentials().map_or(true, |a| {
+            process_b
+                .get_credentials()
+                .map_or(true, |b| a.format() != b.format() || a.data() != b.data())
+        })
+    }
+}
+
+impl Compress for AppCheckerRsa3072 {
+    // The short ID is the first 31 bits of the signature with the top bit
+    // set, so it is non-zero. As with the SHA256 checker, 31 bits are not
+    // enough to rely on for a unique identity.
+    fn to_short_id(&self, credentials: &TbfFooterV2Credentials) -> ShortID {
+        let data = credentials.data();
+        if data.len() < RSA3072_LEN + 4 {
+            return ShortID::LocallyUnique;
+        }
+        let id = 0x8000_0000
+            | u32::from_be_bytes([
+                data[RSA3072_LEN],
+                data[RSA3072_LEN + 1],
+                data[RSA3072_LEN + 2],
+                data[RSA3072_LEN + 3],
+            ]);
+        match core::num::NonZeroU32::new(id) {
+            Some(nzid) => ShortID::Fixed(nzid),
+            None => ShortID::LocallyUnique, // Should never be generated
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// SHA-512("abc"), FIPS 180-2 appendix C.1.
+    const ABC_SHA512: [u8; SHA512_LEN] = [
+        0xdd, 0xaf, 0x35, 0xa1, 0x93, 0x61, 0x7a, 0xba, 0xcc, 0x41, 0x73, 0x49, 0xae, 0x20, 0x41,
+        0x31, 0x12, 0xe6, 0xfa, 0x4e, 0x89, 0xa9, 0x7e, 0xa2, 0x0a, 0x9e, 0xee, 0xe6, 0x4b, 0x55,
+        0xd3, 0x9a, 0x21, 0x92, 0x99, 0x2a, 0x27, 0x4f, 0xc1, 0xa8, 0x36, 0xba, 0x3c, 0x23, 0xa3,
+        0xfe, 0xeb, 0xbd, 0x45, 0x4d, 0x44, 0x23, 0x64, 0x3c, 0xe8, 0x0e, 0x2a, 0x9a, 0xc9, 0x4f,
+        0xa5, 0x4c, 0xa4, 0x9f,
+    ];
+
+    /// EMSA-PKCS1-v1_5 of SHA-512("abc") for a 3072-bit modulus (RFC 8017,
+    /// section 9.2), with the DigestInfo prefix of note 1 of that section.
+    /// This is the public key operation on OpenSSL's signature of "abc".
+    fn encoded_abc() -> [u8; RSA3072_LEN] {
+        let mut encoded = [0xff; RSA3072_LEN];
+        encoded[..2].copy_from_slice(&[0x00, 0x01]);
+        encoded[300..320].copy_from_slice(&[
+            0x00, 0x30, 0x51, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04,
+            0x02, 0x03, 0x05, 0x00, 0x04, 0x40,
+        ]);
+        encoded[320..].copy_from_slice(&ABC_SHA512);
+        encoded
+    }
+
+    #[test]
+    fn accepts_pkcs1_v15_sha512_encoding() {
+        assert!(AppCheckerRsa3072::valid_encoding(
+            &encoded_abc(),
+            &ABC_SHA512
+        ));
+    }
+
+    #[test]
+    fn rejects_other_hash() {
+        let mut hash = ABC_SHA512;
+        hash[63] ^= 1;
+        assert!(!AppCheckerRsa3072::valid_encoding(&encoded_abc(), &hash));
+    }
+
+    #[test]
+    fn rejects_bad_padding() {
+        // Block type 2 is for encryption.
+        let mut encoded = encoded_abc();
+        encoded[1] = 0x02;
+        assert!(!AppCheckerRsa3072::valid_encoding(&encoded, &ABC_SHA512));
+
+        // A padding byte other than 0xff.
+        let mut encoded = encoded_abc();
+        encoded[100] = 0xfe;
+        assert!(!AppCheckerRsa3072::valid_encoding(&encoded, &ABC_SHA512));
+
+        // The SHA-256 algorithm identifier in the DigestInfo.
+        let mut encoded = encoded_abc();
+        encoded[315] = 0x01;
+        assert!(!AppCheckerRsa3072::valid_encoding(&encoded, &ABC_SHA512));
+
+        // Too short for the modulus.
+        assert!(!AppCheckerRsa3072::valid_encoding(
+            &encoded_abc()[..RSA3072_LEN - 1],
+            &ABC_SHA512
+        ));
+    }
+}
@@ -10,6 +10,7 @@
 
 use core::convert::TryInto;
 use core::fmt;
+use core::ptr::addr_of_mut;
 
 use crate::capabilities::{ProcessApprovalCapability, ProcessManagementCapability};
 use crate::config;
@@ -18,10 +19,13 @@ use crate::debug;
 use crate::kernel::{Kernel, ProcessCheckerMachine};
 use crate::platform::chip::Chip;
 use crate::platform::platform::KernelResources;
-use crate::process::{Process, ShortID};
+use crate::process::{Process, ProcessId, ShortID};
 use crate::process_checker::AppCredentialsChecker;
 use crate::process_policies::ProcessFaultPolicy;
 use crate::process_standard::ProcessStandard;
+use crate::utilities::cells::TakeCell;
+use crate::utilities::heatshrink;
+use crate::ErrorCode;
 
 /// Errors that can occur when trying to load and create processes.
 pub enum ProcessLoadError {
@@ -86,6 +90,15 @@ pub enum ProcessLoadError {
     /// this counter.
     CredentialsReject(u32),
 
+    /// The process binary is compressed, but could not be decompressed. Either
+    /// the kernel was built without the `compressed_apps` feature or the
+    /// compressed data is corrupt.
+    DecompressionFailure,
+
+    /// A process was loaded after boot, but every slot of the processes array
+    /// is already in use.
+    NoProcessSlot,
+
     /// Process loading error due (likely) to a bug in the kernel. If you get
     /// this error please open a bug report.
     InternalError,
@@ -161,6 +174,12 @@ impl fmt::Debug for ProcessLoadError {
                 write!(f, "Credentials index {} rejected.", index)
             }
 
+            ProcessLoadError::DecompressionFailure => {
+                write!(f, "Could not decompress process binary.")
+            }
+
+            ProcessLoadError::NoProcessSlot => write!(f, "No free process slot."),
+
             ProcessLoadError::InternalError => write!(f, "Error in kernel. Likely a bug."),
         }
     }
@@ -179,6 +198,35 @@ pub fn load_and_check_processes<KR: KernelResources<C>, C: Chip>(
     chip: &'static C,
     app_flash: &'static [u8],
     app_memory: &'static mut [u8],
+    procs: &'static mut [Option<&'static dyn Process>],
+    fault_policy: &'static dyn ProcessFaultPolicy,
+    capability_management: &dyn ProcessManagementCapability,
+) -> Result<(), ProcessLoadError>
+where
+    <KR as KernelResources<C>>::CredentialsCheckingPolicy: 'static,
+{
+    load_and_check_processes_from_regions(
+        kernel,
+        kernel_resources,
+        chip,
+        &[app_flash],
+        app_memory,
+        procs,
+        fault_policy,
+        capability_management,
+    )
+}
+
+/// Like `load_and_check_processes()`, but loads processes from several
+/// regions of flash, e.g. internal flash and external flash that apps
+/// execute in place from (see `hil::xip`). The regions are searched in order.
+#[inline(always)]
+pub fn load_and_check_processes_from_regions<KR: KernelResources<C>, C: Chip>(
+    kernel: &'static Kernel,
+    kernel_resources: &KR,
+    chip: &'static C,
+    flash_regions: &[&'static [u8]],
+    app_memory: &'static mut [u8],
     mut procs: &'static mut [Option<&'static dyn Process>],
     fault_policy: &'static dyn ProcessFaultPolicy,
     capability_management: &dyn ProcessManagementCapability,
@@ -189,7 +237,7 @@ where
     load_processes_from_flash(
         kernel,
         chip,
-        app_flash,
+        flash_regions,
         app_memory,
         &mut procs,
         fault_policy,
@@ -213,6 +261,30 @@ pub fn load_processes<C: Chip>(
     chip: &'static C,
     app_flash: &'static [u8],
     app_memory: &'static mut [u8],
+    procs: &'static mut [Option<&'static dyn Process>],
+    fault_policy: &'static dyn ProcessFaultPolicy,
+    capability_management: &dyn ProcessManagementCapability,
+) -> Result<(), ProcessLoadError> {
+    load_processes_from_regions(
+        kernel,
+        chip,
+        &[app_flash],
+        app_memory,
+        procs,
+        fault_policy,
+        capability_management,
+    )
+}
+
+/// Like `load_processes()`, but loads processes from several regions of
+/// flash, e.g. internal flash and external flash that apps execute in place
+/// from (see `hil::xip`). The regions are searched in order.
+#[inline(always)]
+pub fn load_processes_from_regions<C: Chip>(
+    kernel: &'static Kernel,
+    chip: &'static C,
+    flash_regions: &[&'static [u8]],
+    app_memory: &'static mut [u8],
     mut procs: &'static mut [Option<&'static dyn Process>],
     fault_policy: &'static dyn ProcessFaultPolicy,
     capability_management: &dyn ProcessManagementCapability,
@@ -220,7 +292,7 @@ pub fn load_processes<C: Chip>(
     load_processes_from_flash(
         kernel,
         chip,
-        app_flash,
+        flash_regions,
         app_memory,
         &mut procs,
         fault_policy,
@@ -233,7 +305,8 @@ pub fn load_processes<C: Chip>(
     let capability = create_capability!(ProcessApprovalCapability);
     for proc in procs.iter() {
         let res = proc.map(|p| {
-            p.mark_credentials_pass(None, ShortID::LocallyUnique, &capability)
+            let short_id = kernel.get_checker().short_id(p, ShortID::LocallyUnique);
+            p.mark_credentials_pass(None, short_id, &capability)
                 .or(Err(ProcessLoadError::InternalError))?;
             if config::CONFIG.debug_process_credentials {
                 debug!("Running {}", p.get_process_name());
@@ -251,8 +324,8 @@ pub fn load_processes<C: Chip>(
 /// processes. This is the default template for loading processes, but a board
 /// is able to create its own `load_processes()` function and use that instead.
 ///
-/// Processes are found in flash starting from the given address and iterating
-/// through Tock Binary Format (TBF) headers. Processes are given memory out of
+/// Processes are found in each of the flash regions starting from its start
+/// address and iterating through Tock Binary Format (TBF) headers. Processes are given memory out of
 /// the `app_memory` buffer until either the memory is exhausted or the
 /// allocated number of processes are created. This buffer is a non-static slice,
 /// ensuring that this code cannot hold onto the slice past the end of this function
@@ -273,12 +346,43 @@ pub fn load_processes<C: Chip>(
 fn load_processes_from_flash<C: Chip>(
     kernel: &'static Kernel,
     chip: &'static C,
-    app_flash: &'static [u8],
+    flash_regions: &[&'static [u8]],
     app_memory: &'static mut [u8],
     procs: &mut &'static mut [Option<&'static dyn Process>],
     fault_policy: &'static dyn ProcessFaultPolicy,
     capability: &dyn ProcessManagementCapability,
 ) -> Result<(), ProcessLoadError> {
+    let mut remaining_memory = app_memory;
+    // Try to discover up to `procs.len()` processes in flash.
+    let mut index = 0;
+    for app_flash in flash_regions {
+        (index, remaining_memory) = load_processes_from_region(
+            kernel,
+            chip,
+            app_flash,
+            remaining_memory,
+            procs,
+            index,
+            fault_policy,
+            capability,
+        );
+    }
+    Ok(())
+}
+
+/// Load processes from the flash region `app_flash` into `procs`, starting at
+/// `index`. Returns the index of the next free slot of `procs` and the
+/// remaining memory.
+fn load_processes_from_region<C: Chip>(
+    kernel: &'static Kernel,
+    chip: &'static C,
+    app_flash: &'static [u8],
+    app_memory: &'static mut [u8],
+    procs: &mut &'static mut [Option<&'static dyn Process>],
+    mut index: usize,
+    fault_policy: &'static dyn ProcessFaultPolicy,
+    capability: &dyn ProcessManagementCapability,
+) -> (usize, &'static mut [u8]) {
     if config::CONFIG.debug_load_processes {
         debug!(
             "Loading processes from flash={:#010X}-{:#010X} into sram={:#010X}-{:#010X}",
@@ -291,8 +395,6 @@ fn load_processes_from_flash<C: Chip>(
 
     let mut remaining_flash = app_flash;
     let mut remaining_memory = app_memory;
-    // Try to discover up to `procs.len()` processes in flash.
-    let mut index = 0;
     let num_procs = procs.len();
     while index < num_procs {
         let load_result = load_process(
@@ -320,16 +422,17 @@ fn load_processes_from_flash<C: Chip>(
                     }
                 }
             }
-            Err((_new_flash, _new_mem, err)) => {
+            Err((_new_flash, new_mem, err)) => {
+                remaining_memory = new_mem;
                 if config::CONFIG.debug_load_processes {
                     debug!("No more processes to load: {:?}.", err);
                 }
-                // No more processes to load.
+                // No more processes to load from this region.
                 break;
             }
         }
     }
-    Ok(())
+    (index, remaining_memory)
 }
 
 /// Use `checker` to transition `procs` from the
@@ -434,6 +537,16 @@ fn load_process<C: Chip>(
         Some(val) => val,
     };
 
+    // Compressed apps run from a decompressed copy.
+    let entry_flash = if config::CONFIG.compressed_apps && header_length > 0 {
+        match decompress_process_binary(entry_flash, header_length as usize, version) {
+            Ok(entry) => entry,
+            Err(err) => return Err((remaining_flash, app_memory, err)),
+        }
+    } else {
+        entry_flash
+    };
+
     // Need to reassign remaining_memory in every iteration so the compiler
     // knows it will not be re-borrowed.
     let (process_option, remaining_memory) = if header_length > 0 {
@@ -485,3 +598,286 @@ fn load_process<C: Chip>(
     };
     Ok((remaining_flash, remaining_memory, process_option))
 }
+
+/// RAM that compressed apps are decompressed into, see
+/// `set_decompression_buffer()`.
+static mut DECOMPRESSION_BUFFER: Option<&'static mut [u8]> = None;
+
+/// Heatshrink parameters of compressed apps.
+const COMPRESSION_WINDOW_BITS: usize = 8;
+const COMPRESSION_LOOKAHEAD_BITS: usize = 4;
+
+/// Set the RAM that the process loader decompresses compressed apps into if
+/// the kernel is built with the `compressed_apps` feature. Each compressed
+/// app takes the part of `buffer` it needs when it is loaded, and runs from
+/// there.
+///
+/// The board must call this before loading processes. `buffer` must not
+/// overlap the RAM given to processes, and processes must be able to execute
+/// code in it.
+pub unsafe fn set_decompression_buffer(buffer: &'static mut [u8]) {
+    *addr_of_mut!(DECOMPRESSION_BUFFER) = Some(buffer);
+}
+
+/// If the TBF object `entry_flash` is compressed, decompress it into the
+/// decompression buffer and return the decompressed object. Otherwise return
+/// `entry_flash`.
+fn decompress_process_binary(
+    entry_flash: &'static [u8],
+    header_length: usize,
+    version: u16,
+) -> Result<&'static [u8], ProcessLoadError> {
+    // Header errors and disabled processes are handled when the process is
+    // created.
+    let compressed = entry_flash
+        .get(..header_length)
+        .and_then(|header| tock_tbf::parse::parse_tbf_header(header, version).ok())
+        .is_some_and(|header| header.enabled() && header.compressed());
+    if !compressed {
+        return Ok(entry_flash);
+    }
+
+    // SAFETY: the buffer is only set during board setup, and processes are
+    // loaded from the kernel loop, so this is not reentered.
+    let buffer = match unsafe { (*addr_of_mut!(DECOMPRESSION_BUFFER)).take() } {
+        Some(buffer) => buffer,
+        None => return Err(ProcessLoadError::NotEnoughMemory),
+    };
+    let (header, payload) = entry_flash.split_at(header_length);
+
+    let result = buffer
+        .get_mut(header_length..)
+        .ok_or(ErrorCode::SIZE)
+        .and_then(|output| {
+            heatshrink::decompress(
+                payload,
+                output,
+                COMPRESSION_WINDOW_BITS,
+                COMPRESSION_LOOKAHEAD_BITS,
+            )
+        });
+    let entry_length = match result {
+        Ok(length) => header_length + length,
+        Err(err) => {
+            unsafe { *addr_of_mut!(DECOMPRESSION_BUFFER) = Some(buffer) };
+            return Err(match err {
+                ErrorCode::SIZE => ProcessLoadError::NotEnoughMemory,
+                _ => ProcessLoadError::DecompressionFailure,
+            });
+        }
+    };
+
+    // As for apps in flash, the MPU may require the object to be aligned to
+    // its length rounded up to a power of two. The RAM skipped for that is
+    // not used.
+    let alignment = entry_length.next_power_of_two();
+    let padding = (alignment - buffer.as_ptr() as usize % alignment) % alignment;
+    if padding + entry_length > buffer.len() {
+        unsafe { *addr_of_mut!(DECOMPRESSION_BUFFER) = Some(buffer) };
+        return Err(ProcessLoadError::NotEnoughMemory);
+    }
+    buffer.copy_within(header_length..entry_length, padding + header_length);
+    buffer[padding..padding + header_length].copy_from_slice(header);
+
+    let (entry, remaining) = buffer[padding..].split_at_mut(entry_length);
+    unsafe { *addr_of_mut!(DECOMPRESSION_BUFFER) = Some(remaining) };
+    if config::CONFIG.debug_load_processes {
+        debug!(
+            "Decompressed process from flash={:#010X} into sram={:#010X}-{:#010X}",
+            entry_flash.as_ptr() as usize,
+            entry.as_ptr() as usize,
+            entry.as_ptr() as usize + entry.len() - 1
+        );
+    }
+    Ok(entry)
+}
+
+/// Length of a TBF padding header, see `padding_header()`.
+pub const PADDING_HEADER_LENGTH: usize = 16;
+
+/// Returns a TBF header for a padding object of `length` bytes in total.
+/// Process discovery skips over padding, which lets a binary that must be
+/// aligned start after a gap while keeping the TBF objects in flash
+/// back-to-back.
+pub fn padding_header(length: u32) -> [u8; PADDING_HEADER_LENGTH] {
+    let version: u32 = 2;
+    let header_length = PADDING_HEADER_LENGTH as u32;
+    let flags: u32 = 0;
+    let words = [version | (header_length << 16), length, flags];
+    let checksum = words.iter().fold(0, |checksum, word| checksum ^ word);
+
+    let mut header = [0; PADDING_HEADER_LENGTH];
+    for (chunk, word) in header
+        .chunks_exact_mut(4)
+        .zip(words.iter().chain(core::iter::once(&checksum)))
+    {
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+    header
+}
+
+/// Interface for loading processes while the kernel is running, used by
+/// capsules that receive application binaries (e.g. from userspace or over
+/// a UART) and write them to flash.
+pub trait DynamicProcessLoading {
+    /// Find where a TBF object of `length` bytes can be written in the app
+    /// flash region. Returns `(padding_start, binary_start)`: the binary must
+    /// be written at `binary_start`, and if `padding_start` is smaller, a
+    /// padding header (see `padding_header()`) covering the gap must be
+    /// written at `padding_start` so processes are still found on the next
+    /// boot. Both are absolute addresses.
+    fn find_flash(&self, length: usize) -> Result<(usize, usize), ProcessLoadError>;
+
+    /// Create a process from the TBF object stored at the absolute address
+    /// `address` and check its credentials. The kernel starts the process
+    /// once its credentials are approved.
+    fn load(&self, address: usize) -> Result<ProcessId, ProcessLoadError>;
+}
+
+/// Loads processes after boot from TBF objects written into the unused end
+/// of the app flash region.
+///
+/// New processes are placed in empty slots of the processes array and
+/// allocate their RAM from a pool reserved for processes loaded at runtime.
+/// This RAM is not reclaimed when a process is terminated. The credentials of
+/// a new process are checked with the policy passed to
+/// `load_and_check_processes()`; if the board used `load_processes()`
+/// instead, new processes are approved without checking.
+///
+/// The binary is placed at an address aligned to its length rounded up to a
+/// power of two, as some MPUs require.
+pub struct DynamicProcessLoader<C: 'static + Chip> {
+    kernel: &'static Kernel,
+    chip: &'static C,
+    app_flash: &'static [u8],
+    app_memory: TakeCell<'static, [u8]>,
+    procs: TakeCell<'static, [Option<&'static dyn Process>]>,
+    fault_policy: &'static dyn ProcessFaultPolicy,
+}
+
+impl<C: 'static + Chip> DynamicProcessLoader<C> {
+    /// `procs` must be the processes array passed to `Kernel::new()`, and
+    /// `app_memory` must not overlap the RAM given to the processes loaded at
+    /// boot.
+    pub fn new(
+        kernel: &'static Kernel,
+        chip: &'static C,
+        app_flash: &'static [u8],
+        app_memory: &'static mut [u8],
+        procs: &'static mut [Option<&'static dyn Process>],
+        fault_policy: &'static dyn ProcessFaultPolicy,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> DynamicProcessLoader<C> {
+        DynamicProcessLoader {
+            kernel,
+            chip,
+            app_flash,
+            app_memory: TakeCell::new(app_memory),
+            procs: TakeCell::new(procs),
+            fault_policy,
+        }
+    }
+
+    /// Offset in the app flash region just past the last TBF object.
+    fn installed_length(&self) -> usize {
+        let mut offset = 0;
+        while let Some(header) = self
+            .app_flash
+            .get(offset..offset + 8)
+            .and_then(|h| h.try_into().ok())
+        {
+            let entry_length = match tock_tbf::parse::parse_tbf_header_lengths(header) {
+                Ok((_, _, entry_length)) => entry_length,
+                Err(tock_tbf::types::InitialTbfParseError::InvalidHeader(entry_length)) => {
+                    entry_length
+                }
+                Err(tock_tbf::types::InitialTbfParseError::UnableToParse) => break,
+            };
+            if entry_length == 0 {
+                break;
+            }
+            offset += entry_length as usize;
+        }
+        offset.min(self.app_flash.len())
+    }
+
+    fn approve(&self, process: &'static dyn Process) -> Result<(), ProcessLoadError> {
+        let checker = self.kernel.get_checker();
+        if checker.has_policy() {
+            checker.check_new_processes()?;
+        } else {
+            let capability = create_capability!(ProcessApprovalCapability);
+            let short_id = checker.short_id(process, ShortID::LocallyUnique);
+            process
+                .mark_credentials_pass(None, short_id, &capability)
+                .or(Err(ProcessLoadError::InternalError))?;
+        }
+        Ok(())
+    }
+}
+
+impl<C: 'static + Chip> DynamicProcessLoading for DynamicProcessLoader<C> {
+    fn find_flash(&self, length: usize) -> Result<(usize, usize), ProcessLoadError> {
+        if length < PADDING_HEADER_LENGTH {
+            return Err(ProcessLoadError::TbfHeaderNotFound);
+        }
+        let base = self.app_flash.as_ptr() as usize;
+        let padding_start = base + self.installed_length();
+        let alignment = length.next_power_of_two();
+        let binary_start = if padding_start % alignment == 0 {
+            padding_start
+        } else {
+            // Leave room for the padding header.
+            (padding_start + PADDING_HEADER_LENGTH + alignment - 1) & !(alignment - 1)
+        };
+        if binary_start + length > base + self.app_flash.len() {
+            return Err(ProcessLoadError::NotEnoughFlash);
+        }
+        Ok((padding_start, binary_start))
+    }
+
+    fn load(&self, address: usize) -> Result<ProcessId, ProcessLoadError> {
+        let app_flash = address
+            .checked_sub(self.app_flash.as_ptr() as usize)
+            .and_then(|offset| self.app_flash.get(offset..))
+            .ok_or(ProcessLoadError::NotEnoughFlash)?;
+        let process = self
+            .procs
+            .map_or(Err(ProcessLoadError::InternalError), |procs| {
+                let index = procs
+                    .iter()
+                    .position(|p| p.is_none())
+                    .ok_or(ProcessLoadError::NoProcessSlot)?;
+                let app_memory = self
+                    .app_memory
+                    .take()
+                    .ok_or(ProcessLoadError::InternalError)?;
+                let capability = create_capability!(ProcessManagementCapability);
+                let (process, remaining_memory, result) = match load_process(
+                    self.kernel,
+                    self.chip,
+                    app_flash,
+                    app_memory,
+                    index,
+                    self.fault_policy,
+                    &capability,
+                ) {
+                    Ok((_, memory, Some(process))) => (Some(process), memory, Ok(())),
+                    // Padding or a disabled process.
+                    Ok((_, memory, None)) => {
+                        (None, memory, Err(ProcessLoadError::TbfHeaderNotFound))
+                    }
+                    Err((_, memory, err)) => (None, memory, Err(err)),
+                };
+                self.app_memory.replace(remaining_memory);
+                result?;
+                procs[index] = process;
+                process.ok_or(ProcessLoadError::InternalError)
+            })?;
+        if config::CONFIG.debug_load_processes {
+            debug!("Loaded process {} at runtime", process.get_process_name());
+        }
+        self.approve(process)?;
+        Ok(process.processid())
+    }
+}
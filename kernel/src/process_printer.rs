@@ -61,6 +61,16 @@ pub trait ProcessPrinter {
         writer: &mut dyn BinaryWrite,
         context: Option<ProcessPrinterContext>,
     ) -> Option<ProcessPrinterContext>;
+
+    /// Print the memory layout of a process to the `writer`: its flash and
+    /// RAM regions, app break, stack pointer and MPU regions. Uses `context`
+    /// and the return value in the same way as `print_overview()`.
+    fn print_memory_map(
+        &self,
+        process: &dyn Process,
+        writer: &mut dyn BinaryWrite,
+        context: Option<ProcessPrinterContext>,
+    ) -> Option<ProcessPrinterContext>;
 }
 
 /// A Process Printer that displays a process as a human-readable string.
@@ -111,6 +121,8 @@ impl ProcessPrinter for ProcessPrinterText {
         let syscall_count = process.debug_syscall_count();
         let dropped_upcall_count = process.debug_dropped_upcall_count();
         let restart_count = process.get_restart_count();
+        let cpu_time_us = process.debug_cpu_time_us();
+        let context_switch_count = process.debug_context_switch_count();
 
         let addresses = process.get_addresses();
         let sizes = process.get_sizes();
@@ -129,6 +141,7 @@ impl ProcessPrinter for ProcessPrinterText {
                  𝐀𝐩𝐩: {}   -   [{:?}]\
                  \r\n Events Queued: {}   Syscall Count: {}   Dropped Upcall Count: {}\
                  \r\n Restart Count: {}\
+                 \r\n CPU Time (us): {}   Context Switches: {}\
                  \r\n",
             process.get_process_name(),
             process.get_state(),
@@ -136,6 +149,34 @@ impl ProcessPrinter for ProcessPrinterText {
             syscall_count,
             dropped_upcall_count,
             restart_count,
+            cpu_time_us,
+            context_switch_count,
+        ));
+
+        let grant_stats = process.debug_grant_stats();
+        let _ = bww.write_fmt(format_args!(
+            " Grant High Water: {}   Failed Grant Allocations: {}",
+            grant_stats.high_water, grant_stats.failed_allocations,
+        ));
+        let _ = match grant_stats.last_failed_driver_num {
+            Some(driver_num) => bww.write_fmt(format_args!(" (last {:#x})\r\n", driver_num)),
+            None => bww.write_str("\r\n"),
+        };
+
+        let high_water = process.debug_memory_high_water();
+        let _ = bww.write_str(" Stack High Water: ");
+        let _ = match high_water.stack {
+            Some(stack) => bww.write_fmt(format_args!("{}", stack)),
+            None => bww.write_str("?"),
+        };
+        let _ = bww.write_str("   Heap High Water: ");
+        let _ = match high_water.heap {
+            Some(heap) => bww.write_fmt(format_args!("{}", heap)),
+            None => bww.write_str("?"),
+        };
+        let _ = bww.write_fmt(format_args!(
+            "   App Memory High Water: {} of {}\r\n",
+            high_water.app_memory, high_water.allocated,
         ));
 
         let _ = match process.debug_syscall_last() {
@@ -298,6 +339,73 @@ impl ProcessPrinter for ProcessPrinterText {
             None
         }
     }
+
+    // Uses the same approach as `print_overview()`: the whole message is
+    // formatted on every call and the bytes sent on earlier calls are skipped.
+    fn print_memory_map(
+        &self,
+        process: &dyn Process,
+        writer: &mut dyn BinaryWrite,
+        context: Option<ProcessPrinterContext>,
+    ) -> Option<ProcessPrinterContext> {
+        let offset = context.map_or(0, |c| c.offset);
+        let addresses = process.get_addresses();
+
+        let mut bww = WriteToBinaryOffsetWrapper::new(writer);
+        bww.set_offset(offset);
+
+        let _ = bww.write_fmt(format_args!(
+            "\
+                 𝐌𝐞𝐦𝐨𝐫𝐲 𝐌𝐚𝐩: {}\
+                 \r\n Flash:       {:#010X} - {:#010X}   Protected: {:#010X} - {:#010X}\
+                 \r\n RAM:         {:#010X} - {:#010X}\
+                 \r\n App Break:   {:#010X}   Grant Start: {:#010X}\
+                 \r\n",
+            process.get_process_name(),
+            addresses.flash_start,
+            addresses.flash_end,
+            addresses.flash_start,
+            addresses.flash_non_protected_start,
+            addresses.sram_start,
+            addresses.sram_end,
+            addresses.sram_app_brk,
+            addresses.sram_grant_start,
+        ));
+
+        let _ = bww.write_str(" Heap Start:  ");
+        let _ = match addresses.sram_heap_start {
+            Some(heap_start) => bww.write_fmt(format_args!("{:#010X}", heap_start)),
+            None => bww.write_str("??????????"),
+        };
+        let _ = bww.write_str("   Stack Top:   ");
+        let _ = match addresses.sram_stack_top {
+            Some(stack_top) => bww.write_fmt(format_args!("{:#010X}", stack_top)),
+            None => bww.write_str("??????????"),
+        };
+        let _ = bww.write_str("\r\n Stack Ptr:   ");
+        let _ = match process.get_pc_and_sp() {
+            Some((_, sp)) => bww.write_fmt(format_args!("{:#010X}", sp)),
+            None => bww.write_str("??????????"),
+        };
+        let _ = bww.write_str("   Lowest SP:   ");
+        let _ = match addresses.sram_stack_bottom {
+            Some(stack_bottom) => bww.write_fmt(format_args!("{:#010X}\r\n", stack_bottom)),
+            None => bww.write_str("??????????\r\n"),
+        };
+
+        if !bww.bytes_remaining() {
+            process.print_mpu_config(&mut bww);
+            let _ = bww.write_str("\r\n");
+        }
+
+        if bww.bytes_remaining() {
+            Some(ProcessPrinterContext {
+                offset: bww.get_index(),
+            })
+        } else {
+            None
+        }
+    }
 }
 
 /// If `size` is greater than `allocated` then it returns a warning string to
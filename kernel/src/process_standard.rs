@@ -23,8 +23,8 @@ use crate::kernel::Kernel;
 use crate::platform::chip::Chip;
 use crate::platform::mpu::{self, MPU};
 use crate::process::{Error, FunctionCall, FunctionCallSource, Process, State, Task};
-use crate::process::{FaultAction, ProcessCustomGrantIdentifier, ProcessId};
-use crate::process::{ProcessAddresses, ProcessSizes, ShortID};
+use crate::process::{FaultAction, FaultReason, ProcessCustomGrantIdentifier, ProcessId};
+use crate::process::{GrantStats, MemoryHighWater, ProcessAddresses, ProcessSizes, ShortID};
 use crate::process_loading::ProcessLoadError;
 use crate::process_policies::ProcessFaultPolicy;
 use crate::processbuffer::{ReadOnlyProcessBuffer, ReadWriteProcessBuffer};
@@ -35,6 +35,11 @@ use crate::utilities::cells::{MapCell, NumericCellExt, OptionalCell};
 
 use tock_tbf::types::{CommandPermissions, TbfFooterV2Credentials};
 
+/// How many bytes of the accepted credentials are kept to detect changes.
+/// This covers a SHA-256 digest, and enough of a signature that it cannot be
+/// replaced by another one without being noticed.
+const CREDENTIALS_COPY_LEN: usize = 32;
+
 /// State for helping with debugging apps.
 ///
 /// These pointers and counters are not strictly required for kernel operation,
@@ -74,6 +79,64 @@ struct ProcessStandardDebug {
     /// How many times this process has been paused because it exceeded its
     /// timeslice.
     timeslice_expiration_count: usize,
+
+    /// How long the process has run for, in microseconds.
+    cpu_time_us: u64,
+
+    /// How many times the kernel has switched to this process.
+    context_switch_count: usize,
+
+    /// How many bytes of the grant region are allocated.
+    grant_bytes: usize,
+
+    /// The most bytes of the grant region that have ever been allocated,
+    /// including before the process restarted.
+    grant_high_water_bytes: usize,
+
+    /// How many grant allocations failed because the grant region was full,
+    /// including before the process restarted.
+    grant_failed_allocations: usize,
+
+    /// The driver number of the last grant that could not be allocated.
+    grant_last_failed_driver_num: Option<usize>,
+
+    /// The deepest the stack has grown below its start, in bytes, including
+    /// before the process restarted.
+    stack_high_water_bytes: usize,
+
+    /// The most bytes between the heap start and the app break, including
+    /// before the process restarted.
+    heap_high_water_bytes: usize,
+
+    /// The highest the app break has been, including before the process
+    /// restarted.
+    app_break_high_water: Option<*const u8>,
+}
+
+impl ProcessStandardDebug {
+    /// Update the high-water marks of the heap and of process-accessible
+    /// memory with a new app break.
+    fn app_break_moved(&mut self, app_break: *const u8) {
+        if self
+            .app_break_high_water
+            .map_or(true, |high| app_break > high)
+        {
+            self.app_break_high_water = Some(app_break);
+        }
+        if let Some(heap_start) = self.app_heap_start_pointer {
+            let heap = (app_break as usize).saturating_sub(heap_start as usize);
+            self.heap_high_water_bytes = self.heap_high_water_bytes.max(heap);
+        }
+    }
+
+    /// Update the stack high-water mark with the lowest stack pointer seen.
+    fn stack_moved(&mut self) {
+        if let (Some(start), Some(min)) = (self.app_stack_start_pointer, self.app_stack_min_pointer)
+        {
+            let stack = (start as usize).saturating_sub(min as usize);
+            self.stack_high_water_bytes = self.stack_high_water_bytes.max(stack);
+        }
+    }
 }
 
 /// Entry that is stored in the grant pointer table at the top of process
@@ -186,6 +249,10 @@ pub struct ProcessStandard<'a, C: 'static + Chip> {
     /// Credentials that were accepted to make this process runnable.
     credentials: OptionalCell<TbfFooterV2Credentials>,
 
+    /// Copy of the start of the accepted credentials data, taken when the
+    /// process was approved, to detect later changes of the credentials.
+    credentials_copy: Cell<[u8; CREDENTIALS_COPY_LEN]>,
+
     /// State saved on behalf of the process each time the app switches to the
     /// kernel.
     stored_state:
@@ -220,6 +287,9 @@ pub struct ProcessStandard<'a, C: 'static + Chip> {
     /// determine if the process should be restarted or not.
     restart_count: Cell<usize>,
 
+    /// Why this process last faulted.
+    fault_reason: Cell<Option<FaultReason>>,
+
     /// The completion code set by the process when it last exited, restarted,
     /// or was terminated. If the process is has never terminated, then the
     /// `OptionalCell` will be empty (i.e. `None`). If the process has exited,
@@ -261,6 +331,7 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
             match tasks.enqueue(task) {
                 true => {
                     // The task has been successfully enqueued.
+                    self.kernel.process_work_added();
                     Ok(())
                 }
                 false => {
@@ -294,7 +365,13 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
 
         self.state.set(State::CredentialsApproved);
         self.app_id.set(short_app_id);
-        credentials.map(|c| self.credentials.replace(c));
+        credentials.map(|c| {
+            let mut copy = [0; CREDENTIALS_COPY_LEN];
+            let len = cmp::min(c.data().len(), CREDENTIALS_COPY_LEN);
+            copy[..len].copy_from_slice(&c.data()[..len]);
+            self.credentials_copy.set(copy);
+            self.credentials.replace(c)
+        });
         Ok(())
     }
 
@@ -308,6 +385,13 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
         c
     }
 
+    fn credentials_unchanged(&self) -> bool {
+        self.get_credentials().map_or(true, |c| {
+            let len = cmp::min(c.data().len(), CREDENTIALS_COPY_LEN);
+            c.data()[..len] == self.credentials_copy.get()[..len]
+        })
+    }
+
     // Enqueue the initialization function of a process onto its task
     // list; this is used to start a process. Should only be called
     // when a process is in the `State::Terminated` or
@@ -341,9 +425,12 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
     }
 
     fn ready(&self) -> bool {
-        self.tasks.map_or(false, |ring_buf| ring_buf.has_elements())
-            || self.state.get() == State::Running
-            || self.state.get() == State::CredentialsApproved
+        match self.state.get() {
+            // Tasks of a stopped process stay queued until it is resumed.
+            State::StoppedRunning | State::StoppedYielded => false,
+            State::Running | State::CredentialsApproved => true,
+            _ => self.tasks.map_or(false, |ring_buf| ring_buf.has_elements()),
+        }
     }
 
     fn remove_pending_upcalls(&self, upcall_id: UpcallId) {
@@ -400,11 +487,13 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
         match self.state.get() {
             State::StoppedRunning => self.state.set(State::Running),
             State::StoppedYielded => self.state.set(State::Yielded),
-            _ => {} // Do nothing
+            _ => return, // Do nothing
         }
+        self.kernel.process_work_added();
     }
 
-    fn set_fault_state(&self) {
+    fn set_fault_state(&self, reason: FaultReason) {
+        self.fault_reason.set(Some(reason));
         // Use the per-process fault policy to determine what action the kernel
         // should take since the process faulted.
         let action = self.fault_policy.action(self);
@@ -498,6 +587,10 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
         self.restart_count.get()
     }
 
+    fn get_fault_reason(&self) -> Option<FaultReason> {
+        self.fault_reason.get()
+    }
+
     fn has_tasks(&self) -> bool {
         self.tasks.map_or(false, |tasks| tasks.has_elements())
     }
@@ -560,6 +653,7 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
         if heap_pointer >= self.mem_start() && heap_pointer < self.mem_end() {
             self.debug.map(|debug| {
                 debug.app_heap_start_pointer = Some(heap_pointer);
+                debug.app_break_moved(self.app_break.get());
             });
         }
     }
@@ -655,6 +749,7 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
                 } else {
                     let old_break = self.app_break.get();
                     self.app_break.set(new_break);
+                    self.debug.map(|debug| debug.app_break_moved(new_break));
                     self.chip.mpu().configure_mpu(&config, &self.processid());
                     Ok(old_break)
                 }
@@ -887,6 +982,8 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
             })
         } else {
             // Could not allocate the memory for the grant region.
+            self.debug
+                .map(|debug| debug.grant_last_failed_driver_num = Some(driver_num));
             false
         }
     }
@@ -1013,6 +1110,74 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
         })
     }
 
+    fn grant_allocation(&self, grant_num: usize) -> Option<(usize, usize)> {
+        self.grant_pointers.map_or(None, |grant_pointers| {
+            let grant_entry = grant_pointers
+                .get(grant_num)
+                .filter(|grant_entry| !grant_entry.grant_ptr.is_null())?;
+            let start = grant_entry.grant_ptr as usize;
+            // Grants are allocated downwards from the process struct, so a
+            // grant ends where the lowest allocation above it starts. Custom
+            // grants are not in the table, their bytes are counted with the
+            // grant allocated after them.
+            let end = grant_pointers
+                .iter()
+                .map(|grant_entry| grant_entry.grant_ptr as usize)
+                .filter(|ptr| *ptr > start)
+                .min()
+                .unwrap_or(self as *const Self as usize);
+            Some((grant_entry.driver_num, end - start))
+        })
+    }
+
+    fn debug_grant_stats(&self) -> GrantStats {
+        let available =
+            (self.kernel_memory_break.get() as usize).saturating_sub(self.app_break.get() as usize);
+        self.debug.map_or(
+            GrantStats {
+                allocated: 0,
+                high_water: 0,
+                available,
+                failed_allocations: 0,
+                last_failed_driver_num: None,
+            },
+            |debug| GrantStats {
+                allocated: debug.grant_bytes,
+                high_water: debug.grant_high_water_bytes,
+                available,
+                failed_allocations: debug.grant_failed_allocations,
+                last_failed_driver_num: debug.grant_last_failed_driver_num,
+            },
+        )
+    }
+
+    fn debug_memory_high_water(&self) -> MemoryHighWater {
+        let app_break = self.app_break.get();
+        let app_break_high_water = self
+            .debug
+            .map_or(None, |debug| debug.app_break_high_water)
+            .map_or(app_break, |high| high.max(app_break));
+        let app_memory = (app_break_high_water as usize).saturating_sub(self.mem_start() as usize);
+        self.debug.map_or(
+            MemoryHighWater {
+                stack: None,
+                heap: None,
+                app_memory,
+                allocated: self.memory_len,
+            },
+            |debug| MemoryHighWater {
+                stack: debug
+                    .app_stack_start_pointer
+                    .map(|_| debug.stack_high_water_bytes),
+                heap: debug
+                    .app_heap_start_pointer
+                    .map(|_| debug.heap_high_water_bytes),
+                app_memory,
+                allocated: self.memory_len,
+            },
+        )
+    }
+
     fn lookup_grant_from_driver_num(&self, driver_num: usize) -> Result<usize, Error> {
         self.grant_pointers
             .map_or(Err(Error::KernelError), |grant_pointers| {
@@ -1070,13 +1235,13 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
                 // If we get an `Err`, then the UKB implementation could not set
                 // the return value, likely because the process's stack is no
                 // longer accessible to it. All we can do is fault.
-                self.set_fault_state();
+                self.set_fault_state(FaultReason::Stack);
             }
 
             None => {
                 // We should never be here since `stored_state` should always be
                 // occupied.
-                self.set_fault_state();
+                self.set_fault_state(FaultReason::ContextSwitch);
             }
         }
     }
@@ -1117,13 +1282,13 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
                 // the details of the particular architecture this is running
                 // on. This process has essentially faulted, so we mark it as
                 // such.
-                self.set_fault_state();
+                self.set_fault_state(FaultReason::Stack);
             }
 
             None => {
                 // We should never be here since `stored_state` should always be
                 // occupied.
-                self.set_fault_state();
+                self.set_fault_state(FaultReason::ContextSwitch);
             }
         }
     }
@@ -1161,6 +1326,7 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
                         }
                     }
                 }
+                debug.stack_moved();
             });
         });
 
@@ -1185,6 +1351,23 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
             .map(|debug| debug.timeslice_expiration_count += 1);
     }
 
+    fn debug_cpu_time_us(&self) -> u64 {
+        self.debug.map_or(0, |debug| debug.cpu_time_us)
+    }
+
+    fn debug_cpu_time_used(&self, us: u32) {
+        self.debug
+            .map(|debug| debug.cpu_time_us = debug.cpu_time_us.saturating_add(us as u64));
+    }
+
+    fn debug_context_switch_count(&self) -> usize {
+        self.debug.map_or(0, |debug| debug.context_switch_count)
+    }
+
+    fn debug_context_switched(&self) {
+        self.debug.map(|debug| debug.context_switch_count += 1);
+    }
+
     fn debug_syscall_called(&self, last_syscall: Syscall) {
         self.debug.map(|debug| {
             debug.syscall_count += 1;
@@ -1321,6 +1504,26 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
         });
     }
 
+    fn get_pc_and_sp(&self) -> Option<(usize, usize)> {
+        self.stored_state.map_or(None, |stored_state| {
+            // We guarantee the memory bounds pointers provided to the UKB are
+            // correct.
+            unsafe {
+                self.chip.userspace_kernel_boundary().get_pc_and_sp(
+                    self.mem_start(),
+                    self.app_break.get(),
+                    stored_state,
+                )
+            }
+        })
+    }
+
+    fn print_mpu_config(&self, writer: &mut dyn Write) {
+        self.mpu_config.map(|config| {
+            let _ = writer.write_fmt(format_args!("{}", config));
+        });
+    }
+
     fn get_stored_state(&self, out: &mut [u8]) -> Result<usize, ErrorCode> {
         self.stored_state
             .map(|stored_state| {
@@ -1392,6 +1595,18 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
             return Ok((None, remaining_memory));
         }
 
+        // Without the `compressed_apps` feature, the process loader did not
+        // decompress the binary, so it cannot run.
+        if tbf_header.compressed() && !config::CONFIG.compressed_apps {
+            if config::CONFIG.debug_load_processes {
+                debug!(
+                    "Process {:?} is compressed, but the kernel cannot decompress it",
+                    process_name.unwrap_or("(no name)")
+                );
+            }
+            return Err((ProcessLoadError::DecompressionFailure, remaining_memory));
+        }
+
         if let Some((major, minor)) = tbf_header.get_kernel_version() {
             // If the `KernelVersion` header is present, we read the requested
             // kernel version and compare it to the running kernel version.
@@ -1734,6 +1949,7 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
         process.grant_pointers = MapCell::new(grant_pointers);
 
         process.credentials = OptionalCell::empty();
+        process.credentials_copy = Cell::new([0; CREDENTIALS_COPY_LEN]);
 
         process.footers = footer_region;
         process.flash = app_flash;
@@ -1744,6 +1960,7 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
         process.state = Cell::new(State::CredentialsUnchecked);
         process.fault_policy = fault_policy;
         process.restart_count = Cell::new(0);
+        process.fault_reason = Cell::new(None);
         process.completion_code = OptionalCell::empty();
 
         process.mpu_config = MapCell::new(mpu_config);
@@ -1768,6 +1985,15 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
             last_syscall: None,
             dropped_upcall_count: 0,
             timeslice_expiration_count: 0,
+            cpu_time_us: 0,
+            context_switch_count: 0,
+            grant_bytes: 0,
+            grant_high_water_bytes: 0,
+            grant_failed_allocations: 0,
+            grant_last_failed_driver_num: None,
+            stack_high_water_bytes: 0,
+            heap_high_water_bytes: 0,
+            app_break_high_water: None,
         });
 
         // Handle any architecture-specific requirements for a new process.
@@ -1831,6 +2057,9 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
             debug.last_syscall = None;
             debug.dropped_upcall_count = 0;
             debug.timeslice_expiration_count = 0;
+            debug.cpu_time_us = 0;
+            debug.context_switch_count = 0;
+            debug.grant_bytes = 0;
         });
 
         // Reset MPU region configuration.
@@ -2012,9 +2241,11 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
 
             // Verify there is space for this allocation
             if new_break < self.app_break.get() {
+                self.debug.map(|debug| debug.grant_failed_allocations += 1);
                 None
                 // Verify it didn't wrap around
             } else if new_break > self.kernel_memory_break.get() {
+                self.debug.map(|debug| debug.grant_failed_allocations += 1);
                 None
                 // Verify this is compatible with the MPU.
             } else if let Err(_) = self.chip.mpu().update_app_memory_region(
@@ -2023,10 +2254,19 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
                 mpu::Permissions::ReadWriteOnly,
                 &mut config,
             ) {
+                self.debug.map(|debug| debug.grant_failed_allocations += 1);
                 None
             } else {
                 // Allocation is valid.
 
+                // Account for the allocation, including alignment padding.
+                let allocated = self.kernel_memory_break.get() as usize - new_break as usize;
+                self.debug.map(|debug| {
+                    debug.grant_bytes += allocated;
+                    debug.grant_high_water_bytes =
+                        cmp::max(debug.grant_high_water_bytes, debug.grant_bytes);
+                });
+
                 // We always allocate down, so we must lower the
                 // kernel_memory_break.
                 self.kernel_memory_break.set(new_break);
@@ -0,0 +1,206 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Mailbox that tells the next boot why the kernel rebooted.
+//!
+//! Before the kernel resets the chip, or when it panics, it records why in a
+//! small block of RAM that is not initialized at boot (the `.noinit` section
+//! of the kernel layout), together with a short message. A warm reset keeps
+//! the contents of RAM, so after the next boot the board calls `report()`,
+//! which prints the recorded reason and clears the mailbox. If nothing was
+//! recorded (e.g. the chip was powered on or the watchdog expired), the
+//! reset cause the chip reports is printed instead.
+//!
+//! ```rust,ignore
+//! kernel::reboot_reason::report(Some(pm));
+//! ```
+
+use core::fmt::{self, Write};
+use core::mem::MaybeUninit;
+use core::ptr::{self, addr_of_mut};
+use core::str;
+
+use crate::debug;
+use crate::hil::reset::{ResetCause, ResetCauses};
+
+/// Longest message kept across the reboot, in bytes.
+pub const MESSAGE_LEN: usize = 96;
+
+/// Marks a valid mailbox, "RBRN".
+const MAGIC: u32 = 0x5242_524e;
+
+/// Why the kernel rebooted.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RebootReason {
+    /// The kernel panicked and the chip was reset afterwards, usually by
+    /// the watchdog.
+    Panic = 1,
+    /// The kernel reset the chip because the watchdog was about to expire.
+    Watchdog = 2,
+    /// A user asked for the reset, e.g. from the process console.
+    UserCommand = 3,
+    /// The chip was reset to update the firmware.
+    Update = 4,
+}
+
+impl RebootReason {
+    fn from_u32(reason: u32) -> Option<RebootReason> {
+        match reason {
+            1 => Some(RebootReason::Panic),
+            2 => Some(RebootReason::Watchdog),
+            3 => Some(RebootReason::UserCommand),
+            4 => Some(RebootReason::Update),
+            _ => None,
+        }
+    }
+}
+
+#[repr(C)]
+struct Mailbox {
+    magic: u32,
+    reason: u32,
+    len: u32,
+    message: [u8; MESSAGE_LEN],
+    checksum: u32,
+}
+
+impl Mailbox {
+    fn checksum(&self) -> u32 {
+        self.message.iter().fold(
+            self.magic ^ self.reason.rotate_left(8) ^ self.len.rotate_left(16),
+            |sum, b| sum.rotate_left(5) ^ u32::from(*b),
+        )
+    }
+}
+
+#[cfg_attr(target_os = "none", link_section = ".noinit")]
+static mut MAILBOX: MaybeUninit<Mailbox> = MaybeUninit::uninit();
+
+/// Why the kernel last rebooted, as found by `report()`.
+#[derive(Copy, Clone)]
+pub struct RebootRecord {
+    /// The reason the kernel recorded, if any.
+    pub reason: Option<RebootReason>,
+    /// The reset cause the chip reports, if the board provided it.
+    pub cause: Option<ResetCause>,
+    message: [u8; MESSAGE_LEN],
+    len: usize,
+}
+
+impl RebootRecord {
+    /// The message recorded with the reason.
+    pub fn message(&self) -> &str {
+        str::from_utf8(&self.message[..self.len]).unwrap_or("")
+    }
+}
+
+impl fmt::Display for RebootRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.reason, self.cause) {
+            (Some(reason), Some(cause)) => write!(f, "{:?} (reset cause: {:?})", reason, cause)?,
+            (Some(reason), None) => write!(f, "{:?}", reason)?,
+            (None, Some(cause)) => write!(f, "{:?}", cause)?,
+            (None, None) => write!(f, "unknown")?,
+        }
+        if self.len > 0 {
+            write!(f, ": {}", self.message())?;
+        }
+        Ok(())
+    }
+}
+
+/// Truncates what is written to the message of the mailbox.
+struct MessageWriter<'a> {
+    message: &'a mut [u8; MESSAGE_LEN],
+    len: usize,
+}
+
+impl Write for MessageWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            let end = self.len + c.len_utf8();
+            if end > MESSAGE_LEN {
+                return Err(fmt::Error);
+            }
+            c.encode_utf8(&mut self.message[self.len..end]);
+            self.len = end;
+        }
+        Ok(())
+    }
+}
+
+/// Record why the kernel is about to reboot. Messages longer than
+/// `MESSAGE_LEN` bytes are truncated.
+pub fn record(reason: RebootReason, message: fmt::Arguments) {
+    // Safety: the kernel is single threaded, and the mailbox is only read
+    // by `report()` at boot.
+    let mailbox = unsafe { &mut *addr_of_mut!(MAILBOX) };
+    let mut message_buffer = [0; MESSAGE_LEN];
+    let mut writer = MessageWriter {
+        message: &mut message_buffer,
+        len: 0,
+    };
+    let _ = writer.write_fmt(message);
+    let len = writer.len;
+    let mailbox = mailbox.write(Mailbox {
+        magic: MAGIC,
+        reason: reason as u32,
+        len: len as u32,
+        message: message_buffer,
+        checksum: 0,
+    });
+    mailbox.checksum = mailbox.checksum();
+}
+
+/// Read and clear the mailbox. Returns `None` if no valid reason was
+/// recorded.
+fn take() -> Option<(RebootReason, [u8; MESSAGE_LEN], usize)> {
+    // Safety: only the address of the mailbox is taken here.
+    let mailbox = unsafe { addr_of_mut!(MAILBOX) } as *mut Mailbox;
+    // Safety: every bit pattern is a valid `Mailbox`, and the kernel is
+    // single threaded. The RAM was not initialized by the kernel, so read it
+    // without letting the compiler assume its contents.
+    let contents = unsafe { ptr::read_volatile(mailbox) };
+    unsafe { ptr::write_volatile(addr_of_mut!((*mailbox).magic), 0) };
+
+    let valid = contents.magic == MAGIC
+        && contents.checksum == contents.checksum()
+        && (contents.len as usize) <= MESSAGE_LEN;
+    RebootReason::from_u32(contents.reason)
+        .filter(|_| valid)
+        .map(|reason| (reason, contents.message, contents.len as usize))
+}
+
+static mut LAST_REBOOT: Option<RebootRecord> = None;
+
+/// Find out why the kernel rebooted, print it with `debug!()` and clear the
+/// mailbox. Boards call this once during initialization, after the debug
+/// writer is set up. `chip` provides the reset cause the hardware recorded.
+pub fn report(chip: Option<&dyn ResetCauses>) -> RebootRecord {
+    let cause = chip.map(|chip| chip.last_reset_cause());
+    let record = match take() {
+        Some((reason, message, len)) => RebootRecord {
+            reason: Some(reason),
+            cause,
+            message,
+            len,
+        },
+        None => RebootRecord {
+            reason: None,
+            cause,
+            message: [0; MESSAGE_LEN],
+            len: 0,
+        },
+    };
+    // Safety: the kernel is single threaded.
+    unsafe { *addr_of_mut!(LAST_REBOOT) = Some(record) };
+    debug!("Reboot reason: {}", record);
+    record
+}
+
+/// Why the kernel last rebooted, if the board called `report()`.
+pub fn last() -> Option<RebootRecord> {
+    // Safety: the kernel is single threaded.
+    unsafe { *addr_of_mut!(LAST_REBOOT) }
+}
@@ -5,6 +5,7 @@
 //! Interface for Tock kernel schedulers.
 
 pub mod cooperative;
+pub mod edf;
 pub mod mlfq;
 pub mod priority;
 pub mod round_robin;
@@ -13,6 +14,7 @@ use crate::deferred_call::DeferredCall;
 use crate::kernel::StoppedExecutingReason;
 use crate::platform::chip::Chip;
 use crate::process::ProcessId;
+use crate::work_queue::Work;
 
 /// Trait which any scheduler must implement.
 pub trait Scheduler<C: Chip> {
@@ -35,9 +37,9 @@ pub trait Scheduler<C: Chip> {
     fn result(&self, result: StoppedExecutingReason, execution_time_us: Option<u32>);
 
     /// Tell the scheduler to execute kernel work such as interrupt bottom
-    /// halves and dynamic deferred calls. Most schedulers will use this default
-    /// implementation, but schedulers which at times wish to defer interrupt
-    /// handling will reimplement it.
+    /// halves, dynamic deferred calls and chunks of queued work. Most
+    /// schedulers will use this default implementation, but schedulers which
+    /// at times wish to defer interrupt handling will reimplement it.
     ///
     /// Providing this interface allows schedulers to fully manage how the main
     /// kernel loop executes. For example, a more advanced scheduler that
@@ -53,6 +55,11 @@ pub trait Scheduler<C: Chip> {
         while DeferredCall::has_tasks() && !chip.has_pending_interrupts() {
             DeferredCall::service_next_pending();
         }
+        // Queued work runs one chunk at a time, only once interrupts and
+        // deferred calls are handled.
+        if !chip.has_pending_interrupts() && !DeferredCall::has_tasks() {
+            Work::run_next();
+        }
     }
 
     /// Ask the scheduler whether to take a break from executing userspace
@@ -60,7 +67,7 @@ pub trait Scheduler<C: Chip> {
     /// implementation, which always prioritizes kernel work, but schedulers
     /// that wish to defer interrupt handling may reimplement it.
     unsafe fn do_kernel_work_now(&self, chip: &C) -> bool {
-        chip.has_pending_interrupts() || DeferredCall::has_tasks()
+        chip.has_pending_interrupts() || DeferredCall::has_tasks() || Work::has_work()
     }
 
     /// Ask the scheduler whether to continue trying to execute a process.
@@ -0,0 +1,312 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Earliest Deadline First Scheduler for Tock
+//!
+//! Processes can declare a periodic deadline (a period and a relative
+//! deadline within that period) through a system call driver, see
+//! `capsules_extra::deadline`. Each time a process finishes the work of
+//! one period (a "job"), it reports so and its deadline moves to the next
+//! period.
+//!
+//! Among the processes that are ready, the scheduler always runs the one
+//! with the earliest absolute deadline. Processes that have not declared a
+//! deadline are best-effort: they only run when no process with a deadline
+//! is ready, in round-robin order with a fixed timeslice. A process with a
+//! deadline is run cooperatively, but is preempted as soon as a process
+//! with an earlier deadline becomes ready.
+//!
+//! When a job does not complete before its deadline, the miss is counted
+//! and reported once to the `DeadlineMissClient`, if any. This is meant for
+//! diagnostics: the scheduler does not otherwise penalize the process, and
+//! a late job still has the most urgent deadline.
+//!
+//! Deadlines are tracked in ticks of the scheduler's clock, so periods must
+//! be shorter than half the wraparound time of that clock.
+
+use core::cell::Cell;
+
+use crate::deferred_call::DeferredCall;
+use crate::hil::time::{self, ConvertTicks, Ticks};
+use crate::kernel::{Kernel, StoppedExecutingReason};
+use crate::platform::chip::Chip;
+use crate::process::ProcessId;
+use crate::scheduler::{Scheduler, SchedulingDecision};
+use crate::utilities::cells::OptionalCell;
+use crate::ErrorCode;
+
+/// Interface used by the deadline system call driver to declare deadlines.
+pub trait DeadlineControl {
+    /// Declare that `process_id` runs periodic jobs every `period_us`, each of
+    /// which must complete within `deadline_us` of its release. The first job
+    /// is released now.
+    fn set_deadline(
+        &self,
+        process_id: ProcessId,
+        period_us: u32,
+        deadline_us: u32,
+    ) -> Result<(), ErrorCode>;
+
+    /// Make `process_id` a best-effort process again.
+    fn clear_deadline(&self, process_id: ProcessId) -> Result<(), ErrorCode>;
+
+    /// The current job of `process_id` completed, move to the next period.
+    fn job_complete(&self, process_id: ProcessId) -> Result<(), ErrorCode>;
+
+    /// Number of deadlines `process_id` missed since declaring its deadline.
+    fn deadline_misses(&self, process_id: ProcessId) -> Result<u32, ErrorCode>;
+}
+
+/// Callback for diagnostics when a process misses a deadline.
+pub trait DeadlineMissClient {
+    /// `process_id` did not complete its job by its deadline. `misses` is
+    /// the total number of deadlines it missed so far.
+    fn deadline_missed(&self, process_id: ProcessId, misses: u32);
+}
+
+struct EdfProcessState<T: Ticks> {
+    /// Process the deadline belongs to, empty for best-effort slots.
+    process: OptionalCell<ProcessId>,
+    period: Cell<T>,
+    relative_deadline: Cell<T>,
+    /// A point in time no later than now and no later than the release of
+    /// the current job, used to tell deadlines in the past from deadlines in
+    /// the future.
+    reference: Cell<T>,
+    /// Release time of the current job.
+    release: Cell<T>,
+    /// Absolute deadline of the current job.
+    deadline: Cell<T>,
+    miss_reported: Cell<bool>,
+    misses: Cell<u32>,
+}
+
+impl<T: Ticks> EdfProcessState<T> {
+    fn new() -> EdfProcessState<T> {
+        let zero = T::from(0);
+        EdfProcessState {
+            process: OptionalCell::empty(),
+            period: Cell::new(zero),
+            relative_deadline: Cell::new(zero),
+            reference: Cell::new(zero),
+            release: Cell::new(zero),
+            deadline: Cell::new(zero),
+            miss_reported: Cell::new(false),
+            misses: Cell::new(0),
+        }
+    }
+
+    fn is_for(&self, process_id: ProcessId) -> bool {
+        self.process.map_or(false, |p| *p == process_id)
+    }
+
+    fn missed(&self, now: T) -> bool {
+        !now.within_range(self.reference.get(), self.deadline.get())
+    }
+
+    /// Ticks left until the deadline, 0 if it passed already.
+    fn slack(&self, now: T) -> T {
+        if self.missed(now) {
+            T::from(0)
+        } else {
+            self.deadline.get().wrapping_sub(now)
+        }
+    }
+}
+
+/// Earliest deadline first scheduler for up to `NUM_PROCS` processes.
+pub struct EDFSched<A: 'static + time::Time, const NUM_PROCS: usize> {
+    kernel: &'static Kernel,
+    time: &'static A,
+    states: [EdfProcessState<A::Ticks>; NUM_PROCS],
+    /// Process currently running, and whether it has a deadline.
+    running: OptionalCell<(ProcessId, bool)>,
+    /// Index of the last best-effort process that ran, to round-robin them.
+    last_best_effort: Cell<usize>,
+    miss_client: OptionalCell<&'static dyn DeadlineMissClient>,
+}
+
+impl<A: 'static + time::Time, const NUM_PROCS: usize> EDFSched<A, NUM_PROCS> {
+    /// Timeslice given to best-effort processes.
+    pub const BEST_EFFORT_TIMESLICE_US: u32 = 10000;
+
+    pub fn new(kernel: &'static Kernel, time: &'static A) -> Self {
+        Self {
+            kernel,
+            time,
+            states: core::array::from_fn(|_| EdfProcessState::new()),
+            running: OptionalCell::empty(),
+            last_best_effort: Cell::new(NUM_PROCS),
+            miss_client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_miss_client(&self, client: &'static dyn DeadlineMissClient) {
+        self.miss_client.set(client);
+    }
+
+    /// Deadline state of `process_id`, if it declared a deadline.
+    fn state(&self, process_id: ProcessId) -> Option<&EdfProcessState<A::Ticks>> {
+        self.states
+            .get(process_id.index)
+            .filter(|state| state.is_for(process_id))
+    }
+
+    /// Record and report a missed deadline, once per job.
+    fn check_miss(&self, state: &EdfProcessState<A::Ticks>, now: A::Ticks) {
+        if !state.miss_reported.get() && state.missed(now) {
+            state.miss_reported.set(true);
+            let misses = state.misses.get().wrapping_add(1);
+            state.misses.set(misses);
+            if let Some(process_id) = state.process.extract() {
+                self.miss_client
+                    .map(|client| client.deadline_missed(process_id, misses));
+            }
+        }
+    }
+
+    /// The ready process with the earliest deadline, and its slack.
+    fn earliest_ready(&self, now: A::Ticks) -> Option<(ProcessId, A::Ticks)> {
+        let mut earliest: Option<(ProcessId, A::Ticks)> = None;
+        for process in self.kernel.get_process_iter() {
+            let process_id = process.processid();
+            if let Some(state) = self.state(process_id) {
+                self.check_miss(state, now);
+                if process.ready() {
+                    let slack = state.slack(now);
+                    if earliest.map_or(true, |(_, best)| slack < best) {
+                        earliest = Some((process_id, slack));
+                    }
+                }
+            }
+        }
+        earliest
+    }
+
+    /// The next ready best-effort process, in round-robin order.
+    fn next_best_effort(&self) -> Option<ProcessId> {
+        let last = self.last_best_effort.get();
+        let mut first = None;
+        for process in self.kernel.get_process_iter() {
+            let process_id = process.processid();
+            if self.state(process_id).is_some() || !process.ready() {
+                continue;
+            }
+            if process_id.index > last || last >= NUM_PROCS {
+                return Some(process_id);
+            }
+            if first.is_none() {
+                first = Some(process_id);
+            }
+        }
+        first
+    }
+}
+
+impl<A: 'static + time::Time, const NUM_PROCS: usize> DeadlineControl for EDFSched<A, NUM_PROCS> {
+    fn set_deadline(
+        &self,
+        process_id: ProcessId,
+        period_us: u32,
+        deadline_us: u32,
+    ) -> Result<(), ErrorCode> {
+        if period_us == 0 || deadline_us == 0 || deadline_us > period_us {
+            return Err(ErrorCode::INVAL);
+        }
+        let state = self.states.get(process_id.index).ok_or(ErrorCode::NOMEM)?;
+        let period = self.time.ticks_from_us(period_us);
+        if period > A::Ticks::half_max_value() {
+            return Err(ErrorCode::SIZE);
+        }
+        let relative_deadline = self.time.ticks_from_us(deadline_us);
+        let now = self.time.now();
+        state.process.set(process_id);
+        state.period.set(period);
+        state.relative_deadline.set(relative_deadline);
+        state.reference.set(now);
+        state.release.set(now);
+        state.deadline.set(now.wrapping_add(relative_deadline));
+        state.miss_reported.set(false);
+        state.misses.set(0);
+        Ok(())
+    }
+
+    fn clear_deadline(&self, process_id: ProcessId) -> Result<(), ErrorCode> {
+        let state = self.state(process_id).ok_or(ErrorCode::INVAL)?;
+        state.process.clear();
+        Ok(())
+    }
+
+    fn job_complete(&self, process_id: ProcessId) -> Result<(), ErrorCode> {
+        let state = self.state(process_id).ok_or(ErrorCode::INVAL)?;
+        let now = self.time.now();
+        self.check_miss(state, now);
+
+        let mut release = state.release.get().wrapping_add(state.period.get());
+        let mut deadline = release.wrapping_add(state.relative_deadline.get());
+        if !now.within_range(state.reference.get(), deadline) {
+            // Running late by more than a period: restart the period
+            // sequence now rather than accumulating missed jobs.
+            release = now;
+            deadline = now.wrapping_add(state.relative_deadline.get());
+        }
+        state.reference.set(now);
+        state.release.set(release);
+        state.deadline.set(deadline);
+        state.miss_reported.set(false);
+        Ok(())
+    }
+
+    fn deadline_misses(&self, process_id: ProcessId) -> Result<u32, ErrorCode> {
+        self.state(process_id)
+            .map(|state| state.misses.get())
+            .ok_or(ErrorCode::INVAL)
+    }
+}
+
+impl<A: 'static + time::Time, C: Chip, const NUM_PROCS: usize> Scheduler<C>
+    for EDFSched<A, NUM_PROCS>
+{
+    fn next(&self) -> SchedulingDecision {
+        let now = self.time.now();
+        if let Some((process_id, _)) = self.earliest_ready(now) {
+            self.running.set((process_id, true));
+            return SchedulingDecision::RunProcess((process_id, None));
+        }
+        match self.next_best_effort() {
+            Some(process_id) => {
+                self.last_best_effort.set(process_id.index);
+                self.running.set((process_id, false));
+                SchedulingDecision::RunProcess((process_id, Some(Self::BEST_EFFORT_TIMESLICE_US)))
+            }
+            None => {
+                self.running.clear();
+                SchedulingDecision::TrySleep
+            }
+        }
+    }
+
+    unsafe fn continue_process(&self, _: ProcessId, chip: &C) -> bool {
+        if chip.has_pending_interrupts() || DeferredCall::has_tasks() {
+            return false;
+        }
+        // A system call or an interrupt may have made a process with an
+        // earlier deadline ready.
+        let now = self.time.now();
+        match (self.running.extract(), self.earliest_ready(now)) {
+            (None, _) | (_, None) => true,
+            (Some((_, false)), Some(_)) => false,
+            (Some((running, true)), Some((earliest, slack))) => {
+                earliest == running
+                    || self
+                        .state(running)
+                        .map_or(false, |state| state.slack(now) <= slack)
+            }
+        }
+    }
+
+    fn result(&self, _: StoppedExecutingReason, _: Option<u32>) {
+        self.running.clear();
+    }
+}
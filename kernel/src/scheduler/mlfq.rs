@@ -20,6 +20,21 @@
 //!           reduced (i.e., it moves down one queue).
 //! - Rule 5: After some time period S, move all the jobs in the system to the
 //!           topmost queue.
+//!
+//! The number of queues, the timeslice of each queue and the priority boost
+//! period S are configurable with `MLFQSched::new_with_config()`.
+//! `MLFQSched::new()` uses three queues with timeslices of 10, 20 and 50 ms
+//! and boosts every 5 seconds.
+//!
+//! Like the other schedulers, it is selected through
+//! `KernelResources::Scheduler`:
+//!
+//! ```rust,ignore
+//! impl KernelResources<Chip> for Platform {
+//!     type Scheduler = MLFQSched<'static, VirtualMuxAlarm<'static, Rtc<'static>>>;
+//!     // ...
+//! }
+//! ```
 
 use core::cell::Cell;
 
@@ -60,45 +75,59 @@ impl<'a> ListNode<'a, MLFQProcessNode<'a>> for MLFQProcessNode<'a> {
     }
 }
 
-pub struct MLFQSched<'a, A: 'static + time::Alarm<'static>> {
+pub struct MLFQSched<'a, A: 'static + time::Alarm<'static>, const NUM_QUEUES: usize = 3> {
     alarm: &'static A,
-    pub processes: [List<'a, MLFQProcessNode<'a>>; 3], // Using Self::NUM_QUEUES causes rustc to crash..
+    pub processes: [List<'a, MLFQProcessNode<'a>>; NUM_QUEUES],
+    /// Timeslice of each queue, highest priority first.
+    timeslices_us: [u32; NUM_QUEUES],
+    /// How often to restore all processes to max priority, 0 to never.
+    priority_boost_period_ms: u32,
     next_reset: Cell<A::Ticks>,
     last_reset_check: Cell<A::Ticks>,
-    last_timeslice: Cell<u32>,
     last_queue_idx: Cell<usize>,
 }
 
 impl<'a, A: 'static + time::Alarm<'static>> MLFQSched<'a, A> {
-    /// How often to restore all processes to max priority
+    /// Default period to restore all processes to max priority.
     pub const PRIORITY_REFRESH_PERIOD_MS: u32 = 5000;
-    pub const NUM_QUEUES: usize = 3;
+    /// Default timeslices of the three queues.
+    pub const TIMESLICES_US: [u32; 3] = [10000, 20000, 50000];
 
     pub fn new(alarm: &'static A) -> Self {
+        Self::new_with_config(alarm, Self::TIMESLICES_US, Self::PRIORITY_REFRESH_PERIOD_MS)
+    }
+}
+
+impl<'a, A: 'static + time::Alarm<'static>, const NUM_QUEUES: usize> MLFQSched<'a, A, NUM_QUEUES> {
+    /// Number of priority queues.
+    pub const NUM_QUEUES: usize = NUM_QUEUES;
+
+    /// Create a scheduler with `NUM_QUEUES` queues. `timeslices_us` holds the
+    /// timeslice of each queue, highest priority first, and all processes are
+    /// moved back to the highest priority queue every
+    /// `priority_boost_period_ms` (never if 0).
+    pub fn new_with_config(
+        alarm: &'static A,
+        timeslices_us: [u32; NUM_QUEUES],
+        priority_boost_period_ms: u32,
+    ) -> Self {
+        assert!(NUM_QUEUES > 0, "MLFQ needs at least one queue");
         Self {
             alarm,
-            processes: [List::new(), List::new(), List::new()],
+            processes: core::array::from_fn(|_| List::new()),
+            timeslices_us,
+            priority_boost_period_ms,
             next_reset: Cell::new(A::Ticks::from(0)),
             last_reset_check: Cell::new(A::Ticks::from(0)),
-            last_timeslice: Cell::new(0),
             last_queue_idx: Cell::new(0),
         }
     }
 
-    fn get_timeslice_us(&self, queue_idx: usize) -> u32 {
-        match queue_idx {
-            0 => 10000,
-            1 => 20000,
-            2 => 50000,
-            _ => panic!("invalid queue idx"),
-        }
-    }
-
     fn redeem_all_procs(&self) {
         for queue in self.processes.iter().skip(1) {
-            match queue.pop_head() {
-                Some(proc) => self.processes[0].push_tail(proc),
-                None => continue,
+            while let Some(proc) = queue.pop_head() {
+                proc.state.us_used_this_queue.set(0);
+                self.processes[0].push_tail(proc);
             }
         }
     }
@@ -134,7 +163,9 @@ impl<'a, A: 'static + time::Alarm<'static>> MLFQSched<'a, A> {
     }
 }
 
-impl<'a, A: 'static + time::Alarm<'static>, C: Chip> Scheduler<C> for MLFQSched<'a, A> {
+impl<'a, A: 'static + time::Alarm<'static>, C: Chip, const NUM_QUEUES: usize> Scheduler<C>
+    for MLFQSched<'a, A, NUM_QUEUES>
+{
     fn next(&self) -> SchedulingDecision {
         let now = self.alarm.now();
         let next_reset = self.next_reset.get();
@@ -142,10 +173,10 @@ impl<'a, A: 'static + time::Alarm<'static>, C: Chip> Scheduler<C> for MLFQSched<
 
         // storing last reset check is necessary to avoid missing a reset when the underlying
         // alarm wraps around
-        if !now.within_range(last_reset_check, next_reset) {
+        if self.priority_boost_period_ms > 0 && !now.within_range(last_reset_check, next_reset) {
             // Promote all processes to highest priority queue
             self.next_reset
-                .set(now.wrapping_add(self.alarm.ticks_from_ms(Self::PRIORITY_REFRESH_PERIOD_MS)));
+                .set(now.wrapping_add(self.alarm.ticks_from_ms(self.priority_boost_period_ms)));
             self.redeem_all_procs();
         }
         self.last_reset_check.set(now);
@@ -154,10 +185,10 @@ impl<'a, A: 'static + time::Alarm<'static>, C: Chip> Scheduler<C> for MLFQSched<
             return SchedulingDecision::TrySleep;
         }
         let node_ref = node_ref_opt.unwrap();
-        let timeslice = self.get_timeslice_us(queue_idx) - node_ref.state.us_used_this_queue.get();
+        let timeslice =
+            self.timeslices_us[queue_idx].saturating_sub(node_ref.state.us_used_this_queue.get());
         let next = node_ref.proc.unwrap().processid();
         self.last_queue_idx.set(queue_idx);
-        self.last_timeslice.set(timeslice);
 
         SchedulingDecision::RunProcess((next, Some(timeslice)))
     }
@@ -167,15 +198,18 @@ impl<'a, A: 'static + time::Alarm<'static>, C: Chip> Scheduler<C> for MLFQSched<
         let queue_idx = self.last_queue_idx.get();
         // Last executed node will always be at head of its queue
         let node_ref = self.processes[queue_idx].head().unwrap();
-        node_ref
+        let used = node_ref
             .state
             .us_used_this_queue
-            .set(self.last_timeslice.get() - execution_time_us);
+            .get()
+            .saturating_add(execution_time_us);
+        node_ref.state.us_used_this_queue.set(used);
 
-        let punish = result == StoppedExecutingReason::TimesliceExpired;
+        let punish = result == StoppedExecutingReason::TimesliceExpired
+            || used >= self.timeslices_us[queue_idx];
         if punish {
             node_ref.state.us_used_this_queue.set(0);
-            let next_queue = if queue_idx == Self::NUM_QUEUES - 1 {
+            let next_queue = if queue_idx == NUM_QUEUES - 1 {
                 queue_idx
             } else {
                 queue_idx + 1
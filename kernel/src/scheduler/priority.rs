@@ -13,18 +13,47 @@
 //! process running to not be the highest priority process at any point while it
 //! is running. The only way for a process to longer be the highest priority is
 //! for an interrupt to occur, which will cause the process to stop running.
+//!
+//! Boards can give the scheduler a table of priority levels, one per slot of
+//! the `PROCESSES` array, with `set_priority_levels()`. Processes with a lower
+//! level run first, and processes with the same level keep their array order.
+//! All levels start at 0, so the table does not change the order until a level
+//! is changed at runtime through `PriorityControl`, e.g. by the process
+//! console.
+
+use core::cell::Cell;
 
+use crate::capabilities::ProcessManagementCapability;
 use crate::deferred_call::DeferredCall;
 use crate::kernel::{Kernel, StoppedExecutingReason};
 use crate::platform::chip::Chip;
-use crate::process::ProcessId;
+use crate::process::{Process, ProcessId};
 use crate::scheduler::{Scheduler, SchedulingDecision};
 use crate::utilities::cells::OptionalCell;
+use crate::ErrorCode;
+
+/// Runtime access to the priority levels of processes.
+pub trait PriorityControl {
+    /// Return the priority level of a process, or `None` if levels are not
+    /// configurable for it. Lower levels run first.
+    fn priority(&self, processid: ProcessId) -> Option<u8>;
+
+    /// Set the priority level of a process. Returns `NOSUPPORT` if the
+    /// scheduler has no priority table, and `INVAL` if the process does not
+    /// fit in it.
+    fn set_priority(
+        &self,
+        processid: ProcessId,
+        level: u8,
+        capability: &dyn ProcessManagementCapability,
+    ) -> Result<(), ErrorCode>;
+}
 
 /// Priority scheduler based on the order of processes in the `PROCESSES` array.
 pub struct PrioritySched {
     kernel: &'static Kernel,
     running: OptionalCell<ProcessId>,
+    levels: OptionalCell<&'static [Cell<u8>]>,
 }
 
 impl PrioritySched {
@@ -32,20 +61,62 @@ impl PrioritySched {
         Self {
             kernel,
             running: OptionalCell::empty(),
+            levels: OptionalCell::empty(),
         }
     }
+
+    /// Provide the table of priority levels, indexed by process slot.
+    pub fn set_priority_levels(&self, levels: &'static [Cell<u8>]) {
+        self.levels.set(levels);
+    }
+
+    /// The sort key of a process: its level, then its position in the
+    /// process array.
+    fn rank(&self, processid: ProcessId) -> (u8, usize) {
+        let level = self.levels.map_or(0, |levels| {
+            levels.get(processid.index).map_or(0, |level| level.get())
+        });
+        (level, processid.index)
+    }
+
+    /// The ready process that should run first, if any.
+    fn highest_ready(&self) -> Option<&dyn Process> {
+        self.kernel
+            .get_process_iter()
+            .filter(|proc| proc.ready())
+            .min_by_key(|proc| self.rank(proc.processid()))
+    }
+}
+
+impl PriorityControl for PrioritySched {
+    fn priority(&self, processid: ProcessId) -> Option<u8> {
+        self.levels
+            .and_then(|levels| levels.get(processid.index).map(|level| level.get()))
+    }
+
+    fn set_priority(
+        &self,
+        processid: ProcessId,
+        level: u8,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> Result<(), ErrorCode> {
+        self.levels.map_or(Err(ErrorCode::NOSUPPORT), |levels| {
+            levels
+                .get(processid.index)
+                .map_or(Err(ErrorCode::INVAL), |cell| {
+                    cell.set(level);
+                    Ok(())
+                })
+        })
+    }
 }
 
 impl<C: Chip> Scheduler<C> for PrioritySched {
     fn next(&self) -> SchedulingDecision {
-        // Iterates in-order through the process array, always running the
-        // first process it finds that is ready to run. This enforces the
-        // priorities of all processes.
-        let next = self
-            .kernel
-            .get_process_iter()
-            .find(|&proc| proc.ready())
-            .map_or(None, |proc| Some(proc.processid()));
+        // Always run the ready process with the lowest level, and among
+        // those the first in the process array. This enforces the priorities
+        // of all processes.
+        let next = self.highest_ready().map(|proc| proc.processid());
         self.running.insert(next);
 
         next.map_or(SchedulingDecision::TrySleep, |next| {
@@ -60,15 +131,11 @@ impl<C: Chip> Scheduler<C> for PrioritySched {
         // this app is communicating via IPC with a higher priority app.
         !(chip.has_pending_interrupts()
             || DeferredCall::has_tasks()
-            || self
-                .kernel
-                .get_process_iter()
-                .find(|proc| proc.ready())
-                .map_or(false, |ready_proc| {
-                    self.running.map_or(false, |running| {
-                        ready_proc.processid().index < running.index
-                    })
-                }))
+            || self.highest_ready().is_some_and(|ready_proc| {
+                self.running.map_or(false, |running| {
+                    self.rank(ready_proc.processid()) < self.rank(*running)
+                })
+            }))
     }
 
     fn result(&self, _: StoppedExecutingReason, _: Option<u32>) {
@@ -6,6 +6,8 @@
 
 use core::cmp;
 
+use crate::capabilities;
+
 /// List of storage permissions for a storage user.
 ///
 /// These identifiers signify what permissions a storage user has. The storage
@@ -56,6 +58,15 @@ impl StoragePermissions {
         }
     }
 
+    /// Create the permissions of a kernel storage user, which can only read and
+    /// update the items it stores itself, under `storage_id`. Processes should
+    /// not be given `storage_id` in their own permissions.
+    pub fn new_kernel(storage_id: u32, _cap: &dyn capabilities::KernelStorageCapability) -> Self {
+        let mut storage_ids = [0; 8];
+        storage_ids[0] = storage_id;
+        Self::new(1, storage_ids, 1, storage_ids, Some(storage_id))
+    }
+
     /// Check if this permission object grants read access to the specified
     /// `storage_id`. Returns `true` if access is permitted, `false` otherwise.
     pub fn check_read_permission(&self, storage_id: u32) -> bool {
@@ -211,6 +211,42 @@ impl Syscall {
             Err(_) => None,
         }
     }
+
+    /// The driver the system call is for. Returns `None` for the system call
+    /// classes that are not handled by a driver.
+    pub fn driver_number(&self) -> Option<usize> {
+        match *self {
+            Syscall::Subscribe { driver_number, .. }
+            | Syscall::Command { driver_number, .. }
+            | Syscall::ReadWriteAllow { driver_number, .. }
+            | Syscall::UserspaceReadableAllow { driver_number, .. }
+            | Syscall::ReadOnlyAllow { driver_number, .. } => Some(driver_number),
+            Syscall::Yield { .. } | Syscall::Memop { .. } | Syscall::Exit { .. } => None,
+        }
+    }
+}
+
+// ---------- SYSCALL TRACING ----------
+
+/// Observer of the system calls processes make, e.g. to record a trace for
+/// debugging. Installed with `Kernel::set_syscall_tracer()`.
+///
+/// Both functions are called synchronously in the kernel loop, so they
+/// should return quickly.
+pub trait SyscallTracer {
+    /// The process `process_id` made the system call `syscall`. This is
+    /// called before the system call filter is applied.
+    fn syscall_entry(&self, process_id: process::ProcessId, syscall: &Syscall);
+
+    /// The system call the process `process_id` made returns `result`.
+    /// `yield`, and `exit` unless it is invalid, do not return a value, so
+    /// this is not called for them.
+    fn syscall_exit(
+        &self,
+        process_id: process::ProcessId,
+        syscall: &Syscall,
+        result: &SyscallReturn,
+    );
 }
 
 // ---------- SYSCALL RETURN VALUE ENCODING ----------
@@ -349,6 +385,21 @@ impl SyscallReturn {
         }
     }
 
+    /// The error code of a failure, `None` for any success type.
+    pub fn error_code(&self) -> Option<ErrorCode> {
+        match *self {
+            SyscallReturn::Failure(e)
+            | SyscallReturn::FailureU32(e, _)
+            | SyscallReturn::FailureU32U32(e, _, _)
+            | SyscallReturn::FailureU64(e, _)
+            | SyscallReturn::AllowReadWriteFailure(e, _, _)
+            | SyscallReturn::UserspaceReadableAllowFailure(e, _, _)
+            | SyscallReturn::AllowReadOnlyFailure(e, _, _)
+            | SyscallReturn::SubscribeFailure(e, _, _) => Some(e),
+            _ => None,
+        }
+    }
+
     /// Encode the system call return value into 4 registers, following
     /// the encoding specified in TRD104. Architectures which do not follow
     /// TRD104 are free to define their own encoding.
@@ -653,6 +704,23 @@ pub trait UserspaceKernelBoundary {
         writer: &mut dyn Write,
     );
 
+    /// Return the program counter and stack pointer of a process identified
+    /// by the stored state for that process, e.g. where it faulted. Returns
+    /// `None` if they cannot be read.
+    ///
+    /// ### Safety
+    ///
+    /// This function guarantees that it if needs to read process memory, it
+    /// will only read memory starting at `accessible_memory_start` and before
+    /// `app_brk`. The caller is responsible for guaranteeing that those
+    /// pointers are valid for the process.
+    unsafe fn get_pc_and_sp(
+        &self,
+        accessible_memory_start: *const u8,
+        app_brk: *const u8,
+        state: &Self::StoredState,
+    ) -> Option<(usize, usize)>;
+
     /// Store architecture specific (e.g. CPU registers or status flags) data
     /// for a process. On success returns the number of elements written to out.
     fn store_context(&self, state: &Self::StoredState, out: &mut [u8]) -> Result<usize, ErrorCode>;
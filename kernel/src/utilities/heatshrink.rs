@@ -0,0 +1,182 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Decompressor for the heatshrink LZSS format.
+//!
+//! [heatshrink](https://github.com/atomicobject/heatshrink) compresses data
+//! into a bit stream of literal bytes and back-references to data that was
+//! already decompressed. Each element starts with a tag bit:
+//!
+//! - `1`: a literal, followed by the 8 bits of the byte.
+//! - `0`: a back-reference, followed by `window_bits` bits of the distance
+//!   minus one and `lookahead_bits` bits of the length minus one.
+//!
+//! All values are stored most significant bit first, and the last byte is
+//! padded with zeros. The parameters are not part of the stream and must
+//! match those used to compress it.
+//!
+//! As the whole output is kept in memory, the decompressor needs no window
+//! buffer of its own and back-references are copied within the output.
+
+use crate::ErrorCode;
+
+/// Reads a bit stream most significant bit first.
+struct BitReader<'a> {
+    input: &'a [u8],
+    /// Index of the next bit to read.
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn bits_left(&self) -> usize {
+        self.input.len() * 8 - self.position
+    }
+
+    /// Read `count` bits, at most 16, or `None` if the stream has fewer.
+    fn read(&mut self, count: usize) -> Option<usize> {
+        if self.bits_left() < count {
+            return None;
+        }
+        let mut value = 0;
+        for _ in 0..count {
+            let byte = self.input[self.position / 8];
+            let bit = (byte >> (7 - self.position % 8)) & 1;
+            value = (value << 1) | bit as usize;
+            self.position += 1;
+        }
+        Some(value)
+    }
+}
+
+/// Decompress `input` into `output` and return the decompressed length.
+///
+/// `window_bits` and `lookahead_bits` are the `-w` and `-l` parameters the
+/// data was compressed with, and must be at most 15.
+///
+/// The possible ErrorCodes are:
+///    - SIZE: the decompressed data does not fit into `output`
+///    - INVAL: a back-reference points before the start of the data, or the
+///      parameters are invalid
+pub fn decompress(
+    input: &[u8],
+    output: &mut [u8],
+    window_bits: usize,
+    lookahead_bits: usize,
+) -> Result<usize, ErrorCode> {
+    if window_bits == 0 || window_bits > 15 || lookahead_bits == 0 || lookahead_bits > 15 {
+        return Err(ErrorCode::INVAL);
+    }
+    let mut reader = BitReader { input, position: 0 };
+    let mut length = 0;
+    // The zero padding at the end is too short for another element, as each
+    // element is at least 9 bits long.
+    while let Some(tag) = reader.read(1) {
+        if tag == 1 {
+            let byte = match reader.read(8) {
+                Some(byte) => byte as u8,
+                None => break,
+            };
+            *output.get_mut(length).ok_or(ErrorCode::SIZE)? = byte;
+            length += 1;
+        } else {
+            let (distance, count) = match (reader.read(window_bits), reader.read(lookahead_bits)) {
+                (Some(index), Some(count)) => (index + 1, count + 1),
+                _ => break,
+            };
+            let start = length.checked_sub(distance).ok_or(ErrorCode::INVAL)?;
+            if length + count > output.len() {
+                return Err(ErrorCode::SIZE);
+            }
+            // Copy byte by byte, as the source may overlap the bytes being
+            // produced, e.g. to repeat a single byte.
+            for offset in 0..count {
+                output[length + offset] = output[start + offset];
+            }
+            length += count;
+        }
+    }
+    Ok(length)
+}
+
+#[cfg(test)]
+mod test {
+    use super::decompress;
+    use crate::ErrorCode;
+
+    /// Writes a bit stream most significant bit first.
+    struct BitWriter {
+        bytes: [u8; 64],
+        position: usize,
+    }
+
+    impl BitWriter {
+        fn new() -> BitWriter {
+            BitWriter {
+                bytes: [0; 64],
+                position: 0,
+            }
+        }
+
+        fn write(&mut self, value: usize, count: usize) {
+            for i in (0..count).rev() {
+                if (value >> i) & 1 == 1 {
+                    self.bytes[self.position / 8] |= 1 << (7 - self.position % 8);
+                }
+                self.position += 1;
+            }
+        }
+
+        fn literal(&mut self, byte: u8) {
+            self.write(1, 1);
+            self.write(byte as usize, 8);
+        }
+
+        fn backref(&mut self, distance: usize, count: usize) {
+            self.write(0, 1);
+            self.write(distance - 1, 8);
+            self.write(count - 1, 4);
+        }
+
+        fn stream(&self) -> &[u8] {
+            &self.bytes[..(self.position + 7) / 8]
+        }
+    }
+
+    #[test]
+    fn test_literals_and_backrefs() {
+        let mut writer = BitWriter::new();
+        for byte in b"abc" {
+            writer.literal(*byte);
+        }
+        writer.backref(3, 6);
+        writer.literal(b'x');
+        // Overlapping back-reference repeating the last byte.
+        writer.backref(1, 4);
+
+        let mut output = [0; 16];
+        assert_eq!(decompress(writer.stream(), &mut output, 8, 4), Ok(14));
+        assert_eq!(&output[..14], b"abcabcabcxxxxx");
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut writer = BitWriter::new();
+        writer.literal(b'a');
+        writer.backref(2, 1);
+        let mut output = [0; 16];
+        assert_eq!(
+            decompress(writer.stream(), &mut output, 8, 4),
+            Err(ErrorCode::INVAL)
+        );
+
+        let mut writer = BitWriter::new();
+        writer.literal(b'a');
+        writer.backref(1, 16);
+        let mut output = [0; 8];
+        assert_eq!(
+            decompress(writer.stream(), &mut output, 8, 4),
+            Err(ErrorCode::SIZE)
+        );
+    }
+}
@@ -137,6 +137,11 @@ impl<'a, T> LeasableMutableBuffer<'a, T> {
         self.active_slice().as_ptr()
     }
 
+    /// Returns the range of the raw buffer that is currently accessible
+    pub fn active_range(&self) -> Range<usize> {
+        self.active_range.clone()
+    }
+
     /// Reduces the range of the LeasableBuffer that is accessible. This should be called
     /// whenever an upper layer wishes to pass only a portion of a larger buffer down to
     /// a lower layer. For example: if the application layer has a 1500 byte packet
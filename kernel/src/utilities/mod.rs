@@ -6,11 +6,13 @@
 
 pub mod binary_write;
 pub mod copy_slice;
+pub mod heatshrink;
 pub mod helpers;
 pub mod leasable_buffer;
 pub mod math;
 pub mod mut_imut_buffer;
 pub mod peripheral_management;
+pub mod process_ring_buffer;
 pub mod static_init;
 pub mod storage_volume;
 
@@ -0,0 +1,252 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Ring buffer shared between a capsule and a process in an allowed buffer.
+//!
+//! A capsule that streams data to a process, e.g. ADC samples, sniffed
+//! packets or console input, usually fills the allowed buffer, schedules an
+//! upcall and waits for the process to allow a new buffer. Data that arrives
+//! in the meantime is lost or has to be buffered in the kernel.
+//!
+//! `ProcessRingBuffer` instead treats an allowed buffer as a ring buffer with
+//! a small header that holds the indices of both sides. The capsule appends
+//! data at the tail and the process consumes it from the head, each side only
+//! moving its own index, so data keeps flowing without a new allow per chunk.
+//! The same layout works in the other direction, with the process producing
+//! and the capsule consuming.
+//!
+//! Buffer Layout
+//! -------------
+//!
+//! ```text
+//! 0         4         8         12
+//! +---------+---------+---------+-------------------------------+
+//! | head    | tail    | dropped | data ...                      |
+//! +---------+---------+---------+-------------------------------+
+//! ```
+//!
+//! All header fields are little endian `u32`s:
+//!
+//! - `head`: offset into `data` of the next byte to consume. Only the
+//!   consumer moves it.
+//! - `tail`: offset into `data` where the next byte is produced. Only the
+//!   producer moves it.
+//! - `dropped`: how many bytes the producer dropped because the buffer was
+//!   full. The consumer may reset it.
+//!
+//! The buffer is empty when `head == tail`, and full when the tail is one
+//! byte behind the head, so it holds at most `data.len() - 1` bytes. A
+//! process initializes the header to zeros before it allows the buffer.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! kernel_data
+//!     .get_readwrite_processbuffer(rw_allow::STREAM)
+//!     .and_then(|stream| {
+//!         stream.mut_enter(|slice| match ProcessRingBuffer::new(slice) {
+//!             Ok(ring) => {
+//!                 let was_empty = ring.is_empty();
+//!                 ring.push_slice(&samples);
+//!                 // Only wake the process up when new data arrived in an
+//!                 // empty buffer, it consumes everything else anyway.
+//!                 if was_empty {
+//!                     let _ = kernel_data.schedule_upcall(0, (ring.len(), 0, 0));
+//!                 }
+//!             }
+//!             Err(_) => {}
+//!         })
+//!     });
+//! ```
+
+use crate::processbuffer::WriteableProcessSlice;
+use crate::ErrorCode;
+
+/// Length of the header in front of the data, in bytes.
+pub const HEADER_LEN: usize = 12;
+
+const HEAD_OFFSET: usize = 0;
+const TAIL_OFFSET: usize = 4;
+const DROPPED_OFFSET: usize = 8;
+
+/// A ring buffer in a process buffer. See the module documentation for the
+/// layout.
+pub struct ProcessRingBuffer<'a> {
+    header: &'a WriteableProcessSlice,
+    data: &'a WriteableProcessSlice,
+}
+
+impl<'a> ProcessRingBuffer<'a> {
+    /// Use `slice` as a ring buffer.
+    ///
+    /// The possible ErrorCodes are:
+    ///    - SIZE: the slice cannot hold the header and at least one byte
+    ///    - INVAL: the header holds an index outside of the data
+    pub fn new(slice: &'a WriteableProcessSlice) -> Result<ProcessRingBuffer<'a>, ErrorCode> {
+        if slice.len() < HEADER_LEN + 2 {
+            return Err(ErrorCode::SIZE);
+        }
+        let ring = ProcessRingBuffer {
+            header: &slice[..HEADER_LEN],
+            data: &slice[HEADER_LEN..],
+        };
+        // The process can write anything into the header, so check the
+        // indices once instead of on every access.
+        if ring.head() >= ring.data.len() || ring.tail() >= ring.data.len() {
+            return Err(ErrorCode::INVAL);
+        }
+        Ok(ring)
+    }
+
+    /// Empty the buffer and reset the dropped counter.
+    pub fn reset(&self) {
+        self.header.iter().for_each(|byte| byte.set(0));
+    }
+
+    fn read_u32(&self, offset: usize) -> u32 {
+        let mut bytes = [0; 4];
+        self.header[offset..offset + 4].copy_to_slice(&mut bytes);
+        u32::from_le_bytes(bytes)
+    }
+
+    fn write_u32(&self, offset: usize, value: u32) {
+        self.header[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn head(&self) -> usize {
+        self.read_u32(HEAD_OFFSET) as usize
+    }
+
+    fn tail(&self) -> usize {
+        self.read_u32(TAIL_OFFSET) as usize
+    }
+
+    /// The most bytes the buffer can hold.
+    pub fn capacity(&self) -> usize {
+        self.data.len() - 1
+    }
+
+    /// The number of bytes in the buffer.
+    pub fn len(&self) -> usize {
+        let (head, tail) = (self.head(), self.tail());
+        if tail >= head {
+            tail - head
+        } else {
+            self.data.len() - head + tail
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head() == self.tail()
+    }
+
+    /// The number of bytes that can be produced before the buffer is full.
+    pub fn free(&self) -> usize {
+        self.capacity() - self.len()
+    }
+
+    /// How many bytes the producer dropped because the buffer was full.
+    pub fn dropped(&self) -> u32 {
+        self.read_u32(DROPPED_OFFSET)
+    }
+
+    /// Produce as much of `src` as fits and count the rest as dropped.
+    /// Returns how many bytes were produced.
+    pub fn push_slice(&self, src: &[u8]) -> usize {
+        let count = src.len().min(self.free());
+        let tail = self.tail();
+        // Copy up to the end of the data, then wrap around.
+        let first = count.min(self.data.len() - tail);
+        self.data[tail..tail + first].copy_from_slice(&src[..first]);
+        self.data[..count - first].copy_from_slice(&src[first..count]);
+        self.write_u32(TAIL_OFFSET, ((tail + count) % self.data.len()) as u32);
+
+        if count < src.len() {
+            let dropped = self.dropped().saturating_add((src.len() - count) as u32);
+            self.write_u32(DROPPED_OFFSET, dropped);
+        }
+        count
+    }
+
+    /// Consume up to `dest.len()` bytes into `dest`. Returns how many bytes
+    /// were consumed.
+    pub fn pop_slice(&self, dest: &mut [u8]) -> usize {
+        let count = dest.len().min(self.len());
+        let head = self.head();
+        let first = count.min(self.data.len() - head);
+        self.data[head..head + first].copy_to_slice(&mut dest[..first]);
+        self.data[..count - first].copy_to_slice(&mut dest[first..count]);
+        self.write_u32(HEAD_OFFSET, ((head + count) % self.data.len()) as u32);
+        count
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ProcessRingBuffer, HEADER_LEN};
+    use crate::processbuffer::WriteableProcessSlice;
+    use crate::ErrorCode;
+
+    #[test]
+    fn test_push_pop_wraps() {
+        let mut buffer = [0; HEADER_LEN + 8];
+        let slice: &WriteableProcessSlice = (&mut buffer[..]).into();
+        let ring = ProcessRingBuffer::new(slice).unwrap();
+        assert_eq!(ring.capacity(), 7);
+
+        let mut out = [0; 7];
+        for round in 0..10u8 {
+            let data = [round, round + 1, round + 2, round + 3, round + 4];
+            assert_eq!(ring.push_slice(&data), 5);
+            assert_eq!(ring.len(), 5);
+            assert_eq!(ring.pop_slice(&mut out), 5);
+            assert_eq!(out[..5], data);
+            assert!(ring.is_empty());
+        }
+        assert_eq!(ring.dropped(), 0);
+    }
+
+    #[test]
+    fn test_full_counts_dropped() {
+        let mut buffer = [0; HEADER_LEN + 4];
+        let slice: &WriteableProcessSlice = (&mut buffer[..]).into();
+        let ring = ProcessRingBuffer::new(slice).unwrap();
+
+        assert_eq!(ring.push_slice(&[1, 2, 3, 4, 5]), 3);
+        assert_eq!(ring.free(), 0);
+        assert_eq!(ring.dropped(), 2);
+
+        let mut out = [0; 2];
+        assert_eq!(ring.pop_slice(&mut out), 2);
+        assert_eq!(out, [1, 2]);
+        assert_eq!(ring.push_slice(&[6]), 1);
+        let mut out = [0; 4];
+        assert_eq!(ring.pop_slice(&mut out), 2);
+        assert_eq!(out[..2], [3, 6]);
+
+        ring.reset();
+        assert_eq!(ring.dropped(), 0);
+        assert!(ring.is_empty());
+    }
+
+    #[test]
+    fn test_invalid_header() {
+        let mut buffer = [0; HEADER_LEN + 1];
+        let slice: &WriteableProcessSlice = (&mut buffer[..]).into();
+        assert!(matches!(
+            ProcessRingBuffer::new(slice),
+            Err(ErrorCode::SIZE)
+        ));
+
+        let mut buffer = [0; HEADER_LEN + 4];
+        // The tail points past the data.
+        buffer[4] = 4;
+        let slice: &WriteableProcessSlice = (&mut buffer[..]).into();
+        assert!(matches!(
+            ProcessRingBuffer::new(slice),
+            Err(ErrorCode::INVAL)
+        ));
+    }
+}
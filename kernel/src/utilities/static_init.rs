@@ -97,3 +97,39 @@ macro_rules! static_buf {
         &mut BUF.0
     }};
 }
+
+/// Allocates a statically-sized, zeroed byte buffer for DMA transfers and
+/// returns a `&'static mut [u8; N]` reference to it.
+///
+/// The buffer starts at a multiple of `$align`, which must be a power of two
+/// literal. As many memory protection units require regions to be aligned to
+/// their size, DMA buffers that should be covered by a single region should
+/// use a power of two size and align to it.
+///
+/// The buffer is recorded so that boards can mark all DMA buffers as kernel
+/// memory with `KernelMPU::allocate_dma_regions()` instead of adding a
+/// region for every buffer by hand. Buffers must therefore be declared before
+/// the kernel MPU is enabled.
+///
+/// ```ignore
+/// let rx_buffer: &'static mut [u8; 256] = static_dma_buf!(256, 256);
+/// ```
+///
+/// # Safety
+///
+/// Like `static_buf!()`, this must not run twice for the same buffer, and
+/// panics if it does.
+#[macro_export]
+macro_rules! static_dma_buf {
+    ($N:expr, $align:literal $(,)?) => {{
+        #[repr(C, align($align))]
+        struct DmaBuffer([u8; $N]);
+
+        static mut BUF: (DmaBuffer, bool) = (DmaBuffer([0; $N]), false);
+        $crate::utilities::static_init::static_buf_check_used(&mut BUF.1);
+
+        let buf: &'static mut [u8; $N] = &mut BUF.0 .0;
+        $crate::platform::mpu::register_dma_buffer(buf.as_ptr(), $N);
+        buf
+    }};
+}
@@ -0,0 +1,222 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Prioritized queue of long-running kernel work.
+//!
+//! Deferred calls are serviced one after the other until none is pending,
+//! so a client that does a lot of computation in a deferred call, like a
+//! software hash over a whole process binary, delays every other deferred
+//! call and interrupt bottom half, e.g. the UART mux, until it is done.
+//!
+//! Such work is better split into chunks that each take a bounded time. A
+//! client schedules its `Work`, and the kernel runs one chunk at a time by
+//! calling `WorkClient::run_work()`. If there is more to do, the client
+//! schedules its work again. Chunks only run when no interrupt and no
+//! deferred call is pending, so those are delayed by at most one chunk.
+//! Among scheduled work, the highest priority runs first; work of the same
+//! priority takes turns.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! use kernel::work_queue::{Priority, Work, WorkClient};
+//! use kernel::static_init;
+//!
+//! struct SomeCapsule {
+//!     work: Work,
+//! }
+//! impl SomeCapsule {
+//!     pub fn new() -> Self {
+//!         Self {
+//!             work: Work::new(Priority::Normal),
+//!         }
+//!     }
+//! }
+//! impl WorkClient for SomeCapsule {
+//!     fn run_work(&self) {
+//!         // Do a chunk of work, and call `self.work.schedule()` if
+//!         // there is more to do.
+//!     }
+//! }
+//!
+//! let some_capsule = unsafe { static_init!(SomeCapsule, SomeCapsule::new()) };
+//! some_capsule.work.register(some_capsule);
+//! some_capsule.work.schedule();
+//! ```
+
+use core::cell::Cell;
+use core::marker::PhantomData;
+use core::ptr::addr_of;
+
+use crate::utilities::cells::OptionalCell;
+
+/// Priority of scheduled `Work`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// Work that other kernel work or processes wait for.
+    High = 0,
+    Normal = 1,
+    /// Background work, e.g. garbage collection.
+    Low = 2,
+}
+
+/// Implemented by clients of a `Work`.
+///
+/// Like `DeferredCallClient`, this trait is not meant to be used as a trait
+/// object.
+pub trait WorkClient: Sized {
+    /// Do one chunk of work. Chunks should be short, e.g. no longer than a
+    /// few hundred microseconds.
+    fn run_work(&self);
+}
+
+/// Reference to a `WorkClient`, without the vtable of a trait object. See
+/// `DynDefCallRef` in `deferred_call`.
+#[derive(Copy, Clone)]
+struct DynWorkRef<'a> {
+    data: *const (),
+    callback: fn(*const ()),
+    _lifetime: PhantomData<&'a ()>,
+}
+
+impl<'a> DynWorkRef<'a> {
+    // SAFETY: the callback casts the pointer back to the type it was created
+    // from before calling `T::run_work()`.
+    fn new<T: WorkClient>(x: &'a T) -> Self {
+        Self {
+            data: x as *const _ as *const (),
+            callback: |p| unsafe { T::run_work(&*p.cast()) },
+            _lifetime: PhantomData,
+        }
+    }
+
+    fn run_work(self) {
+        (self.callback)(self.data)
+    }
+}
+
+/// Maximum number of `Work`s.
+const MAX_WORK: usize = 16;
+
+const EMPTY: OptionalCell<(DynWorkRef<'static>, Priority)> = OptionalCell::empty();
+
+// As for deferred calls, these statics are only accessed through immutable
+// references from the single kernel thread.
+/// Number of `Work`s created.
+static mut CTR: Cell<usize> = Cell::new(0);
+
+/// Bit `i` is set while work `i` is scheduled.
+static mut SCHEDULED: Cell<u16> = Cell::new(0);
+
+/// Work that ran last, so that work of the same priority takes turns.
+static mut LAST: Cell<usize> = Cell::new(0);
+
+static mut WORK: [OptionalCell<(DynWorkRef<'static>, Priority)>; MAX_WORK] = [EMPTY; MAX_WORK];
+
+// SAFETY: single-threaded, see above. The statics are never mutably
+// borrowed, and the shared references are made through raw pointers so that
+// no reference to a `static mut` is created directly.
+fn ctr() -> &'static Cell<usize> {
+    unsafe { &*addr_of!(CTR) }
+}
+
+fn scheduled() -> &'static Cell<u16> {
+    unsafe { &*addr_of!(SCHEDULED) }
+}
+
+fn last() -> &'static Cell<usize> {
+    unsafe { &*addr_of!(LAST) }
+}
+
+fn work() -> &'static [OptionalCell<(DynWorkRef<'static>, Priority)>; MAX_WORK] {
+    unsafe { &*addr_of!(WORK) }
+}
+
+pub struct Work {
+    idx: usize,
+    priority: Priority,
+}
+
+impl Work {
+    /// Creates a new work item with a unique ID.
+    pub fn new(priority: Priority) -> Work {
+        let ctr = ctr();
+        let idx = ctr.get();
+        ctr.set(idx + 1);
+        Work { idx, priority }
+    }
+
+    #[inline(never)]
+    fn register_internal_non_generic(&self, handler: DynWorkRef<'static>) {
+        let work = work();
+        // More than `MAX_WORK` items are caught by `verify_setup()`.
+        if let Some(slot) = work.get(self.idx) {
+            slot.set((handler, self.priority));
+        }
+    }
+
+    /// Register the client that runs this work.
+    pub fn register<W: WorkClient>(&self, client: &'static W) {
+        self.register_internal_non_generic(DynWorkRef::new(client));
+    }
+
+    /// Schedule the client to run a chunk of work.
+    pub fn schedule(&self) {
+        let scheduled = scheduled();
+        if self.idx < MAX_WORK {
+            scheduled.set(scheduled.get() | 1 << self.idx);
+        }
+    }
+
+    /// Unschedule the work, if it is scheduled.
+    pub fn cancel(&self) {
+        let scheduled = scheduled();
+        if self.idx < MAX_WORK {
+            scheduled.set(scheduled.get() & !(1 << self.idx));
+        }
+    }
+
+    pub fn is_scheduled(&self) -> bool {
+        self.idx < MAX_WORK && scheduled().get() & 1 << self.idx != 0
+    }
+
+    /// Whether any work is scheduled.
+    pub fn has_work() -> bool {
+        scheduled().get() != 0
+    }
+
+    /// Run a chunk of the scheduled work with the highest priority. Returns
+    /// which work ran.
+    pub fn run_next() -> Option<usize> {
+        let (scheduled, last, work) = (scheduled(), last(), work());
+        let pending = scheduled.get();
+        // Start after the work that ran last, so that work of the same
+        // priority takes turns.
+        let next = (1..=MAX_WORK)
+            .map(|offset| (last.get() + offset) % MAX_WORK)
+            .filter(|idx| pending & 1 << idx != 0)
+            .filter_map(|idx| {
+                work[idx]
+                    .extract()
+                    .map(|(handler, priority)| (idx, handler, priority))
+            })
+            .min_by_key(|(_, _, priority)| *priority);
+        next.map(|(idx, handler, _)| {
+            scheduled.set(scheduled.get() & !(1 << idx));
+            last.set(idx);
+            handler.run_work();
+            idx
+        })
+    }
+
+    /// Verify at the beginning of the kernel loop that no more than
+    /// `MAX_WORK` work items were created, and that all were registered.
+    pub fn verify_setup() {
+        let (ctr, work) = (ctr(), work());
+        if ctr.get() > MAX_WORK || work.iter().filter(|slot| slot.is_some()).count() != ctr.get() {
+            panic!("ERROR: > 16 work items, or a component forgot to register a work item.");
+        }
+    }
+}
@@ -126,11 +126,15 @@
 //! If the data isn't ready (multiple reads might occur) then the `NotReady`
 //! error types can still be used.
 //!
+//! Values that span several objects are written one chunk at a time. If a
+//! write of a chunk is queued, `is_chunk_pending()` returns true and
+//! `continue_operation()` should be called once the write has finished.
+//!
 
 use crate::error_codes::ErrorCode;
 use crate::flash_controller::FlashController;
 use crate::success_codes::SuccessCode;
-use crate::tickv::{State, TicKV};
+use crate::tickv::{KeyState, State, TicKV, Usage};
 use core::cell::Cell;
 
 /// The return type from the continue operation
@@ -148,6 +152,9 @@ pub struct AsyncTicKV<'a, C: FlashController<S>, const S: usize> {
     key: Cell<Option<u64>>,
     value: Cell<Option<&'static mut [u8]>>,
     buf: Cell<Option<&'static mut [u8]>>,
+    position: Cell<usize>,
+    next_key: Cell<Option<(u64, usize)>>,
+    usage: Cell<Option<Usage>>,
 }
 
 impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
@@ -163,6 +170,9 @@ impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
             key: Cell::new(None),
             value: Cell::new(None),
             buf: Cell::new(None),
+            position: Cell::new(0),
+            next_key: Cell::new(None),
+            usage: Cell::new(None),
         }
     }
 
@@ -267,6 +277,57 @@ impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
         self.tickv.garbage_collect()
     }
 
+    /// Finds the next valid key in flash storage, to list the keys that are
+    /// stored.
+    ///
+    /// `position`: Where to start looking. This is 0 for the first key, and
+    ///             the position returned with the previous key for the next
+    ///             ones.
+    ///
+    /// On success the hashed key and the position after it will be returned.
+    /// If the operation continues asynchronously they are returned by
+    /// `get_next_key()` once `continue_operation()` succeeds.
+    /// On error a `ErrorCode` will be returned, which is `KeyNotFound` once
+    /// there are no more keys.
+    pub fn next_key(&self, position: usize) -> Result<(u64, usize), ErrorCode> {
+        self.next_key.set(None);
+        self.position.set(position);
+        self.tickv.next_key(position)
+    }
+
+    /// Get the hashed key and the position after it that were found by
+    /// the last `next_key()` operation.
+    pub fn get_next_key(&self) -> Option<(u64, usize)> {
+        self.next_key.take()
+    }
+
+    /// Add up the bytes used by valid and invalidated objects in flash
+    /// storage.
+    ///
+    /// On success the `Usage` will be returned.
+    /// If the operation continues asynchronously it is returned by
+    /// `get_usage()` once `continue_operation()` succeeds.
+    /// On error a `ErrorCode` will be returned.
+    pub fn usage(&self) -> Result<Usage, ErrorCode> {
+        self.usage.set(None);
+        self.tickv.usage()
+    }
+
+    /// Get the `Usage` found by the last `usage()` operation.
+    pub fn get_usage(&self) -> Option<Usage> {
+        self.usage.take()
+    }
+
+    /// Whether the last operation waits for a write to finish before it
+    /// continues with the next chunk of a value. If so, `continue_operation()`
+    /// should be called from the write complete callback.
+    pub fn is_chunk_pending(&self) -> bool {
+        matches!(
+            self.tickv.state.get(),
+            State::AppendKey(KeyState::NextChunk) | State::InvalidateKey(KeyState::NextChunk)
+        )
+    }
+
     /// Copy data from `read_buffer` argument to the internal read_buffer.
     /// This should be used to copy the data that the implementation wanted
     /// to read when calling `read_region` after the async operation has
@@ -324,6 +385,20 @@ impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
                 Ok(_) => Ok(SuccessCode::Complete),
                 Err(e) => Err(e),
             },
+            State::NextKey(_) => match self.tickv.next_key(self.position.get()) {
+                Ok(next) => {
+                    self.next_key.set(Some(next));
+                    Ok(SuccessCode::Complete)
+                }
+                Err(e) => Err(e),
+            },
+            State::Usage(_) => match self.tickv.usage() {
+                Ok(usage) => {
+                    self.usage.set(Some(usage));
+                    Ok(SuccessCode::Complete)
+                }
+                Err(e) => Err(e),
+            },
             _ => unreachable!(),
         };
 
@@ -335,7 +410,9 @@ impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
             Err(e) => match e {
                 ErrorCode::ReadNotReady(_) | ErrorCode::EraseNotReady(_) => (ret, None),
                 ErrorCode::WriteNotReady(_) => {
-                    self.tickv.state.set(State::None);
+                    if !self.is_chunk_pending() {
+                        self.tickv.state.set(State::None);
+                    }
                     (ret, None)
                 }
                 _ => {
@@ -879,5 +956,112 @@ mod tests {
                     .unwrap();
             }
         }
+
+        #[test]
+        fn test_next_key() {
+            let mut read_buf: [u8; 1024] = [0; 1024];
+            let mut hash_function = DefaultHasher::new();
+            MAIN_KEY.hash(&mut hash_function);
+            let hash = hash_function.finish();
+
+            let tickv =
+                AsyncTicKV::<FlashCtrl, 1024>::new(FlashCtrl::new(), &mut read_buf, 0x10000);
+
+            let mut ret = tickv.initialise(hash);
+            while ret.is_err() {
+                // There is no actual delay in the test, just continue now
+                let (r, _buf) = tickv.continue_operation();
+                ret = r;
+            }
+
+            static mut VALUE: [u8; 32] = [0x23; 32];
+
+            println!("Add key ONE");
+            let ret = unsafe { tickv.append_key(get_hashed_key(b"ONE"), &mut VALUE) };
+            match ret {
+                Err((_buf, ErrorCode::ReadNotReady(reg))) => {
+                    // There is no actual delay in the test, just continue now
+                    tickv.set_read_buffer(&tickv.tickv.controller.buf.borrow()[reg]);
+                    tickv.continue_operation().0.unwrap();
+                }
+                Ok(_) => {}
+                _ => unreachable!(),
+            }
+
+            println!("List the keys");
+            let mut keys = std::vec::Vec::new();
+            let mut position = 0;
+            loop {
+                let mut ret = tickv.next_key(position);
+                while let Err(ErrorCode::ReadNotReady(reg)) = ret {
+                    // There is no actual delay in the test, just continue now
+                    tickv.set_read_buffer(&tickv.tickv.controller.buf.borrow()[reg]);
+                    ret = match tickv.continue_operation().0 {
+                        Ok(_) => Ok(tickv.get_next_key().unwrap()),
+                        Err(e) => Err(e),
+                    };
+                }
+                match ret {
+                    Ok((key, next)) => {
+                        keys.push(key);
+                        position = next;
+                    }
+                    Err(ErrorCode::KeyNotFound) => break,
+                    Err(e) => panic!("Unexpected error: {:?}", e),
+                }
+            }
+            keys.sort();
+
+            let mut expected = std::vec![hash, get_hashed_key(b"ONE")];
+            expected.sort();
+            assert_eq!(keys, expected);
+        }
+
+        #[test]
+        fn test_large_value() {
+            let mut read_buf: [u8; 1024] = [0; 1024];
+            let mut hash_function = DefaultHasher::new();
+            MAIN_KEY.hash(&mut hash_function);
+
+            let tickv =
+                AsyncTicKV::<FlashCtrl, 1024>::new(FlashCtrl::new(), &mut read_buf, 0x10000);
+            tickv.tickv.set_max_value_regions(2);
+
+            let mut ret = tickv.initialise(hash_function.finish());
+            while ret.is_err() {
+                // There is no actual delay in the test, just continue now
+                let (r, _buf) = tickv.continue_operation();
+                ret = r;
+            }
+            // Skip the checks of the objects written for keys ONE and TWO
+            tickv.tickv.controller.run.set(3);
+
+            static mut VALUE: [u8; 1500] = [0x23; 1500];
+            static mut BUF: [u8; 1500] = [0; 1500];
+
+            println!("Add key ONE");
+            let mut ret = unsafe { tickv.append_key(get_hashed_key(b"ONE"), &mut VALUE) }
+                .map_err(|(_buf, e)| e);
+            while let Err(ErrorCode::ReadNotReady(reg)) = ret {
+                // There is no actual delay in the test, just continue now
+                tickv.set_read_buffer(&tickv.tickv.controller.buf.borrow()[reg]);
+                ret = tickv.continue_operation().0;
+            }
+            ret.unwrap();
+            assert!(!tickv.is_chunk_pending());
+
+            println!("Get key ONE");
+            let mut ret =
+                unsafe { tickv.get_key(get_hashed_key(b"ONE"), &mut BUF) }.map_err(|(_buf, e)| e);
+            while let Err(ErrorCode::ReadNotReady(reg)) = ret {
+                // There is no actual delay in the test, just continue now
+                tickv.set_read_buffer(&tickv.tickv.controller.buf.borrow()[reg]);
+                ret = tickv.continue_operation().0;
+            }
+            ret.unwrap();
+            unsafe {
+                assert_eq!(BUF, VALUE);
+            }
+        }
     }
 }
@@ -227,6 +227,8 @@ pub use crate::error_codes::ErrorCode;
 pub use crate::flash_controller::FlashController;
 #[doc(inline)]
 pub use crate::tickv::TicKV;
+#[doc(inline)]
+pub use crate::tickv::Usage;
 pub use crate::tickv::MAIN_KEY;
 
 // This is used to run the tests on a host
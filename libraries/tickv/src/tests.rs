@@ -418,6 +418,132 @@ mod store_flast_ctrl {
         println!("Add Key ONE");
         tickv.append_key(get_hashed_key(b"ONE"), &value).unwrap();
     }
+
+    #[test]
+    fn test_next_key() {
+        let mut read_buf: [u8; 1024] = [0; 1024];
+        let mut hash_function = DefaultHasher::new();
+        MAIN_KEY.hash(&mut hash_function);
+        let hash = hash_function.finish();
+
+        let tickv = TicKV::<FlashCtrl, 1024>::new(FlashCtrl::new(), &mut read_buf, 0x10000);
+        tickv.initialise(hash).unwrap();
+
+        let value: [u8; 32] = [0x23; 32];
+
+        println!("Add Keys ONE, TWO and THREE");
+        tickv.append_key(get_hashed_key(b"ONE"), &value).unwrap();
+        tickv.append_key(get_hashed_key(b"TWO"), &value).unwrap();
+        tickv.append_key(get_hashed_key(b"THREE"), &value).unwrap();
+
+        println!("Delete Key TWO");
+        tickv.invalidate_key(get_hashed_key(b"TWO")).unwrap();
+
+        println!("List the keys");
+        let mut keys = std::vec::Vec::new();
+        let mut position = 0;
+        loop {
+            match tickv.next_key(position) {
+                Ok((key, next)) => {
+                    assert!(next > position);
+                    keys.push(key);
+                    position = next;
+                }
+                Err(ErrorCode::KeyNotFound) => break,
+                Err(e) => panic!("Unexpected error: {:?}", e),
+            }
+        }
+        keys.sort();
+
+        let mut expected = std::vec![hash, get_hashed_key(b"ONE"), get_hashed_key(b"THREE")];
+        expected.sort();
+        assert_eq!(keys, expected);
+    }
+
+    #[test]
+    fn test_usage() {
+        let mut read_buf: [u8; 1024] = [0; 1024];
+        let mut hash_function = DefaultHasher::new();
+        MAIN_KEY.hash(&mut hash_function);
+
+        let tickv = TicKV::<FlashCtrl, 1024>::new(FlashCtrl::new(), &mut read_buf, 0x10000);
+        tickv.initialise(hash_function.finish()).unwrap();
+
+        let value: [u8; 32] = [0x23; 32];
+
+        println!("Add Keys ONE and TWO");
+        tickv.append_key(get_hashed_key(b"ONE"), &value).unwrap();
+        tickv.append_key(get_hashed_key(b"TWO"), &value).unwrap();
+
+        println!("Delete Key TWO");
+        tickv.invalidate_key(get_hashed_key(b"TWO")).unwrap();
+
+        // The main key has an empty value, ONE and TWO are 47 bytes long
+        let usage = tickv.usage().unwrap();
+        assert_eq!(usage.live_bytes, 15 + 47);
+        assert_eq!(usage.dead_bytes, 47);
+    }
+
+    #[test]
+    fn test_large_value() {
+        let mut read_buf: [u8; 1024] = [0; 1024];
+        let mut hash_function = DefaultHasher::new();
+        MAIN_KEY.hash(&mut hash_function);
+        let hash = hash_function.finish();
+
+        let tickv = TicKV::<FlashCtrl, 1024>::new(FlashCtrl::new(), &mut read_buf, 0x10000);
+        tickv.initialise(hash).unwrap();
+        // Skip the checks of the objects written for keys ONE and TWO
+        tickv.controller.run.set(3);
+
+        let mut value: [u8; 2048] = [0; 2048];
+        for (i, b) in value.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+
+        println!("Add Key ONE longer than a region");
+        assert_eq!(
+            tickv.append_key(get_hashed_key(b"ONE"), &value),
+            Err(ErrorCode::ObjectTooLarge)
+        );
+        tickv.set_max_value_regions(2);
+        tickv.append_key(get_hashed_key(b"ONE"), &value).unwrap();
+
+        println!("Get key ONE");
+        let mut buf: [u8; 2048] = [0; 2048];
+        tickv.get_key(get_hashed_key(b"ONE"), &mut buf).unwrap();
+        assert_eq!(buf, value);
+
+        println!("Get key ONE into a small buffer");
+        let mut small_buf: [u8; 1500] = [0; 1500];
+        match tickv.get_key(get_hashed_key(b"ONE"), &mut small_buf) {
+            Err(ErrorCode::BufferTooSmall(len)) => assert!(len > small_buf.len()),
+            ret => panic!("Unexpected result: {:?}", ret),
+        }
+        assert_eq!(small_buf, value[..1500]);
+
+        println!("Only list key ONE once");
+        let mut keys = std::vec::Vec::new();
+        let mut position = 0;
+        while let Ok((key, next)) = tickv.next_key(position) {
+            keys.push(key);
+            position = next;
+        }
+        keys.sort();
+        let mut expected = std::vec![hash, get_hashed_key(b"ONE")];
+        expected.sort();
+        assert_eq!(keys, expected);
+
+        println!("Delete Key ONE");
+        tickv.invalidate_key(get_hashed_key(b"ONE")).unwrap();
+        let (key, position) = tickv.next_key(0).unwrap();
+        assert_eq!(key, hash);
+        assert_eq!(tickv.next_key(position), Err(ErrorCode::KeyNotFound));
+        assert_eq!(
+            tickv.get_key(get_hashed_key(b"ONE"), &mut buf),
+            Err(ErrorCode::KeyNotFound)
+        );
+    }
 }
 
 mod no_check_store_flast_ctrl {
@@ -29,6 +29,9 @@ pub(crate) enum InitState {
 pub(crate) enum KeyState {
     /// Trying to read the key from a region
     ReadRegion(usize),
+    /// Waiting for a write to finish before continuing with the next chunk
+    /// of a value
+    NextChunk,
 }
 
 #[derive(Clone, Copy, PartialEq)]
@@ -53,6 +56,20 @@ pub(crate) enum State {
     InvalidateKey(KeyState),
     /// Running garbage collection
     GarbageCollect(RubbishState),
+    /// Looking for the next valid key
+    NextKey(KeyState),
+    /// Adding up the bytes used by objects
+    Usage(KeyState),
+}
+
+/// The bytes used by objects in flash storage
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Usage {
+    /// Bytes used by valid objects
+    pub live_bytes: usize,
+    /// Bytes used by invalidated objects. Garbage collection frees them once
+    /// all objects in their region are invalidated.
+    pub dead_bytes: usize,
 }
 
 /// The struct storing all of the TicKV information.
@@ -62,6 +79,12 @@ pub struct TicKV<'a, C: FlashController<S>, const S: usize> {
     flash_size: usize,
     pub(crate) read_buffer: Cell<Option<&'a mut [u8; S]>>,
     pub(crate) state: Cell<State>,
+    /// The chunk of a value the current operation is on
+    chunk: Cell<usize>,
+    /// The number of regions values can be as long as
+    max_value_regions: Cell<usize>,
+    /// The usage of the regions that have been added up so far
+    usage: Cell<Usage>,
 }
 
 /// This is the current object header used for TicKV objects
@@ -75,13 +98,17 @@ struct ObjectHeader {
 }
 
 pub(crate) const FLAGS_VALID: u8 = 8;
+/// More chunks of the value follow this object
+pub(crate) const FLAGS_CONTINUED: u8 = 4;
+/// The object holds a chunk of a value after the first one
+pub(crate) const FLAGS_CHUNK: u8 = 2;
 
 impl ObjectHeader {
-    fn new(hashed_key: u64, len: u16) -> Self {
+    fn new(hashed_key: u64, flags: u8, len: u16) -> Self {
         assert!(len < 0xFFF);
         Self {
             version: VERSION,
-            flags: FLAGS_VALID,
+            flags: FLAGS_VALID | flags,
             len,
             hashed_key,
         }
@@ -113,9 +140,38 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
             flash_size,
             read_buffer: Cell::new(Some(read_buffer)),
             state: Cell::new(State::None),
+            chunk: Cell::new(0),
+            max_value_regions: Cell::new(1),
+            usage: Cell::new(Usage::default()),
         }
     }
 
+    /// Allow values as long as `regions` regions. Values that don't fit in
+    /// one object are split into chunks that are stored as separate objects.
+    ///
+    /// The default is 1.
+    pub fn set_max_value_regions(&self, regions: usize) {
+        self.max_value_regions.set(regions);
+    }
+
+    /// The longest value that can be stored in one object
+    fn object_value_len() -> usize {
+        S.min(0xFFE) - HEADER_LENGTH - CHECK_SUM_LEN
+    }
+
+    /// The length of the chunks longer values are split into. Chunks only
+    /// fill half a region, so that they fit next to other objects.
+    fn chunk_value_len() -> usize {
+        (S / 2).min(0xFFE) - HEADER_LENGTH - CHECK_SUM_LEN
+    }
+
+    /// The hashed key used to store chunk `chunk` of the value of `hash`
+    fn chunk_hash(hash: u64, chunk: usize) -> u64 {
+        // Add a multiple of the golden ratio, to spread the chunks over the
+        // regions
+        hash.wrapping_add((chunk as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15))
+    }
+
     /// This function setups the flash region to be used as a key-value store.
     /// If the region is already initialised this won't make any changes.
     ///
@@ -130,16 +186,16 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
         let mut buf: [u8; 0] = [0; 0];
 
         let key_ret = match self.state.get() {
-            State::None => self.get_key(hashed_main_key, &mut buf),
+            State::None => self.get_object(hashed_main_key, &mut buf),
             State::Init(state) => match state {
-                InitState::GetKeyReadRegion(_) => self.get_key(hashed_main_key, &mut buf),
+                InitState::GetKeyReadRegion(_) => self.get_object(hashed_main_key, &mut buf),
                 _ => Err(ErrorCode::EraseNotReady(0)),
             },
             _ => unreachable!(),
         };
 
         match key_ret {
-            Ok(ret) => Ok(ret),
+            Ok(_) => Ok(SuccessCode::Complete),
             Err(e) => {
                 match e {
                     ErrorCode::ReadNotReady(reg) => {
@@ -178,7 +234,7 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
                         }
 
                         // Save the main key
-                        match self.append_key(hashed_main_key, &buf) {
+                        match self.append_object(hashed_main_key, 0, &buf) {
                             Ok(ret) => {
                                 self.state.set(State::None);
                                 Ok(ret)
@@ -371,9 +427,64 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
     ///         or remove the `value`.
     /// `value`: A buffer containing the data to be stored to flash.
     ///
+    /// Values that don't fit in one object are split into chunks, as long
+    /// as they are at most `set_max_value_regions()` regions long.
+    ///
     /// On success nothing will be returned.
-    /// On error a `ErrorCode` will be returned.
+    /// On error a `ErrorCode` will be returned. `WriteNotReady` means that
+    /// the next chunk is appended once the write of the last one finished.
     pub fn append_key(&self, hash: u64, value: &[u8]) -> Result<SuccessCode, ErrorCode> {
+        if value.len() <= Self::object_value_len() {
+            return self.append_object(hash, 0, value);
+        }
+        if value.len() > self.max_value_regions.get() * S {
+            return Err(ErrorCode::ObjectTooLarge);
+        }
+
+        loop {
+            let chunk = self.chunk.get();
+            let start = chunk * Self::chunk_value_len();
+            let end = value.len().min(start + Self::chunk_value_len());
+
+            let mut flags = 0;
+            if chunk > 0 {
+                flags |= FLAGS_CHUNK;
+            }
+            if end < value.len() {
+                flags |= FLAGS_CONTINUED;
+            }
+
+            let ret = self.append_object(
+                Self::chunk_hash(hash, chunk),
+                flags,
+                value.get(start..end).ok_or(ErrorCode::ObjectTooLarge)?,
+            );
+            match ret {
+                Ok(_) if end == value.len() => {
+                    self.chunk.set(0);
+                    return ret;
+                }
+                Ok(SuccessCode::Queued) => {
+                    // Continue once the write has finished
+                    self.chunk.set(chunk + 1);
+                    self.state.set(State::AppendKey(KeyState::NextChunk));
+                    return Err(ErrorCode::WriteNotReady(chunk));
+                }
+                Ok(_) => {
+                    self.chunk.set(chunk + 1);
+                    self.state.set(State::None);
+                }
+                Err(ErrorCode::ReadNotReady(_)) => return ret,
+                Err(e) => {
+                    self.chunk.set(0);
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    /// Appends one object to flash storage, with the `flags` in its header.
+    fn append_object(&self, hash: u64, flags: u8, value: &[u8]) -> Result<SuccessCode, ErrorCode> {
         let region = self.get_region(hash);
         let mut check_sum = crc32::Crc32::new();
 
@@ -386,7 +497,7 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
         }
 
         // Create the header:
-        let header = ObjectHeader::new(hash, object_length as u16);
+        let header = ObjectHeader::new(hash, flags, object_length as u16);
 
         let mut region_offset: isize = 0;
 
@@ -404,6 +515,7 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
                 }
                 State::AppendKey(key_state) => match key_state {
                     KeyState::ReadRegion(reg) => reg as isize,
+                    KeyState::NextChunk => region as isize + region_offset,
                 },
                 State::GarbageCollect(RubbishState::ReadRegion(reg)) => reg as isize,
                 _ => unreachable!(),
@@ -646,11 +758,50 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
     /// `buf`: A buffer to store the value to.
     ///
     /// On success nothing will be returned.
-    /// On error a `ErrorCode` will be returned.
+    /// On error a `ErrorCode` will be returned. If `buf` is too small, it is
+    /// filled and `BufferTooSmall` holds at least the length of the value.
     ///
     /// If a power loss occurs before success is returned the data is
     /// assumed to be lost.
     pub fn get_key(&self, hash: u64, buf: &mut [u8]) -> Result<SuccessCode, ErrorCode> {
+        loop {
+            let chunk = self.chunk.get();
+            let start = buf.len().min(chunk * Self::chunk_value_len());
+
+            let ret = self.get_object(
+                Self::chunk_hash(hash, chunk),
+                buf.get_mut(start..)
+                    .ok_or(ErrorCode::BufferTooSmall(start))?,
+            );
+            match ret {
+                Ok(flags) if flags & FLAGS_CONTINUED != 0 => {
+                    self.chunk.set(chunk + 1);
+                    self.state.set(State::None);
+                }
+                Ok(_) => {
+                    self.chunk.set(0);
+                    return Ok(SuccessCode::Complete);
+                }
+                Err(ErrorCode::ReadNotReady(reg)) => return Err(ErrorCode::ReadNotReady(reg)),
+                Err(e) => {
+                    self.chunk.set(0);
+                    return Err(match e {
+                        ErrorCode::BufferTooSmall(len) => {
+                            ErrorCode::BufferTooSmall(chunk * Self::chunk_value_len() + len)
+                        }
+                        // A chunk of the value is missing
+                        ErrorCode::KeyNotFound if chunk > 0 => ErrorCode::CorruptData,
+                        _ => e,
+                    });
+                }
+            }
+        }
+    }
+
+    /// Retrieves the value of one object from flash storage.
+    ///
+    /// On success the flags of the object will be returned.
+    fn get_object(&self, hash: u64, buf: &mut [u8]) -> Result<u8, ErrorCode> {
         let region = self.get_region(hash);
 
         let mut region_offset: isize = 0;
@@ -670,6 +821,7 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
                 }
                 State::GetKey(key_state) => match key_state {
                     KeyState::ReadRegion(reg) => reg as isize,
+                    KeyState::NextChunk => region as isize + region_offset,
                 },
                 _ => unreachable!(),
             };
@@ -758,8 +910,13 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
                         return Err(ErrorCode::InvalidCheckSum);
                     }
 
+                    let flags = *region_data
+                        .get(offset + LEN_OFFSET)
+                        .ok_or(ErrorCode::CorruptData)?
+                        >> 4;
+
                     self.read_buffer.replace(Some(region_data));
-                    return Ok(SuccessCode::Complete);
+                    return Ok(flags);
                 }
                 Err((cont, e)) => {
                     self.read_buffer.replace(Some(region_data));
@@ -790,7 +947,40 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
     ///
     /// If a power loss occurs before success is returned the data is
     /// assumed to be lost.
+    ///
+    /// `WriteNotReady` means that the next chunk of the value is invalidated
+    /// once the write for the last one finished.
     pub fn invalidate_key(&self, hash: u64) -> Result<SuccessCode, ErrorCode> {
+        loop {
+            let chunk = self.chunk.get();
+
+            match self.invalidate_object(Self::chunk_hash(hash, chunk)) {
+                Ok((code, flags)) if flags & FLAGS_CONTINUED != 0 => {
+                    self.chunk.set(chunk + 1);
+                    if code == SuccessCode::Queued {
+                        // Continue once the write has finished
+                        self.state.set(State::InvalidateKey(KeyState::NextChunk));
+                        return Err(ErrorCode::WriteNotReady(chunk));
+                    }
+                    self.state.set(State::None);
+                }
+                Ok((code, _)) => {
+                    self.chunk.set(0);
+                    return Ok(code);
+                }
+                Err(ErrorCode::ReadNotReady(reg)) => return Err(ErrorCode::ReadNotReady(reg)),
+                Err(e) => {
+                    self.chunk.set(0);
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    /// Invalidates one object in flash storage.
+    ///
+    /// On success the flags of the object will be returned as well.
+    fn invalidate_object(&self, hash: u64) -> Result<(SuccessCode, u8), ErrorCode> {
         let region = self.get_region(hash);
 
         let mut region_offset: isize = 0;
@@ -801,6 +991,7 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
                 State::None => region as isize + region_offset,
                 State::InvalidateKey(key_state) => match key_state {
                     KeyState::ReadRegion(reg) => reg as isize,
+                    KeyState::NextChunk => region as isize + region_offset,
                 },
                 _ => unreachable!(),
             };
@@ -826,6 +1017,11 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
 
             match self.find_key_offset(hash, region_data) {
                 Ok((offset, _data_len)) => {
+                    let flags = *region_data
+                        .get(offset + LEN_OFFSET)
+                        .ok_or(ErrorCode::CorruptData)?
+                        >> 4;
+
                     // We found a key, let's delete it
                     *region_data
                         .get_mut(offset + LEN_OFFSET)
@@ -839,13 +1035,13 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
                     ) {
                         self.read_buffer.replace(Some(region_data));
                         match e {
-                            ErrorCode::WriteNotReady(_) => return Ok(SuccessCode::Queued),
+                            ErrorCode::WriteNotReady(_) => return Ok((SuccessCode::Queued, flags)),
                             _ => return Err(e),
                         }
                     }
 
                     self.read_buffer.replace(Some(region_data));
-                    return Ok(SuccessCode::Written);
+                    return Ok((SuccessCode::Written, flags));
                 }
                 Err((cont, e)) => {
                     self.read_buffer.replace(Some(region_data));
@@ -970,6 +1166,116 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
         Ok(S)
     }
 
+    /// Find the first valid object in some loaded region data, starting at
+    /// `offset`.
+    ///
+    /// On success return the hashed key of the object and the offset after
+    /// it, or None if there are no more valid objects in the region.
+    fn find_next_object(
+        &self,
+        region_data: &[u8],
+        offset: usize,
+    ) -> Result<Option<(u64, usize)>, ErrorCode> {
+        let mut offset = offset;
+
+        loop {
+            if offset + HEADER_LENGTH >= S {
+                // We have reached the end of the region
+                return Ok(None);
+            }
+
+            let version = *region_data
+                .get(offset + VERSION_OFFSET)
+                .ok_or(ErrorCode::CorruptData)?;
+            if version == 0xFF {
+                // We hit the end of valid data
+                return Ok(None);
+            }
+            if version != VERSION {
+                return Err(ErrorCode::UnsupportedVersion);
+            }
+
+            // Find this entries length
+            let len_high = *region_data
+                .get(offset + LEN_OFFSET)
+                .ok_or(ErrorCode::CorruptData)?;
+            let total_length = ((len_high as u16) & !0xF0) << 8
+                | *region_data
+                    .get(offset + LEN_OFFSET + 1)
+                    .ok_or(ErrorCode::CorruptData)? as u16;
+            if total_length == 0 {
+                // We found something invalid here
+                return Ok(None);
+            }
+
+            // Deleted entries have the valid flag cleared, skip them and the
+            // chunks of values after the first one
+            if len_high & 0x80 == 0x80 && len_high & (FLAGS_CHUNK << 4) == 0 {
+                let hash = region_data
+                    .get(offset + HASH_OFFSET..offset + HASH_OFFSET + 8)
+                    .ok_or(ErrorCode::CorruptData)?;
+                let mut hashed_key = [0; 8];
+                hashed_key.copy_from_slice(hash);
+                return Ok(Some((
+                    u64::from_be_bytes(hashed_key),
+                    offset + total_length as usize,
+                )));
+            }
+
+            offset += total_length as usize;
+        }
+    }
+
+    /// Finds the next valid key in flash storage, to list the keys that are
+    /// stored.
+    ///
+    /// `position`: Where to start looking. This is 0 for the first key, and
+    ///             the position returned with the previous key for the next
+    ///             ones.
+    ///
+    /// On success the hashed key and the position after it will be returned.
+    /// The hashed main key is listed as well.
+    /// On error a `ErrorCode` will be returned, which is `KeyNotFound` once
+    /// there are no more keys.
+    pub fn next_key(&self, position: usize) -> Result<(u64, usize), ErrorCode> {
+        let num_region = self.flash_size / S;
+
+        let (mut region, mut offset) = match self.state.get() {
+            // Continue from the region we were waiting for
+            State::NextKey(KeyState::ReadRegion(reg)) if reg > position / S => (reg, 0),
+            _ => (position / S, position % S),
+        };
+
+        while region < num_region {
+            // Get the data from that region
+            let mut region_data = self.read_buffer.take().unwrap();
+            if self.state.get() != State::NextKey(KeyState::ReadRegion(region)) {
+                match self.controller.read_region(region, 0, &mut region_data) {
+                    Ok(()) => {}
+                    Err(e) => {
+                        self.read_buffer.replace(Some(region_data));
+                        if let ErrorCode::ReadNotReady(reg) = e {
+                            self.state.set(State::NextKey(KeyState::ReadRegion(reg)));
+                        }
+                        return Err(e);
+                    }
+                };
+            }
+
+            let found = self.find_next_object(region_data, offset);
+            self.read_buffer.replace(Some(region_data));
+
+            if let Some((hash, end)) = found? {
+                return Ok((hash, S * region + end));
+            }
+
+            region += 1;
+            offset = 0;
+        }
+
+        Err(ErrorCode::KeyNotFound)
+    }
+
     /// Perform a garbage collection on TicKV
     ///
     /// On success the number of bytes freed will be returned.
@@ -996,4 +1302,94 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
 
         Ok(flash_freed)
     }
+
+    /// Add up the bytes used by the objects in some loaded region data.
+    fn region_usage(&self, region_data: &[u8]) -> Result<Usage, ErrorCode> {
+        let mut usage = Usage::default();
+        let mut offset: usize = 0;
+
+        while offset + HEADER_LENGTH < S {
+            let version = *region_data
+                .get(offset + VERSION_OFFSET)
+                .ok_or(ErrorCode::CorruptData)?;
+            if version == 0xFF {
+                // We hit the end of valid data
+                break;
+            }
+            if version != VERSION {
+                return Err(ErrorCode::UnsupportedVersion);
+            }
+
+            // Find this entries length
+            let len_high = *region_data
+                .get(offset + LEN_OFFSET)
+                .ok_or(ErrorCode::CorruptData)?;
+            let total_length = ((len_high as u16) & !0xF0) << 8
+                | *region_data
+                    .get(offset + LEN_OFFSET + 1)
+                    .ok_or(ErrorCode::CorruptData)? as u16;
+            if total_length == 0 {
+                // We found something invalid here
+                break;
+            }
+
+            if len_high & 0x80 == 0x80 {
+                usage.live_bytes += total_length as usize;
+            } else {
+                usage.dead_bytes += total_length as usize;
+            }
+
+            offset += total_length as usize;
+        }
+
+        Ok(usage)
+    }
+
+    /// Add up the bytes used by valid and invalidated objects in flash
+    /// storage, to decide when to run a garbage collection.
+    ///
+    /// On success the `Usage` will be returned.
+    /// On error a `ErrorCode` will be returned.
+    pub fn usage(&self) -> Result<Usage, ErrorCode> {
+        let num_region = self.flash_size / S;
+
+        let mut region = match self.state.get() {
+            // Continue from the region we were waiting for
+            State::Usage(KeyState::ReadRegion(reg)) => reg,
+            _ => {
+                self.usage.set(Usage::default());
+                0
+            }
+        };
+
+        while region < num_region {
+            // Get the data from that region
+            let mut region_data = self.read_buffer.take().unwrap();
+            if self.state.get() != State::Usage(KeyState::ReadRegion(region)) {
+                match self.controller.read_region(region, 0, &mut region_data) {
+                    Ok(()) => {}
+                    Err(e) => {
+                        self.read_buffer.replace(Some(region_data));
+                        if let ErrorCode::ReadNotReady(reg) = e {
+                            self.state.set(State::Usage(KeyState::ReadRegion(reg)));
+                        }
+                        return Err(e);
+                    }
+                };
+            }
+
+            let region_usage = self.region_usage(region_data);
+            self.read_buffer.replace(Some(region_data));
+            let region_usage = region_usage?;
+
+            let mut usage = self.usage.get();
+            usage.live_bytes += region_usage.live_bytes;
+            usage.dead_bytes += region_usage.dead_bytes;
+            self.usage.set(usage);
+
+            region += 1;
+        }
+
+        Ok(self.usage.get())
+    }
 }
@@ -248,6 +248,7 @@ pub enum TbfFooterV2CredentialsType {
     SHA256 = 3,
     SHA384 = 4,
     SHA512 = 5,
+    EcdsaNistP256 = 6,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -615,6 +616,7 @@ impl core::convert::TryFrom<&'static [u8]> for TbfFooterV2Credentials {
             3 => TbfFooterV2CredentialsType::SHA256,
             4 => TbfFooterV2CredentialsType::SHA384,
             5 => TbfFooterV2CredentialsType::SHA512,
+            6 => TbfFooterV2CredentialsType::EcdsaNistP256,
             _ => {
                 return Err(TbfParseError::InternalError);
             }
@@ -626,6 +628,7 @@ impl core::convert::TryFrom<&'static [u8]> for TbfFooterV2Credentials {
             TbfFooterV2CredentialsType::SHA256 => 32,
             TbfFooterV2CredentialsType::SHA384 => 48,
             TbfFooterV2CredentialsType::SHA512 => 64,
+            TbfFooterV2CredentialsType::EcdsaNistP256 => 64,
         };
         let data = &b
             .get(4..(length + 4))
@@ -710,6 +713,18 @@ impl TbfHeader {
         }
     }
 
+    /// Return whether everything after the header is compressed, and must be
+    /// decompressed by the kernel before the application can run.
+    pub fn compressed(&self) -> bool {
+        match *self {
+            TbfHeader::TbfHeaderV2(hd) => {
+                // Bit 2 of flags is the compressed bit.
+                hd.base.flags & 0x00000004 != 0
+            }
+            TbfHeader::Padding(_) => false,
+        }
+    }
+
     /// Add up all of the relevant fields in header version 1, or just used the
     /// app provided value in version 2 to get the total amount of RAM that is
     /// needed for this app.
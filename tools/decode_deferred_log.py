@@ -0,0 +1,152 @@
+#!/usr/bin/env python3
+
+# Licensed under the Apache License, Version 2.0 or the MIT License.
+# SPDX-License-Identifier: Apache-2.0 OR MIT
+# Copyright Tock Contributors 2023.
+
+# Decodes the debug output of a kernel built with the `deferred_logging`
+# kernel feature.
+#
+# `debug_deferred!()` then writes frames with the ID of an interned format
+# string and the raw arguments instead of text (see `kernel/src/debug.rs` for
+# the encoding). The format strings are kept in the `.tock_log_fmt` section of
+# the kernel ELF, where the ID of a string is its address.
+#
+# Usage:
+#
+#   decode_deferred_log.py <kernel ELF> [captured output, default stdin]
+#
+# e.g. `decode_deferred_log.py target/.../imix.elf < /dev/ttyUSB0`. Text
+# between frames is passed through unchanged.
+
+import argparse
+import os
+import re
+import subprocess
+import sys
+import tempfile
+
+SECTION = ".tock_log_fmt"
+OBJCOPY = os.environ.get("OBJCOPY", "llvm-objcopy")
+
+PLACEHOLDER = re.compile(r"\{\{|\}\}|\{([^{}]*)\}")
+
+
+def read_strings(elf):
+    with tempfile.TemporaryDirectory() as tmp:
+        path = os.path.join(tmp, "strings")
+        subprocess.run(
+            [OBJCOPY, "--dump-section", "{}={}".format(SECTION, path), elf, os.devnull],
+            check=True,
+        )
+        with open(path, "rb") as f:
+            return f.read()
+
+
+def format_string(strings, id):
+    end = strings.find(b"\0", id)
+    if id >= len(strings) or end < 0:
+        return None
+    return strings[id:end].decode("utf-8", errors="replace")
+
+
+def leb128(data, pos):
+    value = 0
+    shift = 0
+    while True:
+        byte = data[pos]
+        pos += 1
+        value |= (byte & 0x7F) << shift
+        shift += 7
+        if byte & 0x80 == 0:
+            return value, pos
+
+
+def decode_args(payload):
+    args = []
+    pos = 0
+    while pos < len(payload):
+        tag = payload[pos]
+        pos += 1
+        if tag == 0x01:
+            value, pos = leb128(payload, pos)
+        elif tag == 0x02:
+            value, pos = leb128(payload, pos)
+            value = (value >> 1) ^ -(value & 1)
+        elif tag == 0x03:
+            value = "true" if payload[pos] else "false"
+            pos += 1
+        elif tag == 0x04:
+            code, pos = leb128(payload, pos)
+            value = chr(code)
+        elif tag == 0x05:
+            length, pos = leb128(payload, pos)
+            value = payload[pos : pos + length].decode("utf-8", errors="replace")
+            pos += length
+        else:
+            break
+        args.append(value)
+    return args
+
+
+def render(fmt, args):
+    args = iter(args)
+
+    def replace(match):
+        if match.group(0) == "{{":
+            return "{"
+        if match.group(0) == "}}":
+            return "}"
+        spec = match.group(1).split(":", 1)
+        spec = spec[1].replace("?", "") if len(spec) == 2 else ""
+        try:
+            value = next(args)
+        except StopIteration:
+            return "<missing>"
+        try:
+            return format(value, spec)
+        except (ValueError, TypeError):
+            return str(value)
+
+    return PLACEHOLDER.sub(replace, fmt)
+
+
+def decode(strings, data, out):
+    pos = 0
+    while pos < len(data):
+        start = data.find(b"\0", pos)
+        if start < 0 or start + 6 > len(data):
+            out.write(data[pos:].decode("utf-8", errors="replace"))
+            return
+        out.write(data[pos:start].decode("utf-8", errors="replace"))
+        id = int.from_bytes(data[start + 1 : start + 5], "little")
+        length = data[start + 5]
+        payload = data[start + 6 : start + 6 + length]
+        pos = start + 6 + length
+
+        fmt = format_string(strings, id)
+        if fmt is None:
+            out.write("<unknown deferred log message {:#x}>\n".format(id))
+        else:
+            out.write(render(fmt, decode_args(payload)) + "\r\n")
+
+
+def main():
+    parser = argparse.ArgumentParser(
+        description="Decode the debug output of a kernel with deferred logging."
+    )
+    parser.add_argument("elf", help="kernel ELF the output was captured from")
+    parser.add_argument("output", nargs="?", help="captured debug output")
+    args = parser.parse_args()
+
+    strings = read_strings(args.elf)
+    if args.output:
+        with open(args.output, "rb") as f:
+            data = f.read()
+    else:
+        data = sys.stdin.buffer.read()
+    decode(strings, data, sys.stdout)
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,179 @@
+#!/usr/bin/env python3
+
+# Licensed under the Apache License, Version 2.0 or the MIT License.
+# SPDX-License-Identifier: Apache-2.0 OR MIT
+# Copyright Tock Contributors 2023.
+
+# Client for the host control channel served by
+# `capsules_extra::host_control`, see that module for the protocol.
+#
+# Usage:
+#
+#   host_control.py <serial port> ping
+#   host_control.py <serial port> list
+#   host_control.py <serial port> counters <index>
+#   host_control.py <serial port> crashdump <index>
+#   host_control.py <serial port> reboot-reason
+#   host_control.py <serial port> stop|resume|fault|terminate|boot <index>
+#   host_control.py <serial port> reset [bootloader]
+#
+# Requires pyserial.
+
+import argparse
+import struct
+import sys
+
+import serial
+
+SYNC = 0xA5
+
+STATES = [
+    "Running",
+    "Yielded",
+    "StoppedRunning",
+    "StoppedYielded",
+    "Faulted",
+    "Terminated",
+    "CredentialsUnchecked",
+    "CredentialsApproved",
+    "CredentialsFailed",
+]
+FAULT_REASONS = [None, "Hardware", "ContextSwitch", "Stack", "Requested"]
+REBOOT_REASONS = [None, "Panic", "Watchdog", "UserCommand", "Update"]
+RESET_CAUSES = [
+    None,
+    "PowerOn",
+    "Brownout",
+    "ExternalPin",
+    "Watchdog",
+    "Software",
+    "Other",
+]
+ACTIONS = {"stop": 1, "resume": 2, "fault": 3, "terminate": 4, "boot": 5}
+
+
+def crc16(data):
+    crc = 0xFFFF
+    for byte in data:
+        crc ^= byte << 8
+        for _ in range(8):
+            crc = ((crc << 1) ^ 0x1021) if crc & 0x8000 else crc << 1
+            crc &= 0xFFFF
+    return crc
+
+
+class HostControl:
+    def __init__(self, port, timeout=1.0, retries=3):
+        self.serial = serial.Serial(port, 115200, timeout=timeout)
+        self.retries = retries
+        self.sequence = 0
+
+    def request(self, command, payload=b""):
+        self.sequence = (self.sequence + 1) & 0xFF
+        body = struct.pack("<BBH", command, self.sequence, len(payload)) + payload
+        frame = bytes([SYNC]) + body + struct.pack("<H", crc16(body))
+        for _ in range(self.retries):
+            self.serial.write(frame)
+            response = self.read_response(command)
+            if response is not None:
+                status, data = response[0], response[1:]
+                if status != 0:
+                    raise RuntimeError("error code {}".format(status))
+                return data
+        raise TimeoutError("no response")
+
+    def read_response(self, command):
+        while True:
+            byte = self.serial.read(1)
+            if not byte:
+                return None
+            if byte[0] != SYNC:
+                continue
+            header = self.serial.read(4)
+            if len(header) < 4:
+                return None
+            response, sequence, length = struct.unpack("<BBH", header)
+            rest = self.serial.read(length + 2)
+            if len(rest) < length + 2:
+                return None
+            payload, crc = rest[:length], struct.unpack("<H", rest[length:])[0]
+            if crc16(header + payload) != crc:
+                continue
+            if response == command | 0x80 and sequence == self.sequence:
+                return payload
+
+
+def main():
+    parser = argparse.ArgumentParser(description="Talk to the Tock host control channel.")
+    parser.add_argument("port")
+    parser.add_argument("command")
+    parser.add_argument("args", nargs="*")
+    args = parser.parse_args()
+    hc = HostControl(args.port)
+
+    if args.command == "ping":
+        version, major, minor, crc_errors, busy = struct.unpack(
+            "<BHHHH", hc.request(0x01)
+        )
+        print(
+            "protocol {}, kernel {}.{}, dropped: {} bad CRC, {} busy".format(
+                version, major, minor, crc_errors, busy
+            )
+        )
+    elif args.command == "list":
+        start = 0
+        while True:
+            data = hc.request(0x02, bytes([start]))
+            total, pos = data[0], 1
+            while pos < len(data):
+                index, state, restarts, name_len = struct.unpack_from("<BBHB", data, pos)
+                pos += 5
+                name = data[pos : pos + name_len].decode(errors="replace")
+                pos += name_len
+                print("{:3} {:24} {:20} restarts: {}".format(index, name, STATES[state], restarts))
+                start = index + 1
+            if start >= total or pos == 1:
+                break
+    elif args.command == "counters":
+        fields = struct.unpack("<IIIIIQIII", hc.request(0x03, bytes([int(args.args[0])])))
+        names = [
+            "syscalls",
+            "dropped upcalls",
+            "timeslice expirations",
+            "restarts",
+            "context switches",
+            "cpu time (us)",
+            "grant bytes",
+            "grant high water",
+            "failed grant allocations",
+        ]
+        for name, value in zip(names, fields):
+            print("{:26} {}".format(name, value))
+    elif args.command == "crashdump":
+        data = hc.request(0x04, bytes([int(args.args[0])]))
+        reason, state, length = struct.unpack_from("<BBH", data)
+        print("fault reason: {}, state: {}".format(FAULT_REASONS[reason], STATES[state]))
+        sys.stdout.write(data[4 : 4 + length].hex() + "\n")
+    elif args.command == "reboot-reason":
+        data = hc.request(0x05)
+        print(
+            "reason: {}, cause: {}, message: {}".format(
+                REBOOT_REASONS[data[0]],
+                RESET_CAUSES[data[1]],
+                data[3 : 3 + data[2]].decode(errors="replace"),
+            )
+        )
+    elif args.command in ACTIONS:
+        hc.request(0x06, bytes([int(args.args[0]), ACTIONS[args.command]]))
+    elif args.command == "reset":
+        mode = 1 if args.args[:1] == ["bootloader"] else 0
+        try:
+            hc.request(0x07, bytes([mode]))
+        except TimeoutError:
+            pass
+    else:
+        parser.error("unknown command {}".format(args.command))
+
+
+if __name__ == "__main__":
+    main()